@@ -1,9 +1,14 @@
-use axum::{extract::FromRef, Router};
+use axum::extract::FromRef;
+use common::broadcast::BroadcastService;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::{config::Config, pocketbase_manager::PocketBaseManager};
-use super::{AppState, queue::Meeting, websocket::WebSocketManager};
+use crate::{config::Config, global_pb::GlobalPb, mailer::Mailer, pocketbase_manager::PocketBaseManager};
+use super::{
+    AppState, audit::AuditLogger, auth_cache::AuthCache, key_audit::KeyAuditLog, key_repository::MasterKey, login_guard::LoginGuard,
+    queue::Meeting, rate_limit::RateLimits, revocation::RevocationList, sessions::SessionList, startup::StartupState,
+    websocket::WebSocketManager,
+};
 
 /// Enable extracting Config from AppState
 impl FromRef<AppState> for Arc<Config> {
@@ -12,6 +17,13 @@ impl FromRef<AppState> for Arc<Config> {
     }
 }
 
+/// Enable extracting the shared broadcast service from AppState
+impl FromRef<AppState> for Arc<BroadcastService> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.broadcast.clone()
+    }
+}
+
 /// Enable extracting PocketBaseManager from AppState  
 impl FromRef<AppState> for Arc<PocketBaseManager> {
     fn from_ref(app_state: &AppState) -> Self {
@@ -26,9 +38,86 @@ impl FromRef<AppState> for Arc<WebSocketManager> {
     }
 }
 
+/// Enable extracting the validated-token cache from AppState
+impl FromRef<AppState> for Arc<AuthCache> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.auth_cache.clone()
+    }
+}
+
+/// Enable extracting the token revocation list from AppState
+impl FromRef<AppState> for Arc<RevocationList> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.revocations.clone()
+    }
+}
+
+/// Enable extracting the global PocketBase admin client from AppState
+impl FromRef<AppState> for Arc<GlobalPb> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.global_pb.clone()
+    }
+}
+
+/// Enable extracting the auth endpoint rate limiters from AppState
+impl FromRef<AppState> for Arc<RateLimits> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.rate_limits.clone()
+    }
+}
+
+/// Enable extracting the smtp-service mailer from AppState
+impl FromRef<AppState> for Arc<Mailer> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.mailer.clone()
+    }
+}
+
+/// Enable extracting the login brute-force guard from AppState
+impl FromRef<AppState> for Arc<LoginGuard> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.login_guard.clone()
+    }
+}
+
+/// Enable extracting the list of signed-in sessions from AppState
+impl FromRef<AppState> for Arc<SessionList> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.sessions.clone()
+    }
+}
+
+/// Enable extracting AuditLogger from AppState
+impl FromRef<AppState> for Arc<AuditLogger> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.audit.clone()
+    }
+}
+
+/// Enable extracting the key audit trail from AppState
+impl FromRef<AppState> for Arc<KeyAuditLog> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.key_audit.clone()
+    }
+}
+
+/// Enable extracting the parsed master key from AppState
+impl FromRef<AppState> for Arc<MasterKey> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.master_key.clone()
+    }
+}
+
 /// Enable extracting meetings queue from AppState
 impl FromRef<AppState> for Arc<RwLock<Vec<Meeting>>> {
     fn from_ref(app_state: &AppState) -> Self {
         app_state.meetings_queue.clone()
     }
 }
+
+/// Enable extracting the startup phase from AppState
+impl FromRef<AppState> for Arc<StartupState> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.startup.clone()
+    }
+}
@@ -0,0 +1,457 @@
+//! Audit trail of authentication events
+//!
+//! Auth handlers record logins, failed logins, registrations, password
+//! changes and token revocations with the client's IP and user agent. Events
+//! are buffered in memory and written to the global PocketBase
+//! `auth_events` collection in the background; writing is best-effort, so a
+//! PocketBase outage delays or, past [`MAX_BUFFERED`] events, drops entries
+//! but never fails the request that produced them. Admins read the trail at
+//! `GET /api/admin/auth_events`.
+//!
+//! The same writer buffers other audit trails, such as the `key_audit`
+//! record of API key changes, through [`AuditEntry`].
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Router,
+};
+use common::clock::Clock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::warn;
+
+use super::{extractors::AdminUser, sessions::SessionOrigin, AppState};
+use crate::global_pb::{self, GlobalPb};
+use crate::metrics::{self, AUTH_FAILURES};
+
+const AUTH_EVENTS: &str = "auth_events";
+
+/// How often buffered events are written out
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Buffered events that wake the writer before its next interval
+const FLUSH_BATCH: usize = 100;
+
+/// Most events held while PocketBase is unreachable; the oldest go first
+pub const MAX_BUFFERED: usize = 10_000;
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventType {
+    Login,
+    Register,
+    PasswordChange,
+    PasswordReset,
+    Logout,
+    LogoutAll,
+    SessionRevoked,
+    ApiKeyDeleted,
+}
+
+impl AuthEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEventType::Login => "login",
+            AuthEventType::Register => "register",
+            AuthEventType::PasswordChange => "password_change",
+            AuthEventType::PasswordReset => "password_reset",
+            AuthEventType::Logout => "logout",
+            AuthEventType::LogoutAll => "logout_all",
+            AuthEventType::SessionRevoked => "session_revoked",
+            AuthEventType::ApiKeyDeleted => "api_key_deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+/// One audited authentication event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthEvent {
+    pub event_type: AuthEventType,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub ip: String,
+    #[serde(default)]
+    pub user_agent: String,
+    /// Why a failure failed, as an error code like `invalid_credentials`, or
+    /// the `service/key_id` an API key event concerns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Unix time the event was recorded at
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+impl AuthEvent {
+    /// A successful event from the client `origin`
+    pub fn new(event_type: AuthEventType, origin: &SessionOrigin) -> Self {
+        Self {
+            event_type,
+            outcome: Outcome::Success,
+            user_id: None,
+            email: None,
+            ip: origin.ip.clone(),
+            user_agent: origin.user_agent.clone(),
+            detail: None,
+            created_at: 0,
+        }
+    }
+
+    pub fn user(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string()).filter(|id| !id.is_empty());
+        self
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = Some(email.trim().to_lowercase()).filter(|email| !email.is_empty());
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Mark the event as failed for `reason`
+    pub fn failed(mut self, reason: &str) -> Self {
+        self.outcome = Outcome::Failure;
+        self.detail = Some(reason.to_string());
+        self
+    }
+}
+
+/// An entry in one of the audit trails [`AuditLogger`] writes
+pub trait AuditEntry: Serialize + Clone + Send + Sync + 'static {
+    /// Global PocketBase collection entries are written to
+    const COLLECTION: &'static str;
+
+    /// Record the Unix time the entry was made at
+    fn stamp(&mut self, at: u64);
+
+    /// Update metrics as the entry is recorded
+    fn observe(&self) {}
+}
+
+impl AuditEntry for AuthEvent {
+    const COLLECTION: &'static str = AUTH_EVENTS;
+
+    fn stamp(&mut self, at: u64) {
+        self.created_at = at;
+    }
+
+    fn observe(&self) {
+        // Other events' details name API keys, too many series to count by
+        let counted = matches!(
+            self.event_type,
+            AuthEventType::Login | AuthEventType::Register | AuthEventType::PasswordChange | AuthEventType::PasswordReset
+        );
+        if counted && self.outcome == Outcome::Failure {
+            let reason = self.detail.as_deref().unwrap_or("unknown");
+            metrics::increment(&AUTH_FAILURES, &[("kind", self.event_type.as_str()), ("reason", reason)]);
+        }
+    }
+}
+
+pub struct AuditLogger<E = AuthEvent> {
+    store: Arc<GlobalPb>,
+    clock: Arc<dyn Clock>,
+    buffer: Mutex<VecDeque<E>>,
+    wake: Notify,
+}
+
+impl<E: AuditEntry> AuditLogger<E> {
+    pub fn new(store: Arc<GlobalPb>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            store,
+            clock,
+            buffer: Mutex::new(VecDeque::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Queue `event` for writing, stamped with the current time
+    pub fn record(&self, mut event: E) {
+        event.stamp(self.clock.unix_now());
+        event.observe();
+        let buffered = {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if buffer.len() >= MAX_BUFFERED {
+                buffer.pop_front();
+                warn!("Audit buffer full, dropping the oldest {} entry", E::COLLECTION);
+            }
+            buffer.push_back(event);
+            buffer.len()
+        };
+        if buffered >= FLUSH_BATCH {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Events recorded but not yet written
+    pub fn pending(&self) -> usize {
+        self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    /// Events waiting to be written, oldest first
+    #[cfg(test)]
+    pub(crate) fn buffered(&self) -> Vec<E> {
+        self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().cloned().collect()
+    }
+
+    /// Write every buffered event, returning how many were written
+    ///
+    /// Events that fail to write go back to the front of the buffer for the
+    /// next flush.
+    pub async fn flush(&self) -> usize {
+        let events: Vec<E> = {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            buffer.drain(..).collect()
+        };
+
+        let mut written = 0;
+        for event in &events {
+            let record = serde_json::to_value(event).unwrap_or(Value::Null);
+            if let Err(e) = self.store.create_record(E::COLLECTION, &record).await {
+                warn!("Failed to write {}, keeping {} for later: {}", E::COLLECTION, events.len() - written, e);
+                break;
+            }
+            written += 1;
+        }
+
+        if written < events.len() {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for event in events.into_iter().skip(written).rev() {
+                buffer.push_front(event);
+            }
+            while buffer.len() > MAX_BUFFERED {
+                buffer.pop_front();
+            }
+        }
+        written
+    }
+
+    /// The store entries are written to
+    pub fn store(&self) -> &GlobalPb {
+        &self.store
+    }
+
+    /// Flush every `interval`, or sooner once [`FLUSH_BATCH`] events are waiting
+    pub fn start_flushing(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let logger = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = logger.wake.notified() => {}
+                }
+                logger.flush().await;
+            }
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthEventQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub user_id: Option<String>,
+    pub event_type: Option<AuthEventType>,
+}
+
+/// Admin routes for the audit trail, nested under `/api`
+pub fn router() -> Router<AppState> {
+    Router::new().route("/admin/auth_events", get(list_auth_events))
+}
+
+/// GET /api/admin/auth_events - recorded auth events, newest first (admin only)
+///
+/// Pending events are flushed first so the listing includes them.
+async fn list_auth_events(
+    _admin: AdminUser,
+    State(audit): State<Arc<AuditLogger>>,
+    Query(query): Query<AuthEventQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    audit.flush().await;
+
+    let mut terms = Vec::new();
+    if let Some(user_id) = query.user_id.as_deref().filter(|id| !id.is_empty()) {
+        terms.push(format!("user_id = {}", global_pb::quote(user_id)));
+    }
+    if let Some(event_type) = query.event_type {
+        terms.push(format!("event_type = {}", global_pb::quote(event_type.as_str())));
+    }
+    let filter = terms.join(" && ");
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let listed = audit
+        .store
+        .list_page(AUTH_EVENTS, Some(filter.as_str()).filter(|f| !f.is_empty()), "-created_at,-created", page, per_page)
+        .await;
+    match listed {
+        Ok(listed) => {
+            let events: Vec<AuthEvent> = listed
+                .items
+                .into_iter()
+                .filter_map(|record| serde_json::from_value(record).ok())
+                .collect();
+            Ok(Json(json!({
+                "events": events,
+                "page": listed.page,
+                "per_page": listed.per_page,
+                "total_items": listed.total_items
+            })))
+        }
+        Err(e) => {
+            warn!("Failed to list auth events: {}", e);
+            Err((
+                StatusCode::BAD_GATEWAY,
+                Json(json!({
+                    "success": false,
+                    "message": "Failed to load auth events"
+                })),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        test_support::{mock_app_state, mock_global_pocketbase, test_config},
+    };
+    use common::clock::SystemClock;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn state() -> AppState {
+        mock_app_state().await
+    }
+
+    async fn list(state: &AppState, query: &str, token: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .uri(format!("/api/admin/auth_events{}", query))
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    fn origin(ip: &str) -> SessionOrigin {
+        SessionOrigin { user_agent: "audit-test".to_string(), ip: ip.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_logins_are_recorded_with_their_outcome() {
+        let state = state().await;
+        for password in ["wrong", "password"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/auth/login")
+                .header("content-type", "application/json")
+                .header("user-agent", "Embedded/1.0")
+                .body(Body::from(json!({ "email": "Nora@example.com", "password": password }).to_string()))
+                .unwrap();
+            create_api_router(state.clone()).oneshot(request).await.unwrap();
+        }
+
+        let (status, body) = list(&state, "", "valid-admin").await;
+        assert_eq!(status, StatusCode::OK);
+        let events = body["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        // Newest first
+        assert_eq!(events[0]["event_type"], "login");
+        assert_eq!(events[0]["outcome"], "success");
+        assert_eq!(events[0]["user_id"], "Nora");
+        assert_eq!(events[0]["email"], "nora@example.com");
+        assert_eq!(events[0]["user_agent"], "Embedded/1.0");
+        assert_eq!(events[0]["ip"], "unknown");
+        assert_eq!(events[1]["outcome"], "failure");
+        assert_eq!(events[1]["detail"], "invalid_credentials");
+        assert!(events[1].get("user_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flush_writes_buffered_events_and_keeps_failed_ones() {
+        let global_url = mock_global_pocketbase().await;
+        let store = Arc::new(GlobalPb::from(&test_config(&global_url).database));
+        let audit = Arc::new(AuditLogger::new(Arc::clone(&store), Arc::new(SystemClock)));
+        audit.record(AuthEvent::new(AuthEventType::Logout, &origin("10.0.0.1")).user("ava"));
+        audit.record(AuthEvent::new(AuthEventType::LogoutAll, &origin("10.0.0.1")).user("ava"));
+
+        // Nothing reaches PocketBase until a flush, as on shutdown
+        assert!(store.list_records(AUTH_EVENTS, None).await.unwrap().is_empty());
+        assert_eq!(audit.pending(), 2);
+        assert_eq!(audit.flush().await, 2);
+        assert_eq!(audit.pending(), 0);
+        let records = store.list_records(AUTH_EVENTS, None).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["event_type"], "logout_all");
+        assert!(records[1]["created_at"].as_u64().unwrap() > 0);
+
+        // An unreachable PocketBase leaves events buffered for the next try
+        let unreachable = Arc::new(GlobalPb::from(&test_config("http://127.0.0.1:9").database));
+        let audit = AuditLogger::new(unreachable, Arc::new(SystemClock));
+        audit.record(AuthEvent::new(AuthEventType::Login, &origin("10.0.0.2")).failed("rate_limited"));
+        assert_eq!(audit.flush().await, 0);
+        assert_eq!(audit.pending(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_listing_filters_and_pages() {
+        let state = state().await;
+        for (event_type, user) in [
+            (AuthEventType::Login, "ava"),
+            (AuthEventType::Logout, "ava"),
+            (AuthEventType::Login, "ben"),
+            (AuthEventType::Logout, "ben"),
+            (AuthEventType::Login, "ava"),
+        ] {
+            state.audit.record(AuthEvent::new(event_type, &origin("10.0.0.3")).user(user));
+        }
+
+        let (_, body) = list(&state, "?user_id=ava", "valid-admin").await;
+        assert_eq!(body["total_items"], 3);
+        let (_, body) = list(&state, "?user_id=ava&event_type=login", "valid-admin").await;
+        assert_eq!(body["total_items"], 2);
+        assert!(body["events"].as_array().unwrap().iter().all(|e| e["user_id"] == "ava" && e["event_type"] == "login"));
+        let (_, body) = list(&state, "?event_type=logout", "valid-admin").await;
+        assert_eq!(body["total_items"], 2);
+
+        let (_, body) = list(&state, "?per_page=2&page=3", "valid-admin").await;
+        assert_eq!(body["total_items"], 5);
+        assert_eq!(body["page"], 3);
+        assert_eq!(body["events"].as_array().unwrap().len(), 1);
+        assert_eq!(body["events"][0]["event_type"], "login");
+
+        let (status, _) = list(&state, "", "valid-ava").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
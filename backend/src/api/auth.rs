@@ -1,21 +1,45 @@
 use axum::{
-    extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::Json,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
     routing::post,
     Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tracing::{error, info, warn};
 
-use crate::config::Config;
+use super::{
+    audit::{AuditLogger, AuthEvent, AuthEventType},
+    auth_cache::AuthCache,
+    csrf, email_verification,
+    error_report::with_cause,
+    extractors::{AuthUser, Role},
+    jwt::{self, JwtError, JwtKeys},
+    login_guard::LoginRejection,
+    oauth, password_reset, pocketbase, profile,
+    revocation::RevocationList,
+    sessions::{self, session_expiry, SessionList, SessionOrigin},
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{self, GlobalPb},
+    pocketbase_manager::PocketBaseManager,
+};
+use common::{
+    validation::{password_problems, username_problems},
+    ApiResponse, AppError, AuthData, AuthUserInfo, ErrorCode, InstanceInfo,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    /// Deliver the session token in an HttpOnly cookie instead of the reply
+    #[serde(default)]
+    pub cookie: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,133 +48,330 @@ pub struct RegisterRequest {
     pub password: String,
     pub password_confirm: String,
     pub name: Option<String>,
+    /// Case-insensitive and stored lowercase; PocketBase generates one when absent
+    pub username: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct AuthResponse {
-    pub success: bool,
-    pub token: Option<String>,
-    pub user: Option<Value>,
-    pub message: Option<String>,
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+    pub new_password_confirm: String,
+}
+
+/// Reply of the endpoints that sign users in; failures are already full responses
+type AuthResult = Result<Json<ApiResponse<AuthData>>, Response>;
+
+pub(super) fn auth_error(status: StatusCode, code: ErrorCode, message: &str) -> Response {
+    (status, Json(ApiResponse::<AuthData>::failure(code, message))).into_response()
+}
+
+fn signed_in(token: Option<String>, user: AuthUserInfo, message: &str) -> Json<ApiResponse<AuthData>> {
+    Json(ApiResponse::success(AuthData {
+        token,
+        user,
+        message: message.to_string(),
+        instance: None,
+    }))
+}
+
+/// Reply with a freshly minted token, in the body or as the session cookie
+fn deliver(
+    config: &Config,
+    token: String,
+    cookie: bool,
+    user: AuthUserInfo,
+    message: &str,
+    instance: Option<InstanceInfo>,
+) -> Response {
+    let cookies = cookie.then(|| csrf::session_cookies(config, &token));
+    let mut reply = signed_in((!cookie).then_some(token), user, message);
+    if let Some(data) = reply.0.data.as_mut() {
+        data.instance = instance;
+    }
+    match cookies {
+        Some(cookies) => (cookies, reply).into_response(),
+        None => reply.into_response(),
+    }
+}
+
+/// Start the user's PocketBase instance in the background, so it is ready
+/// by the time the dashboard needs it
+///
+/// Failing to start it never fails the sign-in; clients poll `pb_status`.
+async fn start_instance(pb_manager: &Arc<PocketBaseManager>, user_id: &str) -> InstanceInfo {
+    let status = pb_manager.init_in_background(user_id).await;
+    pocketbase::instance_info_for(user_id, status)
+}
+
+/// The fields of a PocketBase user record that clients see
+fn user_info(record: &Value) -> AuthUserInfo {
+    let field = |name: &str| record.get(name).and_then(|v| v.as_str()).map(String::from);
+    AuthUserInfo {
+        id: field("id").unwrap_or_default(),
+        email: field("email").unwrap_or_default(),
+        username: field("username").unwrap_or_default(),
+        name: field("name").filter(|name| !name.is_empty()),
+        verified: record.get("verified").and_then(|v| v.as_bool()).unwrap_or(false),
+    }
 }
 
 /// Create router for authentication endpoints
-pub fn router() -> Router<Arc<Config>> {
+pub fn router() -> Router<AppState> {
     Router::new()
         .route("/login", post(login))
         .route("/register", post(register))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/logout_all", post(logout_all))
+        .route("/change_password", post(change_password))
+        .merge(password_reset::router())
+        .merge(email_verification::router())
+        .merge(oauth::router())
+        .merge(profile::router())
+        .merge(sessions::router())
 }
 
-/// POST /auth/login - proxy to global PocketBase
+/// POST /auth/login - authenticate against global PocketBase and issue a session token
+///
+/// Attempts are rate-limited per client IP and email, and repeated failures
+/// lock the email for a while; both answer 429 with `Retry-After`. In cookie
+/// mode the token is set as the session cookie, with a new CSRF token.
 async fn login(
-    State(config): State<Arc<Config>>,
+    State(state): State<AppState>,
+    origin: SessionOrigin,
     Json(request): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, StatusCode> {
+) -> Result<Response, Response> {
+    let AppState { config, revocations, login_guard, sessions, pb_manager, audit, .. } = state;
     info!("Login attempt for email: {}", request.email);
 
     // Validate input
     if request.email.trim().is_empty() || request.password.is_empty() {
-        return Ok(Json(AuthResponse {
-            success: false,
-            token: None,
-            user: None,
-            message: Some("Email and password are required".to_string()),
-        }));
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "Email and password are required",
+        ));
     }
 
-    // Make request to global PocketBase
-    let client = reqwest::Client::new();
+    let email = request.email.trim().to_lowercase();
+    let event = AuthEvent::new(AuthEventType::Login, &origin).email(&email);
+    if let Err(rejection) = login_guard.check(&origin.ip, &email).await {
+        warn!("Refused login attempt for {} from {}: {:?}", email, origin.ip, rejection);
+        audit.record(event.failed("rate_limited"));
+        return Err(too_many_attempts(rejection));
+    }
+
+    match authenticate_with_pb(&config, &request.email, &request.password).await {
+        Ok(auth) => match issue_session_token(&config, &revocations, &sessions, &origin, &auth.pb_response, jwt::now()).await {
+            Ok(token) => {
+                login_guard.record_success(&email).await;
+                info!("Successful login for user: {}", request.email);
+                let user = user_info(&auth.record);
+                audit.record(event.user(&user.id));
+                let instance = start_instance(&pb_manager, &user.id).await;
+                Ok(deliver(&config, token, request.cookie, user, "Login successful", Some(instance)))
+            }
+            Err(e) => {
+                error!("Failed to issue session token: {}", e);
+                Err(server_error(AppError::Internal(format!("Failed to issue session token: {}", e))))
+            }
+        },
+        Err(AuthFailure::InvalidCredentials) => {
+            login_guard.record_failure(&email).await;
+            audit.record(event.failed("invalid_credentials"));
+            Err(invalid_credentials())
+        }
+        Err(AuthFailure::Rejected(status)) => {
+            warn!("Failed login attempt for {}: {}", request.email, status);
+            audit.record(event.failed("invalid_credentials"));
+            Err(invalid_credentials())
+        }
+        Err(AuthFailure::Malformed(e)) => {
+            error!("Failed to parse PocketBase response: {}", e);
+            Err(server_error(AppError::Database(format!("Unreadable PocketBase auth response: {}", e))))
+        }
+        Err(AuthFailure::Unavailable(e)) => {
+            error!("Failed to connect to PocketBase: {}", e);
+            Err(unavailable())
+        }
+    }
+}
+
+fn invalid_credentials() -> Response {
+    auth_error(StatusCode::UNAUTHORIZED, ErrorCode::InvalidCredentials, "Invalid email or password")
+}
+
+fn server_error(cause: AppError) -> Response {
+    with_cause(
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, "Authentication server error"),
+        cause,
+    )
+}
+
+fn unavailable() -> Response {
+    auth_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::ServiceUnavailable,
+        "Authentication server unavailable",
+    )
+}
+
+/// A successful `auth-with-password` against the global PocketBase
+pub struct AuthSuccess {
+    /// The whole response, holding the PocketBase `token` and user `record`
+    pub pb_response: Value,
+    pub record: Value,
+}
+
+#[derive(Debug)]
+pub enum AuthFailure {
+    /// PocketBase answered 400, its response to a wrong email or password
+    InvalidCredentials,
+    /// Any other non-success status
+    Rejected(reqwest::StatusCode),
+    /// PocketBase answered success with a body we couldn't use
+    Malformed(String),
+    /// PocketBase could not be reached
+    Unavailable(String),
+}
+
+/// Check `email` and `password` against the global PocketBase
+pub async fn authenticate_with_pb(config: &Config, email: &str, password: &str) -> Result<AuthSuccess, AuthFailure> {
     let auth_url = format!("{}/api/collections/users/auth-with-password", config.database.url);
-    
     let pb_request = json!({
-        "identity": request.email,
-        "password": request.password
+        "identity": email,
+        "password": password
     });
 
-    match client
+    let response = reqwest::Client::new()
         .post(&auth_url)
         .json(&pb_request)
         .send()
         .await
-    {
-        Ok(response) => {
-            let status = response.status();
-            let response_text = response.text().await.unwrap_or_default();
-            
-            if status.is_success() {
-                match serde_json::from_str::<Value>(&response_text) {
-                    Ok(pb_response) => {
-                        info!("Successful login for user: {}", request.email);
-                        Ok(Json(AuthResponse {
-                            success: true,
-                            token: pb_response.get("token").and_then(|t| t.as_str()).map(String::from),
-                            user: pb_response.get("record").cloned(),
-                            message: Some("Login successful".to_string()),
-                        }))
-                    }
-                    Err(e) => {
-                        error!("Failed to parse PocketBase response: {}", e);
-                        Ok(Json(AuthResponse {
-                            success: false,
-                            token: None,
-                            user: None,
-                            message: Some("Authentication server error".to_string()),
-                        }))
-                    }
-                }
-            } else {
-                warn!("Failed login attempt for {}: {}", request.email, status);
-                Ok(Json(AuthResponse {
-                    success: false,
-                    token: None,
-                    user: None,
-                    message: Some("Invalid email or password".to_string()),
-                }))
-            }
-        }
-        Err(e) => {
-            error!("Failed to connect to PocketBase: {}", e);
-            Err(StatusCode::SERVICE_UNAVAILABLE)
-        }
+        .map_err(|e| AuthFailure::Unavailable(e.to_string()))?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::BAD_REQUEST {
+        return Err(AuthFailure::InvalidCredentials);
     }
+    if !status.is_success() {
+        return Err(AuthFailure::Rejected(status));
+    }
+
+    let pb_response: Value = response
+        .json()
+        .await
+        .map_err(|e| AuthFailure::Malformed(e.to_string()))?;
+    let record = pb_response
+        .get("record")
+        .cloned()
+        .ok_or_else(|| AuthFailure::Malformed("No user record in response".to_string()))?;
+    Ok(AuthSuccess { pb_response, record })
+}
+
+fn too_many_attempts(rejection: LoginRejection) -> Response {
+    let message = match rejection {
+        LoginRejection::RateLimited { .. } => "Too many login attempts, please try again later",
+        LoginRejection::LockedOut { .. } => "Too many failed logins for this account, please try again later",
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, rejection.retry_after().to_string())],
+        Json(ApiResponse::<AuthData>::failure(ErrorCode::RateLimited, message)),
+    )
+        .into_response()
+}
+
+/// Mint our own session token from a PocketBase auth response and record
+/// the session it opens
+pub async fn issue_session_token(
+    config: &Config,
+    revocations: &RevocationList,
+    sessions: &SessionList,
+    origin: &SessionOrigin,
+    pb_response: &Value,
+    auth_time: u64,
+) -> Result<String, String> {
+    let (token, user_id) = mint_session_token(config, revocations, pb_response, auth_time).await?;
+    sessions.open(&token, &user_id, origin, session_expiry(config, &token)).await;
+    Ok(token)
 }
 
-/// POST /auth/register - proxy to global PocketBase
+/// Mint our own session token from a PocketBase auth response, returning it
+/// with the user's id
+async fn mint_session_token(
+    config: &Config,
+    revocations: &RevocationList,
+    pb_response: &Value,
+    auth_time: u64,
+) -> Result<(String, String), String> {
+    let record = pb_response.get("record").ok_or("No user record in response")?;
+    let field = |name: &str| record.get(name).and_then(|v| v.as_str()).map(String::from);
+
+    let user = AuthUser {
+        id: field("id").ok_or("Missing user ID")?,
+        email: field("email").ok_or("Missing user email")?,
+        name: field("name"),
+        token: pb_response
+            .get("token")
+            .and_then(|t| t.as_str())
+            .ok_or("Missing PocketBase token")?
+            .to_string(),
+        role: Role::from_record(record, config),
+    };
+    let generation = revocations.generation(&user.id).await;
+    let token = JwtKeys::new(&config.security)
+        .issue_in_session(&user, user.role.as_str(), auth_time, generation)
+        .map_err(|e| e.to_string())?;
+    Ok((token, user.id))
+}
+
+/// POST /auth/register - proxy to global PocketBase and send a verification email
 async fn register(
-    State(config): State<Arc<Config>>,
+    State(state): State<AppState>,
+    origin: SessionOrigin,
     Json(request): Json<RegisterRequest>,
-) -> Result<Json<AuthResponse>, StatusCode> {
+) -> AuthResult {
+    let AppState { config, revocations, mailer, global_pb, sessions, pb_manager, audit, .. } = state;
     info!("Registration attempt for email: {}", request.email);
+    if !config.features.registration_enabled {
+        return Err(auth_error(
+            StatusCode::FORBIDDEN,
+            ErrorCode::RegistrationClosed,
+            "Registration is closed on this deployment",
+        ));
+    }
+
+    let invalid = |message: &str| auth_error(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Validation, message);
 
     // Validate input
     if request.email.trim().is_empty() || request.password.is_empty() {
-        return Ok(Json(AuthResponse {
-            success: false,
-            token: None,
-            user: None,
-            message: Some("Email and password are required".to_string()),
-        }));
+        return Err(invalid("Email and password are required"));
     }
 
     if request.password != request.password_confirm {
-        return Ok(Json(AuthResponse {
-            success: false,
-            token: None,
-            user: None,
-            message: Some("Passwords do not match".to_string()),
-        }));
+        return Err(invalid("Passwords do not match"));
     }
 
+    let username = match request.username.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        Some(username) => Some(check_username(&global_pb, username).await?),
+        None => None,
+    };
+
     // Make request to global PocketBase
     let client = reqwest::Client::new();
     let register_url = format!("{}/api/collections/users/records", config.database.url);
     
-    let pb_request = json!({
+    let mut pb_request = json!({
         "email": request.email,
         "password": request.password,
         "passwordConfirm": request.password_confirm,
         "name": request.name.unwrap_or_else(|| request.email.split('@').next().unwrap_or("User").to_string())
     });
+    if let Some(username) = &username {
+        pb_request["username"] = json!(username);
+    }
 
     match client
         .post(&register_url)
@@ -166,47 +387,1235 @@ async fn register(
                 match serde_json::from_str::<Value>(&response_text) {
                     Ok(pb_response) => {
                         info!("Successful registration for user: {}", request.email);
-                        
-                        // After successful registration, attempt to login
-                        let login_request = LoginRequest {
-                            email: request.email,
-                            password: request.password,
-                        };
-                        
-                        // Recursively call login to get the token
-                        match login(State(config), Json(login_request)).await {
-                            Ok(login_response) => Ok(login_response),
-                            Err(_) => Ok(Json(AuthResponse {
-                                success: true,
-                                token: None,
-                                user: Some(pb_response),
-                                message: Some("Registration successful, please login".to_string()),
-                            }))
+
+                        // The account works without it, just not for queueing meetings
+                        let user_id = pb_response.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+                        let email = pb_response.get("email").and_then(|v| v.as_str()).unwrap_or(&request.email);
+                        if let Err(e) =
+                            email_verification::send_verification_email(&config, &mailer, user_id, email).await
+                        {
+                            error!("Failed to send verification email to {}: {}", request.email, e);
                         }
+
+                        let mut registered = login_after_registration(
+                            &config,
+                            &revocations,
+                            &sessions,
+                            &origin,
+                            &request.email,
+                            &request.password,
+                            pb_response,
+                        )
+                        .await;
+                        if registered.token.is_some() {
+                            registered.instance = Some(start_instance(&pb_manager, &registered.user.id).await);
+                        }
+                        audit.record(
+                            AuthEvent::new(AuthEventType::Register, &origin)
+                                .user(&registered.user.id)
+                                .email(&registered.user.email),
+                        );
+                        Ok(Json(ApiResponse::success(registered)))
                     }
                     Err(e) => {
                         error!("Failed to parse PocketBase registration response: {}", e);
-                        Ok(Json(AuthResponse {
-                            success: false,
-                            token: None,
-                            user: None,
-                            message: Some("Registration server error".to_string()),
-                        }))
+                        Err(server_error(AppError::Database(format!(
+                            "Unreadable PocketBase registration response: {}",
+                            e
+                        ))))
                     }
                 }
             } else {
                 warn!("Failed registration attempt for {}: {} - {}", request.email, status, response_text);
-                Ok(Json(AuthResponse {
-                    success: false,
-                    token: None,
-                    user: None,
-                    message: Some("Registration failed. Email may already be in use.".to_string()),
-                }))
+                audit.record(AuthEvent::new(AuthEventType::Register, &origin).email(&request.email).failed("rejected"));
+                Err(registration_rejected(status, &response_text))
             }
         }
         Err(e) => {
             error!("Failed to connect to PocketBase for registration: {}", e);
-            Err(StatusCode::SERVICE_UNAVAILABLE)
+            Err(unavailable())
+        }
+    }
+}
+
+/// Translate PocketBase's refusal to create a user into our error codes
+///
+/// PocketBase answers 400 with per-field errors under `data`, e.g.
+/// `{"email": {"code": "validation_not_unique", ...}}`; its messages are
+/// not passed on.
+fn registration_rejected(status: reqwest::StatusCode, body: &str) -> Response {
+    if status != reqwest::StatusCode::BAD_REQUEST {
+        return auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Registration server error");
+    }
+
+    let payload: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    let fields = payload.get("data").and_then(|data| data.as_object()).cloned().unwrap_or_default();
+    let not_unique = |field: &str| {
+        fields
+            .get(field)
+            .and_then(|error| error.get("code"))
+            .is_some_and(|code| code == "validation_not_unique")
+    };
+
+    if not_unique("email") {
+        return auth_error(StatusCode::CONFLICT, ErrorCode::EmailExists, "An account with this email already exists");
+    }
+    if not_unique("username") {
+        return auth_error(StatusCode::CONFLICT, ErrorCode::UsernameTaken, "Username is already taken");
+    }
+
+    let problems: Vec<String> = fields
+        .keys()
+        .map(|field| match field.as_str() {
+            "email" => "Email address is invalid".to_string(),
+            "password" => "Password does not meet the requirements".to_string(),
+            "passwordConfirm" => "Passwords do not match".to_string(),
+            "username" => "Username is invalid".to_string(),
+            other => format!("Invalid {}", other),
+        })
+        .collect();
+    let message = if problems.is_empty() {
+        "Registration was rejected".to_string()
+    } else {
+        problems.join("; ")
+    };
+    auth_error(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Validation, &message)
+}
+
+/// Sign in a user who just registered
+///
+/// The account exists whatever happens here, so failing to sign in still
+/// reports success and asks the user to log in, rather than suggesting the
+/// registration didn't work.
+async fn login_after_registration(
+    config: &Config,
+    revocations: &RevocationList,
+    sessions: &SessionList,
+    origin: &SessionOrigin,
+    email: &str,
+    password: &str,
+    created: Value,
+) -> AuthData {
+    let auth = match authenticate_with_pb(config, email, password).await {
+        Ok(auth) => auth,
+        Err(e) => {
+            warn!("Login after registration failed for {}: {:?}", email, e);
+            return registered_please_login(created);
+        }
+    };
+
+    match issue_session_token(config, revocations, sessions, origin, &auth.pb_response, jwt::now()).await {
+        Ok(token) => {
+            // Clients store `user.username`, so make sure it is there
+            let mut user = user_info(&auth.record);
+            if user.username.is_empty() {
+                user.username = user_info(&created).username;
+            }
+            info!("Successful login after registration for user: {}", email);
+            AuthData {
+                token: Some(token),
+                user,
+                message: "Registration successful".to_string(),
+                instance: None,
+            }
+        }
+        Err(e) => {
+            error!("Failed to issue session token after registration: {}", e);
+            registered_please_login(created)
+        }
+    }
+}
+
+fn registered_please_login(created: Value) -> AuthData {
+    AuthData {
+        token: None,
+        user: user_info(&created),
+        message: "Registration successful, please login".to_string(),
+        instance: None,
+    }
+}
+
+/// Normalize a requested username and check that nobody has it yet
+pub(super) async fn check_username(global_pb: &GlobalPb, username: &str) -> Result<String, Response> {
+    let problems = username_problems(username);
+    if !problems.is_empty() {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            &problems.join("; "),
+        ));
+    }
+
+    let username = username.to_lowercase();
+    let filter = format!("username = {}", global_pb::quote(&username));
+    match global_pb.list_records("users", Some(&filter)).await {
+        Ok(existing) if existing.is_empty() => Ok(username),
+        Ok(_) => Err(auth_error(StatusCode::CONFLICT, ErrorCode::UsernameTaken, "Username is already taken")),
+        Err(e) => {
+            error!("Failed to check username availability: {}", e);
+            Err(auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Registration server error"))
+        }
+    }
+}
+
+/// POST /auth/refresh - exchange the current token for a fresh one
+///
+/// Our own tokens are accepted up to the refresh window past expiry and only
+/// while the session is younger than its maximum age; legacy PocketBase tokens
+/// are accepted while the transition is enabled. Either way PocketBase
+/// refreshes the underlying token, which also picks up record changes.
+async fn refresh(
+    State(config): State<Arc<Config>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(sessions): State<Arc<SessionList>>,
+    origin: SessionOrigin,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let rejected = |message: &str| auth_error(StatusCode::UNAUTHORIZED, ErrorCode::InvalidToken, message);
+
+    let Some(token) = session_token(&headers) else {
+        return Err(rejected("Authorization header must be a Bearer token"));
+    };
+
+    let keys = JwtKeys::new(&config.security);
+    let (pb_token, auth_time) = match keys.verify_for_refresh(token) {
+        Ok(claims) => {
+            if revocations.is_revoked(token).await
+                || claims.token_generation < revocations.generation(&claims.sub).await
+            {
+                return Err(rejected("Token has been revoked"));
+            }
+            let auth_time = claims.auth_time;
+            match keys.user_from_claims(claims) {
+                Ok(user) => (user.token, auth_time),
+                Err(e) => {
+                    warn!("Refresh rejected: {}", e);
+                    return Err(rejected("Invalid token"));
+                }
+            }
+        }
+        Err(JwtError::NotIssuedHere) if config.security.accept_legacy_pb_tokens => {
+            if revocations.is_revoked(token).await {
+                return Err(rejected("Token has been revoked"));
+            }
+            (token.to_string(), jwt::now())
+        }
+        Err(JwtError::SessionTooOld) => return Err(rejected("Session has expired, please log in again")),
+        Err(e) => {
+            warn!("Refresh rejected: {}", e);
+            return Err(rejected("Invalid or expired token"));
+        }
+    };
+
+    let client = reqwest::Client::new();
+    let refresh_url = format!("{}/api/collections/users/auth-refresh", config.database.url);
+    let response = match client.post(&refresh_url).bearer_auth(&pb_token).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Failed to connect to PocketBase: {}", e);
+            return Err(unavailable());
+        }
+    };
+    if !response.status().is_success() {
+        warn!("PocketBase refused to refresh session: {}", response.status());
+        return Err(rejected("Invalid or expired token"));
+    }
+
+    let issued = match response.json::<Value>().await {
+        Ok(pb_response) => mint_session_token(&config, &revocations, &pb_response, auth_time)
+            .await
+            .map(|minted| (minted, pb_response)),
+        Err(e) => Err(e.to_string()),
+    };
+    match issued {
+        Ok(((new_token, user_id), pb_response)) => {
+            let expires_at = session_expiry(&config, &new_token);
+            sessions.rotate(token, &new_token, &user_id, &origin, expires_at).await;
+            let cookie = bearer_token(&headers).is_none();
+            let user = user_info(pb_response.get("record").unwrap_or(&Value::Null));
+            Ok(deliver(&config, new_token, cookie, user, "Token refreshed", None))
+        }
+        Err(e) => {
+            error!("Failed to issue refreshed session token: {}", e);
+            Err(server_error(AppError::Internal(format!("Failed to issue refreshed session token: {}", e))))
+        }
+    }
+}
+
+pub(super) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|token| !token.is_empty())
+}
+
+/// The caller's token, from the bearer header or else the session cookie
+pub(super) fn session_token(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers).or_else(|| csrf::session_cookie(headers))
+}
+
+/// POST /auth/logout - revoke the caller's token
+///
+/// The token stays revoked until it would have expired, including the
+/// refresh window for our own tokens. Succeeds even without a valid token so
+/// clients can always log out, and clears the session cookies.
+async fn logout(
+    State(config): State<Arc<Config>>,
+    State(auth_cache): State<Arc<AuthCache>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(sessions): State<Arc<SessionList>>,
+    State(audit): State<Arc<AuditLogger>>,
+    origin: SessionOrigin,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(token) = session_token(&headers) {
+        auth_cache.invalidate(token).await;
+
+        let mut event = AuthEvent::new(AuthEventType::Logout, &origin);
+        let expires_at = match JwtKeys::new(&config.security).verify_for_refresh(token) {
+            Ok(claims) => {
+                event = event.user(&claims.sub).email(&claims.email);
+                Some(claims.exp + config.security.jwt_refresh_window_secs)
+            }
+            Err(JwtError::NotIssuedHere) => Some(
+                jwt::unverified_expiry(token)
+                    .unwrap_or_else(|| jwt::now() + config.security.jwt_max_session_secs),
+            ),
+            // Forged or already unusable, so there is nothing to revoke
+            Err(_) => None,
+        };
+        if let Some(expires_at) = expires_at {
+            revocations.revoke(token, expires_at).await;
+            sessions.close(token).await;
+            audit.record(event);
+        }
+    }
+
+    let reply = Json(json!({
+        "success": true,
+        "message": "Logged out"
+    }));
+    if csrf::session_cookie(&headers).is_some() {
+        (csrf::cleared_cookies(&config), reply).into_response()
+    } else {
+        reply.into_response()
+    }
+}
+
+/// POST /auth/logout_all - revoke every token issued to the caller so far
+///
+/// Bumps the user's token generation, so tokens minted before now are
+/// rejected while later logins work as usual.
+async fn logout_all(
+    user: AuthUser,
+    origin: SessionOrigin,
+    State(auth_cache): State<Arc<AuthCache>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(sessions): State<Arc<SessionList>>,
+    State(audit): State<Arc<AuditLogger>>,
+) -> Response {
+    auth_cache.invalidate_user(&user.id).await;
+    sessions.close_all(&user.id).await;
+    audit.record(AuthEvent::new(AuthEventType::LogoutAll, &origin).user(&user.id).email(&user.email));
+
+    match revocations.bump_generation(&user.id).await {
+        Ok(generation) => {
+            info!(target: "audit", user_id = %user.id, generation, "Logged out of all sessions");
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "success": true,
+                    "message": "Logged out of all sessions",
+                    "token_generation": generation
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Failed to persist token generation for {}: {}", user.id, e);
+            let response = (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "success": false,
+                    "message": "Sessions were revoked but could not be recorded; they may return after a restart"
+                })),
+            );
+            with_cause(
+                response.into_response(),
+                AppError::Database(format!("Failed to persist token generation: {}", e)),
+            )
         }
     }
 }
+
+
+/// POST /auth/change_password - set a new password after confirming the current one
+///
+/// Every other session ends: PocketBase invalidates its own tokens for the
+/// account and the user's token generation is bumped. The current session
+/// carries on with the fresh token in the reply. Wrong current passwords
+/// count towards the same lockout as failed logins.
+async fn change_password(
+    State(state): State<AppState>,
+    origin: SessionOrigin,
+    user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<Response, Response> {
+    let AppState { config, auth_cache, revocations, login_guard, sessions, audit, .. } = state;
+    let event = AuthEvent::new(AuthEventType::PasswordChange, &origin).user(&user.id).email(&user.email);
+    if request.current_password.is_empty() {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "Current password is required",
+        ));
+    }
+    if request.new_password != request.new_password_confirm {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "Passwords do not match",
+        ));
+    }
+    let problems = password_problems(&request.new_password);
+    if !problems.is_empty() {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::WeakPassword,
+            &problems.join("; "),
+        ));
+    }
+
+    let email = user.email.to_lowercase();
+    if let Err(rejection) = login_guard.check(&origin.ip, &email).await {
+        warn!("Refused password change for {} from {}: {:?}", user.id, origin.ip, rejection);
+        return Err(too_many_attempts(rejection));
+    }
+
+    let current = match authenticate_with_pb(&config, &user.email, &request.current_password).await {
+        Ok(auth) if auth.record.get("id").and_then(|v| v.as_str()) == Some(user.id.as_str()) => auth,
+        Ok(_) | Err(AuthFailure::InvalidCredentials) => {
+            login_guard.record_failure(&email).await;
+            warn!(target: "audit", user_id = %user.id, "Password change with wrong current password");
+            audit.record(event.failed("wrong_password"));
+            return Err(auth_error(
+                StatusCode::FORBIDDEN,
+                ErrorCode::WrongPassword,
+                "Current password is incorrect",
+            ));
+        }
+        Err(AuthFailure::Unavailable(e)) => {
+            error!("Failed to connect to PocketBase: {}", e);
+            return Err(unavailable());
+        }
+        Err(e) => {
+            error!("Failed to confirm current password for {}: {:?}", user.id, e);
+            return Err(server_error(AppError::Database(format!("Failed to confirm current password: {:?}", e))));
+        }
+    };
+    login_guard.record_success(&email).await;
+
+    let pb_token = current.pb_response.get("token").and_then(|t| t.as_str()).unwrap_or(&user.token);
+    let url = format!("{}/api/collections/users/records/{}", config.database.url, user.id);
+    let update = json!({
+        "oldPassword": request.current_password,
+        "password": request.new_password,
+        "passwordConfirm": request.new_password_confirm
+    });
+    match reqwest::Client::new()
+        .patch(&url)
+        .header("Authorization", pb_token)
+        .json(&update)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => {
+            let status = response.status();
+            error!("PocketBase refused password change for {}: {} - {}", user.id, status, response.text().await.unwrap_or_default());
+            return Err(auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Failed to update password"));
+        }
+        Err(e) => {
+            error!("Failed to connect to PocketBase: {}", e);
+            return Err(unavailable());
+        }
+    }
+
+    auth_cache.invalidate_user(&user.id).await;
+    if let Err(e) = revocations.bump_generation(&user.id).await {
+        warn!("Failed to persist session revocation after password change: {}", e);
+    }
+    sessions.close_all(&user.id).await;
+    info!(target: "audit", user_id = %user.id, "Password changed");
+    audit.record(event);
+
+    // Issued after the bump, so this is the only session left
+    let renewed = match authenticate_with_pb(&config, &user.email, &request.new_password).await {
+        Ok(auth) => issue_session_token(&config, &revocations, &sessions, &origin, &auth.pb_response, jwt::now())
+            .await
+            .map(|token| (token, auth.record)),
+        Err(e) => Err(format!("{:?}", e)),
+    };
+    match renewed {
+        Ok((token, record)) => {
+            let cookie = bearer_token(&headers).is_none();
+            Ok(deliver(&config, token, cookie, user_info(&record), "Password changed", None))
+        }
+        Err(e) => {
+            warn!("Failed to renew session after password change for {}: {}", user.id, e);
+            Ok(signed_in(None, user_info(&current.record), "Password changed, please log in again").into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{
+            create_api_router,
+            extractors::{AuthUser, Role},
+            jwt::{self, JwtKeys},
+        },
+        pocketbase_manager::PocketBaseManager,
+        test_support::{counting_global_pocketbase, fake_pocketbase, mock_app_state_with, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    fn login_request(email: &str, password: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "email": email, "password": password }).to_string()))
+            .unwrap()
+    }
+
+    async fn json_body(response: axum::response::Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn authed(method: &str, uri: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_repeated_requests_validate_token_once_until_logout() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, upstream_calls) = counting_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        // The proxy authenticates before its owner check, so a forbidden
+        // request still exercises validation without starting an instance
+        for _ in 0..5 {
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", "/api/users/alice/pb/api/health", "valid-mallory"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+
+        // Rejected tokens are never cached
+        for _ in 0..2 {
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", "/api/users/alice/pb/api/health", "forged"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 3);
+
+        let response = create_api_router(state.clone())
+            .oneshot(authed("POST", "/auth/logout", "valid-mallory"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.auth_cache.is_empty().await);
+
+        // Revoked tokens are turned away before PocketBase is asked
+        let response = create_api_router(state.clone())
+            .oneshot(authed("GET", "/api/users/alice/pb/api/health", "valid-mallory"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_login_issues_token_verified_without_pocketbase() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, upstream_calls) = counting_global_pocketbase().await;
+        let missing_binary = dir.path().join("pocketbase").display().to_string();
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, missing_binary);
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("admin@example.com", "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["success"], false);
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("admin@example.com", "password"))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        assert_ne!(token, "valid-admin");
+
+        let claims = JwtKeys::new(&state.config.security).verify(&token).unwrap();
+        assert_eq!(claims.sub, "admin");
+        assert_eq!(claims.role, "admin");
+
+        // An admin gets through to alice's instance, whose binary is missing
+        for _ in 0..3 {
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", "/api/users/alice/pb/api/health", &token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 0);
+
+        // A broken signature is rejected locally rather than sent upstream
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        let flipped = if signature.starts_with('A') { 'B' } else { 'A' };
+        let tampered = format!("{}.{}{}", signed, flipped, &signature[1..]);
+        let response = create_api_router(state.clone())
+            .oneshot(authed("GET", "/api/users/alice/pb/api/health", &tampered))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_pocketbase_tokens_only_accepted_during_transition() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, upstream_calls) = counting_global_pocketbase().await;
+        let mut config = test_config(&global_url);
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(config.clone(), manager);
+
+        let response = create_api_router(state)
+            .oneshot(authed("GET", "/api/users/alice/pb/api/health", "valid-mallory"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+
+        config.security.accept_legacy_pb_tokens = false;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(config, manager);
+        let response = create_api_router(state)
+            .oneshot(authed("GET", "/api/users/alice/pb/api/health", "valid-mallory"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+    }
+
+    fn refresh_request(token: &str) -> Request<Body> {
+        authed("POST", "/auth/refresh", token)
+    }
+
+    #[tokio::test]
+    async fn test_refresh_reissues_token_within_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, upstream_calls) = counting_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        let keys = JwtKeys::new(&state.config.security);
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("bob@example.com", "password"))
+            .await
+            .unwrap();
+        let token = json_body(response).await["data"]["token"].as_str().unwrap().to_string();
+        let original = keys.verify(&token).unwrap();
+
+        let response = create_api_router(state.clone()).oneshot(refresh_request(&token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["user"]["id"], "bob");
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+
+        let refreshed = keys.verify(body["data"]["token"].as_str().unwrap()).unwrap();
+        assert_eq!(refreshed.sub, "bob");
+        assert_eq!(refreshed.auth_time, original.auth_time);
+
+        // Legacy PocketBase tokens are upgraded to our own
+        let response = create_api_router(state.clone()).oneshot(refresh_request("valid-bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(keys.verify(json_body(response).await["data"]["token"].as_str().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rejects_expired_sessions_and_invalid_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, upstream_calls) = counting_global_pocketbase().await;
+        let mut config = test_config(&global_url);
+        config.security.jwt_expiry_secs = 0;
+        config.security.jwt_refresh_window_secs = 0;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(config.clone(), manager);
+        let user = AuthUser {
+            id: "carol".to_string(),
+            email: "carol@example.com".to_string(),
+            name: None,
+            token: "valid-carol".to_string(),
+            role: Role::User,
+        };
+
+        // Expired beyond the refresh window
+        let expired = JwtKeys::new(&config.security).issue(&user, "user").unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let response = create_api_router(state.clone()).oneshot(refresh_request(&expired)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Within the window but past the maximum session age
+        config.security.jwt_refresh_window_secs = 3600;
+        config.security.jwt_max_session_secs = 60;
+        let stale = JwtKeys::new(&config.security)
+            .issue_in_session(&user, "user", jwt::now() - 120, 0)
+            .unwrap();
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(config, manager);
+        let response = create_api_router(state.clone()).oneshot(refresh_request(&stale)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 0);
+
+        // Tokens PocketBase doesn't recognise
+        let response = create_api_router(state.clone()).oneshot(refresh_request("forged")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(json_body(response).await["success"], false);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+    }
+
+    async fn login_token(state: &crate::api::AppState, email: &str) -> String {
+        let response = create_api_router(state.clone())
+            .oneshot(login_request(email, "password"))
+            .await
+            .unwrap();
+        json_body(response).await["data"]["token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_logout_revokes_token_on_next_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        for token in [login_token(&state, "frank@example.com").await, "valid-frank".to_string()] {
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", "/api/users/alice/pb/api/health", &token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+            let response = create_api_router(state.clone())
+                .oneshot(authed("POST", "/auth/logout", &token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(state.revocations.is_revoked(&token).await);
+
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", "/api/users/alice/pb/api/health", &token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let response = create_api_router(state.clone()).oneshot(refresh_request(&token)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logout_all_invalidates_only_older_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let laptop = login_token(&state, "gina@example.com").await;
+        let phone = login_token(&state, "gina@example.com").await;
+        let other_user = login_token(&state, "hank@example.com").await;
+
+        let response = create_api_router(state.clone())
+            .oneshot(authed("POST", "/auth/logout_all", &laptop))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["token_generation"], 1);
+
+        let status = |token: String| {
+            let state = state.clone();
+            async move {
+                create_api_router(state)
+                    .oneshot(authed("GET", "/api/users/alice/pb/api/health", &token))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(status(laptop).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(phone).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(other_user).await, StatusCode::FORBIDDEN);
+
+        let fresh = login_token(&state, "gina@example.com").await;
+        assert_eq!(JwtKeys::new(&state.config.security).verify(&fresh).unwrap().token_generation, 1);
+        assert_eq!(status(fresh).await, StatusCode::FORBIDDEN);
+    }
+
+    fn login_from(forwarded_for: &str) -> Request<Body> {
+        let mut request = login_request("ivan@example.com", "password");
+        request.headers_mut().insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_lock_the_email() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        for _ in 0..5 {
+            let response = create_api_router(state.clone())
+                .oneshot(login_request("jill@example.com", "wrong"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(json_body(response).await["success"], false);
+        }
+
+        // Even the right password is refused while locked, with no upstream call
+        let response = create_api_router(state.clone())
+            .oneshot(login_request(" Jill@Example.com", "password"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "900");
+        assert_eq!(json_body(response).await["code"], "rate_limited");
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("kurt@example.com", "password"))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_login_rate_limit_only_trusts_proxy_headers_when_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        for trust_proxy_headers in [false, true] {
+            let mut config = test_config(&global_url);
+            config.security.login_max_attempts = 2;
+            config.security.trust_proxy_headers = trust_proxy_headers;
+            let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+            let state = test_app_state(config, manager);
+
+            for forwarded_for in ["203.0.113.1", "203.0.113.2"] {
+                let response = create_api_router(state.clone()).oneshot(login_from(forwarded_for)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+            let response = create_api_router(state.clone()).oneshot(login_from("203.0.113.1")).await.unwrap();
+            if trust_proxy_headers {
+                // Each forwarded address has its own window
+                assert_eq!(response.status(), StatusCode::OK);
+                let response = create_api_router(state.clone()).oneshot(login_from("203.0.113.1")).await.unwrap();
+                assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+                // Putting a fresh address in front of the proxy's doesn't open a new one
+                let spoofed = login_from("198.51.100.7, 203.0.113.1");
+                let response = create_api_router(state.clone()).oneshot(spoofed).await.unwrap();
+                assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            } else {
+                // Spoofed headers are ignored, so every attempt shares one window
+                assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+                assert!(response.headers().contains_key("retry-after"));
+            }
+        }
+    }
+
+    fn register_request(email: &str, username: Option<&str>) -> Request<Body> {
+        let mut body = json!({ "email": email, "password": "password", "password_confirm": "password" });
+        if let Some(username) = username {
+            body["username"] = json!(username);
+        }
+        Request::builder()
+            .method("POST")
+            .uri("/auth/register")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_stores_and_returns_username() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        let register = |email: &'static str, username: Option<&'static str>| {
+            let state = state.clone();
+            async move {
+                let response = create_api_router(state).oneshot(register_request(email, username)).await.unwrap();
+                json_body(response).await
+            }
+        };
+
+        let body = register("lena@example.com", Some(" Lena.K ")).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["user"]["username"], "lena.k");
+        let users = state.global_pb.list_records("users", None).await.unwrap();
+        assert_eq!(users[0]["username"], "lena.k");
+
+        let body = register("lena2@example.com", Some("LENA.k")).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "username_taken");
+        assert_eq!(body["error"], "Username is already taken");
+
+        let body = register("lena3@example.com", Some("no spaces!")).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "validation");
+        assert_eq!(body["error"], "Username may only contain letters, numbers, '_', '.' and '-'");
+        assert_eq!(state.global_pb.list_records("users", None).await.unwrap().len(), 1);
+
+        // Older clients that don't send one can still register
+        let body = register("milo@example.com", None).await;
+        assert_eq!(body["success"], true);
+        assert!(body["data"]["token"].is_string());
+        assert_eq!(state.global_pb.list_records("users", None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_register_signs_in_the_new_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(register_request("nina@example.com", None))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["message"], "Registration successful");
+
+        let created = state.global_pb.list_records("users", None).await.unwrap();
+        let claims = JwtKeys::new(&state.config.security).verify(body["data"]["token"].as_str().unwrap()).unwrap();
+        assert_eq!(claims.sub, created[0]["id"].as_str().unwrap());
+        assert_eq!(body["data"]["user"]["id"], created[0]["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_register_is_refused_when_registration_is_closed() {
+        let state = mock_app_state_with(|config| config.features.registration_enabled = false).await;
+
+        let response = create_api_router(state.clone())
+            .oneshot(register_request("olga@example.com", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "registration_closed");
+        assert!(state.global_pb.list_records("users", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_register_reports_created_account_when_login_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        // The mock only signs in with "password", so this account is created
+        // but the login that follows fails
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/register")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "email": "omar@example.com", "password": "S3cret-pass", "password_confirm": "S3cret-pass" })
+                    .to_string(),
+            ))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        assert!(body["data"]["token"].is_null());
+        assert_eq!(body["data"]["message"], "Registration successful, please login");
+        assert_eq!(body["data"]["user"]["email"], "omar@example.com");
+        assert_eq!(state.global_pb.list_records("users", None).await.unwrap().len(), 1);
+    }
+
+    fn auth_request(uri: &str, body: Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_auth_failures_use_status_codes_and_error_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let unreachable = test_app_state(test_config("http://127.0.0.1:9"), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(register_request("pia@example.com", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let registration = |email: &str, password: &str| {
+            auth_request(
+                "/auth/register",
+                json!({ "email": email, "password": password, "password_confirm": password }),
+            )
+        };
+        let cases = [
+            (&state, login_request("pia@example.com", ""), StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+            (&state, login_request("pia@example.com", "wrong"), StatusCode::UNAUTHORIZED, "invalid_credentials"),
+            (&unreachable, login_request("pia@example.com", "password"), StatusCode::SERVICE_UNAVAILABLE, "service_unavailable"),
+            (&state, registration("pia@example.com", "password"), StatusCode::CONFLICT, "email_exists"),
+            (&state, registration("quin@example.com", "short"), StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+            (&unreachable, registration("quin@example.com", "password"), StatusCode::SERVICE_UNAVAILABLE, "service_unavailable"),
+            (&state, refresh_request("forged"), StatusCode::UNAUTHORIZED, "invalid_token"),
+        ];
+        for (state, request, status, code) in cases {
+            let uri = request.uri().to_string();
+            let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{} {}", uri, code);
+            let body = json_body(response).await;
+            assert_eq!(body["success"], false);
+            assert_eq!(body["code"], code, "{}", uri);
+            assert!(body["data"].is_null());
+            assert!(body["error"].is_string());
+        }
+
+        // PocketBase's per-field messages are replaced with ours
+        let response = create_api_router(state.clone())
+            .oneshot(registration("quin@example.com", "short"))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["error"], "Password does not meet the requirements");
+    }
+
+    async fn change_password(state: &crate::api::AppState, token: &str, current: &str, new: &str, confirm: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/change_password")
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::from(
+                json!({ "current_password": current, "new_password": new, "new_password_confirm": confirm }).to_string(),
+            ))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        (status, json_body(response).await)
+    }
+
+    #[tokio::test]
+    async fn test_change_password_failure_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        state.global_pb.create_record("users", &json!({ "email": "rita@example.com" })).await.unwrap();
+        let token = login_token(&state, "rita@example.com").await;
+
+        for (current, new, confirm, status, code) in [
+            ("password", "N3w-password", "N3w-passw0rd", StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+            ("password", "newpassword", "newpassword", StatusCode::UNPROCESSABLE_ENTITY, "weak_password"),
+            ("not-my-password", "N3w-password", "N3w-password", StatusCode::FORBIDDEN, "wrong_password"),
+            ("", "N3w-password", "N3w-password", StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+        ] {
+            let (actual, body) = change_password(&state, &token, current, new, confirm).await;
+            assert_eq!(actual, status, "{}", code);
+            assert_eq!(body["success"], false);
+            assert_eq!(body["code"], code);
+        }
+        let (_, body) = change_password(&state, &token, "password", "newpassword", "newpassword").await;
+        assert_eq!(body["error"], "Password must contain an uppercase letter; Password must contain a number");
+
+        let (status, _) = change_password(&state, "forged", "password", "N3w-password", "N3w-password").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Nothing changed, so the session and the old password still work
+        let response = create_api_router(state.clone()).oneshot(authed("GET", "/auth/me", &token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("rita@example.com", "password"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_change_password_ends_other_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        state.global_pb.create_record("users", &json!({ "email": "sam@example.com" })).await.unwrap();
+        let laptop = login_token(&state, "sam@example.com").await;
+        let phone = login_token(&state, "sam@example.com").await;
+
+        let (status, body) = change_password(&state, &laptop, "password", "N3w-password", "N3w-password").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["message"], "Password changed");
+        let current = body["data"]["token"].as_str().unwrap().to_string();
+
+        let status = |token: String| {
+            let state = state.clone();
+            async move {
+                create_api_router(state)
+                    .oneshot(authed("GET", "/auth/me", &token))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(status(laptop).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(phone).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(current).await, StatusCode::OK);
+
+        for (password, expected) in [("password", StatusCode::UNAUTHORIZED), ("N3w-password", StatusCode::OK)] {
+            let response = create_api_router(state.clone())
+                .oneshot(login_request("sam@example.com", password))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+
+    async fn poll_instance(state: &crate::api::AppState, user_id: &str, token: &str, until: &str) -> Value {
+        for _ in 0..200 {
+            let response = create_api_router(state.clone())
+                .oneshot(authed("GET", &format!("/api/users/{}/pb_status", user_id), token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = json_body(response).await;
+            if body["status"]["status"] == until {
+                return body["status"].clone();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("instance of {} never became {}", user_id, until);
+    }
+
+    #[tokio::test]
+    async fn test_login_starts_the_users_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir.path());
+        let port = port_window(10);
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 9)
+            .with_readiness_timeout(std::time::Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("erin@example.com", "password"))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["instance"]["status"], "starting");
+        assert!(body["data"]["instance"].get("url").is_none());
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+
+        let instance = poll_instance(&state, "erin", &token, "running").await;
+        assert_eq!(instance["url"], "/api/v1/users/erin/pb");
+
+        // Signing in again reports the running instance straight away
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("erin@example.com", "password"))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["data"]["instance"]["status"], "running");
+        assert_eq!(body["data"]["instance"]["url"], "/api/v1/users/erin/pb");
+
+        // The advertised URL is one the router serves
+        let health = format!("{}/api/health", instance["url"].as_str().unwrap());
+        let response = create_api_router(state.clone())
+            .oneshot(authed("GET", &health, &token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Polling shows the instance's health, not where its data lives
+        let response = create_api_router(state.clone())
+            .oneshot(authed("GET", "/api/users/erin/pb_status", &token))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        let mut fields: Vec<_> = body["instance"].as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+        assert_eq!(fields, ["last_health_check", "port", "status"]);
+
+        // Other users can't poll someone else's instance
+        let response = create_api_router(state.clone())
+            .oneshot(authed("GET", "/api/users/erin/pb_status", "valid-mallory"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        state.pb_manager.stop_user_instance("erin").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_instance_start_failure_does_not_fail_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let missing = dir.path().join("no-such-pocketbase");
+        let port = port_window(10);
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), port, missing.display().to_string())
+            .with_port_range(port, port + 9);
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(login_request("finn@example.com", "password"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["success"], true);
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+
+        let instance = poll_instance(&state, "finn", &token, "failed").await;
+        assert!(instance.get("url").is_none());
+    }
+}
@@ -0,0 +1,173 @@
+//! Short-lived cache of validated bearer tokens
+//!
+//! Saves the round-trip to the global PocketBase's `auth-refresh` endpoint on
+//! every authenticated request. Entries are keyed by the SHA-256 of the token
+//! so raw tokens are never held as map keys, expire after a fixed TTL, and are
+//! evicted least-recently-used first once the cache is full. Only successful
+//! validations are cached.
+
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+
+use super::extractors::AuthUser;
+
+/// SHA-256 of a bearer token
+pub type TokenHash = [u8; 32];
+
+pub fn hash_token(token: &str) -> TokenHash {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+struct Entry {
+    user: AuthUser,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<TokenHash, Entry>,
+    /// Monotonic counter recording how recently each entry was used
+    clock: u64,
+}
+
+/// TTL + LRU cache from token hash to the user it resolved to
+pub struct AuthCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl AuthCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The cached user for `token`, unless missing or expired
+    pub async fn get(&self, token: &str) -> Option<AuthUser> {
+        let key = hash_token(token);
+        let mut entries = self.entries.lock().await;
+        entries.clock += 1;
+        let now = entries.clock;
+
+        match entries.map.get_mut(&key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = now;
+                Some(entry.user.clone())
+            }
+            Some(_) => {
+                entries.map.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Remember that `token` resolved to `user`
+    pub async fn insert(&self, token: &str, user: AuthUser) {
+        let key = hash_token(token);
+        let mut entries = self.entries.lock().await;
+        entries.clock += 1;
+        let now = entries.clock;
+
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.max_entries {
+            let expired_at = Instant::now();
+            entries.map.retain(|_, entry| entry.expires_at > expired_at);
+            if entries.map.len() >= self.max_entries {
+                let oldest = entries
+                    .map
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| *key);
+                if let Some(oldest) = oldest {
+                    entries.map.remove(&oldest);
+                }
+            }
+        }
+
+        entries.map.insert(
+            key,
+            Entry {
+                user,
+                expires_at: Instant::now() + self.ttl,
+                last_used: now,
+            },
+        );
+    }
+
+    /// Forget `token`, e.g. on logout
+    pub async fn invalidate(&self, token: &str) {
+        self.entries.lock().await.map.remove(&hash_token(token));
+    }
+
+    /// Forget every token that resolved to `user_id`
+    pub async fn invalidate_user(&self, user_id: &str) {
+        self.entries.lock().await.map.retain(|_, entry| entry.user.id != user_id);
+    }
+
+    pub async fn len(&self) -> usize {
+        self.entries.lock().await.map.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::extractors::Role;
+
+    fn user(id: &str) -> AuthUser {
+        AuthUser {
+            id: id.to_string(),
+            email: format!("{}@example.com", id),
+            name: None,
+            token: format!("valid-{}", id),
+            role: Role::User,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_entries_expire_after_ttl() {
+        let cache = AuthCache::new(Duration::from_secs(60), 10);
+        cache.insert("token-a", user("a")).await;
+
+        tokio::time::sleep(Duration::from_secs(59)).await;
+        assert_eq!(cache.get("token-a").await.unwrap().id, "a");
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(cache.get("token-a").await.is_none());
+        assert_eq!(cache.len().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_entry_is_evicted() {
+        let cache = AuthCache::new(Duration::from_secs(60), 2);
+        cache.insert("token-a", user("a")).await;
+        cache.insert("token-b", user("b")).await;
+
+        // Touch a so b becomes the eviction candidate
+        assert!(cache.get("token-a").await.is_some());
+        cache.insert("token-c", user("c")).await;
+
+        assert_eq!(cache.len().await, 2);
+        assert!(cache.get("token-a").await.is_some());
+        assert!(cache.get("token-b").await.is_none());
+        assert!(cache.get("token-c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_removes_entry() {
+        let cache = AuthCache::new(Duration::from_secs(60), 10);
+        cache.insert("token-a", user("a")).await;
+        cache.invalidate("token-a").await;
+        assert!(cache.get("token-a").await.is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! Cross-origin access for the browser frontend
+//!
+//! The frontend is served from its own origin and calls the API with
+//! credentials (bearer tokens, or the session cookie plus its CSRF header),
+//! so only the origins in `CORS_ORIGINS` may read responses. A lone "*"
+//! entry mirrors whatever origin asks, which is only meant for development.
+
+use axum::http::{
+    header::{self, HeaderName},
+    HeaderValue, Method,
+};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use super::csrf::CSRF_HEADER;
+use crate::config::CorsConfig;
+
+/// How long browsers may cache a preflight answer
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// The CORS layer applied to every backend route
+pub fn layer(config: &CorsConfig) -> CorsLayer {
+    let origins = if config.origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::mirror_request()
+    } else {
+        // Origins are validated while loading the config
+        AllowOrigin::list(
+            config
+                .origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            header::IF_NONE_MATCH,
+            HeaderName::from_static(CSRF_HEADER),
+            HeaderName::from_static("x-api-key"),
+        ])
+        .expose_headers([header::ETAG, header::RETRY_AFTER])
+        .allow_credentials(true)
+        .max_age(PREFLIGHT_MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(origins: &[&str]) -> Router {
+        let config = CorsConfig {
+            origins: origins.iter().map(|origin| origin.to_string()).collect(),
+        };
+        Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .layer(layer(&config))
+    }
+
+    fn request(method: Method, origin: &str) -> Request<Body> {
+        let builder = Request::builder()
+            .method(method.clone())
+            .uri("/health")
+            .header(header::ORIGIN, origin);
+        let builder = if method == Method::OPTIONS {
+            builder
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+        } else {
+            builder
+        };
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_configured_origins_are_allowed() {
+        let app = app(&["http://localhost:8080"]);
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::GET, "http://localhost:8080"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://localhost:8080"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let response = app
+            .oneshot(request(Method::GET, "https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_succeeds_for_allowed_origins() {
+        let app = app(&["http://localhost:8080"]);
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::OPTIONS, "http://localhost:8080"))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://localhost:8080"
+        );
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("GET"));
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("authorization"));
+
+        let response = app
+            .oneshot(request(Method::OPTIONS, "https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_mirrors_any_origin() {
+        let response = app(&["*"])
+            .oneshot(request(Method::GET, "http://127.0.0.1:5173"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://127.0.0.1:5173"
+        );
+    }
+}
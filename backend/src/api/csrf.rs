@@ -0,0 +1,242 @@
+//! Cookie sessions and their CSRF protection
+//!
+//! Clients that can't hold on to a bearer token, like the embedded browser,
+//! sign in with `"cookie": true`. The session token then travels in an
+//! HttpOnly `session` cookie, next to a readable `csrf_token` cookie. Since
+//! the browser attaches cookies to any request, mutating requests
+//! authenticated by the cookie must repeat the CSRF token in `X-CSRF-Token`
+//! (double submit). Requests with a bearer token never carry ambient
+//! credentials and skip the check.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use base64::Engine;
+use common::crypto::constant_time_eq;
+use rand::RngCore;
+use tracing::warn;
+
+use super::extractors::AuthError;
+use crate::config::Config;
+
+pub const SESSION_COOKIE: &str = "session";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Signing in replaces the session, so it must work without the old CSRF token
+const EXEMPT_PATHS: &[&str] = &["/auth/login"];
+
+/// The value of a request cookie
+pub(super) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+/// The session token carried in the session cookie, if any
+pub(super) fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    cookie(headers, SESSION_COOKIE).filter(|token| !token.is_empty())
+}
+
+fn new_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn cookie_header(config: &Config, name: &str, value: &str, max_age: u64, http_only: bool) -> HeaderValue {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; SameSite={}",
+        name,
+        value,
+        max_age,
+        config.security.session_cookie_same_site.as_str()
+    );
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if config.security.session_cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    // Tokens and the CSRF value are base64url, which is always a valid header
+    HeaderValue::from_str(&cookie).expect("cookie values are header-safe")
+}
+
+/// `Set-Cookie` headers delivering a session token with a fresh CSRF token
+///
+/// The cookies outlive the token by the refresh window so the browser can
+/// still present it to `/auth/refresh`.
+pub(super) fn session_cookies(config: &Config, token: &str) -> AppendHeaders<[(header::HeaderName, HeaderValue); 2]> {
+    let max_age = config.security.jwt_expiry_secs + config.security.jwt_refresh_window_secs;
+    AppendHeaders([
+        (header::SET_COOKIE, cookie_header(config, SESSION_COOKIE, token, max_age, true)),
+        (header::SET_COOKIE, cookie_header(config, CSRF_COOKIE, &new_csrf_token(), max_age, false)),
+    ])
+}
+
+/// `Set-Cookie` headers removing the session and CSRF cookies
+pub(super) fn cleared_cookies(config: &Config) -> AppendHeaders<[(header::HeaderName, HeaderValue); 2]> {
+    AppendHeaders([
+        (header::SET_COOKIE, cookie_header(config, SESSION_COOKIE, "", 0, true)),
+        (header::SET_COOKIE, cookie_header(config, CSRF_COOKIE, "", 0, false)),
+    ])
+}
+
+/// Reject mutating requests authenticated by the session cookie unless
+/// `X-CSRF-Token` matches the `csrf_token` cookie
+pub async fn require_csrf_token(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let bearer = headers.contains_key(header::AUTHORIZATION);
+    if safe || bearer || session_cookie(headers).is_none() || EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let expected = cookie(headers, CSRF_COOKIE).filter(|token| !token.is_empty());
+    let submitted = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, submitted) {
+        (Some(expected), Some(submitted)) if constant_time_eq(expected.as_bytes(), submitted.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            warn!(target: "audit", method = %request.method(), path = %request.uri().path(), "CSRF check failed");
+            AuthError::forbidden("csrf_token_mismatch", "Missing or invalid CSRF token").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CSRF_HEADER;
+    use crate::{
+        api::create_api_router,
+        test_support::mock_app_state,
+    };
+    use axum::{
+        body::Body,
+        http::{header, Request, Response, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn state() -> crate::api::AppState {
+        mock_app_state().await
+    }
+
+    async fn login(state: &crate::api::AppState, cookie: bool) -> Response<Body> {
+        let body = json!({ "email": "nora@example.com", "password": "password", "cookie": cookie });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        create_api_router(state.clone()).oneshot(request).await.unwrap()
+    }
+
+    /// The `name=value` pairs the response sets, ready to send back as `Cookie`
+    fn set_cookies(response: &Response<Body>) -> (String, String) {
+        let values: Vec<&str> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        let find = |name: &str| {
+            let value = values.iter().find(|value| value.starts_with(name)).unwrap();
+            value.split(';').next().unwrap().to_string()
+        };
+        (find("session="), find("csrf_token="))
+    }
+
+    async fn post(state: &crate::api::AppState, uri: &str, headers: &[(&str, &str)]) -> StatusCode {
+        let mut request = Request::builder().method("POST").uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_cookie_sessions_need_the_csrf_header() {
+        let state = state().await;
+        let response = login(&state, true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let set: Vec<String> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert!(set[0].starts_with("session=") && set[0].contains("HttpOnly"));
+        assert!(set[1].starts_with("csrf_token=") && !set[1].contains("HttpOnly"));
+        assert!(set.iter().all(|cookie| cookie.contains("SameSite=Strict") && cookie.contains("Secure")));
+        let (session, csrf) = set_cookies(&response);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body["data"]["token"].is_null());
+
+        let cookies = format!("{}; {}", session, csrf);
+        let csrf_value = csrf.strip_prefix("csrf_token=").unwrap();
+
+        // Reads authenticate with the cookie alone
+        let request = Request::builder()
+            .uri("/auth/sessions")
+            .header(header::COOKIE, &cookies)
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(post(&state, "/auth/logout_all", &[("cookie", &cookies)]).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            post(&state, "/api/queue", &[("cookie", &cookies), (CSRF_HEADER, "guessed")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            post(&state, "/auth/logout_all", &[("cookie", &cookies), (CSRF_HEADER, csrf_value)]).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bearer_requests_skip_the_check() {
+        let state = state().await;
+        let response = login(&state, false).await;
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        let bearer = format!("Bearer {}", body["data"]["token"].as_str().unwrap());
+
+        // Even alongside a stale session cookie
+        assert_eq!(
+            post(&state, "/auth/logout_all", &[("authorization", &bearer), ("cookie", "session=stale")]).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_rotates_the_csrf_token() {
+        let state = state().await;
+        let (_, first_csrf) = set_cookies(&login(&state, true).await);
+        let (session, csrf) = set_cookies(&login(&state, true).await);
+        assert_ne!(first_csrf, csrf);
+
+        let stale = first_csrf.strip_prefix("csrf_token=").unwrap();
+        let cookies = format!("{}; {}", session, csrf);
+        assert_eq!(
+            post(&state, "/auth/logout_all", &[("cookie", &cookies), (CSRF_HEADER, stale)]).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Admin views of the dead-letter queue
+//!
+//! The worker copies a task that failed for good, with every attempt's
+//! error and timing and the last stage it got to, to the global PocketBase
+//! `dead_letter` collection, and tags its queue item with the copy's id so it
+//! is never claimed again. Admins list the copies at
+//! `GET /api/admin/dead_letter` and put one back in the queue with
+//! `POST /api/admin/dead_letter/:id/requeue`, which resets its retries and
+//! removes the copy.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Json, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{auth::auth_error, extractors::AdminUser, AppState};
+use crate::global_pb::{self, GlobalPb, GlobalPbError};
+use common::ErrorCode;
+
+const DEAD_LETTER: &str = "dead_letter";
+const QUEUE_ITEMS: &str = "queue_items";
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 200;
+
+/// A task that failed for good, as the worker recorded it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: String,
+    /// The `queue_items` record it came from
+    #[serde(default)]
+    pub queue_item: String,
+    pub task_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub meeting_id: String,
+    #[serde(default)]
+    pub topic: String,
+    /// The task as it was claimed for its last attempt
+    #[serde(default)]
+    pub task: Value,
+    /// Each attempt's `error`, `started_at`, `failed_at` and `stage`, oldest first
+    #[serde(default)]
+    pub attempts: Vec<Value>,
+    /// The last stage any attempt reported progress in
+    #[serde(default)]
+    pub last_stage: Option<String>,
+    #[serde(default)]
+    pub error_message: String,
+    #[serde(default)]
+    pub failed_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub user_id: Option<String>,
+}
+
+/// Admin routes for the dead-letter queue, nested under `/api`
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/dead_letter", get(list_dead_letters))
+        .route("/admin/dead_letter/:id/requeue", post(requeue_dead_letter))
+}
+
+/// GET /api/admin/dead_letter - tasks that failed for good, newest first (admin only)
+async fn list_dead_letters(
+    _admin: AdminUser,
+    State(global_pb): State<Arc<GlobalPb>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Result<Json<Value>, Response> {
+    let filter = query
+        .user_id
+        .as_deref()
+        .filter(|id| !id.is_empty())
+        .map(|user_id| format!("user_id = {}", global_pb::quote(user_id)));
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let listed = global_pb
+        .list_page(DEAD_LETTER, filter.as_deref(), "-failed_at", page, per_page)
+        .await
+        .map_err(|e| {
+            warn!("Failed to list dead letters: {}", e);
+            auth_error(StatusCode::BAD_GATEWAY, ErrorCode::ServiceUnavailable, "Failed to load the dead-letter queue")
+        })?;
+    let items: Vec<DeadLetter> = listed
+        .items
+        .into_iter()
+        .filter_map(|record| serde_json::from_value(record).ok())
+        .collect();
+    Ok(Json(json!({
+        "items": items,
+        "page": listed.page,
+        "per_page": listed.per_page,
+        "total_items": listed.total_items
+    })))
+}
+
+/// POST /api/admin/dead_letter/:id/requeue - Put a dead-lettered task back
+/// in the queue with its retries reset (admin only)
+///
+/// The task's queue item is reset, or created again from the recorded task
+/// if it has since been removed.
+async fn requeue_dead_letter(
+    AdminUser(admin): AdminUser,
+    State(global_pb): State<Arc<GlobalPb>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, Response> {
+    let unavailable = |e: GlobalPbError| {
+        error!("Failed to requeue dead letter {}: {}", id, e);
+        auth_error(StatusCode::BAD_GATEWAY, ErrorCode::ServiceUnavailable, "Failed to requeue the task")
+    };
+    let entry: DeadLetter = match global_pb.get_record(DEAD_LETTER, &id).await {
+        Ok(record) => serde_json::from_value(record).map_err(|e| {
+            error!("Dead letter {} is malformed: {}", id, e);
+            auth_error(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Validation, "The dead letter is malformed")
+        })?,
+        Err(GlobalPbError::Status { status: 404, .. }) => {
+            return Err(auth_error(StatusCode::NOT_FOUND, ErrorCode::NotFound, "No such dead letter"));
+        }
+        Err(e) => return Err(unavailable(e)),
+    };
+
+    let reset = json!({
+        "status": "Pending",
+        "retry_count": 0,
+        "error_message": "",
+        "next_attempt_at": "",
+        "claimed_by": "",
+        "claimed_at": "",
+        "attempts": [],
+        "dead_letter_id": "",
+    });
+    let existing = match entry.queue_item.as_str() {
+        "" => Err(GlobalPbError::Status { status: 404, body: Value::Null }),
+        item_id => global_pb.update_record(QUEUE_ITEMS, item_id, &reset).await,
+    };
+    let item = match existing {
+        Ok(item) => item,
+        Err(GlobalPbError::Status { status: 404, .. }) => {
+            let mut record = json!({
+                "task_id": entry.task_id,
+                "user_id": entry.user_id,
+                "meeting_id": entry.meeting_id,
+                "topic": entry.topic,
+                "max_retries": entry.task.get("max_retries").cloned().unwrap_or(json!(3)),
+                "fathom_key_id": entry.task.get("fathom_key_id").cloned().unwrap_or(Value::Null),
+                "loom_key_id": entry.task.get("loom_key_id").cloned().unwrap_or(Value::Null),
+                "version": 0,
+            });
+            record.as_object_mut().unwrap().extend(reset.as_object().unwrap().clone());
+            global_pb.create_record(QUEUE_ITEMS, &record).await.map_err(unavailable)?
+        }
+        Err(e) => return Err(unavailable(e)),
+    };
+    let item_id = item.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+
+    if let Err(e) = global_pb.delete_record(DEAD_LETTER, &id).await {
+        // The task is queued either way; the stale copy only lingers in the list
+        warn!("Requeued dead letter {} but failed to remove it: {}", id, e);
+    }
+    info!(target: "audit", admin = %admin.id, task_id = %entry.task_id, "Dead-lettered task requeued as {}", item_id);
+    Ok(Json(json!({ "queue_item": item_id, "task_id": entry.task_id })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        test_support::mock_app_state,
+    };
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+    use worker::queue::{QueueTask, TaskStatus};
+
+    async fn state() -> AppState {
+        mock_app_state().await
+    }
+
+    async fn send(state: &AppState, method: &str, uri: &str, token: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    /// A queue item the worker gave up on, and its dead letter
+    async fn dead_lettered(state: &AppState) -> (String, String) {
+        let item = state
+            .global_pb
+            .create_record(
+                QUEUE_ITEMS,
+                &json!({
+                    "task_id": "5b0c9a56-04c5-4a5e-9b38-3f8f0c1f7f51",
+                    "user_id": "alice",
+                    "meeting_id": "42",
+                    "topic": "Weekly sync",
+                    "status": "Failed",
+                    "retry_count": 3,
+                    "max_retries": 3,
+                    "error_message": "Loom API error: upload rejected",
+                    "next_attempt_at": "2026-03-01 10:00:00.000Z",
+                    "version": 4,
+                    "created": "2026-03-01 09:00:00.000Z",
+                    "updated": "2026-03-01 10:05:00.000Z",
+                }),
+            )
+            .await
+            .unwrap();
+        let item_id = item["id"].as_str().unwrap().to_string();
+        let attempt = |error: &str| json!({ "error": error, "started_at": null, "failed_at": "2026-03-01T10:05:00Z", "stage": "uploading" });
+        let entry = state
+            .global_pb
+            .create_record(
+                DEAD_LETTER,
+                &json!({
+                    "queue_item": item_id,
+                    "task_id": "5b0c9a56-04c5-4a5e-9b38-3f8f0c1f7f51",
+                    "user_id": "alice",
+                    "meeting_id": "42",
+                    "topic": "Weekly sync",
+                    "task": { "max_retries": 3, "loom_key_id": "team" },
+                    "attempts": [attempt("Fathom API error: 503"), attempt("Loom API error: upload rejected")],
+                    "last_stage": "uploading",
+                    "error_message": "Loom API error: upload rejected",
+                    "failed_at": "2026-03-01 10:05:00.000Z",
+                }),
+            )
+            .await
+            .unwrap();
+        let entry_id = entry["id"].as_str().unwrap().to_string();
+        state
+            .global_pb
+            .update_record(QUEUE_ITEMS, &item_id, &json!({ "dead_letter_id": entry_id }))
+            .await
+            .unwrap();
+        (item_id, entry_id)
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_list_with_their_attempts_for_admins_only() {
+        let state = state().await;
+        dead_lettered(&state).await;
+
+        let (status, body) = send(&state, "GET", "/api/admin/dead_letter", "valid-admin").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total_items"], 1);
+        let entry = &body["items"][0];
+        assert_eq!(entry["task_id"], "5b0c9a56-04c5-4a5e-9b38-3f8f0c1f7f51");
+        assert_eq!(entry["last_stage"], "uploading");
+        assert_eq!(entry["attempts"].as_array().unwrap().len(), 2);
+        assert_eq!(entry["attempts"][1]["error"], "Loom API error: upload rejected");
+
+        let (status, body) = send(&state, "GET", "/api/admin/dead_letter?user_id=bob", "valid-admin").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total_items"], 0);
+
+        let (status, _) = send(&state, "GET", "/api/admin/dead_letter", "valid-alice").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_a_requeued_task_is_claimable_again() {
+        let state = state().await;
+        let (item_id, entry_id) = dead_lettered(&state).await;
+        let uri = format!("/api/admin/dead_letter/{}/requeue", entry_id);
+
+        let (status, _) = send(&state, "POST", &uri, "valid-alice").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(&state, "POST", &uri, "valid-admin").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["queue_item"], item_id.as_str());
+
+        // What the worker's claim asks of an item
+        let record = state.global_pb.get_record(QUEUE_ITEMS, &item_id).await.unwrap();
+        let task = QueueTask::from_record(&record).unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert_eq!(task.retry_count, 0);
+        assert_eq!(task.max_retries, 3);
+        assert_eq!(task.next_attempt_at, None);
+        assert_eq!(task.dead_letter_id, None);
+        assert!(task.attempts.is_empty());
+        assert_eq!(task.claimed_by, None);
+        assert!(state.global_pb.list_records(DEAD_LETTER, None).await.unwrap().is_empty());
+
+        let (status, body) = send(&state, "POST", &uri, "valid-admin").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_requeueing_recreates_a_removed_queue_item() {
+        let state = state().await;
+        let (item_id, entry_id) = dead_lettered(&state).await;
+        state.global_pb.delete_record(QUEUE_ITEMS, &item_id).await.unwrap();
+
+        let uri = format!("/api/admin/dead_letter/{}/requeue", entry_id);
+        let (status, body) = send(&state, "POST", &uri, "valid-admin").await;
+        assert_eq!(status, StatusCode::OK);
+        let record = state
+            .global_pb
+            .get_record(QUEUE_ITEMS, body["queue_item"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(record["task_id"], "5b0c9a56-04c5-4a5e-9b38-3f8f0c1f7f51");
+        assert_eq!(record["status"], "Pending");
+        assert_eq!(record["retry_count"], 0);
+        assert_eq!(record["loom_key_id"], "team");
+        assert_eq!(record["dead_letter_id"], "");
+    }
+}
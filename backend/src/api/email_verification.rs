@@ -0,0 +1,287 @@
+//! Email address verification
+//!
+//! Registering sends a link carrying a `verify_email` token through the
+//! smtp-service. Opening it marks the global PocketBase user record
+//! `verified`; until then the account can sign in but not queue meetings.
+//! Signed-in users who lost the email can ask for another, a few per hour.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{
+    extractors::AuthUser,
+    jwt::{JwtError, JwtKeys},
+    rate_limit::RateLimits,
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{GlobalPb, GlobalPbError},
+    mailer::{self, Mailer},
+};
+
+const PURPOSE: &str = "verify_email";
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// Routes for verifying email addresses, nested under `/auth`
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/verify_email", get(verify_email))
+        .route("/verify_email/resend", post(resend_verification))
+}
+
+fn reply(status: StatusCode, success: bool, message: &str) -> (StatusCode, Json<Value>) {
+    (
+        status,
+        Json(json!({
+            "success": success,
+            "message": message
+        })),
+    )
+}
+
+/// Email `email` a link that verifies the account `user_id`
+pub async fn send_verification_email(
+    config: &Config,
+    mailer: &Mailer,
+    user_id: &str,
+    email: &str,
+) -> Result<(), String> {
+    let ttl = config.security.email_verification_ttl_secs;
+    let token = JwtKeys::new(&config.security)
+        .issue_action_token(PURPOSE, user_id, email, ttl)
+        .map_err(|e| e.to_string())?;
+    let link = format!("{}?token={}", config.security.email_verification_url, token);
+    let hours = (ttl / 3600).max(1).to_string();
+
+    let message = mailer::VERIFY_EMAIL.render(email, &[("link", &link), ("hours", &hours)]);
+    mailer.send(&message).await.map_err(|e| e.to_string())?;
+
+    info!(target: "audit", user_id = %user_id, "Verification email sent");
+    Ok(())
+}
+
+/// Whether the global PocketBase record for `user_id` has a verified email
+///
+/// Users without a record there (legacy accounts) count as unverified.
+pub async fn is_verified(global_pb: &GlobalPb, user_id: &str) -> Result<bool, GlobalPbError> {
+    match global_pb.get_record("users", user_id).await {
+        Ok(record) => Ok(record.get("verified").and_then(|v| v.as_bool()).unwrap_or(false)),
+        Err(GlobalPbError::Status { status: 404, .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// GET /auth/verify_email?token=... - mark the account in the token verified
+async fn verify_email(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> (StatusCode, Json<Value>) {
+    let claims = match JwtKeys::new(&config.security).verify_action_token(&query.token, PURPOSE) {
+        Ok(claims) => claims,
+        Err(JwtError::Expired) => {
+            return reply(
+                StatusCode::BAD_REQUEST,
+                false,
+                "Verification link has expired, please request a new one",
+            )
+        }
+        Err(e) => {
+            warn!("Rejected email verification token: {}", e);
+            return reply(StatusCode::BAD_REQUEST, false, "Verification link is invalid");
+        }
+    };
+
+    if let Err(e) = global_pb
+        .update_record("users", &claims.sub, &json!({ "verified": true }))
+        .await
+    {
+        error!("Failed to mark {} verified: {}", claims.sub, e);
+        return reply(StatusCode::BAD_GATEWAY, false, "Failed to verify email address");
+    }
+
+    info!(target: "audit", user_id = %claims.sub, "Email address verified");
+    reply(StatusCode::OK, true, "Email address verified")
+}
+
+/// POST /auth/verify_email/resend - send the signed-in user another verification link
+async fn resend_verification(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(mailer): State<Arc<Mailer>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+) -> (StatusCode, Json<Value>) {
+    match is_verified(&global_pb, &user.id).await {
+        Ok(true) => return reply(StatusCode::OK, true, "Email address is already verified"),
+        Ok(false) => {}
+        Err(e) => {
+            error!("Failed to look up verification status for {}: {}", user.id, e);
+            return reply(StatusCode::BAD_GATEWAY, false, "Failed to send verification email");
+        }
+    }
+
+    if !rate_limits.verification_resend_per_user.check(&user.id).await {
+        warn!("Verification resend rate limit hit for {}", user.id);
+        return reply(
+            StatusCode::TOO_MANY_REQUESTS,
+            false,
+            "Too many verification emails requested, please try again later",
+        );
+    }
+
+    if let Err(e) = send_verification_email(&config, &mailer, &user.id, &user.email).await {
+        error!("Failed to resend verification email to {}: {}", user.id, e);
+        return reply(StatusCode::BAD_GATEWAY, false, "Failed to send verification email");
+    }
+    reply(StatusCode::OK, true, "Verification email sent")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{create_api_router, jwt::JwtKeys, AppState},
+        test_support::{emailed_token, mock_app_state_with, mock_smtp_service},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    async fn test_state(configure: impl FnOnce(&mut crate::config::Config)) -> (AppState, Arc<Mutex<Vec<Value>>>) {
+        let (smtp_url, sent) = mock_smtp_service().await;
+        let state = mock_app_state_with(|config| {
+            config.email.smtp_service_url = smtp_url;
+            configure(config);
+        })
+        .await;
+        (state, sent)
+    }
+
+    async fn send(state: &AppState, method: &str, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn user_record(state: &AppState, email: &str) -> Value {
+        let users = state.global_pb.list_records("users", None).await.unwrap();
+        users.into_iter().find(|user| user["email"] == email).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_then_verify_unblocks_queue() {
+        let (state, sent) = test_state(|_| {}).await;
+
+        let (status, body) = send(
+            &state,
+            "POST",
+            "/auth/register",
+            None,
+            json!({ "email": "mia@example.com", "password": "password", "password_confirm": "password" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let session = body["data"]["token"].as_str().unwrap().to_string();
+        let user_id = body["data"]["user"]["id"].as_str().unwrap().to_string();
+
+        let emails = sent.lock().await.clone();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0]["to_email"], "mia@example.com");
+        assert!(emails[0]["body_html"].as_str().unwrap().contains("Confirm your email address"));
+
+        // Unverified accounts can sign in but not queue meetings
+        let meeting = json!({ "user_id": user_id, "topic": "Standup" });
+        let (status, body) = send(&state, "POST", "/api/queue", Some(&session), meeting.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "validation");
+        assert!(body["message"].as_str().unwrap().starts_with("Validation error:"));
+
+        let token = emailed_token(&emails[0]);
+        let (status, _) = send(&state, "GET", &format!("/auth/verify_email?token={}", token), None, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user_record(&state, "mia@example.com").await["verified"], true);
+
+        let (status, body) = send(&state, "POST", "/api/queue", Some(&session), meeting).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_foreign_tokens_are_rejected() {
+        let (state, _) = test_state(|_| {}).await;
+        let user = state
+            .global_pb
+            .create_record("users", &json!({ "email": "ned@example.com", "verified": false }))
+            .await
+            .unwrap();
+        let user_id = user["id"].as_str().unwrap();
+        let keys = JwtKeys::new(&state.config.security);
+        let expired = keys.issue_action_token("verify_email", user_id, "ned@example.com", 0).unwrap();
+        let wrong_purpose = keys.issue_action_token("password_reset", user_id, "ned@example.com", 600).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        for (token, message) in [
+            (expired, "Verification link has expired, please request a new one"),
+            (wrong_purpose, "Verification link is invalid"),
+        ] {
+            let uri = format!("/auth/verify_email?token={}", token);
+            let (status, body) = send(&state, "GET", &uri, None, Value::Null).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["message"], message);
+        }
+        assert_eq!(user_record(&state, "ned@example.com").await["verified"], false);
+    }
+
+    #[tokio::test]
+    async fn test_resend_is_rate_limited() {
+        let (state, sent) = test_state(|config| config.security.verification_resend_max = 2).await;
+        state
+            .global_pb
+            .create_record("users", &json!({ "email": "ola@example.com", "verified": false }))
+            .await
+            .unwrap();
+        let (_, body) = send(
+            &state,
+            "POST",
+            "/auth/login",
+            None,
+            json!({ "email": "ola@example.com", "password": "password" }),
+        )
+        .await;
+        let session = body["data"]["token"].as_str().unwrap().to_string();
+
+        for expected in [StatusCode::OK, StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let (status, _) = send(&state, "POST", "/auth/verify_email/resend", Some(&session), Value::Null).await;
+            assert_eq!(status, expected);
+        }
+        assert_eq!(sent.lock().await.len(), 2);
+    }
+}
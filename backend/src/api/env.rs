@@ -0,0 +1,369 @@
+//! What the frontend and operators may see of the configuration
+//!
+//! `GET /api/env` is public, so it is built from an allowlist of what a
+//! browser needs: where the API and WebSocket are, the version, the feature
+//! flags and the log level. The WebSocket URL is `PUBLIC_WS_URL` when set,
+//! since behind a TLS-terminating proxy it needn't sit next to the API.
+//! Anything describing the deployment, such as the internal PocketBase URL or
+//! CORS origins, is only shown to admins at `GET /api/admin/config`, with
+//! every secret redacted.
+
+use axum::{extract::State, response::Json, routing::get, Router};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use super::{extractors::AdminUser, AppState};
+use crate::config::Config;
+
+/// The body of `GET /api/env`; nothing else is public
+#[derive(Debug, Serialize)]
+pub struct PublicConfig {
+    pub api: ApiInfo,
+    pub websocket: WebSocketInfo,
+    pub features: Features,
+    pub logging: LoggingInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiInfo {
+    pub base_url: String,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebSocketInfo {
+    /// Where queue updates are pushed
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Features {
+    pub auth_enabled: bool,
+    pub encryption_enabled: bool,
+    /// Whether `/api/docs` serves Swagger UI
+    pub openapi_enabled: bool,
+    /// Whether new accounts can be created
+    pub registration_enabled: bool,
+    /// Providers offered under `/auth/oauth/<provider>`
+    pub oauth_providers: Vec<String>,
+    /// Whether queue changes are refused while the deployment is worked on
+    pub maintenance_mode: bool,
+    /// Meetings each user may have queued at once; 0 for no limit
+    pub max_queue_items_per_user: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoggingInfo {
+    pub level: String,
+}
+
+impl PublicConfig {
+    pub fn new(config: &Config) -> Self {
+        let host = format!("{}:{}", config.server.host, config.server.port);
+        Self {
+            api: ApiInfo {
+                base_url: format!("http://{}", host),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            websocket: WebSocketInfo {
+                url: config
+                    .server
+                    .public_ws_url
+                    .clone()
+                    .unwrap_or_else(|| format!("ws://{}/queue_updates", host)),
+            },
+            features: Features {
+                auth_enabled: true,
+                encryption_enabled: true,
+                openapi_enabled: config.server.openapi_enabled,
+                registration_enabled: config.features.registration_enabled,
+                oauth_providers: config.features.oauth_providers.clone(),
+                maintenance_mode: config.features.maintenance_mode,
+                max_queue_items_per_user: config.features.max_queue_items_per_user,
+            },
+            logging: LoggingInfo {
+                level: config.logging.level.clone(),
+            },
+        }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/env", get(get_env))
+        .route("/admin/config", get(get_admin_config))
+}
+
+/// GET /api/env - configuration the frontend needs, for anyone
+async fn get_env(State(config): State<Arc<Config>>) -> Json<PublicConfig> {
+    Json(PublicConfig::new(&config))
+}
+
+/// GET /api/admin/config - the deployment's settings with secrets redacted (admin only)
+async fn get_admin_config(_admin: AdminUser, State(config): State<Arc<Config>>) -> Json<Value> {
+    Json(admin_config(&config))
+}
+
+/// Stands in for a secret, showing only whether it's set
+fn secret(value: Option<&str>) -> Value {
+    match value {
+        Some(value) if !value.is_empty() => json!("[redacted]"),
+        _ => Value::Null,
+    }
+}
+
+/// The settings shown to admins, each listed so a new secret can't slip in
+fn admin_config(config: &Config) -> Value {
+    let Config {
+        server,
+        database,
+        security,
+        logging,
+        cors,
+        pocketbase,
+        email,
+        integrations,
+        rate_limits,
+        features,
+    } = config;
+    let previous_kids: Vec<&str> = security
+        .jwt_previous_secrets
+        .iter()
+        .map(|(kid, _)| kid.as_str())
+        .collect();
+    json!({
+        "server": {
+            "host": server.host,
+            "port": server.port,
+            "metrics_enabled": server.metrics_enabled,
+            "openapi_enabled": server.openapi_enabled,
+            "shutdown_grace_secs": server.shutdown_grace_secs,
+            "body_limit_bytes": server.body_limit_bytes,
+            "upload_body_limit_bytes": server.upload_body_limit_bytes,
+            "request_timeout_secs": server.request_timeout_secs,
+            "long_request_timeout_secs": server.long_request_timeout_secs,
+            "public_ws_url": server.public_ws_url,
+            "serve_frontend": server.serve_frontend,
+            "frontend_dist_path": server.frontend_dist_path,
+            "tls_cert_path": server.tls_cert_path,
+            "tls_key_path": server.tls_key_path,
+        },
+        "database": {
+            "url": database.url,
+            "admin_email": database.admin_email,
+            "admin_password": secret(Some(&database.admin_password)),
+            "user_db_base_path": database.user_db_base_path,
+        },
+        "security": {
+            "master_key": secret(Some(&security.master_key)),
+            "master_key_previous": secret(security.master_key_previous.as_deref()),
+            "jwt_secret": secret(Some(&security.jwt_secret)),
+            "internal_api_token": secret(security.internal_api_token.as_deref()),
+            "metrics_token": secret(security.metrics_token.as_deref()),
+            "pb_encryption_key": secret(Some(&security.pb_encryption_key)),
+            "jwt_kid": security.jwt_kid,
+            "jwt_previous_kids": previous_kids,
+            "jwt_expiry_secs": security.jwt_expiry_secs,
+            "jwt_refresh_window_secs": security.jwt_refresh_window_secs,
+            "jwt_max_session_secs": security.jwt_max_session_secs,
+            "auth_cache_ttl_secs": security.auth_cache_ttl_secs,
+            "auth_cache_max_entries": security.auth_cache_max_entries,
+            "accept_legacy_pb_tokens": security.accept_legacy_pb_tokens,
+            "trust_proxy_headers": security.trust_proxy_headers,
+            "password_reset_url": security.password_reset_url,
+            "email_verification_url": security.email_verification_url,
+            "login_max_attempts": security.login_max_attempts,
+            "login_window_secs": security.login_window_secs,
+            "login_lockout_threshold": security.login_lockout_threshold,
+            "login_lockout_secs": security.login_lockout_secs,
+            "admin_emails": security.admin_emails,
+            "oauth_google_redirect_url": security.oauth_google_redirect_url,
+            "oauth_frontend_url": security.oauth_frontend_url,
+            "session_cookie_same_site": security.session_cookie_same_site.as_str(),
+            "session_cookie_secure": security.session_cookie_secure,
+        },
+        "logging": {
+            "level": logging.level,
+            "format": logging.format.as_str(),
+            "requests": {
+                "success": logging.requests.success.as_str(),
+                "client_error": logging.requests.client_error.as_str(),
+                "server_error": logging.requests.server_error.as_str(),
+            },
+            "sentry_dsn": secret(logging.sentry_dsn.as_deref()),
+        },
+        "cors": {
+            "origins": cors.origins,
+        },
+        "pocketbase": {
+            "binary_path": pocketbase.binary_path,
+            "user_dbs_path": pocketbase.user_dbs_path,
+            "port_range_start": pocketbase.port_range_start,
+            "port_range_end": pocketbase.port_range_end,
+            "bind_host": pocketbase.bind_host,
+            "auto_download": pocketbase.auto_download,
+            "warm_pool_size": pocketbase.warm_pool_size,
+        },
+        "email": {
+            "smtp_service_url": email.smtp_service_url,
+        },
+        "integrations": {
+            "fathom_api_url": integrations.fathom_api_url,
+            "loom_api_url": integrations.loom_api_url,
+            "key_settings_url": integrations.key_settings_url,
+            "fathom_requests_per_minute": integrations.fathom_requests_per_minute,
+            "fathom_max_attempts": integrations.fathom_max_attempts,
+        },
+        "rate_limits": {
+            "auth_per_minute": rate_limits.auth_per_minute,
+            "read_per_minute": rate_limits.read_per_minute,
+            "write_per_minute": rate_limits.write_per_minute,
+            "webhook_per_minute": rate_limits.webhook_per_minute,
+            "max_clients": rate_limits.max_clients,
+        },
+        "features": {
+            "registration_enabled": features.registration_enabled,
+            "oauth_providers": features.oauth_providers,
+            "maintenance_mode": features.maintenance_mode,
+            "max_queue_items_per_user": features.max_queue_items_per_user,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        create_api_router,
+        extractors::{AuthUser, Role},
+        jwt::JwtKeys,
+    };
+    use crate::test_support::mock_app_state_with;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    async fn get(uri: &str, token: Option<&str>) -> (StatusCode, Value, Config) {
+        let state = mock_app_state_with(|config| {
+            config.security.admin_emails = vec!["root@example.com".to_string()];
+            config.security.internal_api_token = Some("internal-secret".to_string());
+        })
+        .await;
+        let config = Config::clone(&state.config);
+        let app = create_api_router(state);
+
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, body, config)
+    }
+
+    fn token(config: &Config, id: &str, email: &str) -> String {
+        let user = AuthUser {
+            id: id.to_string(),
+            email: email.to_string(),
+            name: None,
+            token: format!("valid-{}", id),
+            role: Role::User,
+        };
+        JwtKeys::new(&config.security).issue(&user, "user").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_public_config_is_only_the_allowlist() {
+        let (status, body, config) = get("/api/env", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let keys: Vec<&str> = body
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(keys, ["api", "features", "logging", "websocket"]);
+        assert!(body.get("database").is_none());
+        assert!(body.get("cors").is_none());
+        assert_eq!(body["api"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            body["websocket"]["url"],
+            "ws://127.0.0.1:3000/queue_updates"
+        );
+        assert_eq!(body["features"]["openapi_enabled"], false);
+        assert_eq!(
+            body["features"],
+            json!({
+                "auth_enabled": true,
+                "encryption_enabled": true,
+                "openapi_enabled": false,
+                "registration_enabled": true,
+                "oauth_providers": ["google"],
+                "maintenance_mode": false,
+                "max_queue_items_per_user": 0,
+            })
+        );
+
+        let text = body.to_string();
+        assert!(!text.contains(&config.database.url), "{}", text);
+    }
+
+    #[test]
+    fn test_public_config_follows_the_deployment() {
+        let mut config = crate::test_support::test_config("http://127.0.0.1:9");
+        config.server.public_ws_url = Some("wss://example.com/ws/queue_updates".to_string());
+        config.features.registration_enabled = false;
+        config.features.oauth_providers = Vec::new();
+        config.features.maintenance_mode = true;
+        config.features.max_queue_items_per_user = 25;
+
+        let body = serde_json::to_value(PublicConfig::new(&config)).unwrap();
+        assert_eq!(
+            body["websocket"]["url"],
+            "wss://example.com/ws/queue_updates"
+        );
+        assert_eq!(body["api"]["base_url"], "http://127.0.0.1:3000");
+        assert_eq!(body["features"]["registration_enabled"], false);
+        assert_eq!(body["features"]["oauth_providers"], json!([]));
+        assert_eq!(body["features"]["maintenance_mode"], true);
+        assert_eq!(body["features"]["max_queue_items_per_user"], 25);
+    }
+
+    #[tokio::test]
+    async fn test_admin_config_needs_an_admin_and_redacts_secrets() {
+        let (status, _, config) = get("/api/admin/config", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let user = token(&config, "alice", "alice@example.com");
+        let (status, _, _) = get("/api/admin/config", Some(&user)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let admin = token(&config, "root", "root@example.com");
+        let (status, body, config) = get("/api/admin/config", Some(&admin)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["database"]["url"], config.database.url);
+        assert_eq!(body["cors"]["origins"], json!(config.cors.origins));
+        assert_eq!(body["security"]["jwt_secret"], "[redacted]");
+        assert_eq!(body["security"]["internal_api_token"], "[redacted]");
+        assert_eq!(body["security"]["metrics_token"], Value::Null);
+
+        let text = body.to_string();
+        for secret in [
+            config.security.master_key.as_str(),
+            &config.security.jwt_secret,
+            &config.security.pb_encryption_key,
+            &config.database.admin_password,
+            "internal-secret",
+        ] {
+            assert!(!text.contains(secret), "{} leaked", secret);
+        }
+    }
+}
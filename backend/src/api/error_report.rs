@@ -0,0 +1,225 @@
+//! Server-side failures sent to the configured error reporter
+//!
+//! A handler failing with [`AppError::Internal`] or [`AppError::Database`]
+//! answers a generic 500 through [`app_error`], or its own response through
+//! [`with_cause`]; both keep the error on the response as a [`Cause`] the
+//! client never sees. [`report_errors`] reports every response carrying one,
+//! tagged with the request id, route, method and the authenticated user.
+//! Client errors carry no cause and are never reported, and neither request
+//! nor response bodies are read.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use common::{
+    telemetry::{ErrorEvent, ErrorReporter, Severity},
+    ApiResponse, AppError, ErrorCode,
+};
+use serde_json::Value;
+use std::sync::{Arc, OnceLock};
+
+use super::request_log::REQUEST_ID_HEADER;
+
+/// The server-side error a response was made from
+#[derive(Debug, Clone)]
+pub struct Cause(pub String);
+
+/// Filled in by the auth extractor for the reporter to tag events with
+#[derive(Debug, Clone, Default)]
+struct ReportedUser(Arc<OnceLock<String>>);
+
+/// The response for `error`; server-side failures hide their detail
+pub fn app_error(error: AppError) -> Response {
+    let (status, code, message) = match &error {
+        AppError::Validation(message) => (StatusCode::BAD_REQUEST, ErrorCode::Validation, message.as_str()),
+        AppError::Auth(message) => (StatusCode::UNAUTHORIZED, ErrorCode::InvalidToken, message.as_str()),
+        AppError::ExternalApi(message) => (StatusCode::BAD_GATEWAY, ErrorCode::ServiceUnavailable, message.as_str()),
+        AppError::Internal(_) | AppError::Database(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, "Internal server error")
+        }
+    };
+    let response = (status, Json(ApiResponse::<Value>::failure(code, message))).into_response();
+    with_cause(response, error)
+}
+
+/// `response`, marked for reporting when `error` is a server-side failure
+pub fn with_cause(mut response: Response, error: AppError) -> Response {
+    if matches!(error, AppError::Internal(_) | AppError::Database(_)) {
+        response.extensions_mut().insert(Cause(error.to_string()));
+    }
+    response
+}
+
+/// Tags the request's error reports with the authenticated user
+pub(super) fn record_user(parts: &Parts, user_id: &str) {
+    if let Some(user) = parts.extensions.get::<ReportedUser>() {
+        let _ = user.0.set(user_id.to_string());
+    }
+}
+
+/// Reports each response made from a server-side failure
+pub async fn report_errors(
+    State(reporter): State<Arc<dyn ErrorReporter>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = ReportedUser::default();
+    request.extensions_mut().insert(user.clone());
+    let mut event = ErrorEvent::new(Severity::Error, String::new()).tag("method", request.method().as_str());
+    if let Some(id) = request.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok()) {
+        event = event.tag("request_id", id);
+    }
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        event = event.tag("route", route.as_str());
+    }
+
+    let response = next.run(request).await;
+    if let Some(Cause(cause)) = response.extensions().get::<Cause>() {
+        event.message = cause.clone();
+        event = event.tag("status", response.status().as_str());
+        if let Some(user_id) = user.0.get() {
+            event = event.tag("user_id", user_id.as_str());
+        }
+        reporter.report(event);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::extractors::AuthUser;
+    use crate::api::request_log::log_requests;
+    use crate::config::RequestLogLevels;
+    use crate::test_support::mock_app_state;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use serde_json::json;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    #[derive(Default)]
+    struct Captured(Mutex<Vec<ErrorEvent>>);
+
+    impl ErrorReporter for Captured {
+        fn report(&self, event: ErrorEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    /// Routes failing each way, layered as in `main`, and a signed-up user
+    async fn app(reporter: Arc<Captured>) -> (Router, String) {
+        let state = mock_app_state().await;
+        let user = json!({ "email": "lou@example.com", "username": "lou", "verified": true });
+        let user_id = state.global_pb.create_record("users", &user).await.unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let app = Router::new()
+            .route(
+                "/meetings/:id",
+                get(|_user: AuthUser| async {
+                    app_error(AppError::Database("users_meetings is locked".to_string()))
+                }),
+            )
+            .route(
+                "/internal",
+                get(|| async {
+                    let response = (StatusCode::INTERNAL_SERVER_ERROR, "Stored key could not be read").into_response();
+                    with_cause(response, AppError::Internal("key does not decrypt".to_string()))
+                }),
+            )
+            .route(
+                "/invalid",
+                get(|| async { app_error(AppError::Validation("title is required".to_string())) }),
+            )
+            .route(
+                "/upstream",
+                get(|| async { app_error(AppError::ExternalApi("Fathom is down".to_string())) }),
+            )
+            .route("/guarded", get(|_user: AuthUser| async { "never" }))
+            .with_state(state)
+            .layer(middleware::from_fn_with_state(
+                reporter as Arc<dyn ErrorReporter>,
+                report_errors,
+            ))
+            .layer(middleware::from_fn_with_state(
+                RequestLogLevels::default(),
+                log_requests,
+            ));
+        (app, user_id)
+    }
+
+    async fn call(app: &Router, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder().uri(uri).header(REQUEST_ID_HEADER, "req-7");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from("{\"secret\":\"body\"}")).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_reported_with_their_tags() {
+        let reporter = Arc::new(Captured::default());
+        let (app, user_id) = app(reporter.clone()).await;
+
+        let (status, body) = call(&app, "/meetings/m-1", Some(&format!("valid-{}", user_id))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        // The detail goes to the reporter, not the client
+        assert_eq!(body["code"], "internal");
+        assert_eq!(body["error"], "Internal server error");
+
+        let (status, _) = call(&app, "/internal", None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let events = reporter.0.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        let event = &events[0];
+        assert_eq!(event.severity, Severity::Error);
+        assert_eq!(event.message, "Database error: users_meetings is locked");
+        assert_eq!(event.tags["request_id"], "req-7");
+        assert_eq!(event.tags["route"], "/meetings/:id");
+        assert_eq!(event.tags["method"], "GET");
+        assert_eq!(event.tags["status"], "500");
+        assert_eq!(event.tags["user_id"], user_id);
+
+        assert_eq!(events[1].message, "Internal error: key does not decrypt");
+        assert_eq!(events[1].tags["route"], "/internal");
+        assert!(!events[1].tags.contains_key("user_id"));
+        assert!(events
+            .iter()
+            .all(|event| !format!("{:?}", event).contains("body")));
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_reported() {
+        let reporter = Arc::new(Captured::default());
+        let (app, _) = app(reporter.clone()).await;
+
+        let (status, body) = call(&app, "/invalid", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation");
+        assert_eq!(body["error"], "title is required");
+
+        let (status, _) = call(&app, "/guarded", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&app, "/nowhere", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, "/upstream", None).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        assert!(reporter.0.lock().unwrap().is_empty());
+    }
+}
@@ -1,16 +1,82 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, State},
+    extract::{FromRef, FromRequestParts, Path},
     http::{request::Parts, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tracing::{error, warn};
 
-use crate::config::Config;
+use super::{
+    auth_cache::AuthCache,
+    csrf, error_report,
+    jwt::{JwtError, JwtKeys},
+    request_log,
+    revocation::RevocationList,
+    sessions::SessionList,
+};
+use crate::{
+    config::Config,
+    metrics::{self, AUTH_FAILURES},
+    pocketbase_manager::sanitize_user_id,
+};
+
+/// What an authenticated user is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    /// Parse a role claim or record field; anything unrecognised is a plain user
+    pub fn parse(role: &str) -> Self {
+        if role.trim().eq_ignore_ascii_case("admin") {
+            Role::Admin
+        } else {
+            Role::User
+        }
+    }
+
+    /// The role of a global PocketBase user record
+    ///
+    /// The record's `role` field decides, except that the configured
+    /// PocketBase admin and anyone in `ADMIN_EMAILS` are always admins.
+    pub fn from_record(record: &Value, config: &Config) -> Self {
+        let is_configured_admin = record
+            .get("email")
+            .and_then(|v| v.as_str())
+            .is_some_and(|email| is_configured_admin(email, config));
+        if is_configured_admin {
+            return Role::Admin;
+        }
+        record
+            .get("role")
+            .and_then(|v| v.as_str())
+            .map(Role::parse)
+            .unwrap_or(Role::User)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Whether `email` is an admin by configuration rather than by its record
+fn is_configured_admin(email: &str, config: &Config) -> bool {
+    email.eq_ignore_ascii_case(&config.database.admin_email)
+        || config
+            .security
+            .admin_emails
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(email))
+}
 
 #[derive(Debug, Clone)]
 pub struct AuthUser {
@@ -18,22 +84,58 @@ pub struct AuthUser {
     pub email: String,
     pub name: Option<String>,
     pub token: String,
+    pub role: Role,
+}
+
+impl AuthUser {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Reject non-admins with 403
+    pub fn require_admin(&self) -> Result<(), AuthError> {
+        if self.is_admin() {
+            Ok(())
+        } else {
+            Err(AuthError::forbidden("admin_required", "Administrator access is required"))
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct AuthError {
+    #[serde(skip)]
+    pub status: StatusCode,
     pub error: String,
     pub message: String,
 }
 
+impl AuthError {
+    /// The caller couldn't be authenticated
+    pub fn unauthorized(error: &str, message: &str) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            error: error.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// The caller is authenticated but not allowed to do this
+    pub fn forbidden(error: &str, message: &str) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            ..Self::unauthorized(error, message)
+        }
+    }
+}
+
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        let status = StatusCode::UNAUTHORIZED;
         let body = Json(json!({
             "error": self.error,
             "message": self.message
         }));
-        (status, body).into_response()
+        (self.status, body).into_response()
     }
 }
 
@@ -41,57 +143,115 @@ impl IntoResponse for AuthError {
 impl<S> FromRequestParts<S> for AuthUser
 where
     S: Send + Sync,
-    Arc<Config>: FromRequestParts<S>,
+    Arc<Config>: FromRef<S>,
+    Arc<AuthCache>: FromRef<S>,
+    Arc<RevocationList>: FromRef<S>,
+    Arc<SessionList>: FromRef<S>,
 {
     type Rejection = AuthError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        // Extract authorization header
-        let auth_header = parts
-            .headers
-            .get("authorization")
-            .and_then(|h| h.to_str().ok())
-            .ok_or_else(|| AuthError {
-                error: "missing_authorization".to_string(),
-                message: "Authorization header is required".to_string(),
-            })?;
-
-        // Extract bearer token
-        let token = auth_header
-            .strip_prefix("Bearer ")
-            .ok_or_else(|| AuthError {
-                error: "invalid_authorization".to_string(),
-                message: "Authorization header must be a Bearer token".to_string(),
-            })?;
+        let user = authenticate(parts, state).await.inspect_err(|e| {
+            metrics::increment(&AUTH_FAILURES, &[("kind", "token"), ("reason", &e.error)]);
+        })?;
+        request_log::record_user(&user.id);
+        error_report::record_user(parts, &user.id);
+        Ok(user)
+    }
+}
 
-        if token.is_empty() {
-            return Err(AuthError {
-                error: "empty_token".to_string(),
-                message: "Token cannot be empty".to_string(),
-            });
-        }
+/// Resolve the request's token to a user, locally or through PocketBase
+async fn authenticate<S>(parts: &mut Parts, state: &S) -> Result<AuthUser, AuthError>
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+    Arc<AuthCache>: FromRef<S>,
+    Arc<RevocationList>: FromRef<S>,
+    Arc<SessionList>: FromRef<S>,
+{
+    // A bearer token wins; cookie-mode sessions carry it in the session cookie
+    let token = match parts.headers.get("authorization") {
+        Some(auth_header) => auth_header
+            .to_str()
+            .ok()
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or_else(|| AuthError::unauthorized("invalid_authorization", "Authorization header must be a Bearer token"))?,
+        None => csrf::session_cookie(&parts.headers)
+            .ok_or_else(|| AuthError::unauthorized("missing_authorization", "Authorization header is required"))?,
+    };
 
-        let token = token.to_string(); // Convert to owned string to avoid lifetime issues
+    if token.is_empty() {
+        return Err(AuthError::unauthorized("empty_token", "Token cannot be empty"));
+    }
+    authenticate_token(token.to_string(), state).await
+}
 
-        // Get config to validate token with PocketBase
-        let config = Arc::<Config>::from_request_parts(parts, state)
-            .await
-            .map_err(|_| AuthError {
-                error: "server_error".to_string(),
-                message: "Failed to access server configuration".to_string(),
-            })?;
+/// Resolve `token` to a user the way [`AuthUser`] does, for callers that
+/// find the token somewhere other than the request headers
+pub(crate) async fn authenticate_token<S>(token: String, state: &S) -> Result<AuthUser, AuthError>
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+    Arc<AuthCache>: FromRef<S>,
+    Arc<RevocationList>: FromRef<S>,
+    Arc<SessionList>: FromRef<S>,
+{
+    let config = Arc::<Config>::from_ref(state);
 
-        // Validate token with global PocketBase
-        match validate_pb_token(&token, &config).await {
-            Ok(user) => Ok(user),
-            Err(e) => {
+    // Our own tokens are verified locally; anything else is a legacy
+    // PocketBase token, accepted only while the transition is enabled
+    let revocations = Arc::<RevocationList>::from_ref(state);
+    let keys = JwtKeys::new(&config.security);
+    let revoked = || AuthError::unauthorized("token_revoked", "Token has been revoked");
+    match keys.verify(&token) {
+        Ok(claims) => {
+            if revocations.is_revoked(&token).await
+                || claims.token_generation < revocations.generation(&claims.sub).await
+            {
+                return Err(revoked());
+            }
+            let user = keys.user_from_claims(claims).map_err(|e| {
                 warn!("Token validation failed: {}", e);
-                Err(AuthError {
-                    error: "invalid_token".to_string(),
-                    message: "Invalid or expired token".to_string(),
-                })
+                AuthError::unauthorized("invalid_token", "Invalid or expired token")
+            })?;
+            // Throttled inside, and off the request path
+            let sessions = Arc::<SessionList>::from_ref(state);
+            tokio::spawn(async move { sessions.touch(&token).await });
+            return Ok(user);
+        }
+        Err(JwtError::NotIssuedHere) if config.security.accept_legacy_pb_tokens => {
+            if revocations.is_revoked(&token).await {
+                return Err(revoked());
             }
         }
+        Err(e) => {
+            warn!("Token validation failed: {}", e);
+            let (error, message) = match e {
+                JwtError::Expired => ("token_expired", "Token has expired"),
+                _ => ("invalid_token", "Invalid or expired token"),
+            };
+            return Err(AuthError::unauthorized(error, message));
+        }
+    }
+
+    // Recently validated tokens skip the PocketBase round-trip
+    let cache = Arc::<AuthCache>::from_ref(state);
+    if let Some(user) = cache.get(&token).await {
+        return Ok(user);
+    }
+
+    // Validate token with global PocketBase
+    // The boxed error isn't Send, so flatten it before awaiting the cache
+    let validated = validate_pb_token(&token, &config).await.map_err(|e| e.to_string());
+    match validated {
+        Ok(user) => {
+            cache.insert(&token, user.clone()).await;
+            Ok(user)
+        }
+        Err(e) => {
+            warn!("Token validation failed: {}", e);
+            Err(AuthError::unauthorized("invalid_token", "Invalid or expired token"))
+        }
     }
 }
 
@@ -133,6 +293,7 @@ async fn validate_pb_token(token: &str, config: &Config) -> Result<AuthUser, Box
                 .and_then(|v| v.as_str())
                 .unwrap_or(token)
                 .to_string(),
+            role: Role::from_record(record, config),
         };
 
         Ok(user)
@@ -144,6 +305,38 @@ async fn validate_pb_token(token: &str, config: &Config) -> Result<AuthUser, Box
     }
 }
 
+/// An authenticated admin; anyone else is rejected with 403
+///
+/// Admins are users whose token or record carries the admin role, plus the
+/// configured bootstrap emails, so adding someone to `ADMIN_EMAILS` takes
+/// effect without them signing in again.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminUser
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+    Arc<AuthCache>: FromRef<S>,
+    Arc<RevocationList>: FromRef<S>,
+    Arc<SessionList>: FromRef<S>,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let mut user = AuthUser::from_request_parts(parts, state).await?;
+        if !user.is_admin() && is_configured_admin(&user.email, &Arc::<Config>::from_ref(state)) {
+            user.role = Role::Admin;
+        }
+        if let Err(e) = user.require_admin() {
+            warn!(target: "audit", user_id = %user.id, path = %parts.uri.path(), "Admin route refused");
+            return Err(e);
+        }
+        Ok(AdminUser(user))
+    }
+}
+
 /// Optional authentication extractor - doesn't fail if no auth provided
 #[derive(Debug, Clone)]
 pub struct OptionalAuthUser(pub Option<AuthUser>);
@@ -152,7 +345,10 @@ pub struct OptionalAuthUser(pub Option<AuthUser>);
 impl<S> FromRequestParts<S> for OptionalAuthUser
 where
     S: Send + Sync,
-    Arc<Config>: FromRequestParts<S>,
+    Arc<Config>: FromRef<S>,
+    Arc<AuthCache>: FromRef<S>,
+    Arc<RevocationList>: FromRef<S>,
+    Arc<SessionList>: FromRef<S>,
 {
     type Rejection = std::convert::Infallible;
 
@@ -163,3 +359,57 @@ where
         }
     }
 }
+
+/// The `:id` path segment, rejected with 400 unless it is a safe user id
+#[derive(Debug, Clone)]
+pub struct UserIdPath(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for UserIdPath
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let invalid = |message: String| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "success": false,
+                    "message": message
+                })),
+            )
+        };
+
+        let Path(user_id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| invalid(e.body_text()))?;
+        sanitize_user_id(&user_id).map_err(|e| invalid(e.to_string()))?;
+        Ok(UserIdPath(user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_config;
+
+    #[test]
+    fn test_role_from_claims_and_records() {
+        assert_eq!(Role::parse("admin"), Role::Admin);
+        assert_eq!(Role::parse(" Admin "), Role::Admin);
+        assert_eq!(Role::parse("user"), Role::User);
+        assert_eq!(Role::parse("superuser"), Role::User);
+
+        let config = test_config("http://unused");
+        assert_eq!(Role::from_record(&json!({ "email": "a@example.com", "role": "admin" }), &config), Role::Admin);
+        assert_eq!(Role::from_record(&json!({ "email": "a@example.com" }), &config), Role::User);
+        // The configured PocketBase admin needs no role field
+        assert_eq!(Role::from_record(&json!({ "email": "Admin@Example.com" }), &config), Role::Admin);
+
+        let mut config = config;
+        config.security.admin_emails = vec!["ops@example.com".to_string()];
+        assert_eq!(Role::from_record(&json!({ "email": "OPS@example.com" }), &config), Role::Admin);
+    }
+}
@@ -0,0 +1,323 @@
+//! The compiled frontend, served from `FRONTEND_DIST_PATH`
+//!
+//! So one process can host the whole app, `GET`s that match no backend route
+//! are answered from the Dioxus `dist` directory, and those that match no
+//! file either get its `index.html` for the frontend's router to handle.
+//! Paths owned by the backend, such as `/api` or `/auth`, are never answered
+//! this way and keep their 404s. Hashed asset names never change content,
+//! so they may be cached for a year; everything else is revalidated each time.
+
+use axum::{
+    body::Body,
+    extract::OriginalUri,
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+
+/// Paths the fallback never answers
+const BACKEND_PREFIXES: &[&str] = &[
+    "/api",
+    "/auth",
+    "/queue_updates",
+    "/internal",
+    "/webhooks",
+    "/health",
+    "/metrics",
+];
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+
+/// A built frontend bundle on disk
+#[derive(Debug, Clone)]
+pub struct FrontendDist {
+    root: PathBuf,
+}
+
+impl FrontendDist {
+    /// The bundle to serve, or `None` when serving is off or there is none
+    pub fn from_config(server: &ServerConfig) -> Option<Self> {
+        if !server.serve_frontend {
+            return None;
+        }
+        let root = PathBuf::from(&server.frontend_dist_path);
+        if !root.join("index.html").is_file() {
+            info!(
+                "No frontend bundle at {}, not serving the frontend",
+                root.display()
+            );
+            return None;
+        }
+        info!("Serving the frontend from {}", root.display());
+        Some(Self { root })
+    }
+
+    /// `router` with unmatched requests answered from the bundle
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let dist = Arc::new(self);
+        router.fallback(move |method: Method, OriginalUri(uri): OriginalUri| {
+            let dist = dist.clone();
+            async move { dist.serve(&method, uri.path()).await }
+        })
+    }
+
+    /// The file at `path`, `index.html` in its place, or 404
+    pub async fn serve(&self, method: &Method, path: &str) -> Response {
+        if !matches!(*method, Method::GET | Method::HEAD) || is_backend_path(path) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        if let Some(file) = self.resolve(path) {
+            if file.is_file() {
+                return send(&file).await;
+            }
+        }
+        send(&self.root.join("index.html")).await
+    }
+
+    /// Where `path` is inside the bundle, refusing anything climbing out of it
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        let mut file = self.root.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => file.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        (file != self.root).then_some(file)
+    }
+}
+
+fn is_backend_path(path: &str) -> bool {
+    BACKEND_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+async fn send(file: &Path) -> Response {
+    let opened = match tokio::fs::File::open(file).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            warn!("Failed to open {}: {}", file.display(), e);
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let cache = if is_hashed(name) {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    };
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(content_type(name)),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static(cache)),
+        ],
+        Body::from_stream(ReaderStream::new(opened)),
+    )
+        .into_response()
+}
+
+/// Whether `name` carries a content hash, as Dioxus writes `main-dxh1a2b3c4d.js`
+///
+/// The hash is the last dash-separated part of the stem: eight or more
+/// letters and digits, at least one of them a digit.
+fn is_hashed(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default();
+    stem.rsplit_once('-').is_some_and(|(_, hash)| {
+        hash.len() >= 8
+            && hash.chars().all(|c| c.is_ascii_alphanumeric())
+            && hash.chars().any(|c| c.is_ascii_digit())
+    })
+}
+
+fn content_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+    match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::mock_app_state_with;
+    use axum::extract::Request;
+    use tower::ServiceExt;
+
+    /// A bundle with an index, a hashed script and its wasm
+    fn bundle() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<html>app</html>").unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/main-dxh1a2b3c4d.js"), "init()").unwrap();
+        std::fs::write(dir.path().join("assets/app_bg.wasm"), b"\0asm").unwrap();
+        dir
+    }
+
+    async fn app(dist: &Path, enabled: bool) -> Router {
+        let state = mock_app_state_with(|config| {
+            config.server.serve_frontend = enabled;
+            config.server.frontend_dist_path = dist.display().to_string();
+        })
+        .await;
+        create_api_router(state)
+    }
+
+    async fn get(app: &Router, method: &str, uri: &str) -> (StatusCode, String, String, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .map(|value: &HeaderValue| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        let (content_type, cache) = (header(header::CONTENT_TYPE), header(header::CACHE_CONTROL));
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            content_type,
+            cache,
+            String::from_utf8_lossy(&bytes).to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_assets_are_served_with_their_type_and_cache_policy() {
+        let dist = bundle();
+        let app = app(dist.path(), true).await;
+
+        let (status, content_type, cache, body) =
+            get(&app, "GET", "/assets/main-dxh1a2b3c4d.js").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/javascript; charset=utf-8");
+        assert_eq!(cache, IMMUTABLE);
+        assert_eq!(body, "init()");
+
+        let (status, content_type, cache, _) = get(&app, "GET", "/assets/app_bg.wasm").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/wasm");
+        assert_eq!(cache, REVALIDATE);
+
+        let (status, content_type, cache, body) = get(&app, "GET", "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert_eq!(cache, REVALIDATE);
+        assert_eq!(body, "<html>app</html>");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_routes_fall_back_to_the_index() {
+        let dist = bundle();
+        let app = app(dist.path(), true).await;
+
+        for uri in [
+            "/dashboard",
+            "/settings/keys?tab=loom",
+            "/assets/missing.js",
+        ] {
+            let (status, _, cache, body) = get(&app, "GET", uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert_eq!(cache, REVALIDATE);
+            assert_eq!(body, "<html>app</html>", "{}", uri);
+        }
+
+        // Nothing outside the bundle is reachable, and only reads fall back
+        let (_, _, _, body) = get(&app, "GET", "/assets/../../etc/passwd").await;
+        assert_eq!(body, "<html>app</html>");
+        let (status, _, _, _) = get(&app, "POST", "/dashboard").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_backend_paths_are_never_swallowed() {
+        let dist = bundle();
+        let app = app(dist.path(), true).await;
+
+        for uri in [
+            "/api/v1/nowhere",
+            "/api/nowhere",
+            "/api",
+            "/auth/nowhere",
+            "/internal/nowhere",
+            "/webhooks/nowhere",
+            "/health/nowhere",
+        ] {
+            let (status, _, _, body) = get(&app, "GET", uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+            assert!(!body.contains("app"), "{}", uri);
+        }
+        let (status, _, _, _) = get(&app, "GET", "/health/live").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, _, _) = get(&app, "GET", "/apiary").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_serving_can_be_turned_off() {
+        let dist = bundle();
+        let app = app(dist.path(), false).await;
+        let (status, _, _, _) = get(&app, "GET", "/").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let missing = tempfile::tempdir().unwrap();
+        let app = self::app(missing.path(), true).await;
+        let (status, _, _, _) = get(&app, "GET", "/dashboard").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_hashed_names() {
+        assert!(is_hashed("main-dxh1a2b3c4d.js"));
+        assert!(is_hashed("fathom-loom-frontend-3f9a0c1b2d.wasm"));
+        assert!(!is_hashed("index.html"));
+        assert!(!is_hashed("app_bg.wasm"));
+        assert!(!is_hashed("tailwind-config.css"));
+    }
+}
@@ -0,0 +1,229 @@
+//! Liveness and readiness probes
+//!
+//! `/health/live` only says the process is answering requests, so a restart
+//! is never triggered by a dependency being down. `/health/ready` checks what
+//! requests depend on and answers 503 with every check's detail when one of
+//! them fails, so the pod is taken out of rotation until it recovers.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use common::health::CompositeHealth;
+use serde_json::json;
+use std::time::Duration;
+
+use super::{startup::Phase, AppState};
+
+/// Longest any one readiness check may take
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// GET /health/live - the process is up
+pub async fn get_live() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// GET /health/ready - 200 when started and every required dependency
+/// answers, else 503
+///
+/// The checks run and are reported in every phase, but only a backend that
+/// has finished starting and isn't draining is ready.
+pub async fn get_ready(State(state): State<AppState>) -> Response {
+    let mut report = readiness(&state).check().await;
+    let phase = state.startup.phase();
+    report.ready &= phase == Phase::Ready;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let mut body = json!(report);
+    body["phase"] = json!(phase);
+    (status, Json(body)).into_response()
+}
+
+/// The checks behind `/health/ready`
+pub fn readiness(state: &AppState) -> CompositeHealth {
+    let global_pb = state.global_pb.clone();
+    let pb_manager = state.pb_manager.clone();
+    let ws_manager = state.ws_manager.clone();
+    let broadcast = state.broadcast.clone();
+
+    CompositeHealth::new()
+        .required("global_pocketbase", CHECK_TIMEOUT, move || {
+            let global_pb = global_pb.clone();
+            async move { global_pb.ping().await.map_err(|e| e.to_string()) }
+        })
+        .required("user_dbs_path", CHECK_TIMEOUT, move || {
+            let dir = pb_manager.user_dbs_path().to_path_buf();
+            async move {
+                // Instances can't start where their data can't be written
+                let probe = dir.join(format!(".ready-{}", uuid::Uuid::new_v4()));
+                tokio::fs::create_dir_all(&dir)
+                    .await
+                    .map_err(|e| format!("{}: {}", dir.display(), e))?;
+                tokio::fs::write(&probe, b"ok")
+                    .await
+                    .map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+                let _ = tokio::fs::remove_file(&probe).await;
+                Ok(())
+            }
+        })
+        .required("websocket_manager", CHECK_TIMEOUT, move || {
+            let ws_manager = ws_manager.clone();
+            async move {
+                if ws_manager.is_shutting_down() {
+                    return Err("shutting down".to_string());
+                }
+                // Waits on the connection table, so a stuck lock times out
+                ws_manager.connection_count().await;
+                Ok(())
+            }
+        })
+        .required("broadcast", CHECK_TIMEOUT, move || {
+            let open = broadcast.has_subscribers();
+            async move {
+                if open {
+                    Ok(())
+                } else {
+                    Err("no subscribers; updates are not reaching WebSocket clients".to_string())
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::pocketbase_manager::PocketBaseManager;
+    use crate::test_support::{spawn_server, test_app_state, test_config};
+    use axum::{body::Body, extract::Request, routing::post, Router};
+    use serde_json::Value;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use tower::ServiceExt;
+
+    /// A global PocketBase whose admin endpoints fail while `down` is set
+    async fn toggled_global_pocketbase() -> (String, Arc<AtomicBool>) {
+        let down = Arc::new(AtomicBool::new(false));
+        let answer = |down: Arc<AtomicBool>| {
+            move || async move {
+                if down.load(Ordering::SeqCst) {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        Json(json!({ "message": "down" })),
+                    )
+                } else {
+                    (StatusCode::OK, Json(json!({ "token": "mock-admin-token" })))
+                }
+            }
+        };
+        let router = Router::new()
+            .route("/api/admins/auth-with-password", post(answer(down.clone())))
+            .route("/api/admins/auth-refresh", post(answer(down.clone())));
+        (spawn_server(router).await, down)
+    }
+
+    async fn ready(app: &Router) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/health/ready")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn check<'a>(body: &'a Value, name: &str) -> &'a Value {
+        body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|check| check["name"] == name)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ready_follows_the_global_pocketbase() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, down) = toggled_global_pocketbase().await;
+        let manager =
+            PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let app = create_api_router(test_app_state(test_config(&global_url), manager));
+
+        let (status, body) = ready(&app).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["ready"], true);
+        for name in [
+            "global_pocketbase",
+            "user_dbs_path",
+            "websocket_manager",
+            "broadcast",
+        ] {
+            let check = check(&body, name);
+            assert_eq!(check["status"], "ok", "{}", check);
+            assert_eq!(check["required"], true);
+            assert!(check["latency_ms"].is_u64());
+        }
+
+        down.store(true, Ordering::SeqCst);
+        let (status, body) = ready(&app).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        let global = check(&body, "global_pocketbase");
+        assert_eq!(global["status"], "failed");
+        assert!(
+            global["error"].as_str().unwrap().contains("503"),
+            "{}",
+            global
+        );
+        // The other checks still report their own state
+        assert_eq!(check(&body, "user_dbs_path")["status"], "ok");
+
+        down.store(false, Ordering::SeqCst);
+        assert_eq!(ready(&app).await.0, StatusCode::OK);
+
+        // Liveness doesn't depend on any of it
+        down.store(true, Ordering::SeqCst);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health/live")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_while_shutting_down_or_unwritable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (global_url, _) = toggled_global_pocketbase().await;
+        // A file where the directory should be can't hold user databases
+        let blocked = dir.path().join("user_dbs");
+        std::fs::write(&blocked, b"not a directory").unwrap();
+        let manager = PocketBaseManager::new(blocked, 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        state.ws_manager.begin_shutdown();
+
+        let (status, body) = ready(&create_api_router(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(check(&body, "user_dbs_path")["status"], "failed");
+        assert_eq!(check(&body, "websocket_manager")["error"], "shutting down");
+        assert_eq!(check(&body, "global_pocketbase")["status"], "ok");
+    }
+}
@@ -0,0 +1,902 @@
+//! Routes for the worker, nested under `/internal`
+//!
+//! These are not for browsers: callers authenticate with the shared
+//! `INTERNAL_API_TOKEN` as a bearer token rather than as a user, and may
+//! act on any user's keys. While the token is unset every call is refused.
+//!
+//! Keys leave only sealed: the worker names a one-off X25519 public key and
+//! gets the decrypted value back in an [`envelope`] only it can open, valid
+//! for [`ENVELOPE_TTL`].
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post, put},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{
+    auth::auth_error,
+    error_report::with_cause,
+    extractors::AuthError,
+    key_repository::{KeyRepository, MasterKey, StoredKey, Touch},
+    keys::store_error,
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{GlobalPb, GlobalPbError},
+    pocketbase_manager::{sanitize_user_id, PocketBaseManager},
+};
+use common::{
+    broadcast::{BroadcastService, ProgressUpdate, QueueUpdate, SystemEvent},
+    crypto::{
+        constant_time_eq,
+        envelope::{self, SealedEnvelope},
+    },
+    ApiResponse, AppError, ErrorCode, ServiceKind,
+};
+
+/// Uses of one key closer together than this are recorded once
+pub const TOUCH_INTERVAL: chrono::Duration = chrono::Duration::hours(1);
+
+/// How long the worker has to open a fetched key's envelope
+pub const ENVELOPE_TTL: chrono::Duration = chrono::Duration::seconds(60);
+
+/// A caller that presented `INTERNAL_API_TOKEN`
+#[derive(Debug, Clone)]
+pub struct InternalCaller;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for InternalCaller
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let presented = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match (config.security.internal_api_token.as_deref(), presented) {
+            (Some(expected), Some(presented))
+                if constant_time_eq(expected.as_bytes(), presented.as_bytes()) =>
+            {
+                Ok(InternalCaller)
+            }
+            _ => {
+                warn!(target: "audit", path = %parts.uri.path(), "Internal route refused");
+                Err(AuthError::unauthorized(
+                    "invalid_internal_token",
+                    "A valid internal API token is required",
+                ))
+            }
+        }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/keys/summary", get(super::key_summary::list_key_summaries))
+        .route("/keys/:user_id/:service", get(fetch_key))
+        .route("/keys/:user_id/:service/touch", post(touch_key))
+        .route("/queue/:item_id/loom", post(record_loom_video))
+        .route(
+            "/users/:user_id/processing_results/:task_id",
+            put(super::processing_results::put_processing_result),
+        )
+        .route("/progress", post(relay_progress))
+        .route("/queue", post(relay_queue_update))
+        .route("/system", post(relay_system_event))
+}
+
+/// Query of `GET /internal/keys/:user_id/:service`
+#[derive(Debug, Default, Deserialize)]
+pub struct FetchKeyQuery {
+    /// The key wanted, the service's default unless given
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// Base64 X25519 public key to seal the value to
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// A decrypted key on its way to the worker
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FetchedKey {
+    pub service: String,
+    pub key_id: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The value, sealed for `api-key/<user_id>/<service>`
+    pub envelope: SealedEnvelope,
+}
+
+/// Why a key can't be fetched: it expired at `expired_at`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpiredKey {
+    pub service: String,
+    pub key_id: String,
+    pub expired_at: DateTime<Utc>,
+}
+
+fn no_such_key() -> Response {
+    auth_error(
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+        "No such API key",
+    )
+}
+
+/// The key repository of `user_id`, without starting an instance for a
+/// user who never had one
+async fn user_repository(
+    pb_manager: &PocketBaseManager,
+    master_key: &MasterKey,
+    user_id: &str,
+) -> Result<KeyRepository, Response> {
+    if sanitize_user_id(user_id).is_err() || !pb_manager.data_dir(user_id).exists() {
+        return Err(no_such_key());
+    }
+    KeyRepository::open(pb_manager, user_id, master_key)
+        .await
+        .map_err(store_error)
+}
+
+/// GET /internal/keys/:user_id/:service - Hand the worker a decrypted key,
+/// sealed to the `public_key` it sent
+///
+/// Replies 410 `key_expired`, with the key and its expiry as `data`, rather
+/// than releasing an expired key.
+async fn fetch_key(
+    _caller: InternalCaller,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    Path((user_id, service)): Path<(String, String)>,
+    Query(query): Query<FetchKeyQuery>,
+) -> Result<Response, Response> {
+    let Some(service) = ServiceKind::parse(&service) else {
+        return Err(no_such_key());
+    };
+    let Some(public_key) = query.public_key.filter(|key| !key.is_empty()) else {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "A public key to seal the API key to is required",
+        ));
+    };
+    let repository = user_repository(&pb_manager, &master_key, &user_id).await?;
+    let Some(StoredKey { key, .. }) = repository
+        .resolve(service.as_str(), query.key_id.as_deref())
+        .await
+        .map_err(store_error)?
+    else {
+        return Err(no_such_key());
+    };
+
+    let now = Utc::now();
+    if let Some(expired_at) = key.expires_at.filter(|_| key.is_expired_at(now)) {
+        let expired = ExpiredKey {
+            service: key.service,
+            key_id: key.key_id,
+            expired_at,
+        };
+        let body = ApiResponse {
+            data: Some(expired),
+            ..ApiResponse::failure(ErrorCode::KeyExpired, "The API key has expired")
+        };
+        return Err((StatusCode::GONE, Json(body)).into_response());
+    }
+
+    let value = repository.decrypt(&key).map_err(|e| {
+        warn!(
+            "Stored {} key {} of {} does not decrypt: {}",
+            key.service, key.key_id, user_id, e
+        );
+        with_cause(
+            auth_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                "Stored API key could not be read",
+            ),
+            AppError::Internal(format!("Stored key does not decrypt: {}", e)),
+        )
+    })?;
+    let context = envelope::key_context(&user_id, service.as_str());
+    let sealed = envelope::seal(&public_key, value.as_bytes(), &context, now + ENVELOPE_TTL);
+    drop(value);
+    let envelope = sealed.map_err(|_| {
+        auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "The public key must be 32 bytes encoded as base64",
+        )
+    })?;
+
+    info!(target: "audit", user_id = %user_id, service = %key.service, key_id = %key.key_id, "API key released to the worker");
+    let fetched = FetchedKey {
+        service: key.service,
+        key_id: key.key_id,
+        expires_at: key.expires_at,
+        envelope,
+    };
+    Ok((StatusCode::OK, Json(fetched)).into_response())
+}
+
+/// Body of `POST /internal/keys/:user_id/:service/touch`
+#[derive(Debug, Default, Deserialize)]
+pub struct TouchKeyRequest {
+    /// The key used, the service's default unless given
+    #[serde(default)]
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TouchedKey {
+    /// False when a use within the last hour was already recorded
+    pub touched: bool,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// POST /internal/keys/:user_id/:service/touch - Record that the worker used a key
+async fn touch_key(
+    _caller: InternalCaller,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    Path((user_id, service)): Path<(String, String)>,
+    body: Option<Json<TouchKeyRequest>>,
+) -> Result<Response, Response> {
+    let Some(service) = ServiceKind::parse(&service) else {
+        return Err(no_such_key());
+    };
+    let Json(request) = body.unwrap_or_default();
+    let repository = user_repository(&pb_manager, &master_key, &user_id).await?;
+    let key_id = match request.key_id {
+        Some(key_id) => key_id,
+        None => match repository
+            .default_key(service.as_str())
+            .await
+            .map_err(store_error)?
+        {
+            Some(stored) => stored.key.key_id,
+            None => return Err(no_such_key()),
+        },
+    };
+    let touched = match repository
+        .touch(service.as_str(), &key_id, Utc::now(), TOUCH_INTERVAL)
+        .await
+        .map_err(store_error)?
+    {
+        Touch::Recorded(at) => TouchedKey {
+            touched: true,
+            last_used_at: at,
+        },
+        Touch::Throttled(at) => TouchedKey {
+            touched: false,
+            last_used_at: at,
+        },
+        Touch::Missing => return Err(no_such_key()),
+    };
+    Ok((StatusCode::OK, Json(touched)).into_response())
+}
+
+/// Body of `POST /internal/queue/:item_id/loom`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoomVideo {
+    pub video_id: String,
+    pub share_url: String,
+}
+
+/// POST /internal/queue/:item_id/loom - Record the Loom video a queue
+/// item's meeting was uploaded as
+async fn record_loom_video(
+    _caller: InternalCaller,
+    State(global_pb): State<Arc<GlobalPb>>,
+    Path(item_id): Path<String>,
+    Json(video): Json<LoomVideo>,
+) -> Result<StatusCode, Response> {
+    let share_url = reqwest::Url::parse(&video.share_url).ok().filter(|url| url.scheme() == "https");
+    if video.video_id.trim().is_empty() || share_url.is_none() {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "A video id and an https share URL are required",
+        ));
+    }
+
+    let record = serde_json::json!({ "loom_video_id": video.video_id, "loom_url": video.share_url });
+    match global_pb.update_record("queue_items", &item_id, &record).await {
+        Ok(_) => {
+            info!(item_id = %item_id, video_id = %video.video_id, "Loom video recorded on queue item");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(GlobalPbError::Status { status: 404, .. }) => Err(auth_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "No such queue item",
+        )),
+        Err(e) => {
+            error!("Failed to record the Loom video of queue item {}: {}", item_id, e);
+            Err(auth_error(
+                StatusCode::BAD_GATEWAY,
+                ErrorCode::Internal,
+                "Failed to record the Loom video",
+            ))
+        }
+    }
+}
+
+/// POST /internal/progress - Pass on how far the worker has got with a
+/// task, for the WebSocket clients of its owner
+async fn relay_progress(
+    _caller: InternalCaller,
+    State(broadcast): State<Arc<BroadcastService>>,
+    Json(update): Json<ProgressUpdate>,
+) -> StatusCode {
+    broadcast.broadcast_progress(update);
+    StatusCode::NO_CONTENT
+}
+
+/// POST /internal/queue - Pass on a change to one of the worker's tasks,
+/// such as a retry and when it is due, for the WebSocket clients of its owner
+async fn relay_queue_update(
+    _caller: InternalCaller,
+    State(broadcast): State<Arc<BroadcastService>>,
+    Json(update): Json<QueueUpdate>,
+) -> StatusCode {
+    broadcast.broadcast(update).await;
+    StatusCode::NO_CONTENT
+}
+
+/// POST /internal/system - Put a worker's system event, such as a task
+/// moving to the dead-letter queue, on the backend's broadcast service
+async fn relay_system_event(
+    _caller: InternalCaller,
+    State(broadcast): State<Arc<BroadcastService>>,
+    Json(event): Json<SystemEvent>,
+) -> StatusCode {
+    broadcast.broadcast_system(event);
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        test_support::{
+            fake_pocketbase, mock_app_state, mock_app_state_with, mock_global_pocketbase, port_window,
+            spawn_server,
+            test_app_state, test_config,
+        },
+    };
+    use axum::{body::Body, http::Request};
+    use serde_json::Value;
+    use std::{
+        io::Write,
+        sync::Mutex,
+        time::Duration,
+    };
+    use tower::ServiceExt;
+    use worker::{config::BackendConfig, keys::fetch_key, loom, WorkerError};
+
+    async fn setup(dir: &std::path::Path) -> AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+        let data_dir = state.pb_manager.data_dir("alice");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        state
+    }
+
+    async fn touch(state: &AppState, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        touch_with(state, uri, token, None).await
+    }
+
+    async fn touch_with(
+        state: &AppState,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        send(state, "POST", uri, token, body).await
+    }
+
+    async fn send(
+        state: &AppState,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let body = body
+            .map(|body| Body::from(body.to_string()))
+            .unwrap_or_default();
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_touch_records_use_at_most_hourly() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        repository
+            .put("fathom", "default", "alice-fathom", None)
+            .await
+            .unwrap();
+
+        let uri = "/internal/keys/alice/fathom/touch";
+        let (status, first) = touch(&state, uri, Some("test-internal-token")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["touched"], true);
+        let stored = repository.get("fathom", "default").await.unwrap().unwrap();
+        let recorded: DateTime<Utc> =
+            serde_json::from_value(first["last_used_at"].clone()).unwrap();
+        assert_eq!(stored.last_used_at, Some(recorded));
+
+        let (status, second) = touch(&state, uri, Some("test-internal-token")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["touched"], false);
+        assert_eq!(second["last_used_at"], first["last_used_at"]);
+
+        // An hour later the next use is recorded again
+        let an_hour_on = Utc::now() + TOUCH_INTERVAL + chrono::Duration::seconds(1);
+        let touched = repository
+            .touch("fathom", "default", an_hour_on, TOUCH_INTERVAL)
+            .await
+            .unwrap();
+        assert_eq!(touched, Touch::Recorded(an_hour_on));
+
+        let (status, _) = touch(
+            &state,
+            "/internal/keys/alice/loom/touch",
+            Some("test-internal-token"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = touch(
+            &state,
+            "/internal/keys/nobody/fathom/touch",
+            Some("test-internal-token"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_touch_falls_back_to_the_default_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        for key_id in ["personal", "team"] {
+            repository
+                .put("fathom", key_id, "alice-fathom", None)
+                .await
+                .unwrap();
+        }
+        repository.set_default("fathom", "team").await.unwrap();
+        let uri = "/internal/keys/alice/fathom/touch";
+        let last_used = |key_id: &'static str| {
+            let repository = &repository;
+            async move {
+                repository
+                    .get("fathom", key_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .last_used_at
+            }
+        };
+
+        // Nothing named: the default is the key used
+        let (status, _) = touch(&state, uri, Some("test-internal-token")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(last_used("team").await.is_some());
+        assert!(last_used("personal").await.is_none());
+
+        // A meeting that names its key overrides the default
+        let body = serde_json::json!({ "key_id": "personal" });
+        let (status, _) = touch_with(&state, uri, Some("test-internal-token"), Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(last_used("personal").await.is_some());
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    /// The worker's view of the backend serving `state`
+    async fn worker_backend(state: &AppState) -> BackendConfig {
+        BackendConfig {
+            url: spawn_server(create_api_router(state.clone())).await,
+            internal_api_token: Some("test-internal-token".to_string()),
+        }
+    }
+
+    /// A base64 public key as a query value
+    fn query_value(key: &str) -> String {
+        key.replace('+', "%2B").replace('/', "%2F").replace('=', "%3D")
+    }
+
+    #[tokio::test]
+    async fn test_fetched_keys_open_only_in_the_worker() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        repository
+            .put("fathom", "default", "fathom-default-0123456789", None)
+            .await
+            .unwrap();
+        repository
+            .put("fathom", "team", "fathom-team-0123456789", None)
+            .await
+            .unwrap();
+
+        let backend = worker_backend(&state).await;
+        let client = reqwest::Client::new();
+        let fetched = fetch_key(&client, &backend, "alice", "fathom", None)
+            .await
+            .unwrap();
+        assert_eq!(fetched.key_id, "default");
+        assert_eq!(fetched.value.expose_secret(), "fathom-default-0123456789");
+        let fetched = fetch_key(&client, &backend, "alice", "fathom", Some("team"))
+            .await
+            .unwrap();
+        assert_eq!(fetched.value.expose_secret(), "fathom-team-0123456789");
+
+        // On the wire there is only the envelope
+        let recipient = envelope::EphemeralRecipient::generate().unwrap();
+        let uri = format!(
+            "/internal/keys/alice/fathom?public_key={}",
+            query_value(recipient.public_key())
+        );
+        let (status, body) = send(&state, "GET", &uri, Some("test-internal-token"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.to_string().contains("0123456789"));
+        let sealed: FetchedKey = serde_json::from_value(body).unwrap();
+        assert!(sealed.envelope.expires_at <= Utc::now() + ENVELOPE_TTL);
+        // Sealed for Alice's Fathom key, it opens as nothing else
+        let context = envelope::key_context("bob", "fathom");
+        assert!(recipient.open(&sealed.envelope, &context, Utc::now()).is_err());
+
+        for uri in [
+            "/internal/keys/alice/fathom",
+            "/internal/keys/alice/fathom?public_key=c2hvcnQ%3D",
+        ] {
+            let (status, body) = send(&state, "GET", uri, Some("test-internal-token"), None).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["code"], "validation");
+        }
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_missing_keys_are_not_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        let expired_at = Utc::now() - chrono::Duration::days(1);
+        repository
+            .put("loom", "default", "loom-expired-0123456789", Some(expired_at))
+            .await
+            .unwrap();
+
+        let recipient = envelope::EphemeralRecipient::generate().unwrap();
+        let query = format!("?public_key={}", query_value(recipient.public_key()));
+        let uri = format!("/internal/keys/alice/loom{}", query);
+        let (status, body) = send(&state, "GET", &uri, Some("test-internal-token"), None).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["code"], "key_expired");
+        assert_eq!(body["data"]["key_id"], "default");
+        assert!(!body.to_string().contains("0123456789"));
+        for uri in [
+            format!("/internal/keys/alice/fathom{}", query),
+            format!("/internal/keys/alice/loom{}&key_id=other", query),
+            format!("/internal/keys/nobody/loom{}", query),
+            format!("/internal/keys/alice/dropbox{}", query),
+        ] {
+            let (status, body) = send(&state, "GET", &uri, Some("test-internal-token"), None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+            assert_eq!(body["code"], "not_found");
+        }
+
+        let backend = worker_backend(&state).await;
+        let client = reqwest::Client::new();
+        let error = fetch_key(&client, &backend, "alice", "loom", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            WorkerError::KeyExpired { service, key_id, expired_at: at }
+                if service == "loom" && key_id == "default" && at.timestamp() == expired_at.timestamp()
+        ));
+        let error = fetch_key(&client, &backend, "alice", "fathom", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, WorkerError::KeyMissing { service } if service == "fathom"));
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetching_never_logs_the_value() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        repository
+            .put("fathom", "default", "fathom-logged-0123456789", None)
+            .await
+            .unwrap();
+        repository
+            .put(
+                "loom",
+                "default",
+                "loom-logged-0123456789",
+                Some(Utc::now() - chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+
+        let backend = worker_backend(&state).await;
+        let client = reqwest::Client::new();
+        let fetched = fetch_key(&client, &backend, "alice", "fathom", None)
+            .await
+            .unwrap();
+        tracing::debug!("fetched {:?}", fetched);
+        assert!(fetch_key(&client, &backend, "alice", "loom", None)
+            .await
+            .is_err());
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("API key released to the worker"));
+        assert!(logs.contains("REDACTED"));
+        assert!(!logs.contains("0123456789"), "{}", logs);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_the_worker_records_the_loom_video_on_the_queue_item() {
+        let state = mock_app_state().await;
+        let item = state
+            .global_pb
+            .create_record("queue_items", &serde_json::json!({ "topic": "Weekly sync", "status": "InProgress" }))
+            .await
+            .unwrap();
+        let item_id = item["id"].as_str().unwrap();
+
+        let backend = worker_backend(&state).await;
+        let video = loom::LoomVideo {
+            video_id: "abc123".to_string(),
+            share_url: "https://www.loom.com/share/abc123".to_string(),
+        };
+        loom::record_video(&reqwest::Client::new(), &backend, item_id, &video)
+            .await
+            .unwrap();
+        let recorded = state.global_pb.get_record("queue_items", item_id).await.unwrap();
+        assert_eq!(recorded["loom_url"], "https://www.loom.com/share/abc123");
+        assert_eq!(recorded["loom_video_id"], "abc123");
+        assert_eq!(recorded["status"], "InProgress");
+
+        let error = loom::record_video(&reqwest::Client::new(), &backend, "missing", &video)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, WorkerError::Network(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND)));
+
+        let uri = format!("/internal/queue/{}/loom", item_id);
+        let body = serde_json::json!({ "video_id": "abc123", "share_url": "javascript:alert(1)" });
+        let (status, _) = touch_with(&state, &uri, Some("test-internal-token"), Some(body)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = touch_with(&state, &uri, None, Some(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// A backend for the worker to relay to, and a way to open its
+    /// `/queue_updates` socket as a user
+    async fn relay_backend() -> (BackendConfig, impl Fn(&'static str) -> futures::future::BoxFuture<'static, Socket>) {
+        use futures::{FutureExt, SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let state = mock_app_state().await;
+        let backend = worker_backend(&state).await;
+        let ws_url = backend.url.replacen("http", "ws", 1);
+        let socket = move |user_id: &'static str| {
+            let url = format!("{}/queue_updates?token=valid-{}", ws_url, user_id);
+            async move {
+                let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+                // The pong means the connection is subscribed
+                socket.send(Message::Text("ping".to_string())).await.unwrap();
+                while socket.next().await.unwrap().unwrap() != Message::Text("pong".to_string()) {}
+                socket
+            }
+            .boxed()
+        };
+        (backend, socket)
+    }
+
+    /// The next message of type `kind` on `socket`
+    async fn next_message(socket: &mut Socket, kind: &str) -> Value {
+        use futures::StreamExt;
+
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .unwrap_or_else(|_| panic!("no {} arrived", kind))
+                .unwrap()
+                .unwrap();
+            let message: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            if message["type"] == kind {
+                return message;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_progress_reaches_only_the_owners_socket() {
+        use common::broadcast::{BroadcastServiceFactory, ProcessingStage};
+        use worker::progress::{bridge, StageProgress};
+
+        let (backend, socket) = relay_backend().await;
+        let mut alice = socket("alice").await;
+        let mut bob = socket("bob").await;
+
+        let worker_broadcast = BroadcastServiceFactory::create_shared(16);
+        let relay = bridge(&worker_broadcast, reqwest::Client::new(), backend.clone());
+        let task_id = uuid::Uuid::new_v4();
+        let download = StageProgress::new(&worker_broadcast, task_id, "alice", ProcessingStage::Downloading);
+        download.start();
+        download.report(1);
+        download.report(50);
+        download.finish();
+        StageProgress::new(&worker_broadcast, uuid::Uuid::new_v4(), "bob", ProcessingStage::Uploading).start();
+
+        let mut percents = Vec::new();
+        for _ in 0..3 {
+            let message = next_message(&mut alice, "TaskProgress").await;
+            assert_eq!(message["task_id"], task_id.to_string());
+            assert_eq!(message["stage"], "downloading");
+            percents.push(message["percent"].as_u64().unwrap());
+        }
+        assert_eq!(percents, [0, 50, 100]);
+        // Alice's updates were relayed first, and none reached Bob
+        let message = next_message(&mut bob, "TaskProgress").await;
+        assert_eq!(message["user_id"], "bob");
+        assert_eq!(message["stage"], "uploading");
+
+        drop(worker_broadcast);
+        relay.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_worker_retries_reach_the_owners_socket_with_when_they_are_due() {
+        use common::broadcast::{BroadcastServiceFactory, QueueUpdateType, RetryInfo};
+        use worker::progress::bridge;
+
+        let (backend, socket) = relay_backend().await;
+        let mut alice = socket("alice").await;
+        let worker_broadcast = BroadcastServiceFactory::create_shared(16);
+        let relay = bridge(&worker_broadcast, reqwest::Client::new(), backend.clone());
+        let task_id = uuid::Uuid::new_v4();
+        let next_attempt_at = DateTime::parse_from_rfc3339("2026-03-01T09:04:00Z").unwrap().with_timezone(&Utc);
+        let update = |update_type, retry| common::broadcast::QueueUpdate {
+            update_type,
+            affected_user_id: Some("alice".to_string()),
+            global_position: None,
+            task_id: Some(task_id),
+            retry,
+            error: None,
+            timestamp: Utc::now(),
+        };
+        let retry = RetryInfo { retry_count: 1, max_retries: 3, next_attempt_at };
+        worker_broadcast.broadcast(update(QueueUpdateType::TaskRetried, Some(retry))).await;
+        worker_broadcast.broadcast(common::broadcast::QueueUpdate {
+            error: Some("Loom API error: upload rejected".to_string()),
+            ..update(QueueUpdateType::TaskFailed, None)
+        }).await;
+
+        let retried = next_message(&mut alice, "QueueUpdate").await;
+        assert_eq!(retried["affected_user_id"], "alice");
+        assert_eq!(retried["task_id"], task_id.to_string());
+        assert_eq!(
+            retried["retry"],
+            serde_json::json!({ "retry_count": 1, "max_retries": 3, "next_attempt_at": "2026-03-01T09:04:00Z" })
+        );
+        let failed = next_message(&mut alice, "QueueUpdate").await;
+        assert_eq!(failed["task_id"], task_id.to_string());
+        assert!(failed.get("retry").is_none(), "{}", failed);
+        assert_eq!(failed["update_type"], "task_failed");
+        assert_eq!(failed["error"], "Loom API error: upload rejected");
+
+        drop(worker_broadcast);
+        relay.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_internal_routes_need_the_shared_secret() {
+        let state = mock_app_state().await;
+        let uri = "/internal/keys/alice/fathom/touch";
+
+        for token in [None, Some("wrong-token"), Some("valid-alice")] {
+            let (status, body) = touch(&state, uri, token).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"], "invalid_internal_token");
+            let fetch = "/internal/keys/alice/fathom?public_key=a2V5";
+            let (status, body) = send(&state, "GET", fetch, token, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"], "invalid_internal_token");
+        }
+
+        // Unset, nothing gets in
+        let state = mock_app_state_with(|config| config.security.internal_api_token = None).await;
+        let (status, _) = touch(&state, uri, Some("test-internal-token")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
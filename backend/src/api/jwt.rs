@@ -0,0 +1,403 @@
+//! Session tokens minted by the backend after a PocketBase login
+//!
+//! Tokens are HS256 JWTs signed with `JWT_SECRET` and carry the user's id,
+//! email and role, so requests can be authenticated without asking the global
+//! PocketBase. The user's PocketBase token rides along encrypted under
+//! `MASTER_KEY`, letting handlers still act on their behalf. Every token names
+//! its signing key in the `kid` header; keys retired into
+//! `JWT_PREVIOUS_SECRETS` keep verifying until their tokens expire.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::crypto::{self, CiphertextBundle};
+use jsonwebtoken::{decode, decode_header, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::extractors::{AuthUser, Role};
+use crate::config::SecurityConfig;
+
+/// Expiry is enforced exactly rather than with jsonwebtoken's default 60s grace
+const LEEWAY_SECS: u64 = 0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    /// No `kid` header, so this isn't one of ours (e.g. a raw PocketBase token)
+    #[error("Token was not issued by this backend")]
+    NotIssuedHere,
+
+    #[error("Token signing key '{0}' is not recognised")]
+    UnknownKey(String),
+
+    #[error("Token has expired")]
+    Expired,
+
+    /// Past the maximum session age, so refreshing requires a fresh login
+    #[error("Session has exceeded its maximum age")]
+    SessionTooOld,
+
+    #[error("Token is invalid: {0}")]
+    Invalid(String),
+}
+
+/// Claims carried by backend-issued tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// PocketBase user id
+    pub sub: String,
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `admin` or `user`
+    pub role: String,
+    /// The user's PocketBase token, AES-256-GCM encrypted as nonce || ciphertext
+    pub pb_token: String,
+    pub iat: u64,
+    pub exp: u64,
+    /// When the user last logged in with their password; kept across refreshes
+    pub auth_time: u64,
+    /// The user's token generation at issue; bumped by logging out everywhere
+    #[serde(default)]
+    pub token_generation: u64,
+}
+
+/// Claims of single-purpose tokens such as password reset links
+///
+/// The purpose is carried as the audience, so these are never mistaken
+/// for session tokens or for each other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionClaims {
+    pub sub: String,
+    pub email: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+/// Claims of the signed cookie that carries an OAuth2 login across the provider redirect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthStateClaims {
+    /// The `state` PocketBase put in the provider URL, echoed back to the callback
+    #[serde(rename = "sub")]
+    pub state: String,
+    /// PKCE verifier PocketBase needs to exchange the code
+    pub code_verifier: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+const OAUTH_STATE_PURPOSE: &str = "oauth_state";
+
+/// Signing key plus the retired keys still accepted for verification
+pub struct JwtKeys<'a> {
+    security: &'a SecurityConfig,
+}
+
+impl<'a> JwtKeys<'a> {
+    pub fn new(security: &'a SecurityConfig) -> Self {
+        Self { security }
+    }
+
+    /// Mint a generation-0 token for `user` under the current key, starting a new session
+    pub fn issue(&self, user: &AuthUser, role: &str) -> Result<String, JwtError> {
+        self.issue_in_session(user, role, now(), 0)
+    }
+
+    /// Mint a token continuing a session that began at `auth_time`
+    pub fn issue_in_session(
+        &self,
+        user: &AuthUser,
+        role: &str,
+        auth_time: u64,
+        token_generation: u64,
+    ) -> Result<String, JwtError> {
+        let iat = now();
+        let claims = Claims {
+            sub: user.id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            role: role.to_string(),
+            pb_token: self.seal_pb_token(&user.token),
+            iat,
+            exp: iat + self.security.jwt_expiry_secs,
+            auth_time,
+            token_generation,
+        };
+
+        self.sign(&claims)
+    }
+
+    fn sign(&self, claims: &impl Serialize) -> Result<String, JwtError> {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(self.security.jwt_kid.clone());
+        encode(
+            &header,
+            claims,
+            &EncodingKey::from_secret(self.security.jwt_secret.as_bytes()),
+        )
+        .map_err(|e| JwtError::Invalid(e.to_string()))
+    }
+
+    /// Check the signature and expiry of `token` and return its claims
+    pub fn verify(&self, token: &str) -> Result<Claims, JwtError> {
+        self.decode_claims(token, LEEWAY_SECS, None)
+    }
+
+    /// Like [`JwtKeys::verify`], but also accepts tokens that expired within
+    /// the refresh window, as long as the session is under its maximum age
+    pub fn verify_for_refresh(&self, token: &str) -> Result<Claims, JwtError> {
+        let claims: Claims = self.decode_claims(token, self.security.jwt_refresh_window_secs, None)?;
+        if now().saturating_sub(claims.auth_time) > self.security.jwt_max_session_secs {
+            return Err(JwtError::SessionTooOld);
+        }
+        Ok(claims)
+    }
+
+    fn decode_claims<T: DeserializeOwned>(&self, token: &str, leeway: u64, audience: Option<&str>) -> Result<T, JwtError> {
+        let header = decode_header(token).map_err(|_| JwtError::NotIssuedHere)?;
+        let kid = header.kid.ok_or(JwtError::NotIssuedHere)?;
+        let secret = self
+            .secret_for(&kid)
+            .ok_or_else(|| JwtError::UnknownKey(kid.clone()))?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = leeway;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(audience) = audience {
+            validation.set_audience(&[audience]);
+        }
+
+        decode::<T>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+            .map(|data| data.claims)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => JwtError::Expired,
+                _ => JwtError::Invalid(e.to_string()),
+            })
+    }
+
+    /// Mint a token for `purpose` (e.g. `password_reset`) valid for `ttl_secs`
+    pub fn issue_action_token(&self, purpose: &str, sub: &str, email: &str, ttl_secs: u64) -> Result<String, JwtError> {
+        let iat = now();
+        let claims = ActionClaims {
+            sub: sub.to_string(),
+            email: email.to_string(),
+            aud: purpose.to_string(),
+            iat,
+            exp: iat + ttl_secs,
+        };
+        self.sign(&claims)
+    }
+
+    /// Check a token minted by [`JwtKeys::issue_action_token`] for `purpose`
+    pub fn verify_action_token(&self, token: &str, purpose: &str) -> Result<ActionClaims, JwtError> {
+        self.decode_claims(token, LEEWAY_SECS, Some(purpose))
+    }
+
+    /// Sign the state of an OAuth2 login that has just been sent to the provider
+    pub fn issue_oauth_state(&self, state: &str, code_verifier: &str, ttl_secs: u64) -> Result<String, JwtError> {
+        let iat = now();
+        self.sign(&OAuthStateClaims {
+            state: state.to_string(),
+            code_verifier: code_verifier.to_string(),
+            aud: OAUTH_STATE_PURPOSE.to_string(),
+            iat,
+            exp: iat + ttl_secs,
+        })
+    }
+
+    /// Check a token minted by [`JwtKeys::issue_oauth_state`]
+    pub fn verify_oauth_state(&self, token: &str) -> Result<OAuthStateClaims, JwtError> {
+        self.decode_claims(token, LEEWAY_SECS, Some(OAUTH_STATE_PURPOSE))
+    }
+
+    /// Verify `token` and rebuild the user it was issued to
+    pub fn authenticate(&self, token: &str) -> Result<AuthUser, JwtError> {
+        let claims = self.verify(token)?;
+        self.user_from_claims(claims)
+    }
+
+    /// The user `claims` were issued to, with their PocketBase token decrypted
+    pub fn user_from_claims(&self, claims: Claims) -> Result<AuthUser, JwtError> {
+        let pb_token = self.open_pb_token(&claims.pb_token)?;
+        Ok(AuthUser {
+            id: claims.sub,
+            email: claims.email,
+            name: claims.name,
+            token: pb_token,
+            role: Role::parse(&claims.role),
+        })
+    }
+
+    fn secret_for(&self, kid: &str) -> Option<&str> {
+        if kid == self.security.jwt_kid {
+            return Some(&self.security.jwt_secret);
+        }
+        self.security
+            .jwt_previous_secrets
+            .iter()
+            .find(|(previous, _)| previous == kid)
+            .map(|(_, secret)| secret.as_str())
+    }
+
+    fn pb_token_key(&self) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(b"jwt-pb-token:")
+            .chain_update(self.security.master_key.as_bytes())
+            .finalize()
+            .into()
+    }
+
+    fn seal_pb_token(&self, pb_token: &str) -> String {
+        let bundle = crypto::encrypt(&self.pb_token_key(), pb_token.as_bytes());
+        let mut sealed = bundle.nonce;
+        sealed.extend_from_slice(&bundle.ciphertext);
+        URL_SAFE_NO_PAD.encode(sealed)
+    }
+
+    fn open_pb_token(&self, sealed: &str) -> Result<String, JwtError> {
+        let invalid = || JwtError::Invalid("PocketBase token could not be decrypted".to_string());
+        let bytes = URL_SAFE_NO_PAD.decode(sealed).map_err(|_| invalid())?;
+        if bytes.len() < 12 {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = bytes.split_at(12);
+        let bundle = CiphertextBundle::new(ciphertext.to_vec(), nonce.to_vec()).map_err(|_| invalid())?;
+        let plaintext = crypto::decrypt(&self.pb_token_key(), &bundle).map_err(|_| invalid())?;
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+}
+
+pub fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+/// The `exp` claim of any JWT, without checking who signed it
+///
+/// Only for deciding how long to remember a revoked token.
+pub fn unverified_expiry(token: &str) -> Option<u64> {
+    #[derive(Deserialize)]
+    struct Expiry {
+        exp: u64,
+    }
+
+    let mut validation = Validation::new(decode_header(token).ok()?.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.set_required_spec_claims::<&str>(&[]);
+    decode::<Expiry>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|data| data.claims.exp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_config;
+
+    fn user() -> AuthUser {
+        AuthUser {
+            id: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            name: Some("Alice".to_string()),
+            token: "valid-alice".to_string(),
+            role: Role::User,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_keeps_claims_and_hides_pb_token() {
+        let security = test_config("http://unused").security;
+        let keys = JwtKeys::new(&security);
+        let token = keys.issue(&user(), "user").unwrap();
+
+        let claims = keys.verify(&token).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.role, "user");
+        assert_eq!(claims.exp - claims.iat, security.jwt_expiry_secs);
+        assert!(!claims.pb_token.contains("valid-alice"));
+
+        let authed = keys.authenticate(&token).unwrap();
+        assert_eq!(authed.email, "alice@example.com");
+        assert_eq!(authed.token, "valid-alice");
+    }
+
+    #[test]
+    fn test_expired_and_tampered_tokens_are_rejected() {
+        let mut security = test_config("http://unused").security;
+        security.jwt_expiry_secs = 0;
+        let expired = JwtKeys::new(&security).issue(&user(), "user").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(matches!(JwtKeys::new(&security).verify(&expired), Err(JwtError::Expired)));
+
+        security.jwt_expiry_secs = 3600;
+        let keys = JwtKeys::new(&security);
+        let token = keys.issue(&user(), "user").unwrap();
+
+        // Promote ourselves to admin without re-signing
+        let mut parts: Vec<String> = token.split('.').map(String::from).collect();
+        let mut claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(&parts[1]).unwrap()).unwrap();
+        claims["role"] = "admin".into();
+        parts[1] = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        assert!(matches!(keys.verify(&parts.join(".")), Err(JwtError::Invalid(_))));
+
+        // Same kid, different secret
+        let mut forger = security.clone();
+        forger.jwt_secret = "guessed".to_string();
+        let forged = JwtKeys::new(&forger).issue(&user(), "admin").unwrap();
+        assert!(matches!(keys.verify(&forged), Err(JwtError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_rotated_keys_keep_verifying_until_dropped() {
+        let old = test_config("http://unused").security;
+        let token = JwtKeys::new(&old).issue(&user(), "user").unwrap();
+
+        let mut rotated = old.clone();
+        rotated.jwt_kid = "next".to_string();
+        rotated.jwt_secret = "next-secret".to_string();
+        rotated.jwt_previous_secrets = vec![(old.jwt_kid.clone(), old.jwt_secret.clone())];
+        assert_eq!(JwtKeys::new(&rotated).verify(&token).unwrap().sub, "alice");
+
+        rotated.jwt_previous_secrets.clear();
+        assert!(matches!(JwtKeys::new(&rotated).verify(&token), Err(JwtError::UnknownKey(_))));
+
+        // PocketBase tokens carry no kid and are left to the legacy path
+        assert!(matches!(JwtKeys::new(&rotated).verify("valid-alice"), Err(JwtError::NotIssuedHere)));
+    }
+
+    #[test]
+    fn test_refresh_accepts_recently_expired_tokens_within_session_age() {
+        let mut security = test_config("http://unused").security;
+        security.jwt_expiry_secs = 0;
+        let keys = JwtKeys::new(&security);
+        let auth_time = now() - 60;
+        let token = keys.issue_in_session(&user(), "user", auth_time, 0).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        assert!(matches!(keys.verify(&token), Err(JwtError::Expired)));
+        assert_eq!(keys.verify_for_refresh(&token).unwrap().auth_time, auth_time);
+
+        let mut strict = security.clone();
+        strict.jwt_max_session_secs = 30;
+        assert!(matches!(JwtKeys::new(&strict).verify_for_refresh(&token), Err(JwtError::SessionTooOld)));
+
+        strict = security.clone();
+        strict.jwt_refresh_window_secs = 0;
+        assert!(matches!(JwtKeys::new(&strict).verify_for_refresh(&token), Err(JwtError::Expired)));
+    }
+
+    #[test]
+    fn test_action_tokens_are_bound_to_their_purpose() {
+        let security = test_config("http://unused").security;
+        let keys = JwtKeys::new(&security);
+        let token = keys.issue_action_token("password_reset", "alice", "alice@example.com", 60).unwrap();
+
+        let claims = keys.verify_action_token(&token, "password_reset").unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert!(keys.verify_action_token(&token, "verify_email").is_err());
+        assert!(keys.verify(&token).is_err());
+
+        let session = keys.issue(&user(), "user").unwrap();
+        assert!(keys.verify_action_token(&session, "password_reset").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Audit trail of API key changes
+//!
+//! Every key added, replaced, deleted or made default through the keys
+//! endpoints, and every key re-encrypted by a master key rotation, leaves an
+//! entry in the global PocketBase `key_audit` collection: whose key, who
+//! changed it and from which IP, and the key's fingerprint before and after.
+//! Fingerprints are keyed hashes, so the trail tells values apart without
+//! holding them. Entries go through the same buffered [`AuditLogger`] as auth
+//! events, so a PocketBase outage never holds up the change itself.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+use super::{
+    audit::{AuditEntry, AuditLogger},
+    auth::auth_error,
+    extractors::AuthUser,
+    sessions::SessionOrigin,
+};
+use crate::global_pb;
+use common::ErrorCode;
+
+const KEY_AUDIT: &str = "key_audit";
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 200;
+
+/// Writer of the key audit trail
+pub type KeyAuditLog = AuditLogger<KeyAuditEntry>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    Created,
+    Replaced,
+    Deleted,
+    /// Re-encrypted under a new master key
+    Rotated,
+    /// Made the default key of its service
+    MadeDefault,
+}
+
+/// One change to a stored key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyAuditEntry {
+    /// Owner of the key
+    pub user_id: String,
+    /// Who made the change: the owner, or the admin running a rotation
+    pub actor_id: String,
+    pub action: KeyAction,
+    pub service: String,
+    pub key_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_after: Option<String>,
+    #[serde(default)]
+    pub ip: String,
+    /// Unix time the change was recorded at
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+impl KeyAuditEntry {
+    /// A change `user_id` made to their own key from the client `origin`
+    pub fn new(action: KeyAction, user_id: &str, service: &str, key_id: &str, origin: &SessionOrigin) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            actor_id: user_id.to_string(),
+            action,
+            service: service.to_string(),
+            key_id: key_id.to_string(),
+            fingerprint_before: None,
+            fingerprint_after: None,
+            ip: origin.ip.clone(),
+            created_at: 0,
+        }
+    }
+
+    pub fn actor(mut self, actor_id: &str) -> Self {
+        self.actor_id = actor_id.to_string();
+        self
+    }
+
+    pub fn fingerprints(mut self, before: Option<&str>, after: Option<&str>) -> Self {
+        self.fingerprint_before = before.map(str::to_string);
+        self.fingerprint_after = after.map(str::to_string);
+        self
+    }
+}
+
+impl AuditEntry for KeyAuditEntry {
+    const COLLECTION: &'static str = KEY_AUDIT;
+
+    fn stamp(&mut self, at: u64) {
+        self.created_at = at;
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyAuditQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    /// Whose history to list; only admins may name someone else
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyAuditPage {
+    pub entries: Vec<KeyAuditEntry>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total_items: u64,
+}
+
+/// GET /api/keys/audit - Changes to the caller's keys, newest first
+///
+/// Pending entries are flushed first so the listing includes them.
+pub async fn list_key_audit(
+    user: AuthUser,
+    State(log): State<Arc<KeyAuditLog>>,
+    Query(query): Query<KeyAuditQuery>,
+) -> Result<Response, Response> {
+    let user_id = match query.user_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(other) if other != user.id => {
+            user.require_admin().map_err(IntoResponse::into_response)?;
+            other
+        }
+        _ => user.id.as_str(),
+    };
+    log.flush().await;
+
+    let filter = format!("user_id = {}", global_pb::quote(user_id));
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let listed = log
+        .store()
+        .list_page(KEY_AUDIT, Some(&filter), "-created_at,-created", page, per_page)
+        .await
+        .map_err(|e| {
+            warn!("Failed to list key audit entries: {}", e);
+            auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Key history could not be loaded",
+            )
+        })?;
+    let entries = listed
+        .items
+        .into_iter()
+        .filter_map(|record| serde_json::from_value(record).ok())
+        .collect();
+    Ok((
+        StatusCode::OK,
+        Json(KeyAuditPage {
+            entries,
+            page: listed.page,
+            per_page: listed.per_page,
+            total_items: listed.total_items,
+        }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, key_repository, AppState},
+        pocketbase_manager::PocketBaseManager,
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn setup(dir: &std::path::Path) -> AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager = PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 9)
+            .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+        for user in ["alice", "bob"] {
+            let data_dir = state.pb_manager.data_dir(user);
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        }
+        state
+    }
+
+    async fn send(state: &AppState, user: &str, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer valid-{}", user))
+            .header("content-type", "application/json")
+            .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn put(state: &AppState, user: &str, value: &str) {
+        let body = json!({ "service": "fathom", "value": value });
+        assert_eq!(send(state, user, "PUT", "/api/keys", Some(body)).await.0, StatusCode::OK);
+    }
+
+    fn fingerprint(state: &AppState, value: &str) -> Value {
+        json!(key_repository::fingerprint(&state.master_key.bytes(), value))
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_recorded_without_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        put(&state, "alice", "fathom-first-0123456789").await;
+        put(&state, "alice", "fathom-second-0123456789").await;
+        let (status, _) = send(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        assert_eq!(status, StatusCode::OK);
+        // Deleting what isn't there changes nothing and records nothing
+        let (status, _) = send(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(&state, "alice", "GET", "/api/keys/audit", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total_items"], 3);
+        let entries = body["entries"].as_array().unwrap();
+        let actions: Vec<&str> = entries.iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+        assert_eq!(actions, ["deleted", "replaced", "created"]);
+
+        let (first, second) = (fingerprint(&state, "fathom-first-0123456789"), fingerprint(&state, "fathom-second-0123456789"));
+        assert!(entries[2].get("fingerprint_before").is_none());
+        assert_eq!(entries[2]["fingerprint_after"], first);
+        assert_eq!((&entries[1]["fingerprint_before"], &entries[1]["fingerprint_after"]), (&first, &second));
+        assert_eq!(entries[0]["fingerprint_before"], second);
+        assert!(entries[0].get("fingerprint_after").is_none());
+        for entry in entries {
+            assert_eq!((&entry["user_id"], &entry["actor_id"]), (&json!("alice"), &json!("alice")));
+            assert_eq!((&entry["service"], &entry["key_id"]), (&json!("fathom"), &json!("default")));
+            assert_eq!(entry["ip"], "unknown");
+            assert!(entry["created_at"].as_u64().unwrap() > 0);
+        }
+        let stored = state.key_audit.store().list_records(KEY_AUDIT, None).await.unwrap();
+        assert!(!json!(stored).to_string().contains("0123456789"));
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_history_is_scoped_to_the_caller() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        put(&state, "alice", "fathom-alice-0123456789").await;
+        put(&state, "bob", "fathom-bob-0123456789").await;
+
+        let (_, body) = send(&state, "alice", "GET", "/api/keys/audit", None).await;
+        assert_eq!(body["total_items"], 1);
+        assert_eq!(body["entries"][0]["user_id"], "alice");
+
+        let (status, body) = send(&state, "alice", "GET", "/api/keys/audit?user_id=bob", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "admin_required");
+        let (status, _) = send(&state, "alice", "GET", "/api/keys/audit?user_id=alice", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&state, "admin", "GET", "/api/keys/audit?user_id=bob", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total_items"], 1);
+        assert_eq!(body["entries"][0]["fingerprint_after"], fingerprint(&state, "fathom-bob-0123456789"));
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+}
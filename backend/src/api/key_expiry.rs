@@ -0,0 +1,389 @@
+//! Renewal reminders for stored API keys
+//!
+//! Once a day the scanner walks the key repository of every user with a
+//! PocketBase instance on disk. For each key that expires within
+//! `KEY_EXPIRY_WARNING_DAYS`, or already has, it emails the owner through
+//! the smtp-service and publishes a `key_expiring` event on the system
+//! topic. The reminder is noted on the key record, so a stored value gets a
+//! single reminder however often the scan runs; replacing the value clears
+//! the note and the new value gets its own.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::key_repository::{self, KeyRepository, KeyStoreError, MasterKey, StoredKey};
+use crate::{
+    config::Config,
+    global_pb::GlobalPb,
+    mailer::{self, Mailer},
+    pocketbase_manager::PocketBaseManager,
+};
+use common::{
+    broadcast::{BroadcastService, SystemEvent, SystemEventType},
+    clock::Clock,
+    ServiceKind,
+};
+
+/// How often the scheduled scan runs
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Outcome of one scan
+#[derive(Debug, Default, PartialEq)]
+pub struct ScanReport {
+    /// Users whose keys were read
+    pub users: usize,
+    /// Reminders sent
+    pub reminded: usize,
+    /// Users whose keys couldn't be read plus reminders that couldn't be
+    /// sent; those are tried again on the next scan
+    pub failed: usize,
+}
+
+pub struct KeyExpiryScanner {
+    global_pb: Arc<GlobalPb>,
+    pb_manager: Arc<PocketBaseManager>,
+    mailer: Arc<Mailer>,
+    broadcast: Option<Arc<BroadcastService>>,
+    clock: Arc<dyn Clock>,
+    master_key: MasterKey,
+    warning_window: ChronoDuration,
+    settings_url: String,
+}
+
+impl KeyExpiryScanner {
+    pub fn new(
+        config: &Config,
+        master_key: MasterKey,
+        global_pb: Arc<GlobalPb>,
+        pb_manager: Arc<PocketBaseManager>,
+        mailer: Arc<Mailer>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            global_pb,
+            pb_manager,
+            mailer,
+            broadcast: None,
+            clock,
+            master_key,
+            warning_window: ChronoDuration::days(config.integrations.key_expiry_warning_days),
+            settings_url: config.integrations.key_settings_url.clone(),
+        }
+    }
+
+    /// Publish a system event for every reminder sent
+    pub fn with_broadcast(mut self, broadcast: Arc<BroadcastService>) -> Self {
+        self.broadcast = Some(broadcast);
+        self
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Remind the owners of every key due a reminder
+    pub async fn scan(&self) -> ScanReport {
+        let mut report = ScanReport::default();
+        let users = match key_repository::key_owners(&self.global_pb, &self.pb_manager).await {
+            Ok(users) => users,
+            Err(e) => {
+                warn!("Key expiry scan could not list users: {}", e);
+                report.failed += 1;
+                return report;
+            }
+        };
+
+        let now = self.now();
+        for user in &users {
+            let user_id = user["id"].as_str().unwrap_or_default();
+            let email = user["email"].as_str().unwrap_or_default();
+            if let Err(e) = self.scan_user(user_id, email, now, &mut report).await {
+                warn!(
+                    "Key expiry scan could not read the keys of {}: {}",
+                    user_id, e
+                );
+                report.failed += 1;
+            }
+        }
+        info!(
+            "Key expiry scan read {} users' keys, sent {} reminders, {} failures",
+            report.users, report.reminded, report.failed
+        );
+        report
+    }
+
+    async fn scan_user(
+        &self,
+        user_id: &str,
+        email: &str,
+        now: DateTime<Utc>,
+        report: &mut ScanReport,
+    ) -> Result<(), KeyStoreError> {
+        let repository = KeyRepository::open(&self.pb_manager, user_id, &self.master_key).await?;
+        let keys = repository.list().await?;
+        report.users += 1;
+
+        for stored in keys.iter().filter(|stored| self.is_due(stored, now)) {
+            let key = &stored.key;
+            let days_left = key.days_until_expiry(now).unwrap_or_default();
+            let service = ServiceKind::parse(&key.service)
+                .map_or(key.service.as_str(), |kind| kind.display_name());
+            let message = mailer::KEY_EXPIRING.render(
+                email,
+                &[
+                    ("service", service),
+                    ("key_id", &key.key_id),
+                    ("status", &status(stored, now)),
+                    ("link", &self.settings_url),
+                ],
+            );
+            if let Err(e) = self.mailer.send(&message).await {
+                warn!(
+                    "Failed to remind {} that their {} key expires: {}",
+                    user_id, key.service, e
+                );
+                report.failed += 1;
+                continue;
+            }
+            repository
+                .mark_reminded(&key.service, &key.key_id, now)
+                .await?;
+            report.reminded += 1;
+
+            if let Some(broadcast) = &self.broadcast {
+                let event_type = SystemEventType::KeyExpiring {
+                    service: key.service.clone(),
+                    key_id: key.key_id.clone(),
+                    days_left,
+                };
+                broadcast.broadcast_system(SystemEvent::new(event_type, user_id, 0));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `stored` is within the warning window and its owner hasn't
+    /// been reminded of it yet
+    fn is_due(&self, stored: &StoredKey, now: DateTime<Utc>) -> bool {
+        let Some(expires_at) = stored.key.expires_at else {
+            return false;
+        };
+        expires_at - now <= self.warning_window && stored.expiry_reminded_at.is_none()
+    }
+
+    /// Scan now and then every `interval`
+    pub fn start(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let scanner = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                scanner.scan().await;
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+/// How soon the key expires, completing "Your Fathom API key ..."
+fn status(stored: &StoredKey, now: DateTime<Utc>) -> String {
+    let expires_at = stored.key.expires_at.unwrap_or(now);
+    match stored.key.days_until_expiry(now).unwrap_or_default() {
+        _ if expires_at <= now => format!("expired on {}", expires_at.format("%Y-%m-%d")),
+        0 => "expires today".to_string(),
+        1 => "expires tomorrow".to_string(),
+        days => format!("expires in {} days", days),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        fake_pocketbase, mock_global_pocketbase, mock_smtp_service, port_window, test_config, ManualClock,
+    };
+    use serde_json::{json, Value};
+    use tokio::sync::Mutex;
+
+    /// 2026-03-01T00:00:00Z
+    const START: u64 = 1_772_323_200;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    struct Fixture {
+        scanner: KeyExpiryScanner,
+        clock: Arc<ManualClock>,
+        sent: Arc<Mutex<Vec<Value>>>,
+        broadcast: Arc<BroadcastService>,
+        repository: KeyRepository,
+        pb_manager: Arc<PocketBaseManager>,
+        user_id: String,
+    }
+
+    async fn setup(dir: &std::path::Path) -> Fixture {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let (smtp_url, sent) = mock_smtp_service().await;
+        let mut config = test_config(&global_url);
+        config.email.smtp_service_url = smtp_url;
+
+        let global_pb = Arc::new(GlobalPb::from(&config.database));
+        let mut user_ids = Vec::new();
+        for email in ["alice@example.com", "bob@example.com"] {
+            let record = json!({ "email": email, "password": "password" });
+            let user = global_pb.create_record("users", &record).await.unwrap();
+            user_ids.push(user["id"].as_str().unwrap().to_string());
+        }
+
+        let binary = fake_pocketbase(dir);
+        let pb_manager = Arc::new(
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(std::time::Duration::from_secs(10)),
+        );
+        // Only Alice has an instance; Bob never stored a key
+        let data_dir = pb_manager.data_dir(&user_ids[0]);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+
+        let clock = Arc::new(ManualClock::new(START));
+        let broadcast = Arc::new(BroadcastService::new(16));
+        let master_key = MasterKey::from_config(&config.security).unwrap();
+        let scanner = KeyExpiryScanner::new(
+            &config,
+            master_key,
+            global_pb,
+            pb_manager.clone(),
+            Arc::new(Mailer::new(&config.email)),
+            clock.clone(),
+        )
+        .with_broadcast(broadcast.clone());
+        let repository = KeyRepository::open(&pb_manager, &user_ids[0], &master_key)
+            .await
+            .unwrap();
+        Fixture {
+            scanner,
+            clock,
+            sent,
+            broadcast,
+            repository,
+            pb_manager,
+            user_id: user_ids.swap_remove(0),
+        }
+    }
+
+    fn days_after_start(days: i64) -> Option<DateTime<Utc>> {
+        Some(DateTime::from_timestamp(START as i64, 0).unwrap() + ChronoDuration::days(days))
+    }
+
+    #[tokio::test]
+    async fn test_reminds_once_per_key_within_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = setup(dir.path()).await;
+        let mut events = fixture.broadcast.subscribe_system();
+        let repository = &fixture.repository;
+        repository
+            .put("fathom", "default", "fathom-soon", days_after_start(10))
+            .await
+            .unwrap();
+        repository
+            .put("loom", "default", "loom-later", days_after_start(30))
+            .await
+            .unwrap();
+        repository
+            .put("loom", "spare", "loom-forever", None)
+            .await
+            .unwrap();
+
+        // Ten days out is outside the seven-day window
+        let report = fixture.scanner.scan().await;
+        assert_eq!(
+            report,
+            ScanReport {
+                users: 1,
+                reminded: 0,
+                failed: 0
+            }
+        );
+
+        fixture.clock.advance(5 * DAY);
+        assert_eq!(fixture.scanner.scan().await.reminded, 1);
+        {
+            let sent = fixture.sent.lock().await;
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0]["to_email"], "alice@example.com");
+            assert_eq!(sent[0]["subject"], "Your Fathom API key expires in 5 days");
+            assert!(sent[0]["body_text"]
+                .as_str()
+                .unwrap()
+                .contains("http://localhost:8080/settings"));
+            assert!(!sent[0].to_string().contains("fathom-soon"));
+        }
+        let event = events.try_recv().unwrap();
+        assert_eq!(
+            event.event_type,
+            SystemEventType::KeyExpiring {
+                service: "fathom".to_string(),
+                key_id: "default".to_string(),
+                days_left: 5
+            }
+        );
+
+        // Later scans in the same window, even past expiry, stay quiet
+        fixture.clock.advance(DAY);
+        assert_eq!(fixture.scanner.scan().await.reminded, 0);
+        fixture.clock.advance(7 * DAY);
+        assert_eq!(fixture.scanner.scan().await.reminded, 0);
+        assert_eq!(fixture.sent.lock().await.len(), 1);
+        assert!(events.try_recv().is_err());
+
+        // A replacement value gets its own reminder
+        repository
+            .put("fathom", "default", "fathom-next", days_after_start(14))
+            .await
+            .unwrap();
+        assert_eq!(fixture.scanner.scan().await.reminded, 1);
+        assert_eq!(
+            fixture.sent.lock().await[1]["subject"],
+            "Your Fathom API key expires tomorrow"
+        );
+
+        fixture
+            .pb_manager
+            .stop_user_instance(&fixture.user_id)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keys_found_already_expired_are_flagged_as_such() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = setup(dir.path()).await;
+        fixture
+            .repository
+            .put("loom", "default", "loom-old", days_after_start(-3))
+            .await
+            .unwrap();
+
+        assert_eq!(fixture.scanner.scan().await.reminded, 1);
+        let sent = fixture.sent.lock().await;
+        assert_eq!(
+            sent[0]["subject"],
+            "Your Loom API key expired on 2026-02-26"
+        );
+
+        let stored = fixture
+            .repository
+            .get("loom", "default")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.expiry_reminded_at, days_after_start(0));
+
+        fixture
+            .pb_manager
+            .stop_user_instance(&fixture.user_id)
+            .await
+            .unwrap();
+    }
+}
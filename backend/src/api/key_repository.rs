@@ -0,0 +1,624 @@
+//! Per-user storage of encrypted API keys
+//!
+//! Keys live in the `user_keys` collection of their owner's PocketBase
+//! instance, reached through the manager's admin client, so which instance
+//! a repository is opened on decides whose keys it sees. Values are
+//! encrypted with AES-256-GCM under the key configured as `MASTER_KEY`, with
+//! the owner's user id as associated data: a record copied into another
+//! user's instance fails to decrypt instead of handing them the key.
+//!
+//! Each record also keeps a fingerprint and a masked hint of the value, so
+//! keys can be listed and told apart without decrypting them, and the
+//! version of the master key it is encrypted under, so a rotation can tell
+//! which records it has already re-encrypted.
+//!
+//! A service can have several keys, told apart by `key_id`, of which one is
+//! its default: the one used when nobody names a key. Writes keep exactly one
+//! default per service, and records stored before defaults existed fall back
+//! to the key named `default`, then to the oldest.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::{
+    config::{ConfigError, SecurityConfig},
+    global_pb::{quote, GlobalPb, GlobalPbError},
+    pocketbase_manager::{PocketBaseError, PocketBaseManager},
+};
+use common::crypto::{parse_master_key, CiphertextBundle, CryptoError, EncryptedApiKey};
+
+const USER_KEYS: &str = "user_keys";
+
+/// Trailing characters of a value shown in its masked hint
+const HINT_CHARS: usize = 4;
+
+/// Values shorter than this get no trailing characters in their hint
+const MIN_HINTED_LENGTH: usize = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyStoreError {
+    #[error("User instance unavailable: {0}")]
+    Instance(#[from] PocketBaseError),
+
+    #[error(transparent)]
+    Store(#[from] GlobalPbError),
+
+    #[error("Stored key record {0} is malformed")]
+    Malformed(String),
+}
+
+/// Key stored API keys are encrypted under, parsed from `MASTER_KEY` once
+/// at startup and shared through the app state
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MasterKey([u8; 32]);
+
+impl MasterKey {
+    /// The configured `MASTER_KEY`, refusing anything but 32 base64-encoded bytes
+    pub fn from_config(security: &SecurityConfig) -> Result<Self, ConfigError> {
+        Self::parse(&security.master_key).map_err(|_| ConfigError::InvalidMasterKey("MASTER_KEY"))
+    }
+
+    pub fn parse(encoded: &str) -> Result<Self, CryptoError> {
+        parse_master_key(encoded).map(Self)
+    }
+
+    pub fn bytes(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MasterKey").field(&key_version(&self.0)).finish()
+    }
+}
+
+/// Short public identifier of `master_key`, stored with each record
+pub fn key_version(master_key: &[u8; 32]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(master_key).expect("HMAC accepts keys of any length");
+    mac.update(b"api-key-version");
+    mac.finalize().into_bytes()[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The `users` records of everyone who may have stored keys
+///
+/// Keys live in the owner's own instance, so users without an instance
+/// directory have none and are left out.
+pub async fn key_owners(global_pb: &GlobalPb, pb_manager: &PocketBaseManager) -> Result<Vec<Value>, GlobalPbError> {
+    Ok(global_pb
+        .list_records("users", None)
+        .await?
+        .into_iter()
+        .filter(|user| {
+            let user_id = user["id"].as_str().unwrap_or_default();
+            !user_id.is_empty() && pb_manager.data_dir(user_id).exists()
+        })
+        .collect())
+}
+
+/// The `user_keys` collection, readable and writable by admins only
+fn collection_schema() -> Value {
+    let text = |name: &str, required: bool| json!({ "name": name, "type": "text", "required": required });
+    json!({
+        "name": USER_KEYS,
+        "type": "base",
+        "schema": [
+            text("service", true),
+            text("key_id", true),
+            text("ciphertext", true),
+            text("nonce", true),
+            text("created_at", true),
+            text("expires_at", false),
+            text("fingerprint", false),
+            text("masked_hint", false),
+            text("last_used_at", false),
+            text("expiry_reminded_at", false),
+            text("key_version", false),
+            json!({ "name": "is_default", "type": "bool", "required": false }),
+        ],
+        "indexes": [
+            "CREATE UNIQUE INDEX idx_user_keys_service_key_id ON user_keys (service, key_id)"
+        ],
+        "listRule": null,
+        "viewRule": null,
+        "createRule": null,
+        "updateRule": null,
+        "deleteRule": null
+    })
+}
+
+/// A stored key with the metadata kept alongside it
+#[derive(Debug, Clone)]
+pub struct StoredKey {
+    pub key: EncryptedApiKey,
+    /// Keyed hash of the value, identical for identical values
+    pub fingerprint: String,
+    /// The value with all but its last few characters masked
+    pub masked_hint: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// When its owner was reminded that this value expires, cleared when
+    /// the value is replaced
+    pub expiry_reminded_at: Option<DateTime<Utc>>,
+    /// [`key_version`] of the master key the value is encrypted under
+    pub key_version: String,
+    /// Used for its service unless a key is named
+    pub is_default: bool,
+}
+
+/// Outcome of [`KeyRepository::touch`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Touch {
+    Recorded(DateTime<Utc>),
+    /// Use was recorded recently enough at the given time
+    Throttled(DateTime<Utc>),
+    Missing,
+}
+
+/// What a rotation did with one user's keys
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct RotationCounts {
+    pub rotated: usize,
+    /// Already under the current master key
+    pub skipped: usize,
+    /// Under neither key, or not writable
+    pub failed: usize,
+    /// The keys rotated, for the audit trail
+    #[serde(skip)]
+    pub changes: Vec<KeyChange>,
+}
+
+/// A key rewritten by a rotation, named by its fingerprints under the old
+/// and new master key
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChange {
+    pub service: String,
+    pub key_id: String,
+    pub fingerprint_before: String,
+    pub fingerprint_after: String,
+}
+
+/// Short hex HMAC-SHA256 of `value` under `master_key`
+///
+/// Keyed, so a leaked listing can't be used to test guesses offline.
+pub fn fingerprint(master_key: &[u8; 32], value: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(master_key).expect("HMAC accepts keys of any length");
+    mac.update(b"api-key-fingerprint:");
+    mac.update(value.as_bytes());
+    mac.finalize().into_bytes()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `••••` followed by the last characters of long enough values
+pub fn masked_hint(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() < MIN_HINTED_LENGTH {
+        return "••••".to_string();
+    }
+    let tail: String = chars[chars.len() - HINT_CHARS..].iter().collect();
+    format!("••••{}", tail)
+}
+
+/// One user's stored API keys
+pub struct KeyRepository {
+    pb: Arc<GlobalPb>,
+    user_id: String,
+    master_key: [u8; 32],
+}
+
+impl KeyRepository {
+    /// Open `user_id`'s keys, starting their instance and creating the
+    /// collection if needed
+    pub async fn open(
+        pb_manager: &PocketBaseManager,
+        user_id: &str,
+        master_key: &MasterKey,
+    ) -> Result<Self, KeyStoreError> {
+        let pb = pb_manager.admin_client(user_id).await?;
+        pb.ensure_collection(&collection_schema()).await?;
+        Ok(Self {
+            pb,
+            user_id: user_id.to_string(),
+            master_key: master_key.bytes(),
+        })
+    }
+
+    /// Every stored key, ordered by service and key id
+    pub async fn list(&self) -> Result<Vec<StoredKey>, KeyStoreError> {
+        let mut keys = self
+            .pb
+            .list_records(USER_KEYS, None)
+            .await?
+            .iter()
+            .map(from_record)
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.sort_by(|a, b| (&a.key.service, &a.key.key_id).cmp(&(&b.key.service, &b.key.key_id)));
+        let services: Vec<String> = keys.iter().map(|stored| stored.key.service.clone()).collect();
+        for service in services {
+            let mut of_service: Vec<&mut StoredKey> =
+                keys.iter_mut().filter(|stored| stored.key.service == service).collect();
+            mark_default(&mut of_service);
+        }
+        Ok(keys)
+    }
+
+    /// The keys stored for `service` with their record ids, oldest first
+    async fn service_keys(&self, service: &str) -> Result<Vec<(String, StoredKey)>, KeyStoreError> {
+        let filter = format!("service = {}", quote(service));
+        let mut keys = self
+            .pb
+            .list_records(USER_KEYS, Some(&filter))
+            .await?
+            .iter()
+            .map(|record| {
+                let id = record.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string();
+                from_record(record).map(|stored| (id, stored))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.sort_by(|(_, a), (_, b)| (a.key.created_at, &a.key.key_id).cmp(&(b.key.created_at, &b.key.key_id)));
+        mark_default(&mut keys.iter_mut().map(|(_, stored)| stored).collect::<Vec<_>>());
+        Ok(keys)
+    }
+
+    /// The default key of `service`
+    pub async fn default_key(&self, service: &str) -> Result<Option<StoredKey>, KeyStoreError> {
+        Ok(self
+            .service_keys(service)
+            .await?
+            .into_iter()
+            .map(|(_, stored)| stored)
+            .find(|stored| stored.is_default))
+    }
+
+    /// The key `key_id` of `service`, or its default when none is named
+    pub async fn resolve(&self, service: &str, key_id: Option<&str>) -> Result<Option<StoredKey>, KeyStoreError> {
+        match key_id {
+            Some(key_id) => self.get(service, key_id).await,
+            None => self.default_key(service).await,
+        }
+    }
+
+    /// Make `key_id` the default of `service`, clearing the old default
+    ///
+    /// Returns the new default, or `None` if there is no such key.
+    pub async fn set_default(&self, service: &str, key_id: &str) -> Result<Option<StoredKey>, KeyStoreError> {
+        let keys = self.service_keys(service).await?;
+        let Some((id, target)) = keys.iter().find(|(_, stored)| stored.key.key_id == key_id) else {
+            return Ok(None);
+        };
+        self.pb.update_record(USER_KEYS, id, &json!({ "is_default": true })).await?;
+        for (other, _) in keys.iter().filter(|(other, _)| other != id) {
+            self.pb.update_record(USER_KEYS, other, &json!({ "is_default": false })).await?;
+        }
+        Ok(Some(StoredKey {
+            is_default: true,
+            ..target.clone()
+        }))
+    }
+
+    /// Encrypt and store `value` as the key `key_id` for `service`,
+    /// replacing any key already stored under that pair
+    pub async fn put(
+        &self,
+        service: &str,
+        key_id: &str,
+        value: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<StoredKey, KeyStoreError> {
+        let existing = self.service_keys(service).await?;
+        let replaced = existing.iter().find(|(_, stored)| stored.key.key_id == key_id);
+        let key = StoredKey {
+            key: EncryptedApiKey::new_for_user(
+                &self.user_id,
+                service.to_string(),
+                key_id.to_string(),
+                value,
+                &self.master_key,
+                expires_at,
+            ),
+            fingerprint: fingerprint(&self.master_key, value),
+            masked_hint: masked_hint(value),
+            last_used_at: None,
+            expiry_reminded_at: None,
+            key_version: key_version(&self.master_key),
+            // A replacement keeps its place; the first key of a service is its default
+            is_default: replaced.map_or(existing.is_empty(), |(_, stored)| stored.is_default),
+        };
+        let record = to_record(&key);
+        match replaced {
+            Some((id, _)) => self.pb.update_record(USER_KEYS, id, &record).await?,
+            None => self.pb.create_record(USER_KEYS, &record).await?,
+        };
+        Ok(key)
+    }
+
+    /// Remove the key stored under `service` and `key_id`
+    ///
+    /// Deleting the default makes the oldest remaining key the default.
+    /// Returns false if there was none.
+    pub async fn delete(&self, service: &str, key_id: &str) -> Result<bool, KeyStoreError> {
+        let keys = self.service_keys(service).await?;
+        let Some((id, deleted)) = keys.iter().find(|(_, stored)| stored.key.key_id == key_id) else {
+            return Ok(false);
+        };
+        self.pb.delete_record(USER_KEYS, id).await?;
+        if deleted.is_default {
+            if let Some((next, _)) = keys.iter().find(|(other, _)| other != id) {
+                self.pb.update_record(USER_KEYS, next, &json!({ "is_default": true })).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Note that the owner was reminded at `at` that the key under `service`
+    /// and `key_id` expires
+    ///
+    /// Returns false if there is no such key.
+    pub async fn mark_reminded(&self, service: &str, key_id: &str, at: DateTime<Utc>) -> Result<bool, KeyStoreError> {
+        match self.find(service, key_id).await? {
+            Some(id) => {
+                let record = json!({ "expiry_reminded_at": at.to_rfc3339() });
+                self.pb.update_record(USER_KEYS, &id, &record).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Record that the key under `service` and `key_id` was used at `now`,
+    /// unless that was already recorded less than `min_interval` earlier
+    pub async fn touch(
+        &self,
+        service: &str,
+        key_id: &str,
+        now: DateTime<Utc>,
+        min_interval: chrono::Duration,
+    ) -> Result<Touch, KeyStoreError> {
+        let Some(record) = self.find_record(service, key_id).await? else {
+            return Ok(Touch::Missing);
+        };
+        let stored = from_record(&record)?;
+        if let Some(last_used_at) = stored.last_used_at.filter(|at| now - *at < min_interval) {
+            return Ok(Touch::Throttled(last_used_at));
+        }
+        let id = record.get("id").and_then(|id| id.as_str()).unwrap_or_default();
+        let changes = json!({ "last_used_at": now.to_rfc3339() });
+        self.pb.update_record(USER_KEYS, id, &changes).await?;
+        Ok(Touch::Recorded(now))
+    }
+
+    /// Re-encrypt every key still under `previous_master_key` under the
+    /// current one
+    ///
+    /// Records already stamped with the current key version are skipped, so
+    /// an interrupted rotation can simply be run again. Unstamped records
+    /// that already decrypt with the current key only get the stamp.
+    pub async fn rotate(&self, previous_master_key: &MasterKey) -> Result<RotationCounts, KeyStoreError> {
+        let current_version = key_version(&self.master_key);
+        let mut counts = RotationCounts::default();
+        for record in self.pb.list_records(USER_KEYS, None).await? {
+            let id = record.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string();
+            let Ok(stored) = from_record(&record) else {
+                counts.failed += 1;
+                continue;
+            };
+            if stored.key_version == current_version {
+                counts.skipped += 1;
+                continue;
+            }
+
+            let key = if stored.key_version.is_empty() && self.decrypt(&stored.key).is_ok() {
+                stored.key.clone()
+            } else {
+                match stored.key.rotate(&self.user_id, &previous_master_key.bytes(), &self.master_key) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        warn!(
+                            "Stored {} key {} of {} decrypts with neither master key: {}",
+                            stored.key.service, stored.key.key_id, self.user_id, e
+                        );
+                        counts.failed += 1;
+                        continue;
+                    }
+                }
+            };
+            let value = self.decrypt(&key).unwrap_or_default();
+            let rotated = StoredKey {
+                fingerprint: fingerprint(&self.master_key, &value),
+                key,
+                key_version: current_version.clone(),
+                ..stored.clone()
+            };
+            match self.pb.update_record(USER_KEYS, &id, &to_record(&rotated)).await {
+                Ok(_) => {
+                    counts.rotated += 1;
+                    counts.changes.push(KeyChange {
+                        service: rotated.key.service,
+                        key_id: rotated.key.key_id,
+                        fingerprint_before: stored.fingerprint,
+                        fingerprint_after: rotated.fingerprint,
+                    });
+                }
+                Err(e) => {
+                    warn!("Failed to write rotated key record {} of {}: {}", id, self.user_id, e);
+                    counts.failed += 1;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    /// The plaintext of a key read from this repository
+    pub fn decrypt(&self, key: &EncryptedApiKey) -> Result<String, CryptoError> {
+        key.decrypt_key_for_user(&self.user_id, &self.master_key)
+    }
+
+    /// The key stored under `service` and `key_id`
+    pub async fn get(&self, service: &str, key_id: &str) -> Result<Option<StoredKey>, KeyStoreError> {
+        Ok(self
+            .service_keys(service)
+            .await?
+            .into_iter()
+            .map(|(_, stored)| stored)
+            .find(|stored| stored.key.key_id == key_id))
+    }
+
+    async fn find_record(&self, service: &str, key_id: &str) -> Result<Option<Value>, KeyStoreError> {
+        let filter = format!("service = {} && key_id = {}", quote(service), quote(key_id));
+        let records = self.pb.list_records(USER_KEYS, Some(&filter)).await?;
+        Ok(records.into_iter().next())
+    }
+
+    /// Record id of the key stored under `service` and `key_id`
+    async fn find(&self, service: &str, key_id: &str) -> Result<Option<String>, KeyStoreError> {
+        Ok(self
+            .find_record(service, key_id)
+            .await?
+            .and_then(|record| record.get("id")?.as_str().map(str::to_string)))
+    }
+}
+
+fn to_record(stored: &StoredKey) -> Value {
+    let key = &stored.key;
+    let date = |at: Option<DateTime<Utc>>| at.map(|at| at.to_rfc3339()).unwrap_or_default();
+    json!({
+        "service": key.service,
+        "key_id": key.key_id,
+        "ciphertext": STANDARD.encode(&key.encrypted_key.ciphertext),
+        "nonce": STANDARD.encode(&key.encrypted_key.nonce),
+        "created_at": key.created_at.to_rfc3339(),
+        "expires_at": date(key.expires_at),
+        "fingerprint": stored.fingerprint,
+        "masked_hint": stored.masked_hint,
+        "last_used_at": date(stored.last_used_at),
+        "expiry_reminded_at": date(stored.expiry_reminded_at),
+        "key_version": stored.key_version,
+        "is_default": stored.is_default,
+    })
+}
+
+/// Make sure exactly one of a service's `keys` is marked default
+///
+/// Records written before defaults existed have none marked; the key named
+/// `default` stands in, else the first.
+fn mark_default(keys: &mut [&mut StoredKey]) {
+    if keys.is_empty() || keys.iter().any(|stored| stored.is_default) {
+        return;
+    }
+    let index = keys.iter().position(|stored| stored.key.key_id == "default").unwrap_or(0);
+    keys[index].is_default = true;
+}
+
+fn from_record(record: &Value) -> Result<StoredKey, KeyStoreError> {
+    let id = record.get("id").and_then(|id| id.as_str()).unwrap_or_default();
+    let malformed = || KeyStoreError::Malformed(id.to_string());
+    let text = |field: &str| record.get(field).and_then(|value| value.as_str()).unwrap_or_default();
+    let bytes = |field: &str| STANDARD.decode(text(field)).map_err(|_| malformed());
+    let date = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|at| at.with_timezone(&Utc))
+            .map_err(|_| malformed())
+    };
+
+    let optional_date = |field: &str| match text(field) {
+        "" => Ok(None),
+        at => date(at).map(Some),
+    };
+
+    Ok(StoredKey {
+        key: EncryptedApiKey {
+            service: text("service").to_string(),
+            key_id: text("key_id").to_string(),
+            encrypted_key: CiphertextBundle::new(bytes("ciphertext")?, bytes("nonce")?).map_err(|_| malformed())?,
+            created_at: date(text("created_at"))?,
+            expires_at: optional_date("expires_at")?,
+        },
+        fingerprint: text("fingerprint").to_string(),
+        masked_hint: text("masked_hint").to_string(),
+        last_used_at: optional_date("last_used_at")?,
+        expiry_reminded_at: optional_date("expiry_reminded_at")?,
+        key_version: text("key_version").to_string(),
+        is_default: record.get("is_default").and_then(|value| value.as_bool()).unwrap_or(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_master_key_must_be_base64_of_32_bytes() {
+        let mut security = crate::test_support::test_config("http://127.0.0.1:1").security;
+        let master_key = MasterKey::from_config(&security).unwrap();
+        assert_eq!(master_key.bytes(), *b"test-master-key-32-bytes-long!!!");
+        assert!(!format!("{:?}", master_key).contains("test-master"));
+
+        for malformed in ["", "test-master-key", "dGVzdA=="] {
+            security.master_key = malformed.to_string();
+            assert!(matches!(
+                MasterKey::from_config(&security),
+                Err(ConfigError::InvalidMasterKey("MASTER_KEY"))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let key = StoredKey {
+            key: EncryptedApiKey::new_for_user(
+                "alice",
+                "fathom".to_string(),
+                "default".to_string(),
+                "sk-alice",
+                &[7u8; 32],
+                Some(Utc::now()),
+            ),
+            fingerprint: fingerprint(&[7u8; 32], "sk-alice"),
+            masked_hint: masked_hint("sk-alice"),
+            last_used_at: None,
+            expiry_reminded_at: Some(Utc::now()),
+            key_version: key_version(&[7u8; 32]),
+            is_default: true,
+        };
+        let mut record = to_record(&key);
+        assert!(!record.to_string().contains("sk-alice"));
+
+        record["id"] = json!("rec1");
+        let read = from_record(&record).unwrap();
+        assert_eq!(read.key.decrypt_key_for_user("alice", &[7u8; 32]).unwrap(), "sk-alice");
+        assert_eq!(
+            read.key.expires_at.map(|at| at.timestamp()),
+            key.key.expires_at.map(|at| at.timestamp())
+        );
+        assert_eq!(read.fingerprint, key.fingerprint);
+        assert_eq!(read.last_used_at, None);
+        assert!(read.expiry_reminded_at.is_some());
+        assert_eq!(read.key_version, key.key_version);
+        assert!(read.is_default);
+
+        record["nonce"] = json!("c2hvcnQ=");
+        assert!(matches!(from_record(&record), Err(KeyStoreError::Malformed(id)) if id == "rec1"));
+    }
+
+    #[test]
+    fn test_hints_and_fingerprints_hide_the_value() {
+        assert_eq!(masked_hint("fathom-key-0123456789abcd"), "••••abcd");
+        assert_eq!(masked_hint("short-key"), "••••");
+
+        let print = fingerprint(&[1u8; 32], "fathom-key-0123456789abcd");
+        assert_eq!(print.len(), 16);
+        assert_eq!(print, fingerprint(&[1u8; 32], "fathom-key-0123456789abcd"));
+        assert_ne!(print, fingerprint(&[2u8; 32], "fathom-key-0123456789abcd"));
+        assert_ne!(print, fingerprint(&[1u8; 32], "fathom-key-0123456789abce"));
+    }
+}
@@ -0,0 +1,491 @@
+//! Re-encrypting stored API keys after a master key rotation
+//!
+//! Once `MASTER_KEY` has changed, keys stored under the old one no longer
+//! decrypt. `POST /api/admin/keys/rotate` re-encrypts every user's keys from
+//! the previous master key, given in the body or as `MASTER_KEY_PREVIOUS`,
+//! to the current one, a few users at a time, along with the webhook secrets
+//! on their settings. Records remember the key version they are under, so
+//! the rotation can be run again after an interruption or a partial failure
+//! and only touches what is left.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::post,
+    Router,
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    auth::auth_error,
+    extractors::AdminUser,
+    key_audit::{KeyAction, KeyAuditEntry},
+    key_repository::{self, KeyRepository, MasterKey, RotationCounts},
+    sessions::SessionOrigin,
+    settings,
+    AppState,
+};
+use common::ErrorCode;
+
+/// Users whose keys are rotated at the same time
+pub const ROTATION_CONCURRENCY: usize = 4;
+
+/// Body of `POST /api/admin/keys/rotate`
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateKeysRequest {
+    /// The old master key, `MASTER_KEY_PREVIOUS` unless given
+    #[serde(default)]
+    pub previous_master_key: Option<String>,
+}
+
+/// What a rotation did with one user's keys
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRotation {
+    pub user_id: String,
+    pub rotated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Why the user's keys couldn't be read at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotationReport {
+    pub rotated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Per user, ordered by user id
+    pub users: Vec<UserRotation>,
+}
+
+/// Admin routes for stored keys, nested under `/api`
+pub fn router() -> Router<AppState> {
+    Router::new().route("/admin/keys/rotate", post(rotate_keys))
+}
+
+/// POST /api/admin/keys/rotate - Re-encrypt stored keys under the current master key (admin only)
+async fn rotate_keys(
+    AdminUser(admin): AdminUser,
+    State(state): State<AppState>,
+    origin: SessionOrigin,
+    body: Option<Json<RotateKeysRequest>>,
+) -> Result<Response, Response> {
+    let AppState {
+        global_pb,
+        pb_manager,
+        config,
+        master_key: current,
+        key_audit,
+        ..
+    } = state;
+    let Json(request) = body.unwrap_or_default();
+    let Some(previous) = request
+        .previous_master_key
+        .or_else(|| config.security.master_key_previous.clone())
+        .filter(|key| !key.is_empty())
+    else {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "No previous master key given and MASTER_KEY_PREVIOUS is not set",
+        ));
+    };
+    let Ok(previous) = MasterKey::parse(&previous) else {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "The previous master key must be 32 bytes encoded as base64",
+        ));
+    };
+    if previous == *current {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "The previous master key is the current one",
+        ));
+    }
+
+    let owners = key_repository::key_owners(&global_pb, &pb_manager)
+        .await
+        .map_err(|e| {
+            warn!("Key rotation could not list users: {}", e);
+            auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Users could not be listed",
+            )
+        })?;
+    let webhook_secrets = settings::rotate_webhook_secrets(&global_pb, &previous, &current)
+        .await
+        .map_err(|e| {
+            warn!("Key rotation could not read webhook secrets: {}", e);
+            auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Settings could not be read",
+            )
+        })?;
+    let user_ids: Vec<String> = owners
+        .iter()
+        .filter_map(|user| user["id"].as_str().map(str::to_string))
+        .collect();
+
+    let mut users: Vec<UserRotation> = stream::iter(user_ids)
+        .map(|user_id| {
+            let (pb_manager, current, key_audit) = (&pb_manager, &current, &key_audit);
+            let (admin_id, origin) = (&admin.id, &origin);
+            async move {
+                let rotated = match KeyRepository::open(pb_manager, &user_id, current).await {
+                    Ok(repository) => repository.rotate(&previous).await,
+                    Err(e) => Err(e),
+                };
+                let (counts, error) = match rotated {
+                    Ok(counts) => {
+                        for change in &counts.changes {
+                            let entry = KeyAuditEntry::new(
+                                KeyAction::Rotated,
+                                &user_id,
+                                &change.service,
+                                &change.key_id,
+                                origin,
+                            )
+                            .actor(admin_id)
+                            .fingerprints(
+                                Some(&change.fingerprint_before),
+                                Some(&change.fingerprint_after),
+                            );
+                            key_audit.record(entry);
+                        }
+                        (counts, None)
+                    }
+                    Err(e) => {
+                        warn!("Key rotation could not read the keys of {}: {}", user_id, e);
+                        (RotationCounts::default(), Some(e.to_string()))
+                    }
+                };
+                UserRotation {
+                    user_id,
+                    rotated: counts.rotated,
+                    skipped: counts.skipped,
+                    failed: counts.failed,
+                    error,
+                }
+            }
+        })
+        .buffer_unordered(ROTATION_CONCURRENCY)
+        .collect()
+        .await;
+    for (user_id, counts) in webhook_secrets {
+        for change in &counts.changes {
+            let entry = KeyAuditEntry::new(KeyAction::Rotated, &user_id, &change.service, &change.key_id, &origin)
+                .actor(&admin.id)
+                .fingerprints(Some(&change.fingerprint_before), Some(&change.fingerprint_after));
+            key_audit.record(entry);
+        }
+        match users.iter_mut().find(|user| user.user_id == user_id) {
+            Some(user) => {
+                user.rotated += counts.rotated;
+                user.skipped += counts.skipped;
+                user.failed += counts.failed;
+            }
+            None => users.push(UserRotation {
+                user_id,
+                rotated: counts.rotated,
+                skipped: counts.skipped,
+                failed: counts.failed,
+                error: None,
+            }),
+        }
+    }
+    users.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+
+    let report = RotationReport {
+        rotated: users.iter().map(|user| user.rotated).sum(),
+        skipped: users.iter().map(|user| user.skipped).sum(),
+        failed: users
+            .iter()
+            .map(|user| user.failed + usize::from(user.error.is_some()))
+            .sum(),
+        users,
+    };
+    info!(
+        target: "audit",
+        admin_id = %admin.id,
+        rotated = report.rotated,
+        skipped = report.skipped,
+        failed = report.failed,
+        "Stored API keys rotated to the current master key"
+    );
+    Ok((StatusCode::OK, Json(report)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        pocketbase_manager::PocketBaseManager,
+        test_support::{fake_pocketbase, mock_app_state, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const OLD_MASTER_KEY: &str = "b2xkLW1hc3Rlci1rZXktMzItYnl0ZXMtbG9uZyEhISE=";
+    const LOST_MASTER_KEY: &str = "bG9zdC1tYXN0ZXIta2V5LTMyLWJ5dGVzLWxvbmchISE=";
+
+    async fn setup(dir: &std::path::Path) -> (AppState, Vec<String>) {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let mut config = test_config(&global_url);
+        config.security.master_key_previous = Some(OLD_MASTER_KEY.to_string());
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(config, manager);
+
+        let mut user_ids = Vec::new();
+        for email in ["alice@example.com", "bob@example.com"] {
+            let record = json!({ "email": email, "password": "password" });
+            let user = state
+                .global_pb
+                .create_record("users", &record)
+                .await
+                .unwrap();
+            let user_id = user["id"].as_str().unwrap().to_string();
+            let data_dir = state.pb_manager.data_dir(&user_id);
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+            user_ids.push(user_id);
+        }
+        (state, user_ids)
+    }
+
+    async fn repository(state: &AppState, user_id: &str, master_key: &str) -> KeyRepository {
+        let master_key = MasterKey::parse(master_key).unwrap();
+        KeyRepository::open(&state.pb_manager, user_id, &master_key)
+            .await
+            .unwrap()
+    }
+
+    async fn rotate(state: &AppState, token: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/admin/keys/rotate")
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn counts(report: &Value, user: usize) -> (u64, u64, u64) {
+        let user = &report["users"][user];
+        (
+            user["rotated"].as_u64().unwrap(),
+            user["skipped"].as_u64().unwrap(),
+            user["failed"].as_u64().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_rotation_is_resumable_and_reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, users) = setup(dir.path()).await;
+        let (alice, bob) = (&users[0], &users[1]);
+
+        // Alice has two keys under the old master key and one already rotated
+        let old = repository(&state, alice, OLD_MASTER_KEY).await;
+        old.put("fathom", "default", "alice-fathom", None)
+            .await
+            .unwrap();
+        old.put("loom", "default", "alice-loom", None)
+            .await
+            .unwrap();
+        let current = repository(&state, alice, &state.config.security.master_key).await;
+        current
+            .put("loom", "spare", "alice-spare", None)
+            .await
+            .unwrap();
+        // Bob has one old key and one under a master key nobody remembers
+        repository(&state, bob, OLD_MASTER_KEY)
+            .await
+            .put("fathom", "default", "bob-fathom", None)
+            .await
+            .unwrap();
+        repository(&state, bob, LOST_MASTER_KEY)
+            .await
+            .put("loom", "default", "bob-loom", None)
+            .await
+            .unwrap();
+
+        let (status, report) = rotate(&state, "valid-admin", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(counts(&report, 0), (2, 1, 0));
+        assert_eq!(counts(&report, 1), (1, 0, 1));
+        assert_eq!(
+            (report["rotated"].as_u64(), report["failed"].as_u64()),
+            (Some(3), Some(1))
+        );
+
+        for (service, key_id, value) in [
+            ("fathom", "default", "alice-fathom"),
+            ("loom", "default", "alice-loom"),
+            ("loom", "spare", "alice-spare"),
+        ] {
+            let stored = current.get(service, key_id).await.unwrap().unwrap();
+            assert_eq!(current.decrypt(&stored.key).unwrap(), value);
+            assert_eq!(
+                stored.fingerprint,
+                key_repository::fingerprint(&state.master_key.bytes(), value)
+            );
+        }
+
+        // Each rotated key is in its owner's history, done by the admin
+        state.key_audit.flush().await;
+        let entries = state
+            .key_audit
+            .store()
+            .list_records("key_audit", None)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .iter()
+            .all(|entry| entry["action"] == "rotated" && entry["actor_id"] == "admin"));
+        let bob_entry = entries
+            .iter()
+            .find(|entry| entry["user_id"] == *bob)
+            .unwrap();
+        assert_eq!(bob_entry["key_id"], "default");
+        assert_eq!(
+            bob_entry["fingerprint_after"],
+            key_repository::fingerprint(&state.master_key.bytes(), "bob-fathom")
+        );
+        assert_ne!(
+            bob_entry["fingerprint_before"],
+            bob_entry["fingerprint_after"]
+        );
+
+        // Running it again only retries what failed
+        let (_, report) = rotate(&state, "valid-admin", json!({})).await;
+        assert_eq!(counts(&report, 0), (0, 3, 0));
+        assert_eq!(counts(&report, 1), (0, 1, 1));
+
+        // With the right old key named in the body, the last one rotates too
+        let (_, report) = rotate(
+            &state,
+            "valid-admin",
+            json!({ "previous_master_key": LOST_MASTER_KEY }),
+        )
+        .await;
+        assert_eq!(counts(&report, 1), (1, 1, 0));
+        let bob_current = repository(&state, bob, &state.config.security.master_key).await;
+        let stored = bob_current.get("loom", "default").await.unwrap().unwrap();
+        assert_eq!(bob_current.decrypt(&stored.key).unwrap(), "bob-loom");
+
+        state.pb_manager.stop_user_instance(alice).await.unwrap();
+        state.pb_manager.stop_user_instance(bob).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_secrets_are_rotated_too() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, users) = setup(dir.path()).await;
+
+        let old = MasterKey::parse(OLD_MASTER_KEY).unwrap();
+        let secret = common::crypto::EncryptedApiKey::new_for_user(
+            "carol",
+            settings::WEBHOOK_SECRET_SERVICE.to_string(),
+            settings::WEBHOOK_SECRET_KEY_ID.to_string(),
+            "whsec-carol-0123456789",
+            &old.bytes(),
+            None,
+        );
+        let record = json!({
+            "user_id": "carol",
+            "fathom_workspace_id": "ws-carol",
+            "fathom_webhook_secret": secret,
+        });
+        state
+            .global_pb
+            .create_record(settings::USER_SETTINGS, &record)
+            .await
+            .unwrap();
+
+        let (status, report) = rotate(&state, "valid-admin", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let carol = report["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .position(|user| user["user_id"] == "carol")
+            .unwrap();
+        assert_eq!(counts(&report, carol), (1, 0, 0));
+        let owner = settings::workspace_owner(&state.global_pb, "ws-carol")
+            .await
+            .unwrap()
+            .unwrap();
+        let rotated = owner.webhook_secret.unwrap();
+        assert_eq!(
+            rotated
+                .decrypt_key_for_user("carol", &state.master_key.bytes())
+                .unwrap(),
+            "whsec-carol-0123456789"
+        );
+
+        let (_, report) = rotate(&state, "valid-admin", json!({})).await;
+        assert_eq!(counts(&report, carol), (0, 1, 0));
+
+        for user_id in &users {
+            let _ = state.pb_manager.stop_user_instance(user_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation_needs_an_admin_and_a_previous_key() {
+        let state = mock_app_state().await;
+
+        let (status, _) = rotate(
+            &state,
+            "valid-alice",
+            json!({ "previous_master_key": OLD_MASTER_KEY }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = rotate(&state, "valid-admin", json!({})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+
+        let current = state.config.security.master_key.clone();
+        for previous in [current.as_str(), "old-master-key"] {
+            let (status, body) = rotate(
+                &state,
+                "valid-admin",
+                json!({ "previous_master_key": previous }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["code"], "validation");
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Which users have keys configured, for support
+//!
+//! `GET /internal/keys/summary` lets whoever runs the worker check a user's
+//! setup with the internal token instead of backend admin access. It lists,
+//! per user, what is stored for each service: key ids, fingerprints, expiry
+//! and last use. Values, masked hints and ciphertext never appear. Users are
+//! paged by id and only the instances of the page's users are opened, a few
+//! at a time.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+use super::{
+    auth::auth_error,
+    internal::InternalCaller,
+    key_repository::{self, KeyRepository, MasterKey, StoredKey},
+};
+use crate::{global_pb::GlobalPb, pocketbase_manager::PocketBaseManager};
+use common::ErrorCode;
+
+/// Users whose instances are read at the same time
+pub const SUMMARY_CONCURRENCY: usize = 4;
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct KeySummaryQuery {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    /// Only this user
+    pub user_id: Option<String>,
+}
+
+/// What support may see of one stored key
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyMetadata {
+    pub service: String,
+    pub key_id: String,
+    pub is_default: bool,
+    pub fingerprint: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expired: bool,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<StoredKey> for KeyMetadata {
+    fn from(stored: StoredKey) -> Self {
+        Self {
+            expired: stored.key.is_expired_at(Utc::now()),
+            service: stored.key.service,
+            key_id: stored.key.key_id,
+            is_default: stored.is_default,
+            fingerprint: stored.fingerprint,
+            expires_at: stored.key.expires_at,
+            last_used_at: stored.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserKeys {
+    pub user_id: String,
+    /// Services with at least one key, in order
+    pub services: Vec<String>,
+    /// Ordered by service and key id
+    pub keys: Vec<KeyMetadata>,
+    /// Why the user's keys couldn't be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeySummaryPage {
+    /// Ordered by user id
+    pub users: Vec<UserKeys>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total_items: u64,
+}
+
+/// GET /internal/keys/summary - Key metadata per user, for support
+pub async fn list_key_summaries(
+    _caller: InternalCaller,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    Query(query): Query<KeySummaryQuery>,
+) -> Result<Response, Response> {
+    let owners = key_repository::key_owners(&global_pb, &pb_manager)
+        .await
+        .map_err(|e| {
+            warn!("Key summary could not list users: {}", e);
+            auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Users could not be listed",
+            )
+        })?;
+    let wanted = query.user_id.as_deref().filter(|id| !id.is_empty());
+    let mut user_ids: Vec<String> = owners
+        .iter()
+        .filter_map(|user| user["id"].as_str())
+        .filter(|id| wanted.is_none_or(|wanted| *id == wanted))
+        .map(str::to_string)
+        .collect();
+    user_ids.sort();
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let total_items = user_ids.len() as u64;
+    let on_page: Vec<String> = user_ids
+        .into_iter()
+        .skip((page as usize - 1) * per_page as usize)
+        .take(per_page as usize)
+        .collect();
+
+    let mut users: Vec<UserKeys> = stream::iter(on_page)
+        .map(|user_id| {
+            let (pb_manager, master_key) = (&pb_manager, &master_key);
+            async move {
+                let listed = match KeyRepository::open(pb_manager, &user_id, master_key).await {
+                    Ok(repository) => repository.list().await,
+                    Err(e) => Err(e),
+                };
+                match listed {
+                    Ok(keys) => {
+                        let mut services: Vec<String> = keys
+                            .iter()
+                            .map(|stored| stored.key.service.clone())
+                            .collect();
+                        services.dedup();
+                        UserKeys {
+                            user_id,
+                            services,
+                            keys: keys.into_iter().map(KeyMetadata::from).collect(),
+                            error: None,
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Key summary could not read the keys of {}: {}", user_id, e);
+                        UserKeys {
+                            user_id,
+                            services: Vec::new(),
+                            keys: Vec::new(),
+                            error: Some("Stored keys could not be read".to_string()),
+                        }
+                    }
+                }
+            }
+        })
+        .buffer_unordered(SUMMARY_CONCURRENCY)
+        .collect()
+        .await;
+    users.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+
+    Ok((
+        StatusCode::OK,
+        Json(KeySummaryPage {
+            users,
+            page,
+            per_page,
+            total_items,
+        }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, AppState},
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// State with three users holding keys, by id, and one without an instance
+    async fn setup(dir: &std::path::Path) -> (AppState, Vec<String>) {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let mut user_ids = Vec::new();
+        for email in [
+            "alice@example.com",
+            "bob@example.com",
+            "carol@example.com",
+            "dave@example.com",
+        ] {
+            let record = json!({ "email": email, "password": "password" });
+            let user = state
+                .global_pb
+                .create_record("users", &record)
+                .await
+                .unwrap();
+            let user_id = user["id"].as_str().unwrap().to_string();
+            if email.starts_with("dave") {
+                continue;
+            }
+            let data_dir = state.pb_manager.data_dir(&user_id);
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+            let repository = KeyRepository::open(&state.pb_manager, &user_id, &state.master_key)
+                .await
+                .unwrap();
+            repository
+                .put("fathom", "default", "fathom-secret-0123456789", None)
+                .await
+                .unwrap();
+            if email.starts_with("alice") {
+                let soon = Utc::now() + chrono::Duration::days(3);
+                repository
+                    .put("loom", "default", "loom-secret-0123456789", Some(soon))
+                    .await
+                    .unwrap();
+            }
+            user_ids.push(user_id);
+        }
+        user_ids.sort();
+        (state, user_ids)
+    }
+
+    async fn summary(state: &AppState, query: &str, token: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder().uri(format!("/internal/keys/summary{}", query));
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn listed_ids(body: &Value) -> Vec<&str> {
+        body["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["user_id"].as_str().unwrap())
+            .collect()
+    }
+
+    async fn stop(state: &AppState, users: &[String]) {
+        for user_id in users {
+            let _ = state.pb_manager.stop_user_instance(user_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summaries_page_through_users_with_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, users) = setup(dir.path()).await;
+
+        let (status, body) = summary(&state, "?per_page=2", Some("test-internal-token")).await;
+        assert_eq!(status, StatusCode::OK);
+        // Dave never had an instance, so isn't counted
+        assert_eq!(body["total_items"], 3);
+        assert_eq!(listed_ids(&body), [users[0].as_str(), users[1].as_str()]);
+        let (_, body) = summary(&state, "?per_page=2&page=2", Some("test-internal-token")).await;
+        assert_eq!(listed_ids(&body), [users[2].as_str()]);
+        let (_, body) = summary(&state, "?per_page=2&page=3", Some("test-internal-token")).await;
+        assert!(listed_ids(&body).is_empty());
+
+        stop(&state, &users).await;
+    }
+
+    #[tokio::test]
+    async fn test_summary_filters_by_user_and_holds_no_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, users) = setup(dir.path()).await;
+        let alice = state.global_pb.list_records("users", None).await.unwrap()[0]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let (status, body) = summary(
+            &state,
+            &format!("?user_id={}", alice),
+            Some("test-internal-token"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total_items"], 1);
+        let user = &body["users"][0];
+        assert_eq!(user["user_id"], alice.as_str());
+        assert_eq!(user["services"], json!(["fathom", "loom"]));
+        let loom = &user["keys"][1];
+        assert_eq!(
+            (&loom["key_id"], &loom["is_default"], &loom["expired"]),
+            (&json!("default"), &json!(true), &json!(false))
+        );
+        assert!(loom["expires_at"].is_string());
+        assert_eq!(loom["last_used_at"], Value::Null);
+        assert_eq!(loom["fingerprint"].as_str().unwrap().len(), 16);
+
+        let text = body.to_string();
+        assert!(!text.contains("0123456789"));
+        for field in [
+            "masked_hint",
+            "ciphertext",
+            "nonce",
+            "encrypted_key",
+            "value",
+        ] {
+            assert!(!text.contains(&format!("\"{}\"", field)), "{}", field);
+        }
+
+        let (_, body) = summary(&state, "?user_id=nobody", Some("test-internal-token")).await;
+        assert_eq!(body["total_items"], 0);
+
+        for token in [None, Some("wrong-token"), Some("valid-admin")] {
+            let (status, body) = summary(&state, "", token).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"], "invalid_internal_token");
+        }
+
+        stop(&state, &users).await;
+    }
+}
@@ -0,0 +1,353 @@
+//! Live checks of API keys against the services they belong to
+//!
+//! `POST /api/keys/:service/validate` makes the cheapest authenticated call
+//! each service offers (Fathom: list one meeting, Loom: the current user)
+//! with either a stored key, decrypted only for the call, or a candidate
+//! `value` the user hasn't saved yet. The key never appears in the response
+//! or the logs, and neither does anything the service answers, since error
+//! bodies may quote the credential back.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::{
+    auth::auth_error,
+    error_report::with_cause,
+    extractors::AuthUser,
+    key_repository::MasterKey,
+    keys::{repository, store_error},
+    rate_limit::RateLimits,
+};
+use crate::{
+    config::{Config, IntegrationsConfig},
+    pocketbase_manager::PocketBaseManager,
+};
+use common::{AppError, ErrorCode, ServiceKind};
+
+/// Body of `POST /api/keys/:service/validate`; empty to check the default key
+#[derive(Debug, Default, Deserialize)]
+pub struct ValidateKeyRequest {
+    /// Stored key to check, the service's default unless given
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// Candidate value to check instead of a stored key
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyValidation {
+    pub valid: bool,
+    pub detail: String,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Ask `service` whether it accepts `value`
+///
+/// Returns whether it did and a description safe to show the user.
+pub async fn check_key(
+    integrations: &IntegrationsConfig,
+    service: ServiceKind,
+    value: &str,
+) -> (bool, String) {
+    let client = reqwest::Client::new();
+    let request = match service {
+        ServiceKind::Fathom => client
+            .get(format!(
+                "{}/meetings",
+                integrations.fathom_api_url.trim_end_matches('/')
+            ))
+            .query(&[("limit", "1")])
+            .header("X-Api-Key", value),
+        ServiceKind::Loom => client
+            .get(format!(
+                "{}/users/me",
+                integrations.loom_api_url.trim_end_matches('/')
+            ))
+            .bearer_auth(value),
+    };
+
+    let name = service.display_name();
+    match request
+        .timeout(integrations.key_validation_timeout)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => {
+            (true, format!("{} accepted the key", name))
+        }
+        Ok(response) => {
+            let status = response.status();
+            let detail = match status {
+                reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                    format!(
+                        "{} rejected the key ({}); it may be expired, revoked or incomplete",
+                        name,
+                        status.as_u16()
+                    )
+                }
+                reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    format!("{} is rate limiting requests; try again later", name)
+                }
+                _ => format!(
+                    "{} answered {} and the key could not be checked",
+                    name,
+                    status.as_u16()
+                ),
+            };
+            (false, detail)
+        }
+        Err(e) if e.is_timeout() => (
+            false,
+            format!(
+                "{} did not answer within {} seconds",
+                name,
+                integrations.key_validation_timeout.as_secs_f32()
+            ),
+        ),
+        Err(e) => {
+            // reqwest errors name the URL, never the headers carrying the key
+            warn!("Key validation request to {} failed: {}", name, e);
+            (false, format!("{} could not be reached", name))
+        }
+    }
+}
+
+/// POST /api/keys/:service/validate - Check a stored or candidate key
+pub async fn validate_key(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+    Path(service): Path<String>,
+    body: Option<Json<ValidateKeyRequest>>,
+) -> Result<Response, Response> {
+    let Some(service) = ServiceKind::parse(&service) else {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            "Keys can only be validated for fathom or loom",
+        ));
+    };
+    let Json(request) = body.unwrap_or_default();
+
+    let value = match request.value {
+        Some(value) if value.trim().is_empty() => {
+            return Err(auth_error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorCode::Validation,
+                "API key value is required",
+            ));
+        }
+        Some(value) => value.trim().to_string(),
+        None => {
+            let repository = repository(&pb_manager, &master_key, &user).await?;
+            let stored = repository
+                .resolve(service.as_str(), request.key_id.as_deref())
+                .await
+                .map_err(store_error)?;
+            let Some(stored) = stored else {
+                return Err(auth_error(
+                    StatusCode::NOT_FOUND,
+                    ErrorCode::NotFound,
+                    "No such API key",
+                ));
+            };
+            repository.decrypt(&stored.key).map_err(|e| {
+                warn!(
+                    "Stored {} key {} of {} does not decrypt: {}",
+                    service.as_str(),
+                    stored.key.key_id,
+                    user.id,
+                    e
+                );
+                with_cause(
+                    auth_error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorCode::Internal,
+                        "Stored API key could not be read",
+                    ),
+                    AppError::Internal(format!("Stored key does not decrypt: {}", e)),
+                )
+            })?
+        }
+    };
+
+    let bucket = format!("{}:{}", user.id, service.as_str());
+    if !rate_limits.key_validation_per_service.check(&bucket).await {
+        return Err(auth_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::RateLimited,
+            "Too many key validations; try again later",
+        ));
+    }
+
+    let (valid, detail) = check_key(&config.integrations, service, &value).await;
+    info!(
+        "Validated {} key for {}: valid={}",
+        service.as_str(),
+        user.id,
+        valid
+    );
+    Ok((
+        StatusCode::OK,
+        Json(KeyValidation {
+            valid,
+            detail,
+            checked_at: Utc::now(),
+        }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, AppState},
+        test_support::{mock_app_state_with, spawn_server},
+    };
+    use axum::{body::Body, http::HeaderMap, http::Request, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const GOOD_KEY: &str = "fathom-good-0123456789";
+
+    /// Fathom and Loom lookalikes accepting only [`GOOD_KEY`]; Loom hangs
+    /// for keys starting with `slow`
+    async fn mock_services() -> String {
+        async fn meetings(headers: HeaderMap) -> StatusCode {
+            match headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+            {
+                Some(GOOD_KEY) => StatusCode::OK,
+                _ => StatusCode::UNAUTHORIZED,
+            }
+        }
+        async fn me(headers: HeaderMap) -> Response {
+            let token = headers
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if token.starts_with("Bearer slow") {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            // Like some real services, echo the credential in the error
+            (StatusCode::UNAUTHORIZED, format!("bad token {}", token)).into_response()
+        }
+        spawn_server(
+            Router::new()
+                .route("/meetings", get(meetings))
+                .route("/users/me", get(me)),
+        )
+        .await
+    }
+
+    async fn setup() -> AppState {
+        let services = mock_services().await;
+        mock_app_state_with(|config| {
+            config.integrations.fathom_api_url = services.clone();
+            config.integrations.loom_api_url = services;
+            config.integrations.key_validation_max = 3;
+        })
+        .await
+    }
+
+    async fn validate(state: &AppState, service: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/api/keys/{}/validate", service))
+            .header("authorization", "Bearer valid-alice")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_valid_and_rejected_keys() {
+        let state = setup().await;
+
+        let (status, body) = validate(&state, "fathom", json!({ "value": GOOD_KEY })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+        assert!(body["checked_at"].is_string());
+
+        let (status, body) = validate(&state, "fathom", json!({ "value": "fathom-typo" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], false);
+        assert!(body["detail"].as_str().unwrap().contains("401"));
+
+        // Nothing the service echoes back reaches the client
+        let (_, body) = validate(&state, "loom", json!({ "value": "loom-secret-value" })).await;
+        assert_eq!(body["valid"], false);
+        assert!(!body.to_string().contains("loom-secret-value"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_services_time_out() {
+        let state = setup().await;
+        let started = std::time::Instant::now();
+
+        let (status, body) = validate(&state, "loom", json!({ "value": "slow-key" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], false);
+        assert!(body["detail"].as_str().unwrap().contains("did not answer"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_service_and_blank_value() {
+        let state = setup().await;
+
+        let (status, body) = validate(&state, "zoom", json!({ "value": GOOD_KEY })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+
+        let (status, body) = validate(&state, "fathom", json!({ "value": "  " })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+    }
+
+    #[tokio::test]
+    async fn test_checks_are_rate_limited_per_service() {
+        let state = setup().await;
+        for _ in 0..3 {
+            assert_eq!(
+                validate(&state, "fathom", json!({ "value": GOOD_KEY }))
+                    .await
+                    .0,
+                StatusCode::OK
+            );
+        }
+        let (status, body) = validate(&state, "fathom", json!({ "value": GOOD_KEY })).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+
+        // Loom has its own budget
+        assert_eq!(
+            validate(&state, "loom", json!({ "value": "loom-key" }))
+                .await
+                .0,
+            StatusCode::OK
+        );
+    }
+}
@@ -1,79 +1,636 @@
 use axum::{
-    routing::{get, post, delete},
+    extract::{Path, State},
+    routing::{delete, get, patch},
     Router,
-    extract::State,
-    response::{Json, IntoResponse},
+    response::{Json, IntoResponse, Response},
     http::StatusCode,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::{info, error};
+use tracing::{error, info};
 
-use crate::config::Config;
-use common::crypto::{EncryptedApiKey, generate_master_key, encrypt, decrypt};
+use common::{validation::validate_api_key_format, ApiResponse, AppError, ErrorCode, ServiceKind};
 use crate::pocketbase_manager::PocketBaseManager;
+use super::{
+    audit::{AuditLogger, AuthEvent, AuthEventType},
+    auth::auth_error,
+    error_report::with_cause,
+    extractors::AuthUser,
+    key_audit::{KeyAction, KeyAuditEntry, KeyAuditLog},
+    key_repository::{KeyRepository, KeyStoreError, MasterKey, StoredKey},
+    sessions::SessionOrigin,
+};
+
+/// What the keys endpoints reveal about a stored key
+///
+/// Deliberately holds no ciphertext, nonce or value: enough to recognise a
+/// key (`fingerprint`, `masked_hint`) and to tell when it needs replacing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeySummary {
+    pub service: String,
+    pub key_id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whole days left before `expires_at`
+    pub days_until_expiry: Option<i64>,
+    pub expired: bool,
+    pub fingerprint: String,
+    pub masked_hint: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Used for `service` whenever no key is named
+    pub is_default: bool,
+}
+
+impl From<StoredKey> for KeySummary {
+    fn from(stored: StoredKey) -> Self {
+        let now = Utc::now();
+        Self {
+            days_until_expiry: stored.key.days_until_expiry(now),
+            expired: stored.key.is_expired_at(now),
+            service: stored.key.service,
+            key_id: stored.key.key_id,
+            created_at: stored.key.created_at,
+            expires_at: stored.key.expires_at,
+            fingerprint: stored.fingerprint,
+            masked_hint: stored.masked_hint,
+            last_used_at: stored.last_used_at,
+            is_default: stored.is_default,
+        }
+    }
+}
+
+/// Key id used when a `PUT /api/keys` doesn't name one
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Body of `PUT /api/keys`, carrying the plaintext value
+///
+/// The value is encrypted under the configured master key before it is
+/// stored and never appears in a response.
+#[derive(Debug, Deserialize)]
+pub struct PutKeyRequest {
+    pub service: ServiceKind,
+    #[serde(default)]
+    pub key_id: Option<String>,
+    pub value: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
 
+impl PutKeyRequest {
+    /// The key id and trimmed value, or why the request is invalid
+    fn validated(&self) -> Result<(&str, &str), &'static str> {
+        let value = self.value.trim();
+        let key_id = self.key_id.as_deref().map(str::trim).unwrap_or(DEFAULT_KEY_ID);
+        validate_api_key_format(key_id, value)?;
+        Ok((key_id, value))
+    }
+}
+
+/// The key a `DELETE /api/keys/:service/:key_id` removed
 #[derive(Debug, Serialize, Deserialize)]
-pub struct KeyEntry {
+pub struct DeletedKey {
     pub service: String,
     pub key_id: String,
-    pub encrypted_key: EncryptedApiKey,
 }
 
 /// Create router for keys management
 pub fn router() -> Router<crate::api::AppState> {
     Router::new()
         .route("/keys", get(get_keys).put(put_key))
+        .route("/keys/audit", get(super::key_audit::list_key_audit))
+        .route("/keys/:service/:key_id", delete(delete_key))
+        .route("/keys/:service/:key_id/default", patch(set_default_key))
+        .route(
+            "/keys/:service/validate",
+            axum::routing::post(super::key_validation::validate_key),
+        )
 }
 
-/// GET /api/keys - Retrieve encrypted API keys
-pub async fn get_keys(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>
-) -> impl IntoResponse {
-    info!("Retrieving API keys");
-    
-    // Dummy implementation, replace with actual logic fetching from storage
-    let keys = vec![
-        KeyEntry {
-            service: "pocketbase".to_string(),
-            key_id: "default-key-id".to_string(),
-            encrypted_key: EncryptedApiKey::new(
-                "pocketbase".to_string(),
-                "default-key-id".to_string(),
-                "fake-api-key",
-                &generate_master_key(),
-                None,
+/// The caller's key repository
+pub(super) async fn repository(
+    pb_manager: &PocketBaseManager,
+    master_key: &MasterKey,
+    user: &AuthUser,
+) -> Result<KeyRepository, Response> {
+    KeyRepository::open(pb_manager, &user.id, master_key)
+        .await
+        .map_err(store_error)
+}
+
+pub(super) fn store_error(e: KeyStoreError) -> Response {
+    error!("API key storage failed: {}", e);
+    match e {
+        KeyStoreError::Malformed(_) => with_cause(
+            auth_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                "Stored API keys could not be read",
             ),
-        }
-    ];
+            AppError::Database(e.to_string()),
+        ),
+        _ => auth_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::ServiceUnavailable,
+            "API key storage is temporarily unavailable",
+        ),
+    }
+}
+
+/// GET /api/keys - Summaries of the caller's API keys
+pub async fn get_keys(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    user: AuthUser,
+) -> Result<Response, Response> {
+    info!("Retrieving API keys for user: {}", user.id);
 
-    (StatusCode::OK, Json(keys))
+    let keys = repository(&pb_manager, &master_key, &user).await?.list().await.map_err(store_error)?;
+    let summaries: Vec<KeySummary> = keys.into_iter().map(KeySummary::from).collect();
+    Ok((StatusCode::OK, Json(summaries)).into_response())
 }
 
-/// PUT /api/keys - Add or update an encrypted API key
+/// PUT /api/keys - Add or replace one of the caller's API keys
 pub async fn put_key(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>,
-    Json(entry): Json<KeyEntry>
-) -> impl IntoResponse {
-    info!("Updating API key for service: {}", entry.service);
-
-    // Dummy implementation, replace with actual logic for storing key to secure storage
-    let encrypted_key = encrypt(&generate_master_key(), entry.encrypted_key.decrypt_key(&generate_master_key()).unwrap().as_bytes());
-
-    let stored_entry = KeyEntry {
-        service: entry.service.clone(),
-        key_id: entry.key_id.clone(),
-        encrypted_key: EncryptedApiKey {
-            service: entry.service.clone(),
-            key_id: entry.key_id.clone(),
-            encrypted_key,
-            created_at: chrono::Utc::now(),
-            expires_at: None,
-        }
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(key_audit): State<Arc<KeyAuditLog>>,
+    user: AuthUser,
+    origin: SessionOrigin,
+    Json(request): Json<PutKeyRequest>,
+) -> Result<Response, Response> {
+    let service = request.service.as_str();
+    info!("Updating API key for service {} for user: {}", service, user.id);
+
+    let (key_id, value) = request
+        .validated()
+        .map_err(|message| auth_error(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Validation, message))?;
+    let repository = repository(&pb_manager, &master_key, &user).await?;
+    let previous = repository.get(service, key_id).await.map_err(store_error)?;
+    let key = repository
+        .put(service, key_id, value, request.expires_at)
+        .await
+        .map_err(store_error)?;
+    info!("API key for service '{}' stored.", key.key.service);
+
+    let before = previous.as_ref().map(|previous| previous.fingerprint.as_str());
+    let action = if before.is_some() { KeyAction::Replaced } else { KeyAction::Created };
+    key_audit.record(
+        KeyAuditEntry::new(action, &user.id, service, key_id, &origin).fingerprints(before, Some(&key.fingerprint)),
+    );
+
+    Ok((StatusCode::OK, Json(KeySummary::from(key))).into_response())
+}
+
+/// DELETE /api/keys/:service/:key_id - Remove one of the caller's API keys
+///
+/// Only the caller's own repository is searched, so another user's key
+/// reads as not found.
+pub async fn delete_key(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(audit): State<Arc<AuditLogger>>,
+    State(key_audit): State<Arc<KeyAuditLog>>,
+    user: AuthUser,
+    origin: SessionOrigin,
+    Path((service, key_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<DeletedKey>>, Response> {
+    let repository = repository(&pb_manager, &master_key, &user).await?;
+    let previous = repository.get(&service, &key_id).await.map_err(store_error)?;
+    let deleted = repository.delete(&service, &key_id).await.map_err(store_error)?;
+
+    let event = AuthEvent::new(AuthEventType::ApiKeyDeleted, &origin)
+        .user(&user.id)
+        .email(&user.email);
+    if !deleted {
+        audit.record(event.failed("not_found"));
+        return Err(auth_error(StatusCode::NOT_FOUND, ErrorCode::NotFound, "No such API key"));
+    }
+    info!(target: "audit", user_id = %user.id, service = %service, key_id = %key_id, "API key deleted");
+    audit.record(event.detail(&format!("{}/{}", service, key_id)));
+    key_audit.record(
+        KeyAuditEntry::new(KeyAction::Deleted, &user.id, &service, &key_id, &origin)
+            .fingerprints(previous.as_ref().map(|previous| previous.fingerprint.as_str()), None),
+    );
+
+    Ok(Json(ApiResponse::success(DeletedKey { service, key_id })))
+}
+
+/// PATCH /api/keys/:service/:key_id/default - Make one of the caller's keys
+/// the default for its service
+///
+/// The previous default stays stored and can still be named explicitly.
+pub async fn set_default_key(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(key_audit): State<Arc<KeyAuditLog>>,
+    user: AuthUser,
+    origin: SessionOrigin,
+    Path((service, key_id)): Path<(String, String)>,
+) -> Result<Response, Response> {
+    let repository = repository(&pb_manager, &master_key, &user).await?;
+    let Some(key) = repository.set_default(&service, &key_id).await.map_err(store_error)? else {
+        return Err(auth_error(StatusCode::NOT_FOUND, ErrorCode::NotFound, "No such API key"));
+    };
+    info!(target: "audit", user_id = %user.id, service = %service, key_id = %key_id, "Default API key changed");
+    key_audit.record(
+        KeyAuditEntry::new(KeyAction::MadeDefault, &user.id, &service, &key_id, &origin)
+            .fingerprints(None, Some(&key.fingerprint)),
+    );
+
+    Ok((StatusCode::OK, Json(KeySummary::from(key))).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{
+            audit::{AuthEventType, Outcome},
+            create_api_router,
+            key_repository::KeyRepository,
+            AppState,
+        },
+        pocketbase_manager::PocketBaseManager,
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
     };
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn setup(dir: &std::path::Path) -> AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager = PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 9)
+            .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+        for user in ["alice", "bob"] {
+            let data_dir = state.pb_manager.data_dir(user);
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        }
+        state
+    }
+
+    async fn send(state: &AppState, user: &str, method: &str, body: Option<Value>) -> (StatusCode, Value) {
+        send_to(state, user, method, "/api/keys", body).await
+    }
+
+    async fn send_to(state: &AppState, user: &str, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer valid-{}", user))
+            .header("content-type", "application/json")
+            .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn put(state: &AppState, user: &str, service: &str, key_id: &str, value: &str) -> StatusCode {
+        let body = json!({ "service": service, "key_id": key_id, "value": value });
+        send(state, user, "PUT", Some(body)).await.0
+    }
+
+    /// The caller's keys as (service, key_id, plaintext), checking the
+    /// listing names the same keys as storage holds
+    async fn list(state: &AppState, user: &str) -> Vec<(String, String, String)> {
+        let (status, body) = send(state, user, "GET", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: Vec<(String, String)> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|summary| (summary["service"].as_str().unwrap().into(), summary["key_id"].as_str().unwrap().into()))
+            .collect();
+
+        let repository = KeyRepository::open(&state.pb_manager, user, &state.master_key).await.unwrap();
+        let stored: Vec<(String, String, String)> = repository
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|stored| {
+                let value = repository.decrypt(&stored.key).unwrap();
+                (stored.key.service, stored.key.key_id, value)
+            })
+            .collect();
+        let names: Vec<(String, String)> = stored.iter().map(|(service, key_id, _)| (service.clone(), key_id.clone())).collect();
+        assert_eq!(listed, names);
+        stored
+    }
+
+    /// Fail if any object in `value` has a field holding key material
+    fn assert_no_key_material(value: &Value) {
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields {
+                    assert!(!["ciphertext", "nonce", "encrypted_key"].contains(&name.as_str()), "{} in {}", name, value);
+                    assert_no_key_material(field);
+                }
+            }
+            Value::Array(items) => items.iter().for_each(assert_no_key_material),
+            _ => {}
+        }
+    }
+
+    fn entry(service: &str, key_id: &str, value: &str) -> (String, String, String) {
+        (service.to_string(), key_id.to_string(), value.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_keys_persist_and_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        assert_eq!(list(&state, "alice").await, vec![]);
+        assert_eq!(put(&state, "alice", "loom", "default", "loom-1").await, StatusCode::OK);
+        assert_eq!(put(&state, "alice", "fathom", "default", "fathom-1").await, StatusCode::OK);
+        assert_eq!(
+            list(&state, "alice").await,
+            vec![entry("fathom", "default", "fathom-1"), entry("loom", "default", "loom-1")]
+        );
+
+        // The same service and key id replace the stored value
+        assert_eq!(put(&state, "alice", "fathom", "default", "fathom-2").await, StatusCode::OK);
+        assert_eq!(
+            list(&state, "alice").await,
+            vec![entry("fathom", "default", "fathom-2"), entry("loom", "default", "loom-1")]
+        );
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_isolated_per_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        assert_eq!(put(&state, "alice", "fathom", "default", "alice-key").await, StatusCode::OK);
+        assert_eq!(put(&state, "bob", "fathom", "default", "bob-key").await, StatusCode::OK);
+
+        assert_eq!(list(&state, "alice").await, vec![entry("fathom", "default", "alice-key")]);
+        assert_eq!(list(&state, "bob").await, vec![entry("fathom", "default", "bob-key")]);
+
+        // Each user's keys live in their own instance
+        let alice = state.pb_manager.get_user_instance("alice").await.unwrap();
+        let bob = state.pb_manager.get_user_instance("bob").await.unwrap();
+        assert_ne!(alice.port, bob.port);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_only_the_callers_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        assert_eq!(put(&state, "alice", "fathom", "default", "alice-key").await, StatusCode::OK);
+        assert_eq!(put(&state, "alice", "loom", "default", "loom-key").await, StatusCode::OK);
+        assert_eq!(put(&state, "bob", "fathom", "default", "bob-key").await, StatusCode::OK);
+
+        let (status, body) = send_to(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"], json!({ "service": "fathom", "key_id": "default" }));
+        assert_eq!(list(&state, "alice").await, vec![entry("loom", "default", "loom-key")]);
+        assert_eq!(list(&state, "bob").await, vec![entry("fathom", "default", "bob-key")]);
+
+        let events = state.audit.buffered();
+        let deleted = events.iter().find(|event| event.event_type == AuthEventType::ApiKeyDeleted).unwrap();
+        assert_eq!(deleted.user_id.as_deref(), Some("alice"));
+        assert_eq!(deleted.outcome, Outcome::Success);
+        assert_eq!(deleted.detail.as_deref(), Some("fathom/default"));
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_of_missing_or_foreign_key_is_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        assert_eq!(put(&state, "bob", "fathom", "default", "bob-key").await, StatusCode::OK);
+
+        let (status, body) = send_to(&state, "bob", "DELETE", "/api/keys/loom/default", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+
+        // Alice can't reach Bob's key, even naming its exact pair
+        let (status, body) = send_to(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(list(&state, "bob").await, vec![entry("fathom", "default", "bob-key")]);
+
+        let failures = state
+            .audit
+            .buffered()
+            .into_iter()
+            .filter(|event| event.event_type == AuthEventType::ApiKeyDeleted)
+            .filter(|event| event.outcome == Outcome::Failure)
+            .count();
+        assert_eq!(failures, 2);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_no_key_material() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let value = "fathom-secret-0123456789wxyz";
+
+        let body = json!({ "service": "fathom", "key_id": "default", "value": value });
+        let (status, saved) = send(&state, "alice", "PUT", Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(saved["masked_hint"], "••••wxyz");
+        assert_eq!(saved["fingerprint"].as_str().unwrap().len(), 16);
+
+        let (_, listed) = send(&state, "alice", "GET", None).await;
+        assert_eq!(listed[0]["fingerprint"], saved["fingerprint"]);
+        assert_eq!(listed[0]["last_used_at"], Value::Null);
+
+        let (_, deleted) = send_to(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        let (_, missing) = send_to(&state, "alice", "DELETE", "/api/keys/fathom/default", None).await;
+        for body in [&saved, &listed, &deleted, &missing] {
+            assert_no_key_material(body);
+            assert!(!body.to_string().contains(value));
+        }
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listing_flags_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let soon = chrono::Utc::now() + chrono::Duration::days(3) + chrono::Duration::hours(1);
+        let past = chrono::Utc::now() - chrono::Duration::days(2) - chrono::Duration::hours(1);
+        for (key_id, expires_at) in [("soon", Some(soon)), ("past", Some(past)), ("never", None)] {
+            let body = json!({ "service": "loom", "key_id": key_id, "value": "loom-key", "expires_at": expires_at });
+            assert_eq!(send(&state, "alice", "PUT", Some(body)).await.0, StatusCode::OK);
+        }
+
+        let (_, listed) = send(&state, "alice", "GET", None).await;
+        let flags: Vec<(Value, Value)> = listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|summary| (summary["days_until_expiry"].clone(), summary["expired"].clone()))
+            .collect();
+        // Ordered never, past, soon
+        assert_eq!(
+            flags,
+            vec![(Value::Null, json!(false)), (json!(-2), json!(true)), (json!(3), json!(false))]
+        );
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        // Without a key id the key is the service's default; padding is trimmed
+        let body = json!({ "service": "loom", "value": "  loom-secret-0123456789\n", "expires_at": "2030-01-01T00:00:00Z" });
+        let (status, summary) = send(&state, "alice", "PUT", Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(summary["service"], "loom");
+        assert_eq!(summary["key_id"], "default");
+        assert_eq!(summary["expires_at"], "2030-01-01T00:00:00Z");
+        assert_eq!(summary["masked_hint"], "••••6789");
+        assert_eq!(list(&state, "alice").await, vec![entry("loom", "default", "loom-secret-0123456789")]);
+
+        let body = json!({ "service": "fathom", "key_id": "workspace-2", "value": "fathom-secret" });
+        assert_eq!(send(&state, "alice", "PUT", Some(body)).await.0, StatusCode::OK);
+        assert_eq!(
+            list(&state, "alice").await,
+            vec![entry("fathom", "workspace-2", "fathom-secret"), entry("loom", "default", "loom-secret-0123456789")]
+        );
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_saved_keys_decrypt_in_the_worker() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        let body = json!({ "service": "fathom", "value": "fathom-secret-0123456789" });
+        assert_eq!(send(&state, "alice", "PUT", Some(body)).await.0, StatusCode::OK);
+        let (_, listed) = send(&state, "alice", "GET", None).await;
+        assert_eq!(listed[0]["key_id"], "default");
+
+        // The worker parses the same MASTER_KEY on its own
+        let worker_key = common::crypto::parse_master_key(&state.config.security.master_key).unwrap();
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key).await.unwrap();
+        let stored = repository.get("fathom", "default").await.unwrap().unwrap();
+        let value = worker::keys::usable_key(&stored.key, "alice", &worker_key, chrono::Utc::now()).unwrap();
+        assert_eq!(value, "fathom-secret-0123456789");
+        assert!(worker::keys::usable_key(&stored.key, "bob", &worker_key, chrono::Utc::now()).is_err());
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    /// The caller's (key_id, is_default) pairs for `service`
+    async fn defaults(state: &AppState, user: &str, service: &str) -> Vec<(String, bool)> {
+        let (_, listed) = send(state, user, "GET", None).await;
+        listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|summary| summary["service"] == service)
+            .map(|summary| (summary["key_id"].as_str().unwrap().to_string(), summary["is_default"].as_bool().unwrap()))
+            .collect()
+    }
+
+    fn flags(pairs: &[(&str, bool)]) -> Vec<(String, bool)> {
+        pairs.iter().map(|(key_id, is_default)| (key_id.to_string(), *is_default)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_service_has_one_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        // The first key of a service becomes its default; later ones don't
+        assert_eq!(put(&state, "alice", "fathom", "personal", "fathom-personal").await, StatusCode::OK);
+        assert_eq!(put(&state, "alice", "fathom", "team", "fathom-team").await, StatusCode::OK);
+        assert_eq!(put(&state, "alice", "loom", "default", "loom-1").await, StatusCode::OK);
+        assert_eq!(defaults(&state, "alice", "fathom").await, flags(&[("personal", true), ("team", false)]));
+
+        let (status, body) = send_to(&state, "alice", "PATCH", "/api/keys/fathom/team/default", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&body["key_id"], &body["is_default"]), (&json!("team"), &json!(true)));
+        assert_eq!(defaults(&state, "alice", "fathom").await, flags(&[("personal", false), ("team", true)]));
+        // Other services are left alone
+        assert_eq!(defaults(&state, "alice", "loom").await, flags(&[("default", true)]));
+
+        // Replacing the default keeps it the default
+        assert_eq!(put(&state, "alice", "fathom", "team", "fathom-team-2").await, StatusCode::OK);
+        assert_eq!(defaults(&state, "alice", "fathom").await, flags(&[("personal", false), ("team", true)]));
+
+        // Deleting it hands the role to a remaining key
+        let (status, _) = send_to(&state, "alice", "DELETE", "/api/keys/fathom/team", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(defaults(&state, "alice", "fathom").await, flags(&[("personal", true)]));
+
+        for uri in ["/api/keys/fathom/missing/default", "/api/keys/loom/personal/default"] {
+            let (status, body) = send_to(&state, "alice", "PATCH", uri, None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "not_found");
+        }
+        let (status, _) = send_to(&state, "bob", "PATCH", "/api/keys/fathom/personal/default", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let made_default = state.key_audit.buffered();
+        let made_default: Vec<_> = made_default
+            .iter()
+            .filter(|entry| entry.action == crate::api::key_audit::KeyAction::MadeDefault)
+            .collect();
+        assert_eq!(made_default.len(), 1);
+        assert_eq!(made_default[0].key_id, "team");
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_rejects_blank_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        for body in [
+            json!({ "service": "fathom", "value": "" }),
+            json!({ "service": "fathom", "value": " \t\n " }),
+            json!({ "service": "fathom", "key_id": "a/b", "value": "fathom-secret" }),
+        ] {
+            let (status, response) = send(&state, "alice", "PUT", Some(body)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["code"], "validation");
+        }
+        // Nothing was stored, so the instance was never needed
+        assert!(state.pb_manager.get_user_instance("alice").await.is_none());
+    }
 
-    // Mock inserting to database or storage
-    info!("API key for service '{}' stored.", stored_entry.service);
+    #[tokio::test]
+    async fn test_unreachable_storage_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        std::fs::write(state.pb_manager.data_dir("alice").join("FAIL_START"), b"").unwrap();
 
-    (StatusCode::OK, Json(stored_entry))
+        let (status, body) = send(&state, "alice", "GET", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "service_unavailable");
+    }
 }
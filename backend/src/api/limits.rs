@@ -0,0 +1,216 @@
+//! Request body size limits and timeouts
+//!
+//! Every route refuses bodies over its limit with 413, whether the client
+//! announces the size or streams past it, and answers 408 once a request,
+//! reading its body included, has run past its timeout. A slow or oversized
+//! upload therefore can't hold a connection and its memory indefinitely.
+//! Both answers use the standard error envelope rather than the bare status
+//! the layers produce.
+
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
+    Router,
+};
+use common::{ApiResponse, ErrorCode};
+use serde_json::Value;
+use std::time::Duration;
+use tower_http::{limit::RequestBodyLimitLayer, timeout::TimeoutLayer};
+
+use crate::config::ServerConfig;
+
+/// The largest body and longest time a group of routes allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub body_bytes: usize,
+    pub timeout: Duration,
+}
+
+impl Limits {
+    /// What ordinary JSON routes allow
+    pub fn standard(config: &ServerConfig) -> Self {
+        Self {
+            body_bytes: config.body_limit_bytes,
+            timeout: Duration::from_secs(config.request_timeout_secs),
+        }
+    }
+
+    /// What backup uploads and the instance proxy allow
+    pub fn long(config: &ServerConfig) -> Self {
+        Self {
+            body_bytes: config.upload_body_limit_bytes,
+            timeout: Duration::from_secs(config.long_request_timeout_secs),
+        }
+    }
+
+    /// `router` with these limits on every route registered so far
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        router
+            // The layer below replaces axum's own 2 MB extractor limit
+            .layer(DefaultBodyLimit::disable())
+            .layer(RequestBodyLimitLayer::new(self.body_bytes))
+            .layer(TimeoutLayer::new(self.timeout))
+            .layer(middleware::map_response(envelope))
+    }
+}
+
+/// Give the layers' bare 413 and 408 answers the error envelope
+async fn envelope(response: Response) -> Response {
+    let (code, message) = match response.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => (
+            ErrorCode::PayloadTooLarge,
+            "Request body is larger than this endpoint accepts",
+        ),
+        StatusCode::REQUEST_TIMEOUT => (ErrorCode::Timeout, "Request took too long; try again"),
+        _ => return response,
+    };
+    let json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if json {
+        // A handler already answered with its own envelope
+        return response;
+    }
+    let body = ApiResponse::<Value>::failure(code, message);
+    (response.status(), Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::{mock_app_state_with, spawn_server};
+    use axum::{
+        body::{Body, Bytes, HttpBody},
+        extract::Request,
+        routing::{get, post},
+    };
+    use futures::stream;
+    use tower::ServiceExt;
+
+    const LIMITS: Limits = Limits {
+        body_bytes: 16,
+        timeout: Duration::from_millis(100),
+    };
+
+    fn limited() -> Router {
+        LIMITS.apply(
+            Router::new()
+                .route("/echo", post(|body: Bytes| async move { body }))
+                .route(
+                    "/json",
+                    post(|Json(body): Json<Value>| async move { Json(body) }),
+                )
+                .route(
+                    "/slow",
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        "done"
+                    }),
+                ),
+        )
+    }
+
+    async fn failure(response: Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    /// A POST announcing its length, as clients do, unless it is streamed
+    fn post_to(uri: &str, body: impl Into<Body>) -> Request {
+        let body = body.into();
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(length) = body.size_hint().exact() {
+            request = request.header("content-length", length);
+        }
+        request.body(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bodies_over_the_limit_get_413() {
+        let app = limited();
+
+        let response = app
+            .clone()
+            .oneshot(post_to("/echo", "\"within limit\""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Announced by Content-Length, streamed without one, and through Json
+        let streamed = Body::from_stream(stream::iter(
+            ["0123456789", "0123456789"].map(Ok::<_, std::io::Error>),
+        ));
+        for request in [
+            post_to("/echo", "x".repeat(17)),
+            post_to("/echo", streamed),
+            post_to("/json", format!("\"{}\"", "x".repeat(32))),
+        ] {
+            let (status, body) = failure(app.clone().oneshot(request).await.unwrap()).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(body["success"], false);
+            assert_eq!(body["code"], "payload_too_large");
+            assert!(body["timestamp"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_requests_get_408() {
+        let request = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let started = std::time::Instant::now();
+        let (status, body) = failure(limited().oneshot(request).await.unwrap()).await;
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(body["code"], "timeout");
+        assert!(body["error"].as_str().unwrap().contains("too long"));
+    }
+
+    #[tokio::test]
+    async fn test_uploads_get_the_larger_limit() {
+        let app = create_api_router(mock_app_state_with(|config| config.server.body_limit_bytes = 1024).await);
+        let archive = format!("{{\"confirm\":true,\"archive\":\"{}\"}}", "A".repeat(4096));
+
+        let response = app
+            .clone()
+            .oneshot(post_to("/api/queue", archive.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Past the size check, and refused for want of a token instead
+        let response = app
+            .oneshot(post_to("/api/users/someone/pb_restore", archive))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_the_websocket_outlives_the_timeout() {
+        let state = mock_app_state_with(|config| config.server.request_timeout_secs = 1).await;
+        let ws_manager = state.ws_manager.clone();
+        let url = spawn_server(create_api_router(state)).await;
+
+        let ws_url = format!(
+            "{}/queue_updates?token=valid-alice",
+            url.replacen("http", "ws", 1)
+        );
+        let (_socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(ws_manager.connection_count().await, 1);
+    }
+}
@@ -0,0 +1,319 @@
+//! Brute-force protection for `/auth/login`
+//!
+//! Two independent checks run before credentials are forwarded to
+//! PocketBase:
+//!
+//! - a sliding window of attempts per (client IP, email), kept in memory
+//! - a lockout per email after too many consecutive failures, doubling with
+//!   each lock in a row and cleared by a successful login
+//!
+//! Lockout state is mirrored to the global PocketBase so a restart doesn't
+//! hand an attacker a fresh set of guesses.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use common::clock::Clock;
+
+use crate::{
+    config::SecurityConfig,
+    global_pb::{self, GlobalPb, GlobalPbError},
+};
+
+const LOGIN_LOCKOUTS: &str = "login_lockouts";
+
+/// Longest a single lockout can grow to
+const MAX_LOCKOUT_SECS: u64 = 24 * 3600;
+
+/// Why an attempt was refused, and how many seconds until the next one may be made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginRejection {
+    RateLimited { retry_after: u64 },
+    LockedOut { retry_after: u64 },
+}
+
+impl LoginRejection {
+    pub fn retry_after(&self) -> u64 {
+        match self {
+            LoginRejection::RateLimited { retry_after } | LoginRejection::LockedOut { retry_after } => *retry_after,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Lockout {
+    /// Failures since the last success or lock
+    failures: u32,
+    /// Locks imposed in a row, which sets the length of the next one
+    lockouts: u32,
+    locked_until: u64,
+    record_id: Option<String>,
+}
+
+pub struct LoginGuard {
+    max_attempts: u32,
+    window_secs: u64,
+    lockout_threshold: u32,
+    lockout_secs: u64,
+    clock: Arc<dyn Clock>,
+    /// Attempt times per `ip|email` within the current window
+    attempts: Mutex<HashMap<String, VecDeque<u64>>>,
+    /// Failure and lock state per email
+    lockouts: Mutex<HashMap<String, Lockout>>,
+    store: Option<Arc<GlobalPb>>,
+}
+
+impl LoginGuard {
+    /// A guard that only lives in memory
+    pub fn new(security: &SecurityConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            max_attempts: security.login_max_attempts,
+            window_secs: security.login_window_secs,
+            lockout_threshold: security.login_lockout_threshold,
+            lockout_secs: security.login_lockout_secs,
+            clock,
+            attempts: Mutex::new(HashMap::new()),
+            lockouts: Mutex::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    /// A guard whose lockouts are mirrored to the global PocketBase
+    pub fn persistent(security: &SecurityConfig, clock: Arc<dyn Clock>, store: Arc<GlobalPb>) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::new(security, clock)
+        }
+    }
+
+    /// Restore lockout state from the global PocketBase
+    pub async fn load(&self) -> Result<(), GlobalPbError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
+        let mut lockouts = HashMap::new();
+        for record in store.list_records(LOGIN_LOCKOUTS, None).await? {
+            let number = |name: &str| record.get(name).and_then(|v| v.as_u64()).unwrap_or(0);
+            let Some(email) = record.get("email").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            lockouts.insert(
+                email.to_string(),
+                Lockout {
+                    failures: number("failures") as u32,
+                    lockouts: number("lockouts") as u32,
+                    locked_until: number("locked_until"),
+                    record_id: record.get("id").and_then(|v| v.as_str()).map(String::from),
+                },
+            );
+        }
+
+        info!("Loaded login lockout state for {} emails", lockouts.len());
+        self.lockouts.lock().await.extend(lockouts);
+        Ok(())
+    }
+
+    /// Count a login attempt for `email` from `ip`, refusing it if over a limit
+    ///
+    /// `email` should already be normalized.
+    pub async fn check(&self, ip: &str, email: &str) -> Result<(), LoginRejection> {
+        let now = self.clock.unix_now();
+        if let Some(lockout) = self.lockouts.lock().await.get(email) {
+            if lockout.locked_until > now {
+                return Err(LoginRejection::LockedOut {
+                    retry_after: lockout.locked_until - now,
+                });
+            }
+        }
+
+        let window_start = now.saturating_sub(self.window_secs);
+        let mut attempts = self.attempts.lock().await;
+        attempts.retain(|_, times| times.back().is_some_and(|last| *last > window_start));
+
+        let times = attempts.entry(format!("{}|{}", ip, email)).or_default();
+        while times.front().is_some_and(|first| *first <= window_start) {
+            times.pop_front();
+        }
+        if times.len() >= self.max_attempts as usize {
+            let oldest = times.front().copied().unwrap_or(now);
+            return Err(LoginRejection::RateLimited {
+                retry_after: (oldest + self.window_secs).saturating_sub(now).max(1),
+            });
+        }
+        times.push_back(now);
+        Ok(())
+    }
+
+    /// Record wrong credentials for `email`, locking it once the threshold is reached
+    pub async fn record_failure(&self, email: &str) {
+        let now = self.clock.unix_now();
+        let snapshot = {
+            let mut lockouts = self.lockouts.lock().await;
+            let lockout = lockouts.entry(email.to_string()).or_default();
+            lockout.failures += 1;
+            if lockout.failures >= self.lockout_threshold {
+                let duration = self
+                    .lockout_secs
+                    .saturating_mul(1 << lockout.lockouts.min(16))
+                    .min(MAX_LOCKOUT_SECS);
+                lockout.locked_until = now + duration;
+                lockout.lockouts += 1;
+                lockout.failures = 0;
+                warn!(target: "audit", email = %email, seconds = duration, "Login locked after repeated failures");
+            }
+            lockout.clone()
+        };
+        self.persist(email, snapshot).await;
+    }
+
+    /// Forget failures and locks for `email` after it logs in
+    pub async fn record_success(&self, email: &str) {
+        let removed = self.lockouts.lock().await.remove(email);
+        let (Some(store), Some(Lockout { record_id: Some(id), .. })) = (&self.store, removed) else {
+            return;
+        };
+        if let Err(e) = store.delete_record(LOGIN_LOCKOUTS, &id).await {
+            warn!("Failed to clear persisted login lockout: {}", e);
+        }
+    }
+
+    async fn persist(&self, email: &str, lockout: Lockout) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        match Self::upsert(store, email, &lockout).await {
+            Ok(id) => {
+                if let Some(lockout) = self.lockouts.lock().await.get_mut(email) {
+                    lockout.record_id = Some(id);
+                }
+            }
+            Err(e) => warn!("Failed to persist login lockout: {}", e),
+        }
+    }
+
+    /// Write `lockout` to its record, creating it if needed, and return the record id
+    async fn upsert(store: &GlobalPb, email: &str, lockout: &Lockout) -> Result<String, GlobalPbError> {
+        let record = serde_json::json!({
+            "email": email,
+            "failures": lockout.failures,
+            "lockouts": lockout.lockouts,
+            "locked_until": lockout.locked_until
+        });
+
+        let existing_id = match &lockout.record_id {
+            Some(id) => Some(id.clone()),
+            // Another replica may have created the record already
+            None => {
+                let filter = format!("email = {}", global_pb::quote(email));
+                let existing = store.list_records(LOGIN_LOCKOUTS, Some(&filter)).await?;
+                existing
+                    .first()
+                    .and_then(|r| r.get("id"))
+                    .and_then(|v| v.as_str())
+                    .map(String::from)
+            }
+        };
+        let saved = match &existing_id {
+            Some(id) => store.update_record(LOGIN_LOCKOUTS, id, &record).await?,
+            None => store.create_record(LOGIN_LOCKOUTS, &record).await?,
+        };
+        saved
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .or(existing_id)
+            .ok_or_else(|| GlobalPbError::Request("Saved lockout record had no id".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_global_pocketbase, test_config, ManualClock};
+
+    fn guard() -> (LoginGuard, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let security = test_config("http://127.0.0.1:9").security;
+        (LoginGuard::new(&security, clock.clone()), clock)
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window_per_ip_and_email() {
+        let (guard, clock) = guard();
+        for _ in 0..10 {
+            assert!(guard.check("192.0.2.1", "a@example.com").await.is_ok());
+            clock.advance(10);
+        }
+        // The first attempt was 100s ago, so it leaves the 300s window in 200s
+        assert_eq!(
+            guard.check("192.0.2.1", "a@example.com").await,
+            Err(LoginRejection::RateLimited { retry_after: 200 })
+        );
+        assert!(guard.check("192.0.2.2", "a@example.com").await.is_ok());
+        assert!(guard.check("192.0.2.1", "b@example.com").await.is_ok());
+
+        clock.advance(200);
+        assert!(guard.check("192.0.2.1", "a@example.com").await.is_ok());
+        assert!(guard.check("192.0.2.1", "a@example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lockout_escalates_and_clears_on_success() {
+        let (guard, clock) = guard();
+        for _ in 0..5 {
+            guard.record_failure("a@example.com").await;
+        }
+        assert_eq!(
+            guard.check("192.0.2.1", "a@example.com").await,
+            Err(LoginRejection::LockedOut { retry_after: 900 })
+        );
+
+        clock.advance(900);
+        for _ in 0..5 {
+            guard.record_failure("a@example.com").await;
+        }
+        assert_eq!(
+            guard.check("192.0.2.9", "a@example.com").await,
+            Err(LoginRejection::LockedOut { retry_after: 1800 })
+        );
+
+        clock.advance(1800);
+        guard.record_success("a@example.com").await;
+        for _ in 0..5 {
+            guard.record_failure("a@example.com").await;
+        }
+        assert_eq!(
+            guard.check("192.0.2.1", "a@example.com").await,
+            Err(LoginRejection::LockedOut { retry_after: 900 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lockouts_survive_reload() {
+        let global_url = mock_global_pocketbase().await;
+        let config = test_config(&global_url);
+        let store = Arc::new(GlobalPb::from(&config.database));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+
+        let guard = LoginGuard::persistent(&config.security, clock.clone(), store.clone());
+        for _ in 0..5 {
+            guard.record_failure("a@example.com").await;
+        }
+        guard.record_failure("b@example.com").await;
+        guard.record_success("b@example.com").await;
+        assert_eq!(store.list_records(LOGIN_LOCKOUTS, None).await.unwrap().len(), 1);
+
+        let reloaded = LoginGuard::persistent(&config.security, clock.clone(), store);
+        reloaded.load().await.unwrap();
+        assert_eq!(
+            reloaded.check("192.0.2.1", "a@example.com").await,
+            Err(LoginRejection::LockedOut { retry_after: 900 })
+        );
+        assert!(reloaded.check("192.0.2.1", "b@example.com").await.is_ok());
+    }
+}
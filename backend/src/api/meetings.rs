@@ -1,110 +1,2087 @@
+//! The caller's Fathom meetings, cached in their PocketBase instance
+//!
+//! Every endpoint takes the caller from their token and uses their default
+//! Fathom key and their instance; no parameter names another user.
+//!
+//! Listings are kept in the `meetings_cache` collection of the caller's
+//! instance, one record per page asked for and Fathom key, so replacing the
+//! key starts afresh. A listing younger than `MEETINGS_CACHE_TTL_SECS` is
+//! served as is; one older but within `MEETINGS_CACHE_STALE_SECS` past that
+//! is served too while a fresh copy is fetched in the background. Anything
+//! older, and `?refresh=true`, goes to Fathom. The cache is best effort: when
+//! it can't be read or written the listing comes from Fathom regardless.
+//!
+//! `POST /api/meetings/refresh` fetches a listing and drops every one cached
+//! before it, at most once per `MEETINGS_REFRESH_COOLDOWN_SECS` per user.
+//!
+//! `GET /api/meetings/:id` adds a recording's summary, size and whether it
+//! can be downloaded, cached by meeting id in `meeting_details` for the same
+//! TTL.
+//!
+//! `GET /api/meetings/:id/downloadable` checks, uncached, that Fathom still
+//! has the recording and its media host serves the download, so a meeting
+//! that can't be fetched is caught before it is queued.
+//!
+//! `GET /api/meetings/:id/transcript` answers 202 with `Retry-After` while
+//! Fathom is still transcribing. Finished transcripts no longer change, so
+//! they are kept in `meeting_transcripts` until `?refresh=true`. They are
+//! written out a few segments at a time rather than serialized whole, as
+//! JSON segments or, with `?format=text`, one `[HH:MM:SS] Speaker: text`
+//! line per turn.
+//!
+//! Pages follow Fathom's cursors: the first is picked by `limit` and
+//! `offset`, and each page names the `next_cursor` to pass as `cursor` for
+//! the one after. Cursor pages keep the first page's size, so `limit` is
+//! ignored alongside a cursor (the response says so) and `offset` is refused.
+//!
+//! The listing can be narrowed by `q` (in the title), `from` and `to` (when
+//! recorded, dates or RFC 3339 times), `participant` and
+//! `min_duration_secs`. Fathom filters by date and invitee email itself; the
+//! rest is applied to what it returns, and `filters_applied` says which was
+//! which. Filters go with every page, cursor pages included, and are part of
+//! the cache key.
+
 use axum::{
-    extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::Json,
-    routing::get,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
     Router,
 };
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::sync::Arc;
-use tracing::{error, info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc};
+use tracing::{info, warn};
+
+use super::{
+    auth::auth_error,
+    error_report::with_cause,
+    extractors::AuthUser,
+    internal::TOUCH_INTERVAL,
+    key_repository::{KeyRepository, MasterKey, StoredKey},
+    keys::{repository, store_error},
+    rate_limit::{rate_limited, RateLimits},
+};
+use crate::{
+    config::Config,
+    fathom::{
+        AppliedBy, DownloadCheck, FathomClient, FathomError, FathomMeeting, FathomMeetingDetail,
+        MeetingFilter, MeetingsPage, Transcript, TranscriptSegment,
+    },
+    global_pb::{quote, GlobalPb, GlobalPbError},
+    pocketbase_manager::PocketBaseManager,
+};
+use common::{AppError, ErrorCode};
 
-use crate::config::Config;
+const MEETINGS_CACHE: &str = "meetings_cache";
+const MEETING_DETAILS: &str = "meeting_details";
+const MEETING_TRANSCRIPTS: &str = "meeting_transcripts";
+
+/// Transcript segments written out per chunk of the response body
+const SEGMENTS_PER_CHUNK: usize = 64;
+
+/// Meetings returned when the query names no `limit`
+const DEFAULT_LIMIT: u32 = 20;
+
+/// The most meetings one request returns
+const MAX_LIMIT: u32 = 100;
 
 #[derive(Debug, Deserialize)]
 pub struct MeetingsQuery {
     pub limit: Option<u32>,
     pub offset: Option<u32>,
-    pub user_id: Option<String>,
+    /// `next_cursor` of the previous page, passed to Fathom as is
+    pub cursor: Option<String>,
+    /// Ask Fathom even if the cache holds the listing
+    #[serde(default)]
+    pub refresh: bool,
+    /// Case-insensitive substring of the title
+    pub q: Option<String>,
+    /// Recorded on or after: a date (`YYYY-MM-DD`) or RFC 3339 time
+    pub from: Option<String>,
+    /// Recorded on or before a date, or before an RFC 3339 time
+    pub to: Option<String>,
+    /// Name or email of an invitee
+    pub participant: Option<String>,
+    pub min_duration_secs: Option<u32>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MeetingsResponse {
     pub success: bool,
-    pub meetings: Vec<Value>,
-    pub total: u32,
+    pub meetings: Vec<FathomMeeting>,
+    /// Meetings in the caller's Fathom account, across all pages; counted
+    /// for first pages only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u32>,
+    /// `cursor` for the page after this one, unless this is the last or
+    /// `offset` isn't a multiple of `limit`
+    pub next_cursor: Option<String>,
+    /// Whether the listing was served from the cache
     pub cached: bool,
+    /// When the listing was fetched from Fathom
+    pub fetched_at: DateTime<Utc>,
+    /// What of the query was ignored, and why
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notice: Option<String>,
+    /// The filters in the query, and whether Fathom or the backend applied
+    /// each
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub filters_applied: BTreeMap<String, AppliedBy>,
+}
+
+/// Which page of the listing a request asks for
+#[derive(Debug, Clone, PartialEq)]
+enum PageRequest {
+    /// `limit` meetings after the first `offset`
+    First { limit: u32, offset: u32 },
+    /// The page a `next_cursor` points at
+    After(String),
+}
+
+impl PageRequest {
+    /// Where the page is cached for the key with `fingerprint`
+    fn cache_key(&self, fingerprint: &str, filter: &MeetingFilter) -> String {
+        let key = match self {
+            PageRequest::First { limit, offset } => {
+                format!("{}:{}:{}", fingerprint, limit, offset)
+            }
+            PageRequest::After(cursor) => format!("{}:cursor:{}", fingerprint, cursor),
+        };
+        match filter.is_empty() {
+            true => key,
+            false => format!("{}:{}", key, filter.cache_key()),
+        }
+    }
+
+    async fn fetch(
+        &self,
+        client: &FathomClient,
+        filter: &MeetingFilter,
+    ) -> Result<MeetingsPage, FathomError> {
+        match self {
+            PageRequest::First { limit, offset } => {
+                client.list_meetings(*limit, *offset, filter).await
+            }
+            PageRequest::After(cursor) => client.meetings_after(cursor, filter).await,
+        }
+    }
+}
+
+/// A listing read back from the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedMeetings {
+    pub page: MeetingsPage,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MeetingQuery {
+    /// Ask Fathom even if the cache holds the meeting
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MeetingDetailResponse {
+    pub success: bool,
+    pub meeting: FathomMeetingDetail,
+    /// Whether the meeting was served from the cache
+    pub cached: bool,
+    /// When the meeting was fetched from Fathom
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    /// [`TranscriptResponse`] JSON
+    #[default]
+    Segments,
+    /// Plain text, a line per segment
+    Text,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TranscriptQuery {
+    #[serde(default)]
+    pub format: TranscriptFormat,
+    /// Ask Fathom even if the cache holds the transcript
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranscriptResponse {
+    pub success: bool,
+    pub meeting_id: String,
+    /// Whether the transcript was served from the cache
+    pub cached: bool,
+    /// When the transcript was fetched from Fathom
+    pub fetched_at: DateTime<Utc>,
+    /// In order of speaking; last, so the body can be streamed
+    pub segments: Vec<TranscriptSegment>,
+}
+
+/// The caller's default Fathom key and a client using it
+struct FathomAccess {
+    repository: KeyRepository,
+    stored: StoredKey,
+    client: FathomClient,
+}
+
+impl FathomAccess {
+    /// Note that the key was just used
+    async fn touch(&self, user: &AuthUser) {
+        if let Err(e) = self
+            .repository
+            .touch(
+                "fathom",
+                &self.stored.key.key_id,
+                Utc::now(),
+                TOUCH_INTERVAL,
+            )
+            .await
+        {
+            warn!("Could not record use of {}'s Fathom key: {}", user.id, e);
+        }
+    }
+}
+
+/// The `meetings_cache` collection, readable and writable by admins only
+fn cache_schema() -> Value {
+    json!({
+        "name": MEETINGS_CACHE,
+        "type": "base",
+        "schema": [
+            { "name": "cache_key", "type": "text", "required": true },
+            { "name": "listing", "type": "json", "required": true },
+            { "name": "fetched_at", "type": "text", "required": true },
+        ],
+        "indexes": [
+            "CREATE UNIQUE INDEX idx_meetings_cache_key ON meetings_cache (cache_key)"
+        ],
+        "listRule": null,
+        "viewRule": null,
+        "createRule": null,
+        "updateRule": null,
+        "deleteRule": null
+    })
+}
+
+/// The `meeting_details` collection, readable and writable by admins only
+fn detail_schema() -> Value {
+    json!({
+        "name": MEETING_DETAILS,
+        "type": "base",
+        "schema": [
+            { "name": "meeting_id", "type": "text", "required": true },
+            { "name": "detail", "type": "json", "required": true },
+            { "name": "fetched_at", "type": "text", "required": true },
+        ],
+        "indexes": [
+            "CREATE UNIQUE INDEX idx_meeting_details_id ON meeting_details (meeting_id)"
+        ],
+        "listRule": null,
+        "viewRule": null,
+        "createRule": null,
+        "updateRule": null,
+        "deleteRule": null
+    })
+}
+
+/// The `meeting_transcripts` collection, readable and writable by admins only
+fn transcript_schema() -> Value {
+    json!({
+        "name": MEETING_TRANSCRIPTS,
+        "type": "base",
+        "schema": [
+            { "name": "meeting_id", "type": "text", "required": true },
+            { "name": "segments", "type": "json", "required": true },
+            { "name": "fetched_at", "type": "text", "required": true },
+        ],
+        "indexes": [
+            "CREATE UNIQUE INDEX idx_meeting_transcripts_id ON meeting_transcripts (meeting_id)"
+        ],
+        "listRule": null,
+        "viewRule": null,
+        "createRule": null,
+        "updateRule": null,
+        "deleteRule": null
+    })
 }
 
 /// Create router for meetings endpoints
 pub fn router() -> Router<crate::api::AppState> {
     Router::new()
         .route("/meetings", get(get_meetings))
+        .route("/meetings/refresh", post(refresh_meetings))
+        .route("/meetings/:id", get(get_meeting))
+        .route("/meetings/:id/transcript", get(get_transcript))
+        .route("/meetings/:id/downloadable", get(get_downloadable))
 }
 
-/// GET /api/meetings - Proxy to Fathom API with caching
+/// GET /api/meetings - The caller's Fathom recordings, fetched with their
+/// default Fathom key
 pub async fn get_meetings(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>,
-    Query(query): Query<MeetingsQuery>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
     headers: HeaderMap,
-) -> Result<Json<MeetingsResponse>, StatusCode> {
-    info!("Fetching meetings with query: {:?}", query);
-
-    // Extract authorization token from headers
-    let auth_token = headers
-        .get("authorization")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer "))
-        .unwrap_or("");
-
-    if auth_token.is_empty() {
-        warn!("No authorization token provided for meetings request");
-        return Err(StatusCode::UNAUTHORIZED);
-    }
-
-    // TODO: Implement actual Fathom API integration
-    // For now, return mock data
-    let mock_meetings = vec![
-        serde_json::json!({
-            "id": "meeting_1",
-            "title": "Team Standup",
-            "start_time": "2023-12-01T10:00:00Z",
-            "duration": 1800,
-            "participants": ["user1", "user2", "user3"]
+    Query(query): Query<MeetingsQuery>,
+) -> Result<Response, Response> {
+    info!("Fetching meetings for {} with query: {:?}", user.id, query);
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    let filter = meeting_filter(&query).map_err(|message| {
+        auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            &message,
+        )
+    })?;
+    let filters_applied = filter.applied();
+    let mut notice = None;
+    let request = match query.cursor.filter(|cursor| !cursor.is_empty()) {
+        Some(_) if query.offset.is_some() => {
+            return Err(auth_error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorCode::Validation,
+                "Pass either cursor or offset, not both",
+            ));
+        }
+        Some(cursor) => {
+            if query.limit.is_some() {
+                notice = Some(
+                    "limit is ignored with a cursor; pages keep the size of the first".to_string(),
+                );
+            }
+            PageRequest::After(cursor)
+        }
+        None => PageRequest::First {
+            limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            offset: query.offset.unwrap_or(0),
+        },
+    };
+
+    let access = fathom_access(&pb_manager, &master_key, &config, &rate_limits, &user).await?;
+    let cache = cache_client(&pb_manager, &user.id, &cache_schema()).await;
+    let cache_key = request.cache_key(&access.stored.fingerprint, &filter);
+    let now = Utc::now();
+    let integrations = &config.integrations;
+    let cached = match &cache {
+        Some(pb) if !query.refresh => {
+            get_cached_meetings(pb, &cache_key)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Could not read {}'s meetings cache: {}", user.id, e);
+                    None
+                })
+        }
+        _ => None,
+    };
+    if let Some(cached) = cached {
+        let age = (now - cached.fetched_at).to_std().unwrap_or_default();
+        if age < integrations.meetings_cache_ttl + integrations.meetings_cache_stale {
+            let etag = listing_etag(&cache_key, &cached.page, cached.fetched_at);
+            if age >= integrations.meetings_cache_ttl {
+                if let Some(pb) = cache {
+                    tokio::spawn(revalidate(pb, access.client, cache_key, request, filter));
+                }
+            }
+            return Ok(listing(
+                cached.page,
+                true,
+                cached.fetched_at,
+                notice,
+                filters_applied,
+                (etag, if_none_match),
+            ));
+        }
+    }
+
+    let page = request
+        .fetch(&access.client, &filter)
+        .await
+        .map_err(|e| fathom_error(&user, e))?;
+    let fetched_at = Utc::now();
+    if let Some(pb) = &cache {
+        if let Err(e) = cache_meetings_to_pb(pb, &cache_key, &page, fetched_at).await {
+            warn!("Could not cache {}'s meetings: {}", user.id, e);
+        }
+    }
+
+    access.touch(&user).await;
+    let etag = listing_etag(&cache_key, &page, fetched_at);
+    Ok(listing(
+        page,
+        false,
+        fetched_at,
+        notice,
+        filters_applied,
+        (etag, if_none_match),
+    ))
+}
+
+/// POST /api/meetings/refresh - The caller's listing fetched from Fathom now,
+/// dropping every listing cached before
+///
+/// Takes the query of `GET /api/meetings`. Each user may refresh once per
+/// `MEETINGS_REFRESH_COOLDOWN_SECS`; the cache is only dropped once Fathom
+/// has answered, so a failed refresh leaves it as it was.
+pub async fn refresh_meetings(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+    Query(mut query): Query<MeetingsQuery>,
+) -> Result<Response, Response> {
+    if let Err(retry_after) = rate_limits.meetings_refresh_per_user.take(&user.id).await {
+        return Err(rate_limited(
+            Some(retry_after),
+            "Meetings were refreshed moments ago; try again shortly",
+        ));
+    }
+    let started = Utc::now();
+    query.refresh = true;
+    let user_id = user.id.clone();
+    let response = get_meetings(
+        State(pb_manager.clone()),
+        State(config),
+        State(master_key),
+        State(rate_limits),
+        user,
+        HeaderMap::new(),
+        Query(query),
+    )
+    .await?;
+    if let Some(pb) = cache_client(&pb_manager, &user_id, &cache_schema()).await {
+        match clear_meetings_cache(&pb, started).await {
+            Ok(cleared) => info!("Refresh of {} dropped {} cached listings", user_id, cleared),
+            Err(e) => warn!("Could not clear {}'s meetings cache: {}", user_id, e),
+        }
+    }
+    Ok(response)
+}
+
+/// The filters `query` asks for, or what's wrong with its dates
+fn meeting_filter(query: &MeetingsQuery) -> Result<MeetingFilter, String> {
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let bound = |value: &Option<String>, name: &str, day_end: bool| match non_empty(value) {
+        None => Ok(None),
+        Some(value) => parse_bound(&value, day_end)
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a date (YYYY-MM-DD) or an RFC 3339 time", name)),
+    };
+    let from = bound(&query.from, "from", false)?;
+    let to = bound(&query.to, "to", true)?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from >= to {
+            return Err("from must be before to".to_string());
+        }
+    }
+    Ok(MeetingFilter {
+        title: non_empty(&query.q),
+        from,
+        to,
+        participant: non_empty(&query.participant),
+        min_duration_secs: query.min_duration_secs.filter(|secs| *secs > 0),
+    })
+}
+
+/// An RFC 3339 time, or the start of a `YYYY-MM-DD` date in UTC (the start of
+/// the next one for `day_end`, so the date itself is included)
+fn parse_bound(value: &str, day_end: bool) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let date = match day_end {
+        true => date.succ_opt()?,
+        false => date,
+    };
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// GET /api/meetings/:id - One of the caller's recordings with its summary,
+/// size and whether it can be downloaded
+pub async fn get_meeting(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    query: Option<Query<MeetingQuery>>,
+) -> Result<Response, Response> {
+    let Query(query) = query.unwrap_or_default();
+    let access = fathom_access(&pb_manager, &master_key, &config, &rate_limits, &user).await?;
+    let cache = cache_client(&pb_manager, &user.id, &detail_schema()).await;
+
+    let cached = match &cache {
+        Some(pb) if !query.refresh => get_cached_detail(pb, &id).await.unwrap_or_else(|e| {
+            warn!("Could not read {}'s meeting details cache: {}", user.id, e);
+            None
         }),
-        serde_json::json!({
-            "id": "meeting_2", 
-            "title": "Sprint Planning",
-            "start_time": "2023-12-01T14:00:00Z",
-            "duration": 3600,
-            "participants": ["user1", "user4", "user5"]
+        _ => None,
+    };
+    if let Some((meeting, fetched_at)) = cached {
+        let age = (Utc::now() - fetched_at).to_std().unwrap_or_default();
+        if age < config.integrations.meetings_cache_ttl {
+            return Ok(detail(meeting, true, fetched_at));
+        }
+    }
+
+    let meeting = access
+        .client
+        .meeting(&id)
+        .await
+        .map_err(|e| fathom_error(&user, e))?;
+    let fetched_at = Utc::now();
+    if let Some(pb) = &cache {
+        if let Err(e) = cache_detail_to_pb(pb, &meeting, fetched_at).await {
+            warn!("Could not cache {}'s meeting {}: {}", user.id, id, e);
+        }
+    }
+    access.touch(&user).await;
+    Ok(detail(meeting, false, fetched_at))
+}
+
+/// GET /api/meetings/:id/downloadable - Whether the media of one of the
+/// caller's recordings can be fetched now
+pub async fn get_downloadable(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let check = download_check(&pb_manager, &master_key, &config, &rate_limits, &user, &id).await?;
+    Ok((StatusCode::OK, Json(check)).into_response())
+}
+
+/// Whether `user` could have the media of their recording `id` fetched,
+/// with their default Fathom key
+pub async fn download_check(
+    pb_manager: &PocketBaseManager,
+    master_key: &MasterKey,
+    config: &Config,
+    rate_limits: &RateLimits,
+    user: &AuthUser,
+    id: &str,
+) -> Result<DownloadCheck, Response> {
+    let access = fathom_access(pb_manager, master_key, config, rate_limits, user).await?;
+    let check = access
+        .client
+        .download_check(id)
+        .await
+        .map_err(|e| fathom_error(user, e))?;
+    access.touch(user).await;
+    Ok(check)
+}
+
+/// GET /api/meetings/:id/transcript - The transcript of one of the caller's
+/// recordings, once Fathom has finished it
+pub async fn get_transcript(
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(config): State<Arc<Config>>,
+    State(master_key): State<Arc<MasterKey>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    query: Option<Query<TranscriptQuery>>,
+) -> Result<Response, Response> {
+    let Query(query) = query.unwrap_or_default();
+    let access = fathom_access(&pb_manager, &master_key, &config, &rate_limits, &user).await?;
+    let cache = cache_client(&pb_manager, &user.id, &transcript_schema()).await;
+
+    let cached = match &cache {
+        Some(pb) if !query.refresh => get_cached_transcript(pb, &id).await.unwrap_or_else(|e| {
+            warn!("Could not read {}'s transcripts cache: {}", user.id, e);
+            None
         }),
-    ];
+        _ => None,
+    };
+    if let Some((segments, fetched_at)) = cached {
+        return Ok(transcript(id, segments, true, fetched_at, query.format));
+    }
 
-    // TODO: Implement caching to user PocketBase
-    // For now, indicate data is not cached
-    Ok(Json(MeetingsResponse {
-        success: true,
-        meetings: mock_meetings,
-        total: 2,
-        cached: false,
-    }))
+    let fetched = access
+        .client
+        .transcript(&id)
+        .await
+        .map_err(|e| fathom_error(&user, e))?;
+    access.touch(&user).await;
+    let segments = match fetched {
+        Transcript::Ready(segments) => segments,
+        Transcript::Processing { retry_after } => {
+            let seconds = retry_after.as_secs().max(1);
+            return Ok((
+                StatusCode::ACCEPTED,
+                [(header::RETRY_AFTER, seconds.to_string())],
+                Json(json!({
+                    "success": true,
+                    "meeting_id": id,
+                    "status": "processing",
+                    "retry_after": seconds,
+                })),
+            )
+                .into_response());
+        }
+    };
+    let fetched_at = Utc::now();
+    if let Some(pb) = &cache {
+        if let Err(e) = cache_transcript_to_pb(pb, &id, &segments, fetched_at).await {
+            warn!("Could not cache {}'s transcript of {}: {}", user.id, id, e);
+        }
+    }
+    Ok(transcript(id, segments, false, fetched_at, query.format))
 }
 
-/// Cache meetings data to user's PocketBase instance
-async fn cache_meetings_to_pb(
-    user_id: &str,
-    meetings: &[Value],
+/// A finished transcript as `format`, its body written out
+/// [`SEGMENTS_PER_CHUNK`] segments at a time
+fn transcript(
+    meeting_id: String,
+    segments: Vec<TranscriptSegment>,
+    cached: bool,
+    fetched_at: DateTime<Utc>,
+    format: TranscriptFormat,
+) -> Response {
+    let (content_type, mut head, tail) = match format {
+        TranscriptFormat::Text => ("text/plain; charset=utf-8", String::new(), ""),
+        TranscriptFormat::Segments => {
+            let empty = serde_json::to_string(&TranscriptResponse {
+                success: true,
+                meeting_id,
+                cached,
+                fetched_at,
+                segments: Vec::new(),
+            })
+            .expect("transcript responses serialize");
+            // `segments` comes last, so its items go between `[` and `]}`
+            let head = empty
+                .strip_suffix("]}")
+                .expect("segments is the last field")
+                .to_string();
+            ("application/json", head, "]}")
+        }
+    };
+
+    let mut segments = segments.into_iter().enumerate();
+    let mut done = false;
+    let chunks = std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let mut chunk = std::mem::take(&mut head);
+        for (i, segment) in segments.by_ref().take(SEGMENTS_PER_CHUNK) {
+            match format {
+                TranscriptFormat::Text => chunk.push_str(&text_line(&segment)),
+                TranscriptFormat::Segments => {
+                    if i > 0 {
+                        chunk.push(',');
+                    }
+                    let segment =
+                        serde_json::to_string(&segment).expect("transcript segments serialize");
+                    chunk.push_str(&segment);
+                }
+            }
+        }
+        if segments.len() == 0 {
+            chunk.push_str(tail);
+            done = true;
+        }
+        Some(Ok::<_, Infallible>(chunk))
+    });
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(stream::iter(chunks)),
+    )
+        .into_response()
+}
+
+/// `[HH:MM:SS] Speaker: text` and a newline
+fn text_line(segment: &TranscriptSegment) -> String {
+    let seconds = segment.start_ms / 1000;
+    format!(
+        "[{:02}:{:02}:{:02}] {}: {}\n",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        segment.speaker,
+        segment.text.trim()
+    )
+}
+
+fn detail(meeting: FathomMeetingDetail, cached: bool, fetched_at: DateTime<Utc>) -> Response {
+    (
+        StatusCode::OK,
+        Json(MeetingDetailResponse {
+            success: true,
+            meeting,
+            cached,
+            fetched_at,
+        }),
+    )
+        .into_response()
+}
+
+/// The caller's default Fathom key, decrypted into a client paced by their
+/// share of `rate_limits`, unless there is none or it has expired
+async fn fathom_access(
+    pb_manager: &PocketBaseManager,
+    master_key: &MasterKey,
     config: &Config,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // TODO: Implement caching logic
-    // 1. Get user's PocketBase instance URL
-    // 2. Store meetings data in user's PB database
-    // 3. Set appropriate TTL/expiration
-    info!("Caching {} meetings for user {}", meetings.len(), user_id);
-    Ok(())
+    rate_limits: &RateLimits,
+    user: &AuthUser,
+) -> Result<FathomAccess, Response> {
+    let repository = repository(pb_manager, master_key, user).await?;
+    let Some(stored) = repository
+        .resolve("fathom", None)
+        .await
+        .map_err(store_error)?
+    else {
+        return Err(auth_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "No Fathom API key is stored; add one in Settings",
+        ));
+    };
+    if stored.key.is_expired_at(Utc::now()) {
+        return Err(auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::KeyExpired,
+            "Your Fathom API key has expired; replace it in Settings",
+        ));
+    }
+
+    let value = repository.decrypt(&stored.key).map_err(|e| {
+        warn!(
+            "Stored fathom key {} of {} does not decrypt: {}",
+            stored.key.key_id, user.id, e
+        );
+        with_cause(
+            auth_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                "Stored API key could not be read",
+            ),
+            AppError::Internal(format!("Stored key does not decrypt: {}", e)),
+        )
+    })?;
+    let client = FathomClient::new(&config.integrations.fathom_api_url, &value)
+        .with_limiter(rate_limits.fathom.clone(), &user.id);
+    drop(value);
+    Ok(FathomAccess {
+        repository,
+        stored,
+        client,
+    })
+}
+
+/// The listing as the body of a 200, or a bodyless 304 when `If-None-Match`
+/// names its ETag
+fn listing(
+    page: MeetingsPage,
+    cached: bool,
+    fetched_at: DateTime<Utc>,
+    notice: Option<String>,
+    filters_applied: BTreeMap<String, AppliedBy>,
+    (etag, if_none_match): (String, Option<&str>),
+) -> Response {
+    if if_none_match.is_some_and(|tags| etag_matches(tags, &etag)) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        StatusCode::OK,
+        [(header::ETAG, etag)],
+        Json(MeetingsResponse {
+            success: true,
+            meetings: page.meetings,
+            total: page.total,
+            next_cursor: page.next_cursor,
+            cached,
+            fetched_at,
+            notice,
+            filters_applied,
+        }),
+    )
+        .into_response()
+}
+
+/// Strong ETag of the listing fetched at `fetched_at` for `cache_key`
+///
+/// The cache key holds the page asked for and the normalized filters, so
+/// different queries never share a tag; `fetched_at` changes it with every
+/// fetch from Fathom.
+fn listing_etag(cache_key: &str, page: &MeetingsPage, fetched_at: DateTime<Utc>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(cache_key.as_bytes());
+    hasher.update(fetched_at.to_rfc3339().as_bytes());
+    hasher.update(
+        serde_json::to_vec(&(&page.meetings, page.total, &page.next_cursor)).unwrap_or_default(),
+    );
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("\"{}\"", hash)
 }
 
-/// Fetch meetings from user's PocketBase cache
-async fn get_cached_meetings(
+/// Whether the `If-None-Match` value `tags` names `etag`
+///
+/// The comparison is the weak one RFC 9110 asks of `If-None-Match`.
+fn etag_matches(tags: &str, etag: &str) -> bool {
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Fetch the listing under `cache_key` again and cache it
+async fn revalidate(
+    pb: Arc<GlobalPb>,
+    client: FathomClient,
+    cache_key: String,
+    request: PageRequest,
+    filter: MeetingFilter,
+) {
+    let refreshed = match request.fetch(&client, &filter).await {
+        Ok(page) => cache_meetings_to_pb(&pb, &cache_key, &page, Utc::now())
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = refreshed {
+        warn!("Background refresh of a meetings listing failed: {}", e);
+    }
+}
+
+/// The response for a failed Fathom request
+fn fathom_error(user: &AuthUser, e: FathomError) -> Response {
+    warn!("Fathom request for {} failed: {}", user.id, e);
+    match e {
+        FathomError::Unauthorized => auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::KeyRejected,
+            "Fathom rejected your API key; fix it in Settings",
+        ),
+        FathomError::RateLimited { retry_after } => rate_limited(
+            retry_after,
+            "Fathom is rate limiting requests; try again later",
+        ),
+        FathomError::Throttled { retry_after } => rate_limited(
+            Some(retry_after),
+            "Too many Fathom requests; try again shortly",
+        ),
+        FathomError::NotFound => auth_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "No such meeting in your Fathom account",
+        ),
+        _ => auth_error(
+            StatusCode::BAD_GATEWAY,
+            ErrorCode::ServiceUnavailable,
+            "Fathom could not answer; try again later",
+        ),
+    }
+}
+
+/// `user_id`'s instance with the cache collection `schema` in place, or
+/// `None` when that can't be had and the cache is skipped
+async fn cache_client(
+    pb_manager: &PocketBaseManager,
     user_id: &str,
-    config: &Config,
-) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
-    // TODO: Implement cache retrieval logic
-    // 1. Get user's PocketBase instance URL
-    // 2. Query cached meetings data
-    // 3. Check if cache is still valid
-    info!("Checking cache for user {}", user_id);
-    Ok(None)
+    schema: &Value,
+) -> Option<Arc<GlobalPb>> {
+    let pb = match pb_manager.admin_client(user_id).await {
+        Ok(pb) => pb,
+        Err(e) => {
+            warn!("Meetings cache of {} unavailable: {}", user_id, e);
+            return None;
+        }
+    };
+    match pb.ensure_collection(schema).await {
+        Ok(_) => Some(pb),
+        Err(e) => {
+            warn!("Meetings cache of {} unavailable: {}", user_id, e);
+            None
+        }
+    }
+}
+
+/// Store `page` under `cache_key`, replacing what was cached there
+pub async fn cache_meetings_to_pb(
+    pb: &GlobalPb,
+    cache_key: &str,
+    page: &MeetingsPage,
+    fetched_at: DateTime<Utc>,
+) -> Result<(), GlobalPbError> {
+    let record = json!({
+        "cache_key": cache_key,
+        "listing": page,
+        "fetched_at": fetched_at.to_rfc3339(),
+    });
+    let filter = format!("cache_key = {}", quote(cache_key));
+    upsert(pb, MEETINGS_CACHE, &filter, &record).await
+}
+
+/// Delete the listings fetched before `before`, returning how many there were
+pub async fn clear_meetings_cache(
+    pb: &GlobalPb,
+    before: DateTime<Utc>,
+) -> Result<usize, GlobalPbError> {
+    let mut cleared = 0;
+    for record in pb.list_records(MEETINGS_CACHE, None).await? {
+        let fetched_at = record["fetched_at"]
+            .as_str()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+        if fetched_at.is_some_and(|at| at >= before) {
+            continue;
+        }
+        if let Some(id) = record["id"].as_str() {
+            pb.delete_record(MEETINGS_CACHE, id).await?;
+            cleared += 1;
+        }
+    }
+    Ok(cleared)
+}
+
+/// Replace the record of `collection` matching `filter` with `record`, or
+/// create it
+async fn upsert(
+    pb: &GlobalPb,
+    collection: &str,
+    filter: &str,
+    record: &Value,
+) -> Result<(), GlobalPbError> {
+    let existing = pb.list_records(collection, Some(filter)).await?;
+    match existing.first().and_then(|record| record["id"].as_str()) {
+        Some(id) => pb.update_record(collection, id, record).await?,
+        None => pb.create_record(collection, record).await?,
+    };
+    Ok(())
+}
+
+/// Store `meeting` under its id, replacing what was cached there
+pub async fn cache_detail_to_pb(
+    pb: &GlobalPb,
+    meeting: &FathomMeetingDetail,
+    fetched_at: DateTime<Utc>,
+) -> Result<(), GlobalPbError> {
+    let record = json!({
+        "meeting_id": meeting.meeting.id,
+        "detail": meeting,
+        "fetched_at": fetched_at.to_rfc3339(),
+    });
+    let filter = format!("meeting_id = {}", quote(&meeting.meeting.id));
+    upsert(pb, MEETING_DETAILS, &filter, &record).await
+}
+
+/// The meeting `id` as cached and when it was fetched, however long ago
+pub async fn get_cached_detail(
+    pb: &GlobalPb,
+    id: &str,
+) -> Result<Option<(FathomMeetingDetail, DateTime<Utc>)>, GlobalPbError> {
+    let filter = format!("meeting_id = {}", quote(id));
+    let Some(record) = pb
+        .list_records(MEETING_DETAILS, Some(&filter))
+        .await?
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+    let meeting = serde_json::from_value(record["detail"].clone()).ok();
+    let fetched_at = record["fetched_at"]
+        .as_str()
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+    match (meeting, fetched_at) {
+        (Some(meeting), Some(fetched_at)) => Ok(Some((meeting, fetched_at.with_timezone(&Utc)))),
+        _ => {
+            warn!("Ignoring malformed meeting details record {}", record["id"]);
+            Ok(None)
+        }
+    }
+}
+
+/// Store the finished transcript of meeting `id`, replacing what was cached
+pub async fn cache_transcript_to_pb(
+    pb: &GlobalPb,
+    id: &str,
+    segments: &[TranscriptSegment],
+    fetched_at: DateTime<Utc>,
+) -> Result<(), GlobalPbError> {
+    let record = json!({
+        "meeting_id": id,
+        "segments": segments,
+        "fetched_at": fetched_at.to_rfc3339(),
+    });
+    let filter = format!("meeting_id = {}", quote(id));
+    upsert(pb, MEETING_TRANSCRIPTS, &filter, &record).await
+}
+
+/// The transcript of meeting `id` as cached and when it was fetched
+pub async fn get_cached_transcript(
+    pb: &GlobalPb,
+    id: &str,
+) -> Result<Option<(Vec<TranscriptSegment>, DateTime<Utc>)>, GlobalPbError> {
+    let filter = format!("meeting_id = {}", quote(id));
+    let Some(record) = pb
+        .list_records(MEETING_TRANSCRIPTS, Some(&filter))
+        .await?
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+    let segments = serde_json::from_value(record["segments"].clone()).ok();
+    let fetched_at = record["fetched_at"]
+        .as_str()
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+    match (segments, fetched_at) {
+        (Some(segments), Some(fetched_at)) => Ok(Some((segments, fetched_at.with_timezone(&Utc)))),
+        _ => {
+            warn!("Ignoring malformed transcript record {}", record["id"]);
+            Ok(None)
+        }
+    }
+}
+
+/// The listing cached under `cache_key`, however old
+///
+/// A record that doesn't parse counts as a miss, to be overwritten.
+pub async fn get_cached_meetings(
+    pb: &GlobalPb,
+    cache_key: &str,
+) -> Result<Option<CachedMeetings>, GlobalPbError> {
+    let filter = format!("cache_key = {}", quote(cache_key));
+    let Some(record) = pb
+        .list_records(MEETINGS_CACHE, Some(&filter))
+        .await?
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+    let page = serde_json::from_value(record["listing"].clone()).ok();
+    let fetched_at = record["fetched_at"]
+        .as_str()
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+    match (page, fetched_at) {
+        (Some(page), Some(fetched_at)) => Ok(Some(CachedMeetings {
+            page,
+            fetched_at: fetched_at.with_timezone(&Utc),
+        })),
+        _ => {
+            warn!("Ignoring malformed meetings cache record {}", record["id"]);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, key_repository::KeyRepository, AppState},
+        config::IntegrationsConfig,
+        test_support::{
+            fake_pocketbase, mock_global_pocketbase, port_window, spawn_server, test_app_state, test_config,
+        },
+    };
+    use axum::{body::Body, http::HeaderMap, http::Request};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const GOOD_KEY: &str = "fathom-good-0123456789";
+
+    /// Fathom lookalike with three meetings, one per page, for [`GOOD_KEY`],
+    /// counting the listings, recordings and transcripts it serves in
+    /// `listings`; the transcript of 2 is ready and that of 3 in progress
+    async fn mock_fathom(listings: Arc<AtomicUsize>) -> String {
+        let served = listings.clone();
+        let recording = move |Path(id): Path<String>, headers: HeaderMap| async move {
+            served.fetch_add(1, Ordering::SeqCst);
+            match id.as_str() {
+                "2" => {
+                    let mut detail = item(2);
+                    detail["default_summary"] = json!({ "markdown_formatted": "Roadmap review" });
+                    detail["recording_size_bytes"] = json!(1_048_576);
+                    let host = headers["host"].to_str().unwrap();
+                    detail["download_url"] = json!(format!("http://{}/media/2?sig=secret", host));
+                    Json(detail).into_response()
+                }
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        };
+        let transcribed = listings.clone();
+        let transcript = move |Path(id): Path<String>| async move {
+            transcribed.fetch_add(1, Ordering::SeqCst);
+            let line = |name: &str, text: &str, at: &str| json!({ "speaker": { "display_name": name }, "text": text, "timestamp": at });
+            match id.as_str() {
+                "2" => Json(json!({ "transcript": [
+                    line("Alice", "Roadmap first. ", "00:00:01"),
+                    line("Bob", "Agreed.", "00:00:09"),
+                ] }))
+                .into_response(),
+                "3" => (StatusCode::ACCEPTED, [("retry-after", "20")]).into_response(),
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        };
+        let meetings =
+            move |headers: HeaderMap,
+                  Query(query): Query<std::collections::HashMap<String, String>>| async move {
+                let key = headers
+                    .get("x-api-key")
+                    .and_then(|value| value.to_str().ok());
+                if key != Some(GOOD_KEY) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                let (n, next_cursor) = match query.get("cursor").map(String::as_str) {
+                    None => {
+                        listings.fetch_add(1, Ordering::SeqCst);
+                        (1, Some("p2"))
+                    }
+                    Some("p2") => (2, Some("p3")),
+                    _ => (3, None),
+                };
+                // Fathom's own filters
+                let meeting = item(n);
+                let at = |name: &str| {
+                    query
+                        .get(name)
+                        .map(|at| DateTime::parse_from_rfc3339(at).unwrap())
+                };
+                let start =
+                    DateTime::parse_from_rfc3339(meeting["recording_start_time"].as_str().unwrap())
+                        .unwrap();
+                let invited = query.get("calendar_invitees[]").is_none_or(|email| {
+                    meeting["calendar_invitees"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .any(|invitee| invitee["email"] == email.as_str())
+                });
+                let kept = at("created_after").is_none_or(|after| start >= after)
+                    && at("created_before").is_none_or(|before| start < before)
+                    && invited;
+                let items = if kept { vec![meeting] } else { vec![] };
+                Json(json!({ "next_cursor": next_cursor, "items": items })).into_response()
+            };
+        fn item(n: u32) -> Value {
+            let alice = json!({ "name": "Alice", "email": "alice@example.com" });
+            let (start, end, invitees) = match n {
+                1 => (
+                    "2026-05-01T09:00:00Z",
+                    "2026-05-01T09:30:00Z",
+                    json!([alice, { "name": null, "email": "bob@example.com" }]),
+                ),
+                2 => (
+                    "2026-05-04T09:00:00Z",
+                    "2026-05-04T09:45:00Z",
+                    json!([alice]),
+                ),
+                _ => (
+                    "2026-05-10T14:00:00Z",
+                    "2026-05-10T15:30:00Z",
+                    json!([{ "name": "Carol", "email": "carol@example.com" }]),
+                ),
+            };
+            json!({
+                "recording_id": n,
+                "title": format!("Call {}", n),
+                "recording_start_time": start,
+                "recording_end_time": end,
+                "calendar_invitees": invitees
+            })
+        }
+        spawn_server(
+            Router::new()
+                .route("/meetings", get(meetings))
+                .route("/recordings/:id", get(recording))
+                .route("/recordings/:id/transcript", get(transcript))
+                .route(
+                    "/media/2",
+                    get(|| async { ([("content-type", "video/mp4")], vec![0u8; 4096]) }),
+                ),
+        )
+        .await
+    }
+
+    /// State for alice, with the count of listings Fathom served
+    async fn setup(dir: &std::path::Path) -> (AppState, Arc<AtomicUsize>) {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let mut config = test_config(&global_url);
+        let listings = Arc::new(AtomicUsize::new(0));
+        config.integrations.fathom_api_url = mock_fathom(listings.clone()).await;
+        let state = test_app_state(config, manager);
+        let data_dir = state.pb_manager.data_dir("alice");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        (state, listings)
+    }
+
+    async fn list(state: &AppState, query: &str) -> (StatusCode, Value) {
+        list_as(state, Some("valid-alice"), query).await
+    }
+
+    /// `/api/meetings{query}` with `token`, if any
+    async fn list_as(state: &AppState, token: Option<&str>, query: &str) -> (StatusCode, Value) {
+        let mut request = Request::builder().uri(format!("/api/meetings{}", query));
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    /// `/api/meetings{query}` as alice with `If-None-Match: if_none_match`:
+    /// the status, ETag and body
+    async fn list_if_none_match(
+        state: &AppState,
+        query: &str,
+        if_none_match: Option<&str>,
+    ) -> (StatusCode, String, Vec<u8>) {
+        let mut request = Request::builder()
+            .uri(format!("/api/meetings{}", query))
+            .header("authorization", "Bearer valid-alice");
+        if let Some(tags) = if_none_match {
+            request = request.header("if-none-match", tags);
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, etag, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_listings_answer_304() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, listings) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let (status, etag, body) = list_if_none_match(&state, "", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+        assert!(!body.is_empty());
+
+        let (status, repeat, body) = list_if_none_match(&state, "", Some(&etag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(repeat, etag);
+        assert!(body.is_empty());
+        let tags = format!("\"other\", W/{}", etag);
+        let (status, _, _) = list_if_none_match(&state, "", Some(&tags)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        let (status, _, _) = list_if_none_match(&state, "", Some("\"other\"")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+        // Other filters and pages have tags of their own
+        let mut tags = vec![etag.clone()];
+        for query in [
+            "?q=standup",
+            "?q=STANDUP%20",
+            "?limit=1",
+            "?min_duration_secs=60",
+        ] {
+            let (status, other, _) = list_if_none_match(&state, query, Some(&etag)).await;
+            assert_eq!(status, StatusCode::OK, "{}", query);
+            tags.push(other);
+        }
+        assert_eq!(tags[1], tags[2], "filters are normalized");
+        tags.dedup();
+        assert_eq!(tags.len(), 4, "{:?}", tags);
+
+        // A refresh is a new listing
+        let (status, _) = refresh(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, fresh, body) = list_if_none_match(&state, "", Some(&etag)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(fresh, etag);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap()["cached"],
+            true
+        );
+        let (status, _, _) = list_if_none_match(&state, "", Some(&fresh)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_meetings_come_from_fathom_with_the_stored_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = setup(dir.path()).await;
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+
+        let (status, body) = list(&state, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+
+        repository
+            .put("fathom", "default", GOOD_KEY, None)
+            .await
+            .unwrap();
+        let (status, body) = list(&state, "?limit=2&offset=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["cached"], false);
+        let meetings = body["meetings"].as_array().unwrap();
+        assert_eq!(meetings.len(), 2);
+        assert_eq!(
+            (&meetings[0]["id"], &meetings[1]["id"]),
+            (&json!("2"), &json!("3"))
+        );
+        assert_eq!(meetings[0]["duration"], 2700);
+        assert_eq!(
+            meetings[0]["participants"],
+            json!([{ "display_name": "Alice", "email": "alice@example.com" }])
+        );
+        let stored = repository.get("fathom", "default").await.unwrap().unwrap();
+        assert!(stored.last_used_at.is_some());
+
+        repository
+            .put("fathom", "default", "fathom-revoked-0123456789", None)
+            .await
+            .unwrap();
+        let (status, body) = list(&state, "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "key_rejected");
+        assert!(body["error"].as_str().unwrap().contains("Settings"));
+        assert!(!body.to_string().contains("0123456789"));
+
+        repository
+            .put(
+                "fathom",
+                "default",
+                GOOD_KEY,
+                Some(Utc::now() - chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+        let (status, body) = list(&state, "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "key_expired");
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_callers_get_their_own_meetings_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, listings) = setup(dir.path()).await;
+        alice_repository(&state).await;
+        let data_dir = state.pb_manager.data_dir("bob");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        // Fathom turns bob's key down
+        KeyRepository::open(&state.pb_manager, "bob", &state.master_key)
+            .await
+            .unwrap()
+            .put("fathom", "default", "fathom-bob-0123456789", None)
+            .await
+            .unwrap();
+
+        for path in ["", "/2", "/2/transcript"] {
+            let (status, body) = list_as(&state, None, path).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", path);
+            assert_eq!(body["error"], "missing_authorization");
+            let (status, body) = list_as(&state, Some("forged-token"), path).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", path);
+            assert_eq!(body["error"], "invalid_token");
+        }
+
+        let (status, body) = list_as(&state, Some("valid-alice"), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 3);
+        let (status, body) = list_as(&state, Some("valid-bob"), "").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "key_rejected");
+
+        // Naming another user changes nothing: bob can't reach alice's
+        // cached listing, and alice still gets her own
+        let (status, body) = list_as(&state, Some("valid-bob"), "?user_id=alice").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "key_rejected");
+        let (status, body) = list_as(&state, Some("valid-alice"), "?user_id=bob").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cached"], true);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+        let _ = state.pb_manager.stop_user_instance("bob").await;
+    }
+
+    async fn alice_repository(state: &AppState) -> KeyRepository {
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        repository
+            .put("fathom", "default", GOOD_KEY, None)
+            .await
+            .unwrap();
+        repository
+    }
+
+    /// `method` `uri` as the user of `token`, with `body` as JSON if any
+    async fn call(
+        state: &AppState,
+        method: &str,
+        uri: &str,
+        token: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_downloadability_is_checked_with_fathom() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let check = |id: &'static str| {
+            let state = state.clone();
+            async move {
+                let uri = format!("/api/meetings/{}/downloadable", id);
+                call(&state, "GET", &uri, "valid-alice", None).await
+            }
+        };
+        let (status, body) = check("2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "downloadable": true,
+                "reason": "available",
+                "size_bytes": 4096,
+                "content_type": "video/mp4"
+            })
+        );
+        let (status, body) = check("9").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({ "downloadable": false, "reason": "not_found" })
+        );
+        let (status, body) = check("1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["reason"], "not_found");
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_queueing_can_verify_the_download_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = setup(dir.path()).await;
+        let record =
+            json!({ "email": "vera@example.com", "password": "password", "verified": true });
+        let user = state
+            .global_pb
+            .create_record("users", &record)
+            .await
+            .unwrap();
+        let user_id = user["id"].as_str().unwrap().to_string();
+        let data_dir = state.pb_manager.data_dir(&user_id);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        KeyRepository::open(&state.pb_manager, &user_id, &state.master_key)
+            .await
+            .unwrap()
+            .put("fathom", "default", GOOD_KEY, None)
+            .await
+            .unwrap();
+        let token = format!("valid-{}", user_id);
+        let meeting = |id: &str| json!({ "user_id": user_id, "topic": "Call", "meeting_id": id });
+
+        let (status, body) = call(
+            &state,
+            "POST",
+            "/api/queue?verify=true",
+            &token,
+            Some(meeting("9")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["data"]["reason"], "not_found");
+        assert!(state.meetings_queue.read().await.is_empty());
+
+        let (status, _) = call(
+            &state,
+            "POST",
+            "/api/queue?verify=true",
+            &token,
+            Some(meeting("2")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // Unverified, anything goes
+        let (status, _) = call(&state, "POST", "/api/queue", &token, Some(meeting("9"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let queued: Vec<_> = state
+            .meetings_queue
+            .read()
+            .await
+            .iter()
+            .map(|meeting| meeting.meeting_id.clone().unwrap())
+            .collect();
+        assert_eq!(queued, ["2", "9"]);
+
+        let _ = state.pb_manager.stop_user_instance(&user_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_spent_fathom_budgets_answer_429_without_calling_fathom() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut state, fetches) = setup(dir.path()).await;
+        with_limits(&mut state, |integrations| {
+            integrations.fathom_requests_per_minute = 2
+        });
+        alice_repository(&state).await;
+
+        for _ in 0..2 {
+            let (status, _) = list(&state, "/2?refresh=true").await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        let (status, body) = list(&state, "/2?refresh=true").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+        let wait = body["data"]["retry_after_secs"].as_u64().unwrap();
+        assert!((1..=30).contains(&wait), "{}", wait);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // What is cached is still served
+        let (status, body) = list(&state, "/2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cached"], true);
+
+        let stats = state.rate_limits.fathom.stats().await;
+        assert_eq!((stats.bucket.allowed, stats.bucket.refused), (2, 1));
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    async fn refresh(state: &AppState, query: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/api/meetings/refresh{}", query))
+            .header("authorization", "Bearer valid-alice")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    /// `state` with `integrations` changed as `change` does
+    fn with_limits(state: &mut AppState, change: impl FnOnce(&mut IntegrationsConfig)) {
+        let mut integrations = state.config.integrations.clone();
+        change(&mut integrations);
+        state.rate_limits = Arc::new(RateLimits::new(&state.config.security, &integrations, &state.config.rate_limits));
+    }
+
+    #[tokio::test]
+    async fn test_refreshing_drops_the_cache_and_refetches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, listings) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let (_, first) = list(&state, "").await;
+        let (_, other) = list(&state, "?limit=1").await;
+        assert_eq!(
+            (first["cached"].clone(), other["cached"].clone()),
+            (json!(false), json!(false))
+        );
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+
+        let (status, refreshed) = refresh(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(refreshed["cached"], false);
+        assert_eq!(refreshed["total"], 3);
+        assert_ne!(refreshed["fetched_at"], first["fetched_at"]);
+        assert_eq!(listings.load(Ordering::SeqCst), 3);
+
+        // The fresh listing is what is cached now, and other pages are gone
+        let (_, after) = list(&state, "").await;
+        assert_eq!(after["cached"], true);
+        assert_eq!(after["fetched_at"], refreshed["fetched_at"]);
+        let (_, other) = list(&state, "?limit=1").await;
+        assert_eq!(other["cached"], false);
+        assert_eq!(listings.load(Ordering::SeqCst), 4);
+
+        // Only once per cooldown
+        let (status, body) = refresh(&state, "").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+        let wait = body["data"]["retry_after_secs"].as_u64().unwrap();
+        assert!((1..=30).contains(&wait), "{}", wait);
+        assert_eq!(listings.load(Ordering::SeqCst), 4);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_refreshing_within_the_fathom_budget_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut state, listings) = setup(dir.path()).await;
+        // A listing walks three pages; enough for one
+        with_limits(&mut state, |integrations| {
+            integrations.fathom_requests_per_minute = 3;
+            integrations.meetings_refresh_cooldown = Duration::ZERO;
+        });
+        alice_repository(&state).await;
+
+        let (status, refreshed) = refresh(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+        let (status, body) = refresh(&state, "").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(body["data"]["retry_after_secs"].as_u64().unwrap() >= 1);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+        // The failed refresh left the cache alone
+        let (status, cached) = list(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cached["cached"], true);
+        assert_eq!(cached["fetched_at"], refreshed["fetched_at"]);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_listings_are_served_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, listings) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let (status, first) = list(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["cached"], false);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+        let (status, second) = list(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["cached"], true);
+        assert_eq!(second["fetched_at"], first["fetched_at"]);
+        assert_eq!(second["meetings"], first["meetings"]);
+        assert_eq!(second["total"], 3);
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+        // Refreshing asks Fathom and caches what it answers
+        let (_, refreshed) = list(&state, "?refresh=true").await;
+        assert_eq!(refreshed["cached"], false);
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+        let (_, after) = list(&state, "").await;
+        assert_eq!(after["cached"], true);
+        assert_eq!(after["fetched_at"], refreshed["fetched_at"]);
+
+        // Another page is cached apart
+        let (_, other) = list(&state, "?offset=1").await;
+        assert_eq!(other["cached"], false);
+        assert_eq!(listings.load(Ordering::SeqCst), 3);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_stale_listings_are_revalidated_and_old_ones_refetched() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, listings) = setup(dir.path()).await;
+        let repository = alice_repository(&state).await;
+        let fingerprint = repository
+            .get("fathom", "default")
+            .await
+            .unwrap()
+            .unwrap()
+            .fingerprint;
+        let pb = cache_client(&state.pb_manager, "alice", &cache_schema())
+            .await
+            .unwrap();
+        let cache_key = format!("{}:{}:0", fingerprint, DEFAULT_LIMIT);
+        let placeholder = MeetingsPage {
+            meetings: vec![],
+            total: Some(0),
+            next_cursor: None,
+        };
+        let integrations = &state.config.integrations;
+        let ttl = chrono::Duration::from_std(integrations.meetings_cache_ttl).unwrap();
+        let stale = chrono::Duration::from_std(integrations.meetings_cache_stale).unwrap();
+
+        // Past the TTL but usable: served, then refreshed behind the scenes
+        let fetched_at = Utc::now() - ttl - chrono::Duration::seconds(1);
+        cache_meetings_to_pb(&pb, &cache_key, &placeholder, fetched_at)
+            .await
+            .unwrap();
+        let (status, body) = list(&state, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&body["cached"], &body["total"]), (&json!(true), &json!(0)));
+        let mut waited = 0;
+        loop {
+            let cached = get_cached_meetings(&pb, &cache_key).await.unwrap().unwrap();
+            if cached.page.total == Some(3) {
+                break;
+            }
+            waited += 1;
+            assert!(waited < 100, "the stale listing was never refreshed");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+        let (_, body) = list(&state, "").await;
+        assert_eq!((&body["cached"], &body["total"]), (&json!(true), &json!(3)));
+
+        // Too old to serve at all
+        let fetched_at = Utc::now() - ttl - stale - chrono::Duration::seconds(1);
+        cache_meetings_to_pb(&pb, &cache_key, &placeholder, fetched_at)
+            .await
+            .unwrap();
+        let (_, body) = list(&state, "").await;
+        assert_eq!(
+            (&body["cached"], &body["total"]),
+            (&json!(false), &json!(3))
+        );
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_pages_follow_fathom_cursors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = setup(dir.path()).await;
+        alice_repository(&state).await;
+        let ids = |body: &Value| {
+            body["meetings"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|meeting| meeting["id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let (status, first) = list(&state, "?limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&first), ["1"]);
+        assert_eq!(first["total"], 3);
+        assert_eq!(first["next_cursor"], "p2");
+        assert_eq!(first["notice"], Value::Null);
+
+        let (status, second) = list(&state, "?cursor=p2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&second), ["2"]);
+        assert_eq!(second["next_cursor"], "p3");
+        // Only first pages are counted
+        assert!(second.get("total").is_none());
+
+        let (_, last) = list(&state, "?cursor=p3&limit=5").await;
+        assert_eq!(ids(&last), ["3"]);
+        assert_eq!(last["next_cursor"], Value::Null);
+        assert!(last["notice"].as_str().unwrap().contains("limit"));
+
+        // A first page holding everything is the last page too
+        let (_, all) = list(&state, "").await;
+        assert_eq!(ids(&all), ["1", "2", "3"]);
+        assert_eq!(all["next_cursor"], Value::Null);
+
+        let (status, body) = list(&state, "?cursor=p2&offset=1").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_listings_are_filtered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = setup(dir.path()).await;
+        alice_repository(&state).await;
+        let filtered = |query: &'static str| {
+            let state = state.clone();
+            async move {
+                let (status, body) = list(&state, query).await;
+                assert_eq!(status, StatusCode::OK, "{}: {}", query, body);
+                let ids: Vec<String> = body["meetings"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|meeting| meeting["id"].as_str().unwrap().to_string())
+                    .collect();
+                assert_eq!(body["total"], ids.len(), "{}", query);
+                (ids, body["filters_applied"].clone())
+            }
+        };
+
+        let (ids, applied) = filtered("").await;
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert_eq!(applied, Value::Null);
+
+        let (ids, applied) = filtered("?q=CALL%203").await;
+        assert_eq!(ids, ["3"]);
+        assert_eq!(applied, json!({ "q": "local" }));
+
+        let (ids, applied) = filtered("?from=2026-05-04").await;
+        assert_eq!(ids, ["2", "3"]);
+        assert_eq!(applied, json!({ "from": "fathom" }));
+        // A date `to` takes in the whole day
+        let (ids, _) = filtered("?to=2026-05-04").await;
+        assert_eq!(ids, ["1", "2"]);
+        let (ids, _) = filtered("?to=2026-05-04T09:00:00Z").await;
+        assert_eq!(ids, ["1"]);
+
+        let (ids, applied) = filtered("?participant=carol").await;
+        assert_eq!(ids, ["3"]);
+        assert_eq!(applied, json!({ "participant": "local" }));
+        let (ids, applied) = filtered("?participant=bob@example.com").await;
+        assert_eq!(ids, ["1"]);
+        assert_eq!(applied, json!({ "participant": "fathom" }));
+
+        let (ids, applied) = filtered("?min_duration_secs=2700").await;
+        assert_eq!(ids, ["2", "3"]);
+        assert_eq!(applied, json!({ "min_duration_secs": "local" }));
+
+        let (ids, applied) =
+            filtered("?q=call&from=2026-05-02&to=2026-05-31&min_duration_secs=3000").await;
+        assert_eq!(ids, ["3"]);
+        assert_eq!(
+            applied,
+            json!({ "q": "local", "from": "fathom", "to": "fathom", "min_duration_secs": "local" })
+        );
+        let (ids, _) = filtered("?from=2026-05-04&to=2026-05-04&participant=ALICE").await;
+        assert_eq!(ids, ["2"]);
+        let (ids, _) = filtered("?q=call%201&min_duration_secs=3000").await;
+        assert!(ids.is_empty());
+
+        for query in [
+            "?from=yesterday",
+            "?to=2026-13-01",
+            "?from=2026-05-05&to=2026-05-04",
+            "?from=2026-05-04T10:00:00Z&to=2026-05-04T10:00:00Z",
+        ] {
+            let (status, body) = list(&state, query).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", query);
+            assert_eq!(body["code"], "validation");
+        }
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    async fn detail_of(state: &AppState, id: &str) -> (StatusCode, Value) {
+        list(state, &format!("/{}", id)).await
+    }
+
+    #[tokio::test]
+    async fn test_meeting_details_are_fetched_and_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, served) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let (status, first) = detail_of(&state, "2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["cached"], false);
+        let meeting = &first["meeting"];
+        assert_eq!(
+            (&meeting["id"], &meeting["title"]),
+            (&json!("2"), &json!("Call 2"))
+        );
+        assert_eq!(meeting["summary"], "Roadmap review");
+        assert_eq!(meeting["share_url"], Value::Null);
+        assert_eq!(meeting["size_bytes"], 1_048_576);
+        assert_eq!(meeting["downloadable"], true);
+        assert!(!first.to_string().contains("sig=secret"));
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let (status, second) = detail_of(&state, "2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["cached"], true);
+        assert_eq!(second["meeting"], first["meeting"]);
+        assert_eq!(second["fetched_at"], first["fetched_at"]);
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let (_, refreshed) = detail_of(&state, "2?refresh=true").await;
+        assert_eq!(refreshed["cached"], false);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+
+        let (status, body) = detail_of(&state, "9").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    async fn transcript_of(state: &AppState, query: &str) -> (StatusCode, HeaderMap, String) {
+        let request = Request::builder()
+            .uri(format!("/api/meetings/{}", query))
+            .header("authorization", "Bearer valid-alice")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_transcripts_are_fetched_cached_and_flattened() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, served) = setup(dir.path()).await;
+        alice_repository(&state).await;
+
+        let (status, headers, body) = transcript_of(&state, "2/transcript").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "application/json");
+        let first: TranscriptResponse = serde_json::from_str(&body).unwrap();
+        assert!(first.success && !first.cached);
+        assert_eq!(first.meeting_id, "2");
+        assert_eq!(
+            first.segments,
+            [
+                TranscriptSegment {
+                    speaker: "Alice".to_string(),
+                    start_ms: 1_000,
+                    end_ms: 9_000,
+                    text: "Roadmap first. ".to_string(),
+                },
+                TranscriptSegment {
+                    speaker: "Bob".to_string(),
+                    start_ms: 9_000,
+                    end_ms: 9_000,
+                    text: "Agreed.".to_string(),
+                },
+            ]
+        );
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let (_, _, body) = transcript_of(&state, "2/transcript").await;
+        let second: TranscriptResponse = serde_json::from_str(&body).unwrap();
+        assert!(second.cached);
+        assert_eq!(second.fetched_at, first.fetched_at);
+        assert_eq!(second.segments, first.segments);
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let (status, headers, text) = transcript_of(&state, "2/transcript?format=text").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(
+            text,
+            "[00:00:01] Alice: Roadmap first.\n[00:00:09] Bob: Agreed.\n"
+        );
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let (_, _, body) = transcript_of(&state, "2/transcript?refresh=true").await;
+        let refreshed: TranscriptResponse = serde_json::from_str(&body).unwrap();
+        assert!(!refreshed.cached);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+
+        // In progress: not cached, so each request asks again
+        for _ in 0..2 {
+            let (status, headers, body) = transcript_of(&state, "3/transcript").await;
+            assert_eq!(status, StatusCode::ACCEPTED);
+            assert_eq!(headers["retry-after"], "20");
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(
+                (&body["status"], &body["retry_after"]),
+                (&json!("processing"), &json!(20))
+            );
+        }
+        assert_eq!(served.load(Ordering::SeqCst), 4);
+
+        let (status, _, _) = transcript_of(&state, "9/transcript").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+
+    #[test]
+    fn test_long_transcripts_are_written_in_chunks() {
+        let segments: Vec<_> = (0..SEGMENTS_PER_CHUNK as u64 * 2 + 1)
+            .map(|i| TranscriptSegment {
+                speaker: "Alice".to_string(),
+                start_ms: i * 61_000,
+                end_ms: (i + 1) * 61_000,
+                text: format!("Point {}", i),
+            })
+            .collect();
+        let response = transcript(
+            "2".to_string(),
+            segments.clone(),
+            false,
+            Utc::now(),
+            TranscriptFormat::Segments,
+        );
+        let body =
+            futures::executor::block_on(axum::body::to_bytes(response.into_body(), usize::MAX))
+                .unwrap();
+        let parsed: TranscriptResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(parsed.segments, segments);
+
+        assert_eq!(text_line(&segments[60]), "[01:01:00] Alice: Point 60\n");
+    }
 }
@@ -0,0 +1,200 @@
+//! The Prometheus scrape endpoint and the layer counting HTTP requests
+//!
+//! `/metrics` sits outside the authenticated routes so Prometheus can reach
+//! it without a user session. Set `METRICS_TOKEN` to make scrapes present it
+//! as a bearer token, or `METRICS_ENABLED=false` to turn the endpoint off.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::{crypto::constant_time_eq, metrics::EXPOSITION_CONTENT_TYPE};
+use std::time::Instant;
+
+use super::{request_limit::RouteClass, AppState};
+use crate::{
+    metrics::{
+        self, HTTP_REQUESTS, HTTP_REQUEST_DURATION, POCKETBASE_INSTANCES, QUEUE_LENGTH,
+        QUEUE_TASKS, RATE_LIMIT_CLIENTS, WEBSOCKET_CONNECTIONS,
+    },
+    pocketbase_manager::InstanceStatus,
+};
+
+/// Counts every request and its latency by method, matched route and status
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    // Raw paths would give every meeting id its own series
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    metrics::increment(
+        &HTTP_REQUESTS,
+        &[("method", &method), ("route", &route), ("status", &status)],
+    );
+    metrics::observe(
+        &HTTP_REQUEST_DURATION,
+        &[("method", &method), ("route", &route)],
+        started.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// GET /metrics - every metric family in the Prometheus text format
+pub async fn get_metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !state.config.server.metrics_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(expected) = state.config.security.metrics_token.as_deref() {
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !presented
+            .is_some_and(|presented| constant_time_eq(expected.as_bytes(), presented.as_bytes()))
+        {
+            return (
+                StatusCode::UNAUTHORIZED,
+                "A valid metrics token is required",
+            )
+                .into_response();
+        }
+    }
+
+    refresh_gauges(&state).await;
+    (
+        [(header::CONTENT_TYPE, EXPOSITION_CONTENT_TYPE)],
+        metrics::render(),
+    )
+        .into_response()
+}
+
+/// Set the gauges from the current state of the queue, sockets, instances
+/// and rate limits
+async fn refresh_gauges(state: &AppState) {
+    // The backend only holds meetings waiting for the worker to pick them up
+    let queued = state.meetings_queue.read().await.len() as f64;
+    metrics::set(&QUEUE_LENGTH, &[], queued);
+    metrics::set(&QUEUE_TASKS, &[("status", "queued")], queued);
+
+    let connections = state.ws_manager.connection_count().await;
+    metrics::set(&WEBSOCKET_CONNECTIONS, &[], connections as f64);
+
+    let instances = state.pb_manager.get_all_instances().await;
+    for (status, label) in [
+        (InstanceStatus::Starting, "starting"),
+        (InstanceStatus::Running, "running"),
+        (InstanceStatus::Failed, "failed"),
+        (InstanceStatus::Stopped, "stopped"),
+    ] {
+        let count = instances
+            .values()
+            .filter(|instance| instance.status == status)
+            .count();
+        metrics::set(&POCKETBASE_INSTANCES, &[("status", label)], count as f64);
+    }
+
+    for class in RouteClass::ALL {
+        if let Some(buckets) = state.rate_limits.requests.get(class) {
+            let clients = buckets.len().await;
+            metrics::set(&RATE_LIMIT_CLIENTS, &[("class", class.as_str())], clients as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::mock_app_state_with;
+    use axum::{body::Body, middleware, Router};
+    use tower::ServiceExt;
+
+    async fn app(configure: impl FnOnce(&mut crate::config::Config)) -> Router {
+        create_api_router(mock_app_state_with(configure).await)
+            .layer(middleware::from_fn(track_requests))
+    }
+
+    async fn get(app: &Router, uri: &str, token: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_scrape_after_driving_requests() {
+        let app = app(|_| {}).await;
+        let (status, _) = get(&app, "/health/ws", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/api/queue", Some("not-a-token")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, text) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+        for family in [
+            "http_requests_total",
+            "http_request_duration_seconds",
+            "queue_length",
+            "queue_tasks",
+            "websocket_connections",
+            "websocket_messages_total",
+            "pocketbase_instances",
+            "auth_failures_total",
+        ] {
+            assert!(
+                text.contains(&format!("# TYPE {} ", family)),
+                "missing {}",
+                family
+            );
+        }
+        assert!(text
+            .contains("http_requests_total{method=\"GET\",route=\"/health/ws\",status=\"200\"}"));
+        assert!(text
+            .contains("http_requests_total{method=\"GET\",route=\"/api/queue\",status=\"401\"}"));
+        assert!(text
+            .contains("http_request_duration_seconds_count{method=\"GET\",route=\"/health/ws\"}"));
+        assert!(text.contains("auth_failures_total{kind=\"token\",reason=\"invalid_token\"}"));
+        assert!(text.contains("queue_tasks{status=\"queued\"}"));
+        assert!(text.contains("pocketbase_instances{status=\"running\"} 0"));
+    }
+
+    #[tokio::test]
+    async fn test_scrapes_can_require_a_token_or_be_disabled() {
+        let guarded =
+            app(|config| config.security.metrics_token = Some("scrape-secret".to_string())).await;
+        assert_eq!(
+            get(&guarded, "/metrics", None).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get(&guarded, "/metrics", Some("wrong")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get(&guarded, "/metrics", Some("scrape-secret")).await.0,
+            StatusCode::OK
+        );
+
+        let disabled = app(|config| config.server.metrics_enabled = false).await;
+        assert_eq!(get(&disabled, "/metrics", None).await.0, StatusCode::NOT_FOUND);
+    }
+}
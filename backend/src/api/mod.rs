@@ -1,17 +1,65 @@
 pub mod adapters;
+pub mod audit;
 pub mod auth;
+pub mod auth_cache;
+pub mod cors;
+pub mod csrf;
+pub mod dead_letter;
+pub mod email_verification;
+pub mod env;
+pub mod error_report;
 pub mod extractors;
+pub mod frontend;
+pub mod health;
+pub mod internal;
+pub mod jwt;
+pub mod key_audit;
+pub mod key_expiry;
+pub mod key_repository;
+pub mod key_rotation;
+pub mod key_summary;
+pub mod key_validation;
 pub mod keys;
+pub mod limits;
+pub mod login_guard;
 pub mod meetings;
+pub mod metrics;
+pub mod oauth;
+pub mod openapi;
+pub mod pb_proxy;
 pub mod pocketbase;
+pub mod password_reset;
+pub mod processing_results;
+pub mod profile;
 pub mod queue;
+pub mod rate_limit;
+pub mod request_limit;
+pub mod request_log;
+pub mod revocation;
+pub mod sessions;
+pub mod settings;
+pub mod startup;
+pub mod version;
+pub mod webhooks;
 pub mod websocket;
 
-use axum::{routing::get, Router};
+use axum::{middleware, routing::get, Router};
+use common::broadcast::BroadcastService;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::{config::Config, pocketbase_manager::PocketBaseManager};
+use crate::{config::Config, global_pb::GlobalPb, mailer::Mailer, pocketbase_manager::PocketBaseManager};
+use audit::AuditLogger;
+use auth_cache::AuthCache;
+use frontend::FrontendDist;
+use key_audit::KeyAuditLog;
+use key_repository::MasterKey;
+use limits::Limits;
+use login_guard::LoginGuard;
+use rate_limit::RateLimits;
+use revocation::RevocationList;
+use sessions::SessionList;
+use startup::StartupState;
 use websocket::WebSocketManager;
 
 /// Application state combining all managers and config
@@ -21,47 +69,154 @@ pub struct AppState {
     pub pb_manager: Arc<PocketBaseManager>,
     pub ws_manager: Arc<WebSocketManager>,
     pub meetings_queue: Arc<RwLock<Vec<queue::Meeting>>>,
+    pub auth_cache: Arc<AuthCache>,
+    pub revocations: Arc<RevocationList>,
+    pub global_pb: Arc<GlobalPb>,
+    pub rate_limits: Arc<RateLimits>,
+    pub mailer: Arc<Mailer>,
+    pub login_guard: Arc<LoginGuard>,
+    pub sessions: Arc<SessionList>,
+    pub audit: Arc<AuditLogger>,
+    pub key_audit: Arc<KeyAuditLog>,
+    pub master_key: Arc<MasterKey>,
+    pub broadcast: Arc<BroadcastService>,
+    /// Whether startup has finished, gating `/health/ready` and the queue
+    pub startup: Arc<StartupState>,
 }
 
 /// Create the main API router with all endpoints
 pub fn create_api_router(app_state: AppState) -> Router {
-    Router::new()
+    let routes = Router::new()
         // Health checks
+        .route("/health/live", get(health::get_live))
+        .route("/health/ready", get(health::get_ready))
         .route("/health/pb", get(pocketbase::pb_health_check))
         .route("/health/ws", get(websocket_health_check))
-        
-        // WebSocket endpoint for real-time updates
-        .route("/queue_updates", get(websocket::websocket_handler))
-        
+        .route("/health/fathom", get(fathom_health_check))
+
+        // Prometheus scrapes, outside the authenticated routes
+        .route("/metrics", get(metrics::get_metrics))
+
+        // Authentication routes (proxied to global PB)
+        .nest("/auth", auth::router())
+
+        // Worker-only routes behind the shared internal token
+        .nest("/internal", internal::router())
+
+        // Fathom's webhooks, signed with the receiving user's secret
+        .nest("/webhooks", webhooks::router());
+
+    let server = &app_state.config.server;
+    let api = Limits::standard(server).apply(create_versioned_api_router(&app_state.startup))
+        // Backup uploads and the instance proxy may send more, for longer
+        .merge(Limits::long(server).apply(pocketbase::upload_router()));
+
+    let router = Limits::standard(server).apply(routes)
+        // Everything under `/api`, at `/api/v1` and the deprecated bare prefix
+        .nest(version::API_PREFIX, api.clone())
+        .nest(version::LEGACY_PREFIX, api.layer(middleware::from_fn(version::deprecate_legacy)))
+
+        // WebSocket endpoint for real-time updates, open long past any timeout
+        .route("/queue_updates", get(websocket::websocket_handler));
+
+    // The frontend bundle answers the GETs no route above matches
+    let router = match FrontendDist::from_config(server) {
+        Some(dist) => dist.apply(router),
+        None => router,
+    };
+
+    router
+
+        // Cookie-authenticated mutations must echo the CSRF token
+        .layer(middleware::from_fn(csrf::require_csrf_token))
+
+        // Per-client budgets, checked before anything else runs
+        .layer(middleware::from_fn_with_state(app_state.clone(), request_limit::limit_requests))
+
+        .with_state(app_state)
+}
+
+/// Everything served under the API prefix
+fn create_versioned_api_router(startup: &Arc<StartupState>) -> Router<AppState> {
+    Router::new()
+        // The OpenAPI spec and Swagger UI, when enabled
+        .route("/openapi.json", get(openapi::get_openapi))
+        .route("/docs", get(openapi::get_docs))
+
         // API routes with authentication
-        .nest("/api", create_authenticated_api_router())
-        
+        .merge(create_authenticated_api_router(startup))
+
         // Legacy PocketBase management routes
-        .nest("/api", pocketbase::router().with_state(app_state.pb_manager.clone()))
-        
-        .with_state(app_state.clone())
-        // Authentication routes (proxied to global PB) - separate router with config state
-        .merge(
-            Router::new()
-                .nest("/auth", auth::router())
-                .with_state(app_state.config.clone())
-        )
+        .merge(pocketbase::router())
+
+        // Admin views of the authentication audit trail
+        .merge(audit::router())
+
+        // What the frontend may see of the configuration, and admins the rest
+        .merge(env::router())
+
+        // Admin re-encryption of stored keys after a master key rotation
+        .merge(key_rotation::router())
+
+        // Admin views of tasks that failed for good, and requeueing them
+        .merge(dead_letter::router())
 }
 
 /// Create authenticated API router
-fn create_authenticated_api_router() -> Router<AppState> {
+fn create_authenticated_api_router(startup: &Arc<StartupState>) -> Router<AppState> {
     Router::new()
         // Key management with encryption
         .route("/keys", axum::routing::get(keys::get_keys))
         .route("/keys", axum::routing::put(keys::put_key))
-        
+        .route("/keys/audit", axum::routing::get(key_audit::list_key_audit))
+        .route("/keys/:service/:key_id", axum::routing::delete(keys::delete_key))
+        .route("/keys/:service/:key_id/default", axum::routing::patch(keys::set_default_key))
+        .route("/keys/:service/validate", axum::routing::post(key_validation::validate_key))
+
+        // Per-user preferences
+        .route("/settings", axum::routing::get(settings::get_settings))
+        .route("/settings", axum::routing::put(settings::update_settings))
+
+        .merge(create_queue_router(startup))
+}
+
+/// The queue and the Fathom meetings feeding it, held back until startup is done
+fn create_queue_router(startup: &Arc<StartupState>) -> Router<AppState> {
+    Router::new()
         // Queue management
         .route("/queue", axum::routing::post(queue::add_meetings))
         .route("/queue", axum::routing::get(queue::get_queue))
         .route("/queue/:id", axum::routing::delete(queue::remove_meeting))
-        
+        .route("/queue/:id/position", axum::routing::patch(queue::reorder_meeting))
+
         // Meetings proxy to Fathom with caching
         .route("/meetings", axum::routing::get(meetings::get_meetings))
+        .route("/meetings/refresh", axum::routing::post(meetings::refresh_meetings))
+        .route("/meetings/:id", axum::routing::get(meetings::get_meeting))
+        .route(
+            "/meetings/:id/downloadable",
+            axum::routing::get(meetings::get_downloadable),
+        )
+        .route(
+            "/meetings/:id/transcript",
+            axum::routing::get(meetings::get_transcript),
+        )
+
+        // Refused with 503 before authentication while starting or draining
+        .route_layer(middleware::from_extractor_with_state::<startup::Ready, _>(startup.clone()))
+}
+
+/// How Fathom requests have been paced and retried
+async fn fathom_health_check(
+    axum::extract::State(app_state): axum::extract::State<AppState>,
+) -> axum::response::Json<serde_json::Value> {
+    let limiter = app_state.rate_limits.fathom.stats().await;
+
+    axum::response::Json(serde_json::json!({
+        "status": "ok",
+        "fathom_limiter": limiter,
+        "timestamp": chrono::Utc::now()
+    }))
 }
 
 /// WebSocket health check
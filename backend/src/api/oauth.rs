@@ -0,0 +1,350 @@
+//! Google sign-in through PocketBase's OAuth2 providers
+//!
+//! `/auth/oauth/google/start` asks the global PocketBase for its Google
+//! provider, remembers PocketBase's `state` and PKCE verifier in a signed
+//! cookie and sends the browser to Google. Google returns to the callback,
+//! which checks the state, has PocketBase exchange the code (creating the
+//! user, or linking an existing one with the same email) and sends the
+//! browser back to the frontend with our session token in the URL fragment.
+//! Anything going wrong lands on the frontend with `#error=<code>` instead.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{
+    audit::{AuditLogger, AuthEvent, AuthEventType},
+    auth,
+    csrf::cookie,
+    jwt::{self, JwtKeys},
+    revocation::RevocationList,
+    sessions::{SessionList, SessionOrigin},
+    AppState,
+};
+use crate::config::Config;
+
+const PROVIDER: &str = "google";
+const STATE_COOKIE: &str = "oauth_state";
+/// How long the user has to finish signing in with Google
+const STATE_TTL_SECS: u64 = 600;
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by Google when the user declines or the request is refused
+    pub error: Option<String>,
+}
+
+/// Routes for OAuth2 sign-in, nested under `/auth`
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/oauth/google/start", get(start))
+        .route("/oauth/google/callback", get(callback))
+}
+
+/// The provider entry PocketBase lists under `auth-methods`
+struct Provider {
+    auth_url: String,
+    state: String,
+    code_verifier: String,
+}
+
+async fn fetch_provider(config: &Config) -> Result<Provider, String> {
+    let url = format!("{}/api/collections/users/auth-methods", config.database.url);
+    let response = reqwest::Client::new().get(&url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("auth-methods returned {}", response.status()));
+    }
+    let methods: Value = response.json().await.map_err(|e| e.to_string())?;
+
+    let provider = methods
+        .get("authProviders")
+        .and_then(|providers| providers.as_array())
+        .and_then(|providers| providers.iter().find(|p| p["name"] == PROVIDER))
+        .ok_or("Google is not configured as a PocketBase OAuth2 provider")?;
+    let field = |name: &str| {
+        provider
+            .get(name)
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .ok_or(format!("Provider entry has no {}", name))
+    };
+    Ok(Provider {
+        auth_url: field("authUrl")?,
+        state: field("state")?,
+        code_verifier: field("codeVerifier")?,
+    })
+}
+
+/// PocketBase's auth URL with our callback as the redirect URI
+fn provider_url(auth_url: &str, redirect_url: &str) -> Result<Url, String> {
+    let mut url = Url::parse(auth_url).map_err(|e| e.to_string())?;
+    // PocketBase leaves an empty `redirect_uri=` for the caller to fill in
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != "redirect_uri")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("redirect_uri", redirect_url);
+    Ok(url)
+}
+
+/// Send the browser back to the frontend, clearing the state cookie
+fn to_frontend(config: &Config, fragment: &str) -> Response {
+    let location = format!("{}#{}", config.security.oauth_frontend_url, fragment);
+    let clear = format!("{}=; Path=/auth/oauth; Max-Age=0; HttpOnly; SameSite=Lax", STATE_COOKIE);
+    ([(header::SET_COOKIE, clear)], Redirect::to(&location)).into_response()
+}
+
+fn failed(config: &Config, code: &str) -> Response {
+    to_frontend(config, &format!("error={}", code))
+}
+
+/// GET /auth/oauth/google/start - redirect to Google via PocketBase's provider config
+async fn start(State(config): State<Arc<Config>>) -> Response {
+    if !config.features.oauth_enabled(PROVIDER) {
+        return failed(&config, "oauth_disabled");
+    }
+    let provider = match fetch_provider(&config).await {
+        Ok(provider) => provider,
+        Err(e) => {
+            error!("Google sign-in unavailable: {}", e);
+            return failed(&config, "oauth_unavailable");
+        }
+    };
+
+    let issued = JwtKeys::new(&config.security)
+        .issue_oauth_state(&provider.state, &provider.code_verifier, STATE_TTL_SECS)
+        .map_err(|e| e.to_string());
+    let url = provider_url(&provider.auth_url, &config.security.oauth_google_redirect_url);
+    let (state_token, url) = match (issued, url) {
+        (Ok(state_token), Ok(url)) => (state_token, url),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to start Google sign-in: {}", e);
+            return failed(&config, "oauth_unavailable");
+        }
+    };
+
+    let cookie = format!(
+        "{}={}; Path=/auth/oauth; Max-Age={}; HttpOnly; SameSite=Lax",
+        STATE_COOKIE, state_token, STATE_TTL_SECS
+    );
+    match HeaderValue::from_str(&cookie) {
+        Ok(cookie) => ([(header::SET_COOKIE, cookie)], Redirect::to(url.as_str())).into_response(),
+        Err(e) => {
+            error!("Failed to build OAuth state cookie: {}", e);
+            failed(&config, "oauth_unavailable")
+        }
+    }
+}
+
+/// GET /auth/oauth/google/callback - finish the sign-in Google redirected back from
+async fn callback(
+    State(config): State<Arc<Config>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(sessions): State<Arc<SessionList>>,
+    State(audit): State<Arc<AuditLogger>>,
+    origin: SessionOrigin,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+) -> Response {
+    if !config.features.oauth_enabled(PROVIDER) {
+        return failed(&config, "oauth_disabled");
+    }
+    if let Some(provider_error) = &query.error {
+        warn!("Google sign-in refused by provider: {}", provider_error);
+        return failed(&config, "oauth_denied");
+    }
+
+    let keys = JwtKeys::new(&config.security);
+    let expected = cookie(&headers, STATE_COOKIE).and_then(|token| keys.verify_oauth_state(token).ok());
+    let (Some(expected), Some(code)) = (expected, query.code.as_deref()) else {
+        warn!("Google sign-in callback without a valid state cookie or code");
+        return failed(&config, "oauth_state_mismatch");
+    };
+    if query.state.as_deref() != Some(expected.state.as_str()) {
+        warn!("Google sign-in callback with mismatched state");
+        return failed(&config, "oauth_state_mismatch");
+    }
+
+    // PocketBase creates the user, or links the provider to the existing
+    // account with the same email, and answers like auth-with-password
+    let url = format!("{}/api/collections/users/auth-with-oauth2", config.database.url);
+    let exchanged = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({
+            "provider": PROVIDER,
+            "code": code,
+            "codeVerifier": expected.code_verifier,
+            "redirectUrl": config.security.oauth_google_redirect_url
+        }))
+        .send()
+        .await;
+    let pb_response: Value = match exchanged {
+        Ok(response) if response.status().is_success() => match response.json().await {
+            Ok(pb_response) => pb_response,
+            Err(e) => {
+                error!("Failed to parse PocketBase OAuth2 response: {}", e);
+                return failed(&config, "oauth_failed");
+            }
+        },
+        Ok(response) => {
+            warn!("PocketBase refused the Google code: {}", response.status());
+            return failed(&config, "oauth_failed");
+        }
+        Err(e) => {
+            error!("Failed to connect to PocketBase for Google sign-in: {}", e);
+            return failed(&config, "oauth_unavailable");
+        }
+    };
+
+    match auth::issue_session_token(&config, &revocations, &sessions, &origin, &pb_response, jwt::now()).await {
+        Ok(token) => {
+            let user_id = pb_response["record"]["id"].as_str().unwrap_or_default();
+            let is_new = pb_response["meta"]["isNew"].as_bool().unwrap_or(false);
+            info!(target: "audit", user_id = %user_id, new_account = is_new, "Signed in with Google");
+            let email = pb_response["record"]["email"].as_str().unwrap_or_default();
+            if is_new {
+                audit.record(AuthEvent::new(AuthEventType::Register, &origin).user(user_id).email(email));
+            }
+            audit.record(AuthEvent::new(AuthEventType::Login, &origin).user(user_id).email(email));
+            to_frontend(&config, &format!("token={}", token))
+        }
+        Err(e) => {
+            error!("Failed to issue session token after Google sign-in: {}", e);
+            failed(&config, "oauth_failed")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{create_api_router, jwt::JwtKeys, AppState},
+        test_support::{mock_app_state, test_app_state, test_config, unused_pb_manager},
+    };
+    use axum::{
+        body::Body,
+        http::{header, Request, Response, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    async fn test_state() -> AppState {
+        mock_app_state().await
+    }
+
+    async fn get(state: &AppState, uri: &str, cookie: Option<&str>) -> Response<Body> {
+        let mut request = Request::builder().uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        create_api_router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn location(response: &Response<Body>) -> String {
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        response.headers()[header::LOCATION].to_str().unwrap().to_string()
+    }
+
+    /// Start a sign-in, returning the state Google would echo back and the cookie to send with it
+    async fn start(state: &AppState) -> (String, String) {
+        let response = get(state, "/auth/oauth/google/start", None).await;
+        let provider_url = reqwest::Url::parse(&location(&response)).unwrap();
+        assert_eq!(provider_url.host_str(), Some("accounts.google.com"));
+        let query: std::collections::HashMap<_, _> = provider_url.query_pairs().into_owned().collect();
+        assert_eq!(query["redirect_uri"], "http://localhost:3000/auth/oauth/google/callback");
+
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly"));
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        (query["state"].clone(), cookie)
+    }
+
+    #[tokio::test]
+    async fn test_callback_validates_state() {
+        let state = test_state().await;
+        let (oauth_state, cookie) = start(&state).await;
+
+        for (uri, cookie) in [
+            (format!("/auth/oauth/google/callback?code=google-ann@example.com&state={}", oauth_state), None),
+            (
+                "/auth/oauth/google/callback?code=google-ann@example.com&state=forged".to_string(),
+                Some(cookie.as_str()),
+            ),
+            (
+                format!("/auth/oauth/google/callback?code=google-ann@example.com&state={}", oauth_state),
+                Some("oauth_state=not-a-signed-token"),
+            ),
+        ] {
+            let response = get(&state, &uri, cookie).await;
+            assert_eq!(location(&response), "http://localhost:8080/oauth/callback#error=oauth_state_mismatch");
+        }
+        assert!(state.global_pb.list_records("users", None).await.unwrap().is_empty());
+
+        let uri = format!("/auth/oauth/google/callback?code=google-ann@example.com&state={}", oauth_state);
+        let response = get(&state, &uri, Some(&cookie)).await;
+        let location = location(&response);
+        let token = location
+            .strip_prefix("http://localhost:8080/oauth/callback#token=")
+            .expect("signed in");
+        let claims = JwtKeys::new(&state.config.security).verify(token).unwrap();
+        assert_eq!(claims.email, "ann@example.com");
+        assert_eq!(state.global_pb.list_records("users", None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_callback_links_existing_email() {
+        let state = test_state().await;
+        let existing = state
+            .global_pb
+            .create_record("users", &json!({ "email": "bea@example.com", "verified": true }))
+            .await
+            .unwrap();
+        let (oauth_state, cookie) = start(&state).await;
+
+        let uri = format!("/auth/oauth/google/callback?code=google-bea@example.com&state={}", oauth_state);
+        let response = get(&state, &uri, Some(&cookie)).await;
+        let location = location(&response);
+        let token = location.split("#token=").nth(1).expect("signed in");
+        let claims = JwtKeys::new(&state.config.security).verify(token).unwrap();
+        assert_eq!(claims.sub, existing["id"].as_str().unwrap());
+        assert_eq!(state.global_pb.list_records("users", None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_provider_errors_redirect_with_error_code() {
+        let state = test_state().await;
+        let (oauth_state, cookie) = start(&state).await;
+
+        let uri = format!("/auth/oauth/google/callback?error=access_denied&state={}", oauth_state);
+        let response = get(&state, &uri, Some(&cookie)).await;
+        assert_eq!(location(&response), "http://localhost:8080/oauth/callback#error=oauth_denied");
+
+        // PocketBase refusing the code
+        let uri = format!("/auth/oauth/google/callback?code=expired&state={}", oauth_state);
+        let response = get(&state, &uri, Some(&cookie)).await;
+        assert_eq!(location(&response), "http://localhost:8080/oauth/callback#error=oauth_failed");
+
+        // Unreachable PocketBase when starting
+        let unreachable = test_app_state(test_config("http://127.0.0.1:9"), unused_pb_manager());
+        let response = get(&unreachable, "/auth/oauth/google/start", None).await;
+        assert_eq!(location(&response), "http://localhost:8080/oauth/callback#error=oauth_unavailable");
+    }
+}
@@ -0,0 +1,2029 @@
+//! The OpenAPI description of every route, and Swagger UI to browse it
+//!
+//! [`OPERATIONS`] lists each route with what it takes and returns, and the
+//! request and response types describe themselves through
+//! [`common::openapi::Schema`]. Routes are described at their versioned
+//! `/api/v1` paths; the deprecated `/api` aliases aren't listed.
+//! `/api/v1/openapi.json` and `/api/v1/docs` answer 404 unless
+//! `OPENAPI_ENABLED` is set. The generated spec is checked in as
+//! `backend/openapi.json`; regenerate it with `UPDATE_OPENAPI=1 cargo test -p
+//! backend openapi` after changing a route or a type it serves. The schemas
+//! are written by hand, so the tests check a sample of every body, as serde
+//! writes or reads it, against the schema its operation gives.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Json, Response},
+};
+use common::{
+    fathom::{DownloadCheck, FathomMeeting, FathomMeetingDetail, TranscriptSegment},
+    openapi::{
+        array, boolean, date_time, described, envelope, integer, nullable, object, string,
+        string_enum, uuid, Components, Schema,
+    },
+    AuthData, InstanceInfo, ServiceKind,
+};
+use serde_json::{json, Map, Value};
+
+use super::{
+    auth::{ChangePasswordRequest, LoginRequest, RegisterRequest},
+    env::PublicConfig,
+    key_validation::{KeyValidation, ValidateKeyRequest},
+    keys::{DeletedKey, KeySummary, PutKeyRequest},
+    meetings::{MeetingDetailResponse, MeetingsResponse, TranscriptResponse},
+    pocketbase::{
+        DeletePbRequest, InitPbRequest, InitPbResponse, PbInstanceHealth, PbStatusResponse, RestorePbRequest,
+    },
+    queue::{Meeting, MeetingRequest, QueueResponse, ReorderRequest},
+    AppState,
+};
+use crate::{
+    config::OAUTH_PROVIDERS,
+    fathom::AppliedBy,
+    pocketbase_manager::{InstanceStatus, PocketBaseInstance},
+};
+
+/// Builds the schema of a request or response body
+type Body = fn(&mut Components) -> Value;
+
+/// What a caller has to present
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Auth {
+    Public,
+    /// A session token, as a bearer token or the session cookie
+    User,
+    /// A session token of an admin
+    Admin,
+    /// `INTERNAL_API_TOKEN` as a bearer token
+    Internal,
+    /// `METRICS_TOKEN` as a bearer token, when one is set
+    Metrics,
+    /// The `X-Fathom-Signature` of the body
+    FathomSignature,
+}
+
+/// What a successful call answers
+enum Reply {
+    /// JSON of the given schema
+    Json(Body),
+    /// Anything else: status, description and content type if there's a body
+    Other(u16, &'static str, Option<&'static str>),
+}
+
+/// A query parameter: name, schema, description
+type Param = (&'static str, fn() -> Value, &'static str);
+
+/// One method on one route
+struct Operation {
+    method: &'static str,
+    /// As OpenAPI writes it, with `{param}` for path parameters
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    auth: Auth,
+    query: &'static [Param],
+    request: Option<Body>,
+    reply: Reply,
+}
+
+fn schema<T: Schema>(components: &mut Components) -> Value {
+    components.add::<T>()
+}
+
+fn list<T: Schema>(components: &mut Components) -> Value {
+    array(components.add::<T>())
+}
+
+fn wrapped<T: Schema>(components: &mut Components) -> Value {
+    let data = components.add::<T>();
+    envelope(components, data)
+}
+
+/// A JSON object the spec doesn't detail
+fn any_object(_: &mut Components) -> Value {
+    json!({ "type": "object" })
+}
+
+const MEETINGS_QUERY: &[Param] = &[
+    ("limit", integer, "Meetings per page"),
+    ("offset", integer, "Meetings to skip"),
+    ("cursor", string, "`next_cursor` of the previous page"),
+    (
+        "refresh",
+        boolean,
+        "Ask Fathom even if the cache holds the listing",
+    ),
+    ("q", string, "Case-insensitive substring of the title"),
+    (
+        "from",
+        string,
+        "Recorded on or after: a date (`YYYY-MM-DD`) or RFC 3339 time",
+    ),
+    (
+        "to",
+        string,
+        "Recorded on or before a date, or before an RFC 3339 time",
+    ),
+    ("participant", string, "Name or email of an invitee"),
+    ("min_duration_secs", integer, "Shortest recording to list"),
+];
+
+const PROXY_SUMMARY: &str = "Forward a request to the user's PocketBase instance";
+
+/// Every route the backend serves
+const OPERATIONS: &[Operation] = &[
+    // Service
+    Operation {
+        method: "get",
+        path: "/",
+        tag: "service",
+        summary: "Landing page",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "A short HTML page", Some("text/html")),
+    },
+    Operation {
+        method: "get",
+        path: "/health",
+        tag: "service",
+        summary: "Plain-text health check",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "`OK`", Some("text/plain")),
+    },
+    Operation {
+        method: "get",
+        path: "/health/live",
+        tag: "service",
+        summary: "Liveness probe",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/health/ready",
+        tag: "service",
+        summary: "Readiness probe; 503 with every check's detail when one fails",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/health/pb",
+        tag: "service",
+        summary: "State of the PocketBase instances",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/health/ws",
+        tag: "service",
+        summary: "State of the WebSocket connections",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/health/fathom",
+        tag: "service",
+        summary: "How Fathom requests have been paced and retried",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/metrics",
+        tag: "service",
+        summary: "Prometheus metrics",
+        auth: Auth::Metrics,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "The Prometheus text format", Some("text/plain")),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/env",
+        tag: "service",
+        summary: "Configuration the frontend may see",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<PublicConfig>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/openapi.json",
+        tag: "service",
+        summary: "This document",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/docs",
+        tag: "service",
+        summary: "Swagger UI for this document",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "An HTML page", Some("text/html")),
+    },
+    // Auth
+    Operation {
+        method: "post",
+        path: "/auth/login",
+        tag: "auth",
+        summary: "Sign in with email and password",
+        auth: Auth::Public,
+        query: &[],
+        request: Some(schema::<LoginRequest>),
+        reply: Reply::Json(wrapped::<AuthData>),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/register",
+        tag: "auth",
+        summary: "Create an account and sign in",
+        auth: Auth::Public,
+        query: &[],
+        request: Some(schema::<RegisterRequest>),
+        reply: Reply::Json(wrapped::<AuthData>),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/refresh",
+        tag: "auth",
+        summary: "Exchange the session token for a fresh one",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(wrapped::<AuthData>),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/logout",
+        tag: "auth",
+        summary: "Revoke the session token",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/logout_all",
+        tag: "auth",
+        summary: "Revoke every session of the user",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/change_password",
+        tag: "auth",
+        summary: "Change the password, ending every session",
+        auth: Auth::User,
+        query: &[],
+        request: Some(schema::<ChangePasswordRequest>),
+        reply: Reply::Json(wrapped::<AuthData>),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/password_reset/request",
+        tag: "auth",
+        summary: "Email a password reset link",
+        auth: Auth::Public,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/password_reset/confirm",
+        tag: "auth",
+        summary: "Set a new password with a reset token",
+        auth: Auth::Public,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/verify_email",
+        tag: "auth",
+        summary: "Confirm an email address with the emailed token",
+        auth: Auth::Public,
+        query: &[("token", string, "Token from the verification email")],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/verify_email/resend",
+        tag: "auth",
+        summary: "Send the verification email again",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/oauth/google/start",
+        tag: "auth",
+        summary: "Redirect to Google to sign in",
+        auth: Auth::Public,
+        query: &[],
+        request: None,
+        reply: Reply::Other(303, "Redirect to Google", None),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/oauth/google/callback",
+        tag: "auth",
+        summary: "Finish a Google sign-in",
+        auth: Auth::Public,
+        query: &[
+            ("code", string, "Authorization code from Google"),
+            ("state", string, "State given to Google by the start route"),
+            ("error", string, "Set by Google when the user declines"),
+        ],
+        request: None,
+        reply: Reply::Other(303, "Redirect to the frontend, signed in", None),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/me",
+        tag: "auth",
+        summary: "The signed-in user's profile",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "patch",
+        path: "/auth/me",
+        tag: "auth",
+        summary: "Update the signed-in user's profile",
+        auth: Auth::User,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/sessions",
+        tag: "auth",
+        summary: "The user's signed-in sessions",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "delete",
+        path: "/auth/sessions/{id}",
+        tag: "auth",
+        summary: "End one of the user's sessions",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    // Keys
+    Operation {
+        method: "get",
+        path: "/api/v1/keys",
+        tag: "keys",
+        summary: "The user's stored API keys, without their values",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(list::<KeySummary>),
+    },
+    Operation {
+        method: "put",
+        path: "/api/v1/keys",
+        tag: "keys",
+        summary: "Store or replace an API key",
+        auth: Auth::User,
+        query: &[],
+        request: Some(schema::<PutKeyRequest>),
+        reply: Reply::Json(schema::<KeySummary>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/keys/audit",
+        tag: "keys",
+        summary: "Changes to the user's keys, newest first",
+        auth: Auth::User,
+        query: &[
+            ("page", integer, "Page to list, from 1"),
+            ("per_page", integer, "Entries per page"),
+            (
+                "user_id",
+                string,
+                "Whose history to list; only admins may name someone else",
+            ),
+        ],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "delete",
+        path: "/api/v1/keys/{service}/{key_id}",
+        tag: "keys",
+        summary: "Delete a stored key",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(wrapped::<DeletedKey>),
+    },
+    Operation {
+        method: "patch",
+        path: "/api/v1/keys/{service}/{key_id}/default",
+        tag: "keys",
+        summary: "Make a key the one used when none is named",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<KeySummary>),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/keys/{service}/validate",
+        tag: "keys",
+        summary: "Ask the service whether it accepts a stored or candidate key",
+        auth: Auth::User,
+        query: &[],
+        request: Some(schema::<ValidateKeyRequest>),
+        reply: Reply::Json(schema::<KeyValidation>),
+    },
+    // Queue
+    Operation {
+        method: "post",
+        path: "/api/v1/queue",
+        tag: "queue",
+        summary: "Queue a meeting for upload to Loom",
+        auth: Auth::User,
+        query: &[(
+            "verify",
+            boolean,
+            "Check that the recording can be downloaded first",
+        )],
+        request: Some(schema::<MeetingRequest>),
+        reply: Reply::Json(schema::<QueueResponse>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/queue",
+        tag: "queue",
+        summary: "The queued meetings",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<QueueResponse>),
+    },
+    Operation {
+        method: "delete",
+        path: "/api/v1/queue/{id}",
+        tag: "queue",
+        summary: "Take one of the user's meetings off the queue",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<QueueResponse>),
+    },
+    Operation {
+        method: "patch",
+        path: "/api/v1/queue/{id}/position",
+        tag: "queue",
+        summary: "Move a meeting to another position in the queue",
+        auth: Auth::User,
+        query: &[],
+        request: Some(schema::<ReorderRequest>),
+        reply: Reply::Json(schema::<QueueResponse>),
+    },
+    Operation {
+        method: "get",
+        path: "/queue_updates",
+        tag: "queue",
+        summary: "WebSocket of queue changes as they happen",
+        auth: Auth::Public,
+        query: &[
+            ("user_id", string, "User whose updates to send"),
+            ("token", string, "Session token, instead of `user_id`"),
+        ],
+        request: None,
+        reply: Reply::Other(101, "Switching to the WebSocket protocol", None),
+    },
+    // Settings
+    Operation {
+        method: "get",
+        path: "/api/v1/settings",
+        tag: "settings",
+        summary: "The user's preferences",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "put",
+        path: "/api/v1/settings",
+        tag: "settings",
+        summary: "Change the user's preferences",
+        auth: Auth::User,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    // Meetings
+    Operation {
+        method: "get",
+        path: "/api/v1/meetings",
+        tag: "meetings",
+        summary: "A page of the user's Fathom meetings",
+        auth: Auth::User,
+        query: MEETINGS_QUERY,
+        request: None,
+        reply: Reply::Json(schema::<MeetingsResponse>),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/meetings/refresh",
+        tag: "meetings",
+        summary: "List meetings fresh from Fathom, bypassing the cache",
+        auth: Auth::User,
+        query: MEETINGS_QUERY,
+        request: None,
+        reply: Reply::Json(schema::<MeetingsResponse>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/meetings/{id}",
+        tag: "meetings",
+        summary: "One meeting with its summary and whether it can be downloaded",
+        auth: Auth::User,
+        query: &[(
+            "refresh",
+            boolean,
+            "Ask Fathom even if the cache holds the meeting",
+        )],
+        request: None,
+        reply: Reply::Json(schema::<MeetingDetailResponse>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/meetings/{id}/downloadable",
+        tag: "meetings",
+        summary: "Whether the recording's media can be fetched",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<DownloadCheck>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/meetings/{id}/transcript",
+        tag: "meetings",
+        summary: "The meeting's transcript; plain text with `format=text`",
+        auth: Auth::User,
+        query: &[
+            ("format", transcript_format, "`segments` JSON or `text`"),
+            (
+                "refresh",
+                boolean,
+                "Ask Fathom even if the cache holds the transcript",
+            ),
+        ],
+        request: None,
+        reply: Reply::Json(schema::<TranscriptResponse>),
+    },
+    // PocketBase
+    Operation {
+        method: "post",
+        path: "/api/v1/users/{id}/init_pb",
+        tag: "pocketbase",
+        summary: "Start a user's PocketBase instance",
+        auth: Auth::Admin,
+        query: &[],
+        request: Some(schema::<InitPbRequest>),
+        reply: Reply::Json(schema::<InitPbResponse>),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/users/{id}/pb_status",
+        tag: "pocketbase",
+        summary: "Whether the user's instance is up",
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Json(schema::<PbStatusResponse>),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/users/{id}/stop_pb",
+        tag: "pocketbase",
+        summary: "Stop a user's instance",
+        auth: Auth::Admin,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/users/{id}/pb_restore",
+        tag: "pocketbase",
+        summary: "Replace the user's data with a backup",
+        auth: Auth::User,
+        query: &[],
+        request: Some(schema::<RestorePbRequest>),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/users/{id}/pb_stats",
+        tag: "pocketbase",
+        summary: "Resource use of a user's instance",
+        auth: Auth::Admin,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "delete",
+        path: "/api/v1/users/{id}/pb",
+        tag: "pocketbase",
+        summary: "Delete a user's instance and data",
+        auth: Auth::Admin,
+        query: &[],
+        request: Some(schema::<DeletePbRequest>),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/users/{id}/pb/{path}",
+        tag: "pocketbase",
+        summary: PROXY_SUMMARY,
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "Whatever the instance answers", None),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/users/{id}/pb/{path}",
+        tag: "pocketbase",
+        summary: PROXY_SUMMARY,
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "Whatever the instance answers", None),
+    },
+    Operation {
+        method: "put",
+        path: "/api/v1/users/{id}/pb/{path}",
+        tag: "pocketbase",
+        summary: PROXY_SUMMARY,
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "Whatever the instance answers", None),
+    },
+    Operation {
+        method: "patch",
+        path: "/api/v1/users/{id}/pb/{path}",
+        tag: "pocketbase",
+        summary: PROXY_SUMMARY,
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "Whatever the instance answers", None),
+    },
+    Operation {
+        method: "delete",
+        path: "/api/v1/users/{id}/pb/{path}",
+        tag: "pocketbase",
+        summary: PROXY_SUMMARY,
+        auth: Auth::User,
+        query: &[],
+        request: None,
+        reply: Reply::Other(200, "Whatever the instance answers", None),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/pb_instances",
+        tag: "pocketbase",
+        summary: "Every user's instance",
+        auth: Auth::Admin,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    // Admin
+    Operation {
+        method: "get",
+        path: "/api/v1/admin/auth_events",
+        tag: "admin",
+        summary: "The authentication audit trail",
+        auth: Auth::Admin,
+        query: &[
+            ("page", integer, "Page to list, from 1"),
+            ("per_page", integer, "Events per page"),
+            ("user_id", string, "Only this user's events"),
+            ("event_type", string, "Only events of this type"),
+        ],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/admin/keys/rotate",
+        tag: "admin",
+        summary: "Re-encrypt stored keys under the current master key",
+        auth: Auth::Admin,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/admin/config",
+        tag: "admin",
+        summary: "The deployment's settings, with secrets redacted",
+        auth: Auth::Admin,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/api/v1/admin/dead_letter",
+        tag: "admin",
+        summary: "Tasks that failed for good, with every attempt",
+        auth: Auth::Admin,
+        query: &[
+            ("page", integer, "Page to list, from 1"),
+            ("per_page", integer, "Entries per page"),
+            ("user_id", string, "Only this user's tasks"),
+        ],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/api/v1/admin/dead_letter/{id}/requeue",
+        tag: "admin",
+        summary: "Put a dead-lettered task back in the queue",
+        auth: Auth::Admin,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    // Internal
+    Operation {
+        method: "get",
+        path: "/internal/keys/summary",
+        tag: "internal",
+        summary: "Every user's keys and when they expire",
+        auth: Auth::Internal,
+        query: &[],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "get",
+        path: "/internal/keys/{user_id}/{service}",
+        tag: "internal",
+        summary: "A user's key, sealed to the caller's public key",
+        auth: Auth::Internal,
+        query: &[
+            (
+                "key_id",
+                string,
+                "Key to fetch; the user's default unless given",
+            ),
+            (
+                "public_key",
+                string,
+                "Base64 X25519 public key to seal the value to",
+            ),
+        ],
+        request: None,
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/internal/keys/{user_id}/{service}/touch",
+        tag: "internal",
+        summary: "Note that a key was used, or that the service rejected it",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+    Operation {
+        method: "post",
+        path: "/internal/queue/{item_id}/loom",
+        tag: "internal",
+        summary: "Record the Loom video a queued meeting was uploaded as",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Other(204, "Recorded", None),
+    },
+    Operation {
+        method: "put",
+        path: "/internal/users/{user_id}/processing_results/{task_id}",
+        tag: "internal",
+        summary: "Keep how the latest attempt at a task ended",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Other(204, "Stored", None),
+    },
+    Operation {
+        method: "post",
+        path: "/internal/progress",
+        tag: "internal",
+        summary: "Pass on how far the worker has got with a task",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Other(204, "Relayed", None),
+    },
+    Operation {
+        method: "post",
+        path: "/internal/queue",
+        tag: "internal",
+        summary: "Pass on a change to one of the worker's tasks",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Other(204, "Relayed", None),
+    },
+    Operation {
+        method: "post",
+        path: "/internal/system",
+        tag: "internal",
+        summary: "Pass on a system event from the worker",
+        auth: Auth::Internal,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Other(204, "Relayed", None),
+    },
+    // Webhooks
+    Operation {
+        method: "post",
+        path: "/webhooks/fathom",
+        tag: "webhooks",
+        summary: "Receive a Fathom event",
+        auth: Auth::FathomSignature,
+        query: &[],
+        request: Some(any_object),
+        reply: Reply::Json(any_object),
+    },
+];
+
+fn transcript_format() -> Value {
+    string_enum(&["segments", "text"])
+}
+
+/// The OpenAPI document of the backend
+pub struct ApiDoc;
+
+impl ApiDoc {
+    pub fn spec() -> Value {
+        let mut components = Components::new();
+        let mut paths = Map::new();
+        for operation in OPERATIONS {
+            let item = paths.entry(operation.path).or_insert_with(|| json!({}));
+            item[operation.method] = operation.describe(&mut components);
+        }
+
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "Fathom to Loom backend",
+                "description": "Moves Fathom meeting recordings to Loom, one user's queue at a time.",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "tags": [
+                { "name": "service", "description": "Health, metrics and discovery" },
+                { "name": "auth", "description": "Accounts and sessions" },
+                { "name": "keys", "description": "Fathom and Loom API keys, stored encrypted" },
+                { "name": "queue", "description": "Meetings waiting for upload" },
+                { "name": "settings", "description": "Per-user preferences" },
+                { "name": "meetings", "description": "The user's Fathom meetings" },
+                { "name": "pocketbase", "description": "Per-user PocketBase instances" },
+                { "name": "admin", "description": "Operator actions" },
+                { "name": "internal", "description": "Routes for the worker" },
+                { "name": "webhooks", "description": "Events from Fathom" },
+            ],
+            "paths": paths,
+            "components": {
+                "schemas": components.into_schemas(),
+                "securitySchemes": {
+                    "session_token": {
+                        "type": "http",
+                        "scheme": "bearer",
+                        "description": "Token from `/auth/login`",
+                    },
+                    "session_cookie": {
+                        "type": "apiKey",
+                        "in": "cookie",
+                        "name": super::csrf::SESSION_COOKIE,
+                        "description": "Set by `/auth/login` with `cookie: true`; mutations must \
+                                        echo the `csrf_token` cookie in `X-CSRF-Token`",
+                    },
+                    "internal_token": {
+                        "type": "http",
+                        "scheme": "bearer",
+                        "description": "`INTERNAL_API_TOKEN`",
+                    },
+                    "metrics_token": {
+                        "type": "http",
+                        "scheme": "bearer",
+                        "description": "`METRICS_TOKEN`; not needed while it is unset",
+                    },
+                    "fathom_signature": {
+                        "type": "apiKey",
+                        "in": "header",
+                        "name": "X-Fathom-Signature",
+                        "description": "`sha256=` and the hex HMAC-SHA256 of the body under the \
+                                        user's webhook secret",
+                    },
+                },
+            },
+        })
+    }
+}
+
+impl Operation {
+    fn describe(&self, components: &mut Components) -> Value {
+        let mut parameters: Vec<Value> = path_params(self.path)
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": string() }))
+            .collect();
+        parameters.extend(self.query.iter().map(|(name, schema, description)| {
+            json!({ "name": name, "in": "query", "description": description, "schema": schema() })
+        }));
+
+        let (status, reply) = match &self.reply {
+            Reply::Json(body) => (
+                200,
+                json!({
+                    "description": "OK",
+                    "content": { "application/json": { "schema": body(components) } },
+                }),
+            ),
+            Reply::Other(status, description, content_type) => {
+                let mut reply = json!({ "description": description });
+                if let Some(content_type) = content_type {
+                    reply["content"] = json!({ *content_type: { "schema": string() } });
+                }
+                (*status, reply)
+            }
+        };
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": {
+                status.to_string(): reply,
+                "default": { "description": "Failed; the body says why" },
+            },
+        });
+        if !parameters.is_empty() {
+            operation["parameters"] = json!(parameters);
+        }
+        if let Some(body) = self.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body(components) } },
+            });
+        }
+        let security = match self.auth {
+            Auth::Public => None,
+            Auth::User | Auth::Admin => {
+                Some(json!([{ "session_token": [] }, { "session_cookie": [] }]))
+            }
+            Auth::Internal => Some(json!([{ "internal_token": [] }])),
+            Auth::Metrics => Some(json!([{ "metrics_token": [] }, {}])),
+            Auth::FathomSignature => Some(json!([{ "fathom_signature": [] }])),
+        };
+        if let Some(security) = security {
+            operation["security"] = security;
+        }
+        if self.auth == Auth::Admin {
+            operation["description"] = json!("Only for admins.");
+        }
+        operation
+    }
+}
+
+/// Names of the `{param}`s of `path`
+fn path_params(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+}
+
+/// GET /api/openapi.json - the spec, when `OPENAPI_ENABLED` is set
+pub async fn get_openapi(State(state): State<AppState>) -> Response {
+    if !state.config.server.openapi_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    Json(ApiDoc::spec()).into_response()
+}
+
+/// GET /api/docs - Swagger UI for the spec, when `OPENAPI_ENABLED` is set
+pub async fn get_docs(State(state): State<AppState>) -> Response {
+    if !state.config.server.openapi_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match swagger_ui() {
+        Some(page) => Html(page).into_response(),
+        None => {
+            tracing::warn!("Swagger UI asset digests are not pinned; not serving /api/docs");
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+/// Pinned to one release, so the page only changes when this does
+const SWAGGER_UI_VERSION: &str = "5.17.14";
+
+/// SHA-384 of `swagger-ui.css` from [`SWAGGER_UI_VERSION`], base64 as
+/// `integrity` takes it
+///
+/// Computed from the file unpkg serves, `openssl dgst -sha384 -binary | openssl
+/// base64 -A`, and again for a new version. Without it, or
+/// [`SWAGGER_UI_BUNDLE_SHA384`], the page is not served rather than load
+/// assets the browser can't check.
+const SWAGGER_UI_CSS_SHA384: &str = "";
+
+/// SHA-384 of `swagger-ui-bundle.js`, as [`SWAGGER_UI_CSS_SHA384`]
+const SWAGGER_UI_BUNDLE_SHA384: &str = "";
+
+/// The Swagger UI page, its assets checked against the pinned digests, or
+/// `None` while a digest is missing
+fn swagger_ui() -> Option<String> {
+    if SWAGGER_UI_CSS_SHA384.is_empty() || SWAGGER_UI_BUNDLE_SHA384.is_empty() {
+        return None;
+    }
+    Some(format!(
+        r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Fathom to Loom API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui.css" integrity="sha384-{css}" crossorigin="anonymous">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui-bundle.js" integrity="sha384-{bundle}" crossorigin="anonymous"></script>
+  <script>
+    SwaggerUIBundle({{ url: "/api/v1/openapi.json", dom_id: "#swagger-ui" }});
+  </script>
+</body>
+</html>
+"##,
+        version = SWAGGER_UI_VERSION,
+        css = SWAGGER_UI_CSS_SHA384,
+        bundle = SWAGGER_UI_BUNDLE_SHA384,
+    ))
+}
+
+impl Schema for PublicConfig {
+    const NAME: &'static str = "PublicConfig";
+
+    fn schema(_: &mut Components) -> Value {
+        let api = object(
+            &["base_url", "version"],
+            json!({ "base_url": string(), "version": string() }),
+        );
+        let websocket = object(
+            &["url"],
+            json!({ "url": described(string(), "Where queue updates are pushed") }),
+        );
+        let features = object(
+            &[
+                "auth_enabled",
+                "encryption_enabled",
+                "openapi_enabled",
+                "registration_enabled",
+                "oauth_providers",
+                "maintenance_mode",
+                "max_queue_items_per_user",
+            ],
+            json!({
+                "auth_enabled": boolean(),
+                "encryption_enabled": boolean(),
+                "openapi_enabled": described(boolean(), "Whether `/api/docs` serves Swagger UI"),
+                "registration_enabled": described(boolean(), "Whether new accounts can be created"),
+                "oauth_providers": described(array(string_enum(OAUTH_PROVIDERS)), "Providers offered for sign-in"),
+                "maintenance_mode": described(boolean(), "Whether queue changes are refused with 503"),
+                "max_queue_items_per_user": described(integer(), "Meetings each user may have queued; 0 for no limit"),
+            }),
+        );
+        object(
+            &["api", "websocket", "features", "logging"],
+            json!({
+                "api": api,
+                "websocket": websocket,
+                "features": features,
+                "logging": object(&["level"], json!({ "level": string() })),
+            }),
+        )
+    }
+}
+
+impl Schema for Meeting {
+    const NAME: &'static str = "Meeting";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["id", "user_id", "topic", "position"],
+            json!({
+                "id": uuid(),
+                "user_id": string(),
+                "topic": string(),
+                "position": integer(),
+                "meeting_id": described(string(), "Fathom recording this is"),
+                "fathom_key_id": described(string(), "Fathom key to fetch with; the default unless set"),
+                "loom_key_id": described(string(), "Loom key to upload with; the default unless set"),
+            }),
+        )
+    }
+}
+
+impl Schema for MeetingRequest {
+    const NAME: &'static str = "MeetingRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["user_id", "topic"],
+            json!({
+                "user_id": string(),
+                "topic": string(),
+                "meeting_id": nullable(string()),
+                "fathom_key_id": nullable(string()),
+                "loom_key_id": nullable(string()),
+            }),
+        )
+    }
+}
+
+impl Schema for ReorderRequest {
+    const NAME: &'static str = "ReorderRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["position", "queue_version"],
+            json!({
+                "position": described(integer(), "Where to move the meeting, counting from 1"),
+                "queue_version": described(
+                    string(),
+                    "Version of the queue the move was made against; 409 `queue_changed` when it has changed since",
+                ),
+            }),
+        )
+    }
+}
+
+impl Schema for QueueResponse {
+    const NAME: &'static str = "QueueResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["success", "message"],
+            json!({
+                "success": boolean(),
+                "message": string(),
+                "data": nullable(array(components.add::<Meeting>())),
+            }),
+        )
+    }
+}
+
+impl Schema for KeySummary {
+    const NAME: &'static str = "KeySummary";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &[
+                "service",
+                "key_id",
+                "created_at",
+                "expired",
+                "fingerprint",
+                "masked_hint",
+                "is_default",
+            ],
+            json!({
+                "service": components.add::<ServiceKind>(),
+                "key_id": string(),
+                "created_at": date_time(),
+                "expires_at": nullable(date_time()),
+                "days_until_expiry": described(
+                    nullable(json!({ "type": "integer" })),
+                    "Whole days left before `expires_at`",
+                ),
+                "expired": boolean(),
+                "fingerprint": string(),
+                "masked_hint": string(),
+                "last_used_at": nullable(date_time()),
+                "is_default": described(boolean(), "Used whenever no key is named"),
+            }),
+        )
+    }
+}
+
+impl Schema for PutKeyRequest {
+    const NAME: &'static str = "PutKeyRequest";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["service", "value"],
+            json!({
+                "service": components.add::<ServiceKind>(),
+                "key_id": described(
+                    nullable(string()),
+                    "1-64 letters, digits, '.', '_' or '-'; `default` unless given",
+                ),
+                "value": string(),
+                "expires_at": nullable(date_time()),
+            }),
+        )
+    }
+}
+
+impl Schema for DeletedKey {
+    const NAME: &'static str = "DeletedKey";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["service", "key_id"],
+            json!({
+                "service": components.add::<ServiceKind>(),
+                "key_id": string(),
+            }),
+        )
+    }
+}
+
+impl Schema for ValidateKeyRequest {
+    const NAME: &'static str = "ValidateKeyRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &[],
+            json!({
+                "key_id": described(nullable(string()), "Stored key to check; the default unless given"),
+                "value": described(nullable(string()), "Candidate value to check instead of a stored key"),
+            }),
+        )
+    }
+}
+
+impl Schema for KeyValidation {
+    const NAME: &'static str = "KeyValidation";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["valid", "detail", "checked_at"],
+            json!({
+                "valid": boolean(),
+                "detail": string(),
+                "checked_at": date_time(),
+            }),
+        )
+    }
+}
+
+impl Schema for LoginRequest {
+    const NAME: &'static str = "LoginRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["email", "password"],
+            json!({
+                "email": string(),
+                "password": string(),
+                "cookie": described(
+                    boolean(),
+                    "Deliver the session token in an HttpOnly cookie instead of the reply",
+                ),
+            }),
+        )
+    }
+}
+
+impl Schema for RegisterRequest {
+    const NAME: &'static str = "RegisterRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["email", "password", "password_confirm"],
+            json!({
+                "email": string(),
+                "password": string(),
+                "password_confirm": string(),
+                "name": nullable(string()),
+                "username": described(nullable(string()), "Generated when absent"),
+            }),
+        )
+    }
+}
+
+impl Schema for ChangePasswordRequest {
+    const NAME: &'static str = "ChangePasswordRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["current_password", "new_password", "new_password_confirm"],
+            json!({
+                "current_password": string(),
+                "new_password": string(),
+                "new_password_confirm": string(),
+            }),
+        )
+    }
+}
+
+impl Schema for AppliedBy {
+    const NAME: &'static str = "AppliedBy";
+
+    fn schema(_: &mut Components) -> Value {
+        string_enum(&["fathom", "local"])
+    }
+}
+
+impl Schema for MeetingsResponse {
+    const NAME: &'static str = "MeetingsResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["success", "meetings", "cached", "fetched_at"],
+            json!({
+                "success": boolean(),
+                "meetings": array(components.add::<FathomMeeting>()),
+                "total": described(integer(), "Meetings across all pages; counted for first pages only"),
+                "next_cursor": described(nullable(string()), "`cursor` of the next page"),
+                "cached": boolean(),
+                "fetched_at": date_time(),
+                "notice": described(string(), "What of the query was ignored, and why"),
+                "filters_applied": {
+                    "type": "object",
+                    "additionalProperties": components.add::<AppliedBy>(),
+                },
+            }),
+        )
+    }
+}
+
+impl Schema for MeetingDetailResponse {
+    const NAME: &'static str = "MeetingDetailResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["success", "meeting", "cached", "fetched_at"],
+            json!({
+                "success": boolean(),
+                "meeting": components.add::<FathomMeetingDetail>(),
+                "cached": boolean(),
+                "fetched_at": date_time(),
+            }),
+        )
+    }
+}
+
+impl Schema for TranscriptResponse {
+    const NAME: &'static str = "TranscriptResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["success", "meeting_id", "cached", "fetched_at", "segments"],
+            json!({
+                "success": boolean(),
+                "meeting_id": string(),
+                "cached": boolean(),
+                "fetched_at": date_time(),
+                "segments": array(components.add::<TranscriptSegment>()),
+            }),
+        )
+    }
+}
+
+impl Schema for InstanceStatus {
+    const NAME: &'static str = "InstanceStatus";
+
+    fn schema(_: &mut Components) -> Value {
+        string_enum(&["Starting", "Running", "Failed", "Stopped"])
+    }
+}
+
+impl Schema for PocketBaseInstance {
+    const NAME: &'static str = "PocketBaseInstance";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &[
+                "user_id",
+                "port",
+                "db_path",
+                "url",
+                "status",
+                "created_at",
+                "restart_count",
+            ],
+            json!({
+                "user_id": string(),
+                "port": integer(),
+                "db_path": string(),
+                "url": string(),
+                "status": components.add::<InstanceStatus>(),
+                "created_at": date_time(),
+                "last_health_check": nullable(date_time()),
+                "restart_count": integer(),
+            }),
+        )
+    }
+}
+
+impl Schema for InitPbRequest {
+    const NAME: &'static str = "InitPbRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(&[], json!({ "force_restart": nullable(boolean()) }))
+    }
+}
+
+impl Schema for InitPbResponse {
+    const NAME: &'static str = "InitPbResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["success", "message"],
+            json!({
+                "success": boolean(),
+                "message": string(),
+                "instance": nullable(components.add::<PocketBaseInstance>()),
+            }),
+        )
+    }
+}
+
+impl Schema for PbStatusResponse {
+    const NAME: &'static str = "PbStatusResponse";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["user_id", "status"],
+            json!({
+                "user_id": string(),
+                "status": components.add::<InstanceInfo>(),
+                "instance": nullable(components.add::<PbInstanceHealth>()),
+            }),
+        )
+    }
+}
+
+impl Schema for PbInstanceHealth {
+    const NAME: &'static str = "PbInstanceHealth";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["status", "port"],
+            json!({
+                "status": components.add::<InstanceStatus>(),
+                "port": integer(),
+                "last_health_check": nullable(date_time()),
+            }),
+        )
+    }
+}
+
+impl Schema for RestorePbRequest {
+    const NAME: &'static str = "RestorePbRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        described(
+            object(
+                &["confirm"],
+                json!({
+                    "confirm": boolean(),
+                    "backup_id": nullable(string()),
+                    "archive": described(
+                        nullable(string()),
+                        "Base64 tar.gz of the data directory",
+                    ),
+                }),
+            ),
+            "Exactly one of `backup_id` or `archive`, with `confirm` true",
+        )
+    }
+}
+
+impl Schema for DeletePbRequest {
+    const NAME: &'static str = "DeletePbRequest";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["confirm"],
+            json!({
+                "confirm": described(string(), "The user id being deleted, repeated"),
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::{mock_app_state_with, test_config};
+    use axum::{body::Body, extract::Request};
+    use common::{openapi::validate, ApiResponse};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::collections::{BTreeMap, BTreeSet};
+    use tower::ServiceExt;
+
+    const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    /// Router sources, each with the prefix its routes are nested under and
+    /// the text that starts the part of the file building them
+    const ROUTERS: &[(&str, &str, &str)] = &[
+        (include_str!("../main.rs"), "", "let app = Router::new()"),
+        (include_str!("mod.rs"), "", "pub fn create_api_router"),
+        (
+            include_str!("mod.rs"),
+            "/api/v1",
+            "fn create_versioned_api_router",
+        ),
+        (
+            include_str!("mod.rs"),
+            "/api/v1",
+            "fn create_authenticated_api_router",
+        ),
+        (include_str!("mod.rs"), "/api/v1", "fn create_queue_router"),
+        (include_str!("pocketbase.rs"), "/api/v1", "pub fn router"),
+        (
+            include_str!("pocketbase.rs"),
+            "/api/v1",
+            "pub fn upload_router",
+        ),
+        (include_str!("audit.rs"), "/api/v1", "pub fn router"),
+        (include_str!("env.rs"), "/api/v1", "pub fn router"),
+        (include_str!("key_rotation.rs"), "/api/v1", "pub fn router"),
+        (include_str!("dead_letter.rs"), "/api/v1", "pub fn router"),
+        (include_str!("auth.rs"), "/auth", "pub fn router"),
+        (include_str!("password_reset.rs"), "/auth", "pub fn router"),
+        (
+            include_str!("email_verification.rs"),
+            "/auth",
+            "pub fn router",
+        ),
+        (include_str!("oauth.rs"), "/auth", "pub fn router"),
+        (include_str!("profile.rs"), "/auth", "pub fn router"),
+        (include_str!("sessions.rs"), "/auth", "pub fn router"),
+        (include_str!("internal.rs"), "/internal", "pub fn router"),
+        (include_str!("webhooks.rs"), "/webhooks", "pub fn router"),
+    ];
+
+    /// The `(method, path)` of every `.route(..)` in `source`, in spec form
+    fn routes_in(source: &str, prefix: &str) -> Vec<(String, String)> {
+        let mut routes = Vec::new();
+        for call in source.split(".route(").skip(1) {
+            let path = call.split('"').nth(1).unwrap();
+            // The handlers end where the call's parentheses close
+            let mut depth = 1;
+            let end = call
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .unwrap()
+                .0;
+            let path: String = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix([':', '*']) {
+                    Some(name) => format!("{{{}}}", name),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let path = format!("{}{}", prefix, path);
+            let handlers = &call[..end];
+            for (token, methods) in [
+                ("get(", &["get"][..]),
+                ("post(", &["post"]),
+                ("put(", &["put"]),
+                ("patch(", &["patch"]),
+                ("delete(", &["delete"]),
+                ("any(", &["get", "post", "put", "patch", "delete"]),
+            ] {
+                let called = handlers.match_indices(token).any(|(at, _)| {
+                    !handlers[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                });
+                if called {
+                    for method in methods {
+                        routes.push((method.to_string(), path.clone()));
+                    }
+                }
+            }
+        }
+        routes
+    }
+
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let mut routes = BTreeSet::new();
+        for (source, prefix, start) in ROUTERS {
+            let body = &source[source.find(start).unwrap()..];
+            let body = &body[..body.find("\n}\n").unwrap_or(body.len())];
+            let found = routes_in(body, prefix);
+            assert!(!found.is_empty(), "no routes after {:?}", start);
+            routes.extend(found);
+        }
+        routes
+    }
+
+    #[test]
+    fn test_spec_matches_the_checked_in_copy() {
+        let generated = serde_json::to_string_pretty(&ApiDoc::spec()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(GOLDEN, &generated).unwrap();
+        }
+        let checked_in = std::fs::read_to_string(GOLDEN).unwrap_or_default();
+        assert!(
+            generated == checked_in,
+            "backend/openapi.json is out of date; regenerate it with \
+             UPDATE_OPENAPI=1 cargo test -p backend openapi"
+        );
+    }
+
+    #[test]
+    fn test_every_registered_route_is_in_the_spec() {
+        let spec = ApiDoc::spec();
+        let documented: BTreeSet<(String, String)> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| (method.clone(), path.clone()))
+            })
+            .collect();
+        let registered = registered_routes();
+        assert!(registered.contains(&("get".to_string(), "/api/v1/queue".to_string())));
+        assert!(registered.contains(&(
+            "delete".to_string(),
+            "/api/v1/users/{id}/pb/{path}".to_string()
+        )));
+
+        let missing: Vec<_> = registered.difference(&documented).collect();
+        assert!(
+            missing.is_empty(),
+            "routes missing from the spec: {:?}",
+            missing
+        );
+        let stale: Vec<_> = documented.difference(&registered).collect();
+        assert!(
+            stale.is_empty(),
+            "spec documents routes that don't exist: {:?}",
+            stale
+        );
+    }
+
+    #[test]
+    fn test_every_reference_resolves() {
+        let spec = ApiDoc::spec();
+        let text = spec.to_string();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in text.split("\"$ref\":\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas[name].is_object(), "{} is not defined", name);
+        }
+        assert!(schemas.contains_key("KeySummary"));
+        assert!(schemas.contains_key("AuthData"));
+        assert!(schemas.contains_key("PocketBaseInstance"));
+    }
+
+    /// What serde makes of each type a body is, by schema name
+    type Samples = BTreeMap<&'static str, Value>;
+
+    /// `sample` as serde reads `T` from it and writes it back
+    fn round_trip<T: Schema + Serialize + DeserializeOwned>(samples: &mut Samples, sample: Value) {
+        let value: T = serde_json::from_value(sample)
+            .unwrap_or_else(|e| panic!("{} can't be read from its sample: {}", T::NAME, e));
+        samples.insert(T::NAME, serde_json::to_value(value).unwrap());
+    }
+
+    /// `sample`, checked to be a request body serde reads as `T`
+    fn read<T: Schema + DeserializeOwned>(samples: &mut Samples, sample: Value) {
+        if let Err(e) = serde_json::from_value::<T>(sample.clone()) {
+            panic!("{} can't be read from its sample: {}", T::NAME, e);
+        }
+        samples.insert(T::NAME, sample);
+    }
+
+    fn written<T: Schema + Serialize>(samples: &mut Samples, value: T) {
+        samples.insert(T::NAME, serde_json::to_value(value).unwrap());
+    }
+
+    /// A sample of every type an operation takes or answers, with each
+    /// optional field set so that all of them are checked
+    fn samples() -> Samples {
+        let mut samples = Samples::new();
+        let instance = PocketBaseInstance {
+            user_id: "ana".to_string(),
+            port: 8091,
+            db_path: "/data/user_dbs/ana".into(),
+            url: "http://127.0.0.1:8091".to_string(),
+            status: InstanceStatus::Running,
+            created_at: "2026-10-14T09:00:00Z".parse().unwrap(),
+            last_health_check: Some("2026-10-14T09:05:00Z".parse().unwrap()),
+            restart_count: 1,
+        };
+        let meeting = json!({
+            "id": "rec-1",
+            "title": "Standup",
+            "start_time": "2026-10-14T09:00:00Z",
+            "duration": 900,
+            "participants": [{ "display_name": "Ana", "email": "ana@example.com", "organizer": true }],
+            "participants_raw": ["Ana"],
+            "url": "https://fathom.video/calls/1",
+            "share_url": "https://fathom.video/share/1",
+        });
+        let mut detail = meeting.clone();
+        detail["summary"] = json!("Notes");
+        detail["size_bytes"] = json!(1_048_576);
+        detail["downloadable"] = json!(true);
+
+        written(&mut samples, PublicConfig::new(&test_config("http://127.0.0.1:9")));
+        round_trip::<AuthData>(
+            &mut samples,
+            json!({
+                "token": "token-1",
+                "user": { "id": "ana", "email": "ana@example.com", "username": "ana", "name": "Ana", "verified": true },
+                "message": "Signed in",
+                "instance": { "status": "running", "url": "http://127.0.0.1:8091" },
+            }),
+        );
+        read::<LoginRequest>(&mut samples, json!({ "email": "ana@example.com", "password": "hunter22", "cookie": true }));
+        read::<RegisterRequest>(
+            &mut samples,
+            json!({
+                "email": "ana@example.com",
+                "password": "hunter22",
+                "password_confirm": "hunter22",
+                "name": "Ana",
+                "username": "ana",
+            }),
+        );
+        read::<ChangePasswordRequest>(
+            &mut samples,
+            json!({ "current_password": "hunter22", "new_password": "hunter23", "new_password_confirm": "hunter23" }),
+        );
+        round_trip::<KeySummary>(
+            &mut samples,
+            json!({
+                "service": "fathom",
+                "key_id": "default",
+                "created_at": "2026-10-14T09:00:00Z",
+                "expires_at": "2027-01-01T00:00:00Z",
+                "days_until_expiry": 78,
+                "expired": false,
+                "fingerprint": "3f2a9c",
+                "masked_hint": "fk_...9c",
+                "last_used_at": "2026-10-14T09:05:00Z",
+                "is_default": true,
+            }),
+        );
+        read::<PutKeyRequest>(
+            &mut samples,
+            json!({ "service": "loom", "key_id": "team", "value": "lk_secret", "expires_at": "2027-01-01T00:00:00Z" }),
+        );
+        round_trip::<DeletedKey>(&mut samples, json!({ "service": "loom", "key_id": "team" }));
+        read::<ValidateKeyRequest>(&mut samples, json!({ "key_id": "team", "value": "lk_secret" }));
+        round_trip::<KeyValidation>(
+            &mut samples,
+            json!({ "valid": true, "detail": "Accepted by Loom", "checked_at": "2026-10-14T09:00:00Z" }),
+        );
+        round_trip::<MeetingRequest>(
+            &mut samples,
+            json!({
+                "user_id": "ana",
+                "topic": "Standup",
+                "meeting_id": "rec-1",
+                "fathom_key_id": "default",
+                "loom_key_id": "team",
+            }),
+        );
+        round_trip::<ReorderRequest>(&mut samples, json!({ "position": 2, "queue_version": "7" }));
+        round_trip::<QueueResponse>(
+            &mut samples,
+            json!({
+                "success": true,
+                "message": "Queue retrieved",
+                "data": [{
+                    "id": "6c3ad7f4-1b7e-4f0e-9a53-4f1f6f7a9e10",
+                    "user_id": "ana",
+                    "topic": "Standup",
+                    "position": 1,
+                    "meeting_id": "rec-1",
+                    "fathom_key_id": "default",
+                    "loom_key_id": "team",
+                }],
+            }),
+        );
+        round_trip::<MeetingsResponse>(
+            &mut samples,
+            json!({
+                "success": true,
+                "meetings": [meeting],
+                "total": 1,
+                "next_cursor": "c2",
+                "cached": false,
+                "fetched_at": "2026-10-14T09:00:00Z",
+                "notice": "`participant` was applied locally",
+                "filters_applied": { "q": "fathom", "participant": "local" },
+            }),
+        );
+        round_trip::<MeetingDetailResponse>(
+            &mut samples,
+            json!({ "success": true, "meeting": detail, "cached": true, "fetched_at": "2026-10-14T09:00:00Z" }),
+        );
+        round_trip::<TranscriptResponse>(
+            &mut samples,
+            json!({
+                "success": true,
+                "meeting_id": "rec-1",
+                "cached": false,
+                "fetched_at": "2026-10-14T09:00:00Z",
+                "segments": [{ "speaker": "Ana", "start_ms": 0, "end_ms": 1500, "text": "Morning" }],
+            }),
+        );
+        round_trip::<DownloadCheck>(
+            &mut samples,
+            json!({ "downloadable": true, "reason": "available", "size_bytes": 1_048_576, "content_type": "video/mp4" }),
+        );
+        read::<InitPbRequest>(&mut samples, json!({ "force_restart": true }));
+        written(
+            &mut samples,
+            InitPbResponse {
+                success: true,
+                message: "PocketBase instance started".to_string(),
+                instance: Some(instance.clone()),
+            },
+        );
+        written(
+            &mut samples,
+            PbStatusResponse {
+                user_id: "ana".to_string(),
+                status: InstanceInfo {
+                    status: common::InstanceState::Running,
+                    url: Some(instance.url.clone()),
+                },
+                instance: Some(instance.into()),
+            },
+        );
+        read::<RestorePbRequest>(
+            &mut samples,
+            json!({ "confirm": true, "backup_id": "20261014T090000Z", "archive": null }),
+        );
+        read::<DeletePbRequest>(&mut samples, json!({ "confirm": "ana" }));
+        samples
+    }
+
+    #[test]
+    fn test_every_body_matches_its_schema() {
+        let samples = samples();
+        let mut components = Components::new();
+        let mut bodies = Vec::new();
+        for operation in OPERATIONS {
+            let reply = match operation.reply {
+                Reply::Json(body) => Some(body),
+                Reply::Other(..) => None,
+            };
+            for body in operation.request.into_iter().chain(reply) {
+                bodies.push((operation.method, operation.path, body(&mut components)));
+            }
+        }
+        let schemas = components.into_schemas();
+
+        let name = |reference: &Value| {
+            let reference = reference["$ref"].as_str()?;
+            Some(reference.trim_start_matches("#/components/schemas/").to_string())
+        };
+        let mut checked = BTreeSet::new();
+        for (method, path, schema) in bodies {
+            if schema == json!({ "type": "object" }) {
+                continue;
+            }
+            // As `schema`, `list` and `wrapped` build them
+            let data = &schema["properties"]["data"]["allOf"][0];
+            let (type_name, shape): (_, fn(Value) -> Value) = if let Some(type_name) = name(&schema) {
+                (type_name, |sample| sample)
+            } else if let Some(type_name) = name(&schema["items"]) {
+                (type_name, |sample| json!([sample]))
+            } else if let Some(type_name) = name(data) {
+                (type_name, |sample| serde_json::to_value(ApiResponse::success(sample)).unwrap())
+            } else {
+                panic!("{} {}: unexpected body schema {}", method, path, schema);
+            };
+            let sample = samples
+                .get(type_name.as_str())
+                .unwrap_or_else(|| panic!("{} {}: no sample of {}", method, path, type_name));
+            if let Err(mismatch) = validate(&shape(sample.clone()), &schema, &schemas) {
+                panic!("{} {}: {} doesn't match its schema: {}", method, path, type_name, mismatch);
+            }
+            checked.insert(type_name);
+        }
+
+        let unused: Vec<_> = samples.keys().filter(|name| !checked.contains(**name)).collect();
+        assert!(unused.is_empty(), "samples of types no body is: {:?}", unused);
+    }
+
+    #[tokio::test]
+    async fn test_docs_are_served_only_when_enabled() {
+        for enabled in [false, true] {
+            let app = create_api_router(mock_app_state_with(|config| config.server.openapi_enabled = enabled).await);
+
+            for uri in ["/api/v1/openapi.json", "/api/v1/docs"] {
+                let response = app
+                    .clone()
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                if !enabled {
+                    assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+                    continue;
+                }
+                // The page itself is checked by the test below
+                let expected = match (uri, swagger_ui()) {
+                    ("/api/v1/docs", None) => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                };
+                assert_eq!(response.status(), expected, "{}", uri);
+                if uri == "/api/v1/openapi.json" {
+                    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                        .await
+                        .unwrap();
+                    let spec: Value = serde_json::from_slice(&bytes).unwrap();
+                    assert_eq!(spec["openapi"], "3.0.3");
+                    assert!(spec["paths"]["/api/v1/keys"]["put"].is_object());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_the_docs_page_loads_only_pinned_checked_assets() {
+        for digest in [SWAGGER_UI_CSS_SHA384, SWAGGER_UI_BUNDLE_SHA384] {
+            assert!(
+                digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/'),
+                "not a base64 SHA-384 digest: {:?}",
+                digest
+            );
+        }
+
+        let page = swagger_ui().unwrap();
+        assert!(page.contains("/api/v1/openapi.json"));
+        // Assets come from one exact release, never a moving tag
+        let assets: Vec<_> = page.split("swagger-ui-dist@").skip(1).collect();
+        assert_eq!(assets.len(), 2);
+        for asset in assets {
+            let version = asset.split('/').next().unwrap();
+            assert_eq!(version.split('.').count(), 3, "unpinned asset version {}", version);
+            let tag = asset.split('>').next().unwrap();
+            assert!(tag.contains(" integrity=\"sha384-"), "unchecked asset {}", tag);
+        }
+    }
+}
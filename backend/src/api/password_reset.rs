@@ -0,0 +1,378 @@
+//! Password reset via emailed one-time links
+//!
+//! Requesting a reset always answers the same way, and as quickly, whether or
+//! not the email belongs to an account. Known users are sent a link carrying a short-lived
+//! `password_reset` token through the smtp-service. Confirming
+//! burns the token, sets the new password through the global PocketBase
+//! admin API, and signs the user out everywhere.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+    routing::post,
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    audit::{AuditLogger, AuthEvent, AuthEventType},
+    jwt::{JwtError, JwtKeys},
+    rate_limit::{client_ip, RateLimits},
+    revocation::RevocationList,
+    sessions::{SessionList, SessionOrigin},
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{self, GlobalPb},
+    mailer::{self, Mailer},
+};
+use common::validation::password_problems;
+
+const PURPOSE: &str = "password_reset";
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub password: String,
+    pub password_confirm: String,
+}
+
+/// Routes for requesting and confirming password resets, nested under `/auth`
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/password_reset/request", post(request_reset))
+        .route("/password_reset/confirm", post(confirm_reset))
+}
+
+fn reply(status: StatusCode, success: bool, message: &str) -> (StatusCode, Json<Value>) {
+    (
+        status,
+        Json(json!({
+            "success": success,
+            "message": message
+        })),
+    )
+}
+
+/// POST /auth/password_reset/request - email a reset link if the account exists
+async fn request_reset(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(mailer): State<Arc<Mailer>>,
+    State(rate_limits): State<Arc<RateLimits>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<PasswordResetRequest>,
+) -> (StatusCode, Json<Value>) {
+    let email = request.email.trim().to_lowercase();
+    if email.is_empty() {
+        return reply(StatusCode::BAD_REQUEST, false, "Email is required");
+    }
+
+    let ip = client_ip(&headers, peer.map(|ConnectInfo(addr)| addr), config.security.trust_proxy_headers);
+    if !rate_limits.password_reset_per_ip.check(&ip).await
+        || !rate_limits.password_reset_per_email.check(&email).await
+    {
+        warn!("Password reset rate limit hit for {} from {}", email, ip);
+        return reply(
+            StatusCode::TOO_MANY_REQUESTS,
+            false,
+            "Too many password reset requests, please try again later",
+        );
+    }
+
+    // Looked up and sent in the background, so unknown emails can't be told
+    // apart from known ones by how long the answer takes
+    tokio::spawn(async move {
+        if let Err(e) = send_reset_email(&config, &global_pb, &mailer, &email).await {
+            error!("Failed to send password reset email: {}", e);
+        }
+    });
+
+    reply(
+        StatusCode::OK,
+        true,
+        "If an account exists for that email, a password reset link has been sent",
+    )
+}
+
+async fn send_reset_email(config: &Config, global_pb: &GlobalPb, mailer: &Mailer, email: &str) -> Result<(), String> {
+    let filter = format!("email = {}", global_pb::quote(email));
+    let users = global_pb.list_records("users", Some(&filter)).await.map_err(|e| e.to_string())?;
+    let Some(user_id) = users.first().and_then(|user| user.get("id")).and_then(|id| id.as_str()) else {
+        debug!("Password reset requested for unknown email");
+        return Ok(());
+    };
+
+    let token = JwtKeys::new(&config.security)
+        .issue_action_token(PURPOSE, user_id, email, config.security.password_reset_ttl_secs)
+        .map_err(|e| e.to_string())?;
+    let link = format!("{}?token={}", config.security.password_reset_url, token);
+    let minutes = (config.security.password_reset_ttl_secs / 60).to_string();
+
+    let message = mailer::PASSWORD_RESET.render(email, &[("link", &link), ("minutes", &minutes)]);
+    mailer.send(&message).await.map_err(|e| e.to_string())?;
+
+    info!(target: "audit", user_id = %user_id, "Password reset email sent");
+    Ok(())
+}
+
+/// POST /auth/password_reset/confirm - set a new password using a reset token
+async fn confirm_reset(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(sessions): State<Arc<SessionList>>,
+    State(audit): State<Arc<AuditLogger>>,
+    origin: SessionOrigin,
+    Json(request): Json<PasswordResetConfirm>,
+) -> (StatusCode, Json<Value>) {
+    let claims = match JwtKeys::new(&config.security).verify_action_token(&request.token, PURPOSE) {
+        Ok(claims) => claims,
+        Err(JwtError::Expired) => {
+            return reply(StatusCode::BAD_REQUEST, false, "Reset link has expired, please request a new one")
+        }
+        Err(e) => {
+            warn!("Rejected password reset token: {}", e);
+            return reply(StatusCode::BAD_REQUEST, false, "Reset link is invalid");
+        }
+    };
+    let event = AuthEvent::new(AuthEventType::PasswordReset, &origin).user(&claims.sub).email(&claims.email);
+    if revocations.is_revoked(&request.token).await {
+        audit.record(event.failed("link_reused"));
+        return reply(StatusCode::BAD_REQUEST, false, "Reset link has already been used");
+    }
+
+    if request.password != request.password_confirm {
+        return reply(StatusCode::BAD_REQUEST, false, "Passwords do not match");
+    }
+    let problems = password_problems(&request.password);
+    if !problems.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "message": "Password is too weak",
+                "errors": problems
+            })),
+        );
+    }
+
+    // Burnt before the password changes, so of concurrent submits of one
+    // link only the first gets through
+    match revocations.claim(&request.token, claims.exp).await {
+        Ok(true) => {}
+        Ok(false) => {
+            audit.record(event.failed("link_reused"));
+            return reply(StatusCode::BAD_REQUEST, false, "Reset link has already been used");
+        }
+        Err(e) => {
+            error!("Failed to claim password reset link for {}: {}", claims.sub, e);
+            return reply(StatusCode::BAD_GATEWAY, false, "Failed to update password");
+        }
+    }
+
+    let update = json!({
+        "password": request.password,
+        "passwordConfirm": request.password_confirm
+    });
+    if let Err(e) = global_pb.update_record("users", &claims.sub, &update).await {
+        error!("Failed to reset password for {}: {}", claims.sub, e);
+        return reply(StatusCode::BAD_GATEWAY, false, "Failed to update password");
+    }
+
+    // Sessions opened with the old password end here
+    if let Err(e) = revocations.bump_generation(&claims.sub).await {
+        warn!("Failed to persist session revocation after password reset: {}", e);
+    }
+    sessions.close_all(&claims.sub).await;
+
+    info!(target: "audit", user_id = %claims.sub, "Password reset completed");
+    audit.record(event);
+    reply(StatusCode::OK, true, "Password has been reset, please log in")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{create_api_router, jwt::JwtKeys, AppState},
+        test_support::{emailed_token, mock_app_state_with, mock_smtp_service},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    struct Harness {
+        state: AppState,
+        user_id: String,
+        sent: Arc<Mutex<Vec<Value>>>,
+    }
+
+    async fn state_with_user(email: &str, configure: impl FnOnce(&mut crate::config::Config)) -> Harness {
+        let (smtp_url, sent) = mock_smtp_service().await;
+        let state = mock_app_state_with(|config| {
+            config.email.smtp_service_url = smtp_url;
+            configure(config);
+        })
+        .await;
+        let user = state
+            .global_pb
+            .create_record("users", &json!({ "email": email, "password": "Old-passw0rd" }))
+            .await
+            .unwrap();
+        Harness {
+            user_id: user["id"].as_str().unwrap().to_string(),
+            state,
+            sent,
+        }
+    }
+
+    /// The emails sent so far, once there are at least `count` of them
+    async fn sent_emails(sent: &Mutex<Vec<Value>>, count: usize) -> Vec<Value> {
+        let mut waited = 0;
+        loop {
+            let emails = sent.lock().await.clone();
+            if emails.len() >= count {
+                return emails;
+            }
+            waited += 1;
+            assert!(waited < 100, "only {} of {} emails were sent", emails.len(), count);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    async fn post(state: &AppState, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reset_token_round_trip() {
+        let Harness { state, user_id, sent } = state_with_user("ivy@example.com", |_| {}).await;
+
+        let (status, _) = post(&state, "/auth/password_reset/request", json!({ "email": " Ivy@Example.com " })).await;
+        assert_eq!(status, StatusCode::OK);
+        let emails = sent_emails(&sent, 1).await;
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0]["to_email"], "ivy@example.com");
+        let token = emailed_token(&emails[0]);
+
+        let confirm = |password: &str| json!({ "token": token, "password": password, "password_confirm": password });
+        let (status, body) = post(&state, "/auth/password_reset/confirm", confirm("weak")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!body["errors"].as_array().unwrap().is_empty());
+
+        let (status, _) = post(&state, "/auth/password_reset/confirm", confirm("N3w-password")).await;
+        assert_eq!(status, StatusCode::OK);
+        let users = state.global_pb.list_records("users", None).await.unwrap();
+        assert_eq!(users[0]["password"], "N3w-password");
+        assert_eq!(state.revocations.generation(&user_id).await, 1);
+
+        // The link only works once
+        let (status, body) = post(&state, "/auth/password_reset/confirm", confirm("An0ther-password")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "Reset link has already been used");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_confirms_of_one_link_change_the_password_once() {
+        let Harness { state, user_id, .. } = state_with_user("ike@example.com", |_| {}).await;
+        let token = JwtKeys::new(&state.config.security)
+            .issue_action_token("password_reset", &user_id, "ike@example.com", 600)
+            .unwrap();
+
+        let confirm = |password: &str| json!({ "token": token, "password": password, "password_confirm": password });
+        let (first, second) = tokio::join!(
+            post(&state, "/auth/password_reset/confirm", confirm("N3w-password")),
+            post(&state, "/auth/password_reset/confirm", confirm("An0ther-password")),
+        );
+        let mut statuses = [first.0, second.0];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+        let users = state.global_pb.list_records("users", None).await.unwrap();
+        let winner = if first.0 == StatusCode::OK { "N3w-password" } else { "An0ther-password" };
+        assert_eq!(users[0]["password"], winner);
+        assert_eq!(state.revocations.generation(&user_id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_foreign_tokens_are_rejected() {
+        let Harness { state, user_id, .. } = state_with_user("jack@example.com", |_| {}).await;
+        let keys = JwtKeys::new(&state.config.security);
+        let expired = keys.issue_action_token("password_reset", &user_id, "jack@example.com", 0).unwrap();
+        let wrong_purpose = keys.issue_action_token("verify_email", &user_id, "jack@example.com", 600).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        for (token, message) in [
+            (expired, "Reset link has expired, please request a new one"),
+            (wrong_purpose, "Reset link is invalid"),
+        ] {
+            let (status, body) = post(
+                &state,
+                "/auth/password_reset/confirm",
+                json!({ "token": token, "password": "N3w-password", "password_confirm": "N3w-password" }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["message"], message);
+        }
+        let users = state.global_pb.list_records("users", None).await.unwrap();
+        assert_eq!(users[0]["password"], "Old-passw0rd");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_emails_get_identical_response() {
+        let Harness { state, sent, .. } = state_with_user("kim@example.com", |_| {}).await;
+
+        let known = post(&state, "/auth/password_reset/request", json!({ "email": "kim@example.com" })).await;
+        let unknown = post(&state, "/auth/password_reset/request", json!({ "email": "nobody@example.com" })).await;
+        assert_eq!(known, unknown);
+        assert_eq!(sent_emails(&sent, 1).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_rate_limited_per_email_and_ip() {
+        let Harness { state, sent, .. } = state_with_user("lee@example.com", |config| {
+            config.security.password_reset_max_per_email = 2;
+            config.security.password_reset_max_per_ip = 3;
+        })
+        .await;
+
+        let request = |email: &str| json!({ "email": email });
+        for _ in 0..2 {
+            let (status, _) = post(&state, "/auth/password_reset/request", request("lee@example.com")).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, _) = post(&state, "/auth/password_reset/request", request("lee@example.com")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(sent_emails(&sent, 2).await.len(), 2);
+
+        // Test requests all share the unknown address, whose budget of three
+        // is now spent whatever email is asked for
+        let (status, _) = post(&state, "/auth/password_reset/request", request("other@example.com")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
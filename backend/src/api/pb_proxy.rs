@@ -0,0 +1,364 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use std::sync::{Arc, OnceLock};
+use tracing::{debug, error};
+
+use super::{
+    csrf::CSRF_HEADER,
+    extractors::{AuthError, AuthUser},
+};
+use crate::pocketbase_manager::{sanitize_user_id, PocketBaseManager};
+
+/// Headers that describe a single connection and must not be forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// ANY /api/users/{id}/pb/{*path}
+/// Forward a request to the user's PocketBase instance, for its owner or an
+/// admin, starting it if needed
+///
+/// The caller's Authorization header authenticates against the backend and is
+/// not passed through; the instance only sees the remaining end-to-end headers.
+pub async fn proxy_user_pocketbase(
+    Path((user_id, path)): Path<(String, String)>,
+    auth: AuthUser,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    request: Request,
+) -> Result<Response, AuthError> {
+    if let Err(e) = sanitize_user_id(&user_id) {
+        return Ok(proxy_error(StatusCode::BAD_REQUEST, e.to_string()));
+    }
+
+    if auth.id != user_id {
+        auth.require_admin()?;
+    }
+
+    if is_websocket_upgrade(request.headers()) {
+        return Ok(proxy_error(
+            StatusCode::NOT_IMPLEMENTED,
+            "WebSocket proxying is not supported yet".to_string(),
+        ));
+    }
+
+    let instance = match pb_manager.ensure_running(&user_id).await {
+        Ok(instance) => instance,
+        Err(e) => {
+            error!("Failed to start PocketBase for proxied request by user {}: {}", user_id, e);
+            return Ok(proxy_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("PocketBase instance is unavailable: {}", e),
+            ));
+        }
+    };
+
+    let mut upstream_url = format!("{}/{}", instance.url, path.trim_start_matches('/'));
+    if let Some(query) = request.uri().query() {
+        upstream_url.push('?');
+        upstream_url.push_str(query);
+    }
+    debug!("Proxying {} {} for user {}", request.method(), upstream_url, user_id);
+
+    let (parts, body) = request.into_parts();
+    let method = match reqwest::Method::from_bytes(parts.method.as_str().as_bytes()) {
+        Ok(method) => method,
+        Err(_) => return Ok(proxy_error(StatusCode::METHOD_NOT_ALLOWED, "Unsupported method".to_string())),
+    };
+
+    let mut upstream = client()
+        .request(method, &upstream_url)
+        .headers(forwarded_request_headers(&parts.headers));
+    if has_body(&parts.headers) {
+        upstream = upstream.body(stream_body(body));
+    }
+
+    let response = match upstream.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Proxy request to {} failed: {}", upstream_url, e);
+            return Ok(proxy_error(
+                StatusCode::BAD_GATEWAY,
+                format!("Failed to reach PocketBase instance: {}", e),
+            ));
+        }
+    };
+
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let headers = forwarded_response_headers(response.headers());
+    let mut proxied = Response::new(Body::from_stream(response.bytes_stream()));
+    *proxied.status_mut() = status;
+    *proxied.headers_mut() = headers;
+    Ok(proxied)
+}
+
+fn proxy_error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "success": false, "message": message }))).into_response()
+}
+
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false)
+}
+
+fn has_body(headers: &HeaderMap) -> bool {
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    content_length > 0 || headers.contains_key(header::TRANSFER_ENCODING)
+}
+
+/// Names listed in the Connection header are hop-by-hop as well
+fn connection_tokens(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect()
+}
+
+fn is_forwardable(name: &str, connection: &[String]) -> bool {
+    !HOP_BY_HOP.contains(&name) && !connection.iter().any(|token| token == name)
+}
+
+fn forwarded_request_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
+    let connection = connection_tokens(headers);
+    let mut forwarded = reqwest::header::HeaderMap::new();
+
+    for (name, value) in headers {
+        let name = name.as_str();
+        // The caller's credentials for the backend are no business of the instance
+        if matches!(name, "host" | "authorization" | "cookie" | CSRF_HEADER) || !is_forwardable(name, &connection) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+            reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            forwarded.append(name, value);
+        }
+    }
+
+    forwarded
+}
+
+fn forwarded_response_headers(headers: &reqwest::header::HeaderMap) -> HeaderMap {
+    let connection: Vec<String> = headers
+        .get_all(reqwest::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect();
+    let mut forwarded = HeaderMap::new();
+
+    for (name, value) in headers {
+        if !is_forwardable(name.as_str(), &connection) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_str().as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            forwarded.append(name, value);
+        }
+    }
+
+    forwarded
+}
+
+/// Adapt the incoming body into a stream reqwest can send
+///
+/// axum bodies are not `Sync`, so chunks are relayed through a channel.
+fn stream_body(body: Body) -> reqwest::Body {
+    let (mut tx, rx) = futures::channel::mpsc::channel::<Result<Bytes, std::io::Error>>(8);
+    let mut data = body.into_data_stream();
+
+    tokio::spawn(async move {
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk.map_err(std::io::Error::other);
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    reqwest::Body::wrap_stream(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        pocketbase_manager::InstanceStatus,
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::body::to_bytes;
+    use serde_json::Value;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn setup(dir: &std::path::Path) -> crate::api::AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager = PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 9)
+            .with_readiness_timeout(Duration::from_secs(10));
+        test_app_state(test_config(&global_url), manager)
+    }
+
+    async fn body_json(response: Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_method_body_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        state.pb_manager.init_user_instance("alice").await.unwrap();
+
+        let request = Request::builder()
+            .method("PATCH")
+            .uri("/api/users/alice/pb/api/collections/notes/records/1?expand=owner")
+            .header("authorization", "Bearer valid-alice")
+            .header("content-type", "application/json")
+            .header("content-length", "15")
+            .body(Body::from(r#"{"title":"hi"} "#))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let echo = body_json(response).await;
+        assert_eq!(echo["method"], "PATCH");
+        assert_eq!(echo["path"], "/api/collections/notes/records/1?expand=owner");
+        assert_eq!(echo["body"], r#"{"title":"hi"} "#);
+        assert_eq!(echo["authorization"], Value::Null);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_keeps_the_backend_session_to_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        state.pb_manager.init_user_instance("alice").await.unwrap();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/alice/pb/api/collections/notes/records")
+            .header("cookie", "session=valid-alice; csrf_token=csrf-123")
+            .header(CSRF_HEADER, "csrf-123")
+            .header("content-type", "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let echo = body_json(response).await;
+        assert_eq!(echo["method"], "POST");
+        assert_eq!(echo["cookie"], Value::Null);
+        assert_eq!(echo["csrf"], Value::Null);
+        assert_eq!(echo["authorization"], Value::Null);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_is_for_the_owner_or_an_admin() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        let unauthenticated = Request::builder()
+            .uri("/api/users/alice/pb/api/health")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(unauthenticated).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let other_user = Request::builder()
+            .uri("/api/users/alice/pb/api/health")
+            .header("authorization", "Bearer valid-mallory")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(other_user).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(state.pb_manager.get_user_instance("alice").await.is_none());
+
+        let admin = Request::builder()
+            .uri("/api/users/alice/pb/api/health")
+            .header("authorization", "Bearer valid-admin")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(admin).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["authorization"], Value::Null);
+
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_starts_stopped_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        assert!(state.pb_manager.get_user_instance("bob").await.is_none());
+
+        let request = Request::builder()
+            .uri("/api/users/bob/pb/api/health")
+            .header("authorization", "Bearer valid-bob")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["method"], "GET");
+        let instance = state.pb_manager.get_user_instance("bob").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+
+        state.pb_manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_websocket_upgrade() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        let request = Request::builder()
+            .uri("/api/users/carol/pb/api/realtime")
+            .header("authorization", "Bearer valid-carol")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_api_router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+}
@@ -1,8 +1,8 @@
 use axum::{
-    extract::{Path, State},
+    extract::State,
     http::StatusCode,
     response::Json,
-    routing::{get, post},
+    routing::{any, delete, get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
@@ -10,7 +10,11 @@ use serde_json::{json, Value};
 use std::sync::Arc;
 use tracing::{error, info};
 
-use crate::pocketbase_manager::{PocketBaseManager, PocketBaseInstance, PocketBaseError};
+use base64::Engine;
+
+use super::{extractors::{AdminUser, AuthError, AuthUser, UserIdPath}, version, AppState};
+use crate::pocketbase_manager::{InstanceStatus, PocketBaseManager, PocketBaseInstance, PocketBaseError, RestoreSource};
+use common::InstanceInfo;
 
 /// Response for PocketBase initialization
 #[derive(Debug, Serialize)]
@@ -24,7 +28,27 @@ pub struct InitPbResponse {
 #[derive(Debug, Serialize)]
 pub struct PbStatusResponse {
     pub user_id: String,
-    pub instance: Option<PocketBaseInstance>,
+    /// Startup state, as returned by login; poll this until it leaves `starting`
+    pub status: InstanceInfo,
+    pub instance: Option<PbInstanceHealth>,
+}
+
+/// What a status poll shows of an instance, leaving out where it keeps its data
+#[derive(Debug, Serialize)]
+pub struct PbInstanceHealth {
+    pub status: InstanceStatus,
+    pub port: u16,
+    pub last_health_check: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<PocketBaseInstance> for PbInstanceHealth {
+    fn from(instance: PocketBaseInstance) -> Self {
+        Self {
+            status: instance.status,
+            port: instance.port,
+            last_health_check: instance.last_health_check,
+        }
+    }
 }
 
 /// Request body for PocketBase initialization
@@ -33,33 +57,55 @@ pub struct InitPbRequest {
     pub force_restart: Option<bool>,
 }
 
+/// Request body for restoring a PocketBase instance
+///
+/// Exactly one of `backup_id` or `archive` (a base64-encoded tar.gz of the
+/// data directory) must be provided, and `confirm` must be `true`.
+#[derive(Debug, Deserialize)]
+pub struct RestorePbRequest {
+    #[serde(default)]
+    pub confirm: bool,
+    pub backup_id: Option<String>,
+    pub archive: Option<String>,
+}
+
+/// Request body for deleting a user's instance and data
+///
+/// `confirm` must repeat the user id being deleted.
+#[derive(Debug, Deserialize)]
+pub struct DeletePbRequest {
+    pub confirm: String,
+}
+
 /// Create router for PocketBase API endpoints
-pub fn router() -> Router<Arc<PocketBaseManager>> {
+pub fn router() -> Router<AppState> {
     Router::new()
         .route("/users/:id/init_pb", post(init_user_pocketbase))
         .route("/users/:id/pb_status", get(get_user_pocketbase_status))
         .route("/users/:id/stop_pb", post(stop_user_pocketbase))
+        .route("/users/:id/pb_stats", get(get_user_pocketbase_stats))
+        .route("/users/:id/pb", delete(delete_user_pocketbase))
         .route("/pb_instances", get(list_all_instances))
 }
 
+/// Routes carrying whole backups or proxied instance traffic, which get the
+/// larger body limit and longer timeout
+pub fn upload_router() -> Router<AppState> {
+    Router::new()
+        .route("/users/:id/pb_restore", post(restore_user_pocketbase))
+        .route("/users/:id/pb/*path", any(super::pb_proxy::proxy_user_pocketbase))
+}
+
 /// POST /api/users/{id}/init_pb
-/// Initialize PocketBase instance for a user
+/// Initialize PocketBase instance for a user (admin only)
 async fn init_user_pocketbase(
-    Path(user_id): Path<String>,
+    UserIdPath(user_id): UserIdPath,
+    _admin: AdminUser,
     State(pb_manager): State<Arc<PocketBaseManager>>,
     Json(request): Json<InitPbRequest>,
 ) -> Result<Json<InitPbResponse>, StatusCode> {
     info!("Received request to initialize PocketBase for user: {}", user_id);
 
-    // Validate user ID
-    if user_id.trim().is_empty() {
-        return Ok(Json(InitPbResponse {
-            success: false,
-            message: "Invalid user ID".to_string(),
-            instance: None,
-        }));
-    }
-
     // If force_restart is true, stop existing instance first
     if request.force_restart.unwrap_or(false) {
         if let Err(e) = pb_manager.stop_user_instance(&user_id).await {
@@ -105,33 +151,74 @@ async fn init_user_pocketbase(
     }
 }
 
+/// The status of a user's instance as reported to clients; the proxy URL,
+/// under the versioned prefix, is only given once the instance is running
+pub fn instance_info_for(user_id: &str, status: InstanceStatus) -> InstanceInfo {
+    InstanceInfo {
+        url: (status == InstanceStatus::Running).then(|| format!("{}/users/{}/pb", version::API_PREFIX, user_id)),
+        status: status.into(),
+    }
+}
+
+/// The current startup status of a user's instance
+pub async fn instance_info(pb_manager: &PocketBaseManager, user_id: &str) -> InstanceInfo {
+    let status = pb_manager.startup_status(user_id).await.unwrap_or(InstanceStatus::Stopped);
+    instance_info_for(user_id, status)
+}
+
 /// GET /api/users/{id}/pb_status
-/// Get PocketBase instance status for a user
+/// Get PocketBase instance status for a user (the user themselves or an admin)
 async fn get_user_pocketbase_status(
-    Path(user_id): Path<String>,
+    UserIdPath(user_id): UserIdPath,
+    user: AuthUser,
     State(pb_manager): State<Arc<PocketBaseManager>>,
-) -> Result<Json<PbStatusResponse>, StatusCode> {
+) -> Result<Json<PbStatusResponse>, AuthError> {
+    if user_id != user.id {
+        user.require_admin()?;
+    }
     info!("Checking PocketBase status for user: {}", user_id);
 
-    let instance = pb_manager.get_user_instance(&user_id).await;
-    
+    let status = instance_info(&pb_manager, &user_id).await;
+    let instance = pb_manager.get_user_instance(&user_id).await.map(PbInstanceHealth::from);
+
     Ok(Json(PbStatusResponse {
         user_id,
+        status,
         instance,
     }))
 }
 
+/// GET /api/users/{id}/pb_stats
+/// Resource usage for a user's instance, as last collected by the health monitor (admin only)
+async fn get_user_pocketbase_stats(
+    UserIdPath(user_id): UserIdPath,
+    _admin: AdminUser,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+) -> Result<Json<Value>, StatusCode> {
+    if pb_manager.get_user_instance(&user_id).await.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let stats = pb_manager.get_instance_stats(&user_id).await;
+    Ok(Json(json!({
+        "user_id": user_id,
+        "collected": stats.is_some(),
+        "stats": stats
+    })))
+}
+
 /// POST /api/users/{id}/stop_pb
-/// Stop PocketBase instance for a user
+/// Stop PocketBase instance for a user (admin only)
 async fn stop_user_pocketbase(
-    Path(user_id): Path<String>,
+    UserIdPath(user_id): UserIdPath,
+    AdminUser(admin): AdminUser,
     State(pb_manager): State<Arc<PocketBaseManager>>,
 ) -> Result<Json<Value>, StatusCode> {
     info!("Received request to stop PocketBase for user: {}", user_id);
 
     match pb_manager.stop_user_instance(&user_id).await {
         Ok(()) => {
-            info!("Successfully stopped PocketBase for user: {}", user_id);
+            info!(target: "audit", actor = %admin.id, user_id = %user_id, "Stopped PocketBase instance");
             Ok(Json(json!({
                 "success": true,
                 "message": "PocketBase instance stopped successfully"
@@ -147,9 +234,157 @@ async fn stop_user_pocketbase(
     }
 }
 
+/// POST /api/users/{id}/pb_restore
+/// Restore a user's PocketBase data directory from a backup archive (owner
+/// or admin)
+async fn restore_user_pocketbase(
+    UserIdPath(user_id): UserIdPath,
+    auth: AuthUser,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    Json(request): Json<RestorePbRequest>,
+) -> Result<(StatusCode, Json<Value>), AuthError> {
+    info!("Received request to restore PocketBase for user: {}", user_id);
+
+    if auth.id != user_id {
+        auth.require_admin()?;
+    }
+
+    if !request.confirm {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "message": "Restoring replaces all current data; set \"confirm\": true to proceed"
+            })),
+        ));
+    }
+
+    let source = match (request.backup_id, request.archive) {
+        (Some(backup_id), None) => RestoreSource::Backup(backup_id),
+        (None, Some(encoded)) => match base64::engine::general_purpose::STANDARD.decode(encoded.trim()) {
+            Ok(bytes) => RestoreSource::Archive(bytes),
+            Err(e) => {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "success": false,
+                        "message": format!("Archive is not valid base64: {}", e)
+                    })),
+                ));
+            }
+        },
+        _ => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "success": false,
+                    "message": "Provide exactly one of backup_id or archive"
+                })),
+            ));
+        }
+    };
+
+    match pb_manager.restore_user_instance(&user_id, source).await {
+        Ok(report) => {
+            info!(
+                target: "audit",
+                actor = %auth.id,
+                user_id = %user_id,
+                "Restored PocketBase instance ({} entries)",
+                report.entries_restored
+            );
+            Ok((
+                StatusCode::OK,
+                Json(json!({
+                    "success": true,
+                    "message": format!("Restored {} entries from backup", report.entries_restored),
+                    "instance": report.instance
+                })),
+            ))
+        }
+        Err(e) => {
+            error!("Failed to restore PocketBase for user {}: {}", user_id, e);
+
+            let (status, stage) = match &e {
+                PocketBaseError::BackupNotFound(_) => (StatusCode::NOT_FOUND, None),
+                PocketBaseError::InvalidArchive(_) => (StatusCode::UNPROCESSABLE_ENTITY, None),
+                PocketBaseError::RestoreFailed { stage, .. } => {
+                    (StatusCode::INTERNAL_SERVER_ERROR, Some(stage.clone()))
+                }
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            };
+
+            Ok((
+                status,
+                Json(json!({
+                    "success": false,
+                    "message": e.to_string(),
+                    "stage": stage
+                })),
+            ))
+        }
+    }
+}
+
+/// DELETE /api/users/{id}/pb
+/// Permanently delete a user's instance, data directory and backups (admin only)
+async fn delete_user_pocketbase(
+    UserIdPath(user_id): UserIdPath,
+    AdminUser(auth): AdminUser,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    Json(request): Json<DeletePbRequest>,
+) -> (StatusCode, Json<Value>) {
+    if request.confirm != user_id {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "message": "Deletion is permanent; set \"confirm\" to the user id to proceed"
+            })),
+        );
+    }
+
+    match pb_manager.delete_user_instance(&user_id).await {
+        Ok(report) => {
+            info!(
+                target: "audit",
+                actor = %auth.id,
+                user_id = %user_id,
+                files_removed = report.files_removed,
+                bytes_removed = report.bytes_removed,
+                "Deleted PocketBase instance and data"
+            );
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "success": true,
+                    "message": "PocketBase instance and data deleted",
+                    "files_removed": report.files_removed,
+                    "bytes_removed": report.bytes_removed
+                })),
+            )
+        }
+        Err(e) => {
+            error!("Failed to delete PocketBase data for user {}: {}", user_id, e);
+            let status = match e {
+                PocketBaseError::UnsafePath(_) | PocketBaseError::InvalidUserId(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(json!({
+                    "success": false,
+                    "message": e.to_string()
+                })),
+            )
+        }
+    }
+}
+
 /// GET /api/pb_instances
-/// List all PocketBase instances
+/// List all PocketBase instances (admin only)
 async fn list_all_instances(
+    _admin: AdminUser,
     State(pb_manager): State<Arc<PocketBaseManager>>,
 ) -> Result<Json<Value>, StatusCode> {
     let instances = pb_manager.get_all_instances().await;
@@ -173,6 +408,171 @@ pub async fn pb_health_check(
         "status": "ok",
         "total_instances": instances.len(),
         "running_instances": running_count,
+        "allocated_ports": pb_manager.allocated_ports().await.len(),
+        "warm_instances": pb_manager.warm_pool_len().await,
         "timestamp": chrono::Utc::now()
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::create_api_router,
+        pocketbase_manager::PocketBaseManager,
+        test_support::{mock_global_pocketbase, test_app_state, test_config},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    fn delete_request(user_id: &str, token: &str, confirm: &str) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/api/users/{}/pb", user_id))
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(format!(r#"{{"confirm":"{}"}}"#, confirm)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_requires_admin_and_matching_confirm() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+        let data_dir = state.pb_manager.data_dir("frank");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("data.db"), b"data").unwrap();
+
+        // The data owner is not an admin
+        let response = create_api_router(state.clone())
+            .oneshot(delete_request("frank", "valid-frank", "frank"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = create_api_router(state.clone())
+            .oneshot(delete_request("frank", "valid-admin", "someone-else"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(data_dir.join("data.db").exists());
+
+        let response = create_api_router(state.clone())
+            .oneshot(delete_request("frank", "valid-admin", "frank"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!data_dir.exists());
+    }
+
+    fn get_request(uri: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_instance_management_requires_admin() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let mut config = test_config(&global_url);
+        config.security.admin_emails = vec!["ops@example.com".to_string()];
+        let state = test_app_state(config, manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(get_request("/api/pb_instances", "valid-admin"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for uri in ["/api/pb_instances", "/api/users/other/pb_status"] {
+            let response = create_api_router(state.clone())
+                .oneshot(get_request(uri, "valid-grace"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], "admin_required");
+            assert_eq!(body["message"], "Administrator access is required");
+        }
+
+        // Listed in ADMIN_EMAILS, with no role on the record
+        let response = create_api_router(state.clone())
+            .oneshot(get_request("/api/pb_instances", "valid-ops"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn restore_request(user_id: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/users/{}/pb_restore", user_id))
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"confirm":true,"backup_id":"missing"}"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_restore_is_for_the_owner_or_an_admin() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let response = create_api_router(state.clone())
+            .oneshot(restore_request("frank", "valid-grace"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Past the check, both find there is no such backup
+        for token in ["valid-frank", "valid-admin"] {
+            let response = create_api_router(state.clone())
+                .oneshot(restore_request("frank", token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", token);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers_reject_unsafe_user_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_url = mock_global_pocketbase().await;
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), 9000, "pocketbase".to_string());
+        let state = test_app_state(test_config(&global_url), manager);
+
+        for (method, uri) in [
+            ("GET", "/api/users/..%2F..%2Fetc/pb_status"),
+            ("GET", "/api/users/%C3%A5lice/pb_stats"),
+            ("POST", "/api/users/..%2Fescape/stop_pb"),
+            ("GET", "/api/users/..%2Fescape/pb/api/health"),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", "Bearer valid-alice")
+                .body(Body::empty())
+                .unwrap();
+            let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{} {}", method, uri);
+        }
+
+        let response = create_api_router(state.clone())
+            .oneshot(delete_request("..%2F..%2Fvictim", "valid-admin", "../../victim"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!dir.path().join("user_dbs").exists());
+    }
+}
@@ -0,0 +1,263 @@
+//! What became of each of a user's tasks, kept in their own instance
+//!
+//! The worker reports how every attempt at a task ended with
+//! `PUT /internal/users/:user_id/processing_results/:task_id`. The user's
+//! `processing_results` collection holds one record per task: the first
+//! attempt creates it and each retry replaces it, so it tells how the latest
+//! attempt went: the Loom link or the error the user was shown, and the
+//! worker's report of the attempt's stages, file sizes and throughput.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Json, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use super::{auth::auth_error, internal::InternalCaller};
+use crate::{
+    global_pb::{quote, GlobalPb, GlobalPbError},
+    pocketbase_manager::{sanitize_user_id, PocketBaseManager},
+};
+use common::ErrorCode;
+
+/// Collection of task results in each user's instance
+pub const PROCESSING_RESULTS: &str = "processing_results";
+
+/// How an attempt at a task ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    Completed,
+    Retrying,
+    Failed,
+    Interrupted,
+    Cancelled,
+}
+
+/// Body of `PUT /internal/users/:user_id/processing_results/:task_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingResult {
+    pub meeting_id: String,
+    pub status: ResultStatus,
+    pub attempt: u32,
+    #[serde(default)]
+    pub loom_url: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub finished_at: DateTime<Utc>,
+    /// The worker's `TaskReport`, kept as sent
+    #[serde(default)]
+    pub report: Value,
+}
+
+/// The `processing_results` collection, readable and writable by admins only
+fn collection_schema() -> Value {
+    let field = |name: &str, kind: &str, required: bool| json!({ "name": name, "type": kind, "required": required });
+    json!({
+        "name": PROCESSING_RESULTS,
+        "type": "base",
+        "schema": [
+            field("task_id", "text", true),
+            field("meeting_id", "text", true),
+            field("status", "text", true),
+            field("attempt", "number", true),
+            field("loom_url", "text", false),
+            field("error", "text", false),
+            field("finished_at", "text", true),
+            field("report", "json", false),
+        ],
+        "indexes": [
+            "CREATE UNIQUE INDEX idx_processing_results_task_id ON processing_results (task_id)"
+        ],
+        "listRule": null,
+        "viewRule": null,
+        "createRule": null,
+        "updateRule": null,
+        "deleteRule": null
+    })
+}
+
+/// Make `result` the record of `task_id` in `pb`, creating it the first time
+pub async fn store_result(
+    pb: &GlobalPb,
+    task_id: Uuid,
+    result: &ProcessingResult,
+) -> Result<(), GlobalPbError> {
+    pb.ensure_collection(&collection_schema()).await?;
+    let mut record = serde_json::to_value(result).unwrap_or_default();
+    record["task_id"] = json!(task_id);
+    let filter = format!("task_id = {}", quote(&task_id.to_string()));
+    let existing = pb.list_records(PROCESSING_RESULTS, Some(&filter)).await?;
+    match existing.first().and_then(|record| record["id"].as_str()) {
+        Some(id) => pb.update_record(PROCESSING_RESULTS, id, &record).await?,
+        None => pb.create_record(PROCESSING_RESULTS, &record).await?,
+    };
+    Ok(())
+}
+
+/// PUT /internal/users/:user_id/processing_results/:task_id - Keep how the
+/// latest attempt at a task ended in its owner's instance
+pub async fn put_processing_result(
+    _caller: InternalCaller,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    Path((user_id, task_id)): Path<(String, Uuid)>,
+    Json(result): Json<ProcessingResult>,
+) -> Result<StatusCode, Response> {
+    if sanitize_user_id(&user_id).is_err() || !pb_manager.data_dir(&user_id).exists() {
+        return Err(auth_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "No such user",
+        ));
+    }
+    let stored = match pb_manager.admin_client(&user_id).await {
+        Ok(pb) => store_result(&pb, task_id, &result).await,
+        Err(e) => {
+            error!("Failed to open the instance of {}: {}", user_id, e);
+            return Err(unavailable());
+        }
+    };
+    match stored {
+        Ok(()) => {
+            info!(user_id = %user_id, task_id = %task_id, status = ?result.status, "Processing result stored");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            error!("Failed to store the result of task {}: {}", task_id, e);
+            Err(unavailable())
+        }
+    }
+}
+
+fn unavailable() -> Response {
+    auth_error(
+        StatusCode::BAD_GATEWAY,
+        ErrorCode::Internal,
+        "Failed to store the processing result",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, AppState},
+        test_support::{
+            fake_pocketbase, mock_global_pocketbase, port_window, spawn_server, test_app_state, test_config,
+        },
+    };
+    use std::time::Duration;
+    use worker::{
+        config::BackendConfig,
+        report::{Measurements, TaskReport},
+        results::{self, BackendResults},
+        WorkerError,
+    };
+
+    async fn setup(dir: &std::path::Path) -> AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+        let data_dir = state.pb_manager.data_dir("alice");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        state
+    }
+
+    fn attempt(status: results::ResultStatus, attempt: u32) -> results::ProcessingResult {
+        let measurements = Measurements::default();
+        let started_at = Utc::now();
+        measurements.stage("download", started_at, Duration::from_millis(2500));
+        measurements.downloaded(5_000_000);
+        let finished_at = started_at + chrono::Duration::seconds(3);
+        results::ProcessingResult {
+            meeting_id: "42".to_string(),
+            status,
+            attempt,
+            loom_url: None,
+            error: Some("Loom API error: 503".to_string()),
+            finished_at,
+            report: TaskReport::new(measurements.snapshot(), status, attempt - 1, started_at, finished_at),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_replace_the_tasks_one_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+        let store = BackendResults {
+            client: reqwest::Client::new(),
+            backend: BackendConfig {
+                url: spawn_server(create_api_router(state.clone())).await,
+                internal_api_token: Some("test-internal-token".to_string()),
+            },
+        };
+        let task_id = Uuid::new_v4();
+
+        store
+            .put("alice", task_id, &attempt(results::ResultStatus::Retrying, 1))
+            .await
+            .unwrap();
+        let pb = state.pb_manager.admin_client("alice").await.unwrap();
+        let records = pb.list_records(PROCESSING_RESULTS, None).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["status"], "retrying");
+        assert_eq!(records[0]["error"], "Loom API error: 503");
+        let id = records[0]["id"].clone();
+
+        let completed = results::ProcessingResult {
+            loom_url: Some("https://www.loom.com/share/abc123".to_string()),
+            error: None,
+            ..attempt(results::ResultStatus::Completed, 2)
+        };
+        store.put("alice", task_id, &completed).await.unwrap();
+        let records = pb.list_records(PROCESSING_RESULTS, None).await.unwrap();
+        assert_eq!(records.len(), 1, "a retry updates the record");
+        let record = &records[0];
+        assert_eq!(record["id"], id);
+        assert_eq!(record["task_id"], task_id.to_string());
+        assert_eq!(record["meeting_id"], "42");
+        assert_eq!(record["status"], "completed");
+        assert_eq!(record["attempt"], 2);
+        assert_eq!(record["report"]["outcome"], "completed");
+        assert_eq!(record["report"]["retry_count"], 1);
+        assert_eq!(record["report"]["stages"][0]["stage"], "download");
+        assert_eq!(record["report"]["download_mb_per_sec"], 2.0);
+        assert_eq!(record["loom_url"], "https://www.loom.com/share/abc123");
+        assert!(record["error"].is_null());
+
+        // Another task gets a record of its own
+        store
+            .put("alice", Uuid::new_v4(), &attempt(results::ResultStatus::Failed, 1))
+            .await
+            .unwrap();
+        assert_eq!(pb.list_records(PROCESSING_RESULTS, None).await.unwrap().len(), 2);
+
+        let error = store
+            .put("bob", task_id, &completed)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, WorkerError::Network(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND)));
+        let unauthorized = BackendResults {
+            backend: BackendConfig {
+                internal_api_token: Some("wrong".to_string()),
+                ..store.backend.clone()
+            },
+            client: reqwest::Client::new(),
+        };
+        let error = unauthorized.put("alice", task_id, &completed).await.unwrap_err();
+        assert!(matches!(error, WorkerError::Network(e) if e.status() == Some(reqwest::StatusCode::UNAUTHORIZED)));
+        state.pb_manager.stop_user_instance("alice").await.unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! The signed-in user's own profile
+//!
+//! `GET /auth/me` combines the global PocketBase user record with the state
+//! of the user's PocketBase instance. `PATCH /auth/me` changes the username
+//! and display name, writing to the record with the user's own PocketBase
+//! token so PocketBase's access rules still apply. Email changes need their
+//! own verified flow and are refused here.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Json, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{
+    auth::{auth_error, check_username},
+    extractors::{AuthUser, Role},
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{GlobalPb, GlobalPbError},
+    pocketbase_manager::{PocketBaseInstance, PocketBaseManager},
+};
+use common::{validation::display_name_problems, ApiResponse, ErrorCode};
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub email: String,
+    pub username: String,
+    pub name: Option<String>,
+    pub role: &'static str,
+    pub verified: bool,
+    pub created: Option<String>,
+    pub updated: Option<String>,
+    /// The user's PocketBase instance, if one has been started
+    pub instance: Option<PocketBaseInstance>,
+}
+
+/// Fields `PATCH /auth/me` accepts; anything else in the body is ignored
+#[derive(Debug, Deserialize)]
+pub struct ProfileUpdate {
+    pub username: Option<String>,
+    pub name: Option<String>,
+    /// Only present to refuse it explicitly
+    pub email: Option<String>,
+}
+
+/// Routes for the caller's profile, nested under `/auth`
+pub fn router() -> Router<AppState> {
+    Router::new().route("/me", get(get_profile).patch(update_profile))
+}
+
+type ProfileResult = Result<Json<ApiResponse<Profile>>, Response>;
+
+async fn profile(record: &Value, config: &Config, pb_manager: &PocketBaseManager) -> Profile {
+    let field = |name: &str| record.get(name).and_then(|v| v.as_str()).map(String::from);
+    let id = field("id").unwrap_or_default();
+    Profile {
+        instance: pb_manager.get_user_instance(&id).await,
+        id,
+        email: field("email").unwrap_or_default(),
+        username: field("username").unwrap_or_default(),
+        name: field("name").filter(|name| !name.is_empty()),
+        role: Role::from_record(record, config).as_str(),
+        verified: record.get("verified").and_then(|v| v.as_bool()).unwrap_or(false),
+        created: field("created"),
+        updated: field("updated"),
+    }
+}
+
+async fn user_record(global_pb: &GlobalPb, user: &AuthUser) -> Result<Value, Response> {
+    match global_pb.get_record("users", &user.id).await {
+        Ok(record) => Ok(record),
+        Err(GlobalPbError::Status { status: 404, .. }) => Err(auth_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "No user record exists for this account",
+        )),
+        Err(GlobalPbError::Request(e)) => {
+            error!("Failed to connect to PocketBase for profile of {}: {}", user.id, e);
+            Err(auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Authentication server unavailable",
+            ))
+        }
+        Err(e) => {
+            error!("Failed to load profile of {}: {}", user.id, e);
+            Err(auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Failed to load profile"))
+        }
+    }
+}
+
+/// GET /auth/me - the caller's profile and PocketBase instance status
+async fn get_profile(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    user: AuthUser,
+) -> ProfileResult {
+    let record = user_record(&global_pb, &user).await?;
+    Ok(Json(ApiResponse::success(profile(&record, &config, &pb_manager).await)))
+}
+
+/// PATCH /auth/me - change the caller's username and display name
+async fn update_profile(
+    State(config): State<Arc<Config>>,
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    user: AuthUser,
+    Json(update): Json<ProfileUpdate>,
+) -> ProfileResult {
+    let invalid = |message: &str| auth_error(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Validation, message);
+
+    if update.email.is_some() {
+        return Err(invalid("Email addresses can't be changed from the profile"));
+    }
+
+    let record = user_record(&global_pb, &user).await?;
+    let mut changes = Map::new();
+    if let Some(name) = &update.name {
+        let problems = display_name_problems(name);
+        if !problems.is_empty() {
+            return Err(invalid(&problems.join("; ")));
+        }
+        changes.insert("name".to_string(), Value::from(name.trim()));
+    }
+    if let Some(username) = update.username.as_deref().map(str::trim) {
+        let unchanged = record
+            .get("username")
+            .and_then(|v| v.as_str())
+            .is_some_and(|current| current.eq_ignore_ascii_case(username));
+        if !unchanged {
+            let username = check_username(&global_pb, username).await?;
+            changes.insert("username".to_string(), Value::from(username));
+        }
+    }
+    if changes.is_empty() {
+        return Ok(Json(ApiResponse::success(profile(&record, &config, &pb_manager).await)));
+    }
+
+    let url = format!("{}/api/collections/users/records/{}", config.database.url, user.id);
+    let response = reqwest::Client::new()
+        .patch(&url)
+        .header("Authorization", &user.token)
+        .json(&changes)
+        .send()
+        .await
+        .map_err(|e| {
+            error!("Failed to connect to PocketBase to update {}: {}", user.id, e);
+            auth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::ServiceUnavailable,
+                "Authentication server unavailable",
+            )
+        })?;
+    if !response.status().is_success() {
+        let status = response.status();
+        warn!("PocketBase refused profile update for {}: {} - {}", user.id, status, response.text().await.unwrap_or_default());
+        return Err(auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Failed to update profile"));
+    }
+    let updated: Value = response.json().await.map_err(|e| {
+        error!("Failed to parse PocketBase profile update: {}", e);
+        auth_error(StatusCode::BAD_GATEWAY, ErrorCode::Internal, "Failed to update profile")
+    })?;
+
+    let fields: Vec<&String> = changes.keys().collect();
+    info!(target: "audit", user_id = %user.id, fields = ?fields, "Profile updated");
+    Ok(Json(ApiResponse::success(profile(&updated, &config, &pb_manager).await)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{create_api_router, AppState},
+        test_support::mock_app_state,
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    /// A state with two registered users, and a session for the first
+    async fn signed_in() -> (AppState, String, Vec<Value>) {
+        let state = mock_app_state().await;
+        let mut users = Vec::new();
+        for (email, username) in [("uma@example.com", "uma"), ("vic@example.com", "vic")] {
+            let record = json!({
+                "email": email,
+                "username": username,
+                "name": "",
+                "verified": true,
+                "created": "2024-01-02 03:04:05.000Z",
+                "updated": "2024-01-02 03:04:05.000Z"
+            });
+            users.push(state.global_pb.create_record("users", &record).await.unwrap());
+        }
+
+        let (_, body) = send(&state, "POST", "/auth/login", None, json!({ "email": "uma@example.com", "password": "password" })).await;
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        (state, token, users)
+    }
+
+    async fn send(state: &AppState, method: &str, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_get_profile() {
+        let (state, token, users) = signed_in().await;
+
+        let (status, body) = send(&state, "GET", "/auth/me", Some(&token), Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let profile = &body["data"];
+        assert_eq!(profile["id"], users[0]["id"]);
+        assert_eq!(profile["email"], "uma@example.com");
+        assert_eq!(profile["username"], "uma");
+        assert_eq!(profile["role"], "user");
+        assert_eq!(profile["verified"], true);
+        assert_eq!(profile["created"], "2024-01-02 03:04:05.000Z");
+        assert!(profile["instance"].is_null());
+
+        let (status, _) = send(&state, "GET", "/auth/me", None, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_patch_updates_own_record_only() {
+        let (state, token, users) = signed_in().await;
+
+        // Ids, roles and other users' fields in the body are ignored
+        let patch = json!({
+            "username": "Uma.R",
+            "name": " Uma Rao ",
+            "id": users[1]["id"],
+            "role": "admin",
+            "verified": false
+        });
+        let (status, body) = send(&state, "PATCH", "/auth/me", Some(&token), patch).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["username"], "uma.r");
+        assert_eq!(body["data"]["name"], "Uma Rao");
+        assert_eq!(body["data"]["role"], "user");
+
+        let records = state.global_pb.list_records("users", None).await.unwrap();
+        assert_eq!(records[0]["username"], "uma.r");
+        assert_eq!(records[0]["verified"], true);
+        assert!(records[0].get("role").is_none());
+        assert_eq!(records[1], users[1]);
+    }
+
+    #[tokio::test]
+    async fn test_patch_validation_failures() {
+        let (state, token, _) = signed_in().await;
+
+        for (patch, status, code, message) in [
+            (json!({ "username": "vic" }), StatusCode::CONFLICT, "username_taken", "Username is already taken"),
+            (
+                json!({ "username": "no spaces!" }),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation",
+                "Username may only contain letters, numbers, '_', '.' and '-'",
+            ),
+            (json!({ "name": "   " }), StatusCode::UNPROCESSABLE_ENTITY, "validation", "Display name cannot be blank"),
+            (
+                json!({ "email": "new@example.com" }),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation",
+                "Email addresses can't be changed from the profile",
+            ),
+        ] {
+            let (actual, body) = send(&state, "PATCH", "/auth/me", Some(&token), patch).await;
+            assert_eq!(actual, status);
+            assert_eq!(body["code"], code);
+            assert_eq!(body["error"], message);
+        }
+
+        let records = state.global_pb.list_records("users", None).await.unwrap();
+        assert_eq!(records[0]["username"], "uma");
+        assert_eq!(records[0]["email"], "uma@example.com");
+    }
+}
@@ -1,30 +1,68 @@
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::Json,
-    routing::{get, post, delete},
+    response::{IntoResponse, Json, Response},
+    routing::{get, patch, post, delete},
     Router,
 };
-use crate::api::websocket::{QueueUpdate, QueueUpdateType};
+use crate::api::{
+    email_verification,
+    extractors::AuthUser,
+    meetings,
+    websocket::{QueueUpdate, QueueUpdateType},
+};
+use crate::config::Config;
+use common::{
+    queue_order::{move_within, queue_version},
+    ApiResponse, AppError, ErrorCode,
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use serde_json::{json, Value};
+use tracing::error;
 use uuid::Uuid;
 
-use crate::pocketbase_manager::PocketBaseManager;
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Meeting {
     pub id: Uuid,
     pub user_id: String,
     pub topic: String,
     pub position: usize,
+    /// Fathom recording this is; the same one is queued once per user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meeting_id: Option<String>,
+    /// Fathom key to fetch with; the user's default unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fathom_key_id: Option<String>,
+    /// Loom key to upload with; the user's default unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loom_key_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MeetingRequest {
     pub user_id: String,
     pub topic: String,
+    #[serde(default)]
+    pub meeting_id: Option<String>,
+    #[serde(default)]
+    pub fathom_key_id: Option<String>,
+    #[serde(default)]
+    pub loom_key_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReorderRequest {
+    /// Where to move the meeting, counting from 1
+    pub position: usize,
+    /// [`queue_version`] of the queue the move was made against
+    pub queue_version: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AddQuery {
+    /// Check that the recording can be downloaded before queueing it
+    #[serde(default)]
+    pub verify: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,8 +72,15 @@ pub struct QueueResponse {
     pub data: Option<Vec<Meeting>>,
 }
 
-/// In-memory storage for meetings queue (replace with persistent storage in prod)
-type MeetingsQueue = Arc<RwLock<Vec<Meeting>>>;
+/// What [`enqueue`] did with a meeting
+#[derive(Debug, Clone)]
+pub enum Enqueued {
+    Added(Meeting),
+    /// The user already has this recording queued, as this entry
+    AlreadyQueued(Meeting),
+    /// The user has `max_queue_items_per_user` meetings queued already
+    QueueFull,
+}
 
 /// Create router for queue management
 pub fn router() -> Router<crate::api::AppState> {
@@ -43,22 +88,135 @@ pub fn router() -> Router<crate::api::AppState> {
         .route("/queue", post(add_meetings))
         .route("/queue", get(get_queue))
         .route("/queue/:id", delete(remove_meeting))
+        .route("/queue/:id/position", patch(reorder_meeting))
 }
 
 /// POST /api/queue - Add meetings to the queue
+///
+/// Only users with a verified email address may queue meetings, and only
+/// admins may queue them for someone else. With `?verify=true` a meeting
+/// naming its recording is only queued once the recording is found to be
+/// downloadable with the Fathom key of the user it is queued for.
 pub async fn add_meetings(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>,
+    State(app_state): State<crate::api::AppState>,
+    user: AuthUser,
+    query: Option<Query<AddQuery>>,
     Json(payload): Json<MeetingRequest>,
-) -> Result<Json<QueueResponse>, StatusCode> {
-    let queue = &app_state.meetings_queue;
-    let mut queue = queue.write().await;
-    let position = queue.len() + 1;
+) -> Result<Json<QueueResponse>, Response> {
+    if let Some(refusal) = maintenance_refusal(&app_state.config) {
+        return Err(refusal);
+    }
+    let Query(query) = query.unwrap_or_default();
+    if payload.user_id != user.id {
+        user.require_admin().map_err(IntoResponse::into_response)?;
+    }
+
+    match email_verification::is_verified(&app_state.global_pb, &user.id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let error = AppError::Validation(
+                "Verify your email address before adding meetings to the queue".to_string(),
+            );
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "success": false,
+                    "error": "validation",
+                    "message": error.to_string()
+                })),
+            )
+                .into_response());
+        }
+        Err(e) => {
+            error!("Failed to look up verification status for {}: {}", user.id, e);
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                Json(json!({
+                    "success": false,
+                    "message": "Failed to check email verification"
+                })),
+            )
+                .into_response());
+        }
+    }
+
+    if let Some(meeting_id) = payload.meeting_id.as_ref().filter(|_| query.verify) {
+        let owner = AuthUser { id: payload.user_id.clone(), ..user.clone() };
+        let check = meetings::download_check(
+            &app_state.pb_manager,
+            &app_state.master_key,
+            &app_state.config,
+            &app_state.rate_limits,
+            &owner,
+            meeting_id,
+        )
+        .await?;
+        if !check.downloadable {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "success": false,
+                    "error": "validation",
+                    "message": check.reason.describe(),
+                    "data": check
+                })),
+            )
+                .into_response());
+        }
+    }
+
+    let (enqueued, queue) = enqueue(&app_state, payload).await;
+    let message = match enqueued {
+        Enqueued::Added(_) => "Meeting added to queue",
+        Enqueued::AlreadyQueued(_) => "Meeting is already in the queue",
+        Enqueued::QueueFull => {
+            let message = format!(
+                "No more than {} meetings can be queued at once; wait for some to finish",
+                app_state.config.features.max_queue_items_per_user
+            );
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<Value>::failure(ErrorCode::QueueFull, message)),
+            )
+                .into_response());
+        }
+    };
+    Ok(Json(QueueResponse {
+        success: true,
+        message: message.into(),
+        data: Some(queue),
+    }))
+}
+
+/// Append the meeting `request` describes to the queue and tell WebSocket
+/// clients, returning the queue after
+///
+/// A recording the user already has queued is left where it is, and nothing
+/// is added for a user at `max_queue_items_per_user`. Whether the caller may
+/// queue for `request.user_id` is theirs to check.
+pub async fn enqueue(app_state: &crate::api::AppState, request: MeetingRequest) -> (Enqueued, Vec<Meeting>) {
+    let mut queue = app_state.meetings_queue.write().await;
+    let queued = request.meeting_id.as_ref().and_then(|meeting_id| {
+        queue
+            .iter()
+            .find(|meeting| meeting.user_id == request.user_id && meeting.meeting_id.as_ref() == Some(meeting_id))
+    });
+    if let Some(queued) = queued {
+        return (Enqueued::AlreadyQueued(queued.clone()), queue.clone());
+    }
+    let limit = app_state.config.features.max_queue_items_per_user;
+    if limit > 0 && queue.iter().filter(|meeting| meeting.user_id == request.user_id).count() >= limit {
+        return (Enqueued::QueueFull, queue.clone());
+    }
 
     let meeting = Meeting {
         id: Uuid::new_v4(),
-        user_id: payload.user_id.clone(),
-        topic: payload.topic.clone(),
-        position,
+        user_id: request.user_id,
+        topic: request.topic,
+        position: queue.len() + 1,
+        meeting_id: request.meeting_id,
+        fathom_key_id: request.fathom_key_id,
+        loom_key_id: request.loom_key_id,
     };
 
     queue.push(meeting.clone());
@@ -71,19 +229,34 @@ pub async fn add_meetings(
         queue: queue_clone.clone(),
         affected_user_id: Some(meeting.user_id.clone()),
         global_position: Some(meeting.position),
+        task_id: None,
+        retry: None,
+        error: None,
         timestamp: chrono::Utc::now(),
     }).await;
 
-    Ok(Json(QueueResponse {
-        success: true,
-        message: "Meeting added to queue".into(),
-        data: Some(queue_clone),
-    }))
+    (Enqueued::Added(meeting), queue_clone)
+}
+
+/// The refusal of a queue change while the deployment is in maintenance mode
+pub fn maintenance_refusal(config: &Config) -> Option<Response> {
+    if !config.features.maintenance_mode {
+        return None;
+    }
+    Some((
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<Value>::failure(
+            ErrorCode::Maintenance,
+            "The queue can't be changed during maintenance; try again later",
+        )),
+    )
+        .into_response())
 }
 
 /// GET /api/queue - Get all meetings in the queue
 pub async fn get_queue(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>
+    State(app_state): State<crate::api::AppState>,
+    _user: AuthUser,
 ) -> Result<Json<QueueResponse>, StatusCode> {
     let queue = &app_state.meetings_queue;
     let queue = queue.read().await;
@@ -95,13 +268,22 @@ pub async fn get_queue(
 }
 
 /// DELETE /api/queue/:id - Remove a meeting from the queue
+///
+/// Users can remove their own meetings; admins can remove anyone's.
 pub async fn remove_meeting(
-    axum::extract::State(app_state): axum::extract::State<crate::api::AppState>,
-    axum::extract::Path(id): axum::extract::Path<Uuid>,
-) -> Result<Json<QueueResponse>, StatusCode> {
+    State(app_state): State<crate::api::AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<QueueResponse>, Response> {
+    if let Some(refusal) = maintenance_refusal(&app_state.config) {
+        return Err(refusal);
+    }
     let queue = &app_state.meetings_queue;
     let mut queue = queue.write().await;
     if let Some(pos) = queue.iter().position(|m| m.id == id) {
+        if queue[pos].user_id != user.id {
+            user.require_admin().map_err(IntoResponse::into_response)?;
+        }
         let removed_meeting = queue.remove(pos);
         for (i, meeting) in queue.iter_mut().enumerate() {
             meeting.position = i + 1;
@@ -116,6 +298,9 @@ pub async fn remove_meeting(
             queue: queue_clone.clone(),
             affected_user_id: Some(removed_meeting.user_id.clone()),
             global_position: Some(pos + 1), // Previous position
+            task_id: None,
+            retry: None,
+            error: None,
             timestamp: chrono::Utc::now(),
         }).await;
 
@@ -135,3 +320,304 @@ pub async fn remove_meeting(
         }))
     }
 }
+
+/// PATCH /api/queue/:id/position - Move a meeting to another position
+///
+/// Admins may move any meeting anywhere; other users may move their own
+/// meetings among their own, leaving everyone else's where they are. A move
+/// made against a `queue_version` other than the current queue's is refused
+/// with 409 `queue_changed` rather than applied to a queue its sender
+/// hasn't seen.
+pub async fn reorder_meeting(
+    State(app_state): State<crate::api::AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ReorderRequest>,
+) -> Result<Json<QueueResponse>, Response> {
+    if let Some(refusal) = maintenance_refusal(&app_state.config) {
+        return Err(refusal);
+    }
+    let refuse = |status: StatusCode, code: ErrorCode, message: String| {
+        (status, Json(ApiResponse::<Value>::failure(code, message))).into_response()
+    };
+    let mut queue = app_state.meetings_queue.write().await;
+    if queue_version(queue.iter().map(|meeting| &meeting.id)) != payload.queue_version {
+        return Err(refuse(
+            StatusCode::CONFLICT,
+            ErrorCode::QueueChanged,
+            "The queue changed since it was loaded; try again".to_string(),
+        ));
+    }
+    let Some(from) = queue.iter().position(|meeting| meeting.id == id) else {
+        return Err(refuse(StatusCode::NOT_FOUND, ErrorCode::NotFound, "Meeting not found".to_string()));
+    };
+    if payload.position == 0 || payload.position > queue.len() {
+        return Err(refuse(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            format!("position must be between 1 and {}", queue.len()),
+        ));
+    }
+    let to = payload.position - 1;
+    let movable = |meeting: &Meeting| user.is_admin() || meeting.user_id == user.id;
+    if !movable(&queue[from]) || !movable(&queue[to]) {
+        user.require_admin().map_err(IntoResponse::into_response)?;
+    }
+    move_within(&mut queue, from, to, movable);
+    for (i, meeting) in queue.iter_mut().enumerate() {
+        meeting.position = i + 1;
+    }
+
+    let moved_user_id = queue[to].user_id.clone();
+    let queue_clone = queue.clone();
+    drop(queue); // Release the write lock before broadcasting
+
+    app_state.ws_manager.broadcast_queue_update(QueueUpdate {
+        update_type: QueueUpdateType::PositionUpdated,
+        queue: queue_clone.clone(),
+        affected_user_id: Some(moved_user_id),
+        global_position: Some(to + 1),
+        task_id: None,
+        retry: None,
+        error: None,
+        timestamp: chrono::Utc::now(),
+    }).await;
+
+    Ok(Json(QueueResponse {
+        success: true,
+        message: "Meeting moved".into(),
+        data: Some(queue_clone),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, AppState},
+        test_support::mock_app_state_with,
+    };
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn test_state() -> AppState {
+        state_with(|_| {}).await
+    }
+
+    async fn state_with(configure: impl FnOnce(&mut Config)) -> AppState {
+        mock_app_state_with(configure).await
+    }
+
+    fn request_for(user_id: &str, meeting_id: &str) -> MeetingRequest {
+        MeetingRequest {
+            user_id: user_id.to_string(),
+            topic: "Standup".to_string(),
+            meeting_id: Some(meeting_id.to_string()),
+            fathom_key_id: None,
+            loom_key_id: None,
+        }
+    }
+
+    async fn login(state: &AppState, email: &str) -> String {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "email": email, "password": "password" }).to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"]["token"].as_str().unwrap().to_string()
+    }
+
+    async fn send(state: &AppState, method: &str, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn reorder(state: &AppState, id: Uuid, position: usize, token: &str) -> (StatusCode, Value) {
+        let version = queue_version(state.meetings_queue.read().await.iter().map(|meeting| &meeting.id));
+        let request = Request::builder()
+            .method("PATCH")
+            .uri(format!("/api/queue/{}/position", id))
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "position": position, "queue_version": version }).to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn order(state: &AppState) -> Vec<Uuid> {
+        state.meetings_queue.read().await.iter().map(|meeting| meeting.id).collect()
+    }
+
+    async fn queue_meeting(state: &AppState, user_id: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        let mut queue = state.meetings_queue.write().await;
+        let position = queue.len() + 1;
+        queue.push(Meeting {
+            id,
+            user_id: user_id.to_string(),
+            topic: "Standup".to_string(),
+            position,
+            meeting_id: None,
+            fathom_key_id: None,
+            loom_key_id: None,
+        });
+        id
+    }
+
+    #[tokio::test]
+    async fn test_queue_routes_require_authentication() {
+        let state = test_state().await;
+        let (status, body) = send(&state, "GET", "/api/queue", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "missing_authorization");
+
+        let token = login(&state, "pat@example.com").await;
+        let (status, body) = send(&state, "GET", "/api/queue", Some(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_only_owners_and_admins_remove_meetings() {
+        let state = test_state().await;
+        let owner = login(&state, "quinn@example.com").await;
+        let other = login(&state, "rosa@example.com").await;
+        let admin = login(&state, "admin@example.com").await;
+        let first = queue_meeting(&state, "quinn").await;
+        let second = queue_meeting(&state, "quinn").await;
+
+        // Authenticated but not allowed is 403, distinct from the 401 above
+        let (status, body) = send(&state, "DELETE", &format!("/api/queue/{}", first), Some(&other)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "admin_required");
+
+        let (status, _) = send(&state, "DELETE", &format!("/api/queue/{}", first), Some(&owner)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&state, "DELETE", &format!("/api/queue/{}", second), Some(&admin)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["data"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_refuses_queue_changes() {
+        let state = state_with(|config| config.features.maintenance_mode = true).await;
+        let token = login(&state, "sam@example.com").await;
+        let queued = queue_meeting(&state, "sam").await;
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/queue")
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "user_id": "sam", "topic": "Retro" }).to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "maintenance");
+
+        let (status, body) = send(&state, "DELETE", &format!("/api/queue/{}", queued), Some(&token)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "maintenance");
+
+        // Reading the queue still works, and nothing changed
+        let (status, body) = send(&state, "GET", "/api/queue", Some(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_users_can_only_queue_up_to_their_limit() {
+        let state = state_with(|config| config.features.max_queue_items_per_user = 2).await;
+        for meeting_id in ["m1", "m2"] {
+            let (enqueued, _) = enqueue(&state, request_for("tess", meeting_id)).await;
+            assert!(matches!(enqueued, Enqueued::Added(_)));
+        }
+
+        let (enqueued, queue) = enqueue(&state, request_for("tess", "m3")).await;
+        assert!(matches!(enqueued, Enqueued::QueueFull));
+        assert_eq!(queue.len(), 2);
+
+        // Requeueing one already there isn't a new item, and others have their own limit
+        let (enqueued, _) = enqueue(&state, request_for("tess", "m1")).await;
+        assert!(matches!(enqueued, Enqueued::AlreadyQueued(_)));
+        let (enqueued, _) = enqueue(&state, request_for("uma", "m3")).await;
+        assert!(matches!(enqueued, Enqueued::Added(_)));
+    }
+
+    #[tokio::test]
+    async fn test_users_reorder_their_own_meetings_and_admins_any() {
+        let state = test_state().await;
+        let owner = login(&state, "vic@example.com").await;
+        let admin = login(&state, "admin@example.com").await;
+        let first = queue_meeting(&state, "vic").await;
+        let theirs = queue_meeting(&state, "wren").await;
+        let second = queue_meeting(&state, "vic").await;
+
+        // Among their own, stepping over someone else's
+        let (status, body) = reorder(&state, second, 1, &owner).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order(&state).await, [second, theirs, first]);
+        let positions: Vec<_> = body["data"].as_array().unwrap().iter().map(|meeting| meeting["position"].clone()).collect();
+        assert_eq!(positions, [1, 2, 3]);
+
+        // Not theirs to move, nor to displace
+        let (status, body) = reorder(&state, theirs, 1, &owner).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::FORBIDDEN, Some("admin_required")));
+        let (status, _) = reorder(&state, first, 2, &owner).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = reorder(&state, theirs, 1, &admin).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order(&state).await, [theirs, second, first]);
+
+        let (status, body) = reorder(&state, first, 4, &admin).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNPROCESSABLE_ENTITY, Some("validation")));
+        let (status, _) = reorder(&state, Uuid::new_v4(), 1, &admin).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_moves_against_a_changed_queue_are_refused() {
+        let state = test_state().await;
+        let token = login(&state, "xan@example.com").await;
+        let first = queue_meeting(&state, "xan").await;
+        queue_meeting(&state, "xan").await;
+        let stale = queue_version(order(&state).await.iter());
+        queue_meeting(&state, "xan").await;
+
+        let request = Request::builder()
+            .method("PATCH")
+            .uri(format!("/api/queue/{}/position", first))
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "position": 2, "queue_version": stale }).to_string()))
+            .unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "queue_changed");
+        assert_eq!(order(&state).await[0], first, "nothing moved");
+    }
+}
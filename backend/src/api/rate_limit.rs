@@ -0,0 +1,420 @@
+//! Fixed-window rate limiting for unauthenticated endpoints
+//!
+//! Counters are kept in memory per key (an email address, a client IP, ...)
+//! and reset once their window has passed, so limits apply per backend
+//! process rather than across replicas. Calls to third-party APIs are paced
+//! by a [`TokenBucket`] instead, which lets a short burst through but no more
+//! than its rate over time.
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use common::{ApiResponse, ErrorCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::Mutex, time::Instant};
+
+use super::request_limit::RequestLimits;
+use crate::{
+    config::{IntegrationsConfig, RateLimitConfig, SecurityConfig},
+    fathom::FathomLimiter,
+};
+
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request for `key`, returning false once it is over the limit
+    pub async fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().await;
+        windows.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+
+        let (_, count) = windows.entry(key.to_string()).or_insert((now, 0));
+        if *count >= self.max_requests {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+/// Per-key token buckets holding up to `capacity` tokens, refilled at
+/// `capacity` per `period`
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    buckets: Mutex<HashMap<String, (Instant, f64)>>,
+    allowed: AtomicU64,
+    refused: AtomicU64,
+}
+
+/// How a [`TokenBucket`] has fared since startup
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketStats {
+    pub allowed: u64,
+    pub refused: u64,
+    /// Keys whose bucket isn't full
+    pub keys_limited: usize,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Self {
+            capacity,
+            per_second: capacity / period.as_secs_f64().max(f64::EPSILON),
+            buckets: Mutex::new(HashMap::new()),
+            allowed: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a token for `key`, or say how long until one is there
+    pub async fn take(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        // Buckets that have filled up again are as good as new
+        buckets.retain(|_, bucket| self.refill(bucket, now) < self.capacity);
+
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert((now, self.capacity));
+        if bucket.1 >= 1.0 {
+            bucket.1 -= 1.0;
+            self.allowed.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        } else {
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            Err(Duration::from_secs_f64((1.0 - bucket.1) / self.per_second))
+        }
+    }
+
+    /// Top `bucket` up for the time since it was last, returning its tokens
+    fn refill(&self, bucket: &mut (Instant, f64), now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.0).as_secs_f64();
+        *bucket = (now, (bucket.1 + elapsed * self.per_second).min(self.capacity));
+        bucket.1
+    }
+
+    pub async fn stats(&self) -> BucketStats {
+        BucketStats {
+            allowed: self.allowed.load(Ordering::Relaxed),
+            refused: self.refused.load(Ordering::Relaxed),
+            keys_limited: self.buckets.lock().await.len(),
+        }
+    }
+}
+
+struct ClientBucket {
+    refilled_at: Instant,
+    tokens: f64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Clients {
+    map: HashMap<String, ClientBucket>,
+    /// Monotonic counter recording how recently each client was seen
+    clock: u64,
+}
+
+/// Token buckets like [`TokenBucket`]'s for at most `max_clients` keys
+///
+/// Once full, buckets that have filled up again are dropped first, then the
+/// least recently seen, so a flood of new clients can't grow it without
+/// bound. A forgotten client starts over with a full bucket.
+pub struct ClientBuckets {
+    capacity: f64,
+    per_second: f64,
+    max_clients: usize,
+    clients: Mutex<Clients>,
+}
+
+impl ClientBuckets {
+    pub fn new(capacity: u32, period: Duration, max_clients: usize) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Self {
+            capacity,
+            per_second: capacity / period.as_secs_f64().max(f64::EPSILON),
+            max_clients: max_clients.max(1),
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+
+    /// Take a token for `key`, or say how long until one is there
+    pub async fn take(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().await;
+        clients.clock += 1;
+        let seen = clients.clock;
+
+        if !clients.map.contains_key(key) && clients.map.len() >= self.max_clients {
+            clients.map.retain(|_, bucket| self.tokens_at(bucket, now) < self.capacity);
+            if clients.map.len() >= self.max_clients {
+                let oldest = clients
+                    .map
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    clients.map.remove(&oldest);
+                }
+            }
+        }
+
+        let capacity = self.capacity;
+        let bucket = clients.map.entry(key.to_string()).or_insert(ClientBucket {
+            refilled_at: now,
+            tokens: capacity,
+            last_used: seen,
+        });
+        bucket.tokens = self.tokens_at(bucket, now);
+        bucket.refilled_at = now;
+        bucket.last_used = seen;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        }
+    }
+
+    fn tokens_at(&self, bucket: &ClientBucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.capacity)
+    }
+
+    /// Clients with a bucket
+    pub async fn len(&self) -> usize {
+        self.clients.lock().await.map.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+/// Limiters for the unauthenticated auth endpoints, and for endpoints that
+/// call third-party services on the user's behalf
+pub struct RateLimits {
+    pub password_reset_per_email: RateLimiter,
+    pub password_reset_per_ip: RateLimiter,
+    pub verification_resend_per_user: RateLimiter,
+    /// Keyed by `<user id>:<service>`
+    pub key_validation_per_service: RateLimiter,
+    /// Each user's Fathom requests, and how they are retried
+    pub fathom: Arc<FathomLimiter>,
+    /// One forced meetings refresh per user per cooldown
+    pub meetings_refresh_per_user: TokenBucket,
+    /// Fathom webhook deliveries, keyed by workspace id; `None` when off
+    pub webhook_per_workspace: Option<RateLimiter>,
+    /// Every client's requests, by kind of route
+    pub requests: RequestLimits,
+}
+
+impl RateLimits {
+    pub fn new(
+        security: &SecurityConfig,
+        integrations: &IntegrationsConfig,
+        rate_limits: &RateLimitConfig,
+    ) -> Self {
+        let window = Duration::from_secs(security.password_reset_window_secs);
+        Self {
+            password_reset_per_email: RateLimiter::new(security.password_reset_max_per_email, window),
+            password_reset_per_ip: RateLimiter::new(security.password_reset_max_per_ip, window),
+            verification_resend_per_user: RateLimiter::new(
+                security.verification_resend_max,
+                Duration::from_secs(security.verification_resend_window_secs),
+            ),
+            key_validation_per_service: RateLimiter::new(
+                integrations.key_validation_max,
+                Duration::from_secs(integrations.key_validation_window_secs),
+            ),
+            fathom: Arc::new(FathomLimiter::new(
+                integrations.fathom_requests_per_minute,
+                integrations.fathom_max_attempts,
+            )),
+            meetings_refresh_per_user: TokenBucket::new(1, integrations.meetings_refresh_cooldown),
+            webhook_per_workspace: (rate_limits.webhook_per_minute > 0)
+                .then(|| RateLimiter::new(rate_limits.webhook_per_minute, Duration::from_secs(60))),
+            requests: RequestLimits::new(rate_limits),
+        }
+    }
+}
+
+/// 429 with how long to wait, in whole seconds, when that is known
+pub fn rate_limited(retry_after: Option<Duration>, message: &str) -> Response {
+    let failure = ApiResponse::<Value>::failure(ErrorCode::RateLimited, message);
+    let Some(retry_after) = retry_after else {
+        return (StatusCode::TOO_MANY_REQUESTS, Json(failure)).into_response();
+    };
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let body = ApiResponse {
+        data: Some(json!({ "retry_after_secs": secs })),
+        ..failure
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// The address requests from this caller are counted against
+///
+/// Proxy headers are only believed when `trust_proxy_headers` is set, since
+/// clients talking to the backend directly could otherwise pick their own
+/// bucket. Even then only the last `X-Forwarded-For` entry is taken: the
+/// trusted proxy appends the address it saw, and anything before it came
+/// from the client. Without a known peer, callers share the `unknown` bucket.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_proxy_headers: bool) -> String {
+    let forwarded = trust_proxy_headers
+        .then(|| {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .or_else(|| headers.get("x-real-ip").and_then(|value| value.to_str().ok()))
+                .map(|ip| ip.trim().to_string())
+                .filter(|ip| !ip.is_empty())
+        })
+        .flatten();
+
+    forwarded
+        .or_else(|| peer.map(|addr| addr.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limit_resets_after_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check("a").await);
+        assert!(limiter.check("a").await);
+        assert!(!limiter.check("a").await);
+        assert!(limiter.check("b").await);
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert!(limiter.check("a").await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_buckets_refill_at_their_rate() {
+        let bucket = TokenBucket::new(2, Duration::from_secs(60));
+        assert_eq!(bucket.take("a").await, Ok(()));
+        assert_eq!(bucket.take("a").await, Ok(()));
+        assert_eq!(bucket.take("a").await, Err(Duration::from_secs(30)));
+        assert_eq!(bucket.take("b").await, Ok(()));
+
+        // One token back every 30 seconds
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        let wait = bucket.take("a").await.unwrap_err();
+        assert!(wait <= Duration::from_secs(10) && wait > Duration::from_secs(9));
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(bucket.take("a").await, Ok(()));
+
+        assert_eq!(
+            bucket.stats().await,
+            BucketStats {
+                allowed: 4,
+                refused: 2,
+                keys_limited: 2
+            }
+        );
+        // Full buckets are forgotten
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(bucket.take("c").await, Ok(()));
+        assert_eq!(bucket.stats().await.keys_limited, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_client_buckets_run_out_and_refill() {
+        let buckets = ClientBuckets::new(2, Duration::from_secs(60), 10);
+        assert_eq!(buckets.take("a").await, Ok(()));
+        assert_eq!(buckets.take("a").await, Ok(()));
+        assert_eq!(buckets.take("a").await, Err(Duration::from_secs(30)));
+        assert_eq!(buckets.take("b").await, Ok(()));
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_eq!(buckets.take("a").await, Ok(()));
+        assert!(buckets.take("a").await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_client_buckets_forget_the_least_recently_seen_when_full() {
+        let buckets = ClientBuckets::new(1, Duration::from_secs(60), 2);
+        assert_eq!(buckets.take("a").await, Ok(()));
+        assert_eq!(buckets.take("b").await, Ok(()));
+        // Seeing a again makes b the oldest
+        assert!(buckets.take("a").await.is_err());
+        assert_eq!(buckets.take("c").await, Ok(()));
+        assert_eq!(buckets.len().await, 2);
+        assert!(buckets.take("a").await.is_err());
+        // b was forgotten, so it starts over
+        assert_eq!(buckets.take("b").await, Ok(()));
+
+        // Refilled buckets go before any that are still limited
+        let buckets = ClientBuckets::new(1, Duration::from_secs(60), 2);
+        buckets.take("a").await.unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        buckets.take("b").await.unwrap();
+        buckets.take("c").await.unwrap();
+        assert!(buckets.take("b").await.is_err());
+        assert_eq!(buckets.len().await, 2);
+    }
+
+    #[test]
+    fn test_client_ip_only_trusts_proxy_headers_when_configured() {
+        let peer: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, None, true), "unknown");
+        assert_eq!(client_ip(&headers, Some(peer), true), "192.0.2.1");
+
+        headers.insert("x-real-ip", "10.0.0.2".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), true), "10.0.0.2");
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), true), "203.0.113.7");
+        assert_eq!(client_ip(&headers, Some(peer), false), "192.0.2.1");
+    }
+
+    #[test]
+    fn test_client_ip_ignores_forwarded_entries_the_client_sent() {
+        let peer: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.99, 203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), true), "203.0.113.7");
+
+        // A header of the client's own comes before the one the proxy added
+        headers.insert("x-forwarded-for", "198.51.100.99".parse().unwrap());
+        headers.append("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), true), "203.0.113.7");
+    }
+}
@@ -0,0 +1,329 @@
+//! Per-client request limits in front of every route
+//!
+//! Requests are counted against a budget for their kind of route: signing
+//! in, reading, or changing something. A client is its user when it presents
+//! a valid session token and its IP otherwise, so users behind one NAT don't
+//! share a budget while anonymous callers can't dodge theirs by omitting
+//! one. Over budget, the request is answered 429 with `Retry-After` before
+//! any handler runs. Health probes, `/metrics` and the worker's `/internal`
+//! routes aren't limited.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use std::{net::SocketAddr, time::Duration};
+
+use super::{
+    auth::session_token,
+    jwt::JwtKeys,
+    rate_limit::{client_ip, rate_limited, ClientBuckets},
+    AppState,
+};
+use crate::{
+    config::{Config, RateLimitConfig},
+    metrics::{self, RATE_LIMITED_REQUESTS},
+};
+
+/// Which budget a request is counted against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    /// Anything under `/auth`
+    Auth,
+    /// `GET` and `HEAD` under `/api`
+    Read,
+    /// Other methods under `/api`, and webhooks
+    Write,
+}
+
+impl RouteClass {
+    pub const ALL: [RouteClass; 3] = [RouteClass::Auth, RouteClass::Read, RouteClass::Write];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteClass::Auth => "auth",
+            RouteClass::Read => "read",
+            RouteClass::Write => "write",
+        }
+    }
+
+    /// The class of a request, or `None` for routes that aren't limited
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        let under = |prefix: &str| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        if under("/auth") {
+            Some(RouteClass::Auth)
+        } else if under("/api") {
+            match *method {
+                Method::GET | Method::HEAD => Some(RouteClass::Read),
+                // CORS preflights carry no credentials and do nothing
+                Method::OPTIONS => None,
+                _ => Some(RouteClass::Write),
+            }
+        } else if under("/webhooks") {
+            Some(RouteClass::Write)
+        } else {
+            None
+        }
+    }
+}
+
+/// A budget per route class; `None` where the limit is turned off
+pub struct RequestLimits {
+    auth: Option<ClientBuckets>,
+    read: Option<ClientBuckets>,
+    write: Option<ClientBuckets>,
+}
+
+impl RequestLimits {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let per_minute = |requests: u32| {
+            (requests > 0)
+                .then(|| ClientBuckets::new(requests, Duration::from_secs(60), config.max_clients))
+        };
+        Self {
+            auth: per_minute(config.auth_per_minute),
+            read: per_minute(config.read_per_minute),
+            write: per_minute(config.write_per_minute),
+        }
+    }
+
+    pub fn get(&self, class: RouteClass) -> Option<&ClientBuckets> {
+        match class {
+            RouteClass::Auth => self.auth.as_ref(),
+            RouteClass::Read => self.read.as_ref(),
+            RouteClass::Write => self.write.as_ref(),
+        }
+    }
+}
+
+/// Who a request is counted against: `user:<id>` or `ip:<address>`
+pub fn client_key(config: &Config, request: &Request) -> String {
+    // Only our own signed tokens name a user; anything else counts by IP
+    let user = session_token(request.headers())
+        .and_then(|token| JwtKeys::new(&config.security).verify(token).ok());
+    match user {
+        Some(claims) => format!("user:{}", claims.sub),
+        None => {
+            let peer = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr);
+            let ip = client_ip(request.headers(), peer, config.security.trust_proxy_headers);
+            format!("ip:{}", ip)
+        }
+    }
+}
+
+/// Refuse requests over their client's budget
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(class) = RouteClass::of(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let Some(buckets) = state.rate_limits.requests.get(class) else {
+        return next.run(request).await;
+    };
+
+    let key = client_key(&state.config, &request);
+    match buckets.take(&key).await {
+        Ok(()) => {
+            metrics::increment(
+                &RATE_LIMITED_REQUESTS,
+                &[("class", class.as_str()), ("outcome", "allowed")],
+            );
+            next.run(request).await
+        }
+        Err(retry_after) => {
+            metrics::increment(
+                &RATE_LIMITED_REQUESTS,
+                &[("class", class.as_str()), ("outcome", "limited")],
+            );
+            rate_limited(
+                Some(retry_after),
+                "Too many requests; slow down and try again shortly",
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        create_api_router,
+        extractors::{AuthUser, Role},
+    };
+    use crate::test_support::mock_app_state_with;
+    use axum::{body::Body, http::StatusCode, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn app(configure: impl FnOnce(&mut Config)) -> (Router, Config) {
+        let state = mock_app_state_with(configure).await;
+        let config = Config::clone(&state.config);
+        (create_api_router(state), config)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        peer: &str,
+        token: Option<&str>,
+    ) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let peer: SocketAddr = format!("{}:5000", peer).parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    fn token_for(config: &Config, id: &str) -> String {
+        let user = AuthUser {
+            id: id.to_string(),
+            email: format!("{}@example.com", id),
+            name: None,
+            token: format!("valid-{}", id),
+            role: Role::User,
+        };
+        JwtKeys::new(&config.security).issue(&user, "user").unwrap()
+    }
+
+    #[test]
+    fn test_routes_are_classed_by_prefix_and_method() {
+        for (method, path, class) in [
+            (Method::POST, "/auth/login", Some(RouteClass::Auth)),
+            (Method::GET, "/auth/me", Some(RouteClass::Auth)),
+            (Method::GET, "/api/meetings", Some(RouteClass::Read)),
+            (Method::HEAD, "/api/queue", Some(RouteClass::Read)),
+            (Method::POST, "/api/queue", Some(RouteClass::Write)),
+            (
+                Method::DELETE,
+                "/api/keys/fathom/default",
+                Some(RouteClass::Write),
+            ),
+            (Method::OPTIONS, "/api/queue", None),
+            (Method::POST, "/webhooks/fathom", Some(RouteClass::Write)),
+            (Method::GET, "/health/ready", None),
+            (Method::GET, "/metrics", None),
+            (Method::GET, "/internal/keys/summary", None),
+            (Method::GET, "/queue_updates", None),
+            (Method::GET, "/authors", None),
+        ] {
+            assert_eq!(RouteClass::of(&method, path), class, "{} {}", method, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_users_and_anonymous_clients_have_their_own_budgets() {
+        let (app, config) = app(|config| config.rate_limits.read_per_minute = 2).await;
+        let alice = token_for(&config, "alice");
+        let bob = token_for(&config, "bob");
+
+        // Two users on one IP each get the whole budget
+        for token in [&alice, &bob] {
+            for _ in 0..2 {
+                let response = send(&app, "GET", "/api/queue", "192.0.2.1", Some(token)).await;
+                assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            }
+        }
+        let response = send(&app, "GET", "/api/queue", "192.0.2.9", Some(&alice)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Without a valid token, callers are counted by IP
+        for _ in 0..2 {
+            let response = send(&app, "GET", "/api/queue", "192.0.2.1", Some("forged")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = send(&app, "GET", "/api/queue", "192.0.2.1", None).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = send(&app, "GET", "/api/queue", "192.0.2.2", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Other classes and unlimited routes are unaffected
+        let response = send(&app, "DELETE", "/api/queue/x", "192.0.2.1", None).await;
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = send(&app, "GET", "/health/live", "192.0.2.1", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_clients_cant_change_bucket_through_forwarded_for() {
+        let (app, _) = app(|config| {
+            config.rate_limits.read_per_minute = 2;
+            config.security.trust_proxy_headers = true;
+        })
+        .await;
+        let from = |forwarded_for: &str| {
+            let mut request = Request::builder()
+                .uri("/api/queue")
+                .header("x-forwarded-for", forwarded_for)
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo("10.0.0.1:5000".parse::<SocketAddr>().unwrap()));
+            app.clone().oneshot(request)
+        };
+
+        // The proxy appends the address it saw; what the client put before it is ignored
+        for spoofed in ["198.51.100.1", "198.51.100.2"] {
+            let response = from(&format!("{}, 203.0.113.8", spoofed)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = from("198.51.100.3, 203.0.113.8").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = from("203.0.113.9").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_over_budget_requests_get_the_error_envelope() {
+        let (app, _) = app(|config| config.rate_limits.auth_per_minute = 1).await;
+        send(&app, "POST", "/auth/logout", "198.51.100.4", None).await;
+        let response = send(&app, "POST", "/auth/logout", "198.51.100.4", None).await;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&retry_after), "{}", retry_after);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "rate_limited");
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("Too many requests"));
+        assert_eq!(body["data"]["retry_after_secs"], retry_after);
+        assert!(body["timestamp"].is_string());
+
+        let text = crate::metrics::render();
+        assert!(text.contains("rate_limit_requests_total{class=\"auth\",outcome=\"limited\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_a_zero_budget_turns_the_limit_off() {
+        let (app, _) = app(|config| config.rate_limits.write_per_minute = 0).await;
+        for _ in 0..5 {
+            let response = send(&app, "POST", "/webhooks/fathom", "203.0.113.5", None).await;
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Per-request logging
+//!
+//! Every request runs inside a `request` span carrying its method, path,
+//! matched route and request id; the auth extractor adds the user id once it
+//! knows it. One `request completed` event with the status and latency closes
+//! the span, at the level configured for the status class. An incoming
+//! `X-Request-Id` is kept so a request can be followed across the proxy and
+//! the frontend; otherwise one is generated. Either way it's echoed back.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Level};
+use uuid::Uuid;
+
+use crate::config::RequestLogLevels;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Headers whose values are credentials and never reach the logs
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-csrf-token",
+    "x-fathom-signature",
+];
+
+/// Longest client-supplied request id we keep
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Wraps each request in its `request` span and logs its completion
+pub async fn log_requests(
+    State(levels): State<RequestLogLevels>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    if let Some(value) = &header_value {
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, value.clone());
+    }
+
+    // Only the path: query strings can carry tokens
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        route = field::Empty,
+        request_id = %request_id,
+        user_id = field::Empty,
+    );
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        span.record("route", route.as_str());
+    }
+
+    let started = Instant::now();
+    async move {
+        debug!(headers = %redacted_headers(request.headers()), "request started");
+        let mut response = next.run(request).await;
+
+        let status = response.status().as_u16();
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match levels.for_status(status) {
+            Level::ERROR => error!(status, latency_ms, "request completed"),
+            Level::WARN => warn!(status, latency_ms, "request completed"),
+            Level::INFO => info!(status, latency_ms, "request completed"),
+            Level::DEBUG => debug!(status, latency_ms, "request completed"),
+            _ => trace!(status, latency_ms, "request completed"),
+        }
+
+        if let Some(value) = header_value {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+/// Attaches the authenticated user to the current request span
+pub(super) fn record_user(user_id: &str) {
+    tracing::Span::current().record("user_id", user_id);
+}
+
+/// Request headers for the debug log, with credential values replaced
+pub fn redacted_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                "[redacted]"
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::extractors::AuthUser;
+    use crate::test_support::mock_app_state;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use serde_json::json;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        fmt,
+        sync::{Arc, Mutex},
+    };
+    use tower::ServiceExt;
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    type Fields = BTreeMap<String, String>;
+
+    /// Records spans and events so tests can look at their fields
+    #[derive(Clone, Default)]
+    struct Captured {
+        spans: Arc<Mutex<HashMap<u64, Fields>>>,
+        events: Arc<Mutex<Vec<(Level, Fields, Fields)>>>,
+    }
+
+    struct Recorder<'a>(&'a mut Fields);
+
+    impl Visit for Recorder<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Captured {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, _: Context<'_, S>) {
+            let mut fields = Fields::new();
+            attrs.record(&mut Recorder(&mut fields));
+            self.spans.lock().unwrap().insert(id.into_u64(), fields);
+        }
+
+        fn on_record(&self, id: &span::Id, values: &span::Record<'_>, _: Context<'_, S>) {
+            if let Some(fields) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+                values.record(&mut Recorder(fields));
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields::new();
+            event.record(&mut Recorder(&mut fields));
+            let span = ctx
+                .event_span(event)
+                .and_then(|span| {
+                    self.spans
+                        .lock()
+                        .unwrap()
+                        .get(&span.id().into_u64())
+                        .cloned()
+                })
+                .unwrap_or_default();
+            self.events
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields, span));
+        }
+    }
+
+    impl Captured {
+        fn completed(&self) -> Vec<(Level, Fields, Fields)> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, fields, _)| fields["message"] == "request completed")
+                .cloned()
+                .collect()
+        }
+    }
+
+    async fn logged_app() -> (Router, String) {
+        let state = mock_app_state().await;
+        let user = json!({ "email": "lou@example.com", "username": "lou", "verified": true });
+        let user_id = state.global_pb.create_record("users", &user).await.unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let app = Router::new()
+            .route(
+                "/whoami/:greeting",
+                get(|user: AuthUser| async move { user.id }),
+            )
+            .route("/boom", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+            .with_state(state)
+            .layer(middleware::from_fn_with_state(
+                RequestLogLevels::default(),
+                log_requests,
+            ));
+        (app, user_id)
+    }
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_log_span_fields_and_completion() {
+        let (app, user_id) = logged_app().await;
+        let captured = Captured::default();
+        let _guard = tracing_subscriber::registry()
+            .with(captured.clone())
+            .set_default();
+
+        let token = format!("Bearer valid-{}", user_id);
+        let response = app
+            .clone()
+            .oneshot(request(
+                "/whoami/hello?token=secret",
+                &[("authorization", &token), ("x-request-id", "req-42")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-42");
+
+        let response = app.clone().oneshot(request("/boom", &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let generated = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        let response = app.oneshot(request("/whoami/hello", &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let completed = captured.completed();
+        assert_eq!(completed.len(), 3);
+
+        let (level, fields, span) = &completed[0];
+        assert_eq!(*level, Level::INFO);
+        assert_eq!(fields["status"], "200");
+        assert!(fields.contains_key("latency_ms"));
+        assert_eq!(span["method"], "GET");
+        assert_eq!(span["path"], "/whoami/hello");
+        assert_eq!(span["route"], "/whoami/:greeting");
+        assert_eq!(span["request_id"], "req-42");
+        assert_eq!(span["user_id"], user_id);
+
+        let (level, fields, span) = &completed[1];
+        assert_eq!(*level, Level::ERROR);
+        assert_eq!(fields["status"], "500");
+        assert_eq!(span["request_id"], generated);
+        assert!(!span.contains_key("user_id"));
+
+        let (level, fields, _) = &completed[2];
+        assert_eq!(*level, Level::WARN);
+        assert_eq!(fields["status"], "401");
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_headers_are_redacted() {
+        let (app, _) = logged_app().await;
+        let captured = Captured::default();
+        let _guard = tracing_subscriber::registry()
+            .with(captured.clone())
+            .set_default();
+
+        app.oneshot(request(
+            "/boom",
+            &[
+                ("authorization", "Bearer top-secret"),
+                ("cookie", "session=top-secret"),
+                ("x-api-key", "top-secret"),
+                ("accept", "application/json"),
+            ],
+        ))
+        .await
+        .unwrap();
+
+        let events = captured.events.lock().unwrap();
+        let (_, started, _) = events
+            .iter()
+            .find(|(_, fields, _)| fields["message"] == "request started")
+            .unwrap();
+        let headers = &started["headers"];
+        assert!(headers.contains("authorization: [redacted]"));
+        assert!(headers.contains("cookie: [redacted]"));
+        assert!(headers.contains("x-api-key: [redacted]"));
+        assert!(headers.contains("accept: application/json"));
+        assert!(events
+            .iter()
+            .all(|(_, fields, span)| !format!("{:?}{:?}", fields, span).contains("top-secret")));
+    }
+}
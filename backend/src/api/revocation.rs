@@ -0,0 +1,266 @@
+//! Server-side revocation of session tokens
+//!
+//! Logging out records the SHA-256 of the token until the token would have
+//! expired anyway. Logging out everywhere bumps the user's token generation;
+//! backend-issued tokens carry the generation they were minted under and are
+//! rejected once it falls behind. Both are kept in memory for the hot path
+//! and mirrored to the global PocketBase so they survive restarts.
+
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use super::{
+    auth_cache::{hash_token, TokenHash},
+    jwt,
+};
+use crate::global_pb::{self, GlobalPb, GlobalPbError};
+
+const REVOKED_TOKENS: &str = "revoked_tokens";
+const TOKEN_GENERATIONS: &str = "token_generations";
+
+pub struct RevocationList {
+    /// Revoked token hashes and the unix time each token expires at
+    revoked: RwLock<HashMap<TokenHash, u64>>,
+    /// Current token generation per user; absent means 0
+    generations: RwLock<HashMap<String, u64>>,
+    store: Option<Arc<GlobalPb>>,
+}
+
+impl RevocationList {
+    /// A list that only lives in memory
+    pub fn in_memory() -> Self {
+        Self {
+            revoked: RwLock::new(HashMap::new()),
+            generations: RwLock::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    /// A list mirrored to the global PocketBase
+    pub fn persistent(store: Arc<GlobalPb>) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::in_memory()
+        }
+    }
+
+    /// Load unexpired revocations and all generations from the global PocketBase
+    ///
+    /// Expired revocation records are deleted along the way.
+    pub async fn load(&self) -> Result<(), GlobalPbError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
+        let now = jwt::now();
+        let mut revoked = HashMap::new();
+        for record in store.list_records(REVOKED_TOKENS, None).await? {
+            let expires_at = record.get("expires_at").and_then(|v| v.as_u64()).unwrap_or(0);
+            let hash = record
+                .get("token_hash")
+                .and_then(|v| v.as_str())
+                .and_then(decode_hash);
+            match hash {
+                Some(hash) if expires_at > now => {
+                    revoked.insert(hash, expires_at);
+                }
+                _ => {
+                    if let Some(id) = record.get("id").and_then(|v| v.as_str()) {
+                        if let Err(e) = store.delete_record(REVOKED_TOKENS, id).await {
+                            warn!("Failed to delete expired revocation {}: {}", id, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut generations = HashMap::new();
+        for record in store.list_records(TOKEN_GENERATIONS, None).await? {
+            let user_id = record.get("user_id").and_then(|v| v.as_str());
+            let generation = record.get("generation").and_then(|v| v.as_u64());
+            if let (Some(user_id), Some(generation)) = (user_id, generation) {
+                generations.insert(user_id.to_string(), generation);
+            }
+        }
+
+        info!(
+            "Loaded {} token revocations and {} token generations",
+            revoked.len(),
+            generations.len()
+        );
+        self.revoked.write().await.extend(revoked);
+        self.generations.write().await.extend(generations);
+        Ok(())
+    }
+
+    /// Reject `token` from now until `expires_at` (unix seconds)
+    pub async fn revoke(&self, token: &str, expires_at: u64) {
+        self.revoke_hash(hash_token(token), expires_at).await
+    }
+
+    /// Reject the token hashing to `hash` from now until `expires_at`
+    pub async fn revoke_hash(&self, hash: TokenHash, expires_at: u64) {
+        let now = jwt::now();
+        {
+            let mut revoked = self.revoked.write().await;
+            revoked.retain(|_, expiry| *expiry > now);
+            if expires_at <= now {
+                return;
+            }
+            revoked.insert(hash, expires_at);
+        }
+
+        if let Some(store) = &self.store {
+            let record = serde_json::json!({
+                "token_hash": encode_hash(&hash),
+                "expires_at": expires_at
+            });
+            if let Err(e) = store.create_record(REVOKED_TOKENS, &record).await {
+                warn!("Failed to persist token revocation: {}", e);
+            }
+        }
+    }
+
+    /// Revoke `token` unless it already is, returning whether this call did
+    ///
+    /// Of concurrent claims on one token exactly one wins, here under the
+    /// lock and across backends through the store's unique `token_hash`
+    /// index. If the store can't record the claim it is undone and the error
+    /// returned, so nobody wins without it being persisted.
+    pub async fn claim(&self, token: &str, expires_at: u64) -> Result<bool, GlobalPbError> {
+        let hash = hash_token(token);
+        let now = jwt::now();
+        {
+            let mut revoked = self.revoked.write().await;
+            revoked.retain(|_, expiry| *expiry > now);
+            if expires_at <= now || revoked.contains_key(&hash) {
+                return Ok(false);
+            }
+            revoked.insert(hash, expires_at);
+        }
+
+        if let Some(store) = &self.store {
+            let record = serde_json::json!({
+                "token_hash": encode_hash(&hash),
+                "expires_at": expires_at
+            });
+            match store.create_record(REVOKED_TOKENS, &record).await {
+                Ok(_) => {}
+                Err(e) if e.is_not_unique() => return Ok(false),
+                Err(e) => {
+                    self.revoked.write().await.remove(&hash);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    pub async fn is_revoked(&self, token: &str) -> bool {
+        match self.revoked.read().await.get(&hash_token(token)) {
+            Some(expires_at) => *expires_at > jwt::now(),
+            None => false,
+        }
+    }
+
+    /// The generation new tokens for `user_id` are issued under
+    pub async fn generation(&self, user_id: &str) -> u64 {
+        self.generations.read().await.get(user_id).copied().unwrap_or(0)
+    }
+
+    /// Invalidate every token issued to `user_id` so far, returning the new generation
+    pub async fn bump_generation(&self, user_id: &str) -> Result<u64, GlobalPbError> {
+        let generation = {
+            let mut generations = self.generations.write().await;
+            let generation = generations.entry(user_id.to_string()).or_insert(0);
+            *generation += 1;
+            *generation
+        };
+
+        if let Some(store) = &self.store {
+            let record = serde_json::json!({ "user_id": user_id, "generation": generation });
+            let filter = format!("user_id = {}", global_pb::quote(user_id));
+            let existing = store.list_records(TOKEN_GENERATIONS, Some(&filter)).await?;
+            let existing_id = existing
+                .iter()
+                .find(|r| r.get("user_id").and_then(|v| v.as_str()) == Some(user_id))
+                .and_then(|r| r.get("id").and_then(|v| v.as_str()));
+            match existing_id {
+                Some(id) => store.update_record(TOKEN_GENERATIONS, id, &record).await?,
+                None => store.create_record(TOKEN_GENERATIONS, &record).await?,
+            };
+        }
+        Ok(generation)
+    }
+}
+
+pub(super) fn encode_hash(hash: &TokenHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(super) fn decode_hash(hex: &str) -> Option<TokenHash> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_global_pocketbase, test_config};
+
+    #[tokio::test]
+    async fn test_unexpired_revocations_survive_reload() {
+        let global_url = mock_global_pocketbase().await;
+        let store = Arc::new(GlobalPb::from(&test_config(&global_url).database));
+        let now = jwt::now();
+
+        let list = RevocationList::persistent(Arc::clone(&store));
+        list.revoke("token-live", now + 3600).await;
+        list.revoke("token-stale", now + 2).await;
+        assert_eq!(list.bump_generation("dora").await.unwrap(), 1);
+        assert_eq!(list.bump_generation("dora").await.unwrap(), 2);
+        assert!(list.is_revoked("token-live").await);
+
+        tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+        let reloaded = RevocationList::persistent(Arc::clone(&store));
+        reloaded.load().await.unwrap();
+        assert!(reloaded.is_revoked("token-live").await);
+        assert!(!reloaded.is_revoked("token-stale").await);
+        assert!(!reloaded.is_revoked("token-other").await);
+        assert_eq!(reloaded.generation("dora").await, 2);
+        assert_eq!(reloaded.generation("erin").await, 0);
+
+        // The expired record was cleaned up while loading
+        assert_eq!(store.list_records(REVOKED_TOKENS, None).await.unwrap().len(), 1);
+        assert_eq!(store.list_records(TOKEN_GENERATIONS, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_a_token_is_claimed_once_across_backends() {
+        let global_url = mock_global_pocketbase().await;
+        let store = Arc::new(GlobalPb::from(&test_config(&global_url).database));
+        let expires_at = jwt::now() + 3600;
+
+        let first = RevocationList::persistent(Arc::clone(&store));
+        let second = RevocationList::persistent(Arc::clone(&store));
+        let (a, b) = tokio::join!(first.claim("token", expires_at), second.claim("token", expires_at));
+        assert!(a.unwrap() ^ b.unwrap(), "exactly one backend claims the token");
+        assert!(!first.claim("token", expires_at).await.unwrap());
+        assert!(first.is_revoked("token").await);
+        assert_eq!(store.list_records(REVOKED_TOKENS, None).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_hash_hex_round_trip() {
+        let hash = hash_token("token");
+        assert_eq!(decode_hash(&encode_hash(&hash)), Some(hash));
+        assert_eq!(decode_hash("zz"), None);
+    }
+}
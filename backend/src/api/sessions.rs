@@ -0,0 +1,534 @@
+//! Signed-in sessions, so users can see where they're logged in
+//!
+//! Every session token we mint gets a row in the global PocketBase
+//! `sessions` collection holding the token's SHA-256, the client's user
+//! agent and IP, and when the session was opened and last used. Refreshing
+//! moves the row to the new token. The auth extractor bumps `last_seen_at`
+//! lazily, at most once a minute per session. Ending a session revokes its
+//! token and deletes the row.
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{Json, Response},
+    routing::{delete, get},
+    Router,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use super::{
+    audit::{AuditLogger, AuthEvent, AuthEventType},
+    auth::{auth_error, session_token},
+    auth_cache::{hash_token, TokenHash},
+    extractors::AuthUser,
+    jwt,
+    rate_limit::client_ip,
+    revocation::{decode_hash, encode_hash, RevocationList},
+    AppState,
+};
+use crate::{
+    config::Config,
+    global_pb::{self, GlobalPb, GlobalPbError},
+};
+use common::{clock::Clock, ApiResponse, ErrorCode};
+
+const SESSIONS: &str = "sessions";
+
+/// Shortest gap between two `last_seen_at` writes for one session
+pub const TOUCH_INTERVAL_SECS: u64 = 60;
+
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// The client a session is opened from
+#[derive(Debug, Clone, Default)]
+pub struct SessionOrigin {
+    pub user_agent: String,
+    pub ip: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionOrigin
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .chars()
+            .take(MAX_USER_AGENT_LENGTH)
+            .collect();
+        Ok(Self {
+            user_agent,
+            ip: client_ip(&parts.headers, peer, config.security.trust_proxy_headers),
+        })
+    }
+}
+
+/// When a session token can no longer be refreshed, which is as long as its
+/// session row or revocation needs to be kept
+pub fn session_expiry(config: &Config, token: &str) -> u64 {
+    jwt::unverified_expiry(token).unwrap_or_else(jwt::now) + config.security.jwt_refresh_window_secs
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: String,
+    pub ip: String,
+    pub created_at: u64,
+    pub last_seen_at: u64,
+    /// Whether this is the session making the request
+    pub current: bool,
+    #[serde(skip)]
+    user_id: String,
+    #[serde(skip)]
+    token_hash: TokenHash,
+    #[serde(skip)]
+    expires_at: u64,
+}
+
+impl Session {
+    fn from_record(record: &Value) -> Option<Self> {
+        let text = |name: &str| record.get(name).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        let number = |name: &str| record.get(name).and_then(|v| v.as_u64()).unwrap_or(0);
+        Some(Self {
+            id: record.get("id")?.as_str()?.to_string(),
+            user_agent: text("user_agent"),
+            ip: text("ip"),
+            created_at: number("created_at"),
+            last_seen_at: number("last_seen_at"),
+            current: false,
+            user_id: text("user_id"),
+            token_hash: decode_hash(record.get("token_hash")?.as_str()?)?,
+            expires_at: number("expires_at"),
+        })
+    }
+}
+
+/// What we know about a token's session row without asking PocketBase
+struct Seen {
+    /// `None` while the token is known to have no row
+    record_id: Option<String>,
+    written_at: u64,
+    expires_at: u64,
+}
+
+/// Add `entry`, first dropping the expired ones so only live tokens are kept
+fn remember(seen: &mut HashMap<TokenHash, Seen>, hash: TokenHash, entry: Seen, now: u64) {
+    seen.retain(|_, entry| entry.expires_at > now);
+    seen.insert(hash, entry);
+}
+
+pub struct SessionList {
+    store: Arc<GlobalPb>,
+    clock: Arc<dyn Clock>,
+    seen: Mutex<HashMap<TokenHash, Seen>>,
+}
+
+impl SessionList {
+    pub fn new(store: Arc<GlobalPb>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            store,
+            clock,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record the session opened by the freshly minted `token`
+    ///
+    /// Failing to record it is logged rather than failing the sign-in.
+    pub async fn open(&self, token: &str, user_id: &str, origin: &SessionOrigin, expires_at: u64) {
+        let now = self.clock.unix_now();
+        let hash = hash_token(token);
+        let record = json!({
+            "user_id": user_id,
+            "token_hash": encode_hash(&hash),
+            "user_agent": origin.user_agent,
+            "ip": origin.ip,
+            "created_at": now,
+            "last_seen_at": now,
+            "expires_at": expires_at
+        });
+        match self.store.create_record(SESSIONS, &record).await {
+            Ok(created) => {
+                let record_id = created.get("id").and_then(|v| v.as_str()).map(String::from);
+                let mut seen = self.seen.lock().await;
+                remember(&mut seen, hash, Seen { record_id, written_at: now, expires_at }, now);
+            }
+            Err(e) => warn!("Failed to record session for {}: {}", user_id, e),
+        }
+    }
+
+    /// Move the session of `old_token` to the `new_token` it was refreshed
+    /// into, or open a session if it had none
+    pub async fn rotate(&self, old_token: &str, new_token: &str, user_id: &str, origin: &SessionOrigin, expires_at: u64) {
+        let old_hash = hash_token(old_token);
+        let Some((record_id, _)) = self.find(&old_hash).await else {
+            return self.open(new_token, user_id, origin, expires_at).await;
+        };
+
+        let now = self.clock.unix_now();
+        let new_hash = hash_token(new_token);
+        let changes = json!({
+            "token_hash": encode_hash(&new_hash),
+            "last_seen_at": now,
+            "expires_at": expires_at
+        });
+        if let Err(e) = self.store.update_record(SESSIONS, &record_id, &changes).await {
+            warn!("Failed to move session {} to its refreshed token: {}", record_id, e);
+            return;
+        }
+        let mut seen = self.seen.lock().await;
+        seen.remove(&old_hash);
+        let entry = Seen { record_id: Some(record_id), written_at: now, expires_at };
+        remember(&mut seen, new_hash, entry, now);
+    }
+
+    /// Note that `token` was just used, writing `last_seen_at` if it hasn't
+    /// been written in the last [`TOUCH_INTERVAL_SECS`]
+    pub async fn touch(&self, token: &str) {
+        let now = self.clock.unix_now();
+        let hash = hash_token(token);
+        let known = {
+            let mut seen = self.seen.lock().await;
+            match seen.get_mut(&hash) {
+                Some(entry) if now < entry.written_at + TOUCH_INTERVAL_SECS => return,
+                Some(entry) => {
+                    entry.written_at = now;
+                    entry.record_id.clone()
+                }
+                None => {
+                    // Claim the write before looking the row up, so concurrent
+                    // requests with this token don't each do it
+                    let expires_at = now + TOUCH_INTERVAL_SECS;
+                    remember(&mut seen, hash, Seen { record_id: None, written_at: now, expires_at }, now);
+                    None
+                }
+            }
+        };
+
+        let record_id = match known {
+            Some(record_id) => record_id,
+            None => match self.find(&hash).await {
+                Some((record_id, expires_at)) => {
+                    if let Some(entry) = self.seen.lock().await.get_mut(&hash) {
+                        entry.record_id = Some(record_id.clone());
+                        entry.expires_at = expires_at;
+                    }
+                    record_id
+                }
+                None => return,
+            },
+        };
+        if let Err(e) = self
+            .store
+            .update_record(SESSIONS, &record_id, &json!({ "last_seen_at": now }))
+            .await
+        {
+            warn!("Failed to update last_seen_at of session {}: {}", record_id, e);
+        }
+    }
+
+    /// The unexpired sessions of `user_id`, most recently used first, with
+    /// the one for `current_token` marked
+    ///
+    /// Expired rows are deleted along the way.
+    pub async fn list(&self, user_id: &str, current_token: Option<&str>) -> Result<Vec<Session>, GlobalPbError> {
+        let now = self.clock.unix_now();
+        let current = current_token.map(hash_token);
+        let filter = format!("user_id = {}", global_pb::quote(user_id));
+        let mut sessions = Vec::new();
+        for record in self.store.list_records(SESSIONS, Some(&filter)).await? {
+            let Some(mut session) = Session::from_record(&record) else {
+                continue;
+            };
+            if session.expires_at <= now {
+                if let Err(e) = self.store.delete_record(SESSIONS, &session.id).await {
+                    warn!("Failed to delete expired session {}: {}", session.id, e);
+                }
+                continue;
+            }
+            session.current = current == Some(session.token_hash);
+            sessions.push(session);
+        }
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
+
+    /// Delete the session `id` if it belongs to `user_id`, returning it so
+    /// its token can be revoked
+    pub async fn end(&self, user_id: &str, id: &str) -> Result<Option<Session>, GlobalPbError> {
+        let session = match self.store.get_record(SESSIONS, id).await {
+            Ok(record) => Session::from_record(&record),
+            Err(GlobalPbError::Status { status: 404, .. }) => None,
+            Err(e) => return Err(e),
+        };
+        let Some(session) = session.filter(|session| session.user_id == user_id) else {
+            return Ok(None);
+        };
+        self.store.delete_record(SESSIONS, &session.id).await?;
+        self.seen.lock().await.remove(&session.token_hash);
+        Ok(Some(session))
+    }
+
+    /// Delete the session of `token`, which has been logged out
+    pub async fn close(&self, token: &str) {
+        let hash = hash_token(token);
+        if let Some((record_id, _)) = self.find(&hash).await {
+            if let Err(e) = self.store.delete_record(SESSIONS, &record_id).await {
+                warn!("Failed to delete session {}: {}", record_id, e);
+            }
+        }
+        self.seen.lock().await.remove(&hash);
+    }
+
+    /// Delete every session of `user_id`, whose tokens have all been revoked
+    pub async fn close_all(&self, user_id: &str) {
+        let sessions = match self.list(user_id, None).await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                warn!("Failed to list sessions of {} to delete them: {}", user_id, e);
+                return;
+            }
+        };
+        let mut seen = self.seen.lock().await;
+        for session in sessions {
+            if let Err(e) = self.store.delete_record(SESSIONS, &session.id).await {
+                warn!("Failed to delete session {}: {}", session.id, e);
+            }
+            seen.remove(&session.token_hash);
+        }
+    }
+
+    /// The row id and expiry of the session for a token hash
+    async fn find(&self, hash: &TokenHash) -> Option<(String, u64)> {
+        if let Some(Seen { record_id: Some(record_id), expires_at, .. }) = self.seen.lock().await.get(hash) {
+            return Some((record_id.clone(), *expires_at));
+        }
+        let filter = format!("token_hash = {}", global_pb::quote(&encode_hash(hash)));
+        match self.store.list_records(SESSIONS, Some(&filter)).await {
+            Ok(records) => records.first().and_then(Session::from_record).map(|s| (s.id, s.expires_at)),
+            Err(e) => {
+                warn!("Failed to look up session: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Routes for listing and ending sessions, nested under `/auth`
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(end_session))
+}
+
+fn unavailable() -> Response {
+    auth_error(StatusCode::BAD_GATEWAY, ErrorCode::ServiceUnavailable, "Failed to load sessions")
+}
+
+/// GET /auth/sessions - the caller's sessions, with the current one marked
+async fn list_sessions(
+    State(sessions): State<Arc<SessionList>>,
+    headers: HeaderMap,
+    user: AuthUser,
+) -> Result<Json<ApiResponse<Vec<Session>>>, Response> {
+    match sessions.list(&user.id, session_token(&headers)).await {
+        Ok(list) => Ok(Json(ApiResponse::success(list))),
+        Err(e) => {
+            error!("Failed to list sessions of {}: {}", user.id, e);
+            Err(unavailable())
+        }
+    }
+}
+
+/// DELETE /auth/sessions/:id - end one of the caller's sessions
+async fn end_session(
+    State(sessions): State<Arc<SessionList>>,
+    State(revocations): State<Arc<RevocationList>>,
+    State(audit): State<Arc<AuditLogger>>,
+    origin: SessionOrigin,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<Session>>, Response> {
+    match sessions.end(&user.id, &id).await {
+        Ok(Some(session)) => {
+            revocations.revoke_hash(session.token_hash, session.expires_at).await;
+            info!(target: "audit", user_id = %user.id, session_id = %session.id, "Session ended");
+            audit.record(AuthEvent::new(AuthEventType::SessionRevoked, &origin).user(&user.id).email(&user.email));
+            Ok(Json(ApiResponse::success(session)))
+        }
+        Ok(None) => Err(auth_error(StatusCode::NOT_FOUND, ErrorCode::NotFound, "Session not found")),
+        Err(e) => {
+            error!("Failed to end session {} of {}: {}", id, user.id, e);
+            Err(unavailable())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::create_api_router,
+        test_support::{mock_app_state, mock_global_pocketbase, test_config, ManualClock},
+    };
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn send(state: &AppState, method: &str, uri: &str, token: Option<&str>, user_agent: &str) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("user-agent", user_agent)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let body = match uri {
+            "/auth/login" => json!({ "email": "tess@example.com", "password": "password" }).to_string(),
+            _ => String::new(),
+        };
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn login(state: &AppState, user_agent: &str) -> String {
+        let (_, body) = send(state, "POST", "/auth/login", None, user_agent).await;
+        body["data"]["token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_listing_marks_the_current_session() {
+        let state = mock_app_state().await;
+        let laptop = login(&state, "Laptop Browser").await;
+        let _phone = login(&state, "Phone App").await;
+
+        let (status, body) = send(&state, "GET", "/auth/sessions", Some(&laptop), "Laptop Browser").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed = body["data"].as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        let current: Vec<&Value> = listed.iter().filter(|s| s["current"] == true).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["user_agent"], "Laptop Browser");
+        assert!(listed.iter().all(|s| s["token_hash"].is_null() && s["ip"] == "unknown"));
+
+        let (status, _) = send(&state, "GET", "/auth/sessions", None, "Laptop Browser").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_ending_a_session_revokes_only_its_token() {
+        let state = mock_app_state().await;
+        let laptop = login(&state, "Laptop Browser").await;
+        let phone = login(&state, "Phone App").await;
+
+        let (_, body) = send(&state, "GET", "/auth/sessions", Some(&laptop), "Laptop Browser").await;
+        let phone_session = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|s| s["user_agent"] == "Phone App")
+            .unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let uri = format!("/auth/sessions/{}", phone_session);
+        let (status, _) = send(&state, "DELETE", &uri, Some(&laptop), "Laptop Browser").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.revocations.is_revoked(&phone).await);
+        assert!(!state.revocations.is_revoked(&laptop).await);
+
+        let (status, _) = send(&state, "GET", "/auth/sessions", Some(&phone), "Phone App").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = send(&state, "GET", "/auth/sessions", Some(&laptop), "Laptop Browser").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+
+        // Ended sessions are gone
+        let (status, body) = send(&state, "DELETE", &uri, Some(&laptop), "Laptop Browser").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_last_seen_is_written_at_most_once_a_minute() {
+        let global_url = mock_global_pocketbase().await;
+        let store = Arc::new(GlobalPb::from(&test_config(&global_url).database));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let sessions = SessionList::new(Arc::clone(&store), clock.clone());
+        sessions.open("token-a", "uli", &SessionOrigin::default(), 2_000_000).await;
+        let last_seen = || async {
+            let records = store.list_records(SESSIONS, None).await.unwrap();
+            records[0]["last_seen_at"].as_u64().unwrap()
+        };
+
+        clock.set(1_000_030);
+        sessions.touch("token-a").await;
+        assert_eq!(last_seen().await, 1_000_000);
+
+        clock.set(1_000_061);
+        sessions.touch("token-a").await;
+        assert_eq!(last_seen().await, 1_000_061);
+        clock.set(1_000_100);
+        sessions.touch("token-a").await;
+        assert_eq!(last_seen().await, 1_000_061);
+
+        // A restarted backend finds the row, then throttles as before
+        let restarted = SessionList::new(Arc::clone(&store), clock.clone());
+        restarted.touch("token-a").await;
+        assert_eq!(last_seen().await, 1_000_100);
+        clock.set(1_000_120);
+        restarted.touch("token-a").await;
+        assert_eq!(last_seen().await, 1_000_100);
+
+        // Tokens without a session are left alone
+        restarted.touch("token-b").await;
+        assert_eq!(store.list_records(SESSIONS, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_touched_tokens_are_forgotten_once_expired() {
+        let global_url = mock_global_pocketbase().await;
+        let store = Arc::new(GlobalPb::from(&test_config(&global_url).database));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let sessions = SessionList::new(store, clock.clone());
+        sessions.open("token-a", "vera", &SessionOrigin::default(), 1_000_500).await;
+        for n in 0..20 {
+            sessions.touch(&format!("stray-{}", n)).await;
+        }
+        assert_eq!(sessions.seen.lock().await.len(), 21);
+
+        // Only the session still open is kept once the strays' minute is up
+        clock.set(1_000_000 + TOUCH_INTERVAL_SECS);
+        sessions.touch("token-a").await;
+        sessions.touch("stray-new").await;
+        assert_eq!(sessions.seen.lock().await.len(), 2);
+
+        clock.set(1_000_500);
+        sessions.touch("stray-last").await;
+        assert_eq!(sessions.seen.lock().await.len(), 1);
+    }
+}
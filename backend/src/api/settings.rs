@@ -0,0 +1,574 @@
+//! Per-user preferences
+//!
+//! Settings live in the global PocketBase `user_settings` collection, one
+//! record per user, so the Fathom webhook can find whose workspace an event
+//! is for before it knows the user. Users without a record have the
+//! defaults. The secret Fathom signs that workspace's webhooks with is kept
+//! on the same record, encrypted under the master key for its owner, so a
+//! delivery is checked without starting the owner's instance; it is only
+//! ever reported as present or not.
+//!
+//! Secrets saved before they moved there are still in the owner's instance,
+//! as service [`WEBHOOK_SECRET_SERVICE`]. Reading the settings moves such a
+//! secret onto the record.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Json, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use super::{
+    auth::auth_error,
+    extractors::AuthUser,
+    key_repository::{fingerprint, KeyChange, KeyRepository, MasterKey, RotationCounts},
+    keys::{repository, store_error},
+    AppState,
+};
+use crate::{
+    global_pb::{quote, GlobalPb, GlobalPbError},
+    pocketbase_manager::PocketBaseManager,
+};
+use common::{crypto::EncryptedApiKey, ApiResponse, ErrorCode};
+
+pub const USER_SETTINGS: &str = "user_settings";
+
+/// Service the webhook secret is encrypted as, and the key repository
+/// service it was kept under in the user's instance
+pub const WEBHOOK_SECRET_SERVICE: &str = "fathom_webhook";
+
+/// Key id of the webhook secret within [`WEBHOOK_SECRET_SERVICE`]
+pub const WEBHOOK_SECRET_KEY_ID: &str = "default";
+
+/// Field of a `user_settings` record holding the encrypted webhook secret
+const WEBHOOK_SECRET_FIELD: &str = "fathom_webhook_secret";
+
+const MIN_WEBHOOK_SECRET_LENGTH: usize = 16;
+const MAX_WORKSPACE_ID_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// Queue recordings as Fathom reports them ready
+    pub auto_enqueue: bool,
+    /// The Fathom workspace whose webhooks are this user's
+    pub fathom_workspace_id: Option<String>,
+    /// Email the Loom link of each meeting the worker moves, not only failures
+    pub email_on_success: bool,
+}
+
+impl UserSettings {
+    fn from_record(record: &Value) -> Self {
+        Self {
+            auto_enqueue: record["auto_enqueue"].as_bool().unwrap_or(false),
+            fathom_workspace_id: record["fathom_workspace_id"]
+                .as_str()
+                .filter(|id| !id.is_empty())
+                .map(str::to_string),
+            email_on_success: record["email_on_success"].as_bool().unwrap_or(false),
+        }
+    }
+}
+
+/// The encrypted webhook secret on a `user_settings` record
+fn webhook_secret_of(record: &Value) -> Option<EncryptedApiKey> {
+    record
+        .get(WEBHOOK_SECRET_FIELD)
+        .filter(|secret| secret.is_object())
+        .and_then(|secret| serde_json::from_value(secret.clone()).ok())
+}
+
+fn encrypt_webhook_secret(master_key: &MasterKey, user_id: &str, secret: &str) -> EncryptedApiKey {
+    EncryptedApiKey::new_for_user(
+        user_id,
+        WEBHOOK_SECRET_SERVICE.to_string(),
+        WEBHOOK_SECRET_KEY_ID.to_string(),
+        secret,
+        &master_key.bytes(),
+        None,
+    )
+}
+
+/// The user who linked a workspace, as a webhook delivery finds them
+#[derive(Debug, Clone)]
+pub struct WorkspaceOwner {
+    pub user_id: String,
+    pub settings: UserSettings,
+    /// The secret their webhooks are signed with, encrypted for `user_id`
+    pub webhook_secret: Option<EncryptedApiKey>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SettingsView {
+    #[serde(flatten)]
+    pub settings: UserSettings,
+    pub has_webhook_secret: bool,
+}
+
+/// Fields `PUT /api/settings` changes; those left out keep their value
+#[derive(Debug, Deserialize)]
+pub struct SettingsUpdate {
+    pub auto_enqueue: Option<bool>,
+    pub email_on_success: Option<bool>,
+    /// Empty to unlink the workspace
+    pub fathom_workspace_id: Option<String>,
+    /// Empty to remove the secret
+    pub fathom_webhook_secret: Option<String>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/settings", get(get_settings).put(update_settings))
+}
+
+async fn settings_record(
+    global_pb: &GlobalPb,
+    user_id: &str,
+) -> Result<Option<Value>, GlobalPbError> {
+    let filter = format!("user_id = {}", quote(user_id));
+    Ok(global_pb
+        .list_records(USER_SETTINGS, Some(&filter))
+        .await?
+        .into_iter()
+        .next())
+}
+
+/// `user_id`'s settings, the defaults until they save any
+pub async fn settings_of(
+    global_pb: &GlobalPb,
+    user_id: &str,
+) -> Result<UserSettings, GlobalPbError> {
+    Ok(settings_record(global_pb, user_id)
+        .await?
+        .map(|record| UserSettings::from_record(&record))
+        .unwrap_or_default())
+}
+
+/// The user who linked `workspace_id`, their settings and webhook secret
+pub async fn workspace_owner(
+    global_pb: &GlobalPb,
+    workspace_id: &str,
+) -> Result<Option<WorkspaceOwner>, GlobalPbError> {
+    let filter = format!("fathom_workspace_id = {}", quote(workspace_id));
+    Ok(global_pb
+        .list_records(USER_SETTINGS, Some(&filter))
+        .await?
+        .into_iter()
+        .find_map(|record| {
+            let user_id = record["user_id"].as_str().filter(|id| !id.is_empty())?;
+            Some(WorkspaceOwner {
+                user_id: user_id.to_string(),
+                settings: UserSettings::from_record(&record),
+                webhook_secret: webhook_secret_of(&record),
+            })
+        }))
+}
+
+/// Write `settings`, and the webhook secret when `secret` is given: `Some(None)`
+/// removes it
+async fn save(
+    global_pb: &GlobalPb,
+    user_id: &str,
+    settings: &UserSettings,
+    secret: Option<Option<&EncryptedApiKey>>,
+) -> Result<(), GlobalPbError> {
+    let mut record = json!({
+        "user_id": user_id,
+        "auto_enqueue": settings.auto_enqueue,
+        "fathom_workspace_id": settings.fathom_workspace_id.as_deref().unwrap_or_default(),
+        "email_on_success": settings.email_on_success,
+    });
+    if let Some(secret) = secret {
+        record[WEBHOOK_SECRET_FIELD] = json!(secret);
+    }
+    match settings_record(global_pb, user_id).await? {
+        Some(existing) => {
+            let id = existing["id"].as_str().unwrap_or_default();
+            global_pb.update_record(USER_SETTINGS, id, &record).await?;
+        }
+        None => {
+            global_pb.create_record(USER_SETTINGS, &record).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-encrypt every webhook secret not under the current master key from
+/// `previous`, counted per user
+///
+/// Secrets that already decrypt with the current key are skipped, so an
+/// interrupted rotation can simply be run again.
+pub async fn rotate_webhook_secrets(
+    global_pb: &GlobalPb,
+    previous: &MasterKey,
+    current: &MasterKey,
+) -> Result<Vec<(String, RotationCounts)>, GlobalPbError> {
+    let mut rotations = Vec::new();
+    for record in global_pb.list_records(USER_SETTINGS, None).await? {
+        let Some(secret) = webhook_secret_of(&record) else {
+            continue;
+        };
+        let user_id = record["user_id"].as_str().unwrap_or_default().to_string();
+        let mut counts = RotationCounts::default();
+        if secret.decrypt_key_for_user(&user_id, &current.bytes()).is_ok() {
+            counts.skipped += 1;
+            rotations.push((user_id, counts));
+            continue;
+        }
+        let value = match secret.decrypt_key_for_user(&user_id, &previous.bytes()) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Webhook secret of {} decrypts with neither master key: {}", user_id, e);
+                counts.failed += 1;
+                rotations.push((user_id, counts));
+                continue;
+            }
+        };
+        let rotated = encrypt_webhook_secret(current, &user_id, &value);
+        let id = record["id"].as_str().unwrap_or_default();
+        let changes = json!({ WEBHOOK_SECRET_FIELD: rotated });
+        match global_pb.update_record(USER_SETTINGS, id, &changes).await {
+            Ok(_) => {
+                counts.rotated += 1;
+                counts.changes.push(KeyChange {
+                    service: WEBHOOK_SECRET_SERVICE.to_string(),
+                    key_id: WEBHOOK_SECRET_KEY_ID.to_string(),
+                    fingerprint_before: fingerprint(&previous.bytes(), &value),
+                    fingerprint_after: fingerprint(&current.bytes(), &value),
+                });
+            }
+            Err(e) => {
+                warn!("Failed to write the rotated webhook secret of {}: {}", user_id, e);
+                counts.failed += 1;
+            }
+        }
+        rotations.push((user_id, counts));
+    }
+    Ok(rotations)
+}
+
+fn settings_error(user: &AuthUser, e: GlobalPbError) -> Response {
+    error!("Settings of {} could not be reached: {}", user.id, e);
+    auth_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::ServiceUnavailable,
+        "Settings are temporarily unavailable",
+    )
+}
+
+/// Move a webhook secret still kept in the caller's instance onto their
+/// settings record, returning whether there was one
+async fn adopt_instance_secret(
+    global_pb: &GlobalPb,
+    repository: &KeyRepository,
+    master_key: &MasterKey,
+    user: &AuthUser,
+    settings: &UserSettings,
+) -> Result<bool, Response> {
+    let Some(stored) = repository
+        .get(WEBHOOK_SECRET_SERVICE, WEBHOOK_SECRET_KEY_ID)
+        .await
+        .map_err(store_error)?
+    else {
+        return Ok(false);
+    };
+    let Ok(secret) = repository.decrypt(&stored.key) else {
+        warn!("Webhook secret of {} in their instance does not decrypt", user.id);
+        return Ok(false);
+    };
+    let encrypted = encrypt_webhook_secret(master_key, &user.id, &secret);
+    save(global_pb, &user.id, settings, Some(Some(&encrypted)))
+        .await
+        .map_err(|e| settings_error(user, e))?;
+    repository
+        .delete(WEBHOOK_SECRET_SERVICE, WEBHOOK_SECRET_KEY_ID)
+        .await
+        .map_err(store_error)?;
+    info!(target: "audit", user_id = %user.id, "Webhook secret moved onto the settings record");
+    Ok(true)
+}
+
+/// GET /api/settings - The caller's settings
+pub async fn get_settings(
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    user: AuthUser,
+) -> Result<Json<ApiResponse<SettingsView>>, Response> {
+    let record = settings_record(&global_pb, &user.id)
+        .await
+        .map_err(|e| settings_error(&user, e))?;
+    let settings = record
+        .as_ref()
+        .map(UserSettings::from_record)
+        .unwrap_or_default();
+    let mut has_webhook_secret = record.as_ref().and_then(webhook_secret_of).is_some();
+    if !has_webhook_secret {
+        let repository = repository(&pb_manager, &master_key, &user).await?;
+        has_webhook_secret =
+            adopt_instance_secret(&global_pb, &repository, &master_key, &user, &settings).await?;
+    }
+    Ok(Json(ApiResponse::success(SettingsView {
+        settings,
+        has_webhook_secret,
+    })))
+}
+
+/// PUT /api/settings - Change some of the caller's settings
+pub async fn update_settings(
+    State(global_pb): State<Arc<GlobalPb>>,
+    State(pb_manager): State<Arc<PocketBaseManager>>,
+    State(master_key): State<Arc<MasterKey>>,
+    user: AuthUser,
+    Json(update): Json<SettingsUpdate>,
+) -> Result<Json<ApiResponse<SettingsView>>, Response> {
+    let invalid = |message: &str| {
+        auth_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Validation,
+            message,
+        )
+    };
+
+    let record = settings_record(&global_pb, &user.id)
+        .await
+        .map_err(|e| settings_error(&user, e))?;
+    let mut settings = record
+        .as_ref()
+        .map(UserSettings::from_record)
+        .unwrap_or_default();
+    let mut has_webhook_secret = record.as_ref().and_then(webhook_secret_of).is_some();
+    if let Some(auto_enqueue) = update.auto_enqueue {
+        settings.auto_enqueue = auto_enqueue;
+    }
+    if let Some(email_on_success) = update.email_on_success {
+        settings.email_on_success = email_on_success;
+    }
+    if let Some(workspace_id) = update.fathom_workspace_id.as_deref().map(str::trim) {
+        if workspace_id.len() > MAX_WORKSPACE_ID_LENGTH {
+            return Err(invalid("The Fathom workspace id is too long"));
+        }
+        if !workspace_id.is_empty() {
+            let owner = workspace_owner(&global_pb, workspace_id)
+                .await
+                .map_err(|e| settings_error(&user, e))?;
+            if owner.is_some_and(|owner| owner.user_id != user.id) {
+                return Err(auth_error(
+                    StatusCode::CONFLICT,
+                    ErrorCode::Validation,
+                    "That Fathom workspace is linked to another account",
+                ));
+            }
+        }
+        settings.fathom_workspace_id = Some(workspace_id.to_string()).filter(|id| !id.is_empty());
+    }
+    if update
+        .fathom_webhook_secret
+        .as_deref()
+        .is_some_and(|secret| !secret.is_empty() && secret.len() < MIN_WEBHOOK_SECRET_LENGTH)
+    {
+        return Err(invalid(&format!(
+            "The webhook secret must be at least {} characters",
+            MIN_WEBHOOK_SECRET_LENGTH
+        )));
+    }
+
+    let secret = match update.fathom_webhook_secret.as_deref() {
+        Some("") => {
+            // A secret left in the instance would otherwise be moved back
+            repository(&pb_manager, &master_key, &user)
+                .await?
+                .delete(WEBHOOK_SECRET_SERVICE, WEBHOOK_SECRET_KEY_ID)
+                .await
+                .map_err(store_error)?;
+            Some(None)
+        }
+        Some(secret) => Some(Some(encrypt_webhook_secret(&master_key, &user.id, secret))),
+        None => None,
+    };
+    if let Some(secret) = &secret {
+        has_webhook_secret = secret.is_some();
+    }
+    save(
+        &global_pb,
+        &user.id,
+        &settings,
+        secret.as_ref().map(Option::as_ref),
+    )
+    .await
+    .map_err(|e| settings_error(&user, e))?;
+    info!(target: "audit", user_id = %user.id, "Settings changed");
+    Ok(Json(ApiResponse::success(SettingsView {
+        settings,
+        has_webhook_secret,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, AppState},
+        pocketbase_manager::PocketBaseManager,
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{body::Body, http::Request};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn setup(dir: &std::path::Path) -> AppState {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+        for user_id in ["alice", "bob"] {
+            let data_dir = state.pb_manager.data_dir(user_id);
+            std::fs::create_dir_all(&data_dir).unwrap();
+            std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        }
+        state
+    }
+
+    async fn send(state: &AppState, user: &str, update: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(if update.is_some() { "PUT" } else { "GET" })
+            .uri("/api/settings")
+            .header("authorization", format!("Bearer valid-{}", user))
+            .header("content-type", "application/json");
+        let body = update.map_or_else(Body::empty, |update| Body::from(update.to_string()));
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_settings_are_saved_and_workspaces_linked_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        let (status, body) = send(&state, "alice", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!({
+                "auto_enqueue": false,
+                "fathom_workspace_id": null,
+                "email_on_success": false,
+                "has_webhook_secret": false
+            })
+        );
+
+        let short = json!({ "auto_enqueue": true, "fathom_webhook_secret": "short" });
+        let (status, body) = send(&state, "alice", Some(short)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+
+        let update = json!({
+            "auto_enqueue": true,
+            "fathom_workspace_id": " ws-1 ",
+            "fathom_webhook_secret": "whsec-0123456789abcdef",
+            "email_on_success": true
+        });
+        let (status, body) = send(&state, "alice", Some(update)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!({
+                "auto_enqueue": true,
+                "fathom_workspace_id": "ws-1",
+                "email_on_success": true,
+                "has_webhook_secret": true
+            })
+        );
+        assert!(!body.to_string().contains("0123456789"));
+        // Leaving fields out keeps them
+        let (_, body) = send(&state, "alice", Some(json!({}))).await;
+        assert_eq!(body["data"]["fathom_workspace_id"], "ws-1");
+        assert_eq!(body["data"]["has_webhook_secret"], true);
+
+        let (status, _) = send(
+            &state,
+            "bob",
+            Some(json!({ "fathom_workspace_id": "ws-1" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let unlink = json!({ "fathom_workspace_id": "", "fathom_webhook_secret": "" });
+        let (_, body) = send(&state, "alice", Some(unlink)).await;
+        assert_eq!(
+            body["data"],
+            json!({
+                "auto_enqueue": true,
+                "fathom_workspace_id": null,
+                "email_on_success": true,
+                "has_webhook_secret": false
+            })
+        );
+        let (status, _) = send(
+            &state,
+            "bob",
+            Some(json!({ "fathom_workspace_id": "ws-1" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+        let _ = state.pb_manager.stop_user_instance("bob").await;
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_kept_on_the_record_for_their_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path()).await;
+
+        // A secret saved into the instance before is moved onto the record
+        let repository = KeyRepository::open(&state.pb_manager, "alice", &state.master_key)
+            .await
+            .unwrap();
+        repository
+            .put(WEBHOOK_SECRET_SERVICE, WEBHOOK_SECRET_KEY_ID, "whsec-legacy-0123456789", None)
+            .await
+            .unwrap();
+        let (status, _) = send(&state, "alice", Some(json!({ "fathom_workspace_id": "ws-1" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&state, "alice", None).await;
+        assert_eq!(body["data"]["has_webhook_secret"], true);
+        assert!(repository
+            .get(WEBHOOK_SECRET_SERVICE, WEBHOOK_SECRET_KEY_ID)
+            .await
+            .unwrap()
+            .is_none());
+
+        let owner = workspace_owner(&state.global_pb, "ws-1").await.unwrap().unwrap();
+        let secret = owner.webhook_secret.unwrap();
+        assert_eq!(
+            secret.decrypt_key_for_user("alice", &state.master_key.bytes()).unwrap(),
+            "whsec-legacy-0123456789"
+        );
+        assert!(secret.decrypt_key_for_user("bob", &state.master_key.bytes()).is_err());
+
+        let (_, body) = send(&state, "alice", Some(json!({ "fathom_webhook_secret": "" }))).await;
+        assert_eq!(body["data"]["has_webhook_secret"], false);
+        let (_, body) = send(&state, "alice", None).await;
+        assert_eq!(body["data"]["has_webhook_secret"], false);
+        let owner = workspace_owner(&state.global_pb, "ws-1").await.unwrap().unwrap();
+        assert!(owner.webhook_secret.is_none());
+
+        let _ = state.pb_manager.stop_user_instance("alice").await;
+    }
+}
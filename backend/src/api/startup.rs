@@ -0,0 +1,240 @@
+//! Whether the backend has finished starting, or has begun shutting down
+//!
+//! `main` moves the [`StartupState`] from starting to ready once every
+//! startup step is done, and on to draining when a shutdown signal arrives.
+//! Outside ready, `/health/ready` answers 503 so no new traffic is routed
+//! here, while `/health/live` keeps answering 200 so the process isn't
+//! restarted for it. Requests that arrive anyway are refused with 503 by the
+//! queue and meetings routes themselves, which run the [`Ready`] extractor
+//! before their handlers.
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use common::{ApiResponse, ErrorCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+/// Where the backend is in its lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Startup steps are still running
+    Starting,
+    /// Serving normally
+    Ready,
+    /// A shutdown signal arrived; in-flight requests are finishing
+    Draining,
+}
+
+impl Phase {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Phase::Starting,
+            1 => Phase::Ready,
+            _ => Phase::Draining,
+        }
+    }
+}
+
+/// The current [`Phase`], shared between `main` and the handlers
+#[derive(Debug, Default)]
+pub struct StartupState(AtomicU8);
+
+impl StartupState {
+    /// A backend that is still starting
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn phase(&self) -> Phase {
+        Phase::from_u8(self.0.load(Ordering::SeqCst))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.phase() == Phase::Ready
+    }
+
+    /// Startup is done; ignored once draining, which is never undone
+    pub fn mark_ready(&self) {
+        let _ = self.0.compare_exchange(
+            Phase::Starting as u8,
+            Phase::Ready as u8,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+    }
+
+    /// Shutdown has begun
+    pub fn begin_draining(&self) {
+        self.0.store(Phase::Draining as u8, Ordering::SeqCst);
+    }
+}
+
+/// Holds back routes that must not run until startup is done
+///
+/// Applied with `middleware::from_extractor` as a route layer, so a backend
+/// that isn't ready refuses before asking PocketBase about the token.
+#[derive(Debug)]
+pub struct Ready;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Ready
+where
+    S: Send + Sync,
+    Arc<StartupState>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let (code, message) = match Arc::<StartupState>::from_ref(state).phase() {
+            Phase::Ready => return Ok(Ready),
+            Phase::Starting => (
+                ErrorCode::StartingUp,
+                "The server is starting up; try again shortly",
+            ),
+            Phase::Draining => (
+                ErrorCode::ShuttingDown,
+                "The server is shutting down; try again shortly",
+            ),
+        };
+        Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, "5")],
+            Json(ApiResponse::<Value>::failure(code, message)),
+        )
+            .into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::mock_app_state;
+    use axum::{body::Body, extract::Request, Router};
+    use tower::ServiceExt;
+
+    /// The API of a backend that has just started, and its phase
+    async fn app() -> (Router, Arc<StartupState>) {
+        let mut state = mock_app_state().await;
+        let startup = Arc::new(StartupState::new());
+        state.startup = startup.clone();
+        (create_api_router(state), startup)
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer valid-alice")
+            .header("content-type", "application/json")
+            .body(Body::from("{\"user_id\":\"alice\",\"meetings\":[]}"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn test_phases_only_move_forward() {
+        let state = StartupState::new();
+        assert_eq!(state.phase(), Phase::Starting);
+        state.mark_ready();
+        assert_eq!(state.phase(), Phase::Ready);
+        state.begin_draining();
+        assert_eq!(state.phase(), Phase::Draining);
+        state.mark_ready();
+        assert_eq!(state.phase(), Phase::Draining);
+
+        // Shutting down before startup finished also ends in draining
+        let state = StartupState::new();
+        state.begin_draining();
+        state.mark_ready();
+        assert!(!state.is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_each_phase_answers_the_probes_and_handlers() {
+        let (app, startup) = app().await;
+
+        let guarded = [
+            ("GET", "/api/v1/queue"),
+            ("POST", "/api/v1/queue"),
+            (
+                "DELETE",
+                "/api/v1/queue/00000000-0000-0000-0000-000000000000",
+            ),
+            ("GET", "/api/v1/meetings"),
+            ("POST", "/api/v1/meetings/refresh"),
+            ("GET", "/api/v1/meetings/some-id/transcript"),
+        ];
+        for (phase, code) in [
+            (Phase::Starting, "starting_up"),
+            (Phase::Ready, ""),
+            (Phase::Draining, "shutting_down"),
+        ] {
+            match phase {
+                Phase::Starting => {}
+                Phase::Ready => startup.mark_ready(),
+                Phase::Draining => startup.begin_draining(),
+            }
+            assert_eq!(startup.phase(), phase);
+
+            let (status, _) = call(&app, "GET", "/health/live").await;
+            assert_eq!(status, StatusCode::OK, "{:?}", phase);
+
+            let (status, body) = call(&app, "GET", "/health/ready").await;
+            assert_eq!(
+                body["phase"],
+                serde_json::to_value(phase).unwrap(),
+                "{}",
+                body
+            );
+            if phase == Phase::Ready {
+                assert_eq!(status, StatusCode::OK, "{}", body);
+            } else {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{:?}", phase);
+                assert_eq!(body["ready"], false);
+            }
+
+            for (method, uri) in guarded {
+                let (status, body) = call(&app, method, uri).await;
+                if phase == Phase::Ready {
+                    // Through to the handler, whatever it then makes of the request
+                    for held in ["starting_up", "shutting_down"] {
+                        assert_ne!(body["code"], held, "{} {}: {}", method, uri, body);
+                    }
+                } else {
+                    assert_eq!(
+                        status,
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "{} {} {:?}",
+                        method,
+                        uri,
+                        phase
+                    );
+                    assert_eq!(body["code"], code, "{} {}", method, uri);
+                    assert_eq!(body["success"], false);
+                }
+            }
+        }
+
+        // Routes outside the queue and meetings are not held back
+        let (status, _) = call(&app, "GET", "/api/v1/env").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,123 @@
+//! The versioned API prefix and the unversioned aliases kept beside it
+//!
+//! Everything under `/api` is served at [`API_PREFIX`] (`/api/v1`). The same
+//! routes stay reachable at the bare `/api` for one release so deployed
+//! frontends keep working, with a `Deprecation` header and a `Link` to the
+//! versioned path on every answer.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+pub use common::{API_PREFIX, API_VERSION};
+
+/// The unversioned prefix the legacy aliases are served under
+pub const LEGACY_PREFIX: &str = "/api";
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Mark answers to the legacy prefix deprecated, naming their successor
+///
+/// Runs inside the `/api` nest, so the path it sees has the prefix stripped.
+pub async fn deprecate_legacy(request: Request, next: Next) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        API_PREFIX,
+        request.uri().path()
+    );
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append(axum::http::header::LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::create_api_router;
+    use crate::test_support::mock_app_state_with;
+    use axum::{body::Body, http::StatusCode, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn app() -> Router {
+        create_api_router(mock_app_state_with(|config| config.server.openapi_enabled = true).await)
+    }
+
+    async fn get(app: &Router, uri: &str) -> Response {
+        let request = Request::builder()
+            .uri(uri)
+            .header("authorization", "Bearer valid-alice")
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn json(response: Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_both_prefixes_reach_the_same_handlers() {
+        let app = app().await;
+        for path in ["/queue", "/env", "/openapi.json"] {
+            let current = get(&app, &format!("/api/v1{}", path)).await;
+            let legacy = get(&app, &format!("/api{}", path)).await;
+            assert_eq!(current.status(), StatusCode::OK, "{}", path);
+            assert_eq!(legacy.status(), StatusCode::OK, "{}", path);
+
+            let (current, legacy) = (json(current).await, json(legacy).await);
+            if path == "/env" {
+                assert_eq!(current, legacy);
+            } else if path == "/queue" {
+                assert_eq!(current["data"], legacy["data"]);
+            } else {
+                assert_eq!(current["paths"], legacy["paths"]);
+            }
+        }
+
+        // Whatever isn't a route is missing under either prefix
+        assert_eq!(
+            get(&app, "/api/v1/nowhere").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get(&app, "/api/v2/queue").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_the_legacy_prefix_is_deprecated() {
+        let app = app().await;
+
+        let current = get(&app, "/api/v1/queue").await;
+        assert!(current.headers().get("deprecation").is_none());
+        assert!(current.headers().get("link").is_none());
+
+        let legacy = get(&app, "/api/queue").await;
+        assert_eq!(legacy.headers()["deprecation"], "true");
+        assert_eq!(
+            legacy.headers()["link"],
+            "</api/v1/queue>; rel=\"successor-version\""
+        );
+
+        // Errors on the legacy prefix say so too
+        let refused = get(&app, "/api/admin/config").await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(refused.headers()["deprecation"], "true");
+
+        // Routes outside `/api` were never versioned
+        let health = get(&app, "/health/live").await;
+        assert!(health.headers().get("deprecation").is_none());
+    }
+}
@@ -0,0 +1,498 @@
+//! Fathom webhooks, nested under `/webhooks`
+//!
+//! `POST /webhooks/fathom` takes Fathom's events for a workspace. The user
+//! who linked the workspace in their settings is looked up first, then the
+//! `X-Fathom-Signature` header is checked: `sha256=` and the hex
+//! HMAC-SHA256 of the raw body under that user's webhook secret. An unknown
+//! workspace, a missing secret and a wrong signature all get the same 401,
+//! so the endpoint doesn't tell which workspaces are linked. The secret is
+//! read from the settings record, so nothing of the user's is started for
+//! a delivery until it is signed. Deliveries are limited per workspace on
+//! top of the per-client limit every webhook has.
+//!
+//! A `recording.ready` event queues the recording if the user has
+//! `auto_enqueue` on and a verified email, through the same path as
+//! `POST /api/queue`, so a replayed event finds the recording queued and
+//! adds nothing. Other events are acknowledged and ignored.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{error, info, warn};
+
+use super::{
+    auth::auth_error,
+    email_verification,
+    error_report::with_cause,
+    extractors::AuthError,
+    queue::{self, Enqueued, MeetingRequest},
+    rate_limit::rate_limited,
+    settings::{self, WorkspaceOwner},
+    AppState,
+};
+use crate::global_pb::GlobalPbError;
+use common::{
+    fathom::{wire, FathomMeeting},
+    ApiResponse, AppError, ErrorCode,
+};
+
+pub const SIGNATURE_HEADER: &str = "x-fathom-signature";
+
+/// The event Fathom sends once a recording can be fetched
+pub const RECORDING_READY: &str = "recording.ready";
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    workspace_id: Option<String>,
+    /// The recording, for recording events
+    #[serde(default)]
+    recording: Option<wire::Meeting>,
+}
+
+/// What a delivered event led to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookOutcome {
+    Queued,
+    AlreadyQueued,
+    /// The user hasn't turned `auto_enqueue` on
+    AutoEnqueueOff,
+    /// The user's email isn't verified, so nothing may be queued for them
+    Unverified,
+    /// The user has as many meetings queued as they may
+    QueueFull,
+    /// Not an event anything is done for
+    Ignored,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookReceipt {
+    pub outcome: WebhookOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meeting_id: Option<String>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/fathom", post(fathom_webhook))
+}
+
+/// Whether `signature` is `sha256=` and the hex HMAC-SHA256 of `body` under
+/// `secret`, compared in constant time
+pub fn signature_matches(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .trim()
+        .strip_prefix("sha256=")
+        .and_then(decode_hex)
+    else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn invalid_signature() -> Response {
+    AuthError::unauthorized(
+        "invalid_signature",
+        "The webhook signature is missing or invalid",
+    )
+    .into_response()
+}
+
+fn store_unavailable(e: GlobalPbError) -> Response {
+    error!("Fathom webhook could not read settings: {}", e);
+    auth_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::ServiceUnavailable,
+        "Settings are temporarily unavailable",
+    )
+}
+
+fn receipt(outcome: WebhookOutcome, meeting_id: Option<String>) -> Response {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success(WebhookReceipt {
+            outcome,
+            meeting_id,
+        })),
+    )
+        .into_response()
+}
+
+/// POST /webhooks/fathom - An event from Fathom, signed with the webhook
+/// secret of the user who linked its workspace
+pub async fn fathom_webhook(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let event: Event = serde_json::from_slice(&body).map_err(|_| {
+        auth_error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::Validation,
+            "The webhook body is not a Fathom event",
+        )
+    })?;
+    let workspace_id = event
+        .workspace_id
+        .as_deref()
+        .filter(|id| !id.is_empty())
+        .ok_or_else(invalid_signature)?;
+    if let Some(limiter) = &app_state.rate_limits.webhook_per_workspace {
+        if !limiter.check(workspace_id).await {
+            warn!(target: "audit", workspace_id = %workspace_id, "Fathom webhook deliveries over the limit");
+            return Err(rate_limited(None, "Too many deliveries for this workspace"));
+        }
+    }
+    let Some(WorkspaceOwner {
+        user_id,
+        settings: user_settings,
+        webhook_secret,
+    }) = settings::workspace_owner(&app_state.global_pb, workspace_id)
+        .await
+        .map_err(store_unavailable)?
+    else {
+        warn!(target: "audit", workspace_id = %workspace_id, "Fathom webhook for an unlinked workspace");
+        return Err(invalid_signature());
+    };
+
+    let Some(stored) = webhook_secret else {
+        warn!(target: "audit", user_id = %user_id, "Fathom webhook for a user without a webhook secret");
+        return Err(invalid_signature());
+    };
+    let secret = stored
+        .decrypt_key_for_user(&user_id, &app_state.master_key.bytes())
+        .map_err(|e| {
+            error!("Webhook secret of {} does not decrypt: {}", user_id, e);
+            with_cause(
+                auth_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ErrorCode::Internal,
+                    "Stored webhook secret could not be read",
+                ),
+                AppError::Internal(format!("Webhook secret does not decrypt: {}", e)),
+            )
+        })?;
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let signed = signature_matches(secret.as_bytes(), &body, signature);
+    drop(secret);
+    if !signed {
+        warn!(target: "audit", user_id = %user_id, "Fathom webhook signature mismatch");
+        return Err(invalid_signature());
+    }
+
+    if event.kind != RECORDING_READY {
+        info!("Ignoring Fathom {} event for {}", event.kind, user_id);
+        return Ok(receipt(WebhookOutcome::Ignored, None));
+    }
+    let meeting = event
+        .recording
+        .ok_or_else(|| "no recording".to_string())
+        .and_then(|recording| FathomMeeting::try_from(recording).map_err(|e| e.0))
+        .map_err(|e| {
+            warn!(
+                "Fathom {} event for {} is malformed: {}",
+                RECORDING_READY, user_id, e
+            );
+            auth_error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorCode::Validation,
+                "The event has no usable recording",
+            )
+        })?;
+    if !user_settings.auto_enqueue {
+        return Ok(receipt(WebhookOutcome::AutoEnqueueOff, Some(meeting.id)));
+    }
+    let verified = email_verification::is_verified(&app_state.global_pb, &user_id)
+        .await
+        .map_err(store_unavailable)?;
+    if !verified {
+        return Ok(receipt(WebhookOutcome::Unverified, Some(meeting.id)));
+    }
+
+    let request = MeetingRequest {
+        user_id: user_id.clone(),
+        topic: meeting.title,
+        meeting_id: Some(meeting.id.clone()),
+        fathom_key_id: None,
+        loom_key_id: None,
+    };
+    // Fathom retries a 503 later, by which time maintenance may be over
+    if let Some(refusal) = queue::maintenance_refusal(&app_state.config) {
+        return Err(refusal);
+    }
+    let outcome = match queue::enqueue(&app_state, request).await.0 {
+        Enqueued::Added(_) => WebhookOutcome::Queued,
+        Enqueued::AlreadyQueued(_) => WebhookOutcome::AlreadyQueued,
+        Enqueued::QueueFull => WebhookOutcome::QueueFull,
+    };
+    info!(
+        "Fathom recording {} of {}: {:?}",
+        meeting.id, user_id, outcome
+    );
+    Ok(receipt(outcome, Some(meeting.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{create_api_router, settings::USER_SETTINGS},
+        pocketbase_manager::PocketBaseManager,
+        test_support::{fake_pocketbase, mock_global_pocketbase, port_window, test_app_state, test_config},
+    };
+    use axum::{body::Body, http::Request};
+    use common::crypto::EncryptedApiKey;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "whsec-0123456789abcdef";
+
+    /// State with a verified user linking `ws-1` with [`SECRET`], and their id
+    async fn setup(dir: &std::path::Path, auto_enqueue: bool) -> (AppState, String) {
+        let port = port_window(10);
+        let global_url = mock_global_pocketbase().await;
+        let binary = fake_pocketbase(dir);
+        let manager =
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10));
+        let state = test_app_state(test_config(&global_url), manager);
+
+        let record =
+            json!({ "email": "alice@example.com", "password": "password", "verified": true });
+        let user = state
+            .global_pb
+            .create_record("users", &record)
+            .await
+            .unwrap();
+        let user_id = user["id"].as_str().unwrap().to_string();
+        let secret = EncryptedApiKey::new_for_user(
+            &user_id,
+            settings::WEBHOOK_SECRET_SERVICE.to_string(),
+            settings::WEBHOOK_SECRET_KEY_ID.to_string(),
+            SECRET,
+            &state.master_key.bytes(),
+            None,
+        );
+        let settings = json!({
+            "user_id": user_id,
+            "auto_enqueue": auto_enqueue,
+            "fathom_workspace_id": "ws-1",
+            "fathom_webhook_secret": secret,
+        });
+        state
+            .global_pb
+            .create_record(USER_SETTINGS, &settings)
+            .await
+            .unwrap();
+        (state, user_id)
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        let digest: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("sha256={}", digest)
+    }
+
+    fn ready(workspace_id: &str, recording_id: u64) -> Value {
+        json!({
+            "type": RECORDING_READY,
+            "workspace_id": workspace_id,
+            "recording": {
+                "recording_id": recording_id,
+                "title": "Weekly sync",
+                "recording_start_time": "2026-05-01T10:00:00Z"
+            }
+        })
+    }
+
+    async fn deliver(
+        state: &AppState,
+        event: &Value,
+        signature: Option<&str>,
+    ) -> (StatusCode, Value) {
+        let body = event.to_string();
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/webhooks/fathom")
+            .header("content-type", "application/json");
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        let response = create_api_router(state.clone())
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    async fn signed(state: &AppState, event: &Value) -> (StatusCode, Value) {
+        let signature = sign(SECRET, event.to_string().as_bytes());
+        deliver(state, event, Some(&signature)).await
+    }
+
+    #[test]
+    fn test_signatures_are_checked_against_the_body() {
+        let signature = sign(SECRET, b"{}");
+        assert!(signature_matches(SECRET.as_bytes(), b"{}", &signature));
+        assert!(!signature_matches(SECRET.as_bytes(), b"{ }", &signature));
+        assert!(!signature_matches(b"another-secret", b"{}", &signature));
+        for bad in ["", "sha256=", "sha256=zz", "md5=00", &signature[7..]] {
+            assert!(!signature_matches(SECRET.as_bytes(), b"{}", bad), "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_and_missigned_events_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, user_id) = setup(dir.path(), true).await;
+        let event = ready("ws-1", 1001);
+
+        let forged = sign("not-the-secret-at-all", event.to_string().as_bytes());
+        let other_body = sign(SECRET, ready("ws-1", 1002).to_string().as_bytes());
+        for signature in [
+            None,
+            Some("sha256=00"),
+            Some(forged.as_str()),
+            Some(other_body.as_str()),
+        ] {
+            let (status, body) = deliver(&state, &event, signature).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", signature);
+            assert_eq!(body["error"], "invalid_signature");
+        }
+        // Unlinked workspaces look no different
+        let stranger = ready("ws-2", 1001);
+        let signature = sign(SECRET, stranger.to_string().as_bytes());
+        let (status, body) = deliver(&state, &stranger, Some(&signature)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_signature");
+
+        let (status, _) = deliver(&state, &json!("not an event"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(state.meetings_queue.read().await.is_empty());
+        // Nothing of the user's was started for deliveries nobody signed
+        assert!(state.pb_manager.get_user_instance(&user_id).await.is_none());
+
+        let _ = state.pb_manager.stop_user_instance(&user_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_ready_recordings_are_queued_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, user_id) = setup(dir.path(), true).await;
+
+        let (status, body) = signed(&state, &ready("ws-1", 1001)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!({ "outcome": "queued", "meeting_id": "1001" })
+        );
+        {
+            let queue = state.meetings_queue.read().await;
+            assert_eq!(queue.len(), 1);
+            assert_eq!(queue[0].user_id, user_id);
+            assert_eq!(queue[0].topic, "Weekly sync");
+            assert_eq!(queue[0].meeting_id.as_deref(), Some("1001"));
+        }
+
+        // Fathom retrying the delivery adds nothing
+        let (status, body) = signed(&state, &ready("ws-1", 1001)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["outcome"], "already_queued");
+        assert_eq!(state.meetings_queue.read().await.len(), 1);
+
+        let other = json!({ "type": "recording.deleted", "workspace_id": "ws-1" });
+        let (status, body) = signed(&state, &other).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["outcome"], "ignored");
+
+        let (status, _) = signed(
+            &state,
+            &json!({ "type": RECORDING_READY, "workspace_id": "ws-1" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(state.meetings_queue.read().await.len(), 1);
+
+        let _ = state.pb_manager.stop_user_instance(&user_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_limited_per_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut state, user_id) = setup(dir.path(), false).await;
+        let mut config = crate::config::Config::clone(&state.config);
+        config.rate_limits.webhook_per_minute = 2;
+        state.rate_limits = std::sync::Arc::new(crate::api::rate_limit::RateLimits::new(
+            &config.security,
+            &config.integrations,
+            &config.rate_limits,
+        ));
+
+        let event = ready("ws-1", 1001);
+        let (status, _) = signed(&state, &event).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = deliver(&state, &event, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = signed(&state, &event).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+        // Other workspaces have their own budget
+        let (status, _) = deliver(&state, &ready("ws-2", 1001), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let _ = state.pb_manager.stop_user_instance(&user_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_users_without_auto_enqueue_get_nothing_queued() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, user_id) = setup(dir.path(), false).await;
+
+        let (status, body) = signed(&state, &ready("ws-1", 1001)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["outcome"], "auto_enqueue_off");
+        assert!(state.meetings_queue.read().await.is_empty());
+
+        let _ = state.pb_manager.stop_user_instance(&user_id).await;
+    }
+}
@@ -1,9 +1,10 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
         State, Query,
     },
-    response::Response,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
@@ -11,11 +12,16 @@ use std::{
     collections::HashMap,
     sync::Arc,
 };
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::api::queue::Meeting;
+use crate::api::{
+    csrf,
+    extractors::{authenticate_token, AuthError},
+    queue::Meeting,
+};
+use crate::metrics::{self, AUTH_FAILURES, WEBSOCKET_MESSAGES};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueUpdate {
@@ -23,6 +29,15 @@ pub struct QueueUpdate {
     pub queue: Vec<Meeting>,
     pub affected_user_id: Option<String>,
     pub global_position: Option<usize>,
+    /// The worker's task, on updates relayed from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<Uuid>,
+    /// When a task that failed goes again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<common::broadcast::RetryInfo>,
+    /// Why the worker's task failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
@@ -33,6 +48,10 @@ pub enum QueueUpdateType {
     MeetingRemoved,
     PositionUpdated,
     QueueCleared,
+    /// The worker's task is on Loom, so it has left the queue
+    TaskCompleted,
+    /// The worker's task failed for good
+    TaskFailed,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +75,9 @@ pub enum ProgressType {
 pub enum WebSocketMessage {
     QueueUpdate(QueueUpdate),
     ProgressUpdate(ProgressUpdate),
+    SystemEvent(common::broadcast::SystemEvent),
+    /// How far the worker has got with one of the user's tasks
+    TaskProgress(common::broadcast::ProgressUpdate),
     Ping,
     Pong,
 }
@@ -64,7 +86,11 @@ pub enum WebSocketMessage {
 pub struct WebSocketManager {
     queue_sender: broadcast::Sender<QueueUpdate>,
     progress_sender: broadcast::Sender<ProgressUpdate>,
+    system_sender: broadcast::Sender<common::broadcast::SystemEvent>,
+    task_progress_sender: broadcast::Sender<common::broadcast::ProgressUpdate>,
     connections: Arc<RwLock<HashMap<String, ConnectionInfo>>>,
+    /// Flips to true once the server starts shutting down
+    shutdown: watch::Sender<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -77,11 +103,16 @@ impl WebSocketManager {
     pub fn new() -> Self {
         let (queue_sender, _) = broadcast::channel(1000);
         let (progress_sender, _) = broadcast::channel(1000);
+        let (system_sender, _) = broadcast::channel(1000);
+        let (task_progress_sender, _) = broadcast::channel(1000);
         
         Self {
             queue_sender,
             progress_sender,
+            system_sender,
+            task_progress_sender,
             connections: Arc::new(RwLock::new(HashMap::new())),
+            shutdown: watch::channel(false).0,
         }
     }
     
@@ -89,24 +120,62 @@ impl WebSocketManager {
     pub fn with_external_broadcast(external_broadcast: Arc<common::broadcast::BroadcastService>) -> Self {
         let manager = Self::new();
         
+        // Forward system topic events; only admin connections receive them
+        let system_sender = manager.system_sender.clone();
+        let mut system_rx = external_broadcast.subscribe_system();
+        tokio::spawn(async move {
+            loop {
+                match system_rx.recv().await {
+                    Ok(event) => {
+                        let _ = system_sender.send(event);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("System event forwarder lagged, skipped {} events", skipped);
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        // Forward the worker's task progress; only the owner receives it
+        let task_progress_sender = manager.task_progress_sender.clone();
+        let mut task_progress_rx = external_broadcast.subscribe_progress();
+        tokio::spawn(async move {
+            loop {
+                match task_progress_rx.recv().await {
+                    Ok(update) => {
+                        let _ = task_progress_sender.send(update);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Task progress forwarder lagged, skipped {} updates", skipped);
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
         // Spawn task to forward external broadcasts to WebSocket clients
         let queue_sender = manager.queue_sender.clone();
+        let mut rx = external_broadcast.subscribe();
         tokio::spawn(async move {
-            let mut rx = external_broadcast.subscribe();
             while let Ok(update) = rx.recv().await {
                 // Convert external broadcast format to WebSocket format
                 let ws_update = QueueUpdate {
                     update_type: match update.update_type {
                         common::broadcast::QueueUpdateType::TaskStarted => QueueUpdateType::PositionUpdated,
-                        common::broadcast::QueueUpdateType::TaskCompleted => QueueUpdateType::MeetingRemoved,
-                        common::broadcast::QueueUpdateType::TaskFailed => QueueUpdateType::PositionUpdated,
+                        common::broadcast::QueueUpdateType::TaskCompleted => QueueUpdateType::TaskCompleted,
+                        common::broadcast::QueueUpdateType::TaskFailed => QueueUpdateType::TaskFailed,
                         common::broadcast::QueueUpdateType::TaskRetried => QueueUpdateType::PositionUpdated,
+                        common::broadcast::QueueUpdateType::TaskCancelled => QueueUpdateType::MeetingRemoved,
                         common::broadcast::QueueUpdateType::PositionUpdated => QueueUpdateType::PositionUpdated,
                         common::broadcast::QueueUpdateType::QueueCleared => QueueUpdateType::QueueCleared,
                     },
                     queue: vec![], // Will be populated by the queue state
                     affected_user_id: update.affected_user_id,
                     global_position: update.global_position,
+                    task_id: update.task_id,
+                    retry: update.retry,
+                    error: update.error,
                     timestamp: update.timestamp,
                 };
                 
@@ -119,11 +188,12 @@ impl WebSocketManager {
         manager
     }
 
-    /// Handle new WebSocket connection
+    /// Handle new WebSocket connection from the authenticated `user_id`
     pub async fn handle_socket(
         &self,
         socket: WebSocket,
         user_id: String,
+        is_admin: bool,
     ) {
         let connection_id = Uuid::new_v4().to_string();
         let user_id_clone = user_id.clone();
@@ -144,6 +214,9 @@ impl WebSocketManager {
         // Subscribe to updates
         let mut queue_rx = self.queue_sender.subscribe();
         let mut progress_rx = self.progress_sender.subscribe();
+        let mut system_rx = self.system_sender.subscribe();
+        let mut task_progress_rx = self.task_progress_sender.subscribe();
+        let mut shutdown_rx = self.shutdown.subscribe();
         
         // Clone connection manager for cleanup
         let connections_cleanup = Arc::clone(&self.connections);
@@ -159,6 +232,7 @@ impl WebSocketManager {
             while let Some(msg) = receiver.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
+                        metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "inbound")]);
                         // Handle incoming text messages (like ping/pong)
                         if text == "ping" {
                             if pong_tx.send(Message::Text("pong".to_string())).is_err() {
@@ -183,12 +257,22 @@ impl WebSocketManager {
         let outgoing_task = tokio::spawn(async move {
             loop {
                 tokio::select! {
+                    // Also fires for sockets opened just as shutdown began
+                    _ = async { shutdown_rx.wait_for(|shutting_down| *shutting_down).await.map(drop) } => {
+                        let close = Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "Server shutting down".into(),
+                        }));
+                        let _ = sender_clone.lock().await.send(close).await;
+                        break;
+                    }
                     pong_msg = pong_rx.recv() => {
                         if let Some(msg) = pong_msg {
                             let mut sender_guard = sender_clone.lock().await;
                             if sender_guard.send(msg).await.is_err() {
                                 break;
                             }
+                            metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "outbound")]);
                         }
                     }
                     queue_update = queue_rx.recv() => {
@@ -213,6 +297,7 @@ impl WebSocketManager {
                                         if sender_guard.send(Message::Text(json)).await.is_err() {
                                             break;
                                         }
+                                        metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "outbound")]);
                                     }
                                 }
                             }
@@ -231,6 +316,7 @@ impl WebSocketManager {
                                     if sender_guard.send(Message::Text(json)).await.is_err() {
                                         break;
                                     }
+                                    metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "outbound")]);
                                 }
                             }
                             Err(broadcast::error::RecvError::Lagged(_)) => {
@@ -239,6 +325,44 @@ impl WebSocketManager {
                             Err(_) => break,
                         }
                     }
+                    task_progress = task_progress_rx.recv() => {
+                        match task_progress {
+                            Ok(update) if update.user_id == user_id_clone => {
+                                let message = WebSocketMessage::TaskProgress(update);
+                                if let Ok(json) = serde_json::to_string(&message) {
+                                    let mut sender_guard = sender_clone.lock().await;
+                                    if sender_guard.send(Message::Text(json)).await.is_err() {
+                                        break;
+                                    }
+                                    metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "outbound")]);
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(broadcast::error::RecvError::Lagged(_)) => {
+                                warn!("WebSocket lagged behind on task progress");
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    system_event = system_rx.recv() => {
+                        match system_event {
+                            Ok(event) if is_admin => {
+                                let message = WebSocketMessage::SystemEvent(event);
+                                if let Ok(json) = serde_json::to_string(&message) {
+                                    let mut sender_guard = sender_clone.lock().await;
+                                    if sender_guard.send(Message::Text(json)).await.is_err() {
+                                        break;
+                                    }
+                                    metrics::increment(&WEBSOCKET_MESSAGES, &[("direction", "outbound")]);
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(broadcast::error::RecvError::Lagged(_)) => {
+                                warn!("WebSocket lagged behind on system events");
+                            }
+                            Err(_) => break,
+                        }
+                    }
                 }
             }
         });
@@ -276,34 +400,164 @@ impl WebSocketManager {
     pub async fn connection_count(&self) -> usize {
         self.connections.read().await.len()
     }
+
+    /// Close every open socket with "going away" and refuse new ones
+    ///
+    /// Open sockets would otherwise hold the graceful HTTP drain open until
+    /// its grace period runs out.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
 }
 
 #[derive(Deserialize)]
 pub struct WebSocketQuery {
-    pub user_id: Option<String>,
+    /// The access token, as browsers can't set headers on the upgrade
     pub token: Option<String>,
 }
 
 /// WebSocket upgrade handler with user authentication
+///
+/// The token comes from `?token=` or, in cookie mode, the session cookie,
+/// and is verified as for any other request; the socket's user and whether
+/// it sees system events follow from it. Sockets without a valid token are
+/// refused. Browsers send the cookie whichever page opens the socket, so a
+/// cookie only counts with an `Origin` from `CORS_ORIGINS`.
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<WebSocketQuery>,
+    headers: HeaderMap,
     State(app_state): State<crate::api::AppState>,
 ) -> Response {
-    // Extract user_id from query params or authentication token
-    let user_id = match (params.user_id, params.token) {
-        (Some(uid), _) => uid,
-        (None, Some(token)) => {
-            // TODO: Validate token and extract user_id
-            // For now, use a placeholder
-            format!("user_from_token_{}", token.chars().take(8).collect::<String>())
-        },
-        (None, None) => "anonymous".to_string(),
-    };
-    
     let ws_manager = app_state.ws_manager.clone();
-    
+    if ws_manager.is_shutting_down() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down").into_response();
+    }
+
+    let (token, from_cookie) = match params.token.filter(|token| !token.is_empty()) {
+        Some(token) => (Some(token), false),
+        None => (csrf::session_cookie(&headers).map(String::from), true),
+    };
+    let Some(token) = token else {
+        metrics::increment(&AUTH_FAILURES, &[("kind", "token"), ("reason", "missing_authorization")]);
+        return AuthError::unauthorized("missing_authorization", "A token is required to subscribe").into_response();
+    };
+    if from_cookie {
+        let origin = headers.get(header::ORIGIN).and_then(|origin| origin.to_str().ok());
+        if !origin.is_some_and(|origin| app_state.config.cors.allows(origin)) {
+            metrics::increment(&AUTH_FAILURES, &[("kind", "token"), ("reason", "origin_not_allowed")]);
+            return AuthError::forbidden("origin_not_allowed", "The session cookie is only accepted from the app's origins")
+                .into_response();
+        }
+    }
+    let user = match authenticate_token(token, &app_state).await {
+        Ok(user) => user,
+        Err(e) => {
+            metrics::increment(&AUTH_FAILURES, &[("kind", "token"), ("reason", &e.error)]);
+            return e.into_response();
+        }
+    };
+
+    let is_admin = user.is_admin();
     ws.on_upgrade(move |socket| async move {
-        ws_manager.handle_socket(socket, user_id).await
+        ws_manager.handle_socket(socket, user.id, is_admin).await
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::api::create_api_router;
+    use crate::test_support::{mock_app_state, spawn_server};
+    use common::broadcast::{SystemEvent, SystemEventType};
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, Message};
+
+    type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    async fn subscribe(request: impl IntoClientRequest + Unpin) -> Result<Socket, tungstenite::Error> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+        // The pong means the connection is subscribed
+        socket.send(Message::Text("ping".to_string())).await.unwrap();
+        while socket.next().await.unwrap().unwrap() != Message::Text("pong".to_string()) {}
+        Ok(socket)
+    }
+
+    fn refused_with(result: Result<Socket, tungstenite::Error>) -> u16 {
+        match result {
+            Err(tungstenite::Error::Http(response)) => response.status().as_u16(),
+            other => panic!("expected the upgrade to be refused, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sockets_need_a_valid_token() {
+        let state = mock_app_state().await;
+        let ws_manager = state.ws_manager.clone();
+        let ws_url = spawn_server(create_api_router(state)).await.replacen("http", "ws", 1);
+
+        assert_eq!(refused_with(subscribe(&format!("{}/queue_updates", ws_url)).await), 401);
+        // Naming a user is no longer enough to be them
+        let forged = format!("{}/queue_updates?user_id=admin", ws_url);
+        assert_eq!(refused_with(subscribe(&forged).await), 401);
+        let invalid = format!("{}/queue_updates?token=not-a-token", ws_url);
+        assert_eq!(refused_with(subscribe(&invalid).await), 401);
+        assert_eq!(ws_manager.connection_count().await, 0);
+
+        subscribe(&format!("{}/queue_updates?token=valid-alice", ws_url)).await.unwrap();
+        assert_eq!(ws_manager.connection_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_the_session_cookie_only_counts_from_allowed_origins() {
+        let state = mock_app_state().await;
+        let ws_url = spawn_server(create_api_router(state)).await.replacen("http", "ws", 1);
+        let with_cookie = |origin: Option<&str>| {
+            let mut request = format!("{}/queue_updates", ws_url).into_client_request().unwrap();
+            request.headers_mut().insert("cookie", "session=valid-alice".parse().unwrap());
+            if let Some(origin) = origin {
+                request.headers_mut().insert("origin", origin.parse().unwrap());
+            }
+            request
+        };
+
+        subscribe(with_cookie(Some("http://localhost:8080"))).await.unwrap();
+        assert_eq!(refused_with(subscribe(with_cookie(Some("https://evil.example.com"))).await), 403);
+        assert_eq!(refused_with(subscribe(with_cookie(None)).await), 403);
+
+        // A token in the URL isn't sent by the browser on its own
+        let mut request = format!("{}/queue_updates?token=valid-alice", ws_url).into_client_request().unwrap();
+        request.headers_mut().insert("origin", "https://evil.example.com".parse().unwrap());
+        subscribe(request).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_only_verified_admins_see_system_events() {
+        let state = mock_app_state().await;
+        let broadcast = state.broadcast.clone();
+        let ws_url = spawn_server(create_api_router(state)).await.replacen("http", "ws", 1);
+
+        // The mock's `admin` signs in as the configured admin email
+        let mut admin = subscribe(&format!("{}/queue_updates?token=valid-admin", ws_url)).await.unwrap();
+        let mut alice = subscribe(&format!("{}/queue_updates?token=valid-alice&user_id=admin", ws_url))
+            .await
+            .unwrap();
+
+        broadcast.broadcast_system(SystemEvent::new(SystemEventType::InstanceRunning, "bob", 8091));
+        let message = tokio::time::timeout(Duration::from_secs(5), admin.next())
+            .await
+            .expect("the admin hears of it")
+            .unwrap()
+            .unwrap();
+        let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(message["type"], "SystemEvent");
+
+        alice.send(Message::Text("ping".to_string())).await.unwrap();
+        let next = alice.next().await.unwrap().unwrap();
+        assert_eq!(next, Message::Text("pong".to_string()), "alice heard nothing before her pong");
+    }
+}
@@ -1,6 +1,13 @@
 use std::env;
 use serde::Deserialize;
 
+use common::logging::LogFormat;
+
+use crate::{api::key_repository::MasterKey, pocketbase_manager::binary};
+
+/// `config.toml` and `<VAR>_FILE` secrets beneath the environment
+pub mod file;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub server: ServerConfig,
@@ -9,15 +16,50 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub cors: CorsConfig,
     pub pocketbase: PocketBaseConfig,
+    pub email: EmailConfig,
+    pub integrations: IntegrationsConfig,
+    pub rate_limits: RateLimitConfig,
+    pub features: FeaturesConfig,
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub port: u16,
     pub host: String,
+    /// Whether `/metrics` serves Prometheus metrics or answers 404
+    pub metrics_enabled: bool,
+    /// Whether `/api/openapi.json` and `/api/docs` are served or answer 404
+    pub openapi_enabled: bool,
+    /// Seconds in-flight requests get to finish after a shutdown signal
+    pub shutdown_grace_secs: u64,
+    /// Largest request body accepted, in bytes
+    pub body_limit_bytes: usize,
+    /// Largest body accepted by the backup upload and instance proxy routes
+    pub upload_body_limit_bytes: usize,
+    /// Seconds a request may take before it is answered 408
+    pub request_timeout_secs: u64,
+    /// Seconds the backup upload and instance proxy routes may take
+    pub long_request_timeout_secs: u64,
+    /// Where browsers reach `/queue_updates` when a proxy serves it elsewhere
+    pub public_ws_url: Option<String>,
+    /// Whether unmatched `GET`s are answered from the frontend bundle
+    pub serve_frontend: bool,
+    /// The Dioxus `dist` directory to serve
+    pub frontend_dist_path: String,
+    /// PEM certificate chain to serve HTTPS with, leaf first; plain HTTP when unset
+    pub tls_cert_path: Option<String>,
+    /// PEM private key for `tls_cert_path`
+    pub tls_key_path: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+impl ServerConfig {
+    /// The certificate and key paths, when both are set
+    pub fn tls_paths(&self) -> Option<(&str, &str)> {
+        Some((self.tls_cert_path.as_deref()?, self.tls_key_path.as_deref()?))
+    }
+}
+
+#[derive(Clone)]
 pub struct DatabaseConfig {
     pub url: String,
     pub admin_email: String,
@@ -25,95 +67,922 @@ pub struct DatabaseConfig {
     pub user_db_base_path: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct SecurityConfig {
     pub master_key: String,
+    /// Master key stored API keys were encrypted under before the last rotation
+    pub master_key_previous: Option<String>,
     pub jwt_secret: String,
+    /// Shared secret the worker presents to `/internal` routes, which refuse
+    /// every call while it is unset
+    pub internal_api_token: Option<String>,
+    /// Bearer token Prometheus must present to scrape `/metrics`; open when unset
+    pub metrics_token: Option<String>,
     pub pb_encryption_key: String,
+    /// Seconds a validated bearer token is trusted without asking PocketBase again
+    pub auth_cache_ttl_secs: u64,
+    /// Most validated tokens kept in the cache at once
+    pub auth_cache_max_entries: usize,
+    /// Key id stamped into the `kid` header of tokens signed with `jwt_secret`
+    pub jwt_kid: String,
+    /// Retired `(kid, secret)` pairs still accepted when verifying tokens
+    pub jwt_previous_secrets: Vec<(String, String)>,
+    /// Seconds a backend-issued token stays valid
+    pub jwt_expiry_secs: u64,
+    /// Seconds after expiry a token can still be exchanged at `/auth/refresh`
+    pub jwt_refresh_window_secs: u64,
+    /// Seconds after login beyond which refreshing is refused
+    pub jwt_max_session_secs: u64,
+    /// Keep accepting raw PocketBase tokens, validated upstream, during the JWT transition
+    pub accept_legacy_pb_tokens: bool,
+    /// Believe `X-Forwarded-For` / `X-Real-IP` when identifying clients for rate limits
+    pub trust_proxy_headers: bool,
+    /// Frontend page password reset links point at; the token is appended as `?token=`
+    pub password_reset_url: String,
+    /// Seconds a password reset link stays usable
+    pub password_reset_ttl_secs: u64,
+    /// Reset requests allowed per email address within the window
+    pub password_reset_max_per_email: u32,
+    /// Reset requests allowed per client IP within the window
+    pub password_reset_max_per_ip: u32,
+    /// Length of the password reset rate-limit window in seconds
+    pub password_reset_window_secs: u64,
+    /// Endpoint email verification links point at; the token is appended as `?token=`
+    pub email_verification_url: String,
+    /// Seconds an email verification link stays usable
+    pub email_verification_ttl_secs: u64,
+    /// Verification emails a user may resend within the window
+    pub verification_resend_max: u32,
+    /// Length of the verification resend rate-limit window in seconds
+    pub verification_resend_window_secs: u64,
+    /// Login attempts allowed per client IP and email within the window
+    pub login_max_attempts: u32,
+    /// Length of the sliding login rate-limit window in seconds
+    pub login_window_secs: u64,
+    /// Consecutive failed logins that lock an email
+    pub login_lockout_threshold: u32,
+    /// Seconds the first lockout lasts; each further lock in a row doubles it
+    pub login_lockout_secs: u64,
+    /// Lowercased emails treated as admins whatever their record says, for bootstrapping
+    pub admin_emails: Vec<String>,
+    /// Our Google OAuth2 callback, registered as the redirect URL with Google
+    pub oauth_google_redirect_url: String,
+    /// Frontend page OAuth2 logins return to, with `#token=...` or `#error=...`
+    pub oauth_frontend_url: String,
+    /// `SameSite` attribute of the session and CSRF cookies set by cookie-mode logins
+    pub session_cookie_same_site: SameSite,
+    /// Mark the session and CSRF cookies `Secure`; only disable for plain-HTTP development
+    pub session_cookie_secure: bool,
 }
 
-#[derive(Debug, Clone)]
+/// Stands in for a secret in debug output, showing only whether it's set
+fn redacted(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        "[redacted]"
+    }
+}
+
+impl std::fmt::Debug for DatabaseConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatabaseConfig")
+            .field("url", &self.url)
+            .field("admin_email", &self.admin_email)
+            .field("admin_password", &redacted(&self.admin_password))
+            .field("user_db_base_path", &self.user_db_base_path)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for SecurityConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // No `..`, so a new field can't reach the logs without a decision here
+        let SecurityConfig {
+            master_key,
+            master_key_previous,
+            jwt_secret,
+            internal_api_token,
+            metrics_token,
+            pb_encryption_key,
+            auth_cache_ttl_secs,
+            auth_cache_max_entries,
+            jwt_kid,
+            jwt_previous_secrets,
+            jwt_expiry_secs,
+            jwt_refresh_window_secs,
+            jwt_max_session_secs,
+            accept_legacy_pb_tokens,
+            trust_proxy_headers,
+            password_reset_url,
+            password_reset_ttl_secs,
+            password_reset_max_per_email,
+            password_reset_max_per_ip,
+            password_reset_window_secs,
+            email_verification_url,
+            email_verification_ttl_secs,
+            verification_resend_max,
+            verification_resend_window_secs,
+            login_max_attempts,
+            login_window_secs,
+            login_lockout_threshold,
+            login_lockout_secs,
+            admin_emails,
+            oauth_google_redirect_url,
+            oauth_frontend_url,
+            session_cookie_same_site,
+            session_cookie_secure,
+        } = self;
+        let previous_kids: Vec<(&str, &str)> = jwt_previous_secrets
+            .iter()
+            .map(|(kid, secret)| (kid.as_str(), redacted(secret)))
+            .collect();
+        f.debug_struct("SecurityConfig")
+            .field("master_key", &redacted(master_key))
+            .field("master_key_previous", &master_key_previous.as_deref().map(redacted))
+            .field("jwt_secret", &redacted(jwt_secret))
+            .field("internal_api_token", &internal_api_token.as_deref().map(redacted))
+            .field("metrics_token", &metrics_token.as_deref().map(redacted))
+            .field("pb_encryption_key", &redacted(pb_encryption_key))
+            .field("auth_cache_ttl_secs", auth_cache_ttl_secs)
+            .field("auth_cache_max_entries", auth_cache_max_entries)
+            .field("jwt_kid", jwt_kid)
+            .field("jwt_previous_secrets", &previous_kids)
+            .field("jwt_expiry_secs", jwt_expiry_secs)
+            .field("jwt_refresh_window_secs", jwt_refresh_window_secs)
+            .field("jwt_max_session_secs", jwt_max_session_secs)
+            .field("accept_legacy_pb_tokens", accept_legacy_pb_tokens)
+            .field("trust_proxy_headers", trust_proxy_headers)
+            .field("password_reset_url", password_reset_url)
+            .field("password_reset_ttl_secs", password_reset_ttl_secs)
+            .field("password_reset_max_per_email", password_reset_max_per_email)
+            .field("password_reset_max_per_ip", password_reset_max_per_ip)
+            .field("password_reset_window_secs", password_reset_window_secs)
+            .field("email_verification_url", email_verification_url)
+            .field("email_verification_ttl_secs", email_verification_ttl_secs)
+            .field("verification_resend_max", verification_resend_max)
+            .field("verification_resend_window_secs", verification_resend_window_secs)
+            .field("login_max_attempts", login_max_attempts)
+            .field("login_window_secs", login_window_secs)
+            .field("login_lockout_threshold", login_lockout_threshold)
+            .field("login_lockout_secs", login_lockout_secs)
+            .field("admin_emails", admin_emails)
+            .field("oauth_google_redirect_url", oauth_google_redirect_url)
+            .field("oauth_frontend_url", oauth_frontend_url)
+            .field("session_cookie_same_site", session_cookie_same_site)
+            .field("session_cookie_secure", session_cookie_secure)
+            .finish()
+    }
+}
+
+/// The `SameSite` cookie attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Parse `JWT_PREVIOUS_SECRETS`, a comma-separated list of `kid:secret` pairs
+    pub fn parse_previous_secrets(value: &str) -> Result<Vec<(String, String)>, ConfigError> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((kid, secret)) if !kid.trim().is_empty() && !secret.is_empty() => {
+                    Ok((kid.trim().to_string(), secret.to_string()))
+                }
+                _ => Err(ConfigError::InvalidJwtKeys(format!(
+                    "JWT_PREVIOUS_SECRETS entry '{}' must be kid:secret",
+                    entry.split(':').next().unwrap_or_default()
+                ))),
+            })
+            .collect()
+    }
+
+    /// Parse `SESSION_COOKIE_SAMESITE`, one of `Strict`, `Lax` or `None` in any case
+    pub fn parse_same_site(value: &str) -> Result<SameSite, ConfigError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(ConfigError::InvalidSameSite(value.to_string())),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct LoggingConfig {
     pub level: String,
+    /// Pretty lines or JSON objects, from `LOG_FORMAT`
+    pub format: LogFormat,
+    /// Levels of the per-request completion events, by status class
+    pub requests: RequestLogLevels,
+    /// Where panics and 500s are reported; unset reports nothing
+    pub sentry_dsn: Option<String>,
+}
+
+impl std::fmt::Debug for LoggingConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The DSN carries the project's key
+        let LoggingConfig { level, format, requests, sentry_dsn } = self;
+        f.debug_struct("LoggingConfig")
+            .field("level", level)
+            .field("format", format)
+            .field("requests", requests)
+            .field("sentry_dsn", &sentry_dsn.as_deref().map(redacted))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLogLevels {
+    pub success: tracing::Level,
+    pub client_error: tracing::Level,
+    pub server_error: tracing::Level,
+}
+
+impl Default for RequestLogLevels {
+    fn default() -> Self {
+        Self {
+            success: tracing::Level::INFO,
+            client_error: tracing::Level::WARN,
+            server_error: tracing::Level::ERROR,
+        }
+    }
+}
+
+impl RequestLogLevels {
+    /// The level a request finishing with `status` is logged at
+    pub fn for_status(&self, status: u16) -> tracing::Level {
+        match status {
+            500.. => self.server_error,
+            400..=499 => self.client_error,
+            _ => self.success,
+        }
+    }
+
+    pub fn parse_level(var: &'static str, value: &str) -> Result<tracing::Level, ConfigError> {
+        value
+            .trim()
+            .parse()
+            .map_err(|_| ConfigError::InvalidLogLevel(var, value.to_string()))
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailConfig {
+    /// Base URL of the smtp-service that delivers outgoing email
+    pub smtp_service_url: String,
+    /// Bearer token the smtp-service takes from the backend
+    pub smtp_service_api_key: Option<String>,
+}
+
+impl std::fmt::Debug for EmailConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailConfig")
+            .field("smtp_service_url", &self.smtp_service_url)
+            .field("smtp_service_api_key", &self.smtp_service_api_key.as_deref().map(redacted))
+            .finish()
+    }
+}
+
+/// Requests each client may make per minute, by kind of route
+///
+/// Clients are counted by user id when they present a valid session token
+/// and by IP otherwise; 0 turns a limit off.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Requests to `/auth`
+    pub auth_per_minute: u32,
+    /// `GET`s under `/api`
+    pub read_per_minute: u32,
+    /// Other methods under `/api`, and webhooks
+    pub write_per_minute: u32,
+    /// Fathom webhook deliveries for one linked workspace, whoever sends them
+    pub webhook_per_minute: u32,
+    /// Clients tracked per limit before the least recently seen is forgotten
+    pub max_clients: usize,
+}
+
+/// OAuth providers the backend can sign users in with
+pub const OAUTH_PROVIDERS: &[&str] = &["google"];
+
+/// What the deployment offers its users, also told to the frontend
+#[derive(Debug, Clone)]
+pub struct FeaturesConfig {
+    /// Whether `/auth/register` creates accounts or answers 403
+    pub registration_enabled: bool,
+    /// Providers offered for sign-in, from [`OAUTH_PROVIDERS`]
+    pub oauth_providers: Vec<String>,
+    /// Refuse queue changes with 503 while the deployment is worked on
+    pub maintenance_mode: bool,
+    /// Meetings each user may have queued at once; 0 for no limit
+    pub max_queue_items_per_user: usize,
+}
+
+impl FeaturesConfig {
+    pub fn oauth_enabled(&self, provider: &str) -> bool {
+        self.oauth_providers.iter().any(|enabled| enabled == provider)
+    }
+
+    /// Parse `OAUTH_PROVIDERS`, comma-separated and possibly empty
+    pub fn parse_oauth_providers(value: &str) -> Result<Vec<String>, ConfigError> {
+        let mut providers = Vec::new();
+        for provider in value.split(',').map(|p| p.trim().to_lowercase()).filter(|p| !p.is_empty()) {
+            if !OAUTH_PROVIDERS.contains(&provider.as_str()) {
+                return Err(ConfigError::InvalidValue {
+                    var: "OAUTH_PROVIDERS",
+                    value: value.to_string(),
+                    expected: "a comma-separated list of google",
+                });
+            }
+            if !providers.contains(&provider) {
+                providers.push(provider);
+            }
+        }
+        Ok(providers)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegrationsConfig {
+    /// Base URL of the Fathom external API
+    pub fathom_api_url: String,
+    /// Base URL of the Loom API
+    pub loom_api_url: String,
+    /// How long a key validation waits for the service to answer
+    pub key_validation_timeout: std::time::Duration,
+    /// Key validations each user may run per service within the window
+    pub key_validation_max: u32,
+    pub key_validation_window_secs: u64,
+    /// Keys expiring within this many days get a renewal reminder
+    pub key_expiry_warning_days: i64,
+    /// Settings page reminder emails link to
+    pub key_settings_url: String,
+    /// How long a cached meeting listing is served without asking Fathom
+    pub meetings_cache_ttl: std::time::Duration,
+    /// How long past its TTL a cached listing is still served while it is
+    /// refreshed in the background
+    pub meetings_cache_stale: std::time::Duration,
+    /// Fathom requests each user may make per minute, a burst of as many
+    /// allowed
+    pub fathom_requests_per_minute: u32,
+    /// Tries per Fathom request, retrying 429s and 503s
+    pub fathom_max_attempts: u32,
+    /// Shortest gap between two forced refreshes of a user's meetings
+    pub meetings_refresh_cooldown: std::time::Duration,
 }
 
 #[derive(Debug, Clone)]
 pub struct CorsConfig {
+    /// Origins allowed to call the API, or a single "*" to mirror any origin in development
     pub origins: Vec<String>,
 }
 
+impl CorsConfig {
+    /// Whether `origin`, as a browser sends it, is one of [`Self::origins`]
+    pub fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    /// Parse the comma-separated CORS_ORIGINS list into serialized origins
+    pub fn parse_origins(value: &str) -> Result<Vec<String>, ConfigError> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                if entry == "*" {
+                    return Ok(entry.to_string());
+                }
+                let invalid = || ConfigError::InvalidCorsOrigin(entry.to_string());
+                let url = reqwest::Url::parse(entry).map_err(|_| invalid())?;
+                let bare = url.username().is_empty()
+                    && url.password().is_none()
+                    && url.path() == "/"
+                    && url.query().is_none()
+                    && url.fragment().is_none();
+                if !matches!(url.scheme(), "http" | "https") || url.host().is_none() || !bare {
+                    return Err(invalid());
+                }
+                Ok(url.origin().ascii_serialization())
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PocketBaseConfig {
     pub base_port: u16,
     pub binary_path: String,
     pub user_dbs_path: String,
+    /// First port (inclusive) handed out to user instances
+    pub port_range_start: u16,
+    /// Last port (inclusive) handed out to user instances
+    pub port_range_end: u16,
+    /// Address user instances bind to and are reached on
+    pub bind_host: String,
+    /// Seconds instances get to exit after SIGTERM on shutdown
+    pub shutdown_grace_secs: u64,
+    /// Nominal seconds between health checks of each instance
+    pub health_check_interval_secs: u64,
+    /// Download the pinned PocketBase release when the binary is missing
+    pub auto_download: bool,
+    /// Directory downloaded binaries are installed into
+    pub managed_bin_dir: String,
+    /// Expected SHA-256 of the release archive, overriding the embedded table
+    pub binary_sha256: Option<String>,
+    /// Instances kept pre-started for new users; 0 disables the pool
+    pub warm_pool_size: usize,
+}
+
+impl PocketBaseConfig {
+    /// Check the instance port range is usable alongside the backend itself
+    pub fn validate(&self, backend_port: u16) -> Result<(), ConfigError> {
+        if self.port_range_start == 0 {
+            return Err(ConfigError::InvalidPortRange(
+                "PB_PORT_RANGE_START must be greater than 0".to_string(),
+            ));
+        }
+        if self.port_range_start > self.port_range_end {
+            return Err(ConfigError::InvalidPortRange(format!(
+                "start {} is greater than end {}",
+                self.port_range_start, self.port_range_end
+            )));
+        }
+        if (self.port_range_start..=self.port_range_end).contains(&backend_port) {
+            return Err(ConfigError::InvalidPortRange(format!(
+                "{}-{} overlaps the backend port {}",
+                self.port_range_start, self.port_range_end, backend_port
+            )));
+        }
+        if self.health_check_interval_secs == 0 {
+            return Err(ConfigError::InvalidHealthInterval);
+        }
+        if self.bind_host.trim().is_empty() {
+            return Err(ConfigError::InvalidBindHost("PB_BIND_HOST cannot be empty".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Invalid PocketBase port range: {0}")]
+    InvalidPortRange(String),
+
+    #[error("Invalid PocketBase bind host: {0}")]
+    InvalidBindHost(String),
+
+    #[error("PB_HEALTH_CHECK_INTERVAL_SECS must be greater than 0")]
+    InvalidHealthInterval,
+
+    #[error("Invalid JWT signing keys: {0}")]
+    InvalidJwtKeys(String),
+
+    #[error("SESSION_COOKIE_SAMESITE must be Strict, Lax or None, not '{0}'")]
+    InvalidSameSite(String),
+
+    #[error("{0} must be 32 bytes encoded as base64")]
+    InvalidMasterKey(&'static str),
+
+    #[error("{0} must be set")]
+    Missing(&'static str),
+
+    #[error("Config file {0}: {1}")]
+    ConfigFile(String, String),
+
+    #[error("{0}_FILE {1} could not be read: {2}")]
+    SecretFile(&'static str, String, String),
+
+    #[error("{var} must be {expected}, not '{value}'")]
+    InvalidValue {
+        var: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+
+    #[error("JWT_SECRET must be at least 32 characters, not {0}")]
+    WeakJwtSecret(usize),
+
+    #[error("DATABASE_URL '{0}' must be an http(s) URL such as http://pb_global:8090")]
+    InvalidDatabaseUrl(String),
+
+    #[error("PocketBase binary '{0}' was not found; set PB_BINARY_PATH to it or PB_AUTO_DOWNLOAD=true")]
+    MissingBinary(String),
+
+    #[error("{0} must be one of trace, debug, info, warn or error, not '{1}'")]
+    InvalidLogLevel(&'static str, String),
+
+    #[error("CORS_ORIGINS entry '{0}' must be \"*\" or an http(s) origin such as https://app.example.com")]
+    InvalidCorsOrigin(String),
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+    /// Load and validate the configuration from the process environment
+    pub fn from_env() -> Result<Self, ConfigReport> {
+        Self::load(|name| env::var(name).ok())
+    }
+
+    /// Load from the variables `env` gives, over any secret files and config
+    /// file they name
+    pub fn load(env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigReport> {
+        let (sources, mut errors) = file::Sources::load(&env);
+        match Self::from_lookup(|name| env(name).or_else(|| sources.get(name))) {
+            Ok(config) if errors.is_empty() => Ok(config),
+            Ok(_) => Err(ConfigReport { errors }),
+            Err(report) => {
+                errors.extend(report.errors);
+                Err(ConfigReport { errors })
+            }
+        }
+    }
+
+    /// Load and validate the configuration from `lookup`, reporting every
+    /// unparseable or invalid value at once instead of stopping at the first
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigReport> {
+        let mut env = Env { lookup, errors: Vec::new() };
+
         let server = ServerConfig {
-            port: env::var("BACKEND_PORT")
-                .unwrap_or_else(|_| "3000".to_string())
-                .parse()?,
-            host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
+            port: env.parse("BACKEND_PORT", 3000),
+            host: env.var("HOST").unwrap_or_else(|| "0.0.0.0".to_string()),
+            metrics_enabled: env.parse("METRICS_ENABLED", true),
+            openapi_enabled: env.parse("OPENAPI_ENABLED", false),
+            shutdown_grace_secs: env.parse("SHUTDOWN_GRACE_SECS", 30),
+            body_limit_bytes: env.parse("BODY_LIMIT_BYTES", 2 * 1024 * 1024),
+            upload_body_limit_bytes: env.parse("UPLOAD_BODY_LIMIT_BYTES", 256 * 1024 * 1024),
+            request_timeout_secs: env.parse("REQUEST_TIMEOUT_SECS", 30),
+            long_request_timeout_secs: env.parse("LONG_REQUEST_TIMEOUT_SECS", 300),
+            public_ws_url: env.var("PUBLIC_WS_URL").filter(|url| !url.trim().is_empty()),
+            serve_frontend: env.parse("SERVE_FRONTEND", true),
+            frontend_dist_path: env.var("FRONTEND_DIST_PATH")
+                .unwrap_or_else(|| "frontend/dist".to_string()),
+            tls_cert_path: env.var("TLS_CERT_PATH").filter(|path| !path.trim().is_empty()),
+            tls_key_path: env.var("TLS_KEY_PATH").filter(|path| !path.trim().is_empty()),
         };
 
         let database = DatabaseConfig {
-            url: env::var("DATABASE_URL")
-                .or_else(|_| env::var("GLOBAL_PB_URL"))
-                .unwrap_or_else(|_| "http://pb_global:8090".to_string()),
-            admin_email: env::var("PB_ADMIN_EMAIL")
-                .or_else(|_| env::var("GLOBAL_PB_ADMIN_EMAIL"))
-                .unwrap_or_else(|_| "admin@example.com".to_string()),
-            admin_password: env::var("PB_ADMIN_PASSWORD")
-                .or_else(|_| env::var("GLOBAL_PB_ADMIN_PW"))
-                .expect("PB_ADMIN_PASSWORD or GLOBAL_PB_ADMIN_PW must be set"),
-            user_db_base_path: env::var("USER_DB_BASE_PATH")
-                .unwrap_or_else(|_| "/app/user_dbs".to_string()),
+            url: env.var("DATABASE_URL")
+                .or_else(|| env.var("GLOBAL_PB_URL"))
+                .unwrap_or_else(|| "http://pb_global:8090".to_string()),
+            admin_email: env.var("PB_ADMIN_EMAIL")
+                .or_else(|| env.var("GLOBAL_PB_ADMIN_EMAIL"))
+                .unwrap_or_else(|| "admin@example.com".to_string()),
+            admin_password: env.var("PB_ADMIN_PASSWORD")
+                .or_else(|| env.var("GLOBAL_PB_ADMIN_PW"))
+                .unwrap_or_default(),
+            user_db_base_path: env.var("USER_DB_BASE_PATH")
+                .unwrap_or_else(|| "/app/user_dbs".to_string()),
         };
 
         let security = SecurityConfig {
-            master_key: env::var("MASTER_KEY")
-                .or_else(|_| env::var("AES_MASTER_KEY"))
-                .expect("MASTER_KEY or AES_MASTER_KEY must be set"),
-            jwt_secret: env::var("JWT_SECRET")
-                .expect("JWT_SECRET must be set"),
-            pb_encryption_key: env::var("PB_ENCRYPTION_KEY")
-                .expect("PB_ENCRYPTION_KEY must be set"),
+            master_key: env.var("MASTER_KEY")
+                .or_else(|| env.var("AES_MASTER_KEY"))
+                .unwrap_or_default(),
+            master_key_previous: env.var("MASTER_KEY_PREVIOUS").filter(|key| !key.is_empty()),
+            jwt_secret: env.var("JWT_SECRET")
+                .unwrap_or_default(),
+            internal_api_token: env.var("INTERNAL_API_TOKEN").filter(|token| !token.is_empty()),
+            metrics_token: env.var("METRICS_TOKEN").filter(|token| !token.is_empty()),
+            pb_encryption_key: env.var("PB_ENCRYPTION_KEY")
+                .unwrap_or_default(),
+            auth_cache_ttl_secs: env.parse("AUTH_CACHE_TTL_SECS", 60),
+            auth_cache_max_entries: env.parse("AUTH_CACHE_MAX_ENTRIES", 10000),
+            jwt_kid: env.var("JWT_KID").unwrap_or_else(|| "primary".to_string()),
+            jwt_previous_secrets: env.parse_with(
+                "JWT_PREVIOUS_SECRETS",
+                Vec::new(),
+                SecurityConfig::parse_previous_secrets,
+            ),
+            jwt_expiry_secs: env.parse("JWT_EXPIRY_SECS", 3600),
+            jwt_refresh_window_secs: env.parse("JWT_REFRESH_WINDOW_SECS", 86400),
+            jwt_max_session_secs: env.parse("JWT_MAX_SESSION_SECS", 604800),
+            accept_legacy_pb_tokens: env.parse("ACCEPT_LEGACY_PB_TOKENS", true),
+            trust_proxy_headers: env.parse("TRUST_PROXY_HEADERS", false),
+            password_reset_url: env.var("PASSWORD_RESET_URL")
+                .unwrap_or_else(|| "http://localhost:8080/reset-password".to_string()),
+            password_reset_ttl_secs: env.parse("PASSWORD_RESET_TTL_SECS", 1800),
+            password_reset_max_per_email: env.parse("PASSWORD_RESET_MAX_PER_EMAIL", 3),
+            password_reset_max_per_ip: env.parse("PASSWORD_RESET_MAX_PER_IP", 20),
+            password_reset_window_secs: env.parse("PASSWORD_RESET_WINDOW_SECS", 3600),
+            email_verification_url: env.var("EMAIL_VERIFICATION_URL")
+                .unwrap_or_else(|| "http://localhost:3000/auth/verify_email".to_string()),
+            email_verification_ttl_secs: env.parse("EMAIL_VERIFICATION_TTL_SECS", 86400),
+            verification_resend_max: env.parse("VERIFICATION_RESEND_MAX", 3),
+            verification_resend_window_secs: env.parse("VERIFICATION_RESEND_WINDOW_SECS", 3600),
+            login_max_attempts: env.parse("LOGIN_MAX_ATTEMPTS", 10),
+            login_window_secs: env.parse("LOGIN_WINDOW_SECS", 300),
+            login_lockout_threshold: env.parse("LOGIN_LOCKOUT_THRESHOLD", 5),
+            login_lockout_secs: env.parse("LOGIN_LOCKOUT_SECS", 900),
+            admin_emails: env.var("ADMIN_EMAILS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|email| email.trim().to_lowercase())
+                .filter(|email| !email.is_empty())
+                .collect(),
+            oauth_google_redirect_url: env.var("OAUTH_GOOGLE_REDIRECT_URL")
+                .unwrap_or_else(|| "http://localhost:3000/auth/oauth/google/callback".to_string()),
+            oauth_frontend_url: env.var("OAUTH_FRONTEND_URL")
+                .unwrap_or_else(|| "http://localhost:8080/oauth/callback".to_string()),
+            session_cookie_same_site: env.parse_with(
+                "SESSION_COOKIE_SAMESITE",
+                SameSite::Strict,
+                SecurityConfig::parse_same_site,
+            ),
+            session_cookie_secure: env.parse("SESSION_COOKIE_SECURE", true),
         };
 
+        let mut request_level = |var: &'static str, default: tracing::Level| {
+            env.parse_with(var, default, |value| RequestLogLevels::parse_level(var, value))
+        };
+        let defaults = RequestLogLevels::default();
         let logging = LoggingConfig {
-            level: env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+            requests: RequestLogLevels {
+                success: request_level("REQUEST_LOG_SUCCESS_LEVEL", defaults.success),
+                client_error: request_level("REQUEST_LOG_CLIENT_ERROR_LEVEL", defaults.client_error),
+                server_error: request_level("REQUEST_LOG_SERVER_ERROR_LEVEL", defaults.server_error),
+            },
+            level: env.var("RUST_LOG").unwrap_or_else(|| "info".to_string()),
+            format: env.parse_with("LOG_FORMAT", LogFormat::default(), |value| {
+                value.parse().map_err(|_| ConfigError::InvalidValue {
+                    var: "LOG_FORMAT",
+                    value: value.to_string(),
+                    expected: "json or pretty",
+                })
+            }),
+            sentry_dsn: env.var("SENTRY_DSN").filter(|dsn| !dsn.trim().is_empty()),
         };
 
-        let cors_origins = env::var("CORS_ORIGINS")
-            .unwrap_or_else(|_| "http://localhost:8080,http://localhost:3000".to_string());
+        let cors_origins = env.var("CORS_ORIGINS")
+            .unwrap_or_else(|| "http://localhost:8080,http://localhost:3000".to_string());
+        // Bad entries are kept as given for validate() to report
         let cors = CorsConfig {
-            origins: cors_origins
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect(),
+            origins: CorsConfig::parse_origins(&cors_origins).unwrap_or_else(|_| {
+                cors_origins
+                    .split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect()
+            }),
         };
 
+        let base_port: u16 = env.parse("PB_BASE_PORT", 9000);
         let pocketbase = PocketBaseConfig {
-            base_port: env::var("PB_BASE_PORT")
-                .unwrap_or_else(|_| "9000".to_string())
-                .parse()?,
-            binary_path: env::var("PB_BINARY_PATH")
-                .unwrap_or_else(|_| "pocketbase".to_string()),
-            user_dbs_path: env::var("PB_USER_DBS_PATH")
-                .unwrap_or_else(|_| "./user_dbs".to_string()),
+            base_port,
+            binary_path: env.var("PB_BINARY_PATH")
+                .unwrap_or_else(|| "pocketbase".to_string()),
+            user_dbs_path: env.var("PB_USER_DBS_PATH")
+                .unwrap_or_else(|| "./user_dbs".to_string()),
+            port_range_start: env.parse("PB_PORT_RANGE_START", base_port),
+            port_range_end: env.parse("PB_PORT_RANGE_END", base_port.saturating_add(999)),
+            bind_host: env.var("PB_BIND_HOST")
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            shutdown_grace_secs: env.parse("PB_SHUTDOWN_GRACE_SECS", 10),
+            health_check_interval_secs: env.parse("PB_HEALTH_CHECK_INTERVAL_SECS", 30),
+            auto_download: env.parse("PB_AUTO_DOWNLOAD", false),
+            managed_bin_dir: env.var("PB_MANAGED_BIN_DIR")
+                .unwrap_or_else(|| "./pb_bin".to_string()),
+            binary_sha256: env.var("PB_BINARY_SHA256"),
+            warm_pool_size: env.parse("PB_WARM_POOL_SIZE", 0),
         };
 
-        Ok(Config {
+        let email = EmailConfig {
+            smtp_service_url: env.var("SMTP_SERVICE_URL")
+                .unwrap_or_else(|| "http://localhost:3001".to_string()),
+            smtp_service_api_key: env.var("SMTP_SERVICE_API_KEY").filter(|key| !key.is_empty()),
+        };
+
+        let integrations = IntegrationsConfig {
+            fathom_api_url: env.var("FATHOM_API_URL")
+                .unwrap_or_else(|| "https://api.fathom.ai/external/v1".to_string()),
+            loom_api_url: env.var("LOOM_API_URL")
+                .unwrap_or_else(|| "https://api.loom.com/v1".to_string()),
+            key_validation_timeout: std::time::Duration::from_secs(
+                env.parse("KEY_VALIDATION_TIMEOUT_SECS", 10),
+            ),
+            key_validation_max: env.parse("KEY_VALIDATION_MAX", 10),
+            key_validation_window_secs: env.parse("KEY_VALIDATION_WINDOW_SECS", 3600),
+            key_expiry_warning_days: env.parse("KEY_EXPIRY_WARNING_DAYS", 7),
+            key_settings_url: env.var("KEY_SETTINGS_URL")
+                .unwrap_or_else(|| "http://localhost:8080/settings".to_string()),
+            meetings_cache_ttl: std::time::Duration::from_secs(
+                env.parse("MEETINGS_CACHE_TTL_SECS", 600),
+            ),
+            meetings_cache_stale: std::time::Duration::from_secs(
+                env.parse("MEETINGS_CACHE_STALE_SECS", 3600),
+            ),
+            fathom_requests_per_minute: env.parse("FATHOM_REQUESTS_PER_MINUTE", 60),
+            fathom_max_attempts: env.parse("FATHOM_MAX_ATTEMPTS", 3),
+            meetings_refresh_cooldown: std::time::Duration::from_secs(
+                env.parse("MEETINGS_REFRESH_COOLDOWN_SECS", 30),
+            ),
+        };
+
+        let rate_limits = RateLimitConfig {
+            auth_per_minute: env.parse("RATE_LIMIT_AUTH_PER_MINUTE", 20),
+            read_per_minute: env.parse("RATE_LIMIT_READ_PER_MINUTE", 120),
+            write_per_minute: env.parse("RATE_LIMIT_WRITE_PER_MINUTE", 60),
+            webhook_per_minute: env.parse("RATE_LIMIT_WEBHOOK_PER_MINUTE", 30),
+            max_clients: env.parse("RATE_LIMIT_MAX_CLIENTS", 10000),
+        };
+
+        let features = FeaturesConfig {
+            registration_enabled: env.parse("REGISTRATION_ENABLED", true),
+            oauth_providers: env.parse_with(
+                "OAUTH_PROVIDERS",
+                vec!["google".to_string()],
+                FeaturesConfig::parse_oauth_providers,
+            ),
+            maintenance_mode: env.parse("MAINTENANCE_MODE", false),
+            max_queue_items_per_user: env.parse("MAX_QUEUE_ITEMS_PER_USER", 0),
+        };
+
+        let config = Config {
             server,
             database,
             security,
             logging,
             cors,
             pocketbase,
+            email,
+            integrations,
+            rate_limits,
+            features,
+        };
+        let mut errors = env.errors;
+        if let Err(report) = config.validate() {
+            errors.extend(report.errors);
+        }
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigReport { errors })
+        }
+    }
+
+    /// Check the loaded values make sense together, collecting every problem
+    pub fn validate(&self) -> Result<(), ConfigReport> {
+        let mut errors = Vec::new();
+        let security = &self.security;
+
+        if self.database.admin_password.is_empty() {
+            errors.push(ConfigError::Missing("PB_ADMIN_PASSWORD (or GLOBAL_PB_ADMIN_PW)"));
+        }
+        if security.master_key.is_empty() {
+            errors.push(ConfigError::Missing("MASTER_KEY (or AES_MASTER_KEY)"));
+        } else if MasterKey::parse(&security.master_key).is_err() {
+            errors.push(ConfigError::InvalidMasterKey("MASTER_KEY"));
+        }
+        if security.master_key_previous.as_deref().is_some_and(|key| MasterKey::parse(key).is_err()) {
+            errors.push(ConfigError::InvalidMasterKey("MASTER_KEY_PREVIOUS"));
+        }
+        if security.jwt_secret.is_empty() {
+            errors.push(ConfigError::Missing("JWT_SECRET"));
+        } else if security.jwt_secret.len() < MIN_JWT_SECRET_LEN {
+            errors.push(ConfigError::WeakJwtSecret(security.jwt_secret.len()));
+        }
+        if security.pb_encryption_key.is_empty() {
+            errors.push(ConfigError::Missing("PB_ENCRYPTION_KEY"));
+        }
+
+        let database_url = reqwest::Url::parse(&self.database.url).ok();
+        if !database_url.is_some_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some()) {
+            errors.push(ConfigError::InvalidDatabaseUrl(self.database.url.clone()));
+        }
+
+        if !self.pocketbase.auto_download && binary::locate(&self.pocketbase.binary_path).is_none() {
+            errors.push(ConfigError::MissingBinary(self.pocketbase.binary_path.clone()));
+        }
+        if let Err(e) = self.pocketbase.validate(self.server.port) {
+            errors.push(e);
+        }
+
+        for origin in &self.cors.origins {
+            if let Err(e) = CorsConfig::parse_origins(origin) {
+                errors.push(e);
+            }
+        }
+
+        // A zero limit or timeout would refuse every request
+        let server = &self.server;
+        for (var, value, expected) in [
+            ("BODY_LIMIT_BYTES", server.body_limit_bytes as u64, "a positive number of bytes"),
+            ("UPLOAD_BODY_LIMIT_BYTES", server.upload_body_limit_bytes as u64, "a positive number of bytes"),
+            ("REQUEST_TIMEOUT_SECS", server.request_timeout_secs, "a positive number of seconds"),
+            ("LONG_REQUEST_TIMEOUT_SECS", server.long_request_timeout_secs, "a positive number of seconds"),
+        ] {
+            if value == 0 {
+                errors.push(ConfigError::InvalidValue { var, value: "0".to_string(), expected });
+            }
+        }
+
+        if let Some(url) = &server.public_ws_url {
+            let valid = reqwest::Url::parse(url)
+                .is_ok_and(|parsed| matches!(parsed.scheme(), "ws" | "wss") && parsed.has_host());
+            if !valid {
+                errors.push(ConfigError::InvalidValue {
+                    var: "PUBLIC_WS_URL",
+                    value: url.clone(),
+                    expected: "a ws:// or wss:// URL",
+                });
+            }
+        }
+
+        // Half a TLS setup would silently serve plain HTTP
+        match (&server.tls_cert_path, &server.tls_key_path) {
+            (Some(_), None) => errors.push(ConfigError::Missing("TLS_KEY_PATH")),
+            (None, Some(_)) => errors.push(ConfigError::Missing("TLS_CERT_PATH")),
+            _ => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigReport { errors })
+        }
+    }
+}
+
+/// Shortest `JWT_SECRET` accepted; HS256 keys shorter than the hash are weak
+pub const MIN_JWT_SECRET_LEN: usize = 32;
+
+/// Every problem found while loading the configuration
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub errors: Vec<ConfigError>,
+}
+
+impl std::fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problems = if self.errors.len() == 1 { "problem" } else { "problems" };
+        write!(f, "Invalid configuration ({} {}):", self.errors.len(), problems)?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigReport {}
+
+/// Variables read for [`Config::from_lookup`], with the values that didn't parse
+struct Env<F> {
+    lookup: F,
+    errors: Vec<ConfigError>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn var(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+    }
+
+    /// `name` parsed as a `T`, or `default` when unset or invalid
+    fn parse<T: std::str::FromStr>(&mut self, name: &'static str, default: T) -> T {
+        self.parse_with(name, default, |value| {
+            value.trim().parse().map_err(|_| ConfigError::InvalidValue {
+                var: name,
+                value: value.to_string(),
+                expected: expected::<T>(),
+            })
         })
     }
+
+    fn parse_with<T>(
+        &mut self,
+        name: &'static str,
+        default: T,
+        parse: impl FnOnce(&str) -> Result<T, ConfigError>,
+    ) -> T {
+        match self.var(name) {
+            Some(value) => parse(&value).unwrap_or_else(|e| {
+                self.errors.push(e);
+                default
+            }),
+            None => default,
+        }
+    }
+}
+
+/// What a value of type `T` looks like, for error messages
+fn expected<T>() -> &'static str {
+    match std::any::type_name::<T>() {
+        "bool" => "true or false",
+        "u16" => "a whole number from 0 to 65535",
+        _ => "a whole number",
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -146,3 +1015,356 @@ impl EnvConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pocketbase_config(start: u16, end: u16) -> PocketBaseConfig {
+        PocketBaseConfig {
+            base_port: start,
+            binary_path: "pocketbase".to_string(),
+            user_dbs_path: "./user_dbs".to_string(),
+            port_range_start: start,
+            port_range_end: end,
+            bind_host: "127.0.0.1".to_string(),
+            shutdown_grace_secs: 10,
+            health_check_interval_secs: 30,
+            auto_download: false,
+            managed_bin_dir: "./pb_bin".to_string(),
+            binary_sha256: None,
+            warm_pool_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_pocketbase_range_validation() {
+        assert!(pocketbase_config(9000, 9999).validate(3000).is_ok());
+        assert!(pocketbase_config(9000, 9000).validate(3000).is_ok());
+
+        assert!(matches!(
+            pocketbase_config(9100, 9000).validate(3000),
+            Err(ConfigError::InvalidPortRange(_))
+        ));
+        assert!(matches!(
+            pocketbase_config(0, 100).validate(3000),
+            Err(ConfigError::InvalidPortRange(_))
+        ));
+        assert!(matches!(
+            pocketbase_config(2900, 3100).validate(3000),
+            Err(ConfigError::InvalidPortRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_pocketbase_bind_host_validation() {
+        let mut config = pocketbase_config(9000, 9999);
+        config.bind_host = "  ".to_string();
+        assert!(matches!(config.validate(3000), Err(ConfigError::InvalidBindHost(_))));
+
+        let mut config = pocketbase_config(9000, 9999);
+        config.health_check_interval_secs = 0;
+        assert!(matches!(config.validate(3000), Err(ConfigError::InvalidHealthInterval)));
+    }
+
+    #[test]
+    fn test_previous_jwt_secrets_parsing() {
+        assert!(SecurityConfig::parse_previous_secrets("").unwrap().is_empty());
+        assert_eq!(
+            SecurityConfig::parse_previous_secrets("2024q1:old:secret, 2023q4:older").unwrap(),
+            vec![
+                ("2024q1".to_string(), "old:secret".to_string()),
+                ("2023q4".to_string(), "older".to_string()),
+            ]
+        );
+        assert!(matches!(
+            SecurityConfig::parse_previous_secrets("no-secret"),
+            Err(ConfigError::InvalidJwtKeys(_))
+        ));
+    }
+
+    #[test]
+    fn test_same_site_parsing() {
+        assert_eq!(SecurityConfig::parse_same_site("Strict").unwrap(), SameSite::Strict);
+        assert_eq!(SecurityConfig::parse_same_site(" lax ").unwrap(), SameSite::Lax);
+        assert_eq!(SecurityConfig::parse_same_site("NONE").unwrap().as_str(), "None");
+        assert!(matches!(
+            SecurityConfig::parse_same_site("sometimes"),
+            Err(ConfigError::InvalidSameSite(_))
+        ));
+    }
+
+    #[test]
+    fn test_request_log_levels() {
+        let levels = RequestLogLevels::default();
+        assert_eq!(levels.for_status(200), tracing::Level::INFO);
+        assert_eq!(levels.for_status(304), tracing::Level::INFO);
+        assert_eq!(levels.for_status(404), tracing::Level::WARN);
+        assert_eq!(levels.for_status(503), tracing::Level::ERROR);
+
+        assert_eq!(
+            RequestLogLevels::parse_level("REQUEST_LOG_CLIENT_ERROR_LEVEL", " Debug ").unwrap(),
+            tracing::Level::DEBUG
+        );
+        assert!(matches!(
+            RequestLogLevels::parse_level("REQUEST_LOG_CLIENT_ERROR_LEVEL", "loud"),
+            Err(ConfigError::InvalidLogLevel("REQUEST_LOG_CLIENT_ERROR_LEVEL", _))
+        ));
+    }
+
+    #[test]
+    fn test_cors_origin_parsing() {
+        assert_eq!(
+            CorsConfig::parse_origins("http://localhost:8080, https://App.example.com/,").unwrap(),
+            vec!["http://localhost:8080".to_string(), "https://app.example.com".to_string()]
+        );
+        assert_eq!(CorsConfig::parse_origins("https://app.example.com:443").unwrap(), vec!["https://app.example.com".to_string()]);
+        assert_eq!(CorsConfig::parse_origins(" * ").unwrap(), vec!["*".to_string()]);
+
+        for bad in [
+            "localhost:8080",
+            "ftp://files.example.com",
+            "https://app.example.com/path",
+            "https://app.example.com?x=1",
+            "https://user@app.example.com",
+            "not a url",
+        ] {
+            assert!(
+                matches!(CorsConfig::parse_origins(bad), Err(ConfigError::InvalidCorsOrigin(entry)) if entry == bad),
+                "{} should be rejected",
+                bad
+            );
+        }
+    }
+
+    const MASTER_KEY: &str = "dGVzdC1tYXN0ZXIta2V5LTMyLWJ5dGVzLWxvbmchISE=";
+
+    /// Just enough variables for a configuration that loads cleanly
+    fn valid_env() -> std::collections::HashMap<&'static str, String> {
+        let binary = std::env::current_exe().unwrap().display().to_string();
+        [
+            ("PB_ADMIN_PASSWORD", "admin-password".to_string()),
+            ("MASTER_KEY", MASTER_KEY.to_string()),
+            ("JWT_SECRET", "a-jwt-secret-that-is-long-enough!".to_string()),
+            ("PB_ENCRYPTION_KEY", "pb-encryption-key".to_string()),
+            ("PB_BINARY_PATH", binary),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn load(overrides: &[(&'static str, &str)]) -> Result<Config, ConfigReport> {
+        let mut vars = valid_env();
+        for (name, value) in overrides {
+            vars.insert(*name, value.to_string());
+        }
+        Config::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    fn errors(overrides: &[(&'static str, &str)]) -> Vec<ConfigError> {
+        load(overrides).expect_err("configuration should be rejected").errors
+    }
+
+    #[test]
+    fn test_valid_configuration_loads() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.server.port, 3000);
+        assert_eq!(config.database.url, "http://pb_global:8090");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_secrets_are_required() {
+        let errors = Config::from_lookup(|_| None).unwrap_err().errors;
+        let missing: Vec<_> = errors
+            .iter()
+            .filter_map(|e| match e {
+                ConfigError::Missing(var) => Some(*var),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            missing,
+            ["PB_ADMIN_PASSWORD (or GLOBAL_PB_ADMIN_PW)", "MASTER_KEY (or AES_MASTER_KEY)", "JWT_SECRET", "PB_ENCRYPTION_KEY"]
+        );
+    }
+
+    #[test]
+    fn test_master_keys_must_be_32_bytes() {
+        assert!(matches!(
+            errors(&[("MASTER_KEY", "c2hvcnQ=")]).as_slice(),
+            [ConfigError::InvalidMasterKey("MASTER_KEY")]
+        ));
+        assert!(matches!(
+            errors(&[("MASTER_KEY_PREVIOUS", "not base64!")]).as_slice(),
+            [ConfigError::InvalidMasterKey("MASTER_KEY_PREVIOUS")]
+        ));
+        assert!(load(&[("MASTER_KEY_PREVIOUS", MASTER_KEY)]).is_ok());
+    }
+
+    #[test]
+    fn test_jwt_secret_length() {
+        assert!(matches!(
+            errors(&[("JWT_SECRET", "too-short")]).as_slice(),
+            [ConfigError::WeakJwtSecret(9)]
+        ));
+        assert!(load(&[("JWT_SECRET", &"x".repeat(MIN_JWT_SECRET_LEN))]).is_ok());
+    }
+
+    #[test]
+    fn test_database_url_must_parse() {
+        for bad in ["pb_global:8090", "ftp://pb_global", "not a url"] {
+            assert!(
+                matches!(errors(&[("DATABASE_URL", bad)]).as_slice(), [ConfigError::InvalidDatabaseUrl(url)] if url == bad),
+                "{} should be rejected",
+                bad
+            );
+        }
+        assert!(load(&[("DATABASE_URL", "https://pb.example.com")]).is_ok());
+    }
+
+    #[test]
+    fn test_binary_must_exist_unless_downloaded() {
+        assert!(matches!(
+            errors(&[("PB_BINARY_PATH", "/nonexistent/pocketbase")]).as_slice(),
+            [ConfigError::MissingBinary(path)] if path == "/nonexistent/pocketbase"
+        ));
+        assert!(load(&[("PB_BINARY_PATH", "/nonexistent/pocketbase"), ("PB_AUTO_DOWNLOAD", "true")]).is_ok());
+    }
+
+    #[test]
+    fn test_ports_must_not_collide() {
+        assert!(matches!(
+            errors(&[("BACKEND_PORT", "9500")]).as_slice(),
+            [ConfigError::InvalidPortRange(_)]
+        ));
+        assert!(load(&[("BACKEND_PORT", "9500"), ("PB_BASE_PORT", "10000")]).is_ok());
+    }
+
+    #[test]
+    fn test_cors_origins_must_be_urls() {
+        assert!(matches!(
+            errors(&[("CORS_ORIGINS", "http://localhost:8080,localhost:3000")]).as_slice(),
+            [ConfigError::InvalidCorsOrigin(origin)] if origin == "localhost:3000"
+        ));
+    }
+
+    #[test]
+    fn test_limits_and_timeouts_must_be_positive() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.server.body_limit_bytes, 2 * 1024 * 1024);
+        assert_eq!(config.server.request_timeout_secs, 30);
+
+        let errors = errors(&[("BODY_LIMIT_BYTES", "0"), ("LONG_REQUEST_TIMEOUT_SECS", "0")]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "BODY_LIMIT_BYTES must be a positive number of bytes, not '0'");
+        assert_eq!(errors[1].to_string(), "LONG_REQUEST_TIMEOUT_SECS must be a positive number of seconds, not '0'");
+    }
+
+    #[test]
+    fn test_unparseable_values_are_reported() {
+        let errors = errors(&[("BACKEND_PORT", "70000"), ("PB_AUTO_DOWNLOAD", "yes")]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].to_string(),
+            "BACKEND_PORT must be a whole number from 0 to 65535, not '70000'"
+        );
+        assert_eq!(errors[1].to_string(), "PB_AUTO_DOWNLOAD must be true or false, not 'yes'");
+    }
+
+    #[test]
+    fn test_feature_settings() {
+        let config = load(&[
+            ("OAUTH_PROVIDERS", " Google, google "),
+            ("MAX_QUEUE_ITEMS_PER_USER", "20"),
+            ("PUBLIC_WS_URL", "wss://example.com/ws"),
+        ])
+        .unwrap();
+        assert_eq!(config.features.oauth_providers, ["google"]);
+        assert!(config.features.registration_enabled);
+        assert_eq!(config.features.max_queue_items_per_user, 20);
+        assert_eq!(config.server.public_ws_url.as_deref(), Some("wss://example.com/ws"));
+        assert!(load(&[("OAUTH_PROVIDERS", "")]).unwrap().features.oauth_providers.is_empty());
+
+        let errors = errors(&[("OAUTH_PROVIDERS", "google,github"), ("PUBLIC_WS_URL", "https://example.com/ws")]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].to_string(),
+            "OAUTH_PROVIDERS must be a comma-separated list of google, not 'google,github'"
+        );
+        assert_eq!(errors[1].to_string(), "PUBLIC_WS_URL must be a ws:// or wss:// URL, not 'https://example.com/ws'");
+    }
+
+    #[test]
+    fn test_log_format_must_be_known() {
+        let errors = errors(&[("LOG_FORMAT", "xml")]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "LOG_FORMAT must be json or pretty, not 'xml'");
+        assert_eq!(load(&[("LOG_FORMAT", "JSON")]).unwrap().logging.format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_tls_needs_both_paths() {
+        let config = load(&[("TLS_CERT_PATH", "/etc/tls/chain.pem"), ("TLS_KEY_PATH", "/etc/tls/key.pem")]).unwrap();
+        assert_eq!(config.server.tls_paths(), Some(("/etc/tls/chain.pem", "/etc/tls/key.pem")));
+        assert_eq!(load(&[("TLS_CERT_PATH", "")]).unwrap().server.tls_paths(), None);
+
+        assert_eq!(errors(&[("TLS_KEY_PATH", "/etc/tls/key.pem")])[0].to_string(), "TLS_CERT_PATH must be set");
+        let errors = errors(&[("TLS_CERT_PATH", "/etc/tls/chain.pem")]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "TLS_KEY_PATH must be set");
+    }
+
+    #[test]
+    fn test_report_lists_every_problem() {
+        let report = load(&[
+            ("JWT_SECRET", "short"),
+            ("DATABASE_URL", "nowhere"),
+            ("CORS_ORIGINS", "example.com"),
+            ("LOGIN_MAX_ATTEMPTS", "many"),
+            ("SESSION_COOKIE_SAMESITE", "sideways"),
+        ])
+        .unwrap_err();
+        assert_eq!(report.errors.len(), 5);
+
+        let text = report.to_string();
+        assert!(text.starts_with("Invalid configuration (5 problems):\n  - "));
+        for error in &report.errors {
+            assert!(text.contains(&format!("\n  - {}", error)), "{} is not listed", error);
+        }
+        assert!(text.contains("LOGIN_MAX_ATTEMPTS must be a whole number, not 'many'"));
+        assert!(text.contains("JWT_SECRET must be at least 32 characters"));
+    }
+
+    #[test]
+    fn test_debug_output_redacts_secrets() {
+        let config = load(&[
+            ("MASTER_KEY_PREVIOUS", MASTER_KEY),
+            ("JWT_PREVIOUS_SECRETS", "2024q1:old-jwt-secret"),
+            ("INTERNAL_API_TOKEN", "internal-token"),
+            ("METRICS_TOKEN", "metrics-token"),
+            ("SENTRY_DSN", "https://sentry-key@o1.ingest.sentry.io/2"),
+        ])
+        .unwrap();
+        let output = format!("{:?}", config);
+
+        for secret in [
+            "admin-password",
+            MASTER_KEY,
+            "a-jwt-secret-that-is-long-enough!",
+            "pb-encryption-key",
+            "old-jwt-secret",
+            "internal-token",
+            "metrics-token",
+            "sentry-key",
+        ] {
+            assert!(!output.contains(secret), "{} leaked into {}", secret, output);
+        }
+        assert!(output.contains("jwt_secret: \"[redacted]\""));
+        assert!(output.contains("metrics_token: Some(\"[redacted]\")"));
+        assert!(output.contains("(\"2024q1\", \"[redacted]\")"));
+        // Everything else is shown as is
+        assert!(output.contains("url: \"http://pb_global:8090\""));
+        assert!(output.contains("port: 3000"));
+    }
+}
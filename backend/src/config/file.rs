@@ -0,0 +1,387 @@
+//! Settings from `config.toml` and mounted secret files
+//!
+//! The file has one table per [`Config`](super::Config) section, with keys
+//! named after the struct fields (durations in seconds, lists as arrays).
+//! Every setting stands for the environment variable that also sets it, and
+//! a set variable always wins, so existing deployments keep working. Secrets
+//! can also be read from the file named by `<VAR>_FILE`, as Docker and
+//! Kubernetes mount them.
+
+use std::{collections::HashMap, path::Path};
+
+use super::ConfigError;
+
+/// Variable naming the config file; `./config.toml` is used when it exists
+pub const CONFIG_FILE_VAR: &str = "CONFIG_FILE";
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Variables holding credentials, which may come from `<VAR>_FILE`
+pub const SECRET_VARS: &[&str] = &[
+    "PB_ADMIN_PASSWORD",
+    "MASTER_KEY",
+    "MASTER_KEY_PREVIOUS",
+    "JWT_SECRET",
+    "JWT_PREVIOUS_SECRETS",
+    "INTERNAL_API_TOKEN",
+    "METRICS_TOKEN",
+    "PB_ENCRYPTION_KEY",
+    "SMTP_SERVICE_API_KEY",
+];
+
+/// Each file setting and the environment variable it stands for
+const SETTINGS: &[(&str, &str)] = &[
+    ("server.port", "BACKEND_PORT"),
+    ("server.host", "HOST"),
+    ("server.metrics_enabled", "METRICS_ENABLED"),
+    ("server.openapi_enabled", "OPENAPI_ENABLED"),
+    ("server.shutdown_grace_secs", "SHUTDOWN_GRACE_SECS"),
+    ("server.body_limit_bytes", "BODY_LIMIT_BYTES"),
+    ("server.upload_body_limit_bytes", "UPLOAD_BODY_LIMIT_BYTES"),
+    ("server.request_timeout_secs", "REQUEST_TIMEOUT_SECS"),
+    ("server.long_request_timeout_secs", "LONG_REQUEST_TIMEOUT_SECS"),
+    ("server.public_ws_url", "PUBLIC_WS_URL"),
+    ("server.serve_frontend", "SERVE_FRONTEND"),
+    ("server.frontend_dist_path", "FRONTEND_DIST_PATH"),
+    ("server.tls_cert_path", "TLS_CERT_PATH"),
+    ("server.tls_key_path", "TLS_KEY_PATH"),
+    ("database.url", "DATABASE_URL"),
+    ("database.admin_email", "PB_ADMIN_EMAIL"),
+    ("database.admin_password", "PB_ADMIN_PASSWORD"),
+    ("database.user_db_base_path", "USER_DB_BASE_PATH"),
+    ("security.master_key", "MASTER_KEY"),
+    ("security.master_key_previous", "MASTER_KEY_PREVIOUS"),
+    ("security.jwt_secret", "JWT_SECRET"),
+    ("security.internal_api_token", "INTERNAL_API_TOKEN"),
+    ("security.metrics_token", "METRICS_TOKEN"),
+    ("security.pb_encryption_key", "PB_ENCRYPTION_KEY"),
+    ("security.auth_cache_ttl_secs", "AUTH_CACHE_TTL_SECS"),
+    ("security.auth_cache_max_entries", "AUTH_CACHE_MAX_ENTRIES"),
+    ("security.jwt_kid", "JWT_KID"),
+    ("security.jwt_previous_secrets", "JWT_PREVIOUS_SECRETS"),
+    ("security.jwt_expiry_secs", "JWT_EXPIRY_SECS"),
+    ("security.jwt_refresh_window_secs", "JWT_REFRESH_WINDOW_SECS"),
+    ("security.jwt_max_session_secs", "JWT_MAX_SESSION_SECS"),
+    ("security.accept_legacy_pb_tokens", "ACCEPT_LEGACY_PB_TOKENS"),
+    ("security.trust_proxy_headers", "TRUST_PROXY_HEADERS"),
+    ("security.password_reset_url", "PASSWORD_RESET_URL"),
+    ("security.password_reset_ttl_secs", "PASSWORD_RESET_TTL_SECS"),
+    ("security.password_reset_max_per_email", "PASSWORD_RESET_MAX_PER_EMAIL"),
+    ("security.password_reset_max_per_ip", "PASSWORD_RESET_MAX_PER_IP"),
+    ("security.password_reset_window_secs", "PASSWORD_RESET_WINDOW_SECS"),
+    ("security.email_verification_url", "EMAIL_VERIFICATION_URL"),
+    ("security.email_verification_ttl_secs", "EMAIL_VERIFICATION_TTL_SECS"),
+    ("security.verification_resend_max", "VERIFICATION_RESEND_MAX"),
+    ("security.verification_resend_window_secs", "VERIFICATION_RESEND_WINDOW_SECS"),
+    ("security.login_max_attempts", "LOGIN_MAX_ATTEMPTS"),
+    ("security.login_window_secs", "LOGIN_WINDOW_SECS"),
+    ("security.login_lockout_threshold", "LOGIN_LOCKOUT_THRESHOLD"),
+    ("security.login_lockout_secs", "LOGIN_LOCKOUT_SECS"),
+    ("security.admin_emails", "ADMIN_EMAILS"),
+    ("security.oauth_google_redirect_url", "OAUTH_GOOGLE_REDIRECT_URL"),
+    ("security.oauth_frontend_url", "OAUTH_FRONTEND_URL"),
+    ("security.session_cookie_same_site", "SESSION_COOKIE_SAMESITE"),
+    ("security.session_cookie_secure", "SESSION_COOKIE_SECURE"),
+    ("logging.level", "RUST_LOG"),
+    ("logging.format", "LOG_FORMAT"),
+    ("logging.requests.success", "REQUEST_LOG_SUCCESS_LEVEL"),
+    ("logging.requests.client_error", "REQUEST_LOG_CLIENT_ERROR_LEVEL"),
+    ("logging.requests.server_error", "REQUEST_LOG_SERVER_ERROR_LEVEL"),
+    ("logging.sentry_dsn", "SENTRY_DSN"),
+    ("cors.origins", "CORS_ORIGINS"),
+    ("pocketbase.base_port", "PB_BASE_PORT"),
+    ("pocketbase.binary_path", "PB_BINARY_PATH"),
+    ("pocketbase.user_dbs_path", "PB_USER_DBS_PATH"),
+    ("pocketbase.port_range_start", "PB_PORT_RANGE_START"),
+    ("pocketbase.port_range_end", "PB_PORT_RANGE_END"),
+    ("pocketbase.bind_host", "PB_BIND_HOST"),
+    ("pocketbase.shutdown_grace_secs", "PB_SHUTDOWN_GRACE_SECS"),
+    ("pocketbase.health_check_interval_secs", "PB_HEALTH_CHECK_INTERVAL_SECS"),
+    ("pocketbase.auto_download", "PB_AUTO_DOWNLOAD"),
+    ("pocketbase.managed_bin_dir", "PB_MANAGED_BIN_DIR"),
+    ("pocketbase.binary_sha256", "PB_BINARY_SHA256"),
+    ("pocketbase.warm_pool_size", "PB_WARM_POOL_SIZE"),
+    ("email.smtp_service_url", "SMTP_SERVICE_URL"),
+    ("email.smtp_service_api_key", "SMTP_SERVICE_API_KEY"),
+    ("integrations.fathom_api_url", "FATHOM_API_URL"),
+    ("integrations.loom_api_url", "LOOM_API_URL"),
+    ("integrations.key_validation_timeout", "KEY_VALIDATION_TIMEOUT_SECS"),
+    ("integrations.key_validation_max", "KEY_VALIDATION_MAX"),
+    ("integrations.key_validation_window_secs", "KEY_VALIDATION_WINDOW_SECS"),
+    ("integrations.key_expiry_warning_days", "KEY_EXPIRY_WARNING_DAYS"),
+    ("integrations.key_settings_url", "KEY_SETTINGS_URL"),
+    ("integrations.meetings_cache_ttl", "MEETINGS_CACHE_TTL_SECS"),
+    ("integrations.meetings_cache_stale", "MEETINGS_CACHE_STALE_SECS"),
+    ("integrations.fathom_requests_per_minute", "FATHOM_REQUESTS_PER_MINUTE"),
+    ("integrations.fathom_max_attempts", "FATHOM_MAX_ATTEMPTS"),
+    ("integrations.meetings_refresh_cooldown", "MEETINGS_REFRESH_COOLDOWN_SECS"),
+    ("rate_limits.auth_per_minute", "RATE_LIMIT_AUTH_PER_MINUTE"),
+    ("rate_limits.read_per_minute", "RATE_LIMIT_READ_PER_MINUTE"),
+    ("rate_limits.write_per_minute", "RATE_LIMIT_WRITE_PER_MINUTE"),
+    ("rate_limits.webhook_per_minute", "RATE_LIMIT_WEBHOOK_PER_MINUTE"),
+    ("rate_limits.max_clients", "RATE_LIMIT_MAX_CLIENTS"),
+    ("features.registration_enabled", "REGISTRATION_ENABLED"),
+    ("features.oauth_providers", "OAUTH_PROVIDERS"),
+    ("features.maintenance_mode", "MAINTENANCE_MODE"),
+    ("features.max_queue_items_per_user", "MAX_QUEUE_ITEMS_PER_USER"),
+];
+
+/// Values from secret files and the config file, by environment variable
+#[derive(Debug, Default)]
+pub struct Sources {
+    secrets: HashMap<&'static str, String>,
+    file: HashMap<&'static str, String>,
+}
+
+impl Sources {
+    /// Read the config file and secret files that `env` points at
+    pub fn load(env: &impl Fn(&str) -> Option<String>) -> (Self, Vec<ConfigError>) {
+        let mut sources = Sources::default();
+        let mut errors = Vec::new();
+
+        let path = env(CONFIG_FILE_VAR)
+            .or_else(|| Path::new(DEFAULT_CONFIG_FILE).is_file().then(|| DEFAULT_CONFIG_FILE.to_string()));
+        if let Some(path) = path {
+            match std::fs::read_to_string(&path) {
+                Ok(text) => match parse(&text) {
+                    Ok(file) => sources.file = file,
+                    Err(reasons) => errors.extend(
+                        reasons
+                            .into_iter()
+                            .map(|reason| ConfigError::ConfigFile(path.clone(), reason)),
+                    ),
+                },
+                Err(e) => errors.push(ConfigError::ConfigFile(path, e.to_string())),
+            }
+        }
+
+        for var in SECRET_VARS {
+            let Some(path) = env(&format!("{}_FILE", var)) else {
+                continue;
+            };
+            match std::fs::read_to_string(&path) {
+                Ok(secret) => {
+                    sources.secrets.insert(var, secret.trim_end_matches(['\r', '\n']).to_string());
+                }
+                Err(e) => errors.push(ConfigError::SecretFile(var, path, e.to_string())),
+            }
+        }
+
+        (sources, errors)
+    }
+
+    /// The value for `var` from a secret file, or else the config file
+    pub fn get(&self, var: &str) -> Option<String> {
+        self.secrets.get(var).or_else(|| self.file.get(var)).cloned()
+    }
+}
+
+/// Config file settings by the environment variable they stand for
+fn parse(text: &str) -> Result<HashMap<&'static str, String>, Vec<String>> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| vec![e.message().to_string()])?;
+    let mut settings = Vec::new();
+    flatten("", table, &mut settings);
+
+    let mut values = HashMap::new();
+    let mut problems = Vec::new();
+    for (key, value) in settings {
+        let Some((_, var)) = SETTINGS.iter().find(|(setting, _)| *setting == key) else {
+            problems.push(format!("unknown setting '{}'", key));
+            continue;
+        };
+        match as_env_value(value) {
+            Some(value) => {
+                values.insert(*var, value);
+            }
+            None => problems.push(format!("'{}' must be a string, number, boolean or list", key)),
+        }
+    }
+    if problems.is_empty() {
+        Ok(values)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Dotted keys for every non-table value, so `[logging.requests]` nests
+fn flatten(prefix: &str, table: toml::Table, out: &mut Vec<(String, toml::Value)>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+        match value {
+            toml::Value::Table(table) => flatten(&key, table, out),
+            value => out.push((key, value)),
+        }
+    }
+}
+
+/// A file value written the way its environment variable would be
+fn as_env_value(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                toml::Value::Array(_) | toml::Value::Table(_) => None,
+                item => as_env_value(item),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigReport, SameSite};
+    use std::io::Write;
+
+    /// A temporary file holding `contents`
+    fn temp_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn config_file(binary: &str) -> tempfile::NamedTempFile {
+        temp_file(&format!(
+            r#"
+[server]
+port = 4000
+
+[database]
+url = "http://pb.internal:8090"
+admin_password = "from-file"
+
+[security]
+master_key = "dGVzdC1tYXN0ZXIta2V5LTMyLWJ5dGVzLWxvbmchISE="
+jwt_secret = "a-jwt-secret-from-the-config-file"
+pb_encryption_key = "pb-encryption-key"
+admin_emails = ["Ops@example.com", "lou@example.com"]
+session_cookie_same_site = "Lax"
+
+[logging.requests]
+success = "debug"
+
+[cors]
+origins = ["https://app.example.com"]
+
+[pocketbase]
+binary_path = '{}'
+
+[integrations]
+meetings_cache_ttl = 120
+"#,
+            binary
+        ))
+    }
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, ConfigReport> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::load(|name| vars.get(name).cloned())
+    }
+
+    fn current_exe() -> String {
+        std::env::current_exe().unwrap().display().to_string()
+    }
+
+    #[test]
+    fn test_config_from_file_only() {
+        let file = config_file(&current_exe());
+        let config = load(&[(CONFIG_FILE_VAR, file.path().to_str().unwrap())]).unwrap();
+
+        assert_eq!(config.server.port, 4000);
+        assert_eq!(config.database.url, "http://pb.internal:8090");
+        assert_eq!(config.database.admin_password, "from-file");
+        assert_eq!(config.security.jwt_secret, "a-jwt-secret-from-the-config-file");
+        assert_eq!(config.security.admin_emails, ["ops@example.com", "lou@example.com"]);
+        assert_eq!(config.security.session_cookie_same_site, SameSite::Lax);
+        assert_eq!(config.logging.requests.success, tracing::Level::DEBUG);
+        assert_eq!(config.cors.origins, ["https://app.example.com"]);
+        assert_eq!(config.integrations.meetings_cache_ttl, std::time::Duration::from_secs(120));
+        // Untouched settings keep their defaults
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.pocketbase.port_range_start, 9000);
+    }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let file = config_file(&current_exe());
+        let config = load(&[
+            (CONFIG_FILE_VAR, file.path().to_str().unwrap()),
+            ("BACKEND_PORT", "5000"),
+            ("PB_ADMIN_PASSWORD", "from-env"),
+            ("CORS_ORIGINS", "https://other.example.com"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.server.port, 5000);
+        assert_eq!(config.database.admin_password, "from-env");
+        assert_eq!(config.cors.origins, ["https://other.example.com"]);
+        assert_eq!(config.database.url, "http://pb.internal:8090");
+    }
+
+    #[test]
+    fn test_secrets_from_files() {
+        let file = config_file(&current_exe());
+        let jwt_secret = temp_file("a-jwt-secret-from-a-mounted-file\n");
+        let password = temp_file("mounted-password");
+        let config = load(&[
+            (CONFIG_FILE_VAR, file.path().to_str().unwrap()),
+            ("JWT_SECRET_FILE", jwt_secret.path().to_str().unwrap()),
+            ("PB_ADMIN_PASSWORD_FILE", password.path().to_str().unwrap()),
+        ])
+        .unwrap();
+        assert_eq!(config.security.jwt_secret, "a-jwt-secret-from-a-mounted-file");
+        assert_eq!(config.database.admin_password, "mounted-password");
+
+        // The variable itself still wins over its file
+        let config = load(&[
+            (CONFIG_FILE_VAR, file.path().to_str().unwrap()),
+            ("PB_ADMIN_PASSWORD", "from-env"),
+            ("PB_ADMIN_PASSWORD_FILE", password.path().to_str().unwrap()),
+        ])
+        .unwrap();
+        assert_eq!(config.database.admin_password, "from-env");
+
+        let errors = load(&[
+            (CONFIG_FILE_VAR, file.path().to_str().unwrap()),
+            ("JWT_SECRET_FILE", "/nonexistent/jwt_secret"),
+        ])
+        .unwrap_err()
+        .errors;
+        assert!(matches!(
+            errors.as_slice(),
+            [ConfigError::SecretFile("JWT_SECRET", path, _)] if path == "/nonexistent/jwt_secret"
+        ));
+    }
+
+    #[test]
+    fn test_sample_config_file_parses() {
+        let settings = parse(include_str!("../../../config.sample.toml")).unwrap();
+        assert_eq!(settings["BACKEND_PORT"], "3000");
+        assert_eq!(settings["CORS_ORIGINS"], "http://localhost:8080,http://localhost:3000");
+        assert_eq!(settings["ADMIN_EMAILS"], "");
+    }
+
+    #[test]
+    fn test_bad_config_files_are_reported() {
+        let bad = temp_file("[server]\nport = 4000\nprot = 4001\n\n[cors]\norigins = [[\"nested\"]]\n");
+        let errors = load(&[(CONFIG_FILE_VAR, bad.path().to_str().unwrap())]).unwrap_err().errors;
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert!(messages.iter().any(|m| m.contains("unknown setting 'server.prot'")));
+        assert!(messages.iter().any(|m| m.contains("'cors.origins' must be")));
+
+        let errors = load(&[(CONFIG_FILE_VAR, "/nonexistent/config.toml")]).unwrap_err().errors;
+        assert!(matches!(&errors[0], ConfigError::ConfigFile(path, _) if path == "/nonexistent/config.toml"));
+
+        let unparseable = temp_file("[server\nport = ");
+        let errors = load(&[(CONFIG_FILE_VAR, unparseable.path().to_str().unwrap())]).unwrap_err().errors;
+        assert!(matches!(&errors[0], ConfigError::ConfigFile(..)));
+    }
+}
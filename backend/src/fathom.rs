@@ -0,0 +1,1402 @@
+//! Client for the Fathom external API
+//!
+//! Authenticates with one user's Fathom key, sent as `X-Api-Key`. Fathom
+//! pages its meeting listing with opaque cursors (`{limit, next_cursor,
+//! items}`), a cursor carrying on with the page size the listing began with.
+//! [`FathomClient::meetings_after`] passes a cursor through verbatim, one
+//! request per page. For a first page picked by `limit` and `offset`,
+//! [`FathomClient::list_meetings`] walks the cursors from the start instead,
+//! which also counts the whole listing so `total` is exact. Errors never
+//! carry the key: reqwest names the URL, not the headers. What Fathom sends
+//! is read into the types of [`common::fathom`], which the frontend shares.
+//!
+//! Fathom can filter the listing by creation date and invitee email; a
+//! [`MeetingFilter`] passes those on and applies the rest to each page as it
+//! arrives, so offsets and `total` count only the meetings kept.
+//!
+//! Listings a user asks for again while the first ask is still being fetched
+//! share that fetch, so two tabs opening Recordings cost one walk of the
+//! pages and not two.
+//!
+//! Transcripts answer 202 while Fathom is still transcribing. A finished one
+//! is parsed as it arrives rather than read into memory first, since a long
+//! meeting's runs to megabytes.
+
+use chrono::{DateTime, Utc};
+use common::fathom::{wire, Malformed};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt, TryStreamExt,
+};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE, RETRY_AFTER},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+use crate::api::rate_limit::{BucketStats, TokenBucket};
+
+pub use common::fathom::{
+    DownloadCheck, DownloadReason, FathomMeeting, FathomMeetingDetail, TranscriptSegment,
+};
+
+/// How long one Fathom request may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Pages walked at most for one listing, bounding the cost of a huge account
+const MAX_PAGES: usize = 100;
+
+/// How long to wait for a transcript in progress when Fathom doesn't say
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Wait before the first retry of a 429 or 503 that names none, doubled for
+/// each retry after
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait before a retry; when Fathom asks for more the caller is told
+/// to come back instead
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+/// How long callers wait on a listing another caller is fetching before it
+/// fails and the next caller starts afresh
+const FLIGHT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A listing being fetched, awaited by everyone who asked for it
+type Flight = Shared<BoxFuture<'static, Result<MeetingsPage, FathomError>>>;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum FathomError {
+    /// Fathom turned the key down with 401 or 403
+    #[error("Fathom rejected the API key")]
+    Unauthorized,
+
+    /// Fathom answered 429 on every attempt, naming how long to wait if it did
+    #[error("Fathom is rate limiting requests")]
+    RateLimited { retry_after: Option<Duration> },
+
+    /// The user's own budget of Fathom requests is spent, so none was sent
+    #[error("Too many Fathom requests; next one allowed in {retry_after:?}")]
+    Throttled { retry_after: Duration },
+
+    /// Fathom has no such recording for the key's account
+    #[error("Fathom has no such recording")]
+    NotFound,
+
+    #[error("Fathom took too long to answer")]
+    Timeout,
+
+    #[error("Fathom request failed: {0}")]
+    Request(String),
+
+    #[error("Fathom returned {0}")]
+    Status(u16),
+
+    #[error("Fathom sent an unexpected response: {0}")]
+    Malformed(String),
+}
+
+impl From<Malformed> for FathomError {
+    fn from(e: Malformed) -> Self {
+        FathomError::Malformed(e.0)
+    }
+}
+
+impl From<reqwest::Error> for FathomError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.is_timeout() {
+            true => FathomError::Timeout,
+            false => FathomError::Request(e.without_url().to_string()),
+        }
+    }
+}
+
+/// Paces each user's Fathom requests and retries those Fathom turns away
+///
+/// Every attempt, retries included, takes a token from the user's bucket.
+/// 429 and 503 answers are retried up to `max_attempts` in all, after the
+/// `Retry-After` they give or an exponential backoff.
+///
+/// It also holds the listings being fetched, by user and query, so a user
+/// asking twice at once spends their budget once.
+pub struct FathomLimiter {
+    bucket: TokenBucket,
+    requests_per_minute: u32,
+    max_attempts: u32,
+    backoff: Duration,
+    retries: AtomicU64,
+    upstream_rate_limited: AtomicU64,
+    flights: Mutex<HashMap<String, (Instant, Flight)>>,
+    flight_timeout: Duration,
+    shared_fetches: AtomicU64,
+}
+
+/// How a [`FathomLimiter`] has fared since startup
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FathomLimiterStats {
+    pub requests_per_minute: u32,
+    pub max_attempts: u32,
+    /// Requests let through and refused by the users' buckets
+    #[serde(flatten)]
+    pub bucket: BucketStats,
+    /// Attempts made again after a 429 or 503
+    pub retries: u64,
+    /// 429s Fathom answered
+    pub upstream_rate_limited: u64,
+    /// Listings answered with a fetch another caller had already started
+    pub shared_fetches: u64,
+}
+
+impl FathomLimiter {
+    pub fn new(requests_per_minute: u32, max_attempts: u32) -> Self {
+        Self {
+            bucket: TokenBucket::new(requests_per_minute, Duration::from_secs(60)),
+            requests_per_minute,
+            max_attempts: max_attempts.max(1),
+            backoff: RETRY_BACKOFF,
+            retries: AtomicU64::new(0),
+            upstream_rate_limited: AtomicU64::new(0),
+            flights: Mutex::new(HashMap::new()),
+            flight_timeout: FLIGHT_TIMEOUT,
+            shared_fetches: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait `backoff` before the first retry instead of the default
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Give up on a shared listing fetch after `timeout` instead of the
+    /// default
+    pub fn with_flight_timeout(mut self, timeout: Duration) -> Self {
+        self.flight_timeout = timeout;
+        self
+    }
+
+    pub async fn stats(&self) -> FathomLimiterStats {
+        FathomLimiterStats {
+            requests_per_minute: self.requests_per_minute,
+            max_attempts: self.max_attempts,
+            bucket: self.bucket.stats().await,
+            retries: self.retries.load(Ordering::Relaxed),
+            upstream_rate_limited: self.upstream_rate_limited.load(Ordering::Relaxed),
+            shared_fetches: self.shared_fetches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Which meetings a listing keeps; every filter set must match
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeetingFilter {
+    /// Case-insensitive substring of the title
+    pub title: Option<String>,
+    /// Recorded at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Recorded before
+    pub to: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of an invitee; an email address is left to
+    /// Fathom, which matches invitees by email exactly
+    pub participant: Option<String>,
+    pub min_duration_secs: Option<u32>,
+}
+
+/// Where a filter was applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppliedBy {
+    /// Fathom left the other meetings out
+    Fathom,
+    /// The meetings Fathom returned were filtered here
+    Local,
+}
+
+impl MeetingFilter {
+    pub fn is_empty(&self) -> bool {
+        self.applied().is_empty()
+    }
+
+    /// The filters set, by query parameter, and where each is applied
+    pub fn applied(&self) -> BTreeMap<String, AppliedBy> {
+        let participant = match self.participant_email() {
+            Some(_) => AppliedBy::Fathom,
+            None => AppliedBy::Local,
+        };
+        [
+            ("q", self.title.is_some(), AppliedBy::Local),
+            ("from", self.from.is_some(), AppliedBy::Fathom),
+            ("to", self.to.is_some(), AppliedBy::Fathom),
+            ("participant", self.participant.is_some(), participant),
+            (
+                "min_duration_secs",
+                self.min_duration_secs.is_some(),
+                AppliedBy::Local,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, set, _)| *set)
+        .map(|(name, _, by)| (name.to_string(), by))
+        .collect()
+    }
+
+    /// What identifies the filter in a cache key; empty when nothing is
+    /// filtered
+    pub fn cache_key(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let lower = |value: &Option<String>| value.as_deref().unwrap_or("").to_lowercase();
+        let at = |value: &Option<DateTime<Utc>>| value.map(|at| at.timestamp().to_string());
+        format!(
+            "q={:?}&from={}&to={}&participant={:?}&min={}",
+            lower(&self.title),
+            at(&self.from).unwrap_or_default(),
+            at(&self.to).unwrap_or_default(),
+            lower(&self.participant),
+            self.min_duration_secs
+                .map(|secs| secs.to_string())
+                .unwrap_or_default()
+        )
+    }
+
+    fn participant_email(&self) -> Option<&str> {
+        self.participant
+            .as_deref()
+            .filter(|participant| participant.contains('@'))
+    }
+
+    /// Query parameters asking Fathom to apply what it can
+    fn fathom_query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(from) = self.from {
+            query.push(("created_after", from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            query.push(("created_before", to.to_rfc3339()));
+        }
+        if let Some(email) = self.participant_email() {
+            query.push(("calendar_invitees[]", email.to_string()));
+        }
+        query
+    }
+
+    /// Whether `meeting` passes the filters Fathom doesn't apply
+    pub fn keeps(&self, meeting: &FathomMeeting) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        let title = self
+            .title
+            .as_deref()
+            .is_none_or(|title| contains(&meeting.title, title));
+        let participant = self.participant_email().is_some()
+            || self.participant.as_deref().is_none_or(|participant| {
+                meeting.participants.iter().any(|invitee| {
+                    contains(&invitee.display_name, participant)
+                        || invitee
+                            .email
+                            .as_deref()
+                            .is_some_and(|email| contains(email, participant))
+                })
+            });
+        let duration = self
+            .min_duration_secs
+            .is_none_or(|min| meeting.duration >= min);
+        title && participant && duration
+    }
+}
+
+/// What Fathom has of a recording's transcript
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transcript {
+    Ready(Vec<TranscriptSegment>),
+    /// Still being transcribed; worth asking again after `retry_after`
+    Processing {
+        retry_after: Duration,
+    },
+}
+
+/// One page of [`FathomMeeting`]s
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeetingsPage {
+    pub meetings: Vec<FathomMeeting>,
+    /// Meetings in all, known when the listing was walked from the start
+    #[serde(default)]
+    pub total: Option<u32>,
+    /// Where the page after this one starts, unless this is the last
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct FathomClient {
+    base_url: String,
+    api_key: String,
+    client: reqwest::Client,
+    /// The limiter and the user whose bucket requests take from
+    limiter: Option<(Arc<FathomLimiter>, String)>,
+    timeout: Duration,
+}
+
+impl std::fmt::Debug for FathomClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FathomClient")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FathomClient {
+    /// Client for the Fathom API at `base_url` using `api_key`
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            client: reqwest::Client::new(),
+            limiter: None,
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Give each request `timeout` instead of the default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Pace and retry requests with `limiter`, charged to `user_id`
+    pub fn with_limiter(mut self, limiter: Arc<FathomLimiter>, user_id: &str) -> Self {
+        self.limiter = Some((limiter, user_id.to_string()));
+        self
+    }
+
+    /// `limit` meetings after the first `offset`, newest first as Fathom
+    /// lists them, and how many there are in all
+    ///
+    /// When the meetings end where one of Fathom's pages does, they come
+    /// with the cursor of the next. Fathom is asked for pages of `limit`, so
+    /// that is the case whenever `offset` is a multiple of it.
+    ///
+    /// Only meetings `filter` keeps are counted.
+    pub async fn list_meetings(
+        &self,
+        limit: u32,
+        offset: u32,
+        filter: &MeetingFilter,
+    ) -> Result<MeetingsPage, FathomError> {
+        let query = format!("{}:{}:{}", limit, offset, filter.cache_key());
+        let (client, filter) = (self.clone(), filter.clone());
+        self.single_flight(query, async move {
+            client.walk_meetings(limit, offset, &filter).await
+        })
+        .await
+    }
+
+    async fn walk_meetings(
+        &self,
+        limit: u32,
+        offset: u32,
+        filter: &MeetingFilter,
+    ) -> Result<MeetingsPage, FathomError> {
+        let wanted = offset as usize..offset as usize + limit as usize;
+        let mut meetings = Vec::new();
+        let mut next_cursor = None;
+        let mut total = 0usize;
+        let mut cursor: Option<String> = None;
+        for _ in 0..MAX_PAGES {
+            let listing = self.page(cursor.as_deref(), Some(limit), filter).await?;
+            let next = listing.next_cursor.filter(|next| !next.is_empty());
+            let mut kept = Vec::new();
+            for item in listing.items {
+                let meeting = FathomMeeting::try_from(item)?;
+                if filter.keeps(&meeting) {
+                    kept.push(meeting);
+                }
+            }
+            let page = total..total + kept.len();
+            if page.start < wanted.end && page.end > wanted.start {
+                next_cursor = next.clone().filter(|_| page.end <= wanted.end);
+            }
+            for meeting in kept {
+                if wanted.contains(&total) {
+                    meetings.push(meeting);
+                }
+                total += 1;
+            }
+            match next {
+                Some(next) if cursor.as_deref() != Some(next.as_str()) => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(MeetingsPage {
+            meetings,
+            total: Some(total.min(u32::MAX as usize) as u32),
+            next_cursor,
+        })
+    }
+
+    /// The page of meetings `cursor` points at, passed to Fathom as is
+    ///
+    /// What `filter` leaves out of the page is dropped, so it may come back
+    /// short or empty with more pages to follow.
+    pub async fn meetings_after(
+        &self,
+        cursor: &str,
+        filter: &MeetingFilter,
+    ) -> Result<MeetingsPage, FathomError> {
+        let query = format!("cursor:{}:{}", cursor, filter.cache_key());
+        let (client, cursor, filter) = (self.clone(), cursor.to_string(), filter.clone());
+        self.single_flight(
+            query,
+            async move { client.page_after(&cursor, &filter).await },
+        )
+        .await
+    }
+
+    async fn page_after(
+        &self,
+        cursor: &str,
+        filter: &MeetingFilter,
+    ) -> Result<MeetingsPage, FathomError> {
+        let listing = self.page(Some(cursor), None, filter).await?;
+        let mut meetings = Vec::new();
+        for item in listing.items {
+            let meeting = FathomMeeting::try_from(item)?;
+            if filter.keeps(&meeting) {
+                meetings.push(meeting);
+            }
+        }
+        Ok(MeetingsPage {
+            meetings,
+            total: None,
+            next_cursor: listing.next_cursor.filter(|next| !next.is_empty()),
+        })
+    }
+
+    /// What `fetch` lists, or what the fetch already running for the same
+    /// user and `query` does
+    ///
+    /// Everyone waiting shares the outcome, errors included, and the first
+    /// to have it frees the slot. A fetch still running after the limiter's
+    /// flight timeout fails, and the next caller starts a new one. Without a
+    /// limiter there's no user to share with.
+    async fn single_flight(
+        &self,
+        query: String,
+        fetch: impl Future<Output = Result<MeetingsPage, FathomError>> + Send + 'static,
+    ) -> Result<MeetingsPage, FathomError> {
+        let Some((limiter, user_id)) = &self.limiter else {
+            return fetch.await;
+        };
+        let key = format!("{}\n{}", user_id, query);
+        let flight = {
+            let mut flights = limiter
+                .flights
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match flights.get(&key) {
+                Some((started, flight)) if started.elapsed() < limiter.flight_timeout => {
+                    limiter.shared_fetches.fetch_add(1, Ordering::Relaxed);
+                    flight.clone()
+                }
+                _ => {
+                    let timeout = limiter.flight_timeout;
+                    let flight = async move {
+                        tokio::time::timeout(timeout, fetch)
+                            .await
+                            .unwrap_or(Err(FathomError::Timeout))
+                    }
+                    .boxed()
+                    .shared();
+                    flights.insert(key.clone(), (Instant::now(), flight.clone()));
+                    flight
+                }
+            }
+        };
+        let result = flight.clone().await;
+        let mut flights = limiter
+            .flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if flights
+            .get(&key)
+            .is_some_and(|(_, current)| current.ptr_eq(&flight))
+        {
+            flights.remove(&key);
+        }
+        result
+    }
+
+    /// The recording `id` with its summary and download availability
+    pub async fn meeting(&self, id: &str) -> Result<FathomMeetingDetail, FathomError> {
+        let url = self.recording_url(id, None)?;
+        let payload: wire::Meeting = self.get_json(self.client.get(url)).await?;
+        Ok(FathomMeetingDetail::try_from(payload)?)
+    }
+
+    /// Whether the media of recording `id` can be fetched now
+    ///
+    /// Asks Fathom for the recording, then its media host for the signed
+    /// download URL's headers, without the key. Fathom's own rate limiting
+    /// and outages are errors; anything saying the recording can't be had
+    /// is a [`DownloadReason`].
+    pub async fn download_check(&self, id: &str) -> Result<DownloadCheck, FathomError> {
+        let url = self.recording_url(id, None)?;
+        let payload: wire::Meeting = match self.get_json(self.client.get(url)).await {
+            Ok(payload) => payload,
+            Err(FathomError::NotFound) => {
+                return Ok(DownloadCheck::refused(DownloadReason::NotFound))
+            }
+            Err(FathomError::Unauthorized) => {
+                return Ok(DownloadCheck::refused(DownloadReason::Forbidden))
+            }
+            Err(FathomError::Timeout) => {
+                return Ok(DownloadCheck::refused(DownloadReason::Timeout))
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(download_url) = payload.download_url() else {
+            return Ok(DownloadCheck::refused(DownloadReason::NoDownload));
+        };
+
+        let send = |request: reqwest::RequestBuilder| request.timeout(self.timeout).send();
+        let mut response = send(self.client.head(download_url)).await;
+        // Hosts that won't answer HEAD are asked for the first byte instead
+        if let Ok(answer) = &response {
+            if matches!(
+                answer.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            ) {
+                response = send(self.client.get(download_url).header(RANGE, "bytes=0-0")).await;
+            }
+        }
+        let response = match response {
+            Ok(response) => response,
+            Err(e) if e.is_timeout() => return Ok(DownloadCheck::refused(DownloadReason::Timeout)),
+            Err(_) => return Ok(DownloadCheck::refused(DownloadReason::Unavailable)),
+        };
+        let reason = match response.status() {
+            status if status.is_success() => DownloadReason::Available,
+            StatusCode::NOT_FOUND | StatusCode::GONE => DownloadReason::NotFound,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => DownloadReason::Forbidden,
+            _ => DownloadReason::Unavailable,
+        };
+        if reason != DownloadReason::Available {
+            return Ok(DownloadCheck::refused(reason));
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let size_bytes = match response.status() {
+            // `bytes 0-0/<size>`
+            StatusCode::PARTIAL_CONTENT => header(CONTENT_RANGE)
+                .and_then(|range| range.rsplit_once('/'))
+                .and_then(|(_, size)| size.parse().ok()),
+            _ => header(CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+        };
+        Ok(DownloadCheck {
+            downloadable: true,
+            reason,
+            size_bytes: size_bytes.or(payload.size_bytes()),
+            content_type: header(CONTENT_TYPE).map(str::to_string),
+        })
+    }
+
+    /// The transcript of recording `id`, or how long until it might be ready
+    pub async fn transcript(&self, id: &str) -> Result<Transcript, FathomError> {
+        let url = self.recording_url(id, Some("transcript"))?;
+        let response = self.send(self.client.get(url)).await?;
+        if response.status() == StatusCode::ACCEPTED {
+            let retry_after = retry_after(&response).unwrap_or(DEFAULT_RETRY_AFTER);
+            return Ok(Transcript::Processing { retry_after });
+        }
+
+        let body = response.bytes_stream().map_err(std::io::Error::other);
+        let reader = std::io::BufReader::new(SyncIoBridge::new(StreamReader::new(body)));
+        let payload: wire::Transcript =
+            tokio::task::spawn_blocking(move || serde_json::from_reader(reader))
+                .await
+                .map_err(|e| FathomError::Request(e.to_string()))?
+                .map_err(|e| match e.is_io() {
+                    true => FathomError::Request(e.to_string()),
+                    false => FathomError::Malformed(e.to_string()),
+                })?;
+        Ok(Transcript::Ready(payload.try_into()?))
+    }
+
+    /// `{base}/recordings/<id>`, then `/<sub>`, with `id` escaped as one segment
+    fn recording_url(&self, id: &str, sub: Option<&str>) -> Result<reqwest::Url, FathomError> {
+        let mut url = reqwest::Url::parse(&format!("{}/recordings", self.base_url))
+            .map_err(|e| FathomError::Request(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| FathomError::Request("the Fathom URL can't have a path".to_string()))?
+            .push(id)
+            .extend(sub);
+        Ok(url)
+    }
+
+    /// One page of Fathom's listing, from `cursor` or the start, narrowed by
+    /// what of `filter` Fathom applies
+    async fn page(
+        &self,
+        cursor: Option<&str>,
+        limit: Option<u32>,
+        filter: &MeetingFilter,
+    ) -> Result<wire::Listing, FathomError> {
+        let mut request = self
+            .client
+            .get(format!("{}/meetings", self.base_url))
+            .query(&filter.fathom_query());
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        self.get_json(request).await
+    }
+
+    /// Send `request` with the key and parse the JSON it answers
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, FathomError> {
+        let body = self.send(request).await?.bytes().await?;
+        serde_json::from_slice(&body).map_err(|e| FathomError::Malformed(e.to_string()))
+    }
+
+    /// Send `request` with the key, turning error statuses into errors
+    ///
+    /// With a limiter, each attempt takes a token and 429s and 503s are
+    /// retried.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, FathomError> {
+        let max_attempts = self
+            .limiter
+            .as_ref()
+            .map_or(1, |(limiter, _)| limiter.max_attempts);
+        let mut attempt = 1;
+        loop {
+            if let Some((limiter, user_id)) = &self.limiter {
+                limiter
+                    .bucket
+                    .take(user_id)
+                    .await
+                    .map_err(|retry_after| FathomError::Throttled { retry_after })?;
+            }
+            let this_attempt = request
+                .try_clone()
+                .ok_or_else(|| FathomError::Request("the request can't be sent".to_string()))?;
+            let response = this_attempt
+                .header("X-Api-Key", &self.api_key)
+                .timeout(self.timeout)
+                .send()
+                .await?;
+            let status = response.status();
+            match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    return Err(FathomError::Unauthorized)
+                }
+                StatusCode::NOT_FOUND => return Err(FathomError::NotFound),
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {}
+                status if !status.is_success() => return Err(FathomError::Status(status.as_u16())),
+                _ => return Ok(response),
+            }
+
+            let asked = retry_after(&response);
+            if let Some((limiter, _)) = &self.limiter {
+                if status == StatusCode::TOO_MANY_REQUESTS {
+                    limiter
+                        .upstream_rate_limited
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                let wait = asked.unwrap_or(limiter.backoff * 2u32.pow(attempt - 1));
+                if attempt < max_attempts && wait <= MAX_RETRY_WAIT {
+                    limiter.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                    continue;
+                }
+            }
+            return Err(match status {
+                StatusCode::TOO_MANY_REQUESTS => FathomError::RateLimited { retry_after: asked },
+                status => FathomError::Status(status.as_u16()),
+            });
+        }
+    }
+}
+
+/// The wait `response` asks for in seconds in its `Retry-After` header
+///
+/// Fathom names seconds; an HTTP date is treated as no header at all.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::spawn_server;
+    use axum::{
+        extract::{Path, Query},
+        http::{HeaderMap, Method, StatusCode},
+        response::{IntoResponse, Response},
+        routing::{any, get},
+        Json, Router,
+    };
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    const KEY: &str = "fathom-key-0123456789";
+
+    fn meeting(n: usize) -> Value {
+        json!({
+            "recording_id": 1000 + n,
+            "title": format!("Meeting {}", n),
+            "url": format!("https://fathom.video/calls/{}", n),
+            "recording_start_time": "2026-03-02T10:00:00Z",
+            "recording_end_time": "2026-03-02T10:30:00Z",
+            "calendar_invitees": [
+                { "name": "Alice Example", "email": "alice@example.com" },
+                { "name": null, "email": "bob@example.com" }
+            ]
+        })
+    }
+
+    /// Fathom lookalike holding `count` meetings, paged by the `limit` the
+    /// listing began with (3 by default), which its cursors carry on with
+    async fn mock_fathom(count: usize) -> String {
+        let handler = move |headers: HeaderMap, Query(query): Query<HashMap<String, String>>| async move {
+            if headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                != Some(KEY)
+            {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            let (start, page_size): (usize, usize) = match query.get("cursor") {
+                Some(cursor) => {
+                    let (start, size) = cursor.split_once(':').unwrap();
+                    (start.parse().unwrap(), size.parse().unwrap())
+                }
+                None => (
+                    0,
+                    query.get("limit").map_or(3, |limit| limit.parse().unwrap()),
+                ),
+            };
+            let end = (start + page_size).min(count);
+            let next_cursor = (end < count).then(|| format!("{}:{}", end, page_size));
+            Json(json!({
+                "limit": page_size,
+                "next_cursor": next_cursor,
+                "items": (start..end).map(meeting).collect::<Vec<_>>(),
+            }))
+            .into_response()
+        };
+        spawn_server(Router::new().route("/meetings", get(handler))).await
+    }
+
+    #[tokio::test]
+    async fn test_offsets_walk_the_cursors() {
+        let client = FathomClient::new(&mock_fathom(7).await, KEY);
+
+        let page = client
+            .list_meetings(2, 0, &MeetingFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(page.total, Some(7));
+        assert_eq!(page.next_cursor.as_deref(), Some("2:2"));
+        assert_eq!(page.meetings.len(), 2);
+        let first = &page.meetings[0];
+        assert_eq!(
+            (first.id.as_str(), first.title.as_str()),
+            ("1000", "Meeting 0")
+        );
+        assert_eq!(first.start_time.to_rfc3339(), "2026-03-02T10:00:00+00:00");
+        assert_eq!(first.duration, 1800);
+        let names: Vec<_> = first
+            .participants
+            .iter()
+            .map(|participant| participant.display_name.as_str())
+            .collect();
+        assert_eq!(names, ["Alice Example", "bob@example.com"]);
+        assert_eq!(
+            first.participants[0].email.as_deref(),
+            Some("alice@example.com")
+        );
+
+        // Spanning a page boundary and running off the end
+        let ids = |page: MeetingsPage| {
+            page.meetings
+                .into_iter()
+                .map(|meeting| meeting.id)
+                .collect::<Vec<_>>()
+        };
+        let unaligned = client
+            .list_meetings(3, 2, &MeetingFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(unaligned.next_cursor, None);
+        assert_eq!(ids(unaligned), ["1002", "1003", "1004"]);
+        let aligned = client
+            .list_meetings(2, 4, &MeetingFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(aligned.next_cursor.as_deref(), Some("6:2"));
+        assert_eq!(ids(aligned), ["1004", "1005"]);
+        assert_eq!(
+            ids(client
+                .list_meetings(5, 5, &MeetingFilter::default())
+                .await
+                .unwrap()),
+            ["1005", "1006"]
+        );
+        assert!(ids(client
+            .list_meetings(5, 10, &MeetingFilter::default())
+            .await
+            .unwrap())
+        .is_empty());
+
+        let empty = FathomClient::new(&mock_fathom(0).await, KEY);
+        assert_eq!(
+            empty
+                .list_meetings(10, 0, &MeetingFilter::default())
+                .await
+                .unwrap(),
+            MeetingsPage {
+                meetings: vec![],
+                total: Some(0),
+                next_cursor: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cursors_chain_to_the_last_page() {
+        let client = FathomClient::new(&mock_fathom(7).await, KEY);
+        let ids = |page: &MeetingsPage| {
+            page.meetings
+                .iter()
+                .map(|meeting| meeting.id.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let first = client
+            .list_meetings(3, 0, &MeetingFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&first), "1000,1001,1002");
+        let second = client
+            .meetings_after(
+                first.next_cursor.as_deref().unwrap(),
+                &MeetingFilter::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids(&second), "1003,1004,1005");
+        // Cursor pages aren't counted
+        assert_eq!(second.total, None);
+        let last = client
+            .meetings_after(
+                second.next_cursor.as_deref().unwrap(),
+                &MeetingFilter::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids(&last), "1006");
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_meeting_details() {
+        async fn recording(axum::extract::Path(id): axum::extract::Path<String>) -> Response {
+            let mut detail = meeting(1);
+            match id.as_str() {
+                "1001" => {
+                    detail["default_summary"] = json!({ "markdown_formatted": "## Plans" });
+                    detail["recording_size_bytes"] = json!(52_428_800);
+                    detail["download_url"] = json!("https://fathom.video/signed/1001?sig=secret");
+                }
+                "1002" => detail["recording_id"] = json!(1002),
+                _ => return StatusCode::NOT_FOUND.into_response(),
+            }
+            Json(detail).into_response()
+        }
+        let url = spawn_server(Router::new().route("/recordings/:id", get(recording))).await;
+        let client = FathomClient::new(&url, KEY);
+
+        let detail = client.meeting("1001").await.unwrap();
+        assert_eq!(detail.meeting.id, "1001");
+        assert_eq!(detail.summary.as_deref(), Some("## Plans"));
+        assert_eq!(detail.size_bytes, Some(52_428_800));
+        assert!(detail.downloadable);
+        assert!(!serde_json::to_string(&detail)
+            .unwrap()
+            .contains("sig=secret"));
+
+        let bare = client.meeting("1002").await.unwrap();
+        assert_eq!(
+            (bare.summary, bare.size_bytes, bare.downloadable),
+            (None, None, false)
+        );
+
+        for id in ["404", "../meetings"] {
+            assert!(
+                matches!(client.meeting(id).await, Err(FathomError::NotFound)),
+                "{}",
+                id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transcripts_ready_and_in_progress() {
+        async fn transcript(axum::extract::Path(id): axum::extract::Path<String>) -> Response {
+            let line = |name: Option<&str>, email: &str, text: &str, at: &str| {
+                json!({
+                    "speaker": { "display_name": name, "matched_calendar_invitee_email": email },
+                    "text": text,
+                    "timestamp": at
+                })
+            };
+            match id.as_str() {
+                "1001" => Json(json!({ "transcript": [
+                    line(Some("Alice Example"), "alice@example.com", "Shall we start?", "00:00:02"),
+                    line(None, "bob@example.com", "Yes.", "00:01:05.5"),
+                    line(Some("Alice Example"), "alice@example.com", "Good.", "01:00:00"),
+                ] }))
+                .into_response(),
+                "1002" => (StatusCode::ACCEPTED, [("retry-after", "12")]).into_response(),
+                "1003" => StatusCode::ACCEPTED.into_response(),
+                "1004" => Json(json!({ "transcript": [
+                    line(None, "bob@example.com", "When?", "late"),
+                ] }))
+                .into_response(),
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+        let url =
+            spawn_server(Router::new().route("/recordings/:id/transcript", get(transcript))).await;
+        let client = FathomClient::new(&url, KEY);
+
+        let Transcript::Ready(segments) = client.transcript("1001").await.unwrap() else {
+            panic!("the transcript is finished");
+        };
+        assert_eq!(
+            segments[0],
+            TranscriptSegment {
+                speaker: "Alice Example".to_string(),
+                start_ms: 2_000,
+                end_ms: 65_500,
+                text: "Shall we start?".to_string(),
+            }
+        );
+        assert_eq!(segments[1].speaker, "bob@example.com");
+        assert_eq!(
+            (segments[2].start_ms, segments[2].end_ms),
+            (3_600_000, 3_600_000)
+        );
+
+        assert_eq!(
+            client.transcript("1002").await.unwrap(),
+            Transcript::Processing {
+                retry_after: Duration::from_secs(12)
+            }
+        );
+        assert_eq!(
+            client.transcript("1003").await.unwrap(),
+            Transcript::Processing {
+                retry_after: DEFAULT_RETRY_AFTER
+            }
+        );
+        assert!(matches!(
+            client.transcript("1004").await,
+            Err(FathomError::Malformed(_))
+        ));
+        assert!(matches!(
+            client.transcript("9").await,
+            Err(FathomError::NotFound)
+        ));
+    }
+
+    /// A recording turned away with each of `script`'s status and
+    /// `Retry-After` in turn before it is served, and the attempts made
+    async fn scripted(script: Vec<(StatusCode, Option<&'static str>)>) -> (String, Arc<AtomicU64>) {
+        let attempts = Arc::new(AtomicU64::new(0));
+        let counted = attempts.clone();
+        let handler = move || {
+            let attempt = counted.fetch_add(1, Ordering::SeqCst) as usize;
+            let answer = script.get(attempt).copied();
+            async move {
+                match answer {
+                    Some((status, Some(wait))) => (status, [("retry-after", wait)]).into_response(),
+                    Some((status, None)) => status.into_response(),
+                    None => Json(meeting(1)).into_response(),
+                }
+            }
+        };
+        let url = spawn_server(Router::new().route("/recordings/:id", get(handler))).await;
+        (url, attempts)
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_requests_are_retried() {
+        let limiter = Arc::new(FathomLimiter::new(60, 3).with_backoff(Duration::from_millis(20)));
+        let client = |url: &str| FathomClient::new(url, KEY).with_limiter(limiter.clone(), "alice");
+
+        // Fathom's Retry-After is waited out
+        let (url, attempts) = scripted(vec![(StatusCode::TOO_MANY_REQUESTS, Some("1"))]).await;
+        let started = std::time::Instant::now();
+        assert_eq!(
+            client(&url).meeting("1001").await.unwrap().meeting.id,
+            "1001"
+        );
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // 503s without one back off
+        let (url, attempts) = scripted(vec![
+            (StatusCode::SERVICE_UNAVAILABLE, None),
+            (StatusCode::SERVICE_UNAVAILABLE, None),
+        ])
+        .await;
+        assert!(client(&url).meeting("1001").await.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Until the attempts run out
+        let (url, attempts) = scripted(vec![(StatusCode::TOO_MANY_REQUESTS, None); 5]).await;
+        assert!(matches!(
+            client(&url).meeting("1001").await,
+            Err(FathomError::RateLimited { retry_after: None })
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Or Fathom asks for longer than is worth waiting
+        let (url, attempts) = scripted(vec![(StatusCode::TOO_MANY_REQUESTS, Some("120"))]).await;
+        assert!(matches!(
+            client(&url).meeting("1001").await,
+            Err(FathomError::RateLimited {
+                retry_after: Some(wait)
+            }) if wait == Duration::from_secs(120)
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // Without a limiter nothing is retried
+        let (url, attempts) = scripted(vec![(StatusCode::SERVICE_UNAVAILABLE, None)]).await;
+        assert!(matches!(
+            FathomClient::new(&url, KEY).meeting("1001").await,
+            Err(FathomError::Status(503))
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let stats = limiter.stats().await;
+        assert_eq!((stats.retries, stats.upstream_rate_limited), (5, 5));
+        assert_eq!((stats.bucket.allowed, stats.bucket.refused), (9, 0));
+    }
+
+    #[tokio::test]
+    async fn test_spent_buckets_send_nothing() {
+        let limiter = Arc::new(FathomLimiter::new(2, 3));
+        let (url, attempts) = scripted(vec![]).await;
+        let alice = FathomClient::new(&url, KEY).with_limiter(limiter.clone(), "alice");
+
+        assert!(alice.meeting("1001").await.is_ok());
+        assert!(alice.meeting("1001").await.is_ok());
+        let Err(FathomError::Throttled { retry_after }) = alice.meeting("1001").await else {
+            panic!("alice's bucket is empty");
+        };
+        assert!(retry_after > Duration::from_secs(25) && retry_after <= Duration::from_secs(30));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // Each user has a bucket of their own
+        let bob = FathomClient::new(&url, KEY).with_limiter(limiter.clone(), "bob");
+        assert!(bob.meeting("1001").await.is_ok());
+        assert_eq!(limiter.stats().await.bucket.refused, 1);
+    }
+
+    /// Fathom lookalike listing one meeting, counting requests; the first
+    /// `slow` of them wait `delay` before answering, and each answers
+    /// `status` when it's an error
+    async fn counted_fathom(
+        slow: u64,
+        delay: Duration,
+        status: StatusCode,
+    ) -> (String, Arc<AtomicU64>) {
+        let requests = Arc::new(AtomicU64::new(0));
+        let counted = requests.clone();
+        let handler = move || {
+            let request = counted.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if request < slow {
+                    tokio::time::sleep(delay).await;
+                }
+                match status.is_success() {
+                    true => Json(json!({ "items": [meeting(request as usize)] })).into_response(),
+                    false => status.into_response(),
+                }
+            }
+        };
+        let url = spawn_server(Router::new().route("/meetings", get(handler))).await;
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_listings_share_one_fetch() {
+        let limiter = Arc::new(FathomLimiter::new(60, 1));
+        let (url, requests) =
+            counted_fathom(u64::MAX, Duration::from_millis(200), StatusCode::OK).await;
+        let [alice, alice_again, bob] = ["alice", "alice", "bob"]
+            .map(|user| FathomClient::new(&url, KEY).with_limiter(limiter.clone(), user));
+        let all = MeetingFilter::default();
+
+        let (first, second) = tokio::join!(
+            alice.list_meetings(10, 0, &all),
+            alice_again.list_meetings(10, 0, &all)
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let stats = limiter.stats().await;
+        assert_eq!((stats.shared_fetches, stats.bucket.allowed), (1, 1));
+
+        // Done, the slot is free again
+        alice.list_meetings(10, 0, &all).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Other users, queries and cursors fetch for themselves
+        let titled = MeetingFilter {
+            title: Some("Meeting".to_string()),
+            ..Default::default()
+        };
+        let _ = tokio::join!(
+            alice.list_meetings(10, 0, &all),
+            bob.list_meetings(10, 0, &all),
+            alice.list_meetings(10, 0, &titled),
+            alice.meetings_after("next", &all)
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+        assert_eq!(limiter.stats().await.shared_fetches, 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_fetches_share_errors() {
+        let limiter = Arc::new(FathomLimiter::new(60, 1));
+        let (url, requests) = counted_fathom(
+            u64::MAX,
+            Duration::from_millis(200),
+            StatusCode::BAD_GATEWAY,
+        )
+        .await;
+        let alice = FathomClient::new(&url, KEY).with_limiter(limiter.clone(), "alice");
+
+        let all = MeetingFilter::default();
+        let (first, second) = tokio::join!(
+            alice.list_meetings(10, 0, &all),
+            alice.list_meetings(10, 0, &all)
+        );
+        assert!(matches!(first, Err(FathomError::Status(502))));
+        assert!(matches!(second, Err(FathomError::Status(502))));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hung_fetches_time_out_and_free_the_slot() {
+        let timeout = Duration::from_millis(300);
+        let limiter = Arc::new(FathomLimiter::new(60, 1).with_flight_timeout(timeout));
+        // The first request never answers in time
+        let (url, requests) = counted_fathom(1, Duration::from_secs(30), StatusCode::OK).await;
+        let alice = FathomClient::new(&url, KEY).with_limiter(limiter.clone(), "alice");
+        let all = MeetingFilter::default();
+
+        let started = Instant::now();
+        let (first, second) = tokio::join!(
+            alice.list_meetings(10, 0, &all),
+            alice.list_meetings(10, 0, &all)
+        );
+        assert!(started.elapsed() >= timeout && started.elapsed() < Duration::from_secs(5));
+        for result in [first, second] {
+            assert!(matches!(result, Err(FathomError::Timeout)), "{:?}", result);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(alice.list_meetings(10, 0, &all).await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_fetches_are_replaced_once_timed_out() {
+        let timeout = Duration::from_millis(300);
+        let limiter = Arc::new(FathomLimiter::new(60, 1).with_flight_timeout(timeout));
+        let (url, requests) = counted_fathom(1, Duration::from_secs(30), StatusCode::OK).await;
+        let alice = FathomClient::new(&url, KEY).with_limiter(limiter.clone(), "alice");
+        let all = MeetingFilter::default();
+
+        // Its caller gives up, leaving the fetch in its slot unfinished
+        let abandoned =
+            tokio::time::timeout(Duration::from_millis(50), alice.list_meetings(10, 0, &all)).await;
+        assert!(abandoned.is_err());
+        tokio::time::sleep(timeout).await;
+
+        assert!(alice.list_meetings(10, 0, &all).await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.stats().await.shared_fetches, 0);
+    }
+
+    /// Fathom lookalike whose recordings' media it serves itself, each
+    /// recording id naming how the check should go
+    async fn media_fathom() -> String {
+        async fn recording(Path(id): Path<String>, headers: HeaderMap) -> Response {
+            let host = headers["host"].to_str().unwrap();
+            let mut detail = meeting(1);
+            detail["recording_size_bytes"] = json!(1_000);
+            match id.as_str() {
+                "deleted" => return StatusCode::NOT_FOUND.into_response(),
+                "private" => return StatusCode::FORBIDDEN.into_response(),
+                "slow" => tokio::time::sleep(Duration::from_secs(30)).await,
+                "no-download" => return Json(detail).into_response(),
+                _ => {}
+            }
+            detail["download_url"] = json!(format!("http://{}/media/{}?sig=secret", host, id));
+            Json(detail).into_response()
+        }
+        async fn media(method: Method, Path(id): Path<String>, headers: HeaderMap) -> Response {
+            match (id.as_str(), method) {
+                ("ok", _) => (
+                    [
+                        ("content-type", "video/mp4"),
+                        ("content-length", "734003200"),
+                    ],
+                    "",
+                )
+                    .into_response(),
+                ("no-head", Method::HEAD) => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+                ("no-head", _) if headers["range"] == "bytes=0-0" => (
+                    StatusCode::PARTIAL_CONTENT,
+                    [
+                        ("content-type", "video/mp4"),
+                        ("content-range", "bytes 0-0/52428800"),
+                    ],
+                    "x",
+                )
+                    .into_response(),
+                ("expired", _) => StatusCode::FORBIDDEN.into_response(),
+                ("purged", _) => StatusCode::GONE.into_response(),
+                ("slow-media", _) => {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    StatusCode::OK.into_response()
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+        spawn_server(
+            Router::new()
+                .route("/recordings/:id", get(recording))
+                .route("/media/:id", any(media)),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_download_checks() {
+        let client =
+            FathomClient::new(&media_fathom().await, KEY).with_timeout(Duration::from_millis(300));
+
+        let check = client.download_check("ok").await.unwrap();
+        assert_eq!(
+            check,
+            DownloadCheck {
+                downloadable: true,
+                reason: DownloadReason::Available,
+                size_bytes: Some(734_003_200),
+                content_type: Some("video/mp4".to_string()),
+            }
+        );
+        let check = client.download_check("no-head").await.unwrap();
+        assert!(check.downloadable);
+        assert_eq!(check.size_bytes, Some(52_428_800));
+
+        for (id, reason) in [
+            ("deleted", DownloadReason::NotFound),
+            ("purged", DownloadReason::NotFound),
+            ("private", DownloadReason::Forbidden),
+            ("expired", DownloadReason::Forbidden),
+            ("slow", DownloadReason::Timeout),
+            ("slow-media", DownloadReason::Timeout),
+            ("no-download", DownloadReason::NoDownload),
+            ("broken", DownloadReason::Unavailable),
+        ] {
+            let check = client.download_check(id).await.unwrap();
+            assert_eq!(check, DownloadCheck::refused(reason), "{}", id);
+        }
+        assert!(
+            !serde_json::to_string(&client.download_check("ok").await.unwrap())
+                .unwrap()
+                .contains("sig=secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_keys_are_told_apart() {
+        let client = FathomClient::new(&mock_fathom(3).await, "fathom-wrong-key");
+        let error = client
+            .list_meetings(10, 0, &MeetingFilter::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FathomError::Unauthorized));
+        assert!(!format!("{:?} {}", client, error).contains("fathom-wrong-key"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_payloads_are_errors() {
+        async fn serve(body: &'static str) -> String {
+            let handler = move || async move { Response::new(axum::body::Body::from(body)) };
+            spawn_server(Router::new().route("/meetings", get(handler))).await
+        }
+        for body in [
+            "not json",
+            r#"{"meetings": []}"#,
+            r#"{"items": [{"title": "No id", "recording_start_time": "2026-03-02T10:00:00Z"}]}"#,
+            r#"{"items": [{"recording_id": 1, "title": "No start"}]}"#,
+        ] {
+            let client = FathomClient::new(&serve(body).await, KEY);
+            let error = client
+                .list_meetings(10, 0, &MeetingFilter::default())
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(error, FathomError::Malformed(_)),
+                "{}: {:?}",
+                body,
+                error
+            );
+        }
+
+        let failing = spawn_server(
+            Router::new().route("/meetings", get(|| async { StatusCode::BAD_GATEWAY })),
+        )
+        .await;
+        let error = FathomClient::new(&failing, KEY)
+            .list_meetings(10, 0, &MeetingFilter::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FathomError::Status(502)));
+    }
+}
@@ -0,0 +1,18 @@
+//! Admin client for the global PocketBase
+//!
+//! Backend-owned collections (revoked tokens, token generations, ...) are
+//! only writable by admins, so the client shared with the worker and
+//! smtp-service in [`common::pocketbase`] authenticates with the configured
+//! admin credentials, caches the admin token, and re-authenticates once if
+//! PocketBase rejects it. The manager hands out the same client for user
+//! instances (see `PocketBaseManager::admin_client`).
+
+pub use common::pocketbase::{quote, PbError as GlobalPbError, PocketBase as GlobalPb, RecordPage};
+
+use crate::config::DatabaseConfig;
+
+impl From<&DatabaseConfig> for GlobalPb {
+    fn from(database: &DatabaseConfig) -> Self {
+        GlobalPb::new(&database.url, &database.admin_email, &database.admin_password)
+    }
+}
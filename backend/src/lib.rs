@@ -1,7 +1,15 @@
 pub mod config;
+pub mod fathom;
+pub mod global_pb;
+pub mod mailer;
+pub mod metrics;
 pub mod pocketbase_manager;
+pub mod tls;
 pub mod api;
 
+#[cfg(test)]
+mod test_support;
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -0,0 +1,174 @@
+//! Outgoing email through the smtp-service
+//!
+//! The backend never talks SMTP itself; it renders a template and hands the
+//! message to the smtp-service's `/send-email` API, which queues and delivers
+//! it with the configured SMTP settings. Requests bear `SMTP_SERVICE_API_KEY`
+//! when it is set.
+
+use serde::Serialize;
+
+use crate::config::EmailConfig;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MailerError {
+    #[error("smtp-service request failed: {0}")]
+    Request(String),
+
+    #[error("smtp-service returned {status}: {body}")]
+    Status { status: u16, body: String },
+}
+
+/// A message as accepted by `POST /send-email`
+#[derive(Debug, Clone, Serialize)]
+pub struct OutgoingEmail {
+    pub to_email: String,
+    pub subject: String,
+    pub body_text: String,
+    pub body_html: String,
+}
+
+/// Subject and bodies with `{{name}}` placeholders
+pub struct Template {
+    pub subject: &'static str,
+    pub text: &'static str,
+    pub html: &'static str,
+}
+
+pub const VERIFY_EMAIL: Template = Template {
+    subject: "Confirm your Fathom to Loom email address",
+    text: "Welcome to Fathom to Loom!\n\n\
+           Confirm your email address within {{hours}} hours by opening this link:\n{{link}}\n\n\
+           If you didn't create an account, you can ignore this email.",
+    html: "<p>Welcome to Fathom to Loom!</p>\
+           <p><a href=\"{{link}}\">Confirm your email address</a> within {{hours}} hours.</p>\
+           <p>If you didn't create an account, you can ignore this email.</p>",
+};
+
+pub const PASSWORD_RESET: Template = Template {
+    subject: "Reset your Fathom to Loom password",
+    text: "Someone asked to reset the password for this account.\n\n\
+           Open this link within {{minutes}} minutes to choose a new one:\n{{link}}\n\n\
+           If it wasn't you, you can ignore this email.",
+    html: "<p>Someone asked to reset the password for this account.</p>\
+           <p><a href=\"{{link}}\">Choose a new password</a> within {{minutes}} minutes.</p>\
+           <p>If it wasn't you, you can ignore this email.</p>",
+};
+
+pub const KEY_EXPIRING: Template = Template {
+    subject: "Your {{service}} API key {{status}}",
+    text: "Your {{service}} API key \"{{key_id}}\" {{status}}.\n\n\
+           Meetings that need it can't be moved from Fathom to Loom once it has expired. \
+           Add a new key in your settings:\n{{link}}",
+    html: "<p>Your {{service}} API key \"{{key_id}}\" {{status}}.</p>\
+           <p>Meetings that need it can't be moved from Fathom to Loom once it has expired. \
+           <a href=\"{{link}}\">Add a new key in your settings</a>.</p>",
+};
+
+impl Template {
+    /// Fill in the placeholders for `to_email`; values are HTML-escaped in the HTML body
+    pub fn render(&self, to_email: &str, vars: &[(&str, &str)]) -> OutgoingEmail {
+        let fill = |template: &str, escape: bool| {
+            vars.iter().fold(template.to_string(), |body, (name, value)| {
+                let value = if escape { escape_html(value) } else { value.to_string() };
+                body.replace(&format!("{{{{{}}}}}", name), &value)
+            })
+        };
+        OutgoingEmail {
+            to_email: to_email.to_string(),
+            subject: fill(self.subject, false),
+            body_text: fill(self.text, false),
+            body_html: fill(self.html, true),
+        }
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub struct Mailer {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Mailer {
+    pub fn new(email: &EmailConfig) -> Self {
+        Self {
+            url: email.smtp_service_url.trim_end_matches('/').to_string(),
+            api_key: email.smtp_service_api_key.clone(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn send(&self, email: &OutgoingEmail) -> Result<(), MailerError> {
+        let mut request = self.client.post(format!("{}/send-email", self.url)).json(email);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| MailerError::Request(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(MailerError::Status {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::State,
+        http::{header, HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_render_escapes_html_only() {
+        let email = VERIFY_EMAIL.render("a@example.com", &[("link", "https://x/?a=1&b=2"), ("hours", "24")]);
+        assert!(email.body_text.contains("https://x/?a=1&b=2"));
+        assert!(email.body_text.contains("within 24 hours"));
+        assert!(email.body_html.contains("href=\"https://x/?a=1&amp;b=2\""));
+        assert!(!email.body_html.contains("{{"));
+    }
+
+    #[tokio::test]
+    async fn test_requests_bear_the_api_key_when_one_is_set() {
+        type Seen = Arc<Mutex<Vec<Option<String>>>>;
+        async fn record(State(seen): State<Seen>, headers: HeaderMap) -> StatusCode {
+            let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+            seen.lock().unwrap().push(authorization.map(str::to_string));
+            StatusCode::ACCEPTED
+        }
+        let seen = Seen::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/send-email", post(record)).with_state(seen.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let email = VERIFY_EMAIL.render("a@example.com", &[("link", "https://x/"), ("hours", "24")]);
+        for api_key in [Some("k-one"), None] {
+            let config = EmailConfig {
+                smtp_service_url: url.clone(),
+                smtp_service_api_key: api_key.map(str::to_string),
+            };
+            Mailer::new(&config).send(&email).await.unwrap();
+        }
+        assert_eq!(*seen.lock().unwrap(), [Some("Bearer k-one".to_string()), None]);
+    }
+}
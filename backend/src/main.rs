@@ -1,70 +1,94 @@
-mod config;
-mod pocketbase_manager;
-mod api;
-
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, Json},
+    response::Html,
     routing::get,
     Router,
 };
-use std::{path::PathBuf, sync::Arc};
+use std::{future::{Future, IntoFuture}, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use common::clock::SystemClock;
 use tokio::{net::TcpListener, sync::RwLock};
 use tracing::{info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use serde_json::{json, Value};
 
-use config::Config;
-use pocketbase_manager::PocketBaseManager;
-use api::{AppState, websocket::WebSocketManager};
+use backend::{
+    api::{
+        self,
+        audit::{self, AuditLogger},
+        auth_cache::AuthCache,
+        key_audit::KeyAuditLog,
+        key_expiry::{self, KeyExpiryScanner},
+        key_repository::MasterKey,
+        login_guard::LoginGuard,
+        rate_limit::RateLimits,
+        revocation::RevocationList,
+        sessions::SessionList,
+        startup::StartupState,
+        websocket::WebSocketManager,
+        AppState,
+    },
+    config::Config,
+    global_pb::GlobalPb,
+    mailer::Mailer,
+    pocketbase_manager::{binary, PocketBaseManager},
+    tls::{self, Tls},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load environment variables from .env file
     dotenvy::dotenv().ok();
 
-    // Load configuration
-    let config = Arc::new(Config::from_env()?);
-
-    // Initialize tracing with level from config
-    let log_level = match config.logging.level.to_lowercase().as_str() {
-        "error" => tracing::Level::ERROR,
-        "warn" => tracing::Level::WARN,
-        "info" => tracing::Level::INFO,
-        "debug" => tracing::Level::DEBUG,
-        "trace" => tracing::Level::TRACE,
-        _ => {
-            warn!("Invalid log level '{}', defaulting to 'info'", config.logging.level);
-            tracing::Level::INFO
+    // Load configuration, listing every problem before refusing to start
+    let config = match Config::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(report) => {
+            eprintln!("{}", report);
+            std::process::exit(1);
         }
     };
+    // Refuse to start rather than fail every request that touches a stored key
+    let master_key = Arc::new(MasterKey::from_config(&config.security)?);
+
+    // Initialize tracing with level and format from config
+    common::logging::init_tracing("backend", config.logging.format, &config.logging.level);
+    let reporter = common::telemetry::reporter(config.logging.sentry_dsn.as_deref(), "backend");
+    common::telemetry::install_panic_hook(reporter.clone());
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::filter::LevelFilter::from_level(log_level))
-        .init();
+    // A certificate that can't be served is better found now than at the first handshake
+    let tls = match config.server.tls_paths() {
+        Some((cert_path, key_path)) => Some(Arc::new(Tls::load(cert_path, key_path)?)),
+        None => None,
+    };
 
     info!("Configuration loaded successfully");
-    info!("Log level: {}", config.logging.level);
-    info!("Database URL: {}", config.database.url);
+    info!("Effective configuration: {:?}", config);
+
+    // Initialize shared broadcast service
+    let broadcast_service = common::broadcast::BroadcastServiceFactory::create_shared(1000);
+    info!("Shared broadcast service initialized");
+    
+    // Fail fast if PocketBase is missing or unsupported
+    let (binary_path, _) = binary::prepare_binary(&config.pocketbase).await?;
 
     // Initialize PocketBase manager
     let user_dbs_path = PathBuf::from(&config.pocketbase.user_dbs_path);
-    let pb_manager = Arc::new(PocketBaseManager::new(
-        user_dbs_path, 
-        config.pocketbase.base_port,
-        config.pocketbase.binary_path.clone()
-    ));
+    let pb_manager = Arc::new(
+        PocketBaseManager::new(
+            user_dbs_path,
+            config.pocketbase.base_port,
+            binary_path.display().to_string(),
+        )
+        .with_port_range(config.pocketbase.port_range_start, config.pocketbase.port_range_end)
+        .with_bind_host(config.pocketbase.bind_host.clone())
+        .with_health_interval(Duration::from_secs(config.pocketbase.health_check_interval_secs))
+        .with_warm_pool(config.pocketbase.warm_pool_size)
+        .with_encryption_key(config.security.pb_encryption_key.clone())
+        .with_broadcast(broadcast_service.clone()),
+    );
     
     // Start health monitoring for PocketBase instances
-    pb_manager.start_health_monitoring().await;
+    let _health_monitor = pb_manager.start_health_monitoring();
+    let _warm_pool = pb_manager.start_warm_pool();
     info!("PocketBase manager initialized with base path: {}", config.pocketbase.user_dbs_path);
 
-    // Initialize shared broadcast service
-    let broadcast_service = common::broadcast::BroadcastServiceFactory::create_shared(1000);
-    info!("Shared broadcast service initialized");
-    
     // Initialize WebSocket manager with external broadcast integration
     let ws_manager = Arc::new(WebSocketManager::with_external_broadcast(broadcast_service.clone()));
     info!("WebSocket manager initialized with broadcast integration");
@@ -73,32 +97,179 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let meetings_queue = Arc::new(RwLock::new(Vec::new()));
     info!("Meetings queue initialized");
 
+    // Restore token revocations so logged-out tokens stay rejected across restarts
+    let global_pb = Arc::new(GlobalPb::from(&config.database));
+    let revocations = Arc::new(RevocationList::persistent(global_pb.clone()));
+    if let Err(e) = revocations.load().await {
+        warn!("Failed to load token revocations from global PocketBase: {}", e);
+    }
+
+    // Likewise keep active login lockouts in force
+    let login_guard = Arc::new(LoginGuard::persistent(&config.security, Arc::new(SystemClock), global_pb.clone()));
+    if let Err(e) = login_guard.load().await {
+        warn!("Failed to load login lockouts from global PocketBase: {}", e);
+    }
+
+    let sessions = Arc::new(SessionList::new(global_pb.clone(), Arc::new(SystemClock)));
+
+    // Auth events are written in the background and flushed once more on shutdown
+    let audit = Arc::new(AuditLogger::new(global_pb.clone(), Arc::new(SystemClock)));
+    let _audit_writer = audit.start_flushing(audit::FLUSH_INTERVAL);
+    let key_audit = Arc::new(KeyAuditLog::new(global_pb.clone(), Arc::new(SystemClock)));
+    let _key_audit_writer = key_audit.start_flushing(audit::FLUSH_INTERVAL);
+
+    // Remind users of stored API keys that are about to expire
+    let mailer = Arc::new(Mailer::new(&config.email));
+    let key_expiry = Arc::new(
+        KeyExpiryScanner::new(&config, *master_key, global_pb.clone(), pb_manager.clone(), mailer.clone(), Arc::new(SystemClock))
+            .with_broadcast(broadcast_service.clone()),
+    );
+    let _key_expiry_scan = key_expiry.start(key_expiry::SCAN_INTERVAL);
+
+    // Not ready for traffic until every step above and the listener are done
+    let startup = Arc::new(StartupState::new());
+
     // Create application state
     let app_state = AppState {
         config: config.clone(),
-        pb_manager,
-        ws_manager,
+        pb_manager: pb_manager.clone(),
+        ws_manager: ws_manager.clone(),
         meetings_queue,
+        auth_cache: Arc::new(AuthCache::new(
+            Duration::from_secs(config.security.auth_cache_ttl_secs),
+            config.security.auth_cache_max_entries,
+        )),
+        revocations,
+        global_pb,
+        rate_limits: Arc::new(RateLimits::new(&config.security, &config.integrations, &config.rate_limits)),
+        mailer,
+        login_guard,
+        sessions,
+        audit: audit.clone(),
+        key_audit: key_audit.clone(),
+        master_key,
+        broadcast: broadcast_service.clone(),
+        startup: startup.clone(),
     };
 
     // Build our application with unified state
     let app = Router::new()
         .route("/", get(root))
         .route("/health", get(health_check))
-        .route("/api/env", get(env_endpoint))
-        .with_state(config.clone())
-        .merge(api::create_api_router(app_state));  // Add unified API routes
+        .merge(api::create_api_router(app_state))  // Add unified API routes
+        .layer(axum::middleware::from_fn_with_state(reporter, api::error_report::report_errors))
+        .layer(api::cors::layer(&config.cors))
+        .layer(axum::middleware::from_fn(api::metrics::track_requests))
+        .layer(axum::middleware::from_fn_with_state(
+            config.logging.requests,
+            api::request_log::log_requests,
+        ));
 
     let addr = format!("{}:{}", config.server.host, config.server.port);
-    info!("Starting Fathom to Loom backend server on {}", addr);
-    info!("PocketBase API endpoints available under /api/users/{{id}}/init_pb");
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    info!("Starting Fathom to Loom backend server on {}://{}", scheme, addr);
+    info!("PocketBase API endpoints available under {}/users/{{id}}/", api::version::API_PREFIX);
 
     let listener = TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    let drain_grace = Duration::from_secs(config.server.shutdown_grace_secs);
+    let shutdown_grace = Duration::from_secs(config.pocketbase.shutdown_grace_secs);
+    let (draining_tx, draining_rx) = tokio::sync::oneshot::channel();
+    let sockets = ws_manager.clone();
+    let phase = startup.clone();
+    let draining = async move {
+        shutdown_signal().await;
+        phase.begin_draining();
+        // The listener stops accepting once this resolves; open sockets
+        // would hold the drain open, so close them first
+        sockets.begin_shutdown();
+        let _ = draining_tx.send(());
+    };
+    let server: Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> = match tls {
+        Some(tls) => {
+            #[cfg(unix)]
+            tokio::spawn(tls.clone().reload_on_hangup()?);
+            Box::pin(tls::serve(listener, app, tls, draining))
+        }
+        // Peer addresses feed the per-IP rate limits
+        None => Box::pin(
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(draining)
+                .into_future(),
+        ),
+    };
+    let mut server = tokio::spawn(server);
+    startup.mark_ready();
+    info!("Startup complete, ready for traffic");
+
+    let drained = tokio::select! {
+        served = &mut server => {
+            served??;
+            true
+        }
+        _ = draining_rx => match tokio::time::timeout(drain_grace, &mut server).await {
+            Ok(served) => {
+                served??;
+                info!("In-flight requests finished");
+                true
+            }
+            Err(_) => {
+                warn!("Requests still running after {}s, dropping them", drain_grace.as_secs());
+                server.abort();
+                false
+            }
+        },
+    };
 
+    // Nothing records events once requests are done, so this flush is the last
+    let written = audit.flush().await;
+    info!("Flushed {} auth events, {} could not be written", written, audit.pending());
+    let written = key_audit.flush().await;
+    info!("Flushed {} key audit entries, {} could not be written", written, key_audit.pending());
+
+    // Let child databases close cleanly before the process exits
+    let report = pb_manager.shutdown_all(shutdown_grace).await;
+
+    if !drained || !report.forced.is_empty() {
+        warn!("Shutdown was forced");
+        std::process::exit(1);
+    }
+    info!("Shutdown complete");
     Ok(())
 }
 
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received");
+}
+
 async fn root() -> Html<&'static str> {
     Html("<h1>Fathom to Loom Backend</h1><p>API server is running!</p>")
 }
@@ -106,29 +277,3 @@ async fn root() -> Html<&'static str> {
 async fn health_check() -> &'static str {
     "OK"
 }
-
-/// Endpoint to expose safe environment configuration to frontend
-async fn env_endpoint(State(config): State<Arc<Config>>) -> Result<Json<Value>, StatusCode> {
-    // Only expose safe, non-sensitive configuration values to the frontend
-    let safe_config = json!({
-        "api": {
-            "base_url": format!("http://{}:{}", config.server.host, config.server.port),
-            "version": env!("CARGO_PKG_VERSION")
-        },
-        "database": {
-            "url": config.database.url
-        },
-        "logging": {
-            "level": config.logging.level
-        },
-        "cors": {
-            "origins": config.cors.origins
-        },
-        "features": {
-            "auth_enabled": true,
-            "encryption_enabled": true
-        }
-    });
-
-    Ok(Json(safe_config))
-}
@@ -0,0 +1,146 @@
+//! Application metrics in the Prometheus text format
+//!
+//! Every metric the backend exports is declared here, so names, types and
+//! help text stay consistent between the places that record them and the
+//! `/metrics` scrape. Counters and histograms are recorded where things
+//! happen; gauges are set from the current state just before each scrape.
+
+pub use common::metrics::{increment, observe, set};
+use common::metrics::{registry, Family, Kind};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub const HTTP_REQUESTS: Family = Family {
+    name: "http_requests_total",
+    kind: Kind::Counter,
+    help: "HTTP requests handled, by method, matched route and status",
+    buckets: &[],
+};
+
+pub const HTTP_REQUEST_DURATION: Family = Family {
+    name: "http_request_duration_seconds",
+    kind: Kind::Histogram,
+    help: "Time taken to answer HTTP requests, by method and matched route",
+    buckets: LATENCY_BUCKETS,
+};
+
+pub const QUEUE_LENGTH: Family = Family {
+    name: "queue_length",
+    kind: Kind::Gauge,
+    help: "Meetings waiting in the transfer queue",
+    buckets: &[],
+};
+
+pub const QUEUE_TASKS: Family = Family {
+    name: "queue_tasks",
+    kind: Kind::Gauge,
+    help: "Meetings in the transfer queue, by status",
+    buckets: &[],
+};
+
+pub const WEBSOCKET_CONNECTIONS: Family = Family {
+    name: "websocket_connections",
+    kind: Kind::Gauge,
+    help: "Open WebSocket connections",
+    buckets: &[],
+};
+
+pub const WEBSOCKET_MESSAGES: Family = Family {
+    name: "websocket_messages_total",
+    kind: Kind::Counter,
+    help: "WebSocket messages, by direction",
+    buckets: &[],
+};
+
+pub const POCKETBASE_INSTANCES: Family = Family {
+    name: "pocketbase_instances",
+    kind: Kind::Gauge,
+    help: "Per-user PocketBase instances, by status",
+    buckets: &[],
+};
+
+pub const AUTH_FAILURES: Family = Family {
+    name: "auth_failures_total",
+    kind: Kind::Counter,
+    help: "Failed authentications, by kind and reason",
+    buckets: &[],
+};
+
+pub const RATE_LIMITED_REQUESTS: Family = Family {
+    name: "rate_limit_requests_total",
+    kind: Kind::Counter,
+    help: "Requests checked against the per-client limits, by route class and outcome",
+    buckets: &[],
+};
+
+pub const RATE_LIMIT_CLIENTS: Family = Family {
+    name: "rate_limit_clients",
+    kind: Kind::Gauge,
+    help: "Clients the per-client limits are tracking, by route class",
+    buckets: &[],
+};
+
+/// Every family, in the order they're rendered
+const FAMILIES: &[&Family] = &[
+    &HTTP_REQUESTS,
+    &HTTP_REQUEST_DURATION,
+    &QUEUE_LENGTH,
+    &QUEUE_TASKS,
+    &WEBSOCKET_CONNECTIONS,
+    &WEBSOCKET_MESSAGES,
+    &POCKETBASE_INSTANCES,
+    &AUTH_FAILURES,
+    &RATE_LIMITED_REQUESTS,
+    &RATE_LIMIT_CLIENTS,
+];
+
+/// Every family in the Prometheus text exposition format
+pub fn render() -> String {
+    registry().render(FAMILIES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::metrics::Registry;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let registry = Registry::default();
+        registry.increment(
+            &HTTP_REQUESTS,
+            &[("method", "GET"), ("route", "/health"), ("status", "200")],
+        );
+        registry.increment(
+            &HTTP_REQUESTS,
+            &[("method", "GET"), ("route", "/health"), ("status", "200")],
+        );
+        registry.set(&QUEUE_LENGTH, &[], 3.0);
+        registry.observe(
+            &HTTP_REQUEST_DURATION,
+            &[("method", "GET"), ("route", "/health")],
+            0.02,
+        );
+        registry.increment(
+            &AUTH_FAILURES,
+            &[("kind", "token"), ("reason", "say \"hi\"")],
+        );
+
+        let text = registry.render(FAMILIES);
+        assert!(text.contains("# TYPE http_requests_total counter\n"));
+        assert!(text
+            .contains("http_requests_total{method=\"GET\",route=\"/health\",status=\"200\"} 2\n"));
+        assert!(text.contains("queue_length 3\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/health\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/health\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/health\",le=\"+Inf\"} 1\n"));
+        assert!(text
+            .contains("http_request_duration_seconds_count{method=\"GET\",route=\"/health\"} 1\n"));
+        assert!(text.contains("auth_failures_total{kind=\"token\",reason=\"say \\\"hi\\\"\"} 1\n"));
+        // Families are declared even before anything is recorded
+        assert!(text.contains("# TYPE websocket_connections gauge\n"));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
     time::Duration,
@@ -8,12 +8,59 @@ use std::{
 use tokio::{
     fs,
     process::{Child, Command},
-    sync::{Mutex, RwLock},
-    time::{interval, sleep},
+    sync::{watch, Mutex, Notify, RwLock},
+    time::{sleep, Instant},
 };
 use tracing::{error, info, warn, debug};
 use serde::{Deserialize, Serialize};
 
+/// Backup archive validation and unpacking
+pub mod archive;
+/// Resource usage collection for child processes
+pub mod stats;
+/// Background health monitoring
+pub mod monitor;
+/// Binary verification and download
+pub mod binary;
+/// Pre-started instances for new users
+pub mod pool;
+
+use common::broadcast::{BroadcastService, SystemEvent, SystemEventType};
+use crate::global_pb::GlobalPb;
+use monitor::HealthProbe;
+use pool::WarmInstance;
+use stats::InstanceStats;
+use tokio_util::sync::CancellationToken;
+
+/// Default time between health checks of each instance
+const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a (re)started instance may take to answer its health endpoint
+const DEFAULT_READINESS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Parent environment variables passed through to PocketBase processes;
+/// everything else, including the backend's own secrets, is withheld
+const INHERITED_ENV: &[&str] = &[
+    "PATH",
+    "TZ",
+    "LANG",
+    "LC_ALL",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "SYSTEMROOT",
+];
+
+/// Variable holding an instance's settings encryption key, named to
+/// PocketBase via `--encryptionEnv`
+const ENCRYPTION_ENV: &str = "PB_INSTANCE_ENCRYPTION_KEY";
+
+/// File in a data directory naming the key id its encryption key is derived
+/// from, when that isn't the owner's user id (see [`instance_encryption_key`])
+const KEY_ID_FILE: &str = ".encryption_key_id";
+
+/// Admin account the backend keeps in every user instance
+const INSTANCE_ADMIN_EMAIL: &str = "backend@fathom-loom.local";
+
 /// Information about a running PocketBase instance
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PocketBaseInstance {
@@ -24,6 +71,9 @@ pub struct PocketBaseInstance {
     pub status: InstanceStatus,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub last_health_check: Option<chrono::DateTime<chrono::Utc>>,
+    /// Number of times this user's instance has been started after the first
+    #[serde(default)]
+    pub restart_count: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -34,118 +84,490 @@ pub enum InstanceStatus {
     Stopped,
 }
 
+impl From<InstanceStatus> for common::InstanceState {
+    fn from(status: InstanceStatus) -> Self {
+        match status {
+            InstanceStatus::Starting => Self::Starting,
+            InstanceStatus::Running => Self::Running,
+            InstanceStatus::Failed => Self::Failed,
+            InstanceStatus::Stopped => Self::Stopped,
+        }
+    }
+}
+
+/// Where a restore reads its archive from
+#[derive(Debug)]
+pub enum RestoreSource {
+    /// A backup id previously written to the user's backups directory
+    Backup(String),
+    /// A gzipped tarball of a data directory supplied by the caller
+    Archive(Vec<u8>),
+}
+
+/// What was removed when a user's instance was deleted
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeletionReport {
+    pub user_id: String,
+    pub files_removed: u64,
+    pub bytes_removed: u64,
+}
+
+/// Which instances exited on their own during shutdown and which had to be killed
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShutdownReport {
+    pub graceful: Vec<String>,
+    pub forced: Vec<String>,
+}
+
+/// Outcome of a successful restore
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub user_id: String,
+    pub entries_restored: usize,
+    pub instance: PocketBaseInstance,
+}
+
 /// Manages lifecycle of per-user PocketBase instances
 pub struct PocketBaseManager {
-    base_port: u16,
+    port_range_start: u16,
+    port_range_end: u16,
+    bind_host: String,
     user_dbs_path: PathBuf,
     binary_path: String,
     instances: Arc<RwLock<HashMap<String, PocketBaseInstance>>>,
     processes: Arc<Mutex<HashMap<String, Child>>>,
-    allocated_ports: Arc<Mutex<HashSet<u16>>>,
+    /// Ports in use by instances; a listener is held while a port is
+    /// reserved but its child has not been spawned yet
+    allocated_ports: Arc<Mutex<HashMap<u16, Option<std::net::TcpListener>>>>,
+    stats: Arc<RwLock<HashMap<String, InstanceStats>>>,
+    readiness_timeout: Duration,
+    health_interval: Duration,
+    health_probe: HealthProbe,
+    monitor_token: CancellationToken,
+    broadcast: Option<Arc<BroadcastService>>,
+    /// Initializations in progress, so concurrent callers share one attempt
+    inflight: Arc<std::sync::Mutex<HashMap<String, InitFlight>>>,
+    /// Users whose latest initialization failed
+    failed_inits: Arc<std::sync::Mutex<HashSet<String>>>,
+    warm_pool_size: usize,
+    warm_pool: Arc<Mutex<Vec<WarmInstance>>>,
+    /// Wakes the warm pool task when an instance has been claimed
+    pool_notify: Arc<Notify>,
+    /// Master secret per-instance encryption keys are derived from
+    encryption_key: Option<String>,
+    /// Admin clients per user, created on first use
+    admin_clients: Arc<Mutex<HashMap<String, Arc<GlobalPb>>>>,
+    /// Stands in for the master secret when deriving admin passwords without one
+    process_secret: String,
+    /// The most a restored archive may unpack to
+    unpack_limits: archive::UnpackLimits,
+}
+
+type InitResult = Result<PocketBaseInstance, PocketBaseError>;
+
+/// Receives the outcome of an in-flight initialization once it finishes
+type InitFlight = watch::Receiver<Option<InitResult>>;
+
+/// Removes a user's in-flight entry even if the leading init is cancelled
+struct InflightGuard<'a> {
+    inflight: &'a std::sync::Mutex<HashMap<String, InitFlight>>,
+    user_id: &'a str,
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut inflight) = self.inflight.lock() {
+            inflight.remove(self.user_id);
+        }
+    }
 }
 
 impl PocketBaseManager {
     pub fn new(user_dbs_path: PathBuf, base_port: u16, binary_path: String) -> Self {
         Self {
-            base_port,
+            port_range_start: base_port,
+            port_range_end: base_port.saturating_add(999),
+            bind_host: "127.0.0.1".to_string(),
             user_dbs_path,
             binary_path,
             instances: Arc::new(RwLock::new(HashMap::new())),
             processes: Arc::new(Mutex::new(HashMap::new())),
-            allocated_ports: Arc::new(Mutex::new(HashSet::new())),
+            allocated_ports: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(RwLock::new(HashMap::new())),
+            readiness_timeout: DEFAULT_READINESS_TIMEOUT,
+            health_interval: DEFAULT_HEALTH_INTERVAL,
+            health_probe: monitor::http_probe(),
+            monitor_token: CancellationToken::new(),
+            broadcast: None,
+            inflight: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            failed_inits: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            warm_pool_size: 0,
+            warm_pool: Arc::new(Mutex::new(Vec::new())),
+            pool_notify: Arc::new(Notify::new()),
+            encryption_key: None,
+            admin_clients: Arc::new(Mutex::new(HashMap::new())),
+            process_secret: {
+                use rand::RngCore;
+                let mut bytes = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+            },
+            unpack_limits: archive::UnpackLimits::default(),
         }
     }
 
+    /// Publish lifecycle events on the broadcast service's system topic
+    pub fn with_broadcast(mut self, broadcast: Arc<BroadcastService>) -> Self {
+        self.broadcast = Some(broadcast);
+        self
+    }
+
+    fn emit(&self, event_type: SystemEventType, user_id: &str, port: u16) {
+        emit_event(self.broadcast.as_deref(), event_type, user_id, port);
+    }
+
+    /// Override the nominal time between health checks of each instance
+    pub fn with_health_interval(mut self, interval: Duration) -> Self {
+        self.health_interval = interval;
+        self
+    }
+
+    /// Replace the HTTP probe, letting tests run the monitor without servers
+    #[cfg(test)]
+    fn with_health_probe(mut self, probe: HealthProbe) -> Self {
+        self.health_probe = probe;
+        self
+    }
+
+    /// Cap restores lower than the default, letting tests go past the limits
+    #[cfg(test)]
+    fn with_unpack_limits(mut self, limits: archive::UnpackLimits) -> Self {
+        self.unpack_limits = limits;
+        self
+    }
+
+    /// Hand out ports from `start..=end` instead of the default window above `base_port`
+    pub fn with_port_range(mut self, start: u16, end: u16) -> Self {
+        self.port_range_start = start;
+        self.port_range_end = end;
+        self
+    }
+
+    /// Encrypt each instance's settings with a key derived from `master_key`
+    pub fn with_encryption_key(mut self, master_key: impl Into<String>) -> Self {
+        self.encryption_key = Some(master_key.into());
+        self
+    }
+
+    /// Bind instances to `host` instead of 127.0.0.1
+    pub fn with_bind_host(mut self, host: impl Into<String>) -> Self {
+        self.bind_host = host.into();
+        self
+    }
+
+    /// URL the backend uses to reach an instance listening on `port`
+    pub fn instance_url(&self, port: u16) -> String {
+        // A wildcard bind accepts connections on loopback
+        let host = match self.bind_host.as_str() {
+            "0.0.0.0" => "127.0.0.1",
+            "::" => "::1",
+            host => host,
+        };
+        format!("http://{}", host_and_port(host, port))
+    }
+
+    /// What an instance on `port` is told to listen on
+    fn listen_address(&self, port: u16) -> String {
+        host_and_port(&self.bind_host, port)
+    }
+
+    /// Override how long restarted instances may take to become ready
+    pub fn with_readiness_timeout(mut self, timeout: Duration) -> Self {
+        self.readiness_timeout = timeout;
+        self
+    }
+
+    /// Directory holding every user's data directory
+    pub fn user_dbs_path(&self) -> &Path {
+        &self.user_dbs_path
+    }
+
+    /// Data directory passed to a user's PocketBase process via `--dir`
+    pub fn data_dir(&self, user_id: &str) -> PathBuf {
+        self.user_dbs_path.join(format!("pb_user_{}.db", user_id))
+    }
+
+    /// Directory holding a user's backup archives (`<backup_id>.tar.gz`)
+    pub fn backups_dir(&self, user_id: &str) -> PathBuf {
+        self.user_dbs_path.join("backups").join(format!("pb_user_{}", user_id))
+    }
+
+    /// A user's data directory, once the id and resolved path are known to be safe
+    async fn checked_data_dir(&self, user_id: &str) -> Result<PathBuf, PocketBaseError> {
+        let path = self.data_dir(sanitize_user_id(user_id)?);
+        self.ensure_contained(&path).await?;
+        Ok(path)
+    }
+
+    /// A user's backups directory, once the id and resolved path are known to be safe
+    async fn checked_backups_dir(&self, user_id: &str) -> Result<PathBuf, PocketBaseError> {
+        let path = self.backups_dir(sanitize_user_id(user_id)?);
+        self.ensure_contained(&path).await?;
+        Ok(path)
+    }
+
+    /// Fail unless `path`, with symlinks resolved, lies strictly below `user_dbs_path`
+    async fn ensure_contained(&self, path: &Path) -> Result<(), PocketBaseError> {
+        let base = self.user_dbs_path.clone();
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || resolve_within(&base, &path))
+            .await
+            .map_err(|e| PocketBaseError::IoError(e.to_string()))?
+            .map(|_| ())
+    }
+
     /// Initialize a new PocketBase instance for a user
-    pub async fn init_user_instance(&self, user_id: &str) -> Result<PocketBaseInstance, PocketBaseError> {
+    ///
+    /// Concurrent calls for the same user share a single attempt: the first
+    /// caller starts the instance and the rest wait for its result, success
+    /// or error. A later call after a failure starts a fresh attempt.
+    pub async fn init_user_instance(&self, user_id: &str) -> InitResult {
+        loop {
+            let flight = {
+                let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+                match inflight.get(user_id) {
+                    Some(receiver) => Err(receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        inflight.insert(user_id.to_string(), receiver);
+                        Ok(sender)
+                    }
+                }
+            };
+
+            match flight {
+                Ok(sender) => return self.lead_init(user_id, sender).await,
+                Err(mut receiver) => {
+                    debug!("Waiting for in-flight initialization of user {}", user_id);
+                    if let Ok(result) = receiver.wait_for(Option::is_some).await {
+                        if let Some(result) = result.clone() {
+                            return result;
+                        }
+                    }
+                    // The leading call was cancelled before finishing; try again
+                }
+            }
+        }
+    }
+
+    /// Start initializing a user's instance without waiting for it
+    ///
+    /// Joins an initialization already in flight rather than starting
+    /// another. Returns `Running` if the instance is already up, otherwise
+    /// `Starting`; [`Self::startup_status`] reports how it went.
+    pub async fn init_in_background(self: &Arc<Self>, user_id: &str) -> InstanceStatus {
+        if let Some(instance) = self.get_user_instance(user_id).await {
+            if instance.status == InstanceStatus::Running {
+                return InstanceStatus::Running;
+            }
+        }
+
+        let sender = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            if inflight.contains_key(user_id) {
+                return InstanceStatus::Starting;
+            }
+            let (sender, receiver) = watch::channel(None);
+            inflight.insert(user_id.to_string(), receiver);
+            sender
+        };
+        let manager = Arc::clone(self);
+        let user_id = user_id.to_string();
+        tokio::spawn(async move {
+            // Failures are logged and recorded for startup_status
+            let _ = manager.lead_init(&user_id, sender).await;
+        });
+        InstanceStatus::Starting
+    }
+
+    /// Where a user's instance is on its way to running
+    ///
+    /// `Starting` while an initialization is in flight and `Failed` if the
+    /// latest one failed; otherwise the status of the instance, if any.
+    pub async fn startup_status(&self, user_id: &str) -> Option<InstanceStatus> {
+        if self.inflight.lock().unwrap_or_else(|e| e.into_inner()).contains_key(user_id) {
+            return Some(InstanceStatus::Starting);
+        }
+        if self.failed_inits.lock().unwrap_or_else(|e| e.into_inner()).contains(user_id) {
+            return Some(InstanceStatus::Failed);
+        }
+        self.get_user_instance(user_id).await.map(|instance| instance.status)
+    }
+
+    /// Take the user's `inflight` slot, first waiting out any initialization
+    /// holding it
+    ///
+    /// Nothing is ever sent on the returned sender: callers waiting on it see
+    /// it dropped and start over, finding whatever the holder left behind.
+    async fn claim_inflight(&self, user_id: &str) -> watch::Sender<Option<InitResult>> {
+        loop {
+            let mut receiver = {
+                let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+                match inflight.get(user_id) {
+                    Some(receiver) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        inflight.insert(user_id.to_string(), receiver);
+                        return sender;
+                    }
+                }
+            };
+            let _ = receiver.wait_for(Option::is_some).await;
+        }
+    }
+
+    /// Run the initialization this caller registered in `inflight`, and hand
+    /// its result to everyone waiting on it
+    async fn lead_init(&self, user_id: &str, sender: watch::Sender<Option<InitResult>>) -> InitResult {
+        let guard = InflightGuard {
+            inflight: &self.inflight,
+            user_id,
+        };
+        let result = self.start_recorded(user_id).await;
+        drop(guard);
+        sender.send_replace(Some(result.clone()));
+        result
+    }
+
+    /// Start the instance, noting for [`Self::startup_status`] whether it failed
+    async fn start_recorded(&self, user_id: &str) -> InitResult {
+        let result = self.start_user_instance(user_id).await;
+        let mut failed = self.failed_inits.lock().unwrap_or_else(|e| e.into_inner());
+        if result.is_ok() {
+            failed.remove(user_id);
+        } else {
+            failed.insert(user_id.to_string());
+        }
+        result
+    }
+
+    async fn start_user_instance(&self, user_id: &str) -> InitResult {
+        let db_path = self.checked_data_dir(user_id).await?;
         info!("Initializing PocketBase instance for user: {}", user_id);
 
         // Check if instance already exists
-        {
+        let restart_count = {
             let instances = self.instances.read().await;
-            if let Some(instance) = instances.get(user_id) {
-                if instance.status == InstanceStatus::Running {
+            match instances.get(user_id) {
+                Some(instance) if instance.status == InstanceStatus::Running => {
                     info!("PocketBase instance already running for user: {}", user_id);
                     return Ok(instance.clone());
                 }
+                Some(instance) => instance.restart_count + 1,
+                None => 0,
+            }
+        };
+
+        // New users can take over a pre-started instance
+        if !fs::try_exists(&db_path).await.unwrap_or(true) {
+            if let Some(instance) = self.adopt_warm_instance(user_id, &db_path, restart_count).await {
+                return Ok(instance);
             }
         }
 
-        // Allocate port
-        let port = self.allocate_port(user_id).await?;
-        
-        // Create database directory and file path
-        let db_path = self.user_dbs_path.join(format!("pb_user_{}.db", user_id));
-        
-        // Ensure user_dbs directory exists
-        fs::create_dir_all(&self.user_dbs_path).await
-            .map_err(|e| PocketBaseError::IoError(format!("Failed to create user_dbs directory: {}", e)))?;
+        // Each user gets their own data directory under user_dbs_path
+        fs::create_dir_all(&db_path).await
+            .map_err(|e| PocketBaseError::IoError(format!("Failed to create user data directory: {}", e)))?;
 
-        let url = format!("http://localhost:{}", port);
-        
-        let instance = PocketBaseInstance {
-            user_id: user_id.to_string(),
-            port,
-            db_path: db_path.clone(),
-            url: url.clone(),
-            status: InstanceStatus::Starting,
-            created_at: chrono::Utc::now(),
-            last_health_check: None,
-        };
+        // A port can still be taken by another process between our reservation
+        // and the child's bind, so a bind failure gets one retry on a fresh port
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let port = self.allocate_port(user_id).await?;
+
+            let instance = PocketBaseInstance {
+                user_id: user_id.to_string(),
+                port,
+                db_path: db_path.clone(),
+                url: self.instance_url(port),
+                status: InstanceStatus::Starting,
+                created_at: chrono::Utc::now(),
+                last_health_check: None,
+                restart_count,
+            };
 
-        // Start PocketBase process
-        match self.start_pocketbase_process(&instance).await {
-            Ok(child) => {
-                // Store process handle
-                {
-                    let mut processes = self.processes.lock().await;
-                    processes.insert(user_id.to_string(), child);
+            self.emit(SystemEventType::InstanceStarting, user_id, port);
+            let (mut child, stderr_tail) = match self.start_pocketbase_process(&instance).await {
+                Ok(started) => started,
+                Err(e) => {
+                    self.release_port(port).await;
+                    self.emit(SystemEventType::InstanceFailed { reason: e.to_string() }, user_id, port);
+                    error!("Failed to start PocketBase instance for user {}: {}", user_id, e);
+                    return Err(e);
                 }
+            };
 
-                // Update instance status
-                let mut updated_instance = instance.clone();
-                updated_instance.status = InstanceStatus::Running;
+            match self.wait_for_ready(&mut child, &instance.url, &stderr_tail).await {
+                Ok(()) => {
+                    self.processes.lock().await.insert(user_id.to_string(), child);
 
-                // Store instance
-                {
-                    let mut instances = self.instances.write().await;
-                    instances.insert(user_id.to_string(), updated_instance.clone());
+                    let mut running = instance;
+                    running.status = InstanceStatus::Running;
+                    self.instances
+                        .write()
+                        .await
+                        .insert(user_id.to_string(), running.clone());
+
+                    let event_type = if restart_count > 0 {
+                        SystemEventType::InstanceRestarted
+                    } else {
+                        SystemEventType::InstanceRunning
+                    };
+                    self.emit(event_type, user_id, port);
+
+                    info!("Successfully started PocketBase instance for user {} on port {}", user_id, port);
+                    return Ok(running);
                 }
+                Err(e) => {
+                    if let Err(kill_error) = child.kill().await {
+                        debug!("PocketBase child for user {} already gone: {}", user_id, kill_error);
+                    }
+                    self.release_port(port).await;
 
-                info!("Successfully started PocketBase instance for user {} on port {}", user_id, port);
-                Ok(updated_instance)
-            }
-            Err(e) => {
-                // Release the allocated port on failure
-                {
-                    let mut ports = self.allocated_ports.lock().await;
-                    ports.remove(&port);
+                    if matches!(e, PocketBaseError::PortInUse(_)) && attempt == 1 {
+                        warn!("Port {} was taken before PocketBase for user {} could bind it, retrying", port, user_id);
+                        continue;
+                    }
+
+                    self.emit(SystemEventType::InstanceFailed { reason: e.to_string() }, user_id, port);
+                    error!("Failed to start PocketBase instance for user {}: {}", user_id, e);
+                    return Err(e);
                 }
-                
-                error!("Failed to start PocketBase instance for user {}: {}", user_id, e);
-                Err(e)
             }
         }
     }
 
-    /// Allocate an available port for a user
+    /// Reserve an available port for a user
+    ///
+    /// The port stays bound by a placeholder listener until the child is
+    /// spawned, so nothing else can claim it in between.
     async fn allocate_port(&self, user_id: &str) -> Result<u16, PocketBaseError> {
         let mut ports = self.allocated_ports.lock().await;
         
-        // Try preferred port first (base_port + user_id hash % 1000)
-        let user_hash = self.hash_user_id(user_id);
-        let preferred_port = self.base_port + (user_hash % 1000) as u16;
-        
-        if !ports.contains(&preferred_port) && self.is_port_available(preferred_port).await {
-            ports.insert(preferred_port);
-            return Ok(preferred_port);
-        }
+        // Start at a preferred port derived from the user id so users tend
+        // to keep the same port, then scan the rest of the range from there
+        let range_len = u32::from(self.port_range_end - self.port_range_start) + 1;
+        let preferred_offset = self.hash_user_id(user_id) % range_len;
 
-        // Find next available port
-        for offset in 1..1000 {
-            let port = self.base_port + offset;
-            if !ports.contains(&port) && self.is_port_available(port).await {
-                ports.insert(port);
+        for step in 0..range_len {
+            let offset = (preferred_offset + step) % range_len;
+            let port = self.port_range_start + offset as u16;
+            if ports.contains_key(&port) {
+                continue;
+            }
+            if let Some(listener) = self.reserve_port(port) {
+                ports.insert(port, Some(listener));
                 return Ok(port);
             }
         }
@@ -153,6 +575,17 @@ impl PocketBaseManager {
         Err(PocketBaseError::NoPortsAvailable)
     }
 
+    /// Ports currently reserved or held by instances, in ascending order
+    pub async fn allocated_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.allocated_ports.lock().await.keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    async fn release_port(&self, port: u16) {
+        self.allocated_ports.lock().await.remove(&port);
+    }
+
     /// Hash user ID to get a consistent number
     fn hash_user_id(&self, user_id: &str) -> u32 {
         use std::collections::hash_map::DefaultHasher;
@@ -163,103 +596,102 @@ impl PocketBaseManager {
         hasher.finish() as u32
     }
 
-    /// Check if a port is available
-    async fn is_port_available(&self, port: u16) -> bool {
-        match tokio::net::TcpListener::bind(format!("127.0.0.1:{}", port)).await {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+    /// Bind a placeholder listener on a port if it is free
+    fn reserve_port(&self, port: u16) -> Option<std::net::TcpListener> {
+        std::net::TcpListener::bind((self.bind_host.as_str(), port)).ok()
     }
 
-    /// Start a PocketBase process for an instance
-    async fn start_pocketbase_process(&self, instance: &PocketBaseInstance) -> Result<Child, PocketBaseError> {
+    /// Run the PocketBase binary with `args` against an instance's data directory
+    ///
+    /// Only [`INHERITED_ENV`] reaches the process, plus the settings
+    /// encryption key when one is configured.
+    async fn pocketbase_command(&self, instance: &PocketBaseInstance, args: &[&str]) -> Command {
         let mut cmd = Command::new(&self.binary_path);
-        cmd.arg("serve")
-            .arg("--http")
-            .arg(format!("127.0.0.1:{}", instance.port))
+        cmd.args(args)
             .arg("--dir")
-            .arg(&instance.db_path.parent().unwrap_or_else(|| std::path::Path::new(".")))
-            .env("PB_DATA", &instance.db_path.parent().unwrap_or_else(|| std::path::Path::new(".")))
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true);
+            .arg(&instance.db_path)
+            .current_dir(&instance.db_path)
+            .env_clear();
+        for (name, value) in std::env::vars_os() {
+            if name.to_str().is_some_and(|name| INHERITED_ENV.contains(&name)) {
+                cmd.env(name, value);
+            }
+        }
+        cmd.env("PB_DATA", &instance.db_path);
 
-        debug!("Starting PocketBase with command: {:?}", cmd);
+        if let Some(master_key) = &self.encryption_key {
+            let key_id = match fs::read_to_string(instance.db_path.join(KEY_ID_FILE)).await {
+                Ok(key_id) => key_id.trim().to_string(),
+                Err(_) => instance.user_id.clone(),
+            };
+            cmd.arg("--encryptionEnv")
+                .arg(ENCRYPTION_ENV)
+                .env(ENCRYPTION_ENV, instance_encryption_key(master_key, &key_id));
+        }
+        cmd
+    }
 
-        let child = cmd.spawn()
-            .map_err(|e| PocketBaseError::ProcessError(format!("Failed to spawn PocketBase process: {}", e)))?;
+    /// Start a PocketBase process for an instance
+    ///
+    /// Returns the child along with a buffer collecting the tail of its stderr.
+    async fn start_pocketbase_process(
+        &self,
+        instance: &PocketBaseInstance,
+    ) -> Result<(Child, StderrTail), PocketBaseError> {
+        let address = self.listen_address(instance.port);
+        let mut cmd = self.pocketbase_command(instance, &["serve", "--http", &address]).await;
+        cmd.stdout(Stdio::null()).stderr(Stdio::piped()).kill_on_drop(true);
 
-        // Give PocketBase time to start
-        sleep(Duration::from_secs(2)).await;
+        // Not the Command itself: its Debug output includes the environment
+        debug!(
+            "Starting PocketBase for {} with {} serve --http {} --dir {}",
+            instance.user_id,
+            self.binary_path,
+            address,
+            instance.db_path.display()
+        );
 
-        Ok(child)
-    }
+        // Release the placeholder listener and spawn under the same lock so no
+        // other allocation can observe the port as free in between
+        let mut child = {
+            let mut ports = self.allocated_ports.lock().await;
+            if let Some(reservation) = ports.get_mut(&instance.port) {
+                drop(reservation.take());
+            }
+            cmd.spawn()
+                .map_err(|e| PocketBaseError::ProcessError(format!("Failed to spawn PocketBase process: {}", e)))?
+        };
 
-    /// Start health monitoring for all instances
-    pub async fn start_health_monitoring(&self) {
-        let instances = Arc::clone(&self.instances);
-        let processes = Arc::clone(&self.processes);
-        
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(30)); // Check every 30 seconds
-            
-            loop {
-                interval.tick().await;
-                
-                let instance_ids: Vec<String> = {
-                    let instances_guard = instances.read().await;
-                    instances_guard.keys().cloned().collect()
-                };
+        let stderr_tail = StderrTail::default();
+        if let Some(stderr) = child.stderr.take() {
+            stderr_tail.follow(instance.user_id.clone(), stderr);
+        }
 
-                for user_id in instance_ids {
-                    // Check if process is still alive
-                    let process_alive = {
-                        let mut processes_guard = processes.lock().await;
-                        if let Some(child) = processes_guard.get_mut(&user_id) {
-                            match child.try_wait() {
-                                Ok(None) => true, // Still running
-                                Ok(Some(status)) => {
-                                    warn!("PocketBase process for user {} exited with status: {:?}", user_id, status);
-                                    false
-                                }
-                                Err(e) => {
-                                    error!("Error checking process status for user {}: {}", user_id, e);
-                                    false
-                                }
-                            }
-                        } else {
-                            false
-                        }
-                    };
+        Ok((child, stderr_tail))
+    }
 
-                    // Update instance status and potentially restart
-                    {
-                        let mut instances_guard = instances.write().await;
-                        if let Some(instance) = instances_guard.get_mut(&user_id) {
-                            if !process_alive && instance.status == InstanceStatus::Running {
-                                warn!("Detected failed PocketBase instance for user: {}", user_id);
-                                instance.status = InstanceStatus::Failed;
-                                
-                                // TODO: Implement auto-restart logic here
-                                // For now, just log the failure
-                                error!("PocketBase instance for user {} needs restart", user_id);
-                            } else if process_alive {
-                                // Perform HTTP health check
-                                if Self::health_check_http(&instance.url).await {
-                                    instance.last_health_check = Some(chrono::Utc::now());
-                                    if instance.status != InstanceStatus::Running {
-                                        info!("PocketBase instance for user {} is now healthy", user_id);
-                                        instance.status = InstanceStatus::Running;
-                                    }
-                                } else {
-                                    warn!("PocketBase instance for user {} failed health check", user_id);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    /// Gather process and disk usage for an instance (blocking)
+    fn collect_stats(instance: &PocketBaseInstance, pid: Option<u32>) -> InstanceStats {
+        let (db_size_bytes, db_file_count) = stats::dir_size(&instance.db_path).unwrap_or_else(|e| {
+            warn!("Failed to measure data directory {}: {}", instance.db_path.display(), e);
+            (0, 0)
         });
+
+        let now = chrono::Utc::now();
+        InstanceStats {
+            user_id: instance.user_id.clone(),
+            uptime_seconds: (now - instance.created_at).num_seconds().max(0),
+            restart_count: instance.restart_count,
+            process: stats::read_process_metrics(pid),
+            db_size_bytes,
+            db_file_count,
+            collected_at: now,
+        }
+    }
+
+    /// Latest resource usage collected by the health monitor
+    pub async fn get_instance_stats(&self, user_id: &str) -> Option<InstanceStats> {
+        self.stats.read().await.get(user_id).cloned()
     }
 
     /// Perform HTTP health check on a PocketBase instance
@@ -277,6 +709,328 @@ impl PocketBaseManager {
         }
     }
 
+    /// Return the user's instance, starting it if it is not running
+    pub async fn ensure_running(&self, user_id: &str) -> Result<PocketBaseInstance, PocketBaseError> {
+        if let Some(instance) = self.get_user_instance(user_id).await {
+            if instance.status == InstanceStatus::Running {
+                return Ok(instance);
+            }
+        }
+
+        self.init_user_instance(user_id).await
+    }
+
+    /// Admin client for a user's instance, starting the instance if needed
+    ///
+    /// The first call for a user in this process provisions the backend's
+    /// admin account, see [`Self::provision_admin`]: created over the fresh
+    /// instance's API with `create_first_admin`, or reset with `pocketbase
+    /// admin update` when it exists under another password. Its password is
+    /// derived from the master secret, so it stays the same across restarts
+    /// when one is configured.
+    pub async fn admin_client(&self, user_id: &str) -> Result<Arc<GlobalPb>, PocketBaseError> {
+        let instance = self.ensure_running(user_id).await?;
+        let url = self.instance_url(instance.port);
+        let password = self.instance_admin_password(user_id);
+
+        let mut clients = self.admin_clients.lock().await;
+        match clients.get(user_id) {
+            Some(client) if client.url() == url => return Ok(client.clone()),
+            // Restarted on another port; the account lives in the data directory
+            Some(_) => {}
+            None => {
+                let client = GlobalPb::new(&url, INSTANCE_ADMIN_EMAIL, &password);
+                self.provision_admin(&instance, &client, &password).await?;
+            }
+        }
+        let client = Arc::new(GlobalPb::new(&url, INSTANCE_ADMIN_EMAIL, &password));
+        clients.insert(user_id.to_string(), client.clone());
+        Ok(client)
+    }
+
+    fn instance_admin_password(&self, user_id: &str) -> String {
+        let secret = self.encryption_key.as_deref().unwrap_or(&self.process_secret);
+        instance_encryption_key(secret, &format!("admin:{}", user_id))
+    }
+
+    /// Create or reset the backend's admin account in an instance
+    ///
+    /// A fresh instance gets the account through its admin API, the password
+    /// in the request body. Only an account already there under another
+    /// password, such as one restored from a backup made with another master
+    /// secret, is reset with `pocketbase admin update`; while that runs the
+    /// password is in its command line, which local users can read.
+    ///
+    /// The admin API takes the first account from whoever asks once the
+    /// instance listens, so afterwards the admin list must hold the backend's
+    /// account alone; anyone else who got in first fails the provisioning.
+    async fn provision_admin(
+        &self,
+        instance: &PocketBaseInstance,
+        client: &GlobalPb,
+        password: &str,
+    ) -> Result<(), PocketBaseError> {
+        let created = client.create_first_admin().await;
+        if created.is_ok() || client.ping().await.is_ok() {
+            debug!("Provisioned the admin account of {} over its API", instance.user_id);
+            return self.ensure_sole_admin(instance, client).await;
+        }
+
+        warn!(
+            "The admin account of {} has another password; resetting it with pocketbase admin update",
+            instance.user_id
+        );
+        let output = self
+            .pocketbase_command(instance, &["admin", "update", INSTANCE_ADMIN_EMAIL, password])
+            .await
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| PocketBaseError::ProcessError(format!("Failed to run PocketBase admin update: {}", e)))?;
+        if output.status.success() {
+            return self.ensure_sole_admin(instance, client).await;
+        }
+        let created_error = created.err().map(|e| e.to_string()).unwrap_or_default();
+        Err(PocketBaseError::ProcessError(format!(
+            "Failed to provision the admin account of {}: {}; {}",
+            instance.user_id,
+            created_error,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+
+    /// Fail unless the backend's account is the instance's only admin
+    async fn ensure_sole_admin(&self, instance: &PocketBaseInstance, client: &GlobalPb) -> Result<(), PocketBaseError> {
+        let emails = client.list_admin_emails().await.map_err(|e| {
+            PocketBaseError::ProcessError(format!("Failed to list the admins of {}: {}", instance.user_id, e))
+        })?;
+        let foreign: Vec<String> = emails.into_iter().filter(|email| email != INSTANCE_ADMIN_EMAIL).collect();
+        if foreign.is_empty() {
+            return Ok(());
+        }
+        error!(
+            target: "audit",
+            user_id = %instance.user_id,
+            "Instance has admin accounts the backend didn't create: {}",
+            foreign.join(", ")
+        );
+        Err(PocketBaseError::ForeignAdmins {
+            user_id: instance.user_id.clone(),
+            emails: foreign.join(", "),
+        })
+    }
+
+    /// Poll a freshly started child until its health endpoint answers
+    ///
+    /// Fails early if the process exits while we are waiting, reporting
+    /// [`PocketBaseError::PortInUse`] when its stderr shows a bind failure.
+    async fn wait_for_ready(
+        &self,
+        child: &mut Child,
+        url: &str,
+        stderr_tail: &StderrTail,
+    ) -> Result<(), PocketBaseError> {
+        let deadline = Instant::now() + self.readiness_timeout;
+
+        loop {
+            if let Ok(Some(status)) = child.try_wait() {
+                // Give the stderr reader a moment to drain what the child wrote
+                let output = stderr_tail.wait_for_eof(Duration::from_millis(500)).await;
+                if output.to_ascii_lowercase().contains("address already in use") {
+                    let port = url.rsplit(':').next().and_then(|port| port.parse().ok()).unwrap_or(0);
+                    return Err(PocketBaseError::PortInUse(port));
+                }
+                return Err(PocketBaseError::ProcessError(format!(
+                    "PocketBase exited during startup with {}: {}",
+                    status,
+                    output.trim()
+                )));
+            }
+
+            if Self::health_check_http(url).await {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                return Err(PocketBaseError::HealthCheckFailed);
+            }
+
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Replace a user's data directory with the contents of a backup archive
+    ///
+    /// The current data directory is moved aside and only deleted once the
+    /// restored instance is verified ready. Any failure after that point puts
+    /// the safety copy back and restarts the instance if it was running.
+    /// The user's `inflight` slot is held from the stop to the restart, so an
+    /// initialization asked for meanwhile waits and never starts PocketBase
+    /// on a half-unpacked directory.
+    pub async fn restore_user_instance(
+        &self,
+        user_id: &str,
+        source: RestoreSource,
+    ) -> Result<RestoreReport, PocketBaseError> {
+        let data_dir = self.checked_data_dir(user_id).await?;
+        info!("Restoring PocketBase instance for user: {}", user_id);
+
+        let bytes = match source {
+            RestoreSource::Backup(backup_id) => self.read_backup(user_id, &backup_id).await?,
+            RestoreSource::Archive(bytes) => bytes,
+        };
+        let bytes = Arc::new(bytes);
+
+        // Reject corrupt or malicious archives before touching anything
+        {
+            let bytes = Arc::clone(&bytes);
+            tokio::task::spawn_blocking(move || archive::validate_archive(&bytes))
+                .await
+                .map_err(|e| PocketBaseError::IoError(format!("Archive validation panicked: {}", e)))??;
+        }
+
+        let safety_dir = self
+            .user_dbs_path
+            .join(format!("pb_user_{}.db.restore-safety", user_id));
+
+        if fs::try_exists(&safety_dir).await.unwrap_or(false) {
+            return Err(PocketBaseError::RestoreFailed {
+                stage: "safety copy".to_string(),
+                reason: format!(
+                    "A previous restore left {} behind; resolve it manually before retrying",
+                    safety_dir.display()
+                ),
+            });
+        }
+
+        // Declared in this order so the slot is free before waiters wake up
+        // and ask again
+        let _slot = self.claim_inflight(user_id).await;
+        let _guard = InflightGuard {
+            inflight: &self.inflight,
+            user_id,
+        };
+
+        let was_running = self
+            .get_user_instance(user_id)
+            .await
+            .map(|instance| instance.status == InstanceStatus::Running)
+            .unwrap_or(false);
+        self.stop_user_instance(user_id).await?;
+
+        let had_data = fs::try_exists(&data_dir).await.unwrap_or(false);
+        if had_data {
+            fs::rename(&data_dir, &safety_dir).await.map_err(|e| PocketBaseError::RestoreFailed {
+                stage: "safety copy".to_string(),
+                reason: e.to_string(),
+            })?;
+        }
+
+        match self.unpack_and_start(user_id, &data_dir, bytes).await {
+            Ok((entries_restored, instance)) => {
+                if had_data {
+                    if let Err(e) = fs::remove_dir_all(&safety_dir).await {
+                        warn!("Failed to remove restore safety copy {}: {}", safety_dir.display(), e);
+                    }
+                }
+                // The backup may hold another admin password, or none
+                self.admin_clients.lock().await.remove(user_id);
+                info!("Restored {} entries for user {}", entries_restored, user_id);
+                Ok(RestoreReport {
+                    user_id: user_id.to_string(),
+                    entries_restored,
+                    instance,
+                })
+            }
+            Err((stage, cause)) => {
+                error!("Restore for user {} failed during {}: {}", user_id, stage, cause);
+                let rollback = self
+                    .rollback_restore(user_id, &data_dir, &safety_dir, had_data, was_running)
+                    .await;
+
+                let reason = match rollback {
+                    Ok(()) => format!("{}; previous data restored", cause),
+                    Err(rollback_error) => format!("{}; rollback also failed: {}", cause, rollback_error),
+                };
+                Err(PocketBaseError::RestoreFailed {
+                    stage: stage.to_string(),
+                    reason,
+                })
+            }
+        }
+    }
+
+    /// Load a stored backup archive by id
+    async fn read_backup(&self, user_id: &str, backup_id: &str) -> Result<Vec<u8>, PocketBaseError> {
+        let valid_id = !backup_id.is_empty()
+            && !backup_id.contains("..")
+            && backup_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid_id {
+            return Err(PocketBaseError::BackupNotFound(backup_id.to_string()));
+        }
+
+        let path = self.backups_dir(user_id).join(format!("{}.tar.gz", backup_id));
+        fs::read(&path)
+            .await
+            .map_err(|_| PocketBaseError::BackupNotFound(backup_id.to_string()))
+    }
+
+    /// Unpack into a fresh data directory and bring the instance up
+    async fn unpack_and_start(
+        &self,
+        user_id: &str,
+        data_dir: &Path,
+        bytes: Arc<Vec<u8>>,
+    ) -> Result<(usize, PocketBaseInstance), (&'static str, PocketBaseError)> {
+        fs::create_dir_all(data_dir)
+            .await
+            .map_err(|e| ("unpack", PocketBaseError::IoError(e.to_string())))?;
+
+        let dest = data_dir.to_path_buf();
+        let limits = self.unpack_limits;
+        let entries = tokio::task::spawn_blocking(move || archive::unpack_archive(&bytes, &dest, limits))
+            .await
+            .map_err(|e| ("unpack", PocketBaseError::IoError(e.to_string())))?
+            .map_err(|e| ("unpack", e))?;
+
+        let instance = self
+            .start_recorded(user_id)
+            .await
+            .map_err(|e| ("restart", e))?;
+
+        Ok((entries, instance))
+    }
+
+    /// Put the safety copy back after a failed restore
+    async fn rollback_restore(
+        &self,
+        user_id: &str,
+        data_dir: &Path,
+        safety_dir: &Path,
+        had_data: bool,
+        was_running: bool,
+    ) -> Result<(), PocketBaseError> {
+        self.stop_user_instance(user_id).await?;
+
+        if fs::try_exists(data_dir).await.unwrap_or(false) {
+            fs::remove_dir_all(data_dir)
+                .await
+                .map_err(|e| PocketBaseError::IoError(format!("Failed to discard restored data: {}", e)))?;
+        }
+        if had_data {
+            fs::rename(safety_dir, data_dir)
+                .await
+                .map_err(|e| PocketBaseError::IoError(format!("Failed to reinstate safety copy: {}", e)))?;
+        }
+        if was_running {
+            self.start_recorded(user_id).await?;
+        }
+
+        Ok(())
+    }
+
     /// Get instance information for a user
     pub async fn get_user_instance(&self, user_id: &str) -> Option<PocketBaseInstance> {
         let instances = self.instances.read().await;
@@ -285,6 +1039,7 @@ impl PocketBaseManager {
 
     /// Stop a user's PocketBase instance
     pub async fn stop_user_instance(&self, user_id: &str) -> Result<(), PocketBaseError> {
+        sanitize_user_id(user_id)?;
         info!("Stopping PocketBase instance for user: {}", user_id);
 
         // Kill the process
@@ -301,7 +1056,10 @@ impl PocketBaseManager {
         {
             let mut instances = self.instances.write().await;
             if let Some(instance) = instances.get_mut(user_id) {
-                instance.status = InstanceStatus::Stopped;
+                if instance.status != InstanceStatus::Stopped {
+                    instance.status = InstanceStatus::Stopped;
+                    self.emit(SystemEventType::InstanceStopped, user_id, instance.port);
+                }
                 
                 // Release the port
                 let mut ports = self.allocated_ports.lock().await;
@@ -312,6 +1070,120 @@ impl PocketBaseManager {
         Ok(())
     }
 
+    /// Permanently remove a user's instance, data directory and backups
+    pub async fn delete_user_instance(&self, user_id: &str) -> Result<DeletionReport, PocketBaseError> {
+        info!("Deleting PocketBase instance and data for user: {}", user_id);
+
+        // Validate every path before anything is stopped or removed
+        let targets = [
+            self.checked_data_dir(user_id).await?,
+            self.checked_backups_dir(user_id).await?,
+        ];
+
+        self.stop_user_instance(user_id).await?;
+        self.instances.write().await.remove(user_id);
+        self.stats.write().await.remove(user_id);
+        self.admin_clients.lock().await.remove(user_id);
+
+        let mut report = DeletionReport {
+            user_id: user_id.to_string(),
+            ..Default::default()
+        };
+        for path in targets {
+            let measured = path.clone();
+            let (bytes, files) = tokio::task::spawn_blocking(move || stats::dir_size(&measured))
+                .await
+                .map_err(|e| PocketBaseError::IoError(e.to_string()))?
+                .map_err(|e| PocketBaseError::IoError(format!("Failed to measure {}: {}", path.display(), e)))?;
+
+            match fs::remove_dir_all(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(PocketBaseError::IoError(format!(
+                        "Failed to remove {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+            report.bytes_removed += bytes;
+            report.files_removed += files;
+        }
+
+        Ok(report)
+    }
+
+    /// Stop every child process, giving each `grace` to exit after SIGTERM
+    ///
+    /// Instances that exit in time are recorded as `Stopped`; stragglers are
+    /// killed and recorded as `Failed` since they may have been mid-write.
+    pub async fn shutdown_all(&self, grace: Duration) -> ShutdownReport {
+        // Stop health monitors so they don't flag the exits below as failures
+        self.monitor_token.cancel();
+        self.drain_warm_pool().await;
+
+        let children: Vec<(String, Child)> = self.processes.lock().await.drain().collect();
+        info!("Shutting down {} PocketBase instances", children.len());
+
+        for (user_id, child) in &children {
+            request_termination(user_id, child);
+        }
+
+        let deadline = Instant::now() + grace;
+        let outcomes = futures::future::join_all(children.into_iter().map(|(user_id, mut child)| async move {
+            match tokio::time::timeout_at(deadline, child.wait()).await {
+                Ok(_) => (user_id, true),
+                Err(_) => {
+                    warn!("PocketBase for user {} did not exit within {:?}, killing it", user_id, grace);
+                    if let Err(e) = child.kill().await {
+                        error!("Failed to kill PocketBase process for user {}: {}", user_id, e);
+                    }
+                    (user_id, false)
+                }
+            }
+        }))
+        .await;
+
+        let mut report = ShutdownReport::default();
+        {
+            let mut instances = self.instances.write().await;
+            for (user_id, graceful) in outcomes {
+                if let Some(instance) = instances.get_mut(&user_id) {
+                    let event_type = if graceful {
+                        instance.status = InstanceStatus::Stopped;
+                        SystemEventType::InstanceStopped
+                    } else {
+                        instance.status = InstanceStatus::Failed;
+                        SystemEventType::InstanceFailed {
+                            reason: "Killed after shutdown grace period".to_string(),
+                        }
+                    };
+                    self.emit(event_type, &user_id, instance.port);
+                }
+                if graceful {
+                    report.graceful.push(user_id);
+                } else {
+                    report.forced.push(user_id);
+                }
+            }
+            for instance in instances.values_mut() {
+                if instance.status == InstanceStatus::Running || instance.status == InstanceStatus::Starting {
+                    instance.status = InstanceStatus::Stopped;
+                    self.emit(SystemEventType::InstanceStopped, &instance.user_id, instance.port);
+                }
+            }
+        }
+        self.allocated_ports.lock().await.clear();
+
+        info!(
+            "PocketBase shutdown complete: {} graceful, {} forced",
+            report.graceful.len(),
+            report.forced.len()
+        );
+        report
+    }
+
     /// Get all running instances
     pub async fn get_all_instances(&self) -> HashMap<String, PocketBaseInstance> {
         let instances = self.instances.read().await;
@@ -319,7 +1191,160 @@ impl PocketBaseManager {
     }
 }
 
-#[derive(Debug, thiserror::Error)]
+/// Settings encryption key for the instance whose key id is `key_id`
+///
+/// The key is HMAC-SHA256(master_key, "pocketbase-instance:" + key_id),
+/// hex-encoded and truncated to the 32 characters PocketBase expects. It is
+/// recomputed on every start and only ever handed to the child through its
+/// environment, so nothing secret is written to disk. The key id is the
+/// user id, except for directories that started life in the warm pool,
+/// which keep the pool id recorded in their `.encryption_key_id` file.
+pub fn instance_encryption_key(master_key: &str, key_id: &str) -> String {
+    use hmac::{Hmac, Mac};
+
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(master_key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"pocketbase-instance:");
+    mac.update(key_id.as_bytes());
+    mac.finalize().into_bytes()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `host:port`, with an IPv6 host in brackets
+fn host_and_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Accept only user ids that are safe to embed in a file name
+///
+/// Ids must match `[A-Za-z0-9_-]{1,64}`; PocketBase record ids always do.
+pub fn sanitize_user_id(user_id: &str) -> Result<&str, PocketBaseError> {
+    let valid = (1..=64).contains(&user_id.len())
+        && user_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-'));
+    if valid {
+        Ok(user_id)
+    } else {
+        Err(PocketBaseError::InvalidUserId(user_id.chars().take(64).collect()))
+    }
+}
+
+/// Canonicalize `path` and check it lies strictly below the canonical `base`
+///
+/// Trailing components that don't exist yet are resolved against their
+/// deepest existing ancestor, so this works before a directory is created.
+/// `base` itself is created if missing so it can be canonicalized.
+fn resolve_within(base: &Path, path: &Path) -> Result<PathBuf, PocketBaseError> {
+    let unsafe_path = || PocketBaseError::UnsafePath(path.display().to_string());
+
+    std::fs::create_dir_all(base)
+        .map_err(|e| PocketBaseError::IoError(format!("Failed to create {}: {}", base.display(), e)))?;
+    let base = std::fs::canonicalize(base).map_err(|e| PocketBaseError::IoError(e.to_string()))?;
+
+    let mut existing = path;
+    let mut missing = Vec::new();
+    let resolved = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            // A dangling symlink exists but can't be resolved; never follow it
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound && std::fs::symlink_metadata(existing).is_err() =>
+            {
+                match (existing.parent(), existing.components().next_back()) {
+                    (Some(parent), Some(std::path::Component::Normal(name))) => {
+                        missing.push(name.to_os_string());
+                        existing = parent;
+                    }
+                    _ => return Err(unsafe_path()),
+                }
+            }
+            Err(_) => return Err(unsafe_path()),
+        }
+    };
+    let resolved = missing.into_iter().rev().fold(resolved, |path, name| path.join(name));
+
+    if resolved != base && resolved.starts_with(&base) {
+        Ok(resolved)
+    } else {
+        Err(unsafe_path())
+    }
+}
+
+/// Publish a lifecycle event if a broadcast service is configured
+fn emit_event(broadcast: Option<&BroadcastService>, event_type: SystemEventType, user_id: &str, port: u16) {
+    if let Some(broadcast) = broadcast {
+        broadcast.broadcast_system(SystemEvent::new(event_type, user_id, port));
+    }
+}
+
+/// Last lines a child wrote to stderr, collected in the background
+#[derive(Clone, Default)]
+struct StderrTail {
+    lines: Arc<std::sync::Mutex<VecDeque<String>>>,
+    eof: Arc<tokio::sync::Notify>,
+    done: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl StderrTail {
+    const MAX_LINES: usize = 20;
+
+    /// Drain `stderr` so the child never blocks on a full pipe
+    fn follow(&self, user_id: String, stderr: tokio::process::ChildStderr) {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let tail = self.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("pocketbase[{}]: {}", user_id, line);
+                let mut buffer = tail.lines.lock().unwrap();
+                if buffer.len() == Self::MAX_LINES {
+                    buffer.pop_front();
+                }
+                buffer.push_back(line);
+            }
+            tail.done.store(true, std::sync::atomic::Ordering::SeqCst);
+            tail.eof.notify_waiters();
+        });
+    }
+
+    /// Collected output once the stream has closed, or after `timeout`
+    async fn wait_for_eof(&self, timeout: Duration) -> String {
+        let notified = self.eof.notified();
+        if !self.done.load(std::sync::atomic::Ordering::SeqCst) {
+            let _ = tokio::time::timeout(timeout, notified).await;
+        }
+        self.lines.lock().unwrap().iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Ask a child process to exit cleanly
+#[cfg(unix)]
+fn request_termination(user_id: &str, child: &Child) {
+    use nix::{
+        sys::signal::{kill, Signal},
+        unistd::Pid,
+    };
+
+    if let Some(pid) = child.id() {
+        if let Err(e) = kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+            warn!("Failed to send SIGTERM to PocketBase for user {}: {}", user_id, e);
+        }
+    }
+}
+
+/// Without signals the grace period is simply waited out before killing
+#[cfg(not(unix))]
+fn request_termination(_user_id: &str, _child: &Child) {}
+
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum PocketBaseError {
     #[error("IO error: {0}")]
     IoError(String),
@@ -329,10 +1354,670 @@ pub enum PocketBaseError {
     
     #[error("No ports available in the allocated range")]
     NoPortsAvailable,
+
+    #[error("Port {0} was already in use when PocketBase tried to bind it")]
+    PortInUse(u16),
     
     #[error("Instance not found for user: {0}")]
     InstanceNotFound(String),
     
     #[error("Health check failed")]
     HealthCheckFailed,
+
+    #[error("Invalid user id {0:?}: expected 1-64 characters from [A-Za-z0-9_-]")]
+    InvalidUserId(String),
+
+    #[error("Refusing to touch path outside the user database directory: {0}")]
+    UnsafePath(String),
+
+    #[error("Backup not found: {0}")]
+    BackupNotFound(String),
+
+    #[error("Invalid backup archive: {0}")]
+    InvalidArchive(String),
+
+    #[error("Restore failed during {stage}: {reason}")]
+    RestoreFailed { stage: String, reason: String },
+
+    #[error("PocketBase binary unavailable: {0}")]
+    BinaryUnavailable(String),
+
+    #[error("PocketBase {found} is not supported; use a version {supported}")]
+    UnsupportedVersion { found: String, supported: String },
+
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("Failed to download PocketBase: {0}")]
+    DownloadFailed(String),
+
+    #[error("The instance of {user_id} has admin accounts the backend didn't create: {emails}")]
+    ForeignAdmins { user_id: String, emails: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_archive, fake_pocketbase, port_window};
+
+    /// A manager handing out ten ports no other test uses
+    fn test_manager(dir: &Path) -> PocketBaseManager {
+        let binary = fake_pocketbase(dir);
+        let port = port_window(10);
+        PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 9)
+            .with_readiness_timeout(Duration::from_secs(10))
+    }
+
+    #[tokio::test]
+    async fn test_restore_replaces_data_and_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let instance = manager.init_user_instance("alice").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), b"old").unwrap();
+
+        let archive = build_archive(&[("data.db", b"restored"), ("storage/file.txt", b"f")]);
+        let report = manager
+            .restore_user_instance("alice", RestoreSource::Archive(archive))
+            .await
+            .unwrap();
+
+        assert_eq!(report.entries_restored, 2);
+        assert_eq!(report.instance.status, InstanceStatus::Running);
+        let data_dir = manager.data_dir("alice");
+        assert_eq!(std::fs::read(data_dir.join("data.db")).unwrap(), b"restored");
+        assert!(!dir.path().join("user_dbs/pb_user_alice.db.restore-safety").exists());
+
+        manager.stop_user_instance("alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inits_during_a_restore_wait_for_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(test_manager(dir.path()));
+        let instance = manager.init_user_instance("olga").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), b"old").unwrap();
+
+        let archive = build_archive(&[("data.db", b"restored")]);
+        let restore = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move { manager.restore_user_instance("olga", RestoreSource::Archive(archive)).await }
+        });
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while manager.startup_status("olga").await != Some(InstanceStatus::Starting) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("the restore holds the user's slot");
+
+        // Joins once the restored instance is up, rather than starting its own
+        let joined = manager.init_user_instance("olga").await.unwrap();
+        assert_eq!(std::fs::read(joined.db_path.join("data.db")).unwrap(), b"restored");
+        let report = restore.await.unwrap().unwrap();
+        assert_eq!(joined.port, report.instance.port);
+        assert_eq!(manager.startup_status("olga").await, Some(InstanceStatus::Running));
+
+        manager.shutdown_all(Duration::from_secs(5)).await;
+    }
+
+    #[tokio::test]
+    async fn test_restore_rolls_back_when_instance_fails_to_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let instance = manager.init_user_instance("bob").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), b"original").unwrap();
+
+        // The fake binary refuses to start when it finds FAIL_START
+        let archive = build_archive(&[("data.db", b"broken"), ("FAIL_START", b"")]);
+        let err = manager
+            .restore_user_instance("bob", RestoreSource::Archive(archive))
+            .await
+            .unwrap_err();
+
+        match err {
+            PocketBaseError::RestoreFailed { stage, reason } => {
+                assert_eq!(stage, "restart");
+                assert!(reason.contains("previous data restored"), "{}", reason);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let data_dir = manager.data_dir("bob");
+        assert_eq!(std::fs::read(data_dir.join("data.db")).unwrap(), b"original");
+        assert!(!data_dir.join("FAIL_START").exists());
+        let instance = manager.get_user_instance("bob").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+
+        manager.stop_user_instance("bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_rolls_back_an_archive_over_the_unpack_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path())
+            .with_unpack_limits(archive::UnpackLimits { max_bytes: 1024, max_entries: 10 });
+
+        let instance = manager.init_user_instance("dave").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), b"original").unwrap();
+
+        let archive = build_archive(&[("data.db", b"small"), ("storage/big.bin", &[0u8; 4096])]);
+        let err = manager
+            .restore_user_instance("dave", RestoreSource::Archive(archive))
+            .await
+            .unwrap_err();
+
+        match err {
+            PocketBaseError::RestoreFailed { stage, reason } => {
+                assert_eq!(stage, "unpack");
+                assert!(reason.contains("previous data restored"), "{}", reason);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let data_dir = manager.data_dir("dave");
+        assert_eq!(std::fs::read(data_dir.join("data.db")).unwrap(), b"original");
+        assert!(!data_dir.join("storage").exists());
+        assert!(!dir.path().join("user_dbs/pb_user_dave.db.restore-safety").exists());
+        let instance = manager.get_user_instance("dave").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+
+        manager.stop_user_instance("dave").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_rejects_malicious_archive_without_stopping() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let instance = manager.init_user_instance("carol").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), b"original").unwrap();
+
+        let archive = build_archive(&[("data.db", b"evil"), ("../../escape.txt", b"evil")]);
+        let err = manager
+            .restore_user_instance("carol", RestoreSource::Archive(archive))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, PocketBaseError::InvalidArchive(_)));
+        assert_eq!(std::fs::read(instance.db_path.join("data.db")).unwrap(), b"original");
+        assert!(!dir.path().join("escape.txt").exists());
+        let instance = manager.get_user_instance("carol").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+
+        manager.stop_user_instance("carol").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_instance_data_and_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let instance = manager.init_user_instance("erin").await.unwrap();
+        std::fs::write(instance.db_path.join("data.db"), vec![0u8; 100]).unwrap();
+        std::fs::create_dir_all(instance.db_path.join("storage")).unwrap();
+        std::fs::write(instance.db_path.join("storage/upload.bin"), vec![0u8; 50]).unwrap();
+        std::fs::create_dir_all(manager.backups_dir("erin")).unwrap();
+        std::fs::write(manager.backups_dir("erin").join("nightly.tar.gz"), vec![0u8; 25]).unwrap();
+
+        let report = manager.delete_user_instance("erin").await.unwrap();
+
+        assert_eq!(report.files_removed, 3);
+        assert_eq!(report.bytes_removed, 175);
+        assert!(!manager.data_dir("erin").exists());
+        assert!(!manager.backups_dir("erin").exists());
+        assert!(manager.get_user_instance("erin").await.is_none());
+        assert!(!manager.allocated_ports.lock().await.contains_key(&instance.port));
+    }
+
+    #[tokio::test]
+    async fn test_delete_rejects_paths_outside_user_dbs() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+        std::fs::create_dir_all(dir.path().join("victim.db")).unwrap();
+        std::fs::write(dir.path().join("victim.db/keep.txt"), b"keep").unwrap();
+
+        // user_dbs/pb_user_/../../victim.db would resolve to a sibling of user_dbs
+        let err = manager.delete_user_instance("/../../victim").await.unwrap_err();
+        assert!(matches!(err, PocketBaseError::InvalidUserId(_)));
+        assert!(dir.path().join("victim.db/keep.txt").exists());
+
+        // A symlinked data directory pointing outside is caught after resolution
+        std::fs::create_dir_all(dir.path().join("user_dbs")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("victim.db"), manager.data_dir("victim")).unwrap();
+        let err = manager.delete_user_instance("victim").await.unwrap_err();
+        assert!(matches!(err, PocketBaseError::UnsafePath(_)));
+        assert!(dir.path().join("victim.db/keep.txt").exists());
+    }
+
+    #[test]
+    fn test_sanitize_user_id() {
+        for valid in ["abc123def456ghi", "user_1", "A-b_C", &"x".repeat(64)] {
+            assert_eq!(sanitize_user_id(valid).unwrap(), valid);
+        }
+
+        for traversal in ["../../etc", "..", "a/b", "a\\b", "/etc/passwd", "user.db"] {
+            assert!(matches!(sanitize_user_id(traversal), Err(PocketBaseError::InvalidUserId(_))), "{}", traversal);
+        }
+
+        for unicode in ["ålice", "user\u{202e}", "ｕｓｅｒ", "用户"] {
+            assert!(matches!(sanitize_user_id(unicode), Err(PocketBaseError::InvalidUserId(_))), "{}", unicode);
+        }
+
+        assert!(sanitize_user_id("").is_err());
+        assert!(sanitize_user_id(&"x".repeat(65)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_init_rejects_traversal_before_touching_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let err = manager.init_user_instance("../../escaped").await.unwrap_err();
+        assert!(matches!(err, PocketBaseError::InvalidUserId(_)));
+        assert!(!dir.path().join("user_dbs").exists());
+        assert!(manager.allocated_ports.lock().await.is_empty());
+    }
+
+    #[test]
+    fn test_containment_follows_symlinked_base_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real_user_dbs");
+        std::fs::create_dir_all(&real).unwrap();
+        let linked = dir.path().join("user_dbs");
+        std::os::unix::fs::symlink(&real, &linked).unwrap();
+
+        // Paths under a symlinked base resolve into the real directory
+        let resolved = resolve_within(&linked, &linked.join("pb_user_alice.db")).unwrap();
+        assert_eq!(resolved, real.canonicalize().unwrap().join("pb_user_alice.db"));
+        let nested = resolve_within(&linked, &linked.join("backups/pb_user_alice")).unwrap();
+        assert!(nested.starts_with(real.canonicalize().unwrap()));
+
+        // The base itself and anything that resolves outside are rejected
+        assert!(resolve_within(&linked, &linked).is_err());
+        std::os::unix::fs::symlink(dir.path(), real.join("pb_user_out.db")).unwrap();
+        assert!(matches!(
+            resolve_within(&linked, &linked.join("pb_user_out.db")),
+            Err(PocketBaseError::UnsafePath(_))
+        ));
+        std::os::unix::fs::symlink(dir.path().join("missing"), real.join("pb_user_dangling.db")).unwrap();
+        assert!(resolve_within(&linked, &linked.join("pb_user_dangling.db/data.db")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_all_terminates_gracefully() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+        manager.init_user_instance("gina").await.unwrap();
+        manager.init_user_instance("hank").await.unwrap();
+
+        let report = manager.shutdown_all(Duration::from_secs(5)).await;
+
+        let mut graceful = report.graceful.clone();
+        graceful.sort();
+        assert_eq!(graceful, vec!["gina", "hank"]);
+        assert!(report.forced.is_empty());
+        for user_id in ["gina", "hank"] {
+            let instance = manager.get_user_instance(user_id).await.unwrap();
+            assert_eq!(instance.status, InstanceStatus::Stopped);
+        }
+        assert!(manager.allocated_ports.lock().await.is_empty());
+        assert!(manager.processes.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_all_kills_after_grace() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        // The fake binary ignores SIGTERM when it finds IGNORE_SIGTERM
+        std::fs::create_dir_all(manager.data_dir("ivan")).unwrap();
+        std::fs::write(manager.data_dir("ivan").join("IGNORE_SIGTERM"), b"").unwrap();
+        manager.init_user_instance("ivan").await.unwrap();
+
+        let started = Instant::now();
+        let report = manager.shutdown_all(Duration::from_millis(500)).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        assert_eq!(report.forced, vec!["ivan"]);
+        let instance = manager.get_user_instance("ivan").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Failed);
+        assert!(manager.allocated_ports.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_inits_never_share_ports() {
+        let dir = tempfile::tempdir().unwrap();
+        let port = port_window(30);
+        let manager = test_manager(dir.path()).with_port_range(port, port + 29);
+        let user_ids: Vec<String> = (0..24).map(|index| format!("user{}", index)).collect();
+
+        let results = futures::future::join_all(
+            user_ids.iter().map(|user_id| manager.init_user_instance(user_id)),
+        )
+        .await;
+
+        let mut ports: Vec<u16> = results.into_iter().map(|result| result.unwrap().port).collect();
+        ports.sort();
+        ports.dedup();
+        assert_eq!(ports.len(), user_ids.len());
+
+        manager.shutdown_all(Duration::from_secs(5)).await;
+    }
+
+    fn spawn_count(manager: &PocketBaseManager, user_id: &str) -> usize {
+        std::fs::read_to_string(manager.data_dir(user_id).join("spawns"))
+            .map(|spawns| spawns.lines().count())
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_inits_for_one_user_spawn_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+        std::fs::create_dir_all(manager.data_dir("mona")).unwrap();
+        std::fs::write(manager.data_dir("mona").join("COUNT_SPAWNS"), b"").unwrap();
+
+        let results = futures::future::join_all((0..8).map(|_| manager.init_user_instance("mona"))).await;
+
+        let ports: Vec<u16> = results.into_iter().map(|result| result.unwrap().port).collect();
+        assert!(ports.iter().all(|port| *port == ports[0]));
+        assert_eq!(spawn_count(&manager, "mona"), 1);
+        assert_eq!(manager.allocated_ports().await, vec![ports[0]]);
+
+        manager.shutdown_all(Duration::from_secs(5)).await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_init_is_shared_then_retryable() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+        let data_dir = manager.data_dir("nina");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("COUNT_SPAWNS"), b"").unwrap();
+        std::fs::write(data_dir.join("FAIL_START"), b"").unwrap();
+
+        let (first, second) = tokio::join!(manager.init_user_instance("nina"), manager.init_user_instance("nina"));
+        assert!(matches!(first, Err(PocketBaseError::HealthCheckFailed | PocketBaseError::ProcessError(_))));
+        assert_eq!(first.unwrap_err().to_string(), second.unwrap_err().to_string());
+        assert_eq!(spawn_count(&manager, "nina"), 1);
+
+        std::fs::remove_file(data_dir.join("FAIL_START")).unwrap();
+        let instance = manager.init_user_instance("nina").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+        assert_eq!(spawn_count(&manager, "nina"), 2);
+
+        manager.stop_user_instance("nina").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_child_environment_is_allowlisted() {
+        std::env::set_var("FTL_TEST_PARENT_SECRET", "must-not-leak");
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path()).with_encryption_key("master-secret");
+        let data_dir = manager.data_dir("rosa");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("DUMP_ENV"), b"").unwrap();
+
+        manager.init_user_instance("rosa").await.unwrap();
+        let dump: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(data_dir.join("env.json")).unwrap()).unwrap();
+        manager.stop_user_instance("rosa").await.unwrap();
+
+        let env = dump["env"].as_object().unwrap();
+        for name in env.keys() {
+            assert!(
+                INHERITED_ENV.contains(&name.as_str()) || name == "PB_DATA" || name == ENCRYPTION_ENV,
+                "unexpected variable {} in child environment",
+                name
+            );
+        }
+        assert_eq!(env[ENCRYPTION_ENV], instance_encryption_key("master-secret", "rosa"));
+        assert!(env.contains_key("PATH"));
+
+        let args: Vec<&str> = dump["args"].as_array().unwrap().iter().map(|arg| arg.as_str().unwrap()).collect();
+        let flag = args.iter().position(|arg| *arg == "--encryptionEnv").unwrap();
+        assert_eq!(args[flag + 1], ENCRYPTION_ENV);
+        assert_eq!(
+            PathBuf::from(dump["cwd"].as_str().unwrap()),
+            data_dir.canonicalize().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_client_resets_existing_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path())
+            .with_encryption_key("master-secret");
+        let data_dir = manager.data_dir("omar");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        // Left behind by a run with another master secret
+        let stale = serde_json::json!({ INSTANCE_ADMIN_EMAIL: "stale-password" });
+        std::fs::write(data_dir.join("admins.json"), stale.to_string()).unwrap();
+
+        let client = manager.admin_client("omar").await.unwrap();
+        let schema = serde_json::json!({ "name": "notes", "type": "base", "schema": [] });
+        assert!(client.ensure_collection(&schema).await.unwrap());
+        assert!(!client.ensure_collection(&schema).await.unwrap());
+        assert!(Arc::ptr_eq(&client, &manager.admin_client("omar").await.unwrap()));
+        let admins: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(data_dir.join("admins.json")).unwrap()).unwrap();
+        assert_eq!(admins.as_object().unwrap().len(), 1);
+        assert_eq!(admins[INSTANCE_ADMIN_EMAIL], manager.instance_admin_password("omar"));
+        assert_eq!(std::fs::read_to_string(data_dir.join("admin_commands")).unwrap(), "update\n");
+
+        manager.stop_user_instance("omar").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_client_refuses_instances_with_other_admins() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path())
+            .with_encryption_key("master-secret");
+        let data_dir = manager.data_dir("quinn");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+        // Someone reached the port and claimed the first admin account
+        let admins = serde_json::json!({
+            INSTANCE_ADMIN_EMAIL: "stale-password",
+            "intruder@example.com": "their-password",
+        });
+        std::fs::write(data_dir.join("admins.json"), admins.to_string()).unwrap();
+
+        let result = manager.admin_client("quinn").await;
+        assert!(
+            matches!(&result, Err(PocketBaseError::ForeignAdmins { emails, .. }) if emails == "intruder@example.com"),
+            "{:?}",
+            result.err()
+        );
+        // Nothing is handed out for the instance on a later call either
+        assert!(manager.admin_client("quinn").await.is_err());
+
+        manager.stop_user_instance("quinn").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fresh_instances_get_their_admin_without_the_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path())
+            .with_encryption_key("master-secret");
+        let data_dir = manager.data_dir("pia");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("STORE_RECORDS"), b"").unwrap();
+
+        let client = manager.admin_client("pia").await.unwrap();
+        let schema = serde_json::json!({ "name": "notes", "type": "base", "schema": [] });
+        assert!(client.ensure_collection(&schema).await.unwrap());
+        let admins: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(data_dir.join("admins.json")).unwrap()).unwrap();
+        assert_eq!(admins[INSTANCE_ADMIN_EMAIL], manager.instance_admin_password("pia"));
+        assert!(!data_dir.join("admin_commands").exists(), "no pocketbase admin command ran");
+
+        // A restart finds the account already there, with the same password
+        manager.stop_user_instance("pia").await.unwrap();
+        let restarted = test_manager(dir.path())
+            .with_encryption_key("master-secret");
+        restarted.admin_client("pia").await.unwrap();
+        assert!(!data_dir.join("admin_commands").exists());
+
+        restarted.stop_user_instance("pia").await.unwrap();
+    }
+
+    #[test]
+    fn test_encryption_key_is_stable_and_per_user() {
+        let key = instance_encryption_key("master", "alice");
+        assert_eq!(key.len(), 32);
+        assert!(key.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_eq!(key, instance_encryption_key("master", "alice"));
+        assert_ne!(key, instance_encryption_key("master", "bob"));
+        assert_ne!(key, instance_encryption_key("other-master", "alice"));
+    }
+
+    #[tokio::test]
+    async fn test_init_retries_once_when_child_cannot_bind() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+        std::fs::create_dir_all(manager.data_dir("jill")).unwrap();
+        std::fs::write(manager.data_dir("jill").join("PORT_IN_USE_ONCE"), b"").unwrap();
+
+        let instance = manager.init_user_instance("jill").await.unwrap();
+
+        assert_eq!(instance.status, InstanceStatus::Running);
+        assert!(!manager.data_dir("jill").join("PORT_IN_USE_ONCE").exists());
+        let ports = manager.allocated_ports.lock().await;
+        assert_eq!(ports.keys().copied().collect::<Vec<_>>(), vec![instance.port]);
+        drop(ports);
+
+        manager.stop_user_instance("jill").await.unwrap();
+    }
+
+    fn drain(rx: &mut tokio::sync::broadcast::Receiver<SystemEvent>) -> Vec<SystemEventType> {
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event.event_type);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events_are_broadcast() {
+        let dir = tempfile::tempdir().unwrap();
+        let broadcast = Arc::new(BroadcastService::new(100));
+        let mut rx = broadcast.subscribe_system();
+        let manager = test_manager(dir.path()).with_broadcast(Arc::clone(&broadcast));
+
+        let instance = manager.init_user_instance("kate").await.unwrap();
+        let first = rx.try_recv().unwrap();
+        assert_eq!(first.event_type, SystemEventType::InstanceStarting);
+        assert_eq!(first.user_id, "kate");
+        assert_eq!(first.port, instance.port);
+        assert_eq!(drain(&mut rx), vec![SystemEventType::InstanceRunning]);
+
+        // Already running: no transition, no event
+        manager.init_user_instance("kate").await.unwrap();
+        assert!(drain(&mut rx).is_empty());
+
+        manager.stop_user_instance("kate").await.unwrap();
+        assert_eq!(drain(&mut rx), vec![SystemEventType::InstanceStopped]);
+        manager.stop_user_instance("kate").await.unwrap();
+        assert!(drain(&mut rx).is_empty());
+
+        manager.init_user_instance("kate").await.unwrap();
+        assert_eq!(
+            drain(&mut rx),
+            vec![SystemEventType::InstanceStarting, SystemEventType::InstanceRestarted]
+        );
+
+        manager.stop_user_instance("kate").await.unwrap();
+        std::fs::write(manager.data_dir("kate").join("FAIL_START"), b"").unwrap();
+        drain(&mut rx);
+        assert!(manager.init_user_instance("kate").await.is_err());
+        let events = drain(&mut rx);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], SystemEventType::InstanceStarting);
+        assert!(matches!(events[1], SystemEventType::InstanceFailed { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_manager_without_broadcast_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        let instance = manager.init_user_instance("liam").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+        manager.stop_user_instance("liam").await.unwrap();
+        assert_eq!(manager.get_user_instance("liam").await.unwrap().status, InstanceStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_allocate_port_exhausts_range() {
+        let dir = tempfile::tempdir().unwrap();
+        // Two neighbouring ports just seen free, rather than ones guessed at
+        let start = loop {
+            let first = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = first.local_addr().unwrap().port();
+            if port < u16::MAX && std::net::TcpListener::bind(("127.0.0.1", port + 1)).is_ok() {
+                break port;
+            }
+        };
+        let manager = test_manager(dir.path()).with_port_range(start, start + 1);
+
+        let first = manager.allocate_port("a").await.unwrap();
+        let second = manager.allocate_port("b").await.unwrap();
+        assert_ne!(first, second);
+        assert!((start..=start + 1).contains(&first));
+        assert!((start..=start + 1).contains(&second));
+
+        assert!(matches!(
+            manager.allocate_port("c").await,
+            Err(PocketBaseError::NoPortsAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_instance_url_uses_bind_host() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let manager = test_manager(dir.path()).with_bind_host("pb-instances");
+        assert_eq!(manager.instance_url(9001), "http://pb-instances:9001");
+
+        let manager = test_manager(dir.path()).with_bind_host("0.0.0.0");
+        assert_eq!(manager.instance_url(9001), "http://127.0.0.1:9001");
+
+        let manager = test_manager(dir.path()).with_bind_host("fd00::5");
+        assert_eq!(manager.instance_url(9001), "http://[fd00::5]:9001");
+    }
+
+    #[test]
+    fn test_instances_listen_on_bracketed_ipv6_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let manager = test_manager(dir.path());
+        assert_eq!(manager.listen_address(9001), "127.0.0.1:9001");
+
+        let manager = test_manager(dir.path()).with_bind_host("::");
+        assert_eq!(manager.listen_address(9001), "[::]:9001");
+        assert_eq!(manager.instance_url(9001), "http://[::1]:9001");
+        assert!(manager.listen_address(9001).parse::<std::net::SocketAddr>().is_ok());
+
+        let manager = test_manager(dir.path()).with_bind_host("pb-instances");
+        assert_eq!(manager.listen_address(9001), "pb-instances:9001");
+    }
+
+    #[tokio::test]
+    async fn test_restore_unknown_backup_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = test_manager(dir.path());
+
+        for backup_id in ["missing", "../../etc/passwd", ""] {
+            let err = manager
+                .restore_user_instance("dave", RestoreSource::Backup(backup_id.to_string()))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, PocketBaseError::BackupNotFound(_)));
+        }
+    }
 }
@@ -0,0 +1,203 @@
+//! Helpers for reading and unpacking PocketBase data directory archives
+//!
+//! Archives are gzipped tarballs of a user's data directory. Every entry is
+//! checked before anything touches the filesystem so a crafted archive can
+//! never write outside the directory it is unpacked into, and unpacking
+//! stops at [`UnpackLimits`] so a small archive can't fill the disk.
+
+use flate2::read::GzDecoder;
+use std::path::{Component, Path};
+use tar::{Archive, EntryType};
+
+use super::PocketBaseError;
+
+/// The most an archive may unpack to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnpackLimits {
+    /// File contents across every entry, in bytes
+    pub max_bytes: u64,
+    pub max_entries: usize,
+}
+
+impl Default for UnpackLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024 * 1024,
+            max_entries: 100_000,
+        }
+    }
+}
+
+/// Validate every entry in an archive without unpacking it
+///
+/// Returns the number of entries on success.
+pub fn validate_archive(bytes: &[u8]) -> Result<usize, PocketBaseError> {
+    let mut archive = Archive::new(GzDecoder::new(bytes));
+    let mut count = 0;
+
+    let entries = archive
+        .entries()
+        .map_err(|e| PocketBaseError::InvalidArchive(format!("Unreadable archive: {}", e)))?;
+
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| PocketBaseError::InvalidArchive(format!("Corrupt archive entry: {}", e)))?;
+        check_entry(&entry)?;
+        count += 1;
+    }
+
+    if count == 0 {
+        return Err(PocketBaseError::InvalidArchive("Archive is empty".to_string()));
+    }
+
+    Ok(count)
+}
+
+/// Unpack an archive into `dest`, re-checking each entry as it is written
+///
+/// Fails before writing the entry that would go past `limits`; what was
+/// unpacked until then is left for the caller to discard.
+pub fn unpack_archive(bytes: &[u8], dest: &Path, limits: UnpackLimits) -> Result<usize, PocketBaseError> {
+    let mut archive = Archive::new(GzDecoder::new(bytes));
+    let mut count = 0;
+    let mut total_bytes = 0u64;
+
+    let entries = archive
+        .entries()
+        .map_err(|e| PocketBaseError::InvalidArchive(format!("Unreadable archive: {}", e)))?;
+
+    for entry in entries {
+        let mut entry = entry
+            .map_err(|e| PocketBaseError::InvalidArchive(format!("Corrupt archive entry: {}", e)))?;
+        check_entry(&entry)?;
+
+        // Entries hold exactly the size their header gives
+        total_bytes = total_bytes.saturating_add(entry.size());
+        if count >= limits.max_entries {
+            return Err(PocketBaseError::InvalidArchive(format!(
+                "Archive has more than {} entries",
+                limits.max_entries
+            )));
+        }
+        if total_bytes > limits.max_bytes {
+            return Err(PocketBaseError::InvalidArchive(format!(
+                "Archive unpacks to more than {} bytes",
+                limits.max_bytes
+            )));
+        }
+
+        let unpacked = entry
+            .unpack_in(dest)
+            .map_err(|e| PocketBaseError::IoError(format!("Failed to unpack archive entry: {}", e)))?;
+        if !unpacked {
+            return Err(PocketBaseError::InvalidArchive(
+                "Archive entry escapes the data directory".to_string(),
+            ));
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Reject entries that are links, devices, or point outside the destination
+fn check_entry<R: std::io::Read>(entry: &tar::Entry<'_, R>) -> Result<(), PocketBaseError> {
+    let path = entry
+        .path()
+        .map_err(|e| PocketBaseError::InvalidArchive(format!("Invalid entry path: {}", e)))?;
+
+    match entry.header().entry_type() {
+        EntryType::Regular | EntryType::Directory => {}
+        other => {
+            return Err(PocketBaseError::InvalidArchive(format!(
+                "Unsupported entry type {:?} for {}",
+                other,
+                path.display()
+            )));
+        }
+    }
+
+    if !is_contained(&path) {
+        return Err(PocketBaseError::InvalidArchive(format!(
+            "Entry {} escapes the data directory",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// A path is contained when it is relative and never steps upwards
+fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::build_archive;
+
+    #[test]
+    fn test_validate_accepts_plain_files() {
+        let archive = build_archive(&[("data.db", b"sqlite"), ("storage/a.txt", b"a")]);
+        assert_eq!(validate_archive(&archive).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_validate_rejects_parent_dir_entries() {
+        let archive = build_archive(&[("data.db", b"ok"), ("../../etc/passwd", b"evil")]);
+        assert!(matches!(
+            validate_archive(&archive),
+            Err(PocketBaseError::InvalidArchive(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_rejects_absolute_entries() {
+        let archive = build_archive(&[("/etc/passwd", b"evil")]);
+        assert!(matches!(
+            validate_archive(&archive),
+            Err(PocketBaseError::InvalidArchive(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_rejects_corrupt_bytes() {
+        let mut archive = build_archive(&[("data.db", &[7u8; 4096])]);
+        archive.truncate(archive.len() / 2);
+        assert!(validate_archive(&archive).is_err());
+        assert!(validate_archive(b"definitely not gzip").is_err());
+    }
+
+    #[test]
+    fn test_unpack_writes_inside_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = build_archive(&[("data.db", b"sqlite"), ("storage/a.txt", b"a")]);
+
+        assert_eq!(unpack_archive(&archive, dir.path(), UnpackLimits::default()).unwrap(), 2);
+        assert_eq!(std::fs::read(dir.path().join("data.db")).unwrap(), b"sqlite");
+        assert_eq!(std::fs::read(dir.path().join("storage/a.txt")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn test_unpack_stops_at_its_limits() {
+        let archive = build_archive(&[("data.db", &[7u8; 600]), ("storage/a.txt", &[1u8; 600])]);
+        let unpack = |limits| unpack_archive(&archive, tempfile::tempdir().unwrap().path(), limits);
+
+        assert_eq!(unpack(UnpackLimits { max_bytes: 1200, max_entries: 2 }).unwrap(), 2);
+        for limits in [
+            UnpackLimits { max_bytes: 1199, max_entries: 2 },
+            UnpackLimits { max_bytes: 1200, max_entries: 1 },
+        ] {
+            assert!(matches!(unpack(limits), Err(PocketBaseError::InvalidArchive(_))), "{:?}", limits);
+        }
+
+        // The entry past the limit is never written
+        let dir = tempfile::tempdir().unwrap();
+        let limits = UnpackLimits { max_bytes: 1000, max_entries: 2 };
+        assert!(unpack_archive(&archive, dir.path(), limits).is_err());
+        assert!(dir.path().join("data.db").exists());
+        assert!(!dir.path().join("storage/a.txt").exists());
+    }
+}
@@ -0,0 +1,435 @@
+//! Locating, verifying and optionally downloading the PocketBase binary
+//!
+//! Startup fails fast when the configured binary is missing, not executable
+//! or outside [`SUPPORTED_VERSIONS`], instead of surfacing the problem on the
+//! first user's instance start. With `PB_AUTO_DOWNLOAD` enabled a missing
+//! binary is fetched from the pinned GitHub release and checked against a
+//! known SHA-256 before it is installed.
+
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use super::PocketBaseError;
+use crate::config::PocketBaseConfig;
+
+/// Release downloaded when `PB_AUTO_DOWNLOAD` is enabled
+pub const PINNED_VERSION: PocketBaseVersion = PocketBaseVersion::new(0, 22, 21);
+
+/// Versions the manager knows how to drive: from the first inclusive to the
+/// second exclusive. 0.23 replaced the admin API with superusers.
+pub const SUPPORTED_VERSIONS: (PocketBaseVersion, PocketBaseVersion) =
+    (PocketBaseVersion::new(0, 20, 0), PocketBaseVersion::new(0, 23, 0));
+
+/// Operating systems and architectures the pinned version is downloaded for,
+/// as the release names them
+const RELEASE_TARGETS: &[(&str, &str)] = &[
+    ("linux", "amd64"),
+    ("linux", "arm64"),
+    ("darwin", "amd64"),
+    ("darwin", "arm64"),
+    ("windows", "amd64"),
+    ("windows", "arm64"),
+];
+
+/// SHA-256 of the pinned release assets, keyed by asset file name
+///
+/// Copied from the `checksums.txt` published with the release, one entry for
+/// each of [`RELEASE_TARGETS`]; a change of [`PINNED_VERSION`] needs them
+/// copied again. Without an entry, or `PB_BINARY_SHA256` giving the digest,
+/// the download is refused.
+const RELEASE_CHECKSUMS: &[(&str, &str)] = &[];
+
+/// How long `pocketbase --version` may take before the binary is rejected
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a release download may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A PocketBase release version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PocketBaseVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl PocketBaseVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// Parse the output of `pocketbase --version`, e.g. `pocketbase version 0.22.21`
+    ///
+    /// A leading `v` and any pre-release or build suffix are ignored.
+    pub fn parse(output: &str) -> Option<Self> {
+        let token = output.split_whitespace().last()?;
+        let token = token.strip_prefix('v').unwrap_or(token);
+        let core = token.split(['-', '+']).next()?;
+
+        let mut parts = core.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self::new(major, minor, patch))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        let (min, max) = SUPPORTED_VERSIONS;
+        *self >= min && *self < max
+    }
+}
+
+impl fmt::Display for PocketBaseVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Find a usable PocketBase binary, downloading it if allowed, and verify it
+///
+/// Returns the path the manager should spawn and the detected version.
+pub async fn prepare_binary(config: &PocketBaseConfig) -> Result<(PathBuf, PocketBaseVersion), PocketBaseError> {
+    let path = match locate(&config.binary_path) {
+        Some(path) => path,
+        None if config.auto_download => {
+            let managed_dir = Path::new(&config.managed_bin_dir);
+            let managed = managed_dir.join(binary_name());
+            if managed.is_file() {
+                managed
+            } else {
+                warn!(
+                    "PocketBase binary '{}' not found; downloading {} into {}",
+                    config.binary_path,
+                    PINNED_VERSION,
+                    managed_dir.display()
+                );
+                download_release(managed_dir, config.binary_sha256.as_deref()).await?
+            }
+        }
+        None => {
+            return Err(PocketBaseError::BinaryUnavailable(format!(
+                "'{}' was not found. Install PocketBase {} and set PB_BINARY_PATH to it, \
+                 or set PB_AUTO_DOWNLOAD=true to download it automatically",
+                config.binary_path, PINNED_VERSION
+            )))
+        }
+    };
+
+    let version = verify_binary(&path).await?;
+    info!("Using PocketBase {} at {}", version, path.display());
+    Ok((path, version))
+}
+
+/// Check `path` is an executable PocketBase of a supported version
+pub async fn verify_binary(path: &Path) -> Result<PocketBaseVersion, PocketBaseError> {
+    check_executable(path)?;
+
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(VERSION_TIMEOUT, output)
+        .await
+        .map_err(|_| {
+            PocketBaseError::BinaryUnavailable(format!(
+                "{} --version did not finish within {}s",
+                path.display(),
+                VERSION_TIMEOUT.as_secs()
+            ))
+        })?
+        .map_err(|e| PocketBaseError::BinaryUnavailable(format!("Failed to run {}: {}", path.display(), e)))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = PocketBaseVersion::parse(&stdout).ok_or_else(|| {
+        PocketBaseError::BinaryUnavailable(format!(
+            "{} --version printed {:?}; is PB_BINARY_PATH pointing at PocketBase?",
+            path.display(),
+            stdout.trim()
+        ))
+    })?;
+
+    if !version.is_supported() {
+        let (min, max) = SUPPORTED_VERSIONS;
+        return Err(PocketBaseError::UnsupportedVersion {
+            found: version.to_string(),
+            supported: format!(">={}, <{}", min, max),
+        });
+    }
+
+    Ok(version)
+}
+
+/// Resolve a configured binary path, searching `PATH` for bare names
+pub fn locate(binary_path: &str) -> Option<PathBuf> {
+    let path = Path::new(binary_path);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(binary_path))
+        .find(|candidate| candidate.is_file())
+}
+
+fn check_executable(path: &Path) -> Result<(), PocketBaseError> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| PocketBaseError::BinaryUnavailable(format!("{}: {}", path.display(), e)))?;
+    if !metadata.is_file() {
+        return Err(PocketBaseError::BinaryUnavailable(format!("{} is not a file", path.display())));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(PocketBaseError::BinaryUnavailable(format!(
+                "{} is not executable; run chmod +x on it",
+                path.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(windows) {
+        "pocketbase.exe"
+    } else {
+        "pocketbase"
+    }
+}
+
+/// Release asset name for the current OS and architecture
+fn release_asset() -> Option<String> {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    };
+    RELEASE_TARGETS
+        .contains(&(os, arch))
+        .then(|| asset_name(os, arch))
+}
+
+fn asset_name(os: &str, arch: &str) -> String {
+    format!("pocketbase_{}_{}_{}.zip", PINNED_VERSION, os, arch)
+}
+
+fn release_url(file: &str) -> String {
+    format!(
+        "https://github.com/pocketbase/pocketbase/releases/download/v{}/{}",
+        PINNED_VERSION, file
+    )
+}
+
+fn pinned_checksum(asset: &str) -> Option<&'static str> {
+    RELEASE_CHECKSUMS
+        .iter()
+        .find(|(name, _)| *name == asset)
+        .map(|(_, digest)| *digest)
+}
+
+async fn download_release(dest_dir: &Path, checksum_override: Option<&str>) -> Result<PathBuf, PocketBaseError> {
+    let asset = release_asset().ok_or_else(|| {
+        PocketBaseError::BinaryUnavailable(format!(
+            "No PocketBase release for {}/{}; install it manually and set PB_BINARY_PATH",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ))
+    })?;
+    let expected = checksum_override.or_else(|| pinned_checksum(&asset)).ok_or_else(|| {
+        PocketBaseError::BinaryUnavailable(format!(
+            "No known SHA-256 for {}; set PB_BINARY_SHA256 to its digest from the release's checksums.txt",
+            asset
+        ))
+    })?;
+
+    let url = release_url(&asset);
+    let archive = fetch(&url)
+        .await
+        .map_err(|e| PocketBaseError::DownloadFailed(format!("{}: {}", url, e)))?;
+
+    let expected = expected.to_string();
+    let dest_dir = dest_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || install_release(&archive, &expected, &dest_dir))
+        .await
+        .map_err(|e| PocketBaseError::DownloadFailed(e.to_string()))?
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, reqwest::Error> {
+    let client = reqwest::Client::builder().timeout(DOWNLOAD_TIMEOUT).build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Fail unless `bytes` hash to the hex-encoded SHA-256 `expected`
+pub fn verify_checksum(bytes: &[u8], expected: &str) -> Result<(), PocketBaseError> {
+    let actual: String = Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect();
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(PocketBaseError::ChecksumMismatch {
+            expected: expected.trim().to_string(),
+            actual,
+        })
+    }
+}
+
+/// Verify a release zip and extract its binary into `dest_dir`
+///
+/// Nothing is written unless the checksum matches. The binary is written to a
+/// temporary name first so a partial extraction is never picked up.
+fn install_release(archive: &[u8], expected_sha256: &str, dest_dir: &Path) -> Result<PathBuf, PocketBaseError> {
+    verify_checksum(archive, expected_sha256)?;
+
+    let invalid = |e: zip::result::ZipError| PocketBaseError::DownloadFailed(format!("Invalid release archive: {}", e));
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive)).map_err(invalid)?;
+    let mut entry = zip.by_name(binary_name()).map_err(invalid)?;
+    let mut contents = Vec::new();
+    entry
+        .read_to_end(&mut contents)
+        .map_err(|e| PocketBaseError::DownloadFailed(format!("Invalid release archive: {}", e)))?;
+
+    std::fs::create_dir_all(dest_dir).map_err(|e| PocketBaseError::IoError(e.to_string()))?;
+    let staging = dest_dir.join(format!(".{}.download", binary_name()));
+    std::fs::write(&staging, &contents).map_err(|e| PocketBaseError::IoError(e.to_string()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staging, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| PocketBaseError::IoError(e.to_string()))?;
+    }
+
+    let target = dest_dir.join(binary_name());
+    std::fs::rename(&staging, &target).map_err(|e| PocketBaseError::IoError(e.to_string()))?;
+    info!("Installed PocketBase {} to {}", PINNED_VERSION, target.display());
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_pocketbase;
+    use std::io::Write;
+
+    fn release_zip(contents: &[u8]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(binary_name(), zip::write::FileOptions::default().unix_permissions(0o755))
+            .unwrap();
+        writer.write_all(contents).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn sha256_hex(bytes: &[u8]) -> String {
+        Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn config_for(binary_path: &str, managed_bin_dir: &Path) -> PocketBaseConfig {
+        let mut config = crate::test_support::test_config("http://unused").pocketbase;
+        config.binary_path = binary_path.to_string();
+        config.managed_bin_dir = managed_bin_dir.display().to_string();
+        config
+    }
+
+    #[test]
+    fn test_parse_version_output() {
+        assert_eq!(
+            PocketBaseVersion::parse("pocketbase version 0.22.21\n"),
+            Some(PocketBaseVersion::new(0, 22, 21))
+        );
+        assert_eq!(PocketBaseVersion::parse("v0.21.3-rc1"), Some(PocketBaseVersion::new(0, 21, 3)));
+        assert_eq!(PocketBaseVersion::parse("pocketbase version 0.22"), None);
+        assert_eq!(PocketBaseVersion::parse("command not found"), None);
+        assert_eq!(PocketBaseVersion::parse(""), None);
+
+        assert!(PocketBaseVersion::new(0, 22, 21).is_supported());
+        assert!(PocketBaseVersion::new(0, 20, 0).is_supported());
+        assert!(!PocketBaseVersion::new(0, 19, 4).is_supported());
+        assert!(!PocketBaseVersion::new(0, 23, 0).is_supported());
+        assert!(PINNED_VERSION.is_supported());
+    }
+
+    #[test]
+    fn test_checksum_mismatch_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = release_zip(b"binary");
+
+        let result = install_release(&archive, &"0".repeat(64), dir.path());
+        assert!(matches!(result, Err(PocketBaseError::ChecksumMismatch { .. })));
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+
+        let installed = install_release(&archive, &sha256_hex(&archive).to_uppercase(), dir.path()).unwrap();
+        assert_eq!(installed, dir.path().join(binary_name()));
+        assert_eq!(std::fs::read(&installed).unwrap(), b"binary");
+    }
+
+    #[test]
+    fn test_every_release_target_has_a_checksum() {
+        for (os, arch) in RELEASE_TARGETS {
+            let asset = asset_name(os, arch);
+            let digest = pinned_checksum(&asset).unwrap_or_else(|| panic!("no SHA-256 for {}", asset));
+            assert!(
+                digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()),
+                "{} has no SHA-256 digest: {}",
+                asset,
+                digest
+            );
+        }
+        assert_eq!(RELEASE_CHECKSUMS.len(), RELEASE_TARGETS.len(), "entries only for the release targets");
+    }
+
+    #[tokio::test]
+    async fn test_missing_binary_fails_fast() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let config = config_for("/nonexistent/pocketbase", dir.path());
+        let error = prepare_binary(&config).await.unwrap_err();
+        assert!(matches!(error, PocketBaseError::BinaryUnavailable(_)));
+        assert!(error.to_string().contains("PB_BINARY_PATH"), "{}", error);
+
+        let not_executable = dir.path().join("pocketbase");
+        std::fs::write(&not_executable, "#!/bin/sh\n").unwrap();
+        let error = verify_binary(&not_executable).await.unwrap_err();
+        assert!(error.to_string().contains("not executable"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_verifies_installed_binary_version() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let binary = fake_pocketbase(dir.path());
+        let (path, version) = prepare_binary(&config_for(&binary.display().to_string(), dir.path()))
+            .await
+            .unwrap();
+        assert_eq!(path, binary);
+        assert_eq!(version, PINNED_VERSION);
+
+        let too_new = dir.path().join("too-new");
+        std::fs::write(&too_new, "#!/bin/sh\necho 'pocketbase version 0.23.1'\n").unwrap();
+        std::fs::set_permissions(&too_new, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(
+            verify_binary(&too_new).await,
+            Err(PocketBaseError::UnsupportedVersion { .. })
+        ));
+    }
+}
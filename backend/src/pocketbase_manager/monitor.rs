@@ -0,0 +1,412 @@
+//! Background health monitoring for PocketBase instances
+//!
+//! Each instance is checked on its own schedule, `interval` apart with ±20%
+//! jitter, so probes don't all fire in lockstep. At most
+//! [`MAX_CONCURRENT_PROBES`] checks run at once.
+
+use futures::{future::BoxFuture, FutureExt};
+use rand::Rng;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    process::Child,
+    sync::{Mutex, RwLock, Semaphore},
+    task::{JoinHandle, JoinSet},
+    time::{sleep_until, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::{emit_event, stats::InstanceStats, InstanceStatus, PocketBaseInstance, PocketBaseManager};
+use common::broadcast::{BroadcastService, SystemEventType};
+
+/// Upper bound on health checks in flight at any time
+pub const MAX_CONCURRENT_PROBES: usize = 8;
+
+/// Fraction of the interval each instance's schedule may drift either way
+const JITTER: f64 = 0.2;
+
+/// Checks whether the instance at a URL is answering
+pub(super) type HealthProbe = Arc<dyn Fn(String) -> BoxFuture<'static, bool> + Send + Sync>;
+
+pub(super) fn http_probe() -> HealthProbe {
+    Arc::new(|url: String| async move { PocketBaseManager::health_check_http(&url).await }.boxed())
+}
+
+/// Handle to a running health monitor
+pub struct HealthMonitorHandle {
+    token: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+impl HealthMonitorHandle {
+    /// Stop the monitor and wait for its task to finish
+    pub async fn stop(self) {
+        self.token.cancel();
+        if let Err(e) = self.handle.await {
+            error!("Health monitor task failed: {}", e);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Everything the monitor task needs, cloned out of the manager
+#[derive(Clone)]
+struct MonitorContext {
+    instances: Arc<RwLock<HashMap<String, PocketBaseInstance>>>,
+    processes: Arc<Mutex<HashMap<String, Child>>>,
+    allocated_ports: Arc<Mutex<HashMap<u16, Option<std::net::TcpListener>>>>,
+    stats: Arc<RwLock<HashMap<String, InstanceStats>>>,
+    probe: HealthProbe,
+    broadcast: Option<Arc<BroadcastService>>,
+}
+
+impl PocketBaseManager {
+    /// Start health monitoring for all instances
+    ///
+    /// The monitor runs until the returned handle is stopped or
+    /// [`PocketBaseManager::shutdown_all`] is called.
+    pub fn start_health_monitoring(&self) -> HealthMonitorHandle {
+        let context = MonitorContext {
+            instances: Arc::clone(&self.instances),
+            processes: Arc::clone(&self.processes),
+            allocated_ports: Arc::clone(&self.allocated_ports),
+            stats: Arc::clone(&self.stats),
+            probe: Arc::clone(&self.health_probe),
+            broadcast: self.broadcast.clone(),
+        };
+        let token = self.monitor_token.child_token();
+        let interval = self.health_interval;
+
+        let handle = tokio::spawn(run(context, interval, token.clone()));
+        HealthMonitorHandle { token, handle }
+    }
+}
+
+fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(1.0 - JITTER..=1.0 + JITTER))
+}
+
+async fn run(context: MonitorContext, interval: Duration, token: CancellationToken) {
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES));
+    let mut next_due: HashMap<String, Instant> = HashMap::new();
+
+    loop {
+        let now = Instant::now();
+
+        // Pick up new instances and forget removed ones
+        let user_ids: Vec<String> = context.instances.read().await.keys().cloned().collect();
+        next_due.retain(|user_id, _| user_ids.contains(user_id));
+        for user_id in user_ids {
+            next_due.entry(user_id).or_insert_with(|| now + jittered(interval));
+        }
+
+        let mut checks = JoinSet::new();
+        for (user_id, due) in next_due.iter_mut() {
+            if *due > now {
+                continue;
+            }
+            *due = now + jittered(interval);
+
+            let context = context.clone();
+            let semaphore = Arc::clone(&semaphore);
+            let user_id = user_id.clone();
+            checks.spawn(async move {
+                let Ok(_permit) = semaphore.acquire_owned().await else {
+                    return;
+                };
+                context.check_instance(&user_id).await;
+            });
+        }
+
+        // Dropping the JoinSet on cancellation aborts any checks in flight
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = async { while checks.join_next().await.is_some() {} } => {}
+        }
+
+        let wake = next_due
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(now + interval)
+            .min(Instant::now() + interval);
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = sleep_until(wake) => {}
+        }
+    }
+
+    info!("PocketBase health monitor stopped");
+}
+
+impl MonitorContext {
+    async fn check_instance(&self, user_id: &str) {
+        // Check if process is still alive, dropping the handle of one that isn't
+        let process_alive = {
+            let mut processes = self.processes.lock().await;
+            let alive = match processes.get_mut(user_id) {
+                Some(child) => match child.try_wait() {
+                    Ok(None) => true,
+                    Ok(Some(status)) => {
+                        warn!("PocketBase process for user {} exited with status: {:?}", user_id, status);
+                        false
+                    }
+                    Err(e) => {
+                        error!("Error checking process status for user {}: {}", user_id, e);
+                        false
+                    }
+                },
+                None => false,
+            };
+            if !alive {
+                processes.remove(user_id);
+            }
+            alive
+        };
+
+        let Some(snapshot) = self.instances.read().await.get(user_id).cloned() else {
+            return;
+        };
+
+        if !process_alive {
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(user_id) {
+                if instance.status == InstanceStatus::Running {
+                    warn!("Detected failed PocketBase instance for user: {}", user_id);
+                    emit_event(
+                        self.broadcast.as_deref(),
+                        SystemEventType::InstanceFailed {
+                            reason: "Process exited unexpectedly".to_string(),
+                        },
+                        user_id,
+                        instance.port,
+                    );
+
+                    // Nothing listens on the port any more, so hand it back and
+                    // leave the record for the next init to allocate afresh
+                    self.allocated_ports.lock().await.remove(&instance.port);
+                    instance.port = 0;
+                    instance.status = InstanceStatus::Stopped;
+
+                    // TODO: Implement auto-restart logic here
+                    error!("PocketBase instance for user {} needs restart", user_id);
+                }
+            }
+        } else {
+            // Probe without holding the registry lock
+            let healthy = (self.probe)(snapshot.url.clone()).await;
+
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(user_id) {
+                if healthy {
+                    instance.last_health_check = Some(chrono::Utc::now());
+                    if instance.status != InstanceStatus::Running {
+                        info!("PocketBase instance for user {} is now healthy", user_id);
+                        instance.status = InstanceStatus::Running;
+                        emit_event(
+                            self.broadcast.as_deref(),
+                            SystemEventType::InstanceRunning,
+                            user_id,
+                            instance.port,
+                        );
+                    }
+                } else {
+                    warn!("PocketBase instance for user {} failed health check", user_id);
+                }
+            }
+        }
+
+        // Refresh cached resource usage so the stats endpoint never touches /proc
+        let Some(instance) = self.instances.read().await.get(user_id).cloned() else {
+            return;
+        };
+        let pid = self.processes.lock().await.get(user_id).and_then(|child| child.id());
+        match tokio::task::spawn_blocking(move || PocketBaseManager::collect_stats(&instance, pid)).await {
+            Ok(instance_stats) => {
+                self.stats.write().await.insert(user_id.to_string(), instance_stats);
+            }
+            Err(e) => warn!("Failed to collect stats for user {}: {}", user_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Register a running instance backed by a long `sleep` child
+    async fn register(manager: &PocketBaseManager, user_id: &str) {
+        let child = tokio::process::Command::new("sleep")
+            .arg("1000")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        manager.processes.lock().await.insert(user_id.to_string(), child);
+        manager.instances.write().await.insert(
+            user_id.to_string(),
+            PocketBaseInstance {
+                user_id: user_id.to_string(),
+                port: 0,
+                db_path: PathBuf::from("/nonexistent"),
+                url: format!("http://{}", user_id),
+                status: InstanceStatus::Running,
+                created_at: chrono::Utc::now(),
+                last_health_check: None,
+                restart_count: 0,
+            },
+        );
+    }
+
+    /// Probe that counts calls per URL and tracks peak concurrency
+    #[derive(Default)]
+    struct Recorder {
+        calls: std::sync::Mutex<HashMap<String, usize>>,
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    fn recording_probe(recorder: Arc<Recorder>, delay: Duration) -> HealthProbe {
+        Arc::new(move |url: String| {
+            let recorder = Arc::clone(&recorder);
+            async move {
+                *recorder.calls.lock().unwrap().entry(url).or_default() += 1;
+                let now = recorder.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                recorder.peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                recorder.in_flight.fetch_sub(1, Ordering::SeqCst);
+                true
+            }
+            .boxed()
+        })
+    }
+
+    fn manager_with_probe(probe: HealthProbe, interval: Duration) -> PocketBaseManager {
+        PocketBaseManager::new(PathBuf::from("./unused"), 9000, "pocketbase".to_string())
+            .with_health_interval(interval)
+            .with_health_probe(probe)
+    }
+
+    fn total_calls(recorder: &Recorder) -> usize {
+        recorder.calls.lock().unwrap().values().sum()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_checks_follow_jittered_interval() {
+        let recorder = Arc::new(Recorder::default());
+        let manager = manager_with_probe(recording_probe(Arc::clone(&recorder), Duration::ZERO), Duration::from_secs(10));
+        for user_id in ["a", "b", "c"] {
+            register(&manager, user_id).await;
+        }
+        let monitor = manager.start_health_monitoring();
+
+        // Nothing is due before 80% of the interval
+        tokio::time::sleep(Duration::from_millis(7900)).await;
+        assert_eq!(total_calls(&recorder), 0);
+
+        // Everything has been checked exactly once by 120%
+        tokio::time::sleep(Duration::from_millis(4200)).await;
+        let calls = recorder.calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.values().all(|count| *count == 1), "{:?}", calls);
+
+        // The earliest second check is 80% of an interval after the first
+        tokio::time::sleep(Duration::from_millis(3800)).await;
+        assert_eq!(total_calls(&recorder), 3);
+
+        monitor.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_concurrency_is_bounded() {
+        let recorder = Arc::new(Recorder::default());
+        let manager = manager_with_probe(
+            recording_probe(Arc::clone(&recorder), Duration::from_secs(30)),
+            Duration::from_secs(10),
+        );
+        for index in 0..20 {
+            register(&manager, &format!("user{}", index)).await;
+        }
+        let monitor = manager.start_health_monitoring();
+
+        tokio::time::sleep(Duration::from_secs(200)).await;
+
+        assert_eq!(recorder.peak.load(Ordering::SeqCst), MAX_CONCURRENT_PROBES);
+        monitor.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_monitor_stops_on_cancel_and_shutdown() {
+        let recorder = Arc::new(Recorder::default());
+        let manager = manager_with_probe(recording_probe(Arc::clone(&recorder), Duration::ZERO), Duration::from_secs(10));
+        register(&manager, "a").await;
+
+        let monitor = manager.start_health_monitoring();
+        monitor.stop().await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(total_calls(&recorder), 0);
+
+        let monitor = manager.start_health_monitoring();
+        manager.shutdown_all(Duration::from_secs(1)).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(monitor.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_dead_process_releases_port_for_reinit() {
+        use crate::{
+            api::create_api_router,
+            test_support::{fake_pocketbase, port_window, test_app_state, test_config},
+        };
+        use axum::{body::Body, http::Request};
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let binary = fake_pocketbase(dir.path());
+        let port = port_window(2);
+        let manager = PocketBaseManager::new(dir.path().join("user_dbs"), port, binary.display().to_string())
+            .with_port_range(port, port + 1)
+            .with_readiness_timeout(Duration::from_secs(10))
+            .with_health_interval(Duration::from_millis(100));
+        let sentinel = manager.data_dir("lena").join("EXIT_AFTER_FIRST_REQUEST");
+        std::fs::create_dir_all(manager.data_dir("lena")).unwrap();
+        std::fs::write(&sentinel, b"").unwrap();
+
+        let first = manager.init_user_instance("lena").await.unwrap();
+        assert_eq!(manager.allocated_ports().await, vec![first.port]);
+
+        let monitor = manager.start_health_monitoring();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while manager.get_user_instance("lena").await.unwrap().status != InstanceStatus::Stopped {
+            assert!(Instant::now() < deadline, "monitor never noticed the exit");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        monitor.stop().await;
+
+        let stopped = manager.get_user_instance("lena").await.unwrap();
+        assert_eq!(stopped.port, 0);
+        assert!(manager.allocated_ports().await.is_empty());
+        assert!(!manager.processes.lock().await.contains_key("lena"));
+
+        std::fs::remove_file(&sentinel).unwrap();
+        let second = manager.init_user_instance("lena").await.unwrap();
+        assert_eq!(second.status, InstanceStatus::Running);
+        assert_eq!(second.restart_count, 1);
+
+        let state = test_app_state(test_config("http://unused"), manager);
+        let request = Request::builder().uri("/health/pb").body(Body::empty()).unwrap();
+        let response = create_api_router(state.clone()).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["allocated_ports"], 1);
+        assert_eq!(health["running_instances"], 1);
+
+        state.pb_manager.stop_user_instance("lena").await.unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Warm pool of pre-started PocketBase instances
+//!
+//! New users can claim an instance that is already serving (and has applied
+//! its migrations) instead of waiting for a cold start. Pool instances live
+//! under `<user_dbs_path>/.warm` and are never recorded in the instance
+//! registry, so listings, stats and health monitoring only see them once a
+//! user has claimed one.
+
+use rand::Rng;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs, process::Child, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use super::{InstanceStatus, PocketBaseError, PocketBaseInstance, PocketBaseManager, KEY_ID_FILE};
+use common::broadcast::SystemEventType;
+
+/// How long to wait before retrying after a pool instance failed to start
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An anonymous instance waiting to be claimed
+pub(super) struct WarmInstance {
+    id: String,
+    port: u16,
+    data_dir: PathBuf,
+    child: Child,
+}
+
+impl PocketBaseManager {
+    /// Keep up to `size` instances pre-started for new users (0 disables the pool)
+    pub fn with_warm_pool(mut self, size: usize) -> Self {
+        self.warm_pool_size = size;
+        self
+    }
+
+    /// Number of pool instances ready to be claimed
+    pub async fn warm_pool_len(&self) -> usize {
+        self.warm_pool.lock().await.len()
+    }
+
+    fn warm_dir(&self) -> PathBuf {
+        self.user_dbs_path.join(".warm")
+    }
+
+    /// Start filling the pool, topping it up whenever an instance is claimed
+    ///
+    /// Leftovers from a previous run are removed first. The task stops when
+    /// [`PocketBaseManager::shutdown_all`] is called.
+    pub fn start_warm_pool(self: &Arc<Self>) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+        let token = self.monitor_token.child_token();
+
+        tokio::spawn(async move {
+            if manager.warm_pool_size == 0 {
+                return;
+            }
+            if let Err(e) = fs::remove_dir_all(manager.warm_dir()).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to clear stale warm pool directory: {}", e);
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = manager.replenish_pool() => {}
+                }
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = manager.pool_notify.notified() => {}
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+                }
+            }
+            debug!("PocketBase warm pool stopped");
+        })
+    }
+
+    async fn replenish_pool(&self) {
+        while self.warm_pool_len().await < self.warm_pool_size {
+            match self.spawn_warm_instance().await {
+                Ok(warm) => {
+                    debug!("Warm PocketBase {} ready on port {}", warm.id, warm.port);
+                    self.warm_pool.lock().await.push(warm);
+                }
+                Err(e) => {
+                    warn!("Failed to start warm PocketBase instance: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn spawn_warm_instance(&self) -> Result<WarmInstance, PocketBaseError> {
+        // Random so encryption keys derived from it are never shared, even
+        // across restarts; the claiming user inherits it via the key id file
+        let id = format!("warm_{:016x}", rand::thread_rng().gen::<u64>());
+        let data_dir = self.warm_dir().join(&id);
+        fs::create_dir_all(&data_dir)
+            .await
+            .map_err(|e| PocketBaseError::IoError(format!("Failed to create warm pool directory: {}", e)))?;
+        fs::write(data_dir.join(KEY_ID_FILE), &id)
+            .await
+            .map_err(|e| PocketBaseError::IoError(format!("Failed to record warm pool key id: {}", e)))?;
+
+        let port = self.allocate_port(&id).await?;
+        let instance = PocketBaseInstance {
+            user_id: id.clone(),
+            port,
+            db_path: data_dir.clone(),
+            url: self.instance_url(port),
+            status: InstanceStatus::Starting,
+            created_at: chrono::Utc::now(),
+            last_health_check: None,
+            restart_count: 0,
+        };
+
+        let started = match self.start_pocketbase_process(&instance).await {
+            Ok((mut child, stderr_tail)) => match self.wait_for_ready(&mut child, &instance.url, &stderr_tail).await {
+                Ok(()) => Ok(child),
+                Err(e) => Err((Some(child), e)),
+            },
+            Err(e) => Err((None, e)),
+        };
+
+        match started {
+            Ok(child) => Ok(WarmInstance { id, port, data_dir, child }),
+            Err((child, e)) => {
+                self.discard(child, port, &data_dir).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Take a pool instance and move its data directory to `data_dir`
+    ///
+    /// The process keeps its open handles across the rename, which stays on
+    /// one filesystem because the pool lives under `user_dbs_path`. Returns
+    /// `None` when nothing usable is pooled, leaving a cold start to the caller.
+    async fn claim_warm_instance(&self, data_dir: &Path) -> Option<WarmInstance> {
+        if self.warm_pool_size == 0 {
+            return None;
+        }
+
+        loop {
+            let mut warm = self.warm_pool.lock().await.pop()?;
+            self.pool_notify.notify_one();
+
+            if !matches!(warm.child.try_wait(), Ok(None)) {
+                warn!("Warm PocketBase {} exited while pooled, discarding it", warm.id);
+                self.discard(Some(warm.child), warm.port, &warm.data_dir).await;
+                continue;
+            }
+
+            if let Err(e) = fs::rename(&warm.data_dir, data_dir).await {
+                warn!("Failed to move warm PocketBase {} into place: {}", warm.id, e);
+                self.discard(Some(warm.child), warm.port, &warm.data_dir).await;
+                return None;
+            }
+            warm.data_dir = data_dir.to_path_buf();
+            return Some(warm);
+        }
+    }
+
+    /// Claim a pool instance for a new user and register it as theirs
+    pub(super) async fn adopt_warm_instance(
+        &self,
+        user_id: &str,
+        data_dir: &Path,
+        restart_count: u32,
+    ) -> Option<PocketBaseInstance> {
+        let warm = self.claim_warm_instance(data_dir).await?;
+
+        let instance = PocketBaseInstance {
+            user_id: user_id.to_string(),
+            port: warm.port,
+            db_path: warm.data_dir,
+            url: self.instance_url(warm.port),
+            status: InstanceStatus::Running,
+            created_at: chrono::Utc::now(),
+            last_health_check: None,
+            restart_count,
+        };
+        self.processes.lock().await.insert(user_id.to_string(), warm.child);
+        self.instances
+            .write()
+            .await
+            .insert(user_id.to_string(), instance.clone());
+        let event_type = if restart_count > 0 {
+            SystemEventType::InstanceRestarted
+        } else {
+            SystemEventType::InstanceRunning
+        };
+        self.emit(event_type, user_id, instance.port);
+
+        info!("Assigned warm PocketBase {} to user {} on port {}", warm.id, user_id, instance.port);
+        Some(instance)
+    }
+
+    /// Stop every pooled instance and remove the pool directory
+    pub(super) async fn drain_warm_pool(&self) {
+        let pooled: Vec<WarmInstance> = self.warm_pool.lock().await.drain(..).collect();
+        for warm in pooled {
+            self.discard(Some(warm.child), warm.port, &warm.data_dir).await;
+        }
+        if let Err(e) = fs::remove_dir_all(self.warm_dir()).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove warm pool directory: {}", e);
+            }
+        }
+    }
+
+    async fn discard(&self, child: Option<Child>, port: u16, data_dir: &Path) {
+        if let Some(mut child) = child {
+            if let Err(e) = child.kill().await {
+                debug!("Warm PocketBase child already gone: {}", e);
+            }
+        }
+        self.release_port(port).await;
+        if let Err(e) = fs::remove_dir_all(data_dir).await {
+            debug!("Failed to remove warm pool directory {}: {}", data_dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_pocketbase, port_window};
+    use tokio::time::Instant;
+
+    fn pooled_manager(dir: &Path, size: usize) -> Arc<PocketBaseManager> {
+        let binary = fake_pocketbase(dir);
+        let port = port_window(10);
+        Arc::new(
+            PocketBaseManager::new(dir.join("user_dbs"), port, binary.display().to_string())
+                .with_port_range(port, port + 9)
+                .with_readiness_timeout(Duration::from_secs(10))
+                .with_warm_pool(size),
+        )
+    }
+
+    async fn wait_for_pool(manager: &PocketBaseManager, size: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while manager.warm_pool_len().await != size {
+            assert!(Instant::now() < deadline, "pool never reached {} instances", size);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_is_fast_and_pool_replenishes() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = pooled_manager(dir.path(), 2);
+        let pool = manager.start_warm_pool();
+        wait_for_pool(&manager, 2).await;
+        let pooled_ports: Vec<u16> = manager.warm_pool.lock().await.iter().map(|warm| warm.port).collect();
+
+        // Pool instances are invisible until claimed
+        assert!(manager.get_all_instances().await.is_empty());
+
+        let started = Instant::now();
+        let instance = manager.init_user_instance("olga").await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(250), "claim took {:?}", started.elapsed());
+        assert!(pooled_ports.contains(&instance.port));
+        assert_eq!(instance.status, InstanceStatus::Running);
+        assert_eq!(instance.db_path, manager.data_dir("olga"));
+        assert!(manager.data_dir("olga").is_dir());
+        assert!(PocketBaseManager::health_check_http(&instance.url).await);
+        assert_eq!(manager.get_all_instances().await.len(), 1);
+
+        wait_for_pool(&manager, 2).await;
+        assert_eq!(manager.allocated_ports().await.len(), 3);
+
+        manager.shutdown_all(Duration::from_secs(5)).await;
+        pool.await.unwrap();
+        assert_eq!(manager.warm_pool_len().await, 0);
+        assert!(!dir.path().join("user_dbs/.warm").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cold_start_when_pool_is_empty_or_user_has_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = pooled_manager(dir.path(), 1);
+
+        // The pool hasn't been started, so there is nothing to claim
+        let cold = manager.init_user_instance("pia").await.unwrap();
+        assert_eq!(cold.status, InstanceStatus::Running);
+        assert!(!dir.path().join("user_dbs/.warm").exists());
+
+        // Existing users keep their own data instead of taking a pool instance
+        std::fs::create_dir_all(manager.data_dir("quinn")).unwrap();
+        std::fs::write(manager.data_dir("quinn").join("data.db"), b"mine").unwrap();
+        let pool = manager.start_warm_pool();
+        wait_for_pool(&manager, 1).await;
+        let instance = manager.init_user_instance("quinn").await.unwrap();
+        assert_eq!(manager.warm_pool_len().await, 1);
+        assert_eq!(std::fs::read(instance.db_path.join("data.db")).unwrap(), b"mine");
+
+        manager.shutdown_all(Duration::from_secs(5)).await;
+        pool.await.unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Resource usage collection for PocketBase child processes
+//!
+//! Process metrics come from `/proc/<pid>` and are only available on Linux;
+//! other platforms report [`ProcessMetrics::Unsupported`] instead of failing.
+
+use serde::Serialize;
+use std::path::Path;
+
+/// `/proc` reports CPU time in clock ticks; USER_HZ is 100 on every
+/// mainstream Linux architecture
+const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+/// Cached resource usage for one instance
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceStats {
+    pub user_id: String,
+    pub uptime_seconds: i64,
+    pub restart_count: u32,
+    pub process: ProcessMetrics,
+    pub db_size_bytes: u64,
+    pub db_file_count: u64,
+    pub collected_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Memory and CPU usage of a child process
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ProcessMetrics {
+    Available { rss_bytes: u64, cpu_time_ms: u64 },
+    /// The process could not be inspected (e.g. it has exited)
+    Unavailable { reason: String },
+    /// This platform has no `/proc` to read from
+    Unsupported { reason: String },
+}
+
+/// Read the metrics for `pid` on Linux
+#[cfg(target_os = "linux")]
+pub fn read_process_metrics(pid: Option<u32>) -> ProcessMetrics {
+    let Some(pid) = pid else {
+        return ProcessMetrics::Unavailable {
+            reason: "Process is not running".to_string(),
+        };
+    };
+
+    let proc_dir = Path::new("/proc").join(pid.to_string());
+    let stat = std::fs::read_to_string(proc_dir.join("stat"));
+    let status = std::fs::read_to_string(proc_dir.join("status"));
+
+    match (stat, status) {
+        (Ok(stat), Ok(status)) => match (parse_cpu_time_ms(&stat), parse_rss_bytes(&status)) {
+            (Some(cpu_time_ms), Some(rss_bytes)) => ProcessMetrics::Available { rss_bytes, cpu_time_ms },
+            _ => ProcessMetrics::Unavailable {
+                reason: format!("Unrecognised /proc/{} format", pid),
+            },
+        },
+        (Err(e), _) | (_, Err(e)) => ProcessMetrics::Unavailable {
+            reason: format!("Failed to read /proc/{}: {}", pid, e),
+        },
+    }
+}
+
+/// Fallback for platforms without `/proc`
+#[cfg(not(target_os = "linux"))]
+pub fn read_process_metrics(_pid: Option<u32>) -> ProcessMetrics {
+    unsupported()
+}
+
+/// Metrics placeholder for platforms without `/proc`
+pub fn unsupported() -> ProcessMetrics {
+    ProcessMetrics::Unsupported {
+        reason: "Process metrics are only collected on Linux".to_string(),
+    }
+}
+
+/// Total user + system CPU time from the contents of `/proc/<pid>/stat`
+pub fn parse_cpu_time_ms(stat: &str) -> Option<u64> {
+    // The command name is wrapped in parentheses and may contain spaces, so
+    // count fields from the closing parenthesis. utime and stime are fields
+    // 14 and 15 overall, i.e. the 12th and 13th after the name.
+    let after_name = &stat[stat.rfind(')')? + 1..];
+    let mut fields = after_name.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+
+    Some((utime + stime) * 1000 / CLOCK_TICKS_PER_SECOND)
+}
+
+/// Resident set size from the contents of `/proc/<pid>/status`
+pub fn parse_rss_bytes(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Total size and number of regular files under `path`
+///
+/// A missing directory counts as empty.
+pub fn dir_size(path: &Path) -> std::io::Result<(u64, u64)> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    };
+
+    let mut bytes = 0;
+    let mut files = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let (dir_bytes, dir_files) = dir_size(&entry.path())?;
+            bytes += dir_bytes;
+            files += dir_files;
+        } else if file_type.is_file() {
+            bytes += entry.metadata()?.len();
+            files += 1;
+        }
+    }
+
+    Ok((bytes, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "4242 (pocket base) S 1 4242 4242 0 -1 4194560 2783 0 0 0 \
+                        250 75 0 0 20 0 9 0 123456 745123840 5432 18446744073709551615";
+
+    const STATUS: &str = "Name:\tpocketbase\nState:\tS (sleeping)\nVmPeak:\t  800000 kB\n\
+                          VmRSS:\t   21728 kB\nThreads:\t9\n";
+
+    #[test]
+    fn test_parse_canned_proc_files() {
+        // 250 + 75 ticks at 100Hz
+        assert_eq!(parse_cpu_time_ms(STAT), Some(3250));
+        assert_eq!(parse_rss_bytes(STATUS), Some(21728 * 1024));
+
+        assert_eq!(parse_cpu_time_ms("garbage"), None);
+        assert_eq!(parse_rss_bytes("Name:\tpocketbase\n"), None);
+    }
+
+    #[test]
+    fn test_dir_size_aggregates_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.db"), vec![0u8; 1000]).unwrap();
+        std::fs::create_dir_all(dir.path().join("storage/nested")).unwrap();
+        std::fs::write(dir.path().join("storage/a.bin"), vec![0u8; 24]).unwrap();
+        std::fs::write(dir.path().join("storage/nested/b.bin"), vec![0u8; 76]).unwrap();
+
+        assert_eq!(dir_size(dir.path()).unwrap(), (1100, 3));
+        assert_eq!(dir_size(&dir.path().join("missing")).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_unsupported_fallback_shape() {
+        let value = serde_json::to_value(unsupported()).unwrap();
+        assert_eq!(value["status"], "unsupported");
+        assert!(value["reason"].is_string());
+        assert!(value.get("rss_bytes").is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reads_own_process() {
+        match read_process_metrics(Some(std::process::id())) {
+            ProcessMetrics::Available { rss_bytes, .. } => assert!(rss_bytes > 0),
+            other => panic!("unexpected metrics: {:?}", other),
+        }
+    }
+}
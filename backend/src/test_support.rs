@@ -0,0 +1,664 @@
+//! Shared helpers for backend unit tests
+//!
+//! Provides a fake PocketBase binary (a small python3 script speaking just
+//! enough HTTP for health checks), archive builders for backup tests, and
+//! helpers for running handlers against mock upstream servers.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::sync::{Mutex, RwLock};
+use common::{
+    broadcast::BroadcastServiceFactory,
+    clock::{Clock, SystemClock},
+};
+
+pub use common::test_support::{spawn_server, MockPb};
+
+use crate::{
+    api::{
+        audit::AuditLogger,
+        auth_cache::AuthCache,
+        key_audit::KeyAuditLog,
+        key_repository::MasterKey,
+        login_guard::LoginGuard,
+        rate_limit::RateLimits, revocation::RevocationList, sessions::SessionList, startup::StartupState,
+        websocket::WebSocketManager, AppState,
+    },
+    config::{
+        Config, CorsConfig, DatabaseConfig, EmailConfig, FeaturesConfig, IntegrationsConfig, LoggingConfig, PocketBaseConfig, RateLimitConfig, SameSite,
+        SecurityConfig, ServerConfig,
+    },
+    global_pb::GlobalPb,
+    mailer::Mailer,
+    pocketbase_manager::PocketBaseManager,
+};
+
+/// Script emulating `pocketbase serve --http <addr> --dir <dir>`
+///
+/// Answers every request with 200 and a JSON echo of the method, path and
+/// body, exits cleanly on SIGTERM, and reports 0.22.21 for `--version`.
+/// `admin create|update <email> <password> --dir <dir>` keep admin accounts
+/// in `admins.json`, appending the action to `admin_commands`. Sentinel files
+/// in the data directory change its behaviour:
+///
+/// - `FAIL_START`: exit immediately with status 1, so the instance never
+///   becomes ready
+/// - `IGNORE_SIGTERM`: ignore SIGTERM so the process has to be killed
+/// - `PORT_IN_USE_ONCE`: consumed; that start fails as if another process
+///   held the port
+/// - `COUNT_SPAWNS`: append a line to `spawns` on every start
+/// - `DUMP_ENV`: write the environment, arguments and working directory the
+///   process was started with to `env.json`
+/// - `EXIT_AFTER_FIRST_REQUEST`: answer the readiness check, then exit,
+///   simulating a crash after a successful start
+/// - `STORE_RECORDS`: instead of echoing, keep collections and records in
+///   memory behind admin auth, like PocketBase's admin API (filters support
+///   `field = 'value'` joined with `&&`), list the admins in `admins.json`
+///   and create the first one without auth
+const FAKE_POCKETBASE: &str = r#"
+import http.server, json, os, signal, sys, urllib.parse
+
+args = sys.argv[1:]
+if "--version" in args:
+    print("pocketbase version 0.22.21")
+    sys.exit(0)
+
+data_dir = args[args.index("--dir") + 1]
+admins_path = os.path.join(data_dir, "admins.json")
+
+def load_admins():
+    if not os.path.exists(admins_path):
+        return {}
+    with open(admins_path) as admins:
+        return json.load(admins)
+
+if args[0] == "admin":
+    action, email, password = args[1], args[2], args[3]
+    admins = load_admins()
+    if (action == "create") == (email in admins):
+        sys.stderr.write("Failed to %s admin %s\n" % (action, email))
+        sys.exit(1)
+    admins[email] = password
+    with open(admins_path, "w") as out:
+        json.dump(admins, out)
+    with open(os.path.join(data_dir, "admin_commands"), "a") as commands:
+        commands.write(action + "\n")
+    sys.exit(0)
+
+host, port = args[args.index("--http") + 1].rsplit(":", 1)
+
+if os.path.exists(os.path.join(data_dir, "COUNT_SPAWNS")):
+    with open(os.path.join(data_dir, "spawns"), "a") as spawns:
+        spawns.write("%d\n" % os.getpid())
+
+if os.path.exists(os.path.join(data_dir, "DUMP_ENV")):
+    # /proc holds the environment as spawned, before Python adjusts its own
+    try:
+        with open("/proc/self/environ", "rb") as environ:
+            entries = [entry.decode().split("=", 1) for entry in environ.read().split(b"\0") if entry]
+        env = dict(entry for entry in entries if len(entry) == 2)
+    except OSError:
+        env = dict(os.environ)
+    with open(os.path.join(data_dir, "env.json"), "w") as dump:
+        json.dump({"env": env, "args": args, "cwd": os.getcwd()}, dump)
+
+if os.path.exists(os.path.join(data_dir, "FAIL_START")):
+    sys.exit(1)
+
+bind_failure = os.path.join(data_dir, "PORT_IN_USE_ONCE")
+if os.path.exists(bind_failure):
+    os.remove(bind_failure)
+    sys.stderr.write("Error: listen tcp %s:%s: bind: address already in use\n" % (host, port))
+    sys.exit(1)
+
+store_records = os.path.exists(os.path.join(data_dir, "STORE_RECORDS"))
+collections = {}
+admin_tokens = set()
+
+def matches(record, expression):
+    for condition in expression.split("&&"):
+        field, value = condition.split("=", 1)
+        value = value.strip()[1:-1].replace("\\'", "'").replace("\\\\", "\\")
+        if str(record.get(field.strip())) != value:
+            return False
+    return True
+
+def store(method, path, query, authorization, received):
+    parts = path.strip("/").split("/")
+    if path == "/api/admins/auth-with-password":
+        credentials = json.loads(received)
+        if load_admins().get(credentials.get("identity")) != credentials.get("password"):
+            return 400, {"code": 400, "message": "Failed to authenticate."}
+        token = "admin-token-%d" % len(admin_tokens)
+        admin_tokens.add(token)
+        return 200, {"token": token}
+    if path == "/api/admins/auth-refresh":
+        if authorization not in admin_tokens:
+            return 401, {"code": 401, "message": "The request requires admin authorization token to be set."}
+        return 200, {"token": authorization}
+    if path == "/api/admins" and method == "GET":
+        if authorization not in admin_tokens:
+            return 401, {"code": 401, "message": "The request requires admin authorization token to be set."}
+        items = [{"email": email} for email in load_admins()]
+        return 200, {"page": 1, "perPage": len(items), "totalItems": len(items), "items": items}
+    if path == "/api/admins" and method == "POST":
+        admins = load_admins()
+        if admins and authorization not in admin_tokens:
+            return 401, {"code": 401, "message": "The request requires admin authorization token to be set."}
+        admin = json.loads(received)
+        admins[admin["email"]] = admin["password"]
+        with open(admins_path, "w") as out:
+            json.dump(admins, out)
+        return 200, {"email": admin["email"]}
+    if parts[:2] != ["api", "collections"]:
+        return 404, {"code": 404, "message": "Not found."}
+    if authorization not in admin_tokens:
+        return 401, {"code": 401, "message": "The request requires admin authorization token to be set."}
+    if len(parts) == 2 and method == "POST":
+        schema = json.loads(received)
+        collections[schema["name"]] = []
+        return 200, schema
+    name = parts[2] if len(parts) > 2 else None
+    if name not in collections:
+        return 404, {"code": 404, "message": "Missing collection context."}
+    if len(parts) == 3:
+        return 200, {"name": name}
+    records = collections[name]
+    if len(parts) == 4 and method == "GET":
+        expression = urllib.parse.parse_qs(query).get("filter", [""])[0]
+        items = [record for record in records if not expression or matches(record, expression)]
+        return 200, {"page": 1, "perPage": len(items), "totalItems": len(items), "items": items}
+    if len(parts) == 4 and method == "POST":
+        record = json.loads(received)
+        record["id"] = "rec%012d" % sum(len(items) for items in collections.values())
+        records.append(record)
+        return 200, record
+    record = next((record for record in records if record["id"] == parts[4]), None)
+    if record is None:
+        return 404, {"code": 404, "message": "The requested resource wasn't found."}
+    if method == "PATCH":
+        record.update(json.loads(received))
+    elif method == "DELETE":
+        records.remove(record)
+        return 204, None
+    return 200, record
+
+class Handler(http.server.BaseHTTPRequestHandler):
+    def echo(self):
+        length = int(self.headers.get("Content-Length") or 0)
+        received = self.rfile.read(length).decode() if length else ""
+        if store_records and self.path != "/api/health":
+            path, _, query = self.path.partition("?")
+            status, answer = store(self.command, path, query, self.headers.get("Authorization"), received)
+            body = b"" if answer is None else json.dumps(answer).encode()
+            self.send_response(status)
+            self.send_header("Content-Type", "application/json")
+            self.send_header("Content-Length", str(len(body)))
+            self.end_headers()
+            self.wfile.write(body)
+            return
+        body = json.dumps({
+            "method": self.command,
+            "path": self.path,
+            "body": received,
+            "authorization": self.headers.get("Authorization"),
+            "cookie": self.headers.get("Cookie"),
+            "csrf": self.headers.get("X-CSRF-Token"),
+        }).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    do_GET = do_POST = do_PUT = do_PATCH = do_DELETE = echo
+
+    def log_message(self, *args):
+        pass
+
+if os.path.exists(os.path.join(data_dir, "IGNORE_SIGTERM")):
+    signal.signal(signal.SIGTERM, signal.SIG_IGN)
+else:
+    signal.signal(signal.SIGTERM, lambda *_: sys.exit(0))
+server = http.server.HTTPServer((host, int(port)), Handler)
+if os.path.exists(os.path.join(data_dir, "EXIT_AFTER_FIRST_REQUEST")):
+    server.handle_request()
+    sys.exit(1)
+server.serve_forever()
+"#;
+
+/// Absolute path of the python3 interpreter
+///
+/// The fake binary's shebang points straight at it, so launchers such as
+/// version-manager shims can't add to the environment the script observes.
+fn python3() -> &'static str {
+    static PYTHON: OnceLock<String> = OnceLock::new();
+    PYTHON.get_or_init(|| {
+        let output = std::process::Command::new("python3")
+            .args(["-c", "import sys; print(sys.executable)"])
+            .output()
+            .expect("python3 is required to run the fake PocketBase");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    })
+}
+
+/// First of `len` ports no other test in this run is handed, for a test's
+/// instances to be given with `with_port_range`
+///
+/// Windows are taken in turn from below Linux's ephemeral range, where
+/// outgoing connections won't be holding them.
+pub fn port_window(len: u16) -> u16 {
+    const FIRST: u16 = 20000;
+    const END: u16 = 32000;
+    static NEXT: AtomicU16 = AtomicU16::new(FIRST);
+
+    let start = NEXT.fetch_add(len, Ordering::SeqCst);
+    assert!(start.checked_add(len).is_some_and(|end| end <= END), "test port windows used up");
+    start
+}
+
+/// Write the fake PocketBase binary into `dir` and return its path
+pub fn fake_pocketbase(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("fake-pocketbase");
+    std::fs::write(&path, format!("#!{}{}", python3(), FAKE_POCKETBASE)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Build a tar.gz from (path, contents) pairs
+///
+/// Header names are written raw so malicious paths such as `../x` survive
+/// instead of being normalized away by the tar builder.
+pub fn build_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        let name = &mut header.as_old_mut().name;
+        name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        builder.append(&header, *contents).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+/// Mock global PocketBase accepting tokens of the form `valid-<user_id>`
+/// and issuing them from `auth-with-password`
+///
+/// The shared [`MockPb`], with `users` as an auth collection and revoked
+/// token hashes unique as in the schema, plus the routes users sign in with. Any identity logs in with the password
+/// "password" until a record update sets another; identities with a record
+/// in `users` get that record, others a synthetic one.
+pub async fn mock_global_pocketbase() -> String {
+    counting_global_pocketbase().await.0
+}
+
+#[derive(Clone)]
+struct MockGlobal {
+    pb: MockPb,
+    auth_refresh_calls: Arc<AtomicUsize>,
+}
+
+/// [`mock_global_pocketbase`] plus a count of `auth-refresh` calls it served
+pub async fn counting_global_pocketbase() -> (String, Arc<AtomicUsize>) {
+    async fn auth_refresh(
+        State(mock): State<MockGlobal>,
+        headers: HeaderMap,
+    ) -> Result<Json<Value>, StatusCode> {
+        mock.auth_refresh_calls.fetch_add(1, Ordering::SeqCst);
+        let user_id = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer valid-"))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        Ok(Json(json!({
+            "token": MockPb::user_token(user_id),
+            "record": { "id": user_id, "email": format!("{}@example.com", user_id) }
+        })))
+    }
+
+    async fn auth_with_password(
+        State(mock): State<MockGlobal>,
+        Json(body): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let email = body["identity"].as_str().unwrap_or_default();
+        let stored = mock.pb.records("users").into_iter().find(|user| user["email"] == email);
+        let expected = stored
+            .as_ref()
+            .and_then(|record| mock.pb.password("users", record["id"].as_str().unwrap_or_default()))
+            .unwrap_or_else(|| "password".to_string());
+        if body["password"] != expected.as_str() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let record = match stored {
+            Some(mut record) => {
+                if let Some(fields) = record.as_object_mut() {
+                    fields.remove("password");
+                    fields.remove("passwordConfirm");
+                }
+                record
+            }
+            None => json!({ "id": email.split('@').next().unwrap_or_default(), "email": email }),
+        };
+
+        Ok(Json(json!({
+            "token": MockPb::user_token(record["id"].as_str().unwrap_or_default()),
+            "record": record
+        })))
+    }
+
+    // Google, as PocketBase's only OAuth2 provider
+    async fn auth_methods() -> Json<Value> {
+        let auth_url = "https://accounts.google.com/o/oauth2/auth?client_id=mock-client\
+            &code_challenge=mock-challenge&code_challenge_method=S256&response_type=code\
+            &scope=openid+email&state=mock-state&redirect_uri=";
+        Json(json!({
+            "emailPassword": true,
+            "authProviders": [{
+                "name": "google",
+                "displayName": "Google",
+                "state": "mock-state",
+                "authUrl": auth_url,
+                "codeVerifier": "mock-verifier",
+                "codeChallenge": "mock-challenge",
+                "codeChallengeMethod": "S256"
+            }]
+        }))
+    }
+
+    // Codes of the form `google-<email>` sign in that Google account,
+    // linking to the `users` record with its email or creating one
+    async fn auth_with_oauth2(
+        State(mock): State<MockGlobal>,
+        Json(body): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let email = body["code"]
+            .as_str()
+            .and_then(|code| code.strip_prefix("google-"))
+            .filter(|_| body["provider"] == "google" && body["codeVerifier"] == "mock-verifier")
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        let existing = mock.pb.records("users").into_iter().find(|user| user["email"] == email);
+        let is_new = existing.is_none();
+        let record = match existing {
+            Some(record) => record,
+            None => {
+                let id = mock.pb.insert("users", json!({ "email": email, "verified": true }));
+                mock.pb.record("users", &id).unwrap_or_default()
+            }
+        };
+        Ok(Json(json!({
+            "token": MockPb::user_token(record["id"].as_str().unwrap_or_default()),
+            "record": record,
+            "meta": { "email": email, "isNew": is_new }
+        })))
+    }
+
+    let auth_refresh_calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&auth_refresh_calls);
+    let mock = MockPb::start_with(|pb| {
+        Router::new()
+            .route("/api/collections/users/auth-refresh", post(auth_refresh))
+            .route("/api/collections/users/auth-with-password", post(auth_with_password))
+            .route("/api/collections/users/auth-methods", get(auth_methods))
+            .route("/api/collections/users/auth-with-oauth2", post(auth_with_oauth2))
+            .with_state(MockGlobal {
+                pb: pb.clone(),
+                auth_refresh_calls: counted,
+            })
+    })
+    .await;
+    mock.auth_collection("users");
+    mock.unique("revoked_tokens", &["token_hash"]);
+    (mock.url, auth_refresh_calls)
+}
+
+/// Mock smtp-service accepting `POST /send-email`, returning its URL and
+/// every message it was sent
+pub async fn mock_smtp_service() -> (String, Arc<Mutex<Vec<Value>>>) {
+    async fn send_email(
+        State(sent): State<Arc<Mutex<Vec<Value>>>>,
+        Json(email): Json<Value>,
+    ) -> (StatusCode, Json<Value>) {
+        let mut sent = sent.lock().await;
+        sent.push(email);
+        (
+            StatusCode::ACCEPTED,
+            Json(json!({ "success": true, "queue_id": format!("mail{}", sent.len()) })),
+        )
+    }
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let router = Router::new()
+        .route("/send-email", post(send_email))
+        .with_state(Arc::clone(&sent));
+    (spawn_server(router).await, sent)
+}
+
+/// The `?token=` value from the link in an email sent through [`mock_smtp_service`]
+pub fn emailed_token(email: &Value) -> String {
+    let body = email["body_text"].as_str().unwrap_or_default();
+    body.split("?token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A [`Clock`] a test moves by hand, in unix seconds
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        Self(AtomicU64::new(now))
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.0.load(Ordering::SeqCst) as i64, 0).unwrap_or_default()
+    }
+}
+
+/// Config pointing the global PocketBase at `database_url`
+pub fn test_config(database_url: &str) -> Config {
+    Config {
+        server: ServerConfig {
+            port: 3000,
+            host: "127.0.0.1".to_string(),
+            metrics_enabled: true,
+            openapi_enabled: false,
+            shutdown_grace_secs: 30,
+            body_limit_bytes: 2 * 1024 * 1024,
+            upload_body_limit_bytes: 16 * 1024 * 1024,
+            request_timeout_secs: 30,
+            long_request_timeout_secs: 300,
+            public_ws_url: None,
+            serve_frontend: false,
+            frontend_dist_path: "frontend/dist".to_string(),
+            tls_cert_path: None,
+            tls_key_path: None,
+        },
+        database: DatabaseConfig {
+            url: database_url.to_string(),
+            admin_email: "admin@example.com".to_string(),
+            admin_password: "admin-password".to_string(),
+            user_db_base_path: "./user_dbs".to_string(),
+        },
+        security: SecurityConfig {
+            master_key: "dGVzdC1tYXN0ZXIta2V5LTMyLWJ5dGVzLWxvbmchISE=".to_string(),
+            master_key_previous: None,
+            jwt_secret: "test-jwt-secret".to_string(),
+            internal_api_token: Some("test-internal-token".to_string()),
+            metrics_token: None,
+            pb_encryption_key: "test-pb-encryption-key".to_string(),
+            auth_cache_ttl_secs: 60,
+            auth_cache_max_entries: 100,
+            jwt_kid: "test".to_string(),
+            jwt_previous_secrets: Vec::new(),
+            jwt_expiry_secs: 3600,
+            jwt_refresh_window_secs: 86400,
+            jwt_max_session_secs: 604800,
+            accept_legacy_pb_tokens: true,
+            trust_proxy_headers: false,
+            password_reset_url: "http://localhost:8080/reset-password".to_string(),
+            password_reset_ttl_secs: 1800,
+            password_reset_max_per_email: 3,
+            password_reset_max_per_ip: 20,
+            password_reset_window_secs: 3600,
+            email_verification_url: "http://localhost:3000/auth/verify_email".to_string(),
+            email_verification_ttl_secs: 86400,
+            verification_resend_max: 3,
+            verification_resend_window_secs: 3600,
+            login_max_attempts: 10,
+            login_window_secs: 300,
+            login_lockout_threshold: 5,
+            login_lockout_secs: 900,
+            admin_emails: Vec::new(),
+            oauth_google_redirect_url: "http://localhost:3000/auth/oauth/google/callback".to_string(),
+            oauth_frontend_url: "http://localhost:8080/oauth/callback".to_string(),
+            session_cookie_same_site: SameSite::Strict,
+            session_cookie_secure: true,
+        },
+        logging: LoggingConfig {
+            level: "info".to_string(),
+            format: Default::default(),
+            requests: Default::default(),
+            sentry_dsn: None,
+        },
+        cors: CorsConfig {
+            origins: vec!["http://localhost:8080".to_string()],
+        },
+        pocketbase: PocketBaseConfig {
+            base_port: 9000,
+            binary_path: "pocketbase".to_string(),
+            user_dbs_path: "./user_dbs".to_string(),
+            port_range_start: 9000,
+            port_range_end: 9999,
+            bind_host: "127.0.0.1".to_string(),
+            shutdown_grace_secs: 10,
+            health_check_interval_secs: 30,
+            auto_download: false,
+            managed_bin_dir: "./pb_bin".to_string(),
+            binary_sha256: None,
+            warm_pool_size: 0,
+        },
+        // Nothing listens here; tests that send email point it at [`mock_smtp_service`]
+        email: EmailConfig {
+            smtp_service_url: "http://127.0.0.1:9".to_string(),
+            smtp_service_api_key: None,
+        },
+        // Likewise for the third-party APIs keys are checked against
+        integrations: IntegrationsConfig {
+            fathom_api_url: "http://127.0.0.1:9".to_string(),
+            loom_api_url: "http://127.0.0.1:9".to_string(),
+            key_validation_timeout: std::time::Duration::from_millis(500),
+            key_validation_max: 10,
+            key_validation_window_secs: 3600,
+            key_expiry_warning_days: 7,
+            key_settings_url: "http://localhost:8080/settings".to_string(),
+            meetings_cache_ttl: std::time::Duration::from_secs(600),
+            meetings_cache_stale: std::time::Duration::from_secs(3600),
+            fathom_requests_per_minute: 60,
+            fathom_max_attempts: 3,
+            meetings_refresh_cooldown: std::time::Duration::from_secs(30),
+        },
+        // High enough that only tests of the limits themselves run into them
+        rate_limits: RateLimitConfig {
+            auth_per_minute: 10_000,
+            read_per_minute: 10_000,
+            write_per_minute: 10_000,
+            webhook_per_minute: 10_000,
+            max_clients: 1_000,
+        },
+        features: FeaturesConfig {
+            registration_enabled: true,
+            oauth_providers: vec!["google".to_string()],
+            maintenance_mode: false,
+            max_queue_items_per_user: 0,
+        },
+    }
+}
+
+/// Application state around the given config and manager
+pub fn test_app_state(config: Config, pb_manager: PocketBaseManager) -> AppState {
+    let global_pb = Arc::new(GlobalPb::from(&config.database));
+    let rate_limits = Arc::new(RateLimits::new(&config.security, &config.integrations, &config.rate_limits));
+    let mailer = Arc::new(Mailer::new(&config.email));
+    let login_guard = Arc::new(LoginGuard::new(&config.security, Arc::new(SystemClock)));
+    let sessions = Arc::new(SessionList::new(global_pb.clone(), Arc::new(SystemClock)));
+    let audit = Arc::new(AuditLogger::new(global_pb.clone(), Arc::new(SystemClock)));
+    let key_audit = Arc::new(KeyAuditLog::new(global_pb.clone(), Arc::new(SystemClock)));
+    let master_key = Arc::new(MasterKey::from_config(&config.security).unwrap());
+    let broadcast = BroadcastServiceFactory::create_shared(100);
+    AppState {
+        config: Arc::new(config),
+        pb_manager: Arc::new(pb_manager),
+        ws_manager: Arc::new(WebSocketManager::with_external_broadcast(broadcast.clone())),
+        meetings_queue: Arc::new(RwLock::new(Vec::new())),
+        auth_cache: Arc::new(AuthCache::new(Duration::from_secs(60), 100)),
+        revocations: Arc::new(RevocationList::in_memory()),
+        global_pb,
+        rate_limits,
+        mailer,
+        login_guard,
+        sessions,
+        audit,
+        key_audit,
+        master_key,
+        broadcast,
+        startup: {
+            // Tests exercise a backend that has finished starting
+            let startup = Arc::new(StartupState::new());
+            startup.mark_ready();
+            startup
+        },
+    }
+}
+
+/// A manager for tests that never start a user's instance
+pub fn unused_pb_manager() -> PocketBaseManager {
+    PocketBaseManager::new(std::env::temp_dir().join("unused_user_dbs"), 9000, "pocketbase".to_string())
+}
+
+/// Application state around [`mock_global_pocketbase`] and a manager that
+/// starts no instances
+pub async fn mock_app_state() -> AppState {
+    mock_app_state_with(|_| {}).await
+}
+
+/// [`mock_app_state`], its config changed by `configure` first
+pub async fn mock_app_state_with(configure: impl FnOnce(&mut Config)) -> AppState {
+    let global_url = mock_global_pocketbase().await;
+    let mut config = test_config(&global_url);
+    configure(&mut config);
+    test_app_state(config, unused_pb_manager())
+}
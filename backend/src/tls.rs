@@ -0,0 +1,622 @@
+//! HTTPS straight from the backend, for deployments without a proxy in front
+//!
+//! With `TLS_CERT_PATH` and `TLS_KEY_PATH` set the listener speaks TLS
+//! through rustls instead of plain HTTP. The certificate file may hold a
+//! whole chain, leaf first. Its subject and expiry are logged when loaded,
+//! with a warning once fewer than [`EXPIRY_WARNING_DAYS`] remain. On SIGHUP
+//! both files are read again: new connections get the new certificate while
+//! open ones keep the one they started with, and a renewal that fails to
+//! load leaves the old certificate in place.
+
+use axum::{extract::ConnectInfo, Router};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request};
+use hyper_util::rt::TokioIo;
+use rustls::{
+    crypto::ring,
+    pki_types::{
+        CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer, PrivateSec1KeyDer,
+    },
+    ServerConfig,
+};
+use std::{
+    future::Future,
+    io::{self, BufReader},
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::watch};
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, info, warn};
+
+/// Days of validity left below which loading the certificate warns
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// How long a client gets to finish the handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed `accept`, which is usually out of file descriptors
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("{0} could not be read: {1}")]
+    Read(String, io::Error),
+
+    #[error("{0} holds no PEM certificate")]
+    NoCertificate(String),
+
+    #[error("{0} holds no PEM private key")]
+    NoKey(String),
+
+    #[error("The certificate in {0} is not valid X.509")]
+    InvalidCertificate(String),
+
+    #[error("{0} is not the private key for the certificate in {1}")]
+    KeyMismatch(String, String),
+
+    #[error("TLS setup failed: {0}")]
+    Rustls(rustls::Error),
+}
+
+/// What gets logged about the certificate being served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The leaf's subject, such as `O=Example, CN=example.com`
+    pub subject: String,
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertificateInfo {
+    /// The subject and expiry of a DER-encoded X.509 certificate
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let mut certificate = Der(der).expect(SEQUENCE)?;
+        let mut tbs = certificate.expect(SEQUENCE)?;
+        // The version is an explicit [0] that v1 certificates leave out
+        if tbs.0.first() == Some(&VERSION) {
+            tbs.next()?;
+        }
+        tbs.next()?; // serialNumber
+        tbs.next()?; // signature
+        tbs.next()?; // issuer
+        let mut validity = tbs.expect(SEQUENCE)?;
+        validity.next()?; // notBefore
+        let (tag, not_after) = validity.next()?;
+        Some(Self {
+            subject: render_name(tbs.expect(SEQUENCE)?)?,
+            not_after: parse_time(tag, not_after)?,
+        })
+    }
+
+    /// Whether fewer than [`EXPIRY_WARNING_DAYS`] are left at `now`
+    pub fn expires_soon(&self, now: DateTime<Utc>) -> bool {
+        self.not_after - now < chrono::Duration::days(EXPIRY_WARNING_DAYS)
+    }
+}
+
+/// A certificate and key that can be swapped while the server runs
+pub struct Tls {
+    cert_path: String,
+    key_path: String,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Tls {
+    /// Load the chain and key, refusing files that can't be used together
+    pub fn load(cert_path: &str, key_path: &str) -> Result<Self, TlsError> {
+        let (config, certificate) = read(cert_path, key_path)?;
+        log_certificate("Serving TLS", &certificate);
+        Ok(Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Read both files again, keeping the current certificate if that fails
+    pub fn reload(&self) -> Result<CertificateInfo, TlsError> {
+        let (config, certificate) = read(&self.cert_path, &self.key_path)?;
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+        log_certificate("Reloaded TLS", &certificate);
+        Ok(certificate)
+    }
+
+    /// Reload on every SIGHUP until the returned future is dropped
+    ///
+    /// The handler is installed before this returns, so a SIGHUP sent right
+    /// after can't fall back to the default of killing the process.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self: Arc<Self>) -> io::Result<impl Future<Output = ()> + Send> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        Ok(async move {
+            while hangups.recv().await.is_some() {
+                info!("SIGHUP received, reloading the TLS certificate");
+                if let Err(e) = self.reload() {
+                    warn!("Keeping the current TLS certificate: {}", e);
+                }
+            }
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(
+            self.config
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )
+    }
+}
+
+/// Serve `app` over TLS on `listener` until `shutdown` resolves
+///
+/// Like `axum::serve` with graceful shutdown: once `shutdown` resolves no
+/// more connections are accepted, open ones finish their current request,
+/// and the returned future completes when the last has closed. Handlers see
+/// the peer address through `ConnectInfo<SocketAddr>`.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Arc<Tls>,
+    shutdown: impl Future<Output = ()> + Send,
+) -> io::Result<()> {
+    // Each connection holds a receiver, so `closed` waits for the last one
+    let (closed_tx, closed_rx) = watch::channel(());
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+        let mut stop = stop_rx.clone();
+        let closed = closed_rx.clone();
+        tokio::spawn(async move {
+            let _closed = closed;
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", peer);
+                        return;
+                    }
+                };
+
+            // A `Router` is always ready, so it can be called without polling
+            let service = service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                app.clone().call(request)
+            });
+            let connection = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                // The queue WebSocket upgrades from HTTP/1.1
+                .with_upgrades();
+            tokio::pin!(connection);
+            let stopping = async move {
+                let _ = stop.wait_for(|stop| *stop).await;
+            };
+
+            let served = tokio::select! {
+                served = connection.as_mut() => served,
+                _ = stopping => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = served {
+                debug!("Connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+
+    drop(listener);
+    let _ = stop_tx.send(true);
+    drop(closed_rx);
+    closed_tx.closed().await;
+    Ok(())
+}
+
+fn log_certificate(action: &str, certificate: &CertificateInfo) {
+    info!(
+        "{} for {}, valid until {}",
+        action, certificate.subject, certificate.not_after
+    );
+    if certificate.expires_soon(Utc::now()) {
+        warn!(
+            "The TLS certificate for {} expires on {}; renew it and send SIGHUP",
+            certificate.subject, certificate.not_after
+        );
+    }
+}
+
+/// A rustls config for the chain and key, and what the leaf says about itself
+fn read(cert_path: &str, key_path: &str) -> Result<(ServerConfig, CertificateInfo), TlsError> {
+    let chain = read_chain(cert_path)?;
+    let key = read_key(key_path)?;
+    let certificate = CertificateInfo::from_der(&chain[0])
+        .ok_or_else(|| TlsError::InvalidCertificate(cert_path.to_string()))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(TlsError::Rustls)?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| match e {
+            rustls::Error::InconsistentKeys(_) => {
+                TlsError::KeyMismatch(key_path.to_string(), cert_path.to_string())
+            }
+            e => TlsError::Rustls(e),
+        })?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok((config, certificate))
+}
+
+fn open(path: &str) -> Result<BufReader<std::fs::File>, TlsError> {
+    std::fs::File::open(Path::new(path))
+        .map(BufReader::new)
+        .map_err(|e| TlsError::Read(path.to_string(), e))
+}
+
+fn read_chain(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let chain =
+        rustls_pemfile::certs(&mut open(path)?).map_err(|e| TlsError::Read(path.to_string(), e))?;
+    if chain.is_empty() {
+        return Err(TlsError::NoCertificate(path.to_string()));
+    }
+    Ok(chain.into_iter().map(CertificateDer::from).collect())
+}
+
+/// The first PKCS#8, PKCS#1 or SEC1 key in `path`
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let items = rustls_pemfile::read_all(&mut open(path)?)
+        .map_err(|e| TlsError::Read(path.to_string(), e))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key) => Some(PrivatePkcs8KeyDer::from(key).into()),
+            rustls_pemfile::Item::RSAKey(key) => Some(PrivatePkcs1KeyDer::from(key).into()),
+            rustls_pemfile::Item::ECKey(key) => Some(PrivateSec1KeyDer::from(key).into()),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoKey(path.to_string()))
+}
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// Reads DER elements off the front of a slice, just enough to find a
+/// certificate's validity and subject
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// The next element's tag and contents
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let (bytes, rest) = rest.split_at(count);
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, &b| length << 8 | b as usize);
+            (length, rest)
+        };
+        if rest.len() < length {
+            return None;
+        }
+        let (contents, rest) = rest.split_at(length);
+        self.0 = rest;
+        Some((tag, contents))
+    }
+
+    /// The contents of the next element, if it is a `tag`
+    fn expect(&mut self, tag: u8) -> Option<Der<'a>> {
+        self.next()
+            .filter(|(found, _)| *found == tag)
+            .map(|(_, contents)| Der(contents))
+    }
+}
+
+/// A `Name` as `O=Example, CN=example.com`, in the order it is encoded
+fn render_name(mut name: Der<'_>) -> Option<String> {
+    let mut parts = Vec::new();
+    while !name.0.is_empty() {
+        let mut set = name.expect(SET)?;
+        while !set.0.is_empty() {
+            let mut attribute = set.expect(SEQUENCE)?;
+            let (_, oid) = attribute.next()?;
+            let (_, value) = attribute.next()?;
+            // Everything of interest sits under id-at, 2.5.4
+            let label = match oid {
+                [0x55, 0x04, 0x03] => "CN",
+                [0x55, 0x04, 0x06] => "C",
+                [0x55, 0x04, 0x07] => "L",
+                [0x55, 0x04, 0x08] => "ST",
+                [0x55, 0x04, 0x0a] => "O",
+                [0x55, 0x04, 0x0b] => "OU",
+                _ => continue,
+            };
+            parts.push(format!("{}={}", label, String::from_utf8_lossy(value)));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+/// A `UTCTime` or `GeneralizedTime`, which certificates always give in UTC
+fn parse_time(tag: u8, value: &[u8]) -> Option<DateTime<Utc>> {
+    let value = std::str::from_utf8(value).ok()?;
+    let full = match tag {
+        // Two-digit years from 50 on are 19xx (RFC 5280 §4.1.2.5.1)
+        UTC_TIME => {
+            let century = if value.get(..2)? >= "50" { "19" } else { "20" };
+            format!("{}{}", century, value)
+        }
+        GENERALIZED_TIME => value.to_string(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&full, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    //! The fixtures are a test CA and two leaves it signed for `localhost`
+    //! and 127.0.0.1, each `.pem` holding the leaf then the CA:
+    //!
+    //! ```text
+    //! openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+    //!     -keyout ca.key -out ca.pem -days 36500 -subj "/CN=Fathom to Loom Test CA"
+    //! openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+    //!     -keyout first.key -out first.csr -subj "/O=Fathom to Loom/CN=first.localhost"
+    //! openssl x509 -req -in first.csr -CA ca.pem -CAkey ca.key -days 36500 -extfile ext.cnf
+    //! ```
+    //!
+    //! with `ext.cnf` making it a `serverAuth` end-entity for those names.
+
+    use super::*;
+    use axum::routing::get;
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tls");
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .route(
+                "/peer",
+                get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.ip().to_string() }),
+            )
+    }
+
+    /// Serve [`app`] with `tls` until the sender is dropped
+    async fn spawn(tls: Arc<Tls>) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(serve(listener, app(), tls, async {
+            let _ = stop_rx.await;
+        }));
+        (addr, stop_tx)
+    }
+
+    /// A TLS connection to `addr` trusting only the test CA
+    async fn connect(addr: SocketAddr) -> io::Result<TlsStream<tokio::net::TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        for certificate in read_chain(&fixture("ca.pem")).unwrap() {
+            roots.add(certificate).unwrap();
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+    }
+
+    /// The subject of the certificate the server presented
+    fn presented(stream: &TlsStream<tokio::net::TcpStream>) -> String {
+        let chain = stream.get_ref().1.peer_certificates().unwrap();
+        CertificateInfo::from_der(&chain[0]).unwrap().subject
+    }
+
+    /// `GET path` on a kept-alive connection, returning the status line and body
+    async fn request(
+        stream: &mut TlsStream<tokio::net::TcpStream>,
+        path: &str,
+    ) -> (String, String) {
+        let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(head.as_bytes()).await.unwrap();
+
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "connection closed mid-response");
+            received.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&received).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if body.len() >= length {
+                    let status = head.lines().next().unwrap().to_string();
+                    return (status, body.to_string());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_is_served_over_tls() {
+        let tls = Tls::load(&fixture("first.pem"), &fixture("first.key")).unwrap();
+        let (addr, _stop) = spawn(Arc::new(tls)).await;
+
+        let mut stream = connect(addr).await.unwrap();
+        assert_eq!(presented(&stream), "O=Fathom to Loom, CN=first.localhost");
+        // The chain reaches the client, which only trusts the CA behind the leaf
+        assert_eq!(stream.get_ref().1.peer_certificates().unwrap().len(), 2);
+
+        assert_eq!(
+            request(&mut stream, "/health").await,
+            ("HTTP/1.1 200 OK".to_string(), "OK".to_string())
+        );
+        let (_, peer) = request(&mut stream, "/peer").await;
+        assert_eq!(peer, "127.0.0.1");
+
+        // Plain HTTP gets nowhere
+        let mut plain = tokio::net::TcpStream::connect(addr).await.unwrap();
+        plain
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut answer = Vec::new();
+        let _ = plain.read_to_end(&mut answer).await;
+        assert!(!String::from_utf8_lossy(&answer).contains("200 OK"));
+    }
+
+    #[test]
+    fn test_unusable_files_fail_to_load() {
+        let refused =
+            |cert: &str, key: &str| Tls::load(&fixture(cert), &fixture(key)).err().unwrap();
+
+        assert!(
+            matches!(
+                refused("first.pem", "second.key"),
+                TlsError::KeyMismatch(..)
+            ),
+            "a key for another certificate must be refused"
+        );
+        assert!(matches!(
+            refused("missing.pem", "first.key"),
+            TlsError::Read(..)
+        ));
+        assert!(matches!(
+            refused("first.key", "first.key"),
+            TlsError::NoCertificate(_)
+        ));
+        assert!(matches!(
+            refused("first.pem", "first.pem"),
+            TlsError::NoKey(_)
+        ));
+
+        let message = refused("first.pem", "second.key").to_string();
+        assert!(message.contains("second.key"), "{}", message);
+        assert!(message.contains("first.pem"), "{}", message);
+    }
+
+    #[test]
+    fn test_certificate_subject_and_expiry() {
+        let chain = read_chain(&fixture("second.pem")).unwrap();
+        let leaf = CertificateInfo::from_der(&chain[0]).unwrap();
+        assert_eq!(leaf.subject, "O=Fathom to Loom, CN=second.localhost");
+        assert_eq!(leaf.not_after.format("%Y").to_string(), "2126");
+        assert!(!leaf.expires_soon(Utc::now()));
+
+        let ca = CertificateInfo::from_der(&chain[1]).unwrap();
+        assert_eq!(ca.subject, "CN=Fathom to Loom Test CA");
+
+        let soon = leaf.not_after - chrono::Duration::days(EXPIRY_WARNING_DAYS - 1);
+        assert!(leaf.expires_soon(soon));
+        assert!(CertificateInfo::from_der(b"not a certificate").is_none());
+
+        // Two-digit years split at 1950
+        assert_eq!(
+            parse_time(UTC_TIME, b"491231235959Z")
+                .unwrap()
+                .format("%Y")
+                .to_string(),
+            "2049"
+        );
+        assert_eq!(
+            parse_time(UTC_TIME, b"500101000000Z")
+                .unwrap()
+                .format("%Y")
+                .to_string(),
+            "1950"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sighup_swaps_the_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("tls.pem"), dir.path().join("tls.key"));
+        std::fs::copy(fixture("first.pem"), &cert).unwrap();
+        std::fs::copy(fixture("first.key"), &key).unwrap();
+
+        let tls = Arc::new(Tls::load(cert.to_str().unwrap(), key.to_str().unwrap()).unwrap());
+        tokio::spawn(tls.clone().reload_on_hangup().unwrap());
+        let (addr, _stop) = spawn(tls.clone()).await;
+        let mut before = connect(addr).await.unwrap();
+        assert_eq!(presented(&before), "O=Fathom to Loom, CN=first.localhost");
+
+        // A renewal that can't be loaded keeps the old certificate serving
+        std::fs::write(&key, "").unwrap();
+        assert!(matches!(tls.reload(), Err(TlsError::NoKey(_))));
+        assert_eq!(
+            presented(&connect(addr).await.unwrap()),
+            "O=Fathom to Loom, CN=first.localhost"
+        );
+
+        std::fs::copy(fixture("second.pem"), &cert).unwrap();
+        std::fs::copy(fixture("second.key"), &key).unwrap();
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGHUP).unwrap();
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let after = connect(addr).await.unwrap();
+            if presented(&after) == "O=Fathom to Loom, CN=second.localhost" {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "SIGHUP never swapped the certificate"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // The connection opened before the swap is still being served
+        assert_eq!(request(&mut before, "/health").await.1, "OK");
+    }
+}
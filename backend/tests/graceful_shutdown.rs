@@ -0,0 +1,154 @@
+//! Runs the real backend binary and stops it with SIGTERM mid-request
+#![cfg(unix)]
+
+use axum::{http::StatusCode, routing::post, Json, Router};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde_json::json;
+use std::{
+    os::unix::fs::PermissionsExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+/// How long the stand-in global PocketBase takes to answer a login
+const SLOW_LOGIN: Duration = Duration::from_secs(2);
+
+/// A global PocketBase whose password logins take [`SLOW_LOGIN`]
+async fn slow_global_pocketbase() -> String {
+    let router = Router::new()
+        .route(
+            "/api/collections/users/auth-with-password",
+            post(|| async {
+                tokio::time::sleep(SLOW_LOGIN).await;
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "code": 400, "message": "Failed to authenticate." })),
+                )
+            }),
+        )
+        .fallback(|| async {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "code": 404, "message": "Not found." })),
+            )
+        });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    url
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn spawn_backend(dir: &std::path::Path, global_url: &str, port: u16) -> Child {
+    let pocketbase = dir.join("pocketbase");
+    std::fs::write(
+        &pocketbase,
+        "#!/bin/sh\necho 'pocketbase version 0.22.21'\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&pocketbase, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_backend"))
+        // Keep dotenvy from picking up a developer's .env
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOST", "127.0.0.1")
+        .env("BACKEND_PORT", port.to_string())
+        .env("DATABASE_URL", global_url)
+        .env("PB_ADMIN_PASSWORD", "admin-password")
+        .env("MASTER_KEY", "dGVzdC1tYXN0ZXIta2V5LTMyLWJ5dGVzLWxvbmchISE=")
+        .env("JWT_SECRET", "test-jwt-secret-at-least-32-chars")
+        .env("PB_ENCRYPTION_KEY", "test-pb-encryption-key")
+        .env("PB_BINARY_PATH", &pocketbase)
+        .env("PB_USER_DBS_PATH", dir.join("user_dbs"))
+        .env("SMTP_SERVICE_URL", global_url)
+        .env("SHUTDOWN_GRACE_SECS", "10")
+        .env("RUST_LOG", "warn")
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .expect("failed to start the backend binary")
+}
+
+async fn wait_until_ready(base: &str, child: &mut Child) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            panic!("backend exited during startup: {}", status);
+        }
+        if let Ok(response) = reqwest::get(format!("{}/health", base)).await {
+            if response.status().is_success() {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("backend did not become ready");
+}
+
+async fn wait_for_exit(child: &mut Child, within: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + within;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    None
+}
+
+#[tokio::test]
+async fn test_sigterm_drains_in_flight_requests_and_exits_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    let global_url = slow_global_pocketbase().await;
+    let port = free_port();
+    let base = format!("http://127.0.0.1:{}", port);
+    let mut child = spawn_backend(dir.path(), &global_url, port);
+    wait_until_ready(&base, &mut child).await;
+
+    let login = tokio::spawn({
+        let base = base.clone();
+        async move {
+            reqwest::Client::new()
+                .post(format!("{}/auth/login", base))
+                .json(&json!({ "email": "slow@example.com", "password": "password" }))
+                .send()
+                .await
+        }
+    });
+    // Let the login reach the slow PocketBase before signalling
+    tokio::time::sleep(SLOW_LOGIN / 4).await;
+    let signalled = Instant::now();
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+
+    let response = login
+        .await
+        .unwrap()
+        .expect("the in-flight login was cut off");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let status = wait_for_exit(&mut child, Duration::from_secs(10)).await;
+    let Some(status) = status else {
+        let _ = child.kill();
+        panic!("backend did not exit within the grace period");
+    };
+    assert!(status.success(), "backend exited with {}", status);
+    assert!(
+        signalled.elapsed() >= SLOW_LOGIN / 2,
+        "exited before the request finished"
+    );
+
+    // Nothing is listening any more
+    assert!(reqwest::get(format!("{}/health", base)).await.is_err());
+}
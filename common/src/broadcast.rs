@@ -1,11 +1,13 @@
 //! Shared broadcasting service for real-time queue updates
 //! This module provides a centralized service for broadcasting queue changes
-//! between the backend API and worker processes.
+//! between the backend API and worker processes, a progress topic for how far
+//! the worker has got with each task, plus a system topic for
+//! infrastructure events such as PocketBase instance lifecycle changes.
 
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::broadcast;
-use tracing::{error, info};
+use tracing::{debug, error, info};
 use uuid::Uuid;
 
 /// Represents a queue update event
@@ -15,9 +17,24 @@ pub struct QueueUpdate {
     pub affected_user_id: Option<String>,
     pub global_position: Option<usize>,
     pub task_id: Option<Uuid>,
+    /// When the task goes again, on `TaskRetried` updates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryInfo>,
+    /// Why the task failed, on `TaskFailed` updates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+/// A failed task put back in the queue for retry `retry_count` of
+/// `max_retries`, due at `next_attempt_at`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryInfo {
+    pub retry_count: u32,
+    pub max_retries: u32,
+    pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+}
+
 /// Types of queue updates
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -26,20 +43,99 @@ pub enum QueueUpdateType {
     TaskCompleted,
     TaskFailed,
     TaskRetried,
+    /// Its owner asked for it to stop; once the worker has stopped it, it is
+    /// sent again from the worker
+    TaskCancelled,
     PositionUpdated,
     QueueCleared,
 }
 
+/// How far the worker has got with a task
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProgressUpdate {
+    pub task_id: Uuid,
+    pub user_id: String,
+    pub stage: ProcessingStage,
+    /// How much of `stage` is done, 0 to 100
+    pub percent: u8,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// The stages a task goes through, in order
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingStage {
+    FetchingMetadata,
+    Downloading,
+    Transcoding,
+    Uploading,
+    /// Downloading, transcoding and uploading at once, in the worker's
+    /// streaming mode
+    Streaming,
+}
+
+impl ProgressUpdate {
+    pub fn new(task_id: Uuid, user_id: impl Into<String>, stage: ProcessingStage, percent: u8) -> Self {
+        Self {
+            task_id,
+            user_id: user_id.into(),
+            stage,
+            percent: percent.min(100),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Represents an event on the system topic
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SystemEvent {
+    pub event_type: SystemEventType,
+    pub user_id: String,
+    /// Port of the instance concerned, 0 for events about something else
+    pub port: u16,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Types of system events
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SystemEventType {
+    InstanceStarting,
+    InstanceRunning,
+    InstanceFailed { reason: String },
+    InstanceStopped,
+    InstanceRestarted,
+    /// A stored API key expires within the warning window, or already has
+    KeyExpiring { service: String, key_id: String, days_left: i64 },
+    /// A task failed for good and was moved to the dead-letter queue
+    TaskDeadLettered { task_id: Uuid, error: String },
+}
+
+impl SystemEvent {
+    pub fn new(event_type: SystemEventType, user_id: impl Into<String>, port: u16) -> Self {
+        Self {
+            event_type,
+            user_id: user_id.into(),
+            port,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
 /// Shared broadcasting service
 pub struct BroadcastService {
     sender: broadcast::Sender<QueueUpdate>,
+    progress_sender: broadcast::Sender<ProgressUpdate>,
+    system_sender: broadcast::Sender<SystemEvent>,
 }
 
 impl BroadcastService {
     /// Create a new broadcast service with specified channel capacity
     pub fn new(capacity: usize) -> Self {
         let (sender, _) = broadcast::channel(capacity);
-        Self { sender }
+        let (progress_sender, _) = broadcast::channel(capacity);
+        let (system_sender, _) = broadcast::channel(capacity);
+        Self { sender, progress_sender, system_sender }
     }
 
     /// Subscribe to queue updates
@@ -56,10 +152,41 @@ impl BroadcastService {
         }
     }
 
+    /// Subscribe to the progress topic
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<ProgressUpdate> {
+        self.progress_sender.subscribe()
+    }
+
+    /// Broadcast a task's progress
+    ///
+    /// Having no subscribers is normal here, so it is not treated as an error.
+    pub fn broadcast_progress(&self, update: ProgressUpdate) {
+        debug!("Task {} is {}% through {:?}", update.task_id, update.percent, update.stage);
+        let _ = self.progress_sender.send(update);
+    }
+
+    /// Subscribe to the system topic
+    pub fn subscribe_system(&self) -> broadcast::Receiver<SystemEvent> {
+        self.system_sender.subscribe()
+    }
+
+    /// Broadcast an event on the system topic
+    ///
+    /// Having no subscribers is normal here, so it is not treated as an error.
+    pub fn broadcast_system(&self, event: SystemEvent) {
+        debug!("Broadcasting system event: {:?} for user {}", event.event_type, event.user_id);
+        let _ = self.system_sender.send(event);
+    }
+
     /// Get the number of active subscribers
     pub fn subscriber_count(&self) -> usize {
         self.sender.receiver_count()
     }
+
+    /// Whether both topics still have a subscriber to deliver to
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0 && self.system_sender.receiver_count() > 0
+    }
 }
 
 impl Default for BroadcastService {
@@ -0,0 +1,21 @@
+//! The current time, swappable in tests
+
+use chrono::{DateTime, Utc};
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// [`Self::now`] in unix seconds
+    fn unix_now(&self) -> u64 {
+        self.now().timestamp().max(0) as u64
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
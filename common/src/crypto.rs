@@ -4,7 +4,7 @@
 //! Keys are never logged and decryption only happens inside worker task memory.
 
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use serde::{Deserialize, Serialize};
@@ -12,6 +12,12 @@ use serde::{Deserialize, Serialize};
 /// Example implementations showing secure usage patterns
 pub mod examples;
 
+/// Keys held decrypted only while they're in use
+pub mod keys;
+
+/// Sealing secrets to a one-off recipient key for transit
+pub mod envelope;
+
 /// Error types for cryptographic operations
 #[derive(Debug, thiserror::Error)]
 pub enum CryptoError {
@@ -26,6 +32,15 @@ pub enum CryptoError {
     
     #[error("Invalid ciphertext bundle")]
     InvalidCiphertextBundle,
+
+    #[error("Envelope expired")]
+    EnvelopeExpired,
+
+    #[error("API key not found")]
+    KeyNotFound,
+
+    #[error("API key expired at {0}")]
+    KeyExpired(chrono::DateTime<chrono::Utc>),
 }
 
 /// Encrypted data bundle containing ciphertext and nonce
@@ -69,13 +84,22 @@ impl CiphertextBundle {
 /// * Generates a random 12-byte nonce for each encryption
 /// * Master key is never logged or stored
 pub fn encrypt(master_key: &[u8; 32], plaintext: &[u8]) -> CiphertextBundle {
+    encrypt_with_aad(master_key, plaintext, &[])
+}
+
+/// Encrypt plaintext like [`encrypt`], binding it to associated data
+///
+/// The associated data isn't stored in the bundle; decryption only succeeds
+/// with [`decrypt_with_aad`] given the same bytes, so a bundle copied into
+/// another context (e.g. another user's records) fails to decrypt.
+pub fn encrypt_with_aad(master_key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> CiphertextBundle {
     let cipher = Aes256Gcm::new_from_slice(master_key)
         .expect("Invalid master key size");
     
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     
     let ciphertext = cipher
-        .encrypt(&nonce, plaintext)
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
         .expect("Encryption should never fail with valid key and nonce");
     
     CiphertextBundle::new(ciphertext, nonce.to_vec())
@@ -97,6 +121,11 @@ pub fn encrypt(master_key: &[u8; 32], plaintext: &[u8]) -> CiphertextBundle {
 /// * Master key is never logged or stored
 /// * Decrypted data should only exist in worker task memory
 pub fn decrypt(master_key: &[u8; 32], bundle: &CiphertextBundle) -> Result<Vec<u8>, CryptoError> {
+    decrypt_with_aad(master_key, bundle, &[])
+}
+
+/// Decrypt a bundle produced by [`encrypt_with_aad`] with the same associated data
+pub fn decrypt_with_aad(master_key: &[u8; 32], bundle: &CiphertextBundle, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
     let cipher = Aes256Gcm::new_from_slice(master_key)
         .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
     
@@ -104,7 +133,7 @@ pub fn decrypt(master_key: &[u8; 32], bundle: &CiphertextBundle) -> Result<Vec<u
     let nonce = Nonce::from_slice(&nonce_array);
     
     let plaintext = cipher
-        .decrypt(nonce, bundle.ciphertext.as_ref())
+        .decrypt(nonce, Payload { msg: bundle.ciphertext.as_ref(), aad })
         .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
     
     Ok(plaintext)
@@ -118,6 +147,24 @@ pub fn generate_master_key() -> [u8; 32] {
     key
 }
 
+/// Parse a master key given as standard base64, as `MASTER_KEY` is
+///
+/// Anything other than exactly 32 bytes is refused rather than padded or
+/// hashed, so every service configured with the same value uses the same key.
+pub fn parse_master_key(encoded: &str) -> Result<[u8; 32], CryptoError> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or(CryptoError::InvalidKey)
+}
+
+/// Compare without returning early, so timing doesn't reveal the matching prefix
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 /// Securely store encrypted API key with metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedApiKey {
@@ -153,20 +200,71 @@ impl EncryptedApiKey {
         }
     }
     
+    /// Create an encrypted API key entry bound to its owner
+    ///
+    /// The user id is the associated data, so the entry only decrypts with
+    /// [`Self::decrypt_key_for_user`] for the same user.
+    pub fn new_for_user(
+        user_id: &str,
+        service: String,
+        key_id: String,
+        api_key: &str,
+        master_key: &[u8; 32],
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        let encrypted_key = encrypt_with_aad(master_key, api_key.as_bytes(), user_id.as_bytes());
+        
+        Self {
+            service,
+            key_id,
+            encrypted_key,
+            created_at: chrono::Utc::now(),
+            expires_at,
+        }
+    }
+    
     /// Decrypt the API key (should only be done in worker task memory)
     pub fn decrypt_key(&self, master_key: &[u8; 32]) -> Result<String, CryptoError> {
-        let plaintext = decrypt(master_key, &self.encrypted_key)?;
+        self.decrypt_key_for_user("", master_key)
+    }
+    
+    /// Decrypt an API key created with [`Self::new_for_user`]
+    pub fn decrypt_key_for_user(&self, user_id: &str, master_key: &[u8; 32]) -> Result<String, CryptoError> {
+        let plaintext = decrypt_with_aad(master_key, &self.encrypted_key, user_id.as_bytes())?;
         String::from_utf8(plaintext)
             .map_err(|e| CryptoError::DecryptionFailed(format!("Invalid UTF-8: {}", e)))
     }
     
+    /// Re-encrypt `user_id`'s key from `old_master_key` to `new_master_key`
+    ///
+    /// Everything but the ciphertext is kept, including `created_at`.
+    pub fn rotate(
+        &self,
+        user_id: &str,
+        old_master_key: &[u8; 32],
+        new_master_key: &[u8; 32],
+    ) -> Result<Self, CryptoError> {
+        let plaintext = decrypt_with_aad(old_master_key, &self.encrypted_key, user_id.as_bytes())?;
+        Ok(Self {
+            encrypted_key: encrypt_with_aad(new_master_key, &plaintext, user_id.as_bytes()),
+            ..self.clone()
+        })
+    }
+    
     /// Check if the key has expired
     pub fn is_expired(&self) -> bool {
-        if let Some(expires_at) = self.expires_at {
-            chrono::Utc::now() > expires_at
-        } else {
-            false
-        }
+        self.is_expired_at(chrono::Utc::now())
+    }
+    
+    /// Check if the key had expired by `now`
+    pub fn is_expired_at(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
+    }
+    
+    /// Whole days from `now` until the key expires, rounded towards zero
+    /// and negative once it is more than a day past
+    pub fn days_until_expiry(&self, now: chrono::DateTime<chrono::Utc>) -> Option<i64> {
+        self.expires_at.map(|expires_at| (expires_at - now).num_days())
     }
 }
 
@@ -188,6 +286,16 @@ mod tests {
         assert_eq!(plaintext, decrypted.as_slice());
     }
     
+    #[test]
+    fn test_parse_master_key() {
+        let key = parse_master_key("FmbwJVUUZp/7tDAl00IfNO/FQimAax+zjYZWIx3I5ho=\n").unwrap();
+        assert_eq!(key[..3], [0x16, 0x66, 0xf0]);
+
+        for malformed in ["", "not base64!", "c2hvcnQ=", "FmbwJVUUZp/7tDAl00IfNO/FQimAax+zjYZWIx3I5ho+AA=="] {
+            assert!(matches!(parse_master_key(malformed), Err(CryptoError::InvalidKey)));
+        }
+    }
+    
     #[test]
     fn test_encrypt_different_nonces() {
         let master_key = generate_master_key();
@@ -272,6 +380,50 @@ mod tests {
         
         // Should be expired
         assert!(encrypted.is_expired());
+        assert!(!encrypted.is_expired_at(expired_time - chrono::Duration::minutes(1)));
+        assert_eq!(encrypted.days_until_expiry(expired_time - chrono::Duration::hours(73)), Some(3));
+        assert_eq!(encrypted.days_until_expiry(expired_time + chrono::Duration::days(2)), Some(-2));
+    }
+    
+    #[test]
+    fn test_api_key_bound_to_user() {
+        let master_key = generate_master_key();
+        let encrypted = EncryptedApiKey::new_for_user(
+            "alice",
+            "fathom".to_string(),
+            "default".to_string(),
+            "sk-alice",
+            &master_key,
+            None,
+        );
+        
+        assert_eq!(encrypted.decrypt_key_for_user("alice", &master_key).unwrap(), "sk-alice");
+        
+        // Another user's id, or none at all, fails authentication
+        assert!(encrypted.decrypt_key_for_user("bob", &master_key).is_err());
+        assert!(encrypted.decrypt_key(&master_key).is_err());
+    }
+    
+    #[test]
+    fn test_rotate_keeps_metadata() {
+        let (old, new) = (generate_master_key(), generate_master_key());
+        let key = EncryptedApiKey::new_for_user(
+            "alice",
+            "loom".to_string(),
+            "default".to_string(),
+            "sk-rotate",
+            &old,
+            None,
+        );
+        
+        let rotated = key.rotate("alice", &old, &new).unwrap();
+        assert_eq!(rotated.decrypt_key_for_user("alice", &new).unwrap(), "sk-rotate");
+        assert!(rotated.decrypt_key_for_user("alice", &old).is_err());
+        assert_eq!(rotated.created_at, key.created_at);
+        
+        // The wrong old key or owner leaves nothing to rotate
+        assert!(key.rotate("alice", &new, &old).is_err());
+        assert!(key.rotate("bob", &old, &new).is_err());
     }
     
     #[test]
@@ -305,4 +457,12 @@ mod tests {
         
         assert_eq!(large_plaintext, decrypted);
     }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
 }
@@ -0,0 +1,249 @@
+//! Sealing a secret to a one-off recipient key
+//!
+//! The worker fetches decrypted API keys from the backend over the internal
+//! network. So that the plaintext never travels bare, the worker generates an
+//! X25519 key pair for that one request ([`EphemeralRecipient`]) and sends the
+//! public half; the backend [`seal`]s the value to it under a key pair of its
+//! own, and only the holder of the private half can open the envelope. Both
+//! private keys are used once and dropped. An envelope names when it expires,
+//! bound into its ciphertext, and is refused after that.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use ring::{
+    agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519},
+    hkdf,
+    rand::SystemRandom,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{CiphertextBundle, CryptoError};
+
+/// HKDF info string, so the derived key is only ever used for envelopes
+const INFO: &[u8] = b"fathom-loom key envelope v1";
+
+/// A secret sealed to one recipient public key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedEnvelope {
+    /// Base64 public half of the sender's one-off key pair
+    pub ephemeral_public_key: String,
+    /// Base64 AES-GCM nonce
+    pub nonce: String,
+    /// Base64 AES-GCM ciphertext and tag
+    pub ciphertext: String,
+    /// After this the envelope must not be opened
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The private half of a key pair generated to receive one envelope
+pub struct EphemeralRecipient {
+    private_key: EphemeralPrivateKey,
+    public_key: String,
+}
+
+impl std::fmt::Debug for EphemeralRecipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EphemeralRecipient")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EphemeralRecipient {
+    pub fn generate() -> Result<Self, CryptoError> {
+        let private_key = EphemeralPrivateKey::generate(&X25519, &SystemRandom::new())
+            .map_err(|_| CryptoError::EncryptionFailed("key pair generation failed".to_string()))?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| CryptoError::EncryptionFailed("key pair generation failed".to_string()))?;
+        Ok(Self {
+            public_key: STANDARD.encode(public_key.as_ref()),
+            private_key,
+        })
+    }
+
+    /// Base64 public key to hand to the sender
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Open `envelope`, sealed to this key for `context`, unless it expired
+    /// before `now`
+    ///
+    /// Consumes the key, so each one opens a single envelope.
+    pub fn open(
+        self,
+        envelope: &SealedEnvelope,
+        context: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        if now > envelope.expires_at {
+            return Err(CryptoError::EnvelopeExpired);
+        }
+        let sender = decode(&envelope.ephemeral_public_key)?;
+        let recipient = decode(&self.public_key)?;
+        let key = agree(self.private_key, &sender, &sender, &recipient)?;
+        let bundle =
+            CiphertextBundle::new(decode(&envelope.ciphertext)?, decode(&envelope.nonce)?)?;
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| CryptoError::InvalidKey)?;
+        cipher
+            .decrypt(
+                Nonce::from_slice(&bundle.nonce_array()?),
+                Payload {
+                    msg: &bundle.ciphertext,
+                    aad: &associated_data(context, envelope.expires_at),
+                },
+            )
+            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))
+    }
+}
+
+/// Seal `plaintext` to the base64 X25519 `recipient_public_key` for `context`
+///
+/// The same `context` must be given to open it, so an envelope fetched for
+/// one user's key can't be passed off as another's.
+pub fn seal(
+    recipient_public_key: &str,
+    plaintext: &[u8],
+    context: &[u8],
+    expires_at: DateTime<Utc>,
+) -> Result<SealedEnvelope, CryptoError> {
+    let recipient = decode(recipient_public_key)
+        .ok()
+        .filter(|key| key.len() == 32)
+        .ok_or(CryptoError::InvalidKey)?;
+    let sender = EphemeralRecipient::generate()?;
+    let sender_public = decode(&sender.public_key)?;
+    let key = agree(sender.private_key, &recipient, &sender_public, &recipient)?;
+
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| CryptoError::InvalidKey)?;
+    let mut nonce = [0u8; 12];
+    ring::rand::SecureRandom::fill(&SystemRandom::new(), &mut nonce)
+        .map_err(|_| CryptoError::EncryptionFailed("nonce generation failed".to_string()))?;
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &associated_data(context, expires_at),
+            },
+        )
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    Ok(SealedEnvelope {
+        ephemeral_public_key: sender.public_key,
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+        expires_at,
+    })
+}
+
+/// The AES key both sides derive from `private_key` and the `peer` public
+/// key, salted with both public keys
+fn agree(
+    private_key: EphemeralPrivateKey,
+    peer: &[u8],
+    sender: &[u8],
+    recipient: &[u8],
+) -> Result<[u8; 32], CryptoError> {
+    let salt = [sender, recipient].concat();
+    agreement::agree_ephemeral(
+        private_key,
+        &UnparsedPublicKey::new(&X25519, peer),
+        |shared| {
+            let mut key = [0u8; 32];
+            hkdf::Salt::new(hkdf::HKDF_SHA256, &salt)
+                .extract(shared)
+                .expand(&[INFO], hkdf::HKDF_SHA256)
+                .and_then(|okm| okm.fill(&mut key))
+                .map(|_| key)
+        },
+    )
+    .ok()
+    .and_then(Result::ok)
+    .ok_or(CryptoError::InvalidKey)
+}
+
+/// The context a user's API key for `service` is sealed for
+pub fn key_context(user_id: &str, service: &str) -> Vec<u8> {
+    format!("api-key/{}/{}", user_id, service).into_bytes()
+}
+
+fn associated_data(context: &[u8], expires_at: DateTime<Utc>) -> Vec<u8> {
+    [context, b"|", expires_at.to_rfc3339().as_bytes()].concat()
+}
+
+fn decode(encoded: &str) -> Result<Vec<u8>, CryptoError> {
+    STANDARD
+        .decode(encoded)
+        .map_err(|_| CryptoError::InvalidCiphertextBundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn sealed(now: DateTime<Utc>) -> (EphemeralRecipient, SealedEnvelope) {
+        let recipient = EphemeralRecipient::generate().unwrap();
+        let envelope = seal(
+            recipient.public_key(),
+            b"fathom-secret",
+            b"alice/fathom",
+            now + Duration::seconds(60),
+        )
+        .unwrap();
+        (recipient, envelope)
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let now = Utc::now();
+        let (recipient, envelope) = sealed(now);
+        assert_ne!(envelope.ephemeral_public_key, recipient.public_key());
+        assert!(!serde_json::to_string(&envelope)
+            .unwrap()
+            .contains("fathom-secret"));
+        assert_eq!(
+            recipient.open(&envelope, b"alice/fathom", now).unwrap(),
+            b"fathom-secret"
+        );
+    }
+
+    #[test]
+    fn test_envelopes_open_only_as_sealed() {
+        let now = Utc::now();
+
+        let (_, envelope) = sealed(now);
+        let stranger = EphemeralRecipient::generate().unwrap();
+        assert!(stranger.open(&envelope, b"alice/fathom", now).is_err());
+
+        let (recipient, envelope) = sealed(now);
+        assert!(recipient.open(&envelope, b"bob/fathom", now).is_err());
+
+        // The expiry is bound into the ciphertext, so it can't be stretched
+        let (recipient, envelope) = sealed(now);
+        let stretched = SealedEnvelope {
+            expires_at: envelope.expires_at + Duration::days(1),
+            ..envelope
+        };
+        assert!(recipient.open(&stretched, b"alice/fathom", now).is_err());
+
+        let (recipient, envelope) = sealed(now);
+        let late = now + Duration::seconds(61);
+        assert!(matches!(
+            recipient.open(&envelope, b"alice/fathom", late),
+            Err(CryptoError::EnvelopeExpired)
+        ));
+
+        for key in ["not-a-key", "c2hvcnQ="] {
+            assert!(matches!(
+                seal(key, b"x", b"", now),
+                Err(CryptoError::InvalidKey)
+            ));
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! API keys held in memory for the length of one task
+//!
+//! A [`SecureKeyManager`] keeps keys re-encrypted under a master key of its
+//! own, so a plaintext exists only while a [`SecretString`] handed out by
+//! [`SecureKeyManager::get_api_key`] is alive. Neither type prints a key, and
+//! both wipe what they hold when dropped.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use zeroize::Zeroize;
+
+use super::{generate_master_key, CryptoError, EncryptedApiKey};
+
+/// A decrypted API key
+///
+/// Debug output shows only `[REDACTED]`, and the memory is zeroed on drop.
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// API keys by service and key id, encrypted until asked for
+pub struct SecureKeyManager {
+    master_key: [u8; 32],
+    encrypted_keys: HashMap<String, EncryptedApiKey>,
+}
+
+impl SecureKeyManager {
+    /// Create a new key manager with a randomly generated master key
+    pub fn new() -> Self {
+        Self::with_master_key(generate_master_key())
+    }
+
+    /// Create a key manager with an existing master key (e.g., from secure environment)
+    pub fn with_master_key(master_key: [u8; 32]) -> Self {
+        Self {
+            master_key,
+            encrypted_keys: HashMap::new(),
+        }
+    }
+
+    fn entry(service: &str, key_id: &str) -> String {
+        format!("{}:{}", service, key_id)
+    }
+
+    /// Store an API key, replacing any with the same service and key id
+    pub fn store_api_key(
+        &mut self,
+        service: String,
+        key_id: String,
+        api_key: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) {
+        let entry = Self::entry(&service, &key_id);
+        let encrypted_key = EncryptedApiKey::new(service, key_id, api_key, &self.master_key, expires_at);
+        self.encrypted_keys.insert(entry, encrypted_key);
+    }
+
+    /// Decrypt an API key, unless it is missing or has expired
+    ///
+    /// The returned key should be used immediately and not stored.
+    pub fn get_api_key(&self, service: &str, key_id: &str) -> Result<SecretString, CryptoError> {
+        self.get_api_key_at(service, key_id, Utc::now())
+    }
+
+    /// [`Self::get_api_key`] as of `now`
+    pub fn get_api_key_at(&self, service: &str, key_id: &str, now: DateTime<Utc>) -> Result<SecretString, CryptoError> {
+        let encrypted_key = self
+            .encrypted_keys
+            .get(&Self::entry(service, key_id))
+            .ok_or(CryptoError::KeyNotFound)?;
+        if let Some(expired_at) = encrypted_key.expires_at.filter(|_| encrypted_key.is_expired_at(now)) {
+            return Err(CryptoError::KeyExpired(expired_at));
+        }
+        encrypted_key.decrypt_key(&self.master_key).map(SecretString::new)
+    }
+
+    /// List available keys (without revealing the actual key values)
+    pub fn list_keys(&self) -> Vec<(String, String, DateTime<Utc>, bool)> {
+        self.encrypted_keys
+            .values()
+            .map(|key| (key.service.clone(), key.key_id.clone(), key.created_at, key.is_expired()))
+            .collect()
+    }
+
+    /// Remove an expired or unused key
+    pub fn remove_key(&mut self, service: &str, key_id: &str) -> bool {
+        self.encrypted_keys.remove(&Self::entry(service, key_id)).is_some()
+    }
+}
+
+impl Default for SecureKeyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for SecureKeyManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut keys: Vec<&String> = self.encrypted_keys.keys().collect();
+        keys.sort();
+        f.debug_struct("SecureKeyManager").field("keys", &keys).finish_non_exhaustive()
+    }
+}
+
+impl Drop for SecureKeyManager {
+    fn drop(&mut self) {
+        self.master_key.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_manager_basic_operations() {
+        let mut manager = SecureKeyManager::new();
+
+        // Store a key
+        manager.store_api_key(
+            "test-service".to_string(),
+            "main".to_string(),
+            "secret-api-key-123",
+            None,
+        );
+
+        // Retrieve the key
+        let retrieved = manager.get_api_key("test-service", "main").unwrap();
+        assert_eq!(retrieved.expose_secret(), "secret-api-key-123");
+
+        // List keys
+        let keys = manager.list_keys();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].0, "test-service");
+        assert_eq!(keys[0].1, "main");
+        assert!(!keys[0].3); // not expired
+    }
+
+    #[test]
+    fn test_key_not_found() {
+        let manager = SecureKeyManager::new();
+
+        let result = manager.get_api_key("nonexistent", "key");
+        assert!(matches!(result, Err(CryptoError::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_expired_key() {
+        let mut manager = SecureKeyManager::new();
+
+        // Create key that expired 1 hour ago
+        let expired_time = Utc::now() - chrono::Duration::hours(1);
+
+        manager.store_api_key(
+            "test-service".to_string(),
+            "expired".to_string(),
+            "expired-key",
+            Some(expired_time),
+        );
+
+        // Should fail to retrieve expired key
+        let result = manager.get_api_key("test-service", "expired");
+        assert!(matches!(result, Err(CryptoError::KeyExpired(at)) if at == expired_time));
+        let before = expired_time - chrono::Duration::minutes(1);
+        assert!(manager.get_api_key_at("test-service", "expired", before).is_ok());
+    }
+
+    #[test]
+    fn test_remove_key() {
+        let mut manager = SecureKeyManager::new();
+
+        manager.store_api_key(
+            "test-service".to_string(),
+            "temp".to_string(),
+            "temp-key",
+            None,
+        );
+
+        // Key should exist
+        assert!(manager.get_api_key("test-service", "temp").is_ok());
+
+        // Remove key
+        assert!(manager.remove_key("test-service", "temp"));
+
+        // Key should no longer exist
+        assert!(manager.get_api_key("test-service", "temp").is_err());
+
+        // Removing again should return false
+        assert!(!manager.remove_key("test-service", "temp"));
+    }
+
+    #[test]
+    fn test_nothing_prints_a_key() {
+        let mut manager = SecureKeyManager::new();
+        manager.store_api_key("loom".to_string(), "default".to_string(), "loom-secret-0123", None);
+
+        let secret = manager.get_api_key("loom", "default").unwrap();
+        assert_eq!(format!("{:?}", secret), "SecretString([REDACTED])");
+        assert_eq!(format!("{:?}", manager), "SecureKeyManager { keys: [\"loom:default\"], .. }");
+    }
+}
@@ -0,0 +1,717 @@
+//! Fathom meetings as the backend serves them and the frontend shows them
+//!
+//! The types at the top are the API's own shape. [`wire`] holds what the
+//! Fathom external API sends, with its field names, and the conversions
+//! from it, so both sides read a meeting the same way.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A recorded meeting, as the Recordings page shows it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FathomMeeting {
+    /// Fathom's recording id
+    pub id: String,
+    pub title: String,
+    /// When recording started, RFC 3339 on the wire
+    pub start_time: DateTime<Utc>,
+    /// Length of the recording in seconds
+    pub duration: u32,
+    /// Invitees, each once, the organizer first
+    pub participants: Vec<Participant>,
+    /// The invitees as Fathom sent them, before [`normalize_participants`]
+    #[serde(default)]
+    pub participants_raw: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_url: Option<String>,
+}
+
+/// Someone invited to a meeting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participant {
+    /// Their name, or their email when Fathom has no name
+    pub display_name: String,
+    /// Lowercased
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub organizer: bool,
+}
+
+impl Participant {
+    /// Read one of the ways a participant is written: `Jane Doe
+    /// <jane@x.com>`, `"Doe, Jane" <jane@x.com>`, `jane@x.com (Jane Doe)`,
+    /// `jane@x.com` or `Jane Doe`
+    ///
+    /// Angle brackets left unclosed are read as if closed. A bracketed part
+    /// that isn't an email stays in the name. `None` for blank input.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let (name, email) = match (raw.find('<'), raw.rfind('>')) {
+            (Some(open), close) if close.is_none_or(|close| close > open) => {
+                let inside = &raw[open + 1..close.unwrap_or(raw.len())];
+                match as_email(inside) {
+                    Some(email) => {
+                        let rest = close.map_or("", |close| &raw[close + 1..]);
+                        (format!("{} {}", &raw[..open], rest), Some(email))
+                    }
+                    None => (raw.replace(['<', '>'], " "), None),
+                }
+            }
+            _ => match raw.split_once('(') {
+                Some((email, name)) if as_email(email).is_some() => {
+                    (name.trim_end_matches(')').to_string(), as_email(email))
+                }
+                _ => match as_email(raw) {
+                    Some(email) => (String::new(), Some(email)),
+                    None => (raw.replace(['<', '>'], " "), None),
+                },
+            },
+        };
+        let name = name
+            .trim_matches(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let display_name = match (name.is_empty(), &email) {
+            (false, _) => name,
+            (true, Some(email)) => email.clone(),
+            (true, None) => return None,
+        };
+        Some(Self {
+            display_name,
+            email,
+            organizer: false,
+        })
+    }
+
+    fn same_person(&self, other: &Participant) -> bool {
+        match (&self.email, &other.email) {
+            (Some(mine), Some(theirs)) => mine == theirs,
+            _ => self.display_name.to_lowercase() == other.display_name.to_lowercase(),
+        }
+    }
+}
+
+/// `text`, lowercased, if it is a plausible email address
+fn as_email(text: &str) -> Option<String> {
+    let text = text.trim();
+    let text = text.strip_prefix("mailto:").unwrap_or(text);
+    let (local, domain) = text.split_once('@')?;
+    let plausible = !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !text.contains(|c: char| c.is_whitespace() || "<>()\",;".contains(c));
+    plausible.then(|| text.to_lowercase())
+}
+
+/// `participants` with each person once, the organizer first
+///
+/// People are the same when their emails match or, when either has no
+/// email, their names do in any case. The first mention is kept, taking the
+/// name, email and organizer mark of later ones it lacks.
+pub fn normalize_participants(participants: Vec<Participant>) -> Vec<Participant> {
+    let mut people: Vec<Participant> = Vec::new();
+    for participant in participants {
+        match people
+            .iter_mut()
+            .find(|known| known.same_person(&participant))
+        {
+            Some(known) => {
+                if known.email.is_none() {
+                    known.email = participant.email;
+                } else if known.email.as_deref() == Some(&known.display_name)
+                    && participant.email.as_deref() != Some(&participant.display_name)
+                {
+                    known.display_name = participant.display_name;
+                }
+                known.organizer |= participant.organizer;
+            }
+            None => people.push(participant),
+        }
+    }
+    people.sort_by_key(|participant| !participant.organizer);
+    people
+}
+
+/// A meeting with what's worth knowing before queueing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FathomMeetingDetail {
+    #[serde(flatten)]
+    pub meeting: FathomMeeting,
+    /// Fathom's summary of the meeting, as Markdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Size of the recording in bytes, when Fathom says
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// Whether Fathom offers the recording for download; the signed URL
+    /// itself is only fetched by the worker
+    pub downloadable: bool,
+}
+
+/// Whether a recording's media can be fetched, checked before queueing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadCheck {
+    pub downloadable: bool,
+    pub reason: DownloadReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+/// Why a recording can or can't be downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadReason {
+    Available,
+    /// The recording or its media is gone, most likely deleted
+    NotFound,
+    /// The key's account may not fetch it
+    Forbidden,
+    /// Fathom offers no download for it
+    NoDownload,
+    /// Fathom or the media host didn't answer in time
+    Timeout,
+    /// The media host answered something else
+    Unavailable,
+}
+
+impl DownloadReason {
+    /// What to tell a user about to queue the recording
+    pub fn describe(&self) -> &'static str {
+        match self {
+            DownloadReason::Available => "The recording can be downloaded",
+            DownloadReason::NotFound => "The recording no longer exists in Fathom",
+            DownloadReason::Forbidden => "Your Fathom key has no access to the recording",
+            DownloadReason::NoDownload => "Fathom offers no download for the recording",
+            DownloadReason::Timeout => "Fathom took too long to say whether it can be downloaded",
+            DownloadReason::Unavailable => "The recording's media could not be reached",
+        }
+    }
+}
+
+impl DownloadCheck {
+    /// A recording that can't be downloaded, for `reason`
+    pub fn refused(reason: DownloadReason) -> Self {
+        Self {
+            downloadable: false,
+            reason,
+            size_bytes: None,
+            content_type: None,
+        }
+    }
+}
+
+/// One speaker's turn in a transcript
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub speaker: String,
+    /// Offset into the recording, in milliseconds
+    pub start_ms: u64,
+    /// Where the next turn starts, or `start_ms` for the last
+    pub end_ms: u64,
+    pub text: String,
+}
+
+/// A Fathom payload that parsed but can't be made sense of
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{0}")]
+pub struct Malformed(pub String);
+
+/// Fathom's payloads, named as Fathom names them
+pub mod wire {
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+
+    use super::{
+        as_email, normalize_participants, FathomMeeting, FathomMeetingDetail, Malformed,
+        Participant, TranscriptSegment,
+    };
+
+    /// A page of `GET /meetings`
+    #[derive(Debug, Deserialize)]
+    pub struct Listing {
+        pub items: Vec<Meeting>,
+        #[serde(default)]
+        pub next_cursor: Option<String>,
+    }
+
+    /// A meeting of the listing, or `GET /recordings/:id`
+    #[derive(Debug, Deserialize)]
+    pub struct Meeting {
+        /// A number, though some payloads quote it
+        #[serde(rename = "recording_id")]
+        id: serde_json::Value,
+        #[serde(default)]
+        title: Option<String>,
+        /// The calendar event's title, when the recording has none
+        #[serde(default, rename = "meeting_title")]
+        calendar_title: Option<String>,
+        #[serde(default)]
+        url: Option<String>,
+        #[serde(default)]
+        share_url: Option<String>,
+        #[serde(default)]
+        created_at: Option<DateTime<Utc>>,
+        #[serde(default, rename = "scheduled_start_time")]
+        scheduled_at: Option<DateTime<Utc>>,
+        #[serde(default, rename = "recording_start_time")]
+        started_at: Option<DateTime<Utc>>,
+        #[serde(default, rename = "recording_end_time")]
+        ended_at: Option<DateTime<Utc>>,
+        #[serde(default, rename = "calendar_invitees")]
+        invitees: Vec<Invitee>,
+        #[serde(default, rename = "default_summary")]
+        summary: Option<Summary>,
+        #[serde(default, rename = "recording_size_bytes")]
+        size_bytes: Option<u64>,
+        #[serde(default)]
+        download_url: Option<String>,
+        /// Hex SHA-256 of the media, when Fathom gives one
+        #[serde(default, rename = "recording_sha256")]
+        sha256: Option<String>,
+    }
+
+    impl Meeting {
+        /// The signed URL of the recording's media, when Fathom offers one
+        pub fn download_url(&self) -> Option<&str> {
+            self.download_url.as_deref().filter(|url| !url.is_empty())
+        }
+
+        pub fn size_bytes(&self) -> Option<u64> {
+            self.size_bytes
+        }
+
+        pub fn sha256(&self) -> Option<&str> {
+            self.sha256.as_deref().filter(|sha256| !sha256.is_empty())
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Invitee {
+        /// Usually a name, though some carry the email too, or are one
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        email: Option<String>,
+        #[serde(default)]
+        is_organizer: bool,
+    }
+
+    impl Invitee {
+        /// How Fathom wrote the invitee
+        fn raw(&self) -> Option<String> {
+            let name = self.name.as_deref().filter(|name| !name.is_empty());
+            let email = self.email.as_deref().filter(|email| !email.is_empty());
+            match (name, email) {
+                (Some(name), Some(email)) if !name.contains(email) => {
+                    Some(format!("{} <{}>", name, email))
+                }
+                (Some(name), _) => Some(name.to_string()),
+                (None, email) => email.map(str::to_string),
+            }
+        }
+
+        /// The invitee, their `email` taking over any the name holds
+        fn participant(&self) -> Option<Participant> {
+            let email = self.email.as_deref().and_then(as_email);
+            let mut participant = match self.name.as_deref().and_then(Participant::parse) {
+                Some(participant) => participant,
+                None => Participant::parse(email.as_deref()?)?,
+            };
+            if email.is_some() {
+                if participant.email.as_deref() == Some(&participant.display_name) {
+                    participant.display_name = email.clone().unwrap_or_default();
+                }
+                participant.email = email;
+            }
+            participant.organizer = self.is_organizer;
+            Some(participant)
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Summary {
+        #[serde(default, rename = "markdown_formatted")]
+        markdown: Option<String>,
+    }
+
+    /// `GET /recordings/:id/transcript` once it is finished
+    #[derive(Debug, Deserialize)]
+    pub struct Transcript {
+        #[serde(rename = "transcript")]
+        segments: Vec<Segment>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Segment {
+        speaker: Speaker,
+        text: String,
+        /// `HH:MM:SS` into the recording
+        timestamp: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Speaker {
+        #[serde(default)]
+        display_name: Option<String>,
+        #[serde(default, rename = "matched_calendar_invitee_email")]
+        email: Option<String>,
+    }
+
+    impl TryFrom<Meeting> for FathomMeeting {
+        type Error = Malformed;
+
+        fn try_from(wire: Meeting) -> Result<Self, Self::Error> {
+            let id = match wire.id {
+                serde_json::Value::String(id) if !id.is_empty() => id,
+                serde_json::Value::Number(id) => id.to_string(),
+                _ => return Err(Malformed("meeting without a recording id".to_string())),
+            };
+            let start = wire
+                .started_at
+                .or(wire.scheduled_at)
+                .or(wire.created_at)
+                .ok_or_else(|| Malformed(format!("meeting {} has no start time", id)))?;
+            let duration = wire
+                .ended_at
+                .map(|end| (end - start).num_seconds().clamp(0, u32::MAX as i64) as u32)
+                .unwrap_or(0);
+            let participants = normalize_participants(
+                wire.invitees
+                    .iter()
+                    .filter_map(Invitee::participant)
+                    .collect(),
+            );
+            let participants_raw = wire.invitees.iter().filter_map(Invitee::raw).collect();
+            Ok(Self {
+                title: [wire.title, wire.calendar_title]
+                    .into_iter()
+                    .flatten()
+                    .find(|title| !title.is_empty())
+                    .unwrap_or_else(|| "Untitled meeting".to_string()),
+                start_time: start,
+                duration,
+                participants,
+                participants_raw,
+                url: wire.url,
+                share_url: wire.share_url,
+                id,
+            })
+        }
+    }
+
+    impl TryFrom<Meeting> for FathomMeetingDetail {
+        type Error = Malformed;
+
+        fn try_from(mut wire: Meeting) -> Result<Self, Self::Error> {
+            let summary = wire
+                .summary
+                .take()
+                .and_then(|summary| summary.markdown)
+                .filter(|summary| !summary.is_empty());
+            let size_bytes = wire.size_bytes.take();
+            let downloadable = wire.download_url.take().is_some_and(|url| !url.is_empty());
+            Ok(Self {
+                meeting: FathomMeeting::try_from(wire)?,
+                summary,
+                size_bytes,
+                downloadable,
+            })
+        }
+    }
+
+    impl TryFrom<Transcript> for Vec<TranscriptSegment> {
+        type Error = Malformed;
+
+        fn try_from(wire: Transcript) -> Result<Self, Self::Error> {
+            let starts = wire
+                .segments
+                .iter()
+                .map(|segment| {
+                    timestamp_ms(&segment.timestamp)
+                        .ok_or_else(|| Malformed(format!("bad timestamp {:?}", segment.timestamp)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(wire
+                .segments
+                .into_iter()
+                .enumerate()
+                .map(|(i, segment)| TranscriptSegment {
+                    speaker: segment
+                        .speaker
+                        .display_name
+                        .filter(|name| !name.is_empty())
+                        .or(segment.speaker.email)
+                        .unwrap_or_else(|| "Unknown speaker".to_string()),
+                    start_ms: starts[i],
+                    end_ms: starts
+                        .get(i + 1)
+                        .copied()
+                        .unwrap_or(starts[i])
+                        .max(starts[i]),
+                    text: segment.text,
+                })
+                .collect())
+        }
+    }
+
+    /// Milliseconds in an `HH:MM:SS` (or `MM:SS`) timestamp, which may carry
+    /// a fraction of a second
+    pub(super) fn timestamp_ms(timestamp: &str) -> Option<u64> {
+        let mut parts = timestamp.rsplit(':');
+        let seconds: f64 = parts.next()?.parse().ok()?;
+        let minutes: u64 = parts.next()?.parse().ok()?;
+        let hours: u64 = match parts.next() {
+            Some(hours) => hours.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() || !(0.0..60.0).contains(&seconds) || minutes >= 60 {
+            return None;
+        }
+        Some((hours * 3600 + minutes * 60) * 1000 + (seconds * 1000.0).round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wire::{timestamp_ms, Listing, Meeting, Transcript};
+    use super::*;
+
+    /// Payloads in the shape Fathom's API documents, trimmed
+    const LISTING: &str = include_str!("../fixtures/fathom/meetings.json");
+    const RECORDING: &str = include_str!("../fixtures/fathom/recording.json");
+    const TRANSCRIPT: &str = include_str!("../fixtures/fathom/transcript.json");
+
+    #[test]
+    fn test_listing_payload() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        assert!(listing.next_cursor.is_some());
+        let meetings = listing
+            .items
+            .into_iter()
+            .map(FathomMeeting::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            meetings[0],
+            FathomMeeting {
+                id: "123456789".to_string(),
+                title: "Quarterly Business Review".to_string(),
+                start_time: "2025-03-01T17:01:30Z".parse().unwrap(),
+                duration: 3510,
+                participants: vec![
+                    Participant {
+                        display_name: "Alice Johnson".to_string(),
+                        email: Some("alice@acme.com".to_string()),
+                        organizer: false,
+                    },
+                    Participant {
+                        display_name: "bob@acme.com".to_string(),
+                        email: Some("bob@acme.com".to_string()),
+                        organizer: false,
+                    },
+                ],
+                participants_raw: vec![
+                    "Alice Johnson <alice@acme.com>".to_string(),
+                    "bob@acme.com".to_string()
+                ],
+                url: Some("https://fathom.video/calls/123456789".to_string()),
+                share_url: Some("https://fathom.video/share/abcdef".to_string()),
+            }
+        );
+        // No recording title and no recorded start: the calendar's stand in
+        assert_eq!(meetings[1].id, "987654321");
+        assert_eq!(meetings[1].title, "Weekly sync");
+        assert_eq!(meetings[1].start_time.to_rfc3339(), "2025-03-03T09:00:00+00:00");
+        assert_eq!(meetings[1].duration, 0);
+    }
+
+    #[test]
+    fn test_recording_payload() {
+        let meeting: Meeting = serde_json::from_str(RECORDING).unwrap();
+        let detail = FathomMeetingDetail::try_from(meeting).unwrap();
+        assert_eq!(detail.meeting.id, "123456789");
+        assert!(detail.summary.as_deref().unwrap().starts_with("## Summary"));
+        assert_eq!(detail.size_bytes, Some(734_003_200));
+        assert!(detail.downloadable);
+
+        // The API shape round-trips, flattened, and never names the URL
+        let json = serde_json::to_value(&detail).unwrap();
+        assert_eq!(json["id"], "123456789");
+        assert!(!json.to_string().contains("signature"));
+        let back: FathomMeetingDetail = serde_json::from_value(json).unwrap();
+        assert_eq!(back, detail);
+    }
+
+    #[test]
+    fn test_transcript_payload() {
+        let transcript: Transcript = serde_json::from_str(TRANSCRIPT).unwrap();
+        let segments = Vec::<TranscriptSegment>::try_from(transcript).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(
+            segments[0],
+            TranscriptSegment {
+                speaker: "Alice Johnson".to_string(),
+                start_ms: 4_000,
+                end_ms: 12_000,
+                text: "Thanks for joining, let's get started.".to_string(),
+            }
+        );
+        assert_eq!(segments[1].speaker, "bob@acme.com");
+        assert_eq!((segments[2].start_ms, segments[2].end_ms), (75_000, 75_000));
+    }
+
+    #[test]
+    fn test_payloads_missing_what_matters() {
+        let meeting =
+            |json: &str| FathomMeeting::try_from(serde_json::from_str::<Meeting>(json).unwrap());
+        assert!(
+            meeting(r#"{"recording_id": null, "created_at": "2025-03-01T17:00:00Z"}"#).is_err()
+        );
+        assert!(meeting(r#"{"recording_id": "", "created_at": "2025-03-01T17:00:00Z"}"#).is_err());
+        assert!(meeting(r#"{"recording_id": 1}"#).is_err());
+        assert_eq!(
+            meeting(r#"{"recording_id": "7", "created_at": "2025-03-01T17:00:00Z"}"#)
+                .unwrap()
+                .title,
+            "Untitled meeting"
+        );
+    }
+
+    #[test]
+    fn test_participant_formats() {
+        let parsed = |raw: &str| {
+            Participant::parse(raw).map(|participant| (participant.display_name, participant.email))
+        };
+        let person =
+            |name: &str, email: Option<&str>| Some((name.to_string(), email.map(str::to_string)));
+        let cases = [
+            (
+                "Jane Doe <jane@x.com>",
+                person("Jane Doe", Some("jane@x.com")),
+            ),
+            (
+                "  Jane   Doe<Jane@X.com>  ",
+                person("Jane Doe", Some("jane@x.com")),
+            ),
+            (
+                "\"Doe, Jane\" <jane@x.com>",
+                person("Doe, Jane", Some("jane@x.com")),
+            ),
+            (
+                "'Jane Doe' <mailto:jane@x.com>",
+                person("Jane Doe", Some("jane@x.com")),
+            ),
+            ("jane@x.com", person("jane@x.com", Some("jane@x.com"))),
+            ("<jane@x.com>", person("jane@x.com", Some("jane@x.com"))),
+            (
+                "jane@x.com (Jane Doe)",
+                person("Jane Doe", Some("jane@x.com")),
+            ),
+            ("Jane Doe", person("Jane Doe", None)),
+            (
+                "José Núñez <jose@ejemplo.es>",
+                person("José Núñez", Some("jose@ejemplo.es")),
+            ),
+            (
+                "王小明 <xiaoming@例子.中国>",
+                person("王小明", Some("xiaoming@例子.中国")),
+            ),
+            ("Zoë 👩‍💻 Ångström", person("Zoë 👩‍💻 Ångström", None)),
+            // Malformed brackets
+            (
+                "Jane Doe <jane@x.com",
+                person("Jane Doe", Some("jane@x.com")),
+            ),
+            ("Jane Doe jane@x.com>", person("Jane Doe jane@x.com", None)),
+            (
+                "Jane Doe <not an email>",
+                person("Jane Doe not an email", None),
+            ),
+            (
+                "Jane > Doe <jane@x.com>",
+                person("Jane > Doe", Some("jane@x.com")),
+            ),
+            ("Jane <>", person("Jane", None)),
+            ("jane@ (Jane)", person("jane@ (Jane)", None)),
+            ("<>", None),
+            ("   ", None),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(parsed(raw), expected, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn test_participants_normalized() {
+        let meeting = |invitees: &str| {
+            let json = format!(
+                r#"{{"recording_id": 1, "created_at": "2025-03-01T17:00:00Z", "calendar_invitees": {}}}"#,
+                invitees
+            );
+            FathomMeeting::try_from(serde_json::from_str::<Meeting>(&json).unwrap()).unwrap()
+        };
+        let meeting = meeting(
+            r#"[
+                {"name": "jane@x.com", "email": null},
+                {"name": "Bob", "email": null},
+                {"name": "Jane Doe <JANE@x.com>", "email": null},
+                {"name": "Carol <carol@x.com>", "email": "carol@x.com", "is_organizer": true},
+                {"name": "BOB", "email": "bob@x.com"},
+                {"name": null, "email": null},
+                {"name": "", "email": "dave@x.com"}
+            ]"#,
+        );
+        let people: Vec<_> = meeting
+            .participants
+            .iter()
+            .map(|p| (p.display_name.as_str(), p.email.as_deref(), p.organizer))
+            .collect();
+        assert_eq!(
+            people,
+            [
+                ("Carol", Some("carol@x.com"), true),
+                ("Jane Doe", Some("jane@x.com"), false),
+                ("Bob", Some("bob@x.com"), false),
+                ("dave@x.com", Some("dave@x.com"), false),
+            ]
+        );
+        assert_eq!(
+            meeting.participants_raw,
+            [
+                "jane@x.com",
+                "Bob",
+                "Jane Doe <JANE@x.com>",
+                "Carol <carol@x.com>",
+                "BOB <bob@x.com>",
+                "dave@x.com"
+            ]
+        );
+        let json = serde_json::to_value(&meeting).unwrap();
+        assert_eq!(
+            json["participants"][0],
+            serde_json::json!({"display_name": "Carol", "email": "carol@x.com", "organizer": true})
+        );
+        assert_eq!(
+            json["participants"][1],
+            serde_json::json!({"display_name": "Jane Doe", "email": "jane@x.com"})
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(timestamp_ms("00:00:00"), Some(0));
+        assert_eq!(timestamp_ms("01:02:03"), Some(3_723_000));
+        assert_eq!(timestamp_ms("02:03.25"), Some(123_250));
+        for bad in ["", "12", "00:61:00", "00:00:60", "1:2:3:4", "aa:00:00"] {
+            assert_eq!(timestamp_ms(bad), None, "{}", bad);
+        }
+    }
+}
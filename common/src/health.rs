@@ -0,0 +1,208 @@
+//! Readiness built from independent dependency checks
+//!
+//! Each check runs concurrently under its own timeout and reports its
+//! latency, so a failed readiness probe says which dependency is down or
+//! slow. Only required checks decide readiness; optional ones are reported
+//! alongside.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type Probe =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Check {
+    name: &'static str,
+    required: bool,
+    timeout: Duration,
+    probe: Probe,
+}
+
+/// A set of named checks run together
+#[derive(Clone, Default)]
+pub struct CompositeHealth {
+    checks: Vec<Check>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub required: bool,
+    pub status: CheckStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of every check, ready only when all required ones passed
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub ready: bool,
+    pub checks: Vec<CheckResult>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CompositeHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a check that must pass for the service to be ready
+    pub fn required<F, Fut>(self, name: &'static str, timeout: Duration, probe: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.with_check(name, true, timeout, probe)
+    }
+
+    /// Add a check that is reported but doesn't affect readiness
+    pub fn optional<F, Fut>(self, name: &'static str, timeout: Duration, probe: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.with_check(name, false, timeout, probe)
+    }
+
+    fn with_check<F, Fut>(
+        mut self,
+        name: &'static str,
+        required: bool,
+        timeout: Duration,
+        probe: F,
+    ) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.checks.push(Check {
+            name,
+            required,
+            timeout,
+            probe: Arc::new(move || Box::pin(probe())),
+        });
+        self
+    }
+
+    /// Run every check at once and collect the results in the order added
+    pub async fn check(&self) -> HealthReport {
+        let runs: Vec<_> = self
+            .checks
+            .iter()
+            .map(|check| {
+                let probe = (check.probe)();
+                let timeout = check.timeout;
+                tokio::spawn(async move {
+                    let started = Instant::now();
+                    let outcome = tokio::time::timeout(timeout, probe).await;
+                    (outcome, started.elapsed())
+                })
+            })
+            .collect();
+
+        let mut checks = Vec::with_capacity(runs.len());
+        for (check, run) in self.checks.iter().zip(runs) {
+            let (status, error, latency) = match run.await {
+                Ok((Ok(Ok(())), latency)) => (CheckStatus::Ok, None, latency),
+                Ok((Ok(Err(e)), latency)) => (CheckStatus::Failed, Some(e), latency),
+                Ok((Err(_), latency)) => (
+                    CheckStatus::TimedOut,
+                    Some(format!("no answer within {}ms", check.timeout.as_millis())),
+                    latency,
+                ),
+                Err(e) => (
+                    CheckStatus::Failed,
+                    Some(format!("check panicked: {}", e)),
+                    Duration::ZERO,
+                ),
+            };
+            checks.push(CheckResult {
+                name: check.name,
+                required: check.required,
+                status,
+                latency_ms: latency.as_millis() as u64,
+                error,
+            });
+        }
+
+        HealthReport {
+            ready: checks
+                .iter()
+                .all(|check| !check.required || check.status == CheckStatus::Ok),
+            checks,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    #[tokio::test]
+    async fn test_ready_only_when_required_checks_pass() {
+        let report = CompositeHealth::new()
+            .required("db", TIMEOUT, || async { Ok(()) })
+            .optional("cache", TIMEOUT, || async {
+                Err("cache is down".to_string())
+            })
+            .check()
+            .await;
+        assert!(report.ready);
+        assert_eq!(report.checks[0].status, CheckStatus::Ok);
+        assert_eq!(report.checks[1].status, CheckStatus::Failed);
+        assert_eq!(report.checks[1].error.as_deref(), Some("cache is down"));
+
+        let report = CompositeHealth::new()
+            .required("db", TIMEOUT, || async { Err("refused".to_string()) })
+            .check()
+            .await;
+        assert!(!report.ready);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][0]["name"], "db");
+        assert_eq!(json["checks"][0]["status"], "failed");
+        assert_eq!(json["checks"][0]["error"], "refused");
+    }
+
+    #[tokio::test]
+    async fn test_slow_checks_time_out_without_holding_up_others() {
+        let started = Instant::now();
+        let report = CompositeHealth::new()
+            .required("slow", TIMEOUT, || async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .required("slow_too", TIMEOUT, || async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .check()
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(!report.ready);
+        assert!(report
+            .checks
+            .iter()
+            .all(|check| check.status == CheckStatus::TimedOut));
+        assert!(report.checks[0].latency_ms >= TIMEOUT.as_millis() as u64);
+    }
+}
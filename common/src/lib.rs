@@ -10,6 +10,50 @@ pub mod crypto;
 /// Broadcasting services for real-time updates
 pub mod broadcast;
 
+/// Validation rules shared between services
+pub mod validation;
+
+/// Fathom meetings as the API serves them, and Fathom's own payloads
+pub mod fathom;
+
+/// Moving meetings within the queue, and the version a move is made against
+pub mod queue_order;
+
+/// Readiness checks composed from per-dependency probes
+pub mod health;
+
+/// OpenAPI schemas of the shared types
+#[cfg(feature = "openapi")]
+pub mod openapi;
+
+/// Tracing setup shared by the services, pretty or JSON
+#[cfg(feature = "logging")]
+pub mod logging;
+
+/// Panics and server errors reported to Sentry alongside the logs
+pub mod telemetry;
+
+/// Prometheus metrics registry shared by the services
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+/// The current time, swappable in tests
+pub mod clock;
+
+/// Admin client for PocketBase, global or a user's instance
+#[cfg(feature = "pocketbase")]
+pub mod pocketbase;
+
+/// An in-memory PocketBase for the services' tests
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
+/// Version of the HTTP API the backend serves and the frontend calls
+pub const API_VERSION: &str = "v1";
+
+/// Prefix of every versioned API route, `/api/` and [`API_VERSION`]
+pub const API_PREFIX: &str = "/api/v1";
+
 /// Application-wide error type
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -29,12 +73,60 @@ pub enum AppError {
     Internal(String),
 }
 
+/// Machine-readable reason a request failed, sent as `code` next to the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request itself is malformed or fails validation
+    Validation,
+    InvalidCredentials,
+    /// The current password given to confirm a change is wrong
+    WrongPassword,
+    /// A new password fails the strength rules
+    WeakPassword,
+    EmailExists,
+    UsernameTaken,
+    /// The bearer token is missing, invalid, expired or revoked
+    InvalidToken,
+    RateLimited,
+    NotFound,
+    /// The stored API key the request needs has expired
+    KeyExpired,
+    /// The service turned down the stored API key the request used
+    KeyRejected,
+    /// A service the request depends on could not be reached
+    ServiceUnavailable,
+    /// The request body is larger than the route accepts
+    PayloadTooLarge,
+    /// The request took longer than the route allows
+    Timeout,
+    /// This deployment doesn't take new accounts
+    RegistrationClosed,
+    /// The change is refused while the deployment is in maintenance mode
+    Maintenance,
+    /// The user already has as many meetings queued as they may
+    QueueFull,
+    /// The queue changed since the version the request was made against
+    QueueChanged,
+    /// The server hasn't finished starting; retry shortly
+    StartingUp,
+    /// The server is shutting down; retry shortly, likely reaching another
+    ShuttingDown,
+    /// The recipient bounced or complained, so is no longer sent email
+    Suppressed,
+    /// The idempotency key was already used for a different request
+    IdempotencyKeyReused,
+    Internal,
+}
+
 /// Common API response wrapper
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -44,6 +136,7 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            code: None,
             timestamp: Utc::now(),
         }
     }
@@ -53,9 +146,165 @@ impl<T> ApiResponse<T> {
             success: false,
             data: None,
             error: Some(message),
+            code: None,
             timestamp: Utc::now(),
         }
     }
+
+    /// An error the client can tell apart from others by its `code`
+    pub fn failure(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code: Some(code),
+            ..Self::error(message.into())
+        }
+    }
+}
+
+/// The signed-in user as reported by the auth endpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthUserInfo {
+    pub id: String,
+    pub email: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// Payload of a successful login, registration or refresh
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthData {
+    /// Session token; absent when the account was created but needs a separate login
+    pub token: Option<String>,
+    pub user: AuthUserInfo,
+    pub message: String,
+    /// The user's PocketBase instance, which signing in starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<InstanceInfo>,
+}
+
+/// How far a user's PocketBase instance is from being usable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceState {
+    Starting,
+    Running,
+    Failed,
+    Stopped,
+}
+
+/// A user's PocketBase instance as clients see it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub status: InstanceState,
+    /// Where to reach the instance through the backend, once it is running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// Third-party service a stored API key belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceKind {
+    Fathom,
+    Loom,
+}
+
+impl ServiceKind {
+    pub const ALL: [ServiceKind; 2] = [ServiceKind::Fathom, ServiceKind::Loom];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceKind::Fathom => "fathom",
+            ServiceKind::Loom => "loom",
+        }
+    }
+
+    /// The service named `name` as in [`Self::as_str`]
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|service| service.as_str() == name)
+    }
+
+    /// Human-readable name for messages
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ServiceKind::Fathom => "Fathom",
+            ServiceKind::Loom => "Loom",
+        }
+    }
+}
+
+/// What a queue item asks the worker to do, as its `job_type` names it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobType {
+    /// Move the meeting's recording from Fathom to Loom, what an item
+    /// without a `job_type` does
+    #[default]
+    ProcessMeeting,
+    /// Fetch the meeting's title and duration from Fathom again
+    RefreshMetadata,
+    /// Fetch the meeting's transcript from Fathom
+    FetchTranscript,
+}
+
+impl JobType {
+    pub const ALL: [JobType; 3] = [
+        JobType::ProcessMeeting,
+        JobType::RefreshMetadata,
+        JobType::FetchTranscript,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobType::ProcessMeeting => "process_meeting",
+            JobType::RefreshMetadata => "refresh_metadata",
+            JobType::FetchTranscript => "fetch_transcript",
+        }
+    }
+
+    /// The job type named `name` as in [`Self::as_str`]
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|job_type| job_type.as_str() == name)
+    }
+}
+
+impl std::fmt::Display for JobType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a worker is doing, as its heartbeats tell the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    Idle,
+    Processing,
+    /// Shutting down; no more heartbeats will come
+    Offline,
+}
+
+/// A worker's periodic report to the backend's `/internal/heartbeat`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkerHeartbeat {
+    /// Stays the same across restarts of the same worker
+    pub worker_id: String,
+    pub status: WorkerState,
+    /// The task running longest, while any is
+    pub task_id: Option<Uuid>,
+    /// Stage `task_id` last reported progress in
+    pub stage: Option<broadcast::ProcessingStage>,
+    /// How much of `stage` is done, 0 to 100
+    pub percent: Option<u8>,
+    /// Tasks running, `task_id` among them
+    pub active_tasks: usize,
+    /// The worker's crate version
+    pub version: String,
+    pub uptime_secs: u64,
+    pub timestamp: DateTime<Utc>,
 }
 
 /// User representation
@@ -124,4 +373,17 @@ mod tests {
         assert!(response.data.is_none());
         assert_eq!(response.error, Some("test error".to_string()));
     }
+
+    #[test]
+    fn test_api_response_failure_carries_code() {
+        let response: ApiResponse<()> = ApiResponse::failure(ErrorCode::EmailExists, "taken");
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["code"], "email_exists");
+        assert_eq!(json["error"], "taken");
+
+        // Successes leave `code` out entirely
+        let json = serde_json::to_value(ApiResponse::success(1)).unwrap();
+        assert!(json.get("code").is_none());
+    }
 }
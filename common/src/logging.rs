@@ -0,0 +1,359 @@
+//! Tracing setup shared by the backend, worker and smtp-service
+//!
+//! `LOG_FORMAT=pretty`, the default, keeps the human-readable lines of
+//! `tracing_subscriber::fmt`. `LOG_FORMAT=json` writes one JSON object per
+//! event for log shippers: an ISO 8601 `timestamp`, `level`, `target`, the
+//! `service` that wrote it, the fields of every enclosing span (such as
+//! `request_id`, `user_id` or `task_id`) and the event's own fields, all
+//! flattened into the top level.
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::{fmt, str::FromStr};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    filter::LevelFilter,
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, as `tracing_subscriber::fmt` writes them
+    #[default]
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Pretty => "pretty",
+            LogFormat::Json => "json",
+        }
+    }
+
+    /// Read `LOG_FORMAT`, defaulting to pretty when it's unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("LOG_FORMAT") {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(LogFormat::default()),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "LOG_FORMAT must be json or pretty, not '{}'",
+                value
+            )),
+        }
+    }
+}
+
+/// Send `service`'s events at `level` and above to stdout in `format`
+///
+/// An unrecognised `level` falls back to `info`, with a warning once logging
+/// is up. Panics if a global subscriber is already set.
+pub fn init_tracing(service: &'static str, format: LogFormat, level: &str) {
+    let parsed = level.trim().parse::<Level>();
+    let ansi = std::io::IsTerminal::is_terminal(&std::io::stdout());
+    tracing_subscriber::registry()
+        .with(layer(service, format, std::io::stdout, ansi))
+        .with(LevelFilter::from_level(
+            *parsed.as_ref().unwrap_or(&Level::INFO),
+        ))
+        .init();
+    if parsed.is_err() {
+        tracing::warn!("Invalid log level '{}', defaulting to 'info'", level);
+    }
+}
+
+/// The formatting layer [`init_tracing`] installs, writing to `writer`
+pub fn layer<S, W>(
+    service: &'static str,
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Pretty => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer
+            .with_ansi(false)
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat { service })
+            .boxed(),
+    }
+}
+
+/// Collects fields into a JSON object, keeping numbers and booleans typed
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // Events bridged from the `log` crate repeat their metadata as fields
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, Value::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{:?}", value)));
+    }
+}
+
+/// Stores each span's fields as a JSON object, merging later `record` calls
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &span::Record<'_>,
+    ) -> fmt::Result {
+        let mut map = match serde_json::from_str(&current.fields) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+/// Writes each event as one JSON line
+struct JsonFormat {
+    service: &'static str,
+}
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = Map::new();
+
+        // Outer spans first, so an inner span's field wins over an outer one's
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<JsonFields>>() {
+                    if let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) {
+                        line.extend(fields);
+                    }
+                }
+                line.insert("span".to_string(), Value::from(span.name()));
+            }
+        }
+        event.record(&mut JsonVisitor(&mut line));
+
+        let metadata = event.metadata();
+        line.insert(
+            "timestamp".to_string(),
+            Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+        );
+        line.insert("level".to_string(), Value::from(metadata.level().as_str()));
+        line.insert("target".to_string(), Value::from(metadata.target()));
+        line.insert("service".to_string(), Value::from(self.service));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{field, info, info_span, warn};
+
+    /// Everything written, for the tests to read back
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// The lines written while `log` runs under `format`
+    fn capture(format: LogFormat, log: impl FnOnce()) -> Vec<String> {
+        let captured = Captured::default();
+        let subscriber =
+            tracing_subscriber::registry().with(layer("backend", format, captured.clone(), false));
+        tracing::subscriber::with_default(subscriber, log);
+        let bytes = captured.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// A request handled for a user, with a task started inside it
+    fn request_with_a_task() {
+        let request = info_span!("request", request_id = "req-1", user_id = field::Empty);
+        let _request = request.enter();
+        info!("request started");
+        request.record("user_id", "alice");
+
+        let task = info_span!("task", task_id = 42);
+        let _task = task.enter();
+        warn!(attempt = 2, retrying = true, "task {} failed", "fetch");
+    }
+
+    #[test]
+    fn test_json_lines_carry_span_and_event_fields() {
+        let lines = capture(LogFormat::Json, request_with_a_task);
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        let events: Vec<Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).expect(line))
+            .collect();
+
+        for event in &events {
+            for key in [
+                "timestamp",
+                "level",
+                "target",
+                "service",
+                "message",
+                "request_id",
+            ] {
+                assert!(event.get(key).is_some(), "{} missing from {}", key, event);
+            }
+            assert_eq!(event["service"], "backend");
+            assert_eq!(event["request_id"], "req-1");
+            let timestamp = event["timestamp"].as_str().unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+            assert!(timestamp.ends_with('Z'), "{}", timestamp);
+        }
+
+        // Fields recorded on a span later appear once they're set
+        assert_eq!(events[0]["message"], "request started");
+        assert_eq!(events[0]["level"], "INFO");
+        assert_eq!(events[0]["span"], "request");
+        assert!(events[0].get("user_id").is_none());
+
+        assert_eq!(events[1]["message"], "task fetch failed");
+        assert_eq!(events[1]["level"], "WARN");
+        assert_eq!(events[1]["span"], "task");
+        assert_eq!(events[1]["user_id"], "alice");
+        assert_eq!(events[1]["task_id"], 42);
+        assert_eq!(events[1]["attempt"], 2);
+        assert_eq!(events[1]["retrying"], true);
+    }
+
+    #[test]
+    fn test_pretty_lines_name_the_same_fields() {
+        let lines = capture(LogFormat::Pretty, request_with_a_task);
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(serde_json::from_str::<Value>(&lines[0]).is_err());
+
+        assert!(lines[0].contains("INFO"), "{}", lines[0]);
+        assert!(lines[0].contains("request started"), "{}", lines[0]);
+        assert!(lines[0].contains("request_id=\"req-1\""), "{}", lines[0]);
+
+        for expected in [
+            "WARN",
+            "task fetch failed",
+            "user_id=\"alice\"",
+            "task_id=42",
+            "attempt=2",
+        ] {
+            assert!(
+                lines[1].contains(expected),
+                "{} missing from {}",
+                expected,
+                lines[1]
+            );
+        }
+    }
+
+    #[test]
+    fn test_log_format_parses_either_name() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!(" Pretty ".parse(), Ok(LogFormat::Pretty));
+        assert_eq!(
+            "yaml".parse::<LogFormat>(),
+            Err("LOG_FORMAT must be json or pretty, not 'yaml'".to_string())
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Prometheus metrics shared by the backend, worker and smtp-service
+//!
+//! Each service declares its [`Family`]s in its own `metrics` module and
+//! records them into the process-wide [`registry`] where things happen; its
+//! `/metrics` route renders the families it declared, including those nothing
+//! has recorded yet.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+};
+
+/// Content type of the Prometheus text exposition format
+pub const EXPOSITION_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// A metric family: one name, one type, any number of label sets
+#[derive(Debug)]
+pub struct Family {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+    /// Upper bounds of a histogram's buckets; empty for other kinds
+    pub buckets: &'static [f64],
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug)]
+enum Sample {
+    Value(f64),
+    Histogram { buckets: Vec<u64>, sum: f64, count: u64 },
+}
+
+/// Samples by family name and label set
+#[derive(Debug, Default)]
+pub struct Registry {
+    samples: Mutex<BTreeMap<&'static str, BTreeMap<Labels, Sample>>>,
+}
+
+/// The process-wide registry
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+pub fn increment(family: &Family, labels: &[(&'static str, &str)]) {
+    registry().increment(family, labels)
+}
+
+pub fn add(family: &Family, labels: &[(&'static str, &str)], amount: f64) {
+    registry().add(family, labels, amount)
+}
+
+pub fn set(family: &Family, labels: &[(&'static str, &str)], value: f64) {
+    registry().set(family, labels, value)
+}
+
+pub fn observe(family: &Family, labels: &[(&'static str, &str)], value: f64) {
+    registry().observe(family, labels, value)
+}
+
+impl Registry {
+    fn update(&self, family: &Family, labels: &[(&'static str, &str)], update: impl FnOnce(&mut Sample)) {
+        let labels = labels.iter().map(|(name, value)| (*name, value.to_string())).collect();
+        let mut samples = self.samples.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let sample = samples
+            .entry(family.name)
+            .or_default()
+            .entry(labels)
+            .or_insert_with(|| match family.kind {
+                Kind::Histogram => Sample::Histogram {
+                    buckets: vec![0; family.buckets.len()],
+                    sum: 0.0,
+                    count: 0,
+                },
+                _ => Sample::Value(0.0),
+            });
+        update(sample);
+    }
+
+    /// Add one to a counter
+    pub fn increment(&self, family: &Family, labels: &[(&'static str, &str)]) {
+        debug_assert_eq!(family.kind, Kind::Counter, "{} is not a counter", family.name);
+        self.add(family, labels, 1.0)
+    }
+
+    /// Add to a counter, or to a gauge with a negative `amount` to take away
+    pub fn add(&self, family: &Family, labels: &[(&'static str, &str)], amount: f64) {
+        debug_assert!(
+            family.kind == Kind::Gauge || (family.kind == Kind::Counter && amount >= 0.0),
+            "{} can't be added {}",
+            family.name,
+            amount
+        );
+        self.update(family, labels, |sample| {
+            if let Sample::Value(value) = sample {
+                *value += amount;
+            }
+        });
+    }
+
+    /// Set a gauge
+    pub fn set(&self, family: &Family, labels: &[(&'static str, &str)], value: f64) {
+        debug_assert_eq!(family.kind, Kind::Gauge, "{} is not a gauge", family.name);
+        self.update(family, labels, |sample| *sample = Sample::Value(value));
+    }
+
+    /// Record one observation in a histogram
+    pub fn observe(&self, family: &Family, labels: &[(&'static str, &str)], value: f64) {
+        debug_assert_eq!(family.kind, Kind::Histogram, "{} is not a histogram", family.name);
+        self.update(family, labels, |sample| {
+            if let Sample::Histogram { buckets, sum, count } = sample {
+                for (bucket, bound) in buckets.iter_mut().zip(family.buckets) {
+                    if value <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    /// The value of a counter or gauge, or a histogram's count
+    pub fn value(&self, family: &Family, labels: &[(&'static str, &str)]) -> f64 {
+        let labels: Labels = labels.iter().map(|(name, value)| (*name, value.to_string())).collect();
+        let samples = self.samples.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match samples.get(family.name).and_then(|family| family.get(&labels)) {
+            Some(Sample::Value(value)) => *value,
+            Some(Sample::Histogram { count, .. }) => *count as f64,
+            None => 0.0,
+        }
+    }
+
+    /// `families`, in order, in the Prometheus text exposition format
+    pub fn render(&self, families: &[&Family]) -> String {
+        let samples = self.samples.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut out = String::new();
+        for family in families {
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind.as_str());
+            for (labels, sample) in samples.get(family.name).into_iter().flatten() {
+                match sample {
+                    Sample::Value(value) => {
+                        let _ = writeln!(out, "{}{} {}", family.name, label_set(labels, None), value);
+                    }
+                    Sample::Histogram { buckets, sum, count } => {
+                        for (bucket, bound) in buckets.iter().zip(family.buckets) {
+                            let le = bound.to_string();
+                            let _ = writeln!(out, "{}_bucket{} {}", family.name, label_set(labels, Some(&le)), bucket);
+                        }
+                        let _ = writeln!(out, "{}_bucket{} {}", family.name, label_set(labels, Some("+Inf")), count);
+                        let _ = writeln!(out, "{}_sum{} {}", family.name, label_set(labels, None), sum);
+                        let _ = writeln!(out, "{}_count{} {}", family.name, label_set(labels, None), count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `{name="value",...}`, or nothing for an unlabelled sample
+fn label_set(labels: &Labels, le: Option<&str>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUESTS: Family = Family {
+        name: "requests_total",
+        kind: Kind::Counter,
+        help: "Requests",
+        buckets: &[],
+    };
+
+    const IN_FLIGHT: Family = Family {
+        name: "in_flight",
+        kind: Kind::Gauge,
+        help: "Requests running now",
+        buckets: &[],
+    };
+
+    const DURATION: Family = Family {
+        name: "duration_seconds",
+        kind: Kind::Histogram,
+        help: "Time taken",
+        buckets: &[0.1, 1.0],
+    };
+
+    const UNUSED: Family = Family {
+        name: "unused_total",
+        kind: Kind::Counter,
+        help: "Never recorded",
+        buckets: &[],
+    };
+
+    #[test]
+    fn test_render_exposition_format() {
+        let registry = Registry::default();
+        registry.increment(&REQUESTS, &[("route", "/health")]);
+        registry.add(&REQUESTS, &[("route", "/health")], 1.0);
+        registry.increment(&REQUESTS, &[("route", "say \"hi\"")]);
+        registry.add(&IN_FLIGHT, &[], 2.0);
+        registry.add(&IN_FLIGHT, &[], -1.0);
+        registry.observe(&DURATION, &[("stage", "download")], 0.5);
+
+        let text = registry.render(&[&REQUESTS, &IN_FLIGHT, &DURATION, &UNUSED]);
+        assert!(text.contains("# HELP requests_total Requests\n# TYPE requests_total counter\n"));
+        assert!(text.contains("requests_total{route=\"/health\"} 2\n"));
+        assert!(text.contains("requests_total{route=\"say \\\"hi\\\"\"} 1\n"));
+        assert!(text.contains("in_flight 1\n"));
+        assert!(text.contains("duration_seconds_bucket{stage=\"download\",le=\"0.1\"} 0\n"));
+        assert!(text.contains("duration_seconds_bucket{stage=\"download\",le=\"1\"} 1\n"));
+        assert!(text.contains("duration_seconds_bucket{stage=\"download\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("duration_seconds_sum{stage=\"download\"} 0.5\n"));
+        assert_eq!(registry.value(&DURATION, &[("stage", "download")]), 1.0);
+        // Families are declared even before anything is recorded
+        assert!(text.contains("# TYPE unused_total counter\n"));
+
+        registry.set(&IN_FLIGHT, &[], 5.0);
+        assert_eq!(registry.value(&IN_FLIGHT, &[]), 5.0);
+        assert_eq!(registry.value(&REQUESTS, &[("route", "/other")]), 0.0);
+    }
+}
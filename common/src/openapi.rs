@@ -0,0 +1,584 @@
+//! OpenAPI schemas for the types the API sends and accepts
+//!
+//! Each type describes its serialized form through [`Schema`]. A
+//! [`Components`] collects them by name as operations refer to them, so a
+//! spec lists exactly the schemas something uses. The impls for the shared
+//! types are here; services add their own for the types they define.
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use crate::{
+    fathom::{
+        DownloadCheck, DownloadReason, FathomMeeting, FathomMeetingDetail, Participant,
+        TranscriptSegment,
+    },
+    AuthData, AuthUserInfo, ErrorCode, InstanceInfo, InstanceState, ServiceKind,
+};
+
+/// A type that can describe itself as a JSON Schema
+pub trait Schema {
+    /// Name under `components.schemas`
+    const NAME: &'static str;
+
+    /// The schema of the serialized type, adding the types it refers to
+    fn schema(components: &mut Components) -> Value;
+}
+
+/// The named schemas of a spec
+#[derive(Debug, Default)]
+pub struct Components {
+    schemas: BTreeMap<&'static str, Value>,
+}
+
+impl Components {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `T` and what it refers to, and return a reference to it
+    pub fn add<T: Schema>(&mut self) -> Value {
+        if !self.schemas.contains_key(T::NAME) {
+            // Claimed before building so a type that refers to itself stops
+            self.schemas.insert(T::NAME, Value::Null);
+            let schema = T::schema(self);
+            self.schemas.insert(T::NAME, schema);
+        }
+        json!({ "$ref": format!("#/components/schemas/{}", T::NAME) })
+    }
+
+    /// The schemas by name, for `components.schemas`
+    pub fn into_schemas(self) -> Map<String, Value> {
+        self.schemas
+            .into_iter()
+            .map(|(name, schema)| (name.to_string(), schema))
+            .collect()
+    }
+}
+
+/// An object of `properties`, of which `required` are always present
+pub fn object(required: &[&str], properties: Value) -> Value {
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+pub fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+pub fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+/// A non-negative whole number
+pub fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+/// An RFC 3339 timestamp
+pub fn date_time() -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+pub fn uuid() -> Value {
+    json!({ "type": "string", "format": "uuid" })
+}
+
+pub fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// A string that is one of `values`
+pub fn string_enum(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+/// `schema` or `null`, for an `Option` serialized either way
+pub fn nullable(schema: Value) -> Value {
+    if schema.get("$ref").is_some() {
+        // Siblings of a `$ref` are ignored, so it goes in an `allOf`
+        json!({ "allOf": [schema], "nullable": true })
+    } else {
+        let mut schema = schema;
+        schema["nullable"] = json!(true);
+        schema
+    }
+}
+
+/// `schema` with a description
+pub fn described(schema: Value, description: &str) -> Value {
+    if schema.get("$ref").is_some() {
+        json!({ "allOf": [schema], "description": description })
+    } else {
+        let mut schema = schema;
+        schema["description"] = json!(description);
+        schema
+    }
+}
+
+/// The [`crate::ApiResponse`] envelope around `data`
+pub fn envelope(components: &mut Components, data: Value) -> Value {
+    object(
+        &["success", "timestamp"],
+        json!({
+            "success": boolean(),
+            "data": nullable(data),
+            "error": nullable(string()),
+            "code": components.add::<ErrorCode>(),
+            "timestamp": date_time(),
+        }),
+    )
+}
+
+/// Check `value` against `schema`, whose `$ref`s point into `schemas`
+///
+/// Understands what the schemas here are built from: `$ref`, `allOf`,
+/// `nullable`, `type`, `enum`, the `date-time` and `uuid` formats,
+/// `minimum`, `required`, `properties`, `additionalProperties` and `items`.
+/// An object may only hold the properties its schema lists, if it lists any,
+/// so a field serde writes that the schema leaves out is a mismatch too.
+pub fn validate(value: &Value, schema: &Value, schemas: &Map<String, Value>) -> Result<(), String> {
+    check(value, schema, schemas, "$", true)
+}
+
+fn resolve<'a>(schema: &'a Value, schemas: &'a Map<String, Value>, at: &str) -> Result<&'a Value, String> {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            let target = schemas
+                .get(name)
+                .ok_or_else(|| format!("{}: {} is not defined", at, reference))?;
+            resolve(target, schemas, at)
+        }
+        None => Ok(schema),
+    }
+}
+
+/// Names of the properties `schema` lists, through `allOf`
+fn declared<'a>(schema: &'a Value, schemas: &'a Map<String, Value>, names: &mut Vec<&'a str>) {
+    let Ok(schema) = resolve(schema, schemas, "") else {
+        return;
+    };
+    if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+        for part in parts {
+            declared(part, schemas, names);
+        }
+    }
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        names.extend(properties.keys().map(String::as_str));
+    }
+}
+
+/// `strict` unless checking one part of an `allOf`, whose siblings may
+/// list the other properties
+fn check(value: &Value, schema: &Value, schemas: &Map<String, Value>, at: &str, strict: bool) -> Result<(), String> {
+    let schema = resolve(schema, schemas, at)?;
+    if value.is_null() {
+        return match schema.get("nullable") == Some(&json!(true)) {
+            true => Ok(()),
+            false => Err(format!("{}: null where it isn't nullable", at)),
+        };
+    }
+
+    if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+        for part in parts {
+            check(value, part, schemas, at, false)?;
+        }
+        if strict {
+            if let Some(fields) = value.as_object() {
+                let mut names = Vec::new();
+                declared(schema, schemas, &mut names);
+                if let Some(name) = fields.keys().find(|name| !names.is_empty() && !names.contains(&name.as_str())) {
+                    return Err(format!("{}.{}: not in the schema", at, name));
+                }
+            }
+        }
+    }
+
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return Err(format!("{}: {} is not one of {}", at, value, Value::from(values.clone())));
+        }
+    }
+
+    let Some(kind) = schema.get("type").and_then(Value::as_str) else {
+        return Ok(());
+    };
+    let matches = match kind {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        _ => return Err(format!("{}: unknown type {}", at, kind)),
+    };
+    if !matches {
+        return Err(format!("{}: {} is not of type {}", at, value, kind));
+    }
+
+    if let (Some(minimum), Some(number)) = (schema.get("minimum").and_then(Value::as_f64), value.as_f64()) {
+        if number < minimum {
+            return Err(format!("{}: {} is below {}", at, number, minimum));
+        }
+    }
+    if let (Some(format), Some(text)) = (schema.get("format").and_then(Value::as_str), value.as_str()) {
+        let valid = match format {
+            "date-time" => chrono::DateTime::parse_from_rfc3339(text).is_ok(),
+            "uuid" => uuid::Uuid::parse_str(text).is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!("{}: {:?} is not a {}", at, text, format));
+        }
+    }
+
+    if let Some(items) = value.as_array() {
+        let item_schema = schema.get("items").unwrap_or(&Value::Null);
+        for (index, item) in items.iter().enumerate() {
+            if !item_schema.is_null() {
+                check(item, item_schema, schemas, &format!("{}[{}]", at, index), true)?;
+            }
+        }
+    }
+
+    if let Some(fields) = value.as_object() {
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            let name = name.as_str().unwrap_or_default();
+            if !fields.contains_key(name) {
+                return Err(format!("{}.{}: required but missing", at, name));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        for (name, field) in fields {
+            let field_at = format!("{}.{}", at, name);
+            match (properties.and_then(|properties| properties.get(name)), additional) {
+                (Some(property), _) => check(field, property, schemas, &field_at, true)?,
+                (None, Some(additional)) if additional.is_object() => check(field, additional, schemas, &field_at, true)?,
+                (None, _) if strict && properties.is_some() => return Err(format!("{}: not in the schema", field_at)),
+                (None, _) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Schema for ErrorCode {
+    const NAME: &'static str = "ErrorCode";
+
+    fn schema(_: &mut Components) -> Value {
+        described(
+            string_enum(&[
+                "validation",
+                "invalid_credentials",
+                "wrong_password",
+                "weak_password",
+                "email_exists",
+                "username_taken",
+                "invalid_token",
+                "rate_limited",
+                "not_found",
+                "key_expired",
+                "key_rejected",
+                "service_unavailable",
+                "payload_too_large",
+                "timeout",
+                "registration_closed",
+                "maintenance",
+                "queue_full",
+                "queue_changed",
+                "starting_up",
+                "shutting_down",
+                "suppressed",
+                "idempotency_key_reused",
+                "internal",
+            ]),
+            "Machine-readable reason a request failed",
+        )
+    }
+}
+
+impl Schema for ServiceKind {
+    const NAME: &'static str = "ServiceKind";
+
+    fn schema(_: &mut Components) -> Value {
+        let names: Vec<&str> = ServiceKind::ALL.iter().map(ServiceKind::as_str).collect();
+        string_enum(&names)
+    }
+}
+
+impl Schema for AuthUserInfo {
+    const NAME: &'static str = "AuthUserInfo";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["id", "email", "username", "verified"],
+            json!({
+                "id": string(),
+                "email": string(),
+                "username": string(),
+                "name": nullable(string()),
+                "verified": boolean(),
+            }),
+        )
+    }
+}
+
+impl Schema for InstanceState {
+    const NAME: &'static str = "InstanceState";
+
+    fn schema(_: &mut Components) -> Value {
+        string_enum(&["starting", "running", "failed", "stopped"])
+    }
+}
+
+impl Schema for InstanceInfo {
+    const NAME: &'static str = "InstanceInfo";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["status"],
+            json!({
+                "status": components.add::<InstanceState>(),
+                "url": described(string(), "Where to reach the instance once it is running"),
+            }),
+        )
+    }
+}
+
+impl Schema for AuthData {
+    const NAME: &'static str = "AuthData";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["user", "message"],
+            json!({
+                "token": described(
+                    nullable(string()),
+                    "Session token; absent when the account needs a separate login or the \
+                     token went into a cookie",
+                ),
+                "user": components.add::<AuthUserInfo>(),
+                "message": string(),
+                "instance": components.add::<InstanceInfo>(),
+            }),
+        )
+    }
+}
+
+impl Schema for Participant {
+    const NAME: &'static str = "Participant";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["display_name"],
+            json!({
+                "display_name": string(),
+                "email": string(),
+                "organizer": boolean(),
+            }),
+        )
+    }
+}
+
+impl Schema for FathomMeeting {
+    const NAME: &'static str = "FathomMeeting";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &[
+                "id",
+                "title",
+                "start_time",
+                "duration",
+                "participants",
+                "participants_raw",
+            ],
+            json!({
+                "id": described(string(), "Fathom's recording id"),
+                "title": string(),
+                "start_time": date_time(),
+                "duration": described(integer(), "Length of the recording in seconds"),
+                "participants": array(components.add::<Participant>()),
+                "participants_raw": array(string()),
+                "url": string(),
+                "share_url": string(),
+            }),
+        )
+    }
+}
+
+impl Schema for FathomMeetingDetail {
+    const NAME: &'static str = "FathomMeetingDetail";
+
+    fn schema(components: &mut Components) -> Value {
+        json!({
+            "allOf": [
+                components.add::<FathomMeeting>(),
+                object(
+                    &["downloadable"],
+                    json!({
+                        "summary": described(string(), "Fathom's summary, as Markdown"),
+                        "size_bytes": integer(),
+                        "downloadable": boolean(),
+                    }),
+                ),
+            ]
+        })
+    }
+}
+
+impl Schema for DownloadReason {
+    const NAME: &'static str = "DownloadReason";
+
+    fn schema(_: &mut Components) -> Value {
+        string_enum(&[
+            "available",
+            "not_found",
+            "forbidden",
+            "no_download",
+            "timeout",
+            "unavailable",
+        ])
+    }
+}
+
+impl Schema for DownloadCheck {
+    const NAME: &'static str = "DownloadCheck";
+
+    fn schema(components: &mut Components) -> Value {
+        object(
+            &["downloadable", "reason"],
+            json!({
+                "downloadable": boolean(),
+                "reason": components.add::<DownloadReason>(),
+                "size_bytes": integer(),
+                "content_type": string(),
+            }),
+        )
+    }
+}
+
+impl Schema for TranscriptSegment {
+    const NAME: &'static str = "TranscriptSegment";
+
+    fn schema(_: &mut Components) -> Value {
+        object(
+            &["speaker", "start_ms", "end_ms", "text"],
+            json!({
+                "speaker": string(),
+                "start_ms": described(integer(), "Offset into the recording, in milliseconds"),
+                "end_ms": integer(),
+                "text": string(),
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enum_values(schema: &Value) -> Vec<String> {
+        schema["enum"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_enum_schemas_list_what_serde_accepts() {
+        let mut components = Components::new();
+        for value in enum_values(&ErrorCode::schema(&mut components)) {
+            serde_json::from_value::<ErrorCode>(json!(value)).unwrap();
+        }
+        for value in enum_values(&DownloadReason::schema(&mut components)) {
+            serde_json::from_value::<DownloadReason>(json!(value)).unwrap();
+        }
+        for value in enum_values(&InstanceState::schema(&mut components)) {
+            serde_json::from_value::<InstanceState>(json!(value)).unwrap();
+        }
+        assert_eq!(
+            enum_values(&ServiceKind::schema(&mut components)),
+            ["fathom", "loom"]
+        );
+    }
+
+    #[test]
+    fn test_values_are_validated_against_their_schema() {
+        let mut components = Components::new();
+        let reference = components.add::<AuthData>();
+        let schemas = components.into_schemas();
+        let mut data = json!({
+            "token": null,
+            "user": { "id": "u1", "email": "ana@example.com", "username": "ana", "verified": true },
+            "message": "Signed in",
+            "instance": { "status": "running" },
+        });
+        validate(&data, &reference, &schemas).unwrap();
+
+        data["instance"]["status"] = json!("sleeping");
+        assert!(validate(&data, &reference, &schemas).unwrap_err().contains("$.instance.status"));
+        data["instance"]["status"] = json!("running");
+        data["user"]["verified"] = json!("yes");
+        assert!(validate(&data, &reference, &schemas).unwrap_err().contains("not of type boolean"));
+        data["user"]["verified"] = json!(true);
+        data["user"].as_object_mut().unwrap().remove("email");
+        assert_eq!(validate(&data, &reference, &schemas).unwrap_err(), "$.user.email: required but missing");
+        data["user"]["email"] = json!("ana@example.com");
+        data["user"]["role"] = json!("admin");
+        assert_eq!(validate(&data, &reference, &schemas).unwrap_err(), "$.user.role: not in the schema");
+    }
+
+    #[test]
+    fn test_all_of_parts_together_declare_an_objects_properties() {
+        let mut components = Components::new();
+        let reference = components.add::<FathomMeetingDetail>();
+        let schemas = components.into_schemas();
+        let mut detail = json!({
+            "id": "rec-1",
+            "title": "Standup",
+            "start_time": "2026-10-14T09:00:00Z",
+            "duration": 900,
+            "participants": [],
+            "participants_raw": [],
+            "summary": "Notes",
+            "downloadable": true,
+        });
+        validate(&detail, &reference, &schemas).unwrap();
+
+        detail["start_time"] = json!("yesterday");
+        assert!(validate(&detail, &reference, &schemas).unwrap_err().contains("is not a date-time"));
+        detail["start_time"] = json!("2026-10-14T09:00:00Z");
+        detail["duration"] = json!(-1);
+        assert!(validate(&detail, &reference, &schemas).unwrap_err().contains("below 0"));
+        detail["duration"] = json!(900);
+        detail["retention_days"] = json!(30);
+        assert_eq!(validate(&detail, &reference, &schemas).unwrap_err(), "$.retention_days: not in the schema");
+    }
+
+    #[test]
+    fn test_components_collect_referenced_types_once() {
+        let mut components = Components::new();
+        let reference = components.add::<AuthData>();
+        assert_eq!(reference["$ref"], "#/components/schemas/AuthData");
+        components.add::<AuthUserInfo>();
+
+        let schemas = components.into_schemas();
+        let names: Vec<&str> = schemas.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            ["AuthData", "AuthUserInfo", "InstanceInfo", "InstanceState"]
+        );
+        assert_eq!(
+            schemas["AuthData"]["properties"]["user"]["$ref"],
+            "#/components/schemas/AuthUserInfo"
+        );
+    }
+}
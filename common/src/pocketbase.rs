@@ -0,0 +1,367 @@
+//! Admin client for a PocketBase, as the backend, worker and smtp-service use it
+//!
+//! The collections the services keep in the global PocketBase (queues,
+//! revoked tokens, the email queue, ...) are only writable by admins, so
+//! requests carry an admin token fetched with the configured credentials,
+//! cached, and fetched again once if PocketBase turns it down. The backend
+//! hands out the same client for user instances.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+/// Records fetched per page when listing a whole collection
+const PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum PbError {
+    #[error("PocketBase request failed: {0}")]
+    Request(String),
+
+    #[error("PocketBase returned {status}: {body}")]
+    Status { status: u16, body: Value },
+}
+
+impl PbError {
+    /// Whether PocketBase refused a write for repeating a unique value
+    pub fn is_not_unique(&self) -> bool {
+        match self {
+            PbError::Status { status: 400, body } => body
+                .get("data")
+                .and_then(Value::as_object)
+                .is_some_and(|fields| {
+                    fields
+                        .values()
+                        .any(|field| field.get("code").and_then(Value::as_str) == Some("validation_not_unique"))
+                }),
+            _ => false,
+        }
+    }
+}
+
+impl From<reqwest::Error> for PbError {
+    fn from(e: reqwest::Error) -> Self {
+        PbError::Request(e.to_string())
+    }
+}
+
+/// Quote `value` as a string literal for a PocketBase filter
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// `time` as PocketBase writes dates, so filters compare them as it does
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.3fZ").to_string()
+}
+
+/// A PocketBase date, `2024-05-01 09:30:00.000Z`, or RFC 3339
+pub fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .map(|time| time.and_utc())
+        })
+}
+
+/// One page of a collection listing
+#[derive(Debug, Clone)]
+pub struct RecordPage {
+    pub items: Vec<Value>,
+    pub page: u32,
+    pub per_page: u32,
+    /// Records matching the filter across all pages
+    pub total_items: u64,
+}
+
+pub struct PocketBase {
+    url: String,
+    admin_email: String,
+    admin_password: String,
+    client: reqwest::Client,
+    admin_token: Mutex<Option<String>>,
+}
+
+impl PocketBase {
+    /// Client for the PocketBase at `url` with the given admin account
+    pub fn new(url: &str, admin_email: &str, admin_password: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            admin_email: admin_email.to_string(),
+            admin_password: admin_password.to_string(),
+            client: reqwest::Client::new(),
+            admin_token: Mutex::new(None),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Create the collection described by `schema` unless one with its name exists
+    ///
+    /// Returns whether it had to be created.
+    pub async fn ensure_collection(&self, schema: &Value) -> Result<bool, PbError> {
+        let name = schema
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| PbError::Request("Collection schema has no name".to_string()))?;
+        match self.send(Method::GET, &format!("/api/collections/{}", name), |request| request).await {
+            Ok(_) => Ok(false),
+            Err(PbError::Status { status: 404, .. }) => {
+                self.send(Method::POST, "/api/collections", |request| request.json(schema)).await?;
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The first `per_page` records of `collection` matching `filter`, in
+    /// the order of the PocketBase `sort` expression
+    pub async fn list(&self, collection: &str, filter: &str, sort: &str, per_page: u32) -> Result<Vec<Value>, PbError> {
+        let path = format!("/api/collections/{}/records", collection);
+        let query = [
+            ("filter", filter.to_string()),
+            ("sort", sort.to_string()),
+            ("perPage", per_page.to_string()),
+            ("skipTotal", "1".to_string()),
+        ];
+        let body = self.send(Method::GET, &path, |request| request.query(&query)).await?;
+        Ok(items(&body))
+    }
+
+    /// Every record in `collection` matching the PocketBase `filter`
+    pub async fn list_records(&self, collection: &str, filter: Option<&str>) -> Result<Vec<Value>, PbError> {
+        self.list_all(&format!("/api/collections/{}/records", collection), filter).await
+    }
+
+    /// Page `page` (from 1) of the records in `collection` matching `filter`,
+    /// ordered by the PocketBase `sort` expression, with how many match in all
+    pub async fn list_page(
+        &self,
+        collection: &str,
+        filter: Option<&str>,
+        sort: &str,
+        page: u32,
+        per_page: u32,
+    ) -> Result<RecordPage, PbError> {
+        let path = format!("/api/collections/{}/records", collection);
+        let mut query = vec![
+            ("page", page.to_string()),
+            ("perPage", per_page.to_string()),
+            ("sort", sort.to_string()),
+        ];
+        if let Some(filter) = filter {
+            query.push(("filter", filter.to_string()));
+        }
+
+        let body = self.send(Method::GET, &path, |request| request.query(&query)).await?;
+        Ok(RecordPage {
+            items: items(&body),
+            page,
+            per_page,
+            total_items: body.get("totalItems").and_then(Value::as_u64).unwrap_or_default(),
+        })
+    }
+
+    pub async fn get_record(&self, collection: &str, id: &str) -> Result<Value, PbError> {
+        let path = format!("/api/collections/{}/records/{}", collection, id);
+        self.send(Method::GET, &path, |request| request).await
+    }
+
+    pub async fn create_record(&self, collection: &str, record: &Value) -> Result<Value, PbError> {
+        let path = format!("/api/collections/{}/records", collection);
+        self.send(Method::POST, &path, |request| request.json(record)).await
+    }
+
+    pub async fn update_record(&self, collection: &str, id: &str, record: &Value) -> Result<Value, PbError> {
+        let path = format!("/api/collections/{}/records/{}", collection, id);
+        self.send(Method::PATCH, &path, |request| request.json(record)).await
+    }
+
+    pub async fn delete_record(&self, collection: &str, id: &str) -> Result<(), PbError> {
+        let path = format!("/api/collections/{}/records/{}", collection, id);
+        self.send(Method::DELETE, &path, |request| request).await.map(drop)
+    }
+
+    /// Email addresses of every admin account
+    pub async fn list_admin_emails(&self) -> Result<Vec<String>, PbError> {
+        Ok(self
+            .list_all("/api/admins", None)
+            .await?
+            .iter()
+            .filter_map(|admin| admin.get("email").and_then(Value::as_str).map(str::to_string))
+            .collect())
+    }
+
+    /// Create this client's admin account in a PocketBase that has none yet
+    ///
+    /// PocketBase only takes this without an admin token while it has no
+    /// admins, which is the case for a fresh instance.
+    pub async fn create_first_admin(&self) -> Result<(), PbError> {
+        let response = self
+            .client
+            .post(format!("{}/api/admins", self.url))
+            .json(&json!({
+                "email": self.admin_email,
+                "password": self.admin_password,
+                "passwordConfirm": self.admin_password
+            }))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(PbError::Status {
+                status: status.as_u16(),
+                body: response.json().await.unwrap_or(Value::Null),
+            });
+        }
+        Ok(())
+    }
+
+    /// Refresh the admin session, proving PocketBase is up and accepts it
+    pub async fn ping(&self) -> Result<(), PbError> {
+        self.send(Method::POST, "/api/admins/auth-refresh", |request| request)
+            .await
+            .map(drop)
+    }
+
+    /// Every item of the paginated listing at `path`
+    async fn list_all(&self, path: &str, filter: Option<&str>) -> Result<Vec<Value>, PbError> {
+        let mut records = Vec::new();
+        let mut page = 1u32;
+        loop {
+            let mut query = vec![
+                ("page", page.to_string()),
+                ("perPage", PAGE_SIZE.to_string()),
+                ("skipTotal", "1".to_string()),
+            ];
+            if let Some(filter) = filter {
+                query.push(("filter", filter.to_string()));
+            }
+
+            let items = items(&self.send(Method::GET, path, |request| request.query(&query)).await?);
+            let done = items.len() < PAGE_SIZE as usize;
+            records.extend(items);
+            if done {
+                return Ok(records);
+            }
+            page += 1;
+        }
+    }
+
+    /// Send an admin-authenticated request, re-authenticating once on 401
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Value, PbError> {
+        let url = format!("{}{}", self.url, path);
+        for attempt in 0..2 {
+            let token = self.admin_token(attempt > 0).await?;
+            let response = build(self.client.request(method.clone(), &url).header("Authorization", token))
+                .send()
+                .await?;
+
+            let status = response.status();
+            if status == StatusCode::UNAUTHORIZED && attempt == 0 {
+                continue;
+            }
+            if !status.is_success() {
+                return Err(PbError::Status {
+                    status: status.as_u16(),
+                    body: response.json().await.unwrap_or(Value::Null),
+                });
+            }
+            if status == StatusCode::NO_CONTENT {
+                return Ok(Value::Null);
+            }
+            return Ok(response.json().await.unwrap_or(Value::Null));
+        }
+        unreachable!("the second attempt always returns")
+    }
+
+    async fn admin_token(&self, refresh: bool) -> Result<String, PbError> {
+        let mut cached = self.admin_token.lock().await;
+        if let (Some(token), false) = (cached.as_ref(), refresh) {
+            return Ok(token.clone());
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/api/admins/auth-with-password", self.url))
+            .json(&json!({
+                "identity": self.admin_email,
+                "password": self.admin_password
+            }))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(PbError::Status {
+                status: status.as_u16(),
+                body: response.json().await.unwrap_or(Value::Null),
+            });
+        }
+
+        let body: Value = response.json().await?;
+        let token = body
+            .get("token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| PbError::Request("Admin auth response had no token".to_string()))?
+            .to_string();
+        *cached = Some(token.clone());
+        Ok(token)
+    }
+}
+
+/// The `items` of a listing response
+fn items(body: &Value) -> Vec<Value> {
+    body.get("items").and_then(Value::as_array).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_escapes_filter_strings() {
+        assert_eq!(quote("a@example.com"), "'a@example.com'");
+        assert_eq!(quote("o'brien\\"), "'o\\'brien\\\\'");
+    }
+
+    #[test]
+    fn test_unique_violations_are_recognised() {
+        let duplicate = PbError::Status {
+            status: 400,
+            body: json!({
+                "code": 400,
+                "message": "Failed to create record.",
+                "data": { "item": { "code": "validation_not_unique", "message": "Value must be unique." } }
+            }),
+        };
+        assert!(duplicate.is_not_unique());
+
+        let invalid = PbError::Status {
+            status: 400,
+            body: json!({ "data": { "item": { "code": "validation_required" } } }),
+        };
+        assert!(!invalid.is_not_unique());
+        assert!(!PbError::Request("connection refused".to_string()).is_not_unique());
+    }
+
+    #[test]
+    fn test_both_date_formats_are_read() {
+        let expected = DateTime::parse_from_rfc3339("2026-05-01T09:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse_time("2026-05-01 09:30:00.000Z"), Some(expected));
+        assert_eq!(parse_time("2026-05-01T09:30:00Z"), Some(expected));
+        assert_eq!(parse_time(""), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Moving meetings within the queue
+//!
+//! A reorder names the queue it was made against by [`queue_version`], which
+//! the backend and the frontend both compute from the order of the queued
+//! ids; a reorder made against a queue that has since changed is refused
+//! rather than applied to the wrong one.
+
+use uuid::Uuid;
+
+/// The version of a queue holding `ids` in this order
+///
+/// FNV-1a over the ids, so every process computes the same one.
+pub fn queue_version<'a>(ids: impl IntoIterator<Item = &'a Uuid>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for id in ids {
+        for byte in id.as_bytes() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// Move the item at `from` to `to`, shifting only the `movable` items
+/// between them
+///
+/// Items that aren't movable keep their places, so a user moving one of
+/// their own meetings reorders only their own. Returns false, leaving
+/// `items` as they were, unless both `from` and `to` are in range and hold
+/// movable items.
+pub fn move_within<T>(items: &mut [T], from: usize, to: usize, movable: impl Fn(&T) -> bool) -> bool {
+    let slots: Vec<usize> = (0..items.len()).filter(|&index| movable(&items[index])).collect();
+    let (Some(start), Some(end)) = (
+        slots.iter().position(|&slot| slot == from),
+        slots.iter().position(|&slot| slot == to),
+    ) else {
+        return false;
+    };
+    if start < end {
+        for step in start..end {
+            items.swap(slots[step], slots[step + 1]);
+        }
+    } else {
+        for step in (end..start).rev() {
+            items.swap(slots[step], slots[step + 1]);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_version_follows_the_order() {
+        let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        assert_eq!(queue_version([&a, &b]), queue_version(vec![&a, &b]));
+        assert_ne!(queue_version([&a, &b]), queue_version([&b, &a]));
+        assert_ne!(queue_version([&a]), queue_version([&a, &b]));
+        assert_eq!(queue_version([&a, &b]).len(), 16);
+    }
+
+    #[test]
+    fn test_items_move_past_their_neighbours() {
+        let mut items = ['a', 'b', 'c', 'd'];
+        assert!(move_within(&mut items, 0, 2, |_| true));
+        assert_eq!(items, ['b', 'c', 'a', 'd']);
+        assert!(move_within(&mut items, 3, 0, |_| true));
+        assert_eq!(items, ['d', 'b', 'c', 'a']);
+        assert!(move_within(&mut items, 1, 1, |_| true));
+        assert_eq!(items, ['d', 'b', 'c', 'a']);
+        assert!(!move_within(&mut items, 1, 4, |_| true));
+    }
+
+    #[test]
+    fn test_only_movable_items_change_places() {
+        // Upper case items are someone else's
+        let mut items = ['a', 'X', 'b', 'Y', 'c'];
+        let mine = |item: &char| item.is_lowercase();
+        assert!(move_within(&mut items, 4, 0, mine));
+        assert_eq!(items, ['c', 'X', 'a', 'Y', 'b']);
+
+        assert!(!move_within(&mut items, 0, 1, mine), "onto someone else's");
+        assert!(!move_within(&mut items, 3, 4, mine), "someone else's");
+        assert_eq!(items, ['c', 'X', 'a', 'Y', 'b']);
+    }
+}
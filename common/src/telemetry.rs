@@ -0,0 +1,408 @@
+//! Error reporting shared by the backend, worker and smtp-service
+//!
+//! Panics and server-side failures are sent to an [`ErrorReporter`] as well
+//! as logged, so they reach someone instead of scrolling past in container
+//! logs. With `SENTRY_DSN` set, and the `sentry` feature on, events go to
+//! Sentry or anything speaking its store API, such as GlitchTip; without a
+//! DSN the reporter does nothing. Events carry a message and tags only:
+//! reporters never see request or response bodies.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+/// How bad a reported event is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// A request or task failed
+    Error,
+    /// The code panicked
+    Fatal,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Fatal => "fatal",
+        }
+    }
+}
+
+/// One failure to report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorEvent {
+    pub severity: Severity,
+    pub message: String,
+    /// Short values to search and group by, such as `route` or `user_id`
+    pub tags: BTreeMap<&'static str, String>,
+}
+
+impl ErrorEvent {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    pub fn tag(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.tags.insert(name, value.into());
+        self
+    }
+}
+
+/// Somewhere failures are sent
+///
+/// `report` is called on request paths and from the panic hook, so it must
+/// return at once; sending belongs in the background.
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, event: ErrorEvent);
+}
+
+/// Drops every event, for when no DSN is configured
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopReporter;
+
+impl ErrorReporter for NoopReporter {
+    fn report(&self, _event: ErrorEvent) {}
+}
+
+/// The reporter for `dsn`, or a [`NoopReporter`] when there is none
+///
+/// A DSN that can't be parsed, or one set in a build without the `sentry`
+/// feature, is warned about and reporting stays off rather than stopping the
+/// service. With a DSN this must be called inside a Tokio runtime.
+pub fn reporter(dsn: Option<&str>, service: &'static str) -> Arc<dyn ErrorReporter> {
+    let Some(dsn) = dsn.map(str::trim).filter(|dsn| !dsn.is_empty()) else {
+        return Arc::new(NoopReporter);
+    };
+
+    #[cfg(feature = "sentry")]
+    match sentry::SentryReporter::new(dsn, service) {
+        Ok(reporter) => {
+            tracing::info!("Reporting errors to {}", reporter.host());
+            return Arc::new(reporter);
+        }
+        Err(e) => tracing::warn!("Error reporting is off: {}", e),
+    }
+
+    #[cfg(not(feature = "sentry"))]
+    {
+        let _ = (dsn, service);
+        tracing::warn!(
+            "SENTRY_DSN is set but this build has no Sentry support; error reporting is off"
+        );
+    }
+
+    Arc::new(NoopReporter)
+}
+
+/// Report every panic to `reporter` before the previous hook prints it
+pub fn install_panic_hook(reporter: Arc<dyn ErrorReporter>) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        reporter.report(panic_event(info));
+        previous(info);
+    }));
+}
+
+fn panic_event(info: &std::panic::PanicHookInfo<'_>) -> ErrorEvent {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let mut event = ErrorEvent::new(Severity::Fatal, format!("panicked: {}", message));
+    if let Some(location) = info.location() {
+        event = event.tag(
+            "location",
+            format!("{}:{}", location.file(), location.line()),
+        );
+    }
+    if let Some(thread) = std::thread::current().name() {
+        event = event.tag("thread", thread);
+    }
+    event
+}
+
+#[cfg(feature = "sentry")]
+pub mod sentry {
+    //! Events sent to Sentry's store endpoint from a background task
+
+    use super::{ErrorEvent, ErrorReporter};
+    use chrono::Utc;
+    use reqwest::Url;
+    use serde_json::{json, Map, Value};
+    use tokio::sync::mpsc;
+
+    /// Events waiting to be sent beyond this are dropped, not queued
+    const BACKLOG: usize = 256;
+
+    /// Where and as whom events are sent, parsed from a DSN
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Dsn {
+        pub public_key: String,
+        /// `https://host[:port][/prefix]/api/<project>/store/`
+        pub store_url: String,
+    }
+
+    impl Dsn {
+        /// `https://<key>@<host>[:port][/prefix]/<project>`, as Sentry shows it
+        pub fn parse(dsn: &str) -> Result<Self, String> {
+            let invalid = |why: &str| format!("SENTRY_DSN is not a valid DSN: {}", why);
+            let url = Url::parse(dsn).map_err(|e| invalid(&e.to_string()))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(invalid("it must be an http or https URL"));
+            }
+            if url.username().is_empty() {
+                return Err(invalid("it names no public key"));
+            }
+            let host = url.host_str().ok_or_else(|| invalid("it names no host"))?;
+            let path = url.path().trim_end_matches('/');
+            let (prefix, project) = path.rsplit_once('/').unwrap_or(("", path));
+            if project.is_empty() {
+                return Err(invalid("it names no project"));
+            }
+
+            let port = url
+                .port()
+                .map(|port| format!(":{}", port))
+                .unwrap_or_default();
+            Ok(Self {
+                public_key: url.username().to_string(),
+                store_url: format!(
+                    "{}://{}{}{}/api/{}/store/",
+                    url.scheme(),
+                    host,
+                    port,
+                    prefix,
+                    project
+                ),
+            })
+        }
+
+        /// The `X-Sentry-Auth` header value
+        pub fn auth_header(&self) -> String {
+            format!(
+                "Sentry sentry_version=7, sentry_client=fathom-loom/{}, sentry_key={}",
+                env!("CARGO_PKG_VERSION"),
+                self.public_key
+            )
+        }
+    }
+
+    /// The store API's JSON for `event`
+    pub fn payload(event: &ErrorEvent, service: &str) -> Value {
+        let mut tags: Map<String, Value> = event
+            .tags
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::from(value.as_str())))
+            .collect();
+        tags.insert("service".to_string(), Value::from(service));
+        json!({
+            "event_id": uuid::Uuid::new_v4().simple().to_string(),
+            "timestamp": Utc::now().to_rfc3339(),
+            "platform": "other",
+            "level": event.severity.as_str(),
+            "logger": service,
+            "release": format!("fathom-loom@{}", env!("CARGO_PKG_VERSION")),
+            "message": { "formatted": event.message },
+            "tags": tags,
+        })
+    }
+
+    /// Sends events to the DSN's project, dropping them when it falls behind
+    pub struct SentryReporter {
+        dsn: Dsn,
+        events: mpsc::Sender<ErrorEvent>,
+    }
+
+    impl SentryReporter {
+        /// Start sending `service`'s events to `dsn`; needs a Tokio runtime
+        pub fn new(dsn: &str, service: &'static str) -> Result<Self, String> {
+            let dsn = Dsn::parse(dsn)?;
+            let (events, mut queued) = mpsc::channel::<ErrorEvent>(BACKLOG);
+            let client = reqwest::Client::new();
+            let target = dsn.clone();
+            tokio::spawn(async move {
+                while let Some(event) = queued.recv().await {
+                    let sent = client
+                        .post(&target.store_url)
+                        .header("X-Sentry-Auth", target.auth_header())
+                        .json(&payload(&event, service))
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status());
+                    // Reporting a failure to report would only loop
+                    if let Err(e) = sent {
+                        tracing::debug!("Could not send an error report: {}", e);
+                    }
+                }
+            });
+            Ok(Self { dsn, events })
+        }
+
+        /// The host events go to, for the startup log
+        pub fn host(&self) -> &str {
+            let rest = self
+                .dsn
+                .store_url
+                .split_once("://")
+                .map_or("", |(_, rest)| rest);
+            rest.split('/').next().unwrap_or_default()
+        }
+    }
+
+    impl ErrorReporter for SentryReporter {
+        fn report(&self, event: ErrorEvent) {
+            let _ = self.events.try_send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Captured(Mutex<Vec<ErrorEvent>>);
+
+    impl ErrorReporter for Captured {
+        fn report(&self, event: ErrorEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_panics_are_reported_with_their_location() {
+        let captured = Arc::new(Captured::default());
+        let previous = std::panic::take_hook();
+        // The previous hook would print the expected panic below
+        std::panic::set_hook(Box::new(|_| {}));
+        install_panic_hook(captured.clone());
+
+        let result = std::thread::Builder::new()
+            .name("reporting".to_string())
+            .spawn(|| panic!("boom {}", 42))
+            .unwrap()
+            .join();
+        std::panic::set_hook(previous);
+        assert!(result.is_err());
+
+        let events = captured.0.lock().unwrap();
+        let event = events
+            .iter()
+            .find(|event| event.tags.get("thread").map(String::as_str) == Some("reporting"))
+            .expect("the panic was reported");
+        assert_eq!(event.severity, Severity::Fatal);
+        assert_eq!(event.message, "panicked: boom 42");
+        assert!(event.tags["location"].starts_with("common/src/telemetry.rs:"));
+    }
+
+    #[test]
+    fn test_no_dsn_reports_nothing() {
+        // Would need a runtime if it tried to start a sender
+        for dsn in [None, Some(""), Some("  ")] {
+            reporter(dsn, "backend").report(ErrorEvent::new(Severity::Error, "ignored"));
+        }
+    }
+
+    #[cfg(feature = "sentry")]
+    mod sentry {
+        use super::super::sentry::*;
+        use super::super::*;
+        use std::time::Duration;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        #[test]
+        fn test_dsns_parse_to_their_store_endpoint() {
+            let dsn = Dsn::parse("https://abc123@o42.ingest.sentry.io/4501").unwrap();
+            assert_eq!(dsn.public_key, "abc123");
+            assert_eq!(
+                dsn.store_url,
+                "https://o42.ingest.sentry.io/api/4501/store/"
+            );
+            assert!(dsn.auth_header().contains("sentry_key=abc123"));
+
+            // Self-hosted, on a port and under a path
+            let dsn = Dsn::parse("http://key@glitchtip.local:8000/errors/7").unwrap();
+            assert_eq!(
+                dsn.store_url,
+                "http://glitchtip.local:8000/errors/api/7/store/"
+            );
+
+            for invalid in [
+                "not a url",
+                "https://o42.ingest.sentry.io/4501",
+                "https://key@host/",
+                "ftp://key@host/1",
+            ] {
+                assert!(Dsn::parse(invalid).is_err(), "{}", invalid);
+            }
+        }
+
+        #[tokio::test]
+        async fn test_events_reach_the_store_endpoint() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let dsn = format!("http://public@{}/3", listener.local_addr().unwrap());
+            let reporter = reporter(Some(&dsn), "worker");
+            reporter.report(
+                ErrorEvent::new(Severity::Error, "task failed")
+                    .tag("task_id", "t-1")
+                    .tag("user_id", "alice"),
+            );
+
+            let (mut socket, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+                .await
+                .unwrap()
+                .unwrap();
+            let mut received = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let (head, body) = loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                assert!(read > 0);
+                received.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&received).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break (head.to_string(), body.to_string());
+                    }
+                }
+            };
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            assert!(head.starts_with("POST /api/3/store/ HTTP/1.1"), "{}", head);
+            assert!(
+                head.to_ascii_lowercase()
+                    .contains("x-sentry-auth: sentry sentry_version=7"),
+                "{}",
+                head
+            );
+            let event: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(event["level"], "error");
+            assert_eq!(event["message"]["formatted"], "task failed");
+            assert_eq!(event["tags"]["service"], "worker");
+            assert_eq!(event["tags"]["task_id"], "t-1");
+            assert_eq!(event["tags"]["user_id"], "alice");
+            assert_eq!(event["event_id"].as_str().unwrap().len(), 32);
+        }
+    }
+}
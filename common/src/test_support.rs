@@ -0,0 +1,513 @@
+//! An in-memory PocketBase for the services' tests
+//!
+//! [`MockPb`] speaks just enough of PocketBase's admin and record API for
+//! the backend, worker and smtp-service: admin login and refresh, listing
+//! admins, listing records with filters of `=`, `!=`, `<`, `<=`, `>`, `>=`
+//! and `~` terms joined by `&&` or, within parentheses, by `||`, a
+//! comma-separated sort with `-` for descending fields and paging, fetching
+//! one record by id, and creating, patching and deleting records, which get
+//! `created` and `updated` times as PocketBase gives them; creating can be
+//! made to fail part way through. Unique indexes are declared per collection
+//! and enforced under one lock, with PocketBase's `validation_not_unique`
+//! error, leaving out records whose fields are blank as a `WHERE ... != ''`
+//! index does. Auth collections take sign-ups without an admin token and
+//! let users update their own record.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::pocketbase::PocketBase;
+
+/// The admin account [`MockPb`] signs in
+pub const ADMIN_EMAIL: &str = "admin@example.com";
+pub const ADMIN_PASSWORD: &str = "admin-password";
+
+const ADMIN_TOKEN: &str = "admin-token";
+
+/// Shortest password an auth collection takes, as PocketBase's default
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Serve `app` on an ephemeral port, returning its base URL
+pub async fn spawn_server(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+#[derive(Default)]
+struct Store {
+    collections: HashMap<String, Vec<Map<String, Value>>>,
+    /// Field sets whose values must be unique together, per collection
+    unique: HashMap<String, Vec<Vec<String>>>,
+    next_id: u64,
+    /// Creates left before every create fails, if limited
+    creates_left: Option<usize>,
+    /// Collections anyone may sign up to
+    auth: HashSet<String>,
+    /// Passwords auth records were last given by an update, by collection and id
+    passwords: HashMap<(String, String), String>,
+}
+
+/// A running mock PocketBase
+#[derive(Clone)]
+pub struct MockPb {
+    pub url: String,
+    store: Arc<Mutex<Store>>,
+}
+
+impl MockPb {
+    pub async fn start() -> Self {
+        Self::start_with(|_| Router::new()).await
+    }
+
+    /// [`Self::start`], also serving the routes `extend` makes over the
+    /// same records, for the parts of PocketBase only one service uses
+    pub async fn start_with(extend: impl FnOnce(&MockPb) -> Router) -> Self {
+        let mut mock = Self {
+            url: String::new(),
+            store: Arc::default(),
+        };
+        let app = Router::new()
+            .route("/api/admins", get(list_admins))
+            .route("/api/admins/auth-with-password", post(admin_auth))
+            .route("/api/admins/auth-refresh", post(admin_refresh))
+            .route("/api/collections/:collection/records", get(list).post(create))
+            .route(
+                "/api/collections/:collection/records/:id",
+                get(get_one).patch(update).delete(delete),
+            )
+            .with_state(mock.store.clone())
+            .merge(extend(&mock));
+        mock.url = spawn_server(app).await;
+        mock
+    }
+
+    /// An admin client for this PocketBase
+    pub fn client(&self) -> PocketBase {
+        PocketBase::new(&self.url, ADMIN_EMAIL, ADMIN_PASSWORD)
+    }
+
+    /// The token [`MockPb`] takes from the owner of the auth record `id`
+    pub fn user_token(id: &str) -> String {
+        format!("valid-{}", id)
+    }
+
+    /// Refuse records of `collection` repeating another's `fields`
+    pub fn unique(&self, collection: &str, fields: &[&str]) {
+        let mut store = self.store.lock().unwrap();
+        store
+            .unique
+            .entry(collection.to_string())
+            .or_default()
+            .push(fields.iter().map(|field| field.to_string()).collect());
+    }
+
+    /// Make `collection` an auth collection: anyone may create a record
+    /// with a unique email and a password of at least 8 characters, and
+    /// its owner may update it with their [`Self::user_token`], giving
+    /// `oldPassword` to change a password an update set before
+    ///
+    /// Passwords are kept on the record as given, so tests can see them.
+    pub fn auth_collection(&self, collection: &str) {
+        self.unique(collection, &["email"]);
+        self.store.lock().unwrap().auth.insert(collection.to_string());
+    }
+
+    /// The password an update last gave the record `id` of an auth collection
+    pub fn password(&self, collection: &str, id: &str) -> Option<String> {
+        let store = self.store.lock().unwrap();
+        store.passwords.get(&(collection.to_string(), id.to_string())).cloned()
+    }
+
+    /// Add `record` to `collection` as is, returning its id
+    pub fn insert(&self, collection: &str, record: Value) -> String {
+        let mut store = self.store.lock().unwrap();
+        let mut record = record.as_object().cloned().unwrap_or_default();
+        let id = store.new_id();
+        record.entry("id").or_insert(json!(id));
+        let id = record["id"].as_str().unwrap().to_string();
+        store.collections.entry(collection.to_string()).or_default().push(record);
+        id
+    }
+
+    /// Set `fields` on a record of `collection`, as another process writing
+    /// to PocketBase would
+    pub fn patch(&self, collection: &str, id: &str, fields: Value) {
+        let mut store = self.store.lock().unwrap();
+        let record = store
+            .collections
+            .get_mut(collection)
+            .and_then(|records| records.iter_mut().find(|record| record["id"] == id))
+            .expect("the record to patch exists");
+        record.extend(fields.as_object().cloned().unwrap_or_default());
+    }
+
+    /// Let `creates` more records be created, then fail every create with 500
+    pub fn fail_creates_after(&self, creates: usize) {
+        self.store.lock().unwrap().creates_left = Some(creates);
+    }
+
+    pub fn records(&self, collection: &str) -> Vec<Value> {
+        let store = self.store.lock().unwrap();
+        store
+            .collections
+            .get(collection)
+            .map(|records| records.iter().cloned().map(Value::Object).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn record(&self, collection: &str, id: &str) -> Option<Value> {
+        self.records(collection).into_iter().find(|record| record["id"] == id)
+    }
+}
+
+impl Store {
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("r{:014}", self.next_id)
+    }
+
+    /// The first field set `record` would repeat in `collection`
+    fn duplicate(&self, collection: &str, record: &Map<String, Value>, except: Option<&str>) -> Option<String> {
+        let existing = self.collections.get(collection)?;
+        let blank = |value: Option<&Value>| matches!(value, None | Some(Value::Null)) || value == Some(&json!(""));
+        self.unique.get(collection)?.iter().find_map(|fields| {
+            if fields.iter().all(|field| blank(record.get(field))) {
+                return None;
+            }
+            existing
+                .iter()
+                .filter(|other| except.is_none_or(|id| other["id"] != id))
+                .any(|other| fields.iter().all(|field| other.get(field) == record.get(field)))
+                .then(|| fields[0].clone())
+        })
+    }
+}
+
+/// Now as PocketBase writes it, with microseconds so records made one
+/// after another sort in that order
+fn now() -> Value {
+    json!(chrono::Utc::now().format("%Y-%m-%d %H:%M:%S%.6fZ").to_string())
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers.get("authorization").and_then(|value| value.to_str().ok())
+}
+
+fn authorized(headers: &HeaderMap) -> bool {
+    bearer(headers) == Some(ADMIN_TOKEN)
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "code": 404, "message": "The requested resource wasn't found.", "data": {} })),
+    )
+        .into_response()
+}
+
+/// PocketBase's 400 for `field` failing validation with `code`
+fn invalid(field: &str, code: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "code": 400,
+            "message": "Failed to create record.",
+            "data": { field: { "code": code, "message": "The value is invalid or already in use." } }
+        })),
+    )
+        .into_response()
+}
+
+/// Whether `record` matches a filter of terms joined by `&&`, each a
+/// comparison or a parenthesised group of comparisons joined by `||`
+fn matches(record: &Map<String, Value>, filter: &str) -> bool {
+    conjuncts(filter).into_iter().all(|term| match term.strip_prefix('(').and_then(|term| term.strip_suffix(')')) {
+        Some(group) => group.split("||").any(|term| compares(record, term.trim())),
+        None => compares(record, term),
+    })
+}
+
+/// `filter` split on the `&&`s outside parentheses and quotes
+fn conjuncts(filter: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let (mut depth, mut start, mut quoted, mut escaped) = (0, 0, false, false);
+    for (i, c) in filter.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            '&' if !quoted && depth == 0 && filter[i..].starts_with("&&") => {
+                terms.push(filter[start..i].trim());
+                start = i + 2;
+            }
+            _ => {}
+        }
+    }
+    terms.push(filter[start..].trim());
+    terms.into_iter().filter(|term| !term.is_empty()).collect()
+}
+
+/// Whether `record` passes one comparison of a field with a literal;
+/// numbers compare as numbers, `~` tests for a substring
+fn compares(record: &Map<String, Value>, term: &str) -> bool {
+    let (at, operator) = ["!=", "<=", ">=", "~", "=", "<", ">"]
+        .into_iter()
+        .filter_map(|operator| term.find(operator).map(|at| (at, operator)))
+        .min_by_key(|(at, operator)| (*at, Reverse(operator.len())))
+        .expect("filter terms compare a field");
+    let field = term[..at].trim();
+    let literal = term[at + operator.len()..].trim();
+    let quoted = literal.starts_with('\'') || literal.starts_with('"');
+    let value = literal
+        .trim_matches(|c| c == '\'' || c == '"')
+        .replace("\\'", "'")
+        .replace("\\\\", "\\");
+    let value = match (quoted, value.as_str()) {
+        (false, "null") => String::new(),
+        _ => value,
+    };
+    let actual = match record.get(field) {
+        Some(Value::Number(actual)) => {
+            let Ok(value) = value.parse::<f64>() else {
+                return operator == "!=";
+            };
+            return ordered(operator, actual.as_f64().unwrap_or_default().total_cmp(&value));
+        }
+        Some(Value::String(actual)) => actual.clone(),
+        Some(Value::Bool(actual)) => actual.to_string(),
+        None | Some(Value::Null) => String::new(),
+        Some(other) => other.to_string(),
+    };
+    match operator {
+        "~" => actual.to_lowercase().contains(&value.to_lowercase()),
+        _ => ordered(operator, actual.as_str().cmp(value.as_str())),
+    }
+}
+
+fn ordered(operator: &str, order: Ordering) -> bool {
+    match operator {
+        "!=" => order.is_ne(),
+        "<=" => order.is_le(),
+        ">=" => order.is_ge(),
+        "<" => order.is_lt(),
+        ">" => order.is_gt(),
+        _ => order.is_eq(),
+    }
+}
+
+async fn admin_auth(Json(body): Json<Value>) -> Response {
+    if body["password"] != ADMIN_PASSWORD {
+        return invalid("password", "validation_invalid_credentials");
+    }
+    Json(json!({ "token": ADMIN_TOKEN, "admin": { "email": body["identity"] } })).into_response()
+}
+
+async fn admin_refresh(headers: HeaderMap) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(json!({ "token": ADMIN_TOKEN, "admin": { "email": ADMIN_EMAIL } })).into_response()
+}
+
+async fn list_admins(headers: HeaderMap) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let admins = [json!({ "id": "a00000000000001", "email": ADMIN_EMAIL })];
+    Json(json!({ "page": 1, "perPage": 30, "totalItems": admins.len(), "items": admins })).into_response()
+}
+
+async fn list(
+    State(store): State<Arc<Mutex<Store>>>,
+    Path(collection): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let store = store.lock().unwrap();
+    let filter = query.get("filter").map(String::as_str).unwrap_or_default();
+    let mut items: Vec<Map<String, Value>> = store
+        .collections
+        .get(&collection)
+        .map(|records| records.iter().filter(|record| matches(record, filter)).cloned().collect())
+        .unwrap_or_default();
+    let sort: Vec<&str> = query
+        .get("sort")
+        .map(|sort| sort.split(',').filter(|field| !field.is_empty()).collect())
+        .unwrap_or_default();
+    items.sort_by(|a, b| {
+        sort.iter()
+            .map(|field| {
+                let (field, descending) = match field.strip_prefix('-') {
+                    Some(field) => (field, true),
+                    None => (*field, false),
+                };
+                let order = match (a.get(field).and_then(Value::as_f64), b.get(field).and_then(Value::as_f64)) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    _ => {
+                        let key = |record: &Map<String, Value>| record.get(field).map(Value::to_string).unwrap_or_default();
+                        key(a).cmp(&key(b))
+                    }
+                };
+                match descending {
+                    true => order.reverse(),
+                    false => order,
+                }
+            })
+            .find(|order| order.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    let number = |name: &str, default: usize| query.get(name).and_then(|value| value.parse().ok()).unwrap_or(default);
+    let (page, per_page) = (number("page", 1).max(1), number("perPage", 30));
+    let total_items = items.len();
+    let items: Vec<_> = items.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+    Json(json!({ "page": page, "perPage": per_page, "totalItems": total_items, "items": items })).into_response()
+}
+
+async fn get_one(
+    State(store): State<Arc<Mutex<Store>>>,
+    Path((collection, id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let store = store.lock().unwrap();
+    match store
+        .collections
+        .get(&collection)
+        .and_then(|records| records.iter().find(|record| record["id"] == id.as_str()))
+    {
+        Some(record) => Json(record).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn create(
+    State(store): State<Arc<Mutex<Store>>>,
+    Path(collection): Path<String>,
+    headers: HeaderMap,
+    Json(mut record): Json<Map<String, Value>>,
+) -> Response {
+    let mut store = store.lock().unwrap();
+    let auth = store.auth.contains(&collection);
+    if !auth && !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match &mut store.creates_left {
+        Some(0) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(left) => *left -= 1,
+        None => {}
+    }
+    if let Some(field) = store.duplicate(&collection, &record, None) {
+        return invalid(&field, "validation_not_unique");
+    }
+    let short = record.get("password").and_then(Value::as_str).is_some_and(|password| password.len() < MIN_PASSWORD_LEN);
+    if auth && short {
+        return invalid("password", "validation_length_out_of_range");
+    }
+    let id = store.new_id();
+    record.insert("id".to_string(), json!(id));
+    record.entry("created").or_insert_with(now);
+    record.insert("updated".to_string(), now());
+    store.collections.entry(collection).or_default().push(record.clone());
+    Json(record).into_response()
+}
+
+async fn update(
+    State(store): State<Arc<Mutex<Store>>>,
+    Path((collection, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(mut changes): Json<Map<String, Value>>,
+) -> Response {
+    let mut store = store.lock().unwrap();
+    let owner = store.auth.contains(&collection) && bearer(&headers) == Some(MockPb::user_token(&id).as_str());
+    if !owner && !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(mut record) = store
+        .collections
+        .get(&collection)
+        .and_then(|records| records.iter().find(|record| record["id"] == id.as_str()))
+        .cloned()
+    else {
+        return not_found();
+    };
+    let key = (collection.clone(), id.clone());
+    let old_password = changes.remove("oldPassword");
+    let password = match store.auth.contains(&collection) {
+        true => changes.get("password").and_then(Value::as_str).map(str::to_string),
+        false => None,
+    };
+    let current = store.passwords.get(&key).map(String::as_str);
+    if password.is_some() && owner && current.is_some() && old_password.as_ref().and_then(Value::as_str) != current {
+        return invalid("oldPassword", "validation_invalid_old_password");
+    }
+    record.extend(changes);
+    record.insert("updated".to_string(), now());
+    if let Some(field) = store.duplicate(&collection, &record, Some(&id)) {
+        return invalid(&field, "validation_not_unique");
+    }
+    if let Some(password) = password {
+        store.passwords.insert(key, password);
+    }
+    let records = store.collections.get_mut(&collection).unwrap();
+    let stored = records.iter_mut().find(|stored| stored["id"] == id.as_str()).unwrap();
+    *stored = record.clone();
+    Json(record).into_response()
+}
+
+async fn delete(
+    State(store): State<Arc<Mutex<Store>>>,
+    Path((collection, id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let mut store = store.lock().unwrap();
+    let Some(records) = store.collections.get_mut(&collection) else {
+        return not_found();
+    };
+    let before = records.len();
+    records.retain(|record| record["id"] != id.as_str());
+    if records.len() == before {
+        return not_found();
+    }
+    store.passwords.remove(&(collection, id));
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_compare_as_pocketbase_does() {
+        let record = json!({ "status": "pending", "attempts": 3, "verified": true, "note": "a && b" });
+        let record = record.as_object().unwrap();
+        assert!(matches(record, "status = 'pending' && attempts >= 3"));
+        assert!(matches(record, "(status = 'failed' || attempts < 4) && verified = true"));
+        assert!(matches(record, "note = 'a && b' && missing = ''"));
+        assert!(matches(record, "attempts != 'many'"));
+        assert!(!matches(record, "attempts > 3"));
+        assert!(!matches(record, "status != 'pending'"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Input validation rules shared between services
+
+/// Shortest password accepted anywhere in the app
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Every strength rule `password` breaks, as user-facing messages
+///
+/// Mirrors `is_strong_password` in the frontend, which can't depend on this
+/// crate from wasm, so the two must be kept in step.
+pub fn password_problems(password: &str) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        problems.push("Password must be at least 8 characters");
+    }
+    if !password.chars().any(|c| c.is_uppercase()) {
+        problems.push("Password must contain an uppercase letter");
+    }
+    if !password.chars().any(|c| c.is_lowercase()) {
+        problems.push("Password must contain a lowercase letter");
+    }
+    if !password.chars().any(|c| c.is_numeric()) {
+        problems.push("Password must contain a number");
+    }
+    problems
+}
+
+pub fn is_strong_password(password: &str) -> bool {
+    password_problems(password).is_empty()
+}
+
+/// Shortest and longest usernames accepted
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Every username rule `username` breaks, as user-facing messages
+///
+/// Usernames are ASCII letters, digits, `_`, `.` and `-`, starting with a
+/// letter or digit, which also keeps them valid PocketBase usernames.
+pub fn username_problems(username: &str) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    let length = username.chars().count();
+    if length < MIN_USERNAME_LENGTH {
+        problems.push("Username must be at least 3 characters");
+    }
+    if length > MAX_USERNAME_LENGTH {
+        problems.push("Username must be at most 32 characters");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        problems.push("Username may only contain letters, numbers, '_', '.' and '-'");
+    }
+    if username.chars().next().is_some_and(|c| !c.is_ascii_alphanumeric()) {
+        problems.push("Username must start with a letter or number");
+    }
+    problems
+}
+
+pub fn is_valid_username(username: &str) -> bool {
+    username_problems(username).is_empty()
+}
+
+/// Longest display name accepted
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+
+/// Every display name rule `name` breaks, as user-facing messages
+pub fn display_name_problems(name: &str) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    if name.trim().is_empty() {
+        problems.push("Display name cannot be blank");
+    }
+    if name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        problems.push("Display name must be at most 100 characters");
+    }
+    if name.chars().any(char::is_control) {
+        problems.push("Display name cannot contain control characters");
+    }
+    problems
+}
+
+/// Longest key id an API key may be stored under
+pub const MAX_KEY_ID_LENGTH: usize = 64;
+
+/// Why an API key can't be stored under `key_id` with `value`, if it can't
+///
+/// The value only has to be there; what each service accepts is checked by
+/// validating the key with the service itself.
+pub fn validate_api_key_format(key_id: &str, value: &str) -> Result<(), &'static str> {
+    if value.trim().is_empty() {
+        return Err("API key value is required");
+    }
+    let valid_key_id = (1..=MAX_KEY_ID_LENGTH).contains(&key_id.len())
+        && key_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-' | b'.'));
+    if !valid_key_id {
+        return Err("Key id must be 1-64 letters, digits, '.', '_' or '-'");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_strength_rules() {
+        assert!(is_strong_password("Sup3rSecret"));
+        assert_eq!(password_problems("Sh0rt"), vec!["Password must be at least 8 characters"]);
+        assert_eq!(password_problems("alllowercase1").len(), 1);
+        assert_eq!(password_problems("").len(), 4);
+    }
+
+    #[test]
+    fn test_username_rules() {
+        assert!(is_valid_username("dora_the-explorer.2"));
+        assert_eq!(username_problems("ab"), vec!["Username must be at least 3 characters"]);
+        assert_eq!(username_problems(&"a".repeat(33)), vec!["Username must be at most 32 characters"]);
+        assert_eq!(
+            username_problems("no spaces!"),
+            vec!["Username may only contain letters, numbers, '_', '.' and '-'"]
+        );
+        assert_eq!(username_problems("_hidden"), vec!["Username must start with a letter or number"]);
+        assert!(!is_valid_username("émile"));
+    }
+
+    #[test]
+    fn test_display_name_rules() {
+        assert!(display_name_problems("Ada Lovelace").is_empty());
+        assert_eq!(display_name_problems("  "), vec!["Display name cannot be blank"]);
+        assert_eq!(display_name_problems(&"x".repeat(101)).len(), 1);
+        assert_eq!(display_name_problems("tab\there"), vec!["Display name cannot contain control characters"]);
+    }
+
+    #[test]
+    fn test_api_key_format_rules() {
+        assert_eq!(validate_api_key_format("team-2.b_c", "sk_live_123"), Ok(()));
+        assert_eq!(validate_api_key_format("default", "   "), Err("API key value is required"));
+        for key_id in ["", "with space", "slash/id", &"k".repeat(65)] {
+            assert!(validate_api_key_format(key_id, "sk_live_123").is_err(), "{:?}", key_id);
+        }
+    }
+}
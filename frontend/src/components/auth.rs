@@ -1,8 +1,74 @@
 use dioxus::prelude::*;
+use dioxus_router::prelude::*;
+use crate::{Route, services::auth::AuthService};
 
+/// Where Login sends the user once they're in, set by the guard that sent
+/// them to log in
+#[derive(Clone, Copy)]
+pub struct ReturnTo(pub Signal<Option<Route>>);
+
+/// What a guard does with the page it wraps
+#[derive(Debug, Clone, PartialEq)]
+pub enum Guarded {
+    Show,
+    /// Show nothing and send the user to log in, then back to `return_to`
+    LogIn { return_to: Route },
+}
+
+pub fn guard(signed_in: bool, attempted: Route) -> Guarded {
+    if signed_in {
+        Guarded::Show
+    } else {
+        Guarded::LogIn { return_to: attempted }
+    }
+}
+
+/// Render `children` to signed-in users only
 #[component]
 pub fn ProtectedRoute(children: Element) -> Element {
+    let auth_service = use_context::<Signal<AuthService>>();
+    let ReturnTo(mut return_to) = use_context::<ReturnTo>();
+    let navigator = use_navigator();
+    let route = use_route::<Route>();
+    let decide = move |route: Route| guard(auth_service.read().is_authenticated(), route);
+
+    // Again whenever the session changes, so logging out leaves the page
+    let attempted = route.clone();
+    use_effect(move || {
+        if let Guarded::LogIn { return_to: attempted } = decide(attempted.clone()) {
+            return_to.set(Some(attempted));
+            navigator.push(Route::Login {});
+        }
+    });
+
+    match decide(route) {
+        Guarded::Show => rsx! { {children} },
+        Guarded::LogIn { .. } => None,
+    }
+}
+
+/// The routes only signed-in users reach
+#[component]
+pub fn SignedInPages() -> Element {
     rsx! {
-        {children}
+        ProtectedRoute { Outlet::<Route> {} }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_out_users_are_sent_to_log_in_and_back() {
+        assert_eq!(
+            guard(false, Route::Recordings { query: Default::default() }),
+            Guarded::LogIn { return_to: Route::Recordings { query: Default::default() } }
+        );
+    }
+
+    #[test]
+    fn test_signed_in_users_see_protected_pages() {
+        assert_eq!(guard(true, Route::Settings {}), Guarded::Show);
     }
 }
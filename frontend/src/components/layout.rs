@@ -1,15 +1,43 @@
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
 use crate::Route;
+use crate::config::get_config;
+use crate::components::toast::ToastContainer;
 use crate::services::auth::AuthService;
+use crate::services::connection::use_live_updates;
 
 #[component]
 pub fn Layout(children: Element) -> Element {
     let mut auth_service = use_context::<Signal<AuthService>>();
     let is_authenticated = auth_service.read().is_authenticated();
+    let features = get_config().map(|config| config.features.clone()).unwrap_or_default();
+    let live = use_live_updates();
     
     rsx! {
         div { class: "min-h-screen bg-gray-50",
+            if features.maintenance_mode {
+                div { class: "bg-yellow-100 border-b border-yellow-300 text-yellow-800 text-sm text-center px-4 py-2",
+                    "Maintenance in progress: the queue can't be changed right now. Please try again later."
+                }
+            }
+
+            if let Some(message) = live.connection.read().banner() {
+                div { class: "bg-amber-50 border-b border-amber-200 text-amber-800 text-sm px-4 py-2 flex items-center justify-center space-x-4",
+                    span { "{message}" }
+                    button {
+                        class: "font-medium underline hover:text-amber-900",
+                        onclick: move |_| live.retry_now(),
+                        "Retry now"
+                    }
+                    button {
+                        class: "text-amber-600 hover:text-amber-900",
+                        aria_label: "Dismiss",
+                        onclick: move |_| live.dismiss(),
+                        "×"
+                    }
+                }
+            }
+
             // Navigation bar
             nav { class: "bg-white shadow-sm border-b border-gray-200",
                 div { class: "max-w-7xl mx-auto px-4 sm:px-6 lg:px-8",
@@ -31,7 +59,7 @@ pub fn Layout(children: Element) -> Element {
                                     "Dashboard"
                                 }
                                 Link {
-                                    to: Route::Recordings {},
+                                    to: Route::Recordings { query: Default::default() },
                                     class: "text-gray-700 hover:text-indigo-600 px-3 py-2 rounded-md text-sm font-medium transition-colors",
                                     "Recordings"
                                 }
@@ -55,10 +83,12 @@ pub fn Layout(children: Element) -> Element {
                                     class: "text-gray-700 hover:text-indigo-600 px-3 py-2 rounded-md text-sm font-medium transition-colors",
                                     "Login"
                                 }
-                                Link {
-                                    to: Route::Register {},
-                                    class: "bg-indigo-600 hover:bg-indigo-700 text-white px-4 py-2 rounded-md text-sm font-medium transition-colors",
-                                    "Register"
+                                if features.registration_enabled {
+                                    Link {
+                                        to: Route::Register {},
+                                        class: "bg-indigo-600 hover:bg-indigo-700 text-white px-4 py-2 rounded-md text-sm font-medium transition-colors",
+                                        "Register"
+                                    }
                                 }
                             }
                         }
@@ -70,6 +100,8 @@ pub fn Layout(children: Element) -> Element {
             main { class: "max-w-7xl mx-auto py-6 px-4 sm:px-6 lg:px-8",
                 {children}
             }
+
+            ToastContainer {}
         }
     }
 }
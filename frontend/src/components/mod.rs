@@ -2,3 +2,4 @@ pub mod layout;
 pub mod auth;
 pub mod dashboard;
 pub mod common;
+pub mod toast;
@@ -0,0 +1,53 @@
+use dioxus::prelude::*;
+use crate::services::toast::{use_toast, ToastLevel};
+
+/// How often shown toasts are checked for expiry, in milliseconds
+const TICK_MS: u32 = 250;
+
+fn level_class(level: ToastLevel) -> &'static str {
+    match level {
+        ToastLevel::Success => "bg-green-50 border-green-200 text-green-700",
+        ToastLevel::Info => "bg-blue-50 border-blue-200 text-blue-700",
+        ToastLevel::Warning => "bg-yellow-50 border-yellow-200 text-yellow-800",
+        ToastLevel::Error => "bg-red-50 border-red-200 text-red-700",
+    }
+}
+
+/// The toasts raised through `use_toast`, stacked in a corner
+#[component]
+pub fn ToastContainer() -> Element {
+    let toasts = use_toast();
+    let mut queue = toasts.queue();
+
+    use_future(move || async move {
+        loop {
+            gloo_timers::future::TimeoutFuture::new(TICK_MS).await;
+            if queue.peek().is_empty() {
+                continue;
+            }
+            let mut next = queue.peek().clone();
+            if next.tick(js_sys::Date::now() as u64) {
+                queue.set(next);
+            }
+        }
+    });
+
+    rsx! {
+        div { class: "fixed bottom-4 right-4 z-50 flex flex-col space-y-2 w-80",
+            for toast in queue.read().visible().iter().cloned() {
+                div {
+                    key: "{toast.id}",
+                    class: "border px-4 py-3 rounded-lg shadow flex justify-between items-start {level_class(toast.level)}",
+                    role: if toast.level == ToastLevel::Error { "alert" } else { "status" },
+                    span { class: "text-sm", "{toast.message}" }
+                    button {
+                        class: "ml-3 text-sm opacity-60 hover:opacity-100",
+                        aria_label: "Close",
+                        onclick: move |_| toasts.dismiss(toast.id),
+                        "×"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,24 +2,49 @@ use serde::{Deserialize, Serialize};
 use std::sync::OnceLock;
 use gloo_net::http::Request;
 
+/// What the backend's `/api/v1/env` tells the browser
+///
+/// Fields added since the first release default, so older backends still
+/// parse, and fields they sent that this no longer uses are ignored.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub api: ApiConfig,
-    pub database: DatabaseConfig,
-    pub logging: LoggingConfig,
-    pub cors: CorsConfig,
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+    #[serde(default)]
     pub features: FeaturesConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiConfig {
     pub base_url: String,
+    #[serde(default)]
     pub version: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DatabaseConfig {
+pub struct WebSocketConfig {
+    /// Empty from backends that don't say, see [`AppConfig::websocket_url`]
+    #[serde(default)]
     pub url: String,
+    /// Reconnection attempts after the connection drops, before giving up
+    #[serde(default = "reconnect_attempts")]
+    pub max_reconnect_attempts: u32,
+}
+
+fn reconnect_attempts() -> u32 {
+    5
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_reconnect_attempts: reconnect_attempts(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,15 +52,55 @@ pub struct LoggingConfig {
     pub level: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CorsConfig {
-    pub origins: Vec<String>,
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeaturesConfig {
+    #[serde(default = "enabled")]
     pub auth_enabled: bool,
+    #[serde(default = "enabled")]
     pub encryption_enabled: bool,
+    #[serde(default)]
+    pub openapi_enabled: bool,
+    #[serde(default = "enabled")]
+    pub registration_enabled: bool,
+    /// Sign-in providers offered; Google unless the backend says otherwise
+    #[serde(default = "google")]
+    pub oauth_providers: Vec<String>,
+    /// Queue changes are refused while this is set
+    #[serde(default)]
+    pub maintenance_mode: bool,
+    /// Meetings each user may have queued at once; 0 for no limit
+    #[serde(default)]
+    pub max_queue_items_per_user: usize,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn google() -> Vec<String> {
+    vec!["google".to_string()]
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            auth_enabled: true,
+            encryption_enabled: true,
+            openapi_enabled: false,
+            registration_enabled: true,
+            oauth_providers: google(),
+            maintenance_mode: false,
+            max_queue_items_per_user: 0,
+        }
+    }
 }
 
 impl Default for AppConfig {
@@ -45,23 +110,25 @@ impl Default for AppConfig {
                 base_url: get_api_base_url(),
                 version: "0.1.0".to_string(),
             },
-            database: DatabaseConfig {
-                url: "http://pb_global:8090".to_string(),
-            },
-            logging: LoggingConfig {
-                level: "info".to_string(),
-            },
-            cors: CorsConfig {
-                origins: vec![
-                    "http://localhost:8080".to_string(),
-                    "http://localhost:3000".to_string(),
-                ],
-            },
-            features: FeaturesConfig {
-                auth_enabled: true,
-                encryption_enabled: true,
-            },
+            websocket: WebSocketConfig::default(),
+            features: FeaturesConfig::default(),
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Where queue updates are pushed, next to the API unless the backend says
+    pub fn websocket_url(&self) -> String {
+        if !self.websocket.url.is_empty() {
+            return self.websocket.url.clone();
         }
+        let base = self
+            .api
+            .base_url
+            .replace("http://", "ws://")
+            .replace("https://", "wss://");
+        format!("{}/queue_updates", base)
     }
 }
 
@@ -91,7 +158,7 @@ pub fn get_config() -> Option<&'static AppConfig> {
 
 async fn fetch_config_from_backend() -> Result<AppConfig, Box<dyn std::error::Error>> {
     let api_base = get_api_base_url();
-    let url = format!("{}/api/env", api_base);
+    let url = format!("{}{}/env", api_base, common::API_PREFIX);
     
     let response = Request::get(&url)
         .send()
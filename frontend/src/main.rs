@@ -8,9 +8,9 @@ use dioxus::prelude::*;
 use dioxus_router::prelude::*;
 use tracing::info;
 use config::{load_config, BuildConfig};
-use components::{layout::Layout, auth::ProtectedRoute, common::{LoadingSpinner, ErrorMessage, SuccessMessage}};
+use components::auth::{ReturnTo, SignedInPages};
 use pages::*;
-use services::{auth::AuthService, websocket::WebSocketService};
+use services::{api::MeetingFilters, auth::{AuthService, SessionCheck}, connection::{Connection, LiveUpdates}, toast::{ToastQueue, ToastService}};
 
 // App routes
 #[derive(Clone, Routable, Debug, PartialEq)]
@@ -21,12 +21,13 @@ enum Route {
     Login {},
     #[route("/register")]
     Register {},
-    #[route("/dashboard")]
-    Dashboard {},
-    #[route("/recordings")]
-    Recordings {},
-    #[route("/settings")]
-    Settings {},
+    #[layout(SignedInPages)]
+        #[route("/dashboard")]
+        Dashboard {},
+        #[route("/recordings?:..query")]
+        Recordings { query: MeetingFilters },
+        #[route("/settings")]
+        Settings {},
 }
 
 #[component]
@@ -36,7 +37,7 @@ fn RouteComponent() -> Element {
         Route::Login {} => rsx! { Login {} },
         Route::Register {} => rsx! { Register {} },
         Route::Dashboard {} => rsx! { Dashboard {} },
-        Route::Recordings {} => rsx! { Recordings {} },
+        Route::Recordings { query } => rsx! { Recordings { query } },
         Route::Settings {} => rsx! { Settings {} },
     }
 }
@@ -66,13 +67,49 @@ fn App() -> Element {
     });
 
     // Initialize auth service
-    let auth_service = use_signal(|| AuthService::new());
+    let mut auth_service = use_signal(AuthService::new);
+    let return_to = use_signal(|| Option::<Route>::None);
+    let toasts = use_signal(ToastQueue::default);
+    let connection = use_signal(Connection::default);
+    let retry = use_signal(|| None);
+    // Until the stored session is checked no page renders, so an expired
+    // one never shows the Dashboard
+    let mut restoring = use_signal(|| true);
+
+    use_effect(move || {
+        if !matches!(*config_future.read(), Some(Ok(_))) {
+            return;
+        }
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut auth = auth_service.peek().clone();
+            let check = auth.validate_session().await;
+            auth_service.set(auth);
+            if check == SessionCheck::Unreachable {
+                ToastService::new(toasts).warning("Couldn't reach the server to restore your session, so you're signed out for now");
+            }
+            restoring.set(false);
+        });
+    });
 
     match &*config_future.read_unchecked() {
         Some(Ok(_config)) => {
             // Provide auth service context and router
             use_context_provider(|| auth_service);
-            
+            use_context_provider(|| ReturnTo(return_to));
+            use_context_provider(|| ToastService::new(toasts));
+            use_context_provider(|| LiveUpdates::new(connection, retry));
+
+            if *restoring.read() {
+                return rsx! {
+                    div { class: "min-h-screen flex items-center justify-center bg-gray-50",
+                        div { class: "text-center",
+                            div { class: "animate-spin rounded-full h-12 w-12 border-b-2 border-indigo-600 mx-auto mb-4" }
+                            p { class: "text-gray-600", "Restoring your session..." }
+                        }
+                    }
+                };
+            }
+
             rsx! {
                 div { class: "min-h-screen bg-gray-50",
                     Router::<Route> {}
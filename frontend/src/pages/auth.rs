@@ -1,8 +1,8 @@
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
 use validator::Validate;
-use crate::{Route, components::layout::Layout};
-use crate::services::auth::{AuthService, LoginRequest, RegisterRequest};
+use crate::{Route, components::{auth::ReturnTo, layout::Layout}};
+use crate::services::{api::SESSION_EXPIRED, auth::{AuthService, LoginRequest, RegisterRequest}};
 
 #[derive(Debug, Clone, Validate)]
 struct LoginForm {
@@ -27,6 +27,7 @@ struct RegisterForm {
 #[component]
 pub fn Login() -> Element {
     let mut auth_service = use_context::<Signal<AuthService>>();
+    let ReturnTo(mut return_to) = use_context::<ReturnTo>();
     let navigator = use_navigator();
     
     let mut form_data = use_signal(|| LoginForm {
@@ -34,12 +35,21 @@ pub fn Login() -> Element {
         password: String::new(),
     });
     
-    let mut error_message = use_signal(|| Option::<String>::None);
+    // Sent here because the session ran out rather than by logging out
+    let mut error_message = use_signal(|| {
+        auth_service.peek().session_expired().then(|| SESSION_EXPIRED.to_string())
+    });
     let mut is_loading = use_signal(|| false);
 
+    // Back to the page that asked for a login, or the dashboard
+    let mut go_on = move || {
+        let next = return_to.take().unwrap_or(Route::Dashboard {});
+        navigator.push(next);
+    };
+
     // Redirect if already authenticated
     if auth_service.read().is_authenticated() {
-        navigator.push(Route::Dashboard {});
+        go_on();
     }
 
     let handle_submit = move |_evt: FormEvent| {
@@ -66,9 +76,7 @@ pub fn Login() -> Element {
 
         wasm_bindgen_futures::spawn_local(async move {
             match auth_service.write().login(login_request).await {
-                Ok(()) => {
-                    navigator.push(Route::Dashboard {});
-                }
+                Ok(()) => go_on(),
                 Err(e) => {
                     error_message.set(Some(format!("Login failed: {}", e)));
                     is_loading.set(false);
@@ -192,9 +200,12 @@ pub fn Register() -> Element {
 
         wasm_bindgen_futures::spawn_local(async move {
             match auth_service.write().register(register_request).await {
-                Ok(()) => {
+                Ok(true) => {
                     navigator.push(Route::Dashboard {});
                 }
+                Ok(false) => {
+                    navigator.push(Route::Login {});
+                }
                 Err(e) => {
                     error_message.set(Some(format!("Registration failed: {}", e)));
                     is_loading.set(false);
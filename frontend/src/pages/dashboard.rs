@@ -1,36 +1,97 @@
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
-use crate::{Route, components::layout::Layout};
+use futures::StreamExt;
+use crate::{Route, components::layout::Layout, config::get_config};
 use crate::services::{
     auth::AuthService,
-    api::{ApiService, Meeting},
-    websocket::{WebSocketService, WorkerStatus}
+    connection::{use_live_updates, Connection, ConnectionState},
+    api::{ApiError, ApiService, Meeting, QueueResponse, Reordered},
+    progress::{stage_label, track, Outcome, ProgressByTask},
+    toast::use_toast,
+    websocket::{WebSocketMessage, WebSocketService},
+    workers::WorkerBoard,
 };
+use crate::utils::format_duration_minutes;
+use common::queue_order::{move_within, queue_version};
+use uuid::Uuid;
+
+/// How often silent workers are checked for, in milliseconds
+const WORKER_TICK_MS: u32 = 1_000;
+
+/// Whether the viewer may drag `meeting`: admins any, others their own
+fn can_move(meeting: &Meeting, viewer: Option<&str>, admin: bool) -> bool {
+    admin || viewer == Some(meeting.user_id.as_str())
+}
+
+/// The queue with `dragged` dropped in `target`'s place, and the position
+/// to ask the backend for; `None` when the drop moves nothing or isn't allowed
+fn drop_onto(
+    queue: &[Meeting],
+    dragged: Uuid,
+    target: Uuid,
+    movable: impl Fn(&Meeting) -> bool,
+) -> Option<(Vec<Meeting>, usize)> {
+    if dragged == target {
+        return None;
+    }
+    let from = queue.iter().position(|meeting| meeting.id == dragged)?;
+    let to = queue.iter().position(|meeting| meeting.id == target)?;
+    let mut moved = queue.to_vec();
+    if !move_within(&mut moved, from, to, movable) {
+        return None;
+    }
+    for (i, meeting) in moved.iter_mut().enumerate() {
+        meeting.position = i + 1;
+    }
+    Some((moved, to + 1))
+}
+
+/// What the Dashboard shows once the backend answers a move
+#[derive(Debug)]
+enum Settled {
+    /// The queue as the backend has it now
+    Show(Vec<Meeting>),
+    /// Someone changed the queue first: load it again and say so
+    Refetch,
+    /// The move failed, so the queue goes back to how it was
+    Restore(Vec<Meeting>, String),
+}
+
+/// Reconcile the optimistic move with the backend's answer; `before` is the
+/// queue as it was shown before the move
+fn settle(before: Vec<Meeting>, answer: Result<Reordered, ApiError>) -> Settled {
+    match answer {
+        Ok(Reordered::Moved(QueueResponse { data: Some(queue), .. })) => Settled::Show(queue),
+        Ok(Reordered::Moved(_)) | Ok(Reordered::Conflict) => Settled::Refetch,
+        Err(e) => Settled::Restore(before, e.to_string()),
+    }
+}
 
 #[component]
 pub fn Dashboard() -> Element {
     let auth_service = use_context::<Signal<AuthService>>();
-    let navigator = use_navigator();
-    
-    // Redirect if not authenticated
-    if !auth_service.read().is_authenticated() {
-        navigator.push(Route::Login {});
-    }
 
     let mut queue_data = use_signal(|| Vec::<Meeting>::new());
-    let mut worker_status = use_signal(|| Vec::<WorkerStatus>::new());
+    let mut workers = use_signal(WorkerBoard::default);
+    // How far each task has got, by the worker's reports
+    let mut progress_by_task = use_signal(ProgressByTask::new);
+    // Now, as of the last check for silent workers and stale progress
+    let mut now = use_signal(|| js_sys::Date::now() as u64);
     let mut is_loading = use_signal(|| true);
     let mut error_message = use_signal(|| Option::<String>::None);
-    let mut ws_connected = use_signal(|| false);
+    let live = use_live_updates();
+    let toasts = use_toast();
+    // The meeting being dragged to another position
+    let mut dragging = use_signal(|| Option::<Uuid>::None);
 
     // Initialize API service
     let api_service = use_memo(move || {
-        ApiService::new(auth_service.read().clone())
+        ApiService::new(auth_service)
     });
 
     // Load queue data
     use_effect(move || {
-        let api = api_service.read().clone();
+        let api = api_service();
         wasm_bindgen_futures::spawn_local(async move {
             match api.get_queue().await {
                 Ok(response) => {
@@ -47,30 +108,91 @@ pub fn Dashboard() -> Element {
         });
     });
 
-    // Initialize WebSocket connection for real-time updates
-    use_effect(move || {
-        wasm_bindgen_futures::spawn_local(async move {
-            match WebSocketService::new() {
-                Ok(mut ws_service) => {
-                    match ws_service.connect().await {
-                        Ok(()) => {
-                            ws_connected.set(true);
-                            tracing::info!("WebSocket connected for dashboard updates");
+    // Grey out and then drop workers that have gone quiet
+    use_future(move || async move {
+        loop {
+            gloo_timers::future::TimeoutFuture::new(WORKER_TICK_MS).await;
+            let at = js_sys::Date::now() as u64;
+            now.set(at);
+            if workers.peek().is_empty() {
+                continue;
+            }
+            let mut next = workers.peek().clone();
+            if next.tick(at) {
+                workers.set(next);
+            }
+        }
+    });
+
+    // Live updates, reconnecting whenever the connection drops
+    use_future(move || async move {
+        let token = auth_service.peek().get_token().cloned();
+        let mut ws_service = match WebSocketService::new(token.as_deref()) {
+            Ok(ws_service) => ws_service,
+            Err(e) => {
+                tracing::error!("Failed to create WebSocket service: {}", e);
+                return;
+            }
+        };
+        let max_attempts = get_config().map_or(5, |config| config.websocket.max_reconnect_attempts);
+        let mut connection = live.connection;
+        connection.set(Connection::new(max_attempts));
+        let mut retry_now = live.retries();
+
+        let mut updates = ws_service.subscribe();
+        let mut connected = ws_service.reconnect_with_backoff(connection, &mut retry_now).await;
+        loop {
+            match connected {
+                Ok(()) => {
+                    if connection.write().connected() {
+                        // Queue changes may have been missed while away
+                        let api = api_service();
+                        match api.get_queue().await {
+                            Ok(QueueResponse { data: Some(queue), .. }) => queue_data.set(queue),
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!("Failed to refresh the queue after reconnecting: {}", e),
                         }
-                        Err(e) => {
-                            tracing::error!("Failed to connect WebSocket: {}", e);
+                    }
+                    tracing::info!("WebSocket connected for dashboard updates");
+                    while let Some(message) = updates.next().await {
+                        track(&mut progress_by_task.write(), &message, js_sys::Date::now() as u64);
+                        match message {
+                            // Relayed from the worker, these carry no queue
+                            WebSocketMessage::QueueUpdate(update) if update.task_id.is_some() => {}
+                            WebSocketMessage::QueueUpdate(update) => queue_data.set(update.queue),
+                            WebSocketMessage::WorkerStatus(status) => {
+                                workers.write().update(status, js_sys::Date::now() as u64)
+                            }
+                            WebSocketMessage::WorkerSnapshot(snapshot) => {
+                                workers.write().extend(snapshot.workers, js_sys::Date::now() as u64)
+                            }
+                            WebSocketMessage::TaskProgress(_) | WebSocketMessage::Ping | WebSocketMessage::Pong => {}
                         }
                     }
+                    connection.write().lost();
                 }
                 Err(e) => {
-                    tracing::error!("Failed to create WebSocket service: {}", e);
+                    tracing::error!("{}", e);
+                    // Offline until "Retry now" is pressed
+                    if retry_now.next().await.is_none() {
+                        return;
+                    }
+                    connection.write().retry();
                 }
             }
-        });
+            updates = ws_service.subscribe();
+            connected = ws_service.reconnect_with_backoff(connection, &mut retry_now).await;
+        }
+    });
+
+    // Other pages hold no connection to speak of
+    use_drop(move || {
+        let mut connection = live.connection;
+        connection.set(Connection::default());
     });
 
     let remove_from_queue = move |meeting_id: uuid::Uuid| {
-        let api = api_service.read().clone();
+        let api = api_service();
         wasm_bindgen_futures::spawn_local(async move {
             match api.remove_from_queue(meeting_id).await {
                 Ok(response) => {
@@ -79,12 +201,56 @@ pub fn Dashboard() -> Element {
                     }
                 }
                 Err(e) => {
-                    error_message.set(Some(format!("Failed to remove meeting: {}", e)));
+                    toasts.error(format!("Failed to remove meeting: {}", e));
+                }
+            }
+        });
+    };
+
+    let mut move_meeting = move |target: Uuid| {
+        let Some(dragged) = dragging.take() else {
+            return;
+        };
+        let (viewer, admin) = {
+            let auth = auth_service.read();
+            (auth.get_user().map(|user| user.id.clone()), auth.is_admin())
+        };
+        let before = queue_data();
+        let movable = |meeting: &Meeting| can_move(meeting, viewer.as_deref(), admin);
+        let Some((moved, position)) = drop_onto(&before, dragged, target, movable) else {
+            return;
+        };
+        let version = queue_version(before.iter().map(|meeting| &meeting.id));
+        queue_data.set(moved);
+
+        let api = api_service();
+        wasm_bindgen_futures::spawn_local(async move {
+            let answer = api.reorder_queue_item(dragged, position, &version).await;
+            match settle(before, answer) {
+                Settled::Show(queue) => queue_data.set(queue),
+                Settled::Restore(queue, e) => {
+                    queue_data.set(queue);
+                    toasts.error(format!("Failed to move meeting: {}", e));
+                }
+                Settled::Refetch => {
+                    toasts.warning("The queue changed, try again");
+                    match api.get_queue().await {
+                        Ok(QueueResponse { data: Some(queue), .. }) => queue_data.set(queue),
+                        Ok(_) => {}
+                        Err(e) => {
+                            toasts.error(format!("Failed to load queue: {}", e));
+                        }
+                    }
                 }
             }
         });
     };
 
+    let (viewer, admin) = {
+        let auth = auth_service.read();
+        (auth.get_user().map(|user| user.id.clone()), auth.is_admin())
+    };
+
     let calculate_global_position = |queue: &[Meeting], user_id: &str| -> usize {
         queue.iter()
             .position(|m| m.user_id == user_id)
@@ -103,11 +269,16 @@ pub fn Dashboard() -> Element {
                             p { class: "text-gray-600 mt-1", "Monitor your queue status and processing progress" }
                         }
                         div { class: "flex items-center space-x-2",
-                            if *ws_connected.read() {
+                            if live.connection.read().state == ConnectionState::Connected {
                                 div { class: "flex items-center text-green-600",
                                     div { class: "w-3 h-3 bg-green-500 rounded-full mr-2 animate-pulse" }
                                     span { class: "text-sm font-medium", "Live Updates" }
                                 }
+                            } else if let ConnectionState::Reconnecting { .. } = live.connection.read().state {
+                                div { class: "flex items-center text-amber-600",
+                                    div { class: "w-3 h-3 bg-amber-400 rounded-full mr-2 animate-pulse" }
+                                    span { class: "text-sm", "Reconnecting..." }
+                                }
                             } else {
                                 div { class: "flex items-center text-gray-500",
                                     div { class: "w-3 h-3 bg-gray-400 rounded-full mr-2" }
@@ -140,18 +311,30 @@ pub fn Dashboard() -> Element {
                             h3 { class: "text-lg font-medium text-gray-900", "No meetings in queue" }
                             p { class: "text-gray-500 mt-1", "Add some recordings to get started!" }
                             Link {
-                                to: Route::Recordings {},
+                                to: Route::Recordings { query: Default::default() },
                                 class: "mt-4 inline-flex items-center px-4 py-2 border border-transparent text-sm font-medium rounded-md text-white bg-indigo-600 hover:bg-indigo-700",
                                 "Browse Recordings"
                             }
                         }
                     } else {
                         div { class: "space-y-4",
-                            for (index, meeting) in queue_data.read().iter().enumerate() {
-                                div { key: "{meeting.id}", class: "border border-gray-200 rounded-lg p-4",
+                            for meeting in queue_data() {
+                                div {
+                                    key: "{meeting.id}",
+                                    class: if *dragging.read() == Some(meeting.id) { "border border-indigo-300 rounded-lg p-4 opacity-50" } else { "border border-gray-200 rounded-lg p-4" },
+                                    draggable: can_move(&meeting, viewer.as_deref(), admin),
+                                    // Letting a row take the drop is what makes it a target
+                                    prevent_default: if can_move(&meeting, viewer.as_deref(), admin) { "ondragover ondrop" } else { "" },
+                                    ondragstart: move |_| dragging.set(Some(meeting.id)),
+                                    ondragend: move |_| dragging.set(None),
+                                    ondragover: move |_| {},
+                                    ondrop: move |_| move_meeting(meeting.id),
                                         div { class: "flex justify-between items-start",
                                         div { class: "flex-1",
                                             div { class: "flex items-center space-x-3",
+                                                if can_move(&meeting, viewer.as_deref(), admin) {
+                                                    span { class: "cursor-move text-gray-400 select-none", title: "Drag to reorder", "⠿" }
+                                                }
                                                 div { class: "flex-shrink-0",
                                                     span { class: "inline-flex items-center px-2.5 py-0.5 rounded-full text-xs font-medium bg-indigo-100 text-indigo-800",
                                                         "#{meeting.position}"
@@ -161,16 +344,35 @@ pub fn Dashboard() -> Element {
                                             }
                                             p { class: "text-sm text-gray-500 mt-1", "User: {meeting.user_id}" }
                                             
-                                            // Progress indicator for current processing
-                                            if index == 0 {
-                                                div { class: "mt-3",
-                                                    div { class: "flex items-center justify-between text-sm",
-                                                        span { class: "text-green-600 font-medium", "Currently Processing" }
-                                                        span { class: "text-gray-500", "45%" }
-                                                    }
-                                                    div { class: "mt-1 w-full bg-gray-200 rounded-full h-2",
-                                                        div { class: "bg-green-600 h-2 rounded-full transition-all duration-300", style: "width: 45%" }
-                                                    }
+                                            // How far the worker has got with it, once it has said
+                                            if let Some(state) = progress_by_task.read().get(&meeting.id).cloned() {
+                                                match state.outcome {
+                                                    Some(Outcome::Completed) => rsx! {
+                                                        p { class: "mt-3 text-sm text-green-600 font-medium", "✓ Uploaded to Loom" }
+                                                    },
+                                                    Some(Outcome::Failed(reason)) => rsx! {
+                                                        p { class: "mt-3 text-sm text-red-600", "Failed: {reason}" }
+                                                    },
+                                                    None => rsx! {
+                                                        div { class: "mt-3",
+                                                            div { class: "flex items-center justify-between text-sm",
+                                                                span { class: "text-green-600 font-medium",
+                                                                    {state.stage.map_or("Processing", stage_label)}
+                                                                }
+                                                                if state.is_stale(now()) {
+                                                                    span { class: "text-gray-400 italic", "awaiting update…" }
+                                                                } else {
+                                                                    span { class: "text-gray-500", "{state.percent}%" }
+                                                                }
+                                                            }
+                                                            div { class: "mt-1 w-full bg-gray-200 rounded-full h-2",
+                                                                div {
+                                                                    class: "bg-green-600 h-2 rounded-full transition-all duration-500 ease-out",
+                                                                    style: "width: {state.percent}%",
+                                                                }
+                                                            }
+                                                        }
+                                                    },
                                                 }
                                             }
                                         }
@@ -243,7 +445,7 @@ pub fn Dashboard() -> Element {
                                     div { class: "ml-4",
                                         p { class: "text-sm font-medium text-gray-500", "Est. Wait Time" }
                                         p { class: "text-2xl font-semibold text-gray-900", 
-                                            "{(calculate_global_position(&queue_data.read(), &user.id).saturating_sub(1)) * 15}m"
+                                            "{format_duration_minutes((calculate_global_position(&queue_data.read(), &user.id).saturating_sub(1)) as u32 * 15 * 60)}"
                                         }
                                     }
                                 }
@@ -256,7 +458,7 @@ pub fn Dashboard() -> Element {
                 div { class: "bg-white shadow rounded-lg p-6",
                     h2 { class: "text-xl font-semibold text-gray-900 mb-4", "Worker Status" }
                     
-                    if worker_status.read().is_empty() {
+                    if workers.read().is_empty() {
                         div { class: "text-center py-6",
                             div { class: "w-12 h-12 bg-gray-100 rounded-lg flex items-center justify-center mx-auto mb-4",
                                 svg { class: "w-6 h-6 text-gray-400", fill: "none", stroke: "currentColor", view_box: "0 0 24 24",
@@ -267,28 +469,38 @@ pub fn Dashboard() -> Element {
                         }
                     } else {
                         div { class: "space-y-4",
-                            for worker in worker_status.read().iter() {
-                                div { class: "border border-gray-200 rounded-lg p-4",
+                            for row in workers.read().rows().cloned() {
+                                div {
+                                    key: "{row.status.worker_id}",
+                                    class: if row.offline { "border border-gray-200 rounded-lg p-4 opacity-50" } else { "border border-gray-200 rounded-lg p-4" },
                                     div { class: "flex justify-between items-center mb-2",
                                         div {
-                                            h3 { class: "font-medium text-gray-900", "Worker {worker.worker_id}" }
-                                            p { class: "text-sm text-gray-500", "{worker.status}" }
+                                            h3 { class: "font-medium text-gray-900", "Worker {row.status.worker_id}" }
+                                            if row.offline {
+                                                p { class: "text-sm text-gray-500", "Offline, last seen {row.last_seen(*now.read())}" }
+                                            } else {
+                                                p { class: "text-sm text-gray-500", "{row.status.status}" }
+                                            }
                                         }
-                                        span { class: "text-sm font-medium text-gray-900", 
-                                            "{(worker.progress * 100.0) as u32}%"
+                                        span { class: "text-sm font-medium text-gray-900",
+                                            "{(row.status.progress * 100.0) as u32}%"
                                         }
                                     }
-                                    
+
+                                    // Eases from one update's progress to the next
                                     div { class: "w-full bg-gray-200 rounded-full h-2 mb-2",
-                                        div { 
-                                            class: "bg-indigo-600 h-2 rounded-full transition-all duration-300",
-                                            style: "width: {worker.progress * 100.0}%"
+                                        div {
+                                            class: if row.offline { "bg-gray-400 h-2 rounded-full transition-all duration-1000 ease-out" } else { "bg-indigo-600 h-2 rounded-full transition-all duration-1000 ease-out" },
+                                            style: "width: {row.status.progress * 100.0}%"
                                         }
                                     }
-                                    
-                                    if let Some(task) = &worker.current_task {
+
+                                    if let Some(task) = &row.status.current_task {
                                         p { class: "text-sm text-gray-600", "Current: {task}" }
                                     }
+                                    if let Some(stage) = &row.status.stage {
+                                        p { class: "text-sm text-gray-500", "Stage: {stage}" }
+                                    }
                                 }
                             }
                         }
@@ -298,3 +510,72 @@ pub fn Dashboard() -> Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meeting(user_id: &str, position: usize) -> Meeting {
+        Meeting {
+            id: Uuid::new_v4(),
+            user_id: user_id.to_string(),
+            topic: format!("Meeting {}", position),
+            position,
+        }
+    }
+
+    fn ids(queue: &[Meeting]) -> Vec<Uuid> {
+        queue.iter().map(|meeting| meeting.id).collect()
+    }
+
+    #[test]
+    fn test_a_dropped_meeting_takes_the_place_of_the_row_it_lands_on() {
+        let queue = vec![meeting("ana", 1), meeting("ben", 2), meeting("ana", 3)];
+        let (moved, position) = drop_onto(&queue, queue[2].id, queue[0].id, |_| true).unwrap();
+        assert_eq!(position, 1);
+        assert_eq!(ids(&moved), [queue[2].id, queue[0].id, queue[1].id]);
+        let positions: Vec<_> = moved.iter().map(|meeting| meeting.position).collect();
+        assert_eq!(positions, [1, 2, 3]);
+
+        assert!(drop_onto(&queue, queue[1].id, queue[1].id, |_| true).is_none(), "onto itself");
+        assert!(drop_onto(&queue, Uuid::new_v4(), queue[1].id, |_| true).is_none());
+    }
+
+    #[test]
+    fn test_users_move_only_their_own_meetings_among_their_own() {
+        let queue = vec![meeting("ana", 1), meeting("ben", 2), meeting("ana", 3)];
+        let ana = |meeting: &Meeting| can_move(meeting, Some("ana"), false);
+        assert!(ana(&queue[0]) && !ana(&queue[1]));
+        assert!(can_move(&queue[1], Some("ana"), true));
+        assert!(!can_move(&queue[0], None, false));
+
+        let (moved, position) = drop_onto(&queue, queue[2].id, queue[0].id, ana).unwrap();
+        assert_eq!((position, ids(&moved)), (1, vec![queue[2].id, queue[1].id, queue[0].id]));
+        assert!(drop_onto(&queue, queue[0].id, queue[1].id, ana).is_none(), "onto ben's");
+        assert!(drop_onto(&queue, queue[1].id, queue[0].id, ana).is_none(), "ben's");
+    }
+
+    #[test]
+    fn test_a_move_settles_on_the_backends_queue() {
+        let before = vec![meeting("ana", 1), meeting("ana", 2)];
+        let answered = vec![before[1].clone(), before[0].clone()];
+        let moved = Reordered::Moved(QueueResponse {
+            success: true,
+            message: "Meeting moved".to_string(),
+            data: Some(answered.clone()),
+        });
+        assert!(matches!(settle(before.clone(), Ok(moved)), Settled::Show(queue) if ids(&queue) == ids(&answered)));
+
+        // A conflict loads the queue again rather than keeping either order
+        assert!(matches!(settle(before.clone(), Ok(Reordered::Conflict)), Settled::Refetch));
+
+        let failed = ApiError { status: Some(500), ..ApiError::local("Request failed (500)") };
+        match settle(before.clone(), Err(failed)) {
+            Settled::Restore(queue, e) => {
+                assert_eq!(ids(&queue), ids(&before));
+                assert_eq!(e, "Request failed (500)");
+            }
+            settled => panic!("expected the queue back, got {:?}", settled),
+        }
+    }
+}
@@ -42,7 +42,7 @@ pub fn Home() -> Element {
                                     "Go to Dashboard"
                                 }
                                 Link {
-                                    to: Route::Recordings {},
+                                    to: Route::Recordings { query: Default::default() },
                                     class: "bg-gray-200 hover:bg-gray-300 text-gray-800 font-bold py-3 px-6 rounded-lg transition-colors",
                                     "View Recordings"
                                 }
@@ -1,51 +1,213 @@
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
 use crate::{Route, components::layout::Layout};
+use crate::utils::{format_duration_minutes, format_local, format_relative};
 use crate::services::{
     auth::AuthService,
-    api::{ApiService, FathomMeeting, MeetingRequest}
+    api::{ApiError, ApiService, FathomMeeting, MeetingFilters, MeetingRequest},
+    toast::use_toast,
 };
 
+/// Meetings asked for in the first page
+const FIRST_PAGE: u32 = 50;
+
+/// How long typing has to pause before the listing is searched again
+const DEBOUNCE_MS: u32 = 300;
+
+/// Shortest recordings the duration filter offers, in minutes
+const MIN_DURATION_CHOICES: [u32; 4] = [5, 15, 30, 60];
+
+/// Tells the last of a burst of keystrokes from the ones it superseded
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Debounce {
+    latest: u64,
+}
+
+impl Debounce {
+    /// A ticket for this keystroke, superseding those before it
+    fn next(&mut self) -> u64 {
+        self.latest += 1;
+        self.latest
+    }
+
+    fn is_latest(&self, ticket: u64) -> bool {
+        ticket == self.latest
+    }
+}
+
+/// A typed filter, unset when blank
+fn typed(text: &str) -> Option<String> {
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// `filters` with the search and participant boxes as typed
+fn with_typed(filters: &MeetingFilters, search: &str, participant: &str) -> MeetingFilters {
+    MeetingFilters {
+        q: typed(search),
+        participant: typed(participant),
+        ..filters.clone()
+    }
+}
+
+/// The duration filter's choice, in minutes, as seconds; unset for any length
+fn min_duration_secs(minutes: &str) -> Option<u32> {
+    minutes.parse::<u32>().ok().filter(|minutes| *minutes > 0).map(|minutes| minutes * 60)
+}
+
+/// The meetings loaded so far, page after page, and where the next starts
+#[derive(Debug, Clone, Default, PartialEq)]
+struct MeetingPages {
+    meetings: Vec<FathomMeeting>,
+    /// Absent once the backend has given the last page
+    next_cursor: Option<String>,
+}
+
+impl MeetingPages {
+    /// Start over from a first page
+    fn first(meetings: Vec<FathomMeeting>, next_cursor: Option<String>) -> Self {
+        let mut pages = Self::default();
+        pages.add(meetings, next_cursor);
+        pages
+    }
+
+    /// Add the page loaded from `cursor`, leaving out meetings already
+    /// shown; returns false and changes nothing if the listing started over
+    /// since the page was asked for
+    fn append(&mut self, cursor: &str, meetings: Vec<FathomMeeting>, next_cursor: Option<String>) -> bool {
+        if self.next_cursor.as_deref() != Some(cursor) {
+            return false;
+        }
+        self.add(meetings, next_cursor);
+        true
+    }
+
+    fn add(&mut self, meetings: Vec<FathomMeeting>, next_cursor: Option<String>) {
+        let mut seen: std::collections::HashSet<String> =
+            self.meetings.iter().map(|meeting| meeting.id.clone()).collect();
+        self.meetings.extend(meetings.into_iter().filter(|meeting| seen.insert(meeting.id.clone())));
+        self.next_cursor = next_cursor;
+    }
+
+    fn has_more(&self) -> bool {
+        self.next_cursor.is_some()
+    }
+}
+
+/// The Fathom recordings, narrowed by the filters in the URL's query string
+/// so a refresh or a shared link lists the same ones
 #[component]
-pub fn Recordings() -> Element {
+pub fn Recordings(query: MeetingFilters) -> Element {
     let auth_service = use_context::<Signal<AuthService>>();
     let navigator = use_navigator();
-    
-    // Redirect if not authenticated
-    if !auth_service.read().is_authenticated() {
-        navigator.push(Route::Login {});
-    }
 
-    let mut meetings_data = use_signal(|| Vec::<FathomMeeting>::new());
+    let mut pages = use_signal(MeetingPages::default);
     let mut is_loading = use_signal(|| true);
-    let mut error_message = use_signal(|| Option::<String>::None);
-    let mut success_message = use_signal(|| Option::<String>::None);
+    let mut loading_more = use_signal(|| false);
+    let mut refreshing = use_signal(|| false);
+    let mut load_error = use_signal(|| Option::<ApiError>::None);
+    let toasts = use_toast();
     let mut adding_to_queue = use_signal(|| std::collections::HashSet::<String>::new());
+    let mut filters = use_signal(|| query.clone());
+    let mut search = use_signal(|| query.q.clone().unwrap_or_default());
+    let mut participant = use_signal(|| query.participant.clone().unwrap_or_default());
+    let mut debounce = use_signal(Debounce::default);
 
     // Initialize API service
     let api_service = use_memo(move || {
-        ApiService::new(auth_service.read().clone())
+        ApiService::new(auth_service)
     });
 
-    // Load meetings data
-    use_effect(move || {
-        let api = api_service.read().clone();
+    // Load the first page, again whenever the filters in the URL change
+    use_effect(use_reactive((&query,), move |(query,)| {
+        let api = api_service();
+        filters.set(query.clone());
+        let filters = query;
+        is_loading.set(true);
         wasm_bindgen_futures::spawn_local(async move {
-            match api.get_meetings(Some(50), None).await {
+            match api.get_meetings(Some(FIRST_PAGE), None, &filters).await {
                 Ok(response) => {
-                    meetings_data.set(response.meetings);
+                    pages.set(MeetingPages::first(response.meetings, response.next_cursor));
+                    load_error.set(None);
                     is_loading.set(false);
                 }
                 Err(e) => {
-                    error_message.set(Some(format!("Failed to load meetings: {}", e)));
+                    load_error.set(Some(e));
                     is_loading.set(false);
                 }
             }
         });
-    });
+    }));
 
-    let add_to_queue = move |meeting: FathomMeeting| {
-        let api = api_service.read().clone();
+    // Filter by moving to the URL that carries the filters
+    let apply = move |next: MeetingFilters| {
+        if next != *filters.peek() {
+            navigator.replace(Route::Recordings { query: next });
+        }
+    };
+
+    // Search once typing in either box pauses
+    let mut typing = move || {
+        let ticket = debounce.write().next();
+        wasm_bindgen_futures::spawn_local(async move {
+            gloo_timers::future::TimeoutFuture::new(DEBOUNCE_MS).await;
+            if debounce.peek().is_latest(ticket) {
+                apply(with_typed(&filters.peek(), &search.peek(), &participant.peek()));
+            }
+        });
+    };
+
+    let mut clear_filters = move || {
+        debounce.write().next();
+        search.set(String::new());
+        participant.set(String::new());
+        apply(MeetingFilters::default());
+    };
+
+    // Append the page after the last one loaded
+    let load_more = move |_| {
+        let Some(cursor) = pages.read().next_cursor.clone() else {
+            return;
+        };
+        let api = api_service();
+        let filters = filters.read().clone();
+        loading_more.set(true);
+        wasm_bindgen_futures::spawn_local(async move {
+            match api.get_meetings(None, Some(&cursor), &filters).await {
+                Ok(response) => {
+                    // A search or refresh since replaced the listing
+                    if !pages.write().append(&cursor, response.meetings, response.next_cursor) {
+                        tracing::debug!("Dropping a page of meetings from an earlier listing");
+                    }
+                }
+                Err(e) => {
+                    toasts.error(format!("Failed to load more meetings: {}", e));
+                }
+            }
+            loading_more.set(false);
+        });
+    };
+
+    // Fetch the first page from Fathom again, past the cache
+    let refresh = move |_| {
+        let api = api_service();
+        let filters = filters.read().clone();
+        refreshing.set(true);
+        wasm_bindgen_futures::spawn_local(async move {
+            match api.refresh_meetings(Some(FIRST_PAGE), &filters).await {
+                Ok(response) => {
+                    pages.set(MeetingPages::first(response.meetings, response.next_cursor));
+                    load_error.set(None);
+                }
+                Err(e) => {
+                    toasts.error(format!("Failed to refresh meetings: {}", e));
+                }
+            }
+            refreshing.set(false);
+        });
+    };
+
+    let mut add_to_queue = move |meeting: FathomMeeting| {
+        let api = api_service();
         let meeting_id = meeting.id.clone();
         
         // Add to loading set
@@ -55,20 +217,33 @@ pub fn Recordings() -> Element {
             let meeting_request = MeetingRequest {
                 user_id: user.id.clone(),
                 topic: meeting.title.clone(),
+                meeting_id: Some(meeting.id.clone()),
+                // The worker uses the service defaults
+                fathom_key_id: None,
+                loom_key_id: None,
             };
 
             wasm_bindgen_futures::spawn_local(async move {
+                // Warn about recordings the worker would fail to fetch; when
+                // the check itself fails, queueing goes ahead
+                if let Ok(check) = api.check_downloadable(&meeting_id).await {
+                    let confirmed = check.downloadable || gloo_utils::window()
+                        .confirm_with_message(&format!("{}. Add '{}' to the queue anyway?", check.reason.describe(), meeting.title))
+                        .unwrap_or(false);
+                    if !confirmed {
+                        toasts.warning(format!("'{}' was not queued: {}", meeting.title, check.reason.describe()));
+                        adding_to_queue.write().remove(&meeting_id);
+                        return;
+                    }
+                }
+
                 match api.add_to_queue(meeting_request).await {
                     Ok(_response) => {
-                        success_message.set(Some(format!("'{}' added to queue successfully!", meeting.title)));
+                        toasts.success(format!("'{}' added to queue successfully!", meeting.title));
                         adding_to_queue.write().remove(&meeting_id);
-                        
-                        // Clear success message after 3 seconds
-                        gloo_timers::future::TimeoutFuture::new(3000).await;
-                        success_message.set(None);
                     }
                     Err(e) => {
-                        error_message.set(Some(format!("Failed to add to queue: {}", e)));
+                        toasts.error(format!("Failed to add to queue: {}", e));
                         adding_to_queue.write().remove(&meeting_id);
                     }
                 }
@@ -76,25 +251,6 @@ pub fn Recordings() -> Element {
         }
     };
 
-    let format_duration = |duration_seconds: u32| -> String {
-        let hours = duration_seconds / 3600;
-        let minutes = (duration_seconds % 3600) / 60;
-        
-        if hours > 0 {
-            format!("{}h {}m", hours, minutes)
-        } else {
-            format!("{}m", minutes)
-        }
-    };
-
-    let format_date = |date_str: &str| -> String {
-        // Simple date formatting - in a real app you'd use chrono
-        date_str.split('T')
-            .next()
-            .unwrap_or(date_str)
-            .to_string()
-    };
-
     rsx! {
         Layout {
             div { class: "space-y-6",
@@ -106,6 +262,12 @@ pub fn Recordings() -> Element {
                             p { class: "text-gray-600 mt-1", "Browse and add your meeting recordings to the processing queue" }
                         }
                         div { class: "flex items-center space-x-4",
+                            button {
+                                class: "bg-white border border-gray-300 hover:bg-gray-50 text-gray-700 px-4 py-2 rounded-md text-sm font-medium transition-colors disabled:opacity-50",
+                                disabled: *refreshing.read(),
+                                onclick: refresh,
+                                if *refreshing.read() { "Refreshing..." } else { "Refresh" }
+                            }
                             Link {
                                 to: Route::Dashboard {},
                                 class: "bg-indigo-600 hover:bg-indigo-700 text-white px-4 py-2 rounded-md text-sm font-medium transition-colors",
@@ -116,29 +278,121 @@ pub fn Recordings() -> Element {
                 }
 
                 // Messages
-                if let Some(error) = error_message.read().as_ref() {
+                if let Some(error) = load_error.read().as_ref() {
                     div { class: "bg-red-50 border border-red-200 text-red-700 px-4 py-3 rounded-lg",
-                        "{error}"
-                    }
-                }
-
-                if let Some(success) = success_message.read().as_ref() {
-                    div { class: "bg-green-50 border border-green-200 text-green-700 px-4 py-3 rounded-lg",
-                        "{success}"
+                        "Failed to load meetings: {error}"
+                        // The key is the user's to fix, not something to retry
+                        if error.is_key_problem() {
+                            Link {
+                                to: Route::Settings {},
+                                class: "ml-2 font-medium underline",
+                                "Fix your API key in Settings"
+                            }
+                        }
                     }
                 }
 
                 // Meetings list
                 div { class: "bg-white shadow rounded-lg",
-                    div { class: "px-6 py-4 border-b border-gray-200",
+                    div { class: "px-6 py-4 border-b border-gray-200 flex justify-between items-center",
                         h2 { class: "text-lg font-semibold text-gray-900", "Available Recordings" }
+                        form {
+                            class: "flex items-center space-x-2",
+                            onsubmit: move |_evt| {
+                                // Now rather than after the pause
+                                debounce.write().next();
+                                apply(with_typed(&filters.peek(), &search.peek(), &participant.peek()));
+                            },
+                            input {
+                                class: "border border-gray-300 rounded-md px-3 py-2 text-sm",
+                                r#type: "search",
+                                placeholder: "Search titles",
+                                value: "{search}",
+                                oninput: move |event| {
+                                    search.set(event.value());
+                                    typing();
+                                },
+                            }
+                            button {
+                                class: "bg-white border border-gray-300 hover:bg-gray-50 text-gray-700 px-4 py-2 rounded-md text-sm font-medium",
+                                r#type: "submit",
+                                "Search"
+                            }
+                        }
+                    }
+
+                    // Filters
+                    div { class: "px-6 py-3 border-b border-gray-200 flex flex-wrap items-end gap-4 text-sm",
+                        label { class: "flex flex-col text-gray-600",
+                            "From"
+                            input {
+                                class: "mt-1 border border-gray-300 rounded-md px-2 py-1",
+                                r#type: "date",
+                                value: filters.read().from.clone().unwrap_or_default(),
+                                onchange: move |event| apply(MeetingFilters { from: typed(&event.value()), ..filters.peek().clone() }),
+                            }
+                        }
+                        label { class: "flex flex-col text-gray-600",
+                            "To"
+                            input {
+                                class: "mt-1 border border-gray-300 rounded-md px-2 py-1",
+                                r#type: "date",
+                                value: filters.read().to.clone().unwrap_or_default(),
+                                onchange: move |event| apply(MeetingFilters { to: typed(&event.value()), ..filters.peek().clone() }),
+                            }
+                        }
+                        label { class: "flex flex-col text-gray-600",
+                            "Length"
+                            select {
+                                class: "mt-1 border border-gray-300 rounded-md px-2 py-1",
+                                onchange: move |event| apply(MeetingFilters { min_duration_secs: min_duration_secs(&event.value()), ..filters.peek().clone() }),
+                                option { value: "", selected: filters.read().min_duration_secs.is_none(), "Any length" }
+                                for minutes in MIN_DURATION_CHOICES {
+                                    option {
+                                        value: "{minutes}",
+                                        selected: filters.read().min_duration_secs == Some(minutes * 60),
+                                        "At least {minutes} min"
+                                    }
+                                }
+                            }
+                        }
+                        label { class: "flex flex-col text-gray-600",
+                            "Participant"
+                            input {
+                                class: "mt-1 border border-gray-300 rounded-md px-2 py-1",
+                                r#type: "search",
+                                placeholder: "Name or email",
+                                value: "{participant}",
+                                oninput: move |event| {
+                                    participant.set(event.value());
+                                    typing();
+                                },
+                            }
+                        }
+                        if !filters.read().is_empty() {
+                            button {
+                                class: "text-indigo-600 hover:text-indigo-800 font-medium py-1",
+                                onclick: move |_| clear_filters(),
+                                "Clear filters"
+                            }
+                        }
                     }
                     
                     if *is_loading.read() {
                         div { class: "flex justify-center py-12",
                             div { class: "animate-spin rounded-full h-8 w-8 border-b-2 border-indigo-600" }
                         }
-                    } else if meetings_data.read().is_empty() {
+                    } else if pages.read().meetings.is_empty() && !filters.read().is_empty() {
+                        div { class: "text-center py-12",
+                            h3 { class: "text-lg font-medium text-gray-900", "No results for these filters" }
+                            p { class: "text-gray-500 mt-2", "Try a different search or widen the dates." }
+                            button {
+                                class: "mt-4 inline-flex items-center px-4 py-2 border border-gray-300 text-sm font-medium rounded-md text-gray-700 bg-white hover:bg-gray-50",
+                                onclick: move |_| clear_filters(),
+                                "Clear filters"
+                            }
+                        }
+                    } else if pages.read().meetings.is_empty() {
                         div { class: "text-center py-12",
                             svg { class: "mx-auto h-12 w-12 text-gray-400 mb-4", fill: "none", stroke: "currentColor", view_box: "0 0 24 24",
                                 path { stroke_linecap: "round", stroke_linejoin: "round", stroke_width: "2", d: "M15 10l4.553-2.276A1 1 0 0121 8.618v6.764a1 1 0 01-1.447.894L15 14M5 18h8a2 2 0 002-2V8a2 2 0 00-2-2H5a2 2 0 00-2 2v8a2 2 0 002 2z" }
@@ -153,7 +407,7 @@ pub fn Recordings() -> Element {
                         }
                     } else {
                         div { class: "divide-y divide-gray-200",
-                            for meeting in meetings_data.read().iter() {
+                            for meeting in pages().meetings {
                                 div { class: "p-6 hover:bg-gray-50 transition-colors",
                                     div { class: "flex items-center justify-between",
                                         div { class: "flex-1 min-w-0",
@@ -164,16 +418,17 @@ pub fn Recordings() -> Element {
                                                     }
                                                     div { class: "flex items-center space-x-4 text-sm text-gray-500 mb-2",
                                                         div { class: "flex items-center",
+                                                            title: format_relative(&meeting.start_time),
                                                             svg { class: "w-4 h-4 mr-1", fill: "none", stroke: "currentColor", view_box: "0 0 24 24",
                                                                 path { stroke_linecap: "round", stroke_linejoin: "round", stroke_width: "2", d: "M8 7V3m8 4V3m-9 8h10M5 21h14a2 2 0 002-2V7a2 2 0 00-2-2H5a2 2 0 00-2 2v12a2 2 0 002 2z" }
                                                             }
-                                                            "{format_date(&meeting.start_time)}"
+                                                            "{format_local(&meeting.start_time)}"
                                                         }
                                                         div { class: "flex items-center",
                                                             svg { class: "w-4 h-4 mr-1", fill: "none", stroke: "currentColor", view_box: "0 0 24 24",
                                                                 path { stroke_linecap: "round", stroke_linejoin: "round", stroke_width: "2", d: "M12 8v4l3 3m6-3a9 9 0 11-18 0 9 9 0 0118 0z" }
                                                             }
-                                                            "{format_duration(meeting.duration)}"
+                                                            "{format_duration_minutes(meeting.duration)}"
                                                         }
                                                         div { class: "flex items-center",
                                                             svg { class: "w-4 h-4 mr-1", fill: "none", stroke: "currentColor", view_box: "0 0 24 24",
@@ -187,12 +442,13 @@ pub fn Recordings() -> Element {
                                                         div { class: "flex flex-wrap gap-1 mt-2",
                                                             for participant in meeting.participants.iter().take(5) {
                                                                 span { class: "inline-flex items-center px-2 py-1 rounded-full text-xs font-medium bg-gray-100 text-gray-800",
-                                                                    "{participant}"
+                                                                    title: participant.email.clone().unwrap_or_default(),
+                                                                    "{participant.display_name}"
                                                                 }
                                                             }
                                                             if meeting.participants.len() > 5 {
                                                                 span { class: "inline-flex items-center px-2 py-1 rounded-full text-xs font-medium bg-gray-100 text-gray-800",
-                                                                    "+{} more", meeting.participants.len() - 5
+                                                                    {format!("+{} more", meeting.participants.len() - 5)}
                                                                 }
                                                             }
                                                         }
@@ -228,6 +484,26 @@ pub fn Recordings() -> Element {
                                 }
                             }
                         }
+                        if pages.read().has_more() {
+                            div { class: "flex justify-center px-6 py-4 border-t border-gray-200",
+                                if *loading_more.read() {
+                                    div { class: "flex items-center text-sm text-gray-500",
+                                        div { class: "animate-spin rounded-full h-4 w-4 border-b-2 border-indigo-600 mr-2" }
+                                        "Loading more recordings..."
+                                    }
+                                } else {
+                                    button {
+                                        class: "bg-white border border-gray-300 hover:bg-gray-50 text-gray-700 px-4 py-2 rounded-md text-sm font-medium transition-colors",
+                                        onclick: load_more,
+                                        "Load more"
+                                    }
+                                }
+                            }
+                        } else if pages.read().meetings.len() > FIRST_PAGE as usize {
+                            p { class: "text-center text-sm text-gray-500 px-6 py-4 border-t border-gray-200",
+                                "All {pages.read().meetings.len()} recordings loaded"
+                            }
+                        }
                     }
                 }
 
@@ -262,3 +538,95 @@ pub fn Recordings() -> Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meeting(id: &str) -> FathomMeeting {
+        FathomMeeting {
+            id: id.to_string(),
+            title: format!("Meeting {}", id),
+            start_time: "2026-10-14T09:00:00Z".parse().unwrap(),
+            duration: 1800,
+            participants: Vec::new(),
+            participants_raw: Vec::new(),
+            url: None,
+            share_url: None,
+        }
+    }
+
+    fn ids(pages: &MeetingPages) -> Vec<&str> {
+        pages.meetings.iter().map(|meeting| meeting.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_later_pages_are_appended_without_repeating_meetings() {
+        let mut pages = MeetingPages::first(vec![meeting("a"), meeting("b")], Some("c1".to_string()));
+        assert!(pages.has_more());
+
+        assert!(pages.append("c1", vec![meeting("b"), meeting("c")], Some("c2".to_string())));
+        assert_eq!(ids(&pages), ["a", "b", "c"]);
+        assert_eq!(pages.next_cursor.as_deref(), Some("c2"));
+    }
+
+    #[test]
+    fn test_the_last_page_ends_the_listing() {
+        let mut pages = MeetingPages::first(vec![meeting("a")], Some("c1".to_string()));
+        assert!(pages.append("c1", vec![meeting("b")], None));
+        assert!(!pages.has_more());
+        assert!(!pages.append("c1", vec![meeting("c")], None), "no page follows the last");
+        assert_eq!(ids(&pages), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_a_page_from_a_replaced_listing_is_dropped() {
+        // Searched again while the page after "old" was loading
+        let mut pages = MeetingPages::first(vec![meeting("x")], Some("new".to_string()));
+        assert!(!pages.append("old", vec![meeting("b")], None));
+        assert_eq!(ids(&pages), ["x"]);
+        assert!(pages.has_more());
+    }
+
+    #[test]
+    fn test_only_the_last_keystroke_of_a_burst_searches() {
+        let mut debounce = Debounce::default();
+        let first = debounce.next();
+        let second = debounce.next();
+        assert!(!debounce.is_latest(first));
+        assert!(debounce.is_latest(second));
+    }
+
+    #[test]
+    fn test_typed_filters_build_the_query() {
+        let dated = MeetingFilters { from: Some("2026-01-01".to_string()), ..Default::default() };
+        let filters = with_typed(&dated, "  weekly sync ", "");
+        assert_eq!(filters.q.as_deref(), Some("weekly sync"));
+        assert_eq!(filters.participant, None);
+        assert_eq!(filters.from.as_deref(), Some("2026-01-01"), "other filters kept");
+        assert_eq!(filters.to_string(), "q=weekly+sync&from=2026-01-01");
+
+        assert_eq!(min_duration_secs("15"), Some(900));
+        assert_eq!(min_duration_secs(""), None);
+        assert!(with_typed(&MeetingFilters::default(), " ", "").is_empty());
+    }
+
+    #[test]
+    fn test_filters_round_trip_through_the_url() {
+        let filters = MeetingFilters {
+            q: Some("Q&A: 50% done".to_string()),
+            from: Some("2026-01-01".to_string()),
+            to: Some("2026-02-01".to_string()),
+            participant: Some("ana@example.com".to_string()),
+            min_duration_secs: Some(1800),
+        };
+        let route = Route::Recordings { query: filters.clone() };
+        let url = route.to_string();
+        assert!(url.starts_with("/recordings?"), "{}", url);
+        assert_eq!(url.parse::<Route>().unwrap(), route);
+
+        let unfiltered = Route::Recordings { query: MeetingFilters::default() };
+        assert_eq!(unfiltered.to_string().parse::<Route>().unwrap(), unfiltered);
+        assert_eq!(MeetingFilters::from("min_duration_secs=soon&page=2"), MeetingFilters::default());
+    }
+}
@@ -1,35 +1,191 @@
 use dioxus::prelude::*;
-use dioxus_router::prelude::*;
-use crate::{Route, components::layout::Layout};
+use crate::components::layout::Layout;
 use crate::services::{
     auth::AuthService,
-    api::{ApiService, ApiKey, ApiKeyRequest}
+    api::{ApiService, ApiKey, PutKeyRequest},
+    toast::use_toast,
 };
-use gloo_storage::{LocalStorage, Storage};
+use crate::utils::{format_local, format_local_date, format_relative};
+use common::{validation::validate_api_key_format, ServiceKind};
+
+/// When a key was added, when it expires and when it was last used
+fn key_dates(api_key: &ApiKey) -> String {
+    let mut dates = vec![format!("Added: {}", format_local(&api_key.created_at))];
+    if let Some(expires_at) = api_key.expires_at {
+        let expires_on = format_local_date(&expires_at);
+        dates.push(match api_key.days_until_expiry {
+            _ if api_key.expired => format!("Expired {} — replace this key", expires_on),
+            Some(days) if days <= 7 => format!("Expires: {} (in {} days)", expires_on, days),
+            _ => format!("Expires: {}", expires_on),
+        });
+    }
+    dates.push(match api_key.last_used_at {
+        Some(last_used_at) => format!("Last used: {}", format_relative(&last_used_at)),
+        None => "Never used".to_string(),
+    });
+    dates.join(" · ")
+}
+
+/// Days left at which a key's badge turns urgent, and at which it first shows
+const SOON_DAYS: i64 = 7;
+const UPCOMING_DAYS: i64 = 30;
+
+/// How close a key is to expiring, for keys worth warning about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExpiryBadge {
+    Expired,
+    /// Within `SOON_DAYS`
+    Soon(i64),
+    /// Within `UPCOMING_DAYS`
+    Upcoming(i64),
+}
+
+impl ExpiryBadge {
+    fn of(api_key: &ApiKey) -> Option<Self> {
+        match api_key.days_until_expiry {
+            _ if api_key.expired => Some(Self::Expired),
+            Some(days) if days < 0 => Some(Self::Expired),
+            Some(days) if days <= SOON_DAYS => Some(Self::Soon(days)),
+            Some(days) if days <= UPCOMING_DAYS => Some(Self::Upcoming(days)),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> String {
+        match *self {
+            Self::Expired => "Expired".to_string(),
+            Self::Soon(0) | Self::Upcoming(0) => "Expires today".to_string(),
+            Self::Soon(1) | Self::Upcoming(1) => "Expires in 1 day".to_string(),
+            Self::Soon(days) | Self::Upcoming(days) => format!("Expires in {} days", days),
+        }
+    }
+
+    fn class(&self) -> &'static str {
+        match self {
+            Self::Expired => "bg-red-100 text-red-700",
+            Self::Soon(_) => "bg-orange-100 text-orange-700",
+            Self::Upcoming(_) => "bg-yellow-100 text-yellow-800",
+        }
+    }
+}
+
+/// A key as the list shows it: never its value, only its hint and fingerprint
+#[derive(Debug, Clone, PartialEq)]
+struct KeyRow {
+    service: String,
+    key_id: String,
+    hint: String,
+    dates: String,
+    badge: Option<ExpiryBadge>,
+    is_default: bool,
+}
+
+impl From<&ApiKey> for KeyRow {
+    fn from(api_key: &ApiKey) -> Self {
+        Self {
+            service: api_key.service.clone(),
+            key_id: api_key.key_id.clone(),
+            hint: format!("{} · {}", api_key.masked_hint, api_key.fingerprint),
+            dates: key_dates(api_key),
+            badge: ExpiryBadge::of(api_key),
+            is_default: api_key.is_default,
+        }
+    }
+}
+
+/// The key the Add form describes, ready to send, or why it can't be saved
+///
+/// A blank key id stores the key as the service's `default`.
+fn key_request(service: ServiceKind, key_id: &str, value: &str) -> Result<PutKeyRequest, &'static str> {
+    let key_id = key_id.trim();
+    validate_api_key_format(if key_id.is_empty() { "default" } else { key_id }, value)?;
+    Ok(PutKeyRequest {
+        service,
+        key_id: Some(key_id.to_string()).filter(|key_id| !key_id.is_empty()),
+        value: value.trim().to_string(),
+        expires_at: None,
+    })
+}
+
+/// The Add form's fields
+///
+/// They live in the page only: nothing typed here is written to browser
+/// storage, where a key would outlive the page in plaintext.
+#[derive(Debug, Clone, PartialEq)]
+struct KeyForm {
+    service: ServiceKind,
+    key_id: String,
+    value: String,
+    /// Whether the value is shown as typed rather than masked
+    reveal: bool,
+}
+
+impl Default for KeyForm {
+    fn default() -> Self {
+        Self {
+            service: ServiceKind::Fathom,
+            key_id: String::new(),
+            value: String::new(),
+            reveal: false,
+        }
+    }
+}
+
+impl KeyForm {
+    fn request(&self) -> Result<PutKeyRequest, &'static str> {
+        key_request(self.service, &self.key_id, &self.value)
+    }
+
+    /// Empty the form once its key is stored, keeping the chosen service
+    fn clear(&mut self) {
+        self.key_id.clear();
+        self.value.clear();
+        self.reveal = false;
+    }
+}
+
+/// A key taken out of the list while its delete is under way, with where it
+/// stood so a failed delete puts it back in place
+#[derive(Debug, Clone)]
+struct Removed {
+    index: usize,
+    api_key: ApiKey,
+}
+
+fn take_key(keys: &mut Vec<ApiKey>, service: &str, key_id: &str) -> Option<Removed> {
+    let index = keys
+        .iter()
+        .position(|api_key| api_key.service == service && api_key.key_id == key_id)?;
+    Some(Removed { index, api_key: keys.remove(index) })
+}
+
+/// Undo `take_key` after a failed delete
+fn restore_key(keys: &mut Vec<ApiKey>, removed: Removed) {
+    let index = removed.index.min(keys.len());
+    keys.insert(index, removed.api_key);
+}
 
 #[component]
 pub fn Settings() -> Element {
     let auth_service = use_context::<Signal<AuthService>>();
-    let navigator = use_navigator();
-    
-    // Redirect if not authenticated
-    if !auth_service.read().is_authenticated() {
-        navigator.push(Route::Login {});
-    }
 
     let mut api_keys = use_signal(|| Vec::<ApiKey>::new());
     let mut is_loading = use_signal(|| true);
     let mut error_message = use_signal(|| Option::<String>::None);
-    let mut success_message = use_signal(|| Option::<String>::None);
+    let toasts = use_toast();
+    let mut form = use_signal(KeyForm::default);
+    let mut form_error = use_signal(|| Option::<&'static str>::None);
+    // The key whose Delete was pressed, waiting on the confirmation dialog
+    let mut confirm_delete = use_signal(|| Option::<KeyRow>::None);
 
     // Initialize API service
     let api_service = use_memo(move || {
-        ApiService::new(auth_service.read().clone())
+        ApiService::new(auth_service)
     });
 
     // Load API keys
     use_effect(move || {
-        let api = api_service.read().clone();
+        let api = api_service();
         wasm_bindgen_futures::spawn_local(async move {
             match api.get_api_keys().await {
                 Ok(keys) => {
@@ -44,18 +200,24 @@ pub fn Settings() -> Element {
         });
     });
 
-    let save_api_key = move |name: String, value: String| {
-        let api = api_service.read().clone();
+    let mut save_api_key = move || {
+        let api = api_service();
         
-        let api_key_request = ApiKeyRequest {
-            name: name.clone(),
-            value: value.clone(),
+        let api_key_request = match form.read().request() {
+            Ok(request) => request,
+            Err(problem) => {
+                form_error.set(Some(problem));
+                return;
+            }
         };
+        form_error.set(None);
 
         wasm_bindgen_futures::spawn_local(async move {
             match api.save_api_key(api_key_request).await {
-                Ok(()) => {
-                    success_message.set(Some(format!("API key '{}' saved successfully!", name)));
+                Ok(saved) => {
+                    // The value never needs to stay in the page once stored
+                    form.write().clear();
+                    toasts.success(format!("API key '{} / {}' saved successfully!", saved.service, saved.key_id));
                     
                     // Reload API keys
                     match api.get_api_keys().await {
@@ -63,24 +225,84 @@ pub fn Settings() -> Element {
                             api_keys.set(keys);
                         }
                         Err(e) => {
-                            error_message.set(Some(format!("Failed to reload API keys: {}", e)));
+                            toasts.error(format!("Failed to reload API keys: {}", e));
                         }
                     }
+                }
+                Err(e) => {
+                    toasts.error(format!("Failed to save API key: {}", e));
+                }
+            }
+        });
+    };
 
-                    // Clear success message after 3 seconds
-                    gloo_timers::future::TimeoutFuture::new(3000).await;
-                    success_message.set(None);
+    let make_default = move |service: String, key_id: String| {
+        let api = api_service();
+        wasm_bindgen_futures::spawn_local(async move {
+            match api.set_default_key(&service, &key_id).await {
+                Ok(_) => match api.get_api_keys().await {
+                    Ok(keys) => {
+                        toasts.info(format!("'{} / {}' is now the default key", service, key_id));
+                        api_keys.set(keys);
+                    }
+                    Err(e) => {
+                        toasts.error(format!("Failed to reload API keys: {}", e));
+                    }
+                },
+                Err(e) => {
+                    toasts.error(format!("Failed to change the default key: {}", e));
+                }
+            }
+        });
+    };
+
+    let mut delete_key = move |row: KeyRow| {
+        confirm_delete.set(None);
+        let Some(removed) = take_key(&mut api_keys.write(), &row.service, &row.key_id) else {
+            return;
+        };
+        let api = api_service();
+        wasm_bindgen_futures::spawn_local(async move {
+            match api.delete_api_key(&row.service, &row.key_id).await {
+                Ok(()) => {
+                    toasts.success(format!("API key '{} / {}' deleted", row.service, row.key_id));
                 }
                 Err(e) => {
-                    error_message.set(Some(format!("Failed to save API key: {}", e)));
+                    restore_key(&mut api_keys.write(), removed);
+                    toasts.error(format!("Failed to delete API key '{} / {}': {}", row.service, row.key_id, e));
                 }
             }
         });
     };
 
+    let rows: Vec<KeyRow> = api_keys.read().iter().map(KeyRow::from).collect();
+
     rsx! {
         Layout {
             div { class: "space-y-6",
+                if let Some(row) = confirm_delete.read().clone() {
+                    div { class: "fixed inset-0 z-50 flex items-center justify-center bg-gray-900 bg-opacity-50",
+                        div { class: "bg-white rounded-lg shadow-xl p-6 max-w-md w-full",
+                            h3 { class: "text-lg font-semibold text-gray-900", "Delete API key?" }
+                            p { class: "text-gray-600 mt-2",
+                                "'{row.service} / {row.key_id}' will stop working for every meeting that uses it. This can't be undone."
+                            }
+                            div { class: "mt-6 flex justify-end space-x-3",
+                                button {
+                                    class: "px-4 py-2 rounded-md text-sm font-medium text-gray-700 border border-gray-300 hover:bg-gray-50",
+                                    onclick: move |_| confirm_delete.set(None),
+                                    "Cancel"
+                                }
+                                button {
+                                    class: "bg-red-600 hover:bg-red-700 text-white px-4 py-2 rounded-md text-sm font-medium",
+                                    onclick: move |_| delete_key(row.clone()),
+                                    "Delete"
+                                }
+                            }
+                        }
+                    }
+                }
+
                 // Header
                 div { class: "bg-white shadow rounded-lg p-6",
                     div { class: "flex justify-between items-center",
@@ -98,12 +320,6 @@ pub fn Settings() -> Element {
                     }
                 }
 
-                if let Some(success) = success_message.read().as_ref() {
-                    div { class: "bg-green-50 border border-green-200 text-green-700 px-4 py-3 rounded-lg",
-                        "{success}"
-                    }
-                }
-
                 // API Keys
                 div { class: "bg-white shadow rounded-lg p-6",
                     h2 { class: "text-xl font-semibold text-gray-900 mb-4", "API Keys" }
@@ -122,12 +338,41 @@ pub fn Settings() -> Element {
                         }
                     } else {
                         div {
-                            for api_key in api_keys.read().iter() {
+                            for row in rows {
                                 div { class: "border-b border-gray-200 py-4",
                                     div { class: "flex justify-between items-center",
                                         div {
-                                            h4 { class: "text-lg font-medium text-gray-900", "{api_key.name}" }
-                                            p { class: "text-sm text-gray-500", "Added: {api_key.created_at} UTC" }
+                                            h4 { class: "text-lg font-medium text-gray-900",
+                                                "{row.service} / {row.key_id}"
+                                                if row.is_default {
+                                                    span { class: "ml-2 px-2 py-0.5 text-xs font-medium rounded-full bg-indigo-100 text-indigo-700", "Default" }
+                                                }
+                                                if let Some(badge) = row.badge {
+                                                    span { class: "ml-2 px-2 py-0.5 text-xs font-medium rounded-full {badge.class()}", {badge.label()} }
+                                                }
+                                            }
+                                            p { class: "text-sm text-gray-500 font-mono", "{row.hint}" }
+                                            p { class: "text-sm text-gray-500", "{row.dates}" }
+                                        }
+                                        div { class: "flex items-center space-x-4",
+                                            if !row.is_default {
+                                                button {
+                                                    class: "text-sm text-indigo-600 hover:text-indigo-800",
+                                                    onclick: {
+                                                        let (service, key_id) = (row.service.clone(), row.key_id.clone());
+                                                        move |_| make_default(service.clone(), key_id.clone())
+                                                    },
+                                                    "Make default"
+                                                }
+                                            }
+                                            button {
+                                                class: "text-sm text-red-600 hover:text-red-800",
+                                                onclick: {
+                                                    let row = row.clone();
+                                                    move |_| confirm_delete.set(Some(row.clone()))
+                                                },
+                                                "Delete"
+                                            }
                                         }
                                     }
                                 }
@@ -141,34 +386,63 @@ pub fn Settings() -> Element {
                     
                         form {
                             onsubmit: move |_evt| {
-                                let name = LocalStorage::get("new_api_key_name").unwrap_or_default();
-                                let value = LocalStorage::get("new_api_key_value").unwrap_or_default();
-                                save_api_key(name, value);
+                                save_api_key();
                             },
 
                             div { class: "grid grid-cols-1 gap-y-4",
                                 div {
-                                    label { class: "block text-sm font-medium text-gray-700", "API Key Name" }
-                                    input {
-                                        r#type: "text",
+                                    label { class: "block text-sm font-medium text-gray-700", "Service" }
+                                    select {
                                         class: "mt-1 block w-full shadow-sm sm:text-sm border border-gray-300 rounded-md",
                                         onchange: move |evt| {
-                                            LocalStorage::set("new_api_key_name", evt.value()).unwrap_or_else(|_| ());
+                                            if let Some(service) = ServiceKind::parse(&evt.value()) {
+                                                form.write().service = service;
+                                            }
+                                        },
+                                        for service in ServiceKind::ALL {
+                                            option {
+                                                value: service.as_str(),
+                                                selected: form.read().service == service,
+                                                "{service.as_str()}"
+                                            }
                                         }
                                     }
                                 }
                                 div {
-                                    label { class: "block text-sm font-medium text-gray-700", "API Key Value" }
+                                    label { class: "block text-sm font-medium text-gray-700", "Key ID (optional)" }
                                     input {
                                         r#type: "text",
+                                        placeholder: "default",
                                         class: "mt-1 block w-full shadow-sm sm:text-sm border border-gray-300 rounded-md",
-                                        onchange: move |evt| {
-                                            LocalStorage::set("new_api_key_value", evt.value()).unwrap_or_else(|_| ());
+                                        value: "{form.read().key_id}",
+                                        oninput: move |evt| form.write().key_id = evt.value()
+                                    }
+                                }
+                                div {
+                                    label { class: "block text-sm font-medium text-gray-700", "API Key Value" }
+                                    div { class: "mt-1 flex",
+                                        input {
+                                            r#type: if form.read().reveal { "text" } else { "password" },
+                                            autocomplete: "off",
+                                            spellcheck: "false",
+                                            class: "block w-full shadow-sm sm:text-sm border border-gray-300 rounded-l-md",
+                                            value: "{form.read().value}",
+                                            oninput: move |evt| form.write().value = evt.value()
+                                        }
+                                        button {
+                                            r#type: "button",
+                                            class: "px-3 border border-l-0 border-gray-300 rounded-r-md text-sm text-gray-600 hover:bg-gray-50",
+                                            onclick: move |_| form.with_mut(|form| form.reveal = !form.reveal),
+                                            if form.read().reveal { "Hide" } else { "Show" }
                                         }
                                     }
                                 }
                             }
 
+                            if let Some(problem) = *form_error.read() {
+                                p { class: "mt-2 text-sm text-red-600", "{problem}" }
+                            }
+
                             div { class: "pt-5",
                                 div { class: "flex justify-end",
                                     button {
@@ -185,3 +459,98 @@ pub fn Settings() -> Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage;
+    use chrono::{Duration, Utc};
+
+    fn api_key(service: &str, key_id: &str, days_until_expiry: Option<i64>) -> ApiKey {
+        let now = Utc::now();
+        ApiKey {
+            service: service.to_string(),
+            key_id: key_id.to_string(),
+            created_at: now - Duration::days(30),
+            expires_at: days_until_expiry.map(|days| now + Duration::days(days)),
+            days_until_expiry,
+            expired: days_until_expiry.is_some_and(|days| days < 0),
+            fingerprint: "3f9a1c2b".to_string(),
+            masked_hint: "••••wxyz".to_string(),
+            last_used_at: None,
+            is_default: key_id == "default",
+        }
+    }
+
+    #[test]
+    fn test_keys_are_listed_by_hint_and_fingerprint() {
+        let row = KeyRow::from(&api_key("fathom", "default", Some(3)));
+        assert_eq!((row.service.as_str(), row.key_id.as_str()), ("fathom", "default"));
+        assert_eq!(row.hint, "••••wxyz · 3f9a1c2b");
+        assert!(row.dates.contains("(in 3 days)"), "{}", row.dates);
+        assert_eq!(row.badge, Some(ExpiryBadge::Soon(3)));
+        assert!(row.is_default);
+    }
+
+    #[test]
+    fn test_a_deleted_key_leaves_the_list_and_a_failed_delete_puts_it_back() {
+        let mut keys = vec![api_key("fathom", "default", None), api_key("fathom", "team", None), api_key("loom", "default", None)];
+
+        // Deleted: the row is gone for good
+        let removed = take_key(&mut keys, "fathom", "team").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(take_key(&mut keys, "fathom", "team").is_none());
+
+        // Failed: back where it was
+        restore_key(&mut keys, removed);
+        let order: Vec<_> = keys.iter().map(|key| (key.service.as_str(), key.key_id.as_str())).collect();
+        assert_eq!(order, [("fathom", "default"), ("fathom", "team"), ("loom", "default")]);
+
+        // Even if the list shrank meanwhile
+        let removed = take_key(&mut keys, "loom", "default").unwrap();
+        keys.clear();
+        restore_key(&mut keys, removed);
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn test_expiry_badges_by_days_left() {
+        let badge = |days| ExpiryBadge::of(&api_key("loom", "default", days));
+        assert_eq!(badge(None), None);
+        assert_eq!(badge(Some(UPCOMING_DAYS + 1)), None);
+        assert_eq!(badge(Some(UPCOMING_DAYS)), Some(ExpiryBadge::Upcoming(UPCOMING_DAYS)));
+        assert_eq!(badge(Some(SOON_DAYS + 1)), Some(ExpiryBadge::Upcoming(SOON_DAYS + 1)));
+        assert_eq!(badge(Some(SOON_DAYS)), Some(ExpiryBadge::Soon(SOON_DAYS)));
+        assert_eq!(badge(Some(0)).unwrap().label(), "Expires today");
+        assert_eq!(badge(Some(1)).unwrap().label(), "Expires in 1 day");
+        assert_eq!(badge(Some(-2)), Some(ExpiryBadge::Expired));
+        assert_eq!(badge(Some(-2)).unwrap().label(), "Expired");
+    }
+
+    #[test]
+    fn test_the_add_form_builds_the_key_request() {
+        let request = key_request(ServiceKind::Loom, "  team ", " lk_123 ").unwrap();
+        assert_eq!((request.service, request.key_id.as_deref(), request.value.as_str()), (ServiceKind::Loom, Some("team"), "lk_123"));
+        assert_eq!(key_request(ServiceKind::Fathom, "", "fk_123").unwrap().key_id, None, "the default key");
+
+        assert_eq!(key_request(ServiceKind::Fathom, "", "  ").unwrap_err(), "API key value is required");
+        assert!(key_request(ServiceKind::Fathom, "has space", "fk_123").is_err());
+    }
+
+    #[test]
+    fn test_submitting_and_clearing_the_form_never_touches_storage() {
+        let mut form = KeyForm {
+            service: ServiceKind::Loom,
+            key_id: "team".to_string(),
+            value: "lk_123".to_string(),
+            reveal: true,
+        };
+        assert_eq!(form.request().unwrap().value, "lk_123");
+        form.clear();
+        assert_eq!(form, KeyForm { service: ServiceKind::Loom, ..KeyForm::default() });
+
+        form.value = " ".to_string();
+        assert!(form.request().is_err());
+        assert_eq!(storage::calls(), Vec::<String>::new());
+    }
+}
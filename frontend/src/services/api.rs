@@ -1,10 +1,17 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::{DeserializeOwned, IgnoredAny}, Deserialize, Serialize};
 use gloo_net::http::Request;
 use anyhow::{Result, anyhow};
 use crate::config::get_config;
 use crate::services::auth::AuthService;
+use crate::services::response::{retry_once, Answer};
+use crate::services::session::RefreshGate;
+use dioxus::prelude::*;
+use gloo_net::http::Response;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use common::{ErrorCode, ServiceKind};
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Meeting {
@@ -18,6 +25,15 @@ pub struct Meeting {
 pub struct MeetingRequest {
     pub user_id: String,
     pub topic: String,
+    /// Fathom recording, so queuing it twice keeps one entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meeting_id: Option<String>,
+    /// Fathom key to use instead of the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fathom_key_id: Option<String>,
+    /// Loom key to use instead of the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loom_key_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,44 +43,173 @@ pub struct QueueResponse {
     pub data: Option<Vec<Meeting>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FathomMeeting {
-    pub id: String,
-    pub title: String,
-    pub start_time: String,
-    pub duration: u32,
-    pub participants: Vec<String>,
+/// How the backend answered a queue reorder
+#[derive(Debug, Clone)]
+pub enum Reordered {
+    Moved(QueueResponse),
+    /// The queue changed since the version the move was made against
+    Conflict,
 }
 
+pub use crate::services::response::ApiError;
+
+/// The backend's own types, so the two can't drift apart
+pub use common::fathom::{DownloadCheck, FathomMeeting};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MeetingsResponse {
     pub success: bool,
     pub meetings: Vec<FathomMeeting>,
-    pub total: u32,
+    /// Counted for the first page only
+    #[serde(default)]
+    pub total: Option<u32>,
+    /// Pass as `cursor` for the next page; absent on the last
+    #[serde(default)]
+    pub next_cursor: Option<String>,
     pub cached: bool,
+    /// Each filter asked for, and whether `fathom` or the backend (`local`)
+    /// applied it
+    #[serde(default)]
+    pub filters_applied: std::collections::HashMap<String, String>,
+}
+
+thread_local! {
+    /// The last meetings listing of each endpoint with its ETag, reused when
+    /// the backend answers 304
+    static LISTINGS: RefCell<HashMap<String, (String, MeetingsResponse)>> = RefCell::new(HashMap::new());
+}
+
+fn remember_listing(endpoint: String, etag: Option<String>, listing: &MeetingsResponse) {
+    LISTINGS.with(|listings| {
+        let mut listings = listings.borrow_mut();
+        match etag {
+            Some(etag) => listings.insert(endpoint, (etag, listing.clone())),
+            None => listings.remove(&endpoint),
+        };
+    });
+}
+
+/// `path` with `params` as its query string
+fn with_query(path: &str, params: &[String]) -> String {
+    match params.is_empty() {
+        true => path.to_string(),
+        false => format!("{}?{}", path, params.join("&")),
+    }
+}
+
+/// What the Recordings page narrows the listing to; unset fields don't filter
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeetingFilters {
+    /// Part of the title, any case
+    pub q: Option<String>,
+    /// Recorded on or after, `YYYY-MM-DD`
+    pub from: Option<String>,
+    /// Recorded on or before, `YYYY-MM-DD`
+    pub to: Option<String>,
+    /// Name or email of an invitee
+    pub participant: Option<String>,
+    pub min_duration_secs: Option<u32>,
+}
+
+impl MeetingFilters {
+    /// The filters as query parameters, already encoded
+    fn query_params(&self) -> Vec<String> {
+        let mut url = reqwest::Url::parse("http://filters/").expect("a valid base");
+        url.query_pairs_mut().extend_pairs(self.set());
+        match url.query() {
+            Some(query) if !query.is_empty() => query.split('&').map(str::to_string).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The filters that are set, by query parameter
+    fn set(&self) -> Vec<(&'static str, String)> {
+        let mut set = Vec::new();
+        for (name, value) in [("q", &self.q), ("from", &self.from), ("to", &self.to), ("participant", &self.participant)] {
+            if let Some(value) = value.as_deref().map(str::trim).filter(|value| !value.is_empty()) {
+                set.push((name, value.to_string()));
+            }
+        }
+        if let Some(secs) = self.min_duration_secs {
+            set.push(("min_duration_secs", secs.to_string()));
+        }
+        set
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set().is_empty()
+    }
+}
+
+/// The filters as a query string, as the Recordings URL carries them
+impl std::fmt::Display for MeetingFilters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.query_params().join("&"))
+    }
+}
+
+/// Read back from a query string, ignoring parameters that aren't filters
+impl From<&str> for MeetingFilters {
+    fn from(query: &str) -> Self {
+        let mut filters = Self::default();
+        let Ok(url) = reqwest::Url::parse(&format!("http://filters/?{}", query)) else {
+            return filters;
+        };
+        for (name, value) in url.query_pairs() {
+            let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+            match &*name {
+                "q" => filters.q = value,
+                "from" => filters.from = value,
+                "to" => filters.to = value,
+                "participant" => filters.participant = value,
+                "min_duration_secs" => filters.min_duration_secs = value.and_then(|secs| secs.parse().ok()),
+                _ => {}
+            }
+        }
+        filters
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiKey {
-    pub id: String,
-    pub name: String,
-    pub encrypted_value: String,
+    pub service: String,
+    pub key_id: String,
     pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub days_until_expiry: Option<i64>,
+    pub expired: bool,
+    pub fingerprint: String,
+    pub masked_hint: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Used for its service whenever a meeting doesn't name a key
+    #[serde(default)]
+    pub is_default: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ApiKeyRequest {
-    pub name: String,
+pub struct PutKeyRequest {
+    pub service: ServiceKind,
+    /// Defaults to the service's `default` key
+    pub key_id: Option<String>,
     pub value: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+thread_local! {
+    /// Shared by every `ApiService`, so a burst of 401s refreshes once
+    static REFRESH: RefreshGate = RefreshGate::default();
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Shown on the Login page when a refresh couldn't keep the session going
+pub const SESSION_EXPIRED: &str = "Your session has expired, please log in again";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ApiService {
-    auth_service: AuthService,
+    auth_service: Signal<AuthService>,
 }
 
 impl ApiService {
-    pub fn new(auth_service: AuthService) -> Self {
+    pub fn new(auth_service: Signal<AuthService>) -> Self {
         Self { auth_service }
     }
 
@@ -73,145 +218,208 @@ impl ApiService {
         Ok(config.api.base_url.clone())
     }
 
-    fn create_authenticated_request_builder(&self, method: &str, endpoint: &str) -> Result<gloo_net::http::RequestBuilder> {
+    fn create_authenticated_request_builder(&self, method: &str, endpoint: &str, token: Option<&str>) -> Result<gloo_net::http::RequestBuilder> {
         let base_url = self.get_base_url()?;
-        let url = format!("{}/api{}", base_url, endpoint);
+        let url = format!("{}{}{}", base_url, common::API_PREFIX, endpoint);
         
         let mut request = match method {
             "GET" => Request::get(&url),
             "POST" => Request::post(&url),
             "PUT" => Request::put(&url),
+            "PATCH" => Request::patch(&url),
             "DELETE" => Request::delete(&url),
             _ => return Err(anyhow!("Unsupported HTTP method: {}", method)),
         };
 
-        if let Some(auth_header) = self.auth_service.get_auth_header() {
-            request = request.header("Authorization", &auth_header);
+        if let Some(token) = token {
+            request = request.header("Authorization", &format!("Bearer {}", token));
         }
 
         Ok(request)
     }
 
-    // Queue management
-    pub async fn get_queue(&self) -> Result<QueueResponse> {
-        let request = self.create_authenticated_request_builder("GET", "/queue")?
-            .build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to get queue: {}", e))?;
-
-        if !response.ok() {
-            return Err(anyhow!("Get queue failed: {}", response.status()));
-        }
-
-        response.json().await
-            .map_err(|e| anyhow!("Failed to parse queue response: {}", e))
+    /// Send the request `build` makes with the session token, refreshing the
+    /// token and sending it again if the backend answers 401
+    ///
+    /// When the refresh fails the session is cleared and marked expired,
+    /// which sends the user to log in.
+    async fn send(&self, method: &str, build: impl Fn(Option<&str>) -> Result<Request>) -> Result<Response, ApiError> {
+        let token = self.auth_service.read().get_token().cloned();
+        let mut auth_service = self.auth_service;
+        let refresh = move || async move {
+            let mut auth = auth_service.read().clone();
+            let renewal = match auth.refresh().await {
+                Ok(()) => auth.get_token().cloned().ok_or_else(|| "Refresh gave no token".to_string()),
+                Err(e) => {
+                    auth.expire();
+                    Err(e.to_string())
+                }
+            };
+            auth_service.set(auth);
+            renewal
+        };
+        let gate = REFRESH.with(RefreshGate::clone);
+        let idempotent = method == "GET";
+        let send = |token: Option<String>| {
+            let build = &build;
+            async move {
+                retry_once(idempotent, || async {
+                    build(token.as_deref())?.send().await.map_err(|e| anyhow!(e))
+                })
+                .await
+            }
+        };
+        gate.send(token, send, refresh)
+            .await
+            .map_err(|e| ApiError::local(e.to_string()))?
+            .ok_or_else(|| ApiError {
+                code: Some(ErrorCode::InvalidToken),
+                status: Some(401),
+                ..ApiError::local(SESSION_EXPIRED)
+            })
     }
 
-    pub async fn add_to_queue(&self, meeting_request: MeetingRequest) -> Result<QueueResponse> {
-        let json_str = serde_json::to_string(&meeting_request)
-            .map_err(|e| anyhow!("Failed to serialize meeting request: {}", e))?;
-            
-        let request = self.create_authenticated_request_builder("POST", "/queue")?
-            .header("Content-Type", "application/json")
-            .body(json_str);
-            
-        let request = request.build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
-
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to add to queue: {}", e))?;
-
-        if !response.ok() {
-            return Err(anyhow!("Add to queue failed: {}", response.status()));
-        }
+    /// Send `body`, if any, as JSON with `headers` to `endpoint` and take
+    /// whatever comes back
+    async fn exchange(&self, method: &str, endpoint: &str, body: Option<String>, headers: &[(&str, &str)]) -> Result<Answer, ApiError> {
+        let response = self.send(method, |token| {
+            let mut builder = self.create_authenticated_request_builder(method, endpoint, token)?;
+            for (name, value) in headers {
+                builder = builder.header(name, value);
+            }
+            match &body {
+                Some(body) => builder.header("Content-Type", "application/json").body(body.as_str()),
+                None => builder.build(),
+            }
+            .map_err(|e| anyhow!("Failed to build request: {}", e))
+        }).await?;
+
+        let headers = response.headers();
+        let status = response.status();
+        let body = response.text().await
+            .map_err(|e| ApiError::local(format!("Failed to read the response ({}): {}", status, e)))?;
+        Ok(Answer {
+            status,
+            etag: headers.get("etag"),
+            request_id: headers.get("x-request-id"),
+            body,
+        })
+    }
 
-        response.json().await
-            .map_err(|e| anyhow!("Failed to parse add queue response: {}", e))
+    /// Call `endpoint` and read its answer as a `T`
+    async fn request<T: DeserializeOwned>(&self, method: &str, endpoint: &str, body: Option<String>) -> Result<T, ApiError> {
+        self.exchange(method, endpoint, body, &[]).await?.parse()
     }
 
-    pub async fn remove_from_queue(&self, meeting_id: Uuid) -> Result<QueueResponse> {
-        let endpoint = format!("/queue/{}", meeting_id);
-        let request = self.create_authenticated_request_builder("DELETE", &endpoint)?
-            .build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
+    // Queue management
+    pub async fn get_queue(&self) -> Result<QueueResponse, ApiError> {
+        self.request("GET", "/queue", None).await
+    }
 
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to remove from queue: {}", e))?;
+    pub async fn add_to_queue(&self, meeting_request: MeetingRequest) -> Result<QueueResponse, ApiError> {
+        self.request("POST", "/queue", Some(json(&meeting_request)?)).await
+    }
 
-        if !response.ok() {
-            return Err(anyhow!("Remove from queue failed: {}", response.status()));
+    /// Move a meeting to `new_position`, counting from 1, in the queue whose
+    /// version is `queue_version`
+    pub async fn reorder_queue_item(&self, meeting_id: Uuid, new_position: usize, queue_version: &str) -> Result<Reordered, ApiError> {
+        let endpoint = format!("/queue/{}/position", meeting_id);
+        let body = serde_json::json!({ "position": new_position, "queue_version": queue_version }).to_string();
+        match self.request("PATCH", &endpoint, Some(body)).await {
+            Ok(queue) => Ok(Reordered::Moved(queue)),
+            Err(e) if e.code == Some(ErrorCode::QueueChanged) => Ok(Reordered::Conflict),
+            Err(e) => Err(e),
         }
+    }
 
-        response.json().await
-            .map_err(|e| anyhow!("Failed to parse remove queue response: {}", e))
+    pub async fn remove_from_queue(&self, meeting_id: Uuid) -> Result<QueueResponse, ApiError> {
+        self.request("DELETE", &format!("/queue/{}", meeting_id), None).await
     }
 
     // Meetings (Fathom proxy)
-    pub async fn get_meetings(&self, limit: Option<u32>, offset: Option<u32>) -> Result<MeetingsResponse> {
-        let mut endpoint = "/meetings".to_string();
+    /// The first page of `limit` meetings, or the page `cursor` (a previous
+    /// page's `next_cursor`) points at, which keeps the first page's size;
+    /// every page of a listing must be asked for with the same `filters`
+    pub async fn get_meetings(&self, limit: Option<u32>, cursor: Option<&str>, filters: &MeetingFilters) -> Result<MeetingsResponse, ApiError> {
         let mut params = Vec::new();
         
-        if let Some(limit) = limit {
+        if let Some(cursor) = cursor {
+            params.push(format!("cursor={}", String::from(js_sys::encode_uri_component(cursor))));
+        } else if let Some(limit) = limit {
             params.push(format!("limit={}", limit));
         }
-        if let Some(offset) = offset {
-            params.push(format!("offset={}", offset));
-        }
-        
-        if !params.is_empty() {
-            endpoint.push('?');
-            endpoint.push_str(&params.join("&"));
+        params.extend(filters.query_params());
+        let endpoint = with_query("/meetings", &params);
+
+        // Unchanged listings come back as a bodyless 304
+        let known = LISTINGS.with(|listings| listings.borrow().get(&endpoint).cloned());
+        let headers: Vec<(&str, &str)> = known.iter().map(|(etag, _)| ("If-None-Match", etag.as_str())).collect();
+        let answer = self.exchange("GET", &endpoint, None, &headers).await?;
+
+        if answer.status == 304 {
+            if let Some((_, listing)) = known {
+                return Ok(listing);
+            }
         }
+        // Including when the Fathom key needs fixing in Settings
+        let listing: MeetingsResponse = answer.parse()?;
+        remember_listing(endpoint, answer.etag, &listing);
+        Ok(listing)
+    }
 
-        let request = self.create_authenticated_request_builder("GET", &endpoint)?
-            .build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to get meetings: {}", e))?;
-
-        if !response.ok() {
-            return Err(anyhow!("Get meetings failed: {}", response.status()));
+    /// The first page fetched from Fathom now, dropping the cached listings
+    pub async fn refresh_meetings(&self, limit: Option<u32>, filters: &MeetingFilters) -> Result<MeetingsResponse, ApiError> {
+        let mut params = Vec::new();
+        if let Some(limit) = limit {
+            params.push(format!("limit={}", limit));
         }
+        params.extend(filters.query_params());
+        let endpoint = with_query("/meetings/refresh", &params);
+
+        // Including when refreshed too recently
+        let answer = self.exchange("POST", &endpoint, None, &[]).await?;
+        let listing: MeetingsResponse = answer.parse()?;
+        // It is what the same listing from get_meetings is now
+        remember_listing(with_query("/meetings", &params), answer.etag, &listing);
+        Ok(listing)
+    }
 
-        response.json().await
-            .map_err(|e| anyhow!("Failed to parse meetings response: {}", e))
+    /// Whether the recording `id` can still be downloaded from Fathom
+    pub async fn check_downloadable(&self, id: &str) -> Result<DownloadCheck, ApiError> {
+        let endpoint = format!("/meetings/{}/downloadable", String::from(js_sys::encode_uri_component(id)));
+        self.request("GET", &endpoint, None).await
     }
 
     // API Keys management
-    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
-        let request = self.create_authenticated_request_builder("GET", "/keys")?
-            .build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to get API keys: {}", e))?;
-
-        if !response.ok() {
-            return Err(anyhow!("Get API keys failed: {}", response.status()));
-        }
-
-        response.json().await
-            .map_err(|e| anyhow!("Failed to parse API keys response: {}", e))
+    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>, ApiError> {
+        self.request("GET", "/keys", None).await
     }
 
-    pub async fn save_api_key(&self, api_key_request: ApiKeyRequest) -> Result<()> {
-        let json_str = serde_json::to_string(&api_key_request)
-            .map_err(|e| anyhow!("Failed to serialize API key request: {}", e))?;
-            
-        let request = self.create_authenticated_request_builder("PUT", "/keys")?
-            .header("Content-Type", "application/json")
-            .body(json_str);
-            
-        let request = request.build()
-            .map_err(|e| anyhow!("Failed to build request: {}", e))?;
-
-        let response = request.send().await
-            .map_err(|e| anyhow!("Failed to save API key: {}", e))?;
+    pub async fn save_api_key(&self, api_key_request: PutKeyRequest) -> Result<ApiKey, ApiError> {
+        self.request("PUT", "/keys", Some(json(&api_key_request)?)).await
+    }
 
-        if !response.ok() {
-            return Err(anyhow!("Save API key failed: {}", response.status()));
-        }
+    pub async fn delete_api_key(&self, service: &str, key_id: &str) -> Result<(), ApiError> {
+        let endpoint = format!(
+            "/keys/{}/{}",
+            String::from(js_sys::encode_uri_component(service)),
+            String::from(js_sys::encode_uri_component(key_id))
+        );
+        self.request::<IgnoredAny>("DELETE", &endpoint, None).await.map(drop)
+    }
 
-        Ok(())
+    pub async fn set_default_key(&self, service: &str, key_id: &str) -> Result<ApiKey, ApiError> {
+        let endpoint = format!(
+            "/keys/{}/{}/default",
+            String::from(js_sys::encode_uri_component(service)),
+            String::from(js_sys::encode_uri_component(key_id))
+        );
+        self.request("PATCH", &endpoint, None).await
     }
 }
+
+/// `body` as the JSON a request sends
+fn json(body: &impl Serialize) -> Result<String, ApiError> {
+    serde_json::to_string(body).map_err(|e| ApiError::local(format!("Failed to serialize the request: {}", e)))
+}
+
@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
-use gloo_storage::{LocalStorage, Storage};
-use gloo_net::http::Request;
+use gloo_net::http::{Request, Response};
 use anyhow::{Result, anyhow};
+use common::{ApiResponse, AuthData, AuthUserInfo, ErrorCode};
 use crate::config::get_config;
+use crate::services::storage::{store, KeyValueStore};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoginRequest {
@@ -18,40 +19,76 @@ pub struct RegisterRequest {
     pub password_confirm: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AuthResponse {
-    pub token: String,
-    pub user: UserInfo,
+pub type UserInfo = AuthUserInfo;
+
+const TOKEN_KEY: &str = "auth_token";
+const USER_KEY: &str = "user_info";
+
+/// How the stored session fared when checked at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCheck {
+    /// No session was stored
+    SignedOut,
+    /// The stored token was swapped for a fresh one
+    Restored,
+    /// The stored token was refused, and has been cleared
+    Expired,
+    /// The server couldn't be reached; signed out until it can be, but the
+    /// stored session is kept for the next visit
+    Unreachable,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct UserInfo {
-    pub id: String,
-    pub email: String,
-    pub username: String,
+/// What `/auth/refresh` made of the stored token
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Renewed(String, UserInfo),
+    Refused,
+    Unreachable,
 }
 
-const TOKEN_KEY: &str = "auth_token";
-const USER_KEY: &str = "user_info";
+/// Judge the refresh answer, its status and parsed body, or the error that
+/// kept it from arriving
+fn judge(answer: Result<(u16, Result<AuthData>)>) -> Verdict {
+    match answer {
+        Err(_) => Verdict::Unreachable,
+        // Says nothing about the token, only that the server can't answer now
+        Ok((status, _)) if status == 429 || status >= 500 => Verdict::Unreachable,
+        Ok((_, Ok(AuthData { token: Some(token), user, .. }))) => Verdict::Renewed(token, user),
+        Ok(_) => Verdict::Refused,
+    }
+}
+
+/// The JSON value stored under `key`, if there is a readable one
+fn stored<T: serde::de::DeserializeOwned>(key: &str) -> Option<T> {
+    serde_json::from_str(&store().get(key)?).ok()
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AuthService {
     token: Option<String>,
     user: Option<UserInfo>,
+    /// Signed out because the token couldn't be refreshed, not by the user
+    session_expired: bool,
 }
 
 impl AuthService {
     pub fn new() -> Self {
-        let token = LocalStorage::get(TOKEN_KEY).ok();
-        let user = LocalStorage::get(USER_KEY).ok();
+        let token = stored(TOKEN_KEY);
+        let user = stored(USER_KEY);
         
-        Self { token, user }
+        Self { token, user, session_expired: false }
     }
 
     pub fn is_authenticated(&self) -> bool {
         self.token.is_some() && self.user.is_some()
     }
 
+    /// Whether the session token carries the `admin` role; the backend
+    /// checks it again on every request, so this only decides what to show
+    pub fn is_admin(&self) -> bool {
+        self.token.as_deref().and_then(token_role).is_some_and(|role| role == "admin")
+    }
+
     pub fn get_token(&self) -> Option<&String> {
         self.token.as_ref()
     }
@@ -70,28 +107,16 @@ impl AuthService {
             .await
             .map_err(|e| anyhow!("Login request failed: {}", e))?;
 
-        if !response.ok() {
-            return Err(anyhow!("Login failed: {}", response.status()));
-        }
-
-        let auth_response: AuthResponse = response
-            .json()
-            .await
-            .map_err(|e| anyhow!("Failed to parse login response: {}", e))?;
-
-        // Store token and user info
-        LocalStorage::set(TOKEN_KEY, &auth_response.token)
-            .map_err(|e| anyhow!("Failed to store token: {:?}", e))?;
-        LocalStorage::set(USER_KEY, &auth_response.user)
-            .map_err(|e| anyhow!("Failed to store user info: {:?}", e))?;
-
-        self.token = Some(auth_response.token);
-        self.user = Some(auth_response.user);
-
-        Ok(())
+        let auth = parse_auth_response(response).await?;
+        let token = auth.token.ok_or_else(|| anyhow!("Login response had no token"))?;
+        self.store_session(token, auth.user)
     }
 
-    pub async fn register(&mut self, request: RegisterRequest) -> Result<()> {
+    /// Create an account, signing in if the server issued a token
+    ///
+    /// Returns whether the user is now signed in; if not, the account exists
+    /// and they should log in.
+    pub async fn register(&mut self, request: RegisterRequest) -> Result<bool> {
         let config = get_config().ok_or_else(|| anyhow!("Configuration not loaded"))?;
         let url = format!("{}/auth/register", config.api.base_url);
 
@@ -101,37 +126,211 @@ impl AuthService {
             .await
             .map_err(|e| anyhow!("Registration request failed: {}", e))?;
 
-        if !response.ok() {
-            return Err(anyhow!("Registration failed: {}", response.status()));
+        let auth = parse_auth_response(response).await?;
+        match auth.token {
+            Some(token) => self.store_session(token, auth.user).map(|()| true),
+            None => Ok(false),
         }
+    }
+
+    /// Swap the stored token for a fresh one; on failure the session is cleared
+    pub async fn refresh(&mut self) -> Result<()> {
+        let config = get_config().ok_or_else(|| anyhow!("Configuration not loaded"))?;
+        let url = format!("{}/auth/refresh", config.api.base_url);
+        let auth_header = self
+            .get_auth_header()
+            .ok_or_else(|| anyhow!("Not logged in"))?;
 
-        let auth_response: AuthResponse = response
-            .json()
+        let response = Request::post(&url)
+            .header("Authorization", &auth_header)
+            .send()
             .await
-            .map_err(|e| anyhow!("Failed to parse registration response: {}", e))?;
+            .map_err(|e| anyhow!("Refresh request failed: {}", e))?;
+
+        let (token, user) = match parse_auth_response(response).await {
+            Ok(AuthData { token: Some(token), user, .. }) => (token, user),
+            Ok(_) => {
+                self.logout();
+                return Err(anyhow!("Refresh response had no token"));
+            }
+            Err(e) => {
+                self.logout();
+                return Err(anyhow!("Session expired: {}", e));
+            }
+        };
+        self.store_session(token, user)
+    }
+
+    /// Check the session found in storage with the server before trusting it
+    pub async fn validate_session(&mut self) -> SessionCheck {
+        let Some(auth_header) = self.get_auth_header().filter(|_| self.is_authenticated()) else {
+            self.logout();
+            return SessionCheck::SignedOut;
+        };
+        let answer = match get_config() {
+            Some(config) => {
+                let url = format!("{}/auth/refresh", config.api.base_url);
+                match Request::post(&url).header("Authorization", &auth_header).send().await {
+                    Ok(response) => {
+                        let status = response.status();
+                        Ok((status, parse_auth_response(response).await))
+                    }
+                    Err(e) => Err(anyhow!("Refresh request failed: {}", e)),
+                }
+            }
+            None => Err(anyhow!("Configuration not loaded")),
+        };
+
+        match judge(answer) {
+            Verdict::Renewed(token, user) => match self.store_session(token, user) {
+                Ok(()) => SessionCheck::Restored,
+                Err(e) => {
+                    tracing::warn!("Could not keep the restored session: {}", e);
+                    self.logout();
+                    SessionCheck::Expired
+                }
+            },
+            Verdict::Refused => {
+                self.expire();
+                SessionCheck::Expired
+            }
+            Verdict::Unreachable => {
+                tracing::warn!("Could not reach the server to check the stored session");
+                self.token = None;
+                self.user = None;
+                SessionCheck::Unreachable
+            }
+        }
+    }
 
-        // Store token and user info
-        LocalStorage::set(TOKEN_KEY, &auth_response.token)
-            .map_err(|e| anyhow!("Failed to store token: {:?}", e))?;
-        LocalStorage::set(USER_KEY, &auth_response.user)
-            .map_err(|e| anyhow!("Failed to store user info: {:?}", e))?;
+    fn store_session(&mut self, token: String, user: UserInfo) -> Result<()> {
+        store().set(TOKEN_KEY, &serde_json::to_string(&token)?)?;
+        store().set(USER_KEY, &serde_json::to_string(&user)?)?;
 
-        self.token = Some(auth_response.token);
-        self.user = Some(auth_response.user);
+        self.token = Some(token);
+        self.user = Some(user);
+        self.session_expired = false;
 
         Ok(())
     }
 
     pub fn logout(&mut self) {
         // Clear stored data
-        let _ = LocalStorage::delete(TOKEN_KEY);
-        let _ = LocalStorage::delete(USER_KEY);
+        store().delete(TOKEN_KEY);
+        store().delete(USER_KEY);
         
         self.token = None;
         self.user = None;
     }
 
+    /// Sign out because the session ran out
+    pub fn expire(&mut self) {
+        self.logout();
+        self.session_expired = true;
+    }
+
+    pub fn session_expired(&self) -> bool {
+        self.session_expired
+    }
+
     pub fn get_auth_header(&self) -> Option<String> {
         self.token.as_ref().map(|token| format!("Bearer {}", token))
     }
 }
+
+/// The `role` claim of a JWT, read without checking its signature
+fn token_role(token: &str) -> Option<String> {
+    let mut payload = token.split('.').nth(1)?.replace('-', "+").replace('_', "/");
+    while payload.len() % 4 != 0 {
+        payload.push('=');
+    }
+    let json = gloo_utils::window().atob(&payload).ok()?;
+    let claims: serde_json::Value = serde_json::from_str(&json).ok()?;
+    claims.get("role")?.as_str().map(String::from)
+}
+
+/// Unwrap the `ApiResponse<AuthData>` envelope the auth endpoints reply with
+///
+/// Failures become the server's message, except where the code calls for
+/// wording of our own.
+async fn parse_auth_response(response: Response) -> Result<AuthData> {
+    let status = response.status();
+    let envelope: ApiResponse<AuthData> = response
+        .json()
+        .await
+        .map_err(|_| anyhow!("Unexpected response from server ({})", status))?;
+
+    if let (true, Some(data)) = (envelope.success, envelope.data) {
+        return Ok(data);
+    }
+    let message = envelope.error.unwrap_or_else(|| format!("Request failed ({})", status));
+    Err(match envelope.code {
+        Some(ErrorCode::ServiceUnavailable) => anyhow!("The server is unavailable, please try again shortly"),
+        Some(ErrorCode::EmailExists) => anyhow!("An account with this email already exists, try signing in"),
+        _ => anyhow!(message),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage;
+
+    fn user() -> UserInfo {
+        UserInfo {
+            id: "u1".to_string(),
+            email: "ana@example.com".to_string(),
+            username: "ana".to_string(),
+            name: None,
+            verified: true,
+        }
+    }
+
+    fn auth(token: Option<&str>) -> AuthData {
+        AuthData {
+            token: token.map(String::from),
+            user: user(),
+            message: "Token refreshed".to_string(),
+            instance: None,
+        }
+    }
+
+    #[test]
+    fn test_the_session_is_kept_in_storage_until_logout() {
+        let mut auth = AuthService::new();
+        assert!(!auth.is_authenticated());
+
+        auth.store_session("token".to_string(), user()).unwrap();
+        assert_eq!(storage::store().get(TOKEN_KEY).as_deref(), Some("\"token\""));
+        assert_eq!(AuthService::new().get_user(), Some(&user()));
+
+        auth.logout();
+        assert!(!AuthService::new().is_authenticated());
+        assert!(storage::calls().contains(&format!("delete {}", USER_KEY)));
+    }
+
+    #[test]
+    fn test_a_valid_token_is_renewed() {
+        assert_eq!(
+            judge(Ok((200, Ok(auth(Some("fresh")))))),
+            Verdict::Renewed("fresh".to_string(), user())
+        );
+    }
+
+    #[test]
+    fn test_an_expired_token_is_cleared() {
+        assert_eq!(judge(Ok((401, Err(anyhow!("Invalid token"))))), Verdict::Refused);
+        assert_eq!(judge(Ok((200, Ok(auth(None))))), Verdict::Refused, "no token to keep");
+    }
+
+    #[test]
+    fn test_an_unreachable_server_signs_out_for_now() {
+        assert_eq!(judge(Err(anyhow!("Refresh request failed: NetworkError"))), Verdict::Unreachable);
+        assert_eq!(judge(Ok((503, Err(anyhow!("The server is unavailable"))))), Verdict::Unreachable);
+    }
+
+    #[test]
+    fn test_a_rate_limited_refresh_keeps_the_session() {
+        assert_eq!(judge(Ok((429, Err(anyhow!("Too many requests"))))), Verdict::Unreachable);
+    }
+}
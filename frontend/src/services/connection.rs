@@ -0,0 +1,214 @@
+//! Whether the live updates are getting through
+//!
+//! The Dashboard holds the WebSocket and reports to a [`Connection`] shared
+//! from the root, so the Layout can say when it has dropped. A dropped
+//! connection is retried at once, then with waits doubling from
+//! [`FIRST_RETRY_MS`], until `max_attempts` have failed; after that it stays
+//! offline until "Retry now" is pressed. Once connected again the caller
+//! refetches what it may have missed.
+
+use dioxus::prelude::*;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::time::Duration;
+
+/// Wait before the first retry after a failed attempt
+pub const FIRST_RETRY_MS: u64 = 1_000;
+
+/// Longest wait between attempts
+pub const MAX_RETRY_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The first connection, not yet made
+    Connecting,
+    Connected,
+    /// Attempt `attempt` at getting the connection back is being made or
+    /// waited for
+    Reconnecting { attempt: u32 },
+    /// Given up; only "Retry now" tries again
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub state: ConnectionState,
+    pub max_attempts: u32,
+    /// Whether the banner was closed, until it connects or gives up
+    dismissed: bool,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+/// How long to wait before attempt `attempt`
+pub fn retry_delay(attempt: u32) -> Duration {
+    let doubled = FIRST_RETRY_MS << attempt.saturating_sub(1).min(16);
+    Duration::from_millis(doubled.min(MAX_RETRY_MS))
+}
+
+impl Connection {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            state: ConnectionState::Connecting,
+            max_attempts,
+            dismissed: false,
+        }
+    }
+
+    fn enter(&mut self, state: ConnectionState) {
+        self.dismissed &= std::mem::discriminant(&self.state) == std::mem::discriminant(&state);
+        self.state = state;
+    }
+
+    /// Connected; whether it was a reconnection, after which what was missed
+    /// must be fetched
+    pub fn connected(&mut self) -> bool {
+        let again = matches!(self.state, ConnectionState::Reconnecting { .. } | ConnectionState::Offline);
+        self.enter(ConnectionState::Connected);
+        again
+    }
+
+    /// The connection dropped; the first attempt is made at once
+    pub fn lost(&mut self) {
+        self.enter(ConnectionState::Reconnecting { attempt: 1 });
+    }
+
+    /// The attempt being made failed; how long until the next, or `None`
+    /// having given up
+    pub fn failed(&mut self) -> Option<Duration> {
+        let attempt = match self.state {
+            ConnectionState::Connecting => 1,
+            ConnectionState::Reconnecting { attempt } if attempt < self.max_attempts => attempt + 1,
+            ConnectionState::Reconnecting { .. } => {
+                self.enter(ConnectionState::Offline);
+                return None;
+            }
+            ConnectionState::Connected | ConnectionState::Offline => return None,
+        };
+        self.enter(ConnectionState::Reconnecting { attempt });
+        Some(retry_delay(attempt))
+    }
+
+    /// "Retry now" was pressed; offline, the attempts start over
+    pub fn retry(&mut self) {
+        if self.state == ConnectionState::Offline {
+            self.enter(ConnectionState::Reconnecting { attempt: 1 });
+        }
+    }
+
+    pub fn dismiss(&mut self) {
+        self.dismissed = true;
+    }
+
+    /// What the banner says, if it shows
+    pub fn banner(&self) -> Option<String> {
+        if self.dismissed {
+            return None;
+        }
+        match self.state {
+            ConnectionState::Connecting | ConnectionState::Connected => None,
+            ConnectionState::Reconnecting { attempt } => Some(format!(
+                "Live updates lost. Reconnecting (attempt {} of {})...",
+                attempt, self.max_attempts
+            )),
+            ConnectionState::Offline => Some("You're offline: live updates stopped, so the queue may be out of date".to_string()),
+        }
+    }
+}
+
+/// The connection's state, and the way "Retry now" reaches whoever holds it
+#[derive(Clone, Copy)]
+pub struct LiveUpdates {
+    pub connection: Signal<Connection>,
+    retry: Signal<Option<UnboundedSender<()>>>,
+}
+
+impl LiveUpdates {
+    pub fn new(connection: Signal<Connection>, retry: Signal<Option<UnboundedSender<()>>>) -> Self {
+        Self { connection, retry }
+    }
+
+    /// Every "Retry now" pressed from now on, taking them from whoever had them
+    pub fn retries(&self) -> UnboundedReceiver<()> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut retry = self.retry;
+        retry.set(Some(sender));
+        receiver
+    }
+
+    pub fn retry_now(&self) {
+        if let Some(sender) = self.retry.peek().as_ref() {
+            let _ = sender.unbounded_send(());
+        }
+    }
+
+    pub fn dismiss(&self) {
+        let mut connection = self.connection;
+        connection.write().dismiss();
+    }
+}
+
+/// The live updates provided at the root
+pub fn use_live_updates() -> LiveUpdates {
+    use_context::<LiveUpdates>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_drop_is_retried_until_it_reconnects() {
+        let mut connection = Connection::new(3);
+        assert!(!connection.connected(), "the first connection is no reconnection");
+        assert_eq!(connection.banner(), None);
+
+        connection.lost();
+        assert_eq!(connection.state, ConnectionState::Reconnecting { attempt: 1 });
+        assert_eq!(connection.banner().unwrap(), "Live updates lost. Reconnecting (attempt 1 of 3)...");
+        assert_eq!(connection.failed(), Some(Duration::from_secs(2)));
+        assert_eq!(connection.state, ConnectionState::Reconnecting { attempt: 2 });
+
+        // Back, the queue is fetched again
+        assert!(connection.connected());
+        assert_eq!(connection.banner(), None);
+        assert!(!connection.connected());
+    }
+
+    #[test]
+    fn test_it_gives_up_after_the_last_attempt() {
+        let mut connection = Connection::new(3);
+        assert_eq!(connection.failed(), Some(Duration::from_secs(1)), "the first connection failed");
+        assert_eq!(connection.failed(), Some(Duration::from_secs(2)));
+        assert_eq!(connection.failed(), Some(Duration::from_secs(4)));
+        assert_eq!(connection.state, ConnectionState::Reconnecting { attempt: 3 });
+        assert_eq!(connection.failed(), None);
+        assert_eq!(connection.state, ConnectionState::Offline);
+        assert!(connection.banner().unwrap().starts_with("You're offline"));
+
+        // Only pressing "Retry now" starts the attempts over
+        assert_eq!(connection.failed(), None);
+        connection.retry();
+        assert_eq!(connection.state, ConnectionState::Reconnecting { attempt: 1 });
+        connection.retry();
+        assert_eq!(connection.state, ConnectionState::Reconnecting { attempt: 1 });
+        assert!(connection.connected(), "what was missed offline is fetched too");
+    }
+
+    #[test]
+    fn test_a_dismissed_banner_stays_closed_for_the_outage() {
+        let mut connection = Connection::new(2);
+        connection.lost();
+        connection.dismiss();
+        connection.failed();
+        assert_eq!(connection.banner(), None, "not every attempt shows it again");
+        connection.failed();
+        assert!(connection.banner().is_some(), "giving up does");
+
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(40), Duration::from_millis(MAX_RETRY_MS));
+    }
+}
@@ -1,3 +1,10 @@
 pub mod auth;
 pub mod api;
 pub mod websocket;
+pub mod session;
+pub mod response;
+pub mod toast;
+pub mod workers;
+pub mod progress;
+pub mod connection;
+pub mod storage;
@@ -0,0 +1,210 @@
+//! How far each queued meeting has got with the worker
+//!
+//! The worker reports a task's stage and percent as it goes, then whether it
+//! completed or failed. Reports can arrive out of order, so one older than
+//! what is already known is dropped, and a finished task stays finished
+//! unless a later run reports. An unfinished task not heard about for
+//! [`STALE_AFTER_MS`] is shown as awaiting an update. Arrival times are
+//! milliseconds since the epoch, passed in by the caller.
+
+use chrono::{DateTime, Utc};
+use common::broadcast::ProcessingStage;
+use std::collections::HashMap;
+use uuid::Uuid;
+use crate::services::websocket::WebSocketMessage;
+
+/// Silence after which an unfinished task is shown awaiting an update
+pub const STALE_AFTER_MS: u64 = 2 * 60_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Completed,
+    /// Failed for good, for this reason
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressState {
+    /// `None` when the outcome came before any progress
+    pub stage: Option<ProcessingStage>,
+    /// How much of `stage` is done, 0 to 100
+    pub percent: u8,
+    /// When the worker sent the latest report
+    pub reported_at: DateTime<Utc>,
+    /// When it arrived
+    pub updated_at: u64,
+    pub outcome: Option<Outcome>,
+}
+
+impl ProgressState {
+    /// Unfinished, and not heard about for [`STALE_AFTER_MS`] at `now`
+    pub fn is_stale(&self, now: u64) -> bool {
+        self.outcome.is_none() && now.saturating_sub(self.updated_at) >= STALE_AFTER_MS
+    }
+}
+
+/// Progress by the id of the task, which is the queued meeting's
+pub type ProgressByTask = HashMap<Uuid, ProgressState>;
+
+/// What a stage is called on the Dashboard
+pub fn stage_label(stage: ProcessingStage) -> &'static str {
+    match stage {
+        ProcessingStage::FetchingMetadata => "Fetching details",
+        ProcessingStage::Downloading => "Downloading",
+        ProcessingStage::Transcoding => "Transcoding",
+        ProcessingStage::Uploading => "Uploading to Loom",
+        ProcessingStage::Streaming => "Streaming to Loom",
+    }
+}
+
+/// Take what `message`, arrived at `now`, says about a task; whether it
+/// changed anything
+pub fn track(progress: &mut ProgressByTask, message: &WebSocketMessage, now: u64) -> bool {
+    match message {
+        WebSocketMessage::TaskProgress(update) => {
+            if let Some(known) = progress.get(&update.task_id) {
+                let outdated = match known.outcome {
+                    Some(_) => update.timestamp <= known.reported_at,
+                    None => update.timestamp < known.reported_at,
+                };
+                if outdated {
+                    return false;
+                }
+            }
+            progress.insert(update.task_id, ProgressState {
+                stage: Some(update.stage),
+                percent: update.percent.min(100),
+                reported_at: update.timestamp,
+                updated_at: now,
+                outcome: None,
+            });
+            true
+        }
+        WebSocketMessage::QueueUpdate(update) => {
+            let Some(task_id) = update.task_id else {
+                return false;
+            };
+            let outcome = match update.update_type.as_str() {
+                "task_completed" => Outcome::Completed,
+                "task_failed" => Outcome::Failed(update.error.clone().unwrap_or_else(|| "Processing failed".to_string())),
+                _ => return false,
+            };
+            let known = progress.remove(&task_id);
+            progress.insert(task_id, ProgressState {
+                stage: known.as_ref().and_then(|known| known.stage),
+                percent: match outcome {
+                    Outcome::Completed => 100,
+                    Outcome::Failed(_) => known.as_ref().map_or(0, |known| known.percent),
+                },
+                reported_at: known.map_or(update.timestamp, |known| known.reported_at.max(update.timestamp)),
+                updated_at: now,
+                outcome: Some(outcome),
+            });
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::websocket::QueueUpdate;
+    use chrono::Duration;
+    use common::broadcast::ProgressUpdate;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        "2026-10-14T09:00:00Z".parse::<DateTime<Utc>>().unwrap() + Duration::seconds(secs)
+    }
+
+    fn reported(task_id: Uuid, stage: ProcessingStage, percent: u8, secs: i64) -> WebSocketMessage {
+        WebSocketMessage::TaskProgress(ProgressUpdate {
+            task_id,
+            user_id: "ana".to_string(),
+            stage,
+            percent,
+            timestamp: at(secs),
+        })
+    }
+
+    fn ended(task_id: Uuid, update_type: &str, error: Option<&str>, secs: i64) -> WebSocketMessage {
+        WebSocketMessage::QueueUpdate(QueueUpdate {
+            update_type: update_type.to_string(),
+            queue: Vec::new(),
+            task_id: Some(task_id),
+            error: error.map(String::from),
+            timestamp: at(secs),
+        })
+    }
+
+    fn shown(progress: &ProgressByTask, task_id: Uuid) -> (Option<ProcessingStage>, u8, Option<Outcome>) {
+        let state = &progress[&task_id];
+        (state.stage, state.percent, state.outcome.clone())
+    }
+
+    #[test]
+    fn test_reports_follow_the_task_through_its_stages() {
+        let (task, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut progress = ProgressByTask::new();
+        let script = [
+            reported(task, ProcessingStage::Downloading, 40, 1),
+            reported(other, ProcessingStage::FetchingMetadata, 0, 2),
+            reported(task, ProcessingStage::Uploading, 10, 3),
+            ended(task, "task_completed", None, 4),
+        ];
+        for (n, message) in script.iter().enumerate() {
+            assert!(track(&mut progress, message, n as u64 * 1_000));
+        }
+        assert_eq!(shown(&progress, task), (Some(ProcessingStage::Uploading), 100, Some(Outcome::Completed)));
+        assert_eq!(shown(&progress, other), (Some(ProcessingStage::FetchingMetadata), 0, None));
+
+        // Queue changes of the Dashboard's own say nothing about progress
+        assert!(!track(&mut progress, &ended(task, "position_updated", None, 5), 5_000));
+        let moved = WebSocketMessage::QueueUpdate(QueueUpdate {
+            update_type: "task_completed".to_string(),
+            queue: Vec::new(),
+            task_id: None,
+            error: None,
+            timestamp: at(6),
+        });
+        assert!(!track(&mut progress, &moved, 6_000));
+    }
+
+    #[test]
+    fn test_late_reports_are_dropped() {
+        let task = Uuid::from_u128(1);
+        let mut progress = ProgressByTask::new();
+        track(&mut progress, &reported(task, ProcessingStage::Transcoding, 50, 10), 0);
+        assert!(!track(&mut progress, &reported(task, ProcessingStage::Downloading, 90, 5), 1_000));
+        assert_eq!(shown(&progress, task), (Some(ProcessingStage::Transcoding), 50, None));
+
+        // Failing keeps how far it got, and progress sent before doesn't undo it
+        track(&mut progress, &ended(task, "task_failed", Some("Loom API error: upload rejected"), 12), 2_000);
+        assert!(!track(&mut progress, &reported(task, ProcessingStage::Uploading, 20, 11), 3_000));
+        assert_eq!(
+            shown(&progress, task),
+            (Some(ProcessingStage::Transcoding), 50, Some(Outcome::Failed("Loom API error: upload rejected".to_string())))
+        );
+
+        // An outcome may come before any progress
+        let quiet = Uuid::from_u128(2);
+        track(&mut progress, &ended(quiet, "task_failed", None, 1), 0);
+        assert_eq!(shown(&progress, quiet), (None, 0, Some(Outcome::Failed("Processing failed".to_string()))));
+    }
+
+    #[test]
+    fn test_silent_tasks_go_stale_until_they_report() {
+        let task = Uuid::from_u128(1);
+        let mut progress = ProgressByTask::new();
+        track(&mut progress, &reported(task, ProcessingStage::Downloading, 40, 0), 1_000);
+        assert!(!progress[&task].is_stale(STALE_AFTER_MS));
+        assert!(progress[&task].is_stale(1_000 + STALE_AFTER_MS));
+
+        track(&mut progress, &reported(task, ProcessingStage::Downloading, 60, 130), 131_000);
+        assert!(!progress[&task].is_stale(131_000 + STALE_AFTER_MS - 1));
+
+        // Finished tasks have nothing more to say
+        track(&mut progress, &ended(task, "task_completed", None, 140), 140_000);
+        assert!(!progress[&task].is_stale(u64::MAX));
+    }
+}
@@ -0,0 +1,201 @@
+//! Reading the backend's answers
+//!
+//! Failures come back in the `ApiResponse` envelope with a message and, where
+//! the client can act on it, an `ErrorCode`. [`ApiError`] carries both with
+//! the status and the request id the backend logged, so a page can tell a
+//! rejected Fathom key from a full queue. Successes come either in the same
+//! envelope or, for listings and keys, as the bare value.
+//!
+//! A GET that got no answer is sent once more; other requests are not, as
+//! the backend may have carried them out.
+
+use common::{ApiResponse, ErrorCode};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+
+/// A call to the backend that failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub code: Option<ErrorCode>,
+    pub message: String,
+    /// `None` when no answer came
+    pub status: Option<u16>,
+    /// The backend's `X-Request-Id`, to find the request in its logs
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    /// A failure with no answer to go by
+    pub fn local(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            message: message.into(),
+            status: None,
+            request_id: None,
+        }
+    }
+
+    /// Whether a stored API key is to blame, which the user fixes in Settings
+    pub fn is_key_problem(&self) -> bool {
+        matches!(self.code, Some(ErrorCode::KeyExpired | ErrorCode::KeyRejected))
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// What came back for a request
+#[derive(Debug, Clone, Default)]
+pub struct Answer {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub request_id: Option<String>,
+    pub body: String,
+}
+
+impl Answer {
+    fn failure(&self, code: Option<ErrorCode>, message: String) -> ApiError {
+        ApiError {
+            code,
+            message,
+            status: Some(self.status),
+            request_id: self.request_id.clone(),
+        }
+    }
+
+    /// The body as a `T`, or the failure it reports
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        let succeeded = (200..300).contains(&self.status);
+        let body = match serde_json::from_str::<serde_json::Value>(&self.body) {
+            Ok(body) => body,
+            Err(_) if succeeded => {
+                return Err(self.failure(None, format!("Unexpected response from server ({})", self.status)));
+            }
+            Err(_) => return Err(self.failure(None, format!("Request failed ({})", self.status))),
+        };
+
+        // Only the envelope carries both
+        let enveloped = body.get("success").is_some() && body.get("timestamp").is_some();
+        if !enveloped {
+            return match succeeded {
+                true => serde_json::from_value(body).map_err(|e| {
+                    self.failure(None, format!("Unexpected response from server ({}): {}", self.status, e))
+                }),
+                false => Err(self.failure(None, format!("Request failed ({})", self.status))),
+            };
+        }
+        let envelope: ApiResponse<T> = serde_json::from_value(body).map_err(|e| {
+            self.failure(None, format!("Unexpected response from server ({}): {}", self.status, e))
+        })?;
+        match (succeeded && envelope.success, envelope.data) {
+            (true, Some(data)) => Ok(data),
+            (true, None) => Err(self.failure(None, format!("Unexpected response from server ({})", self.status))),
+            (false, _) => {
+                let message = envelope.error.unwrap_or_else(|| format!("Request failed ({})", self.status));
+                Err(self.failure(envelope.code, message))
+            }
+        }
+    }
+}
+
+/// Run `fetch`, and once more if an `idempotent` request got no answer
+pub async fn retry_once<T, E, F, Fut>(idempotent: bool, fetch: F) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    match fetch().await {
+        Err(e) if idempotent => {
+            tracing::warn!("Request got no answer, sending it again: {}", e);
+            fetch().await
+        }
+        answer => answer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::Cell;
+
+    fn answer(status: u16, body: &str) -> Answer {
+        Answer {
+            status,
+            request_id: Some("req-1".to_string()),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_successes_are_read_in_or_out_of_the_envelope() {
+        let bare: Vec<u32> = answer(200, "[1, 2]").parse().unwrap();
+        assert_eq!(bare, [1, 2]);
+
+        let enveloped: Vec<u32> = answer(
+            200,
+            r#"{"success": true, "data": [3], "error": null, "timestamp": "2026-10-14T09:00:00Z"}"#,
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(enveloped, [3]);
+    }
+
+    #[test]
+    fn test_failures_keep_the_code_status_and_request_id() {
+        let e = answer(
+            502,
+            r#"{"success": false, "data": null, "error": "Fathom rejected the API key",
+                "code": "key_rejected", "timestamp": "2026-10-14T09:00:00Z"}"#,
+        )
+        .parse::<Vec<u32>>()
+        .unwrap_err();
+        assert_eq!(e.code, Some(ErrorCode::KeyRejected));
+        assert_eq!(e.to_string(), "Fathom rejected the API key");
+        assert_eq!((e.status, e.request_id.as_deref()), (Some(502), Some("req-1")));
+        assert!(e.is_key_problem());
+
+        // Without an envelope there is only the status to go by
+        let e = answer(404, "Not Found").parse::<Vec<u32>>().unwrap_err();
+        assert_eq!((e.code, e.message.as_str()), (None, "Request failed (404)"));
+    }
+
+    #[test]
+    fn test_malformed_successes_are_failures() {
+        let e = answer(200, "<html>").parse::<Vec<u32>>().unwrap_err();
+        assert_eq!(e.message, "Unexpected response from server (200)");
+        let e = answer(200, r#"{"unexpected": true}"#).parse::<Vec<u32>>().unwrap_err();
+        assert_eq!(e.code, None);
+        assert!(!e.is_key_problem());
+    }
+
+    #[test]
+    fn test_only_gets_are_sent_again_and_only_once() {
+        let sent = Cell::new(0);
+        let flaky = || {
+            sent.set(sent.get() + 1);
+            let attempt = sent.get();
+            async move { if attempt == 1 { Err("Failed to fetch") } else { Ok(attempt) } }
+        };
+        assert_eq!(block_on(retry_once(true, flaky)), Ok(2));
+
+        sent.set(0);
+        assert_eq!(block_on(retry_once(false, flaky)), Err("Failed to fetch"));
+        assert_eq!(sent.get(), 1, "a POST may have been carried out");
+
+        let down = Cell::new(0);
+        let fetch = || {
+            down.set(down.get() + 1);
+            async { Err::<u32, _>("Failed to fetch") }
+        };
+        assert!(block_on(retry_once(true, fetch)).is_err());
+        assert_eq!(down.get(), 2);
+    }
+}
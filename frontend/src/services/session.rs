@@ -0,0 +1,163 @@
+//! Keeping the session alive across an expired token
+//!
+//! A request answered 401 renews the token through `/auth/refresh` once and
+//! is sent again with the new one. Requests answered 401 while a refresh is
+//! under way wait for that refresh rather than starting their own, and when
+//! it fails the session is over: the caller clears it and the user logs in
+//! again.
+
+use anyhow::Result;
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
+use std::{cell::RefCell, future::Future, rc::Rc};
+
+/// A renewed token, or why the session couldn't be renewed
+pub type Renewal = std::result::Result<String, String>;
+
+/// A response that may say the token was refused
+pub trait Answer {
+    fn unauthorized(&self) -> bool;
+}
+
+impl Answer for gloo_net::http::Response {
+    fn unauthorized(&self) -> bool {
+        self.status() == 401
+    }
+}
+
+/// One token refresh at a time, shared by every request that needs it
+#[derive(Clone, Default)]
+pub struct RefreshGate {
+    in_flight: Rc<RefCell<Option<Shared<LocalBoxFuture<'static, Renewal>>>>>,
+}
+
+impl RefreshGate {
+    /// The outcome of the refresh under way, or of one begun with `start`
+    /// if there is none
+    pub async fn renew<F>(&self, start: impl FnOnce() -> F) -> Renewal
+    where
+        F: Future<Output = Renewal> + 'static,
+    {
+        let refresh = {
+            let mut in_flight = self.in_flight.borrow_mut();
+            let refresh = in_flight.get_or_insert_with(|| start().boxed_local().shared());
+            refresh.clone()
+        };
+        let renewal = refresh.clone().await;
+        // The next 401 after this refresh starts another
+        let mut in_flight = self.in_flight.borrow_mut();
+        if in_flight.as_ref().is_some_and(|current| current.ptr_eq(&refresh)) {
+            *in_flight = None;
+        }
+        renewal
+    }
+
+    /// Send with `token` through `send`; if answered 401, renew the token
+    /// and send once more with the new one. `None` means the session is over
+    pub async fn send<T, S, F, R>(&self, token: Option<String>, send: S, refresh: impl FnOnce() -> R) -> Result<Option<T>>
+    where
+        T: Answer,
+        S: Fn(Option<String>) -> F,
+        F: Future<Output = Result<T>>,
+        R: Future<Output = Renewal> + 'static,
+    {
+        let signed_in = token.is_some();
+        let answer = send(token).await?;
+        if !answer.unauthorized() || !signed_in {
+            return Ok(Some(answer));
+        }
+        match self.renew(refresh).await {
+            Ok(token) => send(Some(token)).await.map(Some),
+            Err(e) => {
+                tracing::warn!("Session could not be renewed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{channel::oneshot, executor::block_on};
+    use std::cell::Cell;
+
+    /// What the backend answers, by the token a request carried
+    struct Status(u16);
+
+    impl Answer for Status {
+        fn unauthorized(&self) -> bool {
+            self.0 == 401
+        }
+    }
+
+    /// A backend that accepts only `fresh`, recording the tokens it was sent
+    fn backend<'a>(
+        fresh: &'static str,
+        sent: &'a RefCell<Vec<Option<String>>>,
+    ) -> impl Fn(Option<String>) -> futures::future::Ready<Result<Status>> + 'a {
+        move |token| {
+            let status = if token.as_deref() == Some(fresh) { 200 } else { 401 };
+            sent.borrow_mut().push(token);
+            futures::future::ready(Ok(Status(status)))
+        }
+    }
+
+    #[test]
+    fn test_requests_refused_together_share_one_refresh() {
+        let gate = RefreshGate::default();
+        let started = Rc::new(Cell::new(0));
+        let (renewed, renewal) = oneshot::channel();
+        let renewal = renewal.shared();
+        let start = || {
+            started.set(started.get() + 1);
+            let renewal = renewal.clone();
+            async move { renewal.await.unwrap_or_else(|_| Err("cancelled".to_string())) }
+        };
+
+        let (first, second, ()) = block_on(async {
+            futures::join!(gate.renew(start), gate.renew(start), async {
+                renewed.send(Ok("fresh".to_string())).unwrap();
+            })
+        });
+        assert_eq!((first.as_deref(), second.as_deref()), (Ok("fresh"), Ok("fresh")));
+        assert_eq!(started.get(), 1);
+
+        // Finished, so the next 401 refreshes again
+        block_on(gate.renew(|| async { Ok("fresher".to_string()) })).unwrap();
+        assert!(gate.in_flight.borrow().is_none());
+    }
+
+    #[test]
+    fn test_a_refused_request_is_sent_again_with_the_renewed_token() {
+        let gate = RefreshGate::default();
+        let sent = RefCell::new(Vec::new());
+        let answer = block_on(gate.send(
+            Some("stale".to_string()),
+            backend("fresh", &sent),
+            || async { Ok("fresh".to_string()) },
+        ));
+
+        assert_eq!(answer.unwrap().map(|status| status.0), Some(200));
+        assert_eq!(*sent.borrow(), [Some("stale".to_string()), Some("fresh".to_string())]);
+    }
+
+    #[test]
+    fn test_a_failed_refresh_ends_the_session() {
+        let gate = RefreshGate::default();
+        let sent = RefCell::new(Vec::new());
+        let answer = block_on(gate.send(
+            Some("stale".to_string()),
+            backend("fresh", &sent),
+            || async { Err("Session expired: Invalid token".to_string()) },
+        ));
+
+        assert!(answer.unwrap().is_none());
+        assert_eq!(sent.borrow().len(), 1, "not sent again");
+
+        // Without a session there is nothing to refresh
+        let answer = block_on(gate.send(None, backend("fresh", &sent), || async {
+            panic!("no refresh without a token")
+        }));
+        assert_eq!(answer.unwrap().map(|status| status.0), Some(401));
+    }
+}
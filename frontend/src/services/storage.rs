@@ -0,0 +1,94 @@
+//! What the app keeps in the browser across visits
+//!
+//! Everything the frontend stores goes through [`KeyValueStore`] and the
+//! [`store`] it is handed, never `localStorage` directly. Under test that
+//! store is kept in memory and records every call, so a test can check what
+//! a page wrote, or that it wrote nothing.
+
+use anyhow::{anyhow, Result};
+use gloo_storage::{LocalStorage, Storage};
+
+/// Strings kept by key across visits
+pub trait KeyValueStore {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+    fn delete(&self, key: &str);
+}
+
+/// The browser's `localStorage`
+// Tests are handed the recording store instead
+#[cfg_attr(test, allow(dead_code))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStore;
+
+impl KeyValueStore for LocalStore {
+    fn get(&self, key: &str) -> Option<String> {
+        LocalStorage::raw().get_item(key).ok().flatten()
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        LocalStorage::raw()
+            .set_item(key, value)
+            .map_err(|e| anyhow!("Failed to store {}: {:?}", key, e))
+    }
+
+    fn delete(&self, key: &str) {
+        let _ = LocalStorage::raw().remove_item(key);
+    }
+}
+
+/// The store the app keeps its data in
+#[cfg(not(test))]
+pub fn store() -> LocalStore {
+    LocalStore
+}
+
+#[cfg(test)]
+pub use recording::{calls, store};
+
+#[cfg(test)]
+mod recording {
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+
+    thread_local! {
+        static VALUES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+        static CALLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// In memory, one per test thread, recording each call
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct MemoryStore;
+
+    pub fn store() -> MemoryStore {
+        MemoryStore
+    }
+
+    /// Every call made on this thread's store, as `get <key>`, `set <key>`
+    /// or `delete <key>`
+    pub fn calls() -> Vec<String> {
+        CALLS.with(|calls| calls.borrow().clone())
+    }
+
+    fn record(call: &str, key: &str) {
+        CALLS.with(|calls| calls.borrow_mut().push(format!("{} {}", call, key)));
+    }
+
+    impl KeyValueStore for MemoryStore {
+        fn get(&self, key: &str) -> Option<String> {
+            record("get", key);
+            VALUES.with(|values| values.borrow().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: &str) -> Result<()> {
+            record("set", key);
+            VALUES.with(|values| values.borrow_mut().insert(key.to_string(), value.to_string()));
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) {
+            record("delete", key);
+            VALUES.with(|values| values.borrow_mut().remove(key));
+        }
+    }
+}
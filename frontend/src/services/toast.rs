@@ -0,0 +1,218 @@
+//! Notifications that outlive the page that raised them
+//!
+//! The queue lives in a signal provided at the root, so a toast raised just
+//! before navigating still shows on the next page. At most `MAX_VISIBLE` are
+//! shown at once; the rest wait their turn, and a toast's time only starts
+//! running once it is shown.
+
+use dioxus::prelude::*;
+
+/// How many toasts show at once
+pub const MAX_VISIBLE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastLevel {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl ToastLevel {
+    /// How long a toast of this level shows, in milliseconds
+    fn duration_ms(self) -> u64 {
+        match self {
+            Self::Success | Self::Info => 3_000,
+            Self::Warning => 5_000,
+            Self::Error => 8_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toast {
+    pub id: u64,
+    pub level: ToastLevel,
+    pub message: String,
+    /// Milliseconds to show for; `None` stays until closed
+    pub duration: Option<u64>,
+    /// When it was first shown, in milliseconds since the epoch
+    shown_at: Option<u64>,
+}
+
+/// Toasts in the order they were raised
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToastQueue {
+    toasts: Vec<Toast>,
+    next_id: u64,
+}
+
+impl ToastQueue {
+    pub fn push(&mut self, level: ToastLevel, message: impl Into<String>, duration: Option<u64>) -> u64 {
+        self.next_id += 1;
+        self.toasts.push(Toast {
+            id: self.next_id,
+            level,
+            message: message.into(),
+            duration,
+            shown_at: None,
+        });
+        self.next_id
+    }
+
+    /// Close a toast, shown or waiting; false if it was already gone
+    pub fn dismiss(&mut self, id: u64) -> bool {
+        let before = self.toasts.len();
+        self.toasts.retain(|toast| toast.id != id);
+        self.toasts.len() != before
+    }
+
+    /// The oldest `MAX_VISIBLE` toasts, the ones on screen
+    pub fn visible(&self) -> &[Toast] {
+        &self.toasts[..self.toasts.len().min(MAX_VISIBLE)]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.toasts.is_empty()
+    }
+
+    /// Drop the shown toasts whose time is up at `now` and start the clock on
+    /// the ones that take their place; whether anything changed
+    pub fn tick(&mut self, now: u64) -> bool {
+        let mut changed = false;
+        loop {
+            let before = self.toasts.len();
+            self.toasts.retain(|toast| match (toast.shown_at, toast.duration) {
+                (Some(shown_at), Some(duration)) => now < shown_at + duration,
+                _ => true,
+            });
+            changed |= self.toasts.len() != before;
+            let mut started = false;
+            for toast in self.toasts.iter_mut().take(MAX_VISIBLE) {
+                if toast.shown_at.is_none() {
+                    toast.shown_at = Some(now);
+                    started = true;
+                }
+            }
+            changed |= started;
+            // Newly shown toasts may have no time at all
+            if !started {
+                return changed;
+            }
+        }
+    }
+}
+
+/// Raises toasts from components and from the futures they spawn
+#[derive(Clone, Copy)]
+pub struct ToastService {
+    queue: Signal<ToastQueue>,
+}
+
+impl ToastService {
+    pub fn new(queue: Signal<ToastQueue>) -> Self {
+        Self { queue }
+    }
+
+    pub fn queue(&self) -> Signal<ToastQueue> {
+        self.queue
+    }
+
+    /// Raise a toast showing for the usual time of its level
+    pub fn show(&self, level: ToastLevel, message: impl Into<String>) -> u64 {
+        self.show_for(level, message, Some(level.duration_ms()))
+    }
+
+    pub fn show_for(&self, level: ToastLevel, message: impl Into<String>, duration: Option<u64>) -> u64 {
+        let mut queue = self.queue;
+        let id = queue.write().push(level, message, duration);
+        id
+    }
+
+    pub fn success(&self, message: impl Into<String>) -> u64 {
+        self.show(ToastLevel::Success, message)
+    }
+
+    pub fn info(&self, message: impl Into<String>) -> u64 {
+        self.show(ToastLevel::Info, message)
+    }
+
+    pub fn warning(&self, message: impl Into<String>) -> u64 {
+        self.show(ToastLevel::Warning, message)
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> u64 {
+        self.show(ToastLevel::Error, message)
+    }
+
+    pub fn dismiss(&self, id: u64) {
+        let mut queue = self.queue;
+        queue.write().dismiss(id);
+    }
+}
+
+/// The toast service provided at the root
+pub fn use_toast() -> ToastService {
+    use_context::<ToastService>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(toasts: &[Toast]) -> Vec<&str> {
+        toasts.iter().map(|toast| toast.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_toasts_show_in_the_order_raised() {
+        let mut queue = ToastQueue::default();
+        let first = queue.push(ToastLevel::Success, "Saved", Some(3_000));
+        let second = queue.push(ToastLevel::Error, "Failed", Some(8_000));
+        assert!(first < second);
+        assert_eq!(messages(queue.visible()), ["Saved", "Failed"]);
+    }
+
+    #[test]
+    fn test_shown_toasts_expire_after_their_duration() {
+        let mut queue = ToastQueue::default();
+        queue.push(ToastLevel::Success, "Saved", Some(3_000));
+        queue.push(ToastLevel::Error, "Failed", None);
+        assert!(queue.tick(1_000), "their clocks start");
+        assert!(!queue.tick(3_999));
+        assert!(queue.tick(4_000));
+        assert_eq!(messages(queue.visible()), ["Failed"]);
+
+        // Without a duration it stays until closed
+        assert!(!queue.tick(1_000_000));
+        assert!(!queue.is_empty());
+    }
+
+    #[test]
+    fn test_closing_a_toast_removes_it() {
+        let mut queue = ToastQueue::default();
+        let saved = queue.push(ToastLevel::Success, "Saved", Some(3_000));
+        queue.push(ToastLevel::Info, "Loading", Some(3_000));
+        assert!(queue.dismiss(saved));
+        assert!(!queue.dismiss(saved));
+        assert_eq!(messages(queue.visible()), ["Loading"]);
+    }
+
+    #[test]
+    fn test_toasts_past_the_cap_wait_their_turn() {
+        let mut queue = ToastQueue::default();
+        for n in 1..=MAX_VISIBLE + 2 {
+            queue.push(ToastLevel::Info, format!("Toast {}", n), Some(1_000));
+        }
+        queue.tick(0);
+        assert_eq!(queue.visible().len(), MAX_VISIBLE);
+        assert_eq!(queue.visible()[0].message, "Toast 1");
+
+        // The waiting ones take the freed places with a full duration
+        queue.tick(1_000);
+        assert_eq!(messages(queue.visible()), ["Toast 4", "Toast 5"]);
+        assert!(!queue.tick(1_999));
+        assert!(queue.tick(2_000));
+        assert!(queue.is_empty());
+    }
+}
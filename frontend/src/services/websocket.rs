@@ -1,55 +1,92 @@
-use futures::{SinkExt, StreamExt};
+use futures::{future::{self, Either}, SinkExt, StreamExt};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use ws_stream_wasm::{WsMeta, WsMessage};
 use gloo_timers::future::TimeoutFuture;
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, anyhow};
-use std::time::Duration;
+use dioxus::prelude::*;
+use std::{cell::RefCell, rc::Rc};
 use crate::config::get_config;
+use crate::services::connection::Connection;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueUpdate {
     pub update_type: String,
     pub queue: Vec<crate::services::api::Meeting>,
+    /// The task it is about, on updates relayed from the worker, which carry
+    /// no queue
+    #[serde(default)]
+    pub task_id: Option<uuid::Uuid>,
+    /// Why the task failed, on `task_failed` updates
+    #[serde(default)]
+    pub error: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WorkerStatus {
     pub worker_id: String,
     pub status: String,
     pub progress: f32,
+    /// Topic of the meeting being worked on
     pub current_task: Option<String>,
+    /// Stage of the pipeline `current_task` is in
+    #[serde(default)]
+    pub stage: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+/// Every worker's last status, sent once on connecting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerSnapshot {
+    pub workers: Vec<WorkerStatus>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WebSocketMessage {
     QueueUpdate(QueueUpdate),
     WorkerStatus(WorkerStatus),
+    WorkerSnapshot(WorkerSnapshot),
+    /// How far one of the user's tasks has got
+    TaskProgress(common::broadcast::ProgressUpdate),
     Ping,
     Pong,
 }
 
+/// Where each `subscribe` receiver is fed from
+type Subscribers = Rc<RefCell<Vec<UnboundedSender<WebSocketMessage>>>>;
+
 pub struct WebSocketService {
     connection: Option<WsMeta>,
     url: String,
+    subscribers: Subscribers,
 }
 
 impl WebSocketService {
-    pub fn new() -> Result<Self> {
+    /// A service subscribing as the holder of `token`; without one, the
+    /// session cookie has to do
+    pub fn new(token: Option<&str>) -> Result<Self> {
         let config = get_config().ok_or_else(|| anyhow!("Configuration not loaded"))?;
-        let ws_url = config.api.base_url
-            .replace("http://", "ws://")
-            .replace("https://", "wss://");
-        let url = format!("{}/queue_updates", ws_url);
-        
+        let url = match token {
+            Some(token) => format!("{}?token={}", config.websocket_url(), token),
+            None => config.websocket_url(),
+        };
         Ok(Self {
             connection: None,
             url,
+            subscribers: Subscribers::default(),
         })
     }
 
+    /// Every message received from now on, until the connection closes and
+    /// the receiver ends
+    pub fn subscribe(&self) -> UnboundedReceiver<WebSocketMessage> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.borrow_mut().push(sender);
+        receiver
+    }
+
     pub async fn connect(&mut self) -> Result<()> {
         if self.connection.is_some() {
             return Ok(());
@@ -59,6 +96,7 @@ impl WebSocketService {
             .map_err(|e| anyhow!("Failed to connect to WebSocket: {:?}", e))?;
 
         self.connection = Some(ws);
+        let subscribers = Rc::clone(&self.subscribers);
         
         // Spawn background task to handle incoming messages
         wasm_bindgen_futures::spawn_local(async move {
@@ -66,12 +104,7 @@ impl WebSocketService {
             
             while let Some(msg) = stream.next().await {
                 match msg {
-                    WsMessage::Text(text) => {
-                        if let Ok(ws_msg) = serde_json::from_str::<WebSocketMessage>(&text) {
-                            tracing::info!("Received WebSocket message: {:?}", ws_msg);
-                            // TODO: Send message to subscribers
-                        }
-                    }
+                    WsMessage::Text(text) => dispatch(&subscribers, &text),
                     WsMessage::Binary(_) => {
                         tracing::warn!("Received unexpected binary WebSocket message");
                     }
@@ -80,6 +113,8 @@ impl WebSocketService {
             }
             
             tracing::info!("WebSocket connection closed");
+            // Ends every receiver, so subscribers know
+            subscribers.borrow_mut().clear();
         });
 
         Ok(())
@@ -108,28 +143,94 @@ impl WebSocketService {
         }
     }
 
-    pub async fn reconnect_with_backoff(&mut self, max_retries: u32) -> Result<()> {
-        let mut retries = 0;
-        let mut delay = Duration::from_millis(1000);
+    /// Connect and, after each failed attempt, wait as `connection` says
+    /// before trying again, until it gives up; a press on `retry_now`
+    /// cuts the wait short. Any earlier connection is taken to have ended.
+    pub async fn reconnect_with_backoff(
+        &mut self,
+        mut connection: Signal<Connection>,
+        retry_now: &mut UnboundedReceiver<()>,
+    ) -> Result<()> {
+        self.connection = None;
+        loop {
+            let e = match self.connect().await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            let next = connection.write().failed();
+            let Some(delay) = next else {
+                return Err(anyhow!("Gave up reconnecting: {}", e));
+            };
+            tracing::warn!("WebSocket connection failed, trying again in {:?}: {}", delay, e);
+            let wait = TimeoutFuture::new(delay.as_millis() as u32);
+            if let Either::Right((None, wait)) = future::select(wait, retry_now.next()).await {
+                // Nobody can press it any more
+                wait.await;
+            }
+        }
+    }
+}
 
-        while retries < max_retries {
-            match self.connect().await {
-                Ok(()) => {
-                    tracing::info!("WebSocket reconnected successfully");
-                    return Ok(());
-                }
-                Err(e) => {
-                    retries += 1;
-                    tracing::warn!("WebSocket reconnection attempt {} failed: {}", retries, e);
-                    
-                    if retries < max_retries {
-                        TimeoutFuture::new(delay.as_millis() as u32).await;
-                        delay = Duration::from_millis((delay.as_millis() as u64 * 2).min(30000));
-                    }
-                }
+/// Hand the message in `text` to every subscriber, forgetting those that
+/// have dropped their receiver
+fn dispatch(subscribers: &Subscribers, text: &str) {
+    match serde_json::from_str::<WebSocketMessage>(text) {
+        Ok(message) => {
+            tracing::debug!("Received WebSocket message: {:?}", message);
+            subscribers
+                .borrow_mut()
+                .retain(|subscriber| subscriber.unbounded_send(message.clone()).is_ok());
+        }
+        Err(e) => tracing::warn!("Ignoring an unreadable WebSocket message: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> WebSocketService {
+        WebSocketService {
+            connection: None,
+            url: "ws://localhost:3000/ws".to_string(),
+            subscribers: Subscribers::default(),
+        }
+    }
+
+    #[test]
+    fn test_received_messages_reach_every_subscriber() {
+        let service = service();
+        let mut dashboard = service.subscribe();
+        let mut header = service.subscribe();
+
+        dispatch(&service.subscribers, r#"{"type":"Ping"}"#);
+        dispatch(&service.subscribers, "not json");
+        dispatch(
+            &service.subscribers,
+            r#"{"type":"WorkerStatus","worker_id":"w1","status":"uploading","progress":0.5,
+                "current_task":null,"timestamp":"2026-10-14T09:00:00Z"}"#,
+        );
+
+        for receiver in [&mut dashboard, &mut header] {
+            assert!(matches!(receiver.try_next(), Ok(Some(WebSocketMessage::Ping))));
+            match receiver.try_next() {
+                Ok(Some(WebSocketMessage::WorkerStatus(status))) => assert_eq!(status.worker_id, "w1"),
+                other => panic!("expected the worker's status, got {:?}", other),
             }
+            assert!(receiver.try_next().is_err(), "nothing else was sent");
         }
+    }
+
+    #[test]
+    fn test_dropped_subscribers_are_forgotten() {
+        let service = service();
+        let kept = service.subscribe();
+        drop(service.subscribe());
 
-        Err(anyhow!("Failed to reconnect after {} attempts", max_retries))
+        dispatch(&service.subscribers, r#"{"type":"Pong"}"#);
+        assert_eq!(service.subscribers.borrow().len(), 1);
+        drop(kept);
+        dispatch(&service.subscribers, r#"{"type":"Pong"}"#);
+        assert!(service.subscribers.borrow().is_empty());
     }
 }
@@ -0,0 +1,152 @@
+//! The workers the Dashboard shows, kept from their `WorkerStatus` messages
+//!
+//! Each worker's row holds its latest status and when that arrived. A worker
+//! that stops sending, or says it is going offline, is shown greyed out with
+//! when it was last seen, and once silent for [`FORGET_AFTER_MS`] its row is
+//! dropped. Times are milliseconds since the epoch, passed in by the caller.
+
+use std::collections::BTreeMap;
+use crate::services::websocket::WorkerStatus;
+
+/// Silence after which a worker is shown offline, three missed heartbeats
+pub const OFFLINE_AFTER_MS: u64 = 30_000;
+
+/// Silence after which a worker is no longer shown at all
+pub const FORGET_AFTER_MS: u64 = 5 * 60_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerRow {
+    pub status: WorkerStatus,
+    /// When `status` arrived
+    pub seen_at: u64,
+    pub offline: bool,
+}
+
+impl WorkerRow {
+    /// How long ago the worker was last heard from, as "45s ago"
+    pub fn last_seen(&self, now: u64) -> String {
+        let secs = now.saturating_sub(self.seen_at) / 1_000;
+        match secs {
+            0..=59 => format!("{}s ago", secs),
+            60..=3_599 => format!("{}m ago", secs / 60),
+            _ => format!("{}h ago", secs / 3_600),
+        }
+    }
+}
+
+/// The workers by id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerBoard {
+    workers: BTreeMap<String, WorkerRow>,
+}
+
+impl WorkerBoard {
+    /// Take `status` as its worker's latest, arrived at `now`
+    pub fn update(&mut self, status: WorkerStatus, now: u64) {
+        let offline = status.status == "offline";
+        self.workers.insert(
+            status.worker_id.clone(),
+            WorkerRow { status, seen_at: now, offline },
+        );
+    }
+
+    /// Take every status in a snapshot, as if each had just arrived
+    pub fn extend(&mut self, snapshot: Vec<WorkerStatus>, now: u64) {
+        for status in snapshot {
+            self.update(status, now);
+        }
+    }
+
+    /// Grey out the workers silent too long at `now` and drop those silent
+    /// longer still; whether anything changed
+    pub fn tick(&mut self, now: u64) -> bool {
+        let before = self.workers.len();
+        self.workers.retain(|_, row| now.saturating_sub(row.seen_at) < FORGET_AFTER_MS);
+        let mut changed = self.workers.len() != before;
+        for row in self.workers.values_mut() {
+            if !row.offline && now.saturating_sub(row.seen_at) >= OFFLINE_AFTER_MS {
+                row.offline = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The rows in worker id order
+    pub fn rows(&self) -> impl Iterator<Item = &WorkerRow> {
+        self.workers.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(worker_id: &str, state: &str, progress: f32) -> WorkerStatus {
+        WorkerStatus {
+            worker_id: worker_id.to_string(),
+            status: state.to_string(),
+            progress,
+            current_task: None,
+            stage: None,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    fn shown(board: &WorkerBoard) -> Vec<(&str, f32, bool)> {
+        board
+            .rows()
+            .map(|row| (row.status.worker_id.as_str(), row.status.progress, row.offline))
+            .collect()
+    }
+
+    #[test]
+    fn test_a_workers_status_replaces_its_last_one() {
+        let mut board = WorkerBoard::default();
+        board.update(status("w2", "processing", 0.2), 0);
+        board.update(status("w1", "processing", 0.1), 0);
+        board.update(status("w1", "processing", 0.6), 1_000);
+        assert_eq!(shown(&board), [("w1", 0.6, false), ("w2", 0.2, false)]);
+
+        board.extend(vec![status("w3", "idle", 0.0)], 2_000);
+        assert_eq!(board.rows().count(), 3);
+    }
+
+    #[test]
+    fn test_a_silent_worker_goes_offline() {
+        let mut board = WorkerBoard::default();
+        board.update(status("w1", "processing", 0.5), 0);
+        board.update(status("w2", "idle", 0.0), 20_000);
+        assert!(!board.tick(OFFLINE_AFTER_MS - 1));
+        assert!(board.tick(OFFLINE_AFTER_MS));
+        assert_eq!(shown(&board), [("w1", 0.5, true), ("w2", 0.0, false)]);
+        assert_eq!(board.rows().next().unwrap().last_seen(OFFLINE_AFTER_MS), "30s ago");
+
+        // Heard from again, it is back
+        board.update(status("w1", "processing", 0.7), 40_000);
+        assert!(!board.tick(40_000));
+        assert!(!board.rows().next().unwrap().offline);
+
+        // Saying it is shutting down takes it offline at once
+        board.update(status("w2", "offline", 0.0), 41_000);
+        assert!(board.rows().nth(1).unwrap().offline);
+    }
+
+    #[test]
+    fn test_long_silent_workers_are_dropped() {
+        let mut board = WorkerBoard::default();
+        board.update(status("w1", "processing", 0.5), 0);
+        board.update(status("w2", "idle", 0.0), 60_000);
+        board.tick(OFFLINE_AFTER_MS);
+        assert!(board.tick(FORGET_AFTER_MS));
+        assert_eq!(shown(&board), [("w2", 0.0, true)]);
+        assert_eq!(board.rows().next().unwrap().last_seen(FORGET_AFTER_MS), "4m ago");
+
+        assert!(board.tick(60_000 + FORGET_AFTER_MS));
+        assert!(board.is_empty());
+    }
+}
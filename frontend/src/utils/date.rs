@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
 
 pub fn format_datetime(dt: &DateTime<Utc>) -> String {
     dt.format("%Y-%m-%d %H:%M:%S UTC").to_string()
@@ -25,3 +25,125 @@ pub fn format_duration_seconds(seconds: u32) -> String {
         format!("{}s", secs)
     }
 }
+
+/// A recording's length to the minute, as "1h 5m" or "45m"
+pub fn format_duration_minutes(seconds: u32) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// `dt` in the browser's timezone, as "2026-10-14 11:00"
+pub fn format_local(dt: &DateTime<Utc>) -> String {
+    dt.with_timezone(&local_offset(dt)).format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// The day of `dt` in the browser's timezone
+pub fn format_local_date(dt: &DateTime<Utc>) -> String {
+    dt.with_timezone(&local_offset(dt)).format("%Y-%m-%d").to_string()
+}
+
+/// How long ago `dt` was, or how long until it is, as "2 hours ago"
+pub fn format_relative(dt: &DateTime<Utc>) -> String {
+    relative_to(dt, now())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(js_sys::Date::now() as i64).unwrap_or_default()
+}
+
+/// Off the browser, in host tests, there is no `Date` to ask
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> DateTime<Utc> {
+    Utc::now()
+}
+
+/// The browser's offset from UTC at `dt`, which daylight saving moves
+#[cfg(target_arch = "wasm32")]
+fn local_offset(dt: &DateTime<Utc>) -> FixedOffset {
+    let date = js_sys::Date::new(&(dt.timestamp_millis() as f64).into());
+    offset_east(date.get_timezone_offset())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn local_offset(_dt: &DateTime<Utc>) -> FixedOffset {
+    offset_east(0.0)
+}
+
+/// `Date.getTimezoneOffset` as an offset; it counts minutes behind UTC, so
+/// zones west of Greenwich are positive
+fn offset_east(minutes_behind: f64) -> FixedOffset {
+    FixedOffset::east_opt(-(minutes_behind as i32) * 60).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+}
+
+fn relative_to(dt: &DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let secs = (now - *dt).num_seconds();
+    let span = match secs.unsigned_abs() {
+        0..=44 => return "just now".to_string(),
+        secs @ 45..=2_699 => count((secs / 60).max(1), "minute"),
+        secs @ 2_700..=79_199 => count((secs / 3_600).max(1), "hour"),
+        secs @ 79_200..=2_246_399 => count((secs / 86_400).max(1), "day"),
+        secs @ 2_246_400..=27_647_999 => count((secs / 2_592_000).max(1), "month"),
+        secs => count((secs / 31_536_000).max(1), "year"),
+    };
+    if secs < 0 {
+        format!("in {}", span)
+    } else {
+        format!("{} ago", span)
+    }
+}
+
+fn count(n: u64, unit: &str) -> String {
+    if n == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", n, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_relative_times_round_to_the_nearest_unit() {
+        let now: DateTime<Utc> = "2026-10-14T12:00:00Z".parse().unwrap();
+        let ago = |span: Duration| relative_to(&(now - span), now);
+
+        assert_eq!(ago(Duration::seconds(44)), "just now");
+        assert_eq!(ago(Duration::seconds(45)), "1 minute ago");
+        assert_eq!(ago(Duration::minutes(44)), "44 minutes ago");
+        assert_eq!(ago(Duration::minutes(45)), "1 hour ago");
+        assert_eq!(ago(Duration::hours(2)), "2 hours ago");
+        assert_eq!(ago(Duration::hours(22)), "1 day ago");
+        assert_eq!(ago(Duration::days(25)), "25 days ago");
+        assert_eq!(ago(Duration::days(26)), "1 month ago");
+        assert_eq!(ago(Duration::days(320)), "1 year ago");
+        assert_eq!(ago(Duration::days(800)), "2 years ago");
+        assert_eq!(ago(Duration::days(-3)), "in 3 days");
+    }
+
+    #[test]
+    fn test_utc_times_show_in_the_browsers_zone() {
+        let dt: DateTime<Utc> = "2026-10-14T23:30:00Z".parse().unwrap();
+        // UTC-4, which getTimezoneOffset reports as 240
+        let new_york = dt.with_timezone(&offset_east(240.0));
+        assert_eq!(new_york.format("%Y-%m-%d %H:%M").to_string(), "2026-10-14 19:30");
+        // UTC+5:30, reported as -330, already the next day
+        let kolkata = dt.with_timezone(&offset_east(-330.0));
+        assert_eq!(kolkata.format("%Y-%m-%d %H:%M").to_string(), "2026-10-15 05:00");
+    }
+
+    #[test]
+    fn test_durations_show_to_the_minute() {
+        assert_eq!(format_duration_minutes(3_900), "1h 5m");
+        assert_eq!(format_duration_minutes(2_700), "45m");
+    }
+}
@@ -5,6 +5,7 @@ pub fn is_valid_email(email: &str) -> bool {
     email_regex.is_match(email)
 }
 
+/// Same rules as `common::validation::password_problems`, enforced by the backend
 pub fn is_strong_password(password: &str) -> bool {
     password.len() >= 8 &&
     password.chars().any(|c| c.is_uppercase()) &&
@@ -0,0 +1,1176 @@
+//! The routes the backend and worker send email through, follow the emails
+//! they sent with, and manage the addresses no longer sent to
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use common::{ApiResponse, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Instant};
+use tracing::error;
+use validator::ValidateEmail;
+
+use crate::{
+    email_service::{
+        BatchSendRequest, Cancel, DeadLetterFilter, EmailPage, EmailService, EmailStatus, Priority,
+        QueueError, QueuedEmail, Requeue, SendEmailRequest,
+    },
+    auth::Caller,
+    pocketbase::parse_time,
+    providers::ConnectionReport,
+    suppressions::Suppression,
+};
+
+/// Emails `GET /emails` lists per page unless asked for another number
+pub const DEFAULT_PER_PAGE: u32 = 50;
+
+/// Most emails `GET /emails` lists per page
+pub const MAX_PER_PAGE: u32 = 200;
+
+pub fn router(emails: Arc<EmailService>) -> Router {
+    Router::new()
+        .route("/send-email", post(send_email))
+        .route("/send-email/batch", post(send_batch))
+        .route("/emails", get(list_emails))
+        .route("/emails/dead_letter", get(list_dead_letters))
+        .route("/emails/dead_letter/requeue_all", post(requeue_dead_letters))
+        .route("/emails/:id", get(get_email).delete(cancel_email))
+        .route("/emails/:id/requeue", post(requeue_email))
+        .route("/suppressions/:email", get(get_suppression).delete(remove_suppression))
+        .route("/test-smtp", post(test_smtp))
+        .with_state(emails)
+}
+
+/// What `POST /send-email` answers with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Queued {
+    /// Id of the email's `email_queue` record
+    pub queue_id: String,
+}
+
+/// What `POST /send-email/batch` answers with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchQueued {
+    /// Recipients whose email is queued
+    pub queued: usize,
+    /// One per recipient, in the order they were given
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub to_email: String,
+    /// Id of the recipient's `email_queue` record, once queued
+    pub queue_id: Option<String>,
+    /// Why the recipient's email wasn't queued
+    pub error: Option<String>,
+    pub code: Option<ErrorCode>,
+}
+
+/// A queued email as `GET /emails` and `GET /emails/:id` show it, without
+/// its bodies or attachments and with the recipient partly masked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailView {
+    pub id: String,
+    pub to_email: String,
+    pub subject: String,
+    pub template: Option<String>,
+    pub status: EmailStatus,
+    pub priority: Priority,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// When the email is scheduled to go, if not straight away
+    pub send_at: Option<DateTime<Utc>>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub provider: Option<String>,
+    /// When the record last changed; for a failed email, when it failed
+    pub updated_at: Option<DateTime<Utc>>,
+    /// The API key label that last requeued the email, and when
+    pub requeued_by: Option<String>,
+    pub requeued_at: Option<DateTime<Utc>>,
+}
+
+impl From<&QueuedEmail> for EmailView {
+    fn from(queued: &QueuedEmail) -> Self {
+        Self {
+            id: queued.id.clone(),
+            to_email: mask_email(&queued.email.to_email),
+            subject: queued.email.subject.clone(),
+            template: queued.template.clone(),
+            status: queued.status,
+            priority: queued.priority,
+            attempts: queued.attempts,
+            last_error: queued.last_error.clone(),
+            created_at: queued.created_at,
+            next_attempt_at: queued.next_attempt_at,
+            send_at: queued.send_at,
+            sent_at: queued.sent_at,
+            provider: queued.provider.clone(),
+            updated_at: queued.updated_at,
+            requeued_by: queued.requeued_by.clone(),
+            requeued_at: queued.requeued_at,
+        }
+    }
+}
+
+/// `to_email` with all of its local part but the first character hidden,
+/// `a***@example.com`, so a lookup doesn't hand out whole addresses
+pub fn mask_email(to_email: &str) -> String {
+    match to_email.rsplit_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailsQuery {
+    pub status: Option<String>,
+    /// RFC 3339; only emails queued at or after it are listed
+    pub since: Option<String>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+/// Query of `GET /emails/dead_letter` and `POST /emails/dead_letter/requeue_all`
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub to_email: Option<String>,
+    pub template: Option<String>,
+    /// RFC 3339; only emails failed at or after it are taken in
+    pub since: Option<String>,
+    /// RFC 3339; only emails failed before it are taken in
+    pub until: Option<String>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+impl DeadLetterQuery {
+    fn filter(&self) -> Result<DeadLetterFilter, String> {
+        fn given(value: &Option<String>) -> Option<&str> {
+            value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+        }
+        Ok(DeadLetterFilter {
+            to_email: given(&self.to_email).map(str::to_string),
+            template: given(&self.template).map(str::to_string),
+            since: query_time("since", given(&self.since))?,
+            until: query_time("until", given(&self.until))?,
+        })
+    }
+}
+
+/// `value` of the query parameter `name` as a time, if given
+fn query_time(name: &str, value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    value
+        .map(|value| {
+            parse_time(value).ok_or_else(|| format!("{} must be an RFC 3339 time, not '{}'", name, value))
+        })
+        .transpose()
+}
+
+/// Body of `POST /test-smtp`
+#[derive(Debug, Deserialize)]
+pub struct SmtpTestRequest {
+    #[serde(default)]
+    pub mode: SmtpTestMode,
+    /// Where send mode sends the test email; refused in connect mode
+    pub test_email: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTestMode {
+    /// Connect to each provider, securing the connection and signing in,
+    /// and send nothing
+    #[default]
+    Connect,
+    Send,
+}
+
+/// What `POST /test-smtp` answers with
+#[derive(Debug, Serialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum SmtpTest {
+    Connect {
+        providers: Vec<ConnectionReport>,
+    },
+    Send {
+        test_email: String,
+        /// The provider the test email went through
+        provider: String,
+        elapsed_ms: u64,
+    },
+}
+
+fn failure(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Response {
+    (status, Json(ApiResponse::<()>::failure(code, message))).into_response()
+}
+
+/// The `Idempotency-Key` header a request is sent with
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Take the `Idempotency-Key` header as the body's `idempotency_key`, or
+/// say why the request is refused if the two disagree
+fn take_idempotency_key(headers: &HeaderMap, key: &mut Option<String>) -> Result<(), &'static str> {
+    let Some(header) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(());
+    };
+    let header = header.to_str().map_err(|_| "Idempotency-Key must be printable ASCII")?;
+    match key {
+        Some(key) if key != header => Err("Idempotency-Key and idempotency_key differ"),
+        _ => {
+            *key = Some(header.to_string());
+            Ok(())
+        }
+    }
+}
+
+/// POST /send-email - Queue an email, answering once it is kept; the same
+/// request under the same idempotency key is answered with the same email
+pub async fn send_email(
+    State(emails): State<Arc<EmailService>>,
+    headers: HeaderMap,
+    Json(mut request): Json<SendEmailRequest>,
+) -> Response {
+    if let Err(problem) = take_idempotency_key(&headers, &mut request.idempotency_key) {
+        return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem);
+    }
+    match emails.queue_email(&request).await {
+        Ok(queue_id) => (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success(Queued { queue_id })),
+        )
+            .into_response(),
+        Err(QueueError::Invalid(problem)) => {
+            failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem)
+        }
+        Err(QueueError::TooLarge(problem)) => {
+            failure(StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::PayloadTooLarge, problem)
+        }
+        Err(QueueError::Template(problems)) => {
+            let response = ApiResponse {
+                data: Some(json!({ "problems": problems })),
+                ..ApiResponse::failure(ErrorCode::Validation, problems.join("; "))
+            };
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+        }
+        Err(QueueError::Suppressed(problem)) => {
+            failure(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Suppressed, problem)
+        }
+        Err(QueueError::KeyReused(problem)) => key_reused(problem),
+        Err(QueueError::ShuttingDown) => shutting_down(),
+        Err(QueueError::PocketBase(e)) => {
+            error!(to = %request.to_email, "Failed to queue an email: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// POST /test-smtp - Check that each provider can be connected to, or send
+/// a test email through them
+pub async fn test_smtp(
+    State(emails): State<Arc<EmailService>>,
+    Json(request): Json<SmtpTestRequest>,
+) -> Response {
+    let invalid = |message: &str| failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, message);
+    let test = match (request.mode, request.test_email) {
+        (SmtpTestMode::Connect, Some(_)) => return invalid("test_email is only taken in send mode"),
+        (SmtpTestMode::Connect, None) => SmtpTest::Connect {
+            providers: emails.test_connections().await,
+        },
+        (SmtpTestMode::Send, None) => return invalid("test_email must be given in send mode"),
+        (SmtpTestMode::Send, Some(test_email)) => {
+            if !test_email.validate_email() {
+                return invalid("test_email must be an email address");
+            }
+            let started = Instant::now();
+            match emails.send_test(&test_email).await {
+                Ok(provider) => SmtpTest::Send {
+                    test_email,
+                    provider,
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                },
+                Err(e) => {
+                    return failure(
+                        StatusCode::BAD_GATEWAY,
+                        ErrorCode::ServiceUnavailable,
+                        format!("The test email couldn't be sent: {}", e),
+                    )
+                }
+            }
+        }
+    };
+    Json(ApiResponse::success(test)).into_response()
+}
+
+/// The code a recipient of a batch is refused with
+fn refusal_code(error: &QueueError) -> ErrorCode {
+    match error {
+        QueueError::Invalid(_) | QueueError::Template(_) => ErrorCode::Validation,
+        QueueError::TooLarge(_) => ErrorCode::PayloadTooLarge,
+        QueueError::Suppressed(_) => ErrorCode::Suppressed,
+        QueueError::ShuttingDown => ErrorCode::ShuttingDown,
+        QueueError::KeyReused(_) => ErrorCode::IdempotencyKeyReused,
+        QueueError::PocketBase(_) => ErrorCode::ServiceUnavailable,
+    }
+}
+
+fn key_reused(problem: String) -> Response {
+    failure(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::IdempotencyKeyReused, problem)
+}
+
+fn unavailable() -> Response {
+    failure(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::ServiceUnavailable,
+        "The email queue is unavailable; try again shortly",
+    )
+}
+
+fn shutting_down() -> Response {
+    failure(
+        StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::ShuttingDown,
+        "The email service is shutting down; try again shortly",
+    )
+}
+
+fn no_such_email() -> Response {
+    failure(StatusCode::NOT_FOUND, ErrorCode::NotFound, "No such email")
+}
+
+/// Record ids are alphanumeric; anything else can't name one
+fn is_record_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// POST /send-email/batch - Queue a template's email to each recipient,
+/// refusing only the recipients that can't be sent one
+pub async fn send_batch(
+    State(emails): State<Arc<EmailService>>,
+    headers: HeaderMap,
+    Json(mut batch): Json<BatchSendRequest>,
+) -> Response {
+    if let Err(problem) = take_idempotency_key(&headers, &mut batch.idempotency_key) {
+        return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem);
+    }
+    let results = match emails.queue_batch(&batch).await {
+        Ok(results) => results,
+        Err(QueueError::KeyReused(problem)) => return key_reused(problem),
+        Err(QueueError::PocketBase(e)) => {
+            error!(template = %batch.template, "Failed to queue an email batch: {}", e);
+            return unavailable();
+        }
+        Err(QueueError::ShuttingDown) => return shutting_down(),
+        Err(e) => return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, e.to_string()),
+    };
+    let results: Vec<BatchResult> = batch
+        .recipients
+        .iter()
+        .zip(results)
+        .map(|(recipient, result)| {
+            let (queue_id, error, code) = match result {
+                Ok(queue_id) => (Some(queue_id), None, None),
+                Err(error) => (None, Some(error.to_string()), Some(refusal_code(&error))),
+            };
+            BatchResult {
+                to_email: recipient.to_email.clone(),
+                queue_id,
+                error,
+                code,
+            }
+        })
+        .collect();
+    let queued = BatchQueued {
+        queued: results.iter().filter(|result| result.queue_id.is_some()).count(),
+        results,
+    };
+    match queued.queued {
+        0 => {
+            let response = ApiResponse {
+                data: Some(queued),
+                ..ApiResponse::failure(ErrorCode::Validation, "No recipient's email could be queued")
+            };
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+        }
+        _ => (StatusCode::ACCEPTED, Json(ApiResponse::success(queued))).into_response(),
+    }
+}
+
+/// GET /emails/:id - What became of a queued email
+pub async fn get_email(State(emails): State<Arc<EmailService>>, Path(id): Path<String>) -> Response {
+    if !is_record_id(&id) {
+        return no_such_email();
+    }
+    match emails.email(&id).await {
+        Ok(Some(queued)) => Json(ApiResponse::success(EmailView::from(&queued))).into_response(),
+        Ok(None) => no_such_email(),
+        Err(e) => {
+            error!(queue_id = %id, "Failed to look up an email: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// DELETE /emails/:id - Cancel an email that hasn't started sending
+pub async fn cancel_email(State(emails): State<Arc<EmailService>>, Path(id): Path<String>) -> Response {
+    if !is_record_id(&id) {
+        return no_such_email();
+    }
+    match emails.cancel(&id).await {
+        Ok(Cancel::Cancelled(queued)) => Json(ApiResponse::success(EmailView::from(&*queued))).into_response(),
+        Ok(Cancel::NotFound) => no_such_email(),
+        Ok(Cancel::TooLate(status)) => {
+            let status = serde_json::to_value(status).unwrap_or_default();
+            failure(
+                StatusCode::CONFLICT,
+                ErrorCode::Validation,
+                format!("The email is {} and can no longer be cancelled", status.as_str().unwrap_or_default()),
+            )
+        }
+        Err(e) => {
+            error!(queue_id = %id, "Failed to cancel an email: {}", e);
+            unavailable()
+        }
+    }
+}
+
+fn not_suppressed() -> Response {
+    failure(StatusCode::NOT_FOUND, ErrorCode::NotFound, "That address isn't suppressed")
+}
+
+/// GET /suppressions/:email - Why an address is no longer sent email
+pub async fn get_suppression(
+    State(emails): State<Arc<EmailService>>,
+    Path(email): Path<String>,
+) -> Response {
+    match emails.suppression(&email).await {
+        Ok(Some(suppression)) => Json(ApiResponse::success(suppression)).into_response(),
+        Ok(None) => not_suppressed(),
+        Err(e) => {
+            error!("Failed to look up a suppression: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// DELETE /suppressions/:email - Send to an address again, answering with
+/// the suppression it had
+pub async fn remove_suppression(
+    State(emails): State<Arc<EmailService>>,
+    Path(email): Path<String>,
+) -> Response {
+    match emails.unsuppress(&email).await {
+        Ok(Some(suppression)) => Json(ApiResponse::<Suppression>::success(suppression)).into_response(),
+        Ok(None) => not_suppressed(),
+        Err(e) => {
+            error!("Failed to remove a suppression: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// GET /emails - Queued emails, newest first, optionally of one `status`
+/// and queued `since` a time
+pub async fn list_emails(
+    State(emails): State<Arc<EmailService>>,
+    Query(query): Query<EmailsQuery>,
+) -> Response {
+    let status = match query.status.as_deref().filter(|status| !status.is_empty()) {
+        Some(status) => match serde_json::from_value(Value::from(status)) {
+            Ok(status) => Some(status),
+            Err(_) => {
+                return failure(
+                    StatusCode::BAD_REQUEST,
+                    ErrorCode::Validation,
+                    format!("status must be pending, sending, sent, failed or cancelled, not '{}'", status),
+                )
+            }
+        },
+        None => None,
+    };
+    let since = match query_time("since", query.since.as_deref().filter(|since| !since.is_empty())) {
+        Ok(since) => since,
+        Err(problem) => return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    match emails.emails(status, since, page, per_page).await {
+        Ok(listed) => listing(&listed),
+        Err(e) => {
+            error!("Failed to list emails: {}", e);
+            unavailable()
+        }
+    }
+}
+
+fn listing(listed: &EmailPage) -> Response {
+    Json(ApiResponse::success(json!({
+        "emails": listed.emails.iter().map(EmailView::from).collect::<Vec<_>>(),
+        "page": listed.page,
+        "per_page": listed.per_page,
+        "total_items": listed.total_items,
+    })))
+    .into_response()
+}
+
+/// Who a request came from, by its API key's label
+fn caller_label(caller: Option<Extension<Caller>>) -> String {
+    caller.map_or_else(|| "unknown".to_string(), |Extension(Caller(label))| label)
+}
+
+/// GET /emails/dead_letter - Failed emails, the latest to fail first,
+/// optionally to one recipient, from one template and failed in a time range
+pub async fn list_dead_letters(
+    State(emails): State<Arc<EmailService>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Response {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(problem) => return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    match emails.dead_letters(&filter, page, per_page).await {
+        Ok(listed) => listing(&listed),
+        Err(e) => {
+            error!("Failed to list failed emails: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// POST /emails/:id/requeue - Send a failed email again, its attempts
+/// begun anew
+pub async fn requeue_email(
+    State(emails): State<Arc<EmailService>>,
+    caller: Option<Extension<Caller>>,
+    Path(id): Path<String>,
+) -> Response {
+    if !is_record_id(&id) {
+        return no_such_email();
+    }
+    match emails.requeue(&id, &caller_label(caller)).await {
+        Ok(Requeue::Requeued(queued)) => Json(ApiResponse::success(EmailView::from(&*queued))).into_response(),
+        Ok(Requeue::NotFound) => no_such_email(),
+        Ok(Requeue::NotFailed(status)) => {
+            let status = serde_json::to_value(status).unwrap_or_default();
+            let status = status.as_str().unwrap_or_default();
+            failure(
+                StatusCode::CONFLICT,
+                ErrorCode::Validation,
+                format!("Only failed emails can be requeued, not {} ones", status),
+            )
+        }
+        Ok(Requeue::Suppressed(suppression)) => failure(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Suppressed,
+            format!("{} is on the suppression list", suppression.email),
+        ),
+        Err(e) => {
+            error!(queue_id = %id, "Failed to requeue an email: {}", e);
+            unavailable()
+        }
+    }
+}
+
+/// POST /emails/dead_letter/requeue_all - Send again every failed email
+/// failed `since` a time, as after an outage, skipping suppressed recipients
+pub async fn requeue_dead_letters(
+    State(emails): State<Arc<EmailService>>,
+    caller: Option<Extension<Caller>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Response {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(problem) => return failure(StatusCode::BAD_REQUEST, ErrorCode::Validation, problem),
+    };
+    if filter.since.is_none() {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::Validation,
+            "since is required, so a bulk requeue is bounded in time",
+        );
+    }
+    match emails.requeue_all(&filter, &caller_label(caller)).await {
+        Ok(requeued) => Json(ApiResponse::success(requeued)).into_response(),
+        Err(e) => {
+            error!("Failed to requeue failed emails: {}", e);
+            unavailable()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        attachments::AttachmentLimits,
+        email_service::EMAIL_QUEUE,
+        pocketbase::{PbSettings, PocketBase},
+        suppressions::SUPPRESSION_LIST,
+        providers::{BreakerPolicy, Providers},
+        test_support::{mock_pb, pb_settings, FakeTransport, MockPb},
+        transport::{Endpoint, Security, SendError, Transport},
+    };
+    use axum::{body::Body, extract::Request};
+    use tower::ServiceExt;
+
+    async fn call(app: Router, request: Request) -> (StatusCode, Value) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn post(settings: &PbSettings, body: Value) -> (StatusCode, Value) {
+        // Small enough limits that refusing an attachment takes a small body
+        let emails = EmailService::new(
+            PocketBase::from(settings),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        )
+        .with_attachment_limits(AttachmentLimits {
+            per_attachment_bytes: 1024,
+            total_bytes: 2048,
+        });
+        let request = Request::post("/send-email")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        call(router(Arc::new(emails)), request).await
+    }
+
+    async fn get(emails: &Arc<EmailService>, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        call(router(emails.clone()), request).await
+    }
+
+    async fn delete(emails: &Arc<EmailService>, uri: &str) -> (StatusCode, Value) {
+        let request = Request::delete(uri).body(Body::empty()).unwrap();
+        call(router(emails.clone()), request).await
+    }
+
+    #[tokio::test]
+    async fn test_a_sent_email_answers_with_its_queue_record() {
+        let pb = mock_pb().await;
+        let email = json!({
+            "to_email": "alice@example.com",
+            "subject": "Confirm your Fathom to Loom email address",
+            "body_text": "Open this link",
+            "body_html": "<p>Open this link</p>",
+        });
+
+        let (status, body) = post(&pb_settings(&pb), email.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let queue_id = body["data"]["queue_id"].as_str().unwrap();
+        assert_eq!(pb.record(EMAIL_QUEUE, queue_id).unwrap()["status"], "pending");
+
+        let invalid = json!({ "to_email": "alice", "subject": "Hi", "body_text": "Hi" });
+        let (status, body) = post(&pb_settings(&pb), invalid).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation");
+        assert_eq!(body["error"], "to_email must be an email address");
+
+        let unknown = json!({ "to_email": "alice@example.com", "template": "digest" });
+        let (status, body) = post(&pb_settings(&pb), unknown).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation");
+        assert!(body["data"]["problems"][0].as_str().unwrap().starts_with("unknown template 'digest'"));
+
+        // 1536 bytes once decoded
+        let oversized = json!({
+            "to_email": "alice@example.com",
+            "subject": "Your export",
+            "body_text": "Attached",
+            "attachments": [{
+                "filename": "export.bin",
+                "content_type": "application/octet-stream",
+                "data_base64": "A".repeat(2048),
+            }],
+        });
+        let (status, body) = post(&pb_settings(&pb), oversized).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "payload_too_large");
+        assert!(body["error"].as_str().unwrap().contains("export.bin"));
+
+        // Nothing listening where PocketBase should be
+        let gone = PbSettings {
+            url: "http://127.0.0.1:9".to_string(),
+            ..pb_settings(&pb)
+        };
+        let (status, body) = post(&gone, email).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "service_unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_a_batch_answers_for_each_recipient() {
+        let pb = mock_pb().await;
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        ));
+        let send = |body: Value| {
+            let request = Request::post("/send-email/batch")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            call(router(emails.clone()), request)
+        };
+        let batch = |recipients: Value| {
+            json!({
+                "template": "generic",
+                "template_data": { "subject": "Your weekly digest", "message": "Nothing new" },
+                "recipients": recipients,
+            })
+        };
+
+        let mixed = json!([
+            { "to_email": "alice@example.com", "to_name": "Alice" },
+            { "to_email": "not-an-address" },
+        ]);
+        let (status, body) = send(batch(mixed)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["data"]["queued"], 1);
+        let results = &body["data"]["results"];
+        let queue_id = results[0]["queue_id"].as_str().unwrap();
+        assert_eq!(pb.record(EMAIL_QUEUE, queue_id).unwrap()["to_name"], "Alice");
+        assert_eq!(results[1]["to_email"], "not-an-address");
+        assert_eq!(results[1]["error"], "to_email must be an email address");
+        assert_eq!(results[1]["code"], "validation");
+        assert!(results[1]["queue_id"].is_null());
+
+        let (status, body) = send(batch(json!([{ "to_email": "bob" }]))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["data"]["results"][0]["error"], "to_email must be an email address");
+
+        let too_many: Vec<Value> = (0..501)
+            .map(|n| json!({ "to_email": format!("user{}@example.com", n) }))
+            .collect();
+        let (status, body) = send(batch(json!(too_many))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation");
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_an_idempotency_key_answers_a_retry_with_the_email_queued() {
+        let pb = mock_pb().await;
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        ));
+        let send = |path: &str, key: Option<&str>, body: Value| {
+            let mut request = Request::post(path).header("content-type", "application/json");
+            if let Some(key) = key {
+                request = request.header("Idempotency-Key", key);
+            }
+            call(router(emails.clone()), request.body(Body::from(body.to_string())).unwrap())
+        };
+        let notice = json!({ "to_email": "alice@example.com", "subject": "Failed", "body_text": "Task 42 failed" });
+
+        let (status, first) = send("/send-email", Some("task-42"), notice.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, retried) = send("/send-email", Some("task-42"), notice.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(retried["data"]["queue_id"], first["data"]["queue_id"]);
+        // The body's key is the same key
+        let mut in_body = notice.clone();
+        in_body["idempotency_key"] = json!("task-42");
+        let (_, retried) = send("/send-email", None, in_body.clone()).await;
+        assert_eq!(retried["data"]["queue_id"], first["data"]["queue_id"]);
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+
+        let (status, body) = send("/send-email", Some("task-43"), in_body).await;
+        assert_eq!((status, &body["code"]), (StatusCode::BAD_REQUEST, &json!("validation")));
+        let other = json!({ "to_email": "bob@example.com", "subject": "Failed", "body_text": "Task 42 failed" });
+        let (status, body) = send("/send-email", Some("task-42"), other).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "idempotency_key_reused");
+
+        let digest = json!({
+            "template": "generic",
+            "template_data": { "subject": "Your weekly digest", "message": "Nothing new" },
+            "recipients": [{ "to_email": "carol@example.com" }],
+        });
+        let (_, first) = send("/send-email/batch", Some("digest-1"), digest.clone()).await;
+        let (status, retried) = send("/send-email/batch", Some("digest-1"), digest).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(retried["data"]["results"], first["data"]["results"]);
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_a_suppressed_address_is_refused_until_taken_off_the_list() {
+        let pb = mock_pb().await;
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        ));
+        pb.insert(
+            SUPPRESSION_LIST,
+            json!({
+                "email": "alice@example.com",
+                "reason": "bounce",
+                "detail": "550 user unknown",
+                "source": "ses",
+            }),
+        );
+        let email = json!({ "to_email": "alice@example.com", "subject": "Hi", "body_text": "Hi" });
+
+        let (status, body) = post(&pb_settings(&pb), email.clone()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "suppressed");
+
+        let (status, body) = get(&emails, "/suppressions/Alice@Example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["email"], "alice@example.com");
+        assert_eq!(body["data"]["reason"], "bounce");
+        assert_eq!(body["data"]["detail"], "550 user unknown");
+
+        let (status, body) = delete(&emails, "/suppressions/alice@example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["source"], "ses");
+        assert!(pb.records(SUPPRESSION_LIST).is_empty());
+        for (status, body) in [
+            get(&emails, "/suppressions/alice@example.com").await,
+            delete(&emails, "/suppressions/alice@example.com").await,
+        ] {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "not_found");
+        }
+
+        let (status, _) = post(&pb_settings(&pb), email).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn test_recipients_are_masked_past_their_first_character() {
+        assert_eq!(mask_email("alice@example.com"), "a***@example.com");
+        assert_eq!(mask_email("a@example.com"), "a***@example.com");
+        assert_eq!(mask_email("\"a@b\"@example.com"), "\"***@example.com");
+        assert_eq!(mask_email("alice"), "***");
+    }
+
+    #[tokio::test]
+    async fn test_an_email_can_be_followed_through_the_queue() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            "smtp-a",
+        ));
+        let queue = |to_email: &str| SendEmailRequest {
+            to_email: to_email.to_string(),
+            subject: Some("Your meeting is on Loom".to_string()),
+            body_text: "It's ready".to_string(),
+            ..Default::default()
+        };
+        let rejected = emails.queue_email(&queue("nobody@example.com")).await.unwrap();
+        let delivered = emails.queue_email(&queue("alice@example.org")).await.unwrap();
+
+        let (status, body) = get(&emails, &format!("/emails/{}", delivered)).await;
+        assert_eq!(status, StatusCode::OK);
+        let email = &body["data"];
+        assert_eq!(email["id"], delivered.as_str());
+        assert_eq!(email["status"], "pending");
+        assert_eq!(email["attempts"], 0);
+        assert_eq!(email["to_email"], "a***@example.org");
+        assert_eq!(email["subject"], "Your meeting is on Loom");
+        assert!(email["created_at"].is_string() && email["next_attempt_at"].is_string());
+        assert!(email["sent_at"].is_null() && email["last_error"].is_null());
+        assert!(email.get("body_text").is_none(), "bodies stay in the queue");
+
+        transport.fail_next(SendError::Permanent("550 No such user".to_string()));
+        emails.process_queue().await.unwrap();
+        let (_, body) = get(&emails, &format!("/emails/{}", rejected)).await;
+        assert_eq!(body["data"]["status"], "failed");
+        assert_eq!(body["data"]["attempts"], 1);
+        assert_eq!(body["data"]["last_error"], "SMTP error: 550 No such user");
+        let (_, body) = get(&emails, &format!("/emails/{}", delivered)).await;
+        assert_eq!(body["data"]["status"], "sent");
+        assert_eq!(body["data"]["attempts"], 1);
+        assert!(body["data"]["sent_at"].is_string());
+        assert_eq!(body["data"]["provider"], "default");
+
+        for unknown in ["/emails/r99999999999999", "/emails/..%2Fr00000000000001"] {
+            let (status, body) = get(&emails, unknown).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", unknown);
+            assert_eq!(body["code"], "not_found");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_scheduled_email_can_be_cancelled_until_it_is_sent() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            "smtp-a",
+        ));
+        let email = |send_at: Option<DateTime<Utc>>| SendEmailRequest {
+            to_email: "alice@example.com".to_string(),
+            subject: Some("Your API key expires soon".to_string()),
+            body_text: "Renew it".to_string(),
+            send_at,
+            ..Default::default()
+        };
+        let send_at = Utc::now() + chrono::Duration::hours(1);
+        let scheduled = emails.queue_email(&email(Some(send_at))).await.unwrap();
+        let immediate = emails.queue_email(&email(None)).await.unwrap();
+
+        let (_, body) = get(&emails, &format!("/emails/{}", scheduled)).await;
+        let shown: DateTime<Utc> = serde_json::from_value(body["data"]["send_at"].clone()).unwrap();
+        assert_eq!(shown.timestamp_millis(), send_at.timestamp_millis());
+        assert!(body["data"]["send_at"] == body["data"]["next_attempt_at"]);
+
+        let (status, body) = delete(&emails, &format!("/emails/{}", scheduled)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], "cancelled");
+
+        emails.process_queue().await.unwrap();
+        assert_eq!(transport.sent().len(), 1);
+        let (status, body) = delete(&emails, &format!("/emails/{}", immediate)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "The email is sent and can no longer be cancelled");
+        let (status, _) = delete(&emails, "/emails/r99999999999999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_emails_are_listed_by_status_and_time_newest_first() {
+        let pb = mock_pb().await;
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        ));
+        let queued = [
+            ("alice@example.com", "failed", "2026-05-01 09:00:00.000Z"),
+            ("bob@example.com", "sent", "2026-05-02 09:00:00.000Z"),
+            ("carol@example.com", "failed", "2026-05-03 09:00:00.000Z"),
+            ("dave@example.com", "failed", "2026-05-04 09:00:00.000Z"),
+            ("erin@example.com", "pending", "2026-05-05 09:00:00.000Z"),
+        ];
+        for (to_email, status, created) in queued {
+            pb.insert(
+                EMAIL_QUEUE,
+                json!({ "to_email": to_email, "status": status, "attempts": 1, "created": created }),
+            );
+        }
+        let recipients = |body: &Value| -> Vec<String> {
+            body["data"]["emails"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|email| email["to_email"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let (status, body) = get(&emails, "/emails").await;
+        assert_eq!(status, StatusCode::OK);
+        let newest_first = ["e", "d", "c", "b", "a"].map(|first| format!("{}***@example.com", first));
+        assert_eq!(recipients(&body), newest_first);
+        assert_eq!(body["data"]["per_page"], DEFAULT_PER_PAGE);
+
+        let (_, body) = get(&emails, "/emails?status=failed").await;
+        assert_eq!(recipients(&body), ["d***@example.com", "c***@example.com", "a***@example.com"]);
+        assert_eq!(body["data"]["total_items"], 3);
+
+        let paged = "/emails?status=failed&since=2026-05-02T00:00:00Z&per_page=1&page=2";
+        let (_, body) = get(&emails, paged).await;
+        assert_eq!(recipients(&body), ["c***@example.com"]);
+        assert_eq!((body["data"]["page"].as_u64(), body["data"]["total_items"].as_u64()), (Some(2), Some(2)));
+
+        let (status, body) = get(&emails, "/emails?status=bounced").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation");
+        let (status, _) = get(&emails, "/emails?since=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_failed_emails_can_be_listed_and_requeued_by_a_caller() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            "smtp-a",
+        ));
+        let queue = |to_email: &str| SendEmailRequest {
+            to_email: to_email.to_string(),
+            subject: Some("Your meeting is on Loom".to_string()),
+            body_text: "It's ready".to_string(),
+            ..Default::default()
+        };
+        let failed = emails.queue_email(&queue("alice@example.com")).await.unwrap();
+        let delivered = emails.queue_email(&queue("bob@example.com")).await.unwrap();
+        transport.fail_next(SendError::Permanent("535 Authentication failed".to_string()));
+        emails.process_queue().await.unwrap();
+        // As main.rs serves it, so requeues note the caller's key label
+        let keys = crate::auth::ApiKeys::parse("backend:k-one").unwrap();
+        let app = router(emails.clone())
+            .layer(axum::middleware::from_fn_with_state(keys, crate::auth::require_api_key));
+        let post = |uri: &str| {
+            let request = Request::post(uri).header("authorization", "Bearer k-one");
+            call(app.clone(), request.body(Body::empty()).unwrap())
+        };
+
+        let (status, body) = get(&emails, "/emails/dead_letter?to_email=alice@example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed = body["data"]["emails"].as_array().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["id"], failed.as_str());
+        assert!(listed[0]["updated_at"].is_string());
+        let (_, body) = get(&emails, "/emails/dead_letter?template=welcome").await;
+        assert_eq!(body["data"]["total_items"], 0);
+        let (status, _) = get(&emails, "/emails/dead_letter?until=tomorrow").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = post(&format!("/emails/{}/requeue", failed)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], "pending");
+        assert_eq!(body["data"]["attempts"], 0);
+        assert_eq!(body["data"]["requeued_by"], "backend");
+        let (status, body) = post(&format!("/emails/{}/requeue", delivered)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "Only failed emails can be requeued, not sent ones");
+        let (status, _) = post("/emails/r99999999999999/requeue").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = post("/emails/dead_letter/requeue_all").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation");
+        let (status, body) = post("/emails/dead_letter/requeue_all?since=2026-01-01T00:00:00Z").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!({ "requeued": 0, "suppressed": 0 }));
+        let unauthorized = Request::post("/emails/dead_letter/requeue_all?since=2026-01-01T00:00:00Z");
+        let (status, _) = call(app.clone(), unauthorized.body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    /// A service sending through `primary`, then `backup`
+    fn two_providers(pb: &MockPb, primary: &FakeTransport, backup: &FakeTransport) -> Arc<EmailService> {
+        let providers = Providers::new(
+            vec![
+                ("primary".to_string(), Arc::new(primary.clone()) as Arc<dyn Transport>),
+                ("backup".to_string(), Arc::new(backup.clone())),
+            ],
+            BreakerPolicy::default(),
+        );
+        let emails = EmailService::new(pb.client(), Arc::new(primary.clone()), "smtp-a")
+            .with_providers(providers);
+        Arc::new(emails)
+    }
+
+    async fn test_smtp_with(emails: &Arc<EmailService>, body: Value) -> (StatusCode, Value) {
+        let request = Request::post("/test-smtp")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        call(router(emails.clone()), request).await
+    }
+
+    #[tokio::test]
+    async fn test_connect_mode_reports_each_provider_without_sending() {
+        let pb = mock_pb().await;
+        let primary = FakeTransport::at(Endpoint {
+            host: "smtp.primary.example".to_string(),
+            port: 587,
+            security: Security::StartTls,
+            authenticates: true,
+        });
+        let backup = FakeTransport::default();
+        backup.refuse_connections(SendError::Permanent("535 5.7.8 Authentication failed".to_string()));
+        let emails = two_providers(&pb, &primary, &backup);
+
+        let (status, body) = test_smtp_with(&emails, json!({ "mode": "connect" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["mode"], "connect");
+        let reports = &body["data"]["providers"];
+        assert_eq!(reports[0]["name"], "primary");
+        assert_eq!(reports[0]["connected"], true);
+        assert!(reports[0]["error"].is_null());
+        assert!(reports[0]["elapsed_ms"].is_u64());
+        assert_eq!(
+            reports[0]["server"],
+            json!({ "host": "smtp.primary.example", "port": 587, "security": "starttls", "authenticates": true })
+        );
+        assert_eq!(reports[1]["connected"], false);
+        assert_eq!(reports[1]["error"], "SMTP error: 535 5.7.8 Authentication failed");
+        assert!(reports[1]["server"].is_null());
+        assert!(primary.sent().is_empty() && backup.sent().is_empty());
+
+        // Connect is what an empty body asks for
+        let (status, body) = test_smtp_with(&emails, json!({})).await;
+        assert_eq!((status, &body["data"]["mode"]), (StatusCode::OK, &json!("connect")));
+    }
+
+    #[tokio::test]
+    async fn test_send_mode_sends_the_test_template_outside_the_queue() {
+        let pb = mock_pb().await;
+        let (primary, backup) = (FakeTransport::default(), FakeTransport::default());
+        let emails = two_providers(&pb, &primary, &backup);
+
+        let body = json!({ "mode": "send", "test_email": "admin@example.com" });
+        let (status, answer) = test_smtp_with(&emails, body.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(answer["data"]["mode"], "send");
+        assert_eq!(answer["data"]["test_email"], "admin@example.com");
+        assert_eq!(answer["data"]["provider"], "primary");
+        let sent = primary.sent();
+        assert_eq!(sent[0].to_email, "admin@example.com");
+        assert_eq!(sent[0].subject, "[Test] Fathom to Loom SMTP check");
+        assert!(sent[0].body_text.starts_with("This is a test email."));
+        assert!(pb.records(EMAIL_QUEUE).is_empty(), "nothing is queued");
+
+        primary.fail_next(SendError::Permanent("550 Relaying denied".to_string()));
+        let (status, answer) = test_smtp_with(&emails, body).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(answer["code"], "service_unavailable");
+        assert!(answer["error"].as_str().unwrap().contains("550 Relaying denied"));
+    }
+
+    #[tokio::test]
+    async fn test_the_test_email_is_taken_in_send_mode_only() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            "smtp-a",
+        ));
+        for (body, message) in [
+            (json!({ "mode": "connect", "test_email": "admin@example.com" }), "test_email is only taken in send mode"),
+            (json!({ "mode": "send" }), "test_email must be given in send mode"),
+            (json!({ "mode": "send", "test_email": "admin" }), "test_email must be an email address"),
+        ] {
+            let (status, answer) = test_smtp_with(&emails, body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(answer["code"], "validation");
+            assert_eq!(answer["error"], message);
+        }
+        assert!(transport.sent().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Files sent along with an email
+//!
+//! `POST /send-email` takes `attachments` of `filename`, `content_type` and
+//! base64 `data_base64`. Each is checked and its filename cleaned when the
+//! email is queued, and kept base64 in the queue record's `attachments`
+//! field, so an email waiting in the queue still has its files after a
+//! restart. An attachment over `EMAIL_ATTACHMENT_MAX_BYTES`, or attachments
+//! adding up to more than `EMAIL_ATTACHMENTS_MAX_TOTAL_BYTES`, are refused
+//! with 413, both limits counting decoded bytes.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lettre::message::header::ContentType;
+use serde::{Deserialize, Serialize};
+
+/// Longest filename kept, in characters
+pub const MAX_FILENAME_CHARS: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data_base64: String,
+}
+
+impl Attachment {
+    /// The file's bytes; only attachments that were checked are queued
+    pub fn data(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        STANDARD.decode(&self.data_base64)
+    }
+}
+
+/// How large attachments may be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachmentLimits {
+    pub per_attachment_bytes: usize,
+    pub total_bytes: usize,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            per_attachment_bytes: 5 * 1024 * 1024,
+            total_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl AttachmentLimits {
+    /// Read EMAIL_ATTACHMENT_MAX_BYTES and EMAIL_ATTACHMENTS_MAX_TOTAL_BYTES
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let defaults = Self::default();
+        let positive = |var: &str, default: usize| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(parsed),
+                _ => Err(format!("{} must be a positive number of bytes, not '{}'", var, value)),
+            },
+            None => Ok(default),
+        };
+        Ok(Self {
+            per_attachment_bytes: positive("EMAIL_ATTACHMENT_MAX_BYTES", defaults.per_attachment_bytes)?,
+            total_bytes: positive("EMAIL_ATTACHMENTS_MAX_TOTAL_BYTES", defaults.total_bytes)?,
+        })
+    }
+
+    /// The body `/send-email` must accept when `ordinary` bytes are allowed
+    /// for everything else: the attachments grow by a third in base64
+    pub fn body_bytes(&self, ordinary: usize) -> usize {
+        ordinary + self.total_bytes.div_ceil(3) * 4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("{0}")]
+    TooLarge(String),
+
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// `attachments` with their filenames cleaned, once each decodes, names a
+/// content type and fits `limits`
+pub fn check(attachments: &[Attachment], limits: &AttachmentLimits) -> Result<Vec<Attachment>, AttachmentError> {
+    let mut total = 0;
+    let mut checked = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let filename = sanitize_filename(&attachment.filename);
+        let size = attachment
+            .data()
+            .map_err(|_| AttachmentError::Invalid(format!("attachment '{}' isn't valid base64", filename)))?
+            .len();
+        if ContentType::parse(&attachment.content_type).is_err() {
+            return Err(AttachmentError::Invalid(format!(
+                "attachment '{}' has an invalid content_type '{}'",
+                filename, attachment.content_type
+            )));
+        }
+        if size > limits.per_attachment_bytes {
+            return Err(AttachmentError::TooLarge(format!(
+                "attachment '{}' is {} bytes; each may be at most {}",
+                filename, size, limits.per_attachment_bytes
+            )));
+        }
+        total += size;
+        checked.push(Attachment {
+            filename,
+            ..attachment.clone()
+        });
+    }
+    if total > limits.total_bytes {
+        return Err(AttachmentError::TooLarge(format!(
+            "attachments add up to {} bytes; together they may be at most {}",
+            total, limits.total_bytes
+        )));
+    }
+    Ok(checked)
+}
+
+/// `filename` without any directories, control characters or leading
+/// dots, or `attachment` when nothing is left
+pub fn sanitize_filename(filename: &str) -> String {
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base.chars().filter(|c| !c.is_control()).collect();
+    let cleaned: String = cleaned
+        .trim()
+        .trim_start_matches('.')
+        .trim()
+        .chars()
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    match cleaned.is_empty() {
+        true => "attachment".to_string(),
+        false => cleaned,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn attachment(filename: &str, size: usize) -> Attachment {
+        Attachment {
+            filename: filename.to_string(),
+            content_type: "text/csv".to_string(),
+            data_base64: STANDARD.encode(vec![b'x'; size]),
+        }
+    }
+
+    #[test]
+    fn test_filenames_lose_paths_and_control_characters() {
+        assert_eq!(sanitize_filename("queue-export.csv"), "queue-export.csv");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\alice\\backup.zip"), "backup.zip");
+        assert_eq!(sanitize_filename("report\r\nBcc: x.pdf"), "reportBcc: x.pdf");
+        assert_eq!(sanitize_filename(" .hidden "), "hidden");
+        assert_eq!(sanitize_filename("dir/"), "attachment");
+        assert_eq!(sanitize_filename(&"a".repeat(300)).len(), MAX_FILENAME_CHARS);
+    }
+
+    #[test]
+    fn test_attachments_must_fit_the_limits() {
+        let limits = AttachmentLimits {
+            per_attachment_bytes: 10,
+            total_bytes: 15,
+        };
+        let checked = check(&[attachment("../a.csv", 10), attachment("b.csv", 5)], &limits).unwrap();
+        assert_eq!(checked[0].filename, "a.csv");
+        assert_eq!(checked[1], attachment("b.csv", 5));
+
+        assert_eq!(
+            check(&[attachment("big.csv", 11)], &limits),
+            Err(AttachmentError::TooLarge("attachment 'big.csv' is 11 bytes; each may be at most 10".to_string()))
+        );
+        assert!(matches!(
+            check(&[attachment("a.csv", 10), attachment("b.csv", 6)], &limits),
+            Err(AttachmentError::TooLarge(message)) if message.contains("add up to 16 bytes")
+        ));
+
+        let garbled = Attachment {
+            data_base64: "not base64!".to_string(),
+            ..attachment("a.csv", 1)
+        };
+        assert!(matches!(check(&[garbled], &limits), Err(AttachmentError::Invalid(_))));
+        let untyped = Attachment {
+            content_type: "csv".to_string(),
+            ..attachment("a.csv", 1)
+        };
+        assert!(matches!(check(&[untyped], &limits), Err(AttachmentError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_limits_come_from_the_environment() {
+        let vars = HashMap::from([("EMAIL_ATTACHMENT_MAX_BYTES", "1000")]);
+        let limits = AttachmentLimits::from_lookup(|var| vars.get(var).map(|value| value.to_string())).unwrap();
+        assert_eq!(limits.per_attachment_bytes, 1000);
+        assert_eq!(limits.total_bytes, 10 * 1024 * 1024);
+        assert_eq!(limits.body_bytes(100), 100 + 13_981_016);
+
+        let vars = HashMap::from([("EMAIL_ATTACHMENTS_MAX_TOTAL_BYTES", "0")]);
+        assert!(AttachmentLimits::from_lookup(|var| vars.get(var).map(|value| value.to_string())).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Bearer-token auth for every route but `/health`
+//!
+//! `SMTP_SERVICE_API_KEYS` lists the keys callers may present, each as
+//! `label:key` and comma separated, so the backend and the worker have keys
+//! of their own and one can be rotated by listing old and new together for a
+//! while. A request without one of them is answered 401 `invalid_token`; one
+//! with a key has that key's label, never the key, in its `request` span.
+//! With no keys listed every such request is refused.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use common::{crypto::constant_time_eq, ApiResponse, ErrorCode};
+use std::{collections::HashSet, sync::Arc};
+use tracing::{warn, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ApiKey {
+    label: String,
+    key: String,
+}
+
+/// The keys `SMTP_SERVICE_API_KEYS` lists
+#[derive(Clone, Default)]
+pub struct ApiKeys(Arc<Vec<ApiKey>>);
+
+impl std::fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ApiKeys").field(&self.labels()).finish()
+    }
+}
+
+impl ApiKeys {
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(&std::env::var("SMTP_SERVICE_API_KEYS").unwrap_or_default())
+    }
+
+    /// `label:key` pairs, comma separated; labels and keys are each unique
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut keys = Vec::new();
+        let (mut labels, mut seen) = (HashSet::new(), HashSet::new());
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (label, key) = entry
+                .split_once(':')
+                .map(|(label, key)| (label.trim(), key.trim()))
+                .filter(|(label, key)| !label.is_empty() && !key.is_empty())
+                .ok_or_else(|| {
+                    "SMTP_SERVICE_API_KEYS entries must be label:key with neither empty".to_string()
+                })?;
+            if !labels.insert(label) {
+                return Err(format!("SMTP_SERVICE_API_KEYS lists the label '{}' twice", label));
+            }
+            if !seen.insert(key) {
+                return Err(format!("SMTP_SERVICE_API_KEYS gives '{}' a key already listed", label));
+            }
+            keys.push(ApiKey {
+                label: label.to_string(),
+                key: key.to_string(),
+            });
+        }
+        Ok(Self(Arc::new(keys)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn labels(&self) -> Vec<&str> {
+        self.0.iter().map(|key| key.label.as_str()).collect()
+    }
+
+    /// The label of the key `presented` is, compared against every key so
+    /// timing tells neither which matched nor how much of one did
+    pub fn label_for(&self, presented: &str) -> Option<&str> {
+        self.0.iter().fold(None, |found, key| {
+            let matches = constant_time_eq(key.key.as_bytes(), presented.as_bytes());
+            found.or(matches.then_some(key.label.as_str()))
+        })
+    }
+}
+
+/// The label of the key a request was let through with, in its extensions
+/// for handlers that note who did what
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller(pub String);
+
+/// Let the request through if it bears one of `keys`
+pub async fn require_api_key(State(keys): State<ApiKeys>, mut request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented.and_then(|presented| keys.label_for(presented)) {
+        Some(label) => {
+            Span::current().record("api_key", label);
+            request.extensions_mut().insert(Caller(label.to_string()));
+            next.run(request).await
+        }
+        None => {
+            warn!(target: "audit", path = %request.uri().path(), "Request without a valid API key refused");
+            let body = ApiResponse::<()>::failure(ErrorCode::InvalidToken, "A valid API key is required");
+            let mut response = (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn get_with(keys: &ApiKeys, authorization: Option<&str>) -> (StatusCode, Value) {
+        let app = Router::new()
+            .route("/send-email", get(|| async { Json(ApiResponse::success("sent")) }))
+            .layer(middleware::from_fn_with_state(keys.clone(), require_api_key));
+        let mut request = Request::get("/send-email");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn test_keys_are_labelled_and_unique() {
+        let keys = ApiKeys::parse(" backend:k-one , worker:k-two,").unwrap();
+        assert_eq!(keys.labels(), ["backend", "worker"]);
+        assert_eq!(keys.label_for("k-two"), Some("worker"));
+        assert_eq!(keys.label_for("k-tw"), None);
+        assert!(!format!("{:?}", keys).contains("k-one"));
+
+        assert!(ApiKeys::parse("").unwrap().is_empty());
+        assert!(ApiKeys::parse("backend").is_err());
+        assert!(ApiKeys::parse("backend:").is_err());
+        assert!(ApiKeys::parse("backend:a,backend:b").is_err());
+        assert!(ApiKeys::parse("backend:a,worker:a").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requests_need_one_of_the_keys() {
+        let keys = ApiKeys::parse("backend:k-one,worker:k-two").unwrap();
+
+        let (status, body) = get_with(&keys, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "invalid_token");
+        assert_eq!(body["success"], false);
+
+        let (status, _) = get_with(&keys, Some("Bearer k-three")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = get_with(&keys, Some("k-one")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "the key must be a bearer token");
+
+        for key in ["k-one", "k-two"] {
+            let (status, body) = get_with(&keys, Some(&format!("Bearer {}", key))).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["data"], "sent");
+        }
+
+        // With no keys listed, nothing gets through
+        let (status, _) = get_with(&ApiKeys::default(), Some("Bearer ")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
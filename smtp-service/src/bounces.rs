@@ -0,0 +1,610 @@
+//! `POST /webhooks/bounce`, where mail providers report what became of the
+//! emails they were handed
+//!
+//! Providers can't present an API key, so each report is checked by its
+//! signature instead. One with an `x-amz-sns-message-type` header is an SES
+//! notification delivered by SNS, signed with the certificate its
+//! `SigningCertURL` names; that must be on an `sns.<region>.amazonaws.com`
+//! host, and is fetched once and kept. Any AWS account can have SNS sign
+//! for its topics, so only messages from the topics in
+//! `BOUNCE_SNS_TOPIC_ARNS` are taken, and none when it is unset. A
+//! subscription confirmation from one of them is confirmed by fetching its
+//! `SubscribeURL`. Any other report is in the
+//! generic format, a JSON event with `X-Webhook-Signature:
+//! sha256=<hex HMAC-SHA256 of the body>` keyed with `BOUNCE_WEBHOOK_SECRET`.
+//!
+//! Hard bounces and complaints put their recipients on the suppression
+//! list. Soft bounces are only logged, as the retries for them may yet get
+//! through.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::post,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use common::{ApiResponse, ErrorCode};
+use ring::{
+    hmac,
+    signature::{
+        UnparsedPublicKey, VerificationAlgorithm, RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+        RSA_PKCS1_2048_8192_SHA256,
+    },
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc, sync::Mutex, time::Duration};
+use tracing::{error, info, warn};
+
+use crate::{
+    email_service::EmailService,
+    suppressions::{Report, SuppressionReason},
+};
+
+/// How long fetching a signing certificate or confirming a subscription
+/// may take
+const SNS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a report was turned away
+#[derive(Debug, thiserror::Error)]
+enum Rejected {
+    #[error("{0}")]
+    Signature(String),
+
+    #[error("{0}")]
+    Malformed(String),
+}
+
+/// How reports are checked to come from the provider
+pub struct BounceVerifier {
+    secret: Option<hmac::Key>,
+    /// The SNS topics whose messages are taken
+    topics: Vec<String>,
+    client: reqwest::Client,
+    /// The public key of each SNS signing certificate fetched, by its URL
+    certificates: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl BounceVerifier {
+    /// Generic reports keyed with `secret`, refused without one, and SNS
+    /// messages from `topics`
+    pub fn new(secret: Option<&str>, topics: Vec<String>) -> Self {
+        Self {
+            secret: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            topics,
+            client: reqwest::Client::builder()
+                .timeout(SNS_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            certificates: Mutex::default(),
+        }
+    }
+
+    /// Read BOUNCE_WEBHOOK_SECRET and the comma-separated
+    /// BOUNCE_SNS_TOPIC_ARNS
+    pub fn from_env() -> Self {
+        let secret = std::env::var("BOUNCE_WEBHOOK_SECRET").ok();
+        let topics = std::env::var("BOUNCE_SNS_TOPIC_ARNS").unwrap_or_default();
+        let topics = topics
+            .split(',')
+            .map(str::trim)
+            .filter(|topic| !topic.is_empty())
+            .map(String::from)
+            .collect();
+        Self::new(secret.as_deref().filter(|secret| !secret.trim().is_empty()), topics)
+    }
+
+    pub fn has_secret(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Take the certificate at `url` as fetched already
+    #[cfg(test)]
+    pub fn with_certificate(self, url: &str, pem: &str) -> Self {
+        let key = certificate_key(pem).unwrap();
+        self.certificates.lock().unwrap().insert(url.to_string(), key);
+        self
+    }
+
+    /// The suppressions a generic report makes, if its signature is right
+    fn generic(&self, headers: &HeaderMap, body: &[u8]) -> Result<Vec<Report>, Rejected> {
+        let Some(secret) = &self.secret else {
+            return Err(Rejected::Signature("BOUNCE_WEBHOOK_SECRET is unset".to_string()));
+        };
+        let signature = headers
+            .get("x-webhook-signature")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().strip_prefix("sha256="))
+            .and_then(decode_hex)
+            .ok_or_else(|| Rejected::Signature("X-Webhook-Signature must be sha256=<hex>".to_string()))?;
+        hmac::verify(secret, body, &signature)
+            .map_err(|_| Rejected::Signature("The signature doesn't match the body".to_string()))?;
+        let event: GenericEvent = serde_json::from_slice(body)
+            .map_err(|e| Rejected::Malformed(format!("Invalid report: {}", e)))?;
+        let reason = match event.kind {
+            GenericKind::HardBounce => SuppressionReason::Bounce,
+            GenericKind::Complaint => SuppressionReason::Complaint,
+            GenericKind::SoftBounce => {
+                info!(to = %event.email, "Soft bounce reported: {}", event.detail);
+                return Ok(Vec::new());
+            }
+        };
+        Ok(vec![Report {
+            email: event.email,
+            reason,
+            detail: event.detail,
+        }])
+    }
+
+    /// The suppressions an SNS message makes, if its signature is right
+    async fn sns(&self, body: &[u8]) -> Result<Vec<Report>, Rejected> {
+        let message: SnsMessage = serde_json::from_slice(body)
+            .map_err(|e| Rejected::Malformed(format!("Invalid SNS message: {}", e)))?;
+        // Before the signature, so a foreign topic's certificate isn't fetched
+        if !self.topics.contains(&message.topic_arn) {
+            return Err(Rejected::Signature(format!(
+                "Topic '{}' isn't in BOUNCE_SNS_TOPIC_ARNS",
+                message.topic_arn
+            )));
+        }
+        self.verify_sns(&message).await?;
+        match message.kind.as_str() {
+            "Notification" => ses_reports(&message.message),
+            "SubscriptionConfirmation" => {
+                let url = message.subscribe_url.as_deref().unwrap_or_default();
+                if !is_sns_url(url) {
+                    return Err(Rejected::Malformed(format!(
+                        "SubscribeURL '{}' isn't on SNS",
+                        url
+                    )));
+                }
+                match self
+                    .client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                {
+                    Ok(_) => info!(topic = %message.topic_arn, "Subscribed to bounce notifications"),
+                    Err(e) => {
+                        warn!(topic = %message.topic_arn, "Failed to confirm an SNS subscription: {}", e)
+                    }
+                }
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn verify_sns(&self, message: &SnsMessage) -> Result<(), Rejected> {
+        let algorithm: &'static dyn VerificationAlgorithm = match message.signature_version.as_str() {
+            "1" => &RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+            "2" => &RSA_PKCS1_2048_8192_SHA256,
+            other => {
+                return Err(Rejected::Signature(format!(
+                    "Unknown SignatureVersion '{}'",
+                    other
+                )))
+            }
+        };
+        let key = self.certificate(&message.signing_cert_url).await?;
+        let signature = BASE64
+            .decode(&message.signature)
+            .map_err(|_| Rejected::Signature("Signature isn't base64".to_string()))?;
+        UnparsedPublicKey::new(algorithm, key)
+            .verify(message.string_to_sign().as_bytes(), &signature)
+            .map_err(|_| Rejected::Signature("The SNS signature doesn't match the message".to_string()))
+    }
+
+    /// The public key of the SNS certificate at `url`
+    async fn certificate(&self, url: &str) -> Result<Vec<u8>, Rejected> {
+        if let Some(key) = self.certificates.lock().unwrap().get(url) {
+            return Ok(key.clone());
+        }
+        if !is_sns_url(url) || !url.ends_with(".pem") {
+            return Err(Rejected::Signature(format!(
+                "SigningCertURL '{}' isn't an SNS certificate",
+                url
+            )));
+        }
+        let pem = async {
+            self.client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|e| Rejected::Signature(format!("The SNS certificate couldn't be fetched: {}", e)))?;
+        let key = certificate_key(&pem)
+            .ok_or_else(|| Rejected::Signature("The SNS certificate can't be read".to_string()))?;
+        self.certificates
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), key.clone());
+        Ok(key)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GenericKind {
+    HardBounce,
+    SoftBounce,
+    Complaint,
+}
+
+/// A report in the generic format
+#[derive(Debug, Deserialize)]
+struct GenericEvent {
+    #[serde(rename = "type")]
+    kind: GenericKind,
+    email: String,
+    #[serde(default)]
+    detail: String,
+}
+
+/// The fields of an SNS message that are signed, and its signature
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SnsMessage {
+    #[serde(rename = "Type")]
+    kind: String,
+    message_id: String,
+    topic_arn: String,
+    message: String,
+    timestamp: String,
+    subject: Option<String>,
+    token: Option<String>,
+    #[serde(rename = "SubscribeURL")]
+    subscribe_url: Option<String>,
+    signature_version: String,
+    signature: String,
+    #[serde(rename = "SigningCertURL")]
+    signing_cert_url: String,
+}
+
+impl SnsMessage {
+    /// The name and value lines SNS signs, in the order it signs them
+    fn string_to_sign(&self) -> String {
+        let fields = match self.kind.as_str() {
+            "Notification" => vec![
+                ("Message", Some(&self.message)),
+                ("MessageId", Some(&self.message_id)),
+                ("Subject", self.subject.as_ref()),
+                ("Timestamp", Some(&self.timestamp)),
+                ("TopicArn", Some(&self.topic_arn)),
+                ("Type", Some(&self.kind)),
+            ],
+            _ => vec![
+                ("Message", Some(&self.message)),
+                ("MessageId", Some(&self.message_id)),
+                ("SubscribeURL", self.subscribe_url.as_ref()),
+                ("Timestamp", Some(&self.timestamp)),
+                ("Token", self.token.as_ref()),
+                ("TopicArn", Some(&self.topic_arn)),
+                ("Type", Some(&self.kind)),
+            ],
+        };
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{}\n{}\n", name, value)))
+            .collect()
+    }
+}
+
+/// Whether `url` is an https URL on an SNS host
+fn is_sns_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| {
+        let host = url.host_str().unwrap_or_default();
+        url.scheme() == "https"
+            && host.starts_with("sns.")
+            && (host.ends_with(".amazonaws.com") || host.ends_with(".amazonaws.com.cn"))
+    })
+}
+
+/// The suppressions an SES bounce or complaint notification makes
+fn ses_reports(message: &str) -> Result<Vec<Report>, Rejected> {
+    let message: Value = serde_json::from_str(message)
+        .map_err(|e| Rejected::Malformed(format!("Invalid SES notification: {}", e)))?;
+    let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+    let recipients = |list: &Value, reason, detail: &dyn Fn(&Value) -> String| {
+        list.as_array()
+            .into_iter()
+            .flatten()
+            .map(|recipient| Report {
+                email: text(&recipient["emailAddress"]),
+                reason,
+                detail: detail(recipient),
+            })
+            .filter(|report| !report.email.is_empty())
+            .collect()
+    };
+    let kind = message.get("notificationType").or(message.get("eventType"));
+    Ok(match kind.and_then(Value::as_str) {
+        Some("Bounce") if message["bounce"]["bounceType"] == "Permanent" => {
+            let bounce = &message["bounce"];
+            recipients(
+                &bounce["bouncedRecipients"],
+                SuppressionReason::Bounce,
+                &|recipient| match recipient["diagnosticCode"].as_str() {
+                    Some(code) => code.to_string(),
+                    None => text(&bounce["bounceSubType"]),
+                },
+            )
+        }
+        Some("Complaint") => {
+            let complaint = &message["complaint"];
+            recipients(
+                &complaint["complainedRecipients"],
+                SuppressionReason::Complaint,
+                &|_| text(&complaint["complaintFeedbackType"]),
+            )
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// The DER element `der` starts with: its tag, its contents and what
+/// follows it
+fn element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        _ => {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let length = rest[..count]
+                .iter()
+                .fold(0, |length, &byte| length << 8 | byte as usize);
+            (length, &rest[count..])
+        }
+    };
+    (rest.len() >= length).then(|| (tag, &rest[..length], &rest[length..]))
+}
+
+/// The RSA public key of a PEM X.509 certificate, as ring verifies with
+fn certificate_key(pem: &str) -> Option<Vec<u8>> {
+    let encoded: String = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != "-----BEGIN CERTIFICATE-----")
+        .skip(1)
+        .take_while(|line| *line != "-----END CERTIFICATE-----")
+        .collect();
+    let der = BASE64.decode(encoded).ok()?;
+    let (0x30, certificate, _) = element(&der)? else {
+        return None;
+    };
+    let (0x30, mut fields, _) = element(certificate)? else {
+        return None;
+    };
+    // An explicit version, then the serial number, signature algorithm,
+    // issuer, validity and subject come before the key
+    if fields.first() == Some(&0xa0) {
+        fields = element(fields)?.2;
+    }
+    for _ in 0..5 {
+        fields = element(fields)?.2;
+    }
+    let (0x30, key_info, _) = element(fields)? else {
+        return None;
+    };
+    let (0x03, bits, _) = element(element(key_info)?.2)? else {
+        return None;
+    };
+    match bits.split_first()? {
+        (0, key) => Some(key.to_vec()),
+        _ => None,
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Clone)]
+struct Webhook {
+    emails: Arc<EmailService>,
+    verifier: Arc<BounceVerifier>,
+}
+
+pub fn router(emails: Arc<EmailService>, verifier: Arc<BounceVerifier>) -> Router {
+    Router::new()
+        .route("/webhooks/bounce", post(bounce))
+        .with_state(Webhook { emails, verifier })
+}
+
+/// POST /webhooks/bounce - Suppress the addresses a provider reports
+/// bounced for good or complained
+async fn bounce(State(webhook): State<Webhook>, headers: HeaderMap, body: Bytes) -> Response {
+    let (source, reports) = match headers.contains_key("x-amz-sns-message-type") {
+        true => ("ses", webhook.verifier.sns(&body).await),
+        false => ("generic", webhook.verifier.generic(&headers, &body)),
+    };
+    let reports = match reports {
+        Ok(reports) => reports,
+        Err(Rejected::Signature(problem)) => {
+            warn!(target: "audit", source, "Bounce report refused: {}", problem);
+            let body = ApiResponse::<()>::failure(ErrorCode::InvalidToken, problem);
+            return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+        }
+        Err(Rejected::Malformed(problem)) => {
+            let body = ApiResponse::<()>::failure(ErrorCode::Validation, problem);
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+    };
+    let mut suppressed = Vec::new();
+    for report in &reports {
+        match webhook.emails.suppress(report, source).await {
+            Ok(_) => suppressed.push(crate::suppressions::normalize(&report.email)),
+            Err(e) => {
+                // Unanswered, the provider sends the report again later
+                error!(source, "Failed to record a suppression: {}", e);
+                let body = ApiResponse::<()>::failure(
+                    ErrorCode::ServiceUnavailable,
+                    "The suppression list is unavailable; try again shortly",
+                );
+                return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+            }
+        }
+    }
+    Json(ApiResponse::success(json!({ "suppressed": suppressed }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        suppressions::SUPPRESSION_LIST,
+        test_support::{mock_pb, FakeTransport},
+    };
+    use axum::{body::Body, extract::Request};
+    use tower::ServiceExt;
+
+    const SNS_BOUNCE: &str = include_str!("../golden/sns_bounce.json");
+    const SNS_CERT: &str = include_str!("../golden/sns_signing_cert.pem");
+    const SNS_CERT_URL: &str = "https://sns.us-east-1.amazonaws.com/SimpleNotificationService-test.pem";
+    const SNS_TOPIC: &str = "arn:aws:sns:us-east-1:123456789012:ses-bounces";
+
+    fn verifier() -> BounceVerifier {
+        BounceVerifier::new(Some("webhook-secret"), vec![SNS_TOPIC.to_string()])
+            .with_certificate(SNS_CERT_URL, SNS_CERT)
+    }
+
+    fn signed(body: &str) -> HeaderMap {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"webhook-secret");
+        let hex: String = hmac::sign(&key, body.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-webhook-signature", format!("sha256={}", hex).parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_an_ses_bounce_is_checked_against_its_certificate() {
+        let reports = verifier().sns(SNS_BOUNCE.as_bytes()).await.unwrap();
+        assert_eq!(
+            reports,
+            [Report {
+                email: "Alice@Example.com".to_string(),
+                reason: SuppressionReason::Bounce,
+                detail: "smtp; 550 5.1.1 user unknown".to_string(),
+            }]
+        );
+
+        let tampered = SNS_BOUNCE.replace("Alice@Example.com", "bob@example.com");
+        assert!(matches!(
+            verifier().sns(tampered.as_bytes()).await,
+            Err(Rejected::Signature(_))
+        ));
+        let elsewhere = SNS_BOUNCE.replace("sns.us-east-1.amazonaws.com", "sns.example.com");
+        let sns_only = BounceVerifier::new(None, vec![SNS_TOPIC.to_string()]);
+        let Err(Rejected::Signature(problem)) = sns_only.sns(elsewhere.as_bytes()).await else {
+            panic!("a certificate off SNS was fetched");
+        };
+        assert!(problem.contains("isn't an SNS certificate"), "{}", problem);
+    }
+
+    #[tokio::test]
+    async fn test_only_the_allowed_topics_are_taken() {
+        // Signed by SNS all the same, but for someone else's topic
+        let foreign = BounceVerifier::new(None, vec!["arn:aws:sns:us-east-1:999999999999:mine".to_string()])
+            .with_certificate(SNS_CERT_URL, SNS_CERT);
+        let Err(Rejected::Signature(problem)) = foreign.sns(SNS_BOUNCE.as_bytes()).await else {
+            panic!("a notification from a foreign topic was taken");
+        };
+        assert!(problem.contains("isn't in BOUNCE_SNS_TOPIC_ARNS"), "{}", problem);
+        let unset = BounceVerifier::new(None, Vec::new()).with_certificate(SNS_CERT_URL, SNS_CERT);
+        assert!(matches!(unset.sns(SNS_BOUNCE.as_bytes()).await, Err(Rejected::Signature(_))));
+
+        // Nor is a foreign topic's subscription confirmed
+        let mut confirmation: Value = serde_json::from_str(SNS_BOUNCE).unwrap();
+        confirmation["Type"] = json!("SubscriptionConfirmation");
+        confirmation["TopicArn"] = json!("arn:aws:sns:us-east-1:999999999999:theirs");
+        confirmation["SubscribeURL"] = json!("https://sns.us-east-1.amazonaws.com/?Action=ConfirmSubscription");
+        let confirmation = confirmation.to_string();
+        let Err(Rejected::Signature(problem)) = verifier().sns(confirmation.as_bytes()).await else {
+            panic!("a foreign topic's subscription was confirmed");
+        };
+        assert!(problem.contains("theirs"), "{}", problem);
+    }
+
+    #[test]
+    fn test_generic_reports_need_the_secret() {
+        let body = r#"{"type":"complaint","email":"carol@example.com","detail":"abuse"}"#;
+        let reports = verifier().generic(&signed(body), body.as_bytes()).unwrap();
+        assert_eq!(reports[0].reason, SuppressionReason::Complaint);
+        assert_eq!(reports[0].email, "carol@example.com");
+
+        let soft = r#"{"type":"soft_bounce","email":"carol@example.com","detail":"mailbox full"}"#;
+        assert!(verifier()
+            .generic(&signed(soft), soft.as_bytes())
+            .unwrap()
+            .is_empty());
+        let forged = body.replace("carol", "dave");
+        assert!(matches!(
+            verifier().generic(&signed(body), forged.as_bytes()),
+            Err(Rejected::Signature(_))
+        ));
+        assert!(matches!(
+            verifier().generic(&HeaderMap::new(), body.as_bytes()),
+            Err(Rejected::Signature(_))
+        ));
+        let unkeyed = BounceVerifier::new(None, Vec::new()).generic(&signed(body), body.as_bytes());
+        assert!(matches!(unkeyed, Err(Rejected::Signature(_))));
+    }
+
+    #[tokio::test]
+    async fn test_a_reported_bounce_suppresses_the_address() {
+        let pb = mock_pb().await;
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        ));
+        let app = router(Arc::clone(&emails), Arc::new(verifier()));
+        let post = |body: &str| {
+            Request::post("/webhooks/bounce")
+                .header("x-amz-sns-message-type", "Notification")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(post(SNS_BOUNCE)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let listed = pb.records(SUPPRESSION_LIST);
+        assert_eq!(listed.len(), 1, "a repeated report is suppressed once");
+        assert_eq!(
+            (listed[0]["email"].as_str(), listed[0]["reason"].as_str()),
+            (Some("alice@example.com"), Some("bounce"))
+        );
+        assert_eq!(listed[0]["source"], "ses");
+        let suppression = emails.suppression("ALICE@example.com").await.unwrap().unwrap();
+        assert_eq!(suppression.detail, "smtp; 550 5.1.1 user unknown");
+
+        let forged = SNS_BOUNCE.replace("Alice@Example.com", "bob@example.com");
+        let response = app.clone().oneshot(post(&forged)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(pb.records(SUPPRESSION_LIST).len(), 1);
+    }
+}
@@ -0,0 +1,2566 @@
+//! The email queue, kept in the global PocketBase
+//!
+//! [`EmailService::queue_email`] writes each message as a `pending` record
+//! of the `email_queue` collection before `POST /send-email` answers, and
+//! the record's id is the `queue_id` the caller is given, so a restart loses
+//! nothing that was queued. [`process_email_queue`] runs
+//! [`EmailService::process_queue`] every [`Processing::interval`], and at
+//! once whenever an email is queued, which goes through the pending records
+//! whose `next_attempt_at` has come, most urgent [`Priority`] first and
+//! oldest first within one, sending each it claims and marking it `sent`,
+//! counting the attempt. Up to
+//! [`Processing::send_concurrency`] emails are sent at a time.
+//! A transient failure is tried again later under the [`RetryPolicy`], and
+//! an email failing for good, or out of attempts, is `failed` with the last
+//! error. Each send takes a token from the [`SendLimiter`] first; a pass
+//! stops early once the tokens are gone, leaving the rest pending. Emails go
+//! through the [`Providers`], and a sent one records which in `provider`.
+//!
+//! An email is claimed as the worker claims queue items, by creating an
+//! `email_claims` record for it at the version it was read: the unique
+//! `(email, version)` index lets exactly one processor through, which then
+//! marks the email `sending` and bumps its version.
+//!
+//! [`EmailService::stop`] refuses new emails and stops passes claiming any
+//! more, letting the sends under way finish; [`EmailService::put_back_unsent`]
+//! then returns any email still `sending`, its send cut short, to `pending`.
+//! An email left `sending` by a processor that died mid-send stays that way.
+//!
+//! [`EmailService::email`] and [`EmailService::emails`] read the records
+//! back for `GET /emails/:id` and `GET /emails`, so a caller can follow what
+//! became of the emails it queued.
+//!
+//! An email may be given a `send_at` up to [`MAX_SEND_AHEAD`] away, which
+//! becomes its first `next_attempt_at`, so it waits in the queue until then.
+//! [`EmailService::cancel`] takes a pending email out of the queue as
+//! `cancelled` by claiming it as a processor would, so an email is either
+//! cancelled or sent, never both.
+//!
+//! [`EmailService::queue_batch`] queues one template's email to as many as
+//! [`MAX_BATCH_RECIPIENTS`], each with template data of their own laid over
+//! the batch's. A recipient that can't be sent to is refused on its own;
+//! the rest are written one by one, as this PocketBase has no batch API,
+//! and the records already written are deleted again if a write fails, so
+//! a batch is queued whole or not at all.
+//!
+//! Attachments are checked against [`AttachmentLimits`] when an email is
+//! queued and kept in its record, as described in [`crate::attachments`].
+//!
+//! An [`Priority::Urgent`] email, such as a password reset, is also tried
+//! as soon as it is queued, before `POST /send-email` answers, if the
+//! [`SendLimiter`] has a token for it; it is claimed and sent as a pass
+//! would, so stays in the queue for the next pass if it can't be sent.
+//!
+//! A request may carry an idempotency key, kept in the record with a hash of
+//! the request. Queueing the same request under the same key again within
+//! [`IDEMPOTENCY_WINDOW`], as a caller retrying after a timeout does, gives
+//! back the email already queued instead of a second one, and the key with a
+//! different request is refused. The unique index on `idempotency_key` keeps
+//! two at once from both going in; a batch's recipients are keyed
+//! `<key>/<index>`. A key older than the window is cleared when next used.
+//!
+//! An email that failed for good is a dead letter, listed by
+//! [`EmailService::dead_letters`]. Once whatever failed it is put right,
+//! [`EmailService::requeue`] and [`EmailService::requeue_all`] make failed
+//! emails pending again with their attempts begun anew, noting who did so in
+//! `requeued_by` and `requeued_at`; an email to a suppressed recipient stays
+//! failed.
+//!
+//! Recipients on the [`crate::suppressions`] list are refused when queued,
+//! and a pass fails the due emails to any suppressed since as `suppressed`
+//! instead of sending them.
+
+use chrono::{DateTime, Utc};
+use common::clock::{Clock, SystemClock};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::{collections::HashMap, future::Future, sync::Arc, sync::Mutex, time::Duration, time::Instant};
+use tokio::sync::{Notify, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+use crate::{
+    attachments::{self, Attachment, AttachmentError, AttachmentLimits},
+    html,
+    metrics::{self, EMAILS_FAILED, EMAILS_QUEUED, EMAILS_RETRIED, EMAILS_SENT, EMAILS_SUPPRESSED},
+    pocketbase::{format_time, parse_time, quote, PbError, PocketBase, RecordPage},
+    providers::{ConnectionReport, ProviderStatus, Providers},
+    rate_limit::{Held, LimiterState, SendLimiter, SendLimits},
+    retry::RetryPolicy,
+    suppressions::{self, Report, Suppression},
+    templates,
+    transport::{OutgoingEmail, SendError, Transport},
+};
+
+/// Global PocketBase collection of queued emails
+pub const EMAIL_QUEUE: &str = "email_queue";
+
+/// Global PocketBase collection whose unique `(email, version)` index lets
+/// one processor claim each email
+pub const EMAIL_CLAIMS: &str = "email_claims";
+
+/// Pending emails read per pass
+pub const BATCH_SIZE: u32 = 50;
+
+/// Most recipients one `POST /send-email/batch` may name
+pub const MAX_BATCH_RECIPIENTS: usize = 500;
+
+/// Furthest ahead an email's `send_at` may be
+pub const MAX_SEND_AHEAD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How long an idempotency key answers with the email queued under it
+pub const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest idempotency key taken, leaving the field room for a batch index
+pub const MAX_IDEMPOTENCY_KEY: usize = 200;
+
+/// Failed emails read per request when gathering those to requeue
+const REQUEUE_PAGE: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailStatus {
+    Pending,
+    Sending,
+    Sent,
+    Failed,
+    /// Taken out of the queue before it was sent
+    Cancelled,
+}
+
+impl EmailStatus {
+    pub const ALL: [EmailStatus; 5] = [
+        EmailStatus::Pending,
+        EmailStatus::Sending,
+        EmailStatus::Sent,
+        EmailStatus::Failed,
+        EmailStatus::Cancelled,
+    ];
+}
+
+/// How soon an email goes: a pass claims the due emails of higher bands
+/// first, oldest first within a band
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Mail that can wait, such as digests
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// Mail someone is waiting on, also tried the moment it is queued
+    Urgent,
+}
+
+impl Priority {
+    /// Most urgent first
+    pub const ALL: [Priority; 4] = [Priority::Urgent, Priority::High, Priority::Normal, Priority::Low];
+
+    /// The `priority` number a record keeps, sorting the bands; records
+    /// from before there were priorities read 0, normal
+    fn rank(self) -> i64 {
+        match self {
+            Priority::Low => -1,
+            Priority::Normal => 0,
+            Priority::High => 1,
+            Priority::Urgent => 2,
+        }
+    }
+
+    fn from_rank(rank: i64) -> Self {
+        match rank {
+            ..=-1 => Priority::Low,
+            0 => Priority::Normal,
+            1 => Priority::High,
+            _ => Priority::Urgent,
+        }
+    }
+}
+
+/// Body of `POST /send-email`: a subject and bodies, or a template of
+/// [`templates::TEMPLATES`] and the data to render it with
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate)]
+pub struct SendEmailRequest {
+    #[validate(email(message = "to_email must be an email address"))]
+    pub to_email: String,
+    #[serde(default)]
+    pub to_name: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 500, message = "subject must be 1 to 500 characters"))]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub body_text: String,
+    #[serde(default)]
+    pub body_html: String,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub template_data: Value,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// When to send the email rather than straight away
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Set from the `Idempotency-Key` header if not given here; not part of
+    /// the request it names
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
+}
+
+impl SendEmailRequest {
+    /// What is wrong with this request apart from its template, or `None`
+    /// when it can be queued
+    pub fn problem(&self) -> Option<String> {
+        let mut problems: Vec<String> = match self.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .field_errors()
+                .values()
+                .flat_map(|errors| errors.iter())
+                .filter_map(|error| error.message.as_ref().map(|message| message.to_string()))
+                .collect(),
+        };
+        problems.extend(self.idempotency_key.as_deref().and_then(key_problem));
+        if self.template.is_none() {
+            if self.subject.is_none() {
+                problems.push("subject must be given".to_string());
+            }
+            if self.body_text.trim().is_empty() && self.body_html.trim().is_empty() {
+                problems.push("body_text or body_html must be given".to_string());
+            }
+        }
+        problems.sort();
+        (!problems.is_empty()).then(|| problems.join("; "))
+    }
+
+    /// The subject and bodies to send, rendered from the template if one is
+    /// named, or why the template can't give them. A caller's own HTML is
+    /// sanitized, and a text body read off it if none was given, as
+    /// [`crate::html`] describes
+    pub fn contents(&self) -> Result<OutgoingEmail, Vec<String>> {
+        let email = |subject: String, body_text: String, body_html: String| OutgoingEmail {
+            to_email: self.to_email.clone(),
+            to_name: self.to_name.clone(),
+            subject,
+            body_text,
+            body_html,
+            attachments: self.attachments.clone(),
+        };
+        let Some(template) = &self.template else {
+            let body_html = html::sanitize(&self.body_html);
+            let body_text = match self.body_text.trim().is_empty() {
+                true => html::to_text(&body_html),
+                false => self.body_text.clone(),
+            };
+            return Ok(email(self.subject.clone().unwrap_or_default(), body_text, body_html));
+        };
+        let raw = self.subject.is_some() || !self.body_text.is_empty() || !self.body_html.is_empty();
+        let rendered = templates::render(template, &self.template_data);
+        match (raw, rendered) {
+            (false, Ok(rendered)) => Ok(email(rendered.subject, rendered.body_text, rendered.body_html)),
+            (raw, rendered) => {
+                let mut problems = rendered.err().unwrap_or_default();
+                if raw {
+                    problems.insert(0, "template can't be given with subject, body_text or body_html".to_string());
+                }
+                Err(problems)
+            }
+        }
+    }
+}
+
+/// One recipient of a batch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchRecipient {
+    pub to_email: String,
+    #[serde(default)]
+    pub to_name: Option<String>,
+    /// Laid over the batch's `template_data`, key by key
+    #[serde(default)]
+    pub template_data: Value,
+}
+
+/// Body of `POST /send-email/batch`: a template of [`templates::TEMPLATES`]
+/// to send to every recipient
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchSendRequest {
+    pub template: String,
+    /// Data every recipient's email is rendered with
+    #[serde(default)]
+    pub template_data: Value,
+    pub recipients: Vec<BatchRecipient>,
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+    /// Every recipient's email's; urgent ones go first but aren't tried
+    /// as they are queued
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
+}
+
+impl BatchSendRequest {
+    /// The request for the email to `recipient`
+    fn request_for(&self, recipient: &BatchRecipient) -> SendEmailRequest {
+        let template_data = match (&self.template_data, &recipient.template_data) {
+            (shared, Value::Null) => shared.clone(),
+            (Value::Object(shared), Value::Object(own)) => {
+                let mut merged = shared.clone();
+                merged.extend(own.clone());
+                Value::Object(merged)
+            }
+            (_, own) => own.clone(),
+        };
+        SendEmailRequest {
+            to_email: recipient.to_email.clone(),
+            to_name: recipient.to_name.clone(),
+            template: Some(self.template.clone()),
+            template_data,
+            send_at: self.send_at,
+            priority: self.priority,
+            ..Default::default()
+        }
+    }
+}
+
+/// A record of the `email_queue` collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedEmail {
+    pub id: String,
+    pub email: OutgoingEmail,
+    /// The template the email was rendered from, if any
+    pub template: Option<String>,
+    pub status: EmailStatus,
+    pub priority: Priority,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Bumped on every claim; a claim names the version it was made from
+    pub version: u64,
+    /// When the email may next be tried; queueing sets it to the time
+    /// queued, or `send_at`
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// When the email was asked to be sent, if not straight away
+    pub send_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the record last changed, which for a failed email is when it failed
+    pub updated_at: Option<DateTime<Utc>>,
+    pub sent_at: Option<DateTime<Utc>>,
+    /// The provider a sent email went through
+    pub provider: Option<String>,
+    /// The API key label of whoever last put the email back in the queue
+    pub requeued_by: Option<String>,
+    pub requeued_at: Option<DateTime<Utc>>,
+}
+
+impl QueuedEmail {
+    pub fn from_record(record: &Value) -> Result<Self, String> {
+        let text = |field: &str| record.get(field).and_then(Value::as_str).unwrap_or_default();
+        let optional = |field: &str| Some(text(field).to_string()).filter(|value| !value.is_empty());
+        let number = |field: &str| record.get(field).and_then(Value::as_u64).unwrap_or_default();
+        let id = optional("id").ok_or("A queued email has no id")?;
+        let status = serde_json::from_value(record.get("status").cloned().unwrap_or(Value::Null))
+            .map_err(|_| format!("Queued email {} has an unknown status: '{}'", id, text("status")))?;
+        Ok(Self {
+            email: OutgoingEmail {
+                to_email: text("to_email").to_string(),
+                to_name: optional("to_name"),
+                subject: text("subject").to_string(),
+                body_text: text("body_text").to_string(),
+                body_html: text("body_html").to_string(),
+                attachments: match record.get("attachments") {
+                    None | Some(Value::Null) => Vec::new(),
+                    Some(attachments) => serde_json::from_value(attachments.clone())
+                        .map_err(|e| format!("Queued email {} has unreadable attachments: {}", id, e))?,
+                },
+            },
+            template: optional("template"),
+            status,
+            priority: Priority::from_rank(record.get("priority").and_then(Value::as_i64).unwrap_or(0)),
+            attempts: number("attempts") as u32,
+            last_error: optional("last_error"),
+            version: number("version"),
+            next_attempt_at: parse_time(text("next_attempt_at")),
+            send_at: parse_time(text("send_at")),
+            created_at: parse_time(text("created")),
+            updated_at: parse_time(text("updated")),
+            sent_at: parse_time(text("sent_at")),
+            provider: optional("provider"),
+            requeued_by: optional("requeued_by"),
+            requeued_at: parse_time(text("requeued_at")),
+            id,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueueError {
+    #[error("{0}")]
+    Invalid(String),
+
+    /// An attachment, or all of them together, over the limits
+    #[error("{0}")]
+    TooLarge(String),
+
+    /// Every problem keeping the named template from rendering
+    #[error("{}", .0.join("; "))]
+    Template(Vec<String>),
+
+    /// The recipient is on the suppression list
+    #[error("{0}")]
+    Suppressed(String),
+
+    /// The service is stopping and takes no more emails
+    #[error("The email service is shutting down")]
+    ShuttingDown,
+
+    /// The idempotency key was used for a different request
+    #[error("{0}")]
+    KeyReused(String),
+
+    #[error(transparent)]
+    PocketBase(#[from] PbError),
+}
+
+/// One page of the queue, newest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailPage {
+    pub emails: Vec<QueuedEmail>,
+    pub page: u32,
+    pub per_page: u32,
+    /// Emails matching the listing's filter across all pages
+    pub total_items: u64,
+}
+
+/// What became of a request to cancel an email
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cancel {
+    /// The email is cancelled, now or already
+    Cancelled(Box<QueuedEmail>),
+    NotFound,
+    /// The email got past pending and has this status
+    TooLate(EmailStatus),
+}
+
+/// Which failed emails a dead-letter listing or bulk requeue takes in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeadLetterFilter {
+    pub to_email: Option<String>,
+    pub template: Option<String>,
+    /// Failed at or after
+    pub since: Option<DateTime<Utc>>,
+    /// Failed before
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl DeadLetterFilter {
+    fn filter(&self) -> String {
+        let mut terms = vec![format!("status = {}", quote("failed"))];
+        if let Some(to_email) = &self.to_email {
+            terms.push(format!("to_email = {}", quote(to_email)));
+        }
+        if let Some(template) = &self.template {
+            terms.push(format!("template = {}", quote(template)));
+        }
+        if let Some(since) = self.since {
+            terms.push(format!("updated >= {}", quote(&format_time(since))));
+        }
+        if let Some(until) = self.until {
+            terms.push(format!("updated < {}", quote(&format_time(until))));
+        }
+        terms.join(" && ")
+    }
+}
+
+/// What became of a request to put a failed email back in the queue
+#[derive(Debug, Clone, PartialEq)]
+pub enum Requeue {
+    /// The email is pending again, with its attempts reset
+    Requeued(Box<QueuedEmail>),
+    NotFound,
+    /// Only failed emails are requeued; this one has this status
+    NotFailed(EmailStatus),
+    /// The recipient is on the suppression list
+    Suppressed(Suppression),
+}
+
+/// What a bulk requeue did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Requeued {
+    pub requeued: usize,
+    /// Failed emails left failed, their recipient being suppressed
+    pub suppressed: usize,
+}
+
+/// How often the queue is gone through, how many emails are sent at once,
+/// and how long those under way may take to finish at shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processing {
+    /// Longest wait between passes when nothing is queued
+    pub interval: Duration,
+    /// Emails a pass may be sending at the same time
+    pub send_concurrency: usize,
+    pub shutdown_grace: Duration,
+}
+
+impl Default for Processing {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            send_concurrency: 1,
+            shutdown_grace: Duration::from_secs(25),
+        }
+    }
+}
+
+impl Processing {
+    /// Read EMAIL_PROCESS_INTERVAL_SECS, EMAIL_SEND_CONCURRENCY and
+    /// SHUTDOWN_GRACE_SECS
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let defaults = Self::default();
+        let positive = |var: &str, unit: &str, default: u64| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(parsed),
+                _ => Err(format!("{} must be a positive number of {}, not '{}'", var, unit, value)),
+            },
+            None => Ok(default),
+        };
+        let interval = positive("EMAIL_PROCESS_INTERVAL_SECS", "seconds", defaults.interval.as_secs())?;
+        let concurrency =
+            positive("EMAIL_SEND_CONCURRENCY", "emails", defaults.send_concurrency as u64)?;
+        let grace = positive("SHUTDOWN_GRACE_SECS", "seconds", defaults.shutdown_grace.as_secs())?;
+        Ok(Self {
+            interval: Duration::from_secs(interval),
+            shutdown_grace: Duration::from_secs(grace),
+            send_concurrency: usize::try_from(concurrency)
+                .ok()
+                .filter(|concurrency| *concurrency <= Semaphore::MAX_PERMITS)
+                .ok_or_else(|| format!("EMAIL_SEND_CONCURRENCY is too large: {}", concurrency))?,
+        })
+    }
+}
+
+/// What one pass over the queue did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Processed {
+    pub sent: usize,
+    pub failed: usize,
+    /// Emails put back to be tried again later
+    pub retrying: usize,
+    /// Emails another processor claimed first
+    pub skipped: usize,
+    /// Due emails left for a later pass for want of a send token
+    pub deferred: usize,
+    /// Emails failed unsent, their recipient having been suppressed
+    pub suppressed: usize,
+}
+
+/// What became of one due email in a pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delivery {
+    Sent,
+    Failed,
+    Retrying,
+    Skipped,
+    Suppressed,
+    /// Left pending, the service having begun to stop
+    Deferred,
+}
+
+impl Processed {
+    fn count(&mut self, delivery: Delivery) {
+        match delivery {
+            Delivery::Sent => self.sent += 1,
+            Delivery::Failed => self.failed += 1,
+            Delivery::Retrying => self.retrying += 1,
+            Delivery::Skipped => self.skipped += 1,
+            Delivery::Suppressed => self.suppressed += 1,
+            Delivery::Deferred => self.deferred += 1,
+        }
+    }
+}
+
+pub struct EmailService {
+    pb: PocketBase,
+    providers: Providers,
+    processor_id: String,
+    attachment_limits: AttachmentLimits,
+    retry: RetryPolicy,
+    limiter: SendLimiter,
+    processing: Processing,
+    /// Held by each email being sent
+    sends: Semaphore,
+    /// Notified when an email is queued, to start a pass without waiting
+    queued: Notify,
+    /// Cancelled once the service begins to stop
+    stopping: CancellationToken,
+    /// Versions of the emails being handed to a provider, by id
+    sending: Mutex<HashMap<String, u64>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl EmailService {
+    /// Queue in `pb` and send through `transport`, claiming as `processor_id`
+    pub fn new(pb: PocketBase, transport: Arc<dyn Transport>, processor_id: &str) -> Self {
+        Self {
+            pb,
+            providers: Providers::single(transport),
+            processor_id: processor_id.to_string(),
+            attachment_limits: AttachmentLimits::default(),
+            retry: RetryPolicy::default(),
+            limiter: SendLimiter::new(SendLimits::default()),
+            processing: Processing::default(),
+            sends: Semaphore::new(Processing::default().send_concurrency),
+            queued: Notify::new(),
+            stopping: CancellationToken::new(),
+            sending: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Send through `providers` rather than the one transport given to `new`
+    pub fn with_providers(mut self, providers: Providers) -> Self {
+        self.providers = providers;
+        self
+    }
+
+    /// How each provider stands
+    pub fn provider_status(&self) -> Vec<ProviderStatus> {
+        self.providers.status(self.clock.now())
+    }
+
+    /// Whether each provider can be connected to
+    pub async fn test_connections(&self) -> Vec<ConnectionReport> {
+        self.providers.test_connections().await
+    }
+
+    /// Send the `smtp_test` template to `to_email` straight away, outside
+    /// the queue, returning the provider it went through
+    pub async fn send_test(&self, to_email: &str) -> Result<String, SendError> {
+        let now = self.clock.now();
+        let rendered = templates::render("smtp_test", &json!({ "sent_at": now.to_rfc2822() }))
+            .map_err(|problems| SendError::Message(problems.join("; ")))?;
+        let email = OutgoingEmail {
+            to_email: to_email.to_string(),
+            to_name: None,
+            subject: rendered.subject,
+            body_text: rendered.body_text,
+            body_html: rendered.body_html,
+            attachments: Vec::new(),
+        };
+        let provider = self.providers.send(&email, now).await?;
+        info!(target: "audit", to = %to_email, provider = %provider, "Test email sent");
+        Ok(provider)
+    }
+
+    /// How many emails of each priority are pending, most urgent first
+    pub async fn queue_depth(&self) -> Result<Vec<(Priority, u64)>, PbError> {
+        let mut depth = Vec::new();
+        for priority in Priority::ALL {
+            let filter = format!("status = {} && priority = {}", quote("pending"), priority.rank());
+            let page = self.pb.list_page(EMAIL_QUEUE, Some(&filter), "", 1, 1).await?;
+            depth.push((priority, page.total_items));
+        }
+        Ok(depth)
+    }
+
+    /// Set the queue, provider and send rate gauges from how they stand now
+    pub async fn refresh_gauges(&self) -> Result<(), PbError> {
+        for provider in self.provider_status() {
+            let labels = [("provider", provider.name.as_str())];
+            metrics::set(&metrics::PROVIDER_HEALTHY, &labels, if provider.healthy { 1.0 } else { 0.0 });
+            metrics::set(&metrics::PROVIDER_FAILURES, &labels, provider.failures as f64);
+        }
+        let rate = self.send_rate();
+        metrics::set(&metrics::SEND_TOKENS, &[], rate.tokens_available as f64);
+        metrics::set(&metrics::SEND_BACKLOG, &[], rate.backlog as f64);
+        for status in EmailStatus::ALL {
+            let status = serde_json::to_value(status).unwrap_or_default();
+            let status = status.as_str().unwrap_or_default();
+            let filter = format!("status = {}", quote(status));
+            let page = self.pb.list_page(EMAIL_QUEUE, Some(&filter), "", 1, 1).await?;
+            metrics::set(&metrics::QUEUE_EMAILS, &[("status", status)], page.total_items as f64);
+        }
+        for (priority, pending) in self.queue_depth().await? {
+            let priority = serde_json::to_value(priority).unwrap_or_default();
+            let labels = [("priority", priority.as_str().unwrap_or_default())];
+            metrics::set(&metrics::QUEUE_PENDING, &labels, pending as f64);
+        }
+        Ok(())
+    }
+
+    /// Try failed emails again under `retry` rather than the default policy
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send no faster than `limits` rather than the default rate
+    pub fn with_send_limits(mut self, limits: SendLimits) -> Self {
+        self.limiter = SendLimiter::new(limits);
+        self
+    }
+
+    /// Go through the queue and send as `processing` says rather than by
+    /// the defaults
+    pub fn with_processing(mut self, processing: Processing) -> Self {
+        self.sends = Semaphore::new(processing.send_concurrency);
+        self.processing = processing;
+        self
+    }
+
+    /// How the send rate limiter stands
+    pub fn send_rate(&self) -> LimiterState {
+        self.limiter.state(self.clock.now())
+    }
+
+    /// Tell the time by `clock`
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Refuse attachments over `limits` rather than the defaults
+    pub fn with_attachment_limits(mut self, limits: AttachmentLimits) -> Self {
+        self.attachment_limits = limits;
+        self
+    }
+
+    /// Keep `request` as a pending email, rendered if it names a template,
+    /// returning its record's id
+    pub async fn queue_email(&self, request: &SendEmailRequest) -> Result<String, QueueError> {
+        if self.stopping.is_cancelled() {
+            return Err(QueueError::ShuttingDown);
+        }
+        let mut record = self.record(request)?;
+        let keyed = request.idempotency_key.as_ref().map(|key| (key.clone(), payload_hash(request)));
+        if let Some((key, hash)) = &keyed {
+            if let Some(id) = self.queued_under(std::slice::from_ref(key), hash).await?.remove(key) {
+                info!(queue_id = %id, "Email request replayed, already queued");
+                return Ok(id);
+            }
+            record["idempotency_key"] = json!(key);
+            record["payload_hash"] = json!(hash);
+        }
+        if self.suppression(&request.to_email).await?.is_some() {
+            metrics::increment(&EMAILS_SUPPRESSED, &[("stage", "queue")]);
+            return Err(suppressed(&request.to_email));
+        }
+        let queued = match (self.create_queued(&record).await, &keyed) {
+            (Ok(queued), _) => queued,
+            // The same request racing this one went in first
+            (Err(e), Some((key, hash))) if e.is_not_unique() => {
+                let raced = self.queued_under(std::slice::from_ref(key), hash).await?.remove(key);
+                return raced.ok_or(QueueError::PocketBase(e));
+            }
+            (Err(e), _) => return Err(e.into()),
+        };
+        let id = queued.id.clone();
+        info!(queue_id = %id, to = %request.to_email, priority = ?queued.priority, "Email queued");
+        metrics::increment(&EMAILS_QUEUED, &[]);
+        let due = queued.next_attempt_at.is_some_and(|due| due <= self.clock.now());
+        if queued.priority == Priority::Urgent && due {
+            self.send_urgent(queued).await;
+        }
+        self.queued.notify_one();
+        Ok(id)
+    }
+
+    /// Try `queued` straight away rather than waiting for a pass, if the
+    /// send rate allows, leaving it to the queue if it isn't sent
+    async fn send_urgent(&self, queued: QueuedEmail) {
+        let id = queued.id.clone();
+        if self.limiter.take(&queued.email.to_email, self.clock.now()).is_err() {
+            debug!(queue_id = %id, "Urgent email over the send rate, leaving it to the queue");
+            return;
+        }
+        match self.deliver(queued, false).await {
+            Ok(Delivery::Sent) => {}
+            Ok(_) => debug!(queue_id = %id, "Urgent email not sent at once, leaving it to the queue"),
+            Err(e) => warn!(queue_id = %id, "Failed to send an urgent email at once: {}", e),
+        }
+    }
+
+    /// Queue the batch's email to every recipient that can be sent one,
+    /// returning each recipient's queue id or problem, in order
+    pub async fn queue_batch(
+        &self,
+        batch: &BatchSendRequest,
+    ) -> Result<Vec<Result<String, QueueError>>, QueueError> {
+        if self.stopping.is_cancelled() {
+            return Err(QueueError::ShuttingDown);
+        }
+        if batch.recipients.is_empty() {
+            return Err(QueueError::Invalid("recipients must name at least one recipient".to_string()));
+        }
+        if batch.recipients.len() > MAX_BATCH_RECIPIENTS {
+            return Err(QueueError::Invalid(format!(
+                "recipients may name at most {}, not {}",
+                MAX_BATCH_RECIPIENTS,
+                batch.recipients.len()
+            )));
+        }
+        if let Some(problem) = batch.idempotency_key.as_deref().and_then(key_problem) {
+            return Err(QueueError::Invalid(problem));
+        }
+        let mut records: Vec<Result<Value, QueueError>> = batch
+            .recipients
+            .iter()
+            .map(|recipient| self.record(&batch.request_for(recipient)))
+            .collect();
+        // Recipients already queued under the batch's key, by index
+        let mut replayed: HashMap<usize, String> = HashMap::new();
+        let keyed = batch.idempotency_key.as_ref().map(|key| {
+            let keys: Vec<String> = (0..records.len()).map(|index| format!("{}/{}", key, index)).collect();
+            (keys, payload_hash(batch))
+        });
+        if let Some((keys, hash)) = &keyed {
+            let mut queued = self.queued_under(keys, hash).await?;
+            replayed.extend(keys.iter().enumerate().filter_map(|(index, key)| Some((index, queued.remove(key)?))));
+            for (index, record) in records.iter_mut().enumerate() {
+                if let Ok(record) = record {
+                    record["idempotency_key"] = json!(keys[index]);
+                    record["payload_hash"] = json!(hash);
+                }
+            }
+        }
+        let addresses = records
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !replayed.contains_key(index))
+            .filter_map(|(_, record)| record.as_ref().ok()?["to_email"].as_str());
+        let listed = suppressions::among(&self.pb, addresses).await?;
+        let records: Vec<Result<Value, QueueError>> = records
+            .into_iter()
+            .enumerate()
+            .map(|(index, record)| {
+                let record = record?;
+                if replayed.contains_key(&index) {
+                    return Ok(record);
+                }
+                let to_email = record["to_email"].as_str().unwrap_or_default();
+                match listed.contains(&suppressions::normalize(to_email)) {
+                    true => Err(suppressed(to_email)),
+                    false => Ok(record),
+                }
+            })
+            .collect();
+        let mut created = Vec::new();
+        let mut ids = Vec::new();
+        for (index, record) in records.iter().enumerate() {
+            let Ok(record) = record else {
+                continue;
+            };
+            if let Some(id) = replayed.get(&index) {
+                ids.push(id.clone());
+                continue;
+            }
+            match self.create_queued(record).await {
+                Ok(queued) => {
+                    ids.push(queued.id.clone());
+                    created.push(queued.id);
+                }
+                Err(e) => {
+                    for id in &created {
+                        if let Err(e) = self.pb.delete_record(EMAIL_QUEUE, id).await {
+                            error!(queue_id = %id, "Failed to remove an email of a batch that failed: {}", e);
+                        }
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+        info!(
+            template = %batch.template,
+            queued = created.len(),
+            replayed = replayed.len(),
+            refused = records.len() - ids.len(),
+            "Email batch queued"
+        );
+        metrics::add(&EMAILS_QUEUED, &[], created.len() as f64);
+        let refused_suppressed = records
+            .iter()
+            .filter(|record| matches!(record, Err(QueueError::Suppressed(_))))
+            .count();
+        metrics::add(&EMAILS_SUPPRESSED, &[("stage", "queue")], refused_suppressed as f64);
+        if !created.is_empty() {
+            self.queued.notify_one();
+        }
+        let mut ids = ids.into_iter();
+        Ok(records
+            .into_iter()
+            .map(|record| record.map(|_| ids.next().unwrap_or_default()))
+            .collect())
+    }
+
+    /// The pending record `request` is kept as, or why it can't be queued
+    fn record(&self, request: &SendEmailRequest) -> Result<Value, QueueError> {
+        if let Some(problem) = request.problem() {
+            return Err(QueueError::Invalid(problem));
+        }
+        let now = self.clock.now();
+        if let Some(send_at) = request.send_at {
+            if send_at > now + chrono::Duration::from_std(MAX_SEND_AHEAD).unwrap_or_default() {
+                return Err(QueueError::Invalid(format!(
+                    "send_at must be within {} days",
+                    MAX_SEND_AHEAD.as_secs() / 86_400
+                )));
+            }
+        }
+        let email = request.contents().map_err(QueueError::Template)?;
+        let attachments = attachments::check(&email.attachments, &self.attachment_limits)
+            .map_err(|e| match e {
+                AttachmentError::TooLarge(message) => QueueError::TooLarge(message),
+                AttachmentError::Invalid(message) => QueueError::Invalid(message),
+            })?;
+        Ok(json!({
+            "to_email": email.to_email,
+            "to_name": email.to_name.unwrap_or_default(),
+            "subject": email.subject,
+            "body_text": email.body_text,
+            "body_html": email.body_html,
+            "template": request.template.clone().unwrap_or_default(),
+            "attachments": attachments,
+            "status": EmailStatus::Pending,
+            "priority": request.priority.rank(),
+            "attempts": 0,
+            "version": 0,
+            "next_attempt_at": format_time(request.send_at.map_or(now, |send_at| send_at.max(now))),
+            "send_at": request.send_at.map(format_time).unwrap_or_default(),
+        }))
+    }
+
+    /// The ids of the emails queued under `keys` within the
+    /// [`IDEMPOTENCY_WINDOW`], by key, or [`QueueError::KeyReused`] if any
+    /// was queued from another request than the one `hash` is of. Keys
+    /// older than the window are cleared, to be used again
+    async fn queued_under(&self, keys: &[String], hash: &str) -> Result<HashMap<String, String>, QueueError> {
+        let window = chrono::Duration::from_std(IDEMPOTENCY_WINDOW).unwrap_or_default();
+        let mut queued = HashMap::new();
+        for chunk in keys.chunks(BATCH_SIZE as usize) {
+            let terms: Vec<String> = chunk
+                .iter()
+                .map(|key| format!("idempotency_key = {}", quote(key)))
+                .collect();
+            let filter = format!("({})", terms.join(" || "));
+            for record in self.pb.list(EMAIL_QUEUE, &filter, "", BATCH_SIZE).await? {
+                let text = |field: &str| record.get(field).and_then(Value::as_str).unwrap_or_default();
+                let fresh = parse_time(text("created")).is_some_and(|created| self.clock.now() - created < window);
+                if !fresh {
+                    debug!(queue_id = %text("id"), "Idempotency key expired, clearing it");
+                    self.pb
+                        .update_record(EMAIL_QUEUE, text("id"), &json!({ "idempotency_key": "" }))
+                        .await?;
+                    continue;
+                }
+                if text("payload_hash") != hash {
+                    return Err(QueueError::KeyReused(
+                        "This idempotency key was already used for a different request".to_string(),
+                    ));
+                }
+                queued.insert(text("idempotency_key").to_string(), text("id").to_string());
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Write `record` to the queue, returning it as written
+    async fn create_queued(&self, record: &Value) -> Result<QueuedEmail, PbError> {
+        let created = self.pb.create_record(EMAIL_QUEUE, record).await?;
+        QueuedEmail::from_record(&created).map_err(PbError::Request)
+    }
+
+    /// The suppression of `email`, if it is suppressed
+    pub async fn suppression(&self, email: &str) -> Result<Option<Suppression>, PbError> {
+        suppressions::find(&self.pb, email).await
+    }
+
+    /// Suppress `report`'s address as reported by `source`, returning
+    /// whether it wasn't already
+    pub async fn suppress(&self, report: &Report, source: &str) -> Result<bool, PbError> {
+        suppressions::add(&self.pb, report, source).await
+    }
+
+    /// Send to `email` again, returning the suppression it had
+    pub async fn unsuppress(&self, email: &str) -> Result<Option<Suppression>, PbError> {
+        suppressions::remove(&self.pb, email).await
+    }
+
+    /// The queued email with `id`, or `None` if there is none
+    pub async fn email(&self, id: &str) -> Result<Option<QueuedEmail>, PbError> {
+        let record = match self.pb.get_record(EMAIL_QUEUE, id).await {
+            Ok(record) => record,
+            Err(PbError::Status { status: 404, .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        QueuedEmail::from_record(&record)
+            .map(Some)
+            .map_err(PbError::Request)
+    }
+
+    /// Cancel the email with `id` if it is still pending
+    pub async fn cancel(&self, id: &str) -> Result<Cancel, PbError> {
+        let Some(queued) = self.email(id).await? else {
+            return Ok(Cancel::NotFound);
+        };
+        match queued.status {
+            EmailStatus::Pending => {}
+            EmailStatus::Cancelled => return Ok(Cancel::Cancelled(Box::new(queued))),
+            status => return Ok(Cancel::TooLate(status)),
+        }
+        if !self.take_claim(&queued).await? {
+            return Ok(Cancel::TooLate(EmailStatus::Sending));
+        }
+        let cancelled = QueuedEmail {
+            status: EmailStatus::Cancelled,
+            version: queued.version + 1,
+            ..queued
+        };
+        self.pb
+            .update_record(
+                EMAIL_QUEUE,
+                &cancelled.id,
+                &json!({ "status": cancelled.status, "version": cancelled.version }),
+            )
+            .await?;
+        info!(queue_id = %cancelled.id, "Email cancelled");
+        Ok(Cancel::Cancelled(Box::new(cancelled)))
+    }
+
+    /// Page `page` of the queued emails, newest first, keeping to those with
+    /// `status` and those queued at or after `since` when given
+    pub async fn emails(
+        &self,
+        status: Option<EmailStatus>,
+        since: Option<DateTime<Utc>>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<EmailPage, PbError> {
+        let mut terms = Vec::new();
+        if let Some(status) = status {
+            let status = serde_json::to_value(status).unwrap_or_default();
+            terms.push(format!("status = {}", quote(status.as_str().unwrap_or_default())));
+        }
+        if let Some(since) = since {
+            terms.push(format!("created >= {}", quote(&format_time(since))));
+        }
+        self.listing(&terms.join(" && "), "-created", page, per_page).await
+    }
+
+    /// Page `page` of the failed emails `filter` takes in, the latest to
+    /// fail first
+    pub async fn dead_letters(
+        &self,
+        filter: &DeadLetterFilter,
+        page: u32,
+        per_page: u32,
+    ) -> Result<EmailPage, PbError> {
+        self.listing(&filter.filter(), "-updated", page, per_page).await
+    }
+
+    async fn listing(&self, filter: &str, sort: &str, page: u32, per_page: u32) -> Result<EmailPage, PbError> {
+        let RecordPage {
+            items,
+            page,
+            per_page,
+            total_items,
+        } = self.pb.list_page(EMAIL_QUEUE, Some(filter), sort, page, per_page).await?;
+        let emails = items
+            .iter()
+            .filter_map(|record| match QueuedEmail::from_record(record) {
+                Ok(queued) => Some(queued),
+                Err(e) => {
+                    warn!("Leaving an unreadable queued email out of a listing: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(EmailPage {
+            emails,
+            page,
+            per_page,
+            total_items,
+        })
+    }
+
+    /// Put the failed email with `id` back in the queue as `by`, to be sent
+    /// by the next pass with its attempts begun again
+    pub async fn requeue(&self, id: &str, by: &str) -> Result<Requeue, PbError> {
+        let Some(queued) = self.email(id).await? else {
+            return Ok(Requeue::NotFound);
+        };
+        if queued.status != EmailStatus::Failed {
+            return Ok(Requeue::NotFailed(queued.status));
+        }
+        if let Some(suppression) = self.suppression(&queued.email.to_email).await? {
+            return Ok(Requeue::Suppressed(suppression));
+        }
+        let requeued = self.put_back_failed(queued, by).await?;
+        self.queued.notify_one();
+        Ok(Requeue::Requeued(Box::new(requeued)))
+    }
+
+    /// Put every failed email `filter` takes in back in the queue as `by`,
+    /// leaving those to suppressed recipients failed
+    pub async fn requeue_all(&self, filter: &DeadLetterFilter, by: &str) -> Result<Requeued, PbError> {
+        // Gathered before any is requeued, as requeueing takes them out of
+        // the filter's pages
+        let mut failed = Vec::new();
+        for page in 1.. {
+            let listed = self.dead_letters(filter, page, REQUEUE_PAGE).await?;
+            failed.extend(listed.emails);
+            if u64::from(page) * u64::from(listed.per_page) >= listed.total_items {
+                break;
+            }
+        }
+        let recipients = failed.iter().map(|queued| queued.email.to_email.as_str());
+        let listed = suppressions::among(&self.pb, recipients).await?;
+        let mut done = Requeued::default();
+        for queued in failed {
+            if listed.contains(&suppressions::normalize(&queued.email.to_email)) {
+                done.suppressed += 1;
+            } else {
+                self.put_back_failed(queued, by).await?;
+                done.requeued += 1;
+            }
+        }
+        if done.requeued > 0 {
+            self.queued.notify_one();
+        }
+        info!(
+            target: "audit",
+            requeued = done.requeued,
+            suppressed = done.suppressed,
+            by,
+            "Failed emails requeued"
+        );
+        Ok(done)
+    }
+
+    /// Make `failed` pending and due now with no attempts, noting who did so;
+    /// its last error stays until it is next tried
+    async fn put_back_failed(&self, failed: QueuedEmail, by: &str) -> Result<QueuedEmail, PbError> {
+        let now = self.clock.now();
+        let requeued = QueuedEmail {
+            status: EmailStatus::Pending,
+            attempts: 0,
+            next_attempt_at: Some(now),
+            requeued_by: Some(by.to_string()),
+            requeued_at: Some(now),
+            ..failed
+        };
+        self.pb
+            .update_record(
+                EMAIL_QUEUE,
+                &requeued.id,
+                &json!({
+                    "status": requeued.status,
+                    "attempts": 0,
+                    "next_attempt_at": format_time(now),
+                    "requeued_by": by,
+                    "requeued_at": format_time(now),
+                }),
+            )
+            .await?;
+        info!(target: "audit", queue_id = %requeued.id, by, "Failed email requeued");
+        Ok(requeued)
+    }
+
+    /// Go through the pending emails that are due once, sending each this
+    /// processor claims
+    pub async fn process_queue(&self) -> Result<Processed, PbError> {
+        let filter = format!(
+            "status = {} && next_attempt_at <= {}",
+            quote("pending"),
+            quote(&format_time(self.clock.now()))
+        );
+        let records = self.pb.list(EMAIL_QUEUE, &filter, "-priority,created", BATCH_SIZE).await?;
+        let recipients = records.iter().filter_map(|record| record["to_email"].as_str());
+        let listed = suppressions::among(&self.pb, recipients).await?;
+        let mut processed = Processed::default();
+        let mut deliveries = FuturesUnordered::new();
+        for (index, record) in records.iter().enumerate() {
+            let queued = match QueuedEmail::from_record(record) {
+                Ok(queued) => queued,
+                Err(e) => {
+                    warn!("Skipping an unreadable queued email: {}", e);
+                    continue;
+                }
+            };
+            if listed.contains(&suppressions::normalize(&queued.email.to_email)) {
+                deliveries.push(self.deliver(queued, true));
+                continue;
+            }
+            match self.limiter.take(&queued.email.to_email, self.clock.now()) {
+                Ok(()) => deliveries.push(self.deliver(queued, false)),
+                Err(Held::Domain) => processed.deferred += 1,
+                Err(Held::Global) => {
+                    debug!(left = records.len() - index, "Out of send tokens until the next pass");
+                    processed.deferred += records.len() - index;
+                    break;
+                }
+            }
+        }
+        // Every delivery already started is seen through, even after one
+        // fails, so none is left claimed but unsent
+        let mut failure = None;
+        while let Some(delivery) = deliveries.next().await {
+            match delivery {
+                Ok(delivery) => processed.count(delivery),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        self.limiter.note_backlog(processed.deferred);
+        Ok(processed)
+    }
+
+    /// Claim `queued` and send it, holding one of the send permits, or fail
+    /// it unsent if its recipient is `suppressed`
+    async fn deliver(&self, queued: QueuedEmail, suppressed: bool) -> Result<Delivery, PbError> {
+        if suppressed {
+            let Some(claimed) = self.claim(&queued).await? else {
+                return Ok(Delivery::Skipped);
+            };
+            info!(queue_id = %claimed.id, "Email not sent, its recipient is suppressed");
+            self.finish(&claimed, Err("suppressed")).await?;
+            metrics::increment(&EMAILS_SUPPRESSED, &[("stage", "send")]);
+            return Ok(Delivery::Suppressed);
+        }
+        let _permit = self.sends.acquire().await.expect("the send semaphore is never closed");
+        if self.stopping.is_cancelled() {
+            self.limiter.give_back(&queued.email.to_email, self.clock.now());
+            return Ok(Delivery::Deferred);
+        }
+        let Some(claimed) = self.claim(&queued).await? else {
+            self.limiter.give_back(&queued.email.to_email, self.clock.now());
+            return Ok(Delivery::Skipped);
+        };
+        self.sending.lock().unwrap().insert(claimed.id.clone(), claimed.version);
+        let started = Instant::now();
+        let sent = self.providers.send(&claimed.email, self.clock.now()).await;
+        let took = started.elapsed().as_secs_f64();
+        self.sending.lock().unwrap().remove(&claimed.id);
+        match sent {
+            Ok(provider) => {
+                let attempt = claimed.attempts;
+                info!(queue_id = %claimed.id, attempt, provider = %provider, "Email sent");
+                self.finish(&claimed, Ok(&provider)).await?;
+                metrics::observe(&metrics::SEND_DURATION, &[("outcome", "sent")], took);
+                metrics::increment(&EMAILS_SENT, &[("provider", &provider)]);
+                if let Some(created_at) = claimed.created_at {
+                    let dwell = (self.clock.now() - created_at).to_std().unwrap_or_default();
+                    metrics::observe(&metrics::QUEUE_DWELL, &[], dwell.as_secs_f64());
+                }
+                Ok(Delivery::Sent)
+            }
+            Err(e) if e.is_transient() && self.retry.allows_another(claimed.attempts) => {
+                let delay = self.retry.delay(claimed.attempts);
+                let next_attempt_at =
+                    self.clock.now() + chrono::Duration::from_std(delay).unwrap_or_default();
+                warn!(
+                    queue_id = %claimed.id,
+                    attempt = claimed.attempts,
+                    "Email failed to send, trying again in {}s: {}",
+                    delay.as_secs(),
+                    e
+                );
+                self.retry_later(&claimed, &e.to_string(), next_attempt_at).await?;
+                metrics::observe(&metrics::SEND_DURATION, &[("outcome", "retrying")], took);
+                metrics::increment(&EMAILS_RETRIED, &[]);
+                Ok(Delivery::Retrying)
+            }
+            Err(e) => {
+                warn!(queue_id = %claimed.id, attempt = claimed.attempts, "Email failed to send for good: {}", e);
+                self.finish(&claimed, Err(&e.to_string())).await?;
+                metrics::observe(&metrics::SEND_DURATION, &[("outcome", "failed")], took);
+                let reason = if e.is_transient() { "out_of_attempts" } else { "rejected" };
+                metrics::increment(&EMAILS_FAILED, &[("reason", reason)]);
+                Ok(Delivery::Failed)
+            }
+        }
+    }
+
+    /// Take no more emails, and have passes claim none either while the
+    /// sends under way finish
+    pub fn stop(&self) {
+        self.stopping.cancel();
+    }
+
+    /// Return the emails whose send was cut short to the queue, as pending
+    /// and due at once, returning how many there were
+    pub async fn put_back_unsent(&self) -> Result<usize, PbError> {
+        let sending: Vec<(String, u64)> = self.sending.lock().unwrap().drain().collect();
+        let mut put_back = 0;
+        for (id, version) in sending {
+            // Only the email as this processor left it, not one since moved on
+            let Some(queued) = self.email(&id).await? else {
+                continue;
+            };
+            if queued.status != EmailStatus::Sending || queued.version != version {
+                continue;
+            }
+            let error = "The service stopped before the email was sent";
+            self.retry_later(&queued, error, self.clock.now()).await?;
+            warn!(queue_id = %id, "Email put back in the queue, its send cut short");
+            put_back += 1;
+        }
+        Ok(put_back)
+    }
+
+    /// Claim `queued` at the version it was read, or `None` if another
+    /// processor did
+    async fn claim(&self, queued: &QueuedEmail) -> Result<Option<QueuedEmail>, PbError> {
+        if !self.take_claim(queued).await? {
+            return Ok(None);
+        }
+        let claimed = QueuedEmail {
+            status: EmailStatus::Sending,
+            attempts: queued.attempts + 1,
+            version: queued.version + 1,
+            ..queued.clone()
+        };
+        self.pb
+            .update_record(
+                EMAIL_QUEUE,
+                &queued.id,
+                &json!({
+                    "status": claimed.status,
+                    "attempts": claimed.attempts,
+                    "version": claimed.version,
+                }),
+            )
+            .await?;
+        Ok(Some(claimed))
+    }
+
+    /// Create the `email_claims` record for `queued` at the version it was
+    /// read, returning whether this processor's went in first
+    async fn take_claim(&self, queued: &QueuedEmail) -> Result<bool, PbError> {
+        let claim = json!({
+            "email": queued.id,
+            "version": queued.version,
+            "processor_id": self.processor_id,
+        });
+        match self.pb.create_record(EMAIL_CLAIMS, &claim).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_unique() => {
+                debug!(queue_id = %queued.id, "Email already claimed by another processor");
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Put `claimed` back as pending, due at `next_attempt_at`
+    async fn retry_later(
+        &self,
+        claimed: &QueuedEmail,
+        error: &str,
+        next_attempt_at: DateTime<Utc>,
+    ) -> Result<(), PbError> {
+        self.pb
+            .update_record(
+                EMAIL_QUEUE,
+                &claimed.id,
+                &json!({
+                    "status": EmailStatus::Pending,
+                    "last_error": truncate_error(error),
+                    "next_attempt_at": format_time(next_attempt_at),
+                }),
+            )
+            .await
+            .map(drop)
+    }
+
+    /// Mark `claimed` sent through the provider named, or failed for good
+    /// with the error
+    async fn finish(&self, claimed: &QueuedEmail, outcome: Result<&str, &str>) -> Result<(), PbError> {
+        let changes = match outcome {
+            Ok(provider) => json!({
+                "status": EmailStatus::Sent,
+                "last_error": "",
+                "sent_at": Utc::now(),
+                "provider": provider,
+            }),
+            Err(error) => json!({
+                "status": EmailStatus::Failed,
+                "last_error": truncate_error(error),
+                "sent_at": "",
+            }),
+        };
+        self.pb
+            .update_record(EMAIL_QUEUE, &claimed.id, &changes)
+            .await
+            .map(drop)
+    }
+}
+
+/// What is wrong with `key` as an idempotency key, if anything
+fn key_problem(key: &str) -> Option<String> {
+    let printable = key.chars().all(|c| c.is_ascii_graphic());
+    (key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY || !printable).then(|| {
+        format!(
+            "idempotency key must be 1 to {} printable ASCII characters",
+            MAX_IDEMPOTENCY_KEY
+        )
+    })
+}
+
+/// Hex SHA-256 of `request` as JSON, the idempotency key left out
+fn payload_hash(request: &impl Serialize) -> String {
+    let json = serde_json::to_vec(request).unwrap_or_default();
+    ring::digest::digest(&ring::digest::SHA256, &json)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn suppressed(to_email: &str) -> QueueError {
+    QueueError::Suppressed(format!("{} is on the suppression list", to_email))
+}
+
+/// As much of `error` as the collection's last_error field holds
+fn truncate_error(error: &str) -> String {
+    error.chars().take(1000).collect()
+}
+
+/// Go through the queue every [`Processing::interval`], and whenever an
+/// email is queued, until the service is stopped
+pub async fn process_email_queue(service: Arc<EmailService>) {
+    let service = &service;
+    let stopping = &service.stopping;
+    run_passes(service.processing.interval, &service.queued, stopping, || async move {
+        match service.process_queue().await {
+            Ok(processed)
+                if processed.sent + processed.failed + processed.retrying + processed.suppressed > 0 =>
+            {
+                info!(
+                    sent = processed.sent,
+                    failed = processed.failed,
+                    retrying = processed.retrying,
+                    deferred = processed.deferred,
+                    suppressed = processed.suppressed,
+                    "Processed the email queue"
+                )
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to process the email queue: {}", e),
+        }
+    })
+    .await;
+    info!("Stopped processing the email queue");
+}
+
+/// Run `pass` straight away and then every `every`, or sooner when `wakeup`
+/// is notified, the wait starting over from that pass, until `stop` is
+/// cancelled; a pass under way is finished first
+async fn run_passes<F: Future<Output = ()>>(
+    every: Duration,
+    wakeup: &Notify,
+    stop: &CancellationToken,
+    mut pass: impl FnMut() -> F,
+) {
+    let mut interval = tokio::time::interval(every);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            _ = stop.cancelled() => return,
+            _ = interval.tick() => {}
+            _ = wakeup.notified() => interval.reset(),
+        }
+        pass().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rate_limit::SendRate,
+        test_support::{mock_pb, FakeTransport, MockPb, SlowTransport},
+        transport::SendError,
+    };
+
+    fn request(to_email: &str) -> SendEmailRequest {
+        SendEmailRequest {
+            to_email: to_email.to_string(),
+            subject: Some("Your meeting is on Loom".to_string()),
+            body_text: "It's ready".to_string(),
+            body_html: "<p>It's ready</p>".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn service(pb: &MockPb, transport: &FakeTransport, processor_id: &str) -> EmailService {
+        EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            processor_id,
+        )
+    }
+
+    #[test]
+    fn test_requests_without_a_recipient_subject_or_body_are_refused() {
+        assert_eq!(request("alice@example.com").problem(), None);
+        let problem = SendEmailRequest {
+            subject: Some(String::new()),
+            body_text: String::new(),
+            body_html: " ".to_string(),
+            ..request("alice")
+        }
+        .problem()
+        .unwrap();
+        assert_eq!(
+            problem,
+            "body_text or body_html must be given; subject must be 1 to 500 characters; to_email must be an email address"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queued_emails_wait_as_pending_records() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+
+        let queue_id = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let record = pb.record(EMAIL_QUEUE, &queue_id).unwrap();
+        assert_eq!(record["status"], "pending");
+        assert_eq!(record["attempts"], 0);
+        assert_eq!(record["to_email"], "alice@example.com");
+        assert_eq!(record["body_html"], "<p>It's ready</p>");
+
+        let invalid = emails.queue_email(&request("alice")).await.unwrap_err();
+        assert!(matches!(invalid, QueueError::Invalid(_)));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1, "nothing invalid is kept");
+        assert!(transport.sent().is_empty(), "queueing sends nothing");
+    }
+
+    #[tokio::test]
+    async fn test_attachments_are_kept_with_the_email_until_it_is_sent() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a").with_attachment_limits(AttachmentLimits {
+            per_attachment_bytes: 16,
+            total_bytes: 24,
+        });
+        let attachment = |filename: &str, data_base64: &str| Attachment {
+            filename: filename.to_string(),
+            content_type: "text/plain".to_string(),
+            data_base64: data_base64.to_string(),
+        };
+        let attached = SendEmailRequest {
+            attachments: vec![attachment("../notes.txt", "aGVsbG8gd29ybGQ=")],
+            ..request("alice@example.com")
+        };
+
+        let queue_id = emails.queue_email(&attached).await.unwrap();
+        let queued = QueuedEmail::from_record(&pb.record(EMAIL_QUEUE, &queue_id).unwrap()).unwrap();
+        assert_eq!(queued.email.attachments, [attachment("notes.txt", "aGVsbG8gd29ybGQ=")]);
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        assert_eq!(transport.sent()[0].attachments, queued.email.attachments);
+
+        // 17 bytes once decoded
+        let oversized = SendEmailRequest {
+            attachments: vec![attachment("big.txt", "MDEyMzQ1Njc4OWFiY2RlZmc=")],
+            ..request("alice@example.com")
+        };
+        assert!(matches!(emails.queue_email(&oversized).await, Err(QueueError::TooLarge(_))));
+        let garbled = SendEmailRequest {
+            attachments: vec![attachment("notes.txt", "not base64!")],
+            ..request("alice@example.com")
+        };
+        assert!(matches!(emails.queue_email(&garbled).await, Err(QueueError::Invalid(_))));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_a_callers_html_is_sanitized_and_given_a_text_body() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let html_only = SendEmailRequest {
+            body_text: String::new(),
+            body_html: "<p onclick=\"x()\">Topic: <b>Weekly</b><script>steal()</script></p>\
+                        <p><a href=\"https://www.loom.com/share/abc123\">Watch it</a></p>"
+                .to_string(),
+            ..request("alice@example.com")
+        };
+
+        let queue_id = emails.queue_email(&html_only).await.unwrap();
+        let record = pb.record(EMAIL_QUEUE, &queue_id).unwrap();
+        assert_eq!(
+            record["body_html"],
+            "<p>Topic: <b>Weekly</b></p><p><a href=\"https://www.loom.com/share/abc123\" \
+             rel=\"noopener noreferrer\">Watch it</a></p>"
+        );
+        assert_eq!(record["body_text"], "Topic: Weekly\n\nWatch it (https://www.loom.com/share/abc123)");
+
+        // A caller's own text is sent as given
+        let both = SendEmailRequest {
+            body_text: "  <b>Kept</b>  as written\n".to_string(),
+            ..html_only
+        };
+        let queue_id = emails.queue_email(&both).await.unwrap();
+        assert_eq!(pb.record(EMAIL_QUEUE, &queue_id).unwrap()["body_text"], "  <b>Kept</b>  as written\n");
+    }
+
+    #[tokio::test]
+    async fn test_templated_emails_are_kept_as_rendered() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let templated = SendEmailRequest {
+            to_email: "alice@example.com".to_string(),
+            template: Some("success_notice".to_string()),
+            template_data: json!({ "topic": "<Weekly> sync", "loom_url": "https://www.loom.com/share/abc123" }),
+            ..Default::default()
+        };
+
+        let queue_id = emails.queue_email(&templated).await.unwrap();
+        let queued = QueuedEmail::from_record(&pb.record(EMAIL_QUEUE, &queue_id).unwrap()).unwrap();
+        assert_eq!(queued.template.as_deref(), Some("success_notice"));
+        assert_eq!(queued.email.subject, "\"<Weekly> sync\" is on Loom");
+        assert!(queued.email.body_html.contains("&lt;Weekly&gt; sync"));
+        assert!(queued.email.body_text.contains("Watch it on Loom: https://www.loom.com/share/abc123"));
+
+        let mixed = SendEmailRequest {
+            body_html: "<p>Mine</p>".to_string(),
+            template_data: json!({}),
+            ..templated
+        };
+        let QueueError::Template(problems) = emails.queue_email(&mixed).await.unwrap_err() else {
+            panic!("a template with a body of its own is a template problem");
+        };
+        assert_eq!(
+            problems,
+            [
+                "template can't be given with subject, body_text or body_html",
+                "success_notice needs template_data.topic",
+                "success_notice needs template_data.loom_url",
+            ]
+        );
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+    }
+
+    fn batch(recipients: &[(&str, Value)]) -> BatchSendRequest {
+        BatchSendRequest {
+            template: "generic".to_string(),
+            template_data: json!({ "subject": "Your weekly digest", "message": "Nothing new" }),
+            recipients: recipients
+                .iter()
+                .map(|(to_email, template_data)| BatchRecipient {
+                    to_email: to_email.to_string(),
+                    template_data: template_data.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_batch_queues_every_recipient_that_can_be_sent_to() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let mixed = batch(&[
+            ("alice@example.com", json!({ "message": "Three meetings moved" })),
+            ("not-an-address", Value::Null),
+            ("bob@example.com", Value::Null),
+            ("carol@example.com", json!({ "message": " " })),
+        ]);
+
+        let results = emails.queue_batch(&mixed).await.unwrap();
+        assert!(results[0].is_ok() && results[2].is_ok());
+        let problem = |index: usize| results[index].as_ref().unwrap_err().to_string();
+        assert_eq!(problem(1), "to_email must be an email address");
+        assert_eq!(problem(3), "generic needs template_data.message");
+        let alice = queued(&pb, results[0].as_ref().unwrap());
+        assert_eq!(alice.email.subject, "Your weekly digest");
+        assert!(alice.email.body_text.contains("Three meetings moved"));
+        let bob = queued(&pb, results[2].as_ref().unwrap());
+        assert!(bob.email.body_text.contains("Nothing new"));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 2);
+
+        let recipient = |to_email: String| BatchRecipient {
+            to_email,
+            ..Default::default()
+        };
+        let mut full = batch(&[]);
+        full.recipients = (0..MAX_BATCH_RECIPIENTS)
+            .map(|n| recipient(format!("user{}@example.com", n)))
+            .collect();
+        let results = emails.queue_batch(&full).await.unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 2 + MAX_BATCH_RECIPIENTS);
+
+        let mut too_many = full;
+        too_many.recipients.push(recipient("one-more@example.com".to_string()));
+        let refused = emails.queue_batch(&too_many).await.unwrap_err();
+        assert_eq!(refused.to_string(), "recipients may name at most 500, not 501");
+        assert!(matches!(emails.queue_batch(&batch(&[])).await, Err(QueueError::Invalid(_))));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 2 + MAX_BATCH_RECIPIENTS);
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_recipients_are_refused_when_queued() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        pb.insert(
+            suppressions::SUPPRESSION_LIST,
+            json!({ "email": "alice@example.com", "reason": "bounce", "source": "ses" }),
+        );
+
+        let refused = emails.queue_email(&request("Alice@Example.com")).await.unwrap_err();
+        assert!(matches!(refused, QueueError::Suppressed(_)));
+        assert_eq!(refused.to_string(), "Alice@Example.com is on the suppression list");
+
+        let recipients = ["bob@example.com", "alice@example.com"].map(|to_email| (to_email, Value::Null));
+        let results = emails.queue_batch(&batch(&recipients)).await.unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(QueueError::Suppressed(_))));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_queued_emails_to_a_newly_suppressed_address_fail_unsent() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+        let alice = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let bob = emails.queue_email(&request("bob@example.com")).await.unwrap();
+        let complaint = Report {
+            email: "ALICE@example.com".to_string(),
+            reason: suppressions::SuppressionReason::Complaint,
+            detail: "abuse".to_string(),
+        };
+        assert!(emails.suppress(&complaint, "generic").await.unwrap());
+        assert!(!emails.suppress(&complaint, "generic").await.unwrap(), "already suppressed");
+
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!((processed.sent, processed.suppressed), (1, 1));
+        let skipped = queued(&pb, &alice);
+        assert_eq!((skipped.status, skipped.last_error.as_deref()), (EmailStatus::Failed, Some("suppressed")));
+        assert_eq!(queued(&pb, &bob).status, EmailStatus::Sent);
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to_email, "bob@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_a_batch_that_fails_part_way_is_taken_back_out() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        pb.fail_creates_after(2);
+        let recipients = ["alice@example.com", "bob@example.com", "carol@example.com"]
+            .map(|to_email| (to_email, Value::Null));
+
+        let failed = emails.queue_batch(&batch(&recipients)).await.unwrap_err();
+        assert!(matches!(failed, QueueError::PocketBase(PbError::Status { status: 500, .. })));
+        assert!(pb.records(EMAIL_QUEUE).is_empty());
+    }
+
+    fn keyed(request: SendEmailRequest, key: &str) -> SendEmailRequest {
+        SendEmailRequest {
+            idempotency_key: Some(key.to_string()),
+            ..request
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_request_replayed_under_its_key_gives_back_the_email_queued() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+        let notice = keyed(request("alice@example.com"), "task-42-failure");
+
+        let queue_id = emails.queue_email(&notice).await.unwrap();
+        assert_eq!(emails.queue_email(&notice).await.unwrap(), queue_id);
+        let records = pb.records(EMAIL_QUEUE);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["idempotency_key"], "task-42-failure");
+        assert_eq!(records[0]["payload_hash"].as_str().unwrap().len(), 64);
+
+        // Still the one email once it is sent
+        emails.process_queue().await.unwrap();
+        assert_eq!(emails.queue_email(&notice).await.unwrap(), queue_id);
+        assert_eq!(transport.sent().len(), 1);
+
+        let changed = SendEmailRequest {
+            body_text: "Something else".to_string(),
+            ..notice.clone()
+        };
+        let reused = emails.queue_email(&changed).await.unwrap_err();
+        assert!(matches!(reused, QueueError::KeyReused(_)), "{:?}", reused);
+
+        // Without a key, every request is an email of its own
+        emails.queue_email(&request("bob@example.com")).await.unwrap();
+        emails.queue_email(&request("bob@example.com")).await.unwrap();
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 3);
+
+        let blank = emails.queue_email(&keyed(request("carol@example.com"), "")).await.unwrap_err();
+        assert!(matches!(blank, QueueError::Invalid(_)));
+        let spaced = emails.queue_email(&keyed(request("carol@example.com"), "a key")).await.unwrap_err();
+        assert!(matches!(spaced, QueueError::Invalid(_)));
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys_expire_after_the_window() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let sent_before = |key: &str, ago: Duration| {
+            pb.insert(
+                EMAIL_QUEUE,
+                json!({
+                    "to_email": "alice@example.com",
+                    "status": "sent",
+                    "idempotency_key": key,
+                    "payload_hash": "0".repeat(64),
+                    "created": format_time(Utc::now() - chrono::Duration::from_std(ago).unwrap()),
+                }),
+            )
+        };
+        let recent = sent_before("weekly-1", IDEMPOTENCY_WINDOW - Duration::from_secs(60));
+        let old = sent_before("weekly-0", IDEMPOTENCY_WINDOW + Duration::from_secs(60));
+
+        let reused = emails.queue_email(&keyed(request("bob@example.com"), "weekly-1")).await;
+        assert!(matches!(reused, Err(QueueError::KeyReused(_))));
+        assert_eq!(pb.record(EMAIL_QUEUE, &recent).unwrap()["idempotency_key"], "weekly-1");
+
+        let fresh = emails.queue_email(&keyed(request("bob@example.com"), "weekly-0")).await.unwrap();
+        assert_ne!(fresh, old);
+        assert_eq!(pb.record(EMAIL_QUEUE, &old).unwrap()["idempotency_key"], "");
+        assert_eq!(pb.record(EMAIL_QUEUE, &fresh).unwrap()["idempotency_key"], "weekly-0");
+    }
+
+    #[tokio::test]
+    async fn test_a_batch_replayed_under_its_key_queues_nothing_twice() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let recipients = [("alice@example.com", Value::Null), ("not-an-address", Value::Null), ("bob@example.com", Value::Null)];
+        let digest = BatchSendRequest {
+            idempotency_key: Some("digest-2026-10-12".to_string()),
+            ..batch(&recipients)
+        };
+
+        let queued = emails.queue_batch(&digest).await.unwrap();
+        let ids: Vec<Option<String>> = queued.iter().map(|result| result.as_ref().ok().cloned()).collect();
+        let replayed = emails.queue_batch(&digest).await.unwrap();
+        let replayed: Vec<Option<String>> = replayed.iter().map(|result| result.as_ref().ok().cloned()).collect();
+        assert_eq!(replayed, ids);
+        assert!(ids[0].is_some() && ids[1].is_none() && ids[2].is_some());
+        let keys: Vec<Value> = pb.records(EMAIL_QUEUE).into_iter().map(|record| record["idempotency_key"].clone()).collect();
+        assert_eq!(keys, [json!("digest-2026-10-12/0"), json!("digest-2026-10-12/2")]);
+
+        let changed = BatchSendRequest {
+            template_data: json!({ "subject": "Your weekly digest", "message": "One new meeting" }),
+            ..digest
+        };
+        assert!(matches!(emails.queue_batch(&changed).await, Err(QueueError::KeyReused(_))));
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_processing_marks_emails_sent_or_failed_with_their_attempts() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+        let first = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let second = emails.queue_email(&request("bob@example.com")).await.unwrap();
+        transport.fail_next(SendError::Permanent("550 No such user".to_string()));
+
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 1, failed: 1, ..Default::default() });
+
+        let failed = QueuedEmail::from_record(&pb.record(EMAIL_QUEUE, &first).unwrap()).unwrap();
+        assert_eq!(failed.status, EmailStatus::Failed);
+        assert_eq!(failed.attempts, 1, "a permanent failure isn't tried again");
+        assert_eq!(failed.last_error.as_deref(), Some("SMTP error: 550 No such user"));
+        assert_eq!(failed.sent_at, None);
+
+        let sent = QueuedEmail::from_record(&pb.record(EMAIL_QUEUE, &second).unwrap()).unwrap();
+        assert_eq!((sent.status, sent.attempts, sent.version), (EmailStatus::Sent, 1, 1));
+        assert_eq!(sent.last_error, None);
+        assert!(sent.sent_at.is_some());
+        assert_eq!(transport.sent(), [sent.email]);
+
+        let claims = pb.records(EMAIL_CLAIMS);
+        assert_eq!(claims.len(), 2);
+        assert!(claims.iter().all(|claim| claim["version"] == 0 && claim["processor_id"] == "smtp-a"));
+
+        // Neither is pending any more
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    /// A clock standing still until it is moved on
+    struct PausedClock(std::sync::Mutex<DateTime<Utc>>);
+
+    impl PausedClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += chrono::Duration::from_std(by).unwrap();
+        }
+    }
+
+    impl Clock for PausedClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// A service giving each email three attempts, a minute and then a
+    /// minute and a half apart, on a paused clock
+    fn retrying(pb: &MockPb, transport: &FakeTransport) -> (EmailService, Arc<PausedClock>) {
+        // On a whole second, so times survive PocketBase's milliseconds
+        let start = chrono::SubsecRound::trunc_subsecs(Utc::now(), 0);
+        let clock = Arc::new(PausedClock(std::sync::Mutex::new(start)));
+        let emails = service(pb, transport, "smtp-a")
+            .with_retry(RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(90),
+            })
+            .with_clock(clock.clone());
+        (emails, clock)
+    }
+
+    fn queued(pb: &MockPb, queue_id: &str) -> QueuedEmail {
+        QueuedEmail::from_record(&pb.record(EMAIL_QUEUE, queue_id).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_tried_again_once_their_backoff_passes() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let queue_id = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let queued_at = queued(&pb, &queue_id).next_attempt_at.unwrap();
+        transport.fail_next(SendError::Transient("421 Service not available".to_string()));
+        transport.fail_next(SendError::Transient("connection refused".to_string()));
+
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { retrying: 1, ..Default::default() });
+        let waiting = queued(&pb, &queue_id);
+        assert_eq!((waiting.status, waiting.attempts), (EmailStatus::Pending, 1));
+        assert_eq!(waiting.last_error.as_deref(), Some("SMTP error: 421 Service not available"));
+        let first_wait = waiting.next_attempt_at.unwrap() - queued_at;
+        assert_eq!(first_wait.num_seconds(), 60);
+
+        // Not due for another second
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(emails.process_queue().await.unwrap().retrying, 1);
+        let waiting = queued(&pb, &queue_id);
+        assert_eq!(waiting.attempts, 2);
+        assert_eq!(waiting.last_error.as_deref(), Some("SMTP error: connection refused"));
+        // Two minutes, but no more than the policy's 90 seconds
+        let second_wait = waiting.next_attempt_at.unwrap() - queued_at - first_wait;
+        assert_eq!(second_wait.num_seconds(), 90);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        let sent = queued(&pb, &queue_id);
+        assert_eq!((sent.status, sent.attempts), (EmailStatus::Sent, 3));
+        assert_eq!(sent.last_error, None);
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_emails_fail_for_good_when_permanent_or_out_of_attempts() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let rejected = emails.queue_email(&request("nobody@example.com")).await.unwrap();
+        transport.fail_next(SendError::Permanent("550 No such user".to_string()));
+
+        assert_eq!(emails.process_queue().await.unwrap().failed, 1);
+        let failed = queued(&pb, &rejected);
+        assert_eq!((failed.status, failed.attempts), (EmailStatus::Failed, 1));
+        assert_eq!(failed.last_error.as_deref(), Some("SMTP error: 550 No such user"));
+
+        let struggling = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        for attempt in 1..=3 {
+            transport.fail_next(SendError::Transient(format!("421 Try again ({})", attempt)));
+        }
+        assert_eq!(emails.process_queue().await.unwrap().retrying, 1);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(emails.process_queue().await.unwrap().retrying, 1);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(emails.process_queue().await.unwrap().failed, 1);
+        let exhausted = queued(&pb, &struggling);
+        assert_eq!((exhausted.status, exhausted.attempts), (EmailStatus::Failed, 3));
+        assert_eq!(exhausted.last_error.as_deref(), Some("SMTP error: 421 Try again (3)"));
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+        assert!(transport.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_emails_over_the_send_rate_wait_for_tokens_to_refill() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let emails = emails.with_send_limits(SendLimits {
+            global: SendRate {
+                per_minute: 60,
+                burst: 2,
+            },
+            per_domain: Some(SendRate {
+                per_minute: 6,
+                burst: 1,
+            }),
+        });
+        let recipients = ["a@one.example", "b@one.example", "c@two.example", "d@three.example", "e@four.example"];
+        for to_email in recipients {
+            emails.queue_email(&request(to_email)).await.unwrap();
+        }
+        let sent = || -> Vec<String> { transport.sent().into_iter().map(|email| email.to_email).collect() };
+
+        // b waits on its domain, and c takes the last token from d and e
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 2, deferred: 3, ..Default::default() });
+        assert_eq!(sent(), ["a@one.example", "c@two.example"]);
+        let rate = emails.send_rate();
+        assert_eq!((rate.tokens_available, rate.domains_limited, rate.backlog), (0, 2, 3));
+
+        // A second brings one token, but one.example's first comes in ten
+        clock.advance(Duration::from_secs(1));
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 1, deferred: 2, ..Default::default() });
+        assert_eq!(sent()[2], "d@three.example");
+
+        clock.advance(Duration::from_secs(10));
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 2, ..Default::default() });
+        assert_eq!(sent()[3..], ["b@one.example", "e@four.example"]);
+        assert_eq!(emails.send_rate().backlog, 0);
+        assert!(pb.records(EMAIL_QUEUE).iter().all(|record| record["status"] == "sent"));
+    }
+
+    fn prioritized(to_email: &str, priority: Priority) -> SendEmailRequest {
+        SendEmailRequest {
+            priority,
+            ..request(to_email)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passes_send_the_most_urgent_emails_first_and_oldest_first_within_a_priority() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, _clock) = retrying(&pb, &transport);
+        let emails = emails.with_send_limits(SendLimits {
+            global: SendRate {
+                per_minute: 60,
+                burst: 3,
+            },
+            per_domain: None,
+        });
+        let queue = [
+            ("digest-1@example.com", Priority::Low),
+            ("invite-1@example.com", Priority::Normal),
+            ("digest-2@example.com", Priority::Low),
+            ("alert@example.com", Priority::High),
+            ("invite-2@example.com", Priority::Normal),
+        ];
+        for (to_email, priority) in queue {
+            emails.queue_email(&prioritized(to_email, priority)).await.unwrap();
+        }
+        let depth = emails.queue_depth().await.unwrap();
+        let expected = [(Priority::Urgent, 0), (Priority::High, 1), (Priority::Normal, 2), (Priority::Low, 2)];
+        assert_eq!(depth, expected);
+
+        // The send rate holds the digests back while the rest go
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 3, deferred: 2, ..Default::default() });
+        let sent: Vec<String> = transport.sent().into_iter().map(|email| email.to_email).collect();
+        assert_eq!(sent, ["alert@example.com", "invite-1@example.com", "invite-2@example.com"]);
+        let waiting = emails.emails(Some(EmailStatus::Pending), None, 1, 50).await.unwrap();
+        assert!(waiting.emails.iter().all(|queued| queued.priority == Priority::Low));
+    }
+
+    #[tokio::test]
+    async fn test_urgent_emails_are_sent_as_they_are_queued() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, _clock) = retrying(&pb, &transport);
+
+        let reset = emails.queue_email(&prioritized("alice@example.com", Priority::Urgent)).await.unwrap();
+        let sent = queued(&pb, &reset);
+        assert_eq!((sent.status, sent.attempts, sent.priority), (EmailStatus::Sent, 1, Priority::Urgent));
+        assert_eq!(transport.sent().len(), 1);
+        assert_eq!(pb.records(EMAIL_CLAIMS).len(), 1, "claimed like any other");
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+
+        // Only urgent ones, and not before their send_at
+        let normal = emails.queue_email(&request("bob@example.com")).await.unwrap();
+        let later = SendEmailRequest {
+            send_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..prioritized("carol@example.com", Priority::Urgent)
+        };
+        let later = emails.queue_email(&later).await.unwrap();
+        assert_eq!(queued(&pb, &normal).status, EmailStatus::Pending);
+        assert_eq!(queued(&pb, &later).status, EmailStatus::Pending);
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_an_urgent_email_that_fails_at_once_is_left_to_the_queue() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        transport.fail_next(SendError::Transient("421 Service not available".to_string()));
+
+        let reset = emails.queue_email(&prioritized("alice@example.com", Priority::Urgent)).await.unwrap();
+        let waiting = queued(&pb, &reset);
+        assert_eq!((waiting.status, waiting.attempts), (EmailStatus::Pending, 1));
+        assert_eq!(waiting.last_error.as_deref(), Some("SMTP error: 421 Service not available"));
+        assert!(transport.sent().is_empty());
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        assert_eq!((queued(&pb, &reset).status, queued(&pb, &reset).attempts), (EmailStatus::Sent, 2));
+    }
+
+    #[tokio::test]
+    async fn test_urgent_emails_take_send_tokens_and_wait_when_there_are_none() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let emails = emails.with_send_limits(SendLimits {
+            global: SendRate {
+                per_minute: 60,
+                burst: 1,
+            },
+            per_domain: None,
+        });
+        // Held back so its token goes to the first urgent email
+        let normal = SendEmailRequest {
+            send_at: Some(clock.now() + chrono::Duration::seconds(1)),
+            ..request("carol@example.com")
+        };
+        emails.queue_email(&normal).await.unwrap();
+
+        let first = emails.queue_email(&prioritized("alice@example.com", Priority::Urgent)).await.unwrap();
+        assert_eq!(queued(&pb, &first).status, EmailStatus::Sent);
+        assert_eq!(emails.send_rate().tokens_available, 0);
+        let second = emails.queue_email(&prioritized("bob@example.com", Priority::Urgent)).await.unwrap();
+        let waiting = queued(&pb, &second);
+        assert_eq!((waiting.status, waiting.attempts), (EmailStatus::Pending, 0));
+        assert_eq!(pb.records(EMAIL_CLAIMS).len(), 1, "not claimed without a token");
+
+        // The next token goes to it ahead of the normal email queued first
+        clock.advance(Duration::from_secs(1));
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!(processed, Processed { sent: 1, deferred: 1, ..Default::default() });
+        assert_eq!(queued(&pb, &second).status, EmailStatus::Sent);
+        assert_eq!(transport.sent()[1].to_email, "bob@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_a_pass_sends_no_more_emails_at_once_than_its_concurrency() {
+        let pb = mock_pb().await;
+        let transport = Arc::new(SlowTransport::taking(Duration::from_millis(50)));
+        let emails = EmailService::new(pb.client(), transport.clone(), "smtp-a")
+            .with_processing(Processing {
+                send_concurrency: 3,
+                ..Default::default()
+            });
+        for index in 0..8 {
+            emails.queue_email(&request(&format!("user{}@example.com", index))).await.unwrap();
+        }
+
+        assert_eq!(emails.process_queue().await.unwrap().sent, 8);
+        assert_eq!((transport.sent(), transport.most_at_once()), (8, 3));
+        assert!(pb.records(EMAIL_QUEUE).iter().all(|record| record["status"] == "sent"));
+
+        // Sending at once still takes a token each
+        let limited = EmailService::new(pb.client(), transport.clone(), "smtp-a")
+            .with_processing(Processing {
+                send_concurrency: 3,
+                ..Default::default()
+            })
+            .with_send_limits(SendLimits {
+                global: SendRate {
+                    per_minute: 60,
+                    burst: 2,
+                },
+                per_domain: None,
+            });
+        for index in 0..4 {
+            limited.queue_email(&request(&format!("later{}@example.com", index))).await.unwrap();
+        }
+        let processed = limited.process_queue().await.unwrap();
+        assert_eq!((processed.sent, processed.deferred), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_queueing_an_email_starts_a_pass_without_waiting_for_the_interval() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(service(&pb, &transport, "smtp-a").with_processing(Processing {
+            interval: Duration::from_secs(3600),
+            ..Default::default()
+        }));
+        let processor = tokio::spawn(process_email_queue(Arc::clone(&emails)));
+        // Let the first pass find the queue empty
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        emails.queue_email(&request("alice@example.com")).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while transport.sent().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the email is sent long before the next tick");
+        processor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_passes_run_at_the_configured_interval() {
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+        let (passes, wakeup) = (Arc::new(AtomicUsize::new(0)), Arc::new(Notify::new()));
+        let runner = {
+            let (passes, wakeup) = (Arc::clone(&passes), Arc::clone(&wakeup));
+            tokio::spawn(async move {
+                run_passes(Duration::from_secs(10), &wakeup, &CancellationToken::new(), || {
+                    passes.fetch_add(1, SeqCst);
+                    async {}
+                })
+                .await
+            })
+        };
+        let at = |seconds: u64| tokio::time::sleep(Duration::from_secs(seconds));
+
+        at(9).await;
+        assert_eq!(passes.load(SeqCst), 1, "the first pass runs at once");
+        at(2).await;
+        assert_eq!(passes.load(SeqCst), 2);
+
+        // A wakeup at 15s runs a pass and puts the next tick back to 25s
+        at(4).await;
+        wakeup.notify_one();
+        tokio::task::yield_now().await;
+        assert_eq!(passes.load(SeqCst), 3);
+        at(9).await;
+        assert_eq!(passes.load(SeqCst), 3);
+        at(2).await;
+        assert_eq!(passes.load(SeqCst), 4);
+        runner.abort();
+
+        let vars = HashMap::from([
+            ("EMAIL_PROCESS_INTERVAL_SECS", "5"),
+            ("EMAIL_SEND_CONCURRENCY", "4"),
+            ("SHUTDOWN_GRACE_SECS", "10"),
+        ]);
+        let processing = Processing::from_lookup(|var| vars.get(var).map(|value| value.to_string())).unwrap();
+        let expected = Processing {
+            interval: Duration::from_secs(5),
+            send_concurrency: 4,
+            shutdown_grace: Duration::from_secs(10),
+        };
+        assert_eq!(processing, expected);
+        let vars = HashMap::from([("EMAIL_SEND_CONCURRENCY", "0")]);
+        assert!(Processing::from_lookup(|var| vars.get(var).map(|value| value.to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_emails_wait_until_their_send_at() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let send_at = clock.now() + chrono::Duration::hours(9);
+        let scheduled = SendEmailRequest {
+            send_at: Some(send_at),
+            ..request("alice@example.com")
+        };
+        let queue_id = emails.queue_email(&scheduled).await.unwrap();
+        let waiting = queued(&pb, &queue_id);
+        assert_eq!((waiting.send_at, waiting.next_attempt_at), (Some(send_at), Some(send_at)));
+
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+        clock.advance(Duration::from_secs(9 * 3600 - 1));
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        assert_eq!(queued(&pb, &queue_id).status, EmailStatus::Sent);
+
+        let too_far = SendEmailRequest {
+            send_at: Some(clock.now() + chrono::Duration::days(31)),
+            ..request("alice@example.com")
+        };
+        let refused = emails.queue_email(&too_far).await.unwrap_err();
+        assert_eq!(refused.to_string(), "send_at must be within 30 days");
+        // A time already past is as good as now
+        let overdue = SendEmailRequest {
+            send_at: Some(clock.now() - chrono::Duration::hours(1)),
+            ..request("bob@example.com")
+        };
+        let queue_id = emails.queue_email(&overdue).await.unwrap();
+        assert_eq!(queued(&pb, &queue_id).next_attempt_at, Some(clock.now()));
+    }
+
+    #[tokio::test]
+    async fn test_only_emails_not_yet_sent_can_be_cancelled() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let scheduled = SendEmailRequest {
+            send_at: Some(clock.now() + chrono::Duration::hours(1)),
+            ..request("alice@example.com")
+        };
+        let cancelled = emails.queue_email(&scheduled).await.unwrap();
+        let sent = emails.queue_email(&request("bob@example.com")).await.unwrap();
+
+        let Cancel::Cancelled(email) = emails.cancel(&cancelled).await.unwrap() else {
+            panic!("a pending email can be cancelled");
+        };
+        assert_eq!((email.status, email.version), (EmailStatus::Cancelled, 1));
+        assert_eq!(queued(&pb, &cancelled).status, EmailStatus::Cancelled);
+        assert!(matches!(emails.cancel(&cancelled).await.unwrap(), Cancel::Cancelled(_)));
+
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(emails.process_queue().await.unwrap(), Processed::default());
+        let recipients: Vec<String> = transport.sent().into_iter().map(|email| email.to_email).collect();
+        assert_eq!(recipients, ["bob@example.com"]);
+
+        assert_eq!(emails.cancel(&sent).await.unwrap(), Cancel::TooLate(EmailStatus::Sent));
+        assert_eq!(emails.cancel("r99999999999999").await.unwrap(), Cancel::NotFound);
+
+        // Claimed by a processor that hasn't marked it sending yet
+        let contested = emails.queue_email(&request("carol@example.com")).await.unwrap();
+        pb.insert(EMAIL_CLAIMS, json!({ "email": contested, "version": 0, "processor_id": "smtp-b" }));
+        assert_eq!(emails.cancel(&contested).await.unwrap(), Cancel::TooLate(EmailStatus::Sending));
+        assert_eq!(queued(&pb, &contested).status, EmailStatus::Pending);
+    }
+
+    /// A record of an email to `to_email` that failed `hours_ago`
+    fn failed_before(pb: &MockPb, to_email: &str, template: &str, hours_ago: i64) -> String {
+        let failed_at = format_time(Utc::now() - chrono::Duration::hours(hours_ago));
+        pb.insert(
+            EMAIL_QUEUE,
+            json!({
+                "to_email": to_email,
+                "subject": "Your meeting is on Loom",
+                "body_text": "It's ready",
+                "template": template,
+                "status": "failed",
+                "attempts": 3,
+                "version": 3,
+                "last_error": "SMTP error: 535 Authentication failed",
+                "next_attempt_at": failed_at,
+                "created": failed_at,
+                "updated": failed_at,
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_are_listed_by_recipient_template_and_when_they_failed() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let alice_old = failed_before(&pb, "alice@example.com", "meeting_ready", 48);
+        let alice = failed_before(&pb, "alice@example.com", "welcome", 2);
+        let bob = failed_before(&pb, "bob@example.com", "meeting_ready", 1);
+        emails.queue_email(&request("carol@example.com")).await.unwrap();
+        let listed = |filter: DeadLetterFilter| {
+            let emails = &emails;
+            async move {
+                let page = emails.dead_letters(&filter, 1, 50).await.unwrap();
+                page.emails.into_iter().map(|queued| queued.id).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(listed(DeadLetterFilter::default()).await, [bob.clone(), alice.clone(), alice_old.clone()]);
+        let to_alice = DeadLetterFilter {
+            to_email: Some("alice@example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(listed(to_alice).await, [alice.clone(), alice_old.clone()]);
+        let meeting_ready = DeadLetterFilter {
+            template: Some("meeting_ready".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(listed(meeting_ready).await, [bob, alice_old]);
+        let yesterday = DeadLetterFilter {
+            since: Some(Utc::now() - chrono::Duration::hours(24)),
+            until: Some(Utc::now() - chrono::Duration::minutes(90)),
+            ..Default::default()
+        };
+        assert_eq!(listed(yesterday).await, [alice]);
+    }
+
+    #[tokio::test]
+    async fn test_a_requeued_email_is_tried_afresh_and_notes_who_requeued_it() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let (emails, clock) = retrying(&pb, &transport);
+        let queue_id = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        transport.fail_next(SendError::Permanent("535 Authentication failed".to_string()));
+        assert_eq!(emails.process_queue().await.unwrap().failed, 1);
+        clock.advance(Duration::from_secs(3600));
+
+        let Requeue::Requeued(email) = emails.requeue(&queue_id, "backend").await.unwrap() else {
+            panic!("a failed email can be requeued");
+        };
+        let stored = queued(&pb, &queue_id);
+        assert_eq!((stored.status, stored.attempts), (EmailStatus::Pending, 0));
+        assert_eq!(stored.next_attempt_at, Some(clock.now()));
+        assert_eq!((email.status, email.attempts), (EmailStatus::Pending, 0));
+        assert_eq!(email.next_attempt_at, Some(clock.now()));
+        assert_eq!((email.requeued_by.as_deref(), email.requeued_at), (Some("backend"), Some(clock.now())));
+        assert_eq!(email.last_error.as_deref(), Some("SMTP error: 535 Authentication failed"));
+
+        assert_eq!(emails.process_queue().await.unwrap().sent, 1);
+        let sent = queued(&pb, &queue_id);
+        assert_eq!((sent.status, sent.attempts, sent.last_error), (EmailStatus::Sent, 1, None));
+        assert_eq!(sent.requeued_by.as_deref(), Some("backend"));
+        assert_eq!(transport.sent().len(), 1);
+
+        assert_eq!(emails.requeue(&queue_id, "backend").await.unwrap(), Requeue::NotFailed(EmailStatus::Sent));
+        assert_eq!(emails.requeue("r99999999999999", "backend").await.unwrap(), Requeue::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_a_bulk_requeue_takes_only_the_emails_failed_since() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+        let before = failed_before(&pb, "alice@example.com", "meeting_ready", 30);
+        let during = [
+            failed_before(&pb, "bob@example.com", "meeting_ready", 3),
+            failed_before(&pb, "carol@example.com", "welcome", 2),
+        ];
+        let outage = DeadLetterFilter {
+            since: Some(Utc::now() - chrono::Duration::hours(6)),
+            ..Default::default()
+        };
+
+        let requeued = emails.requeue_all(&outage, "worker").await.unwrap();
+        assert_eq!(requeued, Requeued { requeued: 2, suppressed: 0 });
+        for queue_id in &during {
+            let email = queued(&pb, queue_id);
+            assert_eq!((email.status, email.attempts), (EmailStatus::Pending, 0));
+            assert_eq!(email.requeued_by.as_deref(), Some("worker"));
+        }
+        assert_eq!(queued(&pb, &before).status, EmailStatus::Failed);
+        assert_eq!(queued(&pb, &before).requeued_by, None);
+
+        assert_eq!(emails.process_queue().await.unwrap().sent, 2);
+        assert_eq!(emails.requeue_all(&outage, "worker").await.unwrap(), Requeued::default());
+    }
+
+    #[tokio::test]
+    async fn test_emails_to_suppressed_recipients_stay_failed_when_requeued() {
+        let pb = mock_pb().await;
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a");
+        let bounced = failed_before(&pb, "alice@example.com", "meeting_ready", 1);
+        let other = failed_before(&pb, "bob@example.com", "meeting_ready", 1);
+        pb.insert(
+            suppressions::SUPPRESSION_LIST,
+            json!({ "email": "alice@example.com", "reason": "bounce", "source": "ses" }),
+        );
+
+        let refused = emails.requeue(&bounced, "backend").await.unwrap();
+        let Requeue::Suppressed(suppression) = refused else {
+            panic!("a suppressed recipient's email isn't requeued");
+        };
+        assert_eq!(suppression.email, "alice@example.com");
+        let all = DeadLetterFilter {
+            since: Some(Utc::now() - chrono::Duration::hours(6)),
+            ..Default::default()
+        };
+        let requeued = emails.requeue_all(&all, "backend").await.unwrap();
+        assert_eq!(requeued, Requeued { requeued: 1, suppressed: 1 });
+        assert_eq!(queued(&pb, &bounced).status, EmailStatus::Failed);
+        assert_eq!(queued(&pb, &other).status, EmailStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_each_sent_email_records_the_provider_it_went_through() {
+        use crate::providers::BreakerPolicy;
+
+        let pb = mock_pb().await;
+        let (primary, backup) = (FakeTransport::default(), FakeTransport::default());
+        let emails = service(&pb, &FakeTransport::default(), "smtp-a").with_providers(Providers::new(
+            vec![
+                ("primary".to_string(), Arc::new(primary.clone()) as Arc<dyn Transport>),
+                ("backup".to_string(), Arc::new(backup.clone())),
+            ],
+            BreakerPolicy::default(),
+        ));
+        let first = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let second = emails.queue_email(&request("bob@example.com")).await.unwrap();
+        primary.fail_next(SendError::Transient("421 Try again later".to_string()));
+
+        assert_eq!(emails.process_queue().await.unwrap().sent, 2);
+        let failed_over = queued(&pb, &first);
+        assert_eq!((failed_over.status, failed_over.attempts), (EmailStatus::Sent, 1));
+        assert_eq!(failed_over.provider.as_deref(), Some("backup"));
+        assert_eq!(queued(&pb, &second).provider.as_deref(), Some("primary"));
+        let status = emails.provider_status();
+        assert_eq!((status[0].failures, status[0].healthy), (0, true));
+    }
+
+    #[tokio::test]
+    async fn test_an_email_claimed_elsewhere_is_left_alone() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = service(&pb, &transport, "smtp-a");
+        let queue_id = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        // Another processor won the claim but hasn't marked the email yet
+        pb.insert(EMAIL_CLAIMS, json!({ "email": queue_id, "version": 0, "processor_id": "smtp-b" }));
+
+        assert_eq!(emails.process_queue().await.unwrap().skipped, 1);
+        assert!(transport.sent().is_empty());
+        assert_eq!(pb.record(EMAIL_QUEUE, &queue_id).unwrap()["status"], "pending");
+
+        // Two processors at once send it only once between them
+        let other = service(&pb, &transport, "smtp-c");
+        let contested = emails.queue_email(&request("bob@example.com")).await.unwrap();
+        let (a, c) = tokio::join!(emails.process_queue(), other.process_queue());
+        assert_eq!(a.unwrap().sent + c.unwrap().sent, 1);
+        assert_eq!(transport.sent().len(), 1);
+        assert_eq!(pb.record(EMAIL_QUEUE, &contested).unwrap()["status"], "sent");
+    }
+
+    #[tokio::test]
+    async fn test_pending_emails_are_sent_after_a_restart() {
+        let pb = mock_pb().await;
+        let before = FakeTransport::default();
+        let queued = {
+            let emails = service(&pb, &before, "smtp-a");
+            vec![
+                emails.queue_email(&request("alice@example.com")).await.unwrap(),
+                emails.queue_email(&request("bob@example.com")).await.unwrap(),
+            ]
+        };
+
+        let after = FakeTransport::default();
+        let restarted = service(&pb, &after, "smtp-b");
+        assert_eq!(restarted.process_queue().await.unwrap().sent, 2);
+        let recipients: Vec<String> = after.sent().into_iter().map(|email| email.to_email).collect();
+        assert_eq!(recipients, ["alice@example.com", "bob@example.com"], "oldest first");
+        for queue_id in queued {
+            assert_eq!(pb.record(EMAIL_QUEUE, &queue_id).unwrap()["status"], "sent");
+        }
+        assert!(before.sent().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! Who can reach the service
+//!
+//! Only the backend and worker call the SMTP service, server to server, so by
+//! default it listens on 127.0.0.1 and answers no browser origin: a
+//! cross-origin request gets no `Access-Control-Allow-Origin` and the browser
+//! keeps the response from the page. `SMTP_BIND_INTERNAL_ONLY=false` lets it
+//! listen on `SMTP_BIND_HOST` (0.0.0.0 unless set), and `SMTP_CORS_ORIGINS`
+//! names the origins a browser may call it from.
+
+use axum::http::{header, HeaderValue, Method};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// The address the service listens on and the origins it answers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exposure {
+    pub bind: SocketAddr,
+    /// Bare origins, or "*" for any
+    pub origins: Vec<String>,
+}
+
+impl Exposure {
+    /// Read SMTP_BIND_INTERNAL_ONLY, SMTP_BIND_HOST, SMTP_PORT and SMTP_CORS_ORIGINS
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let set = |var: &str| lookup(var).filter(|value| !value.trim().is_empty());
+        let internal_only = match set("SMTP_BIND_INTERNAL_ONLY") {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("SMTP_BIND_INTERNAL_ONLY must be true or false, not '{}'", value))?,
+            None => true,
+        };
+        let host = set("SMTP_BIND_HOST")
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("SMTP_BIND_HOST must be an IP address, not '{}'", value))
+            })
+            .transpose()?;
+        let port = match set("SMTP_PORT") {
+            Some(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("SMTP_PORT must be a port number, not '{}'", value))?,
+            None => 3001,
+        };
+        Ok(Self {
+            bind: SocketAddr::new(bind_host(internal_only, host)?, port),
+            origins: parse_origins(&set("SMTP_CORS_ORIGINS").unwrap_or_default())?,
+        })
+    }
+
+    /// Whether the service listens on every interface and answers any origin
+    pub fn is_wide_open(&self) -> bool {
+        self.bind.ip().is_unspecified() && self.origins.iter().any(|origin| origin == "*")
+    }
+
+    /// The CORS layer answering `origins`, none when there are none
+    pub fn cors(&self) -> CorsLayer {
+        let allow_origin = if self.origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::mirror_request()
+        } else {
+            AllowOrigin::list(
+                self.origins
+                    .iter()
+                    .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+            )
+        };
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, header::ACCEPT])
+            .allow_credentials(true)
+    }
+}
+
+/// Loopback unless `internal_only` is off, then `host` or every interface
+fn bind_host(internal_only: bool, host: Option<IpAddr>) -> Result<IpAddr, String> {
+    match (internal_only, host) {
+        (true, Some(host)) if !host.is_loopback() => Err(format!(
+            "SMTP_BIND_HOST {} isn't a loopback address; set SMTP_BIND_INTERNAL_ONLY=false to listen on it",
+            host
+        )),
+        (_, Some(host)) => Ok(host),
+        (true, None) => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        (false, None) => Ok(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+    }
+}
+
+/// Parse origins the same way the backend parses CORS_ORIGINS: "*" or bare
+/// http(s) origins
+fn parse_origins(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            if entry == "*" {
+                return Ok(entry.to_string());
+            }
+            let invalid = || {
+                format!(
+                    "SMTP_CORS_ORIGINS entry '{}' must be \"*\" or an http(s) origin",
+                    entry
+                )
+            };
+            let url = reqwest::Url::parse(entry).map_err(|_| invalid())?;
+            let bare = url.username().is_empty()
+                && url.password().is_none()
+                && url.path() == "/"
+                && url.query().is_none()
+                && url.fragment().is_none();
+            if !matches!(url.scheme(), "http" | "https") || url.host().is_none() || !bare {
+                return Err(invalid());
+            }
+            Ok(url.origin().ascii_serialization())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, http::HeaderMap, routing::get, Router};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    fn exposure(vars: &[(&str, &str)]) -> Result<Exposure, String> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect();
+        Exposure::from_lookup(|var| vars.get(var).cloned())
+    }
+
+    /// The headers `exposure` answers a request from `origin` with
+    async fn answer(exposure: &Exposure, method: Method, origin: &str) -> HeaderMap {
+        let app = Router::new().route("/health", get(|| async { "ok" })).layer(exposure.cors());
+        let request = Request::builder()
+            .method(method)
+            .uri("/health")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn test_cross_origin_requests_are_refused_by_default() {
+        let exposure = exposure(&[]).unwrap();
+        assert!(exposure.origins.is_empty());
+        for method in [Method::GET, Method::OPTIONS] {
+            let headers = answer(&exposure, method, "http://localhost:8080").await;
+            assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configured_origins_are_allowed() {
+        let exposure = exposure(&[("SMTP_CORS_ORIGINS", "https://admin.example.com/")]).unwrap();
+        let headers = answer(&exposure, Method::OPTIONS, "https://admin.example.com").await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://admin.example.com");
+        let headers = answer(&exposure, Method::GET, "https://admin.example.com").await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://admin.example.com");
+        let headers = answer(&exposure, Method::GET, "https://elsewhere.example.com").await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn test_origin_parsing() {
+        assert_eq!(
+            parse_origins("http://localhost:8080/, *").unwrap(),
+            vec!["http://localhost:8080".to_string(), "*".to_string()]
+        );
+        assert!(parse_origins("localhost:8080").is_err());
+        assert!(parse_origins("https://app.example.com/path").is_err());
+    }
+
+    #[test]
+    fn test_the_service_listens_on_loopback_unless_told_otherwise() {
+        let bind = |vars: &[(&str, &str)]| exposure(vars).map(|exposure| exposure.bind.to_string());
+        assert_eq!(bind(&[]).unwrap(), "127.0.0.1:3001");
+        assert_eq!(bind(&[("SMTP_PORT", "4001")]).unwrap(), "127.0.0.1:4001");
+        assert_eq!(bind(&[("SMTP_BIND_HOST", "::1")]).unwrap(), "[::1]:3001");
+        assert_eq!(
+            bind(&[("SMTP_BIND_HOST", "0.0.0.0")]).unwrap_err(),
+            "SMTP_BIND_HOST 0.0.0.0 isn't a loopback address; set SMTP_BIND_INTERNAL_ONLY=false to listen on it"
+        );
+        assert_eq!(bind(&[("SMTP_BIND_INTERNAL_ONLY", "false")]).unwrap(), "0.0.0.0:3001");
+        let chosen = [("SMTP_BIND_INTERNAL_ONLY", "false"), ("SMTP_BIND_HOST", "10.0.0.5")];
+        assert_eq!(bind(&chosen).unwrap(), "10.0.0.5:3001");
+
+        assert!(bind(&[("SMTP_BIND_INTERNAL_ONLY", "no")]).is_err());
+        assert!(bind(&[("SMTP_BIND_HOST", "localhost")]).is_err());
+        assert!(bind(&[("SMTP_PORT", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_every_interface_and_any_origin_is_wide_open() {
+        let public = [("SMTP_BIND_INTERNAL_ONLY", "false"), ("SMTP_CORS_ORIGINS", "*")];
+        assert!(exposure(&public).unwrap().is_wide_open());
+        assert!(!exposure(&[("SMTP_CORS_ORIGINS", "*")]).unwrap().is_wide_open());
+        let listed = [("SMTP_BIND_INTERNAL_ONLY", "false"), ("SMTP_CORS_ORIGINS", "https://a.example")];
+        assert!(!exposure(&listed).unwrap().is_wide_open());
+    }
+}
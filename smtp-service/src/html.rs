@@ -0,0 +1,587 @@
+//! Callers' HTML made safe to send, and a text version taken from it
+//!
+//! A `body_html` given to `/send-email` may carry text a user wrote, a
+//! meeting topic or an error message, so it is [`sanitize`]d before it is
+//! queued. Only the tags of [`ALLOWED`] are kept, each with only the
+//! attributes it lists, and `href` and `src` only when they are http(s),
+//! `mailto:` or `cid:` links; event handlers and `style` go with every other
+//! attribute. Scripts, styles, frames, embedded objects and forms are
+//! removed with all they contain, and any other tag is dropped but keeps its
+//! text. Without a `body_text`, [`to_text`] reads one off the sanitized
+//! HTML: links as `text (url)`, headings marked with `#`, list items with
+//! `-` or their number.
+//!
+//! Rendered templates are written to be safe and are sent as rendered.
+
+/// Tags kept, with the attributes each may keep
+pub const ALLOWED: &[(&str, &[&str])] = &[
+    ("a", &["href", "title"]),
+    ("abbr", &["title"]),
+    ("b", &[]),
+    ("blockquote", &[]),
+    ("br", &[]),
+    ("caption", &[]),
+    ("code", &[]),
+    ("div", &[]),
+    ("em", &[]),
+    ("h1", &[]),
+    ("h2", &[]),
+    ("h3", &[]),
+    ("h4", &[]),
+    ("h5", &[]),
+    ("h6", &[]),
+    ("hr", &[]),
+    ("i", &[]),
+    ("img", &["src", "alt", "width", "height"]),
+    ("li", &[]),
+    ("ol", &["start"]),
+    ("p", &[]),
+    ("pre", &[]),
+    ("s", &[]),
+    ("small", &[]),
+    ("span", &[]),
+    ("strong", &[]),
+    ("sub", &[]),
+    ("sup", &[]),
+    ("table", &[]),
+    ("tbody", &[]),
+    ("td", &["colspan", "rowspan"]),
+    ("tfoot", &[]),
+    ("th", &["colspan", "rowspan"]),
+    ("thead", &[]),
+    ("tr", &[]),
+    ("u", &[]),
+    ("ul", &[]),
+];
+
+/// Tags removed along with everything inside them
+const DROPPED: &[&str] = &[
+    "applet", "button", "embed", "form", "frame", "frameset", "head", "iframe", "math", "noembed",
+    "noframes", "noscript", "object", "script", "select", "style", "svg", "template", "textarea",
+    "title",
+];
+
+/// Tags whose contents are read as text up to their end tag, never as tags
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title", "noscript", "noembed", "noframes"];
+
+/// Tags that never have contents or an end tag
+const VOID: &[&str] = &["br", "hr", "img"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node<'a> {
+    Text(&'a str),
+    Start {
+        name: String,
+        /// Names lowercased, values with their character references decoded
+        attributes: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    End(String),
+}
+
+/// `html` read as text, tags and end tags; comments, doctypes and
+/// processing instructions are left out
+fn parse(html: &str) -> Vec<Node<'_>> {
+    let mut nodes = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let after = &rest[start + 1..];
+        let closing = after.starts_with('/');
+        let tag = if closing { &after[1..] } else { after };
+        if !after.starts_with(['!', '?']) && !tag.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // A `<` that starts no tag is text
+            nodes.push(Node::Text(&rest[..start + 1]));
+            rest = after;
+            continue;
+        }
+        if start > 0 {
+            nodes.push(Node::Text(&rest[..start]));
+        }
+        if let Some(comment) = after.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if after.starts_with(['!', '?']) {
+            rest = after.find('>').map_or("", |end| &after[end + 1..]);
+            continue;
+        }
+        // An unterminated tag is dropped with the rest of the document
+        let Some(end) = tag_end(tag) else {
+            rest = "";
+            break;
+        };
+        let inside = &tag[..end];
+        rest = &tag[end + 1..];
+        let name_end = inside
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(inside.len());
+        let name = inside[..name_end].to_ascii_lowercase();
+        if closing {
+            nodes.push(Node::End(name));
+            continue;
+        }
+        let self_closing = inside.trim_end().ends_with('/');
+        let raw = !self_closing && RAW_TEXT.contains(&name.as_str());
+        nodes.push(Node::Start {
+            attributes: attributes(&inside[name_end..]),
+            name: name.clone(),
+            self_closing,
+        });
+        if raw {
+            // Lowercasing keeps every byte where it was
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(at) => {
+                    let after_close = &rest[at..];
+                    after_close.find('>').map_or("", |end| &after_close[end + 1..])
+                }
+                None => "",
+            };
+            nodes.push(Node::End(name));
+        }
+    }
+    if !rest.is_empty() {
+        nodes.push(Node::Text(rest));
+    }
+    nodes
+}
+
+/// Where the `>` closing a tag is, outside any quoted attribute value
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (at, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '>') => return Some(at),
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn attributes(mut source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        source = source.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if source.is_empty() {
+            return attributes;
+        }
+        let name_end = source
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(source.len());
+        if name_end == 0 {
+            source = &source[1..];
+            continue;
+        }
+        let name = source[..name_end].to_ascii_lowercase();
+        source = source[name_end..].trim_start();
+        let mut value = "";
+        if let Some(assigned) = source.strip_prefix('=') {
+            let assigned = assigned.trim_start();
+            match assigned.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &assigned[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    value = &quoted[..end];
+                    source = quoted.get(end + 1..).unwrap_or_default();
+                }
+                _ => {
+                    let end = assigned.find(char::is_whitespace).unwrap_or(assigned.len());
+                    value = &assigned[..end];
+                    source = &assigned[end..];
+                }
+            }
+        }
+        attributes.push((name, decode_entities(value)));
+    }
+}
+
+/// `text` with its character references, `&amp;` or `&#x27;`, decoded;
+/// an `&` starting none is kept
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        decoded.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let reference = rest[1..].find(';').filter(|end| *end <= 10).and_then(|end| {
+            let name = &rest[1..end + 1];
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                _ => {
+                    let number = name.strip_prefix('#')?;
+                    let code = match number.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => number.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 2))
+        });
+        match reference {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// `text` escaped for HTML, quotes too when it is an attribute value
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether `url` may be kept as the `href` or `src` of a tag
+fn safe_url(attribute: &str, url: &str) -> bool {
+    // Browsers skip whitespace and control characters in a scheme
+    let compact: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let schemes: &[&str] = match attribute {
+        "href" => &["http://", "https://", "mailto:"],
+        _ => &["http://", "https://", "cid:"],
+    };
+    schemes.iter().any(|scheme| compact.starts_with(scheme))
+}
+
+/// `html` with only the tags and attributes of [`ALLOWED`], every tag kept
+/// closed
+pub fn sanitize(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<&'static str> = Vec::new();
+    // The dropped tag being skipped, and how deeply it is nested
+    let mut dropping: Option<(String, usize)> = None;
+    for node in parse(html) {
+        if let Some((dropped, depth)) = &mut dropping {
+            match &node {
+                Node::Start { name, self_closing: false, .. } if name == dropped => *depth += 1,
+                Node::End(name) if name == dropped => {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        dropping = None;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match node {
+            Node::Text(text) => out.push_str(&escape(&decode_entities(text), false)),
+            Node::Start {
+                name,
+                attributes,
+                self_closing,
+            } => {
+                if DROPPED.contains(&name.as_str()) {
+                    if !self_closing {
+                        dropping = Some((name, 1));
+                    }
+                    continue;
+                }
+                let Some((tag, allowed)) = ALLOWED.iter().find(|(tag, _)| *tag == name) else {
+                    continue;
+                };
+                out.push('<');
+                out.push_str(tag);
+                let mut linked = false;
+                for (attribute, value) in &attributes {
+                    let url = matches!(attribute.as_str(), "href" | "src");
+                    if !allowed.contains(&attribute.as_str()) || (url && !safe_url(attribute, value)) {
+                        continue;
+                    }
+                    linked |= attribute == "href";
+                    out.push_str(&format!(" {}=\"{}\"", attribute, escape(value, true)));
+                }
+                if linked {
+                    out.push_str(" rel=\"noopener noreferrer\"");
+                }
+                out.push('>');
+                if !VOID.contains(tag) {
+                    open.push(tag);
+                }
+            }
+            Node::End(name) => {
+                if let Some(at) = open.iter().rposition(|tag| *tag == name) {
+                    for tag in open.drain(at..).rev() {
+                        out.push_str(&format!("</{}>", tag));
+                    }
+                }
+            }
+        }
+    }
+    for tag in open.into_iter().rev() {
+        out.push_str(&format!("</{}>", tag));
+    }
+    out
+}
+
+/// Text read off `html`, as the plain-text part of an email
+pub fn to_text(html: &str) -> String {
+    let mut text = TextWriter::default();
+    for node in parse(html) {
+        match node {
+            Node::Text(chunk) => text.write(&decode_entities(chunk)),
+            Node::Start {
+                name, attributes, ..
+            } => {
+                let attribute = |wanted: &str| {
+                    attributes
+                        .iter()
+                        .find(|(name, _)| name == wanted)
+                        .map(|(_, value)| value.clone())
+                };
+                text.start(&name, attribute("href"), attribute("alt"));
+            }
+            Node::End(name) => text.end(&name),
+        }
+    }
+    text.finish()
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// For each open list, the number of its next item, or `None` if it
+    /// isn't numbered
+    lists: Vec<Option<u32>>,
+    /// For each open link, its URL and where its text starts
+    links: Vec<(Option<String>, usize)>,
+    preformatted: usize,
+    /// Whether the row being written has had a cell yet
+    row_started: bool,
+}
+
+impl TextWriter {
+    fn write(&mut self, chunk: &str) {
+        if self.preformatted > 0 {
+            self.out.push_str(chunk);
+            return;
+        }
+        let words: Vec<&str> = chunk.split_whitespace().collect();
+        if words.is_empty() {
+            if !chunk.is_empty() {
+                self.space();
+            }
+            return;
+        }
+        if chunk.starts_with(char::is_whitespace) {
+            self.space();
+        }
+        self.out.push_str(&words.join(" "));
+        if chunk.ends_with(char::is_whitespace) {
+            self.space();
+        }
+    }
+
+    /// A space, unless the line is empty or already ends with one
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+    }
+
+    /// End the line, leaving `lines - 1` blank lines before what follows
+    fn block(&mut self, lines: usize) {
+        let kept = self.out.trim_end_matches(' ').len();
+        self.out.truncate(kept);
+        if self.out.is_empty() {
+            return;
+        }
+        let ending = self.out.len() - self.out.trim_end_matches('\n').len();
+        for _ in ending..lines {
+            self.out.push('\n');
+        }
+    }
+
+    fn start(&mut self, name: &str, href: Option<String>, alt: Option<String>) {
+        match name {
+            "p" | "div" | "blockquote" | "table" | "pre" => self.block(2),
+            "ul" | "ol" => {
+                self.block(if self.lists.is_empty() { 2 } else { 1 });
+                self.lists.push((name == "ol").then_some(1));
+            }
+            "li" => {
+                self.block(1);
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        self.out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block(2);
+                let level = name[1..].parse().unwrap_or(1);
+                self.out.push_str(&format!("{} ", "#".repeat(level)));
+            }
+            "tr" => {
+                self.block(1);
+                self.row_started = false;
+            }
+            "td" | "th" => {
+                if self.row_started {
+                    self.out.push_str(" | ");
+                }
+                self.row_started = true;
+            }
+            "br" => {
+                let kept = self.out.trim_end_matches(' ').len();
+                self.out.truncate(kept);
+                self.out.push('\n');
+            }
+            "hr" => {
+                self.block(2);
+                self.out.push_str("---");
+                self.block(2);
+            }
+            "img" => {
+                if let Some(alt) = alt.filter(|alt| !alt.trim().is_empty()) {
+                    self.write(&alt);
+                }
+            }
+            "a" => self.links.push((href, self.out.len())),
+            _ => {}
+        }
+        if name == "pre" {
+            self.preformatted += 1;
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "p" | "div" | "blockquote" | "table" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block(2)
+            }
+            "pre" => {
+                self.preformatted = self.preformatted.saturating_sub(1);
+                self.block(2);
+            }
+            "ul" | "ol" => {
+                self.lists.pop();
+                self.block(if self.lists.is_empty() { 2 } else { 1 });
+            }
+            "tr" => self.block(1),
+            "a" => {
+                let Some((Some(href), start)) = self.links.pop() else {
+                    return;
+                };
+                let label = self.out[start..].trim().to_string();
+                let shown = href.strip_prefix("mailto:").unwrap_or(&href);
+                if label.is_empty() {
+                    self.write(&href);
+                } else if label != shown && label != href {
+                    let kept = self.out.trim_end_matches(' ').len();
+                    self.out.truncate(kept);
+                    self.out.push_str(&format!(" ({})", href));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = String::with_capacity(self.out.len());
+        let mut blank = 0;
+        for line in self.out.trim().lines().map(str::trim_end) {
+            blank = if line.is_empty() { blank + 1 } else { 0 };
+            if blank < 2 {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn golden(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(name)
+    }
+
+    #[test]
+    fn test_hostile_html_is_neutered() {
+        let cases = [
+            ("<p>Hi<script>alert(1)</script></p>", "<p>Hi</p>"),
+            ("<SCRIPT src=x>alert('<p>')</SCRIPT >after", "after"),
+            ("<p onclick=\"steal()\" style=\"x\">Weekly sync</p>", "<p>Weekly sync</p>"),
+            ("<a href=\"javascript:alert(1)\">click</a>", "<a>click</a>"),
+            ("<a href=\" J&#x41;vA\tscript:alert(1)\">click</a>", "<a>click</a>"),
+            (
+                "<a href='https://loom.com/share/a?b=1&amp;c=\"2\"' onmouseover=x>Loom</a>",
+                "<a href=\"https://loom.com/share/a?b=1&amp;c=&quot;2&quot;\" rel=\"noopener noreferrer\">Loom</a>",
+            ),
+            ("<img src=\"data:image/png;base64,AA\" alt=\"logo\">", "<img alt=\"logo\">"),
+            ("<form action=\"https://evil.example/collect\"><input name=key>Key</form>done", "done"),
+            ("<iframe src=https://evil.example><p>in</p></iframe>out", "out"),
+            ("<svg><svg onload=x></svg></svg>gone", "gone"),
+            ("<!-- <script>x</script> --><b>bold", "<b>bold</b>"),
+            ("<blink>Topic</blink> 1 < 2 & 3 > 2", "Topic 1 &lt; 2 &amp; 3 &gt; 2"),
+            ("<p><b>unbalanced</p>", "<p><b>unbalanced</b></p>"),
+            ("<b title=\"x\" onerror=y>kept</i></b>", "<b>kept</b>"),
+            ("<p>&lt;script&gt; stays text</p>", "<p>&lt;script&gt; stays text</p>"),
+            ("<p>broken <a href=\"https://loom.com", "<p>broken </p>"),
+        ];
+        for (hostile, expected) in cases {
+            assert_eq!(sanitize(hostile), expected, "{}", hostile);
+        }
+    }
+
+    #[test]
+    fn test_text_is_read_off_the_html_as_the_golden_file_has_it() {
+        let html = std::fs::read_to_string(golden("derived_text.html")).unwrap();
+        let text = format!("{}\n", to_text(&sanitize(&html)));
+        let path = golden("derived_text.txt");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &text).unwrap();
+        }
+        assert!(
+            text == std::fs::read_to_string(&path).unwrap_or_default(),
+            "{} is out of date; regenerate it with \
+             UPDATE_GOLDEN=1 cargo test -p fathom-loom-smtp-service html",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_links_show_their_url_once() {
+        let text = |html: &str| to_text(&sanitize(html));
+        assert_eq!(text("<a href=\"https://loom.com/share/a\">Watch</a>"), "Watch (https://loom.com/share/a)");
+        assert_eq!(text("<a href=\"https://loom.com/share/a\">https://loom.com/share/a</a>"), "https://loom.com/share/a");
+        assert_eq!(text("<a href=\"mailto:help@example.com\">help@example.com</a>"), "help@example.com");
+        assert_eq!(text("Go <a href=\"https://loom.com\"></a> now"), "Go https://loom.com now");
+        assert_eq!(text("<a href=\"javascript:x\">Nowhere</a>"), "Nowhere");
+    }
+}
@@ -0,0 +1,163 @@
+//! Request body size limits and timeouts, matching the backend's
+//!
+//! Bodies over `BODY_LIMIT_BYTES` are answered 413 and requests still running
+//! after `REQUEST_TIMEOUT_SECS` 408, both in the backend's error envelope.
+
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
+    Router,
+};
+use serde_json::json;
+use std::time::Duration;
+use tower_http::{limit::RequestBodyLimitLayer, timeout::TimeoutLayer};
+
+/// The largest body and longest time a request may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub body_bytes: usize,
+    pub timeout: Duration,
+}
+
+impl Limits {
+    /// Read the BODY_LIMIT_BYTES and REQUEST_TIMEOUT_SECS shared with the backend
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let positive = |var: &str, unit: &str, default: u64| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(parsed),
+                _ => Err(format!(
+                    "{} must be a positive number of {}, not '{}'",
+                    var, unit, value
+                )),
+            },
+            None => Ok(default),
+        };
+        Ok(Self {
+            body_bytes: positive("BODY_LIMIT_BYTES", "bytes", 2 * 1024 * 1024)? as usize,
+            timeout: Duration::from_secs(positive("REQUEST_TIMEOUT_SECS", "seconds", 30)?),
+        })
+    }
+
+    /// `router` with these limits on every route registered so far
+    pub fn apply(self, router: Router) -> Router {
+        router
+            // The layer below replaces axum's own 2 MB extractor limit
+            .layer(DefaultBodyLimit::disable())
+            .layer(RequestBodyLimitLayer::new(self.body_bytes))
+            .layer(TimeoutLayer::new(self.timeout))
+            .layer(middleware::map_response(envelope))
+    }
+}
+
+/// Give the layers' bare 413 and 408 answers the error envelope
+async fn envelope(response: Response) -> Response {
+    let (code, message) = match response.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => (
+            "payload_too_large",
+            "Request body is larger than this endpoint accepts",
+        ),
+        StatusCode::REQUEST_TIMEOUT => ("timeout", "Request took too long; try again"),
+        _ => return response,
+    };
+    let json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if json {
+        return response;
+    }
+    let body = json!({
+        "success": false,
+        "data": null,
+        "error": message,
+        "code": code,
+        "timestamp": chrono::Utc::now(),
+    });
+    (response.status(), Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, Bytes},
+        extract::Request,
+        routing::{get, post},
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn limited() -> Router {
+        let limits = Limits {
+            body_bytes: 16,
+            timeout: Duration::from_millis(100),
+        };
+        limits.apply(
+            Router::new()
+                .route("/echo", post(|body: Bytes| async move { body }))
+                .route(
+                    "/slow",
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        "done"
+                    }),
+                ),
+        )
+    }
+
+    async fn send(request: Request) -> (StatusCode, Value) {
+        let response = limited().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_oversized_and_slow_requests_get_the_envelope() {
+        let post = |body: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/echo")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        assert_eq!(send(post("small")).await.0, StatusCode::OK);
+
+        let (status, body) = send(post(&"x".repeat(17))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "payload_too_large");
+
+        let (status, body) = send(Request::get("/slow").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(body["code"], "timeout");
+        assert!(body["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_limits_must_be_positive() {
+        let limits = Limits::from_lookup(|_| None).unwrap();
+        assert_eq!(limits.body_bytes, 2 * 1024 * 1024);
+        assert_eq!(limits.timeout, Duration::from_secs(30));
+
+        let error =
+            Limits::from_lookup(|var| (var == "REQUEST_TIMEOUT_SECS").then(|| "0".to_string()))
+                .unwrap_err();
+        assert_eq!(
+            error,
+            "REQUEST_TIMEOUT_SECS must be a positive number of seconds, not '0'"
+        );
+    }
+}
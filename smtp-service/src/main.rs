@@ -1,34 +1,340 @@
-use axum::{response::Html, routing::get, Router};
-use tracing::info;
+use axum::{
+    extract::State,
+    http::header,
+    middleware,
+    response::{Html, IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use common::metrics::EXPOSITION_CONTENT_TYPE;
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+mod api;
+mod attachments;
+mod auth;
+mod bounces;
+mod dkim;
+mod email_service;
+mod exposure;
+mod html;
+mod limits;
+mod metrics;
+mod pocketbase;
+mod providers;
+mod rate_limit;
+mod request_log;
+mod retry;
+mod shutdown;
+mod suppressions;
+mod templates;
+#[cfg(test)]
+mod test_support;
+mod transport;
+
+use dkim::{DkimSigner, DkimStatus};
+use email_service::EmailService;
+use pocketbase::{PbSettings, PocketBase};
+use providers::{BreakerPolicy, Providers};
+use transport::{LogTransport, SmtpSettings, SmtpTransport, Transport};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    // Initialize tracing with RUST_LOG's level and LOG_FORMAT's format
+    let level = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    common::logging::init_tracing("smtp", common::logging::LogFormat::from_env()?, &level);
+    let sentry_dsn = std::env::var("SENTRY_DSN").ok();
+    common::telemetry::install_panic_hook(common::telemetry::reporter(sentry_dsn.as_deref(), "smtp"));
 
     info!("Starting SMTP service");
 
-    // Build application router
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/health", get(health_check));
+    // Refuse to start with origins the browser could never send
+    let exposure = exposure::Exposure::from_env()?;
+    if exposure.is_wide_open() {
+        warn!(
+            "!!! Listening on every interface ({}) with SMTP_CORS_ORIGINS=*: any web page can call this service; \
+             it is meant only for the backend and worker !!!",
+            exposure.bind
+        );
+    }
+    let levels = request_log::Levels::from_env()?;
+    let limits = limits::Limits::from_env()?;
+    let attachment_limits = attachments::AttachmentLimits::from_env()?;
+    let retry = retry::RetryPolicy::from_env()?;
+    let keys = auth::ApiKeys::from_env()?;
+    let send_limits = rate_limit::SendLimits::from_env()?;
+    let processing = email_service::Processing::from_env()?;
+    let breaker = BreakerPolicy::from_env()?;
+    let dkim = DkimSigner::from_env()?.map(Arc::new);
+    let bounces = Arc::new(bounces::BounceVerifier::from_env());
+    if !bounces.has_secret() {
+        info!("BOUNCE_WEBHOOK_SECRET is unset; only SNS bounce reports are taken");
+    }
+    match &dkim {
+        Some(dkim) => {
+            let status = dkim.status();
+            info!("Signing email as {} with DKIM selector {}", status.domain, status.selector);
+        }
+        None => info!("DKIM_DOMAIN is unset; email is sent unsigned"),
+    }
+    match keys.is_empty() {
+        true => warn!("SMTP_SERVICE_API_KEYS is unset; every request but /health will be refused"),
+        false => info!("Accepting API keys {}", keys.labels().join(", ")),
+    }
 
-    // Start server
-    let port = std::env::var("SMTP_PORT").unwrap_or_else(|_| "3001".to_string());
-    let addr = format!("0.0.0.0:{}", port);
+    let mut transports = Vec::new();
+    for (name, settings) in SmtpSettings::providers_from_env()? {
+        info!("Sending email through {} at {}:{}", name, settings.host, settings.port);
+        let transport = SmtpTransport::new(&settings)?.with_dkim(dkim.clone());
+        transports.push((name, Arc::new(transport) as Arc<dyn Transport>));
+    }
+    if transports.is_empty() {
+        warn!("SMTP_HOST is unset; queued emails will be logged, not sent");
+        transports.push(("default".to_string(), Arc::new(LogTransport)));
+    }
+    let emails = Arc::new(EmailService::new(
+        PocketBase::from(&PbSettings::from_env()),
+        Arc::new(LogTransport),
+        &format!("smtp-{}", Uuid::new_v4()),
+    )
+    .with_providers(Providers::new(transports, breaker))
+    .with_attachment_limits(attachment_limits)
+    .with_retry(retry)
+    .with_send_limits(send_limits)
+    .with_processing(processing));
+    let processor = tokio::spawn(email_service::process_email_queue(Arc::clone(&emails)));
+    let checked = Arc::clone(&emails);
+    tokio::spawn(async move {
+        for report in checked.test_connections().await {
+            match report.error {
+                None => info!(provider = %report.name, "SMTP provider answered"),
+                Some(e) => warn!(provider = %report.name, "SMTP provider can't be reached: {}", e),
+            }
+        }
+    });
 
-    info!("SMTP service listening on {}", addr);
+    let api_limits = limits::Limits {
+        body_bytes: attachment_limits.body_bytes(limits.body_bytes),
+        ..limits
+    };
+    let dkim = dkim.map(|dkim| dkim.status());
+    let app = routes(Arc::clone(&emails), dkim, bounces, keys, limits, api_limits)
+        .layer(exposure.cors())
+        .layer(middleware::from_fn_with_state(
+            levels,
+            request_log::log_requests,
+        ));
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    // Start server
+    info!("SMTP service listening on {}", exposure.bind);
 
+    let listener = tokio::net::TcpListener::bind(exposure.bind).await?;
+    let grace = processing.shutdown_grace;
+    if !shutdown::serve(listener, app, emails, processor, grace, shutdown::signal()).await? {
+        warn!("Shutdown was forced");
+        std::process::exit(1);
+    }
+    info!("Shutdown complete");
     Ok(())
 }
 
+/// Every route, all but `/health`, `/metrics` and the bounce webhook behind
+/// `keys`, each request counted in the metrics; `/send-email` takes
+/// attachments on top of the usual body limit, so gets `api_limits`
+fn routes(
+    emails: Arc<EmailService>,
+    dkim: Option<DkimStatus>,
+    bounces: Arc<bounces::BounceVerifier>,
+    keys: auth::ApiKeys,
+    limits: limits::Limits,
+    api_limits: limits::Limits,
+) -> Router {
+    let health = Router::new()
+        .route("/health", get(health_check))
+        .route("/metrics", get(get_metrics))
+        .with_state(Health {
+            emails: Arc::clone(&emails),
+            dkim,
+        });
+    let webhooks = bounces::router(Arc::clone(&emails), bounces);
+    let protected = limits
+        .apply(Router::new().route("/", get(root)))
+        .merge(api_limits.apply(api::router(emails)))
+        .layer(middleware::from_fn_with_state(keys, auth::require_api_key));
+    limits
+        .apply(health)
+        .merge(limits.apply(webhooks))
+        .merge(protected)
+        .layer(middleware::from_fn(metrics::track_requests))
+}
+
 async fn root() -> Html<&'static str> {
     Html("<h1>Fathom to Loom SMTP Service</h1><p>Service is running!</p>")
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+#[derive(Clone)]
+struct Health {
+    emails: Arc<EmailService>,
+    dkim: Option<DkimStatus>,
+}
+
+/// GET /health - Up, with how the send rate limiter and each SMTP provider
+/// stand, the pending emails of each priority, and the DKIM selector email
+/// is signed under
+async fn health_check(State(health): State<Health>) -> Json<serde_json::Value> {
+    let queue = match health.emails.queue_depth().await {
+        Ok(depth) => Some(depth.into_iter().collect::<std::collections::BTreeMap<_, _>>()),
+        Err(e) => {
+            warn!("Failed to count the email queue for /health: {}", e);
+            None
+        }
+    };
+    Json(serde_json::json!({
+        "status": "ok",
+        "send_rate": health.emails.send_rate(),
+        "queue": queue,
+        "providers": health.emails.provider_status(),
+        "dkim": health.dkim,
+    }))
+}
+
+/// GET /metrics - every metric family in the Prometheus text format, the
+/// queue and provider gauges read afresh
+async fn get_metrics(State(health): State<Health>) -> Response {
+    if let Err(e) = health.emails.refresh_gauges().await {
+        warn!("Failed to count the email queue for /metrics: {}", e);
+    }
+    (
+        [(header::CONTENT_TYPE, EXPOSITION_CONTENT_TYPE)],
+        metrics::render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_pb, FakeTransport};
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{Method, StatusCode},
+    };
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_only_health_answers_without_a_key() {
+        let pb = mock_pb().await;
+        let emails = EmailService::new(
+            pb.client(),
+            Arc::new(FakeTransport::default()),
+            "smtp-a",
+        );
+        let limits = limits::Limits {
+            body_bytes: 1 << 20,
+            timeout: Duration::from_secs(5),
+        };
+        let keys = auth::ApiKeys::parse("backend:k-one").unwrap();
+        let bounces = Arc::new(bounces::BounceVerifier::new(None, Vec::new()));
+        let app = routes(Arc::new(emails), None, bounces, keys, limits, limits);
+        let status = |path: &str, method: Method, key: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(path);
+            if let Some(key) = key {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", key));
+            }
+            let request = request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"to_email":"alice@example.com","subject":"Hi","body_text":"Hi"}"#))
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status("/health", Method::GET, None).await, StatusCode::OK);
+        let request = Request::get("/health").body(Body::empty()).unwrap();
+        let body = axum::body::to_bytes(app.clone().oneshot(request).await.unwrap().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["send_rate"]["tokens_available"], 10);
+        assert_eq!(health["queue"], serde_json::json!({ "urgent": 0, "high": 0, "normal": 0, "low": 0 }));
+        assert_eq!(health["providers"][0]["name"], "default");
+        assert_eq!(health["providers"][0]["healthy"], true);
+        assert!(health["dkim"].is_null());
+        assert_eq!(status("/", Method::GET, None).await, StatusCode::UNAUTHORIZED);
+        // Reached without a key, but its signature is checked
+        assert_eq!(status("/webhooks/bounce", Method::POST, None).await, StatusCode::UNAUTHORIZED);
+        let suppression = status("/suppressions/alice@example.com", Method::GET, None).await;
+        assert_eq!(suppression, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/send-email", Method::POST, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/send-email", Method::POST, Some("k-two")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/send-email", Method::POST, Some("k-one")).await, StatusCode::ACCEPTED);
+        assert_eq!(pb.records(email_service::EMAIL_QUEUE).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_emails_through_the_queue_and_requests() {
+        let pb = mock_pb().await;
+        let transport = FakeTransport::default();
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::new(transport.clone()),
+            "smtp-a",
+        ));
+        let limits = limits::Limits {
+            body_bytes: 1 << 20,
+            timeout: Duration::from_secs(5),
+        };
+        let keys = auth::ApiKeys::parse("backend:k-one").unwrap();
+        let bounces = Arc::new(bounces::BounceVerifier::new(None, Vec::new()));
+        let app = routes(Arc::clone(&emails), None, bounces, keys, limits, limits);
+        for to_email in ["alice@metrics.example", "bob@metrics.example"] {
+            let request = Request::post("/send-email")
+                .header(header::AUTHORIZATION, "Bearer k-one")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "to_email": to_email, "subject": "Hi", "body_text": "Hi" }).to_string(),
+                ))
+                .unwrap();
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::ACCEPTED);
+        }
+        transport.fail_next(transport::SendError::Permanent("550 No such user".to_string()));
+        let processed = emails.process_queue().await.unwrap();
+        assert_eq!((processed.sent, processed.failed), (1, 1));
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], EXPOSITION_CONTENT_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let scrape = String::from_utf8(body.to_vec()).unwrap();
+        for family in [
+            "# TYPE smtp_emails_queued_total counter",
+            "# TYPE smtp_send_duration_seconds histogram",
+            "# TYPE smtp_queue_dwell_seconds histogram",
+            "# TYPE smtp_queue_emails gauge",
+        ] {
+            assert!(scrape.contains(family), "{} in\n{}", family, scrape);
+        }
+        // The registry is shared with every other test, so only this one's
+        // labels and gauges are certain
+        for sample in [
+            "smtp_emails_sent_total{provider=\"default\"} ",
+            "smtp_emails_failed_total{reason=\"rejected\"} ",
+            "smtp_send_duration_seconds_count{outcome=\"sent\"} ",
+            "smtp_queue_emails{status=\"sent\"} 1\n",
+            "smtp_queue_emails{status=\"failed\"} 1\n",
+            "smtp_queue_emails{status=\"pending\"} 0\n",
+            "smtp_queue_pending_emails{priority=\"normal\"} 0\n",
+            "smtp_provider_healthy{provider=\"default\"} 1\n",
+            "smtp_http_requests_total{method=\"POST\",route=\"/send-email\",status=\"202\"} ",
+        ] {
+            assert!(scrape.contains(sample), "{} in\n{}", sample, scrape);
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! SMTP service metrics in the Prometheus text format
+//!
+//! Every family the service exports is declared here and recorded into the
+//! shared [`common::metrics`] registry: counters and histograms by the
+//! [`EmailService`](crate::email_service::EmailService) as emails are queued
+//! and sent, and the queue and provider gauges are set from their current
+//! state on each scrape of `/metrics`. [`track_requests`] counts the
+//! service's own HTTP requests.
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+pub use common::metrics::{add, increment, observe, set};
+use common::metrics::{registry, Family, Kind};
+use std::time::Instant;
+
+pub const EMAILS_QUEUED: Family = Family {
+    name: "smtp_emails_queued_total",
+    kind: Kind::Counter,
+    help: "Emails queued, one by one or in batches",
+    buckets: &[],
+};
+
+pub const EMAILS_SENT: Family = Family {
+    name: "smtp_emails_sent_total",
+    kind: Kind::Counter,
+    help: "Emails sent, by the provider they went through",
+    buckets: &[],
+};
+
+pub const EMAILS_FAILED: Family = Family {
+    name: "smtp_emails_failed_total",
+    kind: Kind::Counter,
+    help: "Emails failed for good, by whether they were rejected or ran out of attempts",
+    buckets: &[],
+};
+
+pub const EMAILS_RETRIED: Family = Family {
+    name: "smtp_emails_retried_total",
+    kind: Kind::Counter,
+    help: "Failed sends put back in the queue to be tried again",
+    buckets: &[],
+};
+
+pub const EMAILS_SUPPRESSED: Family = Family {
+    name: "smtp_emails_suppressed_total",
+    kind: Kind::Counter,
+    help: "Emails to suppressed recipients, refused when queued or failed unsent when due",
+    buckets: &[],
+};
+
+pub const SEND_DURATION: Family = Family {
+    name: "smtp_send_duration_seconds",
+    kind: Kind::Histogram,
+    help: "Time taken handing emails to the providers, by outcome",
+    buckets: &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0],
+};
+
+pub const QUEUE_DWELL: Family = Family {
+    name: "smtp_queue_dwell_seconds",
+    kind: Kind::Histogram,
+    help: "Time from an email being queued to it being sent",
+    buckets: &[1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0, 21600.0, 86400.0],
+};
+
+pub const QUEUE_EMAILS: Family = Family {
+    name: "smtp_queue_emails",
+    kind: Kind::Gauge,
+    help: "Emails in the queue, by status",
+    buckets: &[],
+};
+
+pub const QUEUE_PENDING: Family = Family {
+    name: "smtp_queue_pending_emails",
+    kind: Kind::Gauge,
+    help: "Pending emails in the queue, by priority",
+    buckets: &[],
+};
+
+pub const PROVIDER_HEALTHY: Family = Family {
+    name: "smtp_provider_healthy",
+    kind: Kind::Gauge,
+    help: "Whether each SMTP provider is in use, 1, or out on probation, 0",
+    buckets: &[],
+};
+
+pub const PROVIDER_FAILURES: Family = Family {
+    name: "smtp_provider_failures",
+    kind: Kind::Gauge,
+    help: "Transient failures in a row of each SMTP provider",
+    buckets: &[],
+};
+
+pub const SEND_TOKENS: Family = Family {
+    name: "smtp_send_tokens_available",
+    kind: Kind::Gauge,
+    help: "Sends the rate limiter would let through now",
+    buckets: &[],
+};
+
+pub const SEND_BACKLOG: Family = Family {
+    name: "smtp_send_backlog",
+    kind: Kind::Gauge,
+    help: "Due emails the last pass left waiting for a send token",
+    buckets: &[],
+};
+
+pub const HTTP_REQUESTS: Family = Family {
+    name: "smtp_http_requests_total",
+    kind: Kind::Counter,
+    help: "HTTP requests handled, by method, matched route and status",
+    buckets: &[],
+};
+
+pub const HTTP_REQUEST_DURATION: Family = Family {
+    name: "smtp_http_request_duration_seconds",
+    kind: Kind::Histogram,
+    help: "Time taken to answer HTTP requests, by method and matched route",
+    buckets: &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+};
+
+/// Every family, in the order they're rendered
+const FAMILIES: &[&Family] = &[
+    &EMAILS_QUEUED,
+    &EMAILS_SENT,
+    &EMAILS_FAILED,
+    &EMAILS_RETRIED,
+    &EMAILS_SUPPRESSED,
+    &SEND_DURATION,
+    &QUEUE_DWELL,
+    &QUEUE_EMAILS,
+    &QUEUE_PENDING,
+    &PROVIDER_HEALTHY,
+    &PROVIDER_FAILURES,
+    &SEND_TOKENS,
+    &SEND_BACKLOG,
+    &HTTP_REQUESTS,
+    &HTTP_REQUEST_DURATION,
+];
+
+/// Every family in the Prometheus text exposition format
+pub fn render() -> String {
+    registry().render(FAMILIES)
+}
+
+/// Counts every request and its latency by method, matched route and status
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    // Raw paths would give every queue id its own series
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    increment(&HTTP_REQUESTS, &[("method", &method), ("route", &route), ("status", &status)]);
+    observe(
+        &HTTP_REQUEST_DURATION,
+        &[("method", &method), ("route", &route)],
+        started.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::metrics::Registry;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let registry = Registry::default();
+        registry.add(&EMAILS_QUEUED, &[], 2.0);
+        registry.add(&EMAILS_SENT, &[("provider", "primary")], 1.0);
+        registry.set(&QUEUE_EMAILS, &[("status", "pending")], 4.0);
+        registry.set(&QUEUE_EMAILS, &[("status", "pending")], 3.0);
+        registry.observe(&SEND_DURATION, &[("outcome", "sent")], 0.3);
+
+        let text = registry.render(FAMILIES);
+        assert!(text.contains("# TYPE smtp_emails_queued_total counter\n"));
+        assert!(text.contains("smtp_emails_queued_total 2\n"));
+        assert!(text.contains("smtp_emails_sent_total{provider=\"primary\"} 1\n"));
+        assert!(text.contains("smtp_queue_emails{status=\"pending\"} 3\n"));
+        assert!(text.contains("smtp_send_duration_seconds_bucket{outcome=\"sent\",le=\"0.25\"} 0\n"));
+        assert!(text.contains("smtp_send_duration_seconds_bucket{outcome=\"sent\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("smtp_send_duration_seconds_count{outcome=\"sent\"} 1\n"));
+        assert_eq!(registry.value(&SEND_DURATION, &[("outcome", "sent")]), 1.0);
+        // Families are declared even before anything is recorded
+        assert!(text.contains("# TYPE smtp_queue_dwell_seconds histogram\n"));
+    }
+}
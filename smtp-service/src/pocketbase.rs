@@ -0,0 +1,64 @@
+//! Admin client for the global PocketBase, where the email queue is kept
+//!
+//! The client itself is shared with the backend and worker in
+//! [`common::pocketbase`]; this finds the global PocketBase from the
+//! credentials the backend and worker also use.
+
+pub use common::pocketbase::{format_time, parse_time, quote, PbError, PocketBase, RecordPage};
+
+/// Where the global PocketBase is and how to sign in to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PbSettings {
+    pub url: String,
+    pub admin_email: String,
+    pub admin_password: String,
+}
+
+impl PbSettings {
+    /// Read the DATABASE_URL and PB_ADMIN_* variables shared with the backend
+    /// and worker, or their GLOBAL_PB_* names
+    pub fn from_env() -> Self {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |names: &[&str]| names.iter().find_map(|name| lookup(name));
+        Self {
+            url: var(&["DATABASE_URL", "GLOBAL_PB_URL"])
+                .unwrap_or_else(|| "http://pb_global:8090".to_string()),
+            admin_email: var(&["PB_ADMIN_EMAIL", "GLOBAL_PB_ADMIN_EMAIL"])
+                .unwrap_or_else(|| "admin@example.com".to_string()),
+            admin_password: var(&["PB_ADMIN_PASSWORD", "GLOBAL_PB_ADMIN_PW"]).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&PbSettings> for PocketBase {
+    fn from(settings: &PbSettings) -> Self {
+        PocketBase::new(&settings.url, &settings.admin_email, &settings.admin_password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_settings_take_the_backends_names_first() {
+        let vars = HashMap::from([
+            ("GLOBAL_PB_URL", "http://pb_global:8090"),
+            ("DATABASE_URL", "http://127.0.0.1:8090"),
+            ("GLOBAL_PB_ADMIN_PW", "secret"),
+        ]);
+        let settings = PbSettings::from_lookup(|var| vars.get(var).map(|value| value.to_string()));
+        assert_eq!(
+            settings,
+            PbSettings {
+                url: "http://127.0.0.1:8090".to_string(),
+                admin_email: "admin@example.com".to_string(),
+                admin_password: "secret".to_string(),
+            }
+        );
+    }
+}
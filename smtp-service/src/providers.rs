@@ -0,0 +1,310 @@
+//! Failing over between SMTP providers
+//!
+//! `SMTP_PROVIDERS` may list several providers, in the order they are
+//! preferred, and each email goes through the first that is healthy. A
+//! provider that fails `SMTP_PROVIDER_MAX_FAILURES` sends in a row, with a
+//! transient error (a 4xx answer or no connection), is taken out of use for
+//! `SMTP_PROVIDER_PROBATION_SECS`. After that it is tried again with the
+//! next email: a success puts it back in use, a failure out again for
+//! another probation. A permanent error is about the email, not the
+//! provider, so it is neither counted nor sent elsewhere.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{sync::Arc, sync::Mutex, time::Duration, time::Instant};
+use tracing::{info, warn};
+
+use crate::transport::{Endpoint, OutgoingEmail, SendError, Transport};
+
+/// When a failing provider is taken out of use, and for how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerPolicy {
+    /// Sends in a row a provider may fail before it is taken out of use
+    pub max_failures: u32,
+    /// How long it stays out before it is tried again
+    pub probation: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 3,
+            probation: Duration::from_secs(60),
+        }
+    }
+}
+
+impl BreakerPolicy {
+    /// Read SMTP_PROVIDER_MAX_FAILURES and SMTP_PROVIDER_PROBATION_SECS
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let defaults = Self::default();
+        let positive = |var: &str, unit: &str, default: u64| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(parsed),
+                _ => Err(format!("{} must be a positive number of {}, not '{}'", var, unit, value)),
+            },
+            None => Ok(default),
+        };
+        let failures = positive("SMTP_PROVIDER_MAX_FAILURES", "failures", defaults.max_failures as u64)?;
+        Ok(Self {
+            max_failures: u32::try_from(failures)
+                .map_err(|_| format!("SMTP_PROVIDER_MAX_FAILURES is too large: {}", failures))?,
+            probation: Duration::from_secs(positive(
+                "SMTP_PROVIDER_PROBATION_SECS",
+                "seconds",
+                defaults.probation.as_secs(),
+            )?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Health {
+    failures: u32,
+    /// Until when the provider is out of use, if it was taken out
+    out_until: Option<DateTime<Utc>>,
+}
+
+/// How one provider stands, for `/health`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProviderStatus {
+    pub name: String,
+    /// Whether emails go through it; one on probation is tried again at
+    /// `retry_at`
+    pub healthy: bool,
+    /// Transient failures in a row
+    pub failures: u32,
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+/// One provider's answer to a connection test
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionReport {
+    pub name: String,
+    pub connected: bool,
+    pub error: Option<String>,
+    /// How long connecting, securing the connection and signing in took
+    pub elapsed_ms: u64,
+    /// The server tested, if the provider is one
+    pub server: Option<Endpoint>,
+}
+
+struct Provider {
+    name: String,
+    transport: Arc<dyn Transport>,
+}
+
+/// The providers emails may go through, in the order they are preferred
+pub struct Providers {
+    providers: Vec<Provider>,
+    policy: BreakerPolicy,
+    health: Mutex<Vec<Health>>,
+}
+
+impl Providers {
+    /// Providers named as given, preferred in that order
+    pub fn new(providers: Vec<(String, Arc<dyn Transport>)>, policy: BreakerPolicy) -> Self {
+        Self {
+            health: Mutex::new(vec![Health::default(); providers.len()]),
+            providers: providers
+                .into_iter()
+                .map(|(name, transport)| Provider { name, transport })
+                .collect(),
+            policy,
+        }
+    }
+
+    /// `transport` alone, as the provider `default`
+    pub fn single(transport: Arc<dyn Transport>) -> Self {
+        Self::new(vec![("default".to_string(), transport)], BreakerPolicy::default())
+    }
+
+    /// Send `email` through the first provider in use that takes it,
+    /// returning that provider's name
+    pub async fn send(&self, email: &OutgoingEmail, now: DateTime<Utc>) -> Result<String, SendError> {
+        let mut last_error = None;
+        for (index, provider) in self.providers.iter().enumerate() {
+            let out_until = self.health.lock().unwrap()[index].out_until;
+            if out_until.is_some_and(|until| until > now) {
+                continue;
+            }
+            match provider.transport.send(email).await {
+                Ok(()) => {
+                    let mut health = self.health.lock().unwrap();
+                    if health[index].out_until.is_some() {
+                        info!(provider = %provider.name, "SMTP provider is back in use");
+                    }
+                    health[index] = Health::default();
+                    return Ok(provider.name.clone());
+                }
+                Err(e) if e.is_transient() => {
+                    self.failed(index, now, &e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            SendError::Transient("every SMTP provider is out of use until its probation ends".to_string())
+        }))
+    }
+
+    fn failed(&self, index: usize, now: DateTime<Utc>, error: &SendError) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        health.failures += 1;
+        let on_probation = health.out_until.is_some();
+        if on_probation || health.failures >= self.policy.max_failures {
+            let until = now + chrono::Duration::from_std(self.policy.probation).unwrap_or_default();
+            health.out_until = Some(until);
+            warn!(
+                provider = %self.providers[index].name,
+                failures = health.failures,
+                "SMTP provider taken out of use until {}: {}",
+                until,
+                error
+            );
+        }
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> Vec<ProviderStatus> {
+        let health = self.health.lock().unwrap();
+        self.providers
+            .iter()
+            .zip(health.iter())
+            .map(|(provider, health)| ProviderStatus {
+                name: provider.name.clone(),
+                healthy: health.out_until.is_none_or(|until| until <= now),
+                failures: health.failures,
+                retry_at: health.out_until.filter(|until| *until > now),
+            })
+            .collect()
+    }
+
+    /// Whether each provider can be connected to, asking each in turn
+    pub async fn test_connections(&self) -> Vec<ConnectionReport> {
+        let mut reports = Vec::with_capacity(self.providers.len());
+        for provider in &self.providers {
+            let started = Instant::now();
+            let result = provider.transport.test_connection().await;
+            reports.push(ConnectionReport {
+                name: provider.name.clone(),
+                connected: result.is_ok(),
+                error: result.err().map(|e| e.to_string()),
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                server: provider.transport.endpoint(),
+            });
+        }
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeTransport;
+    use std::collections::HashMap;
+
+    fn email() -> OutgoingEmail {
+        OutgoingEmail {
+            to_email: "alice@example.com".to_string(),
+            to_name: None,
+            subject: "Reset your password".to_string(),
+            body_text: "Open this link".to_string(),
+            body_html: String::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    fn two(primary: &FakeTransport, backup: &FakeTransport) -> Providers {
+        Providers::new(
+            vec![
+                ("primary".to_string(), Arc::new(primary.clone()) as Arc<dyn Transport>),
+                ("backup".to_string(), Arc::new(backup.clone())),
+            ],
+            BreakerPolicy {
+                max_failures: 2,
+                probation: Duration::from_secs(60),
+            },
+        )
+    }
+
+    fn at(start: DateTime<Utc>, seconds: i64) -> DateTime<Utc> {
+        start + chrono::Duration::seconds(seconds)
+    }
+
+    #[tokio::test]
+    async fn test_emails_fail_over_while_the_first_provider_is_failing() {
+        let (primary, backup) = (FakeTransport::default(), FakeTransport::default());
+        let providers = two(&primary, &backup);
+        let start = Utc::now();
+        assert_eq!(providers.send(&email(), start).await.unwrap(), "primary");
+
+        for _ in 0..2 {
+            primary.fail_next(SendError::Transient("421 Try again later".to_string()));
+        }
+        assert_eq!(providers.send(&email(), start).await.unwrap(), "backup");
+        assert!(providers.status(start)[0].healthy, "one failure doesn't take it out");
+        assert_eq!(providers.send(&email(), start).await.unwrap(), "backup");
+        let status = providers.status(start);
+        assert!(!status[0].healthy && status[1].healthy);
+        assert_eq!(status[0].retry_at, Some(at(start, 60)));
+
+        // Out of use, the primary isn't even asked
+        assert_eq!(providers.send(&email(), at(start, 59)).await.unwrap(), "backup");
+        assert_eq!((primary.sent().len(), backup.sent().len()), (1, 3));
+
+        // Nowhere else is a permanent failure sent
+        primary.fail_next(SendError::Permanent("550 No such user".to_string()));
+        let rejected = providers.send(&email(), at(start, 60)).await.unwrap_err();
+        assert!(!rejected.is_transient());
+        assert_eq!(backup.sent().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_a_provider_is_back_in_use_once_a_send_after_probation_goes_through() {
+        let (primary, backup) = (FakeTransport::default(), FakeTransport::default());
+        let providers = two(&primary, &backup);
+        let start = Utc::now();
+        for _ in 0..3 {
+            primary.fail_next(SendError::Transient("connection refused".to_string()));
+        }
+        providers.send(&email(), start).await.unwrap();
+        providers.send(&email(), start).await.unwrap();
+
+        // Failing on probation puts it straight back out
+        assert_eq!(providers.send(&email(), at(start, 60)).await.unwrap(), "backup");
+        assert_eq!(providers.status(at(start, 60))[0].retry_at, Some(at(start, 120)));
+
+        assert_eq!(providers.send(&email(), at(start, 120)).await.unwrap(), "primary");
+        let status = providers.status(at(start, 120));
+        assert!(status[0].healthy);
+        assert_eq!((status[0].failures, status[0].retry_at), (0, None));
+
+        // With every provider out, the email waits to be tried again
+        for transport in [&primary, &backup] {
+            for _ in 0..2 {
+                transport.fail_next(SendError::Transient("421 Try again later".to_string()));
+            }
+        }
+        for _ in 0..2 {
+            assert!(providers.send(&email(), at(start, 120)).await.unwrap_err().is_transient());
+        }
+        let waiting = providers.send(&email(), at(start, 121)).await.unwrap_err();
+        assert!(waiting.to_string().contains("every SMTP provider is out of use"));
+    }
+
+    #[test]
+    fn test_the_breaker_comes_from_the_environment() {
+        let vars = HashMap::from([("SMTP_PROVIDER_MAX_FAILURES", "5")]);
+        let policy = BreakerPolicy::from_lookup(|var| vars.get(var).map(|value| value.to_string())).unwrap();
+        assert_eq!(policy.max_failures, 5);
+        assert_eq!(policy.probation, BreakerPolicy::default().probation);
+        let vars = HashMap::from([("SMTP_PROVIDER_PROBATION_SECS", "soon")]);
+        assert!(BreakerPolicy::from_lookup(|var| vars.get(var).map(|value| value.to_string())).is_err());
+    }
+}
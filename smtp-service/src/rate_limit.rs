@@ -0,0 +1,278 @@
+//! Pacing of the emails the service sends
+//!
+//! SMTP providers suspend accounts that send in bursts, so each email takes
+//! a token from the [`SendLimiter`] before it is sent. Its bucket holds up
+//! to `EMAIL_SEND_BURST` tokens, refilled at `EMAIL_SEND_PER_MINUTE`, and
+//! with `EMAIL_DOMAIN_SEND_PER_MINUTE` set every recipient domain has a
+//! bucket of its own too, so one mail host isn't hammered. An email without
+//! a token is never refused: it stays pending for a later pass.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
+
+/// Emails a bucket lets through: `burst` at once, `per_minute` over time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendRate {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl SendRate {
+    fn per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendLimits {
+    pub global: SendRate,
+    /// Each recipient domain's own rate, if domains are limited
+    pub per_domain: Option<SendRate>,
+}
+
+impl Default for SendLimits {
+    fn default() -> Self {
+        Self {
+            global: SendRate {
+                per_minute: 60,
+                burst: 10,
+            },
+            per_domain: None,
+        }
+    }
+}
+
+impl SendLimits {
+    /// Read EMAIL_SEND_PER_MINUTE, EMAIL_SEND_BURST,
+    /// EMAIL_DOMAIN_SEND_PER_MINUTE and EMAIL_DOMAIN_SEND_BURST
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let positive = |var: &str| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(Some(parsed)),
+                _ => Err(format!("{} must be a positive number of emails, not '{}'", var, value)),
+            },
+            None => Ok(None),
+        };
+        let defaults = Self::default().global;
+        let global = SendRate {
+            per_minute: positive("EMAIL_SEND_PER_MINUTE")?.unwrap_or(defaults.per_minute),
+            burst: positive("EMAIL_SEND_BURST")?.unwrap_or(defaults.burst),
+        };
+        let per_domain = match positive("EMAIL_DOMAIN_SEND_PER_MINUTE")? {
+            Some(per_minute) => Some(SendRate {
+                per_minute,
+                burst: positive("EMAIL_DOMAIN_SEND_BURST")?.unwrap_or(1),
+            }),
+            None => None,
+        };
+        Ok(Self { global, per_domain })
+    }
+}
+
+/// Why an email has to wait for a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Held {
+    /// No email may be sent until the global bucket refills
+    Global,
+    /// Only emails to this recipient's domain have to wait
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    /// Top up for the time since the last refill, returning the tokens
+    fn refill(&mut self, rate: &SendRate, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - self.refilled_at).to_std().unwrap_or_default().as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second()).min(rate.burst as f64);
+        self.refilled_at = now.max(self.refilled_at);
+        self.tokens
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Made full on first use
+    global: Option<Bucket>,
+    /// Domains whose bucket isn't full; a missing one is full
+    domains: HashMap<String, Bucket>,
+    backlog: usize,
+}
+
+/// How the limiter stands, for `/health`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LimiterState {
+    pub per_minute: u32,
+    pub burst: u32,
+    /// Emails that could be sent right now
+    pub tokens_available: u32,
+    /// Recipient domains currently held below their burst
+    pub domains_limited: usize,
+    /// Due emails the last pass over the queue left waiting for a token
+    pub backlog: usize,
+}
+
+/// Tokens for sending, shared by everything that sends in the process
+#[derive(Debug)]
+pub struct SendLimiter {
+    limits: SendLimits,
+    state: Mutex<State>,
+}
+
+impl SendLimiter {
+    pub fn new(limits: SendLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Take a token to send to `to_email`, from the global bucket and its
+    /// domain's, or neither
+    pub fn take(&self, to_email: &str, now: DateTime<Utc>) -> Result<(), Held> {
+        let mut state = self.state.lock().unwrap();
+        if self.global(&mut state, now).tokens < 1.0 {
+            return Err(Held::Global);
+        }
+        if let Some(rate) = &self.limits.per_domain {
+            let domain = state.domains.entry(domain(to_email)).or_insert(Bucket {
+                tokens: rate.burst as f64,
+                refilled_at: now,
+            });
+            if domain.refill(rate, now) < 1.0 {
+                return Err(Held::Domain);
+            }
+            domain.tokens -= 1.0;
+        }
+        if let Some(global) = &mut state.global {
+            global.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Return the token taken for an email that wasn't sent after all
+    pub fn give_back(&self, to_email: &str, now: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        let global = self.global(&mut state, now);
+        global.tokens = (global.tokens + 1.0).min(self.limits.global.burst as f64);
+        let bucket = state.domains.get_mut(&domain(to_email));
+        if let (Some(rate), Some(bucket)) = (&self.limits.per_domain, bucket) {
+            bucket.tokens = (bucket.tokens + 1.0).min(rate.burst as f64);
+        }
+    }
+
+    /// Note how many due emails a pass left waiting
+    pub fn note_backlog(&self, backlog: usize) {
+        self.state.lock().unwrap().backlog = backlog;
+    }
+
+    pub fn state(&self, now: DateTime<Utc>) -> LimiterState {
+        let mut state = self.state.lock().unwrap();
+        let tokens_available = self.global(&mut state, now).tokens;
+        if let Some(rate) = &self.limits.per_domain {
+            state.domains.retain(|_, bucket| bucket.refill(rate, now) < rate.burst as f64);
+        }
+        LimiterState {
+            per_minute: self.limits.global.per_minute,
+            burst: self.limits.global.burst,
+            tokens_available: tokens_available.floor() as u32,
+            domains_limited: state.domains.len(),
+            backlog: state.backlog,
+        }
+    }
+
+    fn global<'a>(&self, state: &'a mut State, now: DateTime<Utc>) -> &'a mut Bucket {
+        let rate = &self.limits.global;
+        let bucket = state.global.get_or_insert(Bucket {
+            tokens: rate.burst as f64,
+            refilled_at: now,
+        });
+        bucket.refill(rate, now);
+        bucket
+    }
+}
+
+fn domain(to_email: &str) -> String {
+    to_email.rsplit('@').next().unwrap_or_default().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn at(start: DateTime<Utc>, seconds: i64) -> DateTime<Utc> {
+        start + chrono::Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn test_sends_are_capped_at_the_rate_after_the_burst() {
+        let limiter = SendLimiter::new(SendLimits {
+            global: SendRate {
+                per_minute: 60,
+                burst: 3,
+            },
+            per_domain: None,
+        });
+        let start = Utc::now();
+        let sent = |now| (0..10).filter(|_| limiter.take("alice@example.com", now).is_ok()).count();
+        assert_eq!(sent(start), 3);
+        assert_eq!(limiter.take("bob@example.org", start), Err(Held::Global));
+        assert_eq!(sent(at(start, 1)), 1);
+        // Sixty a minute, however long the wait
+        assert_eq!((2..=61).map(|second| sent(at(start, second))).sum::<usize>(), 60);
+        assert_eq!(sent(at(start, 3600)), 3, "no more than the burst builds up");
+
+        limiter.give_back("alice@example.com", at(start, 3600));
+        assert_eq!(limiter.state(at(start, 3600)).tokens_available, 1);
+    }
+
+    #[test]
+    fn test_each_domain_has_its_own_rate() {
+        let limiter = SendLimiter::new(SendLimits {
+            global: SendRate {
+                per_minute: 600,
+                burst: 100,
+            },
+            per_domain: Some(SendRate {
+                per_minute: 2,
+                burst: 1,
+            }),
+        });
+        let start = Utc::now();
+        assert_eq!(limiter.take("alice@example.com", start), Ok(()));
+        assert_eq!(limiter.take("bob@EXAMPLE.com", start), Err(Held::Domain));
+        assert_eq!(limiter.take("carol@example.org", start), Ok(()));
+        let state = limiter.state(start);
+        assert_eq!((state.tokens_available, state.domains_limited), (98, 2));
+
+        assert_eq!(limiter.take("bob@example.com", at(start, 29)), Err(Held::Domain));
+        assert_eq!(limiter.take("bob@example.com", at(start, 30)), Ok(()));
+        assert_eq!(limiter.state(at(start, 90)).domains_limited, 0);
+    }
+
+    #[test]
+    fn test_limits_come_from_the_environment() {
+        let lookup = |vars: HashMap<&'static str, &'static str>| {
+            SendLimits::from_lookup(move |var| vars.get(var).map(|value| value.to_string()))
+        };
+        assert_eq!(lookup(HashMap::new()), Ok(SendLimits::default()));
+        let limits = lookup(HashMap::from([
+            ("EMAIL_SEND_PER_MINUTE", "120"),
+            ("EMAIL_DOMAIN_SEND_PER_MINUTE", "10"),
+        ]))
+        .unwrap();
+        assert_eq!(limits.global, SendRate { per_minute: 120, burst: 10 });
+        assert_eq!(limits.per_domain, Some(SendRate { per_minute: 10, burst: 1 }));
+        assert!(lookup(HashMap::from([("EMAIL_SEND_BURST", "0")])).is_err());
+    }
+}
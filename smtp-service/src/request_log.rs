@@ -0,0 +1,144 @@
+//! Per-request logging, matching the backend's `request` spans
+//!
+//! Each request gets a span with its method, path, matched route and request
+//! id, the label of the API key it bore once [`crate::auth`] has checked it,
+//! and one `request completed` event with the status and latency, at the
+//! level configured for its status class.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Level};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "cookie", "set-cookie", "x-api-key"];
+
+/// Completion levels for 2xx/3xx, 4xx and 5xx responses
+#[derive(Debug, Clone, Copy)]
+pub struct Levels {
+    pub success: Level,
+    pub client_error: Level,
+    pub server_error: Level,
+}
+
+impl Levels {
+    /// Read the REQUEST_LOG_*_LEVEL variables shared with the backend
+    pub fn from_env() -> Result<Self, String> {
+        let level = |var: &str, default: Level| match std::env::var(var) {
+            Ok(value) => value.trim().parse().map_err(|_| {
+                format!(
+                    "{} must be one of trace, debug, info, warn or error, not '{}'",
+                    var, value
+                )
+            }),
+            Err(_) => Ok(default),
+        };
+        Ok(Self {
+            success: level("REQUEST_LOG_SUCCESS_LEVEL", Level::INFO)?,
+            client_error: level("REQUEST_LOG_CLIENT_ERROR_LEVEL", Level::WARN)?,
+            server_error: level("REQUEST_LOG_SERVER_ERROR_LEVEL", Level::ERROR)?,
+        })
+    }
+
+    fn for_status(&self, status: u16) -> Level {
+        match status {
+            500.. => self.server_error,
+            400..=499 => self.client_error,
+            _ => self.success,
+        }
+    }
+}
+
+pub async fn log_requests(State(levels): State<Levels>, request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        route = field::Empty,
+        request_id = %request_id,
+        api_key = field::Empty,
+    );
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        span.record("route", route.as_str());
+    }
+
+    let started = Instant::now();
+    async move {
+        debug!(headers = %redacted_headers(request.headers()), "request started");
+        let mut response = next.run(request).await;
+
+        let status = response.status().as_u16();
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match levels.for_status(status) {
+            Level::ERROR => error!(status, latency_ms, "request completed"),
+            Level::WARN => warn!(status, latency_ms, "request completed"),
+            Level::INFO => info!(status, latency_ms, "request completed"),
+            Level::DEBUG => debug!(status, latency_ms, "request completed"),
+            _ => trace!(status, latency_ms, "request completed"),
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+fn redacted_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                "[redacted]"
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_are_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("accept", HeaderValue::from_static("text/html"));
+        assert_eq!(
+            redacted_headers(&headers),
+            "authorization: [redacted], accept: text/html"
+        );
+    }
+
+    #[test]
+    fn test_levels_by_status_class() {
+        let levels = Levels {
+            success: Level::DEBUG,
+            client_error: Level::WARN,
+            server_error: Level::ERROR,
+        };
+        assert_eq!(levels.for_status(204), Level::DEBUG);
+        assert_eq!(levels.for_status(429), Level::WARN);
+        assert_eq!(levels.for_status(502), Level::ERROR);
+    }
+}
@@ -0,0 +1,103 @@
+//! When an email that failed to send is tried again
+//!
+//! A transient failure, a 4xx answer or a connection that didn't go through,
+//! puts the email back as `pending` with a `next_attempt_at` that doubles
+//! with each attempt up to `EMAIL_RETRY_MAX_SECS`. Once `EMAIL_MAX_ATTEMPTS`
+//! attempts have failed, or on the first permanent failure, it is `failed`.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts an email gets, the first included
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt
+    pub base_delay: Duration,
+    /// Longest wait after any failed attempt
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Read EMAIL_MAX_ATTEMPTS, EMAIL_RETRY_BASE_SECS and EMAIL_RETRY_MAX_SECS
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let defaults = Self::default();
+        let positive = |var: &str, unit: &str, default: u64| match lookup(var) {
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) if parsed > 0 => Ok(parsed),
+                _ => Err(format!("{} must be a positive number of {}, not '{}'", var, unit, value)),
+            },
+            None => Ok(default),
+        };
+        let attempts = positive("EMAIL_MAX_ATTEMPTS", "attempts", defaults.max_attempts as u64)?;
+        let max_attempts = u32::try_from(attempts)
+            .map_err(|_| format!("EMAIL_MAX_ATTEMPTS is too large: {}", attempts))?;
+        Ok(Self {
+            max_attempts,
+            base_delay: Duration::from_secs(positive(
+                "EMAIL_RETRY_BASE_SECS",
+                "seconds",
+                defaults.base_delay.as_secs(),
+            )?),
+            max_delay: Duration::from_secs(positive(
+                "EMAIL_RETRY_MAX_SECS",
+                "seconds",
+                defaults.max_delay.as_secs(),
+            )?),
+        })
+    }
+
+    /// The wait after failed attempt number `attempt` (counting from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.base_delay
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// Whether an email may be tried again after `attempts` failed
+    pub fn allows_another(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_delays_double_up_to_the_ceiling() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(300),
+        };
+        let delays: Vec<u64> = (1..=6).map(|attempt| policy.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 300, 300]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(300));
+        assert!(policy.allows_another(2));
+        assert!(!policy.allows_another(3));
+
+        let vars = HashMap::from([("EMAIL_MAX_ATTEMPTS", "8"), ("EMAIL_RETRY_BASE_SECS", "5")]);
+        let policy = RetryPolicy::from_lookup(|var| vars.get(var).map(|value| value.to_string())).unwrap();
+        assert_eq!((policy.max_attempts, policy.base_delay.as_secs()), (8, 5));
+        assert_eq!(policy.max_delay, RetryPolicy::default().max_delay);
+        let vars = HashMap::from([("EMAIL_MAX_ATTEMPTS", "0")]);
+        assert!(RetryPolicy::from_lookup(|var| vars.get(var).map(|value| value.to_string())).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Stopping the service without stranding emails
+//!
+//! On Ctrl+C or SIGTERM the listener stops accepting connections and the
+//! [`EmailService`] is stopped, so it takes no more emails and its queue
+//! processor claims none, while the requests and sends under way get up to
+//! `SHUTDOWN_GRACE_SECS` to finish. A send still going after that is cut
+//! short and its email put back as pending, for the next processor to send.
+
+use axum::Router;
+use std::{future::Future, io, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+use tracing::{error, info, warn};
+
+use crate::email_service::EmailService;
+
+/// Resolves on Ctrl+C or SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received");
+}
+
+/// Serve `app` on `listener` until `signal` resolves, then drain, returning
+/// whether the requests and `processor`'s sends under way finished within
+/// `grace`
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    emails: Arc<EmailService>,
+    processor: JoinHandle<()>,
+    grace: Duration,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<bool> {
+    let (draining_tx, draining_rx) = oneshot::channel();
+    let stopping = Arc::clone(&emails);
+    let draining = async move {
+        signal.await;
+        stopping.stop();
+        let _ = draining_tx.send(());
+    };
+    let mut server =
+        tokio::spawn(async move { axum::serve(listener, app).with_graceful_shutdown(draining).await });
+
+    tokio::select! {
+        served = &mut server => {
+            // The listener failed without being asked to stop
+            emails.stop();
+            finish_sends(&emails, processor, grace).await;
+            served.map_err(io::Error::other)??;
+            return Ok(false);
+        }
+        _ = draining_rx => {}
+    }
+    info!("Draining: finishing the requests and sends under way");
+    let requests = async {
+        match tokio::time::timeout(grace, &mut server).await {
+            Ok(served) => served.map_err(io::Error::other)?.map(|()| true),
+            Err(_) => {
+                warn!("Requests still running after {}s, dropping them", grace.as_secs());
+                server.abort();
+                Ok(false)
+            }
+        }
+    };
+    let (requests, sends) = tokio::join!(requests, finish_sends(&emails, processor, grace));
+    Ok(requests? && sends)
+}
+
+/// Wait up to `grace` for `processor` to finish its pass, then put back any
+/// email it was still sending; returns whether it finished
+async fn finish_sends(emails: &EmailService, mut processor: JoinHandle<()>, grace: Duration) -> bool {
+    let finished = match tokio::time::timeout(grace, &mut processor).await {
+        Ok(_) => true,
+        Err(_) => {
+            warn!("Emails still sending after {}s, cutting them short", grace.as_secs());
+            processor.abort();
+            let _ = processor.await;
+            false
+        }
+    };
+    match emails.put_back_unsent().await {
+        Ok(0) => {}
+        Ok(put_back) => info!(put_back, "Put emails whose send was cut short back in the queue"),
+        Err(e) => error!("Failed to put unsent emails back in the queue: {}", e),
+    }
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api,
+        email_service::{self, QueueError, SendEmailRequest, EMAIL_QUEUE},
+        test_support::{mock_pb, MockPb, SlowTransport},
+    };
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, StatusCode},
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn request(to_email: &str) -> SendEmailRequest {
+        SendEmailRequest {
+            to_email: to_email.to_string(),
+            subject: Some("Your meeting is on Loom".to_string()),
+            body_text: "It's ready".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The service serving on a local port, and what stops it
+    struct Running {
+        addr: SocketAddr,
+        emails: Arc<EmailService>,
+        signal: oneshot::Sender<()>,
+        served: JoinHandle<io::Result<bool>>,
+    }
+
+    /// Serve with one email queued, once `transport` has begun sending it
+    async fn sending(pb: &MockPb, transport: &Arc<SlowTransport>, grace: Duration) -> (Running, String) {
+        let emails = Arc::new(EmailService::new(
+            pb.client(),
+            Arc::clone(transport) as _,
+            "smtp-a",
+        ));
+        let queue_id = emails.queue_email(&request("alice@example.com")).await.unwrap();
+        let processor = tokio::spawn(email_service::process_email_queue(Arc::clone(&emails)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (signal, signalled) = oneshot::channel();
+        let served = tokio::spawn(serve(
+            listener,
+            api::router(Arc::clone(&emails)),
+            Arc::clone(&emails),
+            processor,
+            grace,
+            async move {
+                let _ = signalled.await;
+            },
+        ));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while transport.sending() == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the email is being sent");
+        let running = Running {
+            addr,
+            emails,
+            signal,
+            served,
+        };
+        (running, queue_id)
+    }
+
+    #[tokio::test]
+    async fn test_a_send_under_way_at_shutdown_finishes_within_the_grace() {
+        let pb = mock_pb().await;
+        let transport = Arc::new(SlowTransport::taking(Duration::from_millis(300)));
+        let (running, queue_id) = sending(&pb, &transport, Duration::from_secs(5)).await;
+
+        running.signal.send(()).unwrap();
+        assert!(running.served.await.unwrap().unwrap(), "drained in time");
+        assert_eq!(transport.sent(), 1);
+        assert_eq!(pb.record(EMAIL_QUEUE, &queue_id).unwrap()["status"], "sent");
+    }
+
+    #[tokio::test]
+    async fn test_a_send_outlasting_the_grace_is_put_back_as_pending() {
+        let pb = mock_pb().await;
+        let transport = Arc::new(SlowTransport::taking(Duration::from_secs(60)));
+        let (running, queue_id) = sending(&pb, &transport, Duration::from_millis(200)).await;
+
+        running.signal.send(()).unwrap();
+        assert!(!running.served.await.unwrap().unwrap(), "the send was cut short");
+        assert_eq!(transport.sent(), 0);
+        let record = pb.record(EMAIL_QUEUE, &queue_id).unwrap();
+        assert_eq!(record["status"], "pending");
+        assert_eq!(record["last_error"], "The service stopped before the email was sent");
+        // At the version it was claimed at, so the next processor can claim it
+        assert_eq!(record["version"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_new_work_is_taken_while_draining() {
+        let pb = mock_pb().await;
+        let transport = Arc::new(SlowTransport::taking(Duration::from_millis(500)));
+        let (running, _) = sending(&pb, &transport, Duration::from_secs(5)).await;
+        assert!(tokio::net::TcpStream::connect(running.addr).await.is_ok());
+
+        running.signal.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(transport.sending(), 1, "still draining");
+        assert!(tokio::net::TcpStream::connect(running.addr).await.is_err());
+        let refused = running.emails.queue_email(&request("bob@example.com")).await.unwrap_err();
+        assert!(matches!(refused, QueueError::ShuttingDown));
+        let response = api::router(Arc::clone(&running.emails))
+            .oneshot(
+                Request::post("/send-email")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"to_email":"bob@example.com","subject":"Hi","body_text":"Hi"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(running.served.await.unwrap().unwrap());
+        assert_eq!(pb.records(EMAIL_QUEUE).len(), 1);
+    }
+}
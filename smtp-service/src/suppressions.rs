@@ -0,0 +1,142 @@
+//! Addresses the service no longer sends to
+//!
+//! A hard bounce or a complaint reported through `/webhooks/bounce` puts its
+//! recipient on the `suppression_list`, one record per lower-cased address.
+//! Emails to a suppressed address are refused when queued, and ones already
+//! queued are failed as `suppressed` rather than sent; only an admin taking
+//! the address off with `DELETE /suppressions/:email` lets mail through
+//! again.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use tracing::info;
+
+use crate::pocketbase::{parse_time, quote, PbError, PocketBase};
+
+pub const SUPPRESSION_LIST: &str = "suppression_list";
+
+/// Addresses looked up per request when checking many
+const LOOKUP_CHUNK: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    /// The address hard-bounced: it doesn't exist or never accepts mail
+    Bounce,
+    /// The recipient marked an email as spam
+    Complaint,
+}
+
+/// What a provider reported about one address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub email: String,
+    pub reason: SuppressionReason,
+    /// What the provider said, such as a bounce's diagnostic code
+    pub detail: String,
+}
+
+/// An address on the suppression list
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suppression {
+    #[serde(skip)]
+    pub id: String,
+    pub email: String,
+    pub reason: SuppressionReason,
+    pub detail: String,
+    /// The webhook format that reported it
+    pub source: String,
+    pub suppressed_at: Option<DateTime<Utc>>,
+}
+
+impl Suppression {
+    fn from_record(record: &Value) -> Result<Self, String> {
+        let text = |field: &str| record.get(field).and_then(Value::as_str).unwrap_or_default();
+        Ok(Self {
+            id: text("id").to_string(),
+            email: text("email").to_string(),
+            reason: serde_json::from_value(record.get("reason").cloned().unwrap_or_default())
+                .map_err(|e| format!("Suppression of '{}' has an unknown reason: {}", text("email"), e))?,
+            detail: text("detail").to_string(),
+            source: text("source").to_string(),
+            suppressed_at: parse_time(text("created")),
+        })
+    }
+}
+
+/// `email` as the suppression list keeps it
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// The suppression of `email`, if it is suppressed
+pub async fn find(pb: &PocketBase, email: &str) -> Result<Option<Suppression>, PbError> {
+    let filter = format!("email = {}", quote(&normalize(email)));
+    let records = pb.list(SUPPRESSION_LIST, &filter, "", 1).await?;
+    records
+        .first()
+        .map(Suppression::from_record)
+        .transpose()
+        .map_err(PbError::Request)
+}
+
+/// Which of `emails` are suppressed, normalized
+pub async fn among<'a>(
+    pb: &PocketBase,
+    emails: impl IntoIterator<Item = &'a str>,
+) -> Result<HashSet<String>, PbError> {
+    let emails: Vec<String> = emails
+        .into_iter()
+        .map(normalize)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut suppressed = HashSet::new();
+    for chunk in emails.chunks(LOOKUP_CHUNK) {
+        let terms: Vec<String> = chunk
+            .iter()
+            .map(|email| format!("email = {}", quote(email)))
+            .collect();
+        let filter = format!("({})", terms.join(" || "));
+        for record in pb
+            .list(SUPPRESSION_LIST, &filter, "", LOOKUP_CHUNK as u32)
+            .await?
+        {
+            if let Some(email) = record.get("email").and_then(Value::as_str) {
+                suppressed.insert(email.to_string());
+            }
+        }
+    }
+    Ok(suppressed)
+}
+
+/// Put `report`'s address on the list, returning whether it wasn't already
+pub async fn add(pb: &PocketBase, report: &Report, source: &str) -> Result<bool, PbError> {
+    let record = json!({
+        "email": normalize(&report.email),
+        "reason": report.reason,
+        "detail": report.detail.chars().take(1000).collect::<String>(),
+        "source": source,
+    });
+    match pb.create_record(SUPPRESSION_LIST, &record).await {
+        Ok(_) => {
+            let email = normalize(&report.email);
+            info!(target: "audit", email = %email, reason = ?report.reason, source, "Address suppressed");
+            Ok(true)
+        }
+        Err(e) if e.is_not_unique() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Take `email` off the list, returning the suppression it had
+pub async fn remove(pb: &PocketBase, email: &str) -> Result<Option<Suppression>, PbError> {
+    let Some(suppression) = find(pb, email).await? else {
+        return Ok(None);
+    };
+    pb.delete_record(SUPPRESSION_LIST, &suppression.id).await?;
+    info!(target: "audit", email = %suppression.email, "Address taken off the suppression list");
+    Ok(Some(suppression))
+}
@@ -0,0 +1,395 @@
+//! Emails rendered from the templates in `smtp-service/templates`
+//!
+//! A `/send-email` caller may name a `template` and give its
+//! `template_data` instead of writing the subject and bodies. Each template
+//! has an HTML and a text variant, wrapped in the shared layout, and a
+//! subject. They are written in the part of Handlebars the emails need:
+//! `{{name}}` inserts a value, HTML-escaped in the HTML variant, and
+//! `{{#if name}}...{{else}}...{{/if}}` keeps one branch by whether the value
+//! is truthy as Handlebars sees it. `{{{body}}}` places the rendered
+//! template in the layout unescaped; no template takes raw data that way.
+//!
+//! Data that is missing a template's required values, or gives a link that
+//! isn't http(s), is refused with every problem listed.
+
+use serde_json::{Map, Value};
+
+/// One template, as `/send-email` names it
+pub struct Template {
+    pub name: &'static str,
+    subject: &'static str,
+    html: &'static str,
+    text: &'static str,
+    pub required: &'static [&'static str],
+}
+
+/// Values inserted into an `href`, which must be http(s) URLs
+const LINKS: &[&str] = &["link", "loom_url"];
+
+/// Every template a caller may name
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "failure_notice",
+        subject: "A meeting couldn't be moved from Fathom to Loom",
+        html: include_str!("../templates/failure_notice.html.hbs"),
+        text: include_str!("../templates/failure_notice.txt.hbs"),
+        required: &["topic", "stage", "problem", "link"],
+    },
+    Template {
+        name: "success_notice",
+        subject: "\"{{topic}}\" is on Loom",
+        html: include_str!("../templates/success_notice.html.hbs"),
+        text: include_str!("../templates/success_notice.txt.hbs"),
+        required: &["topic", "loom_url"],
+    },
+    Template {
+        name: "verify_email",
+        subject: "Confirm your Fathom to Loom email address",
+        html: include_str!("../templates/verify_email.html.hbs"),
+        text: include_str!("../templates/verify_email.txt.hbs"),
+        required: &["link", "hours"],
+    },
+    Template {
+        name: "key_expiry",
+        subject: "Your {{service}} API key {{status}}",
+        html: include_str!("../templates/key_expiry.html.hbs"),
+        text: include_str!("../templates/key_expiry.txt.hbs"),
+        required: &["service", "key_id", "status", "link"],
+    },
+    Template {
+        name: "smtp_test",
+        subject: "[Test] Fathom to Loom SMTP check",
+        html: include_str!("../templates/smtp_test.html.hbs"),
+        text: include_str!("../templates/smtp_test.txt.hbs"),
+        required: &["sent_at"],
+    },
+    Template {
+        name: "generic",
+        subject: "{{subject}}",
+        html: include_str!("../templates/generic.html.hbs"),
+        text: include_str!("../templates/generic.txt.hbs"),
+        required: &["subject", "message"],
+    },
+];
+
+const LAYOUT_HTML: &str = include_str!("../templates/layout.html.hbs");
+const LAYOUT_TEXT: &str = include_str!("../templates/layout.txt.hbs");
+
+/// A template's subject and bodies for some data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub subject: String,
+    pub body_html: String,
+    pub body_text: String,
+}
+
+pub fn find(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// Render template `name` with `data`, or list what keeps it from rendering
+pub fn render(name: &str, data: &Value) -> Result<Rendered, Vec<String>> {
+    let Some(template) = find(name) else {
+        let names: Vec<&str> = TEMPLATES.iter().map(|template| template.name).collect();
+        return Err(vec![format!(
+            "unknown template '{}'; use one of {}",
+            name,
+            names.join(", ")
+        )]);
+    };
+    let empty = Map::new();
+    let data = match data {
+        Value::Object(data) => data,
+        Value::Null => &empty,
+        _ => return Err(vec!["template_data must be an object".to_string()]),
+    };
+    let mut problems: Vec<String> = template
+        .required
+        .iter()
+        .filter(|field| !present(data.get(**field)))
+        .map(|field| format!("{} needs template_data.{}", template.name, field))
+        .collect();
+    for field in LINKS {
+        let link = data.get(*field).map(display).unwrap_or_default();
+        let web = link.starts_with("https://") || link.starts_with("http://");
+        if !link.is_empty() && !web {
+            problems.push(format!("template_data.{} must be an http(s) URL", field));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    // A subject is a header, so it may not run onto a second line
+    let subject = fill(template.subject, data, false)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut layout = Map::new();
+    layout.insert("subject".to_string(), Value::String(subject.clone()));
+    layout.insert("body".to_string(), Value::String(fill(template.html, data, true)));
+    let body_html = fill(LAYOUT_HTML, &layout, true);
+    layout.insert("body".to_string(), Value::String(fill(template.text, data, false)));
+    let body_text = fill(LAYOUT_TEXT, &layout, false);
+    Ok(Rendered {
+        subject,
+        body_html,
+        body_text,
+    })
+}
+
+/// Whether a required value was given: `false` and `0` were, blanks weren't
+fn present(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => false,
+        Some(Value::String(text)) => !text.trim().is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Handlebars' falsy values are `false`, `null`, `""`, `0` and `[]`
+fn truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::String(text)) => !text.is_empty(),
+        Some(Value::Number(number)) => number.as_f64() != Some(0.0),
+        Some(Value::Array(items)) => !items.is_empty(),
+        Some(_) => true,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Escaped as Handlebars escapes, so a value can't leave an attribute
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            '`' => escaped.push_str("&#x60;"),
+            '=' => escaped.push_str("&#x3D;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Text(&'a str),
+    Value { name: &'a str, raw: bool },
+    If(&'a str),
+    Else,
+    End,
+}
+
+fn tokens(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        tokens.push(Token::Text(&rest[..start]));
+        let raw = rest[start..].starts_with("{{{");
+        let (open, close) = if raw { (3, "}}}") } else { (2, "}}") };
+        let inner = &rest[start + open..];
+        let end = inner
+            .find(close)
+            .unwrap_or_else(|| panic!("unclosed tag in template: {}", &rest[start..]));
+        let tag = inner[..end].trim();
+        tokens.push(match tag.split_once(' ') {
+            Some(("#if", name)) => Token::If(name.trim()),
+            _ if tag == "else" => Token::Else,
+            _ if tag == "/if" => Token::End,
+            _ => Token::Value { name: tag, raw },
+        });
+        rest = &inner[end + close.len()..];
+    }
+    tokens.push(Token::Text(rest));
+    tokens
+}
+
+/// `source` with `data` filled in, values HTML-escaped when `escape`,
+/// trimmed of the file's trailing newline
+fn fill(source: &str, data: &Map<String, Value>, escape: bool) -> String {
+    let mut out = String::new();
+    // Whether each open `#if` is in the branch being kept
+    let mut keeping: Vec<bool> = Vec::new();
+    for token in tokens(source) {
+        let visible = keeping.iter().all(|keep| *keep);
+        match token {
+            Token::Text(text) if visible => out.push_str(text),
+            Token::Value { name, raw } if visible => {
+                let value = data.get(name).map(display).unwrap_or_default();
+                match escape && !raw {
+                    true => out.push_str(&escape_html(&value)),
+                    false => out.push_str(&value),
+                }
+            }
+            Token::Text(_) | Token::Value { .. } => {}
+            Token::If(name) => keeping.push(truthy(data.get(name))),
+            Token::Else => {
+                let keep = keeping.pop().expect("{{else}} outside an {{#if}}");
+                keeping.push(!keep);
+            }
+            Token::End => {
+                keeping.pop().expect("{{/if}} without an {{#if}}");
+            }
+        }
+    }
+    assert!(keeping.is_empty(), "an {{#if}} in a template is never closed");
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn golden(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("templates/golden")
+            .join(name)
+    }
+
+    fn sample(name: &str) -> Value {
+        match name {
+            "failure_notice" => json!({
+                "topic": "Weekly sync",
+                "stage": "uploading to Loom",
+                "problem": "Loom API error: 503",
+                "stages": "download 2.5s, upload failed",
+                "advice": "Retry the meeting from your queue once the problem has passed.",
+                "link": "http://localhost:8080/dashboard?retry=r1",
+            }),
+            "success_notice" => json!({
+                "topic": "Weekly sync",
+                "loom_url": "https://www.loom.com/share/abc123",
+                "stages": "download 2.5s, upload 4.1s",
+            }),
+            "verify_email" => json!({ "link": "http://localhost:3000/auth/verify_email?token=abc", "hours": 24 }),
+            "key_expiry" => json!({
+                "service": "Fathom",
+                "key_id": "default",
+                "status": "expires in 3 days",
+                "link": "http://localhost:8080/settings",
+            }),
+            "smtp_test" => json!({ "sent_at": "Wed, 14 Oct 2026 09:30:00 +0000" }),
+            _ => json!({
+                "subject": "Scheduled maintenance",
+                "message": "Fathom to Loom will be down for an hour tonight.\nQueued meetings will wait.",
+                "link": "https://status.example.com",
+                "link_label": "Follow along",
+            }),
+        }
+    }
+
+    #[test]
+    fn test_every_template_matches_its_golden_files() {
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        for template in TEMPLATES {
+            let rendered = render(template.name, &sample(template.name)).unwrap();
+            let whole = |body: &str| format!("Subject: {}\n\n{}\n", rendered.subject, body);
+            for (extension, body) in [("html", &rendered.body_html), ("txt", &rendered.body_text)] {
+                let path = golden(&format!("{}.{}", template.name, extension));
+                if update {
+                    std::fs::write(&path, whole(body)).unwrap();
+                }
+                let checked_in = std::fs::read_to_string(&path).unwrap_or_default();
+                assert!(
+                    whole(body) == checked_in,
+                    "{} is out of date; regenerate it with \
+                     UPDATE_GOLDEN=1 cargo test -p fathom-loom-smtp-service templates",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_optional_sections_follow_the_data() {
+        let bare = render("generic", &json!({ "subject": "Hello", "message": "Hi" })).unwrap();
+        assert!(!bare.body_html.contains("<a "));
+        assert!(bare.body_text.starts_with("Hi\n\n--"));
+
+        let unlabelled = render("failure_notice", &json!({
+            "topic": "Weekly sync",
+            "stage": "downloading",
+            "problem": "timed out",
+            "link": "https://app.example.com/dashboard",
+            "stages": "",
+        }))
+        .unwrap();
+        assert!(unlabelled.body_text.contains("Retry the meeting: https://app.example.com/dashboard"));
+        assert!(!unlabelled.body_text.contains("Stages:"));
+    }
+
+    #[test]
+    fn test_hostile_data_is_escaped_in_html_only() {
+        let topic = "<script>alert('x')</script> & \"friends\"";
+        let rendered = render("success_notice", &json!({
+            "topic": topic,
+            "loom_url": "https://www.loom.com/share/abc\" onmouseover=\"alert(1)",
+        }))
+        .unwrap();
+        assert!(!rendered.body_html.contains("<script>"));
+        assert!(rendered
+            .body_html
+            .contains("&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt; &amp; &quot;friends&quot;"));
+        assert!(rendered
+            .body_html
+            .contains("href=\"https://www.loom.com/share/abc&quot; onmouseover&#x3D;&quot;alert(1)\""));
+        assert!(rendered.body_text.contains(topic), "the text variant keeps the data as given");
+        assert!(rendered.subject.contains(topic));
+
+        let header = render("generic", &json!({ "subject": "Hi\r\nBcc: everyone@example.com", "message": "x" })).unwrap();
+        assert_eq!(header.subject, "Hi Bcc: everyone@example.com");
+    }
+
+    #[test]
+    fn test_data_a_template_cant_render_is_refused_with_every_problem() {
+        assert_eq!(
+            render("nope", &json!({})).unwrap_err(),
+            ["unknown template 'nope'; use one of failure_notice, success_notice, verify_email, key_expiry, smtp_test, generic"]
+        );
+        assert_eq!(render("generic", &json!("hello")).unwrap_err(), ["template_data must be an object"]);
+        assert_eq!(
+            render("key_expiry", &json!({ "service": "Loom", "key_id": " ", "link": "javascript:alert(1)" })).unwrap_err(),
+            [
+                "key_expiry needs template_data.key_id",
+                "key_expiry needs template_data.status",
+                "template_data.link must be an http(s) URL",
+            ]
+        );
+        assert!(render("verify_email", &json!({ "link": "https://x.example.com", "hours": 0 })).is_ok());
+    }
+
+    #[test]
+    fn test_tags_are_read_whole() {
+        assert_eq!(
+            tokens("a {{ name }}{{{body}}}{{#if x}}b{{else}}c{{/if}}"),
+            [
+                Token::Text("a "),
+                Token::Value { name: "name", raw: false },
+                Token::Text(""),
+                Token::Value { name: "body", raw: true },
+                Token::Text(""),
+                Token::If("x"),
+                Token::Text("b"),
+                Token::Else,
+                Token::Text("c"),
+                Token::End,
+                Token::Text(""),
+            ]
+        );
+    }
+}
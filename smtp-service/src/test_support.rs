@@ -0,0 +1,143 @@
+//! Stand-ins for the service's peers in its tests
+//!
+//! [`mock_pb`] starts the shared in-memory PocketBase, [`MockPb`], with the
+//! unique indexes of `pb_schema.json` that the queue relies on.
+//! [`FakeTransport`] keeps what it is given to send, failing sends and
+//! connection tests on request, and [`SlowTransport`] takes its time over
+//! each send.
+
+use futures::future::BoxFuture;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+pub use common::test_support::MockPb;
+
+use crate::{
+    pocketbase::PbSettings,
+    transport::{Endpoint, OutgoingEmail, SendError, Transport},
+};
+
+/// A running [`MockPb`] enforcing the email queue's unique indexes
+pub async fn mock_pb() -> MockPb {
+    let pb = MockPb::start().await;
+    pb.unique("email_claims", &["email", "version"]);
+    pb.unique("suppression_list", &["email"]);
+    pb.unique("email_queue", &["idempotency_key"]);
+    pb
+}
+
+/// Admin credentials for `pb`
+pub fn pb_settings(pb: &MockPb) -> PbSettings {
+    PbSettings {
+        url: pb.url.clone(),
+        admin_email: common::test_support::ADMIN_EMAIL.to_string(),
+        admin_password: common::test_support::ADMIN_PASSWORD.to_string(),
+    }
+}
+
+/// A transport keeping what it sends, and failing the sends it is told to
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    sent: Arc<Mutex<Vec<OutgoingEmail>>>,
+    failures: Arc<Mutex<VecDeque<SendError>>>,
+    refusal: Arc<Mutex<Option<SendError>>>,
+    endpoint: Option<Endpoint>,
+}
+
+impl FakeTransport {
+    /// A transport that says it connects to `endpoint`
+    pub fn at(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint: Some(endpoint),
+            ..Default::default()
+        }
+    }
+
+    /// Fail every connection test with `error` from now on
+    pub fn refuse_connections(&self, error: SendError) {
+        *self.refusal.lock().unwrap() = Some(error);
+    }
+
+    /// Fail the next send with `error`, after any failures already asked for
+    pub fn fail_next(&self, error: SendError) {
+        self.failures.lock().unwrap().push_back(error);
+    }
+
+    pub fn sent(&self) -> Vec<OutgoingEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl Transport for FakeTransport {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), SendError>> {
+        let result = match self.failures.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => {
+                self.sent.lock().unwrap().push(email.clone());
+                Ok(())
+            }
+        };
+        Box::pin(async move { result })
+    }
+
+    fn test_connection(&self) -> BoxFuture<'_, Result<(), SendError>> {
+        let result = self.refusal.lock().unwrap().clone().map_or(Ok(()), Err);
+        Box::pin(async move { result })
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        self.endpoint.clone()
+    }
+}
+
+/// A transport taking `delay` over each send, noting the most it was
+/// sending at once
+#[derive(Default)]
+pub struct SlowTransport {
+    delay: Duration,
+    sending: AtomicUsize,
+    most: AtomicUsize,
+    sent: AtomicUsize,
+}
+
+impl SlowTransport {
+    pub fn taking(delay: Duration) -> Self {
+        Self {
+            delay,
+            ..Default::default()
+        }
+    }
+
+    /// Sends begun and not yet finished
+    pub fn sending(&self) -> usize {
+        self.sending.load(SeqCst)
+    }
+
+    pub fn most_at_once(&self) -> usize {
+        self.most.load(SeqCst)
+    }
+
+    /// Sends that finished
+    pub fn sent(&self) -> usize {
+        self.sent.load(SeqCst)
+    }
+}
+
+impl Transport for SlowTransport {
+    fn send<'a>(&'a self, _: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), SendError>> {
+        Box::pin(async move {
+            let sending = self.sending.fetch_add(1, SeqCst) + 1;
+            self.most.fetch_max(sending, SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.sending.fetch_sub(1, SeqCst);
+            self.sent.fetch_add(1, SeqCst);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,516 @@
+//! Handing a message to the SMTP server
+//!
+//! [`SmtpTransport`] relays through the server named by `SMTP_HOST`, over
+//! STARTTLS unless `SMTP_USE_SSL` asks for TLS from the start or
+//! `SMTP_USE_TLS=false` for neither. Without `SMTP_HOST` the service still
+//! queues mail, and [`LogTransport`] logs each message instead of sending it.
+//!
+//! `SMTP_PROVIDERS` names several servers instead, each configured by the
+//! same variables with its name after `SMTP_`, `SMTP_BACKUP_HOST` for the
+//! provider `backup`; the sender falls back to `SMTP_FROM_EMAIL` and
+//! `SMTP_FROM_NAME`. [`crate::providers`] fails over between them.
+//!
+//! Given a [`DkimSigner`], every message is signed as the last thing
+//! before it is sent, once it is formatted.
+
+use futures::future::BoxFuture;
+use lettre::{
+    message::{header::ContentType, Attachment as MimeAttachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tracing::info;
+
+use crate::{attachments::Attachment, dkim::DkimSigner};
+
+/// How long one SMTP conversation may take
+pub const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the queue hands a transport to send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingEmail {
+    pub to_email: String,
+    pub to_name: Option<String>,
+    pub subject: String,
+    pub body_text: String,
+    pub body_html: String,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum SendError {
+    #[error("message could not be built: {0}")]
+    Message(String),
+
+    /// A 4xx answer, or a connection that failed or timed out, which may
+    /// pass if the email is tried again
+    #[error("SMTP error: {0}")]
+    Transient(String),
+
+    /// A 5xx answer, such as the recipient being rejected
+    #[error("SMTP error: {0}")]
+    Permanent(String),
+}
+
+impl SendError {
+    /// Whether sending the same email later could succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendError {
+    fn from(error: lettre::transport::smtp::Error) -> Self {
+        // A 5xx is the server's final word and a client error is ours;
+        // anything else failed on the way there
+        match error.is_permanent() || error.is_client() {
+            true => Self::Permanent(error.to_string()),
+            false => Self::Transient(error.to_string()),
+        }
+    }
+}
+
+pub trait Transport: Send + Sync {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), SendError>>;
+
+    /// Whether the server can be reached and accepts the credentials
+    fn test_connection(&self) -> BoxFuture<'_, Result<(), SendError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// The server it connects to, if it connects to one
+    fn endpoint(&self) -> Option<Endpoint> {
+        None
+    }
+}
+
+/// Logs emails instead of sending them
+pub struct LogTransport;
+
+impl Transport for LogTransport {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), SendError>> {
+        info!(to = %email.to_email, "Email not sent, no SMTP_HOST: {}", email.subject);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    StartTls,
+    Tls,
+    None,
+}
+
+/// Where a transport connects and how; a connection test that passes has
+/// negotiated `security`, as the transport insists on it, and signed in if
+/// it `authenticates`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub security: Security,
+    pub authenticates: bool,
+}
+
+/// The SMTP server to relay through, from the SMTP_* variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from_email: String,
+    pub from_name: Option<String>,
+    pub security: Security,
+}
+
+impl SmtpSettings {
+    /// The server `SMTP_HOST` names, or `None` when it is unset
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        Self::prefixed(&lookup, "SMTP_")
+    }
+
+    /// The servers to relay through, in the order preferred: each that
+    /// `SMTP_PROVIDERS` names, or else the one `SMTP_HOST` names as
+    /// `default`
+    pub fn providers_from_env() -> Result<Vec<(String, Self)>, String> {
+        Self::providers_from_lookup(|var| std::env::var(var).ok())
+    }
+
+    fn providers_from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Vec<(String, Self)>, String> {
+        let Some(names) = lookup("SMTP_PROVIDERS").filter(|names| !names.trim().is_empty()) else {
+            let default = Self::from_lookup(&lookup)?;
+            return Ok(default.map(|settings| ("default".to_string(), settings)).into_iter().collect());
+        };
+        let mut providers: Vec<(String, Self)> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("SMTP_PROVIDERS names may be letters, digits and _, not '{}'", name));
+            }
+            let name = name.to_lowercase();
+            if providers.iter().any(|(listed, _)| *listed == name) {
+                return Err(format!("SMTP_PROVIDERS lists '{}' twice", name));
+            }
+            let prefix = format!("SMTP_{}_", name.to_uppercase());
+            let settings = Self::prefixed(&lookup, &prefix)?
+                .ok_or_else(|| format!("SMTP_PROVIDERS lists '{}' but {}HOST is unset", name, prefix))?;
+            providers.push((name, settings));
+        }
+        Ok(providers)
+    }
+
+    /// The server `{prefix}HOST` names, configured by the other variables
+    /// starting `prefix`, the sender by the SMTP_FROM_* ones if not
+    fn prefixed(lookup: &impl Fn(&str) -> Option<String>, prefix: &str) -> Result<Option<Self>, String> {
+        let name = |suffix: &str| format!("{}{}", prefix, suffix);
+        let set = |var: &str| lookup(var).filter(|value| !value.trim().is_empty());
+        let var = |suffix: &str| set(&name(suffix));
+        let sender = |suffix: &str| var(suffix).or_else(|| set(&format!("SMTP_{}", suffix)));
+        let Some(host) = var("HOST") else {
+            return Ok(None);
+        };
+        let flag = |suffix: &str, default: bool| match var(suffix) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} must be true or false, not '{}'", name(suffix), value)),
+            None => Ok(default),
+        };
+        let security = match (flag("USE_SSL", false)?, flag("USE_TLS", true)?) {
+            (true, _) => Security::Tls,
+            (false, true) => Security::StartTls,
+            (false, false) => Security::None,
+        };
+        let default_port = match security {
+            Security::Tls => 465,
+            Security::StartTls | Security::None => 587,
+        };
+        let port = match var("SERVER_PORT") {
+            Some(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("{} must be a port number, not '{}'", name("SERVER_PORT"), value))?,
+            None => default_port,
+        };
+        let from_email = sender("FROM_EMAIL")
+            .ok_or_else(|| format!("{} must be set when {} is", name("FROM_EMAIL"), name("HOST")))?;
+        from_email
+            .parse::<lettre::Address>()
+            .map_err(|_| format!("{} must be an email address, not '{}'", name("FROM_EMAIL"), from_email))?;
+        Ok(Some(Self {
+            host,
+            port,
+            username: var("USERNAME"),
+            password: var("PASSWORD"),
+            from_email,
+            from_name: sender("FROM_NAME"),
+            security,
+        }))
+    }
+}
+
+pub struct SmtpTransport {
+    endpoint: Endpoint,
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    dkim: Option<Arc<DkimSigner>>,
+}
+
+impl SmtpTransport {
+    pub fn new(settings: &SmtpSettings) -> Result<Self, String> {
+        let builder = match settings.security {
+            Security::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host),
+            Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host),
+            Security::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &settings.host,
+            )),
+        }
+        .map_err(|e| format!("SMTP_HOST '{}' can't be used: {}", settings.host, e))?;
+        let mut builder = builder.port(settings.port).timeout(Some(SMTP_TIMEOUT));
+        let credentials = settings.username.clone().zip(settings.password.clone());
+        if let Some((username, password)) = &credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let from = Mailbox::new(
+            settings.from_name.clone(),
+            settings
+                .from_email
+                .parse()
+                .map_err(|e| format!("SMTP_FROM_EMAIL can't be used: {}", e))?,
+        );
+        Ok(Self {
+            endpoint: Endpoint {
+                host: settings.host.clone(),
+                port: settings.port,
+                security: settings.security,
+                authenticates: credentials.is_some(),
+            },
+            from,
+            transport: builder.build(),
+            dkim: None,
+        })
+    }
+
+    /// Sign every message with `dkim`, if given
+    pub fn with_dkim(mut self, dkim: Option<Arc<DkimSigner>>) -> Self {
+        self.dkim = dkim;
+        self
+    }
+
+    /// `message` as it goes over SMTP, signed if there is a signer
+    fn formatted(&self, message: &Message) -> Result<Vec<u8>, SendError> {
+        let formatted = message.formatted();
+        match &self.dkim {
+            Some(dkim) => dkim
+                .sign(&formatted, chrono::Utc::now())
+                .map_err(|e| SendError::Message(format!("DKIM signing failed: {}", e))),
+            None => Ok(formatted),
+        }
+    }
+}
+
+/// `email` from `from`, with a text part, an HTML part or both, and its
+/// attachments after them in a multipart/mixed message
+pub fn build_message(from: &Mailbox, email: &OutgoingEmail) -> Result<Message, SendError> {
+    let address = email
+        .to_email
+        .parse()
+        .map_err(|e| SendError::Message(format!("invalid recipient: {}", e)))?;
+    let builder = Message::builder()
+        .from(from.clone())
+        .to(Mailbox::new(email.to_name.clone(), address))
+        .subject(&email.subject);
+    let body = match (email.body_text.is_empty(), email.body_html.is_empty()) {
+        (false, false) => Ok(MultiPart::alternative_plain_html(
+            email.body_text.clone(),
+            email.body_html.clone(),
+        )),
+        (false, true) => Err(SinglePart::plain(email.body_text.clone())),
+        (true, _) => Err(SinglePart::html(email.body_html.clone())),
+    };
+    let message = match (body, email.attachments.is_empty()) {
+        (Ok(alternative), true) => builder.multipart(alternative),
+        (Err(single), true) => builder.singlepart(single),
+        (body, false) => {
+            let mixed = MultiPart::mixed();
+            let mut mixed = match body {
+                Ok(alternative) => mixed.multipart(alternative),
+                Err(single) => mixed.singlepart(single),
+            };
+            for attachment in &email.attachments {
+                let invalid = |problem: String| {
+                    SendError::Message(format!("attachment '{}' {}", attachment.filename, problem))
+                };
+                let data = attachment
+                    .data()
+                    .map_err(|e| invalid(format!("isn't base64: {}", e)))?;
+                let content_type = ContentType::parse(&attachment.content_type)
+                    .map_err(|e| invalid(format!("has an invalid content type: {}", e)))?;
+                mixed = mixed.singlepart(
+                    MimeAttachment::new(attachment.filename.clone()).body(data, content_type),
+                );
+            }
+            builder.multipart(mixed)
+        }
+    };
+    message.map_err(|e| SendError::Message(e.to_string()))
+}
+
+impl Transport for SmtpTransport {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), SendError>> {
+        Box::pin(async move {
+            let message = build_message(&self.from, email)?;
+            self.transport
+                .send_raw(message.envelope(), &self.formatted(&message)?)
+                .await
+                .map(drop)
+                .map_err(SendError::from)
+        })
+    }
+
+    fn test_connection(&self) -> BoxFuture<'_, Result<(), SendError>> {
+        Box::pin(async move {
+            match self.transport.test_connection().await? {
+                true => Ok(()),
+                false => Err(SendError::Transient("the server didn't answer".to_string())),
+            }
+        })
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        Some(self.endpoint.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn settings(vars: &[(&str, &str)]) -> Result<Option<SmtpSettings>, String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        SmtpSettings::from_lookup(|var| vars.get(var).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn test_smtp_settings_need_a_host_and_sender() {
+        assert_eq!(settings(&[]), Ok(None));
+        assert!(settings(&[("SMTP_HOST", "smtp.example.com")])
+            .unwrap_err()
+            .contains("SMTP_FROM_EMAIL"));
+
+        let relay = settings(&[
+            ("SMTP_HOST", "smtp.example.com"),
+            ("SMTP_FROM_EMAIL", "noreply@example.com"),
+            ("SMTP_USE_SSL", "true"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(relay.security, Security::Tls);
+        assert_eq!(relay.port, 465);
+
+        let plain = settings(&[
+            ("SMTP_HOST", "mailpit"),
+            ("SMTP_FROM_EMAIL", "noreply@example.com"),
+            ("SMTP_USE_TLS", "false"),
+            ("SMTP_SERVER_PORT", "1025"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!((plain.security, plain.port), (Security::None, 1025));
+        assert!(settings(&[
+            ("SMTP_HOST", "mailpit"),
+            ("SMTP_FROM_EMAIL", "noreply@example.com"),
+            ("SMTP_USE_TLS", "yes"),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_providers_are_configured_under_their_names() {
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("SMTP_HOST", "smtp.example.com"),
+            ("SMTP_FROM_EMAIL", "noreply@example.com"),
+            ("SMTP_PROVIDERS", "primary, Backup"),
+            ("SMTP_PRIMARY_HOST", "smtp.sendgrid.net"),
+            ("SMTP_PRIMARY_USERNAME", "apikey"),
+            ("SMTP_PRIMARY_PASSWORD", "secret"),
+            ("SMTP_BACKUP_HOST", "email-smtp.eu-west-1.amazonaws.com"),
+            ("SMTP_BACKUP_USE_SSL", "true"),
+            ("SMTP_BACKUP_FROM_EMAIL", "mail@example.com"),
+        ]);
+        let providers = |vars: &HashMap<&str, &str>| {
+            SmtpSettings::providers_from_lookup(|var| vars.get(var).map(|value| value.to_string()))
+        };
+        let listed = providers(&vars).unwrap();
+        let names: Vec<&str> = listed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["primary", "backup"]);
+        let (primary, backup) = (&listed[0].1, &listed[1].1);
+        assert_eq!((primary.host.as_str(), primary.port), ("smtp.sendgrid.net", 587));
+        assert_eq!(primary.username.as_deref(), Some("apikey"));
+        assert_eq!(primary.from_email, "noreply@example.com", "the sender is shared");
+        assert_eq!((backup.security, backup.port, backup.username.clone()), (Security::Tls, 465, None));
+        assert_eq!(backup.from_email, "mail@example.com");
+
+        let mut missing = vars.clone();
+        missing.remove("SMTP_BACKUP_HOST");
+        assert_eq!(
+            providers(&missing).unwrap_err(),
+            "SMTP_PROVIDERS lists 'backup' but SMTP_BACKUP_HOST is unset"
+        );
+        let mut twice = vars.clone();
+        twice.insert("SMTP_PROVIDERS", "primary,PRIMARY");
+        assert!(providers(&twice).is_err());
+
+        // Without SMTP_PROVIDERS there is the one SMTP_HOST names, if any
+        let mut single = vars.clone();
+        single.remove("SMTP_PROVIDERS");
+        let listed = providers(&single).unwrap();
+        assert_eq!((listed[0].0.as_str(), listed[0].1.host.as_str()), ("default", "smtp.example.com"));
+        assert!(providers(&HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_messages_carry_the_bodies_they_were_given() {
+        let from: Mailbox = "Fathom to Loom <noreply@example.com>".parse().unwrap();
+        let email = OutgoingEmail {
+            to_email: "alice@example.com".to_string(),
+            to_name: Some("Alice".to_string()),
+            subject: "Your meeting is on Loom".to_string(),
+            body_text: "It's ready".to_string(),
+            body_html: "<p>It's ready</p>".to_string(),
+            attachments: Vec::new(),
+        };
+        let both = String::from_utf8(build_message(&from, &email).unwrap().formatted()).unwrap();
+        assert!(both.contains("To: Alice <alice@example.com>"));
+        assert!(both.contains("multipart/alternative"));
+        assert!(both.contains("text/plain") && both.contains("text/html"));
+
+        let text_only = OutgoingEmail {
+            body_html: String::new(),
+            ..email.clone()
+        };
+        let text = String::from_utf8(build_message(&from, &text_only).unwrap().formatted()).unwrap();
+        assert!(!text.contains("multipart") && text.contains("text/plain"));
+
+        let nowhere = OutgoingEmail {
+            to_email: "not an address".to_string(),
+            ..email.clone()
+        };
+        assert!(matches!(build_message(&from, &nowhere), Err(SendError::Message(_))));
+
+        let attached = OutgoingEmail {
+            attachments: vec![Attachment {
+                filename: "queue-export.csv".to_string(),
+                content_type: "text/csv".to_string(),
+                data_base64: "bWVldGluZyxzdGF0dXMKNDIsY29tcGxldGVkCg==".to_string(),
+            }],
+            ..email
+        };
+        let mixed = String::from_utf8(build_message(&from, &attached).unwrap().formatted()).unwrap();
+        let (headers, body) = mixed.split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("Content-Type: multipart/mixed"));
+        let boundary = headers.split("boundary=\"").nth(1).unwrap().split('"').next().unwrap();
+        let parts: Vec<&str> = body.split(&format!("--{}", boundary)).collect();
+        // Before the first boundary, the bodies, the attachment and after the last
+        assert_eq!(parts.len(), 4);
+        assert!(parts[1].contains("Content-Type: multipart/alternative"));
+        assert!(parts[2].contains("Content-Disposition: attachment; filename=\"queue-export.csv\""));
+        assert!(parts[2].contains("Content-Type: text/csv"));
+        assert!(parts[2].contains("meeting,status\n42,completed"));
+    }
+
+    #[test]
+    fn test_messages_are_signed_only_with_a_dkim_key() {
+        let relay = settings(&[
+            ("SMTP_HOST", "mailpit"),
+            ("SMTP_FROM_EMAIL", "noreply@example.com"),
+            ("SMTP_USE_TLS", "false"),
+        ])
+        .unwrap()
+        .unwrap();
+        let email = OutgoingEmail {
+            to_email: "alice@example.com".to_string(),
+            to_name: None,
+            subject: "Your meeting is on Loom".to_string(),
+            body_text: "It's ready".to_string(),
+            body_html: String::new(),
+            attachments: Vec::new(),
+        };
+        let unsigned = SmtpTransport::new(&relay).unwrap();
+        let message = build_message(&unsigned.from, &email).unwrap();
+        assert_eq!(unsigned.formatted(&message).unwrap(), message.formatted());
+
+        let (dkim, _) = DkimSigner::generated();
+        let signed = SmtpTransport::new(&relay).unwrap().with_dkim(Some(Arc::new(dkim)));
+        let formatted = signed.formatted(&message).unwrap();
+        assert!(formatted.starts_with(b"DKIM-Signature: v=1; a=ed25519-sha256;"));
+        assert!(formatted.ends_with(&message.formatted()));
+    }
+}
@@ -0,0 +1,67 @@
+//! Stopping a task its owner no longer wants
+//!
+//! A task is cancelled by setting `cancel_requested` on its queue item, or
+//! by a `TaskCancelled` queue update for it on the worker's broadcast
+//! service. While an attempt runs, [`requested`] reads the item each poll
+//! interval and listens for the update. Whichever comes first stops the
+//! attempt where it is, between stages or inside one: the pipeline's future
+//! is dropped, so a download or upload goes no further than the chunk it
+//! was on and ffmpeg is killed. The task's workspace is then removed and it
+//! ends `Cancelled`, without an email to its owner.
+//!
+//! An attempt that finishes before the request is seen ends as it would
+//! have; the request is then ignored.
+
+use common::broadcast::{BroadcastService, QueueUpdateType};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::{sync::broadcast::error::RecvError, time::MissedTickBehavior};
+use tracing::{debug, info};
+
+use crate::{
+    pocketbase::{quote, PocketBase},
+    queue::{QueueTask, QUEUE_COLLECTION},
+};
+
+/// Resolves once `task` is to be cancelled, reading its queue item every
+/// `every` from now on
+pub async fn requested(pb: &PocketBase, broadcast_service: &BroadcastService, task: &QueueTask, every: Duration) {
+    let mut updates = broadcast_service.subscribe();
+    let mut listening = true;
+    let mut poll = tokio::time::interval(every);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = poll.tick() => {
+                if flagged(pb, task).await {
+                    info!("Task cancellation requested on its queue item");
+                    return;
+                }
+            }
+            update = updates.recv(), if listening => match update {
+                Ok(update) if matches!(update.update_type, QueueUpdateType::TaskCancelled) && update.task_id == Some(task.id) => {
+                    info!("Task cancellation requested by broadcast");
+                    return;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => listening = false,
+            },
+        }
+    }
+}
+
+/// Whether `task`'s queue item has `cancel_requested` set; an item that
+/// can't be read is taken as not
+async fn flagged(pb: &PocketBase, task: &QueueTask) -> bool {
+    match pb.list(QUEUE_COLLECTION, &format!("id = {}", quote(&task.record_id)), "", 1).await {
+        Ok(records) => records
+            .first()
+            .and_then(|record| record.get("cancel_requested"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        Err(e) => {
+            debug!("Failed to check the task for a cancellation: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Where an interrupted task picks up again
+//!
+//! After each stage the pipeline notes what it finished in the task's
+//! [`Checkpoint`], kept on its queue item as `checkpoint`: the recording's
+//! metadata, then the size and SHA-256 of the download, then of the file the
+//! transcode left. The files stay in the task's
+//! [workspace](crate::workspace::Workspace), and the Loom upload keeps its
+//! session on the queue item as it goes.
+//!
+//! When the task is claimed again, after a retry, a shutdown or a crash, the
+//! checkpoint is [resumed](Checkpoint::resume) against the workspace: stages
+//! whose files are still there and unchanged are skipped. One that isn't
+//! sends the task back to the start, with its files, checkpoint and upload
+//! session dropped, rather than upload something other than what was
+//! checked. Completing the task clears the checkpoint.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    download::{self, Download},
+    fathom::MeetingArtifacts,
+    workspace::Workspace,
+};
+
+/// What earlier attempts at a task finished
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// What Fathom said of the recording, without its media URL
+    #[serde(default)]
+    pub metadata: Option<MeetingArtifacts>,
+    #[serde(default)]
+    pub download: Option<FileCheckpoint>,
+    /// The file the upload sends, which is the download's when the transcode
+    /// passed it through
+    #[serde(default)]
+    pub transcode: Option<FileCheckpoint>,
+}
+
+/// A file a stage left in the task's workspace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileCheckpoint {
+    /// Name of the file in the workspace
+    pub file: String,
+    pub size: u64,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+}
+
+impl FileCheckpoint {
+    pub fn of(download: &Download) -> Self {
+        Self {
+            file: download
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: download.size,
+            sha256: download.sha256.clone(),
+        }
+    }
+
+    /// The file in `workspace`, if it is still the one checked in
+    async fn verified(&self, workspace: &Workspace) -> Result<Download, String> {
+        // Only ever a file of the workspace's own
+        if self.file.is_empty() || self.file.contains(['/', '\\']) || self.file.starts_with('.') {
+            return Err(format!("{:?} isn't a workspace file", self.file));
+        }
+        let download = Download {
+            path: workspace.dir().join(&self.file),
+            size: self.size,
+            sha256: self.sha256.clone(),
+        };
+        download::verify(&download)
+            .await
+            .map_err(|e| format!("{} is gone or changed: {}", self.file, e))?;
+        Ok(download)
+    }
+}
+
+/// What a claim can skip, the checkpoint's stages that still hold
+#[derive(Debug, Default, PartialEq)]
+pub struct Resumed {
+    /// The recording's metadata, once its download no longer needs the
+    /// media URL
+    pub metadata: Option<MeetingArtifacts>,
+    pub download: Option<Download>,
+    pub transcode: Option<Download>,
+}
+
+impl Checkpoint {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// The stages checked in, in order
+    pub fn stages(&self) -> Vec<&'static str> {
+        [
+            ("metadata", self.metadata.is_some()),
+            ("download", self.download.is_some()),
+            ("transcode", self.transcode.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, done)| *done)
+        .map(|(stage, _)| stage)
+        .collect()
+    }
+
+    /// Check each stage's file in `workspace`, returning what can be
+    /// skipped, or why the task has to start over
+    ///
+    /// The metadata is always asked for again unless the download is done,
+    /// as the media URL Fathom gives expires.
+    pub async fn resume(&self, workspace: &Workspace) -> Result<Resumed, String> {
+        let mut resumed = Resumed::default();
+        let Some(checked) = &self.download else {
+            if self.transcode.is_some() {
+                return Err("a transcode was checked in without its download".to_string());
+            }
+            return Ok(resumed);
+        };
+        let Some(metadata) = &self.metadata else {
+            return Err("a download was checked in without its metadata".to_string());
+        };
+        resumed.download = Some(checked.verified(workspace).await?);
+        resumed.metadata = Some(metadata.clone());
+        if let Some(transcode) = &self.transcode {
+            resumed.transcode = Some(transcode.verified(workspace).await?);
+        }
+        Ok(resumed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn metadata() -> MeetingArtifacts {
+        MeetingArtifacts {
+            title: "Weekly sync".to_string(),
+            duration: 2730,
+            download_url: String::new(),
+            size_bytes: Some(5),
+            sha256: None,
+            transcript_available: true,
+        }
+    }
+
+    async fn written(workspace: &Workspace, file: &str, bytes: &[u8]) -> FileCheckpoint {
+        let path = workspace.dir().join(file);
+        std::fs::write(&path, bytes).unwrap();
+        FileCheckpoint {
+            file: file.to_string(),
+            size: bytes.len() as u64,
+            sha256: download::sha256_of(&path).await.unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_each_stage_checked_in_is_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        let workspace = Workspace::create(root.path(), Uuid::new_v4()).unwrap();
+
+        // The media URL has to be asked for again
+        let checkpoint = Checkpoint { metadata: Some(metadata()), ..Default::default() };
+        assert_eq!(checkpoint.stages(), ["metadata"]);
+        assert_eq!(checkpoint.resume(&workspace).await.unwrap(), Resumed::default());
+
+        let download = written(&workspace, "recording.download", b"video").await;
+        let checkpoint = Checkpoint { download: Some(download.clone()), ..checkpoint };
+        let resumed = checkpoint.resume(&workspace).await.unwrap();
+        assert_eq!(resumed.metadata, Some(metadata()));
+        assert_eq!(resumed.download.unwrap().path, workspace.download_path());
+        assert_eq!(resumed.transcode, None);
+
+        let transcode = written(&workspace, "recording.transcoded.mp4", b"smaller").await;
+        let checkpoint = Checkpoint { transcode: Some(transcode), ..checkpoint };
+        assert_eq!(checkpoint.stages(), ["metadata", "download", "transcode"]);
+        let resumed = checkpoint.resume(&workspace).await.unwrap();
+        assert_eq!(resumed.transcode.unwrap().path, workspace.transcode_path("mp4"));
+
+        // A checkpoint survives the queue item
+        let stored: Checkpoint = serde_json::from_value(serde_json::to_value(&checkpoint).unwrap()).unwrap();
+        assert_eq!(stored, checkpoint);
+        assert!(serde_json::from_value::<Checkpoint>(serde_json::json!({})).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_a_changed_or_missing_file_means_starting_over() {
+        let root = tempfile::tempdir().unwrap();
+        let workspace = Workspace::create(root.path(), Uuid::new_v4()).unwrap();
+        let checkpoint = Checkpoint {
+            metadata: Some(metadata()),
+            download: Some(written(&workspace, "recording.download", b"video").await),
+            transcode: Some(written(&workspace, "recording.transcoded.mp4", b"smaller").await),
+        };
+
+        std::fs::write(workspace.transcode_path("mp4"), b"SMALLER").unwrap();
+        let why = checkpoint.resume(&workspace).await.unwrap_err();
+        assert!(why.starts_with("recording.transcoded.mp4 is gone or changed"), "{}", why);
+
+        std::fs::remove_file(workspace.download_path()).unwrap();
+        assert!(checkpoint.resume(&workspace).await.is_err());
+
+        let escaping = Checkpoint {
+            download: Some(FileCheckpoint { file: "../elsewhere".to_string(), ..checkpoint.download.clone().unwrap() }),
+            ..checkpoint.clone()
+        };
+        assert_eq!(escaping.resume(&workspace).await.unwrap_err(), "\"../elsewhere\" isn't a workspace file");
+        let orphaned = Checkpoint { metadata: None, ..checkpoint };
+        assert!(orphaned.resume(&workspace).await.is_err());
+    }
+}
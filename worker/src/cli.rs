@@ -0,0 +1,304 @@
+//! The worker's command line
+//!
+//! `worker`, or `worker run`, claims and runs tasks until it is stopped. The
+//! other commands are for operators, run with the same configuration as the
+//! worker they act on:
+//!
+//! - `drain` asks the worker serving `WORKER_HTTP_PORT` on this host to
+//!   claim nothing more, then waits until it exits with its running tasks
+//!   done
+//! - `inspect <task_id>` prints a task's queue item, checkpoint, attempts
+//!   and dead letter as JSON
+//! - `requeue <task_id>` puts a failed or stuck task back to `Pending`, as
+//!   [`queue::requeue`] does
+//! - `validate-config` lists every problem with the configuration, exiting
+//!   with [`EXIT_FAILURE`] if there are any
+//!
+//! A task is named by its task id or the id of its queue item.
+
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::info;
+
+use crate::{
+    checkpoint::Checkpoint,
+    keys,
+    pocketbase::{quote, PocketBase},
+    queue::{self, QueueTask, DEAD_LETTER_COLLECTION, QUEUE_COLLECTION},
+    WorkerConfig, WorkerError, WorkerResult,
+};
+
+pub const USAGE: &str = "\
+Usage: worker [COMMAND]
+
+Commands:
+  run                Claim and run tasks until stopped (the default)
+  drain              Have the worker on this host finish its running tasks, claim nothing more and exit
+  inspect <task_id>  Print a task's queue item, checkpoint and attempts as JSON
+  requeue <task_id>  Put a failed or stuck task back in the queue
+  validate-config    Check the configuration and exit
+  help               Print this
+";
+
+/// Exit status of a configuration with problems, as of any command failing
+pub const EXIT_FAILURE: u8 = 1;
+
+/// Exit status of a command line that doesn't parse
+pub const EXIT_USAGE: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Drain,
+    Inspect { task_id: String },
+    Requeue { task_id: String },
+    ValidateConfig,
+    Help,
+}
+
+/// The command `args`, the arguments after the program's name, ask for
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        None | Some("run") => Command::Run,
+        Some("drain") => Command::Drain,
+        Some("inspect") => Command::Inspect { task_id: task_id(&mut args, "inspect")? },
+        Some("requeue") => Command::Requeue { task_id: task_id(&mut args, "requeue")? },
+        Some("validate-config") => Command::ValidateConfig,
+        Some("help" | "-h" | "--help") => Command::Help,
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+    };
+    match args.next() {
+        Some(extra) => Err(format!("unexpected argument '{}'", extra)),
+        None => Ok(command),
+    }
+}
+
+/// The task id `command` takes next in `args`
+fn task_id(args: &mut impl Iterator<Item = String>, command: &str) -> Result<String, String> {
+    args.next()
+        .filter(|task_id| !task_id.is_empty() && !task_id.starts_with('-'))
+        .ok_or_else(|| format!("{} needs a task id", command))
+}
+
+/// What `validate-config` prints of the configuration `loaded`, and the
+/// status it exits with
+///
+/// Besides the problems loading finds, the configuration has to pass the
+/// check `/health/ready` makes of it.
+pub fn validated(loaded: &Result<WorkerConfig, WorkerError>) -> (u8, String) {
+    match loaded.as_ref().map(WorkerConfig::validate) {
+        Ok(Ok(())) => (0, "The worker configuration is valid".to_string()),
+        Ok(Err(problem)) => (EXIT_FAILURE, problem),
+        Err(e) => (EXIT_FAILURE, e.to_string()),
+    }
+}
+
+/// The queue item of `task_id`, a task id or a queue item id
+async fn find(pb: &PocketBase, task_id: &str) -> WorkerResult<Value> {
+    let filter = format!("(task_id = {id} || id = {id})", id = quote(task_id));
+    pb.list(QUEUE_COLLECTION, &filter, "", 1)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| WorkerError::Queue(format!("No queue item for task {}", task_id)))
+}
+
+/// `task_id`'s queue item as `task`, with its `checkpoint` and the stages
+/// that names, its `attempts` and its `dead_letter` entry, if it has one
+pub async fn inspect(pb: &PocketBase, task_id: &str) -> WorkerResult<Value> {
+    let mut task = find(pb, task_id).await?;
+    let fields = task.as_object_mut().expect("PocketBase records are objects");
+    let checkpoint = fields.remove("checkpoint").unwrap_or(Value::Null);
+    let attempts = fields.remove("attempts").unwrap_or_else(|| json!([]));
+    let stages = serde_json::from_value::<Checkpoint>(checkpoint.clone())
+        .map(|checkpoint| checkpoint.stages())
+        .unwrap_or_default();
+    let dead_letter = match fields.get("dead_letter_id").and_then(Value::as_str).filter(|id| !id.is_empty()) {
+        Some(id) => pb.list(DEAD_LETTER_COLLECTION, &format!("id = {}", quote(id)), "", 1).await?.into_iter().next(),
+        None => None,
+    };
+    Ok(json!({
+        "task": task,
+        "checkpoint": checkpoint,
+        "checkpoint_stages": stages,
+        "attempts": attempts,
+        "dead_letter": dead_letter,
+    }))
+}
+
+/// Requeue `task_id`, returning the task as it was before
+pub async fn requeue(pb: &PocketBase, task_id: &str) -> WorkerResult<QueueTask> {
+    let task = QueueTask::from_record(&find(pb, task_id).await?)?;
+    queue::requeue(pb, &task).await?;
+    Ok(task)
+}
+
+/// Ask the worker serving `config`'s `WORKER_HTTP_PORT` on this host to
+/// drain, then wait, checking every `poll`, until its server has stopped
+/// answering, which it does only once the pool has returned
+pub async fn drain(config: &WorkerConfig, poll: Duration) -> WorkerResult<()> {
+    let token = keys::internal_token(&config.backend)?;
+    let base_url = format!("http://127.0.0.1:{}", config.worker.http_port);
+    let client = reqwest::Client::new();
+    client
+        .post(format!("{}/drain", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?;
+    info!("The worker is draining; waiting for its running tasks to finish");
+    while client.get(format!("{}/health/live", base_url)).send().await.is_ok() {
+        tokio::time::sleep(poll).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::{readiness, DrainSwitch, Server},
+        shutdown::Shutdown,
+        test_support::{database, mock_backend, worker_config, MockPb},
+    };
+    use std::sync::Arc;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_each_command_parses_with_its_arguments() {
+        assert_eq!(parse(args("")), Ok(Command::Run));
+        assert_eq!(parse(args("run")), Ok(Command::Run));
+        assert_eq!(parse(args("drain")), Ok(Command::Drain));
+        assert_eq!(parse(args("inspect 9f0c")), Ok(Command::Inspect { task_id: "9f0c".to_string() }));
+        assert_eq!(parse(args("requeue 9f0c")), Ok(Command::Requeue { task_id: "9f0c".to_string() }));
+        assert_eq!(parse(args("validate-config")), Ok(Command::ValidateConfig));
+        assert_eq!(parse(args("--help")), Ok(Command::Help));
+
+        assert_eq!(parse(args("requeue")), Err("requeue needs a task id".to_string()));
+        assert_eq!(parse(args("inspect --json")), Err("inspect needs a task id".to_string()));
+        assert_eq!(parse(args("drain now")), Err("unexpected argument 'now'".to_string()));
+        assert_eq!(parse(args("start")), Err("unknown command 'start'".to_string()));
+    }
+
+    #[test]
+    fn test_validate_config_exits_nonzero_on_any_problem() {
+        let valid = |name: &str| match name {
+            "PB_ADMIN_PASSWORD" => Some("pb-password".to_string()),
+            "MASTER_KEY" => Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()),
+            "PB_ENCRYPTION_KEY" => Some("pb-encryption-key".to_string()),
+            "WORKER_ID" => Some("worker-test".to_string()),
+            "INTERNAL_API_TOKEN" => Some("internal-token".to_string()),
+            _ => None,
+        };
+        assert_eq!(validated(&WorkerConfig::from_lookup(valid)).0, 0);
+
+        let (status, report) = validated(&WorkerConfig::from_lookup(|name| match name {
+            "WORKER_CONCURRENCY" => Some("none".to_string()),
+            name => valid(name),
+        }));
+        assert_eq!(status, EXIT_FAILURE);
+        assert!(report.contains("WORKER_CONCURRENCY"), "{}", report);
+
+        // Loads, but no key could ever be fetched
+        let (status, report) = validated(&WorkerConfig::from_lookup(|name| match name {
+            "INTERNAL_API_TOKEN" => None,
+            name => valid(name),
+        }));
+        assert_eq!(status, EXIT_FAILURE);
+        assert!(report.starts_with("INTERNAL_API_TOKEN is not set"), "{}", report);
+    }
+
+    /// A dead-lettered task's queue item, and its dead letter
+    fn dead_lettered(mock: &MockPb, task_id: &str) -> (String, String) {
+        let dead_letter = mock.insert(DEAD_LETTER_COLLECTION, json!({ "task_id": task_id, "error_message": "upload rejected" }));
+        let item = mock.insert(
+            QUEUE_COLLECTION,
+            json!({
+                "task_id": task_id,
+                "user_id": "alice",
+                "meeting_id": "42",
+                "topic": "Weekly sync",
+                "status": "Failed",
+                "retry_count": 3,
+                "max_retries": 3,
+                "version": 4,
+                "error_message": "Loom API error: upload rejected",
+                "next_attempt_at": "2026-03-01 09:20:00.000Z",
+                "attempts": [{ "error": "Loom API error: upload rejected" }],
+                "checkpoint": { "metadata": null, "download": { "file": "recording.download", "size": 5, "sha256": "ab" } },
+                "dead_letter_id": dead_letter,
+                "created": "2026-03-01 09:00:00.000Z",
+                "updated": "2026-03-01 09:30:00.000Z",
+            }),
+        );
+        (item, dead_letter)
+    }
+
+    #[tokio::test]
+    async fn test_requeue_resets_a_failed_task_for_any_worker_to_claim() {
+        let mock = MockPb::start().await;
+        let task_id = uuid::Uuid::new_v4().to_string();
+        let (item, _) = dead_lettered(&mock, &task_id);
+        let pb = mock.client();
+
+        let inspected = inspect(&pb, &task_id).await.unwrap();
+        assert_eq!(inspected["task"]["id"], item);
+        assert!(inspected["task"].get("checkpoint").is_none());
+        assert_eq!(inspected["checkpoint_stages"], json!(["download"]));
+        assert_eq!(inspected["attempts"][0]["error"], "Loom API error: upload rejected");
+        assert_eq!(inspected["dead_letter"]["error_message"], "upload rejected");
+
+        let was = requeue(&pb, &item).await.unwrap();
+        assert_eq!(was.status, queue::TaskStatus::Failed);
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!(record["status"], "Pending");
+        assert_eq!(record["retry_count"], 0);
+        assert_eq!(record["version"], 4, "the next claim goes from the same version");
+        for field in ["error_message", "next_attempt_at", "claimed_by", "claimed_at", "dead_letter_id"] {
+            assert_eq!(record[field], "", "{}", field);
+        }
+        assert_eq!(record["attempts"], json!([]));
+        assert!(mock.records(DEAD_LETTER_COLLECTION).is_empty());
+
+        let claimed = queue::claim_oldest_unclaimed_task(&pb, "worker-b", chrono::Utc::now()).await.unwrap().unwrap();
+        assert_eq!(claimed.record_id, item);
+
+        // Claimed, it is stuck until its worker goes; a pending task isn't
+        requeue(&pb, &task_id).await.unwrap();
+        let error = requeue(&pb, &task_id).await.unwrap_err();
+        assert!(error.to_string().contains("is Pending; only a failed or stuck task is requeued"), "{}", error);
+        let error = requeue(&pb, "missing").await.unwrap_err();
+        assert!(error.to_string().contains("No queue item for task missing"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_the_worker_to_stop() {
+        let pb = MockPb::start().await;
+        let mut config = worker_config(database(&pb), mock_backend(&[]).await, "http://127.0.0.1:9", "http://127.0.0.1:9");
+        let (drain_switch, draining) = Shutdown::channel();
+        let switch = DrainSwitch {
+            token: config.backend.internal_api_token.clone().unwrap(),
+            drain: Arc::new(drain_switch),
+        };
+        let server = Server::start(0, readiness(&config), Some(switch)).await.unwrap();
+        config.worker.http_port = server.local_addr().port();
+        // The worker stops a while after it starts draining
+        let worker = tokio::spawn(async move {
+            draining.requested().await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            server.stop().await;
+        });
+
+        let started = std::time::Instant::now();
+        drain(&config, Duration::from_millis(10)).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        worker.await.unwrap();
+
+        config.backend.internal_api_token = None;
+        assert!(matches!(drain(&config, Duration::from_millis(10)).await, Err(WorkerError::Config(_))));
+    }
+}
@@ -1,5 +1,11 @@
 use std::env;
 use serde::Deserialize;
+use common::{logging::LogFormat, JobType};
+
+use crate::{
+    transcode::{Container, TranscodeMode, TranscodeProfile},
+    WorkerError,
+};
 
 #[derive(Debug, Clone)]
 pub struct WorkerConfig {
@@ -7,6 +13,11 @@ pub struct WorkerConfig {
     pub security: SecurityConfig,
     pub logging: LoggingConfig,
     pub worker: WorkerSettings,
+    pub backend: BackendConfig,
+    pub fathom: FathomConfig,
+    pub loom: LoomConfig,
+    pub email: EmailConfig,
+    pub transcode: TranscodeConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -23,68 +34,405 @@ pub struct SecurityConfig {
     pub pb_encryption_key: String,
 }
 
+/// How the worker reaches the backend's `/internal` routes
+#[derive(Debug, Clone)]
+pub struct BackendConfig {
+    pub url: String,
+    pub internal_api_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FathomConfig {
+    /// Base URL of the Fathom external API, from `FATHOM_API_URL`
+    pub api_url: String,
+    /// Fathom calls per minute across every task
+    pub requests_per_minute: u32,
+    /// Fathom calls that may go at once after a quiet spell
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoomConfig {
+    /// Base URL of the Loom API, from `LOOM_API_URL`
+    pub api_url: String,
+    /// Bytes sent per upload request
+    pub upload_chunk_size: u64,
+    /// Tries per chunk before the upload is given up for a later retry
+    pub upload_chunk_attempts: u32,
+}
+
+/// Where the emails telling users how their tasks ended go, and what they link to
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    /// Base URL of the smtp-service, from `SMTP_SERVICE_URL`
+    pub smtp_service_url: String,
+    /// Bearer token the smtp-service takes from the worker, from `SMTP_SERVICE_API_KEY`
+    pub smtp_service_api_key: Option<String>,
+    /// Tries per email before it is given up, from `SMTP_SEND_ATTEMPTS`
+    pub send_attempts: u32,
+    /// Page a failure email links to for retrying the meeting, from `QUEUE_PAGE_URL`
+    pub queue_url: String,
+    /// Page a missing or expired key is replaced on, from `KEY_SETTINGS_URL`
+    pub settings_url: String,
+}
+
+/// How a downloaded recording is re-encoded before it goes to Loom
+#[derive(Debug, Clone)]
+pub struct TranscodeConfig {
+    /// From `TRANSCODE_MODE`: `passthrough`, `always` or `off`
+    pub mode: TranscodeMode,
+    /// The ffmpeg binary, from `FFMPEG_PATH`
+    pub ffmpeg_path: String,
+    /// The ffprobe binary, from `FFPROBE_PATH`
+    pub ffprobe_path: String,
+    pub profile: TranscodeProfile,
+}
+
 #[derive(Debug, Clone)]
 pub struct LoggingConfig {
     pub level: String,
+    /// Pretty lines or JSON objects, from `LOG_FORMAT`
+    pub format: LogFormat,
+    /// Where panics are reported, from `SENTRY_DSN`; unset reports nothing
+    pub sentry_dsn: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct WorkerSettings {
     pub concurrency: u32,
+    /// The job types this worker claims, each with how many of it may run
+    /// at once within `concurrency`
+    pub job_types: Vec<(JobType, usize)>,
     pub poll_interval: u64,
     pub queue_concurrency: u32,
+    /// Names this worker on the queue items it claims and in its heartbeats
+    pub worker_id: String,
+    /// Where the worker keeps what it remembers between runs besides its id
+    pub state_file: std::path::PathBuf,
+    /// Oldest items of a priority looked across for other users' tasks; 1
+    /// claims strictly oldest first
+    pub claim_fairness_window: usize,
+    /// Seconds before a failed task's first retry, doubling with each one
+    pub retry_base_delay: u64,
+    /// Most seconds a failed task waits before any retry
+    pub retry_max_delay: u64,
+    /// Seconds running tasks get to finish after a shutdown signal before
+    /// they are returned to the queue
+    pub shutdown_grace: u64,
+    /// Largest recording downloaded; bigger ones fail their task
+    pub max_download_bytes: u64,
+    /// Tries per download, each resuming where the last stopped
+    pub download_attempts: u32,
+    /// Seconds the metadata stage may take
+    pub metadata_timeout: u64,
+    /// Seconds the download stage may take
+    pub download_timeout: u64,
+    /// Seconds the transcode stage may take
+    pub transcode_timeout: u64,
+    /// Seconds the upload stage may take
+    pub upload_timeout: u64,
+    /// Seconds a whole task may take, however long each stage was
+    pub task_deadline: u64,
+    /// Port of the worker's health and metrics server
+    pub http_port: u16,
+    /// Where each task's directory of downloads and transcodes is made
+    pub scratch_dir: std::path::PathBuf,
+    /// Bytes left free on the scratch volume whatever tasks need
+    pub scratch_min_free: u64,
+    /// Bytes a task is expected to write, checked for before each claim
+    pub expected_task_bytes: u64,
+    /// Download, transcode and upload each recording at once where it can be,
+    /// rather than one stage after another
+    pub streaming: bool,
 }
 
 impl WorkerConfig {
-    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+    /// Load the configuration from the process environment
+    pub fn from_env() -> Result<Self, WorkerError> {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Load the configuration from `lookup`, reporting every missing secret
+    /// and invalid value in one [`WorkerError::Config`] rather than stopping
+    /// at the first
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, WorkerError> {
+        let mut env = Env { lookup, problems: Vec::new() };
+
         let database = DatabaseConfig {
-            url: env::var("DATABASE_URL")
-                .or_else(|_| env::var("GLOBAL_PB_URL"))
-                .unwrap_or_else(|_| "http://pb_global:8090".to_string()),
-            admin_email: env::var("PB_ADMIN_EMAIL")
-                .or_else(|_| env::var("GLOBAL_PB_ADMIN_EMAIL"))
-                .unwrap_or_else(|_| "admin@example.com".to_string()),
-            admin_password: env::var("PB_ADMIN_PASSWORD")
-                .or_else(|_| env::var("GLOBAL_PB_ADMIN_PW"))
-                .expect("PB_ADMIN_PASSWORD or GLOBAL_PB_ADMIN_PW must be set"),
-            user_db_base_path: env::var("USER_DB_BASE_PATH")
-                .unwrap_or_else(|_| "/app/user_dbs".to_string()),
+            url: env.var("DATABASE_URL")
+                .or_else(|| env.var("GLOBAL_PB_URL"))
+                .unwrap_or_else(|| "http://pb_global:8090".to_string()),
+            admin_email: env.var("PB_ADMIN_EMAIL")
+                .or_else(|| env.var("GLOBAL_PB_ADMIN_EMAIL"))
+                .unwrap_or_else(|| "admin@example.com".to_string()),
+            admin_password: env.required(&["PB_ADMIN_PASSWORD", "GLOBAL_PB_ADMIN_PW"]),
+            user_db_base_path: env.var("USER_DB_BASE_PATH")
+                .unwrap_or_else(|| "/app/user_dbs".to_string()),
         };
 
         let security = SecurityConfig {
-            master_key: env::var("MASTER_KEY")
-                .or_else(|_| env::var("AES_MASTER_KEY"))
-                .expect("MASTER_KEY or AES_MASTER_KEY must be set"),
-            pb_encryption_key: env::var("PB_ENCRYPTION_KEY")
-                .expect("PB_ENCRYPTION_KEY must be set"),
+            master_key: env.required(&["MASTER_KEY", "AES_MASTER_KEY"]),
+            pb_encryption_key: env.required(&["PB_ENCRYPTION_KEY"]),
         };
+        // Stored keys are only readable under the exact key the backend parsed
+        if !security.master_key.is_empty() && common::crypto::parse_master_key(&security.master_key).is_err() {
+            env.problem("MASTER_KEY must be 32 bytes encoded as base64".to_string());
+        }
 
         let logging = LoggingConfig {
-            level: env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+            level: env.var("RUST_LOG").unwrap_or_else(|| "info".to_string()),
+            format: env.parse_with("LOG_FORMAT", LogFormat::default(), |value| value.parse()),
+            sentry_dsn: env.var("SENTRY_DSN").filter(|dsn| !dsn.trim().is_empty()),
         };
 
+        let concurrency = env.positive("WORKER_CONCURRENCY", 1);
+        let job_types = env.parse_with("WORKER_JOB_TYPES", vec![(JobType::ProcessMeeting, concurrency as usize)], |value| {
+            parse_job_types(value, concurrency as usize)
+        });
+        let poll_interval = env.parse("QUEUE_POLL_INTERVAL", 5);
+        if !(1..=MAX_POLL_INTERVAL).contains(&poll_interval) {
+            env.problem(format!(
+                "QUEUE_POLL_INTERVAL must be from 1 to {} seconds, not {}",
+                MAX_POLL_INTERVAL, poll_interval
+            ));
+        }
         let worker = WorkerSettings {
-            concurrency: env::var("WORKER_CONCURRENCY")
-                .unwrap_or_else(|_| "1".to_string())
-                .parse()
-                .unwrap_or(1),
-            poll_interval: env::var("QUEUE_POLL_INTERVAL")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()
-                .unwrap_or(5),
-            queue_concurrency: env::var("QUEUE_CONCURRENCY")
-                .unwrap_or_else(|_| "1".to_string())
-                .parse()
-                .unwrap_or(1),
+            concurrency,
+            job_types,
+            poll_interval,
+            queue_concurrency: env.positive("QUEUE_CONCURRENCY", 1),
+            // Kept on disk so the dashboard knows a restarted worker; without
+            // a writable file the id only lasts this run
+            worker_id: env.var("WORKER_ID")
+                .filter(|id| !id.trim().is_empty())
+                .or_else(|| {
+                    let path = env.var("WORKER_ID_FILE").unwrap_or_else(|| "/app/data/worker_id".to_string());
+                    crate::heartbeat::persisted_worker_id(std::path::Path::new(&path)).ok()
+                })
+                .or_else(|| env.var("HOSTNAME").filter(|id| !id.trim().is_empty()))
+                .unwrap_or_else(|| format!("worker-{}", &uuid::Uuid::new_v4().simple().to_string()[..8])),
+            state_file: env.var("WORKER_STATE_FILE")
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| std::path::PathBuf::from("/app/data/worker_state.json")),
+            claim_fairness_window: env.positive("CLAIM_FAIRNESS_WINDOW", crate::fairness::DEFAULT_WINDOW),
+            retry_base_delay: env.parse("RETRY_BASE_DELAY_SECS", 30),
+            retry_max_delay: env.parse("RETRY_MAX_DELAY_SECS", 3600),
+            shutdown_grace: env.parse("SHUTDOWN_GRACE_SECS", 25),
+            max_download_bytes: env.parse("MAX_DOWNLOAD_BYTES", 10737418240),
+            download_attempts: env.positive("DOWNLOAD_ATTEMPTS", 5),
+            metadata_timeout: env.parse("METADATA_TIMEOUT_SECS", 60),
+            download_timeout: env.parse("DOWNLOAD_TIMEOUT_SECS", 7200),
+            transcode_timeout: env.parse("TRANSCODE_TIMEOUT_SECS", 7200),
+            upload_timeout: env.parse("UPLOAD_TIMEOUT_SECS", 7200),
+            task_deadline: env.parse("TASK_DEADLINE_SECS", 21600),
+            http_port: env.parse("WORKER_HTTP_PORT", 9100),
+            scratch_dir: env.var("SCRATCH_DIR")
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("fathom-to-loom")),
+            scratch_min_free: env.parse("SCRATCH_MIN_FREE_BYTES", 1073741824),
+            expected_task_bytes: env.parse("EXPECTED_TASK_BYTES", 2147483648),
+            streaming: env.flag("STREAMING_PIPELINE", false),
+        };
+
+        let backend = BackendConfig {
+            url: env.var("BACKEND_URL")
+                .unwrap_or_else(|| "http://backend:3000".to_string()),
+            internal_api_token: env.var("INTERNAL_API_TOKEN").filter(|token| !token.is_empty()),
+        };
+
+        let fathom = FathomConfig {
+            api_url: env.var("FATHOM_API_URL")
+                .unwrap_or_else(|| "https://api.fathom.ai/external/v1".to_string()),
+            requests_per_minute: env.positive("WORKER_FATHOM_REQUESTS_PER_MINUTE", 30),
+            burst: env.positive("WORKER_FATHOM_BURST", 5),
         };
 
+        let loom = LoomConfig {
+            api_url: env.var("LOOM_API_URL")
+                .unwrap_or_else(|| "https://api.loom.com/v1".to_string()),
+            upload_chunk_size: env.positive("LOOM_UPLOAD_CHUNK_BYTES", 8388608),
+            upload_chunk_attempts: env.positive("LOOM_UPLOAD_CHUNK_ATTEMPTS", 3),
+        };
+
+        let email = EmailConfig {
+            smtp_service_url: env.var("SMTP_SERVICE_URL")
+                .unwrap_or_else(|| "http://localhost:3001".to_string()),
+            smtp_service_api_key: env.var("SMTP_SERVICE_API_KEY").filter(|key| !key.is_empty()),
+            send_attempts: env.positive("SMTP_SEND_ATTEMPTS", 3),
+            queue_url: env.var("QUEUE_PAGE_URL")
+                .unwrap_or_else(|| "http://localhost:8080/dashboard".to_string()),
+            settings_url: env.var("KEY_SETTINGS_URL")
+                .unwrap_or_else(|| "http://localhost:8080/settings".to_string()),
+        };
+
+        let transcode = TranscodeConfig {
+            mode: env.parse_with("TRANSCODE_MODE", TranscodeMode::Passthrough, |value| {
+                TranscodeMode::parse(value).ok_or_else(|| format!("TRANSCODE_MODE must be passthrough, always or off, not '{}'", value))
+            }),
+            ffmpeg_path: env.var("FFMPEG_PATH")
+                .unwrap_or_else(|| "ffmpeg".to_string()),
+            ffprobe_path: env.var("FFPROBE_PATH")
+                .unwrap_or_else(|| "ffprobe".to_string()),
+            profile: TranscodeProfile {
+                max_height: env.positive("TRANSCODE_MAX_HEIGHT", 1080),
+                video_kbps: env.positive("TRANSCODE_VIDEO_KBPS", 4000),
+                audio_kbps: env.positive("TRANSCODE_AUDIO_KBPS", 128),
+                loudnorm: env.var("TRANSCODE_LOUDNORM")
+                    .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                    .unwrap_or(false),
+                container: env.parse_with("TRANSCODE_CONTAINER", Container::Mp4, |value| {
+                    Container::parse(value).ok_or_else(|| format!("TRANSCODE_CONTAINER must be mp4 or webm, not '{}'", value))
+                }),
+            },
+        };
+
+        env.finish()?;
         Ok(WorkerConfig {
             database,
             security,
             logging,
             worker,
+            backend,
+            fathom,
+            loom,
+            email,
+            transcode,
         })
     }
+
+    /// What would stop every task from getting anywhere, for `/health/ready`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.backend.internal_api_token.is_none() {
+            return Err("INTERNAL_API_TOKEN is not set, so no user's keys can be fetched".to_string());
+        }
+        for (name, value) in [
+            ("DATABASE_URL", &self.database.url),
+            ("BACKEND_URL", &self.backend.url),
+            ("FATHOM_API_URL", &self.fathom.api_url),
+            ("LOOM_API_URL", &self.loom.api_url),
+        ] {
+            url::Url::parse(value).map_err(|e| format!("{} is not a URL: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `WORKER_JOB_TYPES`, such as `process_meeting:2,refresh_metadata`: the
+/// job types to claim, each with how many may run at once; one without a
+/// count may take all of `concurrency`
+pub fn parse_job_types(value: &str, concurrency: usize) -> Result<Vec<(JobType, usize)>, String> {
+    let mut job_types: Vec<(JobType, usize)> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (name, limit) = match entry.split_once(':') {
+            Some((name, limit)) => {
+                let limit = limit
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("WORKER_JOB_TYPES gives {} a limit that isn't a positive integer", name.trim()))?;
+                (name.trim(), limit)
+            }
+            None => (entry, concurrency.max(1)),
+        };
+        let job_type = JobType::parse(name).ok_or_else(|| {
+            let known: Vec<&str> = JobType::ALL.iter().map(JobType::as_str).collect();
+            format!("WORKER_JOB_TYPES names '{}', which is none of {}", name, known.join(", "))
+        })?;
+        if job_types.iter().any(|(listed, _)| *listed == job_type) {
+            return Err(format!("WORKER_JOB_TYPES names {} twice", job_type));
+        }
+        job_types.push((job_type, limit));
+    }
+    if job_types.is_empty() {
+        return Err("WORKER_JOB_TYPES names no job type".to_string());
+    }
+    Ok(job_types)
+}
+
+/// Longest `QUEUE_POLL_INTERVAL` taken; beyond it a worker would look idle
+/// with tasks waiting, and cancellations would go unseen for as long
+pub const MAX_POLL_INTERVAL: u64 = 3600;
+
+/// Variables read for [`WorkerConfig::from_lookup`], with what was wrong
+/// with them
+struct Env<F> {
+    lookup: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn var(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+    }
+
+    fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    /// The first of `names` that is set, noting it missing when none is
+    fn required(&mut self, names: &[&str]) -> String {
+        if let Some(value) = names.iter().find_map(|name| self.var(name).filter(|value| !value.is_empty())) {
+            return value;
+        }
+        let problem = match names {
+            [name] => format!("{} must be set", name),
+            [name, others @ ..] => format!("{} (or {}) must be set", name, others.join(" or ")),
+            [] => unreachable!("a required variable has a name"),
+        };
+        self.problem(problem);
+        String::new()
+    }
+
+    /// `name` parsed as a `T`, or `default` when unset or unparseable
+    fn parse<T: std::str::FromStr>(&mut self, name: &str, default: T) -> T {
+        self.parse_with(name, default, |value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} must be a whole number, not '{}'", name, value))
+        })
+    }
+
+    /// [`parse`](Self::parse), for counts and rates that mean nothing at 0
+    fn positive<T: std::str::FromStr + Default + PartialEq>(&mut self, name: &str, default: T) -> T {
+        self.parse_with(name, default, |value| match value.trim().parse::<T>() {
+            Ok(parsed) if parsed != T::default() => Ok(parsed),
+            _ => Err(format!("{} must be a whole number of at least 1, not '{}'", name, value)),
+        })
+    }
+
+    /// `name` as `true` or `false`, also taking `1`, `0`, `yes` and `no`
+    fn flag(&mut self, name: &str, default: bool) -> bool {
+        self.parse_with(name, default, |value| match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => Ok(true),
+            "0" | "false" | "no" => Ok(false),
+            _ => Err(format!("{} must be true or false, not '{}'", name, value)),
+        })
+    }
+
+    fn parse_with<T>(&mut self, name: &str, default: T, parse: impl FnOnce(&str) -> Result<T, String>) -> T {
+        match self.var(name) {
+            Some(value) => parse(&value).unwrap_or_else(|problem| {
+                self.problem(problem);
+                default
+            }),
+            None => default,
+        }
+    }
+
+    /// Every problem found, one per line
+    fn finish(self) -> Result<(), WorkerError> {
+        if self.problems.is_empty() {
+            return Ok(());
+        }
+        let count = match self.problems.len() {
+            1 => "1 problem".to_string(),
+            n => format!("{} problems", n),
+        };
+        let list: String = self.problems.iter().map(|problem| format!("\n  - {}", problem)).collect();
+        Err(WorkerError::Config(format!("{}:{}", count, list)))
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -117,3 +465,95 @@ impl EnvConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(overrides: &[(&'static str, &str)]) -> Result<WorkerConfig, String> {
+        let mut vars: HashMap<&str, String> = [
+            ("PB_ADMIN_PASSWORD", "pb-password"),
+            ("MASTER_KEY", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            ("PB_ENCRYPTION_KEY", "pb-encryption-key"),
+            ("WORKER_ID", "worker-test"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.to_string()))
+        .collect();
+        for (name, value) in overrides {
+            vars.insert(*name, value.to_string());
+        }
+        WorkerConfig::from_lookup(|name| vars.get(name).cloned()).map_err(|e| match e {
+            WorkerError::Config(message) => message,
+            other => panic!("not a configuration error: {}", other),
+        })
+    }
+
+    fn problem(overrides: &[(&'static str, &str)]) -> String {
+        let message = load(overrides).expect_err("configuration should be rejected");
+        message
+            .strip_prefix("1 problem:\n  - ")
+            .unwrap_or_else(|| panic!("not one problem: {}", message))
+            .to_string()
+    }
+
+    #[test]
+    fn test_valid_configuration_loads() {
+        let config = load(&[("QUEUE_POLL_INTERVAL", " 10 "), ("GLOBAL_PB_ADMIN_PW", "ignored")]).unwrap();
+        assert_eq!(config.database.admin_password, "pb-password");
+        assert_eq!(config.worker.concurrency, 1);
+        assert_eq!(config.worker.poll_interval, 10);
+        assert_eq!(config.worker.job_types, [(JobType::ProcessMeeting, 1)]);
+        assert_eq!(config.transcode.mode, TranscodeMode::Passthrough);
+        assert!(!config.worker.streaming);
+        assert!(load(&[("STREAMING_PIPELINE", "True")]).unwrap().worker.streaming);
+
+        // Either name of a secret will do
+        assert!(load(&[("PB_ADMIN_PASSWORD", ""), ("GLOBAL_PB_ADMIN_PW", "pb-password")]).is_ok());
+    }
+
+    #[test]
+    fn test_each_invalid_value_is_named() {
+        assert_eq!(problem(&[("PB_ENCRYPTION_KEY", "")]), "PB_ENCRYPTION_KEY must be set");
+        assert_eq!(problem(&[("PB_ADMIN_PASSWORD", "")]), "PB_ADMIN_PASSWORD (or GLOBAL_PB_ADMIN_PW) must be set");
+        assert_eq!(problem(&[("MASTER_KEY", "c2hvcnQ=")]), "MASTER_KEY must be 32 bytes encoded as base64");
+        assert_eq!(problem(&[("WORKER_HTTP_PORT", "70000")]), "WORKER_HTTP_PORT must be a whole number, not '70000'");
+        assert_eq!(problem(&[("RETRY_BASE_DELAY_SECS", "30s")]), "RETRY_BASE_DELAY_SECS must be a whole number, not '30s'");
+        assert_eq!(
+            problem(&[("WORKER_CONCURRENCY", "0")]),
+            "WORKER_CONCURRENCY must be a whole number of at least 1, not '0'"
+        );
+        assert_eq!(problem(&[("QUEUE_POLL_INTERVAL", "0")]), "QUEUE_POLL_INTERVAL must be from 1 to 3600 seconds, not 0");
+        assert_eq!(
+            problem(&[("QUEUE_POLL_INTERVAL", "86400")]),
+            "QUEUE_POLL_INTERVAL must be from 1 to 3600 seconds, not 86400"
+        );
+        assert_eq!(problem(&[("STREAMING_PIPELINE", "on")]), "STREAMING_PIPELINE must be true or false, not 'on'");
+        assert_eq!(problem(&[("TRANSCODE_MODE", "sometimes")]), "TRANSCODE_MODE must be passthrough, always or off, not 'sometimes'");
+        assert_eq!(problem(&[("WORKER_JOB_TYPES", "process_meeting:0")]), "WORKER_JOB_TYPES gives process_meeting a limit that isn't a positive integer");
+        assert!(problem(&[("LOG_FORMAT", "xml")]).contains("xml"));
+    }
+
+    #[test]
+    fn test_every_problem_is_reported_at_once() {
+        let message = load(&[
+            ("PB_ADMIN_PASSWORD", ""),
+            ("MASTER_KEY", ""),
+            ("WORKER_CONCURRENCY", "many"),
+            ("QUEUE_POLL_INTERVAL", "0"),
+            ("DOWNLOAD_ATTEMPTS", "-1"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            message,
+            "5 problems:\n  \
+             - PB_ADMIN_PASSWORD (or GLOBAL_PB_ADMIN_PW) must be set\n  \
+             - MASTER_KEY (or AES_MASTER_KEY) must be set\n  \
+             - WORKER_CONCURRENCY must be a whole number of at least 1, not 'many'\n  \
+             - QUEUE_POLL_INTERVAL must be from 1 to 3600 seconds, not 0\n  \
+             - DOWNLOAD_ATTEMPTS must be a whole number of at least 1, not '-1'"
+        );
+        assert!(WorkerError::Config(message).to_string().starts_with("Configuration error: 5 problems:\n"));
+    }
+}
@@ -0,0 +1,479 @@
+//! Downloading a recording's media to disk
+//!
+//! Recordings run to gigabytes and connections drop, so the media is
+//! streamed to a file that outlives a failed try: the next one asks for the
+//! rest with a `Range` request from the file's current length, and a task
+//! retried later resumes the same way. A server that ignores the range
+//! answers with the whole file, which is then written over.
+//!
+//! The finished file must be as long as the server said and, when Fathom
+//! gives one, have its SHA-256. Without one the worker's own digest is kept in
+//! the [`Download`] so the upload stage can [`verify`] the file hasn't
+//! changed since.
+//!
+//! In the streaming pipeline the media goes to a channel instead, through
+//! [`Downloader::stream`], with nothing kept for a later attempt.
+
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    time::sleep,
+};
+use tracing::warn;
+
+use crate::{retry::RetryPolicy, WorkerError, WorkerResult};
+
+/// Wait before resuming a download that stopped
+const RESUME_RETRY: RetryPolicy = RetryPolicy {
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(30),
+};
+
+/// A recording on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Hex SHA-256 of the file, Fathom's or our own
+    pub sha256: String,
+}
+
+/// How far [`Downloader::stream`] has got, across the requests it takes
+struct Streamed {
+    sent: u64,
+    hasher: Sha256,
+    reported: Option<u8>,
+}
+
+pub struct Downloader {
+    client: reqwest::Client,
+    max_bytes: u64,
+    attempts: u32,
+}
+
+impl Downloader {
+    pub fn new(client: reqwest::Client, max_bytes: u64, attempts: u32) -> Self {
+        Self {
+            client,
+            max_bytes,
+            attempts: attempts.max(1),
+        }
+    }
+
+    /// Download `url` to `dest`, carrying on from whatever `dest` holds
+    ///
+    /// `progress` is told each new whole percent received, when the size is
+    /// known. A file whose digest isn't `sha256` is deleted and fails with
+    /// [`WorkerError::Video`]; one bigger than the limit fails with
+    /// [`WorkerError::VideoTooLarge`].
+    pub async fn fetch(
+        &self,
+        url: &str,
+        dest: &Path,
+        sha256: Option<&str>,
+        progress: &(dyn Fn(u8) + Send + Sync),
+    ) -> WorkerResult<Download> {
+        let mut failures = 0;
+        let size = loop {
+            match self.resume(url, dest, progress).await {
+                Ok(size) => break size,
+                Err(e) if e.is_retryable() && failures + 1 < self.attempts => {
+                    failures += 1;
+                    warn!("Download to {} stopped, resuming: {}", dest.display(), e);
+                    sleep(RESUME_RETRY.delay(failures)).await;
+                }
+                Err(e) => {
+                    if matches!(e, WorkerError::VideoTooLarge { .. }) {
+                        discard(dest).await;
+                    }
+                    return Err(e);
+                }
+            }
+        };
+
+        let digest = sha256_of(dest).await?;
+        if let Some(expected) = sha256.filter(|expected| !expected.eq_ignore_ascii_case(&digest)) {
+            discard(dest).await;
+            return Err(WorkerError::Video(format!(
+                "The download's SHA-256 {} isn't the {} Fathom gave",
+                digest, expected
+            )));
+        }
+        progress(100);
+        Ok(Download {
+            path: dest.to_path_buf(),
+            size,
+            sha256: digest,
+        })
+    }
+
+    /// One try at getting the rest of `url` into `dest`, returning its size
+    /// once whole
+    async fn resume(&self, url: &str, dest: &Path, progress: &(dyn Fn(u8) + Send + Sync)) -> WorkerResult<u64> {
+        let have = fs::metadata(dest).await.map(|metadata| metadata.len()).unwrap_or(0);
+        let mut request = self.client.get(url);
+        if have > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", have));
+        }
+        let mut response = request.send().await.map_err(media_error)?;
+
+        let (offset, total) = match response.status() {
+            StatusCode::PARTIAL_CONTENT => match content_range(&response) {
+                Some((start, total)) if start == have => (have, total),
+                _ => return Err(WorkerError::Fathom("The media server resumed at the wrong offset".to_string())),
+            },
+            // Nothing is left to fetch
+            StatusCode::RANGE_NOT_SATISFIABLE if have > 0 => match content_range(&response) {
+                Some((_, Some(total))) if total == have => return Ok(have),
+                _ => {
+                    discard(dest).await;
+                    return Err(WorkerError::Fathom("The media server refused to resume".to_string()));
+                }
+            },
+            status if status.is_success() => (0, response.content_length()),
+            status => return Err(WorkerError::Fathom(format!("The media server returned {}", status.as_u16()))),
+        };
+        if let Some(size) = total.filter(|size| *size > self.max_bytes) {
+            return Err(WorkerError::VideoTooLarge { size, limit: self.max_bytes });
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(dest).await?;
+        if offset == 0 {
+            file.set_len(0).await?;
+        }
+        let mut received = offset;
+        let mut reported = total.map(|total| percent(received, total));
+        let result = async {
+            while let Some(chunk) = response.chunk().await.map_err(media_error)? {
+                received += chunk.len() as u64;
+                if received > self.max_bytes {
+                    return Err(WorkerError::VideoTooLarge { size: received, limit: self.max_bytes });
+                }
+                file.write_all(&chunk).await?;
+                if let Some(total) = total {
+                    let now = percent(received, total);
+                    if reported != Some(now) {
+                        reported = Some(now);
+                        progress(now);
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+        // What did arrive is kept for the next try
+        file.flush().await?;
+        result?;
+
+        match total {
+            Some(total) if received != total => Err(WorkerError::Fathom(format!(
+                "The download ended at {} of {} bytes",
+                received, total
+            ))),
+            _ => Ok(received),
+        }
+    }
+
+    /// Send `url` on to `chunks` as it arrives, returning its size
+    ///
+    /// A response that stops is asked for again from the bytes already
+    /// sent, as [`fetch`](Self::fetch) resumes from its file. As nothing is
+    /// kept, a server that answers that with the whole file fails the
+    /// download. `chunks` is only dropped once the digest checks out, so the
+    /// stage reading it never takes a corrupt download's end for the end of
+    /// the recording; while it is full the download waits.
+    pub async fn stream(
+        &self,
+        url: &str,
+        sha256: Option<&str>,
+        chunks: mpsc::Sender<Vec<u8>>,
+        progress: &(dyn Fn(u8) + Send + Sync),
+    ) -> WorkerResult<u64> {
+        let mut streamed = Streamed { sent: 0, hasher: Sha256::new(), reported: None };
+        let mut failures = 0;
+        loop {
+            match self.stream_rest(url, &chunks, &mut streamed, progress).await {
+                Ok(()) => break,
+                Err(e) if e.is_retryable() && failures + 1 < self.attempts => {
+                    failures += 1;
+                    warn!("Download stream stopped {} bytes in, resuming: {}", streamed.sent, e);
+                    sleep(RESUME_RETRY.delay(failures)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let digest = hex(&streamed.hasher.finalize());
+        if let Some(expected) = sha256.filter(|expected| !expected.eq_ignore_ascii_case(&digest)) {
+            return Err(WorkerError::Video(format!(
+                "The download's SHA-256 {} isn't the {} Fathom gave",
+                digest, expected
+            )));
+        }
+        progress(100);
+        drop(chunks);
+        Ok(streamed.sent)
+    }
+
+    /// One request for what of `url` hasn't been sent on to `chunks` yet
+    async fn stream_rest(
+        &self,
+        url: &str,
+        chunks: &mpsc::Sender<Vec<u8>>,
+        streamed: &mut Streamed,
+        progress: &(dyn Fn(u8) + Send + Sync),
+    ) -> WorkerResult<()> {
+        let mut request = self.client.get(url);
+        if streamed.sent > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", streamed.sent));
+        }
+        let mut response = request.send().await.map_err(media_error)?;
+        let total = match response.status() {
+            StatusCode::PARTIAL_CONTENT => match content_range(&response) {
+                Some((start, total)) if start == streamed.sent => total,
+                _ => return Err(WorkerError::Fathom("The media server resumed at the wrong offset".to_string())),
+            },
+            status if status.is_success() && streamed.sent == 0 => response.content_length(),
+            status if status.is_success() => {
+                return Err(WorkerError::Fathom("The media server started the recording over".to_string()))
+            }
+            status => return Err(WorkerError::Fathom(format!("The media server returned {}", status.as_u16()))),
+        };
+        if let Some(size) = total.filter(|size| *size > self.max_bytes) {
+            return Err(WorkerError::VideoTooLarge { size, limit: self.max_bytes });
+        }
+
+        while let Some(chunk) = response.chunk().await.map_err(media_error)? {
+            let received = streamed.sent + chunk.len() as u64;
+            if received > self.max_bytes {
+                return Err(WorkerError::VideoTooLarge { size: received, limit: self.max_bytes });
+            }
+            streamed.hasher.update(&chunk);
+            chunks
+                .send(chunk.to_vec())
+                .await
+                .map_err(|_| WorkerError::Internal("The recording's next stage stopped reading it".to_string()))?;
+            streamed.sent = received;
+            if let Some(total) = total {
+                let now = percent(received, total);
+                if streamed.reported != Some(now) {
+                    streamed.reported = Some(now);
+                    progress(now);
+                }
+            }
+        }
+        match total {
+            Some(total) if streamed.sent != total => Err(WorkerError::Fathom(format!(
+                "The download ended at {} of {} bytes",
+                streamed.sent, total
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check that `download` is still the file that was downloaded
+pub async fn verify(download: &Download) -> WorkerResult<()> {
+    let size = fs::metadata(&download.path).await?.len();
+    if size != download.size || sha256_of(&download.path).await? != download.sha256 {
+        return Err(WorkerError::Video(format!(
+            "{} changed after it was downloaded",
+            download.path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Hex SHA-256 of the file at `path`
+pub async fn sha256_of(path: &Path) -> WorkerResult<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The start and total of a `Content-Range: bytes <start>-<end>/<total>`
+/// (or `bytes */<total>`), the total being `*` when unknown
+fn content_range(response: &reqwest::Response) -> Option<(u64, Option<u64>)> {
+    let value = response.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = match range {
+        "*" => 0,
+        range => range.split_once('-')?.0.parse().ok()?,
+    };
+    Some((start, total.parse().ok()))
+}
+
+fn percent(received: u64, total: u64) -> u8 {
+    (received * 100).checked_div(total).map_or(100, |percent| percent.min(100) as u8)
+}
+
+fn media_error(e: reqwest::Error) -> WorkerError {
+    // The URL is signed; keep it out of errors and logs
+    WorkerError::Fathom(format!("Media download failed: {}", e.without_url()))
+}
+
+async fn discard(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockMedia;
+    use std::sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    };
+
+    /// 10 000 bytes that tell their offsets apart
+    fn media() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i % 253) as u8).collect()
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_a_dropped_download_resumes_byte_exact() {
+        let bytes = media();
+        // The first two responses stop 3000 bytes in
+        let server = MockMedia::start(bytes.clone(), 2, 3000).await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("meeting.mp4");
+        let percents = Mutex::new(Vec::new());
+
+        let download = Downloader::new(reqwest::Client::new(), 1 << 20, 3)
+            .fetch(&server.url, &dest, Some(&sha256(&bytes).to_uppercase()), &|percent| {
+                percents.lock().unwrap().push(percent)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), bytes);
+        assert_eq!(download.size, 10_000);
+        assert_eq!(download.sha256, sha256(&bytes));
+        assert_eq!(server.ranges(), [None, Some("bytes=3000-".to_string()), Some("bytes=6000-".to_string())]);
+        let percents = percents.into_inner().unwrap();
+        assert!(percents.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", percents);
+        assert_eq!(percents.last(), Some(&100));
+
+        verify(&download).await.unwrap();
+        std::fs::write(&dest, &bytes[..9_999]).unwrap();
+        assert!(matches!(verify(&download).await, Err(WorkerError::Video(_))));
+    }
+
+    #[tokio::test]
+    async fn test_a_checksum_mismatch_fails_for_good() {
+        let server = MockMedia::start(media(), 0, 0).await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("meeting.mp4");
+
+        // Without Fathom's digest our own is kept
+        let download = Downloader::new(reqwest::Client::new(), 1 << 20, 3)
+            .fetch(&server.url, &dest, None, &|_| {})
+            .await
+            .unwrap();
+        assert_eq!(download.sha256, sha256(&media()));
+
+        std::fs::remove_file(&dest).unwrap();
+        let error = Downloader::new(reqwest::Client::new(), 1 << 20, 3)
+            .fetch(&server.url, &dest, Some(&"0".repeat(64)), &|_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(error, WorkerError::Video(_)));
+        assert!(!error.is_retryable());
+        assert!(!dest.exists(), "a corrupt download isn't resumed");
+    }
+
+    /// Everything `chunks` is sent, once its sender is gone
+    async fn collected(mut chunks: mpsc::Receiver<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = chunks.recv().await {
+            bytes.extend(chunk);
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_a_streamed_download_resumes_byte_exact() {
+        let bytes = media();
+        let server = MockMedia::start(bytes.clone(), 2, 3000).await;
+        let downloader = Downloader::new(reqwest::Client::new(), 1 << 20, 3);
+
+        let digest = sha256(&bytes);
+        let (chunks, received) = mpsc::channel(4);
+        let (size, streamed) = tokio::join!(downloader.stream(&server.url, Some(&digest), chunks, &|_| {}), collected(received));
+        assert_eq!(size.unwrap(), 10_000);
+        assert_eq!(streamed, bytes);
+        assert_eq!(server.ranges(), [None, Some("bytes=3000-".to_string()), Some("bytes=6000-".to_string())]);
+
+        let wrong = "0".repeat(64);
+        let (chunks, received) = mpsc::channel(4);
+        let (error, _) = tokio::join!(downloader.stream(&server.url, Some(&wrong), chunks, &|_| {}), collected(received));
+        assert!(matches!(error, Err(WorkerError::Video(_))));
+    }
+
+    #[tokio::test]
+    async fn test_a_streamed_download_waits_for_a_slow_reader() {
+        let bytes: Vec<u8> = (0..32u32 << 20).map(|i| (i % 251) as u8).collect();
+        let server = MockMedia::start(bytes.clone(), 0, 0).await;
+        let reported = Arc::new(AtomicU8::new(0));
+        let (chunks, received) = mpsc::channel(2);
+        let download = tokio::spawn({
+            let (url, reported) = (server.url.clone(), reported.clone());
+            async move {
+                let downloader = Downloader::new(reqwest::Client::new(), 1 << 30, 1);
+                downloader.stream(&url, None, chunks, &|percent| reported.store(percent, Ordering::SeqCst)).await
+            }
+        });
+
+        // Nothing reads, so little more than the channel's two chunks and
+        // the socket's buffers come in
+        sleep(Duration::from_millis(500)).await;
+        assert!(!download.is_finished());
+        let stalled_at = reported.load(Ordering::SeqCst);
+        assert!(stalled_at <= 50, "{}% downloaded with nothing read", stalled_at);
+
+        assert_eq!(collected(received).await, bytes);
+        assert_eq!(download.await.unwrap().unwrap(), bytes.len() as u64);
+        assert_eq!(reported.load(Ordering::SeqCst), 100);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_recordings_are_refused() {
+        let server = MockMedia::start(media(), 0, 0).await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("meeting.mp4");
+
+        let error = Downloader::new(reqwest::Client::new(), 5_000, 3)
+            .fetch(&server.url, &dest, None, &|_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(error, WorkerError::VideoTooLarge { size: 10_000, limit: 5_000 }));
+        assert!(!error.is_retryable());
+        assert_eq!(server.ranges().len(), 1, "not tried again");
+        assert!(!dest.exists());
+    }
+}
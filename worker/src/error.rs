@@ -1,3 +1,6 @@
+use common::fathom::DownloadReason;
+use std::time::Duration;
+
 #[derive(Debug, thiserror::Error)]
 pub enum WorkerError {
     #[error("Queue error: {0}")]
@@ -30,8 +33,44 @@ pub enum WorkerError {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("No {service} API key is stored; add one in Settings")]
+    KeyMissing { service: String },
+
+    #[error("The {service} API key \"{key_id}\" expired at {expired_at}; add a new one in Settings")]
+    KeyExpired {
+        service: String,
+        key_id: String,
+        expired_at: chrono::DateTime<chrono::Utc>,
+    },
+
+    /// Fathom won't give up the recording, for a reason only the user can fix
+    #[error("{} (recording {meeting_id})", .reason.describe())]
+    FathomRefused {
+        meeting_id: String,
+        reason: DownloadReason,
+    },
+
+    /// ffprobe or ffmpeg can't read the recording, with the last of what it said
+    #[error("The recording can't be read as video: {stderr_tail}")]
+    InvalidMedia { stderr_tail: String },
+
+    #[error("The recording is {size} bytes, more than the {limit} allowed")]
+    VideoTooLarge { size: u64, limit: u64 },
+
+    /// A pipeline stage, or the whole task, ran past its time limit
+    #[error("stage timeout: {stage} took longer than {}s", .limit.as_secs())]
+    StageTimeout { stage: &'static str, limit: Duration },
+
     #[error("Internal error: {0}")]
     Internal(String),
+
+    /// The worker is stopping; the task goes back to the queue unfinished
+    #[error("The worker stopped before the task finished")]
+    ShuttingDown,
+
+    /// The task's owner asked for it to stop
+    #[error("The task was cancelled")]
+    Cancelled,
 }
 
 pub type WorkerResult<T> = Result<T, WorkerError>;
@@ -49,7 +88,15 @@ impl WorkerError {
             WorkerError::Io(_) => true,
             WorkerError::Serde(_) => false,
             WorkerError::Config(_) => false,
+            WorkerError::KeyMissing { .. } => false, // Only the user can add the key
+            WorkerError::KeyExpired { .. } => false, // Only the user can replace the key
+            WorkerError::FathomRefused { .. } => false, // Asking again gets the same answer
+            WorkerError::InvalidMedia { .. } => false, // The same bytes are downloaded again
+            WorkerError::VideoTooLarge { .. } => false, // It won't shrink
+            WorkerError::StageTimeout { .. } => true, // A hung upstream may have recovered
             WorkerError::Internal(_) => false,
+            WorkerError::ShuttingDown => true,
+            WorkerError::Cancelled => false,
         }
     }
 }
@@ -0,0 +1,222 @@
+//! Which pending task a worker claims next
+//!
+//! Queue items carry a `priority`, higher first, and the highest band
+//! waiting is always claimed from first. Within a band, claims go round the
+//! users rather than strictly oldest first, so one user queueing a backlog
+//! doesn't hold everyone else's meetings behind it: among the band's oldest
+//! `window` items, each user's oldest is tried in turn, the user served
+//! longest ago first. A window of 1 is plain FIFO.
+//!
+//! When each user was last served is kept in the worker's state file, so a
+//! restart doesn't hand the next claims back to whoever was served last.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+use crate::queue::QueueTask;
+
+/// Items of a band looked across for other users' tasks
+pub const DEFAULT_WINDOW: usize = 50;
+
+/// What the state file holds
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    last_served: HashMap<String, DateTime<Utc>>,
+}
+
+/// The order a worker tries to claim a priority band in
+#[derive(Debug)]
+pub struct Fairness {
+    window: usize,
+    /// Unset, nothing outlasts the process
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+impl Fairness {
+    /// Oldest first within each band, remembering no one
+    pub fn fifo() -> Self {
+        Self {
+            window: 1,
+            path: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Round the users of each band's oldest `window` items, with who was
+    /// served when kept at `path`
+    ///
+    /// A missing file starts with no one served; an unreadable one is warned
+    /// of and then replaced.
+    pub fn load(path: &Path, window: usize) -> Self {
+        let state = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Ignoring the unreadable worker state in {}: {}", path.display(), e);
+                State::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => {
+                warn!("Failed to read the worker state in {}: {}", path.display(), e);
+                State::default()
+            }
+        };
+        Self {
+            window: window.max(1),
+            path: Some(path.to_path_buf()),
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// `band`, one priority's items oldest first, in the order to try
+    /// claiming them
+    ///
+    /// Within the window each user's items keep their age order, and the
+    /// users take turns, starting with the one served longest ago or never;
+    /// ties go to whoever's oldest item is older. Items past the window
+    /// follow in age order.
+    pub fn order(&self, mut band: Vec<QueueTask>) -> Vec<QueueTask> {
+        let Some(priority) = band.first().map(|task| task.priority) else {
+            return band;
+        };
+        if self.window == 1 || band.len() == 1 {
+            debug!(priority, "Claiming the oldest of {} items in age order", band.len());
+            return band;
+        }
+        let rest = band.split_off(self.window.min(band.len()));
+
+        let mut users: Vec<(String, VecDeque<QueueTask>)> = Vec::new();
+        for task in band {
+            match users.iter_mut().find(|(user_id, _)| *user_id == task.user_id) {
+                Some((_, tasks)) => tasks.push_back(task),
+                None => users.push((task.user_id.clone(), VecDeque::from([task]))),
+            }
+        }
+        let state = self.state();
+        // Stable, so users served alike stay in order of their oldest item
+        users.sort_by_key(|(user_id, _)| state.last_served.get(user_id).copied());
+        let rationale: Vec<String> = users
+            .iter()
+            .map(|(user_id, tasks)| {
+                let served = match state.last_served.get(user_id) {
+                    Some(at) => format!("last served {}", at.to_rfc3339()),
+                    None => "never served".to_string(),
+                };
+                format!("{} ({} waiting, {})", user_id, tasks.len(), served)
+            })
+            .collect();
+        drop(state);
+        debug!(
+            priority,
+            "Claiming round {} users, {}; {} older items after",
+            users.len(),
+            rationale.join(", "),
+            rest.len()
+        );
+
+        let mut ordered = Vec::new();
+        while users.iter().any(|(_, tasks)| !tasks.is_empty()) {
+            ordered.extend(users.iter_mut().filter_map(|(_, tasks)| tasks.pop_front()));
+        }
+        ordered.extend(rest);
+        ordered
+    }
+
+    /// Note that a task of `user_id` was claimed `at`, keeping it in the
+    /// state file
+    pub fn served(&self, user_id: &str, at: DateTime<Utc>) {
+        let mut state = self.state();
+        state.last_served.insert(user_id.to_string(), at);
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Err(e) = write_state(path, &state) {
+            warn!("Failed to keep the worker state in {}: {}", path.display(), e);
+        }
+    }
+
+    /// When `user_id` last had a task claimed
+    pub fn last_served(&self, user_id: &str) -> Option<DateTime<Utc>> {
+        self.state().last_served.get(user_id).copied()
+    }
+}
+
+/// Replace the state file whole, so a crash leaves the old one or the new
+fn write_state(path: &Path, state: &State) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, serde_json::to_vec_pretty(state)?)?;
+    std::fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(user_id: &str, second: i64) -> QueueTask {
+        let created_at = DateTime::parse_from_rfc3339("2026-03-01T09:00:00Z").unwrap().with_timezone(&Utc)
+            + chrono::Duration::seconds(second);
+        QueueTask {
+            user_id: user_id.to_string(),
+            topic: format!("{}-{}", user_id, second),
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    fn topics(tasks: &[QueueTask]) -> Vec<&str> {
+        tasks.iter().map(|task| task.topic.as_str()).collect()
+    }
+
+    #[test]
+    fn test_users_take_turns_within_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let fairness = Fairness::load(&dir.path().join("worker_state.json"), 5);
+        let band = vec![task("alice", 0), task("alice", 1), task("alice", 2), task("bob", 3), task("bob", 4), task("carol", 5)];
+
+        // Carol's item is past the window, so it waits its turn by age
+        assert_eq!(topics(&fairness.order(band.clone())), ["alice-0", "bob-3", "alice-1", "bob-4", "alice-2", "carol-5"]);
+
+        let now = Utc::now();
+        fairness.served("alice", now);
+        assert_eq!(topics(&fairness.order(band.clone()))[..2], ["bob-3", "alice-0"]);
+        fairness.served("bob", now + chrono::Duration::seconds(1));
+        assert_eq!(topics(&fairness.order(band.clone()))[..2], ["alice-0", "bob-3"]);
+
+        assert_eq!(topics(&Fairness::fifo().order(band.clone())), topics(&band));
+    }
+
+    #[test]
+    fn test_who_was_served_outlasts_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join("worker_state.json");
+        let at = Utc::now();
+        Fairness::load(&path, 10).served("alice", at);
+
+        let restarted = Fairness::load(&path, 10);
+        assert_eq!(restarted.last_served("alice"), Some(at));
+        assert_eq!(topics(&restarted.order(vec![task("alice", 0), task("bob", 1)])), ["bob-1", "alice-0"]);
+
+        std::fs::write(&path, b"{not json").unwrap();
+        let reset = Fairness::load(&path, 10);
+        assert_eq!(reset.last_served("alice"), None);
+        reset.served("bob", at);
+        assert_eq!(Fairness::load(&path, 10).last_served("bob"), Some(at));
+    }
+}
@@ -0,0 +1,248 @@
+//! Client for the Fathom external API, as the worker uses it
+//!
+//! It mirrors the backend's client for the one thing a task asks of Fathom:
+//! what a recording is and where its media is. The key is sent as
+//! `X-Api-Key` and held as a [`SecretString`], and the payload is read with
+//! [`common::fathom::wire`] so both sides read a recording the same way.
+//!
+//! A recording that is gone, or a key Fathom turns down, fails the task with
+//! [`WorkerError::FathomRefused`], which retrying doesn't help. Outages and
+//! rate limiting stay [`WorkerError::Fathom`], which it does.
+//!
+//! With a [limiter](FathomClient::with_limiter), each request first waits for
+//! a token from the process's [`TokenBucket`], and a `Retry-After` Fathom
+//! sends with a 429 or 503 drains it for every task.
+
+use common::fathom::{wire, DownloadReason, FathomMeeting};
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::{keys::SecretString, metrics, rate_limit::TokenBucket, WorkerError, WorkerResult};
+
+/// How long one request to Fathom may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the pipeline knows of a task's recording once its metadata is in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeetingArtifacts {
+    pub title: String,
+    /// Length of the recording in seconds
+    pub duration: u32,
+    /// Signed URL of the recording's media; it expires, so isn't kept
+    #[serde(skip)]
+    pub download_url: String,
+    /// Size of the media in bytes, when Fathom says
+    pub size_bytes: Option<u64>,
+    /// Hex SHA-256 of the media, when Fathom says
+    pub sha256: Option<String>,
+    /// Whether Fathom has finished transcribing the recording
+    pub transcript_available: bool,
+}
+
+/// Fathom's API as one user, whose key stays with the task that lent it
+pub struct FathomClient<'a> {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: &'a SecretString,
+    limiter: Option<&'a TokenBucket>,
+}
+
+impl<'a> FathomClient<'a> {
+    pub fn new(client: reqwest::Client, base_url: &str, api_key: &'a SecretString) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            limiter: None,
+        }
+    }
+
+    /// Pace requests with `limiter`, shared with the process's other tasks
+    pub fn with_limiter(mut self, limiter: &'a TokenBucket) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// The metadata and media of recording `meeting_id`
+    pub async fn artifacts(&self, meeting_id: &str) -> WorkerResult<MeetingArtifacts> {
+        let refused = |reason| WorkerError::FathomRefused { meeting_id: meeting_id.to_string(), reason };
+        let response = self.send(self.client.get(self.recording_url(meeting_id, None)?)).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Err(refused(DownloadReason::NotFound)),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Err(refused(DownloadReason::Forbidden)),
+            _ => {}
+        }
+        let body = response.bytes().await?;
+        let payload: wire::Meeting = serde_json::from_slice(&body)
+            .map_err(|e| WorkerError::Fathom(format!("Unexpected recording payload: {}", e)))?;
+
+        let download_url = payload
+            .download_url()
+            .map(str::to_string)
+            .ok_or_else(|| refused(DownloadReason::NoDownload))?;
+        let size_bytes = payload.size_bytes();
+        let sha256 = payload.sha256().map(str::to_string);
+        let meeting = FathomMeeting::try_from(payload).map_err(|e| WorkerError::Fathom(e.0))?;
+        Ok(MeetingArtifacts {
+            title: meeting.title,
+            duration: meeting.duration,
+            download_url,
+            size_bytes,
+            sha256,
+            transcript_available: self.transcript_available(meeting_id).await,
+        })
+    }
+
+    /// Whether the transcript of `meeting_id` is ready, asked without
+    /// fetching it; asking failing only means it isn't known to be
+    async fn transcript_available(&self, meeting_id: &str) -> bool {
+        let Ok(url) = self.recording_url(meeting_id, Some("transcript")) else {
+            return false;
+        };
+        match self.send(self.client.head(url)).await {
+            // 202 while Fathom is still transcribing
+            Ok(response) => response.status() == StatusCode::OK,
+            Err(e) => {
+                debug!("Could not tell whether recording {} has a transcript: {}", meeting_id, e);
+                false
+            }
+        }
+    }
+
+    /// `{base}/recordings/<id>`, then `/<sub>`, with `id` escaped as one segment
+    fn recording_url(&self, id: &str, sub: Option<&str>) -> WorkerResult<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&format!("{}/recordings", self.base_url))
+            .map_err(|e| WorkerError::Config(format!("FATHOM_API_URL is not a URL: {}", e)))?;
+        url.path_segments_mut()
+            .map_err(|_| WorkerError::Config("FATHOM_API_URL can't have a path".to_string()))?
+            .push(id)
+            .extend(sub);
+        Ok(url)
+    }
+
+    /// Send `request` with the key; statuses other than the refusals
+    /// [`artifacts`](Self::artifacts) reads are errors
+    async fn send(&self, request: reqwest::RequestBuilder) -> WorkerResult<reqwest::Response> {
+        if let Some(limiter) = self.limiter {
+            limiter.acquire().await;
+        }
+        let response = request
+            .header("X-Api-Key", self.api_key.expose_secret())
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| WorkerError::Fathom(e.without_url().to_string()))?;
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(response),
+            status if status.is_success() => Ok(response),
+            status => {
+                if matches!(status, StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) {
+                    metrics::increment(&metrics::FATHOM_RATE_LIMITED, &[("status", status.as_str())]);
+                    if let (Some(limiter), Some(wait)) = (self.limiter, retry_after(&response)) {
+                        limiter.drain(wait).await;
+                    }
+                }
+                Err(WorkerError::Fathom(format!("Fathom returned {}", status.as_u16())))
+            }
+        }
+    }
+}
+
+/// The wait `response` asks for in seconds in its `Retry-After` header
+///
+/// Fathom names seconds; an HTTP date is treated as no header at all.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_fathom, spawn_server, FATHOM_KEY as KEY};
+    use axum::{http, routing::get, Router};
+    use once_cell::sync::Lazy;
+
+    static SECRET: Lazy<SecretString> = Lazy::new(|| SecretString::new(KEY.to_string()));
+
+    fn client<'a>(url: &str, key: &'a SecretString) -> FathomClient<'a> {
+        FathomClient::new(reqwest::Client::new(), url, key)
+    }
+
+    #[tokio::test]
+    async fn test_a_recording_gives_its_artifacts() {
+        let url = mock_fathom().await;
+
+        let artifacts = client(&url, &SECRET).artifacts("42").await.unwrap();
+        assert_eq!(
+            artifacts,
+            MeetingArtifacts {
+                title: "Weekly sync".to_string(),
+                duration: 45 * 60 + 30,
+                download_url: "https://media.example.com/42.mp4?signature=abc".to_string(),
+                size_bytes: Some(734003200),
+                sha256: None,
+                transcript_available: true,
+            }
+        );
+
+        let artifacts = client(&url, &SECRET).artifacts("43").await.unwrap();
+        assert!(!artifacts.transcript_available, "a transcript still being made isn't available");
+    }
+
+    #[tokio::test]
+    async fn test_a_deleted_recording_fails_for_good() {
+        let url = mock_fathom().await;
+
+        let error = client(&url, &SECRET).artifacts("7").await.unwrap_err();
+        assert!(matches!(
+            &error,
+            WorkerError::FathomRefused { meeting_id, reason: DownloadReason::NotFound } if meeting_id == "7"
+        ));
+        assert!(!error.is_retryable());
+        assert_eq!(error.to_string(), "The recording no longer exists in Fathom (recording 7)");
+    }
+
+    #[tokio::test]
+    async fn test_a_revoked_key_fails_for_good() {
+        let url = mock_fathom().await;
+
+        let revoked = SecretString::new("revoked-key".to_string());
+        let error = client(&url, &revoked).artifacts("42").await.unwrap_err();
+        assert!(matches!(error, WorkerError::FathomRefused { reason: DownloadReason::Forbidden, .. }));
+        assert!(!error.is_retryable());
+        assert!(!format!("{:?}", error).contains("revoked-key"));
+
+        // Fathom being down is worth trying again
+        let error = client("http://127.0.0.1:9", &SECRET).artifacts("42").await.unwrap_err();
+        assert!(matches!(error, WorkerError::Fathom(_)));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_a_retry_after_holds_back_the_other_tasks() {
+        let app = Router::new().route(
+            "/recordings/:id",
+            get(|| async { (http::StatusCode::TOO_MANY_REQUESTS, [("retry-after", "120")]) }),
+        );
+        let url = spawn_server(app).await;
+        let limiter = TokenBucket::new(60, 5);
+        let limited = metrics::registry().value(&metrics::FATHOM_RATE_LIMITED, &[("status", "429")]);
+
+        let error = client(&url, &SECRET).with_limiter(&limiter).artifacts("42").await.unwrap_err();
+        assert!(matches!(&error, WorkerError::Fathom(message) if message == "Fathom returned 429"));
+        assert!(error.is_retryable());
+        let paused_for = limiter.paused_for().await.unwrap();
+        assert!(paused_for > Duration::from_secs(110) && paused_for <= Duration::from_secs(120));
+        assert!(metrics::registry().value(&metrics::FATHOM_RATE_LIMITED, &[("status", "429")]) >= limited + 1.0);
+
+        // Without a limiter there's nothing to hold back
+        client(&url, &SECRET).artifacts("42").await.unwrap_err();
+    }
+}
@@ -0,0 +1,376 @@
+//! Telling the backend this worker is alive and what it is doing
+//!
+//! Every [`INTERVAL`] the worker POSTs a [`WorkerHeartbeat`] to the
+//! backend's `/internal/heartbeat`: idle or processing, the task running
+//! longest with the stage and percent it last reported, its version and
+//! uptime. What it is doing is read off its own broadcast service, so the
+//! pipeline knows nothing of heartbeats, and they are sent from a task of
+//! their own, so a backend that is down or slow only costs missed beats. On
+//! shutdown a last `offline` beat tells the backend not to wait for more.
+//!
+//! The id in the beats is [`persisted_worker_id`], so a restarted worker is
+//! recognised as the same one.
+
+use common::{
+    broadcast::{BroadcastService, ProcessingStage, ProgressUpdate, QueueUpdate, QueueUpdateType},
+    WorkerHeartbeat, WorkerState,
+};
+use std::{io, path::Path, time::Duration};
+use tokio::{
+    sync::{broadcast, oneshot},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{config::BackendConfig, keys};
+
+/// How often a heartbeat is sent
+pub const INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest one heartbeat may take before it is given up on
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The id stored at `path`, or a new `worker-xxxxxxxx` stored there for the
+/// next run
+pub fn persisted_worker_id(path: &Path) -> io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(id) if !id.trim().is_empty() => return Ok(id.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let id = format!("worker-{}", &Uuid::new_v4().simple().to_string()[..8]);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format!("{}\n", id))?;
+    Ok(id)
+}
+
+/// The tasks running here, oldest first, with the stage and percent each
+/// last reported
+#[derive(Debug, Default)]
+pub struct Activity {
+    tasks: Vec<(Uuid, Option<(ProcessingStage, u8)>)>,
+}
+
+impl Activity {
+    /// Start or stop tracking the task `update` is about
+    pub fn apply_queue(&mut self, update: &QueueUpdate) {
+        let Some(task_id) = update.task_id else {
+            return;
+        };
+        match update.update_type {
+            QueueUpdateType::TaskStarted => {
+                if !self.tasks.iter().any(|(id, _)| *id == task_id) {
+                    self.tasks.push((task_id, None));
+                }
+            }
+            // Finished, failed, retried later or handed back at shutdown
+            QueueUpdateType::TaskCompleted
+            | QueueUpdateType::TaskFailed
+            | QueueUpdateType::TaskRetried
+            | QueueUpdateType::TaskCancelled
+            | QueueUpdateType::PositionUpdated => self.tasks.retain(|(id, _)| *id != task_id),
+            QueueUpdateType::QueueCleared => self.tasks.clear(),
+        }
+    }
+
+    pub fn apply_progress(&mut self, update: &ProgressUpdate) {
+        if let Some((_, progress)) = self.tasks.iter_mut().find(|(id, _)| *id == update.task_id) {
+            *progress = Some((update.stage, update.percent));
+        }
+    }
+
+    /// A heartbeat of `worker_id`, up for `uptime`, saying what is running now
+    pub fn heartbeat(&self, worker_id: &str, uptime: Duration) -> WorkerHeartbeat {
+        let current = self.tasks.first();
+        WorkerHeartbeat {
+            worker_id: worker_id.to_string(),
+            status: if current.is_some() { WorkerState::Processing } else { WorkerState::Idle },
+            task_id: current.map(|(id, _)| *id),
+            stage: current.and_then(|(_, progress)| progress.map(|(stage, _)| stage)),
+            percent: current.and_then(|(_, progress)| progress.map(|(_, percent)| percent)),
+            active_tasks: self.tasks.len(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: uptime.as_secs(),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+/// The running heartbeat, stopped with [`Heartbeat::stop`]
+pub struct Heartbeat {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    /// Send `worker_id`'s heartbeat every `interval`, the first right away
+    pub fn start(
+        broadcast_service: &BroadcastService,
+        client: reqwest::Client,
+        backend: BackendConfig,
+        worker_id: String,
+        interval: Duration,
+    ) -> Self {
+        let mut updates = broadcast_service.subscribe();
+        let mut progress = broadcast_service.subscribe_progress();
+        let (stop, mut stopping) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let token = match keys::internal_token(&backend) {
+                Ok(token) => token.to_string(),
+                Err(e) => {
+                    warn!("This worker won't show on the dashboard: {}", e);
+                    return;
+                }
+            };
+            let url = format!("{}/internal/heartbeat", backend.url.trim_end_matches('/'));
+            let send = |beat: WorkerHeartbeat| {
+                let request = client.post(&url).bearer_auth(&token).timeout(SEND_TIMEOUT).json(&beat);
+                async move { request.send().await.and_then(|response| response.error_for_status()).map(drop) }
+            };
+
+            let started = Instant::now();
+            let mut activity = Activity::default();
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut reachable = true;
+            loop {
+                // A task's start is sent before its progress and its end
+                // after, so queue updates go first to keep them in order
+                tokio::select! {
+                    biased;
+                    _ = &mut stopping => break,
+                    update = updates.recv() => match update {
+                        Ok(update) => activity.apply_queue(&update),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Heartbeat lagged, skipped {} queue updates", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    update = progress.recv() => match update {
+                        Ok(update) => activity.apply_progress(&update),
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = ticks.tick() => {
+                        let beat = activity.heartbeat(&worker_id, started.elapsed());
+                        match send(beat).await {
+                            Ok(()) if !reachable => {
+                                info!("Heartbeats are reaching the backend again");
+                                reachable = true;
+                            }
+                            Ok(()) => {}
+                            // Once is enough to say so while the backend stays away
+                            Err(e) if reachable => {
+                                warn!("Failed to send a heartbeat: {}", e.without_url());
+                                reachable = false;
+                            }
+                            Err(e) => debug!("Failed to send a heartbeat: {}", e.without_url()),
+                        }
+                    }
+                }
+            }
+
+            let mut beat = activity.heartbeat(&worker_id, started.elapsed());
+            beat.status = WorkerState::Offline;
+            match send(beat).await {
+                Ok(()) => info!("Told the backend this worker is offline"),
+                Err(e) => warn!("Failed to tell the backend this worker is offline: {}", e.without_url()),
+            }
+        });
+        Self { stop, task }
+    }
+
+    /// Stop beating, once the backend has been told this worker is offline
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        if let Err(e) = self.task.await {
+            warn!("Heartbeat task failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{spawn_server, INTERNAL_TOKEN};
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+    use common::broadcast::BroadcastServiceFactory;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    #[derive(Clone, Default)]
+    struct Beats {
+        received: Arc<Mutex<Vec<WorkerHeartbeat>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Beats {
+        fn received(&self) -> Vec<WorkerHeartbeat> {
+            self.received.lock().unwrap().clone()
+        }
+
+        /// Wait for a beat past the first `seen` that `accept` takes
+        async fn next(&self, seen: usize, accept: impl Fn(&WorkerHeartbeat) -> bool) -> WorkerHeartbeat {
+            for _ in 0..200 {
+                if let Some(beat) = self.received().into_iter().skip(seen).find(|beat| accept(beat)) {
+                    return beat;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("no such heartbeat among {:?}", self.received());
+        }
+    }
+
+    async fn mock_backend(beats: Beats) -> BackendConfig {
+        async fn receive(State(beats): State<Beats>, headers: HeaderMap, Json(beat): Json<WorkerHeartbeat>) -> StatusCode {
+            if headers.get("authorization").and_then(|value| value.to_str().ok())
+                != Some(&format!("Bearer {}", INTERNAL_TOKEN))
+            {
+                return StatusCode::UNAUTHORIZED;
+            }
+            if beats.down.load(Ordering::SeqCst) {
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+            beats.received.lock().unwrap().push(beat);
+            StatusCode::NO_CONTENT
+        }
+        let app = Router::new().route("/internal/heartbeat", post(receive)).with_state(beats);
+        BackendConfig {
+            url: spawn_server(app).await,
+            internal_api_token: Some(INTERNAL_TOKEN.to_string()),
+        }
+    }
+
+    fn queue_update(update_type: QueueUpdateType, task_id: Uuid) -> QueueUpdate {
+        QueueUpdate {
+            update_type,
+            affected_user_id: Some("alice".to_string()),
+            global_position: None,
+            task_id: Some(task_id),
+            retry: None,
+            error: None,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_the_worker_id_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("worker_id");
+
+        let id = persisted_worker_id(&path).unwrap();
+        assert!(id.starts_with("worker-"), "{}", id);
+        assert_eq!(persisted_worker_id(&path).unwrap(), id);
+
+        std::fs::write(&path, "  replica-3\n").unwrap();
+        assert_eq!(persisted_worker_id(&path).unwrap(), "replica-3");
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_say_what_the_worker_is_doing() {
+        let beats = Beats::default();
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let heartbeat = Heartbeat::start(
+            &broadcast_service,
+            reqwest::Client::new(),
+            mock_backend(beats.clone()).await,
+            "worker-a".to_string(),
+            Duration::from_millis(50),
+        );
+
+        let idle = beats.next(0, |_| true).await;
+        assert_eq!(idle.worker_id, "worker-a");
+        assert_eq!(idle.status, WorkerState::Idle);
+        assert_eq!((idle.task_id, idle.stage, idle.percent, idle.active_tasks), (None, None, None, 0));
+        assert_eq!(idle.version, env!("CARGO_PKG_VERSION"));
+
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        broadcast_service.broadcast(queue_update(QueueUpdateType::TaskStarted, first)).await;
+        broadcast_service.broadcast(queue_update(QueueUpdateType::TaskStarted, second)).await;
+        broadcast_service.broadcast_progress(ProgressUpdate::new(second, "alice", ProcessingStage::FetchingMetadata, 100));
+        broadcast_service.broadcast_progress(ProgressUpdate::new(first, "alice", ProcessingStage::Downloading, 40));
+        let seen = beats.received().len();
+        let busy = beats.next(seen, |beat| beat.percent == Some(40)).await;
+        assert_eq!(busy.status, WorkerState::Processing);
+        assert_eq!(busy.task_id, Some(first), "the task running longest");
+        assert_eq!(busy.stage, Some(ProcessingStage::Downloading));
+        assert_eq!(busy.active_tasks, 2);
+
+        broadcast_service.broadcast(queue_update(QueueUpdateType::TaskCompleted, first)).await;
+        let seen = beats.received().len();
+        let next = beats.next(seen, |beat| beat.active_tasks == 1).await;
+        assert_eq!(next.task_id, Some(second));
+        assert_eq!(next.stage, Some(ProcessingStage::FetchingMetadata));
+
+        broadcast_service.broadcast(queue_update(QueueUpdateType::TaskRetried, second)).await;
+        let seen = beats.received().len();
+        assert_eq!(beats.next(seen, |_| true).await.status, WorkerState::Idle);
+        heartbeat.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_a_stopped_worker_says_it_is_offline() {
+        let beats = Beats::default();
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let heartbeat = Heartbeat::start(
+            &broadcast_service,
+            reqwest::Client::new(),
+            mock_backend(beats.clone()).await,
+            "worker-a".to_string(),
+            Duration::from_secs(3600),
+        );
+        beats.next(0, |_| true).await;
+
+        heartbeat.stop().await;
+        let received = beats.received();
+        assert_eq!(received.len(), 2, "{:?}", received);
+        assert_eq!(received[1].status, WorkerState::Offline);
+        assert_eq!(received[1].worker_id, "worker-a");
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_carry_on_once_the_backend_is_back() {
+        let beats = Beats::default();
+        beats.down.store(true, Ordering::SeqCst);
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let heartbeat = Heartbeat::start(
+            &broadcast_service,
+            reqwest::Client::new(),
+            mock_backend(beats.clone()).await,
+            "worker-a".to_string(),
+            Duration::from_millis(20),
+        );
+
+        // What the worker does meanwhile is still followed
+        let task_id = Uuid::new_v4();
+        broadcast_service.broadcast(queue_update(QueueUpdateType::TaskStarted, task_id)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(beats.received().is_empty());
+
+        beats.down.store(false, Ordering::SeqCst);
+        let beat = beats.next(0, |_| true).await;
+        assert_eq!(beat.task_id, Some(task_id));
+        heartbeat.stop().await;
+
+        // Nor does a backend that isn't there at all hold up shutdown
+        let heartbeat = Heartbeat::start(
+            &broadcast_service,
+            reqwest::Client::new(),
+            BackendConfig {
+                url: "http://127.0.0.1:1".to_string(),
+                internal_api_token: Some(INTERNAL_TOKEN.to_string()),
+            },
+            "worker-a".to_string(),
+            Duration::from_millis(20),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tokio::time::timeout(Duration::from_secs(5), heartbeat.stop()).await.unwrap();
+    }
+}
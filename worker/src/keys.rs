@@ -0,0 +1,291 @@
+//! Stored API keys as the worker uses them
+//!
+//! A key is decrypted only in the memory of the task that needs it, and not
+//! at all once it has expired: the task fails with
+//! [`WorkerError::KeyExpired`] rather than sending the key to Fathom or Loom
+//! to be turned down there with a vaguer error. After a successful use the
+//! worker reports it with [`touch_key`], so users can see which keys are
+//! still in use.
+//!
+//! Keys are fetched from the backend with [`fetch_key`], sealed to a key pair
+//! generated for that one request, and held as a [`SecretString`] that never
+//! prints and is wiped when dropped. A task fetches every key it needs when
+//! it starts, into [`TaskKeys`], so a missing Loom key fails it before the
+//! recording is downloaded rather than after.
+
+use chrono::{DateTime, Utc};
+use common::{
+    crypto::{
+        envelope::{self, EphemeralRecipient, SealedEnvelope},
+        keys::SecureKeyManager,
+        CryptoError, EncryptedApiKey,
+    },
+    ServiceKind,
+};
+use serde::Deserialize;
+
+use crate::{config::BackendConfig, WorkerError, WorkerResult};
+
+pub use common::crypto::keys::SecretString;
+
+/// The plaintext of `user_id`'s `key`, unless it had expired by `now`
+pub fn usable_key(
+    key: &EncryptedApiKey,
+    user_id: &str,
+    master_key: &[u8; 32],
+    now: DateTime<Utc>,
+) -> WorkerResult<String> {
+    if let Some(expired_at) = key.expires_at.filter(|_| key.is_expired_at(now)) {
+        return Err(WorkerError::KeyExpired {
+            service: key.service.clone(),
+            key_id: key.key_id.clone(),
+            expired_at,
+        });
+    }
+    key.decrypt_key_for_user(user_id, master_key).map_err(|e| {
+        WorkerError::Internal(format!("Stored {} key {} does not decrypt: {}", key.service, key.key_id, e))
+    })
+}
+
+/// A key fetched with [`fetch_key`]
+#[derive(Debug)]
+pub struct FetchedKey {
+    pub key_id: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub value: SecretString,
+}
+
+#[derive(Deserialize)]
+struct SealedKey {
+    key_id: String,
+    expires_at: Option<DateTime<Utc>>,
+    envelope: SealedEnvelope,
+}
+
+#[derive(Deserialize)]
+struct Refusal {
+    code: Option<String>,
+    data: Option<ExpiredKey>,
+}
+
+#[derive(Deserialize)]
+struct ExpiredKey {
+    key_id: String,
+    expired_at: DateTime<Utc>,
+}
+
+pub(crate) fn internal_token(backend: &BackendConfig) -> WorkerResult<&str> {
+    backend
+        .internal_api_token
+        .as_deref()
+        .ok_or_else(|| WorkerError::Config("INTERNAL_API_TOKEN is not set".to_string()))
+}
+
+/// Fetch `user_id`'s `key_id` key for `service` from the backend, or the
+/// service's default without a `key_id`
+///
+/// Fails with [`WorkerError::KeyMissing`] when there is no such key and
+/// [`WorkerError::KeyExpired`] when it has expired.
+pub async fn fetch_key(
+    client: &reqwest::Client,
+    backend: &BackendConfig,
+    user_id: &str,
+    service: &str,
+    key_id: Option<&str>,
+) -> WorkerResult<FetchedKey> {
+    let token = internal_token(backend)?;
+    let recipient = EphemeralRecipient::generate()
+        .map_err(|e| WorkerError::Internal(format!("Could not generate an envelope key: {}", e)))?;
+    let url = format!(
+        "{}/internal/keys/{}/{}",
+        backend.url.trim_end_matches('/'),
+        user_id,
+        service
+    );
+    let mut query = vec![("public_key", recipient.public_key())];
+    query.extend(key_id.map(|key_id| ("key_id", key_id)));
+    let response = client.get(url).bearer_auth(token).query(&query).send().await?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Err(WorkerError::KeyMissing { service: service.to_string() });
+    }
+    if status == reqwest::StatusCode::GONE {
+        let refusal: Refusal = response.json().await?;
+        if let (Some("key_expired"), Some(expired)) = (refusal.code.as_deref(), refusal.data) {
+            return Err(WorkerError::KeyExpired {
+                service: service.to_string(),
+                key_id: expired.key_id,
+                expired_at: expired.expired_at,
+            });
+        }
+        return Err(WorkerError::Internal(format!("The {} key was refused", service)));
+    }
+    let sealed: SealedKey = response.error_for_status()?.json().await?;
+
+    let context = envelope::key_context(user_id, service);
+    let value = recipient
+        .open(&sealed.envelope, &context, Utc::now())
+        .map_err(|e| WorkerError::Internal(format!("The {} key envelope does not open: {}", service, e)))?;
+    let value = String::from_utf8(value)
+        .map_err(|_| WorkerError::Internal(format!("The {} key is not text", service)))?;
+    Ok(FetchedKey {
+        key_id: sealed.key_id,
+        expires_at: sealed.expires_at,
+        value: SecretString::new(value),
+    })
+}
+
+/// The keys of one task, fetched together when it starts and dropped with it
+///
+/// They stay encrypted under a [`SecureKeyManager`] of the task's own until a
+/// stage asks for one with [`TaskKeys::value`], and the stage only lends the
+/// plaintext to its client.
+#[derive(Debug)]
+pub struct TaskKeys {
+    manager: SecureKeyManager,
+    key_ids: Vec<(ServiceKind, String)>,
+}
+
+impl TaskKeys {
+    /// Fetch `user_id`'s key for each of `services`, the `key_id` one or the
+    /// service's default
+    pub async fn fetch(
+        client: &reqwest::Client,
+        backend: &BackendConfig,
+        user_id: &str,
+        services: &[(ServiceKind, Option<&str>)],
+    ) -> WorkerResult<Self> {
+        let mut keys = TaskKeys {
+            manager: SecureKeyManager::new(),
+            key_ids: Vec::new(),
+        };
+        for (service, key_id) in services {
+            let fetched = fetch_key(client, backend, user_id, service.as_str(), *key_id).await?;
+            keys.manager.store_api_key(
+                service.as_str().to_string(),
+                fetched.key_id.clone(),
+                fetched.value.expose_secret(),
+                fetched.expires_at,
+            );
+            keys.key_ids.push((*service, fetched.key_id));
+        }
+        Ok(keys)
+    }
+
+    /// Which of the user's keys for `service` the task runs with
+    pub fn key_id(&self, service: ServiceKind) -> WorkerResult<&str> {
+        self.key_ids
+            .iter()
+            .find(|(kind, _)| *kind == service)
+            .map(|(_, key_id)| key_id.as_str())
+            .ok_or_else(|| WorkerError::KeyMissing { service: service.as_str().to_string() })
+    }
+
+    /// The plaintext of the `service` key, unless it has expired since the
+    /// task started
+    pub fn value(&self, service: ServiceKind) -> WorkerResult<SecretString> {
+        let key_id = self.key_id(service)?;
+        self.manager.get_api_key(service.as_str(), key_id).map_err(|e| match e {
+            CryptoError::KeyExpired(expired_at) => WorkerError::KeyExpired {
+                service: service.as_str().to_string(),
+                key_id: key_id.to_string(),
+                expired_at,
+            },
+            CryptoError::KeyNotFound => WorkerError::KeyMissing { service: service.as_str().to_string() },
+            e => WorkerError::Internal(format!("The {} key does not decrypt: {}", service.as_str(), e)),
+        })
+    }
+}
+
+/// Tell the backend that `user_id`'s `key_id` key for `service` was just used
+///
+/// Without a `key_id` the service's default key is meant. The backend records
+/// at most one use per key per hour, so this can be called after every
+/// request made with the key.
+pub async fn touch_key(
+    client: &reqwest::Client,
+    backend: &BackendConfig,
+    user_id: &str,
+    service: &str,
+    key_id: Option<&str>,
+) -> WorkerResult<()> {
+    let token = internal_token(backend)?;
+    let url = format!(
+        "{}/internal/keys/{}/{}/touch",
+        backend.url.trim_end_matches('/'),
+        user_id,
+        service
+    );
+    client
+        .post(url)
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "key_id": key_id }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_expired_keys_are_not_decrypted() {
+        let now = Utc::now();
+        let key = EncryptedApiKey::new_for_user(
+            "alice",
+            "loom".to_string(),
+            "default".to_string(),
+            "loom-key",
+            &[3u8; 32],
+            Some(now),
+        );
+
+        let before = now - Duration::minutes(1);
+        assert_eq!(usable_key(&key, "alice", &[3u8; 32], before).unwrap(), "loom-key");
+
+        let error = usable_key(&key, "alice", &[3u8; 32], now + Duration::minutes(1)).unwrap_err();
+        assert!(matches!(
+            &error,
+            WorkerError::KeyExpired { service, key_id, expired_at }
+                if service == "loom" && key_id == "default" && *expired_at == now
+        ));
+        assert!(!error.is_retryable());
+
+        // Anything else wrong with the key is not reported as expiry
+        assert!(matches!(usable_key(&key, "bob", &[3u8; 32], before), Err(WorkerError::Internal(_))));
+    }
+
+    #[test]
+    fn test_a_key_expiring_mid_task_is_not_handed_out() {
+        let expired_at = Utc::now() - Duration::minutes(1);
+        let mut keys = TaskKeys {
+            manager: SecureKeyManager::new(),
+            key_ids: vec![(ServiceKind::Loom, "team".to_string())],
+        };
+        keys.manager.store_api_key("loom".to_string(), "team".to_string(), "loom-key", Some(expired_at));
+
+        let error = keys.value(ServiceKind::Loom).unwrap_err();
+        assert!(matches!(
+            &error,
+            WorkerError::KeyExpired { service, key_id, expired_at: at }
+                if service == "loom" && key_id == "team" && *at == expired_at
+        ));
+        assert!(!error.is_retryable());
+        assert!(error.to_string().ends_with("add a new one in Settings"));
+        assert!(matches!(keys.value(ServiceKind::Fathom), Err(WorkerError::KeyMissing { .. })));
+        assert!(!format!("{:?}", keys).contains("loom-key"));
+    }
+
+    #[test]
+    fn test_secrets_never_print() {
+        let secret = SecretString::new("loom-secret-0123".to_string());
+        assert_eq!(secret.expose_secret(), "loom-secret-0123");
+        assert_eq!(format!("{:?}", secret), "SecretString([REDACTED])");
+        let fetched = FetchedKey { key_id: "default".to_string(), expires_at: None, value: secret };
+        assert!(!format!("{:?}", fetched).contains("loom-secret"));
+    }
+}
@@ -1,6 +1,31 @@
+pub mod cancel;
+pub mod checkpoint;
+pub mod cli;
 pub mod config;
+pub mod download;
 pub mod queue;
 pub mod error;
+pub mod fairness;
+pub mod fathom;
+pub mod heartbeat;
+pub mod keys;
+pub mod loom;
+pub mod metrics;
+pub mod pocketbase;
+pub mod progress;
+pub mod rate_limit;
+pub mod report;
+pub mod results;
+pub mod retry;
+pub mod server;
+pub mod shutdown;
+pub mod smtp;
+pub mod streaming;
+pub mod transcode;
+pub mod workspace;
+
+#[cfg(test)]
+mod test_support;
 
 pub use config::WorkerConfig;
 pub use error::{WorkerError, WorkerResult};
@@ -0,0 +1,470 @@
+//! Client for the Loom upload API, as the worker uses it
+//!
+//! A video goes up in a resumable upload session: the session is created
+//! with the video's size, chunks are `PUT` with a `Content-Range` and each
+//! is acknowledged with the offset Loom now holds, and completing the
+//! session turns it into a video with a share URL.
+//!
+//! Loom's offset is the one trusted. A chunk that fails is tried again from
+//! wherever Loom says it got to, since part of it may have landed, and an
+//! [`UploadJournal`] is told of every acknowledgement so that a task retried
+//! after a crash picks the same session up rather than starting over.
+//!
+//! A video [streamed](LoomClient::upload_stream) while it is still being
+//! made opens its session without a size, and gives Loom the total with the
+//! last chunk.
+
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::{io::SeekFrom, path::Path, time::Duration};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+    time::sleep,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{BackendConfig, LoomConfig},
+    keys::{self, SecretString},
+    retry::RetryPolicy,
+    WorkerError, WorkerResult,
+};
+
+/// How long one request to Loom may take, a chunk's included
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Wait before trying a failed chunk again
+const CHUNK_RETRY: RetryPolicy = RetryPolicy {
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(8),
+};
+
+/// A finished upload
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LoomVideo {
+    pub video_id: String,
+    pub share_url: String,
+}
+
+/// Told each time Loom acknowledges part of an upload
+pub trait UploadJournal: Send + Sync {
+    /// Loom holds the first `offset` of the `size` bytes of `upload_id`
+    fn acknowledged<'a>(&'a self, upload_id: &'a str, offset: u64, size: u64) -> BoxFuture<'a, ()>;
+}
+
+#[derive(Deserialize)]
+struct Session {
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+struct Acknowledged {
+    offset: u64,
+}
+
+/// Uploads to Loom with a user's key, which it only borrows
+pub struct LoomClient<'a> {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: &'a SecretString,
+    chunk_size: u64,
+    chunk_attempts: u32,
+}
+
+impl<'a> LoomClient<'a> {
+    pub fn new(client: reqwest::Client, config: &LoomConfig, api_key: &'a SecretString) -> Self {
+        Self {
+            client,
+            base_url: config.api_url.trim_end_matches('/').to_string(),
+            api_key,
+            chunk_size: config.upload_chunk_size.max(1),
+            chunk_attempts: config.upload_chunk_attempts.max(1),
+        }
+    }
+
+    /// Upload the video at `path` as `title`, carrying on with session
+    /// `resume` when Loom still has it
+    pub async fn upload(
+        &self,
+        path: &Path,
+        title: &str,
+        resume: Option<&str>,
+        journal: &dyn UploadJournal,
+    ) -> WorkerResult<LoomVideo> {
+        let mut file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        let resumed = match resume {
+            Some(upload_id) => self.offset(upload_id).await?.map(|offset| (upload_id.to_string(), offset)),
+            None => None,
+        };
+        let (upload_id, mut offset) = match resumed {
+            Some((upload_id, offset)) => {
+                debug!("Resuming Loom upload {} at {} of {} bytes", upload_id, offset, size);
+                (upload_id, offset)
+            }
+            None => {
+                let upload_id = self.start(title, Some(size)).await?;
+                journal.acknowledged(&upload_id, 0, size).await;
+                (upload_id, 0)
+            }
+        };
+
+        let mut failures = 0;
+        while offset < size {
+            let mut chunk = vec![0; self.chunk_size.min(size - offset) as usize];
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut chunk).await?;
+
+            match self.put_chunk(&upload_id, offset, chunk, Some(size)).await {
+                Ok(acknowledged) => {
+                    failures = 0;
+                    offset = acknowledged;
+                    journal.acknowledged(&upload_id, offset, size).await;
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures >= self.chunk_attempts {
+                        return Err(e);
+                    }
+                    warn!("Chunk at {} of Loom upload {} failed, trying again: {}", offset, upload_id, e);
+                    sleep(CHUNK_RETRY.delay(failures)).await;
+                    // Part of the chunk may have landed before it failed
+                    offset = self
+                        .offset(&upload_id)
+                        .await?
+                        .ok_or_else(|| WorkerError::Loom(format!("Upload session {} expired", upload_id)))?;
+                }
+            }
+        }
+
+        self.complete(&upload_id).await
+    }
+
+    /// Upload the video arriving on `chunks` as `title`, returning it with
+    /// the bytes it took
+    ///
+    /// The session is opened once there is a chunk to send, and each chunk
+    /// goes as soon as more is known to follow it, so the video's size is
+    /// only given with the last, once `chunks` has closed. A failed chunk is
+    /// tried again from Loom's offset as in [`upload`](Self::upload), but
+    /// nothing before it is kept and no journal told: a retried task opens a
+    /// new session. `progress` is told how much of what has arrived Loom
+    /// acknowledges.
+    pub async fn upload_stream(
+        &self,
+        title: &str,
+        mut chunks: mpsc::Receiver<Vec<u8>>,
+        progress: &(dyn Fn(u64, u64) + Send + Sync),
+    ) -> WorkerResult<(LoomVideo, u64)> {
+        let chunk_size = self.chunk_size as usize;
+        let mut upload_id: Option<String> = None;
+        // The video from `offset` on, as far as it has arrived
+        let mut offset = 0;
+        let mut pending = Vec::new();
+        let mut open = true;
+        let mut failures = 0;
+        loop {
+            while open && pending.len() <= chunk_size {
+                match chunks.recv().await {
+                    Some(chunk) => pending.extend_from_slice(&chunk),
+                    None => open = false,
+                }
+            }
+            if pending.is_empty() {
+                break;
+            }
+            let length = chunk_size.min(pending.len());
+            let total = (!open && length == pending.len()).then(|| offset + length as u64);
+            let upload_id: &str = match &upload_id {
+                Some(upload_id) => upload_id,
+                None => upload_id.insert(self.start(title, None).await?),
+            };
+
+            let acknowledged = match self.put_chunk(upload_id, offset, pending[..length].to_vec(), total).await {
+                Ok(acknowledged) => {
+                    failures = 0;
+                    acknowledged
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures >= self.chunk_attempts {
+                        return Err(e);
+                    }
+                    warn!("Chunk at {} of Loom upload {} failed, trying again: {}", offset, upload_id, e);
+                    sleep(CHUNK_RETRY.delay(failures)).await;
+                    self.offset(upload_id)
+                        .await?
+                        .ok_or_else(|| WorkerError::Loom(format!("Upload session {} expired", upload_id)))?
+                }
+            };
+            // Only what is still held can be sent again
+            if acknowledged < offset || acknowledged > offset + length as u64 {
+                return Err(WorkerError::Loom(format!(
+                    "Upload {} is at {}, outside the chunk sent from {}",
+                    upload_id, acknowledged, offset
+                )));
+            }
+            pending.drain(..(acknowledged - offset) as usize);
+            offset = acknowledged;
+            progress(offset, offset + pending.len() as u64);
+        }
+
+        let upload_id = upload_id.ok_or_else(|| WorkerError::Video("There was no video to upload".to_string()))?;
+        Ok((self.complete(&upload_id).await?, offset))
+    }
+
+    /// Open an upload session for `size` bytes, or an unknown number when
+    /// `None`
+    async fn start(&self, title: &str, size: Option<u64>) -> WorkerResult<String> {
+        let request = self
+            .client
+            .post(format!("{}/uploads", self.base_url))
+            .json(&json!({ "title": title, "size_bytes": size }));
+        let session: Session = self.send(request).await?.json().await?;
+        Ok(session.upload_id)
+    }
+
+    /// How much of `upload_id` Loom holds, or `None` once the session is gone
+    async fn offset(&self, upload_id: &str) -> WorkerResult<Option<u64>> {
+        let request = self.client.get(format!("{}/uploads/{}", self.base_url, upload_id));
+        let response = self.dispatch(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(checked(response)?.json::<Acknowledged>().await?.offset))
+    }
+
+    /// Send `chunk`, the bytes of the video from `offset` on, returning the
+    /// offset Loom acknowledges
+    ///
+    /// The video's `size` goes as `*` while it isn't known.
+    async fn put_chunk(&self, upload_id: &str, offset: u64, chunk: Vec<u8>, size: Option<u64>) -> WorkerResult<u64> {
+        let end = offset + chunk.len() as u64 - 1;
+        let size = size.map_or_else(|| "*".to_string(), |size| size.to_string());
+        let request = self
+            .client
+            .put(format!("{}/uploads/{}", self.base_url, upload_id))
+            .header("Content-Range", format!("bytes {}-{}/{}", offset, end, size))
+            .header("Content-Type", "application/octet-stream")
+            .body(chunk);
+        Ok(self.send(request).await?.json::<Acknowledged>().await?.offset)
+    }
+
+    /// Turn the fully uploaded `upload_id` into a video
+    async fn complete(&self, upload_id: &str) -> WorkerResult<LoomVideo> {
+        let request = self.client.post(format!("{}/uploads/{}/complete", self.base_url, upload_id));
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    /// Send `request` with the key; any status but success is an error
+    async fn send(&self, request: reqwest::RequestBuilder) -> WorkerResult<reqwest::Response> {
+        checked(self.dispatch(request).await?)
+    }
+
+    /// Send `request` with the key, whatever Loom answers
+    async fn dispatch(&self, request: reqwest::RequestBuilder) -> WorkerResult<reqwest::Response> {
+        request
+            .bearer_auth(self.api_key.expose_secret())
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| WorkerError::Loom(e.without_url().to_string()))
+    }
+}
+
+fn checked(response: reqwest::Response) -> WorkerResult<reqwest::Response> {
+    match response.status() {
+        status if status.is_success() => Ok(response),
+        status => Err(WorkerError::Loom(format!("Loom returned {}", status.as_u16()))),
+    }
+}
+
+/// Record on queue item `item_id`, through the backend, the video its
+/// meeting became
+pub async fn record_video(
+    client: &reqwest::Client,
+    backend: &BackendConfig,
+    item_id: &str,
+    video: &LoomVideo,
+) -> WorkerResult<()> {
+    let token = keys::internal_token(backend)?;
+    let url = format!("{}/internal/queue/{}/loom", backend.url.trim_end_matches('/'), item_id);
+    client
+        .post(url)
+        .bearer_auth(token)
+        .json(&json!({ "video_id": video.video_id, "share_url": video.share_url }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockLoom, LOOM_KEY};
+    use once_cell::sync::Lazy;
+    use std::{io::Write, sync::Mutex};
+
+    /// Remembers every acknowledgement
+    #[derive(Default)]
+    struct Journal(Mutex<Vec<(String, u64)>>);
+
+    impl UploadJournal for Journal {
+        fn acknowledged<'a>(&'a self, upload_id: &'a str, offset: u64, _size: u64) -> BoxFuture<'a, ()> {
+            self.0.lock().unwrap().push((upload_id.to_string(), offset));
+            Box::pin(async {})
+        }
+    }
+
+    impl Journal {
+        fn offsets(&self) -> Vec<u64> {
+            self.0.lock().unwrap().iter().map(|(_, offset)| *offset).collect()
+        }
+
+        fn session(&self) -> Option<String> {
+            self.0.lock().unwrap().last().map(|(upload_id, _)| upload_id.clone())
+        }
+    }
+
+    static SECRET: Lazy<SecretString> = Lazy::new(|| SecretString::new(LOOM_KEY.to_string()));
+
+    fn client(loom: &MockLoom, attempts: u32) -> LoomClient<'static> {
+        let config = LoomConfig {
+            api_url: loom.url.clone(),
+            upload_chunk_size: 1000,
+            upload_chunk_attempts: attempts,
+        };
+        LoomClient::new(reqwest::Client::new(), &config, &SECRET)
+    }
+
+    /// A 2500-byte video whose bytes tell their offsets apart
+    fn video() -> (tempfile::NamedTempFile, Vec<u8>) {
+        let bytes: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+        (file, bytes)
+    }
+
+    #[tokio::test]
+    async fn test_a_video_goes_up_in_chunks() {
+        let loom = MockLoom::start().await;
+        let (file, bytes) = video();
+        // One flaky chunk is tried again without giving up
+        loom.fail_chunks(1);
+        let journal = Journal::default();
+
+        let video = client(&loom, 3).upload(file.path(), "Weekly sync", None, &journal).await.unwrap();
+
+        let upload_id = journal.session().unwrap();
+        assert_eq!(loom.uploaded(&upload_id), bytes);
+        assert_eq!(video.share_url, format!("https://www.loom.com/share/{}", video.video_id));
+        assert_eq!(journal.offsets().first(), Some(&0));
+        assert_eq!(journal.offsets().last(), Some(&2500));
+        assert_eq!(loom.chunk_requests(), 3, "one failed try, then two chunks");
+    }
+
+    #[tokio::test]
+    async fn test_an_upload_cut_off_mid_chunk_resumes_where_loom_got_to() {
+        let loom = MockLoom::start().await;
+        let (file, bytes) = video();
+        let journal = Journal::default();
+
+        // The second chunk half lands and then every try fails, as if the
+        // worker died sending it
+        loom.fail_chunks_after(1, usize::MAX);
+        let error = client(&loom, 2).upload(file.path(), "Weekly sync", None, &journal).await.unwrap_err();
+        assert!(error.is_retryable());
+        assert_eq!(journal.offsets(), [0, 1000]);
+        let upload_id = journal.session().unwrap();
+        assert_eq!(loom.uploaded(&upload_id).len(), 2000, "each failed try landed half its chunk");
+
+        loom.fail_chunks(0);
+        let video = client(&loom, 2)
+            .upload(file.path(), "Weekly sync", Some(&upload_id), &journal)
+            .await
+            .unwrap();
+        assert_eq!(loom.uploaded(&upload_id), bytes);
+        assert_eq!(journal.offsets()[2..], [2500]);
+        assert_eq!(loom.sessions(), 1, "no second session was opened");
+        assert!(video.share_url.ends_with(&video.video_id));
+    }
+
+    #[tokio::test]
+    async fn test_a_session_loom_forgot_starts_over() {
+        let loom = MockLoom::start().await;
+        let (file, bytes) = video();
+        let journal = Journal::default();
+
+        client(&loom, 1)
+            .upload(file.path(), "Weekly sync", Some("expired-session"), &journal)
+            .await
+            .unwrap();
+        let upload_id = journal.session().unwrap();
+        assert_ne!(upload_id, "expired-session");
+        assert_eq!(loom.uploaded(&upload_id), bytes);
+    }
+
+    /// `bytes` sent on a channel in pieces of 300
+    fn streamed(bytes: Vec<u8>) -> mpsc::Receiver<Vec<u8>> {
+        let (sender, chunks) = mpsc::channel(2);
+        tokio::spawn(async move {
+            for piece in bytes.chunks(300) {
+                sender.send(piece.to_vec()).await.unwrap();
+            }
+        });
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_a_streamed_video_goes_up_with_its_size_last() {
+        let loom = MockLoom::start().await;
+        let (_, bytes) = video();
+        // Half the first chunk lands before it fails
+        loom.fail_chunks(1);
+        let acknowledged = Mutex::new(Vec::new());
+
+        let (video, size) = client(&loom, 3)
+            .upload_stream("Weekly sync", streamed(bytes.clone()), &|offset, received| {
+                assert!(offset <= received);
+                acknowledged.lock().unwrap().push(offset)
+            })
+            .await
+            .unwrap();
+        assert_eq!(size, 2500);
+        assert_eq!(loom.uploaded("upload-1"), bytes);
+        assert_eq!(video.video_id, "video-1");
+        assert_eq!(*acknowledged.lock().unwrap(), [500, 1500, 2500]);
+
+        // A video ending on a chunk's edge still tells Loom its size
+        let (video, size) = client(&loom, 1)
+            .upload_stream("Weekly sync", streamed(bytes[..2000].to_vec()), &|_, _| {})
+            .await
+            .unwrap();
+        assert_eq!((video.video_id.as_str(), size), ("video-2", 2000));
+        assert_eq!(loom.chunk_requests(), 5);
+
+        let error = client(&loom, 1).upload_stream("Weekly sync", streamed(Vec::new()), &|_, _| {}).await.unwrap_err();
+        assert!(matches!(error, WorkerError::Video(_)));
+        assert_eq!(loom.sessions(), 2, "nothing to upload opens no session");
+    }
+
+    #[tokio::test]
+    async fn test_a_failed_completion_is_retryable() {
+        let loom = MockLoom::start().await;
+        let (file, _) = video();
+        loom.fail_completion();
+
+        let error = client(&loom, 3)
+            .upload(file.path(), "Weekly sync", None, &Journal::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(&error, WorkerError::Loom(message) if message == "Loom returned 503"));
+        assert!(error.is_retryable());
+    }
+}
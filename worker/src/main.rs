@@ -1,90 +1,208 @@
-mod config;
-mod queue;
-
-use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use common::broadcast::BroadcastServiceFactory;
+use tokio::time::Duration;
+use tracing::info;
+use common::{broadcast::BroadcastServiceFactory, clock::SystemClock, JobType};
 use std::sync::Arc;
 
-use config::WorkerConfig;
+use worker::{
+    cli::{self, Command},
+    fairness::Fairness,
+    heartbeat::{self, Heartbeat},
+    pocketbase::PocketBase,
+    progress,
+    queue,
+    rate_limit::TokenBucket,
+    results::BackendResults,
+    retry::RetryPolicy,
+    server::{self, DrainSwitch, Server},
+    shutdown::Shutdown,
+    smtp::SmtpClient,
+    transcode,
+    workspace::{self, SpaceGate, Statvfs},
+    WorkerConfig,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE.into());
+        }
+    };
+
     // Load environment variables from .env file
     dotenvy::dotenv().ok();
 
-    // Load configuration
-    let config = WorkerConfig::from_env()?;
-
-    // Initialize tracing with level from config
-    let log_level = match config.logging.level.to_lowercase().as_str() {
-        "error" => tracing::Level::ERROR,
-        "warn" => tracing::Level::WARN,
-        "info" => tracing::Level::INFO,
-        "debug" => tracing::Level::DEBUG,
-        "trace" => tracing::Level::TRACE,
-        _ => {
-            warn!("Invalid log level '{}', defaulting to 'info'", config.logging.level);
-            tracing::Level::INFO
+    // Load configuration, listing every problem before refusing to start
+    let loaded = WorkerConfig::from_env();
+    if command == Command::ValidateConfig {
+        let (status, report) = cli::validated(&loaded);
+        match status {
+            0 => println!("{}", report),
+            _ => eprintln!("{}", report),
+        }
+        std::process::exit(status.into());
+    }
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(cli::EXIT_FAILURE.into());
         }
     };
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::filter::LevelFilter::from_level(log_level))
-        .init();
+    // Initialize tracing with level and format from config
+    common::logging::init_tracing("worker", config.logging.format, &config.logging.level);
+    let reporter = common::telemetry::reporter(config.logging.sentry_dsn.as_deref(), "worker");
+    common::telemetry::install_panic_hook(reporter);
+
+    match command {
+        Command::Drain => {
+            cli::drain(&config, Duration::from_secs(1)).await?;
+            info!("The worker has drained and stopped");
+        }
+        Command::Inspect { task_id } => {
+            let inspected = cli::inspect(&PocketBase::from(&config.database), &task_id).await?;
+            println!("{}", serde_json::to_string_pretty(&inspected)?);
+        }
+        Command::Requeue { task_id } => {
+            let task = cli::requeue(&PocketBase::from(&config.database), &task_id).await?;
+            println!("Task {} requeued from {:?}", task.id, task.status);
+        }
+        Command::Run => run(config).await?,
+        Command::ValidateConfig | Command::Help => unreachable!("answered before the configuration is used"),
+    }
+    Ok(())
+}
 
+/// Claim and run tasks until a signal, or until drained
+async fn run(config: WorkerConfig) -> Result<(), Box<dyn std::error::Error>> {
     info!("Worker configuration loaded successfully");
     info!("Log level: {}", config.logging.level);
     info!("Database URL: {}", config.database.url);
     info!("Worker concurrency: {}", config.worker.concurrency);
     info!("Queue concurrency: {}", config.worker.queue_concurrency);
     info!("Poll interval: {}s", config.worker.poll_interval);
+    info!("Worker id: {}", config.worker.worker_id);
+    info!(
+        "Claiming round the users of each priority's oldest {} items, served as kept in {}",
+        config.worker.claim_fairness_window,
+        config.worker.state_file.display()
+    );
+    info!(
+        "Retry delay: {}s doubling up to {}s",
+        config.worker.retry_base_delay, config.worker.retry_max_delay
+    );
+    info!("Shutdown grace period: {}s", config.worker.shutdown_grace);
+    info!("Transcoding: {:?}", config.transcode.mode);
+    // Better to stop here than fail every task at its transcode
+    transcode::check_tools(&config.transcode).await?;
+    let scratch = &config.worker.scratch_dir;
+    std::fs::create_dir_all(scratch)?;
+    info!("Scratch directory: {}", scratch.display());
+    // Left by a crash, or kept for a retry that never came
+    workspace::sweep_orphans(scratch, workspace::ORPHAN_AGE)?;
 
     info!("Starting Fathom to Loom worker");
     
     // Initialize shared broadcast service
     let broadcast_service = BroadcastServiceFactory::create_shared(1000);
     info!("Broadcast service initialized");
+    // Progress reaches browsers through the backend
+    progress::bridge(&broadcast_service, reqwest::Client::new(), config.backend.clone());
+    // So the dashboard shows this worker, until it says it went offline
+    let heartbeat = Heartbeat::start(
+        &broadcast_service,
+        reqwest::Client::new(),
+        config.backend.clone(),
+        config.worker.worker_id.clone(),
+        heartbeat::INTERVAL,
+    );
 
-    // Main worker loop
-    loop {
-        match process_tasks(&config, broadcast_service.clone()).await {
-            Ok(_) => {
-                info!("Worker cycle completed successfully");
-            }
-            Err(e) => {
-                error!("Worker error: {}", e);
-            }
+    let pipelines = queue::Pipelines::new().with(
+        JobType::ProcessMeeting,
+        queue::FathomToLoom {
+            config: config.clone(),
+            client: reqwest::Client::new(),
+            pb: PocketBase::from(&config.database),
+            broadcast_service: broadcast_service.clone(),
+            fathom_limiter: Arc::new(TokenBucket::new(config.fathom.requests_per_minute, config.fathom.burst)),
+        },
+    );
+    // Claimed tasks of a type no pipeline runs would only fail
+    for (job_type, _) in &config.worker.job_types {
+        if !pipelines.handles(*job_type) {
+            return Err(format!("WORKER_JOB_TYPES names {}, which this worker has no pipeline for", job_type).into());
         }
-
-        // Wait before next cycle based on configuration
-        sleep(Duration::from_secs(config.worker.poll_interval)).await;
     }
-}
 
-async fn process_tasks(
-    config: &WorkerConfig,
-    broadcast_service: Arc<common::broadcast::BroadcastService>
-) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Processing tasks with {} concurrency", config.worker.concurrency);
-    info!("Broadcast service subscribers: {}", broadcast_service.subscriber_count());
+    let context = Arc::new(queue::TaskContext {
+        pb: PocketBase::from(&config.database),
+        worker_id: config.worker.worker_id.clone(),
+        poll_interval: Duration::from_secs(config.worker.poll_interval),
+        disk: SpaceGate::new(
+            scratch.clone(),
+            config.worker.scratch_min_free,
+            config.worker.expected_task_bytes,
+            Arc::new(Statvfs),
+        ),
+        // Who was served when outlasts a restart, as the worker id does
+        fairness: Fairness::load(&config.worker.state_file, config.worker.claim_fairness_window),
+        job_types: config.worker.job_types.clone(),
+        pipeline: pipelines,
+        broadcast_service,
+        email: config.email.clone(),
+        retry: RetryPolicy {
+            base_delay: Duration::from_secs(config.worker.retry_base_delay),
+            max_delay: Duration::from_secs(config.worker.retry_max_delay),
+        },
+        clock: Arc::new(SystemClock),
+        // Owners hear how their tasks ended, unless the smtp-service is down
+        mailer: Arc::new(SmtpClient::new(reqwest::Client::new(), &config.email)),
+        results: Arc::new(BackendResults {
+            client: reqwest::Client::new(),
+            backend: config.backend.clone(),
+        }),
+    });
 
-    // TODO: Replace with actual user authentication/lookup
-    let dummy_user = common::User {
-        id: uuid::Uuid::new_v4(),
-        email: "worker@example.com".to_string(),
-        username: "worker".to_string(),
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
-    };
-
-    // Process tasks with broadcasting
-    match queue::process_task(config, &dummy_user, broadcast_service).await {
-        Ok(_) => info!("Task processing completed"),
-        Err(e) => error!("Task processing failed: {}", e),
-    }
+    // Probes and scrapes are answered for as long as tasks are claimed, and
+    // `worker drain` is heard if the internal token is set
+    let (drain, draining) = Shutdown::channel();
+    let drain_switch = config.backend.internal_api_token.clone().map(|token| DrainSwitch {
+        token,
+        drain: Arc::new(drain),
+    });
+    let http = Server::start(config.worker.http_port, server::readiness(&config), drain_switch).await?;
 
+    // On a signal nothing new is claimed; running tasks get the grace period
+    // to finish, and go back to the queue if they don't. Drained, they get
+    // as long as they take
+    let job_types: Vec<String> = config
+        .worker
+        .job_types
+        .iter()
+        .map(|(job_type, limit)| format!("{} (up to {})", job_type, limit))
+        .collect();
+    info!(
+        "Processing {} tasks with {} concurrency",
+        job_types.join(", "),
+        config.worker.concurrency
+    );
+    queue::run_pool_until_drained(
+        context,
+        config.worker.concurrency as usize,
+        Shutdown::on_signal(),
+        draining,
+        Duration::from_secs(config.worker.shutdown_grace),
+    )
+    .await;
+    heartbeat.stop().await;
+    http.stop().await;
+    info!("Worker stopped");
     Ok(())
 }
@@ -0,0 +1,144 @@
+//! Worker metrics in the Prometheus text format
+//!
+//! Every family the worker exports is declared here and recorded into the
+//! shared [`common::metrics`] registry where things happen, and
+//! [`server`](crate::server) renders them at `/metrics`. Stages run for
+//! minutes to hours, so the duration histogram's buckets reach further than a
+//! request latency's.
+
+pub use common::metrics::{add, increment, observe, registry, Family, Kind};
+
+pub const TASKS_CLAIMED: Family = Family {
+    name: "worker_tasks_claimed_total",
+    kind: Kind::Counter,
+    help: "Queue items this worker claimed",
+    buckets: &[],
+};
+
+pub const TASKS_COMPLETED: Family = Family {
+    name: "worker_tasks_completed_total",
+    kind: Kind::Counter,
+    help: "Tasks whose meeting reached Loom",
+    buckets: &[],
+};
+
+pub const TASKS_FAILED: Family = Family {
+    name: "worker_tasks_failed_total",
+    kind: Kind::Counter,
+    help: "Tasks failed for good and moved to the dead-letter queue",
+    buckets: &[],
+};
+
+pub const TASKS_RETRIED: Family = Family {
+    name: "worker_tasks_retried_total",
+    kind: Kind::Counter,
+    help: "Failed attempts returned to the queue for a retry",
+    buckets: &[],
+};
+
+pub const TASKS_CANCELLED: Family = Family {
+    name: "worker_tasks_cancelled_total",
+    kind: Kind::Counter,
+    help: "Tasks stopped at their owner's request",
+    buckets: &[],
+};
+
+pub const CLAIMS_DEFERRED: Family = Family {
+    name: "worker_claims_deferred_total",
+    kind: Kind::Counter,
+    help: "Claims put off because the scratch volume was short of space",
+    buckets: &[],
+};
+
+pub const TASKS_IN_FLIGHT: Family = Family {
+    name: "worker_tasks_in_flight",
+    kind: Kind::Gauge,
+    help: "Tasks running now",
+    buckets: &[],
+};
+
+pub const STAGE_DURATION: Family = Family {
+    name: "worker_stage_duration_seconds",
+    kind: Kind::Histogram,
+    help: "Time taken by pipeline stages, by stage, whether they succeeded or not",
+    buckets: &[0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0],
+};
+
+pub const BYTES_DOWNLOADED: Family = Family {
+    name: "worker_downloaded_bytes_total",
+    kind: Kind::Counter,
+    help: "Bytes of recordings downloaded from Fathom",
+    buckets: &[],
+};
+
+pub const BYTES_UPLOADED: Family = Family {
+    name: "worker_uploaded_bytes_total",
+    kind: Kind::Counter,
+    help: "Bytes of video uploaded to Loom",
+    buckets: &[],
+};
+
+pub const FATHOM_WAIT: Family = Family {
+    name: "worker_fathom_wait_seconds",
+    kind: Kind::Histogram,
+    help: "Time Fathom calls waited for the worker's rate limit",
+    buckets: &[0.01, 0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0],
+};
+
+pub const FATHOM_RATE_LIMITED: Family = Family {
+    name: "worker_fathom_rate_limited_total",
+    kind: Kind::Counter,
+    help: "Fathom answers asking the worker to back off, by status",
+    buckets: &[],
+};
+
+/// Every family, in the order they're rendered
+const FAMILIES: &[&Family] = &[
+    &TASKS_CLAIMED,
+    &TASKS_COMPLETED,
+    &TASKS_FAILED,
+    &TASKS_RETRIED,
+    &TASKS_CANCELLED,
+    &CLAIMS_DEFERRED,
+    &TASKS_IN_FLIGHT,
+    &STAGE_DURATION,
+    &BYTES_DOWNLOADED,
+    &BYTES_UPLOADED,
+    &FATHOM_WAIT,
+    &FATHOM_RATE_LIMITED,
+];
+
+/// Every family in the Prometheus text exposition format
+pub fn render() -> String {
+    registry().render(FAMILIES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::metrics::Registry;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let registry = Registry::default();
+        registry.add(&TASKS_CLAIMED, &[], 1.0);
+        registry.add(&TASKS_CLAIMED, &[], 1.0);
+        registry.add(&TASKS_IN_FLIGHT, &[], 1.0);
+        registry.add(&TASKS_IN_FLIGHT, &[], -1.0);
+        registry.add(&BYTES_DOWNLOADED, &[], 2500.0);
+        registry.observe(&STAGE_DURATION, &[("stage", "download")], 42.0);
+
+        let text = registry.render(FAMILIES);
+        assert!(text.contains("# TYPE worker_tasks_claimed_total counter\n"));
+        assert!(text.contains("worker_tasks_claimed_total 2\n"));
+        assert!(text.contains("worker_tasks_in_flight 0\n"));
+        assert!(text.contains("worker_downloaded_bytes_total 2500\n"));
+        assert!(text.contains("worker_stage_duration_seconds_bucket{stage=\"download\",le=\"30\"} 0\n"));
+        assert!(text.contains("worker_stage_duration_seconds_bucket{stage=\"download\",le=\"60\"} 1\n"));
+        assert!(text.contains("worker_stage_duration_seconds_bucket{stage=\"download\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("worker_stage_duration_seconds_sum{stage=\"download\"} 42\n"));
+        assert_eq!(registry.value(&STAGE_DURATION, &[("stage", "download")]), 1.0);
+        // Families are declared even before anything is recorded
+        assert!(text.contains("# TYPE worker_tasks_failed_total counter\n"));
+    }
+}
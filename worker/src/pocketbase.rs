@@ -0,0 +1,21 @@
+//! Admin client for the global PocketBase, as the worker uses it
+//!
+//! The client itself is shared with the backend and smtp-service in
+//! [`common::pocketbase`]; this connects it to the worker's configuration
+//! and errors.
+
+pub use common::pocketbase::{quote, PbError, PocketBase};
+
+use crate::{config::DatabaseConfig, WorkerError};
+
+impl From<PbError> for WorkerError {
+    fn from(e: PbError) -> Self {
+        WorkerError::PocketBase(e.to_string())
+    }
+}
+
+impl From<&DatabaseConfig> for PocketBase {
+    fn from(database: &DatabaseConfig) -> Self {
+        PocketBase::new(&database.url, &database.admin_email, &database.admin_password)
+    }
+}
@@ -0,0 +1,206 @@
+//! Reporting how far the worker has got with each task
+//!
+//! A stage reports through a [`StageProgress`]. Stages that count bytes
+//! would otherwise send an event per chunk, so percents are let through at
+//! most once per [`THROTTLE_INTERVAL`] unless they moved [`THROTTLE_STEP`]
+//! points; a stage's first and last percent always get through, and within a
+//! stage the percent never goes back.
+//!
+//! The worker's [`BroadcastService`] only reaches this process. [`bridge`]
+//! relays its progress and system topics to the backend, which puts each
+//! update on its own service: progress for the WebSocket clients of the
+//! task's owner, system events for admins.
+
+use common::broadcast::{BroadcastService, ProcessingStage, ProgressUpdate};
+use std::{sync::Mutex, time::Duration};
+use tokio::{sync::broadcast, task::JoinHandle, time::Instant};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{config::BackendConfig, keys};
+
+/// Longest a stage's percent is held back for
+pub const THROTTLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Points a stage's percent may move before it is sent regardless
+pub const THROTTLE_STEP: u8 = 5;
+
+/// Decides which of a stage's percents are worth sending
+#[derive(Debug, Default)]
+pub struct ProgressThrottle {
+    last: Option<(u8, Instant)>,
+}
+
+impl ProgressThrottle {
+    /// Whether `percent`, reached at `now`, should be sent
+    pub fn admit(&mut self, percent: u8, now: Instant) -> bool {
+        let percent = percent.min(100);
+        let admit = match self.last {
+            None => true,
+            Some((last, _)) if percent <= last => false,
+            Some((last, at)) => percent == 100 || percent - last >= THROTTLE_STEP || now - at >= THROTTLE_INTERVAL,
+        };
+        if admit {
+            self.last = Some((percent, now));
+        }
+        admit
+    }
+}
+
+/// Where one stage of a task reports its percent
+pub struct StageProgress<'a> {
+    broadcast_service: &'a BroadcastService,
+    task_id: Uuid,
+    user_id: &'a str,
+    stage: ProcessingStage,
+    throttle: Mutex<ProgressThrottle>,
+}
+
+impl<'a> StageProgress<'a> {
+    pub fn new(broadcast_service: &'a BroadcastService, task_id: Uuid, user_id: &'a str, stage: ProcessingStage) -> Self {
+        Self {
+            broadcast_service,
+            task_id,
+            user_id,
+            stage,
+            throttle: Mutex::new(ProgressThrottle::default()),
+        }
+    }
+
+    /// The stage has begun
+    pub fn start(&self) {
+        self.report(0);
+    }
+
+    /// The stage is `percent` done; sent if the throttle lets it through
+    pub fn report(&self, percent: u8) {
+        if self.throttle.lock().unwrap().admit(percent, Instant::now()) {
+            self.broadcast_service
+                .broadcast_progress(ProgressUpdate::new(self.task_id, self.user_id, self.stage, percent));
+        }
+    }
+
+    /// The stage is done
+    pub fn finish(&self) {
+        self.report(100);
+    }
+}
+
+/// Relay every progress update, queue update and system event on
+/// `broadcast_service` to the backend's `/internal/progress`,
+/// `/internal/queue` and `/internal/system`, until the service is dropped
+///
+/// These are only ever shown, so one the backend doesn't take is dropped
+/// rather than tried again.
+pub fn bridge(broadcast_service: &BroadcastService, client: reqwest::Client, backend: BackendConfig) -> JoinHandle<()> {
+    let mut updates = broadcast_service.subscribe_progress();
+    let mut queue_updates = broadcast_service.subscribe();
+    let mut events = broadcast_service.subscribe_system();
+    tokio::spawn(async move {
+        let token = match keys::internal_token(&backend) {
+            Ok(token) => token.to_string(),
+            Err(e) => {
+                warn!("Progress, queue and system events won't reach the backend: {}", e);
+                return;
+            }
+        };
+        let base_url = backend.url.trim_end_matches('/');
+        let relay = |path: &str, body: serde_json::Value| {
+            let request = client.post(format!("{}/internal/{}", base_url, path)).bearer_auth(&token).json(&body);
+            async move { request.send().await.and_then(|response| response.error_for_status()).map(drop) }
+        };
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(update) => {
+                        if let Err(e) = relay("progress", serde_json::json!(update)).await {
+                            debug!("Failed to relay progress of task {}: {}", update.task_id, e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Progress bridge lagged, skipped {} updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                update = queue_updates.recv() => match update {
+                    Ok(update) => {
+                        if let Err(e) = relay("queue", serde_json::json!(update)).await {
+                            debug!("Failed to relay queue update {:?}: {}", update.update_type, e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Progress bridge lagged, skipped {} queue updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if let Err(e) = relay("system", serde_json::json!(event)).await {
+                            warn!("Failed to relay system event {:?}: {}", event.event_type, e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Progress bridge lagged, skipped {} system events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock_backend;
+    use common::broadcast::BroadcastServiceFactory;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_percents_are_throttled_by_time_and_step() {
+        let mut throttle = ProgressThrottle::default();
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert!(throttle.admit(0, at(0)), "a stage's first percent gets through");
+        assert!(!throttle.admit(1, at(100)));
+        assert!(!throttle.admit(4, at(1_900)));
+        assert!(throttle.admit(5, at(1_950)), "five points is enough");
+        assert!(!throttle.admit(6, at(3_000)));
+        assert!(throttle.admit(7, at(3_950)), "so is two seconds");
+        assert!(!throttle.admit(7, at(9_000)), "nothing new");
+        assert!(throttle.admit(100, at(9_001)), "the end always gets through");
+        assert!(!throttle.admit(100, at(20_000)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_stage_never_goes_back() {
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let mut updates = broadcast_service.subscribe_progress();
+        let stage = StageProgress::new(&broadcast_service, Uuid::new_v4(), "alice", ProcessingStage::Downloading);
+
+        stage.start();
+        // As a download resumed from an earlier offset might report
+        for percent in [30, 20, 60, 40, 70, 10] {
+            stage.report(percent);
+        }
+        tokio::time::advance(THROTTLE_INTERVAL).await;
+        stage.report(50);
+        stage.report(71);
+        stage.finish();
+
+        let percents: Vec<u8> = std::iter::from_fn(|| updates.try_recv().ok())
+            .map(|update| update.percent)
+            .collect();
+        assert_eq!(percents, [0, 30, 60, 70, 71, 100]);
+    }
+
+    #[tokio::test]
+    async fn test_the_bridge_needs_the_internal_token() {
+        let mut backend = mock_backend(&[]).await;
+        backend.internal_api_token = None;
+        let broadcast_service = BroadcastServiceFactory::create_shared(4);
+
+        let relay = bridge(&broadcast_service, reqwest::Client::new(), backend);
+        relay.await.unwrap();
+    }
+}
@@ -1,25 +1,163 @@
-use std::time::Duration;
-use tokio::time::sleep;
+//! The queue of meetings waiting to move from Fathom to Loom
+//!
+//! Items live in the global PocketBase `queue_items` collection. A worker
+//! claims a `Pending` one by first creating a `queue_claims` record
+//! for the item at the version it read: that collection's unique index on
+//! `(item, version)` lets exactly one worker succeed, and a duplicate means
+//! another worker got there first and the next item is tried. The winner
+//! then marks the item `InProgress` under its id and bumps the version, so
+//! an item returned to the queue can be claimed afresh. Items are tried
+//! highest `priority` first, and within a priority in the turns
+//! [`Fairness`] gives each user. A worker claims only the `job_type`s it is
+//! configured for, each up to its own limit, and runs each with the
+//! pipeline [`Pipelines`] has for it; other types are left for other workers.
+//!
+//! A task failing with a retryable error goes back to `Pending` with a
+//! `next_attempt_at` from the [`RetryPolicy`], and is not claimed again
+//! before then. One failing for good, or out of retries, is `Failed`, its
+//! owner is emailed through the smtp-service, and it is copied with every attempt's error to the
+//! `dead_letter` collection for an operator to look into and requeue. The
+//! item keeps the id of that copy, so claims skip it even if it is set back
+//! to `Pending` by hand.
+//!
+//! However an attempt ends, its outcome and its [`TaskReport`] are logged
+//! and kept as the task's [`ProcessingResult`] in its owner's PocketBase
+//! instance.
+//!
+//! A task its owner cancels is stopped wherever it is and ends `Cancelled`;
+//! see [`cancel`].
+//!
+//! Past the claim, every change of a task's status goes through
+//! [`update_status`], which holds it to [`TaskStatus::can_become`] and
+//! refuses to write over an item another worker has taken over since. The
+//! one exception is an operator's [`requeue`].
+//!
+//! On [`Shutdown`] nothing more is claimed. Pipelines stop between stages,
+//! and tasks still running when the grace period ends are abandoned; either
+//! way the task is released back to `Pending` for another worker. Draining
+//! also stops the claims, but lets running tasks finish however long they
+//! take.
+
+use std::{future::Future, time::Duration};
+use futures::future::BoxFuture;
+use tokio::{sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore}, task::JoinSet, time::{sleep, timeout}};
+use tokio_util::sync::CancellationToken;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
-use common::{JobStatus, User};
-use common::broadcast::{BroadcastService, QueueUpdate, QueueUpdateType};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use common::{clock::Clock, fathom::DownloadReason, JobType, ServiceKind};
+use common::broadcast::{
+    BroadcastService, ProcessingStage, ProgressUpdate, QueueUpdate, QueueUpdateType, RetryInfo, SystemEvent,
+    SystemEventType,
+};
 use std::sync::Arc;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use crate::cancel;
+use crate::checkpoint::{Checkpoint, FileCheckpoint, Resumed};
+use crate::download::{self, Download, Downloader};
+use crate::fairness::Fairness;
+use crate::fathom::{FathomClient, MeetingArtifacts};
+use crate::keys::{self, TaskKeys};
+use crate::loom::{self, LoomClient, UploadJournal};
+use crate::metrics;
+use crate::pocketbase::{quote, PocketBase};
+use crate::progress::StageProgress;
+use crate::rate_limit::TokenBucket;
+use crate::report::{Measurements, TaskReport};
+use crate::results::{ProcessingResult, ResultStatus, ResultStore};
+use crate::retry::RetryPolicy;
+use crate::shutdown::Shutdown;
+use crate::smtp::TaskEmail;
+use crate::streaming::{self, Combined, Part, CHANNEL_DEPTH};
+use crate::transcode::{TranscodeMode, Transcoder};
+use crate::workspace::{SpaceGate, Workspace};
+use crate::config::EmailConfig;
 use crate::{WorkerConfig, WorkerResult, WorkerError};
 
+/// Global PocketBase collection holding the queue
+pub const QUEUE_COLLECTION: &str = "queue_items";
+
+/// Global PocketBase collection whose unique `(item, version)` index makes
+/// claims atomic
+pub const CLAIMS_COLLECTION: &str = "queue_claims";
+
+/// Global PocketBase collection of tasks that failed for good
+pub const DEAD_LETTER_COLLECTION: &str = "dead_letter";
+
+/// Global PocketBase collection of users, whose `email` task emails go to
+pub const USERS_COLLECTION: &str = "users";
+
+/// Global PocketBase collection of per-user preferences, one record per `user_id`
+pub const USER_SETTINGS_COLLECTION: &str = "user_settings";
+
+/// Oldest pending items fetched per attempt to claim one
+const CLAIM_BATCH: u32 = 10;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueTask {
     pub id: Uuid,
     pub user_id: String,
     pub meeting_id: String,
     pub topic: String,
+    /// What the task asks for, and so which pipeline runs it
+    #[serde(default)]
+    pub job_type: JobType,
     pub status: TaskStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub retry_count: u32,
     pub max_retries: u32,
+    /// Higher is claimed first; 0 unless set
+    #[serde(default)]
+    pub priority: i64,
     pub error_message: Option<String>,
+    /// Fathom key chosen for this meeting; the user's default unless set
+    #[serde(default)]
+    pub fathom_key_id: Option<String>,
+    /// Loom key chosen for this meeting; the user's default unless set
+    #[serde(default)]
+    pub loom_key_id: Option<String>,
+    /// PocketBase id of the `queue_items` record
+    #[serde(default)]
+    pub record_id: String,
+    /// Bumped on every claim; a claim names the version it was made from
+    #[serde(default)]
+    pub version: u64,
+    /// Worker holding the task while it runs
+    #[serde(default)]
+    pub claimed_by: Option<String>,
+    #[serde(default)]
+    pub claimed_at: Option<DateTime<Utc>>,
+    /// Not claimed again before this, after a retryable failure
+    #[serde(default)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// Loom upload session of an earlier attempt, to resume
+    #[serde(default)]
+    pub upload_session_id: Option<String>,
+    /// Bytes Loom acknowledged of that session
+    #[serde(default)]
+    pub upload_offset: u64,
+    /// The stages earlier attempts finished, for this one to skip
+    #[serde(default)]
+    pub checkpoint: Checkpoint,
+    /// Every failed attempt so far, oldest first
+    #[serde(default)]
+    pub attempts: Vec<TaskAttempt>,
+    /// The `dead_letter` record of a task that failed for good
+    #[serde(default)]
+    pub dead_letter_id: Option<String>,
+}
+
+/// One failed attempt at a task
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TaskAttempt {
+    pub error: String,
+    /// When the attempt was claimed
+    pub started_at: Option<DateTime<Utc>>,
+    pub failed_at: DateTime<Utc>,
+    /// The last stage the attempt reported progress in
+    pub stage: Option<ProcessingStage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -28,6 +166,27 @@ pub enum TaskStatus {
     InProgress,
     Completed,
     Failed,
+    /// Stopped at its owner's request
+    Cancelled,
+}
+
+impl TaskStatus {
+    /// Whether a task may go from this status to `next`
+    ///
+    /// A claim takes a `Pending` task `InProgress`; from there it is
+    /// returned to `Pending`, for a retry or at shutdown, or it ends. An
+    /// ended task isn't moved again by a worker.
+    pub fn can_become(&self, next: &TaskStatus) -> bool {
+        use TaskStatus::*;
+        matches!(
+            (self, next),
+            (Pending, InProgress | Cancelled) | (InProgress, Pending | Completed | Failed | Cancelled)
+        )
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled)
+    }
 }
 
 impl Default for QueueTask {
@@ -38,124 +197,3392 @@ impl Default for QueueTask {
             user_id: String::new(),
             meeting_id: String::new(),
             topic: String::new(),
+            job_type: JobType::ProcessMeeting,
             status: TaskStatus::Pending,
             created_at: now,
             updated_at: now,
             retry_count: 0,
             max_retries: 3,
+            priority: 0,
             error_message: None,
+            fathom_key_id: None,
+            loom_key_id: None,
+            record_id: String::new(),
+            version: 0,
+            claimed_by: None,
+            claimed_at: None,
+            next_attempt_at: None,
+            upload_session_id: None,
+            upload_offset: 0,
+            checkpoint: Checkpoint::default(),
+            attempts: Vec::new(),
+            dead_letter_id: None,
         }
     }
 }
 
-/// Entry point for processing a single task in the queue
-pub async fn process_task(
-    config: &WorkerConfig, 
-    user: &User, 
-    broadcast_service: Arc<BroadcastService>
-) -> WorkerResult<()> {
+impl QueueTask {
+    /// The key chosen for `service`, or `None` to use the user's default
+    pub fn key_id_for(&self, service: ServiceKind) -> Option<&str> {
+        match service {
+            ServiceKind::Fathom => self.fathom_key_id.as_deref(),
+            ServiceKind::Loom => self.loom_key_id.as_deref(),
+        }
+    }
+
+    /// The task a `queue_items` record describes
+    pub fn from_record(record: &Value) -> WorkerResult<Self> {
+        let text = |field: &str| record.get(field).and_then(Value::as_str).unwrap_or_default();
+        let optional = |field: &str| Some(text(field)).filter(|value| !value.is_empty()).map(str::to_string);
+        let number = |field: &str| record.get(field).and_then(Value::as_u64).unwrap_or_default();
+        let record_id = text("id").to_string();
+        let malformed = |why: String| WorkerError::Queue(format!("Queue item {} {}", record_id, why));
+
+        let id = Uuid::parse_str(text("task_id")).map_err(|e| malformed(format!("has no valid task_id: {}", e)))?;
+        let status = serde_json::from_value(record.get("status").cloned().unwrap_or(Value::Null))
+            .map_err(|_| malformed(format!("has an unknown status: {}", text("status"))))?;
+        let time = |field: &str| {
+            parse_time(text(field)).ok_or_else(|| malformed(format!("has an unreadable {}: '{}'", field, text(field))))
+        };
+        // Items from before job types are meetings
+        let job_type = match text("job_type") {
+            "" => JobType::ProcessMeeting,
+            name => JobType::parse(name).ok_or_else(|| malformed(format!("has an unknown job_type: {}", name)))?,
+        };
+        Ok(Self {
+            id,
+            user_id: text("user_id").to_string(),
+            meeting_id: text("meeting_id").to_string(),
+            topic: text("topic").to_string(),
+            job_type,
+            status,
+            created_at: time("created")?,
+            updated_at: time("updated")?,
+            retry_count: number("retry_count") as u32,
+            max_retries: number("max_retries") as u32,
+            priority: record.get("priority").and_then(Value::as_i64).unwrap_or_default(),
+            error_message: optional("error_message"),
+            fathom_key_id: optional("fathom_key_id"),
+            loom_key_id: optional("loom_key_id"),
+            version: number("version"),
+            claimed_by: optional("claimed_by"),
+            claimed_at: optional("claimed_at").as_deref().and_then(parse_time),
+            next_attempt_at: optional("next_attempt_at").as_deref().and_then(parse_time),
+            upload_session_id: optional("upload_session_id"),
+            upload_offset: number("upload_offset"),
+            checkpoint: record
+                .get("checkpoint")
+                .cloned()
+                .and_then(|checkpoint| serde_json::from_value(checkpoint).ok())
+                .unwrap_or_default(),
+            attempts: record
+                .get("attempts")
+                .cloned()
+                .and_then(|attempts| serde_json::from_value(attempts).ok())
+                .unwrap_or_default(),
+            dead_letter_id: optional("dead_letter_id"),
+            record_id,
+        })
+    }
+}
+
+/// A PocketBase date, `2024-05-01 09:30:00.000Z`, or RFC 3339
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .map(|time| time.and_utc())
+        })
+}
+
+/// `time` as PocketBase writes dates
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.3fZ").to_string()
+}
+
+/// The work done for one claimed task
+///
+/// [`FathomToLoom`] is the real pipeline; tests substitute their own. Between
+/// stages a pipeline checks `shutdown`, failing with
+/// [`WorkerError::ShuttingDown`] to have its task returned to the queue. It
+/// notes when its stages run and what they move in `measurements`.
+///
+/// A run may be dropped partway when its task is cancelled, and
+/// [`discard`](Pipeline::discard) is then asked to remove what it left.
+pub trait Pipeline: Send + Sync + 'static {
+    fn run(
+        &self,
+        task: &QueueTask,
+        shutdown: &Shutdown,
+        measurements: &Measurements,
+    ) -> impl Future<Output = WorkerResult<Delivery>> + Send;
+
+    /// Remove what attempts at `task` left for a later one, as it won't run
+    /// again
+    fn discard(&self, task: &QueueTask) -> impl Future<Output = ()> + Send {
+        let _ = task;
+        async {}
+    }
+}
+
+/// What [`Pipelines`] holds: a [`Pipeline`] of any type, its future boxed
+trait AnyPipeline: Send + Sync + 'static {
+    fn run_boxed<'a>(
+        &'a self,
+        task: &'a QueueTask,
+        shutdown: &'a Shutdown,
+        measurements: &'a Measurements,
+    ) -> BoxFuture<'a, WorkerResult<Delivery>>;
+
+    fn discard_boxed<'a>(&'a self, task: &'a QueueTask) -> BoxFuture<'a, ()>;
+}
+
+impl<P: Pipeline> AnyPipeline for P {
+    fn run_boxed<'a>(
+        &'a self,
+        task: &'a QueueTask,
+        shutdown: &'a Shutdown,
+        measurements: &'a Measurements,
+    ) -> BoxFuture<'a, WorkerResult<Delivery>> {
+        Box::pin(self.run(task, shutdown, measurements))
+    }
+
+    fn discard_boxed<'a>(&'a self, task: &'a QueueTask) -> BoxFuture<'a, ()> {
+        Box::pin(self.discard(task))
+    }
+}
+
+/// Runs each task with the pipeline registered for its job type
+///
+/// A worker only claims the job types it is configured for, so a task with
+/// no pipeline here means the configuration and the registry disagree; it
+/// fails for good rather than be retried.
+#[derive(Default)]
+pub struct Pipelines {
+    routes: Vec<(JobType, Box<dyn AnyPipeline>)>,
+}
+
+impl Pipelines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run tasks of `job_type` with `pipeline`, in place of any registered
+    /// for it before
+    pub fn with(mut self, job_type: JobType, pipeline: impl Pipeline) -> Self {
+        self.routes.retain(|(registered, _)| *registered != job_type);
+        self.routes.push((job_type, Box::new(pipeline)));
+        self
+    }
+
+    pub fn handles(&self, job_type: JobType) -> bool {
+        self.routes.iter().any(|(registered, _)| *registered == job_type)
+    }
+}
+
+impl Pipeline for Pipelines {
+    async fn run(&self, task: &QueueTask, shutdown: &Shutdown, measurements: &Measurements) -> WorkerResult<Delivery> {
+        match self.routes.iter().find(|(job_type, _)| *job_type == task.job_type) {
+            Some((_, pipeline)) => pipeline.run_boxed(task, shutdown, measurements).await,
+            None => Err(WorkerError::Config(format!("No pipeline runs {} tasks", task.job_type))),
+        }
+    }
+
+    async fn discard(&self, task: &QueueTask) {
+        if let Some((_, pipeline)) = self.routes.iter().find(|(job_type, _)| *job_type == task.job_type) {
+            pipeline.discard_boxed(task).await;
+        }
+    }
+}
+
+/// Where a pipeline left a task's meeting
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Delivery {
+    /// The Loom page of the uploaded video
+    pub share_url: Option<String>,
+}
+
+/// Moves a task's meeting from Fathom to Loom
+pub struct FathomToLoom {
+    pub config: WorkerConfig,
+    pub client: reqwest::Client,
+    /// Where an upload's progress is kept, for a retry to resume from
+    pub pb: PocketBase,
+    /// Told how far each stage has got
+    pub broadcast_service: Arc<BroadcastService>,
+    /// Paces every task's calls to Fathom
+    pub fathom_limiter: Arc<TokenBucket>,
+}
+
+impl FathomToLoom {
+    /// Where `task` reports how far it has got with `stage`
+    fn stage<'a>(&'a self, task: &'a QueueTask, stage: ProcessingStage) -> StageProgress<'a> {
+        StageProgress::new(&self.broadcast_service, task.id, &task.user_id, stage)
+    }
+}
+
+impl Pipeline for FathomToLoom {
+    async fn run(&self, task: &QueueTask, shutdown: &Shutdown, measurements: &Measurements) -> WorkerResult<Delivery> {
+        let settings = &self.config.worker;
+        let workspace = Workspace::create(&settings.scratch_dir, task.id)?;
+        let pipeline = process_pipeline(self, task, &workspace, shutdown, measurements);
+        let result = within("task", settings.task_deadline, pipeline).await;
+        // The next claim resumes from what this attempt wrote, unless it stalled
+        let again = match &result {
+            Ok(_) | Err(WorkerError::StageTimeout { .. }) => false,
+            Err(WorkerError::ShuttingDown) => true,
+            Err(e) => e.is_retryable() && task.retry_count < task.max_retries,
+        };
+        match again {
+            true => workspace.keep(),
+            false => workspace.discard(),
+        }
+        result
+    }
+
+    async fn discard(&self, task: &QueueTask) {
+        // Opened without being kept, so removed as it's dropped
+        if let Err(e) = Workspace::create(&self.config.worker.scratch_dir, task.id) {
+            warn!("Failed to remove the task's workspace: {}", e);
+        }
+    }
+}
+
+/// Run `stage`, giving up with [`WorkerError::StageTimeout`] after `limit` seconds
+async fn within<T>(stage: &'static str, limit: u64, future: impl Future<Output = WorkerResult<T>>) -> WorkerResult<T> {
+    let limit = Duration::from_secs(limit);
+    timeout(limit, future)
+        .await
+        .unwrap_or_else(|_| Err(WorkerError::StageTimeout { stage, limit }))
+}
+
+/// [`within`], recording how long the stage took however it ended
+async fn timed<T>(
+    measurements: &Measurements,
+    stage: &'static str,
+    limit: u64,
+    future: impl Future<Output = WorkerResult<T>>,
+) -> WorkerResult<T> {
+    let started_at = Utc::now();
+    let started = tokio::time::Instant::now();
+    let result = within(stage, limit, future).await;
+    let took = started.elapsed();
+    metrics::observe(&metrics::STAGE_DURATION, &[("stage", stage)], took.as_secs_f64());
+    measurements.stage(stage, started_at, took);
+    result
+}
+
+/// Delivers the emails telling users how their tasks ended
+///
+/// [`SmtpClient`](crate::smtp::SmtpClient) is the real one.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: TaskEmail) -> BoxFuture<'_, WorkerResult<()>>;
+}
+
+/// Logs emails instead of sending them
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: TaskEmail) -> BoxFuture<'_, WorkerResult<()>> {
+        info!(to = %email.to_email, "Email: {}", email.subject);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// What every task run by [`run_pool`] shares
+pub struct TaskContext<P> {
+    pub pb: PocketBase,
+    pub worker_id: String,
+    pub poll_interval: Duration,
+    /// Asked before each claim whether the scratch volume has room
+    pub disk: SpaceGate,
+    /// Which user's task within a priority is claimed next
+    pub fairness: Fairness,
+    /// The job types claimed, each with how many of it may run at once
+    pub job_types: Vec<(JobType, usize)>,
+    pub pipeline: P,
+    pub broadcast_service: Arc<BroadcastService>,
+    /// What the emails to tasks' owners link to
+    pub email: EmailConfig,
+    pub retry: RetryPolicy,
+    pub clock: Arc<dyn Clock>,
+    pub mailer: Arc<dyn Mailer>,
+    /// Where the result of each attempt is kept
+    pub results: Arc<dyn ResultStore>,
+}
+
+/// Claim tasks and run up to `concurrency` of them at once until `shutdown`
+/// is requested, then wait up to `grace` for those still running
+///
+/// A permit is taken before each claim, so nothing is claimed while every
+/// permit is in use, and only the job types of `context` with a task
+/// running short of their own limit are claimed. Each task runs in its own
+/// Tokio task: a failing or panicking one is marked `Failed` without
+/// stopping the others. Tasks outliving `grace` are stopped and released
+/// back to the queue.
+pub async fn run_pool<P: Pipeline>(
+    context: Arc<TaskContext<P>>,
+    concurrency: usize,
+    shutdown: Shutdown,
+    grace: Duration,
+) {
+    // Its sender dropped, the drain is never requested
+    run_pool_until_drained(context, concurrency, shutdown, Shutdown::channel().1, grace).await;
+}
+
+/// [`run_pool`], which also stops claiming once `drain` is raised and then
+/// returns when the tasks still running have finished
+///
+/// A shutdown while draining waits only the `grace` for them, as it would
+/// have.
+pub async fn run_pool_until_drained<P: Pipeline>(
+    context: Arc<TaskContext<P>>,
+    concurrency: usize,
+    shutdown: Shutdown,
+    drain: Shutdown,
+    grace: Duration,
+) {
+    let stopping = || async {
+        tokio::select! {
+            _ = shutdown.requested() => {}
+            _ = drain.requested() => {}
+        }
+    };
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let per_type: Vec<(JobType, Arc<Semaphore>)> = context
+        .job_types
+        .iter()
+        .map(|(job_type, limit)| (*job_type, Arc::new(Semaphore::new((*limit).clamp(1, Semaphore::MAX_PERMITS)))))
+        .collect();
+    let mut running = JoinSet::new();
+    let abandon = CancellationToken::new();
+
     loop {
-        if let Some(task) = claim_oldest_unclaimed_task().await? {
-            // Broadcast task started
-            broadcast_service.broadcast(QueueUpdate {
-                update_type: QueueUpdateType::TaskStarted,
-                affected_user_id: Some(task.user_id.clone()),
-                global_position: None, // Will be updated based on queue position
-                task_id: Some(task.id),
-                timestamp: Utc::now(),
-            }).await;
-            
-            update_status(TaskStatus::InProgress).await?;
-
-            let result = process_pipeline(config, &task, broadcast_service.clone()).await;
-
-            match result {
-                Ok(_) => {
-                    update_status(TaskStatus::Completed).await?;
-                    
-                    // Broadcast task completed
-                    broadcast_service.broadcast(QueueUpdate {
-                        update_type: QueueUpdateType::TaskCompleted,
-                        affected_user_id: Some(task.user_id.clone()),
-                        global_position: None,
-                        task_id: Some(task.id),
-                        timestamp: Utc::now(),
-                    }).await;
+        let permit = tokio::select! {
+            permit = permits.clone().acquire_owned() => permit.expect("the semaphore is never closed"),
+            _ = stopping() => break,
+        };
+        while running.try_join_next().is_some() {}
+        if shutdown.is_requested() || drain.is_requested() {
+            break;
+        }
+        let mut open: Vec<(JobType, OwnedSemaphorePermit)> = per_type
+            .iter()
+            .filter_map(|(job_type, permits)| Some((*job_type, permits.clone().try_acquire_owned().ok()?)))
+            .collect();
+        if open.is_empty() {
+            // Every job type is at its limit until one of its tasks ends
+            drop(permit);
+            tokio::select! {
+                _ = running.join_next() => {}
+                _ = stopping() => break,
+            }
+            continue;
+        }
+
+        let job_types: Vec<JobType> = open.iter().map(|(job_type, _)| *job_type).collect();
+        let claimed = match context.disk.has_room() {
+            true => claim_next_task(&context.pb, &context.worker_id, context.clock.now(), &context.fairness, &job_types).await,
+            false => Ok(None),
+        };
+        match claimed {
+            Ok(Some(task)) => {
+                metrics::increment(&metrics::TASKS_CLAIMED, &[]);
+                let type_permit = open
+                    .iter()
+                    .position(|(job_type, _)| *job_type == task.job_type)
+                    .map(|index| open.swap_remove(index).1);
+                let context = context.clone();
+                let shutdown = shutdown.clone();
+                let abandon = abandon.clone();
+                running.spawn(async move {
+                    supervise(&context, task, shutdown, abandon).await;
+                    drop((permit, type_permit));
+                });
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to claim a queue item: {}", e),
+        }
+        drop((permit, open));
+        tokio::select! {
+            _ = sleep(context.poll_interval) => {}
+            _ = stopping() => break,
+        }
+    }
+
+    if !shutdown.is_requested() && !running.is_empty() {
+        info!("Draining: claiming nothing more until the {} running tasks finish", running.len());
+        loop {
+            tokio::select! {
+                joined = running.join_next() => if joined.is_none() {
+                    info!("Drained");
+                    return;
+                },
+                _ = shutdown.requested() => break,
+            }
+        }
+    }
+    if running.is_empty() {
+        return;
+    }
+    info!("Waiting up to {}s for {} running tasks to finish", grace.as_secs(), running.len());
+    let drained = timeout(grace, async { while running.join_next().await.is_some() {} }).await;
+    if drained.is_err() {
+        warn!("{} tasks outlived the grace period and are returned to the queue", running.len());
+        abandon.cancel();
+        while running.join_next().await.is_some() {}
+    }
+}
+
+/// Run `task`, marking it failed if it panics and releasing it if it is
+/// still running when `abandon` is cancelled
+async fn supervise<P: Pipeline>(
+    context: &Arc<TaskContext<P>>,
+    task: QueueTask,
+    shutdown: Shutdown,
+    abandon: CancellationToken,
+) {
+    // Everything logged while the task runs names it and its owner
+    let span = info_span!("task", task_id = %task.id, user_id = %task.user_id);
+    metrics::add(&metrics::TASKS_IN_FLIGHT, &[], 1.0);
+    let mut run = {
+        let context = context.clone();
+        let task = task.clone();
+        tokio::spawn(async move { run_task(&context, &task, &shutdown).await }.instrument(span.clone()))
+    };
+
+    let outcome = tokio::select! {
+        outcome = &mut run => outcome,
+        _ = abandon.cancelled() => {
+            run.abort();
+            run.await
+        }
+    };
+    match outcome {
+        // Sent here, so cutting a task off at shutdown can't cut these off
+        // after the task's outcome is recorded
+        Ok(Ok(ended)) => {
+            async {
+                if let Err(e) = context.results.store(&task.user_id, task.id, &ended.result).await {
+                    warn!("Failed to keep the result of the attempt: {}", e);
+                }
+                if let Some(notice) = ended.notice {
+                    notify(context, &task, notice, &ended.result.report).await;
                 }
+            }
+            .instrument(span.clone())
+            .await
+        }
+        Ok(Err(e)) => error!(parent: &span, "Task failed to record its outcome: {}", e),
+        Err(e) if e.is_cancelled() => {
+            warn!(parent: &span, "Task stopped unfinished at shutdown");
+            if let Err(e) = release_task(context, &task).await {
+                error!(parent: &span, "Failed to return the task to the queue: {}", e);
+            }
+        }
+        Err(e) => {
+            let message = match e.try_into_panic() {
+                Ok(payload) => format!("Task panicked: {}", panic_message(&*payload)),
+                Err(e) => format!("Task was cancelled: {}", e),
+            };
+            error!(parent: &span, "{}", message);
+            let attempt = context.attempt(&task, &message, None);
+            if let Err(e) = dead_letter(context, &task, attempt).await {
+                error!(parent: &span, "Failed to mark the task failed: {}", e);
+            }
+            metrics::increment(&metrics::TASKS_FAILED, &[]);
+            broadcast_failure(&context.broadcast_service, &task, &message).await;
+        }
+    }
+    metrics::add(&metrics::TASKS_IN_FLIGHT, &[], -1.0);
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+async fn broadcast(
+    broadcast_service: &BroadcastService,
+    update_type: QueueUpdateType,
+    task: &QueueTask,
+    retry: Option<RetryInfo>,
+) {
+    broadcast_service.broadcast(queue_update(update_type, task, retry)).await;
+}
+
+/// Tell the task's owner it failed for good, and why
+async fn broadcast_failure(broadcast_service: &BroadcastService, task: &QueueTask, error: &str) {
+    broadcast_service.broadcast(QueueUpdate {
+        error: Some(error.to_string()),
+        ..queue_update(QueueUpdateType::TaskFailed, task, None)
+    }).await;
+}
+
+fn queue_update(update_type: QueueUpdateType, task: &QueueTask, retry: Option<RetryInfo>) -> QueueUpdate {
+    QueueUpdate {
+        update_type,
+        affected_user_id: Some(task.user_id.clone()),
+        global_position: None, // Will be updated based on queue position
+        task_id: Some(task.id),
+        retry,
+        error: None,
+        timestamp: Utc::now(),
+    }
+}
+
+/// What a task's owner may be emailed once its outcome is recorded
+#[derive(Debug)]
+enum Notice {
+    /// It failed for good with `error` in `stage`
+    Failed { error: WorkerError, stage: Option<ProcessingStage> },
+    /// Its meeting is on Loom at `share_url`
+    Delivered { share_url: String },
+}
+
+/// How a task's run ended, once its outcome is recorded on the queue item
+#[derive(Debug)]
+struct Ended {
+    result: ProcessingResult,
+    notice: Option<Notice>,
+}
+
+/// Run one claimed task through the pipeline, reporting its progress, and
+/// return its result and what its owner may want to hear of it
+async fn run_task<P: Pipeline>(context: &TaskContext<P>, task: &QueueTask, shutdown: &Shutdown) -> WorkerResult<Ended> {
+    broadcast(&context.broadcast_service, QueueUpdateType::TaskStarted, task, None).await;
+    let mut progress = context.broadcast_service.subscribe_progress();
+
+    let measurements = Measurements::default();
+    let started_at = context.clock.now();
+    // A run finishing as the cancellation comes in is left to finish
+    let result = tokio::select! {
+        biased;
+        result = context.pipeline.run(task, shutdown, &measurements) => result,
+        _ = cancel::requested(&context.pb, &context.broadcast_service, task, context.poll_interval) => {
+            Err(WorkerError::Cancelled)
+        }
+    };
+    let finished_at = context.clock.now();
+    let stage = last_stage(&mut progress, task.id);
+    let ended = |status, loom_url, error: Option<&WorkerError>, notice| {
+        let report = TaskReport::new(measurements.snapshot(), status, task.retry_count, started_at, finished_at);
+        report.log();
+        Ended {
+            result: ProcessingResult {
+                meeting_id: task.meeting_id.clone(),
+                status,
+                attempt: task.retry_count + 1,
+                loom_url,
+                error: error.map(|e| error_summary(&e.to_string())),
+                finished_at,
+                report,
+            },
+            notice,
+        }
+    };
+    match result {
+        Ok(delivery) => {
+            complete_task(&context.pb, task).await?;
+            metrics::increment(&metrics::TASKS_COMPLETED, &[]);
+            broadcast(&context.broadcast_service, QueueUpdateType::TaskCompleted, task, None).await;
+
+            let notice = delivery.share_url.clone().map(|share_url| Notice::Delivered { share_url });
+            Ok(ended(ResultStatus::Completed, delivery.share_url, None, notice))
+        }
+        Err(e @ WorkerError::ShuttingDown) => {
+            info!("Task stopped between stages at shutdown");
+            release_task(context, task).await?;
+            Ok(ended(ResultStatus::Interrupted, None, Some(&e), None))
+        }
+        // Its owner knows; no email
+        Err(e @ WorkerError::Cancelled) => {
+            info!("Task cancelled");
+            context.pipeline.discard(task).await;
+            cancel_task(&context.pb, task).await?;
+            metrics::increment(&metrics::TASKS_CANCELLED, &[]);
+            broadcast(&context.broadcast_service, QueueUpdateType::TaskCancelled, task, None).await;
+            Ok(ended(ResultStatus::Cancelled, None, Some(&e), None))
+        }
+        Err(e) if e.is_retryable() && task.retry_count < task.max_retries => {
+            let retry_count = task.retry_count + 1;
+            let delay = context.retry.delay(retry_count);
+            let next_attempt_at = context.clock.now() + chrono::Duration::milliseconds(delay.as_millis() as i64);
+            warn!(
+                "Task failed, retry {} of {} in {}s: {}",
+                retry_count,
+                task.max_retries,
+                delay.as_secs(),
+                e
+            );
+            let attempt = context.attempt(task, &e.to_string(), stage);
+            return_task_to_queue(&context.pb, task, retry_count, next_attempt_at, attempt).await?;
+            metrics::increment(&metrics::TASKS_RETRIED, &[]);
+            let retry = RetryInfo { retry_count, max_retries: task.max_retries, next_attempt_at };
+            broadcast(&context.broadcast_service, QueueUpdateType::TaskRetried, task, Some(retry)).await;
+            Ok(ended(ResultStatus::Retrying, None, Some(&e), None))
+        }
+        Err(e) => {
+            dead_letter(context, task, context.attempt(task, &e.to_string(), stage)).await?;
+            metrics::increment(&metrics::TASKS_FAILED, &[]);
+            broadcast_failure(&context.broadcast_service, task, &e.to_string()).await;
+            let result = ended(ResultStatus::Failed, None, Some(&e), None).result;
+            Ok(Ended { result, notice: Some(Notice::Failed { error: e, stage }) })
+        }
+    }
+}
+
+/// Who a task's emails go to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Owner {
+    pub email: String,
+    /// Whether they asked to hear of meetings that made it, not only failures
+    pub email_on_success: bool,
+}
+
+/// The owner of `user_id`'s tasks, `None` for a user with no email address
+pub async fn owner(pb: &PocketBase, user_id: &str) -> WorkerResult<Option<Owner>> {
+    let users = pb.list(USERS_COLLECTION, &format!("id = {}", quote(user_id)), "", 1).await?;
+    let Some(email) = users.first().and_then(|user| user["email"].as_str()).filter(|email| !email.is_empty()) else {
+        return Ok(None);
+    };
+    let settings = pb.list(USER_SETTINGS_COLLECTION, &format!("user_id = {}", quote(user_id)), "", 1).await?;
+    Ok(Some(Owner {
+        email: email.to_string(),
+        email_on_success: settings
+            .first()
+            .and_then(|settings| settings["email_on_success"].as_bool())
+            .unwrap_or(false),
+    }))
+}
+
+/// Email the owner of `task` of `notice`, with the attempt's `report`,
+/// unless it's news they didn't ask for
+///
+/// The task's outcome is recorded before this, so a missing owner or an
+/// email that can't be sent is only logged.
+async fn notify<P>(context: &TaskContext<P>, task: &QueueTask, notice: Notice, report: &TaskReport) {
+    let owner = match owner(&context.pb, &task.user_id).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => {
+            warn!("Task owner has no email address to tell how the task ended");
+            return;
+        }
+        Err(e) => {
+            error!("Failed to look up the task's owner to email: {}", e);
+            return;
+        }
+    };
+    let email = match notice {
+        Notice::Failed { error, stage } => failure_email(&error, task, stage, report, &owner.email, &context.email),
+        Notice::Delivered { share_url } if owner.email_on_success => success_email(task, &share_url, report, &owner.email),
+        Notice::Delivered { .. } => return,
+    };
+    if let Err(e) = context.mailer.send(email).await {
+        error!("Failed to email the task's owner: {}", e);
+    }
+}
+
+/// The stage of the last progress `task_id` reported on `progress`
+fn last_stage(progress: &mut broadcast::Receiver<ProgressUpdate>, task_id: Uuid) -> Option<ProcessingStage> {
+    let mut stage = None;
+    loop {
+        match progress.try_recv() {
+            Ok(update) if update.task_id == task_id => stage = Some(update.stage),
+            Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+            Err(_) => return stage,
+        }
+    }
+}
+
+impl<P> TaskContext<P> {
+    /// The attempt at `task` that just failed with `error` in `stage`
+    fn attempt(&self, task: &QueueTask, error: &str, stage: Option<ProcessingStage>) -> TaskAttempt {
+        TaskAttempt {
+            error: error.to_string(),
+            started_at: task.claimed_at,
+            failed_at: self.clock.now(),
+            stage,
+        }
+    }
+}
+
+/// Claim the oldest pending task of the highest priority waiting for
+/// `worker_id`, or `None` when there is nothing left to claim
+pub async fn claim_oldest_unclaimed_task(
+    pb: &PocketBase,
+    worker_id: &str,
+    now: DateTime<Utc>,
+) -> WorkerResult<Option<QueueTask>> {
+    claim_next_task(pb, worker_id, now, &Fairness::fifo(), &[JobType::ProcessMeeting]).await
+}
+
+/// Claim a pending task of one of `job_types` for `worker_id`, of the
+/// highest priority waiting and the one `fairness` puts first, or `None`
+/// when there is nothing left to claim
+///
+/// Items of other job types, known or not, are left for workers that run
+/// them. Items waiting out a retry delay past `now` aren't due yet, and
+/// dead-lettered ones are never claimed. Items another worker claims first
+/// are skipped for the next in order, and malformed ones are skipped with a
+/// warning rather than blocking the queue. Lower priorities wait until no
+/// item of a higher one is left to claim.
+pub async fn claim_next_task(
+    pb: &PocketBase,
+    worker_id: &str,
+    now: DateTime<Utc>,
+    fairness: &Fairness,
+    job_types: &[JobType],
+) -> WorkerResult<Option<QueueTask>> {
+    if job_types.is_empty() {
+        return Ok(None);
+    }
+    let mut types: Vec<String> = job_types.iter().map(|job_type| format!("job_type = {}", quote(job_type.as_str()))).collect();
+    if job_types.contains(&JobType::ProcessMeeting) {
+        types.push("job_type = ''".to_string());
+    }
+    let batch = CLAIM_BATCH.max(fairness.window() as u32);
+    let mut skipped: Vec<String> = Vec::new();
+    loop {
+        let mut filter = format!(
+            "status = {} && dead_letter_id = '' && (next_attempt_at = '' || next_attempt_at <= {}) && ({})",
+            quote("Pending"),
+            quote(&format_time(now)),
+            types.join(" || ")
+        );
+        for id in &skipped {
+            filter.push_str(&format!(" && id != {}", quote(id)));
+        }
+        let candidates = pb.list(QUEUE_COLLECTION, &filter, "-priority,created,id", batch).await?;
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let mut band = Vec::new();
+        for record in candidates {
+            match QueueTask::from_record(&record) {
+                Ok(task) => band.push(task),
                 Err(e) => {
-                    update_status(TaskStatus::Failed).await?;
-                    
-                    // Broadcast task failed
-                    broadcast_service.broadcast(QueueUpdate {
-                        update_type: QueueUpdateType::TaskFailed,
-                        affected_user_id: Some(task.user_id.clone()),
-                        global_position: None,
-                        task_id: Some(task.id),
-                        timestamp: Utc::now(),
-                    }).await;
-                    
-                    return_task_to_queue(&task).await?;
-                    email_user_failure(&e, user).await?;
+                    warn!("Skipping queue item: {}", e);
+                    skipped.push(record.get("id").and_then(Value::as_str).unwrap_or_default().to_string());
                 }
             }
-        } else {
-            sleep(Duration::from_secs(2)).await;
+        }
+        // The rest of the batch is a lower priority
+        let top = band.first().map(|task| task.priority);
+        band.retain(|task| Some(task.priority) == top);
+        for task in fairness.order(band) {
+            if let Some(claimed) = try_claim(pb, &task, worker_id, now).await? {
+                fairness.served(&claimed.user_id, now);
+                return Ok(Some(claimed));
+            }
+            skipped.push(task.record_id);
         }
     }
 }
 
-async fn claim_oldest_unclaimed_task() -> WorkerResult<Option<QueueTask>> {
-    // Placeholder: simulate task retrieval
-    Ok(Some(QueueTask::default()))
+/// Claim `task` at the version it was read, or `None` if another worker did
+async fn try_claim(
+    pb: &PocketBase,
+    task: &QueueTask,
+    worker_id: &str,
+    now: DateTime<Utc>,
+) -> WorkerResult<Option<QueueTask>> {
+    let claim = json!({
+        "item": task.record_id,
+        "version": task.version,
+        "worker_id": worker_id,
+    });
+    match pb.create_record(CLAIMS_COLLECTION, &claim).await {
+        Ok(_) => {}
+        Err(e) if e.is_not_unique() => {
+            debug!("Queue item {} was claimed by another worker", task.record_id);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let claimed = json!({
+        "status": TaskStatus::InProgress,
+        "claimed_by": worker_id,
+        "claimed_at": format_time(now),
+        "version": task.version + 1,
+    });
+    let record = pb.update_record(QUEUE_COLLECTION, &task.record_id, &claimed).await?;
+    let task = QueueTask::from_record(&record)?;
+    info!(task_id = %task.id, user_id = %task.user_id, "Claimed queue item {}", task.record_id);
+    Ok(Some(task))
 }
 
-async fn return_task_to_queue(task: &QueueTask) -> WorkerResult<()> {
-    // Placeholder: implement queue return logic
-    Ok(())
+/// Put `task` back in the queue for retry number `retry_count`, not to be
+/// claimed before `next_attempt_at`, noting the `attempt` that failed
+async fn return_task_to_queue(
+    pb: &PocketBase,
+    task: &QueueTask,
+    retry_count: u32,
+    next_attempt_at: DateTime<Utc>,
+    attempt: TaskAttempt,
+) -> WorkerResult<()> {
+    let error = attempt.error.clone();
+    let mut attempts = task.attempts.clone();
+    attempts.push(attempt);
+    let update = json!({
+        "retry_count": retry_count,
+        "next_attempt_at": format_time(next_attempt_at),
+        "attempts": attempts,
+        "claimed_by": "",
+        "claimed_at": "",
+    });
+    update_status(pb, task, TaskStatus::Pending, Some(&error), update).await
 }
 
-async fn update_status(status: TaskStatus) -> WorkerResult<()> {
-    // Placeholder: implement status update logic
+/// Give `task` up unfinished, with a note, for any worker to claim afresh
+async fn release_task<P>(context: &TaskContext<P>, task: &QueueTask) -> WorkerResult<()> {
+    let detail = format!("{}; returned to the queue by {}", WorkerError::ShuttingDown, context.worker_id);
+    let update = json!({ "claimed_by": "", "claimed_at": "" });
+    update_status(&context.pb, task, TaskStatus::Pending, Some(&detail), update).await?;
+    broadcast(&context.broadcast_service, QueueUpdateType::PositionUpdated, task, None).await;
     Ok(())
 }
 
-async fn email_user_failure(error: &WorkerError, user: &User) -> WorkerResult<()> {
-    // Placeholder: implement email logic
+/// Fail `task` for good after its last `attempt`, copying it with every
+/// attempt to the dead-letter collection and telling the system topic
+///
+/// Should the copy fail, the item is still marked `Failed`.
+async fn dead_letter<P>(context: &TaskContext<P>, task: &QueueTask, attempt: TaskAttempt) -> WorkerResult<()> {
+    let error = attempt.error.clone();
+    let mut attempts = task.attempts.clone();
+    attempts.push(attempt);
+    let last_stage = attempts.iter().rev().find_map(|attempt| attempt.stage);
+    let record = json!({
+        "queue_item": task.record_id,
+        "task_id": task.id,
+        "user_id": task.user_id,
+        "meeting_id": task.meeting_id,
+        "topic": task.topic,
+        "task": QueueTask { attempts: Vec::new(), ..task.clone() },
+        "attempts": attempts,
+        "last_stage": last_stage,
+        "error_message": error,
+        "failed_at": format_time(context.clock.now()),
+    });
+    let dead_letter_id = match context.pb.create_record(DEAD_LETTER_COLLECTION, &record).await {
+        Ok(record) => record.get("id").and_then(Value::as_str).map(str::to_string),
+        Err(e) => {
+            error!("Failed to dead-letter the task: {}", e);
+            None
+        }
+    };
+
+    let update = json!({
+        "attempts": attempts,
+        "dead_letter_id": dead_letter_id.as_deref().unwrap_or_default(),
+    });
+    update_status(&context.pb, task, TaskStatus::Failed, Some(&error), update).await?;
+    if dead_letter_id.is_some() {
+        info!("Task dead-lettered after {} attempts", attempts.len());
+        let event_type = SystemEventType::TaskDeadLettered { task_id: task.id, error };
+        context.broadcast_service.broadcast_system(SystemEvent::new(event_type, task.user_id.clone(), 0));
+    }
     Ok(())
 }
 
-async fn process_pipeline(
-    config: &WorkerConfig, 
-    task: &QueueTask, 
-    _broadcast_service: Arc<BroadcastService>
+/// Record that `task` is done, with nothing left to resume
+async fn complete_task(pb: &PocketBase, task: &QueueTask) -> WorkerResult<()> {
+    let update = json!({
+        "checkpoint": null,
+        "upload_session_id": "",
+        "upload_offset": 0,
+    });
+    update_status(pb, task, TaskStatus::Completed, None, update).await
+}
+
+/// Mark `task` cancelled, dropping what a later attempt would have resumed
+/// from
+async fn cancel_task(pb: &PocketBase, task: &QueueTask) -> WorkerResult<()> {
+    let update = json!({
+        "checkpoint": null,
+        "upload_session_id": "",
+        "upload_offset": 0,
+    });
+    update_status(pb, task, TaskStatus::Cancelled, Some(&WorkerError::Cancelled.to_string()), update).await
+}
+
+/// Move `task` to `status`, with `detail` as its error message, or none,
+/// and `fields` besides, if its queue item is still as the worker claimed it
+///
+/// The item is read first. One whose version or worker has changed since
+/// the claim has been taken over by someone else, and is left alone with
+/// a retryable [`WorkerError::Queue`]; a move [`TaskStatus::can_become`]
+/// doesn't allow is refused. PocketBase stamps `updated` on the write, and
+/// a task that ends gets `completed_at` as well. The claim itself doesn't
+/// come through here: the claims collection already keeps it to one
+/// worker.
+pub async fn update_status(
+    pb: &PocketBase,
+    task: &QueueTask,
+    status: TaskStatus,
+    detail: Option<&str>,
+    fields: Value,
 ) -> WorkerResult<()> {
-    // Simulate metadata fetching
-    fetch_meeting_data(task).await?;
-    store_metadata_in_user_db(task).await?;
-    
-    // Simulate video processing
-    download_video(task).await?;
-    upload_to_loom(task).await?;
+    let records = pb.list(QUEUE_COLLECTION, &format!("id = {}", quote(&task.record_id)), "", 1).await?;
+    let current = match records.first() {
+        Some(record) => QueueTask::from_record(record)?,
+        None => return Err(WorkerError::Queue(format!("Queue item {} is gone", task.record_id))),
+    };
+    if current.version != task.version || current.claimed_by != task.claimed_by {
+        return Err(WorkerError::Queue(format!(
+            "Queue item {} changed under the worker: it is at version {} held by {}, not version {} held by {}",
+            task.record_id,
+            current.version,
+            current.claimed_by.as_deref().unwrap_or("no one"),
+            task.version,
+            task.claimed_by.as_deref().unwrap_or("no one"),
+        )));
+    }
+    if !current.status.can_become(&status) {
+        return Err(WorkerError::Internal(format!(
+            "Queue item {} can't go from {:?} to {:?}",
+            task.record_id, current.status, status
+        )));
+    }
 
+    let mut update = json!({
+        "status": status,
+        "error_message": detail.unwrap_or_default(),
+    });
+    if status.is_final() {
+        update["completed_at"] = json!(format_time(Utc::now()));
+    }
+    if let (Some(update), Some(fields)) = (update.as_object_mut(), fields.as_object()) {
+        update.extend(fields.clone());
+    }
+    pb.update_record(QUEUE_COLLECTION, &task.record_id, &update).await?;
     Ok(())
 }
 
-async fn fetch_meeting_data(task: &QueueTask) -> WorkerResult<()> {
-    // Placeholder for meeting data fetching
+/// Put `task` back to `Pending` for any worker to claim, at an operator's
+/// asking
+///
+/// Only a task that failed for good, or is stuck `InProgress` under a
+/// worker that is gone, is requeued; other statuses are refused. Like the
+/// backend's requeue of a dead letter, its retries, attempts, error and
+/// claim are reset and its dead-letter entry is removed, with the item's
+/// version kept so its next claim goes through. A worker still running the
+/// task finds its claim gone when it next moves it. This is the one move
+/// [`TaskStatus::can_become`] doesn't allow, so it doesn't go through
+/// [`update_status`].
+pub async fn requeue(pb: &PocketBase, task: &QueueTask) -> WorkerResult<()> {
+    if !matches!(task.status, TaskStatus::Failed | TaskStatus::InProgress) {
+        return Err(WorkerError::Queue(format!(
+            "Queue item {} is {:?}; only a failed or stuck task is requeued",
+            task.record_id, task.status
+        )));
+    }
+    let reset = json!({
+        "status": TaskStatus::Pending,
+        "retry_count": 0,
+        "error_message": "",
+        "next_attempt_at": "",
+        "claimed_by": "",
+        "claimed_at": "",
+        "attempts": [],
+        "dead_letter_id": "",
+    });
+    pb.update_record(QUEUE_COLLECTION, &task.record_id, &reset).await?;
+    if let Some(dead_letter_id) = &task.dead_letter_id {
+        if let Err(e) = pb.delete_record(DEAD_LETTER_COLLECTION, dead_letter_id).await {
+            // The task is queued either way; the stale copy only lingers
+            warn!("Requeued queue item {} but failed to remove dead letter {}: {}", task.record_id, dead_letter_id, e);
+        }
+    }
+    info!(task_id = %task.id, user_id = %task.user_id, "Queue item {} requeued from {:?}", task.record_id, task.status);
     Ok(())
 }
 
-async fn store_metadata_in_user_db(task: &QueueTask) -> WorkerResult<()> {
-    // Placeholder for metadata storage
-    Ok(())
+/// Longest error summary put in an email
+const ERROR_SUMMARY_LIMIT: usize = 300;
+
+/// `message` fit to show a user: its first line, with URLs cut down to
+/// their host so no signed link or token in one is passed on, and no
+/// longer than [`ERROR_SUMMARY_LIMIT`] characters
+pub fn error_summary(message: &str) -> String {
+    let line = message.lines().next().unwrap_or_default();
+    let summary = line
+        .split(' ')
+        .map(|word| match url::Url::parse(word) {
+            Ok(url) if url.has_host() => format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default()),
+            _ => word.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    match summary.char_indices().nth(ERROR_SUMMARY_LIMIT) {
+        Some((end, _)) => format!("{}…", &summary[..end]),
+        None => summary,
+    }
 }
 
-async fn download_video(task: &QueueTask) -> WorkerResult<()> {
-    // Placeholder for video downloading
-    Ok(())
+/// What a user is told the task was doing when it failed
+fn stage_description(stage: Option<ProcessingStage>) -> &'static str {
+    match stage {
+        None => "Starting",
+        Some(ProcessingStage::FetchingMetadata) => "Fetching the meeting from Fathom",
+        Some(ProcessingStage::Downloading) => "Downloading the recording",
+        Some(ProcessingStage::Transcoding) => "Transcoding the recording",
+        Some(ProcessingStage::Uploading) => "Uploading to Loom",
+        Some(ProcessingStage::Streaming) => "Streaming the recording to Loom",
+    }
 }
 
-async fn upload_to_loom(task: &QueueTask) -> WorkerResult<()> {
-    // Placeholder for uploading to Loom
-    Ok(())
+/// The email telling a user that `task` failed with `error` in `stage`, and
+/// how far its last attempt got by `report`
+///
+/// Problems only the user can fix say what to do and link to Settings;
+/// others link to the queue to retry the meeting from.
+pub fn failure_email(
+    error: &WorkerError,
+    task: &QueueTask,
+    stage: Option<ProcessingStage>,
+    report: &TaskReport,
+    to_email: &str,
+    links: &EmailConfig,
+) -> TaskEmail {
+    let service_name = |service: &str| ServiceKind::parse(service).map_or(service.to_string(), |kind| kind.display_name().to_string());
+    let settings = ("Open Settings", links.settings_url.clone());
+    let retry = ("Retry the meeting", format!("{}?retry={}", links.queue_url, task.record_id));
+    let (subject, problem, advice, link) = match error {
+        // Say which key and what to do instead of a bare API error
+        WorkerError::KeyExpired { service, key_id, expired_at } => {
+            let service = service_name(service);
+            (
+                format!("Your {} API key has expired", service),
+                format!("Your {} API key \"{}\" expired on {}.", service, key_id, expired_at.format("%Y-%m-%d")),
+                "Add a new key in Settings, then retry the meeting from your queue.",
+                settings,
+            )
+        }
+        WorkerError::KeyMissing { service } => {
+            let service = service_name(service);
+            (
+                format!("Add a {} API key to move your meetings", service),
+                format!("no {} API key is stored.", service),
+                "Add one in Settings, then retry the meeting from your queue.",
+                settings,
+            )
+        }
+        WorkerError::FathomRefused { meeting_id, reason } => {
+            let advice = match reason {
+                DownloadReason::NotFound => "Remove the meeting from your queue.",
+                DownloadReason::Forbidden => {
+                    "Your Fathom key may have been revoked. Add a new key in Settings, \
+                     then retry the meeting from your queue."
+                }
+                DownloadReason::NoDownload => "Check that the recording can be downloaded in Fathom, then retry it.",
+                _ => "Retry the meeting from your queue later.",
+            };
+            let link = if matches!(reason, DownloadReason::Forbidden) { settings } else { retry };
+            (
+                "A meeting couldn't be fetched from Fathom".to_string(),
+                format!("{} (recording {}).", reason.describe(), meeting_id),
+                advice,
+                link,
+            )
+        }
+        error => (
+            "A meeting couldn't be moved from Fathom to Loom".to_string(),
+            error_summary(&error.to_string()),
+            "Retry the meeting from your queue once the problem has passed.",
+            retry,
+        ),
+    };
+    let paragraphs = [
+        "A meeting couldn't be moved from Fathom to Loom.".to_string(),
+        format!(
+            "Meeting: {}\nFailed while: {}\nWhat went wrong: {}\nStages: {}",
+            task.topic,
+            stage_description(stage),
+            problem,
+            report.summary()
+        ),
+        advice.to_string(),
+    ];
+    TaskEmail::new(to_email, subject, &paragraphs, (link.0, &link.1))
+}
+
+/// The email telling a user who asked for it that `task` is on Loom, with
+/// the stages of `report`
+pub fn success_email(task: &QueueTask, share_url: &str, report: &TaskReport, to_email: &str) -> TaskEmail {
+    let paragraphs = [
+        format!("\"{}\" was moved from Fathom to Loom.", task.topic),
+        format!("Stages: {}", report.summary()),
+    ];
+    TaskEmail::new(
+        to_email,
+        format!("\"{}\" is on Loom", task.topic),
+        &paragraphs,
+        ("Watch it on Loom", share_url),
+    )
+}
+
+async fn process_pipeline(
+    pipeline: &FathomToLoom,
+    task: &QueueTask,
+    workspace: &Workspace,
+    shutdown: &Shutdown,
+    measurements: &Measurements,
+) -> WorkerResult<Delivery> {
+    let limits = &pipeline.config.worker;
+    let keys = within("metadata", limits.metadata_timeout, fetch_keys(pipeline, task)).await?;
+    let (task, resumed) = resume(pipeline, task, workspace).await?;
+    let task = &task;
+    let mut checkpoint = task.checkpoint.clone();
+    let not_streaming = streaming::fallback(limits.streaming, &pipeline.config.transcode, task, &resumed, workspace);
+
+    let artifacts = match resumed.metadata {
+        Some(artifacts) => artifacts,
+        None => {
+            let artifacts = timed(measurements, "metadata", limits.metadata_timeout, fetch_meeting_data(pipeline, task, &keys)).await?;
+            checkpoint.metadata = Some(artifacts.clone());
+            save_checkpoint(pipeline, task, workspace, &checkpoint).await;
+            artifacts
+        }
+    };
+    shutdown.check()?;
+
+    match not_streaming {
+        None => match stream_to_loom(pipeline, task, &keys, &artifacts, measurements).await {
+            // Most likely an MP4 with its index at the end, which ffmpeg
+            // can only find in a file
+            Err(WorkerError::InvalidMedia { stderr_tail }) => {
+                warn!("ffmpeg couldn't read the recording as it arrived, so going one stage at a time: {}", stderr_tail);
+            }
+            result => return result.map(|share_url| Delivery { share_url: Some(share_url) }),
+        },
+        Some(reason) => debug!("Not streaming the recording: {}", reason),
+    }
+
+    let download = match resumed.download {
+        Some(download) => download,
+        None => {
+            let download = download_video(pipeline, task, workspace, &artifacts, measurements);
+            let download = timed(measurements, "download", limits.download_timeout, download).await?;
+            checkpoint.download = Some(FileCheckpoint::of(&download));
+            save_checkpoint(pipeline, task, workspace, &checkpoint).await;
+            download
+        }
+    };
+    shutdown.check()?;
+    let video = match resumed.transcode {
+        Some(video) => video,
+        None => {
+            let video = timed(measurements, "transcode", limits.transcode_timeout, transcode_video(pipeline, task, workspace, &download)).await?;
+            if video.path != download.path {
+                measurements.transcoded(artifacts.duration as f64);
+            }
+            checkpoint.transcode = Some(FileCheckpoint::of(&video));
+            save_checkpoint(pipeline, task, workspace, &checkpoint).await;
+            video
+        }
+    };
+    shutdown.check()?;
+    let upload = upload_to_loom(pipeline, task, &keys, &artifacts, &video, measurements);
+    let share_url = timed(measurements, "upload", limits.upload_timeout, upload).await?;
+
+    Ok(Delivery { share_url: Some(share_url) })
+}
+
+/// What earlier attempts at `task` left that this one can skip
+///
+/// Should a stage's file not hold up, the task starts over: its workspace is
+/// emptied, and its checkpoint and upload session forgotten, here and on the
+/// queue item.
+async fn resume(pipeline: &FathomToLoom, task: &QueueTask, workspace: &Workspace) -> WorkerResult<(QueueTask, Resumed)> {
+    match task.checkpoint.resume(workspace).await {
+        Ok(resumed) => {
+            if let Some(stage) = task.checkpoint.stages().last() {
+                info!("Resuming after the {} stage of an earlier attempt", stage);
+            }
+            Ok((task.clone(), resumed))
+        }
+        Err(why) => {
+            warn!("Starting the task over, as its checkpoint doesn't hold: {}", why);
+            workspace.clear()?;
+            let update = json!({ "checkpoint": null, "upload_session_id": "", "upload_offset": 0 });
+            if let Err(e) = pipeline.pb.update_record(QUEUE_COLLECTION, &task.record_id, &update).await {
+                warn!("Failed to clear the checkpoint of task {}: {}", task.id, e);
+            }
+            let task = QueueTask {
+                checkpoint: Checkpoint::default(),
+                upload_session_id: None,
+                upload_offset: 0,
+                ..task.clone()
+            };
+            Ok((task, Resumed::default()))
+        }
+    }
+}
+
+/// Keep `checkpoint` on the task's queue item, and its workspace for the
+/// next attempt should this one be cut off
+async fn save_checkpoint(pipeline: &FathomToLoom, task: &QueueTask, workspace: &Workspace, checkpoint: &Checkpoint) {
+    workspace.keep();
+    let record = json!({ "checkpoint": checkpoint });
+    if let Err(e) = pipeline.pb.update_record(QUEUE_COLLECTION, &task.record_id, &record).await {
+        // Only a resume is lost; the task itself carries on
+        warn!("Failed to save the checkpoint of task {}: {}", task.id, e);
+    }
+}
+
+/// The user's Fathom and Loom keys, the ones the task names or the defaults
+async fn fetch_keys(pipeline: &FathomToLoom, task: &QueueTask) -> WorkerResult<TaskKeys> {
+    let services = ServiceKind::ALL.map(|service| (service, task.key_id_for(service)));
+    TaskKeys::fetch(&pipeline.client, &pipeline.config.backend, &task.user_id, &services).await
+}
+
+/// What Fathom knows of the task's recording, asked with the user's key
+async fn fetch_meeting_data(pipeline: &FathomToLoom, task: &QueueTask, keys: &TaskKeys) -> WorkerResult<MeetingArtifacts> {
+    let progress = pipeline.stage(task, ProcessingStage::FetchingMetadata);
+    progress.start();
+    let service = ServiceKind::Fathom;
+    let backend = &pipeline.config.backend;
+    let key_id = keys.key_id(service)?;
+    let key = keys.value(service)?;
+
+    let fathom = FathomClient::new(pipeline.client.clone(), &pipeline.config.fathom.api_url, &key)
+        .with_limiter(&pipeline.fathom_limiter);
+    let artifacts = fathom.artifacts(&task.meeting_id).await?;
+    if let Err(e) = keys::touch_key(&pipeline.client, backend, &task.user_id, service.as_str(), Some(key_id)).await {
+        warn!("Failed to record the use of Fathom key {}: {}", key_id, e);
+    }
+
+    info!("Fetched \"{}\", {}s long", artifacts.title, artifacts.duration);
+    progress.finish();
+    Ok(artifacts)
+}
+
+/// Download the recording into the task's workspace, resuming what an
+/// earlier attempt at the task left there
+async fn download_video(
+    pipeline: &FathomToLoom,
+    task: &QueueTask,
+    workspace: &Workspace,
+    artifacts: &MeetingArtifacts,
+    measurements: &Measurements,
+) -> WorkerResult<Download> {
+    let progress = pipeline.stage(task, ProcessingStage::Downloading);
+    progress.start();
+    let settings = &pipeline.config.worker;
+    let dest = workspace.download_path();
+    let downloader = Downloader::new(pipeline.client.clone(), settings.max_download_bytes, settings.download_attempts);
+    let resumed_from = tokio::fs::metadata(&dest).await.map(|metadata| metadata.len()).unwrap_or(0);
+    let download = downloader
+        .fetch(&artifacts.download_url, &dest, artifacts.sha256.as_deref(), &|percent| {
+            progress.report(percent)
+        })
+        .await?;
+    let downloaded = download.size.saturating_sub(resumed_from);
+    metrics::add(&metrics::BYTES_DOWNLOADED, &[], downloaded as f64);
+    measurements.downloaded(downloaded);
+    info!("Downloaded {} bytes of \"{}\"", download.size, artifacts.title);
+    Ok(download)
+}
+
+/// Re-encode the download to the configured profile, unless transcoding is
+/// off or, in passthrough, the recording already fits it
+async fn transcode_video(
+    pipeline: &FathomToLoom,
+    task: &QueueTask,
+    workspace: &Workspace,
+    download: &Download,
+) -> WorkerResult<Download> {
+    if pipeline.config.transcode.mode == TranscodeMode::Off {
+        return Ok(download.clone());
+    }
+    let progress = pipeline.stage(task, ProcessingStage::Transcoding);
+    progress.start();
+    let dest = workspace.transcode_path(pipeline.config.transcode.profile.container.extension());
+    let video = Transcoder::new(&pipeline.config.transcode)
+        .transcode(download, &dest, &|percent| progress.report(percent))
+        .await?;
+    if video.path != download.path {
+        info!("Transcoded the recording to {} bytes", video.size);
+    }
+    progress.finish();
+    Ok(video)
+}
+
+/// Download, transcode and upload the recording at once, recording the
+/// share URL on the queue item and returning it
+async fn stream_to_loom(
+    pipeline: &FathomToLoom,
+    task: &QueueTask,
+    keys: &TaskKeys,
+    artifacts: &MeetingArtifacts,
+    measurements: &Measurements,
+) -> WorkerResult<String> {
+    let settings = &pipeline.config.worker;
+    let service = ServiceKind::Loom;
+    let backend = &pipeline.config.backend;
+    let key_id = keys.key_id(service)?;
+    let key = keys.value(service)?;
+    let transcoding = pipeline.config.transcode.mode != TranscodeMode::Off;
+    let progress = Combined::new(pipeline.stage(task, ProcessingStage::Streaming), transcoding);
+    progress.start();
+
+    let downloader = Downloader::new(pipeline.client.clone(), settings.max_download_bytes, settings.download_attempts);
+    let transcoder = Transcoder::new(&pipeline.config.transcode);
+    let client = LoomClient::new(pipeline.client.clone(), &pipeline.config.loom, &key);
+    let (downloaded, to_transcode) = mpsc::channel(CHANNEL_DEPTH);
+    let (to_upload, transcode) = match transcoding {
+        true => {
+            let (transcoded, to_upload) = mpsc::channel(CHANNEL_DEPTH);
+            (to_upload, Some((to_transcode, transcoded)))
+        }
+        false => (to_transcode, None),
+    };
+
+    let download_progress = |percent| progress.report(Part::Download, percent);
+    let transcode_progress = |percent| progress.report(Part::Transcode, percent);
+    let upload_progress = |acknowledged, received| progress.uploaded(acknowledged, received);
+    let download = downloader.stream(&artifacts.download_url, artifacts.sha256.as_deref(), downloaded, &download_progress);
+    let transcode = async {
+        let Some((input, output)) = transcode else {
+            return Ok(());
+        };
+        let transcode = transcoder.transcode_stream(input, output, Some(artifacts.duration as f64), &transcode_progress);
+        timed(measurements, "transcode", settings.transcode_timeout, transcode).await
+    };
+    let upload = client.upload_stream(&artifacts.title, to_upload, &upload_progress);
+    // All three run on this task, so one failing drops the others before
+    // they can take its channel closing for the end of the recording
+    let (downloaded, (), (uploaded, sent)) = tokio::try_join!(
+        timed(measurements, "download", settings.download_timeout, download),
+        transcode,
+        timed(measurements, "upload", settings.upload_timeout, upload),
+    )?;
+    if let Err(e) = keys::touch_key(&pipeline.client, backend, &task.user_id, service.as_str(), Some(key_id)).await {
+        warn!("Failed to record the use of Loom key {}: {}", key_id, e);
+    }
+
+    metrics::add(&metrics::BYTES_DOWNLOADED, &[], downloaded as f64);
+    measurements.downloaded(downloaded);
+    if transcoding {
+        measurements.transcoded(artifacts.duration as f64);
+    }
+    metrics::add(&metrics::BYTES_UPLOADED, &[], sent as f64);
+    measurements.uploaded(sent);
+    progress.finish();
+    loom::record_video(&pipeline.client, backend, &task.record_id, &uploaded).await?;
+    info!("Streamed {} bytes of \"{}\" to Loom as {}", downloaded, artifacts.title, uploaded.share_url);
+    Ok(uploaded.share_url)
+}
+
+/// Keeps a task's upload session and offset on its queue item, and
+/// reports them as upload progress
+struct TaskUpload<'a> {
+    pipeline: &'a FathomToLoom,
+    task: &'a QueueTask,
+    progress: StageProgress<'a>,
+}
+
+impl UploadJournal for TaskUpload<'_> {
+    fn acknowledged<'a>(&'a self, upload_id: &'a str, offset: u64, size: u64) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let record = json!({ "upload_session_id": upload_id, "upload_offset": offset });
+            if let Err(e) = self.pipeline.pb.update_record(QUEUE_COLLECTION, &self.task.record_id, &record).await {
+                // Only a resume is lost; the upload itself carries on
+                warn!("Failed to save upload progress of task {}: {}", self.task.id, e);
+            }
+            let percent = (offset * 100).checked_div(size).map_or(100, |percent| percent as u8);
+            self.progress.report(percent);
+        })
+    }
+}
+
+/// Upload `video` to Loom with the user's key, resuming the session of an
+/// earlier attempt, and record the share URL on the queue item, returning it
+async fn upload_to_loom(
+    pipeline: &FathomToLoom,
+    task: &QueueTask,
+    keys: &TaskKeys,
+    artifacts: &MeetingArtifacts,
+    video: &Download,
+    measurements: &Measurements,
+) -> WorkerResult<String> {
+    download::verify(video).await?;
+    let service = ServiceKind::Loom;
+    let backend = &pipeline.config.backend;
+    let key_id = keys.key_id(service)?;
+    let key = keys.value(service)?;
+
+    let client = LoomClient::new(pipeline.client.clone(), &pipeline.config.loom, &key);
+    let journal = TaskUpload {
+        pipeline,
+        task,
+        progress: pipeline.stage(task, ProcessingStage::Uploading),
+    };
+    let uploaded = client
+        .upload(&video.path, &artifacts.title, task.upload_session_id.as_deref(), &journal)
+        .await?;
+    if let Err(e) = keys::touch_key(&pipeline.client, backend, &task.user_id, service.as_str(), Some(key_id)).await {
+        warn!("Failed to record the use of Loom key {}: {}", key_id, e);
+    }
+
+    let resumed_from = if task.upload_session_id.is_some() { task.upload_offset } else { 0 };
+    let sent = video.size.saturating_sub(resumed_from);
+    metrics::add(&metrics::BYTES_UPLOADED, &[], sent as f64);
+    measurements.uploaded(sent);
+    loom::record_video(&pipeline.client, backend, &task.record_id, &uploaded).await?;
+    info!("Uploaded \"{}\" to Loom as {}", artifacts.title, uploaded.share_url);
+    Ok(uploaded.share_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::clock::SystemClock;
+    use crate::config::TranscodeConfig;
+    use crate::transcode::{Container, TranscodeProfile};
+    use crate::config::{BackendConfig, WorkerSettings};
+    use crate::test_support::{
+        database, email_config, mock_backend, mock_fathom, mock_fathom_with, roomy_disk, worker_config, CapturedLogs,
+        FakeDisk, MockLoom, MockMedia, MockPb, FATHOM_KEY, LOOM_KEY,
+    };
+    use crate::results::LogResults;
+    use crate::workspace::task_dir;
+    use common::broadcast::BroadcastServiceFactory;
+    use std::collections::{HashSet, VecDeque};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// A mock PocketBase with the queue's unique indexes
+    async fn queue_pb() -> MockPb {
+        let pb = MockPb::start().await;
+        pb.unique(CLAIMS_COLLECTION, &["item", "version"]);
+        pb.insert(USERS_COLLECTION, json!({ "id": "alice", "email": "alice@example.com" }));
+        pb
+    }
+
+    /// Queue a pending item created at second `second` of the day
+    fn queue_item(pb: &MockPb, topic: &str, second: u32) -> String {
+        pb.insert(
+            QUEUE_COLLECTION,
+            json!({
+                "task_id": Uuid::new_v4().to_string(),
+                "user_id": "alice",
+                "meeting_id": format!("m-{}", topic),
+                "topic": topic,
+                "status": "Pending",
+                "retry_count": 0,
+                "max_retries": 3,
+                "error_message": "",
+                "fathom_key_id": "",
+                "loom_key_id": "",
+                "claimed_by": "",
+                "claimed_at": "",
+                "next_attempt_at": "",
+                "version": 0,
+                "created": format!("2026-03-01 09:00:{:02}.000Z", second),
+                "updated": format!("2026-03-01 09:00:{:02}.000Z", second),
+            }),
+        )
+    }
+
+    /// `item` as worker-a's claim leaves it, for tests running a task
+    /// without the pool
+    fn claimed_task(mock: &MockPb, item: &str) -> QueueTask {
+        mock.patch(QUEUE_COLLECTION, item, json!({ "status": "InProgress", "claimed_by": "worker-a", "version": 1 }));
+        QueueTask::from_record(&mock.record(QUEUE_COLLECTION, item).unwrap()).unwrap()
+    }
+
+    /// The report of an attempt that spent 3s downloading 4 MB
+    fn download_report(outcome: ResultStatus) -> TaskReport {
+        let measurements = Measurements::default();
+        let started_at = Utc::now();
+        measurements.stage("download", started_at, Duration::from_secs(3));
+        measurements.downloaded(4_000_000);
+        TaskReport::new(measurements.snapshot(), outcome, 0, started_at, started_at + chrono::Duration::seconds(3))
+    }
+
+    /// The failure email for `error` met while downloading "Weekly sync"
+    fn failure(error: &WorkerError) -> TaskEmail {
+        let task = QueueTask {
+            record_id: "r1".to_string(),
+            topic: "Weekly sync".to_string(),
+            ..Default::default()
+        };
+        let report = download_report(ResultStatus::Failed);
+        failure_email(error, &task, Some(ProcessingStage::Downloading), &report, "alice@example.com", &email_config("http://127.0.0.1:9"))
+    }
+
+    #[test]
+    fn test_expired_keys_get_their_own_failure_email() {
+        let expired_at = DateTime::parse_from_rfc3339("2026-02-26T09:00:00Z").unwrap().with_timezone(&Utc);
+        let error = WorkerError::KeyExpired {
+            service: "fathom".to_string(),
+            key_id: "default".to_string(),
+            expired_at,
+        };
+        let email = failure(&error);
+        assert_eq!(email.to_email, "alice@example.com");
+        assert_eq!(email.subject, "Your Fathom API key has expired");
+        assert!(email.body_text.contains("\"default\" expired on 2026-02-26"));
+        assert!(email.body_text.contains("Add a new key in Settings"));
+
+        let email = failure(&WorkerError::Loom("upload rejected".to_string()));
+        assert_eq!(email.subject, "A meeting couldn't be moved from Fathom to Loom");
+        assert!(email.body_text.contains("upload rejected"));
+    }
+
+    #[test]
+    fn test_failure_emails_say_what_failed_and_link_to_a_retry() {
+        let error = WorkerError::Loom("PUT https://uploads.loom.com/v1/abc?signature=s3cr3t returned 500\nbody".to_string());
+        let email = failure(&error);
+        assert_eq!(
+            email.body_text,
+            "A meeting couldn't be moved from Fathom to Loom.\n\n\
+             Meeting: Weekly sync\n\
+             Failed while: Downloading the recording\n\
+             What went wrong: Loom API error: PUT https://uploads.loom.com returned 500\n\
+             Stages: download 3.0s (4.0 MB at 1.3 MB/s); 3.0s in all\n\n\
+             Retry the meeting from your queue once the problem has passed.\n\n\
+             Retry the meeting: http://localhost:8080/dashboard?retry=r1"
+        );
+        assert!(email.body_html.contains("<a href=\"http://localhost:8080/dashboard?retry=r1\">Retry the meeting</a>"));
+
+        let long = error_summary(&"x".repeat(ERROR_SUMMARY_LIMIT + 10));
+        assert_eq!(long.chars().count(), ERROR_SUMMARY_LIMIT + 1);
+        assert!(long.ends_with('…'));
+    }
+
+    #[test]
+    fn test_missing_keys_direct_users_to_settings() {
+        let error = WorkerError::KeyMissing { service: "loom".to_string() };
+        assert_eq!(error.to_string(), "No loom API key is stored; add one in Settings");
+        let email = failure(&error);
+        assert_eq!(email.subject, "Add a Loom API key to move your meetings");
+        assert!(email.body_text.contains("no Loom API key is stored"));
+        assert!(email.body_text.contains("Add one in Settings"));
+        assert!(email.body_text.ends_with("Open Settings: http://localhost:8080/settings"));
+    }
+
+    #[test]
+    fn test_refused_recordings_say_what_to_do() {
+        let refused = |reason| WorkerError::FathomRefused { meeting_id: "7".to_string(), reason };
+
+        let email = failure(&refused(DownloadReason::NotFound));
+        assert_eq!(email.subject, "A meeting couldn't be fetched from Fathom");
+        assert!(email.body_text.contains("no longer exists in Fathom (recording 7)"));
+        assert!(email.body_text.contains("Remove the meeting from your queue"));
+
+        let email = failure(&refused(DownloadReason::Forbidden));
+        assert!(email.body_text.contains("may have been revoked"));
+    }
+
+    #[tokio::test]
+    async fn test_the_first_stage_fetches_the_recording_with_the_users_key() {
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let pb = MockPb::start().await;
+        let broadcast_service = BroadcastServiceFactory::create_shared(16);
+        let mut progress = broadcast_service.subscribe_progress();
+        let pipeline = FathomToLoom {
+            config: worker_config(database(&pb), backend, &mock_fathom().await, "http://127.0.0.1:9"),
+            client: reqwest::Client::new(),
+            pb: pb.client(),
+            broadcast_service,
+            fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+        };
+        let task = |meeting_id: &str| QueueTask {
+            user_id: "alice".to_string(),
+            meeting_id: meeting_id.to_string(),
+            ..Default::default()
+        };
+
+        let keys = fetch_keys(&pipeline, &task("42")).await.unwrap();
+        let artifacts = fetch_meeting_data(&pipeline, &task("42"), &keys).await.unwrap();
+        assert_eq!(artifacts.title, "Weekly sync");
+        assert_eq!(artifacts.download_url, "https://media.example.com/42.mp4?signature=abc");
+        let percents: Vec<(ProcessingStage, u8)> = std::iter::from_fn(|| progress.try_recv().ok())
+            .map(|update| (update.stage, update.percent))
+            .collect();
+        assert_eq!(
+            percents,
+            [(ProcessingStage::FetchingMetadata, 0), (ProcessingStage::FetchingMetadata, 100)]
+        );
+
+        let error = fetch_meeting_data(&pipeline, &task("7"), &keys).await.unwrap_err();
+        assert!(matches!(error, WorkerError::FathomRefused { reason: DownloadReason::NotFound, .. }));
+
+        // Bob has no Fathom key to fetch with
+        let error = fetch_keys(&pipeline, &QueueTask { user_id: "bob".to_string(), ..task("42") })
+            .await
+            .unwrap_err();
+        assert!(matches!(&error, WorkerError::KeyMissing { service } if service == "fathom"));
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_an_upload_is_resumed_from_the_queue_item() {
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "weekly", 1);
+        let loom = MockLoom::start().await;
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let mut progress = broadcast_service.subscribe_progress();
+        let pipeline = FathomToLoom {
+            config: worker_config(database(&mock), backend, "http://127.0.0.1:9", &loom.url),
+            client: reqwest::Client::new(),
+            pb: mock.client(),
+            broadcast_service,
+            fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+        };
+        let artifacts = MeetingArtifacts {
+            title: "Weekly sync".to_string(),
+            duration: 60,
+            download_url: "https://media.example.com/42.mp4".to_string(),
+            size_bytes: Some(2500),
+            sha256: None,
+            transcript_available: false,
+        };
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [7; 2500]).unwrap();
+        let video = Download {
+            path: file.path().to_path_buf(),
+            size: 2500,
+            sha256: "00bb1a43ab8fcaa7415b54da8740f2298f78b2e9ea6a275a67e68571eec32bbc".to_string(),
+        };
+        let task = || QueueTask::from_record(&mock.record(QUEUE_COLLECTION, &item).unwrap()).unwrap();
+        let keys = fetch_keys(&pipeline, &task()).await.unwrap();
+
+        // The first attempt dies after Loom acknowledged one chunk
+        loom.fail_chunks_after(1, usize::MAX);
+        let error = upload_to_loom(&pipeline, &task(), &keys, &artifacts, &video, &Measurements::default()).await.unwrap_err();
+        assert!(error.is_retryable());
+        let interrupted = task();
+        assert_eq!(interrupted.upload_session_id.as_deref(), Some("upload-1"));
+        assert_eq!(interrupted.upload_offset, 1000);
+
+        loom.fail_chunks(0);
+        let measurements = Measurements::default();
+        upload_to_loom(&pipeline, &interrupted, &keys, &artifacts, &video, &measurements).await.unwrap();
+        assert_eq!(measurements.snapshot().uploaded, Some(1500), "only what the resume sent counts");
+        assert_eq!(loom.sessions(), 1);
+        assert_eq!(loom.uploaded("upload-1"), [7; 2500]);
+        assert_eq!(task().upload_offset, 2500);
+
+        let percents: Vec<u8> = std::iter::from_fn(|| progress.try_recv().ok())
+            .filter(|update| update.stage == ProcessingStage::Uploading)
+            .map(|update| update.percent)
+            .collect();
+        assert_eq!(percents, [0, 40, 100]);
+    }
+
+    #[test]
+    fn test_tasks_use_the_default_key_unless_one_is_chosen() {
+        let task: QueueTask = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "user_id": "alice",
+            "meeting_id": "m-1",
+            "topic": "Standup",
+            "status": "Pending",
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "retry_count": 0,
+            "max_retries": 3,
+            "error_message": null,
+            "loom_key_id": "team"
+        }))
+        .unwrap();
+        assert_eq!(task.key_id_for(ServiceKind::Fathom), None);
+        assert_eq!(task.key_id_for(ServiceKind::Loom), Some("team"));
+    }
+
+    #[tokio::test]
+    async fn test_the_oldest_pending_item_is_claimed() {
+        let mock = queue_pb().await;
+        let newer = queue_item(&mock, "retro", 30);
+        let oldest = queue_item(&mock, "standup", 10);
+        let pb = mock.client();
+
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+        assert_eq!(task.record_id, oldest);
+        assert_eq!(task.topic, "standup");
+        assert_eq!(task.status, TaskStatus::InProgress);
+        assert_eq!(task.claimed_by.as_deref(), Some("worker-a"));
+        assert!(task.claimed_at.is_some());
+        assert_eq!(task.version, 1);
+        assert_eq!(task.fathom_key_id, None);
+
+        let stored = mock.record(QUEUE_COLLECTION, &oldest).unwrap();
+        assert_eq!(stored["status"], "InProgress");
+        assert_eq!(stored["claimed_by"], "worker-a");
+        assert_eq!(mock.record(QUEUE_COLLECTION, &newer).unwrap()["status"], "Pending");
+        let claims = mock.records(CLAIMS_COLLECTION);
+        assert_eq!(claims.len(), 1);
+        assert_eq!(claims[0]["item"], oldest.as_str());
+        assert_eq!(claims[0]["version"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_an_item_claimed_first_elsewhere_is_skipped() {
+        let mock = queue_pb().await;
+        let contested = queue_item(&mock, "standup", 10);
+        let next = queue_item(&mock, "retro", 20);
+        // Another worker won the claim but hasn't marked the item yet
+        mock.insert(CLAIMS_COLLECTION, json!({ "item": contested, "version": 0, "worker_id": "worker-b" }));
+        let pb = mock.client();
+
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+        assert_eq!(task.record_id, next);
+        let stored = mock.record(QUEUE_COLLECTION, &contested).unwrap();
+        assert_eq!(stored["claimed_by"], "", "the lost item is left to its winner");
+
+        // Nothing else is claimable, contested or not
+        assert!(claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().is_none());
+    }
+
+    /// Queue an item of `user_id` at `priority`, created at second `second`
+    async fn prioritised_item(mock: &MockPb, user_id: &str, topic: &str, second: u32, priority: i64) -> String {
+        let id = queue_item(mock, topic, second);
+        mock.client()
+            .update_record(QUEUE_COLLECTION, &id, &json!({ "user_id": user_id, "priority": priority }))
+            .await
+            .unwrap();
+        id
+    }
+
+    /// Claim everything due, one second after another, by topic
+    async fn claim_all(pb: &PocketBase, fairness: &Fairness) -> Vec<String> {
+        let start = Utc::now();
+        let mut topics = Vec::new();
+        for second in 0.. {
+            let now = start + chrono::Duration::seconds(second);
+            match claim_next_task(pb, "worker-a", now, fairness, &[JobType::ProcessMeeting]).await.unwrap() {
+                Some(task) => topics.push(task.topic),
+                None => return topics,
+            }
+        }
+        unreachable!()
+    }
+
+    #[tokio::test]
+    async fn test_higher_priorities_are_claimed_first() {
+        let mock = queue_pb().await;
+        prioritised_item(&mock, "alice", "backlog", 1, -1).await;
+        prioritised_item(&mock, "alice", "standup", 10, 0).await;
+        // Never served, but waiting in a lower band all the same
+        prioritised_item(&mock, "bob", "retro", 11, 0).await;
+        prioritised_item(&mock, "alice", "board", 30, 5).await;
+        prioritised_item(&mock, "alice", "launch", 20, 5).await;
+        let dir = tempfile::tempdir().unwrap();
+        let fairness = Fairness::load(&dir.path().join("worker_state.json"), 10);
+
+        let pb = mock.client();
+        assert_eq!(claim_all(&pb, &fairness).await, ["launch", "board", "retro", "standup", "backlog"]);
+    }
+
+    #[tokio::test]
+    async fn test_users_with_many_items_take_turns() {
+        let mock = queue_pb().await;
+        for second in 0..4 {
+            prioritised_item(&mock, "alice", &format!("alice-{}", second), second, 0).await;
+        }
+        for second in 10..13 {
+            prioritised_item(&mock, "bob", &format!("bob-{}", second), second, 0).await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("worker_state.json");
+        let pb = mock.client();
+
+        assert_eq!(
+            claim_all(&pb, &Fairness::load(&state, 10)).await,
+            ["alice-0", "bob-10", "alice-1", "bob-11", "alice-2", "bob-12", "alice-3"]
+        );
+        // Alice was served last, so a restarted worker goes to Bob first
+        let restarted = Fairness::load(&state, 10);
+        assert!(restarted.last_served("alice") > restarted.last_served("bob"));
+        prioritised_item(&mock, "alice", "alice-20", 20, 0).await;
+        prioritised_item(&mock, "bob", "bob-21", 21, 0).await;
+        assert_eq!(claim_all(&pb, &restarted).await, ["bob-21", "alice-20"]);
+    }
+
+    #[tokio::test]
+    async fn test_a_single_users_queue_is_claimed_oldest_first() {
+        let mock = queue_pb().await;
+        for (topic, second) in [("retro", 30), ("standup", 10), ("planning", 20), ("demo", 40)] {
+            prioritised_item(&mock, "alice", topic, second, 0).await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let fairness = Fairness::load(&dir.path().join("worker_state.json"), 10);
+
+        let pb = mock.client();
+        assert_eq!(claim_all(&pb, &fairness).await, ["standup", "planning", "retro", "demo"]);
+    }
+
+    #[tokio::test]
+    async fn test_each_status_change_is_kept_on_the_item() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let pb = mock.client();
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+
+        let update = json!({ "retry_count": 1, "claimed_by": "", "claimed_at": "" });
+        update_status(&pb, &task, TaskStatus::Pending, Some("Loom API error: 503"), update).await.unwrap();
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!((&record["status"], &record["error_message"]), (&json!("Pending"), &json!("Loom API error: 503")));
+        assert_eq!(record["retry_count"], 1);
+        assert!(record.get("completed_at").is_none(), "the task hasn't ended");
+
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+        update_status(&pb, &task, TaskStatus::Completed, None, json!({})).await.unwrap();
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!((&record["status"], &record["error_message"]), (&json!("Completed"), &json!("")));
+        assert!(parse_time(record["completed_at"].as_str().unwrap()).is_some());
+        assert_eq!(record["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_a_move_the_status_cant_make_is_refused() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let pb = mock.client();
+        let task = claimed_task(&mock, &item);
+        update_status(&pb, &task, TaskStatus::Completed, None, json!({})).await.unwrap();
+
+        let error = update_status(&pb, &task, TaskStatus::Pending, Some("again"), json!({ "retry_count": 1 }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), format!("Internal error: Queue item {} can't go from Completed to Pending", item));
+        assert!(!error.is_retryable());
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!((&record["status"], &record["retry_count"]), (&json!("Completed"), &json!(0)));
+
+        assert!(TaskStatus::Pending.can_become(&TaskStatus::Cancelled));
+        assert!(!TaskStatus::Pending.can_become(&TaskStatus::Completed));
+        assert!(!TaskStatus::Failed.can_become(&TaskStatus::InProgress));
+    }
+
+    #[tokio::test]
+    async fn test_an_item_taken_over_since_the_claim_is_left_alone() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let pb = mock.client();
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+        // Released at shutdown and claimed by another worker meanwhile
+        mock.patch(QUEUE_COLLECTION, &item, json!({ "claimed_by": "worker-b", "version": 2 }));
+
+        let error = update_status(&pb, &task, TaskStatus::Failed, Some("upload rejected"), json!({})).await.unwrap_err();
+        assert!(matches!(error, WorkerError::Queue(_)), "{}", error);
+        assert!(error.is_retryable());
+        assert!(error.to_string().contains("at version 2 held by worker-b, not version 1 held by worker-a"), "{}", error);
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!((&record["status"], &record["error_message"]), (&json!("InProgress"), &json!("")));
+
+        let gone = QueueTask { record_id: "r-missing".to_string(), ..task };
+        let error = update_status(&pb, &gone, TaskStatus::Failed, None, json!({})).await.unwrap_err();
+        assert_eq!(error.to_string(), "Queue error: Queue item r-missing is gone");
+    }
+
+    #[tokio::test]
+    async fn test_an_empty_queue_claims_nothing() {
+        let mock = queue_pb().await;
+        let pb = mock.client();
+        assert!(claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().is_none());
+
+        // Items past pending aren't claimable either
+        let done = queue_item(&mock, "standup", 10);
+        let pb_done = mock.client();
+        pb_done.update_record(QUEUE_COLLECTION, &done, &json!({ "status": "Completed" })).await.unwrap();
+        assert!(claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().is_none());
+        assert!(mock.records(CLAIMS_COLLECTION).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_items_waiting_out_a_retry_are_not_claimed_early() {
+        let mock = queue_pb().await;
+        let waiting = queue_item(&mock, "standup", 10);
+        let due = queue_item(&mock, "retro", 20);
+        let pb = mock.client();
+        let retry_at = "2026-03-01 10:00:00.000Z";
+        pb.update_record(QUEUE_COLLECTION, &waiting, &json!({ "retry_count": 1, "next_attempt_at": retry_at }))
+            .await
+            .unwrap();
+
+        let before = parse_time("2026-03-01 09:59:59.999Z").unwrap();
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", before).await.unwrap().unwrap();
+        assert_eq!(task.record_id, due, "the older item isn't due yet");
+        assert!(claim_oldest_unclaimed_task(&pb, "worker-a", before).await.unwrap().is_none());
+
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", parse_time(retry_at).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(task.record_id, waiting);
+        assert_eq!(task.retry_count, 1);
+        assert_eq!(task.next_attempt_at, parse_time(retry_at));
+    }
+
+    #[tokio::test]
+    async fn test_two_workers_never_claim_the_same_item() {
+        let mock = queue_pb().await;
+        let queued: HashSet<String> = (0..30).map(|i| queue_item(&mock, &format!("meeting-{}", i), i)).collect();
+
+        let workers = ["worker-a", "worker-b"].map(|worker_id| {
+            let pb = mock.client();
+            tokio::spawn(async move {
+                let mut claimed = Vec::new();
+                while let Some(task) = claim_oldest_unclaimed_task(&pb, worker_id, Utc::now()).await.unwrap() {
+                    assert_eq!(task.claimed_by.as_deref(), Some(worker_id));
+                    claimed.push(task.record_id);
+                }
+                claimed
+            })
+        });
+        let mut claimed = Vec::new();
+        for worker in workers {
+            claimed.extend(worker.await.unwrap());
+        }
+
+        let unique: HashSet<String> = claimed.iter().cloned().collect();
+        assert_eq!(unique.len(), claimed.len(), "an item was claimed twice");
+        assert_eq!(unique, queued);
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), queued.len());
+        for record in mock.records(QUEUE_COLLECTION) {
+            assert_eq!(record["status"], "InProgress");
+        }
+    }
+
+    /// Long enough for every test task to finish in
+    const GRACE: Duration = Duration::from_secs(60);
+
+    /// Requested once `until` resolves
+    fn shutdown_after(until: impl Future<Output = ()> + Send + 'static) -> Shutdown {
+        let (stop, shutdown) = Shutdown::channel();
+        tokio::spawn(async move {
+            until.await;
+            let _ = stop.send(true);
+        });
+        shutdown
+    }
+
+    /// Counts runs in flight, panicking for `boom` and failing for `fails`;
+    /// with a gate, each run waits for a permit from it before finishing its
+    /// first stage, and with `stages` checks for shutdown before a second
+    #[derive(Default)]
+    struct Instrumented {
+        running: AtomicUsize,
+        peak: AtomicUsize,
+        finished: AtomicUsize,
+        gate: Option<Arc<Semaphore>>,
+        stages: bool,
+    }
+
+    impl Pipeline for Instrumented {
+        async fn run(&self, task: &QueueTask, shutdown: &Shutdown, _measurements: &Measurements) -> WorkerResult<Delivery> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            match &self.gate {
+                Some(gate) => gate.acquire().await.unwrap().forget(),
+                None => sleep(Duration::from_millis(30)).await,
+            }
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            if self.stages {
+                shutdown.check()?;
+            }
+            match task.topic.as_str() {
+                "boom" => panic!("boom"),
+                "fails" => Err(WorkerError::Loom("upload rejected".to_string())),
+                _ => Ok(Delivery::default()),
+            }
+        }
+    }
+
+    fn context<P: Pipeline>(mock: &MockPb, pipeline: P) -> Arc<TaskContext<P>> {
+        Arc::new(TaskContext {
+            pb: mock.client(),
+            worker_id: "worker-a".to_string(),
+            poll_interval: Duration::from_millis(20),
+            disk: roomy_disk(),
+            fairness: Fairness::fifo(),
+            job_types: vec![(JobType::ProcessMeeting, 16)],
+            pipeline,
+            broadcast_service: BroadcastServiceFactory::create_shared(16),
+            email: email_config("http://127.0.0.1:9"),
+            retry: RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(4),
+            },
+            clock: Arc::new(SystemClock),
+            mailer: Arc::new(LogMailer),
+            results: Arc::new(LogResults),
+        })
+    }
+
+    fn statuses(mock: &MockPb) -> Vec<(String, String)> {
+        let mut statuses: Vec<(String, String)> = mock
+            .records(QUEUE_COLLECTION)
+            .iter()
+            .map(|record| (record["topic"].as_str().unwrap().to_string(), record["status"].as_str().unwrap().to_string()))
+            .collect();
+        statuses.sort();
+        statuses
+    }
+
+    /// Resolves once no item is pending or in progress
+    async fn settled(mock: MockPb) {
+        while statuses(&mock).iter().any(|(_, status)| status == "Pending" || status == "InProgress") {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_more_than_concurrency_tasks_run_at_once() {
+        let mock = queue_pb().await;
+        for i in 0..12 {
+            queue_item(&mock, &format!("meeting-{:02}", i), i);
+        }
+        let context = context(&mock, Instrumented::default());
+
+        tokio::time::timeout(Duration::from_secs(10), run_pool(context.clone(), 3, shutdown_after(settled(mock.clone())), GRACE))
+            .await
+            .expect("the pool drains the queue");
+
+        assert_eq!(context.pipeline.finished.load(Ordering::SeqCst), 12);
+        assert_eq!(context.pipeline.peak.load(Ordering::SeqCst), 3);
+        assert!(statuses(&mock).iter().all(|(_, status)| status == "Completed"));
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 12);
+    }
+
+    /// A pool of one running against `mock` with a `poll_interval` of
+    /// `poll_interval`, asking `disk` before each claim, so it counts them
+    fn polling_pool(mock: &MockPb, poll_interval: Duration, disk: SpaceGate) -> (tokio::sync::watch::Sender<bool>, tokio::task::JoinHandle<()>) {
+        let mut context = Arc::into_inner(context(mock, Instrumented::default())).unwrap();
+        context.poll_interval = poll_interval;
+        context.disk = disk;
+        let (stop, shutdown) = Shutdown::channel();
+        (stop, tokio::spawn(run_pool(Arc::new(context), 1, shutdown, GRACE)))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_processed_task_is_followed_by_the_next_claim_at_once() {
+        let mock = queue_pb().await;
+        for i in 0..3 {
+            queue_item(&mock, &format!("meeting-{:02}", i), i);
+        }
+        let disk = Arc::new(FakeDisk::new(u64::MAX));
+        let poll_interval = Duration::from_secs(3600);
+        let started = tokio::time::Instant::now();
+        let (stop, pool) = polling_pool(&mock, poll_interval, SpaceGate::new(std::env::temp_dir(), 0, 0, disk.clone()));
+
+        // Three claims each as the task before ends, not an interval later
+        settled(mock.clone()).await;
+        assert!(started.elapsed() < poll_interval, "took {:?}", started.elapsed());
+        assert!(disk.asked().len() >= 3);
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_pass_that_claims_nothing_waits_out_the_poll_interval() {
+        let mock = queue_pb().await;
+        queue_item(&mock, "standup", 0);
+        // Short of room, so each pass claims nothing without asking PocketBase
+        let disk = Arc::new(FakeDisk::new(0));
+        let poll_interval = Duration::from_millis(400);
+        let (stop, pool) = polling_pool(&mock, poll_interval, SpaceGate::new(std::env::temp_dir(), 1, 0, disk.clone()));
+        let passes = || async {
+            for _ in 0..100 {
+                tokio::task::yield_now().await;
+            }
+            disk.asked().len()
+        };
+
+        assert_eq!(passes().await, 1);
+        tokio::time::advance(poll_interval - Duration::from_millis(1)).await;
+        assert_eq!(passes().await, 1, "the next pass waits the whole interval");
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(passes().await, 2);
+        tokio::time::advance(poll_interval).await;
+        assert_eq!(passes().await, 3);
+
+        assert_eq!(statuses(&mock), [("standup".to_string(), "Pending".to_string())]);
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+    }
+
+    /// An [`Instrumented`] the test keeps a hold of, to count one job type's
+    /// runs behind a [`Pipelines`]
+    struct Counted(Arc<Instrumented>);
+
+    impl Pipeline for Counted {
+        async fn run(&self, task: &QueueTask, shutdown: &Shutdown, measurements: &Measurements) -> WorkerResult<Delivery> {
+            self.0.run(task, shutdown, measurements).await
+        }
+    }
+
+    /// Queue an item of `job_type`, created at second `second`
+    async fn job_item(mock: &MockPb, topic: &str, second: u32, job_type: &str) -> String {
+        let id = queue_item(mock, topic, second);
+        mock.client()
+            .update_record(QUEUE_COLLECTION, &id, &json!({ "job_type": job_type }))
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_a_worker_claims_only_the_job_types_it_runs() {
+        let mock = queue_pb().await;
+        // Queued before job types, so a meeting
+        queue_item(&mock, "standup", 10);
+        let refresh = job_item(&mock, "refresh", 5, "refresh_metadata").await;
+        job_item(&mock, "retro", 20, "process_meeting").await;
+        let pb = mock.client();
+        let meetings = [JobType::ProcessMeeting];
+
+        let task = claim_next_task(&pb, "worker-a", Utc::now(), &Fairness::fifo(), &meetings).await.unwrap().unwrap();
+        assert_eq!((task.topic.as_str(), task.job_type), ("standup", JobType::ProcessMeeting));
+        let task = claim_next_task(&pb, "worker-a", Utc::now(), &Fairness::fifo(), &meetings).await.unwrap().unwrap();
+        assert_eq!(task.topic, "retro");
+        assert!(claim_next_task(&pb, "worker-a", Utc::now(), &Fairness::fifo(), &meetings).await.unwrap().is_none());
+        assert_eq!(mock.record(QUEUE_COLLECTION, &refresh).unwrap()["status"], "Pending");
+
+        let task = claim_next_task(&pb, "worker-b", Utc::now(), &Fairness::fifo(), &[JobType::RefreshMetadata])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((task.record_id, task.job_type), (refresh, JobType::RefreshMetadata));
+    }
+
+    #[tokio::test]
+    async fn test_each_job_type_runs_within_its_own_limit() {
+        let mock = queue_pb().await;
+        for i in 0..6 {
+            job_item(&mock, &format!("meeting-{}", i), i, "process_meeting").await;
+            job_item(&mock, &format!("refresh-{}", i), i, "refresh_metadata").await;
+        }
+        let meetings = Arc::new(Instrumented::default());
+        let refreshes = Arc::new(Instrumented::default());
+        let pipelines = Pipelines::new()
+            .with(JobType::ProcessMeeting, Counted(meetings.clone()))
+            .with(JobType::RefreshMetadata, Counted(refreshes.clone()));
+        let mut context = Arc::into_inner(context(&mock, pipelines)).unwrap();
+        context.job_types = vec![(JobType::ProcessMeeting, 1), (JobType::RefreshMetadata, 2)];
+        let context = Arc::new(context);
+
+        tokio::time::timeout(Duration::from_secs(10), run_pool(context, 8, shutdown_after(settled(mock.clone())), GRACE))
+            .await
+            .expect("the pool drains the queue");
+
+        assert_eq!(meetings.finished.load(Ordering::SeqCst), 6);
+        assert_eq!(meetings.peak.load(Ordering::SeqCst), 1);
+        assert_eq!(refreshes.finished.load(Ordering::SeqCst), 6);
+        assert_eq!(refreshes.peak.load(Ordering::SeqCst), 2);
+        assert!(statuses(&mock).iter().all(|(_, status)| status == "Completed"));
+    }
+
+    #[tokio::test]
+    async fn test_items_of_unknown_job_types_are_left_for_other_workers() {
+        let mock = queue_pb().await;
+        let unknown = job_item(&mock, "reindex", 1, "reindex_search").await;
+        queue_item(&mock, "standup", 2);
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+        let context = context(&mock, Instrumented::default());
+
+        let standup_done = {
+            let mock = mock.clone();
+            async move {
+                while !statuses(&mock).contains(&("standup".to_string(), "Completed".to_string())) {
+                    sleep(Duration::from_millis(5)).await;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), run_pool(context.clone(), 2, shutdown_after(standup_done), GRACE))
+            .await
+            .expect("the pool stops once the meeting is done");
+
+        assert_eq!(context.pipeline.finished.load(Ordering::SeqCst), 1);
+        let untouched = mock.record(QUEUE_COLLECTION, &unknown).unwrap();
+        assert_eq!(untouched["status"], "Pending");
+        assert_eq!(untouched["version"], 0);
+        assert_eq!(untouched["claimed_by"], "");
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 1);
+        assert!(!logs.text().contains("Skipping queue item"), "{}", logs.text());
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_claimed_while_the_scratch_volume_is_short_of_space() {
+        let mock = queue_pb().await;
+        queue_item(&mock, "standup", 1);
+        let disk = Arc::new(FakeDisk::new(100));
+        let mut context = Arc::into_inner(context(&mock, Instrumented::default())).unwrap();
+        context.disk = SpaceGate::new(std::env::temp_dir(), 50, 100, disk.clone());
+        let context = Arc::new(context);
+
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown_after(settled(mock.clone())), GRACE));
+        while disk.asked().len() < 3 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(statuses(&mock), [("standup".to_string(), "Pending".to_string())]);
+        assert!(mock.records(CLAIMS_COLLECTION).is_empty());
+
+        disk.set(150);
+        tokio::time::timeout(Duration::from_secs(10), pool).await.unwrap().unwrap();
+        assert_eq!(statuses(&mock), [("standup".to_string(), "Completed".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_a_panicking_task_is_failed_without_stopping_the_others() {
+        let mock = queue_pb().await;
+        let boom = queue_item(&mock, "boom", 1);
+        let fails = queue_item(&mock, "fails", 2);
+        queue_item(&mock, "standup", 3);
+        queue_item(&mock, "retro", 4);
+        let context = context(&mock, Instrumented::default());
+
+        tokio::time::timeout(Duration::from_secs(10), run_pool(context.clone(), 2, shutdown_after(settled(mock.clone())), GRACE))
+            .await
+            .expect("the pool outlives the panic");
+
+        assert_eq!(
+            statuses(&mock),
+            [
+                ("boom".to_string(), "Failed".to_string()),
+                ("fails".to_string(), "Failed".to_string()),
+                ("retro".to_string(), "Completed".to_string()),
+                ("standup".to_string(), "Completed".to_string()),
+            ]
+        );
+        assert_eq!(mock.record(QUEUE_COLLECTION, &boom).unwrap()["error_message"], "Task panicked: boom");
+        assert_eq!(
+            mock.record(QUEUE_COLLECTION, &fails).unwrap()["error_message"],
+            "Loom API error: upload rejected"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_claiming_and_waits_for_running_tasks() {
+        let mock = queue_pb().await;
+        for i in 0..5 {
+            queue_item(&mock, &format!("meeting-{}", i), i);
+        }
+        let gate = Arc::new(Semaphore::new(0));
+        let context = context(&mock, Instrumented { gate: Some(gate.clone()), ..Default::default() });
+        let (stop, shutdown) = Shutdown::channel();
+        let pool = tokio::spawn(run_pool(context.clone(), 2, shutdown, GRACE));
+
+        while context.pipeline.running.load(Ordering::SeqCst) < 2 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        // Several poll intervals pass with both permits held, claiming nothing
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 2);
+
+        stop.send(true).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert!(!pool.is_finished(), "running tasks are waited for");
+
+        gate.add_permits(2);
+        tokio::time::timeout(Duration::from_secs(5), pool).await.unwrap().unwrap();
+        let statuses = statuses(&mock);
+        assert_eq!(statuses.iter().filter(|(_, status)| status == "Completed").count(), 2);
+        assert_eq!(statuses.iter().filter(|(_, status)| status == "Pending").count(), 3);
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 2);
+    }
+
+    /// Whether `record` went back to the queue unclaimed, noting why
+    fn released(record: &Value) -> bool {
+        record["status"] == "Pending"
+            && record["claimed_by"] == ""
+            && record["claimed_at"] == ""
+            && record["error_message"]
+                .as_str()
+                .is_some_and(|note| note.ends_with("returned to the queue by worker-a"))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sigterm_returns_an_in_flight_task_to_the_queue() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let gate = Arc::new(Semaphore::new(0));
+        let pipeline = Instrumented { gate: Some(gate.clone()), stages: true, ..Default::default() };
+        let context = context(&mock, pipeline);
+        let shutdown = Shutdown::on_signal();
+        let grace = Duration::from_secs(5);
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown.clone(), grace));
+
+        while context.pipeline.running.load(Ordering::SeqCst) < 1 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        let sent = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(sent.success());
+        tokio::time::timeout(Duration::from_secs(5), shutdown.requested()).await.unwrap();
+
+        // The stage in flight finishes; the next one never starts
+        gate.add_permits(1);
+        tokio::time::timeout(grace, pool).await.expect("the pool stops within the grace period").unwrap();
+        assert_eq!(context.pipeline.finished.load(Ordering::SeqCst), 1);
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert!(released(&record), "{}", record);
+        assert_eq!(record["retry_count"], 0);
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_outliving_the_grace_period_are_returned_to_the_queue() {
+        let mock = queue_pb().await;
+        let stuck = queue_item(&mock, "standup", 0);
+        let untouched = queue_item(&mock, "retro", 1);
+        // Never opened, so the task's stage never ends
+        let gate = Arc::new(Semaphore::new(0));
+        let context = context(&mock, Instrumented { gate: Some(gate), ..Default::default() });
+        let (stop, shutdown) = Shutdown::channel();
+        let grace = Duration::from_millis(200);
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown, grace));
+
+        while context.pipeline.running.load(Ordering::SeqCst) < 1 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        let stopped_at = tokio::time::Instant::now();
+        stop.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), pool).await.unwrap().unwrap();
+        assert!(stopped_at.elapsed() >= grace, "running tasks get the grace period");
+
+        assert_eq!(context.pipeline.finished.load(Ordering::SeqCst), 0);
+        let record = mock.record(QUEUE_COLLECTION, &stuck).unwrap();
+        assert!(released(&record), "{}", record);
+        assert_eq!(mock.record(QUEUE_COLLECTION, &untouched).unwrap()["claimed_by"], "");
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 1);
+
+        // Released, it can be claimed afresh
+        let pb = mock.client();
+        let task = claim_oldest_unclaimed_task(&pb, "worker-b", Utc::now()).await.unwrap().unwrap();
+        assert_eq!(task.record_id, stuck);
+        assert_eq!(task.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_draining_stops_claiming_and_lets_running_tasks_finish() {
+        let mock = queue_pb().await;
+        for i in 0..3 {
+            queue_item(&mock, &format!("meeting-{}", i), i);
+        }
+        let gate = Arc::new(Semaphore::new(0));
+        let context = context(&mock, Instrumented { gate: Some(gate.clone()), stages: true, ..Default::default() });
+        let (drain, draining) = Shutdown::channel();
+        let (_stop, shutdown) = Shutdown::channel();
+        let grace = Duration::from_millis(100);
+        let pool = tokio::spawn(run_pool_until_drained(context.clone(), 2, shutdown, draining, grace));
+
+        while context.pipeline.running.load(Ordering::SeqCst) < 2 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        drain.send(true).unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert!(!pool.is_finished(), "running tasks are waited for past the grace period");
+
+        // Not a shutdown, so they go on to their next stage and complete
+        gate.add_permits(2);
+        tokio::time::timeout(Duration::from_secs(5), pool).await.unwrap().unwrap();
+        let statuses = statuses(&mock);
+        assert_eq!(statuses.iter().filter(|(_, status)| status == "Completed").count(), 2);
+        assert_eq!(statuses.iter().filter(|(_, status)| status == "Pending").count(), 1);
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 2);
+    }
+
+    /// Wall-clock time moving with Tokio's, so pausing Tokio's clock stops it
+    struct TokioClock {
+        start: DateTime<Utc>,
+        anchor: tokio::time::Instant,
+    }
+
+    impl Clock for TokioClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.start + chrono::Duration::from_std(self.anchor.elapsed()).unwrap()
+        }
+    }
+
+    /// Where a [`Scripted`] run that succeeds put the meeting
+    const SHARE_URL: &str = "https://www.loom.com/share/v1";
+
+    /// Size of the recording a [`Scripted`] run downloads and uploads
+    const RECORDING_BYTES: u64 = 6_000_000;
+
+    /// Fails with each scripted error in turn then succeeds, noting when
+    /// each attempt started and the task it was given; each attempt gets as
+    /// far as a download it reports took 3s, and one that succeeds reports a
+    /// 5s upload
+    struct Scripted {
+        clock: Arc<TokioClock>,
+        broadcast_service: Arc<BroadcastService>,
+        errors: Mutex<VecDeque<WorkerError>>,
+        attempts: Mutex<Vec<(DateTime<Utc>, QueueTask)>>,
+    }
+
+    impl Pipeline for Scripted {
+        async fn run(&self, task: &QueueTask, _shutdown: &Shutdown, measurements: &Measurements) -> WorkerResult<Delivery> {
+            self.attempts.lock().unwrap().push((self.clock.now(), task.clone()));
+            StageProgress::new(&self.broadcast_service, task.id, &task.user_id, ProcessingStage::Downloading).start();
+            measurements.stage("download", self.clock.now(), Duration::from_secs(3));
+            measurements.downloaded(RECORDING_BYTES);
+            let error = self.errors.lock().unwrap().pop_front();
+            match error {
+                Some(e) => Err(e),
+                None => {
+                    let started_at = self.clock.now() + chrono::Duration::seconds(3);
+                    measurements.stage("upload", started_at, Duration::from_secs(5));
+                    measurements.uploaded(RECORDING_BYTES);
+                    Ok(Delivery {
+                        share_url: Some(SHARE_URL.to_string()),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Keeps every email it's given, failing to send them when `down`
+    #[derive(Default)]
+    struct CapturedMail {
+        sent: Mutex<Vec<TaskEmail>>,
+        down: bool,
+    }
+
+    impl Mailer for CapturedMail {
+        fn send(&self, email: TaskEmail) -> BoxFuture<'_, WorkerResult<()>> {
+            self.sent.lock().unwrap().push(email);
+            let down = self.down;
+            Box::pin(async move {
+                match down {
+                    true => Err(WorkerError::Email("smtp-service returned 503".to_string())),
+                    false => Ok(()),
+                }
+            })
+        }
+    }
+
+    /// Keeps every result it's given, by owner and task
+    #[derive(Default)]
+    struct CapturedResults(Mutex<Vec<(String, Uuid, ProcessingResult)>>);
+
+    impl ResultStore for CapturedResults {
+        fn store<'a>(&'a self, user_id: &'a str, task_id: Uuid, result: &'a ProcessingResult) -> BoxFuture<'a, WorkerResult<()>> {
+            self.0.lock().unwrap().push((user_id.to_string(), task_id, result.clone()));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    struct Outcome {
+        record: Value,
+        attempts: Vec<(DateTime<Utc>, QueueTask)>,
+        emails: Vec<TaskEmail>,
+        updates: Vec<QueueUpdate>,
+        dead_letters: Vec<Value>,
+        events: Vec<SystemEvent>,
+        results: Vec<(String, Uuid, ProcessingResult)>,
+    }
+
+    impl Outcome {
+        fn retry_counts(&self) -> Vec<Option<u32>> {
+            self.retries().iter().map(|retry| retry.as_ref().map(|retry| retry.retry_count)).collect()
+        }
+
+        fn retries(&self) -> Vec<Option<RetryInfo>> {
+            self.updates
+                .iter()
+                .filter(|update| matches!(update.update_type, QueueUpdateType::TaskRetried))
+                .map(|update| update.retry.clone())
+                .collect()
+        }
+
+        fn failures(&self) -> usize {
+            self.updates
+                .iter()
+                .filter(|update| matches!(update.update_type, QueueUpdateType::TaskFailed))
+                .count()
+        }
+
+        /// How long each retry was scheduled after the attempt before it
+        /// failed, checking it wasn't run early
+        fn waits(&self) -> Vec<Duration> {
+            self.attempts
+                .windows(2)
+                .map(|pair| {
+                    let (failed_at, _) = &pair[0];
+                    let (started_at, task) = &pair[1];
+                    let due = task.next_attempt_at.expect("a retried task has a next attempt time");
+                    assert!(*started_at >= due, "retried at {} before {}", started_at, due);
+                    (due - *failed_at).to_std().unwrap()
+                })
+                .collect()
+        }
+    }
+
+    /// Queue one item allowing `max_retries`, and run it through the pool
+    /// with a paused clock until it completes or fails for good
+    async fn run_scripted(errors: Vec<WorkerError>, max_retries: u32) -> Outcome {
+        run_scripted_on(queue_pb().await, CapturedMail::default(), errors, max_retries).await
+    }
+
+    /// [`run_scripted`] against `mock`, emailing through `mailer`
+    async fn run_scripted_on(mock: MockPb, mailer: CapturedMail, errors: Vec<WorkerError>, max_retries: u32) -> Outcome {
+        let item = queue_item(&mock, "standup", 0);
+        let pb = mock.client();
+        pb.update_record(QUEUE_COLLECTION, &item, &json!({ "max_retries": max_retries })).await.unwrap();
+
+        let clock = Arc::new(TokioClock {
+            start: Utc::now(),
+            anchor: tokio::time::Instant::now(),
+        });
+        let mailer = Arc::new(mailer);
+        let results = Arc::new(CapturedResults::default());
+        let broadcast_service = BroadcastServiceFactory::create_shared(32);
+        let context = Arc::new(TaskContext {
+            pb,
+            worker_id: "worker-a".to_string(),
+            poll_interval: Duration::from_secs(1),
+            disk: roomy_disk(),
+            fairness: Fairness::fifo(),
+            job_types: vec![(JobType::ProcessMeeting, 16)],
+            pipeline: Scripted {
+                clock: clock.clone(),
+                broadcast_service: broadcast_service.clone(),
+                errors: Mutex::new(errors.into()),
+                attempts: Mutex::default(),
+            },
+            broadcast_service,
+            email: email_config("http://127.0.0.1:9"),
+            retry: RetryPolicy {
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(90),
+            },
+            clock,
+            mailer: mailer.clone(),
+            results: results.clone(),
+        });
+        let mut updates = context.broadcast_service.subscribe();
+        let mut events = context.broadcast_service.subscribe_system();
+
+        tokio::time::timeout(Duration::from_secs(3600), run_pool(context.clone(), 1, shutdown_after(settled(mock.clone())), GRACE))
+            .await
+            .expect("the task settles");
+
+        let emails = mailer.sent.lock().unwrap().clone();
+        let attempts = context.pipeline.attempts.lock().unwrap().clone();
+        let results = results.0.lock().unwrap().clone();
+        Outcome {
+            record: mock.record(QUEUE_COLLECTION, &item).unwrap(),
+            attempts,
+            emails,
+            updates: std::iter::from_fn(|| updates.try_recv().ok()).collect(),
+            dead_letters: mock.records(DEAD_LETTER_COLLECTION),
+            events: std::iter::from_fn(|| events.try_recv().ok()).collect(),
+            results,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_retryable_failure_is_retried_after_a_growing_delay() {
+        let outcome = run_scripted(
+            vec![
+                WorkerError::PocketBase("connection reset".to_string()),
+                WorkerError::Fathom("503 Service Unavailable".to_string()),
+            ],
+            3,
+        )
+        .await;
+
+        assert_eq!(outcome.record["status"], "Completed");
+        assert_eq!(outcome.record["retry_count"], 2);
+        assert_eq!(outcome.record["error_message"], "");
+        let retry_counts: Vec<u32> = outcome.attempts.iter().map(|(_, task)| task.retry_count).collect();
+        assert_eq!(retry_counts, [0, 1, 2]);
+        assert_eq!(
+            outcome.attempts[1].1.error_message.as_deref(),
+            Some("PocketBase error: connection reset")
+        );
+
+        // Half to all of 60s, then of 120s capped at 90s
+        let waits = outcome.waits();
+        assert!(waits[0] >= Duration::from_secs(30) && waits[0] <= Duration::from_secs(61), "{:?}", waits);
+        assert!(waits[1] >= Duration::from_secs(45) && waits[1] <= Duration::from_secs(91), "{:?}", waits);
+
+        assert_eq!(outcome.retry_counts(), [Some(1), Some(2)]);
+        // Each retry is announced with when the queue item says it is due
+        for (retry, (_, task)) in outcome.retries().into_iter().zip(&outcome.attempts[1..]) {
+            let retry = retry.unwrap();
+            assert_eq!(retry.max_retries, 3);
+            let due = task.next_attempt_at.unwrap();
+            assert_eq!(retry.next_attempt_at.timestamp_millis(), due.timestamp_millis());
+        }
+        assert_eq!(outcome.failures(), 0);
+        assert!(matches!(
+            outcome.updates.last().map(|update| &update.update_type),
+            Some(QueueUpdateType::TaskCompleted)
+        ));
+        assert!(outcome.emails.is_empty());
+        assert_eq!(outcome.record["attempts"].as_array().unwrap().len(), 2);
+        assert!(outcome.dead_letters.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_every_attempt_keeps_its_result_under_the_task() {
+        let outcome = run_scripted(vec![WorkerError::Fathom("503 from https://api.fathom.video/x?token=abc".to_string())], 3).await;
+        assert_eq!(outcome.record["status"], "Completed");
+        let task_id = outcome.attempts[0].1.id;
+        assert!(outcome.results.iter().all(|(user_id, id, _)| user_id == "alice" && *id == task_id));
+        let results: Vec<&ProcessingResult> = outcome.results.iter().map(|(_, _, result)| result).collect();
+        assert_eq!(results.len(), 2);
+
+        let retried = results[0];
+        assert_eq!((retried.status, retried.attempt), (ResultStatus::Retrying, 1));
+        assert_eq!(retried.meeting_id, outcome.attempts[0].1.meeting_id);
+        assert_eq!(retried.error.as_deref(), Some("Fathom API error: 503 from https://api.fathom.video"));
+        assert_eq!(retried.loom_url, None);
+        // The report of a failed attempt stops where it did
+        let report = &retried.report;
+        assert_eq!((report.outcome, report.retry_count), (ResultStatus::Retrying, 0));
+        assert_eq!(report.stages.len(), 1);
+        assert_eq!((report.download_bytes, report.download_mb_per_sec), (Some(RECORDING_BYTES), Some(2.0)));
+        assert_eq!((report.upload_bytes, report.upload_mb_per_sec), (None, None));
+
+        let completed = results[1];
+        assert_eq!((completed.status, completed.attempt), (ResultStatus::Completed, 2));
+        assert_eq!(completed.loom_url.as_deref(), Some(SHARE_URL));
+        assert_eq!(completed.error, None);
+        let report = &completed.report;
+        assert_eq!((report.outcome, report.retry_count), (ResultStatus::Completed, 1));
+        let stages: Vec<(&str, f64)> = report.stages.iter().map(|timing| (timing.stage.as_str(), timing.seconds)).collect();
+        assert_eq!(stages, [("download", 3.0), ("upload", 5.0)]);
+        assert_eq!(report.stages[1].finished_at - report.stages[0].started_at, chrono::Duration::seconds(8));
+        assert_eq!(report.upload_mb_per_sec, Some(1.2));
+        assert_eq!(report.transcode_speed, None);
+        assert_eq!(report.finished_at, completed.finished_at);
+        assert!(completed.finished_at > retried.finished_at);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_failed_task_keeps_what_went_wrong() {
+        let outcome = run_scripted(vec![WorkerError::Loom("upload rejected".to_string())], 0).await;
+        assert_eq!(outcome.record["status"], "Failed");
+        let [(_, _, result)] = outcome.results.as_slice() else {
+            panic!("one result for one attempt: {:?}", outcome.results);
+        };
+        assert_eq!((result.status, result.attempt), (ResultStatus::Failed, 1));
+        assert_eq!(result.error.as_deref(), Some("Loom API error: upload rejected"));
+        assert_eq!(result.loom_url, None);
+        assert_eq!(result.report.download_bytes, Some(RECORDING_BYTES));
+        assert_eq!(result.report.summary(), "download 3.0s (6.0 MB at 2.0 MB/s); 0.0s in all");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_permanent_failure_fails_at_once_with_one_email() {
+        let expired_at = DateTime::parse_from_rfc3339("2026-02-26T09:00:00Z").unwrap().with_timezone(&Utc);
+        let outcome = run_scripted(
+            vec![WorkerError::KeyExpired {
+                service: "fathom".to_string(),
+                key_id: "default".to_string(),
+                expired_at,
+            }],
+            3,
+        )
+        .await;
+
+        assert_eq!(outcome.record["status"], "Failed");
+        assert_eq!(outcome.record["retry_count"], 0);
+        assert_eq!(outcome.attempts.len(), 1);
+        assert!(outcome.retry_counts().is_empty());
+        assert_eq!(outcome.failures(), 1);
+        assert_eq!(outcome.emails.len(), 1);
+        assert_eq!(outcome.emails[0].subject, "Your Fathom API key has expired");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_retries_fail_with_one_email() {
+        let errors = (0..5).map(|_| WorkerError::Loom("upload rejected".to_string())).collect();
+        let outcome = run_scripted(errors, 2).await;
+
+        assert_eq!(outcome.record["status"], "Failed");
+        assert_eq!(outcome.record["retry_count"], 2);
+        assert_eq!(outcome.record["error_message"], "Loom API error: upload rejected");
+        assert_eq!(outcome.attempts.len(), 3, "no attempt past max_retries");
+        assert_eq!(outcome.waits().len(), 2);
+
+        assert_eq!(outcome.retry_counts(), [Some(1), Some(2)]);
+        assert!(outcome.retries().iter().flatten().all(|retry| retry.max_retries == 2));
+        assert_eq!(outcome.failures(), 1);
+        let failed = outcome.updates.last().unwrap();
+        assert!(matches!(failed.update_type, QueueUpdateType::TaskFailed));
+        assert_eq!(failed.retry, None, "the last failure isn't a retry");
+        assert_eq!(failed.error.as_deref(), Some("Loom API error: upload rejected"));
+        assert_eq!(outcome.emails.len(), 1);
+        assert!(outcome.emails[0].body_text.contains("upload rejected"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_owners_who_ask_are_emailed_the_loom_link() {
+        let outcome = run_scripted(Vec::new(), 3).await;
+        assert_eq!(outcome.record["status"], "Completed");
+        assert!(outcome.emails.is_empty(), "only failures are emailed by default");
+
+        let mock = queue_pb().await;
+        mock.insert(USER_SETTINGS_COLLECTION, json!({ "user_id": "alice", "email_on_success": true }));
+        let outcome = run_scripted_on(mock, CapturedMail::default(), Vec::new(), 3).await;
+        assert_eq!(outcome.emails.len(), 1);
+        let email = &outcome.emails[0];
+        assert_eq!(email.to_email, "alice@example.com");
+        assert_eq!(email.subject, "\"standup\" is on Loom");
+        assert!(email.body_text.ends_with(&format!("Watch it on Loom: {}", SHARE_URL)));
+        assert!(email.body_text.contains("Stages: download 3.0s (6.0 MB at 2.0 MB/s), upload 5.0s (6.0 MB at 1.2 MB/s)"));
+        assert!(email.body_html.contains(&format!("<a href=\"{}\">", SHARE_URL)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_an_email_that_cant_be_sent_leaves_the_outcome_alone() {
+        let down = || CapturedMail { down: true, ..Default::default() };
+        let outcome = run_scripted_on(queue_pb().await, down(), vec![WorkerError::Loom("upload rejected".to_string())], 0).await;
+        assert_eq!(outcome.emails.len(), 1, "the email was tried");
+        assert_eq!(outcome.record["status"], "Failed");
+        assert_eq!(outcome.dead_letters.len(), 1);
+
+        let mock = queue_pb().await;
+        mock.insert(USER_SETTINGS_COLLECTION, json!({ "user_id": "alice", "email_on_success": true }));
+        let outcome = run_scripted_on(mock, down(), Vec::new(), 0).await;
+        assert_eq!(outcome.emails.len(), 1);
+        assert_eq!(outcome.record["status"], "Completed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_tasks_are_dead_lettered_with_every_attempt() {
+        let errors = vec![
+            WorkerError::Fathom("503 Service Unavailable".to_string()),
+            WorkerError::Loom("upload rejected".to_string()),
+            WorkerError::Loom("upload rejected again".to_string()),
+        ];
+        let outcome = run_scripted(errors, 2).await;
+
+        assert_eq!(outcome.dead_letters.len(), 1);
+        let dead_letter = &outcome.dead_letters[0];
+        assert_eq!(outcome.record["status"], "Failed");
+        assert_eq!(outcome.record["dead_letter_id"], dead_letter["id"]);
+        assert_eq!(dead_letter["queue_item"], outcome.record["id"]);
+        assert_eq!(dead_letter["task_id"], outcome.record["task_id"]);
+        assert_eq!(dead_letter["task"]["topic"], "standup");
+        assert_eq!(dead_letter["error_message"], "Loom API error: upload rejected again");
+        assert_eq!(dead_letter["last_stage"], "downloading");
+
+        let attempts: Vec<TaskAttempt> = serde_json::from_value(dead_letter["attempts"].clone()).unwrap();
+        let errors: Vec<&str> = attempts.iter().map(|attempt| attempt.error.as_str()).collect();
+        assert_eq!(
+            errors,
+            [
+                "Fathom API error: 503 Service Unavailable",
+                "Loom API error: upload rejected",
+                "Loom API error: upload rejected again"
+            ]
+        );
+        for (attempt, (started_at, _)) in attempts.iter().zip(&outcome.attempts) {
+            let claimed_at = attempt.started_at.expect("an attempt starts when claimed");
+            assert!(claimed_at <= *started_at && *started_at <= attempt.failed_at);
+            assert_eq!(attempt.stage, Some(ProcessingStage::Downloading));
+        }
+        assert!(attempts.windows(2).all(|pair| pair[0].failed_at < pair[1].started_at.unwrap()));
+
+        assert_eq!(outcome.events.len(), 1);
+        assert_eq!(outcome.events[0].user_id, "alice");
+        assert!(matches!(
+            &outcome.events[0].event_type,
+            SystemEventType::TaskDeadLettered { task_id, error }
+                if task_id.to_string() == outcome.record["task_id"] && error.ends_with("upload rejected again")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dead_lettered_items_are_never_claimed() {
+        let mock = queue_pb().await;
+        let dead = queue_item(&mock, "standup", 10);
+        let live = queue_item(&mock, "retro", 20);
+        let pb = mock.client();
+        // Set back to pending by hand without requeueing it
+        pb.update_record(QUEUE_COLLECTION, &dead, &json!({ "dead_letter_id": "r00000000000099" }))
+            .await
+            .unwrap();
+
+        let task = claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().unwrap();
+        assert_eq!(task.record_id, live);
+        assert!(claim_oldest_unclaimed_task(&pb, "worker-a", Utc::now()).await.unwrap().is_none());
+        assert_eq!(mock.record(QUEUE_COLLECTION, &dead).unwrap()["status"], "Pending");
+    }
+
+    /// The real pipeline for recording 42, whose media hangs 3000 bytes in
+    async fn stalling_pipeline(mock: &MockPb, limits: impl FnOnce(&mut WorkerSettings)) -> FathomToLoom {
+        let media = MockMedia::stalling(vec![1; 10_000], 3000).await;
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let mut config = worker_config(database(mock), backend, &mock_fathom_with(&media.url).await, "http://127.0.0.1:9");
+        limits(&mut config.worker);
+        FathomToLoom {
+            config,
+            client: reqwest::Client::new(),
+            pb: mock.client(),
+            broadcast_service: BroadcastServiceFactory::create_shared(64),
+            fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stages_are_cut_off_at_their_limit() {
+        let slow = |secs| async move {
+            sleep(Duration::from_secs(secs)).await;
+            Ok(secs)
+        };
+        assert_eq!(within("metadata", 60, slow(59)).await.unwrap(), 59);
+
+        let started = tokio::time::Instant::now();
+        let error = within("metadata", 60, slow(3600)).await.unwrap_err();
+        assert_eq!(started.elapsed(), Duration::from_secs(60));
+        assert_eq!(error.to_string(), "stage timeout: metadata took longer than 60s");
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_a_stage_past_its_timeout_fails_the_attempt() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "weekly", 1);
+        let pb = mock.client();
+        pb.update_record(QUEUE_COLLECTION, &item, &json!({ "meeting_id": "42" })).await.unwrap();
+        let pipeline = stalling_pipeline(&mock, |limits| limits.download_timeout = 1).await;
+        let context = TaskContext {
+            pb,
+            worker_id: "worker-a".to_string(),
+            poll_interval: Duration::from_secs(1),
+            disk: roomy_disk(),
+            fairness: Fairness::fifo(),
+            job_types: vec![(JobType::ProcessMeeting, 16)],
+            pipeline,
+            broadcast_service: BroadcastServiceFactory::create_shared(16),
+            email: email_config("http://127.0.0.1:9"),
+            retry: RetryPolicy {
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(60),
+            },
+            clock: Arc::new(SystemClock),
+            mailer: Arc::new(LogMailer),
+            results: Arc::new(LogResults),
+        };
+        let mut updates = context.broadcast_service.subscribe();
+        let task = claimed_task(&mock, &item);
+
+        let started = tokio::time::Instant::now();
+        run_task(&context, &task, &Shutdown::channel().1).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!(record["status"], "Pending");
+        assert_eq!(record["retry_count"], 1);
+        assert_eq!(record["error_message"], "stage timeout: download took longer than 1s");
+        let update = updates.try_recv().unwrap();
+        assert!(matches!(update.update_type, QueueUpdateType::TaskStarted));
+        let update = updates.try_recv().unwrap();
+        assert!(matches!(update.update_type, QueueUpdateType::TaskRetried));
+        let dir = task_dir(&context.pipeline.config.worker.scratch_dir, task.id);
+        assert!(!dir.exists(), "the partial download is removed");
+    }
+
+    #[tokio::test]
+    async fn test_the_task_deadline_fires_with_every_stage_inside_its_limit() {
+        let mock = queue_pb().await;
+        let pipeline = stalling_pipeline(&mock, |limits| limits.task_deadline = 1).await;
+        let task = QueueTask {
+            user_id: "alice".to_string(),
+            meeting_id: "42".to_string(),
+            ..Default::default()
+        };
+
+        let error = pipeline.run(&task, &Shutdown::channel().1, &Measurements::default()).await.unwrap_err();
+        assert!(matches!(error, WorkerError::StageTimeout { stage: "task", limit } if limit == Duration::from_secs(1)));
+        assert!(error.is_retryable());
+        let dir = task_dir(&pipeline.config.worker.scratch_dir, task.id);
+        assert!(!dir.exists(), "the partial download is removed");
+    }
+
+    #[tokio::test]
+    async fn test_only_a_task_that_will_run_again_keeps_its_workspace() {
+        let mock = queue_pb().await;
+        let media = MockMedia::start(vec![4; 2500], 0, 0).await;
+        let loom = MockLoom::start().await;
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let fathom = mock_fathom_with(&media.url).await;
+        let pipeline = |loom_url: &str| FathomToLoom {
+            config: worker_config(database(&mock), backend.clone(), &fathom, loom_url),
+            client: reqwest::Client::new(),
+            pb: mock.client(),
+            broadcast_service: BroadcastServiceFactory::create_shared(64),
+            fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+        };
+        let task = |max_retries| QueueTask {
+            user_id: "alice".to_string(),
+            meeting_id: "42".to_string(),
+            record_id: mock.insert(QUEUE_COLLECTION, json!({ "status": "InProgress" })),
+            max_retries,
+            ..Default::default()
+        };
+        let dir = |pipeline: &FathomToLoom, task: &QueueTask| task_dir(&pipeline.config.worker.scratch_dir, task.id);
+        let shutdown = Shutdown::channel().1;
+
+        let done = pipeline(&loom.url);
+        let completed = task(3);
+        let measurements = Measurements::default();
+        done.run(&completed, &shutdown, &measurements).await.unwrap();
+        assert!(!dir(&done, &completed).exists());
+        let measured = measurements.snapshot();
+        let stages: Vec<&str> = measured.stages.iter().map(|timing| timing.stage.as_str()).collect();
+        assert_eq!(stages, ["metadata", "download", "transcode", "upload"]);
+        assert_eq!((measured.downloaded, measured.uploaded), (Some(2500), Some(2500)));
+
+        // Loom is down: a retry resumes from the download, a last attempt doesn't
+        let down = pipeline("http://127.0.0.1:9");
+        let retried = task(3);
+        assert!(down.run(&retried, &shutdown, &Measurements::default()).await.unwrap_err().is_retryable());
+        let kept = Workspace::create(&down.config.worker.scratch_dir, retried.id).unwrap();
+        assert_eq!(std::fs::read(kept.download_path()).unwrap(), vec![4; 2500]);
+        drop(kept);
+        let failed = task(0);
+        down.run(&failed, &shutdown, &Measurements::default()).await.unwrap_err();
+        assert!(!dir(&down, &failed).exists());
+
+        // Abandoned at shutdown mid-download, it resumes once claimed again
+        let stalling = Arc::new(stalling_pipeline(&mock, |_| {}).await);
+        let abandoned = task(3);
+        let run = tokio::spawn({
+            let (stalling, abandoned) = (stalling.clone(), abandoned.clone());
+            async move { stalling.run(&abandoned, &Shutdown::channel().1, &Measurements::default()).await }
+        });
+        let started = dir(&stalling, &abandoned);
+        while !started.join("recording.download").exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        run.abort();
+        assert!(run.await.unwrap_err().is_cancelled());
+        assert!(started.join("recording.download").exists());
+        std::fs::remove_dir_all(started).unwrap();
+    }
+
+    /// What Loom was sent for meeting 42 with `transcode` and, when
+    /// `streaming`, the stages timed on the way
+    async fn delivered(mock: &MockPb, fathom: &str, loom: &MockLoom, transcode: &TranscodeConfig, streaming: bool) -> (Vec<u8>, Vec<String>) {
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let mut config = worker_config(database(mock), backend, fathom, &loom.url);
+        config.worker.streaming = streaming;
+        config.transcode = transcode.clone();
+        let pipeline = FathomToLoom {
+            config,
+            client: reqwest::Client::new(),
+            pb: mock.client(),
+            broadcast_service: BroadcastServiceFactory::create_shared(64),
+            fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+        };
+        let task = QueueTask {
+            user_id: "alice".to_string(),
+            meeting_id: "42".to_string(),
+            record_id: mock.insert(QUEUE_COLLECTION, json!({ "status": "InProgress" })),
+            ..Default::default()
+        };
+        let measurements = Measurements::default();
+        let delivery = pipeline.run(&task, &Shutdown::channel().1, &measurements).await.unwrap();
+        let video_id = delivery.share_url.unwrap().rsplit('/').next().unwrap().to_string();
+        let mut stages: Vec<String> = measurements.snapshot().stages.into_iter().map(|timing| timing.stage).collect();
+        stages.sort();
+        (loom.uploaded(&video_id.replace("video-", "upload-")), stages)
+    }
+
+    #[tokio::test]
+    async fn test_streaming_sends_loom_what_going_stage_by_stage_does() {
+        use std::os::unix::fs::PermissionsExt;
+        let mock = queue_pb().await;
+        let bytes: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        let media = MockMedia::start(bytes.clone(), 0, 0).await;
+        let fathom = mock_fathom_with(&media.url).await;
+        let loom = MockLoom::start().await;
+        let off = worker_config(database(&mock), BackendConfig { url: String::new(), internal_api_token: None }, "", "").transcode;
+
+        let (sequential, _) = delivered(&mock, &fathom, &loom, &off, false).await;
+        let (streamed, stages) = delivered(&mock, &fathom, &loom, &off, true).await;
+        assert_eq!(sequential, bytes);
+        assert_eq!(streamed, bytes);
+        assert_eq!(stages, ["download", "metadata", "upload"]);
+
+        // ffmpeg stand-ins appending "transcoded", failing on a pipe once
+        // no-pipes exists, as for an MP4 with its index at the end
+        let dir = tempfile::tempdir().unwrap();
+        let tool = |name: &str, body: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let webm = TranscodeConfig {
+            mode: TranscodeMode::Always,
+            ffprobe_path: tool("ffprobe", "echo '{\"format\": {\"format_name\": \"mov,mp4\"}, \"streams\": []}'\n"),
+            ffmpeg_path: tool(
+                "ffmpeg",
+                &format!(
+                    "for arg; do out=$arg; done\n\
+                     while [ $# -gt 0 ]; do [ \"$1\" = -i ] && in=$2; shift; done\n\
+                     if [ \"$in\" = pipe:0 ]; then\n\
+                     [ -e {}/no-pipes ] && echo 'moov atom not found' >&2 && exit 1\n\
+                     in=/dev/stdin out=/dev/stdout\nfi\n\
+                     {{ cat \"$in\" && echo transcoded; }} > \"$out\"\n",
+                    dir.path().display()
+                ),
+            ),
+            profile: TranscodeProfile { container: Container::Webm, ..off.profile.clone() },
+        };
+        let transcoded = [&bytes[..], b"transcoded\n"].concat();
+        let (sequential, _) = delivered(&mock, &fathom, &loom, &webm, false).await;
+        let (streamed, stages) = delivered(&mock, &fathom, &loom, &webm, true).await;
+        assert_eq!(sequential, transcoded);
+        assert_eq!(streamed, transcoded);
+        assert_eq!(stages, ["download", "metadata", "transcode", "upload"]);
+
+        // What ffmpeg can't read from a pipe is sent stage by stage instead
+        std::fs::write(dir.path().join("no-pipes"), b"").unwrap();
+        let (fallen_back, stages) = delivered(&mock, &fathom, &loom, &webm, true).await;
+        assert_eq!(fallen_back, transcoded);
+        assert_eq!(stages, ["download", "download", "metadata", "transcode", "transcode", "upload"]);
+        assert_eq!(media.ranges().len(), 6, "downloaded again to go stage by stage");
+    }
+
+    /// The id of the task queued as `item`
+    fn task_id_of(mock: &MockPb, item: &str) -> Uuid {
+        Uuid::parse_str(mock.record(QUEUE_COLLECTION, item).unwrap()["task_id"].as_str().unwrap()).unwrap()
+    }
+
+    async fn status_becomes(mock: &MockPb, item: &str, status: &str) {
+        let reached = async {
+            while mock.record(QUEUE_COLLECTION, item).unwrap()["status"] != status {
+                sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), reached)
+            .await
+            .unwrap_or_else(|_| panic!("the item never became {}", status));
+    }
+
+    /// The queue updates `updates` has had for `task_id`
+    fn updates_for(updates: &mut broadcast::Receiver<QueueUpdate>, task_id: Uuid) -> Vec<String> {
+        let mut seen = Vec::new();
+        while let Ok(update) = updates.try_recv() {
+            if update.task_id == Some(task_id) {
+                seen.push(format!("{:?}", update.update_type));
+            }
+        }
+        seen
+    }
+
+    #[tokio::test]
+    async fn test_a_task_cancelled_mid_download_stops_and_leaves_nothing_behind() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        mock.patch(QUEUE_COLLECTION, &item, json!({ "meeting_id": "42" }));
+        let mail = Arc::new(CapturedMail::default());
+        let results = Arc::new(CapturedResults::default());
+        let mut context = Arc::into_inner(context(&mock, stalling_pipeline(&mock, |_| {}).await)).unwrap();
+        context.mailer = mail.clone();
+        context.results = results.clone();
+        let context = Arc::new(context);
+        let mut updates = context.broadcast_service.subscribe();
+        let cancelled = metrics::registry().value(&metrics::TASKS_CANCELLED, &[]);
+        let (stop, shutdown) = Shutdown::channel();
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown, GRACE));
+
+        let task_id = task_id_of(&mock, &item);
+        let dir = task_dir(&context.pipeline.config.worker.scratch_dir, task_id);
+        while !dir.join("recording.download").exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        mock.patch(QUEUE_COLLECTION, &item, json!({ "cancel_requested": true }));
+        let asked = std::time::Instant::now();
+        status_becomes(&mock, &item, "Cancelled").await;
+        assert!(asked.elapsed() < Duration::from_secs(1), "took {:?}", asked.elapsed());
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+
+        assert!(!dir.exists(), "the partial download is removed");
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!(record["checkpoint"], Value::Null, "the metadata checked in is dropped");
+        assert_eq!(record["retry_count"], 0);
+        assert_eq!(updates_for(&mut updates, task_id), ["TaskStarted", "TaskCancelled"]);
+        assert!(mail.sent.lock().unwrap().is_empty(), "no email for a cancelled task");
+        let results = results.0.lock().unwrap();
+        assert_eq!(results[0].2.status, ResultStatus::Cancelled);
+        assert_eq!(results[0].2.error.as_deref(), Some("The task was cancelled"));
+        assert!(metrics::registry().value(&metrics::TASKS_CANCELLED, &[]) >= cancelled + 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_a_cancel_broadcast_stops_a_task_before_its_next_stage() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let gate = Arc::new(Semaphore::new(0));
+        let context = context(&mock, Instrumented { gate: Some(gate.clone()), stages: true, ..Default::default() });
+        let (stop, shutdown) = Shutdown::channel();
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown, GRACE));
+        while context.pipeline.running.load(Ordering::SeqCst) < 1 {
+            sleep(Duration::from_millis(5)).await;
+        }
+
+        let task_id = task_id_of(&mock, &item);
+        let task = QueueTask { id: task_id, user_id: "alice".to_string(), ..Default::default() };
+        broadcast(&context.broadcast_service, QueueUpdateType::TaskCancelled, &task, None).await;
+        status_becomes(&mock, &item, "Cancelled").await;
+        // Letting the first stage finish now changes nothing
+        gate.add_permits(1);
+        sleep(Duration::from_millis(50)).await;
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+
+        assert_eq!(context.pipeline.finished.load(Ordering::SeqCst), 0);
+        assert_eq!(mock.record(QUEUE_COLLECTION, &item).unwrap()["status"], "Cancelled");
+        assert_eq!(mock.records(CLAIMS_COLLECTION).len(), 1);
+    }
+
+    /// Finishes at once, its owner asking for it to be cancelled as it does
+    struct CancelledAsItCompletes(MockPb);
+
+    impl Pipeline for CancelledAsItCompletes {
+        async fn run(&self, task: &QueueTask, _shutdown: &Shutdown, _measurements: &Measurements) -> WorkerResult<Delivery> {
+            self.0.patch(QUEUE_COLLECTION, &task.record_id, json!({ "cancel_requested": true }));
+            Ok(Delivery::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_cancellation_racing_completion_leaves_the_task_completed() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "standup", 0);
+        let context = context(&mock, CancelledAsItCompletes(mock.clone()));
+        let mut updates = context.broadcast_service.subscribe();
+        let mut completions = context.broadcast_service.subscribe();
+        let (stop, shutdown) = Shutdown::channel();
+        let pool = tokio::spawn(run_pool(context.clone(), 1, shutdown, GRACE));
+
+        status_becomes(&mock, &item, "Completed").await;
+        while !matches!(completions.recv().await.unwrap().update_type, QueueUpdateType::TaskCompleted) {}
+        // A cancellation after that is no longer looked for
+        let task_id = task_id_of(&mock, &item);
+        let task = QueueTask { id: task_id, ..Default::default() };
+        broadcast(&context.broadcast_service, QueueUpdateType::TaskCancelled, &task, None).await;
+        sleep(Duration::from_millis(100)).await;
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+
+        assert_eq!(mock.record(QUEUE_COLLECTION, &item).unwrap()["status"], "Completed");
+        assert_eq!(updates_for(&mut updates, task_id), ["TaskStarted", "TaskCompleted", "TaskCancelled"]);
+    }
+
+    /// Peers for tasks for recording 42 that were stopped before their upload
+    struct Interrupted {
+        mock: MockPb,
+        media: MockMedia,
+        loom: MockLoom,
+        backend: BackendConfig,
+        fathom: String,
+    }
+
+    impl Interrupted {
+        async fn start() -> Self {
+            let media = MockMedia::start(vec![6; 2500], 0, 0).await;
+            Self {
+                mock: queue_pb().await,
+                fathom: mock_fathom_with(&media.url).await,
+                media,
+                loom: MockLoom::start().await,
+                backend: mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await,
+            }
+        }
+
+        fn pipeline(&self, fathom_url: &str, loom_url: &str) -> FathomToLoom {
+            FathomToLoom {
+                config: worker_config(database(&self.mock), self.backend.clone(), fathom_url, loom_url),
+                client: reqwest::Client::new(),
+                pb: self.mock.client(),
+                broadcast_service: BroadcastServiceFactory::create_shared(64),
+                fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+            }
+        }
+
+        /// A task whose attempt with Loom down checked in every stage before
+        /// the upload, as its next claim reads it
+        async fn task(&self) -> QueueTask {
+            let record_id = self.mock.insert(
+                QUEUE_COLLECTION,
+                json!({
+                    "task_id": Uuid::new_v4().to_string(),
+                    "user_id": "alice",
+                    "meeting_id": "42",
+                    "status": "InProgress",
+                    "max_retries": 3,
+                    "created": "2026-03-01 09:00:00.000Z",
+                    "updated": "2026-03-01 09:00:00.000Z",
+                }),
+            );
+            let task = QueueTask::from_record(&self.mock.record(QUEUE_COLLECTION, &record_id).unwrap()).unwrap();
+            let down = self.pipeline(&self.fathom, "http://127.0.0.1:9");
+            assert!(down.run(&task, &Shutdown::channel().1, &Measurements::default()).await.unwrap_err().is_retryable());
+            QueueTask::from_record(&self.mock.record(QUEUE_COLLECTION, &record_id).unwrap()).unwrap()
+        }
+    }
+
+    fn stages_run(measurements: &Measurements) -> Vec<String> {
+        measurements.snapshot().stages.into_iter().map(|timing| timing.stage).collect()
+    }
+
+    #[tokio::test]
+    async fn test_an_interrupted_task_skips_the_stages_it_finished() {
+        let interrupted = Interrupted::start().await;
+        let shutdown = Shutdown::channel().1;
+
+        for (finished, ran) in [
+            ("metadata", vec!["metadata", "download", "transcode", "upload"]),
+            ("download", vec!["transcode", "upload"]),
+            ("transcode", vec!["upload"]),
+        ] {
+            let mut task = interrupted.task().await;
+            assert_eq!(task.checkpoint.stages(), ["metadata", "download", "transcode"]);
+            // As a crash right after `finished` would have left it
+            match finished {
+                "metadata" => {
+                    task.checkpoint.download = None;
+                    task.checkpoint.transcode = None;
+                }
+                "download" => task.checkpoint.transcode = None,
+                _ => {}
+            }
+            let fetched = interrupted.media.ranges().len();
+
+            // Fathom is only asked again for a fresh media URL
+            let fathom = if finished == "metadata" { interrupted.fathom.as_str() } else { "http://127.0.0.1:9" };
+            let measurements = Measurements::default();
+            let share_url = interrupted
+                .pipeline(fathom, &interrupted.loom.url)
+                .run(&task, &shutdown, &measurements)
+                .await
+                .unwrap()
+                .share_url;
+            assert!(share_url.is_some(), "after {}", finished);
+            assert_eq!(stages_run(&measurements), ran, "after {}", finished);
+            let ranges = interrupted.media.ranges();
+            match finished {
+                // What was on disk was asked for after, and found whole
+                "metadata" => assert_eq!(ranges[fetched..], [Some("bytes=2500-".to_string())]),
+                _ => assert_eq!(ranges.len(), fetched, "after {}", finished),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_changed_download_sends_the_task_back_to_the_start() {
+        let interrupted = Interrupted::start().await;
+        let mut task = interrupted.task().await;
+        let pipeline = interrupted.pipeline(&interrupted.fathom, &interrupted.loom.url);
+        let dir = task_dir(&pipeline.config.worker.scratch_dir, task.id);
+        std::fs::write(dir.join("recording.download"), vec![9; 2500]).unwrap();
+        // A session that was sending the file as it was
+        task.upload_session_id = Some("upload-stale".to_string());
+        task.upload_offset = 1000;
+        let fetched = interrupted.media.ranges().len();
+
+        let measurements = Measurements::default();
+        let share_url = pipeline.run(&task, &Shutdown::channel().1, &measurements).await.unwrap().share_url.unwrap();
+        assert_eq!(stages_run(&measurements), ["metadata", "download", "transcode", "upload"]);
+        assert_eq!(interrupted.media.ranges()[fetched..], [None], "downloaded afresh");
+        let upload_id = share_url.rsplit('/').next().unwrap().replace("video-", "upload-");
+        assert_eq!(interrupted.loom.uploaded(&upload_id), vec![6; 2500]);
+        assert_eq!(interrupted.loom.sessions(), 1, "the stale session isn't resumed");
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_completing_a_task_clears_its_checkpoint() {
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "weekly", 1);
+        let pb = mock.client();
+        let leftover = json!({
+            "checkpoint": { "metadata": { "title": "Weekly sync", "duration": 60, "size_bytes": null, "sha256": null, "transcript_available": false } },
+            "upload_session_id": "upload-1",
+            "upload_offset": 1000,
+        });
+        pb.update_record(QUEUE_COLLECTION, &item, &leftover).await.unwrap();
+        let context = context(&mock, Instrumented::default());
+        let task = claimed_task(&mock, &item);
+        assert_eq!(task.checkpoint.stages(), ["metadata"]);
+
+        run_task(&context, &task, &Shutdown::channel().1).await.unwrap();
+        let record = mock.record(QUEUE_COLLECTION, &item).unwrap();
+        assert_eq!(record["status"], "Completed");
+        assert!(record["checkpoint"].is_null());
+        assert_eq!((&record["upload_session_id"], &record["upload_offset"]), (&json!(""), &json!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_a_task_never_logs_its_keys() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+
+        let media = MockMedia::start(vec![5; 2500], 0, 0).await;
+        let loom = MockLoom::start().await;
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let mock = queue_pb().await;
+        let item = queue_item(&mock, "weekly", 1);
+        let pb = mock.client();
+        pb.update_record(QUEUE_COLLECTION, &item, &json!({ "meeting_id": "42" })).await.unwrap();
+        let config = worker_config(database(&mock), backend, &mock_fathom_with(&media.url).await, &loom.url);
+        let context = Arc::new(TaskContext {
+            pb,
+            worker_id: "worker-a".to_string(),
+            poll_interval: Duration::from_millis(20),
+            disk: roomy_disk(),
+            fairness: Fairness::fifo(),
+            job_types: vec![(JobType::ProcessMeeting, 16)],
+            pipeline: FathomToLoom {
+                config,
+                client: reqwest::Client::new(),
+                pb: mock.client(),
+                broadcast_service: BroadcastServiceFactory::create_shared(64),
+                fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+            },
+            broadcast_service: BroadcastServiceFactory::create_shared(64),
+            email: email_config("http://127.0.0.1:9"),
+            retry: RetryPolicy {
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(60),
+            },
+            clock: Arc::new(SystemClock),
+            mailer: Arc::new(LogMailer),
+            results: Arc::new(LogResults),
+        });
+        let task = claimed_task(&mock, &item);
+
+        supervise(&context, task, Shutdown::channel().1, CancellationToken::new()).await;
+        assert_eq!(mock.record(QUEUE_COLLECTION, &item).unwrap()["status"], "Completed");
+
+        let logs = logs.text();
+        assert!(logs.contains("task{task_id="), "{}", logs);
+        assert!(logs.contains("Uploaded \"Weekly sync\" to Loom"), "{}", logs);
+        for key in [FATHOM_KEY, LOOM_KEY] {
+            assert!(!logs.contains(key), "{}", logs);
+        }
+    }
 }
@@ -0,0 +1,237 @@
+//! Pacing of the worker's calls to Fathom
+//!
+//! Fathom limits requests per account, and the backend's interactive
+//! listings share that account with every task the worker runs. One
+//! [`TokenBucket`] per process paces them all: it holds up to `burst`
+//! tokens, refilled at `requests_per_minute`, and each call waits until
+//! there is one to take. Waits are timed in `worker_fathom_wait_seconds`.
+//!
+//! When Fathom answers 429 or 503 with a `Retry-After`, the bucket is
+//! [drained](TokenBucket::drain) until then, so every task backs off rather
+//! than only the one that was told. Calls that keep waiting for a minute
+//! straight mean the rate is too low for the work queued, and are warned of.
+
+use std::time::Duration;
+use tokio::{sync::Mutex, time::Instant};
+use tracing::warn;
+
+use crate::metrics;
+
+/// How long every call must have waited before saturation is warned of, and
+/// how often the warning is repeated while it lasts
+pub const SATURATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Longest a `Retry-After` is taken at its word
+pub const MAX_DRAIN: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+struct State {
+    /// Tokens as of `refilled_at`
+    tokens: f64,
+    /// Never earlier than `paused_until`: no tokens come in before then
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+    /// Since when every call has had to wait
+    saturated_since: Option<Instant>,
+    warned_at: Option<Instant>,
+}
+
+/// Tokens for calls to Fathom, shared by every task in the process
+#[derive(Debug)]
+pub struct TokenBucket {
+    requests_per_minute: u32,
+    burst: f64,
+    per_second: f64,
+    state: Mutex<State>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `burst` tokens, refilled at `requests_per_minute`
+    pub fn new(requests_per_minute: u32, burst: u32) -> Self {
+        let requests_per_minute = requests_per_minute.max(1);
+        let burst = burst.max(1) as f64;
+        Self {
+            requests_per_minute,
+            burst,
+            per_second: requests_per_minute as f64 / 60.0,
+            state: Mutex::new(State {
+                tokens: burst,
+                refilled_at: Instant::now(),
+                paused_until: None,
+                saturated_since: None,
+                warned_at: None,
+            }),
+        }
+    }
+
+    /// Wait for a token, returning how long that took
+    pub async fn acquire(&self) -> Duration {
+        let asked = Instant::now();
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+                self.refill(&mut state, now);
+                match state.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if state.tokens >= 1.0 => {
+                        state.tokens -= 1.0;
+                        let waited = now.duration_since(asked);
+                        self.note_wait(&mut state, asked, waited);
+                        metrics::observe(&metrics::FATHOM_WAIT, &[], waited.as_secs_f64());
+                        return waited;
+                    }
+                    _ => Duration::from_secs_f64((1.0 - state.tokens) / self.per_second),
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Hand out no tokens for `retry_after`, as Fathom asked, and start
+    /// refilling from empty after that
+    pub async fn drain(&self, retry_after: Duration) {
+        let now = Instant::now();
+        let until = now + retry_after.min(MAX_DRAIN);
+        let mut state = self.state.lock().await;
+        self.refill(&mut state, now);
+        if state.paused_until.is_some_and(|paused| paused >= until) {
+            return;
+        }
+        warn!(
+            "Fathom asked the worker to wait {}s; holding every Fathom call until then",
+            retry_after.as_secs()
+        );
+        state.tokens = state.tokens.min(0.0);
+        state.refilled_at = until;
+        state.paused_until = Some(until);
+    }
+
+    /// How long from now calls are held by a drain
+    pub async fn paused_for(&self) -> Option<Duration> {
+        let paused_until = self.state.lock().await.paused_until?;
+        Some(paused_until.saturating_duration_since(Instant::now())).filter(|left| !left.is_zero())
+    }
+
+    fn refill(&self, state: &mut State, now: Instant) {
+        // Nothing comes in while drained; refilled_at is then in the future
+        if now <= state.refilled_at {
+            return;
+        }
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.per_second).min(self.burst);
+        state.refilled_at = now;
+    }
+
+    /// Track whether calls keep having to wait, warning once it has gone on
+    /// for a [`SATURATION_WINDOW`] and again each window after
+    fn note_wait(&self, state: &mut State, asked: Instant, waited: Duration) {
+        if waited.is_zero() {
+            state.saturated_since = None;
+            return;
+        }
+        let now = asked + waited;
+        let since = *state.saturated_since.get_or_insert(asked);
+        let warned_lately = state.warned_at.is_some_and(|at| now.duration_since(at) < SATURATION_WINDOW);
+        if now.duration_since(since) >= SATURATION_WINDOW && !warned_lately {
+            warn!(
+                "Every Fathom call has waited for the rate limit for {}s, the last for {:.1}s, at {} requests per minute",
+                now.duration_since(since).as_secs(),
+                waited.as_secs_f64(),
+                self.requests_per_minute
+            );
+            state.warned_at = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::CapturedLogs;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_tasks_share_the_rate() {
+        let bucket = Arc::new(TokenBucket::new(60, 3));
+        let start = Instant::now();
+        let calls: Vec<_> = (0..8)
+            .map(|_| {
+                let bucket = bucket.clone();
+                tokio::spawn(async move {
+                    bucket.acquire().await;
+                    start.elapsed()
+                })
+            })
+            .collect();
+        let mut at = Vec::new();
+        for call in calls {
+            at.push(call.await.unwrap().as_secs());
+        }
+        at.sort();
+        // The burst goes at once, then one a second
+        assert_eq!(at, vec![0, 0, 0, 1, 2, 3, 4, 5]);
+
+        let waits = metrics::registry().value(&metrics::FATHOM_WAIT, &[]);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(bucket.acquire().await.is_zero(), "an idle bucket fills up again");
+        assert!(metrics::registry().value(&metrics::FATHOM_WAIT, &[]) >= waits + 1.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_retry_after_holds_back_every_call() {
+        let bucket = Arc::new(TokenBucket::new(60, 1));
+        bucket.acquire().await;
+        // A call already waiting on its token is held too
+        let waiting = {
+            let bucket = bucket.clone();
+            tokio::spawn(async move { bucket.acquire().await })
+        };
+        tokio::task::yield_now().await;
+
+        bucket.drain(Duration::from_secs(30)).await;
+        assert_eq!(bucket.paused_for().await, Some(Duration::from_secs(30)));
+        assert_eq!(waiting.await.unwrap(), Duration::from_secs(31));
+        // The bucket is empty once the wait is over, so calls are paced
+        assert_eq!(bucket.acquire().await, Duration::from_secs(1));
+        assert_eq!(bucket.paused_for().await, None);
+
+        // A shorter wait doesn't cut a longer one short
+        bucket.drain(Duration::from_secs(20)).await;
+        bucket.drain(Duration::from_secs(5)).await;
+        assert_eq!(bucket.paused_for().await, Some(Duration::from_secs(20)));
+        bucket.drain(Duration::from_secs(24 * 60 * 60)).await;
+        assert_eq!(bucket.paused_for().await, Some(MAX_DRAIN));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sustained_saturation_is_warned_of() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.install();
+        let bucket = TokenBucket::new(60, 1);
+        let warnings = || logs.text().matches("Every Fathom call has waited").count();
+
+        // Thirty seconds of calls as fast as they can go isn't yet sustained
+        for _ in 0..31 {
+            bucket.acquire().await;
+        }
+        assert_eq!(warnings(), 0);
+        for _ in 0..40 {
+            bucket.acquire().await;
+        }
+        assert_eq!(warnings(), 1, "{}", logs.text());
+        assert!(logs.text().contains("at 60 requests per minute"), "{}", logs.text());
+
+        // It's repeated while it lasts, and starts over once a call goes through
+        for _ in 0..60 {
+            bucket.acquire().await;
+        }
+        assert_eq!(warnings(), 2);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        bucket.acquire().await;
+        for _ in 0..30 {
+            bucket.acquire().await;
+        }
+        assert_eq!(warnings(), 2);
+    }
+}
@@ -0,0 +1,226 @@
+//! How long each attempt at a task took, and where the time went
+//!
+//! A running attempt notes in its [`Measurements`] when each stage started
+//! and how long it ran, the bytes it moved and the length of recording it
+//! transcoded. Once the attempt ends these become its [`TaskReport`], with
+//! the throughput of the transfers and the speed of the transcode worked
+//! out. The report is logged as one `task_report` event, kept with the
+//! task's processing result, and summed up in the email to its owner.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+use tracing::info;
+
+use crate::results::ResultStatus;
+
+/// When one stage of an attempt ran, however it ended
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub seconds: f64,
+}
+
+/// What an attempt measured as it ran
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measured {
+    pub stages: Vec<StageTiming>,
+    /// Bytes downloaded by this attempt, not counting what it resumed from
+    pub downloaded: Option<u64>,
+    /// Bytes uploaded by this attempt, not counting what it resumed from
+    pub uploaded: Option<u64>,
+    /// Seconds of recording re-encoded
+    pub transcoded: Option<f64>,
+}
+
+/// Where a running attempt notes what it measured
+#[derive(Debug, Default)]
+pub struct Measurements(Mutex<Measured>);
+
+impl Measurements {
+    fn measured(&self) -> std::sync::MutexGuard<'_, Measured> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Note that `stage` started at `started_at` and ran for `took`
+    pub fn stage(&self, stage: &str, started_at: DateTime<Utc>, took: Duration) {
+        let finished_at = started_at + chrono::Duration::from_std(took).unwrap_or_default();
+        self.measured().stages.push(StageTiming {
+            stage: stage.to_string(),
+            started_at,
+            finished_at,
+            seconds: took.as_secs_f64(),
+        });
+    }
+
+    pub fn downloaded(&self, bytes: u64) {
+        self.measured().downloaded = Some(bytes);
+    }
+
+    pub fn uploaded(&self, bytes: u64) {
+        self.measured().uploaded = Some(bytes);
+    }
+
+    /// Note that `seconds` of recording were re-encoded
+    pub fn transcoded(&self, seconds: f64) {
+        self.measured().transcoded = Some(seconds);
+    }
+
+    /// Everything noted so far
+    pub fn snapshot(&self) -> Measured {
+        self.measured().clone()
+    }
+}
+
+/// How one attempt at a task went, stage by stage
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskReport {
+    pub outcome: ResultStatus,
+    /// Retries before this attempt
+    pub retry_count: u32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub seconds: f64,
+    /// Every stage the attempt started, the one it failed in included
+    pub stages: Vec<StageTiming>,
+    pub download_bytes: Option<u64>,
+    pub download_mb_per_sec: Option<f64>,
+    pub upload_bytes: Option<u64>,
+    pub upload_mb_per_sec: Option<f64>,
+    /// Seconds of recording re-encoded per second spent transcoding
+    pub transcode_speed: Option<f64>,
+}
+
+impl TaskReport {
+    /// The report of an attempt that ran from `started_at` to `finished_at`
+    pub fn new(
+        measured: Measured,
+        outcome: ResultStatus,
+        retry_count: u32,
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+    ) -> Self {
+        let seconds_in = |stage: &str| {
+            measured
+                .stages
+                .iter()
+                .rev()
+                .find(|timing| timing.stage == stage)
+                .map(|timing| timing.seconds)
+                .filter(|seconds| *seconds > 0.0)
+        };
+        let rate = |bytes: Option<u64>, stage: &str| Some(bytes? as f64 / 1_000_000.0 / seconds_in(stage)?);
+        Self {
+            outcome,
+            retry_count,
+            started_at,
+            finished_at,
+            seconds: (finished_at - started_at).to_std().unwrap_or_default().as_secs_f64(),
+            download_mb_per_sec: rate(measured.downloaded, "download"),
+            upload_mb_per_sec: rate(measured.uploaded, "upload"),
+            transcode_speed: measured.transcoded.zip(seconds_in("transcode")).map(|(media, took)| media / took),
+            download_bytes: measured.downloaded,
+            upload_bytes: measured.uploaded,
+            stages: measured.stages,
+        }
+    }
+
+    /// The stages with their times and speeds, and the attempt's total, on
+    /// one line
+    pub fn summary(&self) -> String {
+        let stages: Vec<String> = self
+            .stages
+            .iter()
+            .map(|timing| {
+                let took = format_seconds(timing.seconds);
+                let transfer = |bytes: Option<u64>, rate: Option<f64>| match (bytes, rate) {
+                    (Some(bytes), Some(rate)) => {
+                        format!("{} {} ({:.1} MB at {:.1} MB/s)", timing.stage, took, bytes as f64 / 1_000_000.0, rate)
+                    }
+                    _ => format!("{} {}", timing.stage, took),
+                };
+                match timing.stage.as_str() {
+                    "download" => transfer(self.download_bytes, self.download_mb_per_sec),
+                    "upload" => transfer(self.upload_bytes, self.upload_mb_per_sec),
+                    "transcode" if self.transcode_speed.is_some() => {
+                        format!("transcode {} ({:.1}x real time)", took, self.transcode_speed.unwrap_or_default())
+                    }
+                    _ => format!("{} {}", timing.stage, took),
+                }
+            })
+            .collect();
+        match stages.is_empty() {
+            true => format!("none started, {} in all", format_seconds(self.seconds)),
+            false => format!("{}; {} in all", stages.join(", "), format_seconds(self.seconds)),
+        }
+    }
+
+    /// Log the report as one structured event
+    pub fn log(&self) {
+        let report = serde_json::to_string(self).unwrap_or_default();
+        info!(target: "task_report", outcome = ?self.outcome, seconds = self.seconds, report = %report, "Task report: {}", self.summary());
+    }
+}
+
+/// `seconds` as `4.2s`, or `3m 05s` from a minute up
+fn format_seconds(seconds: f64) -> String {
+    match seconds < 60.0 {
+        true => format!("{:.1}s", seconds),
+        false => {
+            let whole = seconds.round() as u64;
+            format!("{}m {:02}s", whole / 60, whole % 60)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(second: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-03-01T09:00:00Z").unwrap().with_timezone(&Utc) + chrono::Duration::seconds(second)
+    }
+
+    #[test]
+    fn test_reports_work_out_throughput_and_transcode_speed() {
+        let measurements = Measurements::default();
+        measurements.stage("metadata", at(0), Duration::from_secs(1));
+        measurements.stage("download", at(1), Duration::from_secs(40));
+        measurements.downloaded(734_000_000);
+        measurements.stage("transcode", at(41), Duration::from_secs(20));
+        measurements.transcoded(2730.0);
+        measurements.stage("upload", at(61), Duration::from_millis(12_500));
+        measurements.uploaded(500_000_000);
+
+        let report = TaskReport::new(measurements.snapshot(), ResultStatus::Completed, 1, at(0), at(75));
+        assert_eq!(report.seconds, 75.0);
+        assert_eq!(report.stages[1].finished_at, at(41));
+        assert_eq!(report.download_mb_per_sec, Some(18.35));
+        assert_eq!(report.upload_mb_per_sec, Some(40.0));
+        assert_eq!(report.transcode_speed, Some(136.5));
+        assert_eq!(
+            report.summary(),
+            "metadata 1.0s, download 40.0s (734.0 MB at 18.4 MB/s), transcode 20.0s (136.5x real time), \
+             upload 12.5s (500.0 MB at 40.0 MB/s); 1m 15s in all"
+        );
+    }
+
+    #[test]
+    fn test_a_report_cut_short_has_only_what_was_measured() {
+        let measurements = Measurements::default();
+        measurements.stage("metadata", at(0), Duration::from_secs(2));
+        // A download that failed before a byte was counted
+        measurements.stage("download", at(2), Duration::from_secs(3));
+
+        let report = TaskReport::new(measurements.snapshot(), ResultStatus::Retrying, 0, at(0), at(5));
+        assert_eq!(report.stages.len(), 2);
+        assert_eq!((report.download_bytes, report.download_mb_per_sec), (None, None));
+        assert_eq!((report.upload_mb_per_sec, report.transcode_speed), (None, None));
+        assert_eq!(report.summary(), "metadata 2.0s, download 3.0s; 5.0s in all");
+
+        let report = TaskReport::new(Measured::default(), ResultStatus::Failed, 0, at(0), at(0));
+        assert_eq!(report.summary(), "none started, 0.0s in all");
+    }
+}
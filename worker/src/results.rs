@@ -0,0 +1,95 @@
+//! What became of each attempt at a task, kept in its owner's instance
+//!
+//! Once an attempt's outcome is recorded on the queue item, it goes with the
+//! attempt's [`TaskReport`] to the backend as a [`ProcessingResult`]. The
+//! backend keeps one `processing_results` record per task in the user's
+//! PocketBase instance: the first attempt creates it and each retry replaces
+//! it, so it always tells how the latest attempt went.
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{config::BackendConfig, keys, report::TaskReport, WorkerResult};
+
+/// How an attempt at a task ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    Completed,
+    /// It failed and the task went back to the queue for another attempt
+    Retrying,
+    /// It failed for good
+    Failed,
+    /// It was stopped at shutdown and the task returned to the queue
+    Interrupted,
+    /// Its owner asked for it to stop, and the task ended there
+    Cancelled,
+}
+
+/// Body of `PUT /internal/users/:user_id/processing_results/:task_id`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessingResult {
+    pub meeting_id: String,
+    pub status: ResultStatus,
+    /// Which attempt at the task this was, counting from 1
+    pub attempt: u32,
+    /// The Loom page of the video, once completed
+    pub loom_url: Option<String>,
+    /// What went wrong, as shown to the user, unless completed
+    pub error: Option<String>,
+    pub finished_at: DateTime<Utc>,
+    pub report: TaskReport,
+}
+
+/// Keeps the result of each attempt at a task, by task id
+///
+/// [`BackendResults`] is the real one.
+pub trait ResultStore: Send + Sync {
+    fn store<'a>(&'a self, user_id: &'a str, task_id: Uuid, result: &'a ProcessingResult) -> BoxFuture<'a, WorkerResult<()>>;
+}
+
+/// Logs results instead of keeping them
+pub struct LogResults;
+
+impl ResultStore for LogResults {
+    fn store<'a>(&'a self, _user_id: &'a str, _task_id: Uuid, result: &'a ProcessingResult) -> BoxFuture<'a, WorkerResult<()>> {
+        info!("Attempt {} ended {:?}", result.attempt, result.status);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Stores results in their owners' instances through the backend
+pub struct BackendResults {
+    pub client: reqwest::Client,
+    pub backend: BackendConfig,
+}
+
+impl BackendResults {
+    /// Create or replace the record of `task_id` in `user_id`'s instance
+    pub async fn put(&self, user_id: &str, task_id: Uuid, result: &ProcessingResult) -> WorkerResult<()> {
+        let token = keys::internal_token(&self.backend)?;
+        let url = format!(
+            "{}/internal/users/{}/processing_results/{}",
+            self.backend.url.trim_end_matches('/'),
+            user_id,
+            task_id
+        );
+        self.client
+            .put(url)
+            .bearer_auth(token)
+            .json(result)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl ResultStore for BackendResults {
+    fn store<'a>(&'a self, user_id: &'a str, task_id: Uuid, result: &'a ProcessingResult) -> BoxFuture<'a, WorkerResult<()>> {
+        Box::pin(self.put(user_id, task_id, result))
+    }
+}
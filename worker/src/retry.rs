@@ -0,0 +1,60 @@
+//! When a failed task is tried again
+//!
+//! A retryable failure puts the task back in the queue with a
+//! `next_attempt_at` that doubles with each retry up to a ceiling. The wait is
+//! jittered between half and all of that, so tasks failing together against
+//! the same outage don't all come back at the same moment.
+
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Wait before the first retry, before jitter
+    pub base_delay: Duration,
+    /// Longest wait before any retry, before jitter
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The un-jittered wait before retry number `retry` (counting from 1)
+    pub fn ceiling(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31);
+        self.base_delay
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// How long to wait before retry number `retry`: somewhere between half
+    /// of its [`ceiling`](Self::ceiling) and all of it
+    pub fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self.ceiling(retry);
+        let half = ceiling / 2;
+        half + (ceiling - half).mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delays_double_up_to_the_ceiling_with_jitter() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(300),
+        };
+        let ceilings: Vec<u64> = (1..=6).map(|retry| policy.ceiling(retry).as_secs()).collect();
+        assert_eq!(ceilings, [30, 60, 120, 240, 300, 300]);
+        assert_eq!(policy.ceiling(u32::MAX), Duration::from_secs(300));
+
+        for retry in 1..=6 {
+            let ceiling = policy.ceiling(retry);
+            for _ in 0..50 {
+                let delay = policy.delay(retry);
+                assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?} outside {:?}", delay, ceiling);
+            }
+        }
+    }
+}
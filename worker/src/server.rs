@@ -0,0 +1,295 @@
+//! The worker's health and metrics server
+//!
+//! A small HTTP server on `WORKER_HTTP_PORT` for the orchestrator and
+//! Prometheus, as the backend has: `/health/live` only says the process
+//! answers, `/health/ready` that PocketBase takes the worker's credentials
+//! and its configuration can reach the backend, and `/metrics` renders
+//! [`metrics`]. It is started before the claim loop and stopped after it.
+//!
+//! With a [`DrainSwitch`], `POST /drain` also starts draining the worker,
+//! for `worker drain`; it takes the `INTERNAL_API_TOKEN` as a bearer token,
+//! and is refused without it.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use common::{health::CompositeHealth, metrics::EXPOSITION_CONTENT_TYPE};
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+use tracing::{error, info};
+
+use crate::{metrics, pocketbase::PocketBase, WorkerConfig};
+
+/// Longest any one readiness check may take
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The checks behind `/health/ready`
+pub fn readiness(config: &WorkerConfig) -> CompositeHealth {
+    let pb = Arc::new(PocketBase::from(&config.database));
+    let valid = config.validate();
+    CompositeHealth::new()
+        .required("pocketbase", CHECK_TIMEOUT, move || {
+            let pb = pb.clone();
+            async move { pb.ping().await.map_err(|e| e.to_string()) }
+        })
+        .required("config", CHECK_TIMEOUT, move || {
+            let valid = valid.clone();
+            async move { valid }
+        })
+}
+
+/// What `POST /drain` raises, and the token its caller has to give
+#[derive(Clone)]
+pub struct DrainSwitch {
+    pub token: String,
+    pub drain: Arc<watch::Sender<bool>>,
+}
+
+pub fn router(readiness: CompositeHealth, drain: Option<DrainSwitch>) -> Router {
+    let router = Router::new()
+        .route("/health/live", get(get_live))
+        .route("/health/ready", get(get_ready))
+        .route("/metrics", get(get_metrics))
+        .with_state(Arc::new(readiness));
+    match drain {
+        Some(drain) => router.merge(Router::new().route("/drain", post(post_drain)).with_state(drain)),
+        None => router,
+    }
+}
+
+/// GET /health/live - the process is up
+async fn get_live() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// GET /health/ready - 200 when every required check passes, else 503
+async fn get_ready(State(readiness): State<Arc<CompositeHealth>>) -> Response {
+    let report = readiness.check().await;
+    let status = if report.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report)).into_response()
+}
+
+/// GET /metrics - every metric family in the Prometheus text format
+async fn get_metrics() -> Response {
+    ([(header::CONTENT_TYPE, EXPOSITION_CONTENT_TYPE)], metrics::render()).into_response()
+}
+
+/// POST /drain - claim nothing more, and stop once the running tasks finish
+async fn post_drain(State(switch): State<DrainSwitch>, headers: HeaderMap) -> Response {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer != Some(switch.token.as_str()) {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "invalid_internal_token" }))).into_response();
+    }
+    if !switch.drain.send_replace(true) {
+        info!("Drain requested");
+    }
+    (StatusCode::ACCEPTED, Json(json!({ "draining": true }))).into_response()
+}
+
+/// The running server, stopped with [`Server::stop`]
+pub struct Server {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Server {
+    /// Serve on `port` of every interface, any free one for 0
+    pub async fn start(port: u16, readiness: CompositeHealth, drain: Option<DrainSwitch>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        let addr = listener.local_addr()?;
+        let (stop, stopping) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let serve = axum::serve(listener, router(readiness, drain)).with_graceful_shutdown(async {
+                let _ = stopping.await;
+            });
+            if let Err(e) = serve.await {
+                error!("Health and metrics server failed: {}", e);
+            }
+        });
+        info!("Health and metrics server listening on {}", addr);
+        Ok(Self { addr, stop, task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fairness::Fairness,
+        queue::{self, FathomToLoom, TaskContext, CLAIMS_COLLECTION, QUEUE_COLLECTION},
+        rate_limit::TokenBucket,
+        results::LogResults,
+        retry::RetryPolicy,
+        shutdown::Shutdown,
+        test_support::{database, mock_backend, mock_fathom_with, roomy_disk, worker_config, MockLoom, MockMedia, MockPb, FATHOM_KEY, LOOM_KEY},
+    };
+    use common::{broadcast::BroadcastServiceFactory, clock::SystemClock, JobType};
+    use serde_json::Value;
+
+    async fn get(server: &Server, path: &str) -> (StatusCode, String) {
+        let response = reqwest::get(format!("http://{}{}", server.local_addr(), path)).await.unwrap();
+        (StatusCode::from_u16(response.status().as_u16()).unwrap(), response.text().await.unwrap())
+    }
+
+    async fn post(server: &Server, path: &str, token: &str) -> u16 {
+        let request = reqwest::Client::new().post(format!("http://{}{}", server.local_addr(), path));
+        request.bearer_auth(token).send().await.unwrap().status().as_u16()
+    }
+
+    /// The value of an unlabelled sample in `text`
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+            .unwrap_or(0.0)
+    }
+
+    #[tokio::test]
+    async fn test_the_endpoints_report_a_task_run_through_the_pipeline() {
+        let media = MockMedia::start(vec![3; 2500], 0, 0).await;
+        let loom = MockLoom::start().await;
+        let backend = mock_backend(&[("alice", "fathom", FATHOM_KEY), ("alice", "loom", LOOM_KEY)]).await;
+        let pb = MockPb::start().await;
+        pb.unique(CLAIMS_COLLECTION, &["item", "version"]);
+        pb.insert(
+            QUEUE_COLLECTION,
+            json!({
+                "task_id": uuid::Uuid::new_v4().to_string(),
+                "user_id": "alice",
+                "meeting_id": "42",
+                "topic": "Weekly sync",
+                "status": "Pending",
+                "retry_count": 0,
+                "max_retries": 3,
+                "version": 0,
+                "created": "2026-03-01 09:00:00.000Z",
+                "updated": "2026-03-01 09:00:00.000Z",
+            }),
+        );
+        let config = worker_config(database(&pb), backend, &mock_fathom_with(&media.url).await, &loom.url);
+        let server = Server::start(0, readiness(&config), None).await.unwrap();
+
+        let (status, body) = get(&server, "/health/live").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, r#"{"status":"ok"}"#));
+        let (status, body) = get(&server, "/health/ready").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (_, before) = get(&server, "/metrics").await;
+
+        let broadcast_service = BroadcastServiceFactory::create_shared(64);
+        let context = Arc::new(TaskContext {
+            pb: pb.client(),
+            worker_id: "worker-a".to_string(),
+            poll_interval: Duration::from_millis(20),
+            disk: roomy_disk(),
+            fairness: Fairness::fifo(),
+            job_types: vec![(JobType::ProcessMeeting, 1)],
+            pipeline: FathomToLoom {
+                config: config.clone(),
+                client: reqwest::Client::new(),
+                pb: pb.client(),
+                broadcast_service: broadcast_service.clone(),
+                fathom_limiter: Arc::new(TokenBucket::new(600, 10)),
+            },
+            broadcast_service,
+            email: config.email.clone(),
+            retry: RetryPolicy {
+                base_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(60),
+            },
+            clock: Arc::new(SystemClock),
+            mailer: Arc::new(queue::LogMailer),
+            results: Arc::new(LogResults),
+        });
+        let (stop, shutdown) = Shutdown::channel();
+        let pool = tokio::spawn(queue::run_pool(context, 1, shutdown, Duration::from_secs(5)));
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while pb.records(QUEUE_COLLECTION)[0]["status"] != "Completed" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the task completes");
+        stop.send(true).unwrap();
+        pool.await.unwrap();
+
+        // Other tests move the same counters, so only growth is certain
+        let (status, after) = get(&server, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        for name in ["worker_tasks_claimed_total", "worker_tasks_completed_total"] {
+            assert!(sample(&after, name) >= sample(&before, name) + 1.0, "{}", name);
+        }
+        for (name, bytes) in [("worker_downloaded_bytes_total", 2500.0), ("worker_uploaded_bytes_total", 2500.0)] {
+            assert!(sample(&after, name) >= sample(&before, name) + bytes, "{}", name);
+        }
+        for stage in ["metadata", "download", "upload"] {
+            let name = format!("worker_stage_duration_seconds_count{{stage=\"{}\"}}", stage);
+            assert!(sample(&after, &name) >= sample(&before, &name) + 1.0, "{}", name);
+        }
+        assert!(after.contains("# TYPE worker_tasks_in_flight gauge\n"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_without_pocketbase_or_a_usable_config() {
+        let pb = MockPb::start().await;
+        let mut config = worker_config(database(&pb), mock_backend(&[]).await, "http://127.0.0.1:9", "not a url");
+        config.database.url = "http://127.0.0.1:1".to_string();
+        let server = Server::start(0, readiness(&config), None).await.unwrap();
+
+        let (status, body) = get(&server, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let report: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["ready"], false);
+        assert_eq!(report["checks"][0]["name"], "pocketbase");
+        assert_eq!(report["checks"][0]["status"], "failed");
+        assert_eq!(report["checks"][1]["name"], "config");
+        assert!(report["checks"][1]["error"].as_str().unwrap().starts_with("LOOM_API_URL is not a URL"));
+
+        // Still alive, so it isn't restarted for its dependencies
+        assert_eq!(get(&server, "/health/live").await.0, StatusCode::OK);
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_a_drain_needs_the_internal_token() {
+        let pb = MockPb::start().await;
+        let config = worker_config(database(&pb), mock_backend(&[]).await, "http://127.0.0.1:9", "http://127.0.0.1:9");
+        let (drain, draining) = Shutdown::channel();
+        let switch = DrainSwitch { token: "drain-token".to_string(), drain: Arc::new(drain) };
+        let server = Server::start(0, readiness(&config), Some(switch)).await.unwrap();
+
+        assert_eq!(post(&server, "/drain", "wrong").await, 401);
+        assert!(!draining.is_requested());
+        assert_eq!(post(&server, "/drain", "drain-token").await, 202);
+        assert!(draining.is_requested());
+        assert_eq!(post(&server, "/drain", "drain-token").await, 202, "asking again is no different");
+        server.stop().await;
+
+        // Without a switch there is nothing to drain
+        let server = Server::start(0, readiness(&config), None).await.unwrap();
+        assert_eq!(post(&server, "/drain", "drain-token").await, 404);
+        server.stop().await;
+    }
+}
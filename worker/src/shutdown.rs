@@ -0,0 +1,84 @@
+//! Stopping the worker without stranding its tasks
+//!
+//! A signal raises a flag that the claim loop stops claiming on and that
+//! pipelines check between stages, returning their task to the queue rather
+//! than starting the next stage.
+
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::{WorkerError, WorkerResult};
+
+/// Whether the worker has been asked to stop
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// A flag raised by sending `true` on the sender
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (stop, stopping) = watch::channel(false);
+        (stop, Self(stopping))
+    }
+
+    /// A flag raised on Ctrl+C or SIGTERM
+    ///
+    /// SIGTERM is listened for before this returns, so one arriving any time
+    /// after can't kill the worker outright.
+    pub fn on_signal() -> Self {
+        let (stop, shutdown) = Self::channel();
+        #[cfg(unix)]
+        let terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .map_err(|e| warn!("Failed to listen for SIGTERM: {}", e))
+            .ok();
+        tokio::spawn(async move {
+            let ctrl_c = async {
+                if let Err(e) = tokio::signal::ctrl_c().await {
+                    warn!("Failed to listen for Ctrl+C: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            };
+
+            #[cfg(unix)]
+            let terminate = async {
+                match terminate {
+                    Some(mut signal) => {
+                        signal.recv().await;
+                    }
+                    None => std::future::pending::<()>().await,
+                }
+            };
+
+            #[cfg(not(unix))]
+            let terminate = std::future::pending::<()>();
+
+            tokio::select! {
+                _ = ctrl_c => {},
+                _ = terminate => {},
+            }
+
+            info!("Shutdown signal received");
+            let _ = stop.send(true);
+        });
+        shutdown
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// A pipeline's check between stages
+    pub fn check(&self) -> WorkerResult<()> {
+        if self.is_requested() {
+            return Err(WorkerError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    /// Resolves once the flag is raised; never, if its sender is dropped first
+    pub async fn requested(&self) {
+        let mut stopping = self.0.clone();
+        if stopping.wait_for(|stopping| *stopping).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
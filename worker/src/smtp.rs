@@ -0,0 +1,327 @@
+//! Emails to users, through the smtp-service
+//!
+//! The worker never talks SMTP itself: like the backend, it hands each
+//! message to the smtp-service's `/send-email` API, with the key the service
+//! gave the worker. A send is tried a few times with a doubling pause
+//! between tries, every try under the same `Idempotency-Key`, so a try that
+//! timed out after the service queued the email doesn't queue it again.
+//! Once several sends in a row have failed the [`CircuitBreaker`] opens:
+//! for a while emails are given up at once, so a down smtp-service holds up
+//! a finished task by a refusal, not by retries.
+
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{config::EmailConfig, queue::Mailer, WorkerError, WorkerResult};
+use futures::future::BoxFuture;
+
+/// How long one request to the smtp-service may take
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a send's first failed try, doubled after each one after
+pub const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Sends failing in a row before the breaker opens
+pub const BREAKER_THRESHOLD: u32 = 5;
+
+/// How long an open breaker gives emails up before trying one again
+pub const BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// A message as accepted by `POST /send-email`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskEmail {
+    pub to_email: String,
+    pub subject: String,
+    pub body_text: String,
+    pub body_html: String,
+}
+
+impl TaskEmail {
+    /// `paragraphs` as the text body, and as escaped `<p>`s with `link` made
+    /// a button of in the HTML one
+    pub fn new(to_email: &str, subject: String, paragraphs: &[String], link: (&str, &str)) -> Self {
+        let (label, url) = link;
+        let mut body_text = paragraphs.join("\n\n");
+        body_text.push_str(&format!("\n\n{}: {}", label, url));
+        let mut body_html: String = paragraphs
+            .iter()
+            .map(|paragraph| format!("<p>{}</p>", escape_html(paragraph).replace('\n', "<br>")))
+            .collect();
+        body_html.push_str(&format!("<p><a href=\"{}\">{}</a></p>", escape_html(url), escape_html(label)));
+        Self {
+            to_email: to_email.to_string(),
+            subject,
+            body_text,
+            body_html,
+        }
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    /// Calls failed since the last success
+    failures: u32,
+    /// Until when calls are given up without trying
+    open_until: Option<Instant>,
+}
+
+/// Stops calling a service that keeps failing, then tries it again now and then
+///
+/// Once the cooldown is over one call goes ahead; its success closes the
+/// breaker, and its failure opens it for another cooldown.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether a call may be made now
+    pub fn allows(&self) -> bool {
+        self.state().open_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.allows()
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.state();
+        if state.open_until.take().is_some() {
+            info!("smtp-service is sending again");
+        }
+        state.failures = 0;
+    }
+
+    pub fn failed(&self) {
+        let mut state = self.state();
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            if state.open_until.is_none() {
+                warn!(
+                    "smtp-service failed {} sends in a row; emails are given up for {}s at a time",
+                    state.failures,
+                    self.cooldown.as_secs()
+                );
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Why a try at sending failed
+enum Failure {
+    /// The service is down or overloaded; another try may get through
+    Unavailable(String),
+    /// The service turned this message down; it always will
+    Refused(String),
+}
+
+/// Sends emails through the smtp-service
+pub struct SmtpClient {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    attempts: u32,
+    backoff: Duration,
+    breaker: CircuitBreaker,
+}
+
+impl SmtpClient {
+    pub fn new(client: reqwest::Client, config: &EmailConfig) -> Self {
+        Self {
+            client,
+            url: config.smtp_service_url.trim_end_matches('/').to_string(),
+            api_key: config.smtp_service_api_key.clone(),
+            attempts: config.send_attempts.max(1),
+            backoff: FIRST_BACKOFF,
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN),
+        }
+    }
+
+    async fn post(&self, email: &TaskEmail, idempotency_key: &str) -> Result<(), Failure> {
+        let mut request = self
+            .client
+            .post(format!("{}/send-email", self.url))
+            .timeout(SEND_TIMEOUT)
+            .header("Idempotency-Key", idempotency_key)
+            .json(email);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Failure::Unavailable(format!("smtp-service request failed: {}", e)))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let message = format!("smtp-service returned {}", status.as_u16());
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(Failure::Unavailable(message))
+        } else {
+            Err(Failure::Refused(message))
+        }
+    }
+
+    /// Send `email`, trying again while the smtp-service is unavailable
+    pub async fn deliver(&self, email: &TaskEmail) -> WorkerResult<()> {
+        if !self.breaker.allows() {
+            return Err(WorkerError::Email("smtp-service is unavailable; email not sent".to_string()));
+        }
+        let mut backoff = self.backoff;
+        let idempotency_key = Uuid::new_v4().to_string();
+        for attempt in 1..=self.attempts {
+            match self.post(email, &idempotency_key).await {
+                Ok(()) => {
+                    self.breaker.succeeded();
+                    return Ok(());
+                }
+                // Not the service being down, so the breaker is left alone
+                Err(Failure::Refused(message)) => return Err(WorkerError::Email(message)),
+                Err(Failure::Unavailable(message)) if attempt == self.attempts => {
+                    self.breaker.failed();
+                    return Err(WorkerError::Email(message));
+                }
+                Err(Failure::Unavailable(message)) => {
+                    warn!("Email try {} of {} failed: {}", attempt, self.attempts, message);
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+        unreachable!("the last try returns")
+    }
+}
+
+impl Mailer for SmtpClient {
+    fn send(&self, email: TaskEmail) -> BoxFuture<'_, WorkerResult<()>> {
+        Box::pin(async move { self.deliver(&email).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{email_config, MockSmtp};
+
+    fn email() -> TaskEmail {
+        TaskEmail::new(
+            "alice@example.com",
+            "A meeting couldn't be moved".to_string(),
+            &["Meeting: <Weekly> sync".to_string()],
+            ("Retry the meeting", "http://localhost:8080/dashboard?retry=r1&x=2"),
+        )
+    }
+
+    fn client(smtp: &MockSmtp) -> SmtpClient {
+        SmtpClient {
+            backoff: Duration::from_millis(1),
+            ..SmtpClient::new(reqwest::Client::new(), &email_config(&smtp.url))
+        }
+    }
+
+    #[test]
+    fn test_emails_escape_only_their_html_body() {
+        let email = email();
+        assert_eq!(
+            email.body_text,
+            "Meeting: <Weekly> sync\n\nRetry the meeting: http://localhost:8080/dashboard?retry=r1&x=2"
+        );
+        assert_eq!(
+            email.body_html,
+            "<p>Meeting: &lt;Weekly&gt; sync</p>\
+             <p><a href=\"http://localhost:8080/dashboard?retry=r1&amp;x=2\">Retry the meeting</a></p>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_a_send_is_retried_until_the_service_takes_it() {
+        let smtp = MockSmtp::start().await;
+        smtp.fail(2);
+
+        client(&smtp).deliver(&email()).await.unwrap();
+        assert_eq!(smtp.requests(), 3);
+        let sent = smtp.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["to_email"], "alice@example.com");
+        assert_eq!(sent[0]["subject"], "A meeting couldn't be moved");
+        assert!(sent[0]["body_html"].as_str().unwrap().contains("&lt;Weekly&gt;"));
+        let keys = smtp.idempotency_keys();
+        assert!(keys[0].is_some() && keys.iter().all(|key| *key == keys[0]), "one key for every try");
+
+        // A refusal isn't retried
+        let unauthorized = SmtpClient {
+            api_key: Some("wrong".to_string()),
+            ..client(&smtp)
+        };
+        let error = unauthorized.deliver(&email()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Email error: smtp-service returned 401");
+        assert_eq!(smtp.requests(), 4);
+        assert_ne!(smtp.idempotency_keys()[3], smtp.idempotency_keys()[0], "a new key for a new email");
+    }
+
+    #[tokio::test]
+    async fn test_the_breaker_opens_after_sends_fail_in_a_row() {
+        let smtp = MockSmtp::start().await;
+        smtp.fail(usize::MAX);
+        let client = SmtpClient {
+            attempts: 1,
+            ..client(&smtp)
+        };
+
+        for _ in 0..BREAKER_THRESHOLD {
+            assert!(client.deliver(&email()).await.is_err());
+        }
+        assert!(client.breaker.is_open());
+        let error = client.deliver(&email()).await.unwrap_err();
+        assert!(error.to_string().contains("smtp-service is unavailable"));
+        assert_eq!(smtp.requests(), BREAKER_THRESHOLD as usize, "an open breaker sends nothing");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_an_open_breaker_tries_again_after_its_cooldown() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.failed();
+        assert!(breaker.allows());
+        breaker.failed();
+        assert!(breaker.is_open());
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(breaker.allows());
+        // The one try after the cooldown fails, so it opens again at once
+        breaker.failed();
+        assert!(breaker.is_open());
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        breaker.succeeded();
+        breaker.failed();
+        assert!(breaker.allows(), "a success starts the count again");
+    }
+}
@@ -0,0 +1,192 @@
+//! Downloading, transcoding and uploading a recording at once
+//!
+//! One stage after another, a long recording is downloaded, then
+//! transcoded, then uploaded, each stage waiting on the whole of the one
+//! before. With `STREAMING_PIPELINE` on the three run together instead,
+//! joined by channels of [`CHANNEL_DEPTH`] chunks: the download feeds
+//! ffmpeg's stdin and ffmpeg's stdout feeds the Loom upload, so a task takes
+//! about as long as its slowest stage. A full channel holds back the stage
+//! feeding it, so a slow upload slows the download down rather than piling
+//! the recording up in memory.
+//!
+//! Nothing is written to the task's workspace or checkpointed on the way,
+//! and an attempt cut off leaves the next to start over. Whenever
+//! [`fallback`] gives a reason the task goes one stage at a time after all,
+//! as it does from the start when ffmpeg can't read the recording from a
+//! pipe, which an MP4 with its index at the end can only be read from a file.
+//! Progress is reported as one [`Combined`] percent under the `streaming`
+//! stage.
+
+use std::sync::Mutex;
+
+use crate::{
+    checkpoint::Resumed,
+    config::TranscodeConfig,
+    progress::StageProgress,
+    queue::QueueTask,
+    transcode::{Container, TranscodeMode},
+    workspace::Workspace,
+};
+
+/// Chunks each channel between stages holds before its sender waits
+pub const CHANNEL_DEPTH: usize = 16;
+
+/// Why `task` can't be streamed, or `None` when it can
+///
+/// Streaming starts from nothing, so a download or Loom session an earlier
+/// attempt left is resumed one stage at a time instead. A pipe can't be
+/// probed before it is read, which passthrough needs, nor rewritten once
+/// written, which MP4's `+faststart` does.
+pub fn fallback(
+    streaming: bool,
+    transcode: &TranscodeConfig,
+    task: &QueueTask,
+    resumed: &Resumed,
+    workspace: &Workspace,
+) -> Option<&'static str> {
+    if !streaming {
+        return Some("streaming is off");
+    }
+    if resumed.download.is_some() || workspace.download_path().exists() {
+        return Some("an earlier attempt left a download to resume");
+    }
+    if task.upload_session_id.is_some() {
+        return Some("an earlier attempt left a Loom upload to resume");
+    }
+    match (transcode.mode, transcode.profile.container) {
+        (TranscodeMode::Passthrough, _) => Some("passthrough has to probe the whole recording first"),
+        (TranscodeMode::Always, Container::Mp4) => Some("an MP4 transcode is rewritten once it is written"),
+        (TranscodeMode::Always, Container::Webm) | (TranscodeMode::Off, _) => None,
+    }
+}
+
+/// A stage of the streaming pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Download,
+    Transcode,
+    Upload,
+}
+
+/// The percent of a streaming task, from each stage's own
+///
+/// Transcoding counts twice what downloading or uploading does, and nothing
+/// when it is off. Loom's share of a video still being made isn't known, so
+/// the upload's percent is the stage before it's, scaled by how much of what
+/// the upload has been given Loom holds.
+pub struct Combined<'a> {
+    progress: StageProgress<'a>,
+    transcoding: bool,
+    percents: Mutex<[f64; 3]>,
+}
+
+impl<'a> Combined<'a> {
+    /// Report through `progress`, which should be the `Streaming` stage's
+    pub fn new(progress: StageProgress<'a>, transcoding: bool) -> Self {
+        Self {
+            progress,
+            transcoding,
+            percents: Mutex::new([0.0; 3]),
+        }
+    }
+
+    pub fn start(&self) {
+        self.progress.start();
+    }
+
+    /// `part` is `percent` done
+    pub fn report(&self, part: Part, percent: u8) {
+        let mut percents = self.percents.lock().unwrap();
+        percents[part as usize] = f64::from(percent.min(100));
+        self.send(&percents);
+    }
+
+    /// Loom holds `acknowledged` of the `received` bytes the upload has had
+    pub fn uploaded(&self, acknowledged: u64, received: u64) {
+        let mut percents = self.percents.lock().unwrap();
+        let upstream = percents[if self.transcoding { Part::Transcode } else { Part::Download } as usize];
+        let held = match received {
+            0 => 0.0,
+            received => acknowledged as f64 / received as f64,
+        };
+        percents[Part::Upload as usize] = upstream * held.min(1.0);
+        self.send(&percents);
+    }
+
+    pub fn finish(&self) {
+        self.progress.finish();
+    }
+
+    fn send(&self, percents: &[f64; 3]) {
+        let weights = [1.0, if self.transcoding { 2.0 } else { 0.0 }, 1.0];
+        let done: f64 = percents.iter().zip(weights).map(|(percent, weight)| percent * weight).sum();
+        self.progress.report((done / weights.iter().sum::<f64>()) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcode::TranscodeProfile;
+    use common::broadcast::{BroadcastServiceFactory, ProcessingStage};
+    use uuid::Uuid;
+
+    fn transcode(mode: TranscodeMode, container: Container) -> TranscodeConfig {
+        TranscodeConfig {
+            mode,
+            ffmpeg_path: "ffmpeg".to_string(),
+            ffprobe_path: "ffprobe".to_string(),
+            profile: TranscodeProfile {
+                max_height: 1080,
+                video_kbps: 4000,
+                audio_kbps: 128,
+                loudnorm: false,
+                container,
+            },
+        }
+    }
+
+    #[test]
+    fn test_only_a_fresh_task_that_can_be_piped_is_streamed() {
+        let root = tempfile::tempdir().unwrap();
+        let workspace = Workspace::create(root.path(), Uuid::new_v4()).unwrap();
+        let task = QueueTask::default();
+        let resumed = Resumed::default();
+        let off = transcode(TranscodeMode::Off, Container::Mp4);
+        let why = |streaming, config: &TranscodeConfig, task: &QueueTask| fallback(streaming, config, task, &resumed, &workspace);
+
+        assert_eq!(why(false, &off, &task), Some("streaming is off"));
+        assert_eq!(why(true, &off, &task), None);
+        assert_eq!(why(true, &transcode(TranscodeMode::Always, Container::Webm), &task), None);
+        assert!(why(true, &transcode(TranscodeMode::Always, Container::Mp4), &task).is_some());
+        assert!(why(true, &transcode(TranscodeMode::Passthrough, Container::Webm), &task).is_some());
+
+        let resuming = QueueTask { upload_session_id: Some("upload-1".to_string()), ..Default::default() };
+        assert_eq!(why(true, &off, &resuming), Some("an earlier attempt left a Loom upload to resume"));
+        std::fs::write(workspace.download_path(), b"part of it").unwrap();
+        assert_eq!(why(true, &off, &task), Some("an earlier attempt left a download to resume"));
+    }
+
+    #[tokio::test]
+    async fn test_the_streaming_percent_weighs_each_stage() {
+        let broadcast_service = BroadcastServiceFactory::create_shared(16);
+        let mut updates = broadcast_service.subscribe_progress();
+        let stage = || StageProgress::new(&broadcast_service, Uuid::new_v4(), "alice", ProcessingStage::Streaming);
+        let mut percents = || -> Vec<u8> { std::iter::from_fn(|| updates.try_recv().ok()).map(|update| update.percent).collect() };
+
+        let transcoding = Combined::new(stage(), true);
+        transcoding.start();
+        transcoding.report(Part::Download, 100);
+        transcoding.report(Part::Transcode, 50);
+        // Loom holds half of what half the transcode gave it
+        transcoding.uploaded(500, 1000);
+        transcoding.finish();
+        assert_eq!(percents(), [0, 25, 50, 56, 100]);
+
+        let copying = Combined::new(stage(), false);
+        copying.report(Part::Download, 50);
+        copying.uploaded(1000, 1000);
+        copying.report(Part::Transcode, 100);
+        assert_eq!(percents(), [25, 50], "a transcode that isn't run counts for nothing");
+    }
+}
@@ -0,0 +1,607 @@
+//! Servers standing in for the worker's peers in its tests
+//!
+//! [`MockPb`] is the in-memory global PocketBase shared with the other
+//! services, and [`database`] the credentials for it.
+//!
+//! [`mock_backend`] serves the backend's internal key routes, sealing the
+//! keys it is given to the worker's public key as the backend does,
+//! [`mock_fathom`] a Fathom with a few fixed recordings, [`MockMedia`] a
+//! media server honouring `Range` that can drop or stall connections partway, and
+//! [`MockLoom`] Loom's resumable uploads, sized upfront or by the last
+//! chunk, failing chunks or completions on request, and [`MockSmtp`] the smtp-service's `/send-email`, failing sends
+//! on request. [`FakeDisk`] reports whatever free space a test sets, and
+//! [`CapturedLogs`] keeps what a test logs.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use common::{
+    crypto::envelope,
+    test_support::{ADMIN_EMAIL, ADMIN_PASSWORD},
+    JobType,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+pub use common::test_support::{spawn_server, MockPb};
+
+use crate::config::{
+    BackendConfig, DatabaseConfig, EmailConfig, FathomConfig, LoggingConfig, LoomConfig, SecurityConfig,
+    TranscodeConfig, WorkerConfig, WorkerSettings,
+};
+use crate::transcode::{Container, TranscodeMode, TranscodeProfile};
+use crate::workspace::{DiskSpace, SpaceGate};
+
+/// The token [`mock_backend`] wants on internal routes
+pub const INTERNAL_TOKEN: &str = "internal-token";
+
+/// Admin credentials for `pb`
+pub fn database(pb: &MockPb) -> DatabaseConfig {
+    DatabaseConfig {
+        url: pb.url.clone(),
+        admin_email: ADMIN_EMAIL.to_string(),
+        admin_password: ADMIN_PASSWORD.to_string(),
+        user_db_base_path: "/tmp/unused".to_string(),
+    }
+}
+
+/// A backend handing out `keys`, by user id and service, to callers with
+/// [`INTERNAL_TOKEN`]; key uses are accepted and forgotten
+pub async fn mock_backend(keys: &[(&str, &str, &str)]) -> BackendConfig {
+    let keys: HashMap<(String, String), String> = keys
+        .iter()
+        .map(|(user_id, service, key)| ((user_id.to_string(), service.to_string()), key.to_string()))
+        .collect();
+    let fetch = move |Path((user_id, service)): Path<(String, String)>,
+                      Query(query): Query<HashMap<String, String>>,
+                      headers: HeaderMap| {
+        let key = keys.get(&(user_id.clone(), service.clone())).cloned();
+        async move {
+            if headers.get("authorization").and_then(|value| value.to_str().ok())
+                != Some(&format!("Bearer {}", INTERNAL_TOKEN))
+            {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            let Some(key) = key else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            let context = envelope::key_context(&user_id, &service);
+            let expires_at = chrono::Utc::now() + chrono::Duration::seconds(60);
+            let sealed = envelope::seal(&query["public_key"], key.as_bytes(), &context, expires_at).unwrap();
+            Json(json!({ "service": service, "key_id": "default", "expires_at": null, "envelope": sealed }))
+                .into_response()
+        }
+    };
+    let app = Router::new()
+        .route("/internal/keys/:user_id/:service", get(fetch))
+        .route("/internal/keys/:user_id/:service/touch", post(|| async { StatusCode::NO_CONTENT }))
+        .route("/internal/queue/:item_id/loom", post(|| async { StatusCode::NO_CONTENT }));
+    BackendConfig {
+        url: spawn_server(app).await,
+        internal_api_token: Some(INTERNAL_TOKEN.to_string()),
+    }
+}
+
+/// The key [`mock_fathom`] accepts
+pub const FATHOM_KEY: &str = "fathom-key-0123456789";
+
+/// A Fathom with recording 42, a deleted 7, and a transcript still being
+/// made for 43; any key but [`FATHOM_KEY`] is refused as revoked
+pub async fn mock_fathom() -> String {
+    mock_fathom_with("https://media.example.com/42.mp4?signature=abc").await
+}
+
+/// [`mock_fathom`], offering `download_url` as the recordings' media
+pub async fn mock_fathom_with(download_url: &str) -> String {
+    async fn recording(
+        State(download_url): State<String>,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+    ) -> axum::response::Response {
+        if headers.get("x-api-key").and_then(|key| key.to_str().ok()) != Some(FATHOM_KEY) {
+            return (StatusCode::UNAUTHORIZED, "invalid api key").into_response();
+        }
+        match id.as_str() {
+            "42" | "43" => Json(json!({
+                "recording_id": id.parse::<u64>().unwrap(),
+                "title": "Weekly sync",
+                "recording_start_time": "2026-03-01T09:00:00Z",
+                "recording_end_time": "2026-03-01T09:45:30Z",
+                "recording_size_bytes": 734003200,
+                "download_url": download_url,
+            }))
+            .into_response(),
+            _ => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+    async fn transcript(Path(id): Path<String>) -> StatusCode {
+        match id.as_str() {
+            "42" => StatusCode::OK,
+            _ => StatusCode::ACCEPTED,
+        }
+    }
+
+    let app = Router::new()
+        .route("/recordings/:id", get(recording))
+        .route("/recordings/:id/transcript", get(transcript))
+        .with_state(download_url.to_string());
+    spawn_server(app).await
+}
+
+/// A worker's configuration pointed at the given peers
+pub fn worker_config(database: DatabaseConfig, backend: BackendConfig, fathom_url: &str, loom_url: &str) -> WorkerConfig {
+    WorkerConfig {
+        database,
+        security: SecurityConfig {
+            master_key: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+            pb_encryption_key: "test".to_string(),
+        },
+        logging: LoggingConfig {
+            level: "debug".to_string(),
+            format: Default::default(),
+            sentry_dsn: None,
+        },
+        worker: WorkerSettings {
+            concurrency: 1,
+            job_types: vec![(JobType::ProcessMeeting, 1)],
+            poll_interval: 1,
+            queue_concurrency: 1,
+            worker_id: "worker-a".to_string(),
+            state_file: std::env::temp_dir().join("fathom-to-loom-tests").join("worker_state.json"),
+            claim_fairness_window: 1,
+            retry_base_delay: 30,
+            retry_max_delay: 3600,
+            shutdown_grace: 25,
+            max_download_bytes: 1 << 20,
+            download_attempts: 3,
+            metadata_timeout: 60,
+            download_timeout: 7200,
+            transcode_timeout: 7200,
+            upload_timeout: 7200,
+            task_deadline: 21600,
+            http_port: 0,
+            scratch_dir: std::env::temp_dir().join("fathom-to-loom-tests"),
+            scratch_min_free: 0,
+            expected_task_bytes: 0,
+            streaming: false,
+        },
+        backend,
+        fathom: FathomConfig {
+            api_url: fathom_url.to_string(),
+            requests_per_minute: 600,
+            burst: 10,
+        },
+        loom: LoomConfig {
+            api_url: loom_url.to_string(),
+            upload_chunk_size: 1000,
+            upload_chunk_attempts: 3,
+        },
+        email: email_config("http://127.0.0.1:9"),
+        // The mock recordings aren't video
+        transcode: TranscodeConfig {
+            mode: TranscodeMode::Off,
+            ffmpeg_path: "ffmpeg".to_string(),
+            ffprobe_path: "ffprobe".to_string(),
+            profile: TranscodeProfile {
+                max_height: 1080,
+                video_kbps: 4000,
+                audio_kbps: 128,
+                loudnorm: false,
+                container: Container::Mp4,
+            },
+        },
+    }
+}
+
+/// The token [`MockSmtp`] wants
+pub const SMTP_API_KEY: &str = "smtp-api-key";
+
+/// Email settings sending through the smtp-service at `smtp_service_url`
+pub fn email_config(smtp_service_url: &str) -> EmailConfig {
+    EmailConfig {
+        smtp_service_url: smtp_service_url.to_string(),
+        smtp_service_api_key: Some(SMTP_API_KEY.to_string()),
+        send_attempts: 3,
+        queue_url: "http://localhost:8080/dashboard".to_string(),
+        settings_url: "http://localhost:8080/settings".to_string(),
+    }
+}
+
+/// A volume with as many bytes free as last set, noting the paths asked about
+pub struct FakeDisk {
+    available: Mutex<Option<u64>>,
+    asked: Mutex<Vec<std::path::PathBuf>>,
+}
+
+impl FakeDisk {
+    pub fn new(available: u64) -> Self {
+        Self {
+            available: Mutex::new(Some(available)),
+            asked: Mutex::default(),
+        }
+    }
+
+    pub fn set(&self, available: u64) {
+        *self.available.lock().unwrap() = Some(available);
+    }
+
+    /// Fail to read the free space from now on
+    pub fn fail(&self) {
+        *self.available.lock().unwrap() = None;
+    }
+
+    pub fn asked(&self) -> Vec<std::path::PathBuf> {
+        self.asked.lock().unwrap().clone()
+    }
+}
+
+impl DiskSpace for FakeDisk {
+    fn available(&self, path: &std::path::Path) -> std::io::Result<u64> {
+        self.asked.lock().unwrap().push(path.to_path_buf());
+        self.available
+            .lock()
+            .unwrap()
+            .ok_or_else(|| std::io::Error::other("statvfs failed"))
+    }
+}
+
+/// A gate that always finds room, for tests not about disk space
+pub fn roomy_disk() -> SpaceGate {
+    SpaceGate::new(std::env::temp_dir(), 0, 0, Arc::new(FakeDisk::new(u64::MAX)))
+}
+
+/// Everything logged while installed, as plain text
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// Capture this thread's logs at every level until the guard drops
+    pub fn install(&self) -> tracing::subscriber::DefaultGuard {
+        let writer = self.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MediaState {
+    /// `Range` header of each request, in order
+    ranges: Vec<Option<String>>,
+    /// Responses still to cut off
+    drops: usize,
+    /// Whether cut-off responses hang instead of dropping
+    stall: bool,
+}
+
+/// A running media server for one file
+#[derive(Clone)]
+pub struct MockMedia {
+    pub url: String,
+    state: Arc<Mutex<MediaState>>,
+}
+
+impl MockMedia {
+    /// Serve `bytes`, the first `drops` responses stopping `cut` bytes into
+    /// what they send, though their `Content-Length` promised it all
+    pub async fn start(bytes: Vec<u8>, drops: usize, cut: usize) -> Self {
+        Self::serve(bytes, drops, cut, false).await
+    }
+
+    /// Serve `bytes`, every response hanging `cut` bytes in
+    pub async fn stalling(bytes: Vec<u8>, cut: usize) -> Self {
+        Self::serve(bytes, usize::MAX, cut, true).await
+    }
+
+    async fn serve(bytes: Vec<u8>, drops: usize, cut: usize, stall: bool) -> Self {
+        let state = Arc::new(Mutex::new(MediaState { drops, stall, ..Default::default() }));
+        let shared = state.clone();
+        let media = move |headers: HeaderMap| {
+            let state = shared.clone();
+            let bytes = bytes.clone();
+            async move {
+                let range = headers.get("range").and_then(|value| value.to_str().ok()).map(str::to_string);
+                let (drop, stall) = {
+                    let mut state = state.lock().unwrap();
+                    state.ranges.push(range.clone());
+                    let drop = state.drops > 0;
+                    state.drops = state.drops.saturating_sub(1);
+                    (drop, state.stall)
+                };
+                let start: usize = range
+                    .as_deref()
+                    .and_then(|range| range.strip_prefix("bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse().ok())
+                    .unwrap_or(0);
+                if start >= bytes.len() && range.is_some() {
+                    return Response::builder()
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header("content-range", format!("bytes */{}", bytes.len()))
+                        .body(axum::body::Body::empty())
+                        .unwrap();
+                }
+
+                let rest = bytes[start..].to_vec();
+                let sent = if drop { rest[..cut.min(rest.len())].to_vec() } else { rest.clone() };
+                // Dropped once what was sent has had time to arrive
+                let cut_off = futures::stream::once(async move {
+                    if stall {
+                        std::future::pending::<()>().await;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "dropped"))
+                });
+                let sent = futures::stream::once(async move { Ok::<_, std::io::Error>(sent) });
+                let body = if drop {
+                    axum::body::Body::from_stream(futures::StreamExt::chain(sent, cut_off))
+                } else {
+                    axum::body::Body::from_stream(sent)
+                };
+                let response = Response::builder().header("content-length", rest.len());
+                let response = match range {
+                    Some(_) => response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header("content-range", format!("bytes {}-{}/{}", start, bytes.len() - 1, bytes.len())),
+                    None => response.status(StatusCode::OK),
+                };
+                response.body(body).unwrap()
+            }
+        };
+        let url = spawn_server(Router::new().route("/media.mp4", get(media))).await;
+        Self {
+            url: format!("{}/media.mp4", url),
+            state,
+        }
+    }
+
+    pub fn ranges(&self) -> Vec<Option<String>> {
+        self.state.lock().unwrap().ranges.clone()
+    }
+}
+
+/// The key [`MockLoom`] accepts
+pub const LOOM_KEY: &str = "loom-key-0123456789";
+
+#[derive(Default)]
+struct LoomState {
+    /// Bytes received and size announced, per upload session; a streamed
+    /// upload's size is unknown until its last chunk
+    uploads: HashMap<String, (Vec<u8>, Option<u64>)>,
+    /// Chunks to accept before failing any
+    fail_after: usize,
+    /// Chunks still to fail, each after half of it landed
+    failing: usize,
+    fail_completion: bool,
+    chunk_requests: usize,
+}
+
+/// A running mock of Loom's upload API
+#[derive(Clone)]
+pub struct MockLoom {
+    pub url: String,
+    state: Arc<Mutex<LoomState>>,
+}
+
+impl MockLoom {
+    pub async fn start() -> Self {
+        type Shared = State<Arc<Mutex<LoomState>>>;
+
+        fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+            match headers.get("authorization").and_then(|value| value.to_str().ok()) {
+                Some(value) if value == format!("Bearer {}", LOOM_KEY) => Ok(()),
+                _ => Err(StatusCode::UNAUTHORIZED),
+            }
+        }
+
+        async fn start(State(state): Shared, headers: HeaderMap, Json(body): Json<Value>) -> Result<Json<Value>, StatusCode> {
+            authorized(&headers)?;
+            let mut state = state.lock().unwrap();
+            let upload_id = format!("upload-{}", state.uploads.len() + 1);
+            let size = match &body["size_bytes"] {
+                Value::Null => None,
+                size => Some(size.as_u64().ok_or(StatusCode::BAD_REQUEST)?),
+            };
+            state.uploads.insert(upload_id.clone(), (Vec::new(), size));
+            Ok(Json(json!({ "upload_id": upload_id })))
+        }
+
+        async fn offset(State(state): Shared, Path(id): Path<String>, headers: HeaderMap) -> Result<Json<Value>, StatusCode> {
+            authorized(&headers)?;
+            let state = state.lock().unwrap();
+            let (bytes, _) = state.uploads.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+            Ok(Json(json!({ "offset": bytes.len() })))
+        }
+
+        async fn chunk(
+            State(state): Shared,
+            Path(id): Path<String>,
+            headers: HeaderMap,
+            body: axum::body::Bytes,
+        ) -> Result<Json<Value>, StatusCode> {
+            authorized(&headers)?;
+            let (start, total) = headers
+                .get("content-range")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("bytes "))
+                .and_then(|range| {
+                    let (range, total) = range.split_once('/')?;
+                    let start: usize = range.split('-').next()?.parse().ok()?;
+                    Some((start, total.parse::<u64>().ok()))
+                })
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let mut state = state.lock().unwrap();
+            state.chunk_requests += 1;
+            let fail = if state.fail_after > 0 {
+                state.fail_after -= 1;
+                false
+            } else if state.failing > 0 {
+                state.failing -= 1;
+                true
+            } else {
+                false
+            };
+            let (bytes, size) = state.uploads.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
+            if start != bytes.len() || size.zip(total).is_some_and(|(size, total)| size != total) {
+                return Err(StatusCode::CONFLICT);
+            }
+            *size = size.or(total);
+            if fail {
+                bytes.extend_from_slice(&body[..body.len() / 2]);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            bytes.extend_from_slice(&body);
+            Ok(Json(json!({ "offset": bytes.len() })))
+        }
+
+        async fn complete(State(state): Shared, Path(id): Path<String>, headers: HeaderMap) -> Result<Json<Value>, StatusCode> {
+            authorized(&headers)?;
+            let state = state.lock().unwrap();
+            if state.fail_completion {
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            let (bytes, size) = state.uploads.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+            if Some(bytes.len() as u64) != *size {
+                return Err(StatusCode::CONFLICT);
+            }
+            let video_id = format!("video-{}", id.trim_start_matches("upload-"));
+            Ok(Json(json!({
+                "video_id": video_id,
+                "share_url": format!("https://www.loom.com/share/{}", video_id),
+            })))
+        }
+
+        let state = Arc::new(Mutex::new(LoomState::default()));
+        let app = Router::new()
+            .route("/uploads", post(start))
+            .route("/uploads/:id", get(offset).put(chunk))
+            .route("/uploads/:id/complete", post(complete))
+            .with_state(state.clone());
+        let url = spawn_server(app).await;
+        Self { url, state }
+    }
+
+    /// Fail the next `count` chunks
+    pub fn fail_chunks(&self, count: usize) {
+        self.fail_chunks_after(0, count);
+    }
+
+    /// Accept `accepted` chunks, then fail the `count` after them
+    pub fn fail_chunks_after(&self, accepted: usize, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_after = accepted;
+        state.failing = count;
+    }
+
+    /// Answer 503 to every completion
+    pub fn fail_completion(&self) {
+        self.state.lock().unwrap().fail_completion = true;
+    }
+
+    /// What upload `upload_id` holds so far
+    pub fn uploaded(&self, upload_id: &str) -> Vec<u8> {
+        self.state.lock().unwrap().uploads[upload_id].0.clone()
+    }
+
+    pub fn sessions(&self) -> usize {
+        self.state.lock().unwrap().uploads.len()
+    }
+
+    pub fn chunk_requests(&self) -> usize {
+        self.state.lock().unwrap().chunk_requests
+    }
+}
+
+#[derive(Default)]
+struct SmtpState {
+    /// Every message accepted, in order
+    sent: Vec<Value>,
+    /// Requests seen, refused ones included
+    requests: usize,
+    /// Requests still to answer 503
+    failures: usize,
+    /// The `Idempotency-Key` of each request, in order
+    idempotency_keys: Vec<Option<String>>,
+}
+
+/// A running smtp-service
+#[derive(Clone)]
+pub struct MockSmtp {
+    pub url: String,
+    state: Arc<Mutex<SmtpState>>,
+}
+
+impl MockSmtp {
+    pub async fn start() -> Self {
+        async fn send_email(
+            State(state): State<Arc<Mutex<SmtpState>>>,
+            headers: HeaderMap,
+            Json(email): Json<Value>,
+        ) -> Response {
+            let mut state = state.lock().unwrap();
+            state.requests += 1;
+            let key = headers.get("idempotency-key").and_then(|value| value.to_str().ok());
+            state.idempotency_keys.push(key.map(str::to_string));
+            if headers.get("authorization").and_then(|value| value.to_str().ok())
+                != Some(&format!("Bearer {}", SMTP_API_KEY))
+            {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            if state.failures > 0 {
+                state.failures -= 1;
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+            state.sent.push(email);
+            let queue_id = format!("mail{}", state.sent.len());
+            (StatusCode::ACCEPTED, Json(json!({ "success": true, "queue_id": queue_id }))).into_response()
+        }
+
+        let state = Arc::new(Mutex::new(SmtpState::default()));
+        let app = Router::new().route("/send-email", post(send_email)).with_state(state.clone());
+        Self {
+            url: spawn_server(app).await,
+            state,
+        }
+    }
+
+    /// Answer the next `count` sends with 503
+    pub fn fail(&self, count: usize) {
+        self.state.lock().unwrap().failures = count;
+    }
+
+    pub fn sent(&self) -> Vec<Value> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    pub fn requests(&self) -> usize {
+        self.state.lock().unwrap().requests
+    }
+
+    pub fn idempotency_keys(&self) -> Vec<Option<String>> {
+        self.state.lock().unwrap().idempotency_keys.clone()
+    }
+}
@@ -0,0 +1,785 @@
+//! Re-encoding a downloaded recording with ffmpeg
+//!
+//! Between download and upload the recording is probed with ffprobe and, in
+//! [`TranscodeMode::Passthrough`], handed on untouched when it already fits
+//! the configured [`TranscodeProfile`]. Otherwise ffmpeg writes it afresh
+//! to a `.part` file that is renamed once ffmpeg succeeds, so a retried task
+//! reuses a finished transcode instead of starting another that might not
+//! come out byte for byte the same, which a resumed upload depends on.
+//!
+//! ffmpeg's stderr drives the `Transcoding` progress and its last lines are
+//! kept for the error: one ffprobe can't read fails the task for good with
+//! [`WorkerError::InvalidMedia`].
+//!
+//! The streaming pipeline [pipes](Transcoder::transcode_stream) the
+//! recording through ffmpeg instead, so nothing is probed or passed through.
+
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Command,
+    sync::mpsc,
+};
+use tracing::info;
+
+use crate::{
+    config::TranscodeConfig,
+    download::{self, Download},
+    WorkerError, WorkerResult,
+};
+
+/// Lines of ffmpeg's stderr kept for an error
+const TAIL_LINES: usize = 15;
+
+/// What ffmpeg prints that means the input isn't media it can read
+const INVALID_INPUT: &[&str] = &[
+    "Invalid data found when processing input",
+    "moov atom not found",
+    "could not find codec parameters",
+    "does not contain any stream",
+];
+
+/// When recordings are transcoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscodeMode {
+    /// Only recordings that don't already fit the profile
+    Passthrough,
+    /// Every recording
+    Always,
+    /// None, and ffmpeg isn't needed
+    Off,
+}
+
+impl TranscodeMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "passthrough" => Some(Self::Passthrough),
+            "always" => Some(Self::Always),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// The file format a transcode writes, with the codecs that go in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    Webm,
+}
+
+impl Container {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "mp4" => Some(Self::Mp4),
+            "webm" => Some(Self::Webm),
+            _ => None,
+        }
+    }
+
+    /// The ffmpeg muxer, also the name ffprobe lists the format under
+    pub fn muxer(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.muxer()
+    }
+
+    fn encoders(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Mp4 => ("libx264", "aac"),
+            Self::Webm => ("libvpx-vp9", "libopus"),
+        }
+    }
+
+    /// The codecs as ffprobe names them
+    fn codecs(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Mp4 => ("h264", "aac"),
+            Self::Webm => ("vp9", "opus"),
+        }
+    }
+}
+
+/// What a transcoded recording looks like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscodeProfile {
+    /// Taller video is scaled down to this many lines; shorter isn't scaled up
+    pub max_height: u32,
+    pub video_kbps: u32,
+    pub audio_kbps: u32,
+    /// Even out the audio's loudness with ffmpeg's `loudnorm` filter
+    pub loudnorm: bool,
+    pub container: Container,
+}
+
+impl TranscodeProfile {
+    /// Whether `media` can go to Loom as it is
+    ///
+    /// Loudness isn't probed, so with `loudnorm` nothing matches. The
+    /// bitrate is the whole file's, against the video and audio together.
+    pub fn matches(&self, media: &MediaInfo) -> bool {
+        let (video_codec, audio_codec) = self.container.codecs();
+        let Some(video) = &media.video else { return false };
+        let total_bps = u64::from(self.video_kbps + self.audio_kbps) * 1000;
+        !self.loudnorm
+            && media.formats.iter().any(|format| format == self.container.muxer())
+            && video.codec == video_codec
+            && video.height <= self.max_height
+            && media.audio_codec.as_deref().is_none_or(|codec| codec == audio_codec)
+            && media.bit_rate.is_some_and(|bit_rate| bit_rate <= total_bps)
+    }
+
+    /// ffmpeg's arguments to transcode `input` to `output`
+    pub fn args(&self, input: &Path, output: &Path) -> Vec<OsString> {
+        let (video_encoder, audio_encoder) = self.container.encoders();
+        let mut args: Vec<OsString> = ["-hide_banner", "-nostdin", "-y", "-i"].map(OsString::from).into();
+        args.push(input.into());
+        for arg in [
+            "-vf".to_string(),
+            format!("scale=-2:'min({},ih)'", self.max_height),
+            "-c:v".to_string(),
+            video_encoder.to_string(),
+            "-b:v".to_string(),
+            format!("{}k", self.video_kbps),
+            "-c:a".to_string(),
+            audio_encoder.to_string(),
+            "-b:a".to_string(),
+            format!("{}k", self.audio_kbps),
+        ] {
+            args.push(arg.into());
+        }
+        if self.loudnorm {
+            args.extend(["-af", "loudnorm=I=-16:TP=-1.5:LRA=11"].map(OsString::from));
+        }
+        if self.container == Container::Mp4 {
+            // Lets Loom start processing before it has read the whole file
+            args.extend(["-movflags", "+faststart"].map(OsString::from));
+        }
+        args.extend(["-f", self.container.muxer()].map(OsString::from));
+        args.push(output.into());
+        args
+    }
+}
+
+/// What ffprobe found in a file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Every format name the file goes by, such as `mov`, `mp4` and `m4a`
+    pub formats: Vec<String>,
+    pub duration: Option<f64>,
+    pub bit_rate: Option<u64>,
+    pub video: Option<VideoStream>,
+    pub audio_codec: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoStream {
+    pub codec: String,
+    pub height: u32,
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    format: ProbeFormat,
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Default, Deserialize)]
+struct ProbeFormat {
+    #[serde(default)]
+    format_name: String,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    height: Option<u32>,
+}
+
+impl MediaInfo {
+    /// Read `ffprobe -print_format json -show_format -show_streams` output
+    pub fn from_probe(json: &str) -> WorkerResult<Self> {
+        let probe: Probe = serde_json::from_str(json)?;
+        let stream = |kind: &str| probe.streams.iter().find(|stream| stream.codec_type.as_deref() == Some(kind));
+        Ok(Self {
+            formats: probe.format.format_name.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect(),
+            duration: probe.format.duration.and_then(|duration| duration.parse().ok()),
+            bit_rate: probe.format.bit_rate.and_then(|bit_rate| bit_rate.parse().ok()),
+            video: stream("video").map(|stream| VideoStream {
+                codec: stream.codec_name.clone().unwrap_or_default(),
+                height: stream.height.unwrap_or(0),
+            }),
+            audio_codec: stream("audio").and_then(|stream| stream.codec_name.clone()),
+        })
+    }
+}
+
+/// Follows ffmpeg's stderr for how far it has got, keeping its last lines
+#[derive(Debug, Default)]
+pub struct FfmpegOutput {
+    /// Seconds of media in the input, probed or read from ffmpeg's banner
+    duration: Option<f64>,
+    percent: u8,
+    tail: VecDeque<String>,
+}
+
+impl FfmpegOutput {
+    pub fn new(duration: Option<f64>) -> Self {
+        Self {
+            duration: duration.filter(|duration| *duration > 0.0),
+            ..Default::default()
+        }
+    }
+
+    /// Take one line, returning a new whole percent done when it gives one
+    ///
+    /// 100 is left for ffmpeg exiting, however near the end a line says.
+    pub fn line(&mut self, line: &str) -> Option<u8> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        if self.tail.len() == TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.to_string());
+
+        if self.duration.is_none() {
+            self.duration = field(line, "Duration: ").and_then(timestamp).filter(|duration| *duration > 0.0);
+        }
+        let done = field(line, "time=").and_then(timestamp)?;
+        let percent = (done / self.duration? * 100.0).clamp(0.0, 99.0) as u8;
+        (percent > self.percent).then(|| {
+            self.percent = percent;
+            percent
+        })
+    }
+
+    /// The last lines seen, oldest first
+    pub fn tail(&self) -> String {
+        self.tail.iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+
+    fn invalid_input(&self) -> bool {
+        self.tail.iter().any(|line| INVALID_INPUT.iter().any(|sign| line.contains(sign)))
+    }
+}
+
+/// The value after `name` in `line`, up to the next space or comma
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let start = line.find(name)? + name.len();
+    line[start..].split([' ', ',']).next()
+}
+
+/// Seconds in an `HH:MM:SS.ss` timestamp
+fn timestamp(value: &str) -> Option<f64> {
+    let mut parts = value.splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Fail startup when transcoding is on and ffmpeg or ffprobe won't run
+pub async fn check_tools(config: &TranscodeConfig) -> WorkerResult<()> {
+    if config.mode == TranscodeMode::Off {
+        return Ok(());
+    }
+    for (name, path) in [("FFMPEG_PATH", &config.ffmpeg_path), ("FFPROBE_PATH", &config.ffprobe_path)] {
+        let output = Command::new(path)
+            .arg("-version")
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| WorkerError::Config(format!("{} {} can't be run: {}", name, path, e)))?;
+        if !output.status.success() {
+            return Err(WorkerError::Config(format!("{} {} -version exited with {}", name, path, output.status)));
+        }
+        let version = String::from_utf8_lossy(&output.stdout);
+        info!("Found {}", version.lines().next().unwrap_or(path));
+    }
+    Ok(())
+}
+
+pub struct Transcoder<'a> {
+    config: &'a TranscodeConfig,
+}
+
+impl<'a> Transcoder<'a> {
+    pub fn new(config: &'a TranscodeConfig) -> Self {
+        Self { config }
+    }
+
+    /// What ffprobe finds in `path`; a file it can't read is
+    /// [`WorkerError::InvalidMedia`]
+    pub async fn probe(&self, path: &Path) -> WorkerResult<MediaInfo> {
+        let output = Command::new(&self.config.ffprobe_path)
+            .args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        if !output.status.success() {
+            let mut stderr = FfmpegOutput::new(None);
+            String::from_utf8_lossy(&output.stderr).lines().for_each(|line| {
+                stderr.line(line);
+            });
+            return Err(WorkerError::InvalidMedia { stderr_tail: stderr.tail() });
+        }
+        MediaInfo::from_probe(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// `video` fit for the profile: itself when it already is, else a
+    /// transcode to `dest`, or what an earlier attempt left there
+    ///
+    /// `progress` is told each new whole percent ffmpeg gets through.
+    pub async fn transcode(&self, video: &Download, dest: &Path, progress: &(dyn Fn(u8) + Send + Sync)) -> WorkerResult<Download> {
+        if self.config.mode == TranscodeMode::Off {
+            return Ok(video.clone());
+        }
+        if fs::try_exists(dest).await? {
+            info!("Reusing the transcode of an earlier attempt");
+            return finished(dest).await;
+        }
+        let media = self.probe(&video.path).await?;
+        if self.config.mode == TranscodeMode::Passthrough && self.config.profile.matches(&media) {
+            info!("The recording already fits the profile; not transcoding");
+            return Ok(video.clone());
+        }
+
+        let part = part_path(dest);
+        let result = self.run_ffmpeg(&video.path, &part, media.duration, progress).await;
+        if result.is_err() {
+            let _ = fs::remove_file(&part).await;
+        }
+        result?;
+        fs::rename(&part, dest).await?;
+        finished(dest).await
+    }
+
+    async fn run_ffmpeg(&self, input: &Path, output: &Path, duration: Option<f64>, progress: &(dyn Fn(u8) + Send + Sync)) -> WorkerResult<()> {
+        let mut child = Command::new(&self.config.ffmpeg_path)
+            .args(self.config.profile.args(input, output))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            // A timed-out stage drops this future, which mustn't leave ffmpeg running
+            .kill_on_drop(true)
+            .spawn()?;
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let mut output_lines = FfmpegOutput::new(duration);
+        follow(stderr, &mut output_lines, progress).await?;
+        exited(child.wait().await?, &output_lines)
+    }
+
+    /// Transcode the recording arriving on `input`, sending ffmpeg's output
+    /// on to `output` as it comes
+    ///
+    /// ffmpeg reads the recording from its stdin and writes the transcode to
+    /// its stdout, so `duration` stands in for a probe's. `output` is only
+    /// dropped once ffmpeg has exited cleanly, so the upload never takes a
+    /// failed transcode's end for the end of the video.
+    pub async fn transcode_stream(
+        &self,
+        mut input: mpsc::Receiver<Vec<u8>>,
+        output: mpsc::Sender<Vec<u8>>,
+        duration: Option<f64>,
+        progress: &(dyn Fn(u8) + Send + Sync),
+    ) -> WorkerResult<()> {
+        let mut child = Command::new(&self.config.ffmpeg_path)
+            .args(self.config.profile.args(Path::new("pipe:0"), Path::new("pipe:1")))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let mut output_lines = FfmpegOutput::new(duration);
+
+        let feed = async move {
+            while let Some(chunk) = input.recv().await {
+                match stdin.write_all(&chunk).await {
+                    Ok(()) => {}
+                    // ffmpeg stopped reading, and its exit says why; the rest
+                    // is let through so the download still finishes
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                        while input.recv().await.is_some() {}
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            // Dropping stdin is ffmpeg's end of input
+            Ok(())
+        };
+        let drain = async {
+            let mut buffer = vec![0; 1 << 16];
+            loop {
+                let read = stdout.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                if output.send(buffer[..read].to_vec()).await.is_err() {
+                    return Err(std::io::Error::other("the upload stopped reading the transcode"));
+                }
+            }
+        };
+        tokio::try_join!(feed, drain, follow(stderr, &mut output_lines, progress))?;
+        exited(child.wait().await?, &output_lines)?;
+        drop(output);
+        Ok(())
+    }
+}
+
+/// Read ffmpeg's `stderr` into `output_lines` until it closes, telling
+/// `progress` of each new percent
+async fn follow(
+    mut stderr: impl AsyncRead + Unpin,
+    output_lines: &mut FfmpegOutput,
+    progress: &(dyn Fn(u8) + Send + Sync),
+) -> std::io::Result<()> {
+    // Progress lines end in a carriage return, the rest in a newline
+    let mut buffer = [0; 4096];
+    let mut line = Vec::new();
+    loop {
+        let read = stderr.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            if byte == b'\r' || byte == b'\n' {
+                if let Some(percent) = output_lines.line(&String::from_utf8_lossy(&line)) {
+                    progress(percent);
+                }
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+    output_lines.line(&String::from_utf8_lossy(&line));
+    Ok(())
+}
+
+/// What ffmpeg exiting with `status` means for the transcode
+fn exited(status: ExitStatus, output_lines: &FfmpegOutput) -> WorkerResult<()> {
+    match status.code() {
+        Some(0) => Ok(()),
+        _ if output_lines.invalid_input() => Err(WorkerError::InvalidMedia { stderr_tail: output_lines.tail() }),
+        Some(code) => Err(WorkerError::Video(format!("ffmpeg exited with {}: {}", code, output_lines.tail()))),
+        // Killed, most likely for memory; another attempt may get through
+        None => Err(WorkerError::Io(std::io::Error::other(format!("ffmpeg was killed: {}", status)))),
+    }
+}
+
+/// Where ffmpeg writes until it has finished
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+async fn finished(path: &Path) -> WorkerResult<Download> {
+    Ok(Download {
+        path: path.to_path_buf(),
+        size: fs::metadata(path).await?.len(),
+        sha256: download::sha256_of(path).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::PermissionsExt, sync::Mutex};
+
+    const MP4_PROBE: &str = r#"{
+        "streams": [
+            { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720 },
+            { "index": 1, "codec_type": "audio", "codec_name": "aac", "sample_rate": "48000" }
+        ],
+        "format": { "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "4.000000", "bit_rate": "1500000" }
+    }"#;
+
+    /// A canned ffmpeg run over a 4s clip, progress lines ending in `\r`
+    const STDERR: &str = "Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'in.mp4':\n  \
+        Duration: 00:00:04.00, start: 0.000000, bitrate: 1500 kb/s\n\
+        frame=   25 fps=0.0 q=28.0 size=       0kB time=00:00:01.00 bitrate=   0.4kbits/s speed=2.0x\r\
+        frame=   50 fps= 49 q=28.0 size=     256kB time=00:00:02.02 bitrate=1038.1kbits/s speed=1.9x\r\
+        frame=   50 fps= 49 q=28.0 size=     256kB time=00:00:02.02 bitrate=1038.1kbits/s speed=1.9x\r\
+        frame=  100 fps= 48 q=-1.0 Lsize=     740kB time=00:00:04.00 bitrate=1515.4kbits/s speed=1.9x\n\
+        video:680kB audio:62kB subtitle:0kB other streams:0kB global headers:0kB muxing overhead: 0.3%\n";
+
+    fn profile() -> TranscodeProfile {
+        TranscodeProfile {
+            max_height: 1080,
+            video_kbps: 2500,
+            audio_kbps: 128,
+            loudnorm: false,
+            container: Container::Mp4,
+        }
+    }
+
+    /// An executable shell script in `dir`
+    fn script(dir: &Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Stand-ins for ffprobe, printing `probe`, and ffmpeg, printing
+    /// [`STDERR`] and copying its input to its output with "transcoded"
+    /// after it, pipes included, its arguments kept in `args`
+    fn tools(dir: &Path, probe: &str, mode: TranscodeMode) -> TranscodeConfig {
+        let ffprobe = script(dir, "ffprobe", &format!("cat <<'EOF'\n{}\nEOF\n", probe));
+        std::fs::write(dir.join("stderr"), STDERR).unwrap();
+        let ffmpeg = script(
+            dir,
+            "ffmpeg",
+            &format!(
+                "[ \"$1\" = -version ] && echo 'ffmpeg version 6.1' && exit 0\n\
+                 echo \"$@\" > {dir}/args\ncat {dir}/stderr >&2\n\
+                 for arg; do out=$arg; done\n\
+                 while [ $# -gt 0 ]; do [ \"$1\" = -i ] && in=$2; shift; done\n\
+                 [ \"$in\" = pipe:0 ] && in=/dev/stdin\n[ \"$out\" = pipe:1 ] && out=/dev/stdout\n\
+                 {{ cat \"$in\" && echo transcoded; }} > \"$out\"\n",
+                dir = dir.display()
+            ),
+        );
+        TranscodeConfig {
+            mode,
+            ffmpeg_path: ffmpeg,
+            ffprobe_path: ffprobe,
+            profile: TranscodeProfile {
+                max_height: 480,
+                ..profile()
+            },
+        }
+    }
+
+    async fn recording(dir: &Path) -> Download {
+        let path = dir.join("in.mp4");
+        fs::write(&path, b"source").await.unwrap();
+        Download {
+            size: 6,
+            sha256: download::sha256_of(&path).await.unwrap(),
+            path,
+        }
+    }
+
+    #[test]
+    fn test_progress_follows_ffmpegs_time_against_the_duration() {
+        let mut output = FfmpegOutput::new(None);
+        let reported: Vec<u8> = STDERR.split(['\r', '\n']).filter_map(|line| output.line(line)).collect();
+        // The duration comes from ffmpeg's banner, and 100 is left for its exit
+        assert_eq!(reported, [25, 50, 99]);
+        assert!(output.tail().ends_with("muxing overhead: 0.3%"));
+
+        let mut output = FfmpegOutput::new(Some(8.0));
+        assert_eq!(output.line("frame=1 time=00:00:02.00 bitrate=N/A"), Some(25));
+        assert_eq!(output.line("frame=2 time=N/A bitrate=N/A"), None);
+        for i in 0..TAIL_LINES + 5 {
+            output.line(&format!("line {}", i));
+        }
+        assert_eq!(output.tail().lines().count(), TAIL_LINES);
+        assert!(output.tail().starts_with("line 5\n"));
+    }
+
+    #[test]
+    fn test_only_media_that_fits_the_profile_passes_through() {
+        let media = MediaInfo::from_probe(MP4_PROBE).unwrap();
+        assert_eq!(media.duration, Some(4.0));
+        assert_eq!(media.video, Some(VideoStream { codec: "h264".to_string(), height: 720 }));
+        assert!(profile().matches(&media));
+
+        let too_tall = TranscodeProfile { max_height: 480, ..profile() };
+        let too_dense = TranscodeProfile { video_kbps: 1000, ..profile() };
+        let normalised = TranscodeProfile { loudnorm: true, ..profile() };
+        let webm = TranscodeProfile { container: Container::Webm, ..profile() };
+        for profile in [too_tall, too_dense, normalised, webm] {
+            assert!(!profile.matches(&media), "{:?}", profile);
+        }
+        let audio_only = MediaInfo { video: None, ..media.clone() };
+        assert!(!profile().matches(&audio_only));
+        let unknown_rate = MediaInfo { bit_rate: None, ..media };
+        assert!(!profile().matches(&unknown_rate));
+    }
+
+    #[test]
+    fn test_ffmpeg_is_asked_for_the_profile() {
+        let args = TranscodeProfile { loudnorm: true, ..profile() }.args(Path::new("in.mp4"), Path::new("out.part"));
+        let args: Vec<&str> = args.iter().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(
+            args.join(" "),
+            "-hide_banner -nostdin -y -i in.mp4 -vf scale=-2:'min(1080,ih)' -c:v libx264 -b:v 2500k -c:a aac -b:a 128k \
+             -af loudnorm=I=-16:TP=-1.5:LRA=11 -movflags +faststart -f mp4 out.part"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_a_recording_is_transcoded_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = tools(dir.path(), MP4_PROBE, TranscodeMode::Passthrough);
+        let video = recording(dir.path()).await;
+        let dest = dir.path().join("out.mp4");
+        let reported = Mutex::new(Vec::new());
+
+        let transcoder = Transcoder::new(&config);
+        let output = transcoder
+            .transcode(&video, &dest, &|percent| reported.lock().unwrap().push(percent))
+            .await
+            .unwrap();
+        assert_eq!(output.path, dest);
+        assert_eq!(fs::read(&dest).await.unwrap(), b"sourcetranscoded\n");
+        assert_eq!(output.size, 17);
+        download::verify(&output).await.unwrap();
+        assert!(!part_path(&dest).exists());
+        assert_eq!(*reported.lock().unwrap(), [25, 50, 99]);
+        let args = std::fs::read_to_string(dir.path().join("args")).unwrap();
+        assert!(args.contains("scale=-2:'min(480,ih)'"), "{}", args);
+
+        // A retry reuses the finished transcode
+        std::fs::remove_file(dir.path().join("ffmpeg")).unwrap();
+        let again = transcoder.transcode(&video, &dest, &|_| {}).await.unwrap();
+        assert_eq!(again, output);
+    }
+
+    #[tokio::test]
+    async fn test_a_recording_that_fits_is_passed_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = tools(dir.path(), MP4_PROBE, TranscodeMode::Passthrough);
+        config.profile.max_height = 1080;
+        let video = recording(dir.path()).await;
+        let dest = dir.path().join("out.mp4");
+
+        let output = Transcoder::new(&config).transcode(&video, &dest, &|_| {}).await.unwrap();
+        assert_eq!(output, video);
+        assert!(!dest.exists());
+        assert!(!dir.path().join("args").exists(), "ffmpeg isn't run");
+
+        config.mode = TranscodeMode::Always;
+        let output = Transcoder::new(&config).transcode(&video, &dest, &|_| {}).await.unwrap();
+        assert_eq!(output.path, dest);
+    }
+
+    #[tokio::test]
+    async fn test_a_corrupt_recording_fails_for_good_with_what_ffmpeg_said() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = tools(dir.path(), MP4_PROBE, TranscodeMode::Always);
+        config.ffprobe_path = script(
+            dir.path(),
+            "ffprobe-corrupt",
+            "echo '[mov,mp4,m4a,3gp,3g2,mj2 @ 0x1] moov atom not found' >&2\n\
+             echo \"$7: Invalid data found when processing input\" >&2\nexit 1\n",
+        );
+        let video = recording(dir.path()).await;
+        let dest = dir.path().join("out.mp4");
+
+        let error = Transcoder::new(&config).transcode(&video, &dest, &|_| {}).await.unwrap_err();
+        assert!(!error.is_retryable());
+        match &error {
+            WorkerError::InvalidMedia { stderr_tail } => {
+                assert!(stderr_tail.contains("moov atom not found"), "{}", stderr_tail);
+                assert!(stderr_tail.ends_with("in.mp4: Invalid data found when processing input"), "{}", stderr_tail);
+            }
+            error => panic!("{:?}", error),
+        }
+        assert!(error.to_string().starts_with("The recording can't be read as video: "));
+
+        // Nor is what ffmpeg itself can't decode partway through retried
+        config.ffprobe_path = tools(dir.path(), MP4_PROBE, TranscodeMode::Always).ffprobe_path;
+        config.ffmpeg_path = script(
+            dir.path(),
+            "ffmpeg-corrupt",
+            "for arg; do out=$arg; done\necho partial > \"$out\"\n\
+             echo 'Error while decoding stream #0:0: Invalid data found when processing input' >&2\nexit 69\n",
+        );
+        let error = Transcoder::new(&config).transcode(&video, &dest, &|_| {}).await.unwrap_err();
+        assert!(matches!(error, WorkerError::InvalidMedia { .. }), "{:?}", error);
+        assert!(!dest.exists() && !part_path(&dest).exists(), "nothing half-written is kept");
+    }
+
+    #[tokio::test]
+    async fn test_a_streamed_recording_is_piped_through_ffmpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = tools(dir.path(), MP4_PROBE, TranscodeMode::Always);
+        config.profile.container = Container::Webm;
+        let reported = Mutex::new(Vec::new());
+        let (sender, input) = mpsc::channel(2);
+        let (output, mut transcoded) = mpsc::channel(2);
+        let feed = async move {
+            for piece in [&b"sou"[..], b"rce"] {
+                sender.send(piece.to_vec()).await.unwrap();
+            }
+        };
+        let collect = async {
+            let mut bytes = Vec::new();
+            while let Some(chunk) = transcoded.recv().await {
+                bytes.extend(chunk);
+            }
+            bytes
+        };
+
+        let transcoder = Transcoder::new(&config);
+        let report = |percent| reported.lock().unwrap().push(percent);
+        let transcode = transcoder.transcode_stream(input, output, Some(4.0), &report);
+        let (result, (), bytes) = tokio::join!(transcode, feed, collect);
+        result.unwrap();
+        assert_eq!(bytes, b"sourcetranscoded\n");
+        assert_eq!(*reported.lock().unwrap(), [25, 50, 99]);
+        let args = std::fs::read_to_string(dir.path().join("args")).unwrap();
+        assert!(args.contains("-i pipe:0 "), "{}", args);
+        assert!(args.ends_with("-f webm pipe:1\n"), "{}", args);
+
+        // One that gives up without reading its input fails as it would on a file
+        config.ffmpeg_path = script(
+            dir.path(),
+            "ffmpeg-corrupt",
+            "echo 'pipe:0: Invalid data found when processing input' >&2\nexit 1\n",
+        );
+        let (sender, input) = mpsc::channel(1);
+        let (output, mut transcoded) = mpsc::channel(1);
+        let feed = async move {
+            for _ in 0..64 {
+                sender.send(vec![0; 4096]).await.unwrap();
+            }
+        };
+        let transcoder = Transcoder::new(&config);
+        let (result, ()) = tokio::join!(transcoder.transcode_stream(input, output, None, &|_| {}), feed);
+        assert!(matches!(result, Err(WorkerError::InvalidMedia { .. })), "{:?}", result);
+        assert_eq!(transcoded.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_startup_wants_both_tools_only_when_transcoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = tools(dir.path(), MP4_PROBE, TranscodeMode::Passthrough);
+        check_tools(&config).await.unwrap();
+
+        config.ffmpeg_path = dir.path().join("missing").to_string_lossy().into_owned();
+        let error = check_tools(&config).await.unwrap_err();
+        assert!(error.to_string().contains("FFMPEG_PATH"), "{}", error);
+
+        config.mode = TranscodeMode::Off;
+        check_tools(&config).await.unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+//! Scratch space for the files a task writes
+//!
+//! Each task gets a [`Workspace`], a directory of its own under the scratch
+//! root that its download and transcode are written to. Dropping the
+//! workspace removes the directory, so it goes however the task ends: done,
+//! failed, or panicking. A task that will be claimed again, for a retry or
+//! after being stopped at shutdown, [keeps](Workspace::keep) it for the next
+//! attempt to resume from; whatever a crash or a retry that never came
+//! leaves behind is swept away at the next startup by [`sweep_orphans`].
+//!
+//! Before each claim the pool asks a [`SpaceGate`] whether the scratch
+//! volume has room for another recording, and waits when it doesn't rather
+//! than claim a task that would fail for want of disk.
+
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::metrics;
+
+/// Age past which a task's directory is taken to be left over
+pub const ORPHAN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The directory of `task_id` under `root`
+pub fn task_dir(root: &Path, task_id: Uuid) -> PathBuf {
+    root.join(format!("task-{}", task_id))
+}
+
+/// A task's directory, removed when dropped unless kept
+#[derive(Debug)]
+pub struct Workspace {
+    dir: PathBuf,
+    keep: AtomicBool,
+}
+
+impl Workspace {
+    /// Make the directory of `task_id` under `root`, or open the one an
+    /// earlier attempt kept
+    pub fn create(root: &Path, task_id: Uuid) -> io::Result<Self> {
+        let dir = task_dir(root, task_id);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            keep: AtomicBool::new(false),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the recording is downloaded to
+    pub fn download_path(&self) -> PathBuf {
+        self.dir.join("recording.download")
+    }
+
+    /// Where the transcoded recording is written, with `extension`
+    pub fn transcode_path(&self, extension: &str) -> PathBuf {
+        self.dir.join(format!("recording.transcoded.{}", extension))
+    }
+
+    /// Leave the directory for the task's next attempt, unless the task
+    /// panics
+    pub fn keep(&self) {
+        self.keep.store(true, Ordering::Relaxed);
+    }
+
+    /// Remove the directory when dropped after all
+    pub fn discard(&self) {
+        self.keep.store(false, Ordering::Relaxed);
+    }
+
+    /// Empty the directory for a task starting over
+    pub fn clear(&self) -> io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        std::fs::create_dir_all(&self.dir)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if self.keep.load(Ordering::Relaxed) && !std::thread::panicking() {
+            debug!("Kept {} for a retry", self.dir.display());
+            return;
+        }
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => debug!("Removed {}", self.dir.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove {}: {}", self.dir.display(), e),
+        }
+    }
+}
+
+/// Remove the task directories under `root` last changed more than
+/// `older_than` ago, returning how many went
+pub fn sweep_orphans(root: &Path, older_than: Duration) -> io::Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let is_task = entry.file_name().to_string_lossy().starts_with("task-");
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if !metadata.is_dir() || !is_task || age < older_than {
+            continue;
+        }
+        match std::fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Failed to remove {}: {}", entry.path().display(), e),
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} task directories left in {}", removed, root.display());
+    }
+    Ok(removed)
+}
+
+/// Free space on the volume holding a path
+pub trait DiskSpace: Send + Sync {
+    /// Bytes an unprivileged process may still write
+    fn available(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// [`DiskSpace`] as `statvfs(3)` reports it
+pub struct Statvfs;
+
+impl DiskSpace for Statvfs {
+    // The fields are u32 on some platforms and u64 on others
+    #[allow(clippy::unnecessary_cast)]
+    fn available(&self, path: &Path) -> io::Result<u64> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is NUL-terminated and `stat` is only read once
+        // statvfs has filled it in
+        let stat = unsafe {
+            if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            stat.assume_init()
+        };
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+/// Whether the scratch volume has room for another task
+pub struct SpaceGate {
+    root: PathBuf,
+    /// Bytes kept free beyond what a task is expected to need
+    min_free: u64,
+    /// Bytes a task is expected to write
+    expected: u64,
+    disk: Arc<dyn DiskSpace>,
+}
+
+impl SpaceGate {
+    pub fn new(root: PathBuf, min_free: u64, expected: u64, disk: Arc<dyn DiskSpace>) -> Self {
+        Self {
+            root,
+            min_free,
+            expected,
+            disk,
+        }
+    }
+
+    /// Whether a task may be claimed now; when not, warns and counts the
+    /// claim put off
+    ///
+    /// A volume whose space can't be read is let through, as the task's own
+    /// writes will say what's wrong with it.
+    pub fn has_room(&self) -> bool {
+        let available = match self.disk.available(&self.root) {
+            Ok(available) => available,
+            Err(e) => {
+                warn!("Failed to read the free space of {}: {}", self.root.display(), e);
+                return true;
+            }
+        };
+        let needed = self.min_free.saturating_add(self.expected);
+        if available >= needed {
+            return true;
+        }
+        warn!(
+            "Only {} bytes are free in {}, short of the {} a task needs; not claiming",
+            available,
+            self.root.display(),
+            needed
+        );
+        metrics::increment(&metrics::CLAIMS_DEFERRED, &[]);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeDisk;
+    use std::fs::File;
+
+    fn age(path: &Path, by: Duration) {
+        File::open(path).unwrap().set_modified(SystemTime::now() - by).unwrap();
+    }
+
+    #[test]
+    fn test_a_workspace_goes_when_dropped_unless_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let task_id = Uuid::new_v4();
+
+        let workspace = Workspace::create(root.path(), task_id).unwrap();
+        std::fs::write(workspace.download_path(), b"partial").unwrap();
+        assert_eq!(workspace.dir(), task_dir(root.path(), task_id));
+        drop(workspace);
+        assert!(!task_dir(root.path(), task_id).exists());
+
+        let workspace = Workspace::create(root.path(), task_id).unwrap();
+        std::fs::write(workspace.download_path(), b"partial").unwrap();
+        workspace.keep();
+        drop(workspace);
+        // A retry opens what the last attempt kept
+        let workspace = Workspace::create(root.path(), task_id).unwrap();
+        assert_eq!(std::fs::read(workspace.download_path()).unwrap(), b"partial");
+        workspace.clear().unwrap();
+        assert!(workspace.dir().exists() && !workspace.download_path().exists());
+
+        // A workspace kept along the way can be let go at the end
+        workspace.keep();
+        workspace.discard();
+        drop(workspace);
+        assert!(!task_dir(root.path(), task_id).exists());
+    }
+
+    #[test]
+    fn test_a_panicking_task_leaves_no_workspace() {
+        let root = tempfile::tempdir().unwrap();
+        let task_id = Uuid::new_v4();
+        let panicked = std::panic::catch_unwind(|| {
+            let workspace = Workspace::create(root.path(), task_id).unwrap();
+            std::fs::write(workspace.transcode_path("mp4"), b"half").unwrap();
+            // Even one held for a resume
+            workspace.keep();
+            panic!("boom");
+        });
+        assert!(panicked.is_err());
+        assert!(!task_dir(root.path(), task_id).exists());
+    }
+
+    #[test]
+    fn test_the_startup_sweep_removes_only_old_task_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let old = Workspace::create(root.path(), Uuid::new_v4()).unwrap();
+        std::fs::write(old.download_path(), b"left over").unwrap();
+        age(old.dir(), ORPHAN_AGE + Duration::from_secs(60));
+        let old_dir = old.dir().to_path_buf();
+        old.keep();
+        drop(old);
+        let recent = Workspace::create(root.path(), Uuid::new_v4()).unwrap();
+        let other = root.path().join("not-a-task");
+        std::fs::create_dir(&other).unwrap();
+        age(&other, ORPHAN_AGE * 2);
+
+        assert_eq!(sweep_orphans(root.path(), ORPHAN_AGE).unwrap(), 1);
+        assert!(!old_dir.exists());
+        assert!(recent.dir().exists());
+        assert!(other.exists(), "only the worker's own directories are swept");
+        assert_eq!(sweep_orphans(&root.path().join("missing"), ORPHAN_AGE).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_claims_wait_for_room_on_the_scratch_volume() {
+        let disk = Arc::new(FakeDisk::new(1500));
+        let gate = SpaceGate::new(PathBuf::from("/scratch"), 1000, 400, disk.clone());
+        assert!(gate.has_room());
+
+        disk.set(1399);
+        let deferred = metrics::registry().value(&metrics::CLAIMS_DEFERRED, &[]);
+        assert!(!gate.has_room());
+        assert!(metrics::registry().value(&metrics::CLAIMS_DEFERRED, &[]) >= deferred + 1.0);
+
+        disk.fail();
+        assert!(gate.has_room(), "an unreadable volume doesn't stop the worker");
+        assert_eq!(disk.asked(), vec![PathBuf::from("/scratch"); 3]);
+    }
+
+    #[test]
+    fn test_statvfs_reads_the_volume_of_a_path() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Statvfs.available(dir.path()).unwrap() > 0);
+        assert!(Statvfs.available(&dir.path().join("missing")).is_err());
+    }
+}